@@ -0,0 +1,110 @@
+//! ARM Data Watchpoint and Trace unit (DWT), for its cycle counter.
+//!
+//! The DWT counts the cycles of the processor in `CYCCNT`, a 32-bit counter
+//! that wraps, which makes it a cheap way to time short stretches of code.
+//! `Dwt` implements `kernel::profiling::CycleCounter`, so it can drive a
+//! `kernel::profiling::Profiler`.
+//!
+//! The DWT is part of ARMv7-M, so it is not available on Cortex-M0. Some
+//! implementations leave the cycle counter out; `enable()` returns
+//! ENOSUPPORT on those.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! cortexm4::dwt::DWT.enable();
+//! let profiler = static_init!(
+//!     kernel::profiling::Profiler,
+//!     kernel::profiling::Profiler::new(
+//!         &cortexm4::dwt::DWT,
+//!         &mut kernel::profiling::PROCESS_STATS,
+//!         &mut kernel::profiling::DRIVER_STATS,
+//!     )
+//! );
+//! board_kernel.set_profiler(profiler);
+//! ```
+
+use core::ptr;
+use kernel::common::registers::{ReadOnly, ReadWrite};
+use kernel::common::StaticRef;
+use kernel::profiling::CycleCounter;
+use kernel::ReturnCode;
+
+#[repr(C)]
+struct DwtRegisters {
+    ctrl: ReadWrite<u32, Control::Register>,
+    cyccnt: ReadWrite<u32>,
+    cpicnt: ReadWrite<u32>,
+    exccnt: ReadWrite<u32>,
+    sleepcnt: ReadWrite<u32>,
+    lsucnt: ReadWrite<u32>,
+    foldcnt: ReadWrite<u32>,
+    pcsr: ReadOnly<u32>,
+}
+
+register_bitfields![u32,
+    Control [
+        /// The number of comparators
+        NUMCOMP OFFSET(28) NUMBITS(4),
+        /// Set if there is no cycle counter
+        NOCYCCNT 25,
+        /// Count exception overhead cycles in `EXCCNT`
+        EXCEVTENA 16,
+        /// Sample the PC for the trace output
+        PCSAMPLENA 12,
+        /// Enable the cycle counter
+        CYCCNTENA 0
+    ]
+];
+
+const DWT_BASE: StaticRef<DwtRegisters> =
+    unsafe { StaticRef::new(0xE0001000 as *const DwtRegisters) };
+
+/// Debug Exception and Monitor Control Register
+const DEMCR: *mut u32 = 0xE000EDFC as *mut u32;
+const DEMCR_TRCENA: u32 = 1 << 24;
+
+pub static mut DWT: Dwt = Dwt::new();
+
+pub struct Dwt {
+    registers: StaticRef<DwtRegisters>,
+}
+
+impl Dwt {
+    pub const fn new() -> Dwt {
+        Dwt {
+            registers: DWT_BASE,
+        }
+    }
+
+    /// Starts the cycle counter from zero.
+    pub fn enable(&self) -> ReturnCode {
+        unsafe {
+            ptr::write_volatile(DEMCR, ptr::read_volatile(DEMCR) | DEMCR_TRCENA);
+        }
+        if self.registers.ctrl.is_set(Control::NOCYCCNT) {
+            return ReturnCode::ENOSUPPORT;
+        }
+        self.registers.cyccnt.set(0);
+        self.registers.ctrl.modify(Control::CYCCNTENA::SET);
+        ReturnCode::SUCCESS
+    }
+
+    /// Stops the cycle counter.
+    pub fn disable(&self) {
+        self.registers.ctrl.modify(Control::CYCCNTENA::CLEAR);
+    }
+
+    /// The cycles counted since the counter was enabled, wrapping at 32
+    /// bits.
+    pub fn cycles(&self) -> u32 {
+        self.registers.cyccnt.get()
+    }
+}
+
+impl CycleCounter for Dwt {
+    fn cycles(&self) -> u32 {
+        Dwt::cycles(self)
+    }
+}
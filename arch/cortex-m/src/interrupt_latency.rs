@@ -0,0 +1,105 @@
+//! Interrupt latency, measured with the DWT cycle counter.
+//!
+//! Interrupts are handled in two halves: `generic_isr` only disables the
+//! interrupt in the NVIC and returns to the kernel, and the kernel loop
+//! later calls the driver of the interrupt when it services pending
+//! interrupts. The latency is the time between the two, which is what the
+//! timing of radios, ADCs and other peripherals depends on.
+//!
+//! On the Cortex-M3 and M4, `generic_isr` saves the cycle counter in
+//! `INTERRUPT_ENTRY_CYCLES` as it disables the interrupt, and
+//! `nvic::next_pending()`, which the chips call to find the interrupt to
+//! service next, adds the cycles since then to the statistics of the
+//! interrupt. `InterruptLatency` implements
+//! `kernel::profiling::InterruptLatency`, so the process console can print
+//! the statistics.
+//!
+//! Interrupts that are serviced without going through `generic_isr`, for
+//! example because they became pending while disabled, are not measured.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! cortexm4::interrupt_latency::INTERRUPT_LATENCY
+//!     .enable(&mut cortexm4::interrupt_latency::LATENCY_STATS);
+//! ```
+
+use dwt;
+use kernel::common::cells::TakeCell;
+use kernel::profiling::{self, Stats};
+use kernel::ReturnCode;
+
+/// The interrupts whose entry `generic_isr` timestamps. This is also in the
+/// assembly of `generic_isr`.
+pub const MAX_INTERRUPTS: usize = 96;
+
+/// The cycle counter when each interrupt was disabled by `generic_isr`, or
+/// 0 if it has been serviced since.
+#[no_mangle]
+pub static mut INTERRUPT_ENTRY_CYCLES: [u32; MAX_INTERRUPTS] = [0; MAX_INTERRUPTS];
+
+pub static mut LATENCY_STATS: [Stats; MAX_INTERRUPTS] = [Stats::new(); MAX_INTERRUPTS];
+
+pub static mut INTERRUPT_LATENCY: InterruptLatency = InterruptLatency::new();
+
+pub struct InterruptLatency {
+    /// The statistics of each interrupt, from the first, if enabled
+    stats: TakeCell<'static, [Stats]>,
+}
+
+impl InterruptLatency {
+    pub const fn new() -> InterruptLatency {
+        InterruptLatency {
+            stats: TakeCell::empty(),
+        }
+    }
+
+    /// Starts the cycle counter, and measures the latency of the interrupts
+    /// that `stats` has an entry for.
+    pub fn enable(&self, stats: &'static mut [Stats]) -> ReturnCode {
+        let rcode = unsafe { dwt::DWT.enable() };
+        if rcode == ReturnCode::SUCCESS {
+            self.stats.replace(stats);
+        }
+        rcode
+    }
+
+    /// Adds the latency of `interrupt`, which the kernel is about to
+    /// service, if it went through `generic_isr` since it was last
+    /// serviced.
+    pub(crate) fn serviced(&self, interrupt: usize) {
+        self.stats.map(|stats| {
+            if interrupt >= MAX_INTERRUPTS || interrupt >= stats.len() {
+                return;
+            }
+            let entry = unsafe { INTERRUPT_ENTRY_CYCLES[interrupt] };
+            if entry != 0 {
+                let now = unsafe { dwt::DWT.cycles() };
+                stats[interrupt].add(now.wrapping_sub(entry));
+                unsafe {
+                    INTERRUPT_ENTRY_CYCLES[interrupt] = 0;
+                }
+            }
+        });
+    }
+}
+
+impl profiling::InterruptLatency for InterruptLatency {
+    fn number_interrupts(&self) -> usize {
+        self.stats.map_or(0, |stats| stats.len().min(MAX_INTERRUPTS))
+    }
+
+    fn latency_stats(&self, interrupt: usize) -> Option<Stats> {
+        self.stats
+            .map_or(None, |stats| stats.get(interrupt).map(|stats| *stats))
+    }
+
+    fn reset(&self) {
+        self.stats.map(|stats| {
+            for interrupt in stats.iter_mut() {
+                *interrupt = Stats::new();
+            }
+        });
+    }
+}
@@ -0,0 +1,223 @@
+//! ARM Instrumentation Trace Macrocell (ITM), for debug output over SWO.
+//!
+//! The ITM sends what is written to its stimulus ports through the TPIU to
+//! the SWO pin, where a debug probe captures it, so `debug!()` output
+//! doesn't need a UART. With timestamps enabled, the ITM adds a timestamp
+//! packet to the output, so the capture hardware can tell when each part was
+//! written.
+//!
+//! `Itm` implements the transmit side of `hil::uart::UART`, so it can be
+//! passed to `kernel::debug::DebugWriter` instead of a UART, and
+//! `kernel::debug::PanicSink`. Bytes are written to the stimulus port
+//! synchronously, and `transmit_complete` is signaled with a deferred call,
+//! so the chip must service deferred calls.
+//!
+//! The ITM is part of ARMv7-M, so it is not available on Cortex-M0. Routing
+//! the SWO signal to a pin is chip specific and left to the board.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! cortexm4::itm::ITM.initialize();
+//! cortexm4::itm::ITM.enable(48_000_000, 2_000_000, true);
+//! let debugger = static_init!(
+//!     kernel::debug::DebugWriter,
+//!     kernel::debug::DebugWriter::new(
+//!         &cortexm4::itm::ITM,
+//!         &mut kernel::debug::OUTPUT_BUF,
+//!         &mut kernel::debug::INTERNAL_BUF,
+//!     )
+//! );
+//! hil::uart::UART::set_client(&cortexm4::itm::ITM, debugger);
+//! ```
+
+use core::ptr;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::common::registers::{ReadOnly, ReadWrite, WriteOnly};
+use kernel::common::StaticRef;
+use kernel::debug::PanicSink;
+use kernel::hil;
+use kernel::ReturnCode;
+
+#[repr(C)]
+struct ItmRegisters {
+    stim: [ReadWrite<u32>; 256],
+    _reserved0: [u32; 640],
+    ter: [ReadWrite<u32>; 8],
+    _reserved1: [u32; 8],
+    tpr: ReadWrite<u32>,
+    _reserved2: [u32; 15],
+    tcr: ReadWrite<u32, TraceControl::Register>,
+    _reserved3: [u32; 75],
+    lar: WriteOnly<u32>,
+}
+
+#[repr(C)]
+struct TpiuRegisters {
+    sspsr: ReadOnly<u32>,
+    cspsr: ReadWrite<u32>,
+    _reserved0: [u32; 2],
+    acpr: ReadWrite<u32>,
+    _reserved1: [u32; 55],
+    sppr: ReadWrite<u32>,
+    _reserved2: [u32; 132],
+    ffcr: ReadWrite<u32>,
+}
+
+register_bitfields![u32,
+    TraceControl [
+        /// Set while the ITM is sending
+        BUSY 23,
+        /// The ID of the trace stream
+        TRACEBUSID OFFSET(16) NUMBITS(7),
+        /// The timestamp clock is the SWO clock (1) or the processor clock (0)
+        SWOENA 4,
+        /// Forward DWT packets
+        TXENA 3,
+        /// Send synchronization packets
+        SYNCENA 2,
+        /// Send timestamps
+        TSENA 1,
+        /// Enable the ITM
+        ITMENA 0
+    ]
+];
+
+const ITM_BASE: StaticRef<ItmRegisters> =
+    unsafe { StaticRef::new(0xE0000000 as *const ItmRegisters) };
+const TPIU_BASE: StaticRef<TpiuRegisters> =
+    unsafe { StaticRef::new(0xE0040000 as *const TpiuRegisters) };
+
+/// Debug Exception and Monitor Control Register
+const DEMCR: *mut u32 = 0xE000EDFC as *mut u32;
+const DEMCR_TRCENA: u32 = 1 << 24;
+
+/// Unlocks writes to the ITM registers
+const LAR_UNLOCK: u32 = 0xC5ACCE55;
+
+/// The TPIU sends SWO as NRZ, like a UART
+const SPPR_NRZ: u32 = 2;
+
+/// Without the formatter, only ITM packets are sent
+const FFCR_TRIGIN: u32 = 1 << 8;
+
+pub static mut ITM: Itm = Itm::new(0);
+
+pub struct Itm {
+    registers: StaticRef<ItmRegisters>,
+    /// The stimulus port written to
+    port: usize,
+    client: OptionalCell<&'static hil::uart::Client>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    deferred_call: DeferredCall,
+}
+
+impl Itm {
+    pub const fn new(port: usize) -> Itm {
+        Itm {
+            registers: ITM_BASE,
+            port: port,
+            client: OptionalCell::empty(),
+            tx_buffer: TakeCell::empty(),
+            deferred_call: DeferredCall::new(),
+        }
+    }
+
+    /// Registers the deferred call that signals `transmit_complete`. The ITM
+    /// can be used as a UART once this returns SUCCESS.
+    pub fn initialize(&'static self) -> ReturnCode {
+        self.deferred_call.register(self)
+    }
+
+    /// Starts sending the output of the stimulus port over SWO at
+    /// `baud_rate`, which must divide `trace_clock_hz`, the clock of the
+    /// TPIU. With `timestamps`, the ITM adds timestamps to the output.
+    ///
+    /// A debug probe may configure the ITM itself when it starts capturing,
+    /// in which case this isn't needed.
+    pub unsafe fn enable(&self, trace_clock_hz: u32, baud_rate: u32, timestamps: bool) {
+        ptr::write_volatile(DEMCR, ptr::read_volatile(DEMCR) | DEMCR_TRCENA);
+
+        TPIU_BASE.cspsr.set(1);
+        TPIU_BASE.acpr.set(trace_clock_hz / baud_rate - 1);
+        TPIU_BASE.sppr.set(SPPR_NRZ);
+        TPIU_BASE.ffcr.set(FFCR_TRIGIN);
+
+        let regs = &*self.registers;
+        regs.lar.set(LAR_UNLOCK);
+        regs.tcr.write(
+            TraceControl::ITMENA::SET
+                + TraceControl::TRACEBUSID.val(1)
+                + if timestamps {
+                    TraceControl::TSENA::SET
+                } else {
+                    TraceControl::TSENA::CLEAR
+                },
+        );
+        // Unprivileged code may not write to the stimulus ports
+        regs.tpr.set(0);
+        regs.ter[self.port / 32].set(regs.ter[self.port / 32].get() | 1 << (self.port % 32));
+    }
+
+    /// Whether the stimulus port sends what is written to it. If it
+    /// doesn't, writes are dropped instead of waiting for a FIFO that is
+    /// never emptied.
+    fn is_enabled(&self) -> bool {
+        let regs = &*self.registers;
+        regs.tcr.is_set(TraceControl::ITMENA)
+            && regs.ter[self.port / 32].get() & 1 << (self.port % 32) != 0
+    }
+
+    fn write_bytes(&self, bytes: &[u8]) {
+        if !self.is_enabled() {
+            return;
+        }
+        let stim = &self.registers.stim[self.port];
+        for &byte in bytes {
+            // The port reads 1 once its FIFO has room
+            while stim.get() & 1 == 0 {}
+            // A byte write sends a single byte
+            unsafe {
+                ptr::write_volatile(stim as *const ReadWrite<u32> as *mut u8, byte);
+            }
+        }
+    }
+}
+
+impl hil::uart::UART for Itm {
+    fn set_client(&self, client: &'static hil::uart::Client) {
+        self.client.set(client);
+    }
+
+    fn configure(&self, _params: hil::uart::UARTParameters) -> ReturnCode {
+        ReturnCode::SUCCESS
+    }
+
+    fn transmit(&self, tx_data: &'static mut [u8], tx_len: usize) {
+        self.write_bytes(&tx_data[..tx_len]);
+        self.tx_buffer.replace(tx_data);
+        self.deferred_call.set();
+    }
+
+    fn receive(&self, _rx_buf: &'static mut [u8], _rx_len: usize) {}
+
+    fn abort_receive(&self) {}
+}
+
+impl DeferredCallClient for Itm {
+    fn handle_deferred_call(&self) {
+        self.tx_buffer.take().map(|buffer| {
+            self.client.map(move |client| {
+                client.transmit_complete(buffer, hil::uart::Error::CommandComplete);
+            });
+        });
+    }
+}
+
+impl PanicSink for Itm {
+    fn write(&self, s: &str) {
+        self.write_bytes(s.as_bytes());
+    }
+}
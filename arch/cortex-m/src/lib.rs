@@ -8,8 +8,12 @@
 #[macro_use(register_bitfields, register_bitmasks)]
 extern crate kernel;
 
+pub mod dwt;
+pub mod interrupt_latency;
+pub mod itm;
 pub mod nvic;
 pub mod scb;
+pub mod semihosting;
 pub mod support;
 pub mod syscall;
 pub mod systick;
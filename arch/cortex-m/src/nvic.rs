@@ -1,5 +1,6 @@
 //! Cortex-M NVIC
 
+use interrupt_latency;
 use kernel::common::cells::VolatileCell;
 use kernel::common::StaticRef;
 
@@ -49,7 +50,19 @@ pub unsafe fn disable_all() {
 
 /// Get the index (0-240) the lowest number pending interrupt, or `None` if none
 /// are pending.
+///
+/// Chips call this to find the interrupt to service next, so this is where
+/// the latency of the interrupt is measured, if enabled.
 pub unsafe fn next_pending() -> Option<u32> {
+    lowest_pending().map(|interrupt| {
+        interrupt_latency::INTERRUPT_LATENCY.serviced(interrupt as usize);
+        interrupt
+    })
+}
+
+/// Like `next_pending()`, but for finding out which interrupt is pending
+/// without servicing it, for example the one that woke the chip up.
+pub unsafe fn lowest_pending() -> Option<u32> {
     let nvic: &NvicRegisters = &*NVIC_BASE_ADDRESS;
 
     for (block, ispr) in nvic.ispr.iter().enumerate() {
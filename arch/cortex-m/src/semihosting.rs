@@ -0,0 +1,164 @@
+//! ARM semihosting, for debug output through an attached debugger.
+//!
+//! With semihosting, the kernel asks the debugger to do I/O for it by
+//! executing a `bkpt 0xAB` instruction, here to write to the console of the
+//! debugger, for example of OpenOCD. This needs no pins besides the debug
+//! port, but halts the core for every write, so it is slow.
+//!
+//! Without an attached debugger, the breakpoint would cause a hard fault, so
+//! output is dropped if no debugger is attached. Software can only tell this
+//! on ARMv7-M, so semihosting is not available on Cortex-M0.
+//!
+//! `Semihosting` implements the transmit side of `hil::uart::UART`, so it can
+//! be passed to `kernel::debug::DebugWriter` instead of a UART, and
+//! `kernel::debug::PanicSink`. Each transmission is a single write to the
+//! debugger, and `transmit_complete` is signaled with a deferred call, so the
+//! chip must service deferred calls.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! cortexm4::semihosting::SEMIHOSTING.initialize();
+//! let debugger = static_init!(
+//!     kernel::debug::DebugWriter,
+//!     kernel::debug::DebugWriter::new(
+//!         &cortexm4::semihosting::SEMIHOSTING,
+//!         &mut kernel::debug::OUTPUT_BUF,
+//!         &mut kernel::debug::INTERNAL_BUF,
+//!     )
+//! );
+//! hil::uart::UART::set_client(&cortexm4::semihosting::SEMIHOSTING, debugger);
+//! ```
+
+use core::cell::Cell;
+use core::ptr;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::debug::PanicSink;
+use kernel::hil;
+use kernel::ReturnCode;
+
+/// Semihosting operations
+const SYS_OPEN: usize = 0x01;
+const SYS_WRITE: usize = 0x05;
+
+/// Opening this file gives the console of the debugger.
+const CONSOLE_NAME: &[u8] = b":tt\0";
+/// The mode of `SYS_OPEN` for writing, like `fopen(name, "w")`
+const OPEN_MODE_WRITE: usize = 4;
+
+/// Debug Halting Control and Status Register
+const DHCSR: *const u32 = 0xE000EDF0 as *const u32;
+const DHCSR_C_DEBUGEN: u32 = 1 << 0;
+
+#[cfg(target_os = "none")]
+unsafe fn call(operation: usize, argument: usize) -> usize {
+    let result;
+    asm!("bkpt 0xAB"
+         : "={r0}"(result)
+         : "{r0}"(operation), "{r1}"(argument)
+         : "memory"
+         : "volatile");
+    result
+}
+
+#[cfg(not(target_os = "none"))]
+unsafe fn call(_operation: usize, _argument: usize) -> usize {
+    usize::max_value()
+}
+
+pub static mut SEMIHOSTING: Semihosting = Semihosting::new();
+
+pub struct Semihosting {
+    /// The handle of the console of the debugger, once it is opened
+    handle: Cell<Option<usize>>,
+    client: OptionalCell<&'static hil::uart::Client>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    deferred_call: DeferredCall,
+}
+
+impl Semihosting {
+    pub const fn new() -> Semihosting {
+        Semihosting {
+            handle: Cell::new(None),
+            client: OptionalCell::empty(),
+            tx_buffer: TakeCell::empty(),
+            deferred_call: DeferredCall::new(),
+        }
+    }
+
+    /// Registers the deferred call that signals `transmit_complete`.
+    /// Semihosting can be used as a UART once this returns SUCCESS.
+    pub fn initialize(&'static self) -> ReturnCode {
+        self.deferred_call.register(self)
+    }
+
+    fn debugger_attached(&self) -> bool {
+        unsafe { ptr::read_volatile(DHCSR) & DHCSR_C_DEBUGEN != 0 }
+    }
+
+    /// Opens the console of the debugger the first time it is written to.
+    fn console(&self) -> Option<usize> {
+        if self.handle.get().is_none() {
+            let arguments = [
+                CONSOLE_NAME.as_ptr() as usize,
+                OPEN_MODE_WRITE,
+                CONSOLE_NAME.len() - 1,
+            ];
+            let handle = unsafe { call(SYS_OPEN, arguments.as_ptr() as usize) };
+            if handle != usize::max_value() {
+                self.handle.set(Some(handle));
+            }
+        }
+        self.handle.get()
+    }
+
+    fn write_bytes(&self, bytes: &[u8]) {
+        if bytes.is_empty() || !self.debugger_attached() {
+            return;
+        }
+        self.console().map(|handle| {
+            let arguments = [handle, bytes.as_ptr() as usize, bytes.len()];
+            unsafe {
+                call(SYS_WRITE, arguments.as_ptr() as usize);
+            }
+        });
+    }
+}
+
+impl hil::uart::UART for Semihosting {
+    fn set_client(&self, client: &'static hil::uart::Client) {
+        self.client.set(client);
+    }
+
+    fn configure(&self, _params: hil::uart::UARTParameters) -> ReturnCode {
+        ReturnCode::SUCCESS
+    }
+
+    fn transmit(&self, tx_data: &'static mut [u8], tx_len: usize) {
+        self.write_bytes(&tx_data[..tx_len]);
+        self.tx_buffer.replace(tx_data);
+        self.deferred_call.set();
+    }
+
+    fn receive(&self, _rx_buf: &'static mut [u8], _rx_len: usize) {}
+
+    fn abort_receive(&self) {}
+}
+
+impl DeferredCallClient for Semihosting {
+    fn handle_deferred_call(&self) {
+        self.tx_buffer.take().map(|buffer| {
+            self.client.map(move |client| {
+                client.transmit_complete(buffer, hil::uart::Error::CommandComplete);
+            });
+        });
+    }
+}
+
+impl PanicSink for Semihosting {
+    fn write(&self, s: &str) {
+        self.write_bytes(s.as_bytes());
+    }
+}
@@ -16,8 +16,12 @@ pub mod mpu;
 // valid on cortex-m4.
 pub use cortexm::support;
 
+pub use cortexm::dwt;
+pub use cortexm::interrupt_latency;
+pub use cortexm::itm;
 pub use cortexm::nvic;
 pub use cortexm::scb;
+pub use cortexm::semihosting;
 pub use cortexm::syscall;
 pub use cortexm::systick;
 
@@ -89,6 +93,19 @@ pub unsafe extern "C" fn generic_isr() {
     /* ISRs start at 16, so substract 16 to get zero-indexed */
     sub r0, #16
 
+    /* Save the cycle counter of the DWT for the first MAX_INTERRUPTS (96) */
+    /* interrupts, so the kernel can measure their latency: */
+    /* INTERRUPT_ENTRY_CYCLES[r0] = DWT.CYCCNT */
+    cmp r0, #96
+    bhs _ggeneric_isr_no_timestamp
+    movw r2, #0x1004
+    movt r2, #0xe000
+    ldr r2, [r2]
+    movw r3, #:lower16:INTERRUPT_ENTRY_CYCLES
+    movt r3, #:upper16:INTERRUPT_ENTRY_CYCLES
+    str r2, [r3, r0, lsl #2]
+  _ggeneric_isr_no_timestamp:
+
     /*
      * High level:
      *    NVIC.ICER[r0 / 32] = 1 << (r0 & 31)
@@ -205,6 +205,12 @@ impl CortexMRegion {
             mpu::Permissions::ExecuteOnly => {
                 (RegionAttributes::AP::NoAccess, RegionAttributes::XN::Enable)
             }
+            // The kernel keeps write access to memory that it lends to DMA
+            // while the process can only read it.
+            mpu::Permissions::LentReadOnly => (
+                RegionAttributes::AP::UnprivilegedReadOnly,
+                RegionAttributes::XN::Disable,
+            ),
         };
 
         // Base address register
@@ -599,6 +605,37 @@ impl kernel::mpu::MPU for MPU {
         Ok(())
     }
 
+    fn overlay_region(
+        &self,
+        start: *const u8,
+        size: usize,
+        permissions: mpu::Permissions,
+        config: &mut Self::MpuConfig,
+    ) -> Option<mpu::Region> {
+        // Without subregions, a region covers exactly a power of two that
+        // is at least 32 bytes and aligned to its size.
+        if size < 32 || size.count_ones() != 1 || (start as usize) % size != 0 {
+            return None;
+        }
+
+        // Regions with higher numbers take priority where regions overlap,
+        // and the app memory region has the lowest number.
+        let region_num = config.unused_region_number()?;
+        config.regions[region_num] =
+            CortexMRegion::new(start, size, start, size, region_num, None, permissions);
+
+        Some(mpu::Region::new(start, size))
+    }
+
+    fn remove_overlay_region(&self, region: mpu::Region, config: &mut Self::MpuConfig) {
+        let location = Some((region.start_address(), region.size()));
+        for (number, cortexm_region) in config.regions.iter_mut().enumerate() {
+            if number != APP_MEMORY_REGION_NUM && cortexm_region.location() == location {
+                *cortexm_region = CortexMRegion::empty(number);
+            }
+        }
+    }
+
     fn configure_mpu(&self, config: &Self::MpuConfig) {
         let regs = &*self.0;
 
@@ -4,7 +4,7 @@ use core::str;
 use cortexm4;
 use kernel::debug;
 use kernel::hil::led;
-use kernel::hil::uart::{self, UART};
+use kernel::hil::uart::{self, UARTEmergency, UART};
 use tm4c129x;
 
 use PROCESSES;
@@ -31,9 +31,8 @@ impl Write for Writer {
             }
             uart.enable_tx();
         }
-        for c in s.bytes() {
-            uart.send_byte(c);
-            while !uart.tx_ready() {}
+        unsafe {
+            uart.emergency_write(s.as_bytes());
         }
         Ok(())
     }
@@ -28,7 +28,7 @@ pub mod io;
 const NUM_PROCS: usize = 4;
 
 // How should the kernel respond when a process faults.
-const FAULT_RESPONSE: kernel::procs::FaultResponse = kernel::procs::FaultResponse::Panic;
+const FAULT_RESPONSE: kernel::procs::PanicFaultResponse = kernel::procs::PanicFaultResponse;
 
 // RAM to be shared by all application processes.
 #[link_section = ".app_memory"]
@@ -83,7 +83,14 @@ pub unsafe fn reset_handler() {
     tm4c129x::sysctl::PSYSCTLM
         .setup_system_clock(tm4c129x::sysctl::SystemClockSource::PllPioscAt120MHz);
 
-    let board_kernel = static_init!(kernel::Kernel, kernel::Kernel::new(&PROCESSES));
+    let scheduler = static_init!(
+        kernel::RoundRobinScheduler,
+        kernel::RoundRobinScheduler::new()
+    );
+    let board_kernel = static_init!(
+        kernel::Kernel,
+        kernel::Kernel::new(&mut PROCESSES, scheduler)
+    );
 
     // Create capabilities that the board needs to call certain protected kernel
     // functions.
@@ -259,8 +266,8 @@ pub unsafe fn reset_handler() {
         chip.mpu(),
         &_sapps as *const u8,
         &mut APP_MEMORY,
-        &mut PROCESSES,
-        FAULT_RESPONSE,
+        &FAULT_RESPONSE,
+        None,
         &process_management_capability,
     );
     board_kernel.kernel_loop(&tm4c1294, chip, Some(&tm4c1294.ipc), &main_loop_capability);
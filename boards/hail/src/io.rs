@@ -4,7 +4,7 @@ use core::str;
 use cortexm4;
 use kernel::debug;
 use kernel::hil::led;
-use kernel::hil::uart::{self, UART};
+use kernel::hil::uart::{self, UARTEmergency, UART};
 use sam4l;
 
 use PROCESSES;
@@ -18,21 +18,19 @@ static mut WRITER: Writer = Writer { initialized: false };
 impl Write for Writer {
     fn write_str(&mut self, s: &str) -> ::core::fmt::Result {
         let uart = unsafe { &mut sam4l::usart::USART0 };
-        let regs_manager = &sam4l::usart::USARTRegManager::panic_new(&uart);
         if !self.initialized {
             self.initialized = true;
+            // Keep the USART clock enabled for the rest of the panic
+            sam4l::usart::USARTRegManager::panic_new(&uart);
             uart.configure(uart::UARTParameters {
                 baud_rate: 115200,
                 stop_bits: uart::StopBits::One,
                 parity: uart::Parity::None,
                 hw_flow_control: false,
             });
-            uart.enable_tx(regs_manager);
         }
-        // XXX: I'd like to get this working the "right" way, but I'm not sure how
-        for c in s.bytes() {
-            uart.send_byte(regs_manager, c);
-            while !uart.tx_ready(regs_manager) {}
+        unsafe {
+            uart.emergency_write(s.as_bytes());
         }
         Ok(())
     }
@@ -21,7 +21,7 @@ use capsules::virtual_spi::{MuxSpiMaster, VirtualSpiMasterDevice};
 use capsules::virtual_uart::{UartDevice, UartMux};
 use kernel::capabilities;
 use kernel::hil;
-use kernel::hil::entropy::Entropy32;
+use kernel::hil::led;
 use kernel::hil::rng::Rng;
 use kernel::hil::spi::SpiMaster;
 use kernel::hil::Controller;
@@ -35,17 +35,35 @@ use kernel::Platform;
 pub mod io;
 #[allow(dead_code)]
 mod test_take_map_cell;
+#[allow(dead_code)]
+mod test_tbf_header;
 
 static mut SPI_READ_BUF: [u8; 64] = [0; 64];
 static mut SPI_WRITE_BUF: [u8; 64] = [0; 64];
 
+static mut AES_GCM_BUF: [u8; 16 + 256] = [0; 16 + 256];
+static mut AES_BUF: [u8; 256] = [0; 256];
+
 // State for loading and holding applications.
 
 // Number of concurrent processes this platform supports.
 const NUM_PROCS: usize = 20;
 
 // How should the kernel respond when a process faults.
-const FAULT_RESPONSE: kernel::procs::FaultResponse = kernel::procs::FaultResponse::Panic;
+const FAULT_RESPONSE: kernel::procs::PanicFaultResponse = kernel::procs::PanicFaultResponse;
+
+/// Where `debug!()` output goes.
+#[allow(dead_code)]
+enum DebugOutput {
+    /// Shares the console UART
+    Uart,
+    /// ITM over SWO, with timestamps
+    Itm,
+    /// Through semihosting, needs an attached debugger
+    Semihosting,
+}
+
+const DEBUG_OUTPUT: DebugOutput = DebugOutput::Uart;
 
 // RAM to be shared by all application processes.
 #[link_section = ".app_memory"]
@@ -57,6 +75,19 @@ static mut PROCESSES: [Option<&'static kernel::procs::ProcessType>; NUM_PROCS] =
     None, None, None, None,
 ];
 
+// Flash page that keeps the output of the last kernel panic. It is the only
+// volume in the page-aligned storage region, so it fills a page.
+#[link_section = ".storage"]
+#[used]
+static CRASH_LOG_STORAGE: [u8; 512] = [0; 512];
+
+// Buffer for writing the crash log page.
+static mut CRASH_LOG_PAGE: sam4l::flashcalw::Sam4lPage = sam4l::flashcalw::Sam4lPage::new();
+
+// Page of kernel information that processes map with memop 13.
+static mut KERNEL_INFO_PAGE: kernel::kernel_info::KernelInfoPage =
+    kernel::kernel_info::KernelInfoPage::new();
+
 /// Dummy buffer that causes the linker to reserve enough space for the stack.
 #[no_mangle]
 #[link_section = ".stack_buffer"]
@@ -86,7 +117,16 @@ struct Hail {
     rng: &'static capsules::rng::RngDriver<'static>,
     ipc: kernel::ipc::IPC,
     crc: &'static capsules::crc::Crc<'static, sam4l::crccu::Crccu<'static>>,
+    aes: &'static capsules::aes::AesDriver<
+        'static,
+        capsules::aes_gcm::AES128GCM<'static, sam4l::aes::Aes<'static>>,
+    >,
     dac: &'static capsules::dac::Dac<'static>,
+    crash_log: &'static capsules::crash_log::CrashLog<'static, sam4l::flashcalw::FLASHCALW>,
+    timestamp: &'static capsules::timestamp::Timestamp<
+        'static,
+        VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+    >,
 }
 
 /// Mapping of integer syscalls to objects that implement syscalls.
@@ -100,6 +140,7 @@ impl Platform for Hail {
             capsules::gpio::DRIVER_NUM => f(Some(self.gpio)),
 
             capsules::alarm::DRIVER_NUM => f(Some(self.alarm)),
+            capsules::timestamp::DRIVER_NUM => f(Some(self.timestamp)),
             capsules::spi::DRIVER_NUM => f(Some(self.spi)),
             capsules::nrf51822_serialization::DRIVER_NUM => f(Some(self.nrf51822)),
             capsules::ambient_light::DRIVER_NUM => f(Some(self.ambient_light)),
@@ -113,15 +154,29 @@ impl Platform for Hail {
             capsules::rng::DRIVER_NUM => f(Some(self.rng)),
 
             capsules::crc::DRIVER_NUM => f(Some(self.crc)),
+            capsules::aes::DRIVER_NUM => f(Some(self.aes)),
 
             capsules::dac::DRIVER_NUM => f(Some(self.dac)),
 
+            capsules::crash_log::DRIVER_NUM => f(Some(self.crash_log)),
+
             kernel::ipc::DRIVER_NUM => f(Some(&self.ipc)),
             _ => f(None),
         }
     }
 }
 
+/// Services the flash controller while the crash log is written during a
+/// panic, when interrupts are no longer serviced.
+fn poll_flash() {
+    unsafe {
+        if cortexm4::nvic::next_pending() == Some(sam4l::nvic::HFLASHC) {
+            sam4l::flashcalw::FLASH_CONTROLLER.handle_interrupt();
+            cortexm4::nvic::Nvic::new(sam4l::nvic::HFLASHC).clear_pending();
+        }
+    }
+}
+
 /// Helper function called during bring-up that configures multiplexed I/O.
 unsafe fn set_pin_primary_functions() {
     use sam4l::gpio::PeripheralFunction::{A, B};
@@ -204,7 +259,14 @@ pub unsafe fn reset_handler() {
 
     set_pin_primary_functions();
 
-    let board_kernel = static_init!(kernel::Kernel, kernel::Kernel::new(&PROCESSES));
+    let scheduler = static_init!(
+        kernel::RoundRobinScheduler,
+        kernel::RoundRobinScheduler::new()
+    );
+    let board_kernel = static_init!(
+        kernel::Kernel,
+        kernel::Kernel::new(&mut PROCESSES, scheduler)
+    );
 
     // Create capabilities that the board needs to call certain protected kernel
     // functions.
@@ -274,8 +336,34 @@ pub unsafe fn reset_handler() {
     );
     ast.configure(mux_alarm);
 
+    let kernel_info_alarm = static_init!(
+        VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+        VirtualMuxAlarm::new(mux_alarm)
+    );
+    let kernel_info = static_init!(
+        kernel::kernel_info::KernelInfo<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+        kernel::kernel_info::KernelInfo::new(&KERNEL_INFO_PAGE, "hail", kernel_info_alarm)
+    );
+    board_kernel.set_kernel_info(kernel_info);
+
+    let restart_alarm = static_init!(
+        VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+        VirtualMuxAlarm::new(mux_alarm)
+    );
+    let restart_timer = static_init!(
+        kernel::restart_timer::RestartTimer<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+        kernel::restart_timer::RestartTimer::new(restart_alarm)
+    );
+    restart_alarm.set_client(restart_timer);
+    board_kernel.set_restart_timer(restart_timer);
+
     let sensors_i2c = static_init!(MuxI2C<'static>, MuxI2C::new(&sam4l::i2c::I2C1));
     sam4l::i2c::I2C1.set_master_client(sensors_i2c);
+    sam4l::i2c::I2C1.set_bus_pins(
+        &sam4l::gpio::PB[01],
+        &sam4l::gpio::PB[00],
+        sam4l::gpio::PeripheralFunction::A,
+    );
 
     // SI7021 Temperature / Humidity Sensor, address: 0x40
     let si7021_i2c = static_init!(
@@ -355,6 +443,19 @@ pub unsafe fn reset_handler() {
     );
     virtual_alarm1.set_client(alarm);
 
+    // Timestamps
+    let timestamp_alarm = static_init!(
+        VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+        VirtualMuxAlarm::new(mux_alarm)
+    );
+    let timestamp = static_init!(
+        capsules::timestamp::Timestamp<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+        capsules::timestamp::Timestamp::new(
+            timestamp_alarm,
+            board_kernel.create_grant(&memory_allocation_capability)
+        )
+    );
+
     // FXOS8700CQ accelerometer, device address 0x1e
     let fxos8700_i2c = static_init!(I2CDevice, I2CDevice::new(sensors_i2c, 0x1e));
     let fxos8700 = static_init!(
@@ -468,21 +569,22 @@ pub unsafe fn reset_handler() {
         )
     );
     sam4l::adc::ADC0.set_client(adc);
+    sam4l::adc::ADC0.set_scan_client(adc);
 
     // Setup RNG
-    let entropy_to_random = static_init!(
-        capsules::rng::Entropy32ToRandom<'static>,
-        capsules::rng::Entropy32ToRandom::new(&sam4l::trng::TRNG)
+    let csprng = static_init!(
+        capsules::csprng::Csprng<'static>,
+        capsules::csprng::Csprng::new(&sam4l::trng::TRNG)
     );
     let rng = static_init!(
         capsules::rng::RngDriver<'static>,
         capsules::rng::RngDriver::new(
-            entropy_to_random,
+            csprng,
             board_kernel.create_grant(&memory_allocation_capability)
         )
     );
-    sam4l::trng::TRNG.set_client(entropy_to_random);
-    entropy_to_random.set_client(rng);
+    csprng.start();
+    csprng.set_client(rng);
 
     // set GPIO driver controlling remaining GPIO pins
     let gpio_pins = static_init!(
@@ -512,11 +614,70 @@ pub unsafe fn reset_handler() {
     );
     sam4l::crccu::CRCCU.set_client(crc);
 
+    // AES-GCM
+    let aes_gcm = static_init!(
+        capsules::aes_gcm::AES128GCM<'static, sam4l::aes::Aes<'static>>,
+        capsules::aes_gcm::AES128GCM::new(&sam4l::aes::AES, &mut AES_GCM_BUF)
+    );
+    hil::symmetric_encryption::AES128::set_client(&sam4l::aes::AES, aes_gcm);
+    hil::symmetric_encryption::AES128::enable(&sam4l::aes::AES);
+    let aes = static_init!(
+        capsules::aes::AesDriver<
+            'static,
+            capsules::aes_gcm::AES128GCM<'static, sam4l::aes::Aes<'static>>,
+        >,
+        capsules::aes::AesDriver::new(
+            aes_gcm,
+            &mut AES_BUF,
+            board_kernel.create_grant(&memory_allocation_capability)
+        )
+    );
+    hil::symmetric_encryption::AES128GCM::set_client(aes_gcm, aes);
+
     // DAC
+    let dac_virtual_alarm = static_init!(
+        VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+        VirtualMuxAlarm::new(mux_alarm)
+    );
+    let dac_waveform = static_init!(
+        capsules::dac_waveform::DacWaveform<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+        capsules::dac_waveform::DacWaveform::new(&sam4l::dac::DAC, dac_virtual_alarm)
+    );
+    dac_virtual_alarm.set_client(dac_waveform);
     let dac = static_init!(
         capsules::dac::Dac<'static>,
-        capsules::dac::Dac::new(&mut sam4l::dac::DAC)
+        capsules::dac::Dac::new(
+            &sam4l::dac::DAC,
+            Some(dac_waveform),
+            &mut capsules::dac::DAC_BUFFER
+        )
+    );
+    hil::dac::DacBuffered::set_client(dac_waveform, dac);
+
+    // Crash log, and the line number of a panic blinked on the red LED
+    sam4l::flashcalw::FLASH_CONTROLLER.configure();
+    let crash_log = static_init!(
+        capsules::crash_log::CrashLog<'static, sam4l::flashcalw::FLASHCALW>,
+        capsules::crash_log::CrashLog::new(
+            &sam4l::flashcalw::FLASH_CONTROLLER,
+            &CRASH_LOG_STORAGE as *const [u8; 512] as usize / 512,
+            &mut CRASH_LOG_PAGE,
+            &poll_flash,
+            board_kernel.create_grant(&memory_allocation_capability)
+        )
+    );
+    hil::flash::HasClient::set_client(&sam4l::flashcalw::FLASH_CONTROLLER, crash_log);
+    kernel::debug::add_panic_sink(crash_log);
+    crash_log.initialize();
+    let panic_led = static_init!(
+        led::LedLow<'static>,
+        led::LedLow::new(&mut sam4l::gpio::PA[13])
+    );
+    let panic_blink_code = static_init!(
+        kernel::debug::PanicBlinkCode<led::LedLow<'static>>,
+        kernel::debug::PanicBlinkCode::new(panic_led)
     );
+    kernel::debug::add_panic_sink(panic_blink_code);
 
     // // DEBUG Restart All Apps
     // //
@@ -556,23 +717,42 @@ pub unsafe fn reset_handler() {
         rng: rng,
         ipc: kernel::ipc::IPC::new(board_kernel, &memory_allocation_capability),
         crc: crc,
+        aes: aes,
         dac: dac,
+        crash_log: crash_log,
+        timestamp: timestamp,
     };
 
     hail.console.initialize();
 
     // Create virtual device for kernel debug.
-    let debugger_uart = static_init!(UartDevice, UartDevice::new(uart_mux, false));
-    debugger_uart.setup();
+    let debugger_output: &'static hil::uart::UART = match DEBUG_OUTPUT {
+        DebugOutput::Uart => {
+            let debugger_uart = static_init!(UartDevice, UartDevice::new(uart_mux, false));
+            debugger_uart.setup();
+            debugger_uart
+        }
+        DebugOutput::Itm => {
+            cortexm4::itm::ITM.initialize();
+            cortexm4::itm::ITM.enable(48_000_000, 2_000_000, true);
+            kernel::debug::add_panic_sink(&cortexm4::itm::ITM);
+            &cortexm4::itm::ITM
+        }
+        DebugOutput::Semihosting => {
+            cortexm4::semihosting::SEMIHOSTING.initialize();
+            kernel::debug::add_panic_sink(&cortexm4::semihosting::SEMIHOSTING);
+            &cortexm4::semihosting::SEMIHOSTING
+        }
+    };
     let debugger = static_init!(
         kernel::debug::DebugWriter,
         kernel::debug::DebugWriter::new(
-            debugger_uart,
+            debugger_output,
             &mut kernel::debug::OUTPUT_BUF,
             &mut kernel::debug::INTERNAL_BUF,
         )
     );
-    hil::uart::UART::set_client(debugger_uart, debugger);
+    hil::uart::UART::set_client(debugger_output, debugger);
 
     let debug_wrapper = static_init!(
         kernel::debug::DebugWriterWrapper,
@@ -602,9 +782,18 @@ pub unsafe fn reset_handler() {
         chip.mpu(),
         &_sapps as *const u8,
         &mut APP_MEMORY,
-        &mut PROCESSES,
-        FAULT_RESPONSE,
+        &FAULT_RESPONSE,
+        None,
         &process_management_capability,
     );
+
+    // Uncomment to test that malformed TBF headers are refused:
+    // test_tbf_header::test_tbf_header(
+    //     board_kernel,
+    //     &cortexm4::syscall::SysCall::new(),
+    //     chip.mpu(),
+    //     &process_management_capability,
+    // );
+
     board_kernel.kernel_loop(&hail, chip, Some(&hail.ipc), &main_loop_capability);
 }
@@ -0,0 +1,146 @@
+//! Test that the kernel refuses malformed TBF headers.
+//!
+//! Each case builds a header in RAM and asks the kernel to create a process
+//! from it. A malformed header must be refused without a flash size, so the
+//! loader stops instead of skipping by a size it cannot trust. The first case
+//! is a well-formed header of a disabled app, which is refused as well, but
+//! with its size.
+//!
+//! To run the test, uncomment the call to `test_tbf_header()` in `main.rs`.
+
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::mpu::MPU;
+use kernel::procs::PanicFaultResponse;
+use kernel::syscall::UserspaceKernelBoundary;
+use kernel::{Kernel, ReturnCode};
+
+/// Words in the headers of the tests.
+const HEADER_WORDS: usize = 8;
+
+/// The base of a header, and a main TLV element that fills the rest of it.
+struct Header {
+    version: u16,
+    header_size: u16,
+    total_size: u32,
+    flags: u32,
+    /// The length of the main element
+    main_length: u16,
+    /// Flipped bits of the checksum
+    checksum_error: u32,
+}
+
+impl Header {
+    /// The header of a disabled app with a 12 byte main element.
+    fn new() -> Header {
+        Header {
+            version: 2,
+            header_size: (HEADER_WORDS * 4) as u16,
+            total_size: 1024,
+            flags: 0,
+            main_length: 12,
+            checksum_error: 0,
+        }
+    }
+
+    fn words(&self) -> [u32; HEADER_WORDS] {
+        let mut words = [0; HEADER_WORDS];
+        words[0] = self.version as u32 | (self.header_size as u32) << 16;
+        words[1] = self.total_size;
+        words[2] = self.flags;
+        // The main element, with its fields all zero.
+        words[4] = 1 | (self.main_length as u32) << 16;
+        let checksum_words = (self.header_size as usize / 4).min(HEADER_WORDS);
+        words[3] = words[..checksum_words]
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| i != 3)
+            .fold(0, |checksum, (_, word)| checksum ^ word)
+            ^ self.checksum_error;
+        words
+    }
+}
+
+pub unsafe fn test_tbf_header<S: UserspaceKernelBoundary, M: MPU>(
+    kernel: &'static Kernel,
+    syscall: &'static S,
+    mpu: &'static M,
+    capability: &ProcessManagementCapability,
+) {
+    let header_size = (HEADER_WORDS * 4) as u16;
+    let cases = [
+        ("well formed", Header::new(), 1024),
+        (
+            "shorter than the base",
+            Header {
+                header_size: 8,
+                ..Header::new()
+            },
+            0,
+        ),
+        (
+            "longer than the app",
+            Header {
+                total_size: header_size as u32,
+                ..Header::new()
+            },
+            0,
+        ),
+        (
+            "huge app",
+            Header {
+                total_size: 0x20000000,
+                ..Header::new()
+            },
+            0,
+        ),
+        (
+            "bad checksum",
+            Header {
+                checksum_error: 1,
+                ..Header::new()
+            },
+            0,
+        ),
+        (
+            "unknown version",
+            Header {
+                version: 3,
+                ..Header::new()
+            },
+            0,
+        ),
+        (
+            "element past the end",
+            Header {
+                main_length: 16,
+                ..Header::new()
+            },
+            0,
+        ),
+        (
+            "truncated element",
+            Header {
+                header_size: header_size - 8,
+                ..Header::new()
+            },
+            0,
+        ),
+    ];
+
+    for &(name, ref header, flash_size) in cases.iter() {
+        let words = header.words();
+        let result = kernel.create_process(
+            syscall,
+            mpu,
+            words.as_ptr() as *const u8,
+            &PanicFaultResponse,
+            None,
+            capability,
+        );
+        if result == (ReturnCode::FAIL, flash_size) {
+            debug!("TBF header {}: passed", name);
+        } else {
+            debug!("TBF header {}: failed, {:?}", name, result);
+        }
+    }
+}
@@ -53,6 +53,7 @@ impl Component for AdcComponent {
             )
         );
         sam4l::adc::ADC0.set_client(adc);
+        sam4l::adc::ADC0.set_scan_client(adc);
 
         adc
     }
@@ -1,13 +1,14 @@
 //! Component for the Analog Comparator on the imix board.
 //!
 //! This provides one Component, AcComponent, which implements
-//! a userspace syscall interface to the SAM4L ACIFC. It provides
+//! a userspace syscall interface to the SAM4L ACIFC, shared with other
+//! kernel users through the analog comparator mux. It provides
 //! 4 AC channels, AC0-AC3.
 //!
 //! Usage
 //! -----
 //! ```rust
-//! let ac = AcComponent::new().finalize();
+//! let ac = AcComponent::new(board_kernel, mux_ac, mux_alarm).finalize();
 //! ```
 
 // Author: Danilo Verhaert <verhaert@cs.stanford.edu>
@@ -16,22 +17,43 @@
 #![allow(dead_code)] // Components are intended to be conditionally included
 
 use capsules::analog_comparator;
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules::virtual_analog_comparator::{MuxAnalogComparator, VirtualAnalogComparator};
+use kernel;
+use kernel::capabilities;
 use kernel::component::Component;
 use sam4l;
 
-pub struct AcComponent {}
+pub struct AcComponent {
+    board_kernel: &'static kernel::Kernel,
+    ac_mux: &'static MuxAnalogComparator<'static, sam4l::acifc::Acifc<'static>>,
+    alarm_mux: &'static MuxAlarm<'static, sam4l::ast::Ast<'static>>,
+}
 
 impl AcComponent {
-    pub fn new() -> AcComponent {
-        AcComponent {}
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        ac_mux: &'static MuxAnalogComparator<'static, sam4l::acifc::Acifc>,
+        mux: &'static MuxAlarm<'static, sam4l::ast::Ast>,
+    ) -> AcComponent {
+        AcComponent {
+            board_kernel: board_kernel,
+            ac_mux: ac_mux,
+            alarm_mux: mux,
+        }
     }
 }
 
 impl Component for AcComponent {
-    type Output =
-        &'static analog_comparator::AnalogComparator<'static, sam4l::acifc::Acifc<'static>>;
+    type Output = &'static analog_comparator::AnalogComparator<
+        'static,
+        VirtualAnalogComparator<'static, sam4l::acifc::Acifc<'static>>,
+        VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+    >;
 
     unsafe fn finalize(&mut self) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
         let ac_channels = static_init!(
             [&'static sam4l::acifc::AcChannel; 4],
             [
@@ -41,11 +63,29 @@ impl Component for AcComponent {
                 &sam4l::acifc::CHANNEL_AC3,
             ]
         );
+        let virtual_ac = static_init!(
+            VirtualAnalogComparator<'static, sam4l::acifc::Acifc>,
+            VirtualAnalogComparator::new(self.ac_mux)
+        );
+        let ac_virtual_alarm = static_init!(
+            VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
         let analog_comparator = static_init!(
-            analog_comparator::AnalogComparator<'static, sam4l::acifc::Acifc>,
-            analog_comparator::AnalogComparator::new(&mut sam4l::acifc::ACIFC, ac_channels)
+            analog_comparator::AnalogComparator<
+                'static,
+                VirtualAnalogComparator<'static, sam4l::acifc::Acifc>,
+                VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+            >,
+            analog_comparator::AnalogComparator::new(
+                virtual_ac,
+                ac_channels,
+                ac_virtual_alarm,
+                self.board_kernel.create_grant(&grant_cap),
+            )
         );
-        sam4l::acifc::ACIFC.set_client(analog_comparator);
+        virtual_ac.set_client(analog_comparator);
+        ac_virtual_alarm.set_client(analog_comparator);
 
         analog_comparator
     }
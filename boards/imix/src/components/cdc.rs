@@ -0,0 +1,50 @@
+//! Component for a CDC-ACM serial port over USB on the imix board.
+//!
+//! This provides one Component, CdcComponent, which enumerates the
+//! SAM4L USB controller as a serial port, and returns it as a UART.
+//! It can be used instead of USART3 underneath the console's UartMux.
+//! The USB controller has a single client, so this cannot be used
+//! together with the UsbComponent or the HidComponent.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let cdc = CdcComponent::new().finalize();
+//! let uart_mux = static_init!(
+//!     UartMux<'static>,
+//!     UartMux::new(cdc, &mut capsules::virtual_uart::RX_BUF, 115200)
+//! );
+//! hil::uart::UART::set_client(cdc, uart_mux);
+//! ```
+
+#![allow(dead_code)] // Components are intended to be conditionally included
+
+use capsules::cdc::CdcAcm;
+use kernel::component::Component;
+use kernel::hil;
+use sam4l;
+
+pub struct CdcComponent {}
+
+impl CdcComponent {
+    pub fn new() -> CdcComponent {
+        CdcComponent {}
+    }
+}
+
+impl Component for CdcComponent {
+    type Output = &'static CdcAcm<'static, sam4l::usbc::Usbc<'static>>;
+
+    unsafe fn finalize(&mut self) -> Self::Output {
+        let cdc = static_init!(
+            CdcAcm<'static, sam4l::usbc::Usbc<'static>>,
+            CdcAcm::new(&sam4l::usbc::USBC)
+        );
+        sam4l::usbc::USBC.set_client(cdc);
+
+        hil::usb::Client::enable(cdc);
+        hil::usb::Client::attach(cdc);
+
+        cdc
+    }
+}
@@ -0,0 +1,60 @@
+//! Component for a USB keyboard on the imix board.
+//!
+//! This provides one Component, HidComponent, which enumerates the
+//! SAM4L USB controller as a keyboard, and implements a userspace
+//! syscall interface for sending its input reports. The USB controller
+//! has a single client, so this cannot be used together with the
+//! UsbComponent or the CdcComponent.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let hid_driver = HidComponent::new(board_kernel).finalize();
+//! ```
+
+#![allow(dead_code)] // Components are intended to be conditionally included
+
+use capsules::hid::{self, UsbHid};
+use capsules::hid_user::UsbHidDriver;
+use kernel;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::hil;
+use sam4l;
+
+pub struct HidComponent {
+    board_kernel: &'static kernel::Kernel,
+}
+
+impl HidComponent {
+    pub fn new(board_kernel: &'static kernel::Kernel) -> HidComponent {
+        HidComponent {
+            board_kernel: board_kernel,
+        }
+    }
+}
+
+impl Component for HidComponent {
+    type Output = &'static UsbHidDriver<'static>;
+
+    unsafe fn finalize(&mut self) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let usb_hid = static_init!(
+            UsbHid<'static, sam4l::usbc::Usbc<'static>>,
+            UsbHid::new(&sam4l::usbc::USBC, &hid::KEYBOARD_REPORT_DESCRIPTOR)
+        );
+        sam4l::usbc::USBC.set_client(usb_hid);
+
+        let hid_driver = static_init!(
+            UsbHidDriver<'static>,
+            UsbHidDriver::new(usb_hid, self.board_kernel.create_grant(&grant_cap))
+        );
+        hid::Hid::set_client(usb_hid, hid_driver);
+
+        hil::usb::Client::enable(usb_hid);
+        hil::usb::Client::attach(usb_hid);
+
+        hid_driver
+    }
+}
@@ -2,10 +2,12 @@ pub mod adc;
 pub mod alarm;
 pub mod analog_comparator;
 pub mod button;
+pub mod cdc;
 pub mod console;
 pub mod crc;
 pub mod fxos8700;
 pub mod gpio;
+pub mod hid;
 pub mod isl29035;
 pub mod led;
 pub mod nonvolatile_storage;
@@ -21,10 +23,12 @@ pub use self::adc::AdcComponent;
 pub use self::alarm::AlarmDriverComponent;
 pub use self::analog_comparator::AcComponent;
 pub use self::button::ButtonComponent;
+pub use self::cdc::CdcComponent;
 pub use self::console::ConsoleComponent;
 pub use self::crc::CrcComponent;
 pub use self::fxos8700::NineDofComponent;
 pub use self::gpio::GpioComponent;
+pub use self::hid::HidComponent;
 pub use self::isl29035::Isl29035Component;
 pub use self::led::LedComponent;
 pub use self::nonvolatile_storage::NonvolatileStorageComponent;
@@ -1,12 +1,19 @@
 //! Component to initialize the udp/6lowpan interface on imix board.
 //!
-//! This provides one Component, UDPComponent, which implements a
-//! userspace syscall interface to a full udp stack on top of 6lowpan
+//! This provides one Component, UDPComponent, which implements
+//! userspace syscall interfaces to a full udp stack on top of 6lowpan,
+//! to a single TCP connection over the same stack, and to an MQTT-SN client
+//! that talks to a gateway over UDP.
+//!
+//! The stack also answers ICMPv6 echo requests, so the board can be pinged.
+//! Echo replies, TCP segments and MQTT-SN messages share the IPv6 sender with
+//! UDP, and their completion is reported to the UDP driver like that of any
+//! other packet.
 //!
 //! Usage
 //! -----
 //! ```rust
-//! let udp_driver = UDPComponent::new(mux_mac,
+//! let (udp_driver, tcp_driver, mqttsn_driver) = UDPComponent::new(mux_mac,
 //!                                    DEFAULT_CTX_PREFIX_LEN,
 //!                                    DEFAULT_CTX_PREFIX,
 //!                                    DST_MAC_ADDR,
@@ -20,12 +27,16 @@
 
 use capsules;
 use capsules::ieee802154::device::MacDevice;
+use capsules::net::icmpv6::icmpv6_recv::ICMP6RecvStruct;
+use capsules::net::icmpv6::icmpv6_send::ICMP6SendStruct;
 use capsules::net::ieee802154::MacAddress;
 use capsules::net::ipv6::ip_utils::IPAddr;
 use capsules::net::ipv6::ipv6::{IP6Packet, IPPayload, TransportHeader};
 use capsules::net::ipv6::ipv6_recv::IP6Receiver;
 use capsules::net::ipv6::ipv6_send::IP6Sender;
+use capsules::net::mqttsn::client::{MQTTSNStruct, MQTTSN};
 use capsules::net::sixlowpan::{sixlowpan_compression, sixlowpan_state};
+use capsules::net::tcp::tcp_socket::{TCPSocket, TCPSocketStruct};
 use capsules::net::udp::udp::UDPHeader;
 use capsules::net::udp::udp_recv::UDPReceiver;
 use capsules::net::udp::udp_send::{UDPSendStruct, UDPSender};
@@ -49,6 +60,12 @@ const UDP_HDR_SIZE: usize = 8;
 static mut RF233_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
 static mut SIXLOWPAN_RX_BUF: [u8; 1280] = [0x00; 1280];
 static mut UDP_DGRAM: [u8; PAYLOAD_LEN - UDP_HDR_SIZE] = [0; PAYLOAD_LEN - UDP_HDR_SIZE];
+// TCP segments are copied into the same IPv6 payload buffer as UDP datagrams,
+// so they cannot carry more data than it holds.
+static mut TCP_TX_BUF: [u8; PAYLOAD_LEN - UDP_HDR_SIZE] = [0; PAYLOAD_LEN - UDP_HDR_SIZE];
+// The MQTT-SN client keeps the request waiting for its acknowledgement here,
+// so it can be sent again.
+static mut MQTTSN_TX_BUF: [u8; PAYLOAD_LEN - UDP_HDR_SIZE] = [0; PAYLOAD_LEN - UDP_HDR_SIZE];
 
 pub struct UDPComponent {
     board_kernel: &'static kernel::Kernel,
@@ -86,7 +103,11 @@ impl UDPComponent {
 }
 
 impl Component for UDPComponent {
-    type Output = &'static capsules::net::udp::UDPDriver<'static>;
+    type Output = (
+        &'static capsules::net::udp::UDPDriver<'static>,
+        &'static capsules::net::tcp::TCPDriver<'static>,
+        &'static capsules::net::mqttsn::MQTTSNDriver<'static>,
+    );
 
     unsafe fn finalize(&mut self) -> Self::Output {
         let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
@@ -177,6 +198,22 @@ impl Component for UDPComponent {
         let udp_recv = static_init!(UDPReceiver<'static>, UDPReceiver::new());
         ip_receive.set_client(udp_recv);
 
+        // Echo replies go out through the same IPv6 sender, whose completion
+        // callbacks stay with UDP.
+        let icmp_send = static_init!(
+            ICMP6SendStruct<
+                'static,
+                capsules::net::ipv6::ipv6_send::IP6SendStruct<
+                    'static,
+                    VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+                >,
+            >,
+            ICMP6SendStruct::new(ip_send)
+        );
+        let icmp_recv = static_init!(ICMP6RecvStruct<'static>, ICMP6RecvStruct::new());
+        icmp_recv.set_sender(icmp_send);
+        ip_receive.set_icmp_client(icmp_recv);
+
         let udp_driver = static_init!(
             capsules::net::udp::UDPDriver<'static>,
             capsules::net::udp::UDPDriver::new(
@@ -188,7 +225,56 @@ impl Component for UDPComponent {
             )
         );
         udp_send.set_client(udp_driver);
-        udp_recv.set_client(udp_driver);
-        udp_driver
+
+        // The MQTT-SN client sees received datagrams first, and passes those
+        // that are not from its gateway on to the UDP driver.
+        let mqttsn_virtual_alarm = static_init!(
+            VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let mqttsn = static_init!(
+            MQTTSNStruct<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
+            MQTTSNStruct::new(udp_send, mqttsn_virtual_alarm, &mut MQTTSN_TX_BUF)
+        );
+        mqttsn_virtual_alarm.set_client(mqttsn);
+        mqttsn.set_next_client(udp_driver);
+        udp_recv.set_client(mqttsn);
+
+        let mqttsn_driver = static_init!(
+            capsules::net::mqttsn::MQTTSNDriver<'static>,
+            capsules::net::mqttsn::MQTTSNDriver::new(
+                mqttsn,
+                self.board_kernel.create_grant(&grant_cap)
+            )
+        );
+        mqttsn.set_client(mqttsn_driver);
+
+        let tcp_virtual_alarm = static_init!(
+            VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let tcp_socket = static_init!(
+            TCPSocketStruct<
+                'static,
+                VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+                capsules::net::ipv6::ipv6_send::IP6SendStruct<
+                    'static,
+                    VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+                >,
+            >,
+            TCPSocketStruct::new(ip_send, tcp_virtual_alarm, &mut TCP_TX_BUF)
+        );
+        tcp_virtual_alarm.set_client(tcp_socket);
+        ip_receive.set_tcp_client(tcp_socket);
+
+        let tcp_driver = static_init!(
+            capsules::net::tcp::TCPDriver<'static>,
+            capsules::net::tcp::TCPDriver::new(
+                tcp_socket,
+                self.board_kernel.create_grant(&grant_cap)
+            )
+        );
+        tcp_socket.set_client(tcp_driver);
+        (udp_driver, tcp_driver, mqttsn_driver)
     }
 }
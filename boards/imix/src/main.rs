@@ -22,6 +22,7 @@ use capsules::alarm::AlarmDriver;
 use capsules::net::ieee802154::MacAddress;
 use capsules::net::ipv6::ip_utils::IPAddr;
 use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules::virtual_analog_comparator::{MuxAnalogComparator, VirtualAnalogComparator};
 use capsules::virtual_i2c::MuxI2C;
 use capsules::virtual_spi::{MuxSpiMaster, VirtualSpiMasterDevice};
 use capsules::virtual_uart::{UartDevice, UartMux};
@@ -112,7 +113,7 @@ static LOCAL_IP_IFACES: [IPAddr; 2] = [
 ];
 
 // how should the kernel respond when a process faults
-const FAULT_RESPONSE: kernel::procs::FaultResponse = kernel::procs::FaultResponse::Panic;
+const FAULT_RESPONSE: kernel::procs::PanicFaultResponse = kernel::procs::PanicFaultResponse;
 
 #[link_section = ".app_memory"]
 static mut APP_MEMORY: [u8; 16384] = [0; 16384];
@@ -136,13 +137,16 @@ struct Imix {
     button: &'static capsules::button::Button<'static, sam4l::gpio::GPIOPin>,
     analog_comparator: &'static capsules::analog_comparator::AnalogComparator<
         'static,
-        sam4l::acifc::Acifc<'static>,
+        VirtualAnalogComparator<'static, sam4l::acifc::Acifc<'static>>,
+        VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
     >,
     spi: &'static capsules::spi::Spi<'static, VirtualSpiMasterDevice<'static, sam4l::spi::SpiHw>>,
     ipc: kernel::ipc::IPC,
     ninedof: &'static capsules::ninedof::NineDof<'static>,
     radio_driver: &'static capsules::ieee802154::RadioDriver<'static>,
     udp_driver: &'static capsules::net::udp::UDPDriver<'static>,
+    tcp_driver: &'static capsules::net::tcp::TCPDriver<'static>,
+    mqttsn_driver: &'static capsules::net::mqttsn::MQTTSNDriver<'static>,
     crc: &'static capsules::crc::Crc<'static, sam4l::crccu::Crccu<'static>>,
     usb_driver: &'static capsules::usb_user::UsbSyscallDriver<
         'static,
@@ -190,6 +194,8 @@ impl kernel::Platform for Imix {
             capsules::usb_user::DRIVER_NUM => f(Some(self.usb_driver)),
             capsules::ieee802154::DRIVER_NUM => f(Some(self.radio_driver)),
             capsules::net::udp::DRIVER_NUM => f(Some(self.udp_driver)),
+            capsules::net::tcp::DRIVER_NUM => f(Some(self.tcp_driver)),
+            capsules::net::mqttsn::DRIVER_NUM => f(Some(self.mqttsn_driver)),
             capsules::nrf51822_serialization::DRIVER_NUM => f(Some(self.nrf51822)),
             capsules::nonvolatile_storage_driver::DRIVER_NUM => f(Some(self.nonvolatile_storage)),
             kernel::ipc::DRIVER_NUM => f(Some(&self.ipc)),
@@ -302,10 +308,20 @@ pub unsafe fn reset_handler() {
         trng: true,
     });
 
-    let board_kernel = static_init!(kernel::Kernel, kernel::Kernel::new(&PROCESSES));
+    let scheduler = static_init!(
+        kernel::RoundRobinScheduler,
+        kernel::RoundRobinScheduler::new()
+    );
+    let board_kernel = static_init!(
+        kernel::Kernel,
+        kernel::Kernel::new(&mut PROCESSES, scheduler)
+    );
 
     // # CONSOLE
     // Create a shared UART channel for the console and for kernel debug.
+    // To run the console over USB instead, pass the UART returned by
+    // `components::CdcComponent::new().finalize()` to the UartMux (and do not
+    // create the UsbComponent below).
     sam4l::usart::USART3.set_mode(sam4l::usart::UsartMode::Uart);
     let uart_mux = static_init!(
         UartMux<'static>,
@@ -335,6 +351,11 @@ pub unsafe fn reset_handler() {
     // # I2C and I2C Sensors
     let mux_i2c = static_init!(MuxI2C<'static>, MuxI2C::new(&sam4l::i2c::I2C2));
     sam4l::i2c::I2C2.set_master_client(mux_i2c);
+    sam4l::i2c::I2C2.set_bus_pins(
+        &sam4l::gpio::PA[22],
+        &sam4l::gpio::PA[21],
+        sam4l::gpio::PeripheralFunction::E,
+    );
 
     let ambient_light = AmbientLightComponent::new(board_kernel, mux_i2c, mux_alarm).finalize();
     let si7021 = SI7021Component::new(mux_i2c, mux_alarm).finalize();
@@ -370,7 +391,23 @@ pub unsafe fn reset_handler() {
     let led = LedComponent::new().finalize();
     let button = ButtonComponent::new(board_kernel).finalize();
     let crc = CrcComponent::new(board_kernel).finalize();
-    let analog_comparator = AcComponent::new().finalize();
+
+    // # ANALOG COMPARATOR
+    let ac_channels = static_init!(
+        [&'static sam4l::acifc::AcChannel; 4],
+        [
+            &sam4l::acifc::CHANNEL_AC0,
+            &sam4l::acifc::CHANNEL_AC1,
+            &sam4l::acifc::CHANNEL_AC2,
+            &sam4l::acifc::CHANNEL_AC3,
+        ]
+    );
+    let mux_ac = static_init!(
+        MuxAnalogComparator<'static, sam4l::acifc::Acifc>,
+        MuxAnalogComparator::new(&sam4l::acifc::ACIFC, ac_channels)
+    );
+    sam4l::acifc::ACIFC.set_client(mux_ac);
+    let analog_comparator = AcComponent::new(board_kernel, mux_ac, mux_alarm).finalize();
 
     // Can this initialize be pushed earlier, or into component? -pal
     rf233.initialize(&mut RF233_BUF, &mut RF233_REG_WRITE, &mut RF233_REG_READ);
@@ -380,7 +417,7 @@ pub unsafe fn reset_handler() {
     let usb_driver = UsbComponent::new(board_kernel).finalize();
     let nonvolatile_storage = NonvolatileStorageComponent::new(board_kernel).finalize();
 
-    let udp_driver = UDPComponent::new(
+    let (udp_driver, tcp_driver, mqttsn_driver) = UDPComponent::new(
         board_kernel,
         mux_mac,
         DEFAULT_CTX_PREFIX_LEN,
@@ -408,6 +445,8 @@ pub unsafe fn reset_handler() {
         ninedof,
         radio_driver,
         udp_driver,
+        tcp_driver,
+        mqttsn_driver,
         usb_driver,
         nrf51822: nrf_serialization,
         nonvolatile_storage: nonvolatile_storage,
@@ -440,8 +479,8 @@ pub unsafe fn reset_handler() {
         chip.mpu(),
         &_sapps as *const u8,
         &mut APP_MEMORY,
-        &mut PROCESSES,
-        FAULT_RESPONSE,
+        &FAULT_RESPONSE,
+        None,
         &process_mgmt_cap,
     );
 
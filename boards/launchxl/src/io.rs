@@ -4,7 +4,7 @@ use core::panic::PanicInfo;
 use cortexm4;
 use kernel::debug;
 use kernel::hil::led;
-use kernel::hil::uart::{self, UART};
+use kernel::hil::uart::{self, UARTEmergency, UART};
 
 use PROCESSES;
 
@@ -26,9 +26,8 @@ impl Write for Writer {
                 hw_flow_control: false,
             });
         }
-        for c in s.bytes() {
-            uart.send_byte(c);
-            while !uart.tx_fifo_not_full() {}
+        unsafe {
+            uart.emergency_write(s.as_bytes());
         }
         Ok(())
     }
@@ -16,7 +16,6 @@ use cc26x2::aon;
 use cc26x2::prcm;
 use kernel::capabilities;
 use kernel::hil;
-use kernel::hil::entropy::Entropy32;
 use kernel::hil::rng::Rng;
 use kernel::Chip;
 
@@ -29,7 +28,7 @@ mod i2c_tests;
 mod uart_echo;
 
 // How should the kernel respond when a process faults.
-const FAULT_RESPONSE: kernel::procs::FaultResponse = kernel::procs::FaultResponse::Panic;
+const FAULT_RESPONSE: kernel::procs::PanicFaultResponse = kernel::procs::PanicFaultResponse;
 
 // Number of concurrent processes this platform supports.
 const NUM_PROCS: usize = 2;
@@ -187,7 +186,14 @@ pub unsafe fn reset_handler() {
     // Wait for it to turn on until we continue
     while !prcm::Power::is_enabled(prcm::PowerDomain::Peripherals) {}
 
-    let board_kernel = static_init!(kernel::Kernel, kernel::Kernel::new(&PROCESSES));
+    let scheduler = static_init!(
+        kernel::RoundRobinScheduler,
+        kernel::RoundRobinScheduler::new()
+    );
+    let board_kernel = static_init!(
+        kernel::Kernel,
+        kernel::Kernel::new(&mut PROCESSES, scheduler)
+    );
 
     // Enable the GPIO clocks
     prcm::Clock::enable_gpio();
@@ -342,19 +348,19 @@ pub unsafe fn reset_handler() {
     );
     virtual_alarm1.set_client(alarm);
 
-    let entropy_to_random = static_init!(
-        capsules::rng::Entropy32ToRandom<'static>,
-        capsules::rng::Entropy32ToRandom::new(&cc26x2::trng::TRNG)
+    let csprng = static_init!(
+        capsules::csprng::Csprng<'static>,
+        capsules::csprng::Csprng::new(&cc26x2::trng::TRNG)
     );
     let rng = static_init!(
         capsules::rng::RngDriver<'static>,
         capsules::rng::RngDriver::new(
-            entropy_to_random,
+            csprng,
             board_kernel.create_grant(&memory_allocation_capability)
         )
     );
-    cc26x2::trng::TRNG.set_client(entropy_to_random);
-    entropy_to_random.set_client(rng);
+    csprng.start();
+    csprng.set_client(rng);
 
     let launchxl = Platform {
         console,
@@ -380,8 +386,8 @@ pub unsafe fn reset_handler() {
         chip.mpu(),
         &_sapps as *const u8,
         &mut APP_MEMORY,
-        &mut PROCESSES,
-        FAULT_RESPONSE,
+        &FAULT_RESPONSE,
+        None,
         &process_management_capability,
     );
 
@@ -66,7 +66,6 @@ use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
 use capsules::virtual_uart::{UartDevice, UartMux};
 use kernel::capabilities;
 use kernel::hil;
-use kernel::hil::entropy::Entropy32;
 use kernel::hil::rng::Rng;
 use kernel::hil::uart::UART;
 use kernel::{Chip, SysTick};
@@ -94,7 +93,7 @@ const BUTTON4_PIN: usize = 20;
 // State for loading and holding applications.
 
 // How should the kernel respond when a process faults.
-const FAULT_RESPONSE: kernel::procs::FaultResponse = kernel::procs::FaultResponse::Panic;
+const FAULT_RESPONSE: kernel::procs::PanicFaultResponse = kernel::procs::PanicFaultResponse;
 
 // Number of concurrent processes this platform supports.
 const NUM_PROCS: usize = 1;
@@ -150,7 +149,14 @@ pub unsafe fn reset_handler() {
     // Loads relocations and clears BSS
     nrf51::init();
 
-    let board_kernel = static_init!(kernel::Kernel, kernel::Kernel::new(&PROCESSES));
+    let scheduler = static_init!(
+        kernel::RoundRobinScheduler,
+        kernel::RoundRobinScheduler::new()
+    );
+    let board_kernel = static_init!(
+        kernel::Kernel,
+        kernel::Kernel::new(&mut PROCESSES, scheduler)
+    );
 
     // Create capabilities that the board needs to call certain protected kernel
     // functions.
@@ -338,19 +344,19 @@ pub unsafe fn reset_handler() {
     );
     kernel::hil::sensors::TemperatureDriver::set_client(&nrf5x::temperature::TEMP, temp);
 
-    let entropy_to_random = static_init!(
-        capsules::rng::Entropy32ToRandom<'static>,
-        capsules::rng::Entropy32ToRandom::new(&nrf5x::trng::TRNG)
+    let csprng = static_init!(
+        capsules::csprng::Csprng<'static>,
+        capsules::csprng::Csprng::new(&nrf5x::trng::TRNG)
     );
     let rng = static_init!(
         capsules::rng::RngDriver<'static>,
         capsules::rng::RngDriver::new(
-            entropy_to_random,
+            csprng,
             board_kernel.create_grant(&memory_allocation_capability)
         )
     );
-    nrf5x::trng::TRNG.set_client(entropy_to_random);
-    entropy_to_random.set_client(rng);
+    csprng.start();
+    csprng.set_client(rng);
 
     let ble_radio = static_init!(
         capsules::ble_advertising_driver::BLE<
@@ -416,8 +422,8 @@ pub unsafe fn reset_handler() {
         chip.mpu(),
         &_sapps as *const u8,
         &mut APP_MEMORY,
-        &mut PROCESSES,
-        FAULT_RESPONSE,
+        &FAULT_RESPONSE,
+        None,
         &process_management_capability,
     );
 
@@ -17,7 +17,7 @@ extern crate nrf52;
 extern crate nrf52dk_base;
 extern crate nrf5x;
 
-use nrf52dk_base::{SpiMX25R6435FPins, SpiPins, UartPins};
+use nrf52dk_base::{SpiMX25R6435FPins, SpiPins, UartChannel, UartPins};
 
 // The nRF52840DK LEDs (see back of board)
 const LED1_PIN: usize = 13;
@@ -51,7 +51,7 @@ pub mod io;
 
 // State for loading and holding applications.
 // How should the kernel respond when a process faults.
-const FAULT_RESPONSE: kernel::procs::FaultResponse = kernel::procs::FaultResponse::Panic;
+const FAULT_RESPONSE: kernel::procs::PanicFaultResponse = kernel::procs::PanicFaultResponse;
 
 // Number of concurrent processes this platform supports.
 const NUM_PROCS: usize = 8;
@@ -138,7 +138,14 @@ pub unsafe fn reset_handler() {
         ]
     );
 
-    let board_kernel = static_init!(kernel::Kernel, kernel::Kernel::new(&PROCESSES));
+    let scheduler = static_init!(
+        kernel::RoundRobinScheduler,
+        kernel::RoundRobinScheduler::new()
+    );
+    let board_kernel = static_init!(
+        kernel::Kernel,
+        kernel::Kernel::new(&mut PROCESSES, scheduler)
+    );
 
     nrf52dk_base::setup_board(
         board_kernel,
@@ -148,7 +155,7 @@ pub unsafe fn reset_handler() {
         LED2_PIN,
         LED3_PIN,
         led_pins,
-        &UartPins::new(UART_RTS, UART_TXD, UART_RXD, UART_CTS),
+        UartChannel::Pins(UartPins::new(UART_RTS, UART_TXD, UART_RXD, UART_CTS)),
         &SpiPins::new(SPI_MOSI, SPI_MISO, SPI_CLK),
         &Some(SpiMX25R6435FPins::new(
             SPI_MX25R6435F_CHIP_SELECT,
@@ -157,7 +164,6 @@ pub unsafe fn reset_handler() {
         )),
         button_pins,
         &mut APP_MEMORY,
-        &mut PROCESSES,
-        FAULT_RESPONSE,
+        &FAULT_RESPONSE,
     );
 }
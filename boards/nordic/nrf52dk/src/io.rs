@@ -3,7 +3,7 @@ use core::panic::PanicInfo;
 use cortexm4;
 use kernel::debug;
 use kernel::hil::led;
-use kernel::hil::uart::{self, UART};
+use kernel::hil::uart::{self, UARTEmergency, UART};
 use nrf52;
 use nrf5x;
 
@@ -27,11 +27,8 @@ impl Write for Writer {
                 hw_flow_control: false,
             });
         }
-        for c in s.bytes() {
-            unsafe {
-                uart.send_byte(c);
-            }
-            while !uart.tx_ready() {}
+        unsafe {
+            uart.emergency_write(s.as_bytes());
         }
         Ok(())
     }
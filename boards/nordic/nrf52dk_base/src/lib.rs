@@ -21,9 +21,8 @@ use capsules::virtual_spi::MuxSpiMaster;
 use capsules::virtual_uart::{UartDevice, UartMux};
 use kernel::capabilities;
 use kernel::hil;
-use kernel::hil::entropy::Entropy32;
 use kernel::hil::rng::Rng;
-use kernel::Chip;
+use kernel::{Chip, ClockManager};
 use nrf5x::rtc::Rtc;
 
 /// Pins for SPI for the flash chip MX25R6435F
@@ -73,6 +72,14 @@ impl UartPins {
     }
 }
 
+/// Where the console and kernel debug output go
+pub enum UartChannel {
+    /// The UART on these pins
+    Pins(UartPins),
+    /// Segger RTT buffers in RAM, read and written by a debug probe
+    Rtt,
+}
+
 /// Supported drivers by the platform
 pub struct Platform {
     ble_radio: &'static capsules::ble_advertising_driver::BLE<
@@ -129,13 +136,12 @@ pub unsafe fn setup_board(
     debug_pin2_index: usize,
     debug_pin3_index: usize,
     led_pins: &'static mut [(&'static nrf5x::gpio::GPIOPin, capsules::led::ActivationMode)],
-    uart_pins: &UartPins,
+    uart_channel: UartChannel,
     spi_pins: &SpiPins,
     mx25r6435f: &Option<SpiMX25R6435FPins>,
     button_pins: &'static mut [(&'static nrf5x::gpio::GPIOPin, capsules::button::GpioMode)],
-    app_memory: &mut [u8],
-    process_pointers: &'static mut [Option<&'static kernel::procs::ProcessType>],
-    app_fault_response: kernel::procs::FaultResponse,
+    app_memory: &'static mut [u8],
+    app_fault_response: &'static kernel::procs::FaultResponse,
 ) {
     // Make non-volatile memory writable and activate the reset button
     let uicr = nrf52::uicr::Uicr::new();
@@ -216,27 +222,56 @@ pub unsafe fn setup_board(
         capsules::virtual_alarm::VirtualMuxAlarm::new(mux_alarm)
     );
 
+    let channel: &'static hil::uart::UART = match uart_channel {
+        UartChannel::Pins(uart_pins) => {
+            nrf52::uart::UARTE0.initialize(
+                nrf5x::pinmux::Pinmux::new(uart_pins.txd as u32),
+                nrf5x::pinmux::Pinmux::new(uart_pins.rxd as u32),
+                nrf5x::pinmux::Pinmux::new(uart_pins.cts as u32),
+                nrf5x::pinmux::Pinmux::new(uart_pins.rts as u32),
+            );
+            &nrf52::uart::UARTE0
+        }
+        UartChannel::Rtt => {
+            let virtual_alarm_rtt = static_init!(
+                capsules::virtual_alarm::VirtualMuxAlarm<'static, nrf5x::rtc::Rtc>,
+                capsules::virtual_alarm::VirtualMuxAlarm::new(mux_alarm)
+            );
+            let rtt_memory = static_init!(
+                capsules::segger_rtt::SeggerRttMemory,
+                capsules::segger_rtt::SeggerRttMemory::new(
+                    b"Terminal\0",
+                    &capsules::segger_rtt::UP_BUFFER,
+                    b"Terminal\0",
+                    &capsules::segger_rtt::DOWN_BUFFER
+                )
+            );
+            let rtt = static_init!(
+                capsules::segger_rtt::SeggerRtt<VirtualMuxAlarm<'static, Rtc>>,
+                capsules::segger_rtt::SeggerRtt::new(
+                    virtual_alarm_rtt,
+                    rtt_memory,
+                    &mut capsules::segger_rtt::UP_BUFFER,
+                    &mut capsules::segger_rtt::DOWN_BUFFER
+                )
+            );
+            virtual_alarm_rtt.set_client(rtt);
+            kernel::debug::add_panic_sink(rtt);
+            rtt
+        }
+    };
+
     // Create a shared UART channel for the console and for kernel debug.
     let uart_mux = static_init!(
         UartMux<'static>,
-        UartMux::new(
-            &nrf52::uart::UARTE0,
-            &mut capsules::virtual_uart::RX_BUF,
-            115200
-        )
+        UartMux::new(channel, &mut capsules::virtual_uart::RX_BUF, 115200)
     );
-    hil::uart::UART::set_client(&nrf52::uart::UARTE0, uart_mux);
+    hil::uart::UART::set_client(channel, uart_mux);
 
     // Create a UartDevice for the console.
     let console_uart = static_init!(UartDevice, UartDevice::new(uart_mux, true));
     console_uart.setup();
 
-    nrf52::uart::UARTE0.initialize(
-        nrf5x::pinmux::Pinmux::new(uart_pins.txd as u32),
-        nrf5x::pinmux::Pinmux::new(uart_pins.rxd as u32),
-        nrf5x::pinmux::Pinmux::new(uart_pins.cts as u32),
-        nrf5x::pinmux::Pinmux::new(uart_pins.rts as u32),
-    );
     let console = static_init!(
         capsules::console::Console<UartDevice>,
         capsules::console::Console::new(
@@ -301,20 +336,35 @@ pub unsafe fn setup_board(
     );
     kernel::hil::sensors::TemperatureDriver::set_client(&nrf5x::temperature::TEMP, temp);
 
-    let entropy_to_random = static_init!(
-        capsules::rng::Entropy32ToRandom<'static>,
-        capsules::rng::Entropy32ToRandom::new(&nrf5x::trng::TRNG)
+    let csprng = static_init!(
+        capsules::csprng::Csprng<'static>,
+        capsules::csprng::Csprng::new(&nrf5x::trng::TRNG)
     );
 
     let rng = static_init!(
         capsules::rng::RngDriver<'static>,
         capsules::rng::RngDriver::new(
-            entropy_to_random,
+            csprng,
             board_kernel.create_grant(&memory_allocation_capability)
         )
     );
-    nrf5x::trng::TRNG.set_client(entropy_to_random);
-    entropy_to_random.set_client(rng);
+    csprng.start();
+    csprng.set_client(rng);
+
+    // Resolvable private addresses for BLE advertisements
+    let rpa_generator = static_init!(
+        capsules::ble_privacy::AesRpaGenerator<'static, nrf5x::aes::AesECB<'static>>,
+        capsules::ble_privacy::AesRpaGenerator::new(
+            &nrf5x::aes::AESECB,
+            csprng,
+            static_init!([u8; 16], [0; 16]),
+            static_init!([u8; 16], [0; 16])
+        )
+    );
+    hil::symmetric_encryption::AES128::set_client(&nrf5x::aes::AESECB, rpa_generator);
+    hil::symmetric_encryption::AES128::enable(&nrf5x::aes::AESECB);
+    rpa_generator.set_client(ble_radio);
+    ble_radio.set_address_generator(rpa_generator);
 
     // SPI
     let mux_spi = static_init!(
@@ -402,17 +452,14 @@ pub unsafe fn setup_board(
         None
     };
 
-    // Start all of the clocks. Low power operation will require a better
-    // approach than this.
+    // The RTC always needs the LFCLK. The HFXO is requested by the peripherals
+    // that need it, like the radio, so the chip can deep sleep without them.
     nrf52::clock::CLOCK.low_stop();
     nrf52::clock::CLOCK.high_stop();
 
     nrf52::clock::CLOCK.low_set_source(nrf52::clock::LowClockSource::XTAL);
-    nrf52::clock::CLOCK.low_start();
-    nrf52::clock::CLOCK.high_set_source(nrf52::clock::HighClockSource::XTAL);
-    nrf52::clock::CLOCK.high_start();
+    nrf52::clock::CLOCK.request(nrf52::clock::ManagedClock::Low, 0);
     while !nrf52::clock::CLOCK.low_started() {}
-    while !nrf52::clock::CLOCK.high_started() {}
 
     let platform = Platform {
         button: button,
@@ -442,8 +489,8 @@ pub unsafe fn setup_board(
         chip.mpu(),
         &_sapps as *const u8,
         app_memory,
-        process_pointers,
         app_fault_response,
+        None,
         &process_management_capability,
     );
 
@@ -27,6 +27,7 @@
 //!     )
 //! );
 //! sam4l::adc::ADC0.set_client(adc);
+//! sam4l::adc::ADC0.set_scan_client(adc);
 //! ```
 
 use core::cell::Cell;
@@ -40,7 +41,13 @@ pub const DRIVER_NUM: usize = 0x00000005;
 
 /// ADC application driver, used by applications to interact with ADC.
 /// Not currently virtualized, only one application can use it at a time.
-pub struct Adc<'a, A: hil::adc::Adc + hil::adc::AdcHighSpeed> {
+pub struct Adc<
+    'a,
+    A: hil::adc::Adc
+        + hil::adc::AdcHighSpeed
+        + hil::adc::AdcScan
+        + hil::analog_comparator::EventSink<Channel = <A as hil::adc::Adc>::Channel>,
+> {
     // ADC driver
     adc: &'a A,
     channels: &'a [&'a <A as hil::adc::Adc>::Channel],
@@ -58,6 +65,7 @@ pub struct Adc<'a, A: hil::adc::Adc + hil::adc::AdcHighSpeed> {
     samples_outstanding: Cell<usize>,
     next_samples_outstanding: Cell<usize>,
     using_app_buf1: Cell<bool>,
+    dropouts: Cell<usize>,
 
     // ADC buffers
     adc_buf1: TakeCell<'static, [u16]>,
@@ -74,6 +82,8 @@ enum AdcMode {
     ContinuousSample = 1,
     SingleBuffer = 2,
     ContinuousBuffer = 3,
+    EventSample = 4,
+    Scan = 5,
 }
 
 /// Holds buffers that the application has passed us
@@ -83,6 +93,9 @@ pub struct App {
     app_buf2: Option<AppSlice<Shared, u8>>,
 }
 
+/// The most channels a scan can sample, if the ADC supports as many
+const MAX_SCAN_CHANNELS: usize = 16;
+
 /// Buffers to use for DMA transfers
 /// The size is chosen somewhat arbitrarily, but has been tested. At 175000 Hz,
 /// buffers need to be swapped every 70 us and copied over before the next
@@ -92,7 +105,12 @@ pub static mut ADC_BUFFER2: [u16; 128] = [0; 128];
 pub static mut ADC_BUFFER3: [u16; 128] = [0; 128];
 
 /// Functions to create, initialize, and interact with the ADC
-impl<A: hil::adc::Adc + hil::adc::AdcHighSpeed> Adc<'a, A> {
+impl<
+    A: hil::adc::Adc
+        + hil::adc::AdcHighSpeed
+        + hil::adc::AdcScan
+        + hil::analog_comparator::EventSink<Channel = <A as hil::adc::Adc>::Channel>,
+> Adc<'a, A> {
     /// Create a new Adc application interface
     ///
     /// adc - ADC driver to provide application access to
@@ -124,6 +142,7 @@ impl<A: hil::adc::Adc + hil::adc::AdcHighSpeed> Adc<'a, A> {
             samples_outstanding: Cell::new(0),
             next_samples_outstanding: Cell::new(0),
             using_app_buf1: Cell::new(true),
+            dropouts: Cell::new(0),
 
             // ADC buffers
             adc_buf1: TakeCell::new(adc_buf1),
@@ -236,6 +255,110 @@ impl<A: hil::adc::Adc + hil::adc::AdcHighSpeed> Adc<'a, A> {
         ReturnCode::SUCCESS
     }
 
+    /// Collect a single analog sample on a channel every time the output of an
+    /// analog comparator changes. The comparator triggers the conversions in
+    /// hardware, so the CPU is only woken up when a sample is ready.
+    ///
+    /// channel - index into `channels` array, which channel to sample
+    /// ac - the analog comparator whose output changes trigger samples
+    fn sample_on_events(&self, channel: usize, ac: usize) -> ReturnCode {
+        // only one sample at a time
+        if self.active.get() {
+            return ReturnCode::EBUSY;
+        }
+
+        // convert channel index
+        if channel >= self.channels.len() {
+            return ReturnCode::EINVAL;
+        }
+        let chan = self.channels[channel];
+
+        // save state for callback
+        self.active.set(true);
+        self.mode.set(AdcMode::EventSample);
+        self.channel.set(channel);
+
+        // connect the ADC to the comparator events
+        let res = self.adc.start_on_events(ac, chan);
+        if res != ReturnCode::SUCCESS {
+            // failure, clear state
+            self.active.set(false);
+            self.mode.set(AdcMode::NoMode);
+
+            return res;
+        }
+
+        ReturnCode::SUCCESS
+    }
+
+    /// Sample several channels once, in order, into the first app buffer
+    /// provided. The samples are stored in consecutive entries of the buffer
+    ///
+    /// channel_mask - bit `i` selects index `i` into the `channels` array;
+    ///                the channels are sampled in increasing index order
+    fn scan(&self, channel_mask: usize) -> ReturnCode {
+        // only one sample at a time
+        if self.active.get() {
+            return ReturnCode::EBUSY;
+        }
+
+        // convert channel indexes
+        if channel_mask == 0 || self.channels.len() < 1 || channel_mask >> self.channels.len() != 0
+        {
+            return ReturnCode::EINVAL;
+        }
+        let mut sequence = [self.channels[0]; MAX_SCAN_CHANNELS];
+        let mut length = 0;
+        for (index, channel) in self.channels.iter().enumerate() {
+            if channel_mask & (1 << index) != 0 {
+                if length == MAX_SCAN_CHANNELS {
+                    return ReturnCode::EINVAL;
+                }
+                sequence[length] = channel;
+                length += 1;
+            }
+        }
+
+        // the samples have to fit the app buffer
+        let app_buf_length = self.app.map_or(0, |state| {
+            state.app_buf1.as_mut().map_or(0, |buf| buf.len())
+        });
+        if app_buf_length == 0 {
+            return ReturnCode::ENOMEM;
+        }
+        if app_buf_length < length * 2 {
+            return ReturnCode::ESIZE;
+        }
+
+        let res = self.adc.configure_scan(&sequence[..length]);
+        if res != ReturnCode::SUCCESS {
+            return res;
+        }
+
+        // save state for callback
+        self.active.set(true);
+        self.mode.set(AdcMode::Scan);
+        self.channel.set(channel_mask);
+
+        // start the scan
+        let res = self.adc_buf1.take().map_or(ReturnCode::EBUSY, |buf| {
+            let (rc, retbuf) = self.adc.scan(buf);
+            retbuf.map(|buf| {
+                self.replace_buffer(buf);
+            });
+            rc
+        });
+        if res != ReturnCode::SUCCESS {
+            // failure, clear state
+            self.active.set(false);
+            self.mode.set(AdcMode::NoMode);
+
+            return res;
+        }
+
+        ReturnCode::SUCCESS
+    }
+
     /// Collect a buffer-full of analog samples
     /// Samples are collected into the first app buffer provided. The number of
     /// samples collected is equal to the size of the buffer "allowed"
@@ -268,6 +391,7 @@ impl<A: hil::adc::Adc + hil::adc::AdcHighSpeed> Adc<'a, A> {
         self.active.set(true);
         self.mode.set(AdcMode::SingleBuffer);
         self.app_buf_offset.set(0);
+        self.dropouts.set(0);
         self.channel.set(channel);
 
         // start a continuous sample
@@ -355,6 +479,7 @@ impl<A: hil::adc::Adc + hil::adc::AdcHighSpeed> Adc<'a, A> {
         self.active.set(true);
         self.mode.set(AdcMode::ContinuousBuffer);
         self.app_buf_offset.set(0);
+        self.dropouts.set(0);
         self.channel.set(channel);
 
         // start a continuous sample
@@ -431,12 +556,17 @@ impl<A: hil::adc::Adc + hil::adc::AdcHighSpeed> Adc<'a, A> {
         }
 
         // clean up state
+        let mode = self.mode.get();
         self.active.set(false);
         self.mode.set(AdcMode::NoMode);
         self.app_buf_offset.set(0);
 
         // actually cancel the operation
-        let rc = self.adc.stop_sampling();
+        let rc = if mode == AdcMode::EventSample {
+            self.adc.stop_on_events()
+        } else {
+            self.adc.stop_sampling()
+        };
         if rc != ReturnCode::SUCCESS {
             return rc;
         }
@@ -458,7 +588,12 @@ impl<A: hil::adc::Adc + hil::adc::AdcHighSpeed> Adc<'a, A> {
 }
 
 /// Callbacks from the ADC driver
-impl<A: hil::adc::Adc + hil::adc::AdcHighSpeed> hil::adc::Client for Adc<'a, A> {
+impl<
+    A: hil::adc::Adc
+        + hil::adc::AdcHighSpeed
+        + hil::adc::AdcScan
+        + hil::analog_comparator::EventSink<Channel = <A as hil::adc::Adc>::Channel>,
+> hil::adc::Client for Adc<'a, A> {
     /// Single sample operation complete
     /// Collects the sample and provides a callback to the application
     ///
@@ -477,13 +612,16 @@ impl<A: hil::adc::Adc + hil::adc::AdcHighSpeed> hil::adc::Client for Adc<'a, A>
                     sample as usize,
                 );
             });
-        } else if self.active.get() && self.mode.get() == AdcMode::ContinuousSample {
+        } else if self.active.get()
+            && (self.mode.get() == AdcMode::ContinuousSample
+                || self.mode.get() == AdcMode::EventSample)
+        {
             // sample ready in continuous sampling operation, keep state
 
             // perform callback
             self.callback.map(|callback| {
                 callback.schedule(
-                    AdcMode::ContinuousSample as usize,
+                    self.mode.get() as usize,
                     self.channel.get(),
                     sample as usize,
                 );
@@ -498,7 +636,18 @@ impl<A: hil::adc::Adc + hil::adc::AdcHighSpeed> hil::adc::Client for Adc<'a, A>
 }
 
 /// Callbacks from the High Speed ADC driver
-impl<A: hil::adc::Adc + hil::adc::AdcHighSpeed> hil::adc::HighSpeedClient for Adc<'a, A> {
+impl<
+    A: hil::adc::Adc
+        + hil::adc::AdcHighSpeed
+        + hil::adc::AdcScan
+        + hil::analog_comparator::EventSink<Channel = <A as hil::adc::Adc>::Channel>,
+> hil::adc::HighSpeedClient for Adc<'a, A> {
+    /// Samples were lost before the next internal buffer. Counted so the
+    /// application can tell that its buffers have gaps
+    fn samples_dropped(&self) {
+        self.dropouts.set(self.dropouts.get() + 1);
+    }
+
     /// Internal buffer has filled from a buffered sampling operation.
     /// Copies data over to application buffer, determines if more data is
     /// needed, and performs a callback to the application if ready. If
@@ -754,8 +903,62 @@ impl<A: hil::adc::Adc + hil::adc::AdcHighSpeed> hil::adc::HighSpeedClient for Ad
     }
 }
 
+/// Callbacks from scans of the ADC driver
+impl<
+    A: hil::adc::Adc
+        + hil::adc::AdcHighSpeed
+        + hil::adc::AdcScan
+        + hil::analog_comparator::EventSink<Channel = <A as hil::adc::Adc>::Channel>,
+> hil::adc::ScanClient for Adc<'a, A> {
+    /// Scan complete
+    /// Copies the samples to the application buffer and provides a callback
+    /// to the application
+    ///
+    /// buffer - internal buffer holding a sample of each scanned channel
+    /// length - number of channels scanned
+    fn scan_done(&self, buffer: &'static mut [u16], length: usize) {
+        if self.active.get() && self.mode.get() == AdcMode::Scan {
+            // scan complete, clean up state
+            self.active.set(false);
+            self.mode.set(AdcMode::NoMode);
+
+            self.app.map(|state| {
+                state.app_buf1.as_mut().map(|app_buf| {
+                    // split each sample into its two bytes, like buffered
+                    // samples
+                    for (chunk, &sample) in app_buf.chunks_mut(2).zip(buffer.iter()).take(length) {
+                        let mut val = sample;
+                        for byte in chunk.iter_mut() {
+                            *byte = (val & 0xFF) as u8;
+                            val = val >> 8;
+                        }
+                    }
+
+                    // perform callback
+                    self.callback.map(|callback| {
+                        callback.schedule(AdcMode::Scan as usize, length, app_buf.ptr() as usize);
+                    });
+                });
+            });
+        } else {
+            // operation probably canceled. Make sure state is consistent. No
+            // callback
+            self.active.set(false);
+            self.mode.set(AdcMode::NoMode);
+        }
+
+        // still need to replace the buffer
+        self.replace_buffer(buffer);
+    }
+}
+
 /// Implementations of application syscalls
-impl<A: hil::adc::Adc + hil::adc::AdcHighSpeed> Driver for Adc<'a, A> {
+impl<
+    A: hil::adc::Adc
+        + hil::adc::AdcHighSpeed
+        + hil::adc::AdcScan
+        + hil::analog_comparator::EventSink<Channel = <A as hil::adc::Adc>::Channel>,
+> Driver for Adc<'a, A> {
     /// Provides access to a buffer from the application to store data in or
     /// read data from
     ///
@@ -851,6 +1054,18 @@ impl<A: hil::adc::Adc + hil::adc::AdcHighSpeed> Driver for Adc<'a, A> {
             // Stop sampling
             5 => self.stop_sampling(),
 
+            // Single samples on a channel, triggered by an analog comparator
+            6 => self.sample_on_events(channel, frequency),
+
+            // Number of times samples were lost since buffered sampling
+            // started
+            7 => ReturnCode::SuccessWithValue {
+                value: self.dropouts.get(),
+            },
+
+            // Scan of several channels
+            8 => self.scan(channel),
+
             // default
             _ => ReturnCode::ENOSUPPORT,
         }
@@ -0,0 +1,256 @@
+//! Provides userspace access to AES-128-GCM authenticated encryption.
+//!
+//! Processes share a key, a nonce and a data buffer holding the additional
+//! data, the message and room for the tag, and ask for them to be encrypted
+//! or decrypted. One process is served at a time: its data is copied into a
+//! kernel buffer for the operation, and the result is copied back before the
+//! callback. A process whose request fails while another one is served
+//! should retry after a while.
+//!
+//! With a key provider, such as the keystore, processes can encrypt with a
+//! key selected by handle instead of sharing it, so that they never hold
+//! the key.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! static mut AES_BUF: [u8; 256] = [0; 256];
+//!
+//! let aes = static_init!(
+//!     capsules::aes::AesDriver<
+//!         'static,
+//!         capsules::aes_gcm::AES128GCM<'static, sam4l::aes::Aes<'static>>,
+//!     >,
+//!     capsules::aes::AesDriver::new(
+//!         aes_gcm,
+//!         &mut AES_BUF,
+//!         board_kernel.create_grant(&memory_allocation_capability)
+//!     )
+//! );
+//! hil::symmetric_encryption::AES128GCM::set_client(aes_gcm, aes);
+//! aes.set_key_provider(keystore);
+//! ```
+
+use keystore::{KeyKind, KeyProvider};
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::symmetric_encryption::{
+    GCMClient, AES128GCM, AES128_KEY_SIZE, GCM_NONCE_LENGTH, GCM_TAG_LENGTH,
+};
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+/// Syscall number
+pub const DRIVER_NUM: usize = 0x40000;
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    key: Option<AppSlice<Shared, u8>>,
+    nonce: Option<AppSlice<Shared, u8>>,
+    data: Option<AppSlice<Shared, u8>>,
+    /// The handle of the key to use instead of the key buffer
+    key_handle: Option<usize>,
+}
+
+pub struct AesDriver<'a, G: AES128GCM<'a> + 'a> {
+    gcm: &'a G,
+    apps: Grant<App>,
+    /// The process served, and the lengths of its additional data and
+    /// message
+    serving: OptionalCell<(AppId, usize, usize)>,
+    buffer: TakeCell<'static, [u8]>,
+    keys: OptionalCell<&'a KeyProvider>,
+}
+
+impl<G: AES128GCM<'a>> AesDriver<'a, G> {
+    pub fn new(gcm: &'a G, buffer: &'static mut [u8], grant: Grant<App>) -> AesDriver<'a, G> {
+        AesDriver {
+            gcm: gcm,
+            apps: grant,
+            serving: OptionalCell::empty(),
+            buffer: TakeCell::new(buffer),
+            keys: OptionalCell::empty(),
+        }
+    }
+
+    /// Lets processes use the keys of `keys` by handle.
+    pub fn set_key_provider(&self, keys: &'a KeyProvider) {
+        self.keys.set(keys);
+    }
+
+    /// Starts encrypting or decrypting the data of `appid`. The buffer is
+    /// only returned once an operation ends, even if its process no longer
+    /// exists, so an operation is in progress while it is taken.
+    fn start(&self, appid: AppId, a_len: usize, m_len: usize, encrypting: bool) -> ReturnCode {
+        if self.buffer.is_none() {
+            return ReturnCode::EBUSY;
+        }
+        let len = match a_len
+            .checked_add(m_len)
+            .and_then(|len| len.checked_add(GCM_TAG_LENGTH))
+        {
+            Some(len) => len,
+            None => return ReturnCode::EINVAL,
+        };
+        self.apps
+            .enter(appid, |app, _| {
+                let mut key = [0; AES128_KEY_SIZE];
+                let result = match app.key_handle {
+                    Some(handle) => self.keys.map_or(ReturnCode::ENOSUPPORT, |keys| {
+                        keys.copy_key(appid, handle, KeyKind::Aes128, &mut key)
+                    }),
+                    None => match app.key.as_ref().map(|key| key.as_ref()) {
+                        Some(app_key) if app_key.len() == AES128_KEY_SIZE => {
+                            key.copy_from_slice(app_key);
+                            ReturnCode::SUCCESS
+                        }
+                        _ => ReturnCode::EINVAL,
+                    },
+                };
+                if result != ReturnCode::SUCCESS {
+                    return result;
+                }
+                let nonce = app.nonce.as_ref().map_or(&[][..], |nonce| nonce.as_ref());
+                let data = app.data.as_ref().map_or(&[][..], |data| data.as_ref());
+                if nonce.len() != GCM_NONCE_LENGTH {
+                    return ReturnCode::EINVAL;
+                }
+                if data.len() < len {
+                    return ReturnCode::ESIZE;
+                }
+                let buffer = match self.buffer.take() {
+                    Some(buffer) => buffer,
+                    None => return ReturnCode::EBUSY,
+                };
+                if buffer.len() < len {
+                    self.buffer.replace(buffer);
+                    return ReturnCode::ESIZE;
+                }
+                buffer[..len].copy_from_slice(&data[..len]);
+
+                self.gcm.set_key(&key);
+                self.gcm.set_nonce(nonce);
+                for byte in key.iter_mut() {
+                    *byte = 0;
+                }
+                let (result, buffer) =
+                    self.gcm
+                        .crypt(buffer, 0, a_len, m_len, GCM_TAG_LENGTH, encrypting);
+                match buffer {
+                    Some(buffer) => {
+                        buffer.iter_mut().for_each(|b| *b = 0);
+                        self.buffer.replace(buffer);
+                    }
+                    None => self.serving.set((appid, a_len, m_len)),
+                }
+                result
+            }).unwrap_or_else(|err| err.into())
+    }
+}
+
+impl<G: AES128GCM<'a>> GCMClient for AesDriver<'a, G> {
+    fn crypt_done(&self, buf: &'static mut [u8], res: ReturnCode, tag_is_valid: bool) {
+        self.serving.take().map(|(appid, a_len, m_len)| {
+            let len = a_len + m_len + GCM_TAG_LENGTH;
+            let _ = self.apps.enter(appid, |app, _| {
+                if res == ReturnCode::SUCCESS {
+                    app.data.as_mut().map(|data| {
+                        data.as_mut()[a_len..len].copy_from_slice(&buf[a_len..len]);
+                    });
+                }
+                app.callback
+                    .map(|mut cb| cb.schedule(usize::from(res), tag_is_valid as usize, 0));
+            });
+        });
+        // Do not leave the plaintext behind
+        buf.iter_mut().for_each(|b| *b = 0);
+        self.buffer.replace(buf);
+    }
+}
+
+impl<G: AES128GCM<'a>> Driver for AesDriver<'a, G> {
+    /// Share buffers with the driver.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: The key, 16 bytes.
+    /// - `1`: The nonce, 12 bytes. A nonce must never be used twice with
+    ///        the same key.
+    /// - `2`: The data: the additional data, the message, and 16 bytes for
+    ///        the tag.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 | 1 | 2 => self
+                .apps
+                .enter(appid, |app, _| {
+                    match allow_num {
+                        0 => app.key = slice,
+                        1 => app.nonce = slice,
+                        _ => app.data = slice,
+                    }
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Subscribe to the end of operations.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: An encryption or decryption ended. The callback is passed its
+    ///        result, and whether the tag is valid.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        appid: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Encrypt and decrypt.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Encrypt the message of `data2` bytes after the `data1` bytes
+    ///        of additional data, and write the tag after it.
+    /// - `2`: Decrypt the message of `data2` bytes after the `data1` bytes
+    ///        of additional data, checking the tag after it. The message is
+    ///        zeroed if the tag is not valid.
+    /// - `3`: Use the AES-128 key with handle `data1` of the key provider
+    ///        instead of the key buffer, or the key buffer again if `data1`
+    ///        is 0.
+    fn command(&self, command_num: usize, data1: usize, data2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => self.start(appid, data1, data2, true),
+            2 => self.start(appid, data1, data2, false),
+            3 => {
+                if data1 != 0 && self.keys.is_none() {
+                    return ReturnCode::ENOSUPPORT;
+                }
+                self.apps
+                    .enter(appid, |app, _| {
+                        app.key_handle = if data1 == 0 { None } else { Some(data1) };
+                        ReturnCode::SUCCESS
+                    }).unwrap_or_else(|err| err.into())
+            }
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
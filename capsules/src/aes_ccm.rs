@@ -36,6 +36,10 @@
 //! combine saved_tag and the unencrypted tag to form the encrypted tag and
 //! verify its correctness.
 //!
+//! The nonce may be between `CCM_MIN_NONCE_LENGTH` and `CCM_NONCE_LENGTH`
+//! bytes long. IEEE 802.15.4 uses 13-byte nonces, and TLS 12-byte nonces
+//! (RFC 6655). The shorter the nonce, the longer the messages it allows.
+//!
 //! Usage
 //! -----
 //!
@@ -55,7 +59,8 @@ use core::cell::Cell;
 use kernel::common::cells::{OptionalCell, TakeCell};
 use kernel::hil::symmetric_encryption;
 use kernel::hil::symmetric_encryption::{
-    AES128Ctr, AES128, AES128CBC, AES128_BLOCK_SIZE, AES128_KEY_SIZE, CCM_NONCE_LENGTH,
+    AES128Ctr, AES128, AES128CBC, AES128_BLOCK_SIZE, AES128_KEY_SIZE, CCM_MIN_NONCE_LENGTH,
+    CCM_NONCE_LENGTH,
 };
 use kernel::ReturnCode;
 use net::stream::SResult;
@@ -83,6 +88,7 @@ pub struct AES128CCM<'a, A: AES128<'a> + AES128Ctr + AES128CBC> {
     pos: Cell<(usize, usize, usize, usize)>,
     key: Cell<[u8; AES128_KEY_SIZE]>,
     nonce: Cell<[u8; CCM_NONCE_LENGTH]>,
+    nonce_len: Cell<usize>,
     saved_tag: Cell<[u8; AES128_BLOCK_SIZE]>,
 }
 
@@ -101,6 +107,7 @@ impl<A: AES128<'a> + AES128Ctr + AES128CBC> AES128CCM<'a, A> {
             pos: Cell::new((0, 0, 0, 0)),
             key: Cell::new(Default::default()),
             nonce: Cell::new(Default::default()),
+            nonce_len: Cell::new(CCM_NONCE_LENGTH),
             saved_tag: Cell::new(Default::default()),
         }
     }
@@ -110,7 +117,7 @@ impl<A: AES128<'a> + AES128Ctr + AES128CBC> AES128CCM<'a, A> {
     /// not present or if it is not long enough.
     fn prepare_ccm_buffer(
         &self,
-        nonce: &[u8; CCM_NONCE_LENGTH],
+        nonce: &[u8],
         mic_len: usize,
         a_data: &[u8],
         m_data: &[u8],
@@ -148,7 +155,7 @@ impl<A: AES128<'a> + AES128Ctr + AES128CBC> AES128CCM<'a, A> {
     /// guaranteed to be >= AES128_BLOCK_SIZE
     fn encode_ccm_buffer(
         buf: &mut [u8],
-        nonce: &[u8; CCM_NONCE_LENGTH],
+        nonce: &[u8],
         mic_len: usize,
         a_data: &[u8],
         m_data: &[u8],
@@ -156,7 +163,7 @@ impl<A: AES128<'a> + AES128Ctr + AES128CBC> AES128CCM<'a, A> {
         // IEEE 802.15.4-2015: Appendix B.4.1.2, CCM* authentication
         // The authentication tag T is computed with AES128-CBC-MAC on
         // B_0 | AuthData, where
-        //   B_0 = Flags (1 byte) | nonce (13 bytes) | m length (2 bytes)
+        //   B_0 = Flags (1 byte) | nonce (15 - L bytes) | m length (L bytes)
         //   Flags = 0 | A data present? (1 bit) | M (3 bits) | L (3 bits)
         //   AuthData = AddAuthData | PlaintextData
         //   AddAuthData = L(a) (encoding of a_data.len()) | a_data
@@ -165,6 +172,7 @@ impl<A: AES128<'a> + AES128Ctr + AES128CBC> AES128CCM<'a, A> {
         // The following code places B_0 | AuthData into crypt_buf.
 
         // flags = reserved | Adata | (M - 2) / 2 | (L - 1)
+        let l = AES128_BLOCK_SIZE - 1 - nonce.len();
         let mut flags: u8 = 0;
         if a_data.len() != 0 {
             flags |= 1 << 6;
@@ -172,14 +180,20 @@ impl<A: AES128<'a> + AES128Ctr + AES128CBC> AES128CCM<'a, A> {
         if mic_len != 0 {
             flags |= (((mic_len - 2) / 2) as u8) << 3;
         }
-        flags |= 1;
+        flags |= (l - 1) as u8;
+
+        // The length of the message must fit in L bytes
+        stream_cond!(l >= 8 || (m_data.len() as u64) >> (8 * l) == 0);
 
         stream_len_cond!(buf, AES128_BLOCK_SIZE);
-        // The first block is flags | nonce | m length
+        // The first block is flags | nonce | m length, with the length in
+        // big-endian
         buf[0] = flags;
-        buf[1..14].copy_from_slice(nonce.as_ref());
-        let mut off = enc_consume!(buf, 14; encode_u16,
-                                            (m_data.len() as u16).to_le());
+        buf[1..1 + nonce.len()].copy_from_slice(nonce);
+        for i in 0..l {
+            buf[AES128_BLOCK_SIZE - 1 - i] = ((m_data.len() as u64) >> (8 * i)) as u8;
+        }
+        let mut off = AES128_BLOCK_SIZE;
 
         // After that comes L(a) | a, where L(a) is the following
         // encoding of a_len:
@@ -279,10 +293,11 @@ impl<A: AES128<'a> + AES128Ctr + AES128CBC> AES128CCM<'a, A> {
         // });
 
         let mut iv = [0u8; AES128_BLOCK_SIZE];
-        // flags = reserved | reserved | 0 | (L - 1)
-        // Since L = 2, flags = 1.
-        iv[0] = 1;
-        iv[1..1 + CCM_NONCE_LENGTH].copy_from_slice(&self.nonce.get());
+        // flags = reserved | reserved | 0 | (L - 1), followed by the nonce
+        // and a counter of L bytes, which starts at 0
+        let nonce_len = self.nonce_len.get();
+        iv[0] = (AES128_BLOCK_SIZE - 2 - nonce_len) as u8;
+        iv[1..1 + nonce_len].copy_from_slice(&self.nonce.get()[..nonce_len]);
         let res = self.aes.set_iv(&iv);
         if res != ReturnCode::SUCCESS {
             return res;
@@ -433,12 +448,13 @@ impl<A: AES128<'a> + AES128Ctr + AES128CBC> symmetric_encryption::AES128CCM<'a>
     }
 
     fn set_nonce(&self, nonce: &[u8]) -> ReturnCode {
-        if nonce.len() < CCM_NONCE_LENGTH {
+        if nonce.len() < CCM_MIN_NONCE_LENGTH || nonce.len() > CCM_NONCE_LENGTH {
             ReturnCode::EINVAL
         } else {
             let mut new_nonce = [0u8; CCM_NONCE_LENGTH];
-            new_nonce.copy_from_slice(nonce);
+            new_nonce[..nonce.len()].copy_from_slice(nonce);
             self.nonce.set(new_nonce);
+            self.nonce_len.set(nonce.len());
             ReturnCode::SUCCESS
         }
     }
@@ -464,7 +480,7 @@ impl<A: AES128<'a> + AES128Ctr + AES128CBC> symmetric_encryption::AES128CCM<'a>
         self.encrypting.set(encrypting);
 
         let res = self.prepare_ccm_buffer(
-            &self.nonce.get(),
+            &self.nonce.get()[..self.nonce_len.get()],
             mic_len,
             &buf[a_off..m_off],
             &buf[m_off..m_off + m_len],
@@ -0,0 +1,320 @@
+//! Implements AES-GCM authenticated encryption/decryption using an underlying
+//! AES-CTR implementation, as described in NIST SP 800-38D.
+//!
+//! GCM encrypts the message in counter mode and authenticates the additional
+//! data and the ciphertext with GHASH, a polynomial hash keyed with the
+//! encryption of the zero block, H. The hash is computed in software, so
+//! only AES-CTR is needed from the hardware. Two passes of AES are performed:
+//! one over a zero block with counter 0 to compute H, and one over a zero
+//! block followed by the message, starting at counter J0 = nonce | 1.
+//!
+//! ```text
+//! crypt_buf: [ -- 1 blk -- | -------------- PData/CData -------------- ]
+//! hash key:   \___________/  (counter 0)
+//! aes_ctr:    \___________________________________________________________/
+//!              E(K, J0)      (counters J0 + 1, J0 + 2, ...)
+//! ```
+//!
+//! The first block of the second pass is E(K, J0), which is XORed with the
+//! GHASH of the additional data and the ciphertext to form the tag. For
+//! encryption, we append the tag to the message. For decryption, we compare
+//! it with the provided tag, and only copy out the plaintext if they match.
+//!
+//! The counter mode of the hardware increments the whole block, while GCM
+//! only increments its last 32 bits. They agree for the 96-bit nonces
+//! supported here, as the last 32 bits of the counter cannot overflow for
+//! messages that fit in memory.
+//!
+//! Usage
+//! -----
+//!
+//! ```
+//! const CRYPT_SIZE: usize = symmetric_encryption::AES128_BLOCK_SIZE + 256;
+//! static mut CRYPT_BUF: [u8; CRYPT_SIZE] = [0x00; CRYPT_SIZE];
+//!
+//! let aes_gcm = static_init!(
+//!     capsules::aes_gcm::AES128GCM<'static, sam4l::aes::Aes<'static>>,
+//!     capsules::aes_gcm::AES128GCM::new(&sam4l::aes::AES, &mut CRYPT_BUF)
+//! );
+//! sam4l::aes::AES.set_client(aes_gcm);
+//! sam4l::aes::AES.enable();
+//! ```
+//!
+//! Messages can be as long as `crypt_buf`, less one block.
+
+use core::cell::Cell;
+use crypto::ghash::Ghash;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::symmetric_encryption;
+use kernel::hil::symmetric_encryption::{
+    AES128Ctr, AES128, AES128_BLOCK_SIZE, AES128_KEY_SIZE, GCM_NONCE_LENGTH, GCM_TAG_LENGTH,
+};
+use kernel::ReturnCode;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum GCMState {
+    Idle,
+    HashKey,
+    Encrypt,
+}
+
+pub struct AES128GCM<'a, A: AES128<'a> + AES128Ctr> {
+    aes: &'a A,
+    crypt_buf: TakeCell<'a, [u8]>,
+    crypt_client: OptionalCell<&'a symmetric_encryption::GCMClient>,
+
+    state: Cell<GCMState>,
+    encrypting: Cell<bool>,
+
+    buf: TakeCell<'static, [u8]>,
+    pos: Cell<(usize, usize, usize, usize)>,
+    key: Cell<[u8; AES128_KEY_SIZE]>,
+    nonce: Cell<[u8; GCM_NONCE_LENGTH]>,
+    hash_key: Cell<[u8; AES128_BLOCK_SIZE]>,
+}
+
+impl<A: AES128<'a> + AES128Ctr> AES128GCM<'a, A> {
+    pub fn new(aes: &'a A, crypt_buf: &'static mut [u8]) -> AES128GCM<'a, A> {
+        AES128GCM {
+            aes: aes,
+            crypt_buf: TakeCell::new(crypt_buf),
+            crypt_client: OptionalCell::empty(),
+            state: Cell::new(GCMState::Idle),
+            encrypting: Cell::new(false),
+            buf: TakeCell::empty(),
+            pos: Cell::new((0, 0, 0, 0)),
+            key: Cell::new(Default::default()),
+            nonce: Cell::new(Default::default()),
+            hash_key: Cell::new(Default::default()),
+        }
+    }
+
+    /// The length of the message in crypt_buf, after the first block and
+    /// padded to a multiple of the block size.
+    fn crypt_len(m_len: usize) -> usize {
+        AES128_BLOCK_SIZE + (m_len + AES128_BLOCK_SIZE - 1) / AES128_BLOCK_SIZE * AES128_BLOCK_SIZE
+    }
+
+    /// Runs AES-CTR from `iv` over the first `len` bytes of crypt_buf, and
+    /// moves to `state` if it started.
+    fn start_ctr(&self, iv: &[u8; AES128_BLOCK_SIZE], len: usize, state: GCMState) -> ReturnCode {
+        let res = self.aes.set_key(&self.key.get());
+        if res != ReturnCode::SUCCESS {
+            return res;
+        }
+        let res = self.aes.set_iv(iv);
+        if res != ReturnCode::SUCCESS {
+            return res;
+        }
+
+        let crypt_buf = match self.crypt_buf.take() {
+            None => panic!("Cannot perform GCM because crypt_buf is not present."),
+            Some(buf) => buf,
+        };
+
+        // The keystream is the same both ways, so always encrypt the
+        // counter blocks.
+        self.aes.set_mode_aes128ctr(true);
+        self.aes.start_message();
+        match self.aes.crypt(None, crypt_buf, 0, len) {
+            None => {
+                self.state.set(state);
+                ReturnCode::SUCCESS
+            }
+            Some((res, _, crypt_buf)) => {
+                self.crypt_buf.replace(crypt_buf);
+                res
+            }
+        }
+    }
+
+    /// Computes H, the encryption of the zero block.
+    fn start_hash_key(&self) -> ReturnCode {
+        self.crypt_buf.map(|cbuf| {
+            cbuf[..AES128_BLOCK_SIZE].iter_mut().for_each(|b| *b = 0);
+        });
+        self.start_ctr(&[0; AES128_BLOCK_SIZE], AES128_BLOCK_SIZE, GCMState::HashKey)
+    }
+
+    /// Encrypts a zero block followed by the message from counter J0, which
+    /// gives E(K, J0) to encrypt the tag with, and the encrypted/decrypted
+    /// message.
+    fn start_gcm_encrypt(&self) -> ReturnCode {
+        let (_, m_off, m_len, _) = self.pos.get();
+        let crypt_len = Self::crypt_len(m_len);
+        self.crypt_buf.map(|cbuf| {
+            cbuf[..AES128_BLOCK_SIZE].iter_mut().for_each(|b| *b = 0);
+            self.buf.map(|buf| {
+                cbuf[AES128_BLOCK_SIZE..AES128_BLOCK_SIZE + m_len]
+                    .copy_from_slice(&buf[m_off..m_off + m_len]);
+            });
+            cbuf[AES128_BLOCK_SIZE + m_len..crypt_len]
+                .iter_mut()
+                .for_each(|b| *b = 0);
+        });
+
+        // J0 = nonce | 0x00000001
+        let mut iv = [0u8; AES128_BLOCK_SIZE];
+        iv[..GCM_NONCE_LENGTH].copy_from_slice(&self.nonce.get());
+        iv[AES128_BLOCK_SIZE - 1] = 1;
+        self.start_ctr(&iv, crypt_len, GCMState::Encrypt)
+    }
+
+    fn end_gcm(&self) {
+        let (a_off, m_off, m_len, tag_len) = self.pos.get();
+        let tag_valid = self.buf.map_or(false, |buf| {
+            self.crypt_buf.map_or_else(
+                || {
+                    panic!("We lost track of crypt_buf!");
+                },
+                |cbuf| {
+                    let m_end = m_off + m_len;
+                    let result = &cbuf[AES128_BLOCK_SIZE..AES128_BLOCK_SIZE + m_len];
+
+                    // The tag authenticates the ciphertext, which is the
+                    // result when encrypting, and the input when decrypting.
+                    let mut tag = [0u8; AES128_BLOCK_SIZE];
+                    let mut ghash = Ghash::new(&self.hash_key.get());
+                    ghash.update_padded(&buf[a_off..m_off]);
+                    if self.encrypting.get() {
+                        ghash.update_padded(result);
+                    } else {
+                        ghash.update_padded(&buf[m_off..m_end]);
+                    }
+                    ghash.finish(m_off - a_off, m_len, &mut tag);
+                    tag.iter_mut()
+                        .zip(cbuf[..AES128_BLOCK_SIZE].iter())
+                        .for_each(|(t, e)| *t ^= *e);
+
+                    if self.encrypting.get() {
+                        // Copy the encrypted message, and the tag after it
+                        buf[m_off..m_end].copy_from_slice(result);
+                        buf[m_end..m_end + tag_len].copy_from_slice(&tag[..tag_len]);
+                        true
+                    } else {
+                        // Compare every byte of the computed tag to the
+                        // received tag, so that the time taken does not
+                        // tell how much of a forged tag is right, and only
+                        // release the plaintext if it is authentic.
+                        let difference = buf[m_end..m_end + tag_len]
+                            .iter()
+                            .zip(tag[..tag_len].iter())
+                            .fold(0, |difference, (a, b)| difference | (a ^ b));
+                        if difference == 0 {
+                            buf[m_off..m_end].copy_from_slice(result);
+                        } else {
+                            buf[m_off..m_end].iter_mut().for_each(|b| *b = 0);
+                        }
+                        difference == 0
+                    }
+                },
+            )
+        });
+
+        // Do not leave the plaintext or the keystream behind
+        self.crypt_buf.map(|cbuf| {
+            cbuf[..Self::crypt_len(m_len)]
+                .iter_mut()
+                .for_each(|b| *b = 0);
+        });
+        self.hash_key.set(Default::default());
+
+        self.state.set(GCMState::Idle);
+        self.crypt_client.map(|client| {
+            self.buf.take().map(|buf| {
+                client.crypt_done(buf, ReturnCode::SUCCESS, tag_valid);
+            });
+        });
+    }
+}
+
+impl<A: AES128<'a> + AES128Ctr> symmetric_encryption::AES128GCM<'a> for AES128GCM<'a, A> {
+    fn set_client(&self, client: &'a symmetric_encryption::GCMClient) {
+        self.crypt_client.set(client);
+    }
+
+    fn set_key(&self, key: &[u8]) -> ReturnCode {
+        if key.len() < AES128_KEY_SIZE {
+            ReturnCode::EINVAL
+        } else {
+            let mut new_key = [0u8; AES128_KEY_SIZE];
+            new_key.copy_from_slice(&key[..AES128_KEY_SIZE]);
+            self.key.set(new_key);
+            ReturnCode::SUCCESS
+        }
+    }
+
+    fn set_nonce(&self, nonce: &[u8]) -> ReturnCode {
+        if nonce.len() < GCM_NONCE_LENGTH {
+            ReturnCode::EINVAL
+        } else {
+            let mut new_nonce = [0u8; GCM_NONCE_LENGTH];
+            new_nonce.copy_from_slice(&nonce[..GCM_NONCE_LENGTH]);
+            self.nonce.set(new_nonce);
+            ReturnCode::SUCCESS
+        }
+    }
+
+    fn crypt(
+        &self,
+        buf: &'static mut [u8],
+        a_off: usize,
+        m_off: usize,
+        m_len: usize,
+        tag_len: usize,
+        encrypting: bool,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.state.get() != GCMState::Idle {
+            return (ReturnCode::EBUSY, Some(buf));
+        }
+        if !(a_off <= m_off && m_off + m_len + tag_len <= buf.len()) {
+            return (ReturnCode::EINVAL, Some(buf));
+        }
+        if !(tag_len == 4 || tag_len == 8 || (tag_len >= 12 && tag_len <= GCM_TAG_LENGTH)) {
+            return (ReturnCode::EINVAL, Some(buf));
+        }
+        if self.crypt_buf.map_or(0, |cbuf| cbuf.len()) < Self::crypt_len(m_len) {
+            return (ReturnCode::ENOMEM, Some(buf));
+        }
+
+        self.encrypting.set(encrypting);
+        self.pos.set((a_off, m_off, m_len, tag_len));
+        self.buf.replace(buf);
+        let res = self.start_hash_key();
+        if res != ReturnCode::SUCCESS {
+            (res, self.buf.take())
+        } else {
+            (ReturnCode::SUCCESS, None)
+        }
+    }
+}
+
+impl<A: AES128<'a> + AES128Ctr> symmetric_encryption::Client<'a> for AES128GCM<'a, A> {
+    fn crypt_done(&self, _: Option<&'a mut [u8]>, crypt_buf: &'a mut [u8]) {
+        self.crypt_buf.replace(crypt_buf);
+        match self.state.get() {
+            GCMState::Idle => {}
+            GCMState::HashKey => {
+                self.crypt_buf.map(|cbuf| {
+                    let mut hash_key = [0u8; AES128_BLOCK_SIZE];
+                    hash_key.copy_from_slice(&cbuf[..AES128_BLOCK_SIZE]);
+                    self.hash_key.set(hash_key);
+                });
+
+                let res = self.start_gcm_encrypt();
+                if res != ReturnCode::SUCCESS {
+                    // Return client buffer to client
+                    self.state.set(GCMState::Idle);
+                    self.buf.take().map(|buf| {
+                        self.crypt_client.map(move |client| {
+                            client.crypt_done(buf, res, false);
+                        });
+                    });
+                }
+            }
+            GCMState::Encrypt => {
+                self.end_gcm();
+            }
+        }
+    }
+}
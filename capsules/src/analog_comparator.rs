@@ -11,11 +11,25 @@
 //!         &sam4l::acifc::CHANNEL_AC1,
 //!     ]
 //! );
+//! let ac_virtual_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     VirtualMuxAlarm::new(mux_alarm)
+//! );
 //! let analog_comparator = static_init!(
-//!     capsules::analog_comparator::AnalogComparator<'static, sam4l::acifc::Acifc>,
-//!     capsules::analog_comparator::AnalogComparator::new(&mut sam4l::acifc::ACIFC, ac_channels)
+//!     capsules::analog_comparator::AnalogComparator<
+//!         'static,
+//!         sam4l::acifc::Acifc,
+//!         VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     >,
+//!     capsules::analog_comparator::AnalogComparator::new(
+//!         &mut sam4l::acifc::ACIFC,
+//!         ac_channels,
+//!         ac_virtual_alarm,
+//!         board_kernel.create_grant(&grant_cap),
+//!     )
 //! );
 //! sam4l::acifc::ACIFC.set_client(analog_comparator);
+//! ac_virtual_alarm.set_client(analog_comparator);
 //! ```
 //!
 //! ## Number of Analog Comparators
@@ -25,6 +39,35 @@
 //! For a normal comparison or an interrupt-based comparison, just one analog
 //! comparator is necessary.
 //!
+//! ## Measurement Modes
+//! By default every comparison triggers a single measurement. A comparator can
+//! instead measure continuously, so that comparisons return its latched result
+//! right away, or in event-only mode, in which the applications that started
+//! it are only notified when its output changes.
+//!
+//! ## Window Comparison
+//! A window comparison uses two analog comparators, and checks whether a
+//! voltage lies within the window set by the other inputs of the pair. Window
+//! interrupts can fire when the voltage is inside or outside the window, or
+//! when it enters or leaves the window.
+//!
+//! ## Multiple Applications
+//! Each application registers its own callback, and is only notified of
+//! interrupts on the comparators it started interrupt-based comparisons on.
+//! A comparator keeps generating interrupts until all applications that
+//! started comparisons on it have stopped them.
+//!
+//! ## Hysteresis and Blanking
+//! To keep noisy signals from generating a storm of interrupts, comparators
+//! can apply hysteresis if the chip supports it. In addition, a blanking
+//! interval can be set: after an interrupt on a comparator, its interrupts are
+//! disabled for that interval. The blanking interval uses an alarm so that it
+//! works on chips without a hardware filter.
+//!
+//! ## Sleep
+//! A comparator can be enabled in sleep, so that it keeps comparing while the
+//! chip is in deep sleep and its interrupts wake the chip up.
+//!
 //! For more information on how this capsule works, please take a look at the
 //! README: 00007_analog_comparator.md in doc/syscalls.
 
@@ -36,29 +79,62 @@ pub const DRIVER_NUM: usize = 0x00007;
 
 use core::cell::Cell;
 use kernel::hil;
-use kernel::{AppId, Callback, Driver, ReturnCode};
+use kernel::hil::analog_comparator::{Hysteresis, InterruptMode, Reference, WindowInterruptMode};
+use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::{AppId, Callback, Driver, Grant, ReturnCode};
+
+/// The maximum number of comparators this capsule can handle.
+const MAX_CHANNELS: usize = 8;
+
+/// Per-app state: the callback for interrupts and bit arrays of the channels
+/// and windows the app has started interrupt-based comparisons on.
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    subscribed: u32,
+    subscribed_windows: u32,
+}
 
-pub struct AnalogComparator<'a, A: hil::analog_comparator::AnalogComparator + 'a> {
+pub struct AnalogComparator<'a, A: hil::analog_comparator::AnalogComparator + 'a, T: Alarm + 'a> {
     // Analog Comparator driver
     analog_comparator: &'a A,
     channels: &'a [&'a <A as hil::analog_comparator::AnalogComparator>::Channel],
 
+    // Blanking
+    alarm: &'a T,
+    blanking_ms: Cell<u32>,
+    /// Bit array of the channels whose interrupts are disabled until the alarm fires
+    blanked: Cell<u32>,
+    /// The interrupt condition each channel was last started with
+    modes: Cell<[InterruptMode; MAX_CHANNELS]>,
+    /// Bit array of the channels that were started in event-only mode
+    event_only: Cell<u32>,
+
     // App state
-    callback: Cell<Option<Callback>>,
+    apps: Grant<App>,
 }
 
-impl<'a, A: hil::analog_comparator::AnalogComparator> AnalogComparator<'a, A> {
+impl<'a, A: hil::analog_comparator::AnalogComparator, T: Alarm> AnalogComparator<'a, A, T> {
     pub fn new(
         analog_comparator: &'a A,
         channels: &'a [&'a <A as hil::analog_comparator::AnalogComparator>::Channel],
-    ) -> AnalogComparator<'a, A> {
+        alarm: &'a T,
+        grant: Grant<App>,
+    ) -> AnalogComparator<'a, A, T> {
         AnalogComparator {
             // Analog Comparator driver
             analog_comparator: analog_comparator,
             channels: channels,
 
+            // Blanking
+            alarm: alarm,
+            blanking_ms: Cell::new(0),
+            blanked: Cell::new(0),
+            modes: Cell::new([InterruptMode::VpGtVn; MAX_CHANNELS]),
+            event_only: Cell::new(0),
+
             // App state
-            callback: Cell::new(None),
+            apps: grant,
         }
     }
 
@@ -76,32 +152,260 @@ impl<'a, A: hil::analog_comparator::AnalogComparator> AnalogComparator<'a, A> {
         };
     }
 
+    // Do a single comparison on a window, made up of two consecutive channels
+    fn window_comparison(&self, window: usize) -> ReturnCode {
+        if window >= self.channels.len() / 2 {
+            return ReturnCode::EINVAL;
+        }
+        let result = self.analog_comparator.window_comparison(window);
+
+        return ReturnCode::SuccessWithValue {
+            value: result as usize,
+        };
+    }
+
     // Start comparing on a channel
-    fn start_comparing(&self, channel: usize) -> ReturnCode {
-        if channel >= self.channels.len() {
+    fn start_comparing(&self, channel: usize, mode: usize, appid: AppId) -> ReturnCode {
+        if channel >= self.channels.len() || channel >= MAX_CHANNELS {
             return ReturnCode::EINVAL;
         }
+        let mode = match mode {
+            0 => InterruptMode::VpGtVn,
+            1 => InterruptMode::VpLtVn,
+            2 => InterruptMode::Toggle,
+            _ => return ReturnCode::EINVAL,
+        };
+        let res = self
+            .apps
+            .enter(appid, |app, _| {
+                app.subscribed |= 1 << channel;
+                ReturnCode::SUCCESS
+            }).unwrap_or_else(|err| err.into());
+        if res != ReturnCode::SUCCESS {
+            return res;
+        }
+        let mut modes = self.modes.get();
+        modes[channel] = mode;
+        self.modes.set(modes);
+        self.event_only.set(self.event_only.get() & !(1 << channel));
+        self.blanked.set(self.blanked.get() & !(1 << channel));
+
         // Convert channel index
         let chan = self.channels[channel];
-        let result = self.analog_comparator.start_comparing(chan);
+        let result = self.analog_comparator.start_comparing(chan, mode);
 
         return result;
     }
 
-    // Stop comparing on a channel
-    fn stop_comparing(&self, channel: usize) -> ReturnCode {
+    // Stop comparing on a channel, unless other apps are still listening to it
+    fn stop_comparing(&self, channel: usize, appid: AppId) -> ReturnCode {
         if channel >= self.channels.len() {
             return ReturnCode::EINVAL;
         }
+        let res = self
+            .apps
+            .enter(appid, |app, _| {
+                app.subscribed &= !(1 << channel);
+                ReturnCode::SUCCESS
+            }).unwrap_or_else(|err| err.into());
+        if res != ReturnCode::SUCCESS {
+            return res;
+        }
+
+        let listeners = Cell::new(0);
+        self.apps.each(|app| {
+            if app.subscribed & (1 << channel) != 0 {
+                listeners.set(listeners.get() + 1);
+            }
+        });
+        if listeners.get() > 0 {
+            return ReturnCode::SUCCESS;
+        }
+        self.blanked.set(self.blanked.get() & !(1 << channel));
+
         // Convert channel index
         let chan = self.channels[channel];
         let result = self.analog_comparator.stop_comparing(chan);
 
         return result;
     }
+
+    // Measure continuously on a channel, so comparisons return right away
+    fn start_continuous(&self, channel: usize) -> ReturnCode {
+        if channel >= self.channels.len() {
+            return ReturnCode::EINVAL;
+        }
+        let chan = self.channels[channel];
+        self.analog_comparator.start_continuous(chan)
+    }
+
+    // Measure continuously on a channel, notifying the app only when the
+    // output changes
+    fn start_event_only(&self, channel: usize, appid: AppId) -> ReturnCode {
+        if channel >= self.channels.len() || channel >= MAX_CHANNELS {
+            return ReturnCode::EINVAL;
+        }
+        let res = self
+            .apps
+            .enter(appid, |app, _| {
+                app.subscribed |= 1 << channel;
+                ReturnCode::SUCCESS
+            }).unwrap_or_else(|err| err.into());
+        if res != ReturnCode::SUCCESS {
+            return res;
+        }
+        self.event_only.set(self.event_only.get() | (1 << channel));
+        self.blanked.set(self.blanked.get() & !(1 << channel));
+
+        let chan = self.channels[channel];
+        self.analog_comparator.start_event_only(chan)
+    }
+
+    // Stop continuous or event-only measurements on a channel. Returns
+    // `EBUSY` if other apps are still listening to it.
+    fn stop(&self, channel: usize, appid: AppId) -> ReturnCode {
+        if channel >= self.channels.len() {
+            return ReturnCode::EINVAL;
+        }
+        let res = self
+            .apps
+            .enter(appid, |app, _| {
+                app.subscribed &= !(1 << channel);
+                ReturnCode::SUCCESS
+            }).unwrap_or_else(|err| err.into());
+        if res != ReturnCode::SUCCESS {
+            return res;
+        }
+
+        let listeners = Cell::new(0);
+        self.apps.each(|app| {
+            if app.subscribed & (1 << channel) != 0 {
+                listeners.set(listeners.get() + 1);
+            }
+        });
+        if listeners.get() > 0 {
+            return ReturnCode::EBUSY;
+        }
+        self.event_only.set(self.event_only.get() & !(1 << channel));
+        self.blanked.set(self.blanked.get() & !(1 << channel));
+
+        let chan = self.channels[channel];
+        self.analog_comparator.stop(chan)
+    }
+
+    // Keep a channel comparing in deep sleep, or stop doing so
+    fn set_enabled_in_sleep(&self, channel: usize, enable: usize) -> ReturnCode {
+        if channel >= self.channels.len() {
+            return ReturnCode::EINVAL;
+        }
+        let chan = self.channels[channel];
+        match enable {
+            0 => self.analog_comparator.disable_in_sleep(chan),
+            1 => self.analog_comparator.enable_in_sleep(chan),
+            _ => ReturnCode::EINVAL,
+        }
+    }
+
+    // Select the negative input of a channel. The low byte of `source` selects
+    // the type of reference, the next byte its parameter.
+    fn set_reference(&self, channel: usize, source: usize) -> ReturnCode {
+        if channel >= self.channels.len() {
+            return ReturnCode::EINVAL;
+        }
+        let param = (source >> 8) as u8;
+        let reference = match source & 0xff {
+            0 => Reference::ExternalPin(param),
+            1 => Reference::Bandgap,
+            2 => Reference::ScaledVdd(param),
+            3 => Reference::Dac,
+            _ => return ReturnCode::EINVAL,
+        };
+        let chan = self.channels[channel];
+        self.analog_comparator.set_reference(chan, reference)
+    }
+
+    // Set the hysteresis of a channel
+    fn set_hysteresis(&self, channel: usize, level: usize) -> ReturnCode {
+        if channel >= self.channels.len() {
+            return ReturnCode::EINVAL;
+        }
+        let level = match level {
+            0 => Hysteresis::None,
+            1 => Hysteresis::Low,
+            2 => Hysteresis::Medium,
+            3 => Hysteresis::High,
+            _ => return ReturnCode::EINVAL,
+        };
+        let chan = self.channels[channel];
+        self.analog_comparator.set_hysteresis(chan, level)
+    }
+
+    // Disable the interrupts of a channel for the blanking interval
+    fn start_blanking(&self, channel: usize) {
+        let chan = self.channels[channel];
+        self.analog_comparator.stop_comparing(chan);
+        self.blanked.set(self.blanked.get() | (1 << channel));
+
+        let interval = self.blanking_ms.get() as u64 * T::Frequency::frequency() as u64 / 1000;
+        self.alarm
+            .set_alarm(self.alarm.now().wrapping_add(interval as u32));
+    }
+
+    // Start window interrupts on a window
+    fn enable_window_interrupts(&self, window: usize, mode: usize, appid: AppId) -> ReturnCode {
+        if window >= self.channels.len() / 2 {
+            return ReturnCode::EINVAL;
+        }
+        let mode = match mode {
+            0 => WindowInterruptMode::Inside,
+            1 => WindowInterruptMode::Outside,
+            2 => WindowInterruptMode::Entering,
+            3 => WindowInterruptMode::Leaving,
+            _ => return ReturnCode::EINVAL,
+        };
+        let res = self
+            .apps
+            .enter(appid, |app, _| {
+                app.subscribed_windows |= 1 << window;
+                ReturnCode::SUCCESS
+            }).unwrap_or_else(|err| err.into());
+        if res != ReturnCode::SUCCESS {
+            return res;
+        }
+        self.analog_comparator.enable_window_interrupts(window, mode)
+    }
+
+    // Stop window interrupts on a window, unless other apps are still listening to it
+    fn disable_window_interrupts(&self, window: usize, appid: AppId) -> ReturnCode {
+        if window >= self.channels.len() / 2 {
+            return ReturnCode::EINVAL;
+        }
+        let res = self
+            .apps
+            .enter(appid, |app, _| {
+                app.subscribed_windows &= !(1 << window);
+                ReturnCode::SUCCESS
+            }).unwrap_or_else(|err| err.into());
+        if res != ReturnCode::SUCCESS {
+            return res;
+        }
+
+        let listeners = Cell::new(0);
+        self.apps.each(|app| {
+            if app.subscribed_windows & (1 << window) != 0 {
+                listeners.set(listeners.get() + 1);
+            }
+        });
+        if listeners.get() > 0 {
+            return ReturnCode::SUCCESS;
+        }
+        self.analog_comparator.disable_window_interrupts(window)
+    }
 }
 
-impl<'a, A: hil::analog_comparator::AnalogComparator> Driver for AnalogComparator<'a, A> {
+impl<'a, A: hil::analog_comparator::AnalogComparator, T: Alarm> Driver
+    for AnalogComparator<'a, A, T>
+{
     /// Control the analog comparator.
     ///
     /// ### `command_num`
@@ -112,11 +416,39 @@ impl<'a, A: hil::analog_comparator::AnalogComparator> Driver for AnalogComparato
     ///        hail, 0-3 for imix)
     /// - `2`: Start interrupt-based comparisons.
     ///        Input x chooses the desired comparator ACx (e.g. 0 or 1 for
-    ///        hail, 0-3 for imix)
+    ///        hail, 0-3 for imix), input y the condition (0: Vp > Vn,
+    ///        1: Vp < Vn, 2: both crossings)
     /// - `3`: Stop interrupt-based comparisons.
     ///        Input x chooses the desired comparator ACx (e.g. 0 or 1 for
     ///        hail, 0-3 for imix)
-    fn command(&self, command_num: usize, channel: usize, _: usize, _: AppId) -> ReturnCode {
+    /// - `4`: Perform a window comparison.
+    ///        Input x chooses the desired window, made up of AC(2x) and
+    ///        AC(2x+1) (e.g. 0 for hail, 0 or 1 for imix)
+    /// - `5`: Start window interrupts.
+    ///        Input x chooses the desired window, input y the condition
+    ///        (0: inside, 1: outside, 2: entering, 3: leaving)
+    /// - `6`: Stop window interrupts.
+    ///        Input x chooses the desired window
+    /// - `7`: Select the negative input of a comparator.
+    ///        Input x chooses the desired comparator ACx, input y the source
+    ///        (bits 0-7: 0 external pin, 1 bandgap, 2 scaled VDD, 3 DAC;
+    ///        bits 8-15: pin number or sixteenths of VDD)
+    /// - `8`: Set the hysteresis of a comparator.
+    ///        Input x chooses the desired comparator ACx, input y the level
+    ///        (0: none, 1: low, 2: medium, 3: high)
+    /// - `9`: Set the blanking interval of all comparators.
+    ///        Input x is the interval in ms, 0 to disable blanking
+    /// - `10`: Start continuous measurements.
+    ///        Input x chooses the desired comparator ACx
+    /// - `11`: Start event-only measurements, notifying the app when the
+    ///        output changes.
+    ///        Input x chooses the desired comparator ACx
+    /// - `12`: Stop continuous or event-only measurements.
+    ///        Input x chooses the desired comparator ACx
+    /// - `13`: Keep a comparator comparing in deep sleep.
+    ///        Input x chooses the desired comparator ACx, input y enables
+    ///        (1) or disables (0) it in sleep
+    fn command(&self, command_num: usize, channel: usize, mode: usize, appid: AppId) -> ReturnCode {
         match command_num {
             0 => ReturnCode::SuccessWithValue {
                 value: self.channels.len() as usize,
@@ -124,9 +456,32 @@ impl<'a, A: hil::analog_comparator::AnalogComparator> Driver for AnalogComparato
 
             1 => self.comparison(channel),
 
-            2 => self.start_comparing(channel),
+            2 => self.start_comparing(channel, mode, appid),
+
+            3 => self.stop_comparing(channel, appid),
+
+            4 => self.window_comparison(channel),
+
+            5 => self.enable_window_interrupts(channel, mode, appid),
+
+            6 => self.disable_window_interrupts(channel, appid),
+
+            7 => self.set_reference(channel, mode),
+
+            8 => self.set_hysteresis(channel, mode),
+
+            9 => {
+                self.blanking_ms.set(channel as u32);
+                ReturnCode::SUCCESS
+            }
+
+            10 => self.start_continuous(channel),
 
-            3 => self.stop_comparing(channel),
+            11 => self.start_event_only(channel, appid),
+
+            12 => self.stop(channel, appid),
+
+            13 => self.set_enabled_in_sleep(channel, mode),
 
             _ => return ReturnCode::ENOSUPPORT,
         }
@@ -137,27 +492,68 @@ impl<'a, A: hil::analog_comparator::AnalogComparator> Driver for AnalogComparato
         &self,
         subscribe_num: usize,
         callback: Option<Callback>,
-        _app_id: AppId,
+        app_id: AppId,
     ) -> ReturnCode {
         match subscribe_num {
             // Subscribe to all interrupts
-            0 => {
-                self.callback.set(callback);
-                ReturnCode::SUCCESS
-            }
+            0 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
             // Default
             _ => ReturnCode::ENOSUPPORT,
         }
     }
 }
 
-impl<'a, A: hil::analog_comparator::AnalogComparator> hil::analog_comparator::Client
-    for AnalogComparator<'a, A>
+impl<'a, A: hil::analog_comparator::AnalogComparator, T: Alarm> hil::analog_comparator::Client
+    for AnalogComparator<'a, A, T>
 {
-    /// Callback to userland, signaling the application
+    /// Callback to userland, signaling the applications that started
+    /// comparisons on this channel
     fn fired(&self, channel: usize) {
-        self.callback
-            .get()
-            .map_or_else(|| false, |mut cb| cb.schedule(channel, 0, 0));
+        self.apps.each(|app| {
+            if app.subscribed & (1 << channel) != 0 {
+                app.callback.map(|mut cb| cb.schedule(channel, 0, 0));
+            }
+        });
+        if self.blanking_ms.get() > 0 && channel < self.channels.len() {
+            self.start_blanking(channel);
+        }
+    }
+
+    /// Callback to userland, signaling the applications that started window
+    /// comparisons on this window
+    fn window_fired(&self, window: usize) {
+        self.apps.each(|app| {
+            if app.subscribed_windows & (1 << window) != 0 {
+                app.callback.map(|mut cb| cb.schedule(window, 1, 0));
+            }
+        });
+    }
+}
+
+impl<'a, A: hil::analog_comparator::AnalogComparator, T: Alarm> time::Client
+    for AnalogComparator<'a, A, T>
+{
+    /// End of the blanking interval: re-enable the interrupts of the blanked
+    /// channels
+    fn fired(&self) {
+        let modes = self.modes.get();
+        let blanked = self.blanked.replace(0);
+        let event_only = self.event_only.get();
+        for channel in 0..self.channels.len() {
+            if blanked & (1 << channel) != 0 {
+                let chan = self.channels[channel];
+                if event_only & (1 << channel) != 0 {
+                    self.analog_comparator.start_event_only(chan);
+                } else {
+                    self.analog_comparator
+                        .start_comparing(chan, modes[channel]);
+                }
+            }
+        }
     }
 }
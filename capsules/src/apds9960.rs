@@ -0,0 +1,602 @@
+//! Driver for the Broadcom APDS-9960 light, proximity and gesture sensor.
+//!
+//! <https://docs.broadcom.com/docs/AV02-4191EN>
+//!
+//! > The APDS-9960 device features advanced Gesture detection, Proximity
+//! > detection, Digital Ambient Light Sense (ALS) and Color Sense (RGBC).
+//!
+//! It implements `hil::sensors::AmbientLight`, `hil::sensors::Proximity` and
+//! `hil::sensors::GestureSensor`. All three are driven by the interrupt
+//! output of the sensor, so the driver needs no alarm: a light reading sets
+//! thresholds that every conversion crosses, and a proximity reading sets
+//! the thresholds of the request. The engines that no request uses are
+//! turned off.
+//!
+//! A gesture is recognized from the first and the last datasets of the
+//! gesture FIFO with enough light on all four photodiodes. Its direction is
+//! the axis where the balance between the opposite photodiodes changed the
+//! most, in the orientation of the datasheet.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let apds9960_i2c = static_init!(
+//!     capsules::virtual_i2c::I2CDevice,
+//!     capsules::virtual_i2c::I2CDevice::new(i2c_bus, 0x39));
+//! let apds9960 = static_init!(
+//!     capsules::apds9960::Apds9960<'static>,
+//!     capsules::apds9960::Apds9960::new(
+//!         apds9960_i2c,
+//!         &sam4l::gpio::PA[8], // INT
+//!         &mut capsules::apds9960::BUFFER));
+//! apds9960_i2c.set_client(apds9960);
+//! sam4l::gpio::PA[8].set_client(apds9960);
+//! apds9960.configure();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::gpio;
+use kernel::hil::i2c;
+use kernel::hil::sensors;
+use kernel::ReturnCode;
+
+/// The gesture datasets read at a time, four bytes each
+const FIFO_CHUNK: usize = 8;
+
+pub static mut BUFFER: [u8; 4 * FIFO_CHUNK] = [0; 4 * FIFO_CHUNK];
+
+// Registers
+const ENABLE: u8 = 0x80;
+const ATIME: u8 = 0x81;
+const AILTL: u8 = 0x84;
+const AILTH: u8 = 0x85;
+const AIHTL: u8 = 0x86;
+const AIHTH: u8 = 0x87;
+const PILT: u8 = 0x89;
+const PIHT: u8 = 0x8b;
+const PERS: u8 = 0x8c;
+const PPULSE: u8 = 0x8e;
+const CONTROL: u8 = 0x8f;
+const ID: u8 = 0x92;
+const STATUS: u8 = 0x93;
+const CDATAL: u8 = 0x94;
+const PDATA: u8 = 0x9c;
+const GPENTH: u8 = 0xa0;
+const GEXTH: u8 = 0xa1;
+const GCONF1: u8 = 0xa2;
+const GCONF2: u8 = 0xa3;
+const GPULSE: u8 = 0xa6;
+const GCONF4: u8 = 0xab;
+const GFLVL: u8 = 0xae;
+const PICLEAR: u8 = 0xe5;
+const AICLEAR: u8 = 0xe7;
+const GFIFO_U: u8 = 0xfc;
+
+const ID_APDS9960: u8 = 0xab;
+
+// ENABLE
+const PON: u8 = 1 << 0;
+const AEN: u8 = 1 << 1;
+const PEN: u8 = 1 << 2;
+const AIEN: u8 = 1 << 4;
+const PIEN: u8 = 1 << 5;
+const GEN: u8 = 1 << 6;
+
+// STATUS
+const GINT: u8 = 1 << 2;
+const AINT: u8 = 1 << 4;
+const PINT: u8 = 1 << 5;
+
+// GCONF4
+const GMODE: u8 = 1 << 0;
+const GIEN: u8 = 1 << 1;
+
+/// The ALS integration time in cycles of 2.78 ms
+const ATIME_CYCLES: u16 = 37;
+
+/// The ALS gain of CONTROL
+const AGAIN: i64 = 4;
+
+/// The device factor of the lux approximation of application note DN40
+const DEVICE_FACTOR: i64 = 310;
+
+/// The least light on each photodiode for a gesture dataset to count
+const GESTURE_MIN: u8 = 10;
+
+/// The least change of the balance of an axis for a gesture, in percent
+const GESTURE_DELTA: i32 = 30;
+
+/// The register writes of the configuration
+const MAX_WRITES: usize = 13;
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Off,
+    Identify,
+    Writing,
+    Idle,
+    ReadStatus,
+    ReadLight,
+    ClearLight,
+    ReadProximity,
+    ClearProximity,
+    ReadGestureLevel,
+    /// The datasets being read, and the ones left after them
+    ReadGestureFifo(usize, usize),
+    ReadGestureMode,
+}
+
+pub struct Apds9960<'a> {
+    i2c: &'a i2c::I2CDevice,
+    int_pin: &'a gpio::Pin,
+    state: Cell<State>,
+    buffer: TakeCell<'static, [u8]>,
+    /// The register writes in progress, and the next one
+    writes: Cell<[(u8, u8); MAX_WRITES]>,
+    write_count: Cell<usize>,
+    write_index: Cell<usize>,
+    /// The STATUS flags of the interrupt being handled
+    status: Cell<u8>,
+    /// Whether the interrupt pin fired while the bus was busy
+    interrupt_pending: Cell<bool>,
+    /// The values of PILT, PIHT, GCONF4 and ENABLE last written
+    written: Cell<[u8; 4]>,
+    light_pending: Cell<bool>,
+    lux: Cell<usize>,
+    proximity_pending: Cell<bool>,
+    proximity: Cell<u8>,
+    /// The proximity below and above which the sensor interrupts
+    thresholds: Cell<(u8, u8)>,
+    gestures: Cell<bool>,
+    /// The first and the last dataset of the gesture, as U, D, L and R
+    gesture_first: Cell<Option<[u8; 4]>>,
+    gesture_last: Cell<[u8; 4]>,
+    light_client: OptionalCell<&'static sensors::AmbientLightClient>,
+    proximity_client: OptionalCell<&'static sensors::ProximityClient>,
+    gesture_client: OptionalCell<&'static sensors::GestureClient>,
+}
+
+impl Apds9960<'a> {
+    pub fn new(
+        i2c: &'a i2c::I2CDevice,
+        int_pin: &'a gpio::Pin,
+        buffer: &'static mut [u8],
+    ) -> Apds9960<'a> {
+        Apds9960 {
+            i2c: i2c,
+            int_pin: int_pin,
+            state: Cell::new(State::Off),
+            buffer: TakeCell::new(buffer),
+            writes: Cell::new([(0, 0); MAX_WRITES]),
+            write_count: Cell::new(0),
+            write_index: Cell::new(0),
+            status: Cell::new(0),
+            interrupt_pending: Cell::new(false),
+            written: Cell::new([0; 4]),
+            light_pending: Cell::new(false),
+            lux: Cell::new(0),
+            proximity_pending: Cell::new(false),
+            proximity: Cell::new(0),
+            thresholds: Cell::new((0, 0)),
+            gestures: Cell::new(false),
+            gesture_first: Cell::new(None),
+            gesture_last: Cell::new([0; 4]),
+            light_client: OptionalCell::empty(),
+            proximity_client: OptionalCell::empty(),
+            gesture_client: OptionalCell::empty(),
+        }
+    }
+
+    /// Identifies the sensor and configures its engines, which stay off
+    /// until requested. The other calls return EOFF until this is done.
+    pub fn configure(&self) -> ReturnCode {
+        if self.state.get() != State::Off {
+            return ReturnCode::EALREADY;
+        }
+        // The INT output is active low, and stays low until cleared
+        self.int_pin.make_input();
+        self.int_pin.enable_interrupt(0, gpio::InterruptMode::FallingEdge);
+        self.read(State::Identify, ID, 1);
+        ReturnCode::SUCCESS
+    }
+
+    fn read(&self, state: State, register: u8, len: usize) {
+        self.state.set(state);
+        self.buffer.take().map(|buffer| {
+            self.i2c.enable();
+            buffer[0] = register;
+            self.i2c.write_read(buffer, 1, len as u8);
+        });
+    }
+
+    fn write(&self, register: u8, value: u8) {
+        self.buffer.take().map(|buffer| {
+            self.i2c.enable();
+            buffer[0] = register;
+            buffer[1] = value;
+            self.i2c.write(buffer, 2);
+        });
+    }
+
+    /// Writes the address of a special function register, which clears an
+    /// interrupt.
+    fn clear(&self, state: State, register: u8) {
+        self.state.set(state);
+        self.buffer.take().map(|buffer| {
+            self.i2c.enable();
+            buffer[0] = register;
+            self.i2c.write(buffer, 1);
+        });
+    }
+
+    /// Writes a list of registers in order, and then returns to idle.
+    fn write_registers(&self, writes: &[(u8, u8)]) {
+        let mut list = [(0, 0); MAX_WRITES];
+        list[..writes.len()].copy_from_slice(writes);
+        self.writes.set(list);
+        self.write_count.set(writes.len());
+        self.write_index.set(1);
+        self.state.set(State::Writing);
+        self.write(writes[0].0, writes[0].1);
+    }
+
+    fn idle(&self) {
+        self.i2c.disable();
+        self.state.set(State::Idle);
+        let engines = self.engines();
+        if engines != self.written.get() {
+            self.write_engines(engines);
+        } else if self.interrupt_pending.get() {
+            self.interrupt_pending.set(false);
+            self.read(State::ReadStatus, STATUS, 1);
+        }
+    }
+
+    /// The values of PILT, PIHT, GCONF4 and ENABLE that turn on the engines
+    /// the requests use, and the others off.
+    fn engines(&self) -> [u8; 4] {
+        let mut enable = 0;
+        if self.light_pending.get() {
+            enable |= AEN | AIEN;
+        }
+        if self.proximity_pending.get() {
+            enable |= PEN | PIEN;
+        }
+        if self.gestures.get() {
+            // Gestures start when the proximity exceeds GPENTH
+            enable |= PEN | GEN;
+        }
+        if enable != 0 {
+            enable |= PON;
+        }
+        let gconf4 = if self.gestures.get() { GIEN } else { 0 };
+        let (low, high) = self.thresholds.get();
+        [low, high, gconf4, enable]
+    }
+
+    /// Writes the registers of `engines` that changed. Writing GCONF4 ends
+    /// a gesture in progress, so it is only written when needed.
+    fn write_engines(&self, engines: [u8; 4]) {
+        let registers = [PILT, PIHT, GCONF4, ENABLE];
+        let written = self.written.get();
+        let mut writes = [(0, 0); 4];
+        let mut count = 0;
+        for index in 0..registers.len() {
+            if engines[index] != written[index] {
+                writes[count] = (registers[index], engines[index]);
+                count += 1;
+            }
+        }
+        self.written.set(engines);
+        self.write_registers(&writes[..count]);
+    }
+
+    /// Applies a change of the requests, now if the driver is idle.
+    fn request(&self) {
+        if self.state.get() == State::Idle {
+            self.idle();
+        }
+    }
+
+    /// Handles the next flag of the interrupt.
+    fn next_interrupt(&self) {
+        let status = self.status.get();
+        if status & AINT != 0 {
+            self.read(State::ReadLight, CDATAL, 8);
+        } else if status & PINT != 0 {
+            self.read(State::ReadProximity, PDATA, 1);
+        } else if status & GINT != 0 {
+            self.read(State::ReadGestureLevel, GFLVL, 1);
+        } else {
+            // Check for interrupts that came meanwhile, which do not cause a
+            // new edge while the pin is low, once the engines are updated
+            self.interrupt_pending.set(true);
+            self.idle();
+        }
+    }
+
+    fn read_gesture_fifo(&self, datasets: usize) {
+        let count = cmp::min(datasets, FIFO_CHUNK);
+        // The address wraps from GFIFO_R back to GFIFO_U
+        self.read(State::ReadGestureFifo(count, datasets - count), GFIFO_U, 4 * count);
+    }
+
+    fn start_proximity(&self, low: u8, high: u8) -> ReturnCode {
+        if self.state.get() == State::Off {
+            return ReturnCode::EOFF;
+        }
+        if self.proximity_pending.get() {
+            return ReturnCode::EBUSY;
+        }
+        self.thresholds.set((low, high));
+        self.proximity_pending.set(true);
+        self.request();
+        ReturnCode::SUCCESS
+    }
+
+    fn decode_gesture(&self) -> Option<sensors::Gesture> {
+        let first = self.gesture_first.take()?;
+        let last = self.gesture_last.get();
+        let up_down = balance(last[0], last[1]) - balance(first[0], first[1]);
+        let left_right = balance(last[2], last[3]) - balance(first[2], first[3]);
+
+        if up_down.abs() < GESTURE_DELTA && left_right.abs() < GESTURE_DELTA {
+            None
+        } else if up_down.abs() > left_right.abs() {
+            if up_down < 0 {
+                Some(sensors::Gesture::Up)
+            } else {
+                Some(sensors::Gesture::Down)
+            }
+        } else if left_right < 0 {
+            Some(sensors::Gesture::Left)
+        } else {
+            Some(sensors::Gesture::Right)
+        }
+    }
+}
+
+/// The balance between two opposite photodiodes, in percent
+fn balance(a: u8, b: u8) -> i32 {
+    (a as i32 - b as i32) * 100 / (a as i32 + b as i32)
+}
+
+fn word(buffer: &[u8], index: usize) -> i64 {
+    ((buffer[2 * index + 1] as i64) << 8) | buffer[2 * index] as i64
+}
+
+/// Approximates the illuminance from the color channels as in application
+/// note DN40, removing the infrared first.
+fn lux(clear: i64, red: i64, green: i64, blue: i64) -> usize {
+    let ir = cmp::max(red + green + blue - clear, 0) / 2;
+    // The weights of the channels, in thousandths
+    let weighted = 136 * (red - ir) + 1000 * (green - ir) - 444 * (blue - ir);
+    // The integration time in hundredths of milliseconds
+    let time = ATIME_CYCLES as i64 * 278;
+    cmp::max(weighted * DEVICE_FACTOR * 100 / (1000 * time * AGAIN), 0) as usize
+}
+
+impl i2c::I2CClient for Apds9960<'a> {
+    fn command_complete(&self, buffer: &'static mut [u8], error: i2c::Error) {
+        if error != i2c::Error::CommandComplete {
+            self.buffer.replace(buffer);
+            if self.state.get() == State::Identify {
+                self.i2c.disable();
+                self.state.set(State::Off);
+            } else {
+                self.idle();
+            }
+            return;
+        }
+
+        match self.state.get() {
+            State::Identify => {
+                let found = buffer[0] == ID_APDS9960;
+                self.buffer.replace(buffer);
+                if found {
+                    self.write_registers(&[
+                        (ATIME, (256 - ATIME_CYCLES) as u8),
+                        // 100 mA LED, 4x proximity and ALS gain
+                        (CONTROL, 0x09),
+                        // 8 proximity pulses of 16 us
+                        (PPULSE, 0x87),
+                        // Interrupt after 1 proximity and each ALS conversion
+                        (PERS, 0x10),
+                        // ALS thresholds that every conversion crosses
+                        (AILTL, 0xff),
+                        (AILTH, 0xff),
+                        (AIHTL, 0x00),
+                        (AIHTH, 0x00),
+                        (GPENTH, 40),
+                        (GEXTH, 30),
+                        // Interrupt with 4 datasets in the FIFO
+                        (GCONF1, 0x40),
+                        // 4x gain, 100 mA LED, 2.8 ms between datasets
+                        (GCONF2, 0x41),
+                        // 10 gesture pulses of 32 us
+                        (GPULSE, 0xc9),
+                    ]);
+                } else {
+                    self.i2c.disable();
+                    self.state.set(State::Off);
+                }
+            }
+
+            State::Writing => {
+                self.buffer.replace(buffer);
+                let index = self.write_index.get();
+                if index < self.write_count.get() {
+                    self.write_index.set(index + 1);
+                    let (register, value) = self.writes.get()[index];
+                    self.write(register, value);
+                } else {
+                    self.idle();
+                }
+            }
+
+            State::ReadStatus => {
+                let status = buffer[0] & (AINT | PINT | GINT);
+                self.buffer.replace(buffer);
+                if status == 0 {
+                    self.idle();
+                } else {
+                    self.status.set(status);
+                    self.next_interrupt();
+                }
+            }
+
+            State::ReadLight => {
+                let (clear, red) = (word(buffer, 0), word(buffer, 1));
+                let (green, blue) = (word(buffer, 2), word(buffer, 3));
+                self.buffer.replace(buffer);
+                self.lux.set(lux(clear, red, green, blue));
+                self.clear(State::ClearLight, AICLEAR);
+            }
+
+            State::ClearLight => {
+                self.buffer.replace(buffer);
+                self.status.set(self.status.get() & !AINT);
+                if self.light_pending.get() {
+                    self.light_pending.set(false);
+                    self.light_client.map(|client| client.callback(self.lux.get()));
+                }
+                self.next_interrupt();
+            }
+
+            State::ReadProximity => {
+                self.proximity.set(buffer[0]);
+                self.buffer.replace(buffer);
+                self.clear(State::ClearProximity, PICLEAR);
+            }
+
+            State::ClearProximity => {
+                self.buffer.replace(buffer);
+                self.status.set(self.status.get() & !PINT);
+                if self.proximity_pending.get() {
+                    self.proximity_pending.set(false);
+                    self.proximity_client.map(|client| client.callback(self.proximity.get()));
+                }
+                self.next_interrupt();
+            }
+
+            State::ReadGestureLevel => {
+                let level = buffer[0] as usize;
+                self.buffer.replace(buffer);
+                if level > 0 {
+                    self.read_gesture_fifo(level);
+                } else {
+                    self.read(State::ReadGestureMode, GCONF4, 1);
+                }
+            }
+
+            State::ReadGestureFifo(count, remaining) => {
+                for dataset in buffer[..4 * count].chunks(4) {
+                    if dataset.iter().all(|&light| light >= GESTURE_MIN) {
+                        let dataset = [dataset[0], dataset[1], dataset[2], dataset[3]];
+                        if self.gesture_first.get().is_none() {
+                            self.gesture_first.set(Some(dataset));
+                        }
+                        self.gesture_last.set(dataset);
+                    }
+                }
+                self.buffer.replace(buffer);
+                if remaining > 0 {
+                    self.read_gesture_fifo(remaining);
+                } else {
+                    self.read(State::ReadGestureMode, GCONF4, 1);
+                }
+            }
+
+            State::ReadGestureMode => {
+                // The sensor leaves gesture mode when the gesture ends
+                let ended = buffer[0] & GMODE == 0;
+                self.buffer.replace(buffer);
+                self.status.set(self.status.get() & !GINT);
+                if ended {
+                    self.decode_gesture().map(|gesture| {
+                        self.gesture_client.map(|client| client.gesture(gesture));
+                    });
+                }
+                self.next_interrupt();
+            }
+
+            State::Off | State::Idle => {
+                self.buffer.replace(buffer);
+            }
+        }
+    }
+}
+
+impl gpio::Client for Apds9960<'a> {
+    fn fired(&self, _: usize) {
+        match self.state.get() {
+            State::Off => {}
+            State::Idle => self.read(State::ReadStatus, STATUS, 1),
+            _ => self.interrupt_pending.set(true),
+        }
+    }
+}
+
+impl sensors::AmbientLight for Apds9960<'a> {
+    fn set_client(&self, client: &'static sensors::AmbientLightClient) {
+        self.light_client.set(client);
+    }
+
+    fn read_light_intensity(&self) -> ReturnCode {
+        if self.state.get() == State::Off {
+            return ReturnCode::EOFF;
+        }
+        if self.light_pending.get() {
+            return ReturnCode::EBUSY;
+        }
+        self.light_pending.set(true);
+        self.request();
+        ReturnCode::SUCCESS
+    }
+}
+
+impl sensors::Proximity for Apds9960<'a> {
+    fn set_client(&self, client: &'static sensors::ProximityClient) {
+        self.proximity_client.set(client);
+    }
+
+    fn read_proximity(&self) -> ReturnCode {
+        // Thresholds that every reading crosses
+        self.start_proximity(0xff, 0x00)
+    }
+
+    fn read_proximity_on_interrupt(&self, low: u8, high: u8) -> ReturnCode {
+        self.start_proximity(low, high)
+    }
+}
+
+impl sensors::GestureSensor for Apds9960<'a> {
+    fn set_client(&self, client: &'static sensors::GestureClient) {
+        self.gesture_client.set(client);
+    }
+
+    fn enable_gestures(&self) -> ReturnCode {
+        if self.state.get() == State::Off {
+            return ReturnCode::EOFF;
+        }
+        if self.gestures.get() {
+            return ReturnCode::EALREADY;
+        }
+        self.gestures.set(true);
+        self.request();
+        ReturnCode::SUCCESS
+    }
+
+    fn disable_gestures(&self) -> ReturnCode {
+        if !self.gestures.get() {
+            return ReturnCode::EALREADY;
+        }
+        self.gestures.set(false);
+        self.gesture_first.set(None);
+        self.request();
+        ReturnCode::SUCCESS
+    }
+}
@@ -0,0 +1,79 @@
+//! Verifies the signatures of apps in software.
+//!
+//! This implements `kernel::procs::AppVerifier` for boards without a crypto
+//! accelerator, with `signature::verify_signature`. It accepts Ed25519 and
+//! ECDSA P-256 signatures by any of the public keys the board provisions.
+//! Verifying a signature takes millions of cycles, which delays loading
+//! processes at boot.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! static ED25519_KEYS: [[u8; 32]; 1] = [[...]];
+//! static P256_KEYS: [[u8; 64]; 0] = [];
+//!
+//! let app_verifier = static_init!(
+//!     capsules::app_verifier::SoftwareAppVerifier,
+//!     capsules::app_verifier::SoftwareAppVerifier::new(&ED25519_KEYS, &P256_KEYS)
+//! );
+//!
+//! kernel::procs::load_processes(
+//!     board_kernel,
+//!     &cortexm4::syscall::SysCall::new(),
+//!     chip.mpu(),
+//!     &_sapps as *const u8,
+//!     &mut APP_MEMORY,
+//!     &FAULT_RESPONSE,
+//!     Some(app_verifier),
+//!     &process_management_capability,
+//! );
+//! ```
+
+use crypto::sha256::{self, Sha256};
+use crypto::{ed25519, p256};
+use kernel::procs::{AppVerifier, SignatureAlgorithm};
+use signature::verify_signature;
+
+pub struct SoftwareAppVerifier {
+    /// The public keys of the board for Ed25519
+    ed25519_keys: &'static [[u8; ed25519::PUBLIC_KEY_LEN]],
+    /// The public keys of the board for ECDSA P-256, as the x and y
+    /// coordinates of the point
+    p256_keys: &'static [[u8; p256::PUBLIC_KEY_LEN]],
+}
+
+impl SoftwareAppVerifier {
+    pub fn new(
+        ed25519_keys: &'static [[u8; ed25519::PUBLIC_KEY_LEN]],
+        p256_keys: &'static [[u8; p256::PUBLIC_KEY_LEN]],
+    ) -> SoftwareAppVerifier {
+        SoftwareAppVerifier {
+            ed25519_keys: ed25519_keys,
+            p256_keys: p256_keys,
+        }
+    }
+}
+
+impl AppVerifier for SoftwareAppVerifier {
+    fn verify(&self, algorithm: SignatureAlgorithm, data: &[u8], signature: &[u8]) -> bool {
+        match algorithm {
+            SignatureAlgorithm::Ed25519 => self
+                .ed25519_keys
+                .iter()
+                .any(|key| verify_signature(algorithm, key, data, signature)),
+            SignatureAlgorithm::EcdsaNistP256 => {
+                if self.p256_keys.is_empty() {
+                    return false;
+                }
+                let mut sha = Sha256::new();
+                sha.update(data);
+                let mut digest = [0; sha256::DIGEST_LEN];
+                sha.finish(&mut digest);
+                self.p256_keys
+                    .iter()
+                    .any(|key| verify_signature(algorithm, key, &digest, signature))
+            }
+        }
+    }
+}
@@ -0,0 +1,288 @@
+//! Audio playback system call interface
+//!
+//! This capsule lets a process play 16-bit PCM samples through a
+//! `hil::i2s::I2S` controller, for example to a speaker amplifier. The
+//! process shares a buffer of little-endian samples, stereo samples
+//! interleaved starting with the left channel, and asks for a number of them
+//! to be played. The capsule copies them into two kernel buffers in turn,
+//! refilling each buffer once the controller has sent it, and pads the last
+//! one with silence. One process plays at a time.
+//!
+//! ## Instantiation
+//!
+//! ```rust
+//! let audio = static_init!(
+//!     capsules::audio_playback::AudioPlayback<'static>,
+//!     capsules::audio_playback::AudioPlayback::new(
+//!         &nrf52::i2s::I2S,
+//!         &mut capsules::audio_playback::BUFFER1.0,
+//!         &mut capsules::audio_playback::BUFFER2.0,
+//!         board_kernel.create_grant(&grant_cap)
+//!     )
+//! );
+//! hil::i2s::I2S::set_client(&nrf52::i2s::I2S, audio);
+//! ```
+
+use core::cell::Cell;
+use core::cmp::min;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::i2s::{self, Channels, Config};
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+/// Syscall number
+pub const DRIVER_NUM: usize = 0x00009;
+
+/// The number of samples in each kernel buffer
+pub const BUFFER_LEN: usize = 512;
+
+/// A kernel buffer, aligned for controllers that move whole words
+#[repr(align(4))]
+pub struct Buffer(pub [i16; BUFFER_LEN]);
+
+pub static mut BUFFER1: Buffer = Buffer([0; BUFFER_LEN]);
+pub static mut BUFFER2: Buffer = Buffer([0; BUFFER_LEN]);
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    samples: Option<AppSlice<Shared, u8>>,
+}
+
+pub struct AudioPlayback<'a> {
+    i2s: &'a i2s::I2S,
+    apps: Grant<App>,
+    buffer1: TakeCell<'static, [i16]>,
+    buffer2: TakeCell<'static, [i16]>,
+    // The app that is playing
+    owner: OptionalCell<AppId>,
+    // The number of samples to play, and the number copied so far
+    len: Cell<usize>,
+    offset: Cell<usize>,
+    // The number of buffers with samples that the controller holds
+    in_flight: Cell<usize>,
+    // The result reported once the controller stops
+    result: Cell<ReturnCode>,
+}
+
+impl AudioPlayback<'a> {
+    pub fn new(
+        i2s: &'a i2s::I2S,
+        buffer1: &'static mut [i16],
+        buffer2: &'static mut [i16],
+        apps: Grant<App>,
+    ) -> AudioPlayback<'a> {
+        AudioPlayback {
+            i2s: i2s,
+            apps: apps,
+            buffer1: TakeCell::new(buffer1),
+            buffer2: TakeCell::new(buffer2),
+            owner: OptionalCell::empty(),
+            len: Cell::new(0),
+            offset: Cell::new(0),
+            in_flight: Cell::new(0),
+            result: Cell::new(ReturnCode::SUCCESS),
+        }
+    }
+
+    /// Copy the next samples of the owner into `buffer`, followed by silence,
+    /// and count the buffer as in flight if it holds any samples
+    fn fill(&self, buffer: &mut [i16]) {
+        let offset = self.offset.get();
+        let count = min(self.len.get() - offset, buffer.len());
+        self.owner.map(|appid| {
+            let _ = self.apps.enter(*appid, |app, _| {
+                // The process may have shared a shorter buffer since it
+                // started playing
+                app.samples
+                    .as_ref()
+                    .and_then(|samples| samples.as_ref().get(2 * offset..2 * (offset + count)))
+                    .map(|bytes| {
+                        for (sample, bytes) in buffer.iter_mut().zip(bytes.chunks(2)) {
+                            *sample = (bytes[0] as u16 | (bytes[1] as u16) << 8) as i16;
+                        }
+                    });
+            });
+        });
+        for sample in buffer[count..].iter_mut() {
+            *sample = 0;
+        }
+        self.offset.set(offset + count);
+        if count > 0 {
+            self.in_flight.set(self.in_flight.get() + 1);
+        }
+    }
+
+    fn play(&self, appid: AppId, len: usize) -> ReturnCode {
+        if self.owner.is_some() {
+            return ReturnCode::EBUSY;
+        }
+        let valid = self
+            .apps
+            .enter(appid, |app, _| {
+                app.samples
+                    .as_ref()
+                    .map_or(false, |samples| len > 0 && 2 * len <= samples.len())
+            }).unwrap_or(false);
+        if !valid {
+            return ReturnCode::EINVAL;
+        }
+        let (buffer1, buffer2) = match (self.buffer1.take(), self.buffer2.take()) {
+            (Some(buffer1), Some(buffer2)) => (buffer1, buffer2),
+            (buffer1, buffer2) => {
+                self.buffer1.put(buffer1);
+                self.buffer2.put(buffer2);
+                return ReturnCode::EBUSY;
+            }
+        };
+
+        self.owner.set(appid);
+        self.len.set(len);
+        self.offset.set(0);
+        self.in_flight.set(0);
+        self.result.set(ReturnCode::SUCCESS);
+        self.fill(buffer1);
+        self.fill(buffer2);
+        let (result, buffer1, buffer2) = self.i2s.transmit(buffer1, buffer2);
+        if result != ReturnCode::SUCCESS {
+            self.buffer1.put(buffer1);
+            self.buffer2.put(buffer2);
+            self.owner.clear();
+        }
+        result
+    }
+}
+
+impl Driver for AudioPlayback<'a> {
+    /// Share the buffer of samples to play.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: The samples, as little-endian 16-bit numbers.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.samples = slice;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Subscribe to playback events.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Playback ended. The callback is passed SUCCESS if all samples
+    ///        were played and ECANCEL if the playback was stopped, and the
+    ///        number of samples that were copied to the controller.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        appid: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Control playback.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Set the sample rate to `data1` frames per second, and the
+    ///        number of channels to `data2`, 1 or 2. Returns the sample rate
+    ///        the controller actually produces.
+    /// - `2`: Play the first `data1` samples of the shared buffer. Returns
+    ///        EBUSY if a process is playing.
+    /// - `3`: Stop playing.
+    fn command(&self, command_num: usize, data1: usize, data2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            1 => {
+                let channels = match data2 {
+                    1 => Channels::Mono,
+                    2 => Channels::Stereo,
+                    _ => return ReturnCode::EINVAL,
+                };
+                let result = self.i2s.configure(Config {
+                    sample_rate: data1 as u32,
+                    channels: channels,
+                });
+                if result != ReturnCode::SUCCESS {
+                    return result;
+                }
+                ReturnCode::SuccessWithValue {
+                    value: self.i2s.sample_rate() as usize,
+                }
+            }
+
+            2 => self.play(appid, data1),
+
+            3 => {
+                if !self.owner.map_or(false, |owner| owner.idx() == appid.idx()) {
+                    return ReturnCode::EOFF;
+                }
+                self.result.set(ReturnCode::ECANCEL);
+                self.i2s.stop()
+            }
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+impl i2s::Client for AudioPlayback<'a> {
+    fn buffer_done(&self, buffer: &'static mut [i16]) {
+        self.in_flight.set(self.in_flight.get().saturating_sub(1));
+        if self.in_flight.get() == 0 && self.offset.get() >= self.len.get() {
+            self.i2s.stop();
+        }
+        // The buffer is sent again while the controller stops, so it is
+        // filled with silence then
+        self.fill(buffer);
+        let (_, buffer) = self.i2s.provide_buffer(buffer);
+        buffer.map(|buffer| {
+            if self.buffer1.is_none() {
+                self.buffer1.replace(buffer);
+            } else {
+                self.buffer2.replace(buffer);
+            }
+        });
+    }
+
+    fn stopped(&self, buffer1: Option<&'static mut [i16]>, buffer2: Option<&'static mut [i16]>) {
+        for buffer in [buffer1, buffer2].iter_mut() {
+            buffer.take().map(|buffer| {
+                if self.buffer1.is_none() {
+                    self.buffer1.replace(buffer);
+                } else {
+                    self.buffer2.replace(buffer);
+                }
+            });
+        }
+        let result = self.result.get();
+        let played = self.offset.get();
+        self.owner.take().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                app.callback
+                    .map(|mut cb| cb.schedule(isize::from(result) as usize, played, 0));
+            });
+        });
+    }
+}
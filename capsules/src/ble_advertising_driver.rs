@@ -14,9 +14,11 @@
 //!
 //! The allow systems calls are used for buffers from allocated by userland
 //!
-//! There are two different buffers:
+//! There are four different buffers:
 //! * 0: Advertising data
 //! * 1: Passive scanning buffer
+//! * 2: Advertiser address (6 bytes, least significant byte first)
+//! * 3: Identity resolving key (16 bytes, least significant byte first)
 //!
 //! The possible return codes from the 'allow' system call indicate the following:
 //!
@@ -48,12 +50,29 @@
 //!
 //! * 0: start advertisement
 //! * 1: stop advertisement or scanning
+//! * 2: configure tx power
+//! * 3: set advertiser address from buffer 2, `data` selects the address type
+//!      (0: public, 1: random static)
 //! * 5: start scanning
+//! * 6: set scan filter, `data` is a bitmask of the PDU types to receive (0 receives all)
+//! * 7: advertise from resolvable private addresses of the key in buffer 3, `data` is the number
+//!      of seconds after which the address is changed, at most 3600 (0 stops using them)
+//!
+//! ### Privacy
+//!
+//! With command 7, a process advertises from resolvable private addresses instead of a fixed
+//! address, so that it cannot be tracked by its address, while peers that know its identity
+//! resolving key can still recognize it. The driver generates a new address once the interval
+//! has passed, at the start of the next advertising event, and sends it from the event after.
+//! Addresses are generated by a `ResolvableAddressGenerator` set with `set_address_generator`.
+//! Until the first address was generated, starting advertisements returns EBUSY.
 //!
 //! The possible return codes from the `command` system call indicate the following:
 //!
 //! * SUCCESS:      The command was successful
 //! * EBUSY:        The driver is currently busy with other tasks
+//! * EINVAL:       Invalid argument, e.g. a malformed random static address
+//! * ENOMEM:       Too many processes are already advertising or scanning
 //! * ENOSUPPORT:   The operation is not supported
 //!
 //! Usage
@@ -85,19 +104,26 @@
 
 // # Implementation
 //
-// Advertising virtualization works by implementing a virtual periodic timer for each process. The
-// timer is configured to fire at each advertising interval, as specified by the process. When a
-// timer fires, we serialize the advertising packet for that process (using the provided AdvData
-// payload, generated address and PDU type) and perform one advertising event (on each of three
-// channels).
+// Advertising virtualization works by keeping the time of the next advertising or scanning event of
+// each process in a queue, ordered by expiration time, and a single alarm set to the earliest one.
+// When the alarm fires, we serialize the advertising packet for that process (using the provided
+// AdvData payload, generated address and PDU type) and perform one advertising event (on each of
+// three channels). The next event of the process is queued as soon as an event starts, at its own
+// interval plus a pseudo random `advDelay` of 0-10 ms, as required by the Bluetooth specification.
 //
-// This means that advertising events can collide. In this case, we just defer one of the
-// advertisements. Because we add a pseudo random pad to the timer interval each time (as required
-// by the Bluetooth specification) multiple collisions of the same processes are highly unlikely.
+// This means that advertising events can collide. In this case, the event that became due while
+// the radio was busy is started as soon as the current event completes. Because of `advDelay`,
+// repeated collisions of the same processes are highly unlikely.
+//
+// When a single app has a pending advertising event, the next event is not driven by the alarm.
+// Instead, the radio is armed right after the previous event completes and started by a hardware
+// timer, so the CPU can sleep until the event is over. If another process starts advertising or
+// scanning in the meantime, the armed event is taken back into the queue.
 
 use core::cell::Cell;
 use core::cmp;
 use kernel;
+use ble_privacy::{ResolvableAddressGenerator, RpaClient, IRK_LEN};
 use kernel::common::cells::OptionalCell;
 use kernel::hil::ble_advertising;
 use kernel::hil::ble_advertising::RadioChannel;
@@ -111,6 +137,8 @@ pub const DRIVER_NUM: usize = 0x03_00_00;
 pub static mut BUF: [u8; PACKET_LENGTH] = [0; PACKET_LENGTH];
 
 const PACKET_ADDR_LEN: usize = 6;
+/// The longest interval between changes of a resolvable private address, an hour
+const MAX_ROTATION_S: usize = 3600;
 const PACKET_LENGTH: usize = 39;
 const ADV_HEADER_TXADD_OFFSET: usize = 6;
 
@@ -124,29 +152,86 @@ enum BLEState {
     Advertising(RadioChannel),
 }
 
+/// Maximum number of processes that can advertise or scan at the same time
+const MAX_PENDING_EVENTS: usize = 8;
+
 #[derive(Copy, Clone)]
-enum Expiration {
-    Disabled,
-    Abs(u32),
+struct ScheduledEvent {
+    appid: kernel::AppId,
+    expiration: u32,
 }
 
-#[derive(Copy, Clone)]
-struct AlarmData {
-    t0: u32,
-    expiration: Expiration,
+/// The next advertising or scanning event of each process, earliest first
+struct EventQueue {
+    events: Cell<[Option<ScheduledEvent>; MAX_PENDING_EVENTS]>,
 }
 
-impl AlarmData {
-    fn new() -> AlarmData {
-        AlarmData {
-            t0: 0,
-            expiration: Expiration::Disabled,
+impl EventQueue {
+    fn new() -> EventQueue {
+        EventQueue {
+            events: Cell::new([None; MAX_PENDING_EVENTS]),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.events.get().iter().take_while(|e| e.is_some()).count()
+    }
+
+    fn peek(&self) -> Option<ScheduledEvent> {
+        self.events.get()[0]
+    }
+
+    // Queues the next event of `appid`, replacing the event it already has queued
+    fn insert(&self, appid: kernel::AppId, expiration: u32) -> ReturnCode {
+        self.remove(appid);
+        let mut events = self.events.get();
+        let mut pos = self.len();
+        if pos == MAX_PENDING_EVENTS {
+            return ReturnCode::ENOMEM;
+        }
+        while pos > 0 && events[pos - 1].map_or(false, |e| expires_before(expiration, e.expiration))
+        {
+            events[pos] = events[pos - 1];
+            pos -= 1;
+        }
+        events[pos] = Some(ScheduledEvent {
+            appid: appid,
+            expiration: expiration,
+        });
+        self.events.set(events);
+        ReturnCode::SUCCESS
+    }
+
+    fn remove(&self, appid: kernel::AppId) {
+        let mut events = self.events.get();
+        if let Some(pos) = events
+            .iter()
+            .position(|e| e.map_or(false, |e| e.appid == appid))
+        {
+            for i in pos..MAX_PENDING_EVENTS - 1 {
+                events[i] = events[i + 1];
+            }
+            events[MAX_PENDING_EVENTS - 1] = None;
+            self.events.set(events);
         }
     }
 }
 
+// Compares two alarm times, taking into account that the alarm counter wraps around
+fn expires_before(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
 type AdvPduType = u8;
 
+/// The type of the address in the AdvA field, signalled by the TxAdd bit
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum AddressType {
+    Public,
+    RandomStatic,
+    RandomResolvable,
+}
+
 // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 2.3.3
 const ADV_IND: AdvPduType = 0b0000;
 #[allow(dead_code)]
@@ -160,14 +245,28 @@ const SCAN_RESP: AdvPduType = 0b0100;
 const CONNECT_IND: AdvPduType = 0b0101;
 const ADV_SCAN_IND: AdvPduType = 0b0110;
 
+const ADV_HEADER_PDU_TYPE_MASK: u8 = 0x0f;
+const SCAN_FILTER_ALL: u16 = 0xffff;
+
 /// Process specific memory
 pub struct App {
     process_status: Option<BLEState>,
-    alarm_data: AlarmData,
 
     // Advertising meta-data
     adv_data: Option<kernel::AppSlice<kernel::Shared, u8>>,
+    address_cfg: Option<kernel::AppSlice<kernel::Shared, u8>>,
     address: [u8; PACKET_ADDR_LEN],
+    /// `None` until an address has been generated or configured by the process
+    address_type: Option<AddressType>,
+    irk_cfg: Option<kernel::AppSlice<kernel::Shared, u8>>,
+    /// The identity resolving key, if the process advertises from resolvable private addresses
+    irk: Option<[u8; IRK_LEN]>,
+    /// The number of seconds after which the resolvable private address is changed
+    rotation_interval_s: u32,
+    /// The time after which the resolvable private address is changed
+    rotate_at: u32,
+    /// A resolvable private address generated during an advertising event, used from the next
+    next_address: Option<[u8; PACKET_ADDR_LEN]>,
     pdu_type: AdvPduType,
     advertisement_interval_ms: u32,
     tx_power: u8,
@@ -181,17 +280,26 @@ pub struct App {
     // Scanning meta-data
     scan_buffer: Option<kernel::AppSlice<kernel::Shared, u8>>,
     scan_callback: Option<kernel::Callback>,
+    /// Bitmask of the PDU types to receive, bit `n` is PDU type `n`
+    scan_filter: u16,
 }
 
 impl Default for App {
     fn default() -> App {
         App {
-            alarm_data: AlarmData::new(),
             adv_data: None,
+            address_cfg: None,
             scan_buffer: None,
             address: [0; PACKET_ADDR_LEN],
+            address_type: None,
+            irk_cfg: None,
+            irk: None,
+            rotation_interval_s: 0,
+            rotate_at: 0,
+            next_address: None,
             pdu_type: ADV_NONCONN_IND,
             scan_callback: None,
+            scan_filter: SCAN_FILTER_ALL,
             process_status: Some(BLEState::NotInitialized),
             tx_power: 0,
             advertisement_interval_ms: 200,
@@ -227,49 +335,147 @@ impl App {
             ((appid.idx() << 24) & 0xff) as u8,
             0xf0,
         ];
+        self.address_type = Some(AddressType::RandomStatic);
         ReturnCode::SUCCESS
     }
 
+    // Copies the advertiser address from the address buffer shared by the process
+    //
+    // The address is expected with the least significant byte first, i.e. in the order it is
+    // transmitted in the AdvA field. Random static addresses are validated according to the
+    // requirements listed above `generate_random_address`.
+    fn configure_address(&mut self, address_type: AddressType) -> ReturnCode {
+        let mut address = [0; PACKET_ADDR_LEN];
+        let copied = self
+            .address_cfg
+            .as_ref()
+            .map_or(false, |cfg| {
+                if cfg.len() < PACKET_ADDR_LEN {
+                    false
+                } else {
+                    address.copy_from_slice(&cfg.as_ref()[..PACKET_ADDR_LEN]);
+                    true
+                }
+            });
+        if !copied {
+            return ReturnCode::EINVAL;
+        }
+
+        if address_type == AddressType::RandomStatic && !is_valid_static_address(&address) {
+            return ReturnCode::EINVAL;
+        }
+
+        self.address = address;
+        self.address_type = Some(address_type);
+        // A configured address replaces resolvable private addresses
+        self.irk = None;
+        self.next_address = None;
+        ReturnCode::SUCCESS
+    }
+
+    // Copies the identity resolving key from the key buffer shared by the process
+    fn configure_irk(&mut self) -> ReturnCode {
+        let mut irk = [0; IRK_LEN];
+        let copied = self.irk_cfg.as_ref().map_or(false, |cfg| {
+            if cfg.len() < IRK_LEN {
+                false
+            } else {
+                irk.copy_from_slice(&cfg.as_ref()[..IRK_LEN]);
+                true
+            }
+        });
+        if !copied {
+            return ReturnCode::EINVAL;
+        }
+        self.irk = Some(irk);
+        ReturnCode::SUCCESS
+    }
+
+    // Serializes the advertising PDU for this app into `buf` and returns the total length
+    //
+    // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 2.3 Advertising Channel PDU
+    //
+    // +-------------+--------------+---------+
+    // | Header      | AdvA         | AdvData |
+    // | (2 bytes)   | (6 bytes)    | (0-31)  |
+    // +-------------+--------------+---------+
+    fn prepare_advertisement(&self, buf: &mut [u8]) -> Option<usize> {
+        self.adv_data.as_ref().map(|adv_data| {
+            let adv_data_len = cmp::min(buf.len() - PACKET_ADDR_LEN - 2, adv_data.len());
+            let adv_data_corrected = &adv_data.as_ref()[..adv_data_len];
+            let payload_len = adv_data_corrected.len() + PACKET_ADDR_LEN;
+            {
+                let (header, payload) = buf.split_at_mut(2);
+                header[0] = self.pdu_type;
+                match self.pdu_type {
+                    ADV_IND | ADV_NONCONN_IND | ADV_SCAN_IND => {
+                        // Set TxAdd if the AdvA field is a random address
+                        if self.address_type != Some(AddressType::Public) {
+                            header[0] |= 1 << ADV_HEADER_TXADD_OFFSET;
+                        }
+                    }
+                    _ => {}
+                }
+                // The LENGTH field is 6-bits wide, so make sure to truncate it
+                header[1] = (payload_len & 0x3f) as u8;
+
+                let (adva, data) = payload.split_at_mut(PACKET_ADDR_LEN);
+                adva.copy_from_slice(&self.address);
+                data[..adv_data_len].copy_from_slice(adv_data_corrected);
+            }
+            cmp::min(PACKET_LENGTH, payload_len + 2)
+        })
+    }
+
     fn send_advertisement<'a, B, A>(&self, ble: &BLE<'a, B, A>, channel: RadioChannel) -> ReturnCode
     where
-        B: ble_advertising::BleAdvertisementDriver + ble_advertising::BleConfig,
+        B: ble_advertising::BleAdvertisementDriver
+            + ble_advertising::BleConfig
+            + ble_advertising::BleAdvertisementScheduler,
         A: kernel::hil::time::Alarm,
     {
-        self.adv_data
-            .as_ref()
-            .map(|adv_data| {
-                ble.kernel_tx
-                    .take()
-                    .map(|kernel_tx| {
-                        let adv_data_len =
-                            cmp::min(kernel_tx.len() - PACKET_ADDR_LEN - 2, adv_data.len());
-                        let adv_data_corrected = &adv_data.as_ref()[..adv_data_len];
-                        let payload_len = adv_data_corrected.len() + PACKET_ADDR_LEN;
-                        {
-                            let (header, payload) = kernel_tx.split_at_mut(2);
-                            header[0] = self.pdu_type;
-                            match self.pdu_type {
-                                ADV_IND | ADV_NONCONN_IND | ADV_SCAN_IND => {
-                                    // Set TxAdd because AdvA field is going to be a "random"
-                                    // address
-                                    header[0] |= 1 << ADV_HEADER_TXADD_OFFSET;
-                                }
-                                _ => {}
-                            }
-                            // The LENGTH field is 6-bits wide, so make sure to truncate it
-                            header[1] = (payload_len & 0x3f) as u8;
+        ble.kernel_tx
+            .take()
+            .map(|kernel_tx| match self.prepare_advertisement(kernel_tx) {
+                Some(total_len) => {
+                    // The radio hands the buffer back with `transmit_event`
+                    ble.radio
+                        .transmit_advertisement(kernel_tx, total_len, channel);
+                    ReturnCode::SUCCESS
+                }
+                None => {
+                    ble.kernel_tx.replace(kernel_tx);
+                    ReturnCode::FAIL
+                }
+            }).unwrap_or(ReturnCode::FAIL)
+    }
 
-                            let (adva, data) = payload.split_at_mut(6);
-                            adva.copy_from_slice(&self.address);
-                            data[..adv_data_len].copy_from_slice(adv_data_corrected);
-                        }
-                        let total_len = cmp::min(PACKET_LENGTH, payload_len + 2);
-                        let result = ble
-                            .radio
-                            .transmit_advertisement(kernel_tx, total_len, channel);
-                        ble.kernel_tx.replace(result);
-                        ReturnCode::SUCCESS
-                    }).unwrap_or(ReturnCode::FAIL)
+    // Like `send_advertisement` but the radio starts the transmission `delay_us` microseconds
+    // from now without waking up the CPU.
+    fn schedule_advertisement<'a, B, A>(
+        &self,
+        ble: &BLE<'a, B, A>,
+        channel: RadioChannel,
+        delay_us: u32,
+    ) -> ReturnCode
+    where
+        B: ble_advertising::BleAdvertisementDriver
+            + ble_advertising::BleConfig
+            + ble_advertising::BleAdvertisementScheduler,
+        A: kernel::hil::time::Alarm,
+    {
+        ble.kernel_tx
+            .take()
+            .map(|kernel_tx| match self.prepare_advertisement(kernel_tx) {
+                Some(total_len) => {
+                    ble.radio
+                        .schedule_transmit_advertisement(kernel_tx, total_len, channel, delay_us);
+                    ReturnCode::SUCCESS
+                }
+                None => {
+                    ble.kernel_tx.replace(kernel_tx);
+                    ReturnCode::FAIL
+                }
             }).unwrap_or(ReturnCode::FAIL)
     }
 
@@ -287,19 +493,37 @@ impl App {
         self.random_nonce
     }
 
-    // Set the next alarm for this app using the period and provided start time.
-    fn set_next_alarm<F: Frequency>(&mut self, now: u32) {
-        self.alarm_data.t0 = now;
-        let nonce = self.random_nonce() % 10;
+    // Returns the time of the next event of this app, one interval plus a pseudo-random
+    // `advDelay` of 0-10 ms after `start`.
+    //
+    // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 4.4.2.2
+    fn next_event_time<F: Frequency>(&mut self, start: u32) -> u32 {
+        let adv_delay_ms = self.random_nonce() % 11;
+        let period_ms = (self.advertisement_interval_ms + adv_delay_ms) as u64;
+        let ticks = period_ms * F::frequency() as u64 / 1000;
+        start.wrapping_add(ticks as u32)
+    }
+}
 
-        let period_ms = (self.advertisement_interval_ms + nonce) * F::frequency() / 1000;
-        self.alarm_data.expiration = Expiration::Abs(now.wrapping_add(period_ms));
+// Bluetooth Core Specification:Vol. 6, Part B, section 1.3.2.1 Static Device Address
+//
+// `address` is in transmission order, so the most significant byte is the last one.
+fn is_valid_static_address(address: &[u8; PACKET_ADDR_LEN]) -> bool {
+    let (random_part, msb) = address.split_at(PACKET_ADDR_LEN - 1);
+    if msb[0] & 0xc0 != 0xc0 {
+        return false;
     }
+    // The remaining 46 bits are random and must be neither all zeros nor all ones
+    let all_zeros = msb[0] & 0x3f == 0 && random_part.iter().all(|b| *b == 0);
+    let all_ones = msb[0] & 0x3f == 0x3f && random_part.iter().all(|b| *b == 0xff);
+    !all_zeros && !all_ones
 }
 
 pub struct BLE<'a, B, A>
 where
-    B: ble_advertising::BleAdvertisementDriver + ble_advertising::BleConfig,
+    B: ble_advertising::BleAdvertisementDriver
+        + ble_advertising::BleConfig
+        + ble_advertising::BleAdvertisementScheduler,
     A: kernel::hil::time::Alarm,
 {
     radio: &'a B,
@@ -309,11 +533,19 @@ where
     alarm: &'a A,
     sending_app: OptionalCell<kernel::AppId>,
     receiving_app: OptionalCell<kernel::AppId>,
+    events: EventQueue,
+    /// The advertising event armed in the radio, if any
+    hw_scheduled: OptionalCell<ScheduledEvent>,
+    address_generator: OptionalCell<&'a ResolvableAddressGenerator>,
+    /// The app whose resolvable private address is being generated, if any
+    rotating_app: OptionalCell<kernel::AppId>,
 }
 
 impl<B, A> BLE<'a, B, A>
 where
-    B: ble_advertising::BleAdvertisementDriver + ble_advertising::BleConfig,
+    B: ble_advertising::BleAdvertisementDriver
+        + ble_advertising::BleConfig
+        + ble_advertising::BleAdvertisementScheduler,
     A: kernel::hil::time::Alarm,
 {
     pub fn new(
@@ -330,34 +562,189 @@ where
             alarm: alarm,
             sending_app: OptionalCell::empty(),
             receiving_app: OptionalCell::empty(),
+            events: EventQueue::new(),
+            hw_scheduled: OptionalCell::empty(),
+            address_generator: OptionalCell::empty(),
+            rotating_app: OptionalCell::empty(),
         }
     }
 
-    // Determines which app timer will expire next and sets the underlying alarm
-    // to it.
-    //
-    // This method iterates through all grants so it should be used somewhat
-    // sparingly. Moreover, it should _not_ be called from within a grant,
-    // since any open grant will not be iterated over and the wrong timer will
-    // likely be chosen.
-    fn reset_active_alarm(&self) {
+    /// Lets processes advertise from resolvable private addresses made by `generator`.
+    pub fn set_address_generator(&self, generator: &'a ResolvableAddressGenerator) {
+        self.address_generator.set(generator);
+    }
+
+    // Starts generating a resolvable private address for `appid`, and sets the time of the
+    // change after it.
+    fn generate_address(&self, appid: kernel::AppId, app: &mut App, now: u32) -> ReturnCode {
+        if self.rotating_app.is_some() {
+            return ReturnCode::EBUSY;
+        }
+        let irk = match app.irk {
+            Some(irk) => irk,
+            None => return ReturnCode::EINVAL,
+        };
+        let result = self
+            .address_generator
+            .map_or(ReturnCode::ENOSUPPORT, |generator| generator.generate(&irk));
+        if result == ReturnCode::SUCCESS {
+            self.rotating_app.set(appid);
+            let ticks = app.rotation_interval_s as u64 * A::Frequency::frequency() as u64;
+            app.rotate_at = now.wrapping_add(ticks as u32);
+        }
+        result
+    }
+
+    // Switches to the resolvable private address generated during the last advertising event of
+    // the app, and starts generating a new one once its address is due to change. If generating
+    // cannot start, for example while the address of another app is generated, it is tried again
+    // at the next event.
+    fn rotate_address(&self, appid: kernel::AppId, app: &mut App, now: u32) {
+        if let Some(address) = app.next_address.take() {
+            app.address = address;
+            app.address_type = Some(AddressType::RandomResolvable);
+        }
+        if app.irk.is_some() && !expires_before(now, app.rotate_at) {
+            self.generate_address(appid, app, now);
+        }
+    }
+
+    // Starts the earliest queued event if it is due and the radio is free. Otherwise the alarm is
+    // set to fire when the earliest event becomes due.
+    fn run_next_event(&self) {
+        if self.busy.get() {
+            return;
+        }
+        while let Some(event) = self.events.peek() {
+            if expires_before(self.alarm.now(), event.expiration) {
+                self.alarm.set_alarm(event.expiration);
+                return;
+            }
+            if self.start_event(event.appid) {
+                return;
+            }
+            // The app has stopped advertising and scanning or no longer exists
+            self.events.remove(event.appid);
+        }
+    }
+
+    // Starts an advertising or scanning event for `appid` on the first advertising channel and
+    // queues its next event. Returns false if the app is neither advertising nor scanning.
+    fn start_event(&self, appid: kernel::AppId) -> bool {
         let now = self.alarm.now();
-        let mut next_alarm = u32::max_value();
-        let mut next_dist = u32::max_value();
-        for app in self.app.iter() {
-            app.enter(|app, _| match app.alarm_data.expiration {
-                Expiration::Abs(exp) => {
-                    let t_dist = exp.wrapping_sub(now);
-                    if next_dist > t_dist {
-                        next_alarm = exp;
-                        next_dist = t_dist;
+        self.app
+            .enter(appid, |app, _| {
+                match app.process_status {
+                    Some(BLEState::AdvertisingIdle) => {
+                        self.busy.set(true);
+                        self.rotate_address(appid, app, now);
+                        app.process_status =
+                            Some(BLEState::Advertising(RadioChannel::AdvertisingChannel37));
+                        self.sending_app.set(appid);
+                        self.radio.set_tx_power(app.tx_power);
+                        app.send_advertisement(&self, RadioChannel::AdvertisingChannel37);
+                    }
+                    Some(BLEState::ScanningIdle) => {
+                        self.busy.set(true);
+                        app.process_status =
+                            Some(BLEState::Scanning(RadioChannel::AdvertisingChannel37));
+                        self.receiving_app.set(appid);
+                        self.radio.set_tx_power(app.tx_power);
+                        self.radio
+                            .receive_advertisement(RadioChannel::AdvertisingChannel37);
                     }
+                    _ => return false,
                 }
-                Expiration::Disabled => {}
+                self.events
+                    .insert(appid, app.next_event_time::<A::Frequency>(now));
+                true
+            }).unwrap_or(false)
+    }
+
+    // Arms the radio to start the next advertising event from a hardware timer, if only a single
+    // app has a pending event. Otherwise the event is left to the alarm so that the radio isn't
+    // reserved while other apps are waiting for it. Returns whether the event was armed.
+    fn schedule_in_hardware(&self) -> bool {
+        if self.busy.get() || self.events.len() != 1 {
+            return false;
+        }
+        let event = match self.events.peek() {
+            Some(event) => event,
+            None => return false,
+        };
+        let now = self.alarm.now();
+        if !expires_before(now, event.expiration) {
+            return false;
+        }
+        let ticks = event.expiration.wrapping_sub(now) as u64;
+        let delay_us = (ticks * 1_000_000 / A::Frequency::frequency() as u64) as u32;
+
+        let scheduled = self
+            .app
+            .enter(event.appid, |app, _| {
+                if app.process_status != Some(BLEState::AdvertisingIdle) {
+                    return false;
+                }
+                self.rotate_address(event.appid, app, now);
+                app.process_status =
+                    Some(BLEState::Advertising(RadioChannel::AdvertisingChannel37));
+                self.radio.set_tx_power(app.tx_power);
+                let result =
+                    app.schedule_advertisement(&self, RadioChannel::AdvertisingChannel37, delay_us);
+                if result == ReturnCode::SUCCESS {
+                    self.events.insert(
+                        event.appid,
+                        app.next_event_time::<A::Frequency>(event.expiration),
+                    );
+                    true
+                } else {
+                    // Leave it to the alarm
+                    app.process_status = Some(BLEState::AdvertisingIdle);
+                    false
+                }
+            }).unwrap_or(false);
+
+        if scheduled {
+            self.busy.set(true);
+            self.sending_app.set(event.appid);
+            self.hw_scheduled.set(event);
+        }
+        scheduled
+    }
+
+    // Cancels the advertising event armed in the radio, if the radio hasn't started it yet, and
+    // returns it. The state of the app is left to the caller.
+    fn cancel_hardware_event(&self) -> Option<ScheduledEvent> {
+        self.hw_scheduled.take().and_then(|event| {
+            match self.radio.cancel_scheduled_advertisement() {
+                Some(buf) => {
+                    self.kernel_tx.replace(buf);
+                    self.busy.set(false);
+                    Some(event)
+                }
+                // The event has already started and completes through `transmit_event`
+                None => None,
+            }
+        })
+    }
+
+    // Takes the advertising event armed in the radio back into the queue, so that the events of
+    // other apps can be scheduled around it.
+    //
+    // This enters the grant of the armed app, so it must not be called from within a grant.
+    fn requeue_hardware_event(&self) {
+        if let Some(event) = self.cancel_hardware_event() {
+            let _ = self.app.enter(event.appid, |app, _| {
+                app.process_status = Some(BLEState::AdvertisingIdle);
             });
+            self.events.insert(event.appid, event.expiration);
         }
-        if next_alarm != u32::max_value() {
-            self.alarm.set_alarm(next_alarm);
+    }
+
+    // Continues with the next queued event once the current event has completed
+    fn event_completed(&self) {
+        if !self.schedule_in_hardware() {
+            self.run_next_event();
         }
     }
 }
@@ -365,78 +752,74 @@ where
 // Timer alarm
 impl<B, A> kernel::hil::time::Client for BLE<'a, B, A>
 where
-    B: ble_advertising::BleAdvertisementDriver + ble_advertising::BleConfig,
+    B: ble_advertising::BleAdvertisementDriver
+        + ble_advertising::BleConfig
+        + ble_advertising::BleAdvertisementScheduler,
     A: kernel::hil::time::Alarm,
 {
-    // When an alarm is fired, we find which apps have expired timers. Expired
-    // timers indicate a desire to perform some operation (e.g. start an
-    // advertising or scanning event). We know which operation based on the
-    // current app's state.
-    //
-    // In case of collision---if there is already an event happening---we'll
-    // just delay the operation for next time and hope for the best. Since some
-    // randomness is added for each period in an app's timer, collisions should
-    // be rare in practice.
-    //
-    // TODO: perhaps break ties more fairly by prioritizing apps that have least
-    // recently performed an operation.
+    // When the alarm fires, the earliest queued event has become due. The alarm is only set while
+    // the radio is free, but an event may have started in the meantime, in which case the due
+    // event is started once that one completes.
     fn fired(&self) {
-        let now = self.alarm.now();
-
-        self.app.each(|app| {
-            if let Expiration::Abs(exp) = app.alarm_data.expiration {
-                let expired =
-                    now.wrapping_sub(app.alarm_data.t0) >= exp.wrapping_sub(app.alarm_data.t0);
-                if expired {
-                    if self.busy.get() {
-                        // The radio is currently busy, so we won't be able to start the
-                        // operation at the appropriate time. Instead, reschedule the
-                        // operation for later. This is _kind_ of simulating actual
-                        // on-air interference
-                        debug!("BLE: operation delayed for app {:?}", app.appid());
-                        app.set_next_alarm::<A::Frequency>(self.alarm.now());
-                        return;
-                    }
-
-                    app.alarm_data.expiration = Expiration::Disabled;
+        self.run_next_event();
+    }
+}
 
-                    match app.process_status {
-                        Some(BLEState::AdvertisingIdle) => {
-                            self.busy.set(true);
-                            app.process_status =
-                                Some(BLEState::Advertising(RadioChannel::AdvertisingChannel37));
-                            self.sending_app.set(app.appid());
-                            self.radio.set_tx_power(app.tx_power);
-                            app.send_advertisement(&self, RadioChannel::AdvertisingChannel37);
-                        }
-                        Some(BLEState::ScanningIdle) => {
-                            self.busy.set(true);
-                            app.process_status =
-                                Some(BLEState::Scanning(RadioChannel::AdvertisingChannel37));
-                            self.receiving_app.set(app.appid());
-                            self.radio.set_tx_power(app.tx_power);
-                            self.radio
-                                .receive_advertisement(RadioChannel::AdvertisingChannel37);
-                        }
-                        _ => debug!(
-                            "app: {:?} \t invalid state {:?}",
-                            app.appid(),
-                            app.process_status
-                        ),
+// Resolvable private address generated
+impl<B, A> RpaClient for BLE<'a, B, A>
+where
+    B: ble_advertising::BleAdvertisementDriver
+        + ble_advertising::BleConfig
+        + ble_advertising::BleAdvertisementScheduler,
+    A: kernel::hil::time::Alarm,
+{
+    // The address is used at once if the app isn't in an advertising event, so that all three
+    // advertising channels of an event carry the same address.
+    fn address_generated(&self, result: ReturnCode, address: [u8; PACKET_ADDR_LEN]) {
+        self.rotating_app.take().map(|appid| {
+            let _ = self.app.enter(appid, |app, _| {
+                if app.irk.is_none() {
+                    return;
+                }
+                if result != ReturnCode::SUCCESS {
+                    // Try again at the next event
+                    app.rotate_at = self.alarm.now();
+                    return;
+                }
+                match app.process_status {
+                    Some(BLEState::Advertising(_)) => app.next_address = Some(address),
+                    _ => {
+                        app.address = address;
+                        app.address_type = Some(AddressType::RandomResolvable);
                     }
                 }
-            }
+            });
         });
-        self.reset_active_alarm();
     }
 }
 
 // Callback from the radio once a RX event occur
 impl<B, A> ble_advertising::RxClient for BLE<'a, B, A>
 where
-    B: ble_advertising::BleAdvertisementDriver + ble_advertising::BleConfig,
+    B: ble_advertising::BleAdvertisementDriver
+        + ble_advertising::BleConfig
+        + ble_advertising::BleAdvertisementScheduler,
     A: kernel::hil::time::Alarm,
 {
+    // Abort the reception early for packets that can't be advertisements or that the scanning
+    // app isn't interested in, so the radio doesn't have to receive the full payload
+    fn receive_filter(&self, header: u8, len: u8) -> bool {
+        if len as usize > PACKET_LENGTH - 2 {
+            return false;
+        }
+        let pdu_type = header & ADV_HEADER_PDU_TYPE_MASK;
+        self.receiving_app.map_or(true, |appid| {
+            self.app
+                .enter(*appid, |app, _| app.scan_filter & (1 << pdu_type) != 0)
+                .unwrap_or(true)
+        })
+    }
+
     fn receive_event(&self, buf: &'static mut [u8], len: u8, result: ReturnCode) {
         self.receiving_app.map(|appid| {
             let _ = self.app.enter(*appid, |app, _| {
@@ -486,26 +869,31 @@ where
                     Some(BLEState::Scanning(RadioChannel::AdvertisingChannel39)) => {
                         self.busy.set(false);
                         app.process_status = Some(BLEState::ScanningIdle);
-                        app.set_next_alarm::<A::Frequency>(self.alarm.now());
                     }
                     // Invalid state => don't care
                     _ => (),
                 }
             });
-            self.reset_active_alarm();
         });
+        if !self.busy.get() {
+            self.event_completed();
+        }
     }
 }
 
 // Callback from the radio once a TX event occur
 impl<B, A> ble_advertising::TxClient for BLE<'a, B, A>
 where
-    B: ble_advertising::BleAdvertisementDriver + ble_advertising::BleConfig,
+    B: ble_advertising::BleAdvertisementDriver
+        + ble_advertising::BleConfig
+        + ble_advertising::BleAdvertisementScheduler,
     A: kernel::hil::time::Alarm,
 {
     // The ReturnCode indicates valid CRC or not, not used yet but could be used for
     // re-transmissions for invalid CRCs
-    fn transmit_event(&self, _crc_ok: ReturnCode) {
+    fn transmit_event(&self, buf: &'static mut [u8], _crc_ok: ReturnCode) {
+        self.kernel_tx.replace(buf);
+        self.hw_scheduled.clear();
         self.sending_app.map(|appid| {
             let _ = self.app.enter(*appid, |app, _| {
                 match app.process_status {
@@ -527,21 +915,24 @@ where
                     Some(BLEState::Advertising(RadioChannel::AdvertisingChannel39)) => {
                         self.busy.set(false);
                         app.process_status = Some(BLEState::AdvertisingIdle);
-                        app.set_next_alarm::<A::Frequency>(self.alarm.now());
                     }
                     // Invalid state => don't care
                     _ => (),
                 }
             });
-            self.reset_active_alarm();
         });
+        if !self.busy.get() {
+            self.event_completed();
+        }
     }
 }
 
 // System Call implementation
 impl<B, A> kernel::Driver for BLE<'a, B, A>
 where
-    B: ble_advertising::BleAdvertisementDriver + ble_advertising::BleConfig,
+    B: ble_advertising::BleAdvertisementDriver
+        + ble_advertising::BleConfig
+        + ble_advertising::BleAdvertisementScheduler,
     A: kernel::hil::time::Alarm,
 {
     fn command(
@@ -553,27 +944,44 @@ where
     ) -> ReturnCode {
         match command_num {
             // Start periodic advertisements
-            0 => self
-                .app
-                .enter(appid, |app, _| {
-                    if let Some(BLEState::Initialized) = app.process_status {
-                        let pdu_type = data as AdvPduType;
-                        match pdu_type {
-                            ADV_IND | ADV_NONCONN_IND | ADV_SCAN_IND => {
-                                app.pdu_type = pdu_type;
-                                app.process_status = Some(BLEState::AdvertisingIdle);
-                                app.random_nonce = self.alarm.now();
-                                app.advertisement_interval_ms = cmp::max(20, interval as u32);
-                                app.set_next_alarm::<A::Frequency>(self.alarm.now());
-                                self.reset_active_alarm();
-                                ReturnCode::SUCCESS
+            //
+            // data - PDU type
+            // interval - advertising interval in ms, at least 20
+            0 => {
+                self.requeue_hardware_event();
+                let result = self
+                    .app
+                    .enter(appid, |app, _| {
+                        if app.irk.is_some()
+                            && app.address_type != Some(AddressType::RandomResolvable)
+                        {
+                            // The first resolvable private address is not generated yet
+                            ReturnCode::EBUSY
+                        } else if let Some(BLEState::Initialized) = app.process_status {
+                            let pdu_type = data as AdvPduType;
+                            match pdu_type {
+                                ADV_IND | ADV_NONCONN_IND | ADV_SCAN_IND => {
+                                    let now = self.alarm.now();
+                                    app.random_nonce = now;
+                                    app.advertisement_interval_ms = cmp::max(20, interval as u32);
+                                    let result = self
+                                        .events
+                                        .insert(appid, app.next_event_time::<A::Frequency>(now));
+                                    if result == ReturnCode::SUCCESS {
+                                        app.pdu_type = pdu_type;
+                                        app.process_status = Some(BLEState::AdvertisingIdle);
+                                    }
+                                    result
+                                }
+                                _ => ReturnCode::EINVAL,
                             }
-                            _ => ReturnCode::EINVAL,
+                        } else {
+                            ReturnCode::EBUSY
                         }
-                    } else {
-                        ReturnCode::EBUSY
-                    }
-                }).unwrap_or_else(|err| err.into()),
+                    }).unwrap_or_else(|err| err.into());
+                self.run_next_event();
+                result
+            }
 
             // Stop periodic advertisements or passive scanning
             1 => self
@@ -581,6 +989,16 @@ where
                 .enter(appid, |app, _| match app.process_status {
                     Some(BLEState::AdvertisingIdle) | Some(BLEState::ScanningIdle) => {
                         app.process_status = Some(BLEState::Initialized);
+                        self.events.remove(appid);
+                        ReturnCode::SUCCESS
+                    }
+                    // An advertising event armed in the radio can still be cancelled
+                    Some(BLEState::Advertising(RadioChannel::AdvertisingChannel37))
+                        if self.hw_scheduled.map_or(false, |event| event.appid == appid)
+                            && self.cancel_hardware_event().is_some() =>
+                    {
+                        app.process_status = Some(BLEState::Initialized);
+                        self.events.remove(appid);
                         ReturnCode::SUCCESS
                     }
                     _ => ReturnCode::EBUSY,
@@ -616,18 +1034,95 @@ where
                     }).unwrap_or_else(|err| err.into())
             }
 
+            // Configure the advertiser address from the address buffer
+            //
+            // data - 0 for a public address, 1 for a random static address
+            3 => self
+                .app
+                .enter(appid, |app, _| match app.process_status {
+                    Some(BLEState::NotInitialized) | Some(BLEState::Initialized) => {
+                        match data {
+                            0 => app.configure_address(AddressType::Public),
+                            1 => app.configure_address(AddressType::RandomStatic),
+                            _ => ReturnCode::EINVAL,
+                        }
+                    }
+                    _ => ReturnCode::EBUSY,
+                }).unwrap_or_else(|err| err.into()),
+
             // Passive scanning mode
-            5 => self
+            5 => {
+                self.requeue_hardware_event();
+                let result = self
+                    .app
+                    .enter(appid, |app, _| {
+                        if let Some(BLEState::Initialized) = app.process_status {
+                            let now = self.alarm.now();
+                            let result = self
+                                .events
+                                .insert(appid, app.next_event_time::<A::Frequency>(now));
+                            if result == ReturnCode::SUCCESS {
+                                app.process_status = Some(BLEState::ScanningIdle);
+                            }
+                            result
+                        } else {
+                            ReturnCode::EBUSY
+                        }
+                    }).unwrap_or_else(|err| err.into());
+                self.run_next_event();
+                result
+            }
+
+            // Filter received PDUs by type
+            //
+            // data - bitmask of PDU types to receive, 0 to receive all
+            6 => self
                 .app
                 .enter(appid, |app, _| {
-                    if let Some(BLEState::Initialized) = app.process_status {
-                        app.process_status = Some(BLEState::ScanningIdle);
-                        app.set_next_alarm::<A::Frequency>(self.alarm.now());
-                        self.reset_active_alarm();
-                        ReturnCode::SUCCESS
-                    } else {
-                        ReturnCode::EBUSY
+                    app.scan_filter = match data as u16 {
+                        0 => SCAN_FILTER_ALL,
+                        filter => filter,
+                    };
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+
+            // Advertise from resolvable private addresses of the key in the key buffer
+            //
+            // data - seconds after which the address is changed, 0 to stop
+            7 => self
+                .app
+                .enter(appid, |app, _| match app.process_status {
+                    Some(BLEState::NotInitialized) | Some(BLEState::Initialized) => {
+                        if data == 0 {
+                            app.irk = None;
+                            app.next_address = None;
+                            if app.address_type == Some(AddressType::RandomResolvable) {
+                                app.generate_random_address(appid)
+                            } else {
+                                ReturnCode::SUCCESS
+                            }
+                        } else if data > MAX_ROTATION_S {
+                            ReturnCode::EINVAL
+                        } else if self.address_generator.is_none() {
+                            ReturnCode::ENOSUPPORT
+                        } else {
+                            let result = app.configure_irk();
+                            if result != ReturnCode::SUCCESS {
+                                return result;
+                            }
+                            app.rotation_interval_s = data as u32;
+                            // Don't advertise from the previous address until the first
+                            // resolvable private address is generated
+                            app.address_type = None;
+                            app.next_address = None;
+                            let result = self.generate_address(appid, app, self.alarm.now());
+                            if result != ReturnCode::SUCCESS {
+                                app.irk = None;
+                            }
+                            result
+                        }
                     }
+                    _ => ReturnCode::EBUSY,
                 }).unwrap_or_else(|err| err.into()),
 
             _ => ReturnCode::ENOSUPPORT,
@@ -646,7 +1141,12 @@ where
                 .app
                 .enter(appid, |app, _| {
                     app.adv_data = slice;
-                    if let ReturnCode::SUCCESS = app.generate_random_address(appid) {
+                    // Keep an address that the process has already configured
+                    let status = match app.address_type {
+                        None => app.generate_random_address(appid),
+                        Some(_) => ReturnCode::SUCCESS,
+                    };
+                    if let ReturnCode::SUCCESS = status {
                         app.process_status = Some(BLEState::Initialized);
                         ReturnCode::SUCCESS
                     } else {
@@ -666,6 +1166,22 @@ where
                     _ => ReturnCode::EINVAL,
                 }).unwrap_or_else(|err| err.into()),
 
+            // Advertiser address buffer
+            2 => self
+                .app
+                .enter(appid, |app, _| {
+                    app.address_cfg = slice;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+
+            // Identity resolving key buffer
+            3 => self
+                .app
+                .enter(appid, |app, _| {
+                    app.irk_cfg = slice;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+
             // Operation not supported
             _ => ReturnCode::ENOSUPPORT,
         }
@@ -0,0 +1,190 @@
+//! Resolvable private addresses for Bluetooth Low Energy
+//!
+//! A resolvable private address (RPA) lets a device change its address
+//! often, so that it cannot be tracked by its address, while peers that
+//! share its identity resolving key (IRK) can still recognize it. An RPA is
+//! a 24-bit random part `prand`, whose two most significant bits are `0b01`,
+//! and a 24-bit hash of it with the IRK:
+//!
+//! ```text
+//! hash = ah(IRK, prand) = e(IRK, 0 (104 bits) | prand) mod 2^24
+//! ```
+//!
+//! where `e` is the AES-128 encryption of one block (BLUETOOTH SPECIFICATION
+//! Version 4.2 [Vol 6, Part B], section 1.3.2.2, and [Vol 3, Part H],
+//! section 2.2.2). The block is encrypted with the AES HIL by running
+//! AES-CTR over a zero block, with the block as the counter, which works on
+//! chips that only offer CTR mode. The random part is drawn from the CSPRNG.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! static mut RPA_SOURCE: [u8; 16] = [0; 16];
+//! static mut RPA_DEST: [u8; 16] = [0; 16];
+//!
+//! let rpa = static_init!(
+//!     capsules::ble_privacy::AesRpaGenerator<'static, nrf5x::aes::AesECB<'static>>,
+//!     capsules::ble_privacy::AesRpaGenerator::new(
+//!         &nrf5x::aes::AESECB,
+//!         csprng,
+//!         &mut RPA_SOURCE,
+//!         &mut RPA_DEST
+//!     )
+//! );
+//! nrf5x::aes::AESECB.set_client(rpa);
+//! nrf5x::aes::AESECB.enable();
+//! rpa.set_client(ble_radio);
+//! ble_radio.set_address_generator(rpa);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::csprng::Csprng;
+use kernel::hil::symmetric_encryption::{self, AES128Ctr, AES128, AES128_BLOCK_SIZE};
+use kernel::ReturnCode;
+
+/// The length of an identity resolving key, in bytes
+pub const IRK_LEN: usize = 16;
+
+/// The length of a device address, in bytes
+pub const ADDRESS_LEN: usize = 6;
+
+/// Generates resolvable private addresses.
+pub trait ResolvableAddressGenerator {
+    /// Starts generating an address for `irk`, which is least significant
+    /// byte first, as it is exchanged over HCI. The address is passed to
+    /// `RpaClient::address_generated`. Returns EBUSY if an address is being
+    /// generated.
+    fn generate(&self, irk: &[u8; IRK_LEN]) -> ReturnCode;
+}
+
+pub trait RpaClient {
+    /// `address` is in transmission order, least significant byte first.
+    fn address_generated(&self, result: ReturnCode, address: [u8; ADDRESS_LEN]);
+}
+
+/// Draws a random part from `csprng`, with the two most significant bits
+/// `0b01` and the other bits neither all zeros nor all ones.
+fn random_part(csprng: &Csprng) -> Result<u32, ReturnCode> {
+    loop {
+        let mut bytes = [0; 3];
+        let result = csprng.fill_bytes(&mut bytes);
+        if result != ReturnCode::SUCCESS {
+            return Err(result);
+        }
+        let random = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        let random = random & 0x3f_ffff;
+        if random != 0 && random != 0x3f_ffff {
+            return Ok(0x40_0000 | random);
+        }
+    }
+}
+
+pub struct AesRpaGenerator<'a, A: AES128<'a> + AES128Ctr> {
+    aes: &'a A,
+    csprng: &'a Csprng,
+    client: OptionalCell<&'a RpaClient>,
+    source: TakeCell<'a, [u8]>,
+    dest: TakeCell<'a, [u8]>,
+    /// The random part of the address being generated
+    prand: Cell<u32>,
+}
+
+impl<A: AES128<'a> + AES128Ctr> AesRpaGenerator<'a, A> {
+    /// `source` and `dest` must be `AES128_BLOCK_SIZE` bytes long.
+    pub fn new(
+        aes: &'a A,
+        csprng: &'a Csprng,
+        source: &'a mut [u8],
+        dest: &'a mut [u8],
+    ) -> AesRpaGenerator<'a, A> {
+        AesRpaGenerator {
+            aes: aes,
+            csprng: csprng,
+            client: OptionalCell::empty(),
+            source: TakeCell::new(source),
+            dest: TakeCell::new(dest),
+            prand: Cell::new(0),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a RpaClient) {
+        self.client.set(client);
+    }
+}
+
+impl<A: AES128<'a> + AES128Ctr> ResolvableAddressGenerator for AesRpaGenerator<'a, A> {
+    fn generate(&self, irk: &[u8; IRK_LEN]) -> ReturnCode {
+        if self.source.is_none() || self.dest.is_none() {
+            return ReturnCode::EBUSY;
+        }
+        let prand = match random_part(self.csprng) {
+            Ok(prand) => prand,
+            Err(err) => return err,
+        };
+
+        // `e` takes the key most significant byte first
+        let mut key = [0; IRK_LEN];
+        for (k, i) in key.iter_mut().zip(irk.iter().rev()) {
+            *k = *i;
+        }
+        let mut block = [0; AES128_BLOCK_SIZE];
+        block[13] = (prand >> 16) as u8;
+        block[14] = (prand >> 8) as u8;
+        block[15] = prand as u8;
+
+        let result = self.aes.set_key(&key);
+        if result != ReturnCode::SUCCESS {
+            return result;
+        }
+        let result = self.aes.set_iv(&block);
+        if result != ReturnCode::SUCCESS {
+            return result;
+        }
+        self.aes.set_mode_aes128ctr(true);
+        self.aes.start_message();
+
+        match (self.source.take(), self.dest.take()) {
+            (Some(source), Some(dest)) => {
+                for byte in source.iter_mut() {
+                    *byte = 0;
+                }
+                self.prand.set(prand);
+                match self.aes.crypt(Some(source), dest, 0, AES128_BLOCK_SIZE) {
+                    None => ReturnCode::SUCCESS,
+                    Some((result, source, dest)) => {
+                        source.map(|source| self.source.replace(source));
+                        self.dest.replace(dest);
+                        result
+                    }
+                }
+            }
+            (source, dest) => {
+                source.map(|source| self.source.replace(source));
+                dest.map(|dest| self.dest.replace(dest));
+                ReturnCode::EBUSY
+            }
+        }
+    }
+}
+
+impl<A: AES128<'a> + AES128Ctr> symmetric_encryption::Client<'a> for AesRpaGenerator<'a, A> {
+    fn crypt_done(&self, source: Option<&'a mut [u8]>, dest: &'a mut [u8]) {
+        // The hash is the least significant 24 bits of the encrypted block,
+        // and is sent first.
+        let prand = self.prand.get();
+        let address = [
+            dest[15],
+            dest[14],
+            dest[13],
+            prand as u8,
+            (prand >> 8) as u8,
+            (prand >> 16) as u8,
+        ];
+        source.map(|source| self.source.replace(source));
+        self.dest.replace(dest);
+        self.client
+            .map(|client| client.address_generated(ReturnCode::SUCCESS, address));
+    }
+}
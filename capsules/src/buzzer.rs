@@ -0,0 +1,324 @@
+//! Provides userspace with a buzzer on a PWM channel.
+//!
+//! Apps play a tone, a frequency for a duration, or a sequence of notes from
+//! a buffer they share, for example an alarm melody. The buzzer plays one
+//! request at a time. Each app can have one request waiting, and the apps
+//! take turns in round-robin order, so an app that plays again right away
+//! does not keep the others from playing. A request plays for at most
+//! `MAX_PLAY_MS`, after which the rest of a sequence is dropped.
+//!
+//! The PWM group is the buzzer's: the driver changes its frequency with each
+//! note and stops it between requests.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let buzzer_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, nrf5x::rtc::Rtc>,
+//!     VirtualMuxAlarm::new(mux_alarm));
+//! let buzzer = static_init!(
+//!     capsules::buzzer::Buzzer<'static, VirtualMuxAlarm<'static, nrf5x::rtc::Rtc>>,
+//!     capsules::buzzer::Buzzer::new(
+//!         &nrf52::pwm::PWM0,
+//!         0,
+//!         buzzer_alarm,
+//!         board_kernel.create_grant(&memory_allocation_capability)));
+//! buzzer_alarm.set_client(buzzer);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::hil;
+use kernel::hil::time::{self, Frequency};
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = 0x0000E;
+
+/// The longest a request plays.
+pub const MAX_PLAY_MS: usize = 10_000;
+
+/// The bytes of a note in a sequence: the frequency in Hz and the duration
+/// in ms, as little-endian 16-bit numbers.
+const NOTE_LEN: usize = 4;
+
+#[derive(Clone, Copy)]
+enum Request {
+    Tone { frequency_hz: usize, duration_ms: usize },
+    Sequence { count: usize },
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    notes: Option<AppSlice<Shared, u8>>,
+    request: Option<Request>,
+    /// The next note of the request
+    index: usize,
+}
+
+pub struct Buzzer<'a, A: time::Alarm> {
+    pwm: &'a hil::pwm::Pwm,
+    channel: usize,
+    alarm: &'a A,
+    apps: Grant<App>,
+    /// The app whose request plays
+    current: OptionalCell<AppId>,
+    /// The app whose request played last, to take turns
+    last: OptionalCell<AppId>,
+    /// How long the current request played, in ms
+    elapsed_ms: Cell<usize>,
+}
+
+impl<A: time::Alarm> Buzzer<'a, A> {
+    pub fn new(
+        pwm: &'a hil::pwm::Pwm,
+        channel: usize,
+        alarm: &'a A,
+        grant: Grant<App>,
+    ) -> Buzzer<'a, A> {
+        Buzzer {
+            pwm: pwm,
+            channel: channel,
+            alarm: alarm,
+            apps: grant,
+            current: OptionalCell::empty(),
+            last: OptionalCell::empty(),
+            elapsed_ms: Cell::new(0),
+        }
+    }
+
+    fn ticks(ms: usize) -> u32 {
+        (ms as u64 * A::Frequency::frequency() as u64 / 1000) as u32
+    }
+
+    fn enqueue(&self, appid: AppId, request: Request) -> ReturnCode {
+        let rcode = self
+            .apps
+            .enter(appid, |app, _| {
+                if app.request.is_some() {
+                    return ReturnCode::EBUSY;
+                }
+                app.request = Some(request);
+                app.index = 0;
+                ReturnCode::SUCCESS
+            }).unwrap_or_else(|err| err.into());
+        if rcode == ReturnCode::SUCCESS && self.current.is_none() {
+            self.play_next();
+        }
+        rcode
+    }
+
+    /// Starts the request of the app after the last one, in the order of the
+    /// grants, or stops the buzzer if no app waits.
+    fn play_next(&self) {
+        let mut first = None;
+        let mut next = None;
+        let mut after_last = self.last.is_none();
+        for cntr in self.apps.iter() {
+            let (appid, waiting) = cntr.enter(|app, _| (app.appid(), app.request.is_some()));
+            if waiting {
+                if first.is_none() {
+                    first = Some(appid);
+                }
+                if after_last && next.is_none() {
+                    next = Some(appid);
+                }
+            }
+            if self.last.map_or(false, |last| *last == appid) {
+                after_last = true;
+            }
+        }
+
+        match next.or(first) {
+            Some(appid) => {
+                self.current.set(appid);
+                self.elapsed_ms.set(0);
+                self.play_note();
+            }
+            None => {
+                self.pwm.stop();
+            }
+        }
+    }
+
+    /// The frequency and duration of the next note of the current request,
+    /// or `None` once it played.
+    fn next_note(&self, appid: AppId) -> Option<(usize, usize)> {
+        self.apps
+            .enter(appid, |app, _| {
+                let index = app.index;
+                app.index += 1;
+                match app.request {
+                    Some(Request::Tone {
+                        frequency_hz,
+                        duration_ms,
+                    }) if index == 0 => Some((frequency_hz, duration_ms)),
+                    Some(Request::Sequence { count }) if index < count => {
+                        app.notes.as_ref().and_then(|notes| {
+                            let start = index * NOTE_LEN;
+                            if start + NOTE_LEN > notes.len() {
+                                return None;
+                            }
+                            let note = &notes.as_ref()[start..start + NOTE_LEN];
+                            let frequency_hz = note[0] as usize | (note[1] as usize) << 8;
+                            let duration_ms = note[2] as usize | (note[3] as usize) << 8;
+                            Some((frequency_hz, duration_ms))
+                        })
+                    }
+                    _ => None,
+                }
+            }).unwrap_or(None)
+    }
+
+    fn play_note(&self) {
+        let appid = match self.current.map(|appid| *appid) {
+            Some(appid) => appid,
+            None => return,
+        };
+        let (frequency_hz, duration_ms) = match self.next_note(appid) {
+            Some(note) if self.elapsed_ms.get() + note.1 <= MAX_PLAY_MS => note,
+            _ => return self.finish(appid, ReturnCode::SUCCESS),
+        };
+
+        if frequency_hz == 0 {
+            // A rest
+            self.pwm.stop();
+        } else {
+            match self.pwm.start(frequency_hz) {
+                ReturnCode::SuccessWithValue { .. } => {
+                    let half = self.pwm.get_maximum_duty_cycle() / 2;
+                    self.pwm.set_duty_cycle(self.channel, half);
+                }
+                rcode => return self.finish(appid, rcode),
+            }
+        }
+        self.elapsed_ms.set(self.elapsed_ms.get() + duration_ms);
+        self.alarm.set_alarm(self.alarm.now().wrapping_add(Self::ticks(duration_ms)));
+    }
+
+    /// Reports the end of the current request to its app, with the number of
+    /// notes played, and starts the next one.
+    fn finish(&self, appid: AppId, result: ReturnCode) {
+        self.current.clear();
+        self.last.set(appid);
+        let _ = self.apps.enter(appid, |app, _| {
+            // The index is past the note that ended the request
+            let played = app.index.saturating_sub(1);
+            app.request = None;
+            app.callback.map(|mut cb| cb.schedule(isize::from(result) as usize, played, 0));
+        });
+        self.play_next();
+    }
+
+    fn cancel(&self, appid: AppId) -> ReturnCode {
+        let rcode = self
+            .apps
+            .enter(appid, |app, _| match app.request.take() {
+                Some(_) => ReturnCode::SUCCESS,
+                None => ReturnCode::EOFF,
+            }).unwrap_or_else(|err| err.into());
+        if rcode == ReturnCode::SUCCESS && self.current.map_or(false, |current| *current == appid) {
+            self.alarm.disable();
+            self.current.clear();
+            self.last.set(appid);
+            self.play_next();
+        }
+        rcode
+    }
+}
+
+impl<A: time::Alarm> time::Client for Buzzer<'a, A> {
+    fn fired(&self) {
+        self.play_note();
+    }
+}
+
+impl<A: time::Alarm> Driver for Buzzer<'a, A> {
+    /// Share the notes of sequences.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: The notes, each a frequency in Hz, 0 for a rest, and a duration
+    ///        in ms, as little-endian 16-bit numbers.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.notes = slice;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Subscribe to the end of requests.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Called with the result and the number of notes played when a
+    ///        request of the app ends.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Play tones and sequences.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Play a tone of `data` Hz for `data2` ms.
+    /// - `2`: Play the first `data` notes of the `allow` buffer.
+    /// - `3`: Stop or drop the request of the app, without a callback.
+    ///
+    /// Commands 1 and 2 return EBUSY if the app has a request already.
+    fn command(&self, command_num: usize, data: usize, data2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            1 => {
+                if data == 0 || data2 == 0 || data2 > MAX_PLAY_MS {
+                    return ReturnCode::EINVAL;
+                }
+                self.enqueue(
+                    appid,
+                    Request::Tone {
+                        frequency_hz: data,
+                        duration_ms: data2,
+                    },
+                )
+            }
+
+            2 => {
+                if data == 0 {
+                    return ReturnCode::EINVAL;
+                }
+                self.enqueue(appid, Request::Sequence { count: data })
+            }
+
+            3 => self.cancel(appid),
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
@@ -0,0 +1,303 @@
+//! CAN bus system call interface
+//!
+//! This capsule lets processes send and receive frames on a CAN bus through
+//! a `hil::can::Can` controller. Each process sets its own acceptance filter,
+//! and is only told about the frames that match it. The controller itself
+//! is left accepting every frame. Processes send one frame at a time, and
+//! their frames are sent in turn.
+//!
+//! ## Instantiation
+//!
+//! ```rust
+//! let can = static_init!(
+//!     capsules::can::CanDriver<'static>,
+//!     capsules::can::CanDriver::new(mcp2515, board_kernel.create_grant(&grant_cap))
+//! );
+//! hil::can::Can::set_client(mcp2515, can);
+//! hil::can::Can::enable(mcp2515);
+//! ```
+
+use core::cmp::min;
+use kernel::common::cells::OptionalCell;
+use kernel::hil::can::{self, ErrorState, Filter, Frame, Id, MAX_DATA_LEN};
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+/// Syscall number
+pub const DRIVER_NUM: usize = 0x20007;
+
+/// Flags in the identifier arguments of commands and callbacks
+const EXTENDED_FLAG: usize = 1 << 31;
+const REMOTE_FLAG: usize = 1 << 30;
+
+#[derive(Default)]
+pub struct App {
+    rx_callback: Option<Callback>,
+    tx_callback: Option<Callback>,
+    error_callback: Option<Callback>,
+    rx_buffer: Option<AppSlice<Shared, u8>>,
+    tx_buffer: Option<AppSlice<Shared, u8>>,
+    filter: Option<Filter>,
+    // A frame waiting for the frame of another app to be sent
+    pending_frame: Option<Frame>,
+}
+
+/// Read an identifier from a command argument, checking that it fits
+fn decode_id(arg: usize) -> Option<Id> {
+    let id = (arg & !(EXTENDED_FLAG | REMOTE_FLAG)) as u32;
+    if arg & EXTENDED_FLAG != 0 {
+        if id < 1 << 29 {
+            Some(Id::Extended(id))
+        } else {
+            None
+        }
+    } else if id < 1 << 11 {
+        Some(Id::Standard(id as u16))
+    } else {
+        None
+    }
+}
+
+/// Write an identifier and its flags as a callback argument
+fn encode_id(id: Id, remote: bool) -> usize {
+    let flags = match id {
+        Id::Standard(_) => 0,
+        Id::Extended(_) => EXTENDED_FLAG,
+    };
+    id.value() as usize | flags | if remote { REMOTE_FLAG } else { 0 }
+}
+
+/// The number an error state is passed to apps as
+fn error_state_value(state: ErrorState) -> usize {
+    match state {
+        ErrorState::Active => 0,
+        ErrorState::Passive => 1,
+        ErrorState::BusOff => 2,
+    }
+}
+
+pub struct CanDriver<'a> {
+    can: &'a can::Can,
+    apps: Grant<App>,
+    // The app whose frame the controller is sending
+    sending_app: OptionalCell<AppId>,
+}
+
+impl CanDriver<'a> {
+    pub fn new(can: &'a can::Can, apps: Grant<App>) -> CanDriver<'a> {
+        CanDriver {
+            can: can,
+            apps: apps,
+            sending_app: OptionalCell::empty(),
+        }
+    }
+
+    /// Send the frame described by the arguments of a send command, or queue
+    /// it if another app is sending
+    fn send(&self, appid: AppId, id_arg: usize, len: usize) -> ReturnCode {
+        let id = match decode_id(id_arg) {
+            Some(id) => id,
+            None => return ReturnCode::EINVAL,
+        };
+        if len > MAX_DATA_LEN {
+            return ReturnCode::ESIZE;
+        }
+        self.apps
+            .enter(appid, |app, _| {
+                let sending = self
+                    .sending_app
+                    .map_or(false, |sending_app| sending_app.idx() == appid.idx());
+                if sending || app.pending_frame.is_some() {
+                    return ReturnCode::EBUSY;
+                }
+                let remote = id_arg & REMOTE_FLAG != 0;
+                let mut frame = Frame {
+                    id: id,
+                    remote: remote,
+                    len: len,
+                    data: [0; MAX_DATA_LEN],
+                };
+                if !remote {
+                    match app.tx_buffer {
+                        Some(ref buffer) if buffer.len() >= len => {
+                            frame.data[..len].copy_from_slice(&buffer.as_ref()[..len]);
+                        }
+                        _ => return ReturnCode::EINVAL,
+                    }
+                }
+
+                if self.sending_app.is_some() {
+                    app.pending_frame = Some(frame);
+                    return ReturnCode::SUCCESS;
+                }
+                let result = self.can.send(&frame);
+                if result == ReturnCode::SUCCESS {
+                    self.sending_app.set(appid);
+                }
+                result
+            }).unwrap_or_else(|err| err.into())
+    }
+
+    /// Send the frame of the next app that has one waiting
+    fn send_next(&self) {
+        for cntr in self.apps.iter() {
+            let started = cntr.enter(|app, _| {
+                app.pending_frame.take().map_or(false, |frame| {
+                    let result = self.can.send(&frame);
+                    if result == ReturnCode::SUCCESS {
+                        self.sending_app.set(app.appid());
+                        true
+                    } else {
+                        app.tx_callback
+                            .map(|mut cb| cb.schedule(From::from(result), 0, 0));
+                        false
+                    }
+                })
+            });
+            if started {
+                break;
+            }
+        }
+    }
+}
+
+impl Driver for CanDriver<'a> {
+    /// Setup buffers.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Receive buffer. The data of received frames is written to it.
+    /// - `1`: Transmit buffer. Contains the data of the frame to send.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 | 1 => self
+                .apps
+                .enter(appid, |app, _| {
+                    if allow_num == 0 {
+                        app.rx_buffer = slice;
+                    } else {
+                        app.tx_buffer = slice;
+                    }
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Setup callback for when a frame that matches the filter of the
+    ///        app is received. It is passed the identifier of the frame with
+    ///        its flags, and the number of data bytes.
+    /// - `1`: Setup callback for when the frame of the app was sent. It is
+    ///        passed the result.
+    /// - `2`: Setup callback for when the error state of the controller
+    ///        changes. It is passed the new error state.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 | 1 | 2 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    match subscribe_num {
+                        0 => app.rx_callback = callback,
+                        1 => app.tx_callback = callback,
+                        _ => app.error_callback = callback,
+                    }
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// CAN control
+    ///
+    /// Identifiers are passed with bit 31 set for extended identifiers, and
+    /// bit 30 set for remote frames.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Receive the frames whose identifier is of the same kind as
+    ///        `arg1`, and equal to it in the bits set in the mask `arg2`.
+    /// - `2`: Stop receiving frames.
+    /// - `3`: Send a frame with identifier `arg1` and the first `arg2` bytes
+    ///        of the transmit buffer. Returns EBUSY if a frame of the app is
+    ///        still waiting to be sent, and ESIZE if `arg2` is more than 8.
+    /// - `4`: Get the error state of the controller: 0 for error active, 1
+    ///        for error passive, and 2 for bus-off.
+    fn command(&self, command_num: usize, arg1: usize, arg2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            1 | 2 => {
+                let filter = if command_num == 1 {
+                    match decode_id(arg1) {
+                        Some(id) => Some(Filter {
+                            id: id,
+                            mask: arg2 as u32,
+                        }),
+                        None => return ReturnCode::EINVAL,
+                    }
+                } else {
+                    None
+                };
+                self.apps
+                    .enter(appid, |app, _| {
+                        app.filter = filter;
+                        ReturnCode::SUCCESS
+                    }).unwrap_or_else(|err| err.into())
+            }
+
+            3 => self.send(appid, arg1, arg2),
+
+            4 => ReturnCode::SuccessWithValue {
+                value: error_state_value(self.can.error_state()),
+            },
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+impl can::Client for CanDriver<'a> {
+    fn frame_sent(&self, result: ReturnCode) {
+        self.sending_app.take().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                app.tx_callback
+                    .map(|mut cb| cb.schedule(From::from(result), 0, 0));
+            });
+        });
+        self.send_next();
+    }
+
+    fn frame_received(&self, frame: &Frame) {
+        self.apps.each(|app| {
+            if app.filter.map_or(false, |filter| filter.matches(frame.id)) {
+                if let Some(ref mut buffer) = app.rx_buffer {
+                    let len = min(frame.len, buffer.len());
+                    buffer.as_mut()[..len].copy_from_slice(&frame.data[..len]);
+                }
+                let id = encode_id(frame.id, frame.remote);
+                app.rx_callback.map(|mut cb| cb.schedule(id, frame.len, 0));
+            }
+        });
+    }
+
+    fn error_state_changed(&self, state: ErrorState) {
+        let value = error_state_value(state);
+        self.apps.each(|app| {
+            app.error_callback.map(|mut cb| cb.schedule(value, 0, 0));
+        });
+    }
+}
@@ -0,0 +1,541 @@
+//! A serial port over USB, using the Communications Device Class (CDC)
+//! Abstract Control Model (ACM).
+//!
+//! `CdcAcm` is a client of a USB controller that enumerates as a CDC-ACM
+//! device, which hosts drive with their standard serial port driver (it
+//! appears as `/dev/ttyACM*` on Linux). It implements `hil::uart::UART`, so
+//! the console, or a `UartMux`, can run over USB instead of a UART.
+//!
+//! The device has two interfaces. The communications interface has an
+//! interrupt IN endpoint for notifications, which the host polls but which
+//! never has data, as the device sends no serial state notifications. The
+//! data interface has a bulk IN and a bulk OUT endpoint, which carry the
+//! serial data. The line coding the host sets (baud rate, stop bits and so
+//! on) is reported back to it, but has no effect.
+//!
+//! Data is only sent when the host reads from the port, so a transmission
+//! does not complete until a program on the host opens the port. A packet
+//! that does not fit in the buffer of the current receive is left in the
+//! controller, and is delivered to the next one.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let cdc = static_init!(
+//!     capsules::cdc::CdcAcm<'static, sam4l::usbc::Usbc<'static>>,
+//!     capsules::cdc::CdcAcm::new(&sam4l::usbc::USBC)
+//! );
+//! sam4l::usbc::USBC.set_client(cdc);
+//! hil::usb::Client::enable(cdc);
+//! hil::usb::Client::attach(cdc);
+//!
+//! let uart_mux = static_init!(
+//!     UartMux<'static>,
+//!     UartMux::new(cdc, &mut capsules::virtual_uart::RX_BUF, 115200)
+//! );
+//! hil::uart::UART::set_client(cdc, uart_mux);
+//! ```
+
+use core::cell::Cell;
+use core::cmp::min;
+use kernel::common::cells::{OptionalCell, TakeCell, VolatileCell};
+use kernel::hil;
+use kernel::hil::uart;
+use kernel::ReturnCode;
+use usb::ClassRequest;
+use usb::ControlEndpoint;
+use usb::Descriptor;
+use usb::DeviceClass;
+use usb::DeviceDescriptor;
+use usb::EndpointAddress;
+use usb::EndpointDescriptor;
+use usb::InterfaceDescriptor;
+use usb::Recipient;
+use usb::RequestType;
+use usb::SetupData;
+use usb::TransferDirection;
+use usb::TransferType;
+
+const VENDOR_ID: u16 = 0x6667;
+const PRODUCT_ID: u16 = 0xabce;
+
+static STRINGS: &'static [&'static str] = &[
+    "Tock",        // Manufacturer
+    "Tock Serial", // Product
+    "000001",      // Serial number
+];
+
+/// Class codes, from the CDC 1.2 specification
+const CLASS_CDC: u8 = 0x02;
+const CLASS_CDC_DATA: u8 = 0x0a;
+const SUBCLASS_ACM: u8 = 0x02;
+
+/// Type of the class-specific descriptors of the communications interface
+const CS_INTERFACE: u8 = 0x24;
+
+/// Class-specific requests of the Abstract Control Model
+const SET_LINE_CODING: u8 = 0x20;
+const GET_LINE_CODING: u8 = 0x21;
+const SET_CONTROL_LINE_STATE: u8 = 0x22;
+
+const LINE_CODING_LEN: usize = 7;
+
+/// The endpoints the device uses, other than the default control endpoint
+const BULK_IN: usize = 1;
+const BULK_OUT: usize = 2;
+const NOTIFY_IN: usize = 3;
+
+const N_ENDPOINTS: usize = 4;
+
+/// Size of every packet buffer, and the max packet size of every endpoint
+const PACKET_SIZE: usize = 8;
+
+/// A class-specific descriptor of the communications interface, made of a
+/// subtype followed by its fields
+struct FunctionalDescriptor<'a> {
+    subtype: u8,
+    fields: &'a [u8],
+}
+
+impl Descriptor for FunctionalDescriptor<'a> {
+    fn size(&self) -> usize {
+        3 + self.fields.len()
+    }
+
+    fn write_to_unchecked(&self, buf: &[Cell<u8>]) -> usize {
+        let len = self.size();
+        buf[0].set(len as u8);
+        buf[1].set(CS_INTERFACE);
+        buf[2].set(self.subtype);
+        for (i, field) in self.fields.iter().enumerate() {
+            buf[3 + i].set(*field);
+        }
+        len
+    }
+}
+
+/// Serialize the descriptor at `index` among those that follow the
+/// configuration descriptor, returning its length, or `None` if there are
+/// no more descriptors
+fn write_configuration_part(index: usize, buf: &[Cell<u8>]) -> Option<usize> {
+    let len = match index {
+        // The communications interface
+        0 => InterfaceDescriptor {
+            interface_number: 0,
+            num_endpoints: 1,
+            interface_class: CLASS_CDC,
+            interface_subclass: SUBCLASS_ACM,
+            interface_protocol: 0,
+            ..Default::default()
+        }.write_to(buf),
+        // Header, with the CDC release number 1.10
+        1 => FunctionalDescriptor {
+            subtype: 0x00,
+            fields: &[0x10, 0x01],
+        }.write_to(buf),
+        // Call management: no call management, with data interface 1
+        2 => FunctionalDescriptor {
+            subtype: 0x01,
+            fields: &[0x00, 1],
+        }.write_to(buf),
+        // Abstract control management: supports the line coding and control
+        // line state requests
+        3 => FunctionalDescriptor {
+            subtype: 0x02,
+            fields: &[0x02],
+        }.write_to(buf),
+        // Union: interface 0 controls interface 1
+        4 => FunctionalDescriptor {
+            subtype: 0x06,
+            fields: &[0, 1],
+        }.write_to(buf),
+        5 => EndpointDescriptor {
+            endpoint_address: EndpointAddress::new(NOTIFY_IN, TransferDirection::DeviceToHost),
+            transfer_type: TransferType::Interrupt,
+            max_packet_size: PACKET_SIZE as u16,
+            interval: 255,
+        }.write_to(buf),
+        // The data interface
+        6 => InterfaceDescriptor {
+            interface_number: 1,
+            num_endpoints: 2,
+            interface_class: CLASS_CDC_DATA,
+            interface_subclass: 0,
+            interface_protocol: 0,
+            ..Default::default()
+        }.write_to(buf),
+        7 => EndpointDescriptor {
+            endpoint_address: EndpointAddress::new(BULK_IN, TransferDirection::DeviceToHost),
+            transfer_type: TransferType::Bulk,
+            max_packet_size: PACKET_SIZE as u16,
+            interval: 0,
+        }.write_to(buf),
+        8 => EndpointDescriptor {
+            endpoint_address: EndpointAddress::new(BULK_OUT, TransferDirection::HostToDevice),
+            transfer_type: TransferType::Bulk,
+            max_packet_size: PACKET_SIZE as u16,
+            interval: 0,
+        }.write_to(buf),
+        _ => return None,
+    };
+    Some(len)
+}
+
+/// The class-specific Control transfers
+#[derive(Copy, Clone)]
+pub enum Request {
+    GetLineCoding,
+    SetLineCoding,
+}
+
+pub struct CdcAcm<'a, C: 'a> {
+    // The hardware controller
+    controller: &'a C,
+
+    // The default control endpoint
+    ctrl: ControlEndpoint<'a, C, Request>,
+
+    // A packet buffer for each endpoint
+    buffers: [[VolatileCell<u8>; PACKET_SIZE]; N_ENDPOINTS],
+
+    // The line coding, as last set by the host
+    line_coding: [Cell<u8>; LINE_CODING_LEN],
+
+    // The transmission in progress
+    tx_buffer: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+    tx_offset: Cell<usize>,
+    // The last packet sent was full, and ended a transmission, so the host
+    // needs a zero-length packet to see the end of the transfer
+    tx_zlp: Cell<bool>,
+    delayed_in: Cell<bool>,
+
+    // The reception in progress
+    rx_buffer: TakeCell<'static, [u8]>,
+    rx_len: Cell<usize>,
+    rx_offset: Cell<usize>,
+    // Bytes at the start of the packet in the bulk OUT buffer that were
+    // already delivered
+    rx_skip: Cell<usize>,
+    delayed_out: Cell<bool>,
+
+    client: OptionalCell<&'static uart::Client>,
+}
+
+impl<C: hil::usb::UsbController> CdcAcm<'a, C> {
+    pub fn new(controller: &'a C) -> Self {
+        let line_coding: [Cell<u8>; LINE_CODING_LEN] = Default::default();
+        // 115200 baud, one stop bit, no parity, eight data bits
+        line_coding[0].set(0x00);
+        line_coding[1].set(0xc2);
+        line_coding[2].set(0x01);
+        line_coding[6].set(8);
+
+        CdcAcm {
+            controller: controller,
+            ctrl: ControlEndpoint::new(controller),
+            buffers: Default::default(),
+            line_coding: line_coding,
+            tx_buffer: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            tx_offset: Cell::new(0),
+            tx_zlp: Cell::new(false),
+            delayed_in: Cell::new(false),
+            rx_buffer: TakeCell::empty(),
+            rx_len: Cell::new(0),
+            rx_offset: Cell::new(0),
+            rx_skip: Cell::new(0),
+            delayed_out: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    fn alert_full(&self) {
+        // In case we reported Delay before, alert the controller
+        // that we now have data to send on the Bulk IN endpoint
+        if self.delayed_in.take() {
+            self.controller.endpoint_bulk_resume(BULK_IN);
+        }
+    }
+
+    fn alert_empty(&self) {
+        // In case we reported Delay before, alert the controller
+        // that we can now receive data on the Bulk OUT endpoint
+        if self.delayed_out.take() {
+            self.controller.endpoint_bulk_resume(BULK_OUT);
+        }
+    }
+}
+
+impl<C: hil::usb::UsbController> DeviceClass for CdcAcm<'a, C> {
+    type Request = Request;
+
+    fn device_descriptor(&self) -> DeviceDescriptor {
+        DeviceDescriptor {
+            class: CLASS_CDC,
+            vendor_id: VENDOR_ID,
+            product_id: PRODUCT_ID,
+            manufacturer_string: 1,
+            product_string: 2,
+            serial_number_string: 3,
+            ..Default::default()
+        }
+    }
+
+    fn strings(&self) -> &'static [&'static str] {
+        STRINGS
+    }
+
+    fn num_interfaces(&self) -> u8 {
+        2
+    }
+
+    fn write_configuration_part(&self, index: usize, buf: &[Cell<u8>]) -> Option<usize> {
+        write_configuration_part(index, buf)
+    }
+
+    fn class_request(&self, setup_data: SetupData) -> ClassRequest<Request> {
+        match (
+            setup_data.request_type.request_type(),
+            setup_data.request_type.recipient(),
+        ) {
+            (RequestType::Class, Recipient::Interface) => {}
+            _ => return ClassRequest::Err(hil::usb::CtrlSetupResult::ErrNonstandardRequest),
+        }
+        match setup_data.request_code {
+            SET_LINE_CODING => ClassRequest::Write(Request::SetLineCoding),
+            GET_LINE_CODING => ClassRequest::Read(Request::GetLineCoding, setup_data.length),
+            SET_CONTROL_LINE_STATE => {
+                // There are no modem control lines to set
+                ClassRequest::Ok
+            }
+            _ => ClassRequest::Err(hil::usb::CtrlSetupResult::ErrNonstandardRequest),
+        }
+    }
+
+    fn write_request_part(
+        &self,
+        request: Request,
+        index: usize,
+        buf: &[Cell<u8>],
+    ) -> Option<usize> {
+        match (request, index) {
+            (Request::GetLineCoding, 0) => {
+                for (i, b) in self.line_coding.iter().enumerate() {
+                    buf[i].set(b.get());
+                }
+                Some(LINE_CODING_LEN)
+            }
+            _ => None,
+        }
+    }
+
+    fn request_out(
+        &self,
+        request: Request,
+        packet: &[VolatileCell<u8>],
+        packet_bytes: usize,
+    ) -> hil::usb::CtrlOutResult {
+        match request {
+            Request::SetLineCoding => {
+                let len = min(packet_bytes, LINE_CODING_LEN);
+                for i in 0..len {
+                    self.line_coding[i].set(packet[i].get());
+                }
+                hil::usb::CtrlOutResult::Ok
+            }
+            _ => hil::usb::CtrlOutResult::Halted,
+        }
+    }
+}
+
+impl<C: hil::usb::UsbController> hil::usb::Client for CdcAcm<'a, C> {
+    fn enable(&self) {
+        // Set up the default control endpoint
+        self.controller.endpoint_set_buffer(0, &self.buffers[0]);
+        self.controller
+            .enable_as_device(hil::usb::DeviceSpeed::Full); // must be Full for Bulk transfers
+        self.controller.endpoint_ctrl_out_enable(0);
+
+        // Set up the endpoints of the data interface
+        self.controller
+            .endpoint_set_buffer(BULK_IN, &self.buffers[BULK_IN]);
+        self.controller.endpoint_bulk_in_enable(BULK_IN);
+        self.controller
+            .endpoint_set_buffer(BULK_OUT, &self.buffers[BULK_OUT]);
+        self.controller.endpoint_bulk_out_enable(BULK_OUT);
+
+        // The notification endpoint never has data, so it is enough that the
+        // controller answers it like a Bulk IN endpoint
+        self.controller
+            .endpoint_set_buffer(NOTIFY_IN, &self.buffers[NOTIFY_IN]);
+        self.controller.endpoint_bulk_in_enable(NOTIFY_IN);
+    }
+
+    fn attach(&self) {
+        self.controller.attach();
+    }
+
+    fn bus_reset(&self) {
+        // The controller reinitializes the endpoints, so any packet that was
+        // being delivered is gone, and the endpoints are no longer delayed
+        self.tx_zlp.set(false);
+        self.delayed_in.set(false);
+        self.rx_skip.set(0);
+        self.delayed_out.set(false);
+    }
+
+    /// Handle a Control Setup transaction
+    fn ctrl_setup(&self, endpoint: usize) -> hil::usb::CtrlSetupResult {
+        if endpoint != 0 {
+            // We only support the default Control endpoint
+            return hil::usb::CtrlSetupResult::ErrInvalidDeviceIndex;
+        }
+        self.ctrl.ctrl_setup(self, &self.buffers[endpoint])
+    }
+
+    /// Handle a Control In transaction
+    fn ctrl_in(&self, endpoint: usize) -> hil::usb::CtrlInResult {
+        self.ctrl.ctrl_in(self, &self.buffers[endpoint])
+    }
+
+    /// Handle a Control Out transaction
+    fn ctrl_out(&self, endpoint: usize, packet_bytes: u32) -> hil::usb::CtrlOutResult {
+        self.ctrl
+            .ctrl_out(self, &self.buffers[endpoint], packet_bytes)
+    }
+
+    fn ctrl_status(&self, _endpoint: usize) {
+        // Entered Status stage
+    }
+
+    /// Handle the completion of a Control transfer
+    fn ctrl_status_complete(&self, _endpoint: usize) {
+        self.ctrl.ctrl_status_complete();
+    }
+
+    /// Handle a Bulk IN transaction
+    fn bulk_in(&self, endpoint: usize) -> hil::usb::BulkInResult {
+        if endpoint != BULK_IN {
+            // There are no notifications to send
+            return hil::usb::BulkInResult::Delay;
+        }
+
+        self.tx_buffer.take().map_or_else(
+            || {
+                if self.tx_zlp.take() {
+                    hil::usb::BulkInResult::Packet(0)
+                } else {
+                    // Nothing to send
+                    self.delayed_in.set(true);
+                    hil::usb::BulkInResult::Delay
+                }
+            },
+            |tx_buffer| {
+                // Write a packet into the endpoint buffer
+                let offset = self.tx_offset.get();
+                let len = self.tx_len.get();
+                let packet_bytes = min(PACKET_SIZE, len - offset);
+                let packet = &self.buffers[endpoint];
+                for i in 0..packet_bytes {
+                    packet[i].set(tx_buffer[offset + i]);
+                }
+
+                let offset = offset + packet_bytes;
+                self.tx_zlp.set(false);
+                if offset < len {
+                    self.tx_offset.set(offset);
+                    self.tx_buffer.replace(tx_buffer);
+                } else {
+                    self.tx_zlp.set(packet_bytes == PACKET_SIZE);
+                    self.client.map(move |client| {
+                        client.transmit_complete(tx_buffer, uart::Error::CommandComplete);
+                    });
+                }
+
+                hil::usb::BulkInResult::Packet(packet_bytes)
+            },
+        )
+    }
+
+    /// Handle a Bulk OUT transaction
+    fn bulk_out(&self, endpoint: usize, packet_bytes: u32) -> hil::usb::BulkOutResult {
+        self.rx_buffer.take().map_or_else(
+            || {
+                // No receive in progress: leave the packet in the controller
+                self.delayed_out.set(true);
+                hil::usb::BulkOutResult::Delay
+            },
+            |rx_buffer| {
+                // Consume as much of the packet as fits in the receive buffer
+                let packet_bytes = packet_bytes as usize;
+                let skip = self.rx_skip.get();
+                let offset = self.rx_offset.get();
+                let len = self.rx_len.get();
+                let n = min(packet_bytes.saturating_sub(skip), len - offset);
+                let packet = &self.buffers[endpoint];
+                for i in 0..n {
+                    rx_buffer[offset + i] = packet[skip + i].get();
+                }
+
+                let offset = offset + n;
+                let skip = skip + n;
+                let result = if skip < packet_bytes {
+                    // The rest of the packet is delivered to the next receive
+                    self.rx_skip.set(skip);
+                    self.delayed_out.set(true);
+                    hil::usb::BulkOutResult::Delay
+                } else {
+                    self.rx_skip.set(0);
+                    hil::usb::BulkOutResult::Ok
+                };
+
+                if offset < len {
+                    self.rx_offset.set(offset);
+                    self.rx_buffer.replace(rx_buffer);
+                } else {
+                    self.client.map(move |client| {
+                        client.receive_complete(rx_buffer, offset, uart::Error::CommandComplete);
+                    });
+                }
+
+                result
+            },
+        )
+    }
+}
+
+impl<C: hil::usb::UsbController> uart::UART for CdcAcm<'a, C> {
+    fn set_client(&self, client: &'static uart::Client) {
+        self.client.set(client);
+    }
+
+    fn configure(&self, _params: uart::UARTParameters) -> ReturnCode {
+        // The host sets the line coding, which has no effect anyway
+        ReturnCode::SUCCESS
+    }
+
+    fn transmit(&self, tx_data: &'static mut [u8], tx_len: usize) {
+        self.tx_len.set(min(tx_len, tx_data.len()));
+        self.tx_offset.set(0);
+        self.tx_buffer.replace(tx_data);
+        self.alert_full();
+    }
+
+    fn receive(&self, rx_buffer: &'static mut [u8], rx_len: usize) {
+        self.rx_len.set(min(rx_len, rx_buffer.len()));
+        self.rx_offset.set(0);
+        self.rx_buffer.replace(rx_buffer);
+        self.alert_empty();
+    }
+
+    fn abort_receive(&self) {
+        self.rx_buffer.take().map(|rx_buffer| {
+            let rx_offset = self.rx_offset.get();
+            self.client.map(move |client| {
+                client.receive_complete(rx_buffer, rx_offset, uart::Error::Aborted);
+            });
+        });
+    }
+}
@@ -0,0 +1,188 @@
+//! Lets processes keep a small blob of state across restarts.
+//!
+//! A process saves a checkpoint, for example calibration data or counters,
+//! and reads it back after the kernel restarts it because of a fault. Since
+//! grants are reset when a process restarts, the checkpoints are held in
+//! kernel memory the board provides, with one `Checkpoint` per process slot.
+//! Each checkpoint remembers the flash region of the app that saved it, so a
+//! different app that is later loaded into the slot does not read it.
+//! Checkpoints do not survive a reboot of the board.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! static mut CHECKPOINTS: [capsules::checkpoint::Checkpoint; NUM_PROCS] =
+//!     [capsules::checkpoint::Checkpoint::new(); NUM_PROCS];
+//!
+//! let checkpoint = static_init!(
+//!     capsules::checkpoint::CheckpointDriver,
+//!     capsules::checkpoint::CheckpointDriver::new(
+//!         &mut CHECKPOINTS,
+//!         board_kernel.create_grant(&memory_allocation_capability)
+//!     )
+//! );
+//! ```
+
+use core::cmp;
+use kernel::common::cells::TakeCell;
+use kernel::{AppId, AppSlice, Driver, Grant, ReturnCode, Shared};
+
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = 0x10002;
+
+/// Maximum size of a checkpoint in bytes.
+pub const CHECKPOINT_SIZE: usize = 64;
+
+/// The checkpoint of one process slot.
+#[derive(Clone, Copy)]
+pub struct Checkpoint {
+    /// The flash region of the app that saved the checkpoint
+    owner: (usize, usize),
+    len: usize,
+    data: [u8; CHECKPOINT_SIZE],
+}
+
+impl Checkpoint {
+    pub const fn new() -> Checkpoint {
+        Checkpoint {
+            owner: (0, 0),
+            len: 0,
+            data: [0; CHECKPOINT_SIZE],
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct App {
+    buffer: Option<AppSlice<Shared, u8>>,
+}
+
+pub struct CheckpointDriver {
+    checkpoints: TakeCell<'static, [Checkpoint]>,
+    apps: Grant<App>,
+}
+
+impl CheckpointDriver {
+    pub fn new(checkpoints: &'static mut [Checkpoint], grant: Grant<App>) -> CheckpointDriver {
+        CheckpointDriver {
+            checkpoints: TakeCell::new(checkpoints),
+            apps: grant,
+        }
+    }
+
+    /// Copy the `allow` buffer into the checkpoint of the app.
+    fn save(&self, appid: AppId) -> ReturnCode {
+        self.apps
+            .enter(appid, |app, _| {
+                app.buffer.as_mut().map_or(ReturnCode::ERESERVE, |app_buffer| {
+                    if app_buffer.len() > CHECKPOINT_SIZE {
+                        return ReturnCode::ESIZE;
+                    }
+                    self.checkpoints.map_or(ReturnCode::FAIL, |checkpoints| {
+                        checkpoints
+                            .get_mut(appid.idx())
+                            .map_or(ReturnCode::ENOMEM, |checkpoint| {
+                                let length = app_buffer.len();
+                                checkpoint.data[..length].copy_from_slice(app_buffer.as_ref());
+                                checkpoint.owner = appid.get_editable_flash_range();
+                                checkpoint.len = length;
+                                ReturnCode::SUCCESS
+                            })
+                    })
+                })
+            }).unwrap_or_else(|err| err.into())
+    }
+
+    /// Copy the checkpoint of the app into the `allow` buffer, returning the
+    /// size of the checkpoint.
+    fn restore(&self, appid: AppId) -> ReturnCode {
+        self.apps
+            .enter(appid, |app, _| {
+                app.buffer.as_mut().map_or(ReturnCode::ERESERVE, |app_buffer| {
+                    self.checkpoints.map_or(ReturnCode::FAIL, |checkpoints| {
+                        checkpoints
+                            .get(appid.idx())
+                            .map_or(ReturnCode::ENOMEM, |checkpoint| {
+                                // A checkpoint of an app that was in the slot
+                                // before is not the app's.
+                                if checkpoint.owner != appid.get_editable_flash_range() {
+                                    return ReturnCode::SuccessWithValue { value: 0 };
+                                }
+                                let length = cmp::min(app_buffer.len(), checkpoint.len);
+                                app_buffer.as_mut()[..length]
+                                    .copy_from_slice(&checkpoint.data[..length]);
+                                ReturnCode::SuccessWithValue {
+                                    value: checkpoint.len,
+                                }
+                            })
+                    })
+                })
+            }).unwrap_or_else(|err| err.into())
+    }
+
+    /// Drop the checkpoint of the app.
+    fn clear(&self, appid: AppId) -> ReturnCode {
+        self.checkpoints.map_or(ReturnCode::FAIL, |checkpoints| {
+            checkpoints
+                .get_mut(appid.idx())
+                .map_or(ReturnCode::ENOMEM, |checkpoint| {
+                    checkpoint.len = 0;
+                    ReturnCode::SUCCESS
+                })
+        })
+    }
+}
+
+impl Driver for CheckpointDriver {
+    /// Setup the buffer to save and restore checkpoints with.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Buffer holding the state to save, or to restore the state into.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.buffer = slice;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Save and restore checkpoints.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Save the `allow` buffer as the checkpoint. Returns `ESIZE` if
+    ///   the buffer is larger than a checkpoint can be.
+    /// - `2`: Copy the checkpoint into the `allow` buffer. Returns the size of
+    ///   the checkpoint, 0 if there is none.
+    /// - `3`: Clear the checkpoint.
+    /// - `4`: Get the maximum size of a checkpoint.
+    fn command(&self, command_num: usize, _: usize, _: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            1 => self.save(appid),
+
+            2 => self.restore(appid),
+
+            3 => self.clear(appid),
+
+            4 => ReturnCode::SuccessWithValue {
+                value: CHECKPOINT_SIZE,
+            },
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
@@ -30,29 +30,109 @@
 //! command(CONSOLE_DRIVER_NUM, 1, len_to_write_in_bytes)
 //! ```
 //!
-//! When the buffer has been written successfully, the buffer is released from
-//! the driver. Successive writes must call `allow` each time a buffer is to be
-//! written.
+//! Each process has a buffer for its output in its grant. A write that fits
+//! in the free space of that buffer is copied at once, and its callback is
+//! scheduled right away, so the process can keep writing while the output of
+//! other processes is sent. A longer write is copied in parts as the buffer
+//! drains, and its callback comes once the last part is copied. The buffer
+//! shared with `allow` is released when the callback is scheduled, so
+//! successive writes must call `allow` each time a buffer is to be written.
+//!
+//! The output of processes is sent one line at a time, taking turns between
+//! processes, so that lines of concurrent processes and of kernel debug
+//! output sharing the UART are not mixed up. With `enable_app_prefixes`,
+//! each line starts with the name of the process that printed it, for
+//! example `[blink] `.
+//!
+//! If the whole buffer is written, the output buffer of the process is empty,
+//! lines are not prefixed and the MPU can make exactly the buffer read-only
+//! to the app, the UART transmits straight from the buffer instead of copying
+//! it. On Cortex-M, this needs a buffer whose size is a power of two of at
+//! least 32 bytes and that is aligned to its size.
+//!
+//! Reading
+//! -------
+//!
+//! Input is read a line at a time. Backspace removes the last character of
+//! the line, and a line ends at a carriage return or newline. A read started
+//! with `command(CONSOLE_DRIVER_NUM, 2, len)` completes with the next line,
+//! without its end, truncated to `len` bytes. Several processes can wait for
+//! a line at once: if a process is focused, only it gets the line, otherwise
+//! all of them do.
+//!
+//! Lines that start with `!` are commands for the kernel and are not passed
+//! to processes:
+//!
+//! * `!focus <process>`: only pass lines to the process, given by its name if
+//!   prefixes are enabled, or otherwise by its index
+//! * `!focus`: pass lines to all processes again
+//!
+//! Other commands go to the `CommandClient` of the console, if any.
 
+use core::cell::Cell;
 use core::cmp;
-use kernel::common::cells::{OptionalCell, TakeCell};
+use core::str;
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::common::cells::{MapCell, OptionalCell, TakeCell};
 use kernel::hil::uart::{self, Client, UART};
-use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+use kernel::introspection::Introspection;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, LentAppSlice, ReturnCode, Shared};
 
 /// Syscall driver number.
 pub const DRIVER_NUM: usize = 0x00000001;
 
-#[derive(Default)]
+/// The size of the output buffer of each process.
+const APP_BUF_LEN: usize = 256;
+
+/// The longest line of input, longer lines are truncated.
+const MAX_LINE_LEN: usize = 64;
+
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7f;
+
+/// Handles kernel commands typed on the console.
+pub trait CommandClient {
+    /// `command` is the line without its leading `!`.
+    fn command(&self, command: &[u8]);
+}
+
 pub struct App {
     write_callback: Option<Callback>,
     write_buffer: Option<AppSlice<Shared, u8>>,
     write_len: usize,
-    write_remaining: usize, // How many bytes didn't fit in the buffer and still need to be printed.
-    pending_write: bool,
+    /// How many bytes of the write are copied into `tx_ring`.
+    write_copied: usize,
+    /// Output that is not sent yet.
+    tx_ring: [u8; APP_BUF_LEN],
+    tx_head: usize,
+    tx_count: usize,
+    /// Whether the next byte sent starts a line.
+    line_start: bool,
 
     read_callback: Option<Callback>,
     read_buffer: Option<AppSlice<Shared, u8>>,
     read_len: usize,
+    /// Whether the app waits for a line.
+    read_pending: bool,
+}
+
+impl Default for App {
+    fn default() -> App {
+        App {
+            write_callback: None,
+            write_buffer: None,
+            write_len: 0,
+            write_copied: 0,
+            tx_ring: [0; APP_BUF_LEN],
+            tx_head: 0,
+            tx_count: 0,
+            line_start: true,
+            read_callback: None,
+            read_buffer: None,
+            read_len: 0,
+            read_pending: false,
+        }
+    }
 }
 
 pub static mut WRITE_BUF: [u8; 64] = [0; 64];
@@ -62,10 +142,22 @@ pub struct Console<'a, U: UART> {
     uart: &'a U,
     apps: Grant<App>,
     tx_in_progress: OptionalCell<AppId>,
+    /// The index of the app whose output was sent last.
+    tx_last: OptionalCell<usize>,
     tx_buffer: TakeCell<'static, [u8]>,
-    rx_in_progress: OptionalCell<AppId>,
+    /// The app buffer the UART transmits from, if it was not copied.
+    tx_lent: MapCell<LentAppSlice>,
+    /// Holds the buffer until the first read starts receiving.
     rx_buffer: TakeCell<'static, [u8]>,
+    line: Cell<[u8; MAX_LINE_LEN]>,
+    line_len: Cell<usize>,
+    /// The app that gets all input, if any.
+    focus: OptionalCell<AppId>,
+    command_client: OptionalCell<&'a CommandClient>,
     baud_rate: u32,
+    /// Set to prefix lines with the name of the process, and to focus apps
+    /// by name.
+    prefixes: OptionalCell<(&'a Introspection, &'a ProcessManagementCapability)>,
 }
 
 impl<U: UART> Console<'a, U> {
@@ -80,10 +172,16 @@ impl<U: UART> Console<'a, U> {
             uart: uart,
             apps: grant,
             tx_in_progress: OptionalCell::empty(),
+            tx_last: OptionalCell::empty(),
             tx_buffer: TakeCell::new(tx_buffer),
-            rx_in_progress: OptionalCell::empty(),
+            tx_lent: MapCell::empty(),
             rx_buffer: TakeCell::new(rx_buffer),
+            line: Cell::new([0; MAX_LINE_LEN]),
+            line_len: Cell::new(0),
+            focus: OptionalCell::empty(),
+            command_client: OptionalCell::empty(),
             baud_rate: baud_rate,
+            prefixes: OptionalCell::empty(),
         }
     }
 
@@ -96,93 +194,190 @@ impl<U: UART> Console<'a, U> {
         });
     }
 
+    /// Starts each line of output with the name of the process that printed
+    /// it. Finding the name needs the `ProcessManagementCapability`.
+    pub fn enable_app_prefixes(
+        &self,
+        introspection: &'a Introspection,
+        capability: &'a ProcessManagementCapability,
+    ) {
+        self.prefixes.set((introspection, capability));
+    }
+
+    /// Passes console lines starting with `!` that the console doesn't handle
+    /// itself to `client`.
+    pub fn set_command_client(&self, client: &'a CommandClient) {
+        self.command_client.set(client);
+    }
+
     /// Internal helper function for setting up a new send transaction
-    fn send_new(&self, app_id: AppId, app: &mut App, len: usize) -> ReturnCode {
+    fn send_new(&self, app: &mut App, len: usize) -> ReturnCode {
+        if app.write_len > 0 {
+            return ReturnCode::EBUSY;
+        }
         match app.write_buffer.take() {
             Some(slice) => {
                 app.write_len = cmp::min(len, slice.len());
-                app.write_remaining = app.write_len;
-                self.send(app_id, app, slice);
+                app.write_copied = 0;
+                if app.write_len == 0 {
+                    app.write_callback.map(|mut cb| {
+                        cb.schedule(0, 0, 0);
+                    });
+                    return ReturnCode::SUCCESS;
+                }
+                app.write_buffer = Some(slice);
+
+                // A longer write is copied when it is sent, so it can be
+                // sent straight from the app buffer.
+                if app.write_len <= APP_BUF_LEN - app.tx_count {
+                    self.fill_ring(app);
+                }
                 ReturnCode::SUCCESS
             }
             None => ReturnCode::EBUSY,
         }
     }
 
-    /// Internal helper function for continuing a previously set up transaction
-    /// Returns true if this send is still active, or false if it has completed
-    fn send_continue(&self, app_id: AppId, app: &mut App) -> Result<bool, ReturnCode> {
-        if app.write_remaining > 0 {
-            app.write_buffer
-                .take()
-                .map_or(Err(ReturnCode::ERESERVE), |slice| {
-                    self.send(app_id, app, slice);
-                    Ok(true)
-                })
-        } else {
-            Ok(false)
+    /// Internal helper function for copying as much of the write as fits
+    /// into the output buffer of the app. Signals the app once all of it is
+    /// copied.
+    fn fill_ring(&self, app: &mut App) {
+        if app.write_copied < app.write_len {
+            if let Some(ref slice) = app.write_buffer {
+                let data = slice.as_ref();
+                while app.write_copied < app.write_len && app.tx_count < APP_BUF_LEN {
+                    let tail = (app.tx_head + app.tx_count) % APP_BUF_LEN;
+                    app.tx_ring[tail] = data[app.write_copied];
+                    app.tx_count += 1;
+                    app.write_copied += 1;
+                }
+            } else {
+                // The buffer was replaced during the write, stop at what
+                // was copied.
+                app.write_len = app.write_copied;
+            }
+        }
+
+        if app.write_len > 0 && app.write_copied == app.write_len {
+            let written = app.write_len;
+            app.write_len = 0;
+            app.write_buffer = None;
+            app.write_callback.map(|mut cb| {
+                cb.schedule(written, 0, 0);
+            });
         }
     }
 
-    /// Internal helper function for sending data for an existing transaction.
-    /// Cannot fail. If can't send now, it will schedule for sending later.
-    fn send(&self, app_id: AppId, app: &mut App, slice: AppSlice<Shared, u8>) {
-        if self.tx_in_progress.is_none() {
-            self.tx_in_progress.set(app_id);
-            self.tx_buffer.take().map(|buffer| {
-                let mut transaction_len = app.write_remaining;
-                for (i, c) in slice.as_ref()[slice.len() - app.write_remaining..slice.len()]
-                    .iter()
-                    .enumerate()
-                {
-                    if buffer.len() <= i {
-                        break;
+    /// Internal helper function for starting the next transmission, if the
+    /// UART is idle. Apps take turns, starting after the app sent last.
+    fn transmit_next(&self) {
+        if self.tx_in_progress.is_some() {
+            return;
+        }
+        let last = self.tx_last.map(|last| *last);
+        for &skip_served in [true, false].iter() {
+            for cntr in self.apps.iter() {
+                let started_tx = cntr.enter(|app, _| {
+                    let appid = app.appid();
+                    if skip_served && last.map_or(false, |last| appid.idx() <= last) {
+                        false
+                    } else {
+                        self.transmit(appid, app)
                     }
-                    buffer[i] = *c;
+                });
+                if started_tx {
+                    return;
                 }
+            }
+        }
+    }
 
-                // Check if everything we wanted to print
-                // fit in the buffer.
-                if app.write_remaining > buffer.len() {
-                    transaction_len = buffer.len();
-                    app.write_remaining -= buffer.len();
-                    app.write_buffer = Some(slice);
+    /// Internal helper function for sending the next line of output of an
+    /// app. Returns true if a transmission started.
+    fn transmit(&self, app_id: AppId, app: &mut App) -> bool {
+        // Transmit straight from the app buffer if the whole buffer is
+        // written and can be lent.
+        if app.tx_count == 0
+            && app.write_len > 0
+            && app.write_copied == 0
+            && self.prefixes.is_none()
+        {
+            if let Some(slice) = app.write_buffer.take() {
+                if slice.len() == app.write_len {
+                    match slice.lend() {
+                        Ok((lent, buffer)) => {
+                            self.tx_lent.put(lent);
+                            app.write_copied = app.write_len;
+                            app.line_start = buffer[app.write_len - 1] == b'\n';
+                            self.tx_in_progress.set(app_id);
+                            self.tx_last.set(app_id.idx());
+                            self.uart.transmit(buffer, app.write_len);
+                            return true;
+                        }
+                        Err(slice) => app.write_buffer = Some(slice),
+                    }
                 } else {
-                    app.write_remaining = 0;
+                    app.write_buffer = Some(slice);
                 }
+            }
+        }
 
-                self.uart.transmit(buffer, transaction_len);
-            });
-        } else {
-            app.pending_write = true;
-            app.write_buffer = Some(slice);
+        self.fill_ring(app);
+        if app.tx_count == 0 {
+            return false;
         }
+
+        self.tx_buffer.take().map_or(false, |buffer| {
+            let mut len = 0;
+            if app.line_start {
+                self.prefixes.map(|prefixes| {
+                    let (introspection, capability) = *prefixes;
+                    // Leave at least half of the buffer for output
+                    let name = introspection.process_name(app_id, capability).as_bytes();
+                    let name_len = cmp::min(name.len(), buffer.len() / 2 - 3);
+                    buffer[0] = b'[';
+                    buffer[1..1 + name_len].copy_from_slice(&name[..name_len]);
+                    buffer[1 + name_len] = b']';
+                    buffer[2 + name_len] = b' ';
+                    len = name_len + 3;
+                });
+            }
+
+            // Stop at the end of a line, so that other output only comes
+            // between lines.
+            while app.tx_count > 0 && len < buffer.len() {
+                let byte = app.tx_ring[app.tx_head];
+                app.tx_head = (app.tx_head + 1) % APP_BUF_LEN;
+                app.tx_count -= 1;
+                buffer[len] = byte;
+                len += 1;
+                app.line_start = byte == b'\n';
+                if app.line_start {
+                    break;
+                }
+            }
+
+            self.tx_in_progress.set(app_id);
+            self.tx_last.set(app_id.idx());
+            self.uart.transmit(buffer, len);
+            true
+        })
     }
 
     /// Internal helper function for starting a receive operation
-    fn receive_new(&self, app_id: AppId, app: &mut App, len: usize) -> ReturnCode {
-        if self.rx_buffer.is_none() {
-            // For now, we tolerate only one concurrent receive operation on this console.
-            // Competing apps will have to retry until success.
+    fn receive_new(&self, app: &mut App, len: usize) -> ReturnCode {
+        if app.read_pending {
             return ReturnCode::EBUSY;
         }
-
         match app.read_buffer {
             Some(ref slice) => {
-                let read_len = cmp::min(len, slice.len());
-                if read_len > self.rx_buffer.map_or(0, |buf| buf.len()) {
-                    // For simplicity, impose a small maximum receive length
-                    // instead of doing incremental reads
-                    ReturnCode::EINVAL
-                } else {
-                    // Note: We have ensured above that rx_buffer is present
-                    app.read_len = read_len;
-                    self.rx_buffer.take().map(|buffer| {
-                        self.rx_in_progress.set(app_id);
-                        self.uart.receive(buffer, app.read_len);
-                    });
-                    ReturnCode::SUCCESS
-                }
+                app.read_len = cmp::min(len, slice.len());
+                app.read_pending = true;
+                // Input is read continuously once the first read starts.
+                self.rx_buffer
+                    .take()
+                    .map(|buffer| self.uart.receive(buffer, 1));
+                ReturnCode::SUCCESS
             }
             None => {
                 // Must supply read buffer before performing receive operation
@@ -190,6 +385,88 @@ impl<U: UART> Console<'a, U> {
             }
         }
     }
+
+    /// Internal helper function for completing the read of an app with
+    /// `line`, or cancelling it if `line` is `None`.
+    fn receive_done(&self, app: &mut App, line: Option<&[u8]>) {
+        if !app.read_pending {
+            return;
+        }
+        app.read_pending = false;
+        let read_len = app.read_len;
+        let (result, len) = match (line, app.read_buffer.take()) {
+            (Some(line), Some(mut app_buffer)) => {
+                let len = cmp::min(line.len(), read_len);
+                app_buffer.as_mut()[..len].copy_from_slice(&line[..len]);
+                (ReturnCode::SUCCESS, len)
+            }
+            // Oops, no app buffer
+            (Some(_), None) => (ReturnCode::EINVAL, 0),
+            (None, app_buffer) => {
+                app.read_buffer = app_buffer;
+                (ReturnCode::ECANCEL, 0)
+            }
+        };
+        app.read_callback.map(|mut cb| {
+            cb.schedule(From::from(result), len, 0);
+        });
+    }
+
+    /// Internal helper function for passing a line to the focused app, or to
+    /// all apps waiting for one.
+    fn dispatch_line(&self, line: &[u8]) {
+        if line.first() == Some(&b'!') {
+            self.execute(&line[1..]);
+            return;
+        }
+        match self.focus.map(|appid| *appid) {
+            Some(appid) => {
+                let _ = self.apps.enter(appid, |app, _| {
+                    self.receive_done(app, Some(line));
+                });
+            }
+            None => self.apps.each(|app| self.receive_done(app, Some(line))),
+        }
+    }
+
+    /// Internal helper function for running a kernel command.
+    fn execute(&self, command: &[u8]) {
+        let mut words = command
+            .split(|&c| c == b' ')
+            .filter(|word| !word.is_empty());
+        match (words.next(), words.next()) {
+            (Some(b"focus"), None) => self.focus.clear(),
+            (Some(b"focus"), Some(name)) => {
+                for cntr in self.apps.iter() {
+                    let appid = cntr.appid();
+                    if self.is_named(appid, name) {
+                        self.focus.set(appid);
+                        break;
+                    }
+                }
+            }
+            _ => {
+                self.command_client.map(|client| client.command(command));
+            }
+        }
+    }
+
+    /// Internal helper function for matching an app with the name of its
+    /// process, or with its index if the names are not available.
+    fn is_named(&self, appid: AppId, name: &[u8]) -> bool {
+        self.prefixes.map_or_else(
+            || {
+                str::from_utf8(name)
+                    .ok()
+                    .and_then(|index| index.parse::<usize>().ok())
+                    == Some(appid.idx())
+            },
+            |prefixes| {
+                let (introspection, capability) = *prefixes;
+                introspection.process_name(appid, capability).as_bytes() == name
+            },
+        )
+    }
 }
 
 impl<U: UART> Driver for Console<'a, U> {
@@ -266,19 +543,25 @@ impl<U: UART> Driver for Console<'a, U> {
             0 /* check if present */ => ReturnCode::SUCCESS,
             1 /* putstr */ => {
                 let len = arg1;
-                self.apps.enter(appid, |app, _| {
-                    self.send_new(appid, app, len)
-                }).unwrap_or_else(|err| err.into())
+                let result = self.apps.enter(appid, |app, _| {
+                    self.send_new(app, len)
+                }).unwrap_or_else(|err| err.into());
+                if result == ReturnCode::SUCCESS {
+                    self.transmit_next();
+                }
+                result
             },
             2 /* getnstr */ => {
                 let len = arg1;
                 self.apps.enter(appid, |app, _| {
-                    self.receive_new(appid, app, len)
+                    self.receive_new(app, len)
                 }).unwrap_or_else(|err| err.into())
             },
             3 /* abort rx */ => {
-                self.uart.abort_receive();
-                ReturnCode::SUCCESS
+                self.apps.enter(appid, |app, _| {
+                    self.receive_done(app, None);
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into())
             }
             _ => ReturnCode::ENOSUPPORT
         }
@@ -287,106 +570,46 @@ impl<U: UART> Driver for Console<'a, U> {
 
 impl<U: UART> Client for Console<'a, U> {
     fn transmit_complete(&self, buffer: &'static mut [u8], _error: uart::Error) {
-        // Either print more from the AppSlice or send a callback to the
-        // application.
-        self.tx_buffer.replace(buffer);
+        match self.tx_lent.take() {
+            // The app buffer is released once written, like a copied one.
+            Some(lent) => {
+                let _ = lent.reclaim(buffer);
+            }
+            None => {
+                self.tx_buffer.replace(buffer);
+            }
+        }
+
+        // Copy more of the write into the output buffer that drained, or
+        // signal the app once a lent buffer is written.
         self.tx_in_progress.take().map(|appid| {
             self.apps.enter(appid, |app, _| {
-                match self.send_continue(appid, app) {
-                    Ok(more_to_send) => {
-                        if !more_to_send {
-                            // Go ahead and signal the application
-                            let written = app.write_len;
-                            app.write_len = 0;
-                            app.write_callback.map(|mut cb| {
-                                cb.schedule(written, 0, 0);
-                            });
-                        }
-                    }
-                    Err(return_code) => {
-                        // XXX This shouldn't ever happen?
-                        app.write_len = 0;
-                        app.write_remaining = 0;
-                        app.pending_write = false;
-                        let r0 = isize::from(return_code) as usize;
-                        app.write_callback.map(|mut cb| {
-                            cb.schedule(r0, 0, 0);
-                        });
-                    }
-                }
+                self.fill_ring(app);
             })
         });
 
-        // If we are not printing more from the current AppSlice,
-        // see if any other applications have pending messages.
-        if self.tx_in_progress.is_none() {
-            for cntr in self.apps.iter() {
-                let started_tx = cntr.enter(|app, _| {
-                    if app.pending_write {
-                        app.pending_write = false;
-                        match self.send_continue(app.appid(), app) {
-                            Ok(more_to_send) => more_to_send,
-                            Err(return_code) => {
-                                // XXX This shouldn't ever happen?
-                                app.write_len = 0;
-                                app.write_remaining = 0;
-                                app.pending_write = false;
-                                let r0 = isize::from(return_code) as usize;
-                                app.write_callback.map(|mut cb| {
-                                    cb.schedule(r0, 0, 0);
-                                });
-                                false
-                            }
-                        }
-                    } else {
-                        false
-                    }
-                });
-                if started_tx {
-                    break;
-                }
-            }
-        }
+        self.transmit_next();
     }
 
     fn receive_complete(&self, buffer: &'static mut [u8], rx_len: usize, error: uart::Error) {
-        self.rx_in_progress
-            .take()
-            .map(|appid| {
-                self.apps
-                    .enter(appid, |app, _| {
-                        app.read_callback.map(|mut cb| {
-                            // An iterator over the returned buffer yielding only the first `rx_len`
-                            // bytes
-                            let rx_buffer = buffer.iter().take(rx_len);
-                            match error {
-                                uart::Error::CommandComplete | uart::Error::Aborted => {
-                                    // Receive some bytes, signal error type and return bytes to process buffer
-                                    if let Some(mut app_buffer) = app.read_buffer.take() {
-                                        for (a, b) in app_buffer.iter_mut().zip(rx_buffer) {
-                                            *a = *b;
-                                        }
-                                        let rettype = if error == uart::Error::CommandComplete {
-                                            ReturnCode::SUCCESS
-                                        } else {
-                                            ReturnCode::ECANCEL
-                                        };
-                                        cb.schedule(From::from(rettype), rx_len, 0);
-                                    } else {
-                                        // Oops, no app buffer
-                                        cb.schedule(From::from(ReturnCode::EINVAL), 0, 0);
-                                    }
-                                }
-                                _ => {
-                                    // Some UART error occurred
-                                    cb.schedule(From::from(ReturnCode::FAIL), 0, 0);
-                                }
-                            }
-                        });
-                    }).unwrap_or_default();
-            }).unwrap_or_default();
-
-        // Whatever happens, we want to make sure to replace the rx_buffer for future transactions
-        self.rx_buffer.replace(buffer);
+        if rx_len > 0 && error == uart::Error::CommandComplete {
+            let c = buffer[0];
+            let mut line = self.line.get();
+            let len = self.line_len.get();
+            if c == b'\r' || c == b'\n' {
+                // Skip the empty line between a carriage return and a newline
+                if len > 0 {
+                    self.line_len.set(0);
+                    self.dispatch_line(&line[..len]);
+                }
+            } else if c == BACKSPACE || c == DELETE {
+                self.line_len.set(len.saturating_sub(1));
+            } else if len < MAX_LINE_LEN {
+                line[len] = c;
+                self.line.set(line);
+                self.line_len.set(len + 1);
+            }
+        }
+        self.uart.receive(buffer, 1);
     }
 }
@@ -0,0 +1,284 @@
+//! Keeps the output of the last kernel panic in a flash page, so it can be
+//! retrieved after the board reboots.
+//!
+//! `CrashLog` is a `kernel::debug::PanicSink`. When the kernel panics it
+//! collects the panic output in its page buffer, truncating what does not fit,
+//! and writes the buffer to its flash page. Since interrupts are not serviced
+//! after a panic, the board provides a `poll` function that runs the flash
+//! controller's interrupt handler once it is pending.
+//!
+//! At boot the capsule reads the page back, and processes can read the log
+//! through the syscall driver and clear it once they have stored or sent it
+//! elsewhere.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! pub static mut CRASH_LOG_PAGE: sam4l::flashcalw::Sam4lPage =
+//!     sam4l::flashcalw::Sam4lPage::new();
+//!
+//! fn poll_flash() {
+//!     unsafe {
+//!         if cortexm4::nvic::next_pending() == Some(sam4l::nvic::HFLASHC) {
+//!             sam4l::flashcalw::FLASH_CONTROLLER.handle_interrupt();
+//!             cortexm4::nvic::Nvic::new(sam4l::nvic::HFLASHC).clear_pending();
+//!         }
+//!     }
+//! }
+//!
+//! let crash_log = static_init!(
+//!     capsules::crash_log::CrashLog<'static, sam4l::flashcalw::FLASHCALW>,
+//!     capsules::crash_log::CrashLog::new(
+//!         &sam4l::flashcalw::FLASH_CONTROLLER,
+//!         CRASH_LOG_PAGE_NUMBER,
+//!         &mut CRASH_LOG_PAGE,
+//!         &poll_flash,
+//!         board_kernel.create_grant(&memory_allocation_capability)
+//!     )
+//! );
+//! hil::flash::HasClient::set_client(&sam4l::flashcalw::FLASH_CONTROLLER, crash_log);
+//! kernel::debug::add_panic_sink(crash_log);
+//! crash_log.initialize();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use core::panic::PanicInfo;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::debug::PanicSink;
+use kernel::hil;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = 0x50003;
+
+/// Marks a page that holds a crash log.
+const MAGIC: [u8; 4] = *b"CRSH";
+
+/// The magic number followed by the length of the log as a little-endian
+/// `u32`.
+const HEADER_LEN: usize = 8;
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    buffer: Option<AppSlice<Shared, u8>>,
+}
+
+pub struct CrashLog<'a, F: hil::flash::Flash + 'static> {
+    flash: &'a F,
+    page_number: usize,
+    buffer: TakeCell<'static, F::Page>,
+    /// Length of the log in `buffer`, without the header.
+    len: Cell<usize>,
+    /// Whether the write of the log has completed, while panicking.
+    panic_write_done: Cell<bool>,
+    poll: &'a Fn(),
+    apps: Grant<App>,
+    /// The app that is waiting for the log to be cleared.
+    clearing_app: OptionalCell<AppId>,
+}
+
+impl<F: hil::flash::Flash> CrashLog<'a, F> {
+    pub fn new(
+        flash: &'a F,
+        page_number: usize,
+        buffer: &'static mut F::Page,
+        poll: &'a Fn(),
+        grant: Grant<App>,
+    ) -> CrashLog<'a, F> {
+        CrashLog {
+            flash: flash,
+            page_number: page_number,
+            buffer: TakeCell::new(buffer),
+            len: Cell::new(0),
+            panic_write_done: Cell::new(false),
+            poll: poll,
+            apps: grant,
+            clearing_app: OptionalCell::empty(),
+        }
+    }
+
+    /// Read the log of the last panic from flash.
+    pub fn initialize(&self) -> ReturnCode {
+        self.buffer
+            .take()
+            .map_or(ReturnCode::EBUSY, |buffer| {
+                self.flash.read_page(self.page_number, buffer)
+            })
+    }
+
+    /// Copy the log into the buffer the app shared with `allow`, returning
+    /// the number of bytes copied.
+    fn read_log(&self, appid: AppId) -> ReturnCode {
+        self.apps
+            .enter(appid, |app, _| {
+                app.buffer.as_mut().map_or(ReturnCode::ERESERVE, |app_buffer| {
+                    self.buffer.map_or(ReturnCode::EBUSY, |buffer| {
+                        let page = buffer.as_mut();
+                        let length = cmp::min(app_buffer.len(), self.len.get());
+                        app_buffer.as_mut()[..length]
+                            .copy_from_slice(&page[HEADER_LEN..HEADER_LEN + length]);
+                        ReturnCode::SuccessWithValue { value: length }
+                    })
+                })
+            }).unwrap_or_else(|err| err.into())
+    }
+
+    fn clear_log(&self, appid: AppId) -> ReturnCode {
+        if self.clearing_app.is_some() {
+            return ReturnCode::EBUSY;
+        }
+        let ret = self.flash.erase_page(self.page_number);
+        if ret == ReturnCode::SUCCESS {
+            self.len.set(0);
+            self.clearing_app.set(appid);
+        }
+        ret
+    }
+}
+
+impl<F: hil::flash::Flash> PanicSink for CrashLog<'a, F> {
+    fn start(&self, _panic_info: &PanicInfo) {
+        self.len.set(0);
+    }
+
+    fn write(&self, s: &str) {
+        self.buffer.map(|buffer| {
+            let page = buffer.as_mut();
+            let start = HEADER_LEN + self.len.get();
+            let length = cmp::min(s.len(), page.len() - start);
+            page[start..start + length].copy_from_slice(&s.as_bytes()[..length]);
+            self.len.set(self.len.get() + length);
+        });
+    }
+
+    fn finish(&self) {
+        // If a flash operation was in progress when the kernel panicked, the
+        // buffer is not available and there is no log to write.
+        self.buffer.take().map(|buffer| {
+            {
+                let page = buffer.as_mut();
+                let len = self.len.get() as u32;
+                page[..4].copy_from_slice(&MAGIC);
+                for i in 0..4 {
+                    page[4 + i] = (len >> (8 * i)) as u8;
+                }
+            }
+            self.panic_write_done.set(false);
+            if self.flash.write_page(self.page_number, buffer) == ReturnCode::SUCCESS {
+                while !self.panic_write_done.get() {
+                    (self.poll)();
+                }
+            }
+        });
+    }
+}
+
+impl<F: hil::flash::Flash> hil::flash::Client<F> for CrashLog<'a, F> {
+    fn read_complete(&self, buffer: &'static mut F::Page, error: hil::flash::Error) {
+        let len = {
+            let page = buffer.as_mut();
+            if error == hil::flash::Error::CommandComplete && page[..4] == MAGIC {
+                let len = (0..4).fold(0, |len, i| len | (page[4 + i] as usize) << (8 * i));
+                cmp::min(len, page.len() - HEADER_LEN)
+            } else {
+                0
+            }
+        };
+        self.len.set(len);
+        self.buffer.replace(buffer);
+    }
+
+    fn write_complete(&self, buffer: &'static mut F::Page, _error: hil::flash::Error) {
+        // The log is only written while panicking.
+        self.buffer.replace(buffer);
+        self.panic_write_done.set(true);
+    }
+
+    fn erase_complete(&self, error: hil::flash::Error) {
+        self.clearing_app.take().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                app.callback.map(|mut cb| {
+                    let result = match error {
+                        hil::flash::Error::CommandComplete => ReturnCode::SUCCESS,
+                        hil::flash::Error::FlashError => ReturnCode::FAIL,
+                    };
+                    cb.schedule(usize::from(result), 0, 0);
+                });
+            });
+        });
+    }
+}
+
+impl<F: hil::flash::Flash> Driver for CrashLog<'a, F> {
+    /// Setup the buffer to read the log into.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Buffer to copy the log into.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.buffer = slice;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Set a callback for when the log has been cleared.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Read and clear the crash log.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Get the length of the log in bytes, 0 if there is none.
+    /// - `2`: Copy the log into the `allow` buffer. Returns the number of bytes
+    ///   copied.
+    /// - `3`: Clear the log. The callback is called once it is cleared.
+    fn command(&self, command_num: usize, _: usize, _: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            1 => ReturnCode::SuccessWithValue {
+                value: self.len.get(),
+            },
+
+            2 => self.read_log(appid),
+
+            3 => self.clear_log(appid),
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
@@ -0,0 +1,67 @@
+//! The ChaCha20 block function, as described in RFC 8439.
+//!
+//! Each block of the keystream is computed from the key, the nonce and the
+//! number of the block, so blocks can be computed in any order.
+
+/// The length of a key, in bytes
+pub const KEY_LEN: usize = 32;
+
+/// The length of a nonce, in bytes
+pub const NONCE_LEN: usize = 12;
+
+/// The length of a block, in bytes
+pub const BLOCK_LEN: usize = 64;
+
+/// "expand 32-byte k"
+const CONSTANTS: [u32; 4] = [0x61707865, 0x3320646e, 0x79622d32, 0x6b206574];
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    bytes[0] as u32 | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16 | (bytes[3] as u32) << 24
+}
+
+fn quarter_round(x: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    x[a] = x[a].wrapping_add(x[b]);
+    x[d] = (x[d] ^ x[a]).rotate_left(16);
+    x[c] = x[c].wrapping_add(x[d]);
+    x[b] = (x[b] ^ x[c]).rotate_left(12);
+    x[a] = x[a].wrapping_add(x[b]);
+    x[d] = (x[d] ^ x[a]).rotate_left(8);
+    x[c] = x[c].wrapping_add(x[d]);
+    x[b] = (x[b] ^ x[c]).rotate_left(7);
+}
+
+/// Write block `counter` of the keystream of `key` and `nonce` to `block`.
+pub fn block(
+    key: &[u8; KEY_LEN],
+    counter: u32,
+    nonce: &[u8; NONCE_LEN],
+    block: &mut [u8; BLOCK_LEN],
+) {
+    let mut state = [0u32; 16];
+    state[0..4].copy_from_slice(&CONSTANTS);
+    for i in 0..8 {
+        state[4 + i] = read_u32(&key[4 * i..]);
+    }
+    state[12] = counter;
+    for i in 0..3 {
+        state[13 + i] = read_u32(&nonce[4 * i..]);
+    }
+
+    let mut x = state;
+    for _ in 0..10 {
+        quarter_round(&mut x, 0, 4, 8, 12);
+        quarter_round(&mut x, 1, 5, 9, 13);
+        quarter_round(&mut x, 2, 6, 10, 14);
+        quarter_round(&mut x, 3, 7, 11, 15);
+        quarter_round(&mut x, 0, 5, 10, 15);
+        quarter_round(&mut x, 1, 6, 11, 12);
+        quarter_round(&mut x, 2, 7, 8, 13);
+        quarter_round(&mut x, 3, 4, 9, 14);
+    }
+    for i in 0..16 {
+        let word = x[i].wrapping_add(state[i]);
+        for j in 0..4 {
+            block[4 * i + j] = (word >> (8 * j)) as u8;
+        }
+    }
+}
@@ -0,0 +1,221 @@
+//! Ed25519 signature verification, as described in RFC 8032.
+//!
+//! Points of the curve are kept in extended coordinates `(X:Y:Z:T)`, where
+//! `x = X/Z`, `y = Y/Z` and `x * y = T/Z`, which allows adding points without
+//! inversions.
+
+use core::cmp::Ordering;
+use crypto::field::{self, Modulus, ONE, U256, ZERO};
+use crypto::sha512::{self, Sha512};
+
+/// The length of a public key, in bytes
+pub const PUBLIC_KEY_LEN: usize = 32;
+
+/// The length of a signature, in bytes
+pub const SIGNATURE_LEN: usize = 64;
+
+/// 2^255 - 19
+const P: Modulus = Modulus {
+    m: [
+        0xffffffed, 0xffffffff, 0xffffffff, 0xffffffff, 0xffffffff, 0xffffffff, 0xffffffff,
+        0x7fffffff,
+    ],
+    m_inv: 0x286bca1b,
+    r2: [0x000005a4, 0, 0, 0, 0, 0, 0, 0],
+};
+
+/// The order of the base point, 2^252 + 27742317777372353535851937790883648493
+const L: Modulus = Modulus {
+    m: [
+        0x5cf5d3ed, 0x5812631a, 0xa2f79cd6, 0x14def9de, 0x00000000, 0x00000000, 0x00000000,
+        0x10000000,
+    ],
+    m_inv: 0x12547e1b,
+    r2: [
+        0x449c0f01, 0xa40611e3, 0x68859347, 0xd00e1ba7, 0x17f5be65, 0xceec73d2, 0x7c309a3d,
+        0x0399411b,
+    ],
+};
+
+/// The curve constant -121665/121666
+const D: U256 = [
+    0x135978a3, 0x75eb4dca, 0x4141d8ab, 0x00700a4d, 0x7779e898, 0x8cc74079, 0x2b6ffe73, 0x52036cee,
+];
+
+/// 2d, in Montgomery form
+const D2_MONT: U256 = [
+    0xbe8fd3f4, 0x01db17fd, 0x5f8c52e7, 0x21430eef, 0x78310d20, 0xcb27240f, 0xe53f8a4d, 0x590456b4,
+];
+
+/// A square root of -1
+const SQRT_M1: U256 = [
+    0x4a0ea0b0, 0xc4ee1b27, 0xad2fe478, 0x2f431806, 0x3dfbd7a7, 0x2b4d0099, 0x4fc1df0b, 0x2b832480,
+];
+
+/// (p - 5) / 8, the exponent for square roots
+const SQRT_EXPONENT: U256 = [
+    0xfffffffd, 0xffffffff, 0xffffffff, 0xffffffff, 0xffffffff, 0xffffffff, 0xffffffff, 0x0fffffff,
+];
+
+/// The encoding of the base point
+const BASE_POINT: [u8; 32] = [
+    0x58, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66,
+    0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66,
+];
+
+/// A point of the curve, with coordinates in Montgomery form.
+#[derive(Clone, Copy)]
+struct Point {
+    x: U256,
+    y: U256,
+    z: U256,
+    t: U256,
+}
+
+impl Point {
+    fn identity() -> Point {
+        let one = P.to_mont(&ONE);
+        Point {
+            x: ZERO,
+            y: one,
+            z: one,
+            t: ZERO,
+        }
+    }
+
+    /// Decode a point, if `bytes` encodes one.
+    fn decode(bytes: &[u8]) -> Option<Point> {
+        let x_odd = bytes[31] >> 7 == 1;
+        let mut y = field::from_le_bytes(bytes);
+        y[7] &= 0x7fffffff;
+        if field::compare(&y, &P.m) != Ordering::Less {
+            return None;
+        }
+
+        // x^2 = (y^2 - 1) / (d y^2 + 1), so x = u / v with u = y^2 - 1 and
+        // v = d y^2 + 1. Its square root is u v^3 (u v^7)^((p - 5) / 8),
+        // possibly times the square root of -1.
+        let one = P.to_mont(&ONE);
+        let y = P.to_mont(&y);
+        let y2 = P.square(&y);
+        let u = P.sub(&y2, &one);
+        let v = P.add(&P.mul(&P.to_mont(&D), &y2), &one);
+        let v3 = P.mul(&P.square(&v), &v);
+        let v7 = P.mul(&P.square(&v3), &v);
+        let mut x = P.mul(
+            &P.mul(&u, &v3),
+            &P.pow(&P.mul(&u, &v7), &SQRT_EXPONENT),
+        );
+        let vx2 = P.mul(&v, &P.square(&x));
+        if vx2 != u {
+            if vx2 != P.neg(&u) {
+                return None;
+            }
+            x = P.mul(&x, &P.to_mont(&SQRT_M1));
+        }
+
+        let x_plain = P.from_mont(&x);
+        if field::is_zero(&x_plain) && x_odd {
+            return None;
+        }
+        if field::bit(&x_plain, 0) != x_odd {
+            x = P.neg(&x);
+        }
+        Some(Point {
+            x: x,
+            y: y,
+            z: one,
+            t: P.mul(&x, &y),
+        })
+    }
+
+    fn encode(&self, bytes: &mut [u8]) {
+        let z_inv = P.inv(&self.z);
+        let x = P.from_mont(&P.mul(&self.x, &z_inv));
+        let y = P.from_mont(&P.mul(&self.y, &z_inv));
+        field::to_le_bytes(&y, bytes);
+        if field::bit(&x, 0) {
+            bytes[31] |= 0x80;
+        }
+    }
+
+    fn neg(&self) -> Point {
+        Point {
+            x: P.neg(&self.x),
+            y: self.y,
+            z: self.z,
+            t: P.neg(&self.t),
+        }
+    }
+
+    /// The sum of two points. This also works for doubling a point.
+    fn add(&self, other: &Point) -> Point {
+        let a = P.mul(&P.sub(&self.y, &self.x), &P.sub(&other.y, &other.x));
+        let b = P.mul(&P.add(&self.y, &self.x), &P.add(&other.y, &other.x));
+        let c = P.mul(&P.mul(&self.t, &D2_MONT), &other.t);
+        let d = P.mul(&P.add(&self.z, &self.z), &other.z);
+        let e = P.sub(&b, &a);
+        let f = P.sub(&d, &c);
+        let g = P.add(&d, &c);
+        let h = P.add(&b, &a);
+        Point {
+            x: P.mul(&e, &f),
+            y: P.mul(&g, &h),
+            z: P.mul(&f, &g),
+            t: P.mul(&e, &h),
+        }
+    }
+}
+
+/// `a * p + b * q`.
+fn double_scalar_mul(a: &U256, p: &Point, b: &U256, q: &Point) -> Point {
+    let pq = p.add(q);
+    let mut result = Point::identity();
+    for i in (0..256).rev() {
+        result = result.add(&result);
+        match (field::bit(a, i), field::bit(b, i)) {
+            (true, true) => result = result.add(&pq),
+            (true, false) => result = result.add(p),
+            (false, true) => result = result.add(q),
+            (false, false) => {}
+        }
+    }
+    result
+}
+
+/// Return whether `signature` is a valid signature of `message` by the key
+/// `public_key`.
+pub fn verify(public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    if public_key.len() != PUBLIC_KEY_LEN || signature.len() != SIGNATURE_LEN {
+        return false;
+    }
+    let a = match Point::decode(public_key) {
+        Some(a) => a,
+        None => return false,
+    };
+    let s = field::from_le_bytes(&signature[32..64]);
+    if field::compare(&s, &L.m) != Ordering::Less {
+        return false;
+    }
+
+    // h = SHA-512(R || A || message) mod L
+    let mut sha = Sha512::new();
+    sha.update(&signature[0..32]);
+    sha.update(public_key);
+    sha.update(message);
+    let mut digest = [0; sha512::DIGEST_LEN];
+    sha.finish(&mut digest);
+    // Converting into the Montgomery form multiplies the high half by 2^256.
+    let low = L.from_mont(&L.to_mont(&field::from_le_bytes(&digest[0..32])));
+    let high = L.to_mont(&field::from_le_bytes(&digest[32..64]));
+    let h = L.add(&low, &high);
+
+    // The signature is valid if s B - h A = R.
+    let base = match Point::decode(&BASE_POINT) {
+        Some(base) => base,
+        None => return false,
+    };
+    let mut r = [0; 32];
+    double_scalar_mul(&s, &base, &h, &a.neg()).encode(&mut r);
+    r[..] == signature[0..32]
+}
@@ -0,0 +1,191 @@
+//! Arithmetic modulo odd 256-bit numbers, for elliptic curve cryptography.
+//!
+//! Numbers are arrays of eight 32-bit limbs, least significant limb first.
+//! Multiplication uses the Montgomery form: a number `a` is kept as
+//! `a * 2^256 mod m`, and `Modulus::mul()` of two numbers in this form gives
+//! their product in this form. Addition and subtraction work the same in both
+//! forms. Numbers enter the form with `Modulus::to_mont()` and leave it with
+//! `Modulus::from_mont()`.
+
+use core::cmp::Ordering;
+
+pub type U256 = [u32; 8];
+
+pub const ZERO: U256 = [0; 8];
+pub const ONE: U256 = [1, 0, 0, 0, 0, 0, 0, 0];
+
+/// Read a number from 32 big-endian bytes.
+pub fn from_be_bytes(bytes: &[u8]) -> U256 {
+    let mut a = ZERO;
+    for i in 0..32 {
+        a[7 - i / 4] |= (bytes[i] as u32) << (24 - 8 * (i % 4));
+    }
+    a
+}
+
+/// Read a number from 32 little-endian bytes.
+pub fn from_le_bytes(bytes: &[u8]) -> U256 {
+    let mut a = ZERO;
+    for i in 0..32 {
+        a[i / 4] |= (bytes[i] as u32) << (8 * (i % 4));
+    }
+    a
+}
+
+/// Write `a` to 32 little-endian bytes.
+pub fn to_le_bytes(a: &U256, bytes: &mut [u8]) {
+    for i in 0..32 {
+        bytes[i] = (a[i / 4] >> (8 * (i % 4))) as u8;
+    }
+}
+
+pub fn compare(a: &U256, b: &U256) -> Ordering {
+    for i in (0..8).rev() {
+        if a[i] != b[i] {
+            return a[i].cmp(&b[i]);
+        }
+    }
+    Ordering::Equal
+}
+
+pub fn is_zero(a: &U256) -> bool {
+    a.iter().all(|&limb| limb == 0)
+}
+
+/// Bit `n` of `a`.
+pub fn bit(a: &U256, n: usize) -> bool {
+    a[n / 32] >> (n % 32) & 1 == 1
+}
+
+/// `a + b`, and the carry out of the top limb.
+fn add_carry(a: &U256, b: &U256) -> (U256, bool) {
+    let mut sum = ZERO;
+    let mut carry = 0u64;
+    for i in 0..8 {
+        let s = a[i] as u64 + b[i] as u64 + carry;
+        sum[i] = s as u32;
+        carry = s >> 32;
+    }
+    (sum, carry != 0)
+}
+
+/// `a - b`, and whether it borrowed out of the top limb.
+fn sub_borrow(a: &U256, b: &U256) -> (U256, bool) {
+    let mut difference = ZERO;
+    let mut borrow = 0i64;
+    for i in 0..8 {
+        let d = a[i] as i64 - b[i] as i64 + borrow;
+        difference[i] = d as u32;
+        borrow = d >> 32;
+    }
+    (difference, borrow != 0)
+}
+
+/// An odd modulus, with the constants for its Montgomery form.
+pub struct Modulus {
+    pub m: U256,
+    /// `-m^-1 mod 2^32`
+    pub m_inv: u32,
+    /// `2^512 mod m`
+    pub r2: U256,
+}
+
+impl Modulus {
+    /// `a + b mod m`, for `a` and `b` less than `m`.
+    pub fn add(&self, a: &U256, b: &U256) -> U256 {
+        let (sum, carry) = add_carry(a, b);
+        if carry || compare(&sum, &self.m) != Ordering::Less {
+            sub_borrow(&sum, &self.m).0
+        } else {
+            sum
+        }
+    }
+
+    /// `a - b mod m`, for `a` and `b` less than `m`.
+    pub fn sub(&self, a: &U256, b: &U256) -> U256 {
+        let (difference, borrow) = sub_borrow(a, b);
+        if borrow {
+            add_carry(&difference, &self.m).0
+        } else {
+            difference
+        }
+    }
+
+    /// `-a mod m`, for `a` less than `m`.
+    pub fn neg(&self, a: &U256) -> U256 {
+        self.sub(&ZERO, a)
+    }
+
+    /// `a * b * 2^-256 mod m`, the Montgomery product. `b` must be less than
+    /// `m`, `a` can be any number.
+    pub fn mul(&self, a: &U256, b: &U256) -> U256 {
+        let m = &self.m;
+        let mut t = [0u32; 10];
+        for i in 0..8 {
+            let mut carry = 0u64;
+            for j in 0..8 {
+                let s = t[j] as u64 + a[j] as u64 * b[i] as u64 + carry;
+                t[j] = s as u32;
+                carry = s >> 32;
+            }
+            let s = t[8] as u64 + carry;
+            t[8] = s as u32;
+            t[9] = (s >> 32) as u32;
+
+            // Add a multiple of m that makes the lowest limb zero, and shift
+            // it out.
+            let q = t[0].wrapping_mul(self.m_inv);
+            let mut carry = (t[0] as u64 + q as u64 * m[0] as u64) >> 32;
+            for j in 1..8 {
+                let s = t[j] as u64 + q as u64 * m[j] as u64 + carry;
+                t[j - 1] = s as u32;
+                carry = s >> 32;
+            }
+            let s = t[8] as u64 + carry;
+            t[7] = s as u32;
+            t[8] = t[9] + (s >> 32) as u32;
+        }
+
+        let mut product = ZERO;
+        product.copy_from_slice(&t[0..8]);
+        if t[8] != 0 || compare(&product, m) != Ordering::Less {
+            sub_borrow(&product, m).0
+        } else {
+            product
+        }
+    }
+
+    pub fn square(&self, a: &U256) -> U256 {
+        self.mul(a, a)
+    }
+
+    /// Convert `a` into the Montgomery form. This also reduces any `a` modulo
+    /// `m`.
+    pub fn to_mont(&self, a: &U256) -> U256 {
+        self.mul(a, &self.r2)
+    }
+
+    /// Convert `a` out of the Montgomery form.
+    pub fn from_mont(&self, a: &U256) -> U256 {
+        self.mul(a, &ONE)
+    }
+
+    /// `a^e mod m`, with `a` and the result in Montgomery form.
+    pub fn pow(&self, a: &U256, e: &U256) -> U256 {
+        let mut result = self.to_mont(&ONE);
+        for i in (0..256).rev() {
+            result = self.square(&result);
+            if bit(e, i) {
+                result = self.mul(&result, a);
+            }
+        }
+        result
+    }
+
+    /// `a^-1 mod m`, with `a` and the result in Montgomery form. The modulus
+    /// must be prime, and `a` must not be zero.
+    pub fn inv(&self, a: &U256) -> U256 {
+        let e = sub_borrow(&self.m, &[2, 0, 0, 0, 0, 0, 0, 0]).0;
+        self.pow(a, &e)
+    }
+}
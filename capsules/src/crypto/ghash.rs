@@ -0,0 +1,86 @@
+//! GHASH, the authentication function of AES-GCM, as described in NIST SP
+//! 800-38D.
+//!
+//! GHASH multiplies blocks in GF(2^128) by the hash key H, the encryption of
+//! the zero block. Blocks are big-endian 128-bit numbers whose first bit is
+//! the coefficient of x^0. The multiplication masks instead of branching on
+//! the bits of the data and the key.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let mut ghash = Ghash::new(&h);
+//! ghash.update_padded(aad);
+//! ghash.update_padded(ciphertext);
+//! let mut s = [0; BLOCK_LEN];
+//! ghash.finish(aad.len(), ciphertext.len(), &mut s);
+//! ```
+
+/// The length of a block, in bytes
+pub const BLOCK_LEN: usize = 16;
+
+/// The reduction polynomial x^128 + x^7 + x^2 + x + 1, in the bit order of
+/// GHASH
+const R: u64 = 0xe100000000000000;
+
+type Block = [u64; 2];
+
+fn read_block(bytes: &[u8]) -> Block {
+    let mut block = [0; 2];
+    for i in 0..BLOCK_LEN {
+        block[i / 8] |= (bytes[i] as u64) << (56 - 8 * (i % 8));
+    }
+    block
+}
+
+/// `x * y` in GF(2^128).
+fn mul(x: &Block, y: &Block) -> Block {
+    let mut z = [0; 2];
+    let mut v = *y;
+    for i in 0..128 {
+        let mask = 0u64.wrapping_sub(x[i / 64] >> (63 - i % 64) & 1);
+        z[0] ^= v[0] & mask;
+        z[1] ^= v[1] & mask;
+        let carry = 0u64.wrapping_sub(v[1] & 1);
+        v[1] = v[1] >> 1 | v[0] << 63;
+        v[0] = v[0] >> 1 ^ R & carry;
+    }
+    z
+}
+
+pub struct Ghash {
+    h: Block,
+    y: Block,
+}
+
+impl Ghash {
+    /// `h` is the hash key, 16 bytes.
+    pub fn new(h: &[u8]) -> Ghash {
+        Ghash {
+            h: read_block(h),
+            y: [0; 2],
+        }
+    }
+
+    /// Add `data` in blocks, padding the last block with zeros.
+    pub fn update_padded(&mut self, data: &[u8]) {
+        for chunk in data.chunks(BLOCK_LEN) {
+            let mut bytes = [0; BLOCK_LEN];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            let block = read_block(&bytes);
+            self.y = mul(&[self.y[0] ^ block[0], self.y[1] ^ block[1]], &self.h);
+        }
+    }
+
+    /// Add the block of the lengths of the additional data and of the
+    /// ciphertext, in bytes, and write the result to the first `BLOCK_LEN`
+    /// bytes of `s`.
+    pub fn finish(mut self, aad_len: usize, ciphertext_len: usize, s: &mut [u8]) {
+        let lengths = [(aad_len as u64) * 8, (ciphertext_len as u64) * 8];
+        self.y = mul(&[self.y[0] ^ lengths[0], self.y[1] ^ lengths[1]], &self.h);
+        for i in 0..BLOCK_LEN {
+            s[i] = (self.y[i / 8] >> (56 - 8 * (i % 8))) as u8;
+        }
+    }
+}
@@ -0,0 +1,66 @@
+//! HMAC-SHA256, as described in RFC 2104.
+//!
+//! It is used the same way as `sha256::Sha256`, with the key given to
+//! `HmacSha256::new()`.
+
+use crypto::sha256::{self, Sha256};
+
+/// The length of a code, in bytes
+pub const DIGEST_LEN: usize = sha256::DIGEST_LEN;
+
+/// The length of a SHA-256 block, which keys are padded or hashed to
+const BLOCK_LEN: usize = 64;
+
+const IPAD: u8 = 0x36;
+const OPAD: u8 = 0x5c;
+
+#[derive(Clone)]
+pub struct HmacSha256 {
+    /// The hash of the inner key and the data added so far
+    inner: Sha256,
+    /// The key, XORed with the outer padding
+    outer_key: [u8; BLOCK_LEN],
+}
+
+impl HmacSha256 {
+    /// Keys longer than a SHA-256 block are replaced by their digest.
+    pub fn new(key: &[u8]) -> HmacSha256 {
+        let mut block = [0; BLOCK_LEN];
+        if key.len() > BLOCK_LEN {
+            let mut sha = Sha256::new();
+            sha.update(key);
+            sha.finish(&mut block);
+        } else {
+            block[..key.len()].copy_from_slice(key);
+        }
+
+        let mut inner_key = [0; BLOCK_LEN];
+        let mut outer_key = [0; BLOCK_LEN];
+        for i in 0..BLOCK_LEN {
+            inner_key[i] = block[i] ^ IPAD;
+            outer_key[i] = block[i] ^ OPAD;
+        }
+        let mut inner = Sha256::new();
+        inner.update(&inner_key);
+        HmacSha256 {
+            inner: inner,
+            outer_key: outer_key,
+        }
+    }
+
+    /// Add `data` to the data to authenticate.
+    pub fn update(&mut self, data: &[u8]) {
+        self.inner.update(data);
+    }
+
+    /// Write the code of the data to the first `DIGEST_LEN` bytes of
+    /// `digest`.
+    pub fn finish(self, digest: &mut [u8]) {
+        let mut inner_digest = [0; sha256::DIGEST_LEN];
+        self.inner.finish(&mut inner_digest);
+        let mut outer = Sha256::new();
+        outer.update(&self.outer_key);
+        outer.update(&inner_digest);
+        outer.finish(digest);
+    }
+}
@@ -0,0 +1,16 @@
+//! Software implementations of cryptographic algorithms.
+//!
+//! These are for boards without hardware that accelerates the algorithms.
+//! The elliptic curve algorithms do not run in constant time, so they must
+//! only be used on public data, like when verifying signatures. SHA-256,
+//! HMAC-SHA256, ChaCha20 and GHASH do not branch on or index with the data,
+//! so they can be used with secret keys.
+
+pub mod chacha20;
+pub mod ed25519;
+pub mod field;
+pub mod ghash;
+pub mod hmac_sha256;
+pub mod p256;
+pub mod sha256;
+pub mod sha512;
@@ -0,0 +1,228 @@
+//! ECDSA signature verification on the NIST P-256 curve, as described in
+//! FIPS 186-4.
+//!
+//! Public keys are the big-endian x and y coordinates of the point, without
+//! the `0x04` prefix of the uncompressed SEC 1 encoding. Signatures are the
+//! big-endian r and s values. Points of the curve are kept in Jacobian
+//! coordinates `(X:Y:Z)`, where `x = X/Z^2` and `y = Y/Z^3`, which allows
+//! adding points without inversions. `Z = 0` is the point at infinity.
+
+use core::cmp::Ordering;
+use crypto::field::{self, Modulus, ONE, U256, ZERO};
+
+/// The length of a public key, in bytes
+pub const PUBLIC_KEY_LEN: usize = 64;
+
+/// The length of a signature, in bytes
+pub const SIGNATURE_LEN: usize = 64;
+
+/// The length of the digest of the message, in bytes
+pub const DIGEST_LEN: usize = 32;
+
+/// 2^256 - 2^224 + 2^192 + 2^96 - 1
+const P: Modulus = Modulus {
+    m: [
+        0xffffffff, 0xffffffff, 0xffffffff, 0x00000000, 0x00000000, 0x00000000, 0x00000001,
+        0xffffffff,
+    ],
+    m_inv: 0x00000001,
+    r2: [
+        0x00000003, 0x00000000, 0xffffffff, 0xfffffffb, 0xfffffffe, 0xffffffff, 0xfffffffd,
+        0x00000004,
+    ],
+};
+
+/// The order of the base point
+const N: Modulus = Modulus {
+    m: [
+        0xfc632551, 0xf3b9cac2, 0xa7179e84, 0xbce6faad, 0xffffffff, 0xffffffff, 0x00000000,
+        0xffffffff,
+    ],
+    m_inv: 0xee00bc4f,
+    r2: [
+        0xbe79eea2, 0x83244c95, 0x49bd6fa6, 0x4699799c, 0x2b6bec59, 0x2845b239, 0xf3d95620,
+        0x66e12d94,
+    ],
+};
+
+/// The curve constant b of y^2 = x^3 - 3x + b
+const B: U256 = [
+    0x27d2604b, 0x3bce3c3e, 0xcc53b0f6, 0x651d06b0, 0x769886bc, 0xb3ebbd55, 0xaa3a93e7, 0x5ac635d8,
+];
+
+/// The coordinates of the base point
+const GX: U256 = [
+    0xd898c296, 0xf4a13945, 0x2deb33a0, 0x77037d81, 0x63a440f2, 0xf8bce6e5, 0xe12c4247, 0x6b17d1f2,
+];
+const GY: U256 = [
+    0x37bf51f5, 0xcbb64068, 0x6b315ece, 0x2bce3357, 0x7c0f9e16, 0x8ee7eb4a, 0xfe1a7f9b, 0x4fe342e2,
+];
+
+/// A point of the curve, with coordinates in Montgomery form.
+#[derive(Clone, Copy)]
+struct Point {
+    x: U256,
+    y: U256,
+    z: U256,
+}
+
+impl Point {
+    fn infinity() -> Point {
+        Point {
+            x: ZERO,
+            y: ZERO,
+            z: ZERO,
+        }
+    }
+
+    /// The point with affine coordinates `x` and `y`, if it is on the curve.
+    fn from_affine(x: &U256, y: &U256) -> Option<Point> {
+        if field::compare(x, &P.m) != Ordering::Less || field::compare(y, &P.m) != Ordering::Less
+        {
+            return None;
+        }
+        let x = P.to_mont(x);
+        let y = P.to_mont(y);
+        let x3 = P.mul(&P.square(&x), &x);
+        let three_x = P.add(&P.add(&x, &x), &x);
+        let rhs = P.add(&P.sub(&x3, &three_x), &P.to_mont(&B));
+        if P.square(&y) != rhs {
+            return None;
+        }
+        Some(Point {
+            x: x,
+            y: y,
+            z: P.to_mont(&ONE),
+        })
+    }
+
+    fn is_infinity(&self) -> bool {
+        field::is_zero(&self.z)
+    }
+
+    fn double(&self) -> Point {
+        if self.is_infinity() || field::is_zero(&self.y) {
+            return Point::infinity();
+        }
+        let delta = P.square(&self.z);
+        let gamma = P.square(&self.y);
+        let beta = P.mul(&self.x, &gamma);
+        let alpha = P.mul(&P.sub(&self.x, &delta), &P.add(&self.x, &delta));
+        let alpha = P.add(&P.add(&alpha, &alpha), &alpha);
+        let beta4 = P.add(&P.add(&beta, &beta), &P.add(&beta, &beta));
+        let x = P.sub(&P.square(&alpha), &P.add(&beta4, &beta4));
+        let z = P.sub(
+            &P.sub(&P.square(&P.add(&self.y, &self.z)), &gamma),
+            &delta,
+        );
+        let gamma2 = P.square(&gamma);
+        let gamma2_8 = P.add(&gamma2, &gamma2);
+        let gamma2_8 = P.add(&gamma2_8, &gamma2_8);
+        let gamma2_8 = P.add(&gamma2_8, &gamma2_8);
+        let y = P.sub(&P.mul(&alpha, &P.sub(&beta4, &x)), &gamma2_8);
+        Point { x: x, y: y, z: z }
+    }
+
+    fn add(&self, other: &Point) -> Point {
+        if self.is_infinity() {
+            return *other;
+        }
+        if other.is_infinity() {
+            return *self;
+        }
+        let z1z1 = P.square(&self.z);
+        let z2z2 = P.square(&other.z);
+        let u1 = P.mul(&self.x, &z2z2);
+        let u2 = P.mul(&other.x, &z1z1);
+        let s1 = P.mul(&P.mul(&self.y, &other.z), &z2z2);
+        let s2 = P.mul(&P.mul(&other.y, &self.z), &z1z1);
+        let h = P.sub(&u2, &u1);
+        let r = P.sub(&s2, &s1);
+        if field::is_zero(&h) {
+            // The points have the same x coordinate, so they are either the
+            // same point or each other's negation.
+            return if field::is_zero(&r) {
+                self.double()
+            } else {
+                Point::infinity()
+            };
+        }
+        let r = P.add(&r, &r);
+        let i = P.square(&P.add(&h, &h));
+        let j = P.mul(&h, &i);
+        let v = P.mul(&u1, &i);
+        let x = P.sub(&P.sub(&P.square(&r), &j), &P.add(&v, &v));
+        let s1j = P.mul(&s1, &j);
+        let y = P.sub(&P.mul(&r, &P.sub(&v, &x)), &P.add(&s1j, &s1j));
+        let z = P.mul(
+            &P.sub(
+                &P.sub(&P.square(&P.add(&self.z, &other.z)), &z1z1),
+                &z2z2,
+            ),
+            &h,
+        );
+        Point { x: x, y: y, z: z }
+    }
+}
+
+/// `a * p + b * q`.
+fn double_scalar_mul(a: &U256, p: &Point, b: &U256, q: &Point) -> Point {
+    let pq = p.add(q);
+    let mut result = Point::infinity();
+    for i in (0..256).rev() {
+        result = result.double();
+        match (field::bit(a, i), field::bit(b, i)) {
+            (true, true) => result = result.add(&pq),
+            (true, false) => result = result.add(p),
+            (false, true) => result = result.add(q),
+            (false, false) => {}
+        }
+    }
+    result
+}
+
+/// Return whether `signature` is a valid signature by the key `public_key`
+/// of a message with the SHA-256 digest `digest`.
+pub fn verify(public_key: &[u8], digest: &[u8], signature: &[u8]) -> bool {
+    if public_key.len() != PUBLIC_KEY_LEN
+        || digest.len() != DIGEST_LEN
+        || signature.len() != SIGNATURE_LEN
+    {
+        return false;
+    }
+    let q = match Point::from_affine(
+        &field::from_be_bytes(&public_key[0..32]),
+        &field::from_be_bytes(&public_key[32..64]),
+    ) {
+        Some(q) => q,
+        None => return false,
+    };
+    let r = field::from_be_bytes(&signature[0..32]);
+    let s = field::from_be_bytes(&signature[32..64]);
+    if field::is_zero(&r)
+        || field::is_zero(&s)
+        || field::compare(&r, &N.m) != Ordering::Less
+        || field::compare(&s, &N.m) != Ordering::Less
+    {
+        return false;
+    }
+
+    // u1 = e / s and u2 = r / s, where e is the digest reduced modulo n.
+    let w = N.inv(&N.to_mont(&s));
+    let u1 = N.from_mont(&N.mul(&N.to_mont(&field::from_be_bytes(digest)), &w));
+    let u2 = N.from_mont(&N.mul(&N.to_mont(&r), &w));
+
+    // The signature is valid if the x coordinate of u1 G + u2 Q is r modulo
+    // n.
+    let g = match Point::from_affine(&GX, &GY) {
+        Some(g) => g,
+        None => return false,
+    };
+    let point = double_scalar_mul(&u1, &g, &u2, &q);
+    if point.is_infinity() {
+        return false;
+    }
+    let z_inv = P.inv(&point.z);
+    let x = P.from_mont(&P.mul(&point.x, &P.square(&z_inv)));
+    N.from_mont(&N.to_mont(&x)) == r
+}
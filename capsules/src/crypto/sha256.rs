@@ -0,0 +1,131 @@
+//! SHA-256, as described in FIPS 180-4.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let mut sha = Sha256::new();
+//! sha.update(b"hello ");
+//! sha.update(b"world");
+//! let mut digest = [0; DIGEST_LEN];
+//! sha.finish(&mut digest);
+//! ```
+
+/// The length of a digest, in bytes
+pub const DIGEST_LEN: usize = 32;
+
+const BLOCK_LEN: usize = 64;
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+const INITIAL_STATE: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+#[derive(Clone)]
+pub struct Sha256 {
+    state: [u32; 8],
+    /// The data of the block that is not complete yet
+    block: [u8; BLOCK_LEN],
+    block_len: usize,
+    /// The number of bytes hashed
+    length: u64,
+}
+
+impl Sha256 {
+    pub fn new() -> Sha256 {
+        Sha256 {
+            state: INITIAL_STATE,
+            block: [0; BLOCK_LEN],
+            block_len: 0,
+            length: 0,
+        }
+    }
+
+    /// Add `data` to the data to hash.
+    pub fn update(&mut self, data: &[u8]) {
+        self.length += data.len() as u64;
+        for &byte in data.iter() {
+            self.block[self.block_len] = byte;
+            self.block_len += 1;
+            if self.block_len == BLOCK_LEN {
+                self.compress();
+            }
+        }
+    }
+
+    /// Write the digest of the data to the first `DIGEST_LEN` bytes of
+    /// `digest`.
+    pub fn finish(mut self, digest: &mut [u8]) {
+        let bits = self.length * 8;
+        self.update(&[0x80]);
+        while self.block_len != BLOCK_LEN - 8 {
+            self.update(&[0]);
+        }
+        for i in 0..8 {
+            self.block[BLOCK_LEN - 8 + i] = (bits >> (56 - 8 * i)) as u8;
+        }
+        self.compress();
+        for (i, word) in self.state.iter().enumerate() {
+            for j in 0..4 {
+                digest[4 * i + j] = (word >> (24 - 8 * j)) as u8;
+            }
+        }
+    }
+
+    /// Hashes the complete block.
+    fn compress(&mut self) {
+        let mut w = [0u32; 64];
+        for i in 0..16 {
+            w[i] = (self.block[4 * i] as u32) << 24
+                | (self.block[4 * i + 1] as u32) << 16
+                | (self.block[4 * i + 2] as u32) << 8
+                | self.block[4 * i + 3] as u32;
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let mut v = self.state;
+        for i in 0..64 {
+            let s1 = v[4].rotate_right(6) ^ v[4].rotate_right(11) ^ v[4].rotate_right(25);
+            let ch = (v[4] & v[5]) ^ (!v[4] & v[6]);
+            let t1 = v[7]
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = v[0].rotate_right(2) ^ v[0].rotate_right(13) ^ v[0].rotate_right(22);
+            let maj = (v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]);
+            let t2 = s0.wrapping_add(maj);
+            v = [
+                t1.wrapping_add(t2),
+                v[0],
+                v[1],
+                v[2],
+                v[3].wrapping_add(t1),
+                v[4],
+                v[5],
+                v[6],
+            ];
+        }
+        for i in 0..8 {
+            self.state[i] = self.state[i].wrapping_add(v[i]);
+        }
+        self.block_len = 0;
+    }
+}
@@ -0,0 +1,229 @@
+//! A cryptographically secure pseudorandom number generator, seeded from a
+//! hardware entropy source.
+//!
+//! Words of entropy are collected into a pool, hashed with SHA-256. Once the
+//! pool holds `POOL_WORDS` words, its digest is hashed together with the
+//! current key into a new ChaCha20 key. Random bytes are the keystream of
+//! the key, and each request replaces the key with the first block of its
+//! keystream, so that the bytes handed out before cannot be recovered from
+//! the state ("fast key erasure").
+//!
+//! The generator is seeded once the first pool is full. After that, each
+//! request through `hil::rng::Rng` mixes one more word of entropy into the
+//! pool, so the key keeps being reseeded as the generator is used. Requests
+//! through `hil::csprng::Csprng` are answered synchronously and do not wait
+//! for the entropy source.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let csprng = static_init!(
+//!     capsules::csprng::Csprng<'static>,
+//!     capsules::csprng::Csprng::new(&sam4l::trng::TRNG)
+//! );
+//! let rng = static_init!(
+//!     capsules::rng::RngDriver<'static>,
+//!     capsules::rng::RngDriver::new(
+//!         csprng,
+//!         board_kernel.create_grant(&memory_allocation_capability)
+//!     )
+//! );
+//! csprng.start();
+//! csprng.set_client(rng);
+//! ```
+
+use core::cell::Cell;
+use crypto::chacha20::{self, BLOCK_LEN, KEY_LEN, NONCE_LEN};
+use crypto::sha256::{self, Sha256};
+use kernel::common::cells::{MapCell, OptionalCell};
+use kernel::hil::csprng;
+use kernel::hil::entropy::{self, Entropy32};
+use kernel::hil::rng::{self, Rng};
+use kernel::ReturnCode;
+
+/// The number of words of entropy hashed into each new key, 256 bits
+pub const POOL_WORDS: usize = 8;
+
+/// Every key is used with a new counter from 0, so the nonce can be fixed.
+const NONCE: [u8; NONCE_LEN] = [0; NONCE_LEN];
+
+pub struct Csprng<'a> {
+    entropy: &'a Entropy32<'a>,
+    client: OptionalCell<&'a rng::Client>,
+    key: Cell<[u8; KEY_LEN]>,
+    /// The words of entropy collected for the next key
+    pool: MapCell<Sha256>,
+    pool_words: Cell<usize>,
+    seeded: Cell<bool>,
+    /// Whether the client waits for random numbers
+    requested: Cell<bool>,
+}
+
+impl Csprng<'a> {
+    pub fn new(entropy: &'a Entropy32<'a>) -> Csprng<'a> {
+        Csprng {
+            entropy: entropy,
+            client: OptionalCell::empty(),
+            key: Cell::new([0; KEY_LEN]),
+            pool: MapCell::new(Sha256::new()),
+            pool_words: Cell::new(0),
+            seeded: Cell::new(false),
+            requested: Cell::new(false),
+        }
+    }
+
+    /// Starts collecting the seed from the entropy source.
+    pub fn start(&'a self) -> ReturnCode {
+        self.entropy.set_client(self);
+        self.entropy.get()
+    }
+
+    /// Hashes the pool into a new key.
+    fn reseed(&self) {
+        self.pool.replace(Sha256::new()).map(|pool| {
+            let mut digest = [0; sha256::DIGEST_LEN];
+            pool.finish(&mut digest);
+            let mut sha = Sha256::new();
+            sha.update(&self.key.get());
+            sha.update(&digest);
+            let mut key = [0; KEY_LEN];
+            sha.finish(&mut key);
+            self.key.set(key);
+        });
+        self.pool_words.set(0);
+        self.seeded.set(true);
+    }
+
+    /// Fills `buf` from the keystream, and replaces the key.
+    fn generate(&self, buf: &mut [u8]) {
+        let key = self.key.get();
+        let mut block = [0; BLOCK_LEN];
+        chacha20::block(&key, 0, &NONCE, &mut block);
+        let mut next_key = [0; KEY_LEN];
+        next_key.copy_from_slice(&block[..KEY_LEN]);
+        for (counter, chunk) in buf.chunks_mut(BLOCK_LEN).enumerate() {
+            chacha20::block(&key, counter as u32 + 1, &NONCE, &mut block);
+            let len = chunk.len();
+            chunk.copy_from_slice(&block[..len]);
+        }
+        self.key.set(next_key);
+    }
+}
+
+impl csprng::Csprng for Csprng<'a> {
+    fn is_seeded(&self) -> bool {
+        self.seeded.get()
+    }
+
+    fn fill_bytes(&self, buf: &mut [u8]) -> ReturnCode {
+        if !self.seeded.get() {
+            return ReturnCode::EBUSY;
+        }
+        self.generate(buf);
+        ReturnCode::SUCCESS
+    }
+}
+
+impl Rng<'a> for Csprng<'a> {
+    fn get(&self) -> ReturnCode {
+        self.requested.set(true);
+        let result = self.entropy.get();
+        if result != ReturnCode::SUCCESS {
+            self.requested.set(false);
+        }
+        result
+    }
+
+    fn cancel(&self) -> ReturnCode {
+        // The entropy source keeps running if the generator is not seeded
+        // yet, but the client is not called back.
+        self.requested.set(false);
+        ReturnCode::SUCCESS
+    }
+
+    fn set_client(&'a self, client: &'a rng::Client) {
+        self.client.set(client);
+    }
+}
+
+impl entropy::Client32 for Csprng<'a> {
+    fn entropy_available(
+        &self,
+        entropy: &mut Iterator<Item = u32>,
+        error: ReturnCode,
+    ) -> entropy::Continue {
+        // Fill the pool for the seed, and once seeded, mix one word into it
+        // for each request.
+        let wanted = if self.seeded.get() {
+            1
+        } else {
+            POOL_WORDS - self.pool_words.get()
+        };
+        for word in entropy.take(wanted) {
+            let bytes = [word as u8, (word >> 8) as u8, (word >> 16) as u8, (word >> 24) as u8];
+            self.pool.map(|pool| pool.update(&bytes));
+            self.pool_words.set(self.pool_words.get() + 1);
+        }
+        if self.pool_words.get() >= POOL_WORDS {
+            self.reseed();
+        }
+
+        if !self.seeded.get() {
+            return if error == ReturnCode::SUCCESS {
+                entropy::Continue::More
+            } else {
+                entropy::Continue::Done
+            };
+        }
+        if !self.requested.get() {
+            return entropy::Continue::Done;
+        }
+        self.requested.set(false);
+        self.client.map(|client| {
+            let mut randomness = CsprngIter {
+                csprng: self,
+                block: [0; BLOCK_LEN],
+                used: BLOCK_LEN,
+            };
+            if client.randomness_available(&mut randomness, ReturnCode::SUCCESS)
+                == rng::Continue::More
+            {
+                self.requested.set(true);
+            }
+        });
+        if self.requested.get() {
+            entropy::Continue::More
+        } else {
+            entropy::Continue::Done
+        }
+    }
+}
+
+/// Yields words of random numbers, generated a block at a time. It never
+/// ends, as the generator can always produce more, so clients take the
+/// words they need.
+struct CsprngIter<'a: 'b, 'b> {
+    csprng: &'b Csprng<'a>,
+    block: [u8; BLOCK_LEN],
+    used: usize,
+}
+
+impl Iterator for CsprngIter<'a, 'b> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        if self.used == BLOCK_LEN {
+            self.csprng.generate(&mut self.block);
+            self.used = 0;
+        }
+        let bytes = &self.block[self.used..self.used + 4];
+        self.used += 4;
+        Some(
+            bytes[0] as u32
+                | (bytes[1] as u32) << 8
+                | (bytes[2] as u32) << 16
+                | (bytes[3] as u32) << 24,
+        )
+    }
+}
@@ -1,31 +1,141 @@
 //! Provides a DAC interface for userspace.
 //!
+//! Besides setting the output directly, an app can play a buffer of values
+//! at a fixed rate, once or repeatedly, if the board provides a
+//! `DacBuffered` implementation such as `DacWaveform`. The values are copied
+//! into a kernel buffer before playback starts, so the app can prepare the
+//! next waveform while one plays. Not virtualized: the last app to allow a
+//! buffer and subscribe gets the playback callbacks.
+//!
 //! Usage
 //! -----
 //!
 //! ```rust
 //! let dac = static_init!(
 //!     capsules::dac::Dac<'static>,
-//!     capsules::dac::Dac::new(&mut sam4l::dac::DAC));
+//!     capsules::dac::Dac::new(
+//!         &sam4l::dac::DAC,
+//!         Some(dac_waveform),
+//!         &mut capsules::dac::DAC_BUFFER));
+//! hil::dac::DacBuffered::set_client(dac_waveform, dac);
 //! ```
 
 /// Syscall driver number.
 pub const DRIVER_NUM: usize = 0x00000006;
 
+use kernel::common::cells::{MapCell, OptionalCell, TakeCell};
 use kernel::hil;
-use kernel::{AppId, Driver, ReturnCode};
+use kernel::{AppId, AppSlice, Callback, Driver, ReturnCode, Shared};
+
+/// Values for buffered playback. This bounds the length of a waveform.
+pub static mut DAC_BUFFER: [u16; 256] = [0; 256];
 
 pub struct Dac<'a> {
     dac: &'a hil::dac::DacChannel,
+    buffered: Option<&'a hil::dac::DacBuffered>,
+    buffer: TakeCell<'static, [u16]>,
+    app_buf: MapCell<AppSlice<Shared, u8>>,
+    callback: OptionalCell<Callback>,
 }
 
 impl Dac<'a> {
-    pub fn new(dac: &'a hil::dac::DacChannel) -> Dac<'a> {
-        Dac { dac: dac }
+    pub fn new(
+        dac: &'a hil::dac::DacChannel,
+        buffered: Option<&'a hil::dac::DacBuffered>,
+        buffer: &'static mut [u16],
+    ) -> Dac<'a> {
+        Dac {
+            dac: dac,
+            buffered: buffered,
+            buffer: TakeCell::new(buffer),
+            app_buf: MapCell::empty(),
+            callback: OptionalCell::empty(),
+        }
+    }
+
+    /// Plays the first `count` values of the app buffer.
+    fn play(&self, frequency: usize, count: usize, repeat: bool) -> ReturnCode {
+        let buffered = match self.buffered {
+            Some(buffered) => buffered,
+            None => return ReturnCode::ENOSUPPORT,
+        };
+        let buffer = match self.buffer.take() {
+            Some(buffer) => buffer,
+            None => return ReturnCode::EBUSY,
+        };
+
+        let copied = self.app_buf.map_or(false, |app_buf| {
+            if count == 0 || count > buffer.len() || count * 2 > app_buf.len() {
+                return false;
+            }
+            let bytes = app_buf.as_ref();
+            for (i, value) in buffer[..count].iter_mut().enumerate() {
+                *value = bytes[2 * i] as u16 | (bytes[2 * i + 1] as u16) << 8;
+            }
+            true
+        });
+        if !copied {
+            self.buffer.replace(buffer);
+            return ReturnCode::EINVAL;
+        }
+
+        let (rcode, buffer) = buffered.play(buffer, count, frequency as u32, repeat);
+        buffer.map(|buffer| self.buffer.replace(buffer));
+        rcode
+    }
+}
+
+impl hil::dac::BufferedClient for Dac<'a> {
+    fn playback_done(&self, buffer: &'static mut [u16], result: ReturnCode) {
+        self.buffer.replace(buffer);
+        self.callback.map(|mut cb| cb.schedule(isize::from(result) as usize, 0, 0));
     }
 }
 
 impl Driver for Dac<'a> {
+    /// Setup the buffer of values to play.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Values as little-endian 16-bit numbers.
+    fn allow(
+        &self,
+        _appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 => {
+                match slice {
+                    Some(slice) => self.app_buf.replace(slice),
+                    None => self.app_buf.take(),
+                };
+                ReturnCode::SUCCESS
+            }
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Setup a callback for when playback ends.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Playback finished or was stopped.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        _app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => {
+                self.callback.insert(callback);
+                ReturnCode::SUCCESS
+            }
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
     /// Control the DAC.
     ///
     /// ### `command_num`
@@ -33,7 +143,12 @@ impl Driver for Dac<'a> {
     /// - `0`: Driver check.
     /// - `1`: Initialize and enable the DAC.
     /// - `2`: Set the output to `data1`, a scaled output value.
-    fn command(&self, command_num: usize, data: usize, _: usize, _: AppId) -> ReturnCode {
+    /// - `3`: Play `data2` values from the buffer once, `data1` per second.
+    /// - `4`: Play `data2` values from the buffer repeatedly, `data1` per
+    ///   second.
+    /// - `5`: Stop playing.
+    /// - `6`: Get the resolution of the DAC in bits.
+    fn command(&self, command_num: usize, data: usize, data2: usize, _: AppId) -> ReturnCode {
         match command_num {
             0 /* check if present */ => return ReturnCode::SUCCESS,
 
@@ -43,6 +158,18 @@ impl Driver for Dac<'a> {
             // set the dac output
             2 => self.dac.set_value(data),
 
+            // play the buffer
+            3 => self.play(data, data2, false),
+            4 => self.play(data, data2, true),
+
+            // stop playing
+            5 => self.buffered.map_or(ReturnCode::ENOSUPPORT, |buffered| buffered.stop()),
+
+            // resolution
+            6 => ReturnCode::SuccessWithValue {
+                value: self.dac.get_resolution_bits(),
+            },
+
             _ => return ReturnCode::ENOSUPPORT,
         }
     }
@@ -0,0 +1,138 @@
+//! Buffered output on any DAC, paced by an alarm.
+//!
+//! `DacWaveform` implements `DacBuffered` for a `DacChannel` that can only
+//! be set one value at a time: each time the alarm fires, it outputs the
+//! next value of the buffer. The next alarm is set relative to when the
+//! previous one should have fired, so the output rate doesn't drift when
+//! interrupts are late. Each value costs an interrupt, so this suits
+//! waveforms of up to a few kHz; a value the DAC rejects, for example one
+//! out of range, is skipped.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let dac_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     VirtualMuxAlarm::new(mux_alarm));
+//! let dac_waveform = static_init!(
+//!     capsules::dac_waveform::DacWaveform<'static,
+//!         VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::dac_waveform::DacWaveform::new(&sam4l::dac::DAC, dac_alarm));
+//! dac_alarm.set_client(dac_waveform);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::dac;
+use kernel::hil::time::{self, Frequency};
+use kernel::ReturnCode;
+
+pub struct DacWaveform<'a, A: time::Alarm> {
+    dac: &'a dac::DacChannel,
+    alarm: &'a A,
+    buffer: TakeCell<'static, [u16]>,
+    length: Cell<usize>,
+    /// Index of the next value to output
+    index: Cell<usize>,
+    repeat: Cell<bool>,
+    /// Alarm ticks between values
+    interval: Cell<u32>,
+    /// When the alarm was last set to fire
+    tics: Cell<u32>,
+    client: OptionalCell<&'static dac::BufferedClient>,
+}
+
+impl<A: time::Alarm> DacWaveform<'a, A> {
+    pub fn new(dac: &'a dac::DacChannel, alarm: &'a A) -> DacWaveform<'a, A> {
+        DacWaveform {
+            dac: dac,
+            alarm: alarm,
+            buffer: TakeCell::empty(),
+            length: Cell::new(0),
+            index: Cell::new(0),
+            repeat: Cell::new(false),
+            interval: Cell::new(0),
+            tics: Cell::new(0),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    fn schedule(&self) {
+        let tics = self.tics.get().wrapping_add(self.interval.get());
+        self.tics.set(tics);
+        self.alarm.set_alarm(tics);
+    }
+}
+
+impl<A: time::Alarm> dac::DacBuffered for DacWaveform<'a, A> {
+    fn set_client(&self, client: &'static dac::BufferedClient) {
+        self.client.set(client);
+    }
+
+    fn play(
+        &self,
+        buffer: &'static mut [u16],
+        length: usize,
+        frequency: u32,
+        repeat: bool,
+    ) -> (ReturnCode, Option<&'static mut [u16]>) {
+        if self.buffer.is_some() {
+            return (ReturnCode::EBUSY, Some(buffer));
+        }
+        if length == 0 || length > buffer.len() {
+            return (ReturnCode::ESIZE, Some(buffer));
+        }
+        if frequency == 0 || frequency > A::Frequency::frequency() {
+            return (ReturnCode::EINVAL, Some(buffer));
+        }
+
+        self.length.set(length);
+        self.index.set(0);
+        self.repeat.set(repeat);
+        self.interval.set(A::Frequency::frequency() / frequency);
+        self.buffer.replace(buffer);
+
+        // The first value is output when the alarm first fires, so that
+        // every value is held for the same time.
+        self.tics.set(self.alarm.now());
+        self.schedule();
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn stop(&self) -> ReturnCode {
+        match self.buffer.take() {
+            Some(buffer) => {
+                self.alarm.disable();
+                self.client
+                    .map(|client| client.playback_done(buffer, ReturnCode::ECANCEL));
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::EOFF,
+        }
+    }
+}
+
+impl<A: time::Alarm> time::Client for DacWaveform<'a, A> {
+    fn fired(&self) {
+        let buffer = match self.buffer.take() {
+            Some(buffer) => buffer,
+            None => return,
+        };
+
+        let index = self.index.get();
+        self.dac.set_value(buffer[index] as usize);
+
+        if index + 1 < self.length.get() {
+            self.index.set(index + 1);
+        } else if self.repeat.get() {
+            self.index.set(0);
+        } else {
+            self.client
+                .map(|client| client.playback_done(buffer, ReturnCode::SUCCESS));
+            return;
+        }
+        self.buffer.replace(buffer);
+        self.schedule();
+    }
+}
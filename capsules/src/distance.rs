@@ -0,0 +1,189 @@
+//! Provides userspace with access to distance sensors.
+//!
+//! The driver serves a list of sensors, and measures one at a time, so
+//! ultrasonic sensors on the same board do not hear each other's bursts.
+//! Each app can request a measurement of each sensor; requests of several
+//! apps for the same sensor are served by one measurement, and sensors are
+//! measured in turn.
+//!
+//! Usage
+//! -----
+//!
+//! You need devices that provide the `hil::sensors::Distance` trait.
+//!
+//! ```rust
+//! let distance_sensors = static_init!(
+//!     [&'static kernel::hil::sensors::Distance; 2],
+//!     [hc_sr04_front, hc_sr04_back]);
+//! let distance = static_init!(
+//!     capsules::distance::DistanceSensor<'static>,
+//!     capsules::distance::DistanceSensor::new(
+//!         distance_sensors,
+//!         board_kernel.create_grant(&memory_allocation_capability)));
+//! for sensor in distance_sensors.iter() {
+//!     sensor.set_client(distance);
+//! }
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::hil::sensors;
+use kernel::{AppId, Callback, Driver, Grant, ReturnCode};
+
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = 0x60005;
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    /// The sensors the app requested a measurement of, one bit each
+    requested: u32,
+}
+
+pub struct DistanceSensor<'a> {
+    sensors: &'a [&'a sensors::Distance],
+    apps: Grant<App>,
+    /// The sensor being measured
+    current: OptionalCell<usize>,
+}
+
+impl DistanceSensor<'a> {
+    pub fn new(sensors: &'a [&'a sensors::Distance], grant: Grant<App>) -> DistanceSensor<'a> {
+        DistanceSensor {
+            sensors: sensors,
+            apps: grant,
+            current: OptionalCell::empty(),
+        }
+    }
+
+    fn sensor_count(&self) -> usize {
+        if self.sensors.len() < 32 {
+            self.sensors.len()
+        } else {
+            32
+        }
+    }
+
+    /// Starts measuring the next requested sensor after `last`, unless a
+    /// measurement is in progress.
+    fn start_next(&self, last: usize) {
+        while self.current.is_none() {
+            let requested = Cell::new(0);
+            self.apps.each(|app| {
+                requested.set(requested.get() | app.requested);
+            });
+            if requested.get() == 0 {
+                return;
+            }
+
+            let count = self.sensor_count();
+            let next = (1..count + 1)
+                .map(|offset| (last + offset) % count)
+                .find(|&sensor| requested.get() & (1 << sensor) != 0)
+                .unwrap_or(0);
+            self.current.set(next);
+            let rcode = self.sensors[next].read_distance();
+            if rcode != ReturnCode::SUCCESS {
+                self.finish(next, 0, rcode);
+            }
+        }
+    }
+
+    /// Reports a measurement to the apps that requested it.
+    fn finish(&self, sensor: usize, distance: u32, result: ReturnCode) {
+        self.current.clear();
+        self.apps.each(|app| {
+            if app.requested & (1 << sensor) != 0 {
+                app.requested &= !(1 << sensor);
+                app.callback.map(|mut cb| {
+                    cb.schedule(sensor, distance as usize, isize::from(result) as usize)
+                });
+            }
+        });
+    }
+}
+
+impl sensors::DistanceClient for DistanceSensor<'a> {
+    fn callback(&self, distance: u32, result: ReturnCode) {
+        self.current.take().map(|sensor| {
+            self.finish(sensor, distance, result);
+            self.start_next(sensor);
+        });
+    }
+}
+
+impl Driver for DistanceSensor<'a> {
+    /// Subscribe to distance readings.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Called with the sensor, the distance in millimeters and the
+    ///        result of a measurement the app requested.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Measure distances.
+    ///
+    /// `data` is the sensor for all commands but 0. They return EINVAL if
+    /// the sensor does not exist.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check and get the number of sensors.
+    /// - `1`: Request a measurement. Returns EALREADY if one is requested.
+    /// - `2`: Get the shortest distance the sensor measures, in millimeters.
+    /// - `3`: Get the longest distance the sensor measures, in millimeters.
+    fn command(&self, command_num: usize, data: usize, _: usize, appid: AppId) -> ReturnCode {
+        if command_num == 0 {
+            return ReturnCode::SuccessWithValue {
+                value: self.sensor_count(),
+            };
+        }
+        if data >= self.sensor_count() {
+            return ReturnCode::EINVAL;
+        }
+
+        match command_num {
+            1 => {
+                let rcode = self
+                    .apps
+                    .enter(appid, |app, _| {
+                        if app.requested & (1 << data) != 0 {
+                            ReturnCode::EALREADY
+                        } else {
+                            app.requested |= 1 << data;
+                            ReturnCode::SUCCESS
+                        }
+                    }).unwrap_or_else(|err| err.into());
+                if rcode == ReturnCode::SUCCESS {
+                    self.start_next(data + self.sensor_count() - 1);
+                }
+                rcode
+            }
+
+            2 => ReturnCode::SuccessWithValue {
+                value: self.sensors[data].get_minimum_distance() as usize,
+            },
+
+            3 => ReturnCode::SuccessWithValue {
+                value: self.sensors[data].get_maximum_distance() as usize,
+            },
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
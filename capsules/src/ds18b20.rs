@@ -0,0 +1,218 @@
+//! Driver for the Maxim DS18B20 1-Wire temperature sensor.
+//!
+//! <https://www.maximintegrated.com/en/products/sensors/DS18B20.html>
+//!
+//! > The DS18B20 digital thermometer provides 9-bit to 12-bit Celsius
+//! > temperature measurements and has an alarm function with nonvolatile
+//! > user-programmable upper and lower trigger points. The DS18B20
+//! > communicates over a 1-Wire bus that by definition requires only one data
+//! > line (and ground) for communication with a central microprocessor.
+//!
+//! With a ROM code, the driver addresses that sensor, so several can share
+//! a bus; use `OneWire::search` to find their codes. Without one, the sensor
+//! must be alone on the bus. Sensors powered parasitically over the data
+//! line are not supported, as they need the line pulled up strongly during
+//! the conversion.
+//!
+//! A conversion at the default 12-bit resolution takes up to 750 ms. If the
+//! sensor doesn't answer or its data is corrupted, no reading is reported.
+//! Temperatures below zero are reported as negative values cast to `usize`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let ds18b20_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, tm4c129x::gpt::AlarmTimer>,
+//!     VirtualMuxAlarm::new(mux_alarm));
+//! let ds18b20 = static_init!(
+//!     capsules::ds18b20::DS18B20<'static,
+//!         VirtualMuxAlarm<'static, tm4c129x::gpt::AlarmTimer>>,
+//!     capsules::ds18b20::DS18B20::new(onewire, ds18b20_alarm, None,
+//!                                     &mut capsules::ds18b20::BUFFER));
+//! onewire.set_client(ds18b20);
+//! ds18b20_alarm.set_client(ds18b20);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::onewire;
+use kernel::hil::sensors;
+use kernel::hil::time::{self, Frequency};
+use kernel::ReturnCode;
+
+/// A ROM command with a ROM code, then a function command
+pub static mut BUFFER: [u8; 10] = [0; 10];
+
+/// The family code of the DS18B20 in its ROM code.
+pub const FAMILY_CODE: u8 = 0x28;
+
+const CONVERT_T: u8 = 0x44;
+const READ_SCRATCHPAD: u8 = 0xBE;
+
+/// The scratchpad is 8 bytes followed by their CRC
+const SCRATCHPAD_LEN: usize = 9;
+
+const CONVERSION_MS: u32 = 750;
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    ResetConvert,
+    Convert,
+    Converting,
+    ResetRead,
+    SelectRead,
+    ReadScratchpad,
+}
+
+pub struct DS18B20<'a, A: time::Alarm> {
+    onewire: &'a onewire::OneWire,
+    alarm: &'a A,
+    /// The sensor to address, or `None` to skip ROM
+    rom: Option<u64>,
+    state: Cell<State>,
+    buffer: TakeCell<'static, [u8]>,
+    client: OptionalCell<&'static sensors::TemperatureClient>,
+}
+
+impl<A: time::Alarm> DS18B20<'a, A> {
+    pub fn new(
+        onewire: &'a onewire::OneWire,
+        alarm: &'a A,
+        rom: Option<u64>,
+        buffer: &'static mut [u8],
+    ) -> DS18B20<'a, A> {
+        DS18B20 {
+            onewire: onewire,
+            alarm: alarm,
+            rom: rom,
+            state: Cell::new(State::Idle),
+            buffer: TakeCell::new(buffer),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Writes the ROM command that selects the sensor followed by `command`.
+    fn send_command(&self, buffer: &'static mut [u8], command: u8) {
+        let len = match self.rom {
+            Some(rom) => {
+                buffer[0] = onewire::MATCH_ROM;
+                for i in 0..8 {
+                    buffer[1 + i] = (rom >> (8 * i)) as u8;
+                }
+                buffer[9] = command;
+                10
+            }
+            None => {
+                buffer[0] = onewire::SKIP_ROM;
+                buffer[1] = command;
+                2
+            }
+        };
+        let (rcode, buffer) = self.onewire.write(buffer, len);
+        if rcode != ReturnCode::SUCCESS {
+            buffer.map(|buffer| self.buffer.replace(buffer));
+            self.state.set(State::Idle);
+        }
+    }
+
+    fn reset(&self, state: State) {
+        if self.onewire.reset() == ReturnCode::SUCCESS {
+            self.state.set(state);
+        } else {
+            self.state.set(State::Idle);
+        }
+    }
+}
+
+impl<A: time::Alarm> onewire::Client for DS18B20<'a, A> {
+    fn reset_done(&self, present: bool) {
+        if !present {
+            self.state.set(State::Idle);
+            return;
+        }
+        let (state, command) = match self.state.get() {
+            State::ResetConvert => (State::Convert, CONVERT_T),
+            State::ResetRead => (State::SelectRead, READ_SCRATCHPAD),
+            _ => return,
+        };
+        self.buffer.take().map(|buffer| {
+            self.state.set(state);
+            self.send_command(buffer, command);
+        });
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], result: ReturnCode) {
+        if result != ReturnCode::SUCCESS {
+            self.buffer.replace(buffer);
+            self.state.set(State::Idle);
+            return;
+        }
+        match self.state.get() {
+            State::Convert => {
+                self.buffer.replace(buffer);
+                self.state.set(State::Converting);
+                let interval =
+                    (CONVERSION_MS as u64 * A::Frequency::frequency() as u64 / 1000) as u32;
+                let tics = self.alarm.now().wrapping_add(interval);
+                self.alarm.set_alarm(tics);
+            }
+            State::SelectRead => {
+                self.state.set(State::ReadScratchpad);
+                let (rcode, buffer) = self.onewire.read(buffer, SCRATCHPAD_LEN);
+                if rcode != ReturnCode::SUCCESS {
+                    buffer.map(|buffer| self.buffer.replace(buffer));
+                    self.state.set(State::Idle);
+                }
+            }
+            _ => {
+                self.buffer.replace(buffer);
+            }
+        }
+    }
+
+    fn read_done(&self, buffer: &'static mut [u8], result: ReturnCode) {
+        self.state.set(State::Idle);
+        let valid = result == ReturnCode::SUCCESS
+            && onewire::crc8(&buffer[..SCRATCHPAD_LEN]) == 0
+            // A scratchpad of all ones means nothing answered
+            && buffer[..SCRATCHPAD_LEN].iter().any(|byte| *byte != 0xFF);
+        // Sixteenths of degrees centigrade
+        let raw = (buffer[0] as u16 | (buffer[1] as u16) << 8) as i16;
+        self.buffer.replace(buffer);
+
+        if valid {
+            let hundredths = raw as i32 * 100 / 16;
+            self.client.map(|client| client.callback(hundredths as usize));
+        }
+    }
+
+    fn search_done(&self, _rom: u64, _last: bool, _result: ReturnCode) {}
+}
+
+impl<A: time::Alarm> time::Client for DS18B20<'a, A> {
+    fn fired(&self) {
+        if self.state.get() == State::Converting {
+            self.reset(State::ResetRead);
+        }
+    }
+}
+
+impl<A: time::Alarm> sensors::TemperatureDriver for DS18B20<'a, A> {
+    fn set_client(&self, client: &'static sensors::TemperatureClient) {
+        self.client.set(client);
+    }
+
+    fn read_temperature(&self) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        self.state.set(State::ResetConvert);
+        let rcode = self.onewire.reset();
+        if rcode != ReturnCode::SUCCESS {
+            self.state.set(State::Idle);
+        }
+        rcode
+    }
+}
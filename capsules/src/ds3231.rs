@@ -0,0 +1,354 @@
+//! Driver for the Maxim DS3231 real-time clock.
+//!
+//! <https://datasheets.maximintegrated.com/en/ds/DS3231.pdf>
+//!
+//! > The DS3231 is a low-cost, extremely accurate I2C real-time clock (RTC)
+//! > with an integrated temperature-compensated crystal oscillator (TCXO) and
+//! > crystal. The device incorporates a battery input, and maintains accurate
+//! > timekeeping when main power to the device is interrupted.
+//!
+//! The clock keeps the date and time in 24-hour mode, for the years 2000 to
+//! 2199. It implements `hil::time::WallClock`. It reports EOFF when read
+//! after its oscillator stopped, for example when it first gets power,
+//! until the time is set.
+//!
+//! The alarm uses alarm 1 of the clock and its active-low INT/SQW output,
+//! which is optional. Alarm 1 matches the day of the month, the hour, the
+//! minute and the second, so the driver checks the month and the year when
+//! it fires, and waits for the next month if they are early.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let ds3231_i2c = static_init!(
+//!     capsules::virtual_i2c::I2CDevice,
+//!     capsules::virtual_i2c::I2CDevice::new(i2c_bus, 0x68));
+//! let ds3231 = static_init!(
+//!     capsules::ds3231::Ds3231<'static>,
+//!     capsules::ds3231::Ds3231::new(
+//!         ds3231_i2c,
+//!         Some(&sam4l::gpio::PA[9]), // INT/SQW
+//!         &mut capsules::ds3231::BUFFER));
+//! ds3231_i2c.set_client(ds3231);
+//! sam4l::gpio::PA[9].set_client(ds3231);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::gpio;
+use kernel::hil::i2c;
+use kernel::hil::time::{self, DateTime};
+use kernel::ReturnCode;
+
+pub static mut BUFFER: [u8; 16] = [0; 16];
+
+#[allow(dead_code)]
+enum Registers {
+    Seconds = 0x00, // Seconds, minutes, hours, day, date, month and year, in BCD
+    Alarm1 = 0x07,  // Seconds, minutes, hours and date of alarm 1
+    Alarm2 = 0x0b,
+    Control = 0x0e,
+    Status = 0x0f,
+}
+
+/// Bits of the hours registers
+const HOURS_12: u8 = 1 << 6;
+const HOURS_PM: u8 = 1 << 5;
+
+/// The century bit of the month register
+const MONTH_CENTURY: u8 = 1 << 7;
+
+/// Bits of the control register: the INT/SQW output is the alarm interrupt,
+/// enabled for alarm 1
+const CONTROL_INTCN: u8 = 1 << 2;
+const CONTROL_A1IE: u8 = 1 << 0;
+
+/// Flags of the status register: the oscillator stopped, and alarm 1
+/// matched
+const STATUS_OSF: u8 = 1 << 7;
+const STATUS_A1F: u8 = 1 << 0;
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    ReadTime,
+    SetTime,
+    SetClearStatus,
+    SetAlarm,
+    DisableAlarm,
+    AlarmRead,
+    AlarmClear,
+    AlarmDisable,
+}
+
+fn bcd(value: u8) -> u8 {
+    (value / 10) << 4 | value % 10
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0f)
+}
+
+pub struct Ds3231<'a> {
+    i2c: &'a i2c::I2CDevice,
+    interrupt_pin: Option<&'a gpio::Pin>,
+    state: Cell<State>,
+    buffer: TakeCell<'static, [u8]>,
+    /// The date and time of the alarm, if set
+    alarm: Cell<Option<DateTime>>,
+    /// Whether the alarm fired while the bus was busy
+    alarm_pending: Cell<bool>,
+    client: OptionalCell<&'static time::WallClockClient>,
+}
+
+impl Ds3231<'a> {
+    pub fn new(
+        i2c: &'a i2c::I2CDevice,
+        interrupt_pin: Option<&'a gpio::Pin>,
+        buffer: &'static mut [u8],
+    ) -> Ds3231<'a> {
+        Ds3231 {
+            i2c: i2c,
+            interrupt_pin: interrupt_pin,
+            state: Cell::new(State::Idle),
+            buffer: TakeCell::new(buffer),
+            alarm: Cell::new(None),
+            alarm_pending: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Reads all the registers, from the time to the status.
+    fn read_all(&self, state: State) -> ReturnCode {
+        self.buffer.take().map_or(ReturnCode::EBUSY, |buffer| {
+            self.i2c.enable();
+            buffer[0] = Registers::Seconds as u8;
+            self.i2c.write_read(buffer, 1, 16);
+            self.state.set(state);
+            ReturnCode::SUCCESS
+        })
+    }
+
+    fn write(&self, state: State, register: Registers, values: &[u8]) -> ReturnCode {
+        self.buffer.take().map_or(ReturnCode::EBUSY, |buffer| {
+            self.i2c.enable();
+            buffer[0] = register as u8;
+            buffer[1..values.len() + 1].copy_from_slice(values);
+            self.i2c.write(buffer, values.len() as u8 + 1);
+            self.state.set(state);
+            ReturnCode::SUCCESS
+        })
+    }
+
+    fn idle(&self) {
+        self.i2c.disable();
+        self.state.set(State::Idle);
+        if self.alarm_pending.get() {
+            self.alarm_pending.set(false);
+            self.read_all(State::AlarmRead);
+        }
+    }
+
+    fn hours_from_register(value: u8) -> u8 {
+        if value & HOURS_12 != 0 {
+            let hour = from_bcd(value & 0x1f) % 12;
+            if value & HOURS_PM != 0 {
+                hour + 12
+            } else {
+                hour
+            }
+        } else {
+            from_bcd(value & 0x3f)
+        }
+    }
+
+    fn date_time_from_registers(registers: &[u8]) -> DateTime {
+        let century = if registers[5] & MONTH_CENTURY != 0 {
+            100
+        } else {
+            0
+        };
+        DateTime {
+            year: 2000 + century + from_bcd(registers[6]) as u16,
+            month: from_bcd(registers[5] & 0x1f),
+            day: from_bcd(registers[4] & 0x3f),
+            hour: Ds3231::hours_from_register(registers[2]),
+            minute: from_bcd(registers[1] & 0x7f),
+            second: from_bcd(registers[0] & 0x7f),
+        }
+    }
+}
+
+impl i2c::I2CClient for Ds3231<'a> {
+    fn command_complete(&self, buffer: &'static mut [u8], error: i2c::Error) {
+        let state = self.state.get();
+        if error != i2c::Error::CommandComplete {
+            self.buffer.replace(buffer);
+            self.idle();
+            self.client.map(|client| match state {
+                State::ReadTime => {
+                    client.date_time_read(DateTime::from_seconds(0), ReturnCode::FAIL)
+                }
+                State::SetTime
+                | State::SetClearStatus
+                | State::SetAlarm
+                | State::DisableAlarm => client.set_done(ReturnCode::FAIL),
+                _ => {}
+            });
+            return;
+        }
+
+        match state {
+            State::ReadTime => {
+                let date_time = Ds3231::date_time_from_registers(buffer);
+                let stopped = buffer[Registers::Status as usize] & STATUS_OSF != 0;
+                self.buffer.replace(buffer);
+                self.idle();
+                let result = if stopped {
+                    ReturnCode::EOFF
+                } else {
+                    ReturnCode::SUCCESS
+                };
+                self.client.map(|client| client.date_time_read(date_time, result));
+            }
+            State::SetTime => {
+                self.buffer.replace(buffer);
+                // Clearing the oscillator stop flag marks the time as valid
+                self.write(State::SetClearStatus, Registers::Status, &[0]);
+            }
+            State::SetClearStatus | State::SetAlarm | State::DisableAlarm => {
+                self.buffer.replace(buffer);
+                self.idle();
+                self.client.map(|client| client.set_done(ReturnCode::SUCCESS));
+            }
+            State::AlarmRead => {
+                let now = Ds3231::date_time_from_registers(buffer);
+                // Clearing the alarm flag releases INT/SQW
+                let status = buffer[Registers::Status as usize] & !STATUS_A1F;
+                self.buffer.replace(buffer);
+                let reached = self
+                    .alarm
+                    .get()
+                    .map_or(false, |alarm| now.seconds() >= alarm.seconds());
+                if reached {
+                    self.alarm.set(None);
+                    self.write(
+                        State::AlarmDisable,
+                        Registers::Control,
+                        &[CONTROL_INTCN, status],
+                    );
+                } else {
+                    // Wait for the day of the month to come again
+                    self.write(State::AlarmClear, Registers::Status, &[status]);
+                }
+            }
+            State::AlarmClear => {
+                self.buffer.replace(buffer);
+                self.idle();
+            }
+            State::AlarmDisable => {
+                self.buffer.replace(buffer);
+                self.idle();
+                self.client.map(|client| client.alarm());
+            }
+            State::Idle => {
+                self.buffer.replace(buffer);
+            }
+        }
+    }
+}
+
+impl gpio::Client for Ds3231<'a> {
+    fn fired(&self, _: usize) {
+        if self.state.get() == State::Idle {
+            self.read_all(State::AlarmRead);
+        } else {
+            self.alarm_pending.set(true);
+        }
+    }
+}
+
+impl time::WallClock for Ds3231<'a> {
+    fn set_client(&self, client: &'static time::WallClockClient) {
+        self.client.set(client);
+    }
+
+    fn get_date_time(&self) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        self.read_all(State::ReadTime)
+    }
+
+    fn set_date_time(&self, date_time: DateTime) -> ReturnCode {
+        if !date_time.is_valid() || date_time.year < 2000 || date_time.year >= 2200 {
+            return ReturnCode::EINVAL;
+        }
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        let century = if date_time.year >= 2100 {
+            MONTH_CENTURY
+        } else {
+            0
+        };
+        self.write(
+            State::SetTime,
+            Registers::Seconds,
+            &[
+                bcd(date_time.second),
+                bcd(date_time.minute),
+                bcd(date_time.hour),
+                date_time.weekday(),
+                bcd(date_time.day),
+                bcd(date_time.month) | century,
+                bcd((date_time.year % 100) as u8),
+            ],
+        )
+    }
+
+    fn set_alarm(&self, date_time: DateTime) -> ReturnCode {
+        let pin = match self.interrupt_pin {
+            Some(pin) => pin,
+            None => return ReturnCode::ENODEVICE,
+        };
+        if !date_time.is_valid() {
+            return ReturnCode::EINVAL;
+        }
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+
+        self.alarm.set(Some(date_time));
+        pin.make_input();
+        pin.enable_interrupt(0, gpio::InterruptMode::FallingEdge);
+        // Alarm 1 matching the date, the hours, the minutes and the seconds,
+        // alarm 2 cleared and the interrupt of alarm 1 enabled, in one
+        // write. A flag left from an earlier match fires at once, and is
+        // cleared as a date that is early.
+        self.write(
+            State::SetAlarm,
+            Registers::Alarm1,
+            &[
+                bcd(date_time.second),
+                bcd(date_time.minute),
+                bcd(date_time.hour),
+                bcd(date_time.day),
+                0,
+                0,
+                0,
+                CONTROL_INTCN | CONTROL_A1IE,
+            ],
+        )
+    }
+
+    fn disable_alarm(&self) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        self.alarm.set(None);
+        self.interrupt_pin.map(|pin| pin.disable_interrupt());
+        self.write(State::DisableAlarm, Registers::Control, &[CONTROL_INTCN])
+    }
+}
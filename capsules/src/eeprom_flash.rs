@@ -0,0 +1,250 @@
+//! Flash pages on byte-addressable memory, such as EEPROM or FRAM.
+//!
+//! This capsule implements `hil::flash::Flash` on top of `hil::eeprom`, so
+//! that capsules written for flash, like the `kv_store`, the
+//! `nonvolatile_counter` and the `log_storage`, can be used on boards that
+//! have FRAM instead. With `NonvolatileToPages` on top, the memory can also
+//! back the `nonvolatile_storage_driver`. The memory is split into pages of
+//! `PAGE_SIZE` bytes. Since the memory does not have to be erased, writing a
+//! page only writes it, and erasing a page sets its bytes to 0xFF as erased
+//! flash reads. Pages are moved in as many transfers as the memory needs,
+//! through a buffer of the adapter.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let eeprom_flash = static_init!(
+//!     capsules::eeprom_flash::EepromFlash<
+//!         'static,
+//!         capsules::fm25cl::FM25CL<'static, VirtualSpiMasterDevice<'static, usart::USART>>,
+//!     >,
+//!     capsules::eeprom_flash::EepromFlash::new(fm25cl, &mut capsules::eeprom_flash::BUFFER)
+//! );
+//! hil::eeprom::Eeprom::set_client(fm25cl, eeprom_flash);
+//!
+//! let kv_store = static_init!(
+//!     capsules::kv_store::KVStore<'static, capsules::eeprom_flash::EepromFlash<...>>,
+//!     capsules::kv_store::KVStore::new(
+//!         eeprom_flash,
+//!         0,
+//!         static_init!(
+//!             capsules::eeprom_flash::EepromPage,
+//!             capsules::eeprom_flash::EepromPage::new()
+//!         ),
+//!         introspection,
+//!         &process_mgmt_cap,
+//!         board_kernel.create_grant(&memory_allocation_capability)
+//!     )
+//! );
+//! hil::flash::HasClient::set_client(eeprom_flash, kv_store);
+//! kv_store.mount();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use core::ops::{Index, IndexMut};
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil;
+use kernel::ReturnCode;
+
+/// The size of a page
+pub const PAGE_SIZE: usize = 512;
+
+pub static mut BUFFER: [u8; 128] = [0; 128];
+
+/// This is a wrapper around a u8 array that is sized to a single page.
+///
+/// An example looks like:
+///
+/// ```
+/// static mut PAGEBUFFER: EepromPage = EepromPage::new();
+/// ```
+pub struct EepromPage(pub [u8; PAGE_SIZE]);
+
+impl EepromPage {
+    pub const fn new() -> EepromPage {
+        EepromPage([0; PAGE_SIZE])
+    }
+}
+
+impl Index<usize> for EepromPage {
+    type Output = u8;
+
+    fn index(&self, idx: usize) -> &u8 {
+        &self.0[idx]
+    }
+}
+
+impl IndexMut<usize> for EepromPage {
+    fn index_mut(&mut self, idx: usize) -> &mut u8 {
+        &mut self.0[idx]
+    }
+}
+
+impl AsMut<[u8]> for EepromPage {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    Idle,
+    Read,
+    Write,
+    Erase,
+}
+
+pub struct EepromFlash<'a, E: hil::eeprom::Eeprom + 'a> {
+    eeprom: &'a E,
+    operation: Cell<Operation>,
+    /// The page of the operation, and how much of it was moved
+    page_number: Cell<usize>,
+    offset: Cell<usize>,
+    buffer: TakeCell<'static, [u8]>,
+    client: OptionalCell<&'a hil::flash::Client<EepromFlash<'a, E>>>,
+    client_page: TakeCell<'static, EepromPage>,
+}
+
+impl<E: hil::eeprom::Eeprom + 'a> EepromFlash<'a, E> {
+    pub fn new(eeprom: &'a E, buffer: &'static mut [u8]) -> EepromFlash<'a, E> {
+        EepromFlash {
+            eeprom: eeprom,
+            operation: Cell::new(Operation::Idle),
+            page_number: Cell::new(0),
+            offset: Cell::new(0),
+            buffer: TakeCell::new(buffer),
+            client: OptionalCell::empty(),
+            client_page: TakeCell::empty(),
+        }
+    }
+
+    fn start(
+        &self,
+        operation: Operation,
+        page_number: usize,
+        page: Option<&'static mut EepromPage>,
+    ) -> ReturnCode {
+        if self.operation.get() != Operation::Idle {
+            return ReturnCode::EBUSY;
+        }
+        if (page_number + 1) * PAGE_SIZE > self.eeprom.size() {
+            return ReturnCode::EINVAL;
+        }
+        self.operation.set(operation);
+        self.page_number.set(page_number);
+        self.offset.set(0);
+        page.map(|page| self.client_page.replace(page));
+        let result = self.transfer();
+        if result != ReturnCode::SUCCESS {
+            self.operation.set(Operation::Idle);
+        }
+        result
+    }
+
+    /// Starts moving the next part of the page.
+    fn transfer(&self) -> ReturnCode {
+        self.buffer.take().map_or(ReturnCode::EBUSY, |buffer| {
+            let offset = self.offset.get();
+            let length = cmp::min(buffer.len(), PAGE_SIZE - offset);
+            let address = self.page_number.get() * PAGE_SIZE + offset;
+            let (result, buffer) = match self.operation.get() {
+                Operation::Read => self.eeprom.read(address, buffer, length),
+                Operation::Write => {
+                    self.client_page.map(|page| {
+                        buffer[..length].copy_from_slice(&page.0[offset..offset + length]);
+                    });
+                    self.eeprom.write(address, buffer, length)
+                }
+                Operation::Erase => {
+                    for byte in buffer[..length].iter_mut() {
+                        *byte = 0xFF;
+                    }
+                    self.eeprom.write(address, buffer, length)
+                }
+                Operation::Idle => (ReturnCode::FAIL, Some(buffer)),
+            };
+            buffer.map(|buffer| self.buffer.replace(buffer));
+            result
+        })
+    }
+
+    /// Ends the operation in progress and tells the client.
+    fn finish(&self, error: hil::flash::Error) {
+        let operation = self.operation.get();
+        self.operation.set(Operation::Idle);
+        self.client.map(|client| match operation {
+            Operation::Read => {
+                self.client_page.take().map(|page| {
+                    client.read_complete(page, error);
+                });
+            }
+            Operation::Write => {
+                self.client_page.take().map(|page| {
+                    client.write_complete(page, error);
+                });
+            }
+            Operation::Erase => client.erase_complete(error),
+            Operation::Idle => {}
+        });
+    }
+
+    /// Continues after `length` bytes were moved with `result`.
+    fn transferred(&self, length: usize, result: ReturnCode) {
+        if result != ReturnCode::SUCCESS || length == 0 {
+            self.finish(hil::flash::Error::FlashError);
+            return;
+        }
+        let offset = self.offset.get() + length;
+        self.offset.set(offset);
+        if offset >= PAGE_SIZE {
+            self.finish(hil::flash::Error::CommandComplete);
+        } else if self.transfer() != ReturnCode::SUCCESS {
+            self.finish(hil::flash::Error::FlashError);
+        }
+    }
+}
+
+impl<E: hil::eeprom::Eeprom + 'a> hil::eeprom::Client for EepromFlash<'a, E> {
+    fn read_done(&self, buffer: &'static mut [u8], length: usize, result: ReturnCode) {
+        if self.operation.get() == Operation::Read && result == ReturnCode::SUCCESS {
+            let offset = self.offset.get();
+            let length = cmp::min(length, PAGE_SIZE - offset);
+            self.client_page.map(|page| {
+                page.0[offset..offset + length].copy_from_slice(&buffer[..length]);
+            });
+        }
+        self.buffer.replace(buffer);
+        self.transferred(length, result);
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], length: usize, result: ReturnCode) {
+        self.buffer.replace(buffer);
+        self.transferred(length, result);
+    }
+}
+
+impl<E: hil::eeprom::Eeprom + 'a, C: hil::flash::Client<Self>> hil::flash::HasClient<'a, C>
+    for EepromFlash<'a, E>
+{
+    fn set_client(&self, client: &'a C) {
+        self.client.set(client);
+    }
+}
+
+impl<E: hil::eeprom::Eeprom + 'a> hil::flash::Flash for EepromFlash<'a, E> {
+    type Page = EepromPage;
+
+    fn read_page(&self, page_number: usize, buf: &'static mut Self::Page) -> ReturnCode {
+        self.start(Operation::Read, page_number, Some(buf))
+    }
+
+    fn write_page(&self, page_number: usize, buf: &'static mut Self::Page) -> ReturnCode {
+        self.start(Operation::Write, page_number, Some(buf))
+    }
+
+    fn erase_page(&self, page_number: usize) -> ReturnCode {
+        self.start(Operation::Erase, page_number, None)
+    }
+}
@@ -0,0 +1,773 @@
+//! Driver for the ENC28J60 Ethernet controller.
+//!
+//! <http://www.microchip.com/wwwproducts/en/ENC28J60>
+//!
+//! The ENC28J60 is a 10BASE-T Ethernet MAC and PHY with an SPI interface. It
+//! stores received and transmitted frames in an 8 KB buffer, and signals
+//! received frames, sent frames and errors on its INT pin. This driver
+//! implements `hil::ethernet::Ethernet`, using the controller in half duplex
+//! mode, with most of the buffer for received frames and room for one frame
+//! to send.
+//!
+//! Register banks are selected as they are needed, so the sequences of
+//! register operations below do not switch banks themselves.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let enc28j60_spi = static_init!(
+//!     capsules::virtual_spi::VirtualSpiMasterDevice<'static, sam4l::spi::SpiHw>,
+//!     capsules::virtual_spi::VirtualSpiMasterDevice::new(mux_spi, 2)
+//! );
+//! let enc28j60_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! let enc28j60 = static_init!(
+//!     capsules::enc28j60::ENC28J60<
+//!         'static,
+//!         capsules::virtual_spi::VirtualSpiMasterDevice<'static, sam4l::spi::SpiHw>,
+//!         VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     >,
+//!     capsules::enc28j60::ENC28J60::new(
+//!         enc28j60_spi,
+//!         enc28j60_alarm,
+//!         &sam4l::gpio::PA[20],
+//!         &mut capsules::enc28j60::SPI_TX_BUF,
+//!         &mut capsules::enc28j60::SPI_RX_BUF,
+//!         &mut capsules::enc28j60::FRAME_BUF
+//!     )
+//! );
+//! enc28j60_spi.set_client(enc28j60);
+//! enc28j60_alarm.set_client(enc28j60);
+//! sam4l::gpio::PA[20].set_client(enc28j60);
+//! hil::ethernet::Ethernet::set_address(enc28j60, [0x02, 0x00, 0x00, 0x00, 0x00, 0x01]);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::ethernet::{self, Filter, MacAddress, HEADER_LEN, MAX_FRAME_LEN};
+use kernel::hil::gpio;
+use kernel::hil::spi;
+use kernel::hil::time::{self, Frequency};
+use kernel::ReturnCode;
+
+/// The longest SPI transaction: an instruction, the per-packet control byte,
+/// and a frame
+const BUF_LEN: usize = MAX_FRAME_LEN + 2;
+
+pub static mut SPI_TX_BUF: [u8; BUF_LEN] = [0; BUF_LEN];
+pub static mut SPI_RX_BUF: [u8; BUF_LEN] = [0; BUF_LEN];
+pub static mut FRAME_BUF: [u8; BUF_LEN] = [0; BUF_LEN];
+
+const SPI_SPEED: u32 = 8000000;
+
+/// Time to wait for the oscillator after a reset, in milliseconds
+const RESET_DELAY_MS: u32 = 1;
+
+/// Layout of the buffer. The receive buffer starts at 0, and its read
+/// pointer must always be odd.
+const RX_START: u16 = 0x0000;
+const RX_END: u16 = 0x19ff;
+const TX_START: u16 = 0x1a00;
+
+/// The receive status vector before each received frame
+const RX_HEADER_LEN: usize = 6;
+const CRC_LEN: usize = 4;
+
+/// SPI instructions
+const RCR: u8 = 0x00;
+const RBM: u8 = 0x3a;
+const WCR: u8 = 0x40;
+const WBM: u8 = 0x7a;
+const BFS: u8 = 0x80;
+const BFC: u8 = 0xa0;
+const SRC: u8 = 0xff;
+
+/// Registers are numbered by their bank in bits 5-6 and their address in
+/// bits 0-4. Registers at addresses 0x1b to 0x1f are in every bank.
+mod register {
+    const BANK1: u8 = 1 << 5;
+    const BANK2: u8 = 2 << 5;
+    const BANK3: u8 = 3 << 5;
+
+    pub const ERDPTL: u8 = 0x00;
+    pub const ERDPTH: u8 = 0x01;
+    pub const EWRPTL: u8 = 0x02;
+    pub const EWRPTH: u8 = 0x03;
+    pub const ETXSTL: u8 = 0x04;
+    pub const ETXSTH: u8 = 0x05;
+    pub const ETXNDL: u8 = 0x06;
+    pub const ETXNDH: u8 = 0x07;
+    pub const ERXSTL: u8 = 0x08;
+    pub const ERXSTH: u8 = 0x09;
+    pub const ERXNDL: u8 = 0x0a;
+    pub const ERXNDH: u8 = 0x0b;
+    pub const ERXRDPTL: u8 = 0x0c;
+    pub const ERXRDPTH: u8 = 0x0d;
+
+    pub const ERXFCON: u8 = BANK1 | 0x18;
+    pub const EPKTCNT: u8 = BANK1 | 0x19;
+
+    pub const MACON1: u8 = BANK2 | 0x00;
+    pub const MACON3: u8 = BANK2 | 0x02;
+    pub const MACON4: u8 = BANK2 | 0x03;
+    pub const MABBIPG: u8 = BANK2 | 0x04;
+    pub const MAIPGL: u8 = BANK2 | 0x06;
+    pub const MAIPGH: u8 = BANK2 | 0x07;
+    pub const MAMXFLL: u8 = BANK2 | 0x0a;
+    pub const MAMXFLH: u8 = BANK2 | 0x0b;
+    pub const MIREGADR: u8 = BANK2 | 0x14;
+    pub const MIWRL: u8 = BANK2 | 0x16;
+    pub const MIWRH: u8 = BANK2 | 0x17;
+
+    pub const MAADR5: u8 = BANK3 | 0x00;
+    pub const MAADR6: u8 = BANK3 | 0x01;
+    pub const MAADR3: u8 = BANK3 | 0x02;
+    pub const MAADR4: u8 = BANK3 | 0x03;
+    pub const MAADR1: u8 = BANK3 | 0x04;
+    pub const MAADR2: u8 = BANK3 | 0x05;
+
+    pub const EIE: u8 = 0x1b;
+    pub const EIR: u8 = 0x1c;
+    pub const ECON2: u8 = 0x1e;
+    pub const ECON1: u8 = 0x1f;
+
+    /// The bank a register is in, or `None` if it is in every bank
+    pub fn bank(register: u8) -> Option<u8> {
+        if register & 0x1f >= 0x1b {
+            None
+        } else {
+            Some(register >> 5)
+        }
+    }
+
+    /// The address of a register in its bank
+    pub fn address(register: u8) -> u8 {
+        register & 0x1f
+    }
+}
+
+/// PHY registers
+const PHCON2: u8 = 0x10;
+const PHCON2_HDLDIS: u16 = 0x0100;
+
+/// ECON1 bits
+const ECON1_TXRTS: u8 = 0x08;
+const ECON1_RXEN: u8 = 0x04;
+const ECON1_BSEL: u8 = 0x03;
+
+/// ECON2 bits
+const ECON2_PKTDEC: u8 = 0x40;
+
+/// EIE and EIR bits
+const INTIE: u8 = 0x80;
+const PKTIE: u8 = 0x40;
+const TXIF: u8 = 0x08;
+const TXERIF: u8 = 0x02;
+const RXERIF: u8 = 0x01;
+
+/// ERXFCON bits
+const UCEN: u8 = 0x80;
+const CRCEN: u8 = 0x20;
+const MCEN: u8 = 0x02;
+const BCEN: u8 = 0x01;
+
+/// MACON1 bits
+const MARXEN: u8 = 0x01;
+
+/// MACON3 bits: pad short frames to 60 bytes, add the CRC, and check the
+/// length field of received frames
+const MACON3_VALUE: u8 = 0x32;
+
+/// MACON4 bits
+const DEFER: u8 = 0x40;
+
+/// Inter-packet gaps for half duplex
+const MABBIPG_VALUE: u8 = 0x12;
+const MAIPGL_VALUE: u8 = 0x12;
+const MAIPGH_VALUE: u8 = 0x0c;
+
+/// The longest frame the MAC accepts, with the CRC
+const MAX_FRAME_LEN_CRC: u16 = (MAX_FRAME_LEN + CRC_LEN) as u16;
+
+/// The "received OK" bit of the receive status vector
+const RX_OK: u8 = 0x80;
+
+/// An operation on a register
+#[derive(Clone, Copy)]
+enum Op {
+    Write(u8, u8),
+    SetBits(u8, u8),
+    ClearBits(u8, u8),
+    Read(u8),
+}
+
+impl Op {
+    fn register(&self) -> u8 {
+        match *self {
+            Op::Write(register, _)
+            | Op::SetBits(register, _)
+            | Op::ClearBits(register, _)
+            | Op::Read(register) => register,
+        }
+    }
+}
+
+/// Switching to another bank before running an operation
+#[derive(Clone, Copy)]
+enum BankSwitch {
+    None,
+    ClearBank(Op),
+    SetBank(Op),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Disabled,
+
+    // Enabling
+    Reset,
+    WaitOscillator,
+    Init(usize),
+
+    Idle,
+
+    // Receiving
+    ReadPacketCount,
+    SetReadPointer(usize),
+    ReadHeader,
+    ReadFrame,
+    FreePacket(usize),
+
+    // Handling other interrupts
+    ReadFlags,
+    ClearTxFlags { result: ReturnCode },
+    ClearRxError,
+
+    // Sending
+    SetWritePointer(usize),
+    WriteFrame,
+    SetTxEnd(usize),
+    StartTransmit,
+
+    // Disabling
+    StopReceiving,
+}
+
+pub struct ENC28J60<'a, S: spi::SpiMasterDevice + 'a, A: time::Alarm + 'a> {
+    spi: &'a S,
+    alarm: &'a A,
+    int_pin: &'a gpio::Pin,
+    state: Cell<State>,
+    client: OptionalCell<&'static ethernet::Client>,
+    spi_tx_buffer: TakeCell<'static, [u8]>,
+    spi_rx_buffer: TakeCell<'static, [u8]>,
+    // Holds the frame to send, after the instruction and control byte
+    frame_buffer: TakeCell<'static, [u8]>,
+
+    // Configuration, written when the controller is enabled
+    address: Cell<MacAddress>,
+    filter: Cell<Filter>,
+
+    bank: Cell<u8>,
+    bank_switch: Cell<BankSwitch>,
+    interrupt_pending: Cell<bool>,
+    // The address of the next received frame in the buffer
+    next_packet: Cell<u16>,
+    // The length of the received frame being read, without the CRC
+    rx_len: Cell<usize>,
+    // The length of the frame to send, if it has not been written yet
+    tx_len: Cell<Option<usize>>,
+    // Whether a frame has been written and not reported as sent
+    sending: Cell<bool>,
+}
+
+impl<S: spi::SpiMasterDevice, A: time::Alarm> ENC28J60<'a, S, A> {
+    pub fn new(
+        spi: &'a S,
+        alarm: &'a A,
+        int_pin: &'a gpio::Pin,
+        spi_tx_buffer: &'static mut [u8],
+        spi_rx_buffer: &'static mut [u8],
+        frame_buffer: &'static mut [u8],
+    ) -> ENC28J60<'a, S, A> {
+        ENC28J60 {
+            spi: spi,
+            alarm: alarm,
+            int_pin: int_pin,
+            state: Cell::new(State::Disabled),
+            client: OptionalCell::empty(),
+            spi_tx_buffer: TakeCell::new(spi_tx_buffer),
+            spi_rx_buffer: TakeCell::new(spi_rx_buffer),
+            frame_buffer: TakeCell::new(frame_buffer),
+            address: Cell::new([0; 6]),
+            filter: Cell::new(Filter::default()),
+            bank: Cell::new(0),
+            bank_switch: Cell::new(BankSwitch::None),
+            interrupt_pending: Cell::new(false),
+            next_packet: Cell::new(RX_START),
+            rx_len: Cell::new(0),
+            tx_len: Cell::new(None),
+            sending: Cell::new(false),
+        }
+    }
+
+    /// Start an SPI transaction of the `len` bytes that `fill` writes to the
+    /// transmit buffer, reading into the receive buffer
+    fn transfer<F>(&self, fill: F)
+    where
+        F: FnOnce(&mut [u8]) -> usize,
+    {
+        self.spi_tx_buffer.take().map(|tx_buffer| {
+            self.spi_rx_buffer.take().map(move |rx_buffer| {
+                let len = fill(tx_buffer);
+                self.spi.read_write_bytes(tx_buffer, Some(rx_buffer), len);
+            });
+        });
+    }
+
+    /// Run a register operation as part of `state`, switching banks first if
+    /// needed
+    fn run(&self, state: State, op: Op) {
+        self.state.set(state);
+        match register::bank(op.register()) {
+            Some(bank) if bank != self.bank.get() => {
+                self.bank_switch.set(BankSwitch::ClearBank(op));
+                self.transfer(|buf| {
+                    buf[0] = BFC | register::ECON1;
+                    buf[1] = ECON1_BSEL;
+                    2
+                });
+            }
+            _ => self.run_op(op),
+        }
+    }
+
+    fn run_op(&self, op: Op) {
+        self.bank_switch.set(BankSwitch::None);
+        self.transfer(|buf| {
+            let (instruction, value) = match op {
+                Op::Write(_, value) => (WCR, value),
+                Op::SetBits(_, mask) => (BFS, mask),
+                Op::ClearBits(_, mask) => (BFC, mask),
+                Op::Read(_) => (RCR, 0),
+            };
+            buf[0] = instruction | register::address(op.register());
+            buf[1] = value;
+            2
+        });
+    }
+
+    /// Continue switching banks, returning whether the switch is over
+    fn continue_bank_switch(&self) -> bool {
+        match self.bank_switch.get() {
+            BankSwitch::None => true,
+            BankSwitch::ClearBank(op) => {
+                let bank = register::bank(op.register()).unwrap_or(0);
+                if bank == 0 {
+                    self.bank.set(0);
+                    self.run_op(op);
+                } else {
+                    self.bank_switch.set(BankSwitch::SetBank(op));
+                    self.transfer(|buf| {
+                        buf[0] = BFS | register::ECON1;
+                        buf[1] = bank;
+                        2
+                    });
+                }
+                false
+            }
+            BankSwitch::SetBank(op) => {
+                self.bank
+                    .set(register::bank(op.register()).unwrap_or(0));
+                self.run_op(op);
+                false
+            }
+        }
+    }
+
+    /// The `i`th operation of the sequence that sets the controller up after
+    /// a reset, or `None` if there are no more
+    fn init_op(&self, i: usize) -> Option<Op> {
+        let address = self.address.get();
+        let filter = self.filter.get();
+        let filter_bits = if filter.promiscuous {
+            CRCEN
+        } else {
+            CRCEN
+                | if filter.unicast { UCEN } else { 0 }
+                | if filter.multicast { MCEN } else { 0 }
+                | if filter.broadcast { BCEN } else { 0 }
+        };
+        let op = match i {
+            0 => Op::Write(register::ERXSTL, RX_START as u8),
+            1 => Op::Write(register::ERXSTH, (RX_START >> 8) as u8),
+            2 => Op::Write(register::ERXNDL, RX_END as u8),
+            3 => Op::Write(register::ERXNDH, (RX_END >> 8) as u8),
+            4 => Op::Write(register::ERXRDPTL, RX_END as u8),
+            5 => Op::Write(register::ERXRDPTH, (RX_END >> 8) as u8),
+            6 => Op::Write(register::ETXSTL, TX_START as u8),
+            7 => Op::Write(register::ETXSTH, (TX_START >> 8) as u8),
+            8 => Op::Write(register::ERXFCON, filter_bits),
+            9 => Op::Write(register::MACON1, MARXEN),
+            10 => Op::Write(register::MACON3, MACON3_VALUE),
+            11 => Op::Write(register::MACON4, DEFER),
+            12 => Op::Write(register::MAMXFLL, MAX_FRAME_LEN_CRC as u8),
+            13 => Op::Write(register::MAMXFLH, (MAX_FRAME_LEN_CRC >> 8) as u8),
+            14 => Op::Write(register::MABBIPG, MABBIPG_VALUE),
+            15 => Op::Write(register::MAIPGL, MAIPGL_VALUE),
+            16 => Op::Write(register::MAIPGH, MAIPGH_VALUE),
+            // Writing the high byte of a PHY register starts the write. Keep
+            // the PHY from looping sent frames back in half duplex mode.
+            17 => Op::Write(register::MIREGADR, PHCON2),
+            18 => Op::Write(register::MIWRL, PHCON2_HDLDIS as u8),
+            19 => Op::Write(register::MIWRH, (PHCON2_HDLDIS >> 8) as u8),
+            20 => Op::Write(register::MAADR1, address[0]),
+            21 => Op::Write(register::MAADR2, address[1]),
+            22 => Op::Write(register::MAADR3, address[2]),
+            23 => Op::Write(register::MAADR4, address[3]),
+            24 => Op::Write(register::MAADR5, address[4]),
+            25 => Op::Write(register::MAADR6, address[5]),
+            26 => Op::Write(register::EIE, INTIE | PKTIE | TXIF | TXERIF | RXERIF),
+            27 => Op::SetBits(register::ECON1, ECON1_RXEN),
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    /// Start the next operation, if the controller is idle
+    fn run_next(&self) {
+        if self.state.get() != State::Idle {
+            return;
+        }
+        if self.interrupt_pending.take() {
+            // Frames are counted rather than flagged, as the flag is
+            // unreliable
+            self.run(State::ReadPacketCount, Op::Read(register::EPKTCNT));
+        } else if self.tx_len.get().is_some() {
+            self.run(
+                State::SetWritePointer(0),
+                Op::Write(register::EWRPTL, TX_START as u8),
+            );
+        }
+    }
+
+    /// Go back to checking for interrupts, until there are none left
+    fn handle_next_interrupt(&self) {
+        self.state.set(State::Idle);
+        self.interrupt_pending.set(true);
+        self.run_next();
+    }
+
+    /// Start reading the frame of the given length from the buffer, after
+    /// its header
+    fn read_frame(&self, len: usize) {
+        self.rx_len.set(len);
+        self.state.set(State::ReadFrame);
+        self.transfer(|buf| {
+            buf[0] = RBM;
+            1 + len
+        });
+    }
+
+    /// Free the space of the frame that was read, moving the read pointer to
+    /// the byte before the next frame, which must be odd
+    fn free_packet(&self, i: usize) {
+        let next_packet = self.next_packet.get();
+        let read_pointer = if next_packet == RX_START {
+            RX_END
+        } else {
+            next_packet - 1
+        };
+        match i {
+            0 => self.run(
+                State::FreePacket(0),
+                Op::Write(register::ERXRDPTL, read_pointer as u8),
+            ),
+            1 => self.run(
+                State::FreePacket(1),
+                Op::Write(register::ERXRDPTH, (read_pointer >> 8) as u8),
+            ),
+            2 => self.run(
+                State::FreePacket(2),
+                Op::SetBits(register::ECON2, ECON2_PKTDEC),
+            ),
+            _ => self.handle_next_interrupt(),
+        }
+    }
+
+    fn handle_flags(&self, flags: u8) {
+        if flags & (TXIF | TXERIF) != 0 {
+            let result = if flags & TXERIF != 0 {
+                ReturnCode::FAIL
+            } else {
+                ReturnCode::SUCCESS
+            };
+            self.run(
+                State::ClearTxFlags { result: result },
+                Op::ClearBits(register::EIR, TXIF | TXERIF),
+            );
+        } else if flags & RXERIF != 0 {
+            // The receive buffer was full, and a frame was dropped
+            self.run(State::ClearRxError, Op::ClearBits(register::EIR, RXERIF));
+        } else {
+            self.state.set(State::Idle);
+            self.run_next();
+        }
+    }
+}
+
+impl<S: spi::SpiMasterDevice, A: time::Alarm> ethernet::Ethernet for ENC28J60<'a, S, A> {
+    fn set_client(&self, client: &'static ethernet::Client) {
+        self.client.set(client);
+    }
+
+    fn set_address(&self, address: MacAddress) -> ReturnCode {
+        if self.state.get() != State::Disabled {
+            return ReturnCode::EBUSY;
+        }
+        self.address.set(address);
+        ReturnCode::SUCCESS
+    }
+
+    fn get_address(&self) -> MacAddress {
+        self.address.get()
+    }
+
+    fn set_filter(&self, filter: Filter) -> ReturnCode {
+        if self.state.get() != State::Disabled {
+            return ReturnCode::EBUSY;
+        }
+        self.filter.set(filter);
+        ReturnCode::SUCCESS
+    }
+
+    fn enable(&self) -> ReturnCode {
+        if self.state.get() != State::Disabled {
+            return ReturnCode::EALREADY;
+        }
+        self.spi.configure(
+            spi::ClockPolarity::IdleLow,
+            spi::ClockPhase::SampleLeading,
+            SPI_SPEED,
+        );
+        self.int_pin.make_input();
+        self.int_pin
+            .enable_interrupt(0, gpio::InterruptMode::FallingEdge);
+
+        // A reset selects bank 0
+        self.bank.set(0);
+        self.next_packet.set(RX_START);
+        self.state.set(State::Reset);
+        self.transfer(|buf| {
+            buf[0] = SRC;
+            1
+        });
+        ReturnCode::SUCCESS
+    }
+
+    fn disable(&self) -> ReturnCode {
+        match self.state.get() {
+            State::Disabled => ReturnCode::EALREADY,
+            State::Idle => {
+                self.int_pin.disable_interrupt();
+                self.tx_len.set(None);
+                self.sending.set(false);
+                self.interrupt_pending.set(false);
+                self.run(
+                    State::StopReceiving,
+                    Op::ClearBits(register::ECON1, ECON1_RXEN | ECON1_TXRTS),
+                );
+                ReturnCode::SUCCESS
+            }
+            _ => ReturnCode::EBUSY,
+        }
+    }
+
+    fn transmit(&self, frame: &[u8]) -> ReturnCode {
+        match self.state.get() {
+            State::Disabled
+            | State::Reset
+            | State::WaitOscillator
+            | State::Init(_)
+            | State::StopReceiving => return ReturnCode::EOFF,
+            _ => {}
+        }
+        if frame.len() < HEADER_LEN || frame.len() > MAX_FRAME_LEN {
+            return ReturnCode::ESIZE;
+        }
+        if self.sending.get() || self.tx_len.get().is_some() {
+            return ReturnCode::EBUSY;
+        }
+        self.frame_buffer.map_or(ReturnCode::EBUSY, |buffer| {
+            buffer[0] = WBM;
+            // Send the frame with the settings in MACON3
+            buffer[1] = 0;
+            buffer[2..2 + frame.len()].copy_from_slice(frame);
+            self.tx_len.set(Some(frame.len()));
+            self.sending.set(true);
+            self.run_next();
+            ReturnCode::SUCCESS
+        })
+    }
+}
+
+impl<S: spi::SpiMasterDevice, A: time::Alarm> spi::SpiMasterClient for ENC28J60<'a, S, A> {
+    fn read_write_done(
+        &self,
+        write_buffer: &'static mut [u8],
+        read_buffer: Option<&'static mut [u8]>,
+        _len: usize,
+    ) {
+        let state = self.state.get();
+        if state == State::WriteFrame {
+            self.frame_buffer.replace(write_buffer);
+        } else {
+            self.spi_tx_buffer.replace(write_buffer);
+        }
+        let mut value = 0;
+        let mut header = [0; RX_HEADER_LEN];
+        read_buffer.map(|read_buffer| {
+            if state == State::ReadFrame {
+                let len = self.rx_len.get();
+                self.client
+                    .map(|client| client.frame_received(&read_buffer[1..1 + len]));
+            }
+            value = read_buffer[1];
+            header.copy_from_slice(&read_buffer[1..1 + RX_HEADER_LEN]);
+            self.spi_rx_buffer.replace(read_buffer);
+        });
+
+        if !self.continue_bank_switch() {
+            return;
+        }
+
+        match state {
+            State::Disabled | State::Idle => {}
+
+            State::Reset => {
+                self.state.set(State::WaitOscillator);
+                let interval = RESET_DELAY_MS * <A::Frequency>::frequency() / 1000 + 1;
+                let tics = self.alarm.now().wrapping_add(interval);
+                self.alarm.set_alarm(tics);
+            }
+            State::WaitOscillator => {}
+            State::Init(i) => match self.init_op(i + 1) {
+                Some(op) => self.run(State::Init(i + 1), op),
+                None => {
+                    // Check for frames that arrived while enabling
+                    self.handle_next_interrupt();
+                }
+            },
+
+            State::ReadPacketCount => {
+                if value > 0 {
+                    let next_packet = self.next_packet.get();
+                    self.run(
+                        State::SetReadPointer(0),
+                        Op::Write(register::ERDPTL, next_packet as u8),
+                    );
+                } else {
+                    self.run(State::ReadFlags, Op::Read(register::EIR));
+                }
+            }
+            State::SetReadPointer(0) => {
+                let next_packet = self.next_packet.get();
+                self.run(
+                    State::SetReadPointer(1),
+                    Op::Write(register::ERDPTH, (next_packet >> 8) as u8),
+                );
+            }
+            State::SetReadPointer(_) => {
+                self.state.set(State::ReadHeader);
+                self.transfer(|buf| {
+                    buf[0] = RBM;
+                    1 + RX_HEADER_LEN
+                });
+            }
+            State::ReadHeader => {
+                self.next_packet
+                    .set(header[0] as u16 | (header[1] as u16) << 8);
+                let byte_count = header[2] as usize | (header[3] as usize) << 8;
+                if header[5] & RX_OK != 0 && byte_count >= HEADER_LEN + CRC_LEN
+                    && byte_count <= MAX_FRAME_LEN + CRC_LEN
+                {
+                    self.read_frame(byte_count - CRC_LEN);
+                } else {
+                    self.free_packet(0);
+                }
+            }
+            State::ReadFrame => self.free_packet(0),
+            State::FreePacket(i) => self.free_packet(i + 1),
+
+            State::ReadFlags => self.handle_flags(value),
+            State::ClearTxFlags { result } => {
+                self.sending.set(false);
+                self.client.map(|client| client.transmit_done(result));
+                self.handle_next_interrupt();
+            }
+            State::ClearRxError => self.handle_next_interrupt(),
+
+            State::SetWritePointer(0) => {
+                self.run(
+                    State::SetWritePointer(1),
+                    Op::Write(register::EWRPTH, (TX_START >> 8) as u8),
+                );
+            }
+            State::SetWritePointer(_) => {
+                let len = self.tx_len.get().unwrap_or(0);
+                self.frame_buffer.take().map(|buffer| {
+                    self.state.set(State::WriteFrame);
+                    self.spi.read_write_bytes(buffer, None, 2 + len);
+                });
+            }
+            State::WriteFrame => self.set_tx_end(0),
+            State::SetTxEnd(i) => self.set_tx_end(i + 1),
+            State::StartTransmit => {
+                // The controller signals when the frame is sent
+                self.state.set(State::Idle);
+                self.run_next();
+            }
+
+            State::StopReceiving => {
+                self.state.set(State::Disabled);
+            }
+        }
+    }
+}
+
+impl<S: spi::SpiMasterDevice, A: time::Alarm> ENC28J60<'a, S, A> {
+    /// Set the end of the frame to send, and start sending it. The control
+    /// byte is at `TX_START`, followed by the frame.
+    fn set_tx_end(&self, i: usize) {
+        let tx_end = TX_START + self.tx_len.get().unwrap_or(0) as u16;
+        match i {
+            0 => self.run(State::SetTxEnd(0), Op::Write(register::ETXNDL, tx_end as u8)),
+            1 => self.run(
+                State::SetTxEnd(1),
+                Op::Write(register::ETXNDH, (tx_end >> 8) as u8),
+            ),
+            _ => {
+                self.tx_len.set(None);
+                self.run(State::StartTransmit, Op::SetBits(register::ECON1, ECON1_TXRTS));
+            }
+        }
+    }
+}
+
+impl<S: spi::SpiMasterDevice, A: time::Alarm> time::Client for ENC28J60<'a, S, A> {
+    fn fired(&self) {
+        if self.state.get() == State::WaitOscillator {
+            self.init_op(0).map(|op| self.run(State::Init(0), op));
+        }
+    }
+}
+
+impl<S: spi::SpiMasterDevice, A: time::Alarm> gpio::Client for ENC28J60<'a, S, A> {
+    fn fired(&self, _: usize) {
+        self.interrupt_pending.set(true);
+        self.run_next();
+    }
+}
@@ -0,0 +1,1172 @@
+//! FAT filesystem system call interface
+//!
+//! This capsule lets processes read and write files on a FAT16 or FAT32
+//! volume on a `hil::block_storage::BlockStorage` device, such as an SD card,
+//! so that data logged by a board can be read directly by any computer. The
+//! volume is either the whole device or the first partition of an MBR
+//! partition table, and is mounted when it is first used.
+//!
+//! Files are found by path, such as `/LOGS/DATA.CSV`. Only short (8.3) names
+//! are supported: long names written by other systems are ignored, and names
+//! are compared in upper case. Opening a file that does not exist can create
+//! it, but directories are not created, and a directory that is full is not
+//! grown.
+//!
+//! Each process can have one file open at a time. The capsule works through
+//! a single sector buffer, and runs one operation at a time, for any process.
+//! The directory entry of a file is updated after each write, and modified
+//! sectors are written back before the process is called back, so that the
+//! volume stays consistent if the board loses power between operations.
+//!
+//! ## Instantiation
+//!
+//! ```rust
+//! let fat = static_init!(
+//!     capsules::fat::Fat<'static>,
+//!     capsules::fat::Fat::new(
+//!         sdcard,
+//!         &mut capsules::fat::BUFFER,
+//!         board_kernel.create_grant(&grant_cap)
+//!     )
+//! );
+//! hil::block_storage::BlockStorage::set_client(sdcard, fat);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::block_storage;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+/// Syscall number
+pub const DRIVER_NUM: usize = 0x50004;
+
+/// The only sector size supported
+const SECTOR_SIZE: usize = 512;
+
+/// The size of a directory entry
+const ENTRY_SIZE: usize = 32;
+
+const ENTRIES_PER_SECTOR: usize = SECTOR_SIZE / ENTRY_SIZE;
+
+pub static mut BUFFER: [u8; SECTOR_SIZE] = [0; SECTOR_SIZE];
+
+// Directory entry attributes
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+
+// The first byte of the name of free directory entries
+const ENTRY_END: u8 = 0x00;
+const ENTRY_DELETED: u8 = 0xE5;
+
+// Partition types of FAT16 and FAT32 volumes in an MBR partition table
+const PARTITION_TYPES: [u8; 5] = [0x04, 0x06, 0x0B, 0x0C, 0x0E];
+
+// Flags of the open command
+const OPEN_CREATE: usize = 1 << 0;
+const OPEN_APPEND: usize = 1 << 1;
+
+// Commands, which are also passed to the callback
+const COMMAND_OPEN: usize = 1;
+const COMMAND_READ: usize = 2;
+const COMMAND_WRITE: usize = 3;
+
+fn read_u16(buffer: &[u8], offset: usize) -> u32 {
+    buffer[offset] as u32 | (buffer[offset + 1] as u32) << 8
+}
+
+fn read_u32(buffer: &[u8], offset: usize) -> u32 {
+    read_u16(buffer, offset) | read_u16(buffer, offset + 2) << 16
+}
+
+fn write_u16(buffer: &mut [u8], offset: usize, value: u32) {
+    buffer[offset] = value as u8;
+    buffer[offset + 1] = (value >> 8) as u8;
+}
+
+fn write_u32(buffer: &mut [u8], offset: usize, value: u32) {
+    write_u16(buffer, offset, value);
+    write_u16(buffer, offset + 2, value >> 16);
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum FatType {
+    Fat16,
+    Fat32,
+}
+
+/// The layout of a mounted volume. Sectors are numbered from the start of
+/// the device.
+#[derive(Clone, Copy)]
+struct Volume {
+    fat_type: FatType,
+    sectors_per_cluster: u32,
+    /// The first sector of the first FAT
+    fat_start: u32,
+    /// The number of sectors of each FAT
+    fat_size: u32,
+    fat_count: u32,
+    /// The root directory of FAT16 volumes, which is not in a cluster
+    root_start: u32,
+    root_sectors: u32,
+    /// The first cluster of the root directory of FAT32 volumes
+    root_cluster: u32,
+    /// The first sector of cluster 2, the first cluster
+    data_start: u32,
+    cluster_count: u32,
+}
+
+impl Volume {
+    fn cluster_bytes(&self) -> u32 {
+        self.sectors_per_cluster * SECTOR_SIZE as u32
+    }
+
+    fn cluster_sector(&self, cluster: u32) -> u32 {
+        self.data_start + (cluster - 2) * self.sectors_per_cluster
+    }
+
+    /// The sector and offset of the FAT entry of `cluster` in the first FAT.
+    fn fat_location(&self, cluster: u32) -> (u32, usize) {
+        let offset = match self.fat_type {
+            FatType::Fat16 => cluster * 2,
+            FatType::Fat32 => cluster * 4,
+        };
+        (
+            self.fat_start + offset / SECTOR_SIZE as u32,
+            offset as usize % SECTOR_SIZE,
+        )
+    }
+
+    /// Whether the FAT entry `entry` ends a cluster chain. Free and bad
+    /// entries end it too, as chains of intact volumes do not contain them.
+    fn is_end(&self, entry: u32) -> bool {
+        entry < 2 || entry >= self.cluster_count + 2
+    }
+
+    fn end_marker(&self) -> u32 {
+        match self.fat_type {
+            FatType::Fat16 => 0xFFFF,
+            FatType::Fat32 => 0x0FFFFFFF,
+        }
+    }
+
+    /// The first cluster of the file or directory whose entry is at
+    /// `offset` in `buffer`.
+    fn entry_cluster(&self, buffer: &[u8], offset: usize) -> u32 {
+        let high = match self.fat_type {
+            FatType::Fat16 => 0,
+            FatType::Fat32 => read_u16(buffer, offset + 20),
+        };
+        high << 16 | read_u16(buffer, offset + 26)
+    }
+}
+
+/// A position in a directory
+#[derive(Clone, Copy)]
+struct Cursor {
+    /// The cluster of the directory, or 0 for the root directory of FAT16
+    /// volumes
+    cluster: u32,
+    /// The sector within the cluster or the root directory
+    sector: u32,
+    entry: usize,
+}
+
+impl Cursor {
+    fn at(cluster: u32) -> Cursor {
+        Cursor {
+            cluster: cluster,
+            sector: 0,
+            entry: 0,
+        }
+    }
+
+    fn next(&self) -> Cursor {
+        if self.entry + 1 < ENTRIES_PER_SECTOR {
+            Cursor {
+                entry: self.entry + 1,
+                ..*self
+            }
+        } else {
+            Cursor {
+                cluster: self.cluster,
+                sector: self.sector + 1,
+                entry: 0,
+            }
+        }
+    }
+}
+
+/// An open file
+#[derive(Clone, Copy)]
+struct File {
+    /// The sector and index of the directory entry of the file
+    entry_sector: u32,
+    entry_index: usize,
+    /// The first cluster of the file, or 0 if it is empty
+    first_cluster: u32,
+    size: u32,
+    position: u32,
+    /// The cluster last used and its index in the file, or 0 if none was
+    cluster: u32,
+    cluster_index: u32,
+}
+
+impl File {
+    fn new(entry_sector: u32, entry_index: usize, first_cluster: u32, size: u32) -> File {
+        File {
+            entry_sector: entry_sector,
+            entry_index: entry_index,
+            first_cluster: first_cluster,
+            size: size,
+            position: 0,
+            cluster: 0,
+            cluster_index: 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    Idle,
+    /// Looking up the path
+    Open { create: bool, append: bool },
+    /// Writing a new directory entry into the free entry at `index` in
+    /// `sector`
+    Create { sector: u32, index: usize },
+    Read { length: usize, done: usize },
+    Write { length: usize, done: usize },
+    /// Writing back the cached sector before ending the operation
+    Flush { result: ReturnCode, value: usize },
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    path: Option<AppSlice<Shared, u8>>,
+    read_buffer: Option<AppSlice<Shared, u8>>,
+    write_buffer: Option<AppSlice<Shared, u8>>,
+    file: Option<File>,
+}
+
+pub struct Fat<'a> {
+    storage: &'a block_storage::BlockStorage,
+    apps: Grant<App>,
+    buffer: TakeCell<'static, [u8]>,
+    // The sector held in the buffer, and whether it was modified
+    cached: Cell<Option<u32>>,
+    dirty: Cell<bool>,
+    // The sector being read, and the copy of the FAT being written
+    reading: Cell<u32>,
+    fat_copy: Cell<u32>,
+    // The first sector of the volume, once the partition table was read
+    volume_start: Cell<Option<u32>>,
+    volume: Cell<Option<Volume>>,
+    // Where to look for a free cluster next
+    free_cluster: Cell<u32>,
+    // The operation in progress, the app and command it is for, and the file
+    owner: OptionalCell<AppId>,
+    command: Cell<usize>,
+    operation: Cell<Operation>,
+    file: Cell<Option<File>>,
+    // The progress of looking up a path
+    path_position: Cell<usize>,
+    cursor: Cell<Option<Cursor>>,
+    free_entry: Cell<Option<(u32, usize)>>,
+    // A cluster allocated to the file, but not yet linked to it
+    allocated: Cell<u32>,
+}
+
+impl Fat<'a> {
+    pub fn new(
+        storage: &'a block_storage::BlockStorage,
+        buffer: &'static mut [u8],
+        apps: Grant<App>,
+    ) -> Fat<'a> {
+        Fat {
+            storage: storage,
+            apps: apps,
+            buffer: TakeCell::new(buffer),
+            cached: Cell::new(None),
+            dirty: Cell::new(false),
+            reading: Cell::new(0),
+            fat_copy: Cell::new(0),
+            volume_start: Cell::new(None),
+            volume: Cell::new(None),
+            free_cluster: Cell::new(2),
+            owner: OptionalCell::empty(),
+            command: Cell::new(0),
+            operation: Cell::new(Operation::Idle),
+            file: Cell::new(None),
+            path_position: Cell::new(0),
+            cursor: Cell::new(None),
+            free_entry: Cell::new(None),
+            allocated: Cell::new(0),
+        }
+    }
+
+    fn start(
+        &self,
+        appid: AppId,
+        command: usize,
+        operation: Operation,
+        file: Option<File>,
+    ) -> ReturnCode {
+        if self.operation.get() != Operation::Idle {
+            return ReturnCode::EBUSY;
+        }
+        if self.storage.block_count() == 0 {
+            return ReturnCode::EOFF;
+        }
+        if self.storage.block_size() != SECTOR_SIZE {
+            return ReturnCode::ENOSUPPORT;
+        }
+        self.owner.set(appid);
+        self.command.set(command);
+        self.operation.set(operation);
+        self.file.set(file);
+        self.path_position.set(0);
+        self.cursor.set(None);
+        self.free_entry.set(None);
+        self.allocated.set(0);
+        self.step();
+        ReturnCode::SUCCESS
+    }
+
+    /// Ends the operation in progress and calls back the process.
+    fn finish(&self, result: ReturnCode, value: usize) {
+        let command = self.command.get();
+        let file = self.file.take();
+        self.operation.set(Operation::Idle);
+        if result != ReturnCode::SUCCESS && self.dirty.get() {
+            // The sector could not be written back
+            self.cached.set(None);
+            self.dirty.set(false);
+        }
+        self.owner.take().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                // A failed open leaves the file of the process alone, while
+                // the position of a file moves with what was read or written
+                // before a failure
+                if command != COMMAND_OPEN || result == ReturnCode::SUCCESS {
+                    app.file = file;
+                }
+                app.callback
+                    .map(|mut cb| cb.schedule(command, usize::from(result), value));
+            });
+        });
+    }
+
+    /// Continues the operation in progress until it has to wait for the
+    /// device, or ends.
+    fn step(&self) {
+        if let Err(error) = self.advance() {
+            match self.operation.get() {
+                Operation::Idle => {}
+                Operation::Flush { .. } => self.finish(error, 0),
+                _ => {
+                    // Keep what was changed so far
+                    self.operation.set(Operation::Flush {
+                        result: error,
+                        value: 0,
+                    });
+                    self.step();
+                }
+            }
+        }
+    }
+
+    fn advance(&self) -> Result<(), ReturnCode> {
+        if let Operation::Flush { result, value } = self.operation.get() {
+            if self.dirty.get() {
+                self.flush()?;
+            } else {
+                self.finish(result, value);
+            }
+            return Ok(());
+        }
+        let volume = match self.volume.get() {
+            Some(volume) => volume,
+            None => match self.mount()? {
+                Some(volume) => volume,
+                None => return Ok(()),
+            },
+        };
+        match self.operation.get() {
+            Operation::Open { create, append } => self.open_step(&volume, create, append),
+            Operation::Create { sector, index } => self.create_step(sector, index),
+            Operation::Read { length, done } => self.read_step(&volume, length, done),
+            Operation::Write { length, done } => self.write_step(&volume, length, done),
+            Operation::Idle | Operation::Flush { .. } => Ok(()),
+        }
+    }
+
+    /// Makes `sector` the cached sector. Returns false if it has to be read
+    /// first, in which case `step` runs again once it is.
+    fn load(&self, sector: u32) -> Result<bool, ReturnCode> {
+        if self.cached.get() == Some(sector) {
+            return Ok(true);
+        }
+        if self.dirty.get() {
+            // The sector is loaded once the cached one is written back
+            self.flush()?;
+        } else {
+            self.buffer.take().map_or(Err(ReturnCode::EBUSY), |buffer| {
+                let (result, buffer) = self.storage.read_blocks(sector as usize, 1, buffer);
+                self.buffer.put(buffer);
+                if result != ReturnCode::SUCCESS {
+                    return Err(result);
+                }
+                self.cached.set(None);
+                self.reading.set(sector);
+                Ok(())
+            })?;
+        }
+        Ok(false)
+    }
+
+    /// Starts writing back the cached sector, into each copy of the FAT if
+    /// it is part of the FAT.
+    fn flush(&self) -> Result<(), ReturnCode> {
+        let sector = match self.cached.get() {
+            Some(sector) => sector,
+            None => return Err(ReturnCode::FAIL),
+        };
+        let sector = match self.volume.get() {
+            Some(ref volume) if sector >= volume.fat_start
+                && sector < volume.fat_start + volume.fat_size =>
+            {
+                sector + self.fat_copy.get() * volume.fat_size
+            }
+            _ => sector,
+        };
+        self.buffer.take().map_or(Err(ReturnCode::EBUSY), |buffer| {
+            let (result, buffer) = self.storage.write_blocks(sector as usize, 1, buffer);
+            self.buffer.put(buffer);
+            if result == ReturnCode::SUCCESS {
+                Ok(())
+            } else {
+                Err(result)
+            }
+        })
+    }
+
+    /// Finds the volume and reads its layout. Returns None while sectors are
+    /// read.
+    fn mount(&self) -> Result<Option<Volume>, ReturnCode> {
+        let start = self.volume_start.get();
+        if !self.load(start.unwrap_or(0))? {
+            return Ok(None);
+        }
+        let buffer = match self.buffer.take() {
+            Some(buffer) => buffer,
+            None => return Err(ReturnCode::EBUSY),
+        };
+        let is_boot_sector = (buffer[0] == 0xEB || buffer[0] == 0xE9)
+            && read_u16(buffer, 11) == SECTOR_SIZE as u32
+            && buffer[13] != 0
+            && buffer[16] != 0;
+        let result = if buffer[510] != 0x55 || buffer[511] != 0xAA {
+            Err(ReturnCode::FAIL)
+        } else if start.is_none() && !is_boot_sector {
+            // The first partition of a partition table
+            if PARTITION_TYPES.contains(&buffer[446 + 4]) {
+                Ok(Some(read_u32(buffer, 446 + 8)))
+            } else {
+                Err(ReturnCode::FAIL)
+            }
+        } else if !is_boot_sector {
+            Err(ReturnCode::FAIL)
+        } else {
+            Self::parse_boot_sector(buffer, start.unwrap_or(0)).map(|volume| {
+                self.volume.set(Some(volume));
+                None
+            })
+        };
+        self.buffer.replace(buffer);
+
+        match result? {
+            Some(partition) => {
+                self.volume_start.set(Some(partition));
+                self.mount()
+            }
+            None => Ok(self.volume.get()),
+        }
+    }
+
+    fn parse_boot_sector(buffer: &[u8], start: u32) -> Result<Volume, ReturnCode> {
+        let sectors_per_cluster = buffer[13] as u32;
+        let reserved_sectors = read_u16(buffer, 14);
+        let fat_count = buffer[16] as u32;
+        let root_entries = read_u16(buffer, 17);
+        let fat_size = match read_u16(buffer, 22) {
+            0 => read_u32(buffer, 36),
+            size => size,
+        };
+        let total_sectors = match read_u16(buffer, 19) {
+            0 => read_u32(buffer, 32),
+            sectors => sectors,
+        };
+
+        let root_sectors = (root_entries * ENTRY_SIZE as u32 + SECTOR_SIZE as u32 - 1)
+            / SECTOR_SIZE as u32;
+        let root_start = reserved_sectors + fat_count * fat_size;
+        let data_start = root_start + root_sectors;
+        if total_sectors <= data_start {
+            return Err(ReturnCode::FAIL);
+        }
+        let cluster_count = (total_sectors - data_start) / sectors_per_cluster;
+        let fat_type = if cluster_count < 4085 {
+            // FAT12
+            return Err(ReturnCode::ENOSUPPORT);
+        } else if cluster_count < 65525 {
+            FatType::Fat16
+        } else {
+            FatType::Fat32
+        };
+
+        Ok(Volume {
+            fat_type: fat_type,
+            sectors_per_cluster: sectors_per_cluster,
+            fat_start: start + reserved_sectors,
+            fat_size: fat_size,
+            fat_count: fat_count,
+            root_start: start + root_start,
+            root_sectors: root_sectors,
+            root_cluster: match fat_type {
+                FatType::Fat16 => 0,
+                FatType::Fat32 => read_u32(buffer, 44),
+            },
+            data_start: start + data_start,
+            cluster_count: cluster_count,
+        })
+    }
+
+    /// The FAT entry of `cluster`. Returns None while its sector is read.
+    fn fat_entry(&self, volume: &Volume, cluster: u32) -> Result<Option<u32>, ReturnCode> {
+        let (sector, offset) = volume.fat_location(cluster);
+        if !self.load(sector)? {
+            return Ok(None);
+        }
+        Ok(self.buffer.map(|buffer| match volume.fat_type {
+            FatType::Fat16 => read_u16(buffer, offset),
+            FatType::Fat32 => read_u32(buffer, offset) & 0x0FFFFFFF,
+        }))
+    }
+
+    /// Sets the FAT entry of `cluster`. Returns false while its sector is
+    /// read.
+    fn set_fat_entry(&self, volume: &Volume, cluster: u32, value: u32) -> Result<bool, ReturnCode> {
+        let (sector, offset) = volume.fat_location(cluster);
+        if !self.load(sector)? {
+            return Ok(false);
+        }
+        self.buffer.map(|buffer| match volume.fat_type {
+            FatType::Fat16 => write_u16(buffer, offset, value),
+            FatType::Fat32 => {
+                // The top four bits are reserved
+                let reserved = read_u32(buffer, offset) & 0xF0000000;
+                write_u32(buffer, offset, reserved | value);
+            }
+        });
+        self.dirty.set(true);
+        Ok(true)
+    }
+
+    /// The next component of the path of the process as a short name, and
+    /// whether it is the last one. The path position moves past it.
+    fn path_component(&self) -> Result<([u8; 11], bool), ReturnCode> {
+        let position = self.path_position.get();
+        self.owner
+            .map_or(Err(ReturnCode::FAIL), |appid| {
+                self.apps
+                    .enter(*appid, |app, _| {
+                        app.path.as_ref().map_or(Err(ReturnCode::EINVAL), |path| {
+                            // The path ends at the end of the buffer or a NUL
+                            let path = path.as_ref();
+                            let length = path.iter().position(|c| *c == 0).unwrap_or(path.len());
+                            let path = path[..length].get(position..).unwrap_or(&[]);
+
+                            let start = path
+                                .iter()
+                                .position(|c| *c != b'/')
+                                .unwrap_or(path.len());
+                            let end = path[start..]
+                                .iter()
+                                .position(|c| *c == b'/')
+                                .map_or(path.len(), |end| start + end);
+                            let last = path[end..].iter().all(|c| *c == b'/');
+                            let name = Self::short_name(&path[start..end])?;
+                            Ok((name, last, position + end))
+                        })
+                    }).unwrap_or_else(|err| Err(err.into()))
+            }).map(|(name, last, position)| {
+                self.path_position.set(position);
+                (name, last)
+            })
+    }
+
+    /// Converts a file name into the padded upper case form of directory
+    /// entries.
+    fn short_name(name: &[u8]) -> Result<[u8; 11], ReturnCode> {
+        let mut short = [b' '; 11];
+        let (base, extension) = match name.iter().rposition(|c| *c == b'.') {
+            Some(dot) => (&name[..dot], &name[dot + 1..]),
+            None => (name, &name[name.len()..]),
+        };
+        if base.is_empty() || base.len() > 8 || extension.len() > 3 {
+            return Err(ReturnCode::EINVAL);
+        }
+        for (i, c) in base.iter().enumerate() {
+            short[i] = *c;
+        }
+        for (i, c) in extension.iter().enumerate() {
+            short[8 + i] = *c;
+        }
+        for c in short.iter_mut() {
+            *c = c.to_ascii_uppercase();
+            let valid = c.is_ascii_alphanumeric() || b" !#$%&'()-@^_`{}~".contains(c);
+            if !valid {
+                return Err(ReturnCode::EINVAL);
+            }
+        }
+        Ok(short)
+    }
+
+    fn cursor_sector(&self, volume: &Volume, cursor: &Cursor) -> u32 {
+        if cursor.cluster == 0 {
+            volume.root_start + cursor.sector
+        } else {
+            volume.cluster_sector(cursor.cluster) + cursor.sector
+        }
+    }
+
+    fn open_step(&self, volume: &Volume, create: bool, append: bool) -> Result<(), ReturnCode> {
+        let root = Cursor::at(volume.root_cluster);
+        let start = self.path_position.get();
+        let (name, last) = self.path_component()?;
+        // Only move past the component once its entry was found
+        self.path_position.set(start);
+
+        loop {
+            let cursor = self.cursor.get().unwrap_or(root);
+
+            // Move on to the next cluster of the directory at the end of one
+            let at_end = if cursor.cluster == 0 {
+                cursor.sector >= volume.root_sectors
+            } else if cursor.sector >= volume.sectors_per_cluster {
+                let next = match self.fat_entry(volume, cursor.cluster)? {
+                    Some(next) => next,
+                    None => return Ok(()),
+                };
+                if !volume.is_end(next) {
+                    self.cursor.set(Some(Cursor::at(next)));
+                    continue;
+                }
+                true
+            } else {
+                false
+            };
+
+            let sector = self.cursor_sector(volume, &cursor);
+            if !at_end && !self.load(sector)? {
+                return Ok(());
+            }
+            let offset = cursor.entry * ENTRY_SIZE;
+            // The first byte of the entry, and its attributes, first cluster
+            // and size if it has the name. Long name entries and the volume
+            // label never have it.
+            let (first, found) = if at_end {
+                (ENTRY_END, None)
+            } else {
+                self.buffer.map_or((ENTRY_END, None), |buffer| {
+                    let attributes = buffer[offset + 11];
+                    let found = if buffer[offset..offset + 11] == name
+                        && attributes & ATTR_VOLUME_ID == 0
+                    {
+                        Some((
+                            attributes,
+                            volume.entry_cluster(buffer, offset),
+                            read_u32(buffer, offset + 28),
+                        ))
+                    } else {
+                        None
+                    };
+                    (buffer[offset], found)
+                })
+            };
+
+            match (first, found) {
+                (ENTRY_END, _) => {
+                    // The name is not in the directory
+                    if !create || !last {
+                        return Err(ReturnCode::FAIL);
+                    }
+                    let free = if at_end {
+                        self.free_entry.get()
+                    } else {
+                        self.free_entry.get().or(Some((sector, cursor.entry)))
+                    };
+                    return match free {
+                        Some((sector, index)) => {
+                            self.operation.set(Operation::Create {
+                                sector: sector,
+                                index: index,
+                            });
+                            self.create_step(sector, index)
+                        }
+                        None => Err(ReturnCode::ENOMEM),
+                    };
+                }
+                (_, Some((attributes, cluster, size))) => {
+                    self.path_component()?;
+                    if !last {
+                        if attributes & ATTR_DIRECTORY == 0 {
+                            return Err(ReturnCode::EINVAL);
+                        }
+                        // Entries of directories in the root directory point
+                        // at cluster 0 for the root directory
+                        self.cursor.set(Some(if cluster == 0 {
+                            root
+                        } else {
+                            Cursor::at(cluster)
+                        }));
+                        self.free_entry.set(None);
+                        return self.open_step(volume, create, append);
+                    }
+                    if attributes & ATTR_DIRECTORY != 0 {
+                        return Err(ReturnCode::EINVAL);
+                    }
+                    let mut file = File::new(sector, cursor.entry, cluster, size);
+                    if append {
+                        file.position = size;
+                    }
+                    self.file.set(Some(file));
+                    self.finish(ReturnCode::SUCCESS, size as usize);
+                    return Ok(());
+                }
+                (ENTRY_DELETED, None) => {
+                    if self.free_entry.get().is_none() {
+                        self.free_entry.set(Some((sector, cursor.entry)));
+                    }
+                }
+                _ => {}
+            }
+            self.cursor.set(Some(cursor.next()));
+        }
+    }
+
+    fn create_step(&self, sector: u32, index: usize) -> Result<(), ReturnCode> {
+        let start = self.path_position.get();
+        let (name, _) = self.path_component()?;
+        self.path_position.set(start);
+        if !self.load(sector)? {
+            return Ok(());
+        }
+        self.buffer.map(|buffer| {
+            let entry = &mut buffer[index * ENTRY_SIZE..(index + 1) * ENTRY_SIZE];
+            for byte in entry.iter_mut() {
+                *byte = 0;
+            }
+            entry[..11].copy_from_slice(&name);
+            entry[11] = ATTR_ARCHIVE;
+        });
+        self.dirty.set(true);
+        self.file.set(Some(File::new(sector, index, 0, 0)));
+        self.operation.set(Operation::Flush {
+            result: ReturnCode::SUCCESS,
+            value: 0,
+        });
+        self.advance()
+    }
+
+    /// Moves `file` to the cluster that holds its position. Returns None
+    /// while a FAT sector is read, and false if the file has no such
+    /// cluster, in which case it is left at its last cluster.
+    fn seek_cluster(&self, volume: &Volume, file: &mut File) -> Result<Option<bool>, ReturnCode> {
+        let index = file.position / volume.cluster_bytes();
+        if file.cluster == 0 {
+            if file.first_cluster == 0 {
+                return Ok(Some(false));
+            }
+            file.cluster = file.first_cluster;
+            file.cluster_index = 0;
+        }
+        while file.cluster_index < index {
+            let next = match self.fat_entry(volume, file.cluster)? {
+                Some(next) => next,
+                None => return Ok(None),
+            };
+            if volume.is_end(next) {
+                return Ok(Some(false));
+            }
+            file.cluster = next;
+            file.cluster_index += 1;
+        }
+        Ok(Some(true))
+    }
+
+    fn read_step(&self, volume: &Volume, length: usize, done: usize) -> Result<(), ReturnCode> {
+        let mut file = self.file.get().ok_or(ReturnCode::FAIL)?;
+        let mut done = done;
+        let result = loop {
+            let left = file.size.saturating_sub(file.position) as usize;
+            let remaining = cmp::min(length - done, left);
+            if remaining == 0 {
+                break Ok(true);
+            }
+            match self.seek_cluster(volume, &mut file) {
+                Ok(Some(true)) => {}
+                // The chain is shorter than the file
+                Ok(Some(false)) => break Err(ReturnCode::FAIL),
+                Ok(None) => break Ok(false),
+                Err(error) => break Err(error),
+            }
+
+            let offset = file.position % volume.cluster_bytes();
+            let sector = volume.cluster_sector(file.cluster) + offset / SECTOR_SIZE as u32;
+            match self.load(sector) {
+                Ok(true) => {}
+                other => break other,
+            }
+            let offset = offset as usize % SECTOR_SIZE;
+            let count = cmp::min(SECTOR_SIZE - offset, remaining);
+            self.buffer.map(|buffer| {
+                self.owner.map(|appid| {
+                    let _ = self.apps.enter(*appid, |app, _| {
+                        app.read_buffer.as_mut().map(|data| {
+                            data.as_mut().get_mut(done..done + count).map(|data| {
+                                data.copy_from_slice(&buffer[offset..offset + count]);
+                            });
+                        });
+                    });
+                });
+            });
+            file.position += count as u32;
+            done += count;
+        };
+
+        // Keep the progress while a sector is read
+        self.file.set(Some(file));
+        self.operation.set(Operation::Read {
+            length: length,
+            done: done,
+        });
+        if result? {
+            self.finish(ReturnCode::SUCCESS, done);
+        }
+        Ok(())
+    }
+
+    /// Allocates a cluster and appends it to `file`. Returns false while a
+    /// FAT sector is read.
+    ///
+    /// The capsule never frees clusters, so after looking from the start of
+    /// the FAT once, it looks for a free cluster after the last one allocated.
+    fn extend(&self, volume: &Volume, file: &mut File) -> Result<bool, ReturnCode> {
+        if self.allocated.get() == 0 {
+            let mut cluster = self.free_cluster.get();
+            loop {
+                if cluster >= volume.cluster_count + 2 {
+                    // The volume is full
+                    return Err(ReturnCode::ENOMEM);
+                }
+                match self.fat_entry(volume, cluster)? {
+                    Some(0) => break,
+                    Some(_) => cluster += 1,
+                    None => {
+                        self.free_cluster.set(cluster);
+                        return Ok(false);
+                    }
+                }
+            }
+            // The sector of the entry is cached
+            self.set_fat_entry(volume, cluster, volume.end_marker())?;
+            self.allocated.set(cluster);
+            self.free_cluster.set(cluster + 1);
+        }
+
+        let cluster = self.allocated.get();
+        if file.cluster == 0 {
+            file.first_cluster = cluster;
+            file.cluster_index = 0;
+        } else {
+            if !self.set_fat_entry(volume, file.cluster, cluster)? {
+                return Ok(false);
+            }
+            file.cluster_index += 1;
+        }
+        file.cluster = cluster;
+        self.allocated.set(0);
+        Ok(true)
+    }
+
+    fn write_step(&self, volume: &Volume, length: usize, done: usize) -> Result<(), ReturnCode> {
+        let mut file = self.file.get().ok_or(ReturnCode::FAIL)?;
+        let mut done = done;
+        let result = loop {
+            if done == length {
+                break self.update_entry(volume, &file);
+            }
+            let index = file.position / volume.cluster_bytes();
+            let found = match self.seek_cluster(volume, &mut file) {
+                Ok(Some(true)) => Ok(true),
+                // Past the last cluster of the file
+                Ok(Some(false)) => self.extend(volume, &mut file),
+                Ok(None) => Ok(false),
+                Err(error) => Err(error),
+            };
+            match found {
+                Ok(true) if file.cluster_index == index => {}
+                Ok(true) => continue,
+                other => break other,
+            }
+
+            let offset = file.position % volume.cluster_bytes();
+            let sector = volume.cluster_sector(file.cluster) + offset / SECTOR_SIZE as u32;
+            match self.load(sector) {
+                Ok(true) => {}
+                other => break other,
+            }
+            let offset = offset as usize % SECTOR_SIZE;
+            let count = cmp::min(SECTOR_SIZE - offset, length - done);
+            self.buffer.map(|buffer| {
+                self.owner.map(|appid| {
+                    let _ = self.apps.enter(*appid, |app, _| {
+                        app.write_buffer.as_ref().map(|data| {
+                            data.as_ref().get(done..done + count).map(|data| {
+                                buffer[offset..offset + count].copy_from_slice(data);
+                            });
+                        });
+                    });
+                });
+            });
+            self.dirty.set(true);
+            file.position += count as u32;
+            file.size = cmp::max(file.size, file.position);
+            done += count;
+        };
+
+        self.file.set(Some(file));
+        self.operation.set(Operation::Write {
+            length: length,
+            done: done,
+        });
+        if result? {
+            self.operation.set(Operation::Flush {
+                result: ReturnCode::SUCCESS,
+                value: done,
+            });
+            return self.advance();
+        }
+        Ok(())
+    }
+
+    /// Writes the first cluster and size of `file` into its directory entry.
+    /// Returns false while the sector of the entry is read.
+    fn update_entry(&self, volume: &Volume, file: &File) -> Result<bool, ReturnCode> {
+        if !self.load(file.entry_sector)? {
+            return Ok(false);
+        }
+        self.buffer.map(|buffer| {
+            let offset = file.entry_index * ENTRY_SIZE;
+            if volume.fat_type == FatType::Fat32 {
+                write_u16(buffer, offset + 20, file.first_cluster >> 16);
+            }
+            write_u16(buffer, offset + 26, file.first_cluster);
+            write_u32(buffer, offset + 28, file.size);
+        });
+        self.dirty.set(true);
+        Ok(true)
+    }
+
+    fn app_file(&self, appid: AppId) -> Result<File, ReturnCode> {
+        self.apps
+            .enter(appid, |app, _| app.file.ok_or(ReturnCode::EINVAL))
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    /// Whether the shared buffer `allow_num` holds at least `length` bytes.
+    fn buffer_fits(&self, appid: AppId, allow_num: usize, length: usize) -> bool {
+        self.apps
+            .enter(appid, |app, _| {
+                let buffer = match allow_num {
+                    1 => app.read_buffer.as_ref(),
+                    _ => app.write_buffer.as_ref(),
+                };
+                buffer.map_or(false, |buffer| buffer.len() >= length)
+            }).unwrap_or(false)
+    }
+}
+
+impl Driver for Fat<'a> {
+    /// Share buffers with the filesystem.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: The path of the file to open, ending at the end of the buffer
+    ///        or at a NUL byte.
+    /// - `1`: The buffer that files are read into.
+    /// - `2`: The buffer that files are written from.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 | 1 | 2 => self
+                .apps
+                .enter(appid, |app, _| {
+                    match allow_num {
+                        0 => app.path = slice,
+                        1 => app.read_buffer = slice,
+                        _ => app.write_buffer = slice,
+                    }
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Subscribe to the end of operations.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: An open, read or write ended. The callback is passed the
+    ///        command number of the operation, its result, and the size of
+    ///        the opened file or the number of bytes read or written.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        appid: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Open, read, write and close files.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Open the file at the shared path. `data1` is a set of flags:
+    ///        bit 0 creates the file if it does not exist, and bit 1 starts
+    ///        at the end of the file instead of its start. Returns EALREADY
+    ///        if the process has a file open.
+    /// - `2`: Read up to `data1` bytes from the open file into the read
+    ///        buffer, from the current position.
+    /// - `3`: Write `data1` bytes from the write buffer into the open file,
+    ///        at the current position.
+    /// - `4`: Close the open file.
+    /// - `5`: Get the size of the open file.
+    fn command(&self, command_num: usize, data1: usize, _: usize, appid: AppId) -> ReturnCode {
+        let busy = self.owner.map_or(false, |owner| owner.idx() == appid.idx());
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            COMMAND_OPEN => {
+                if self.app_file(appid).is_ok() {
+                    return ReturnCode::EALREADY;
+                }
+                let operation = Operation::Open {
+                    create: data1 & OPEN_CREATE != 0,
+                    append: data1 & OPEN_APPEND != 0,
+                };
+                self.start(appid, COMMAND_OPEN, operation, None)
+            }
+
+            COMMAND_READ | COMMAND_WRITE => {
+                let file = match self.app_file(appid) {
+                    Ok(file) => file,
+                    Err(err) => return err,
+                };
+                if !self.buffer_fits(appid, command_num - 1, data1) {
+                    return ReturnCode::EINVAL;
+                }
+                let operation = if command_num == COMMAND_READ {
+                    Operation::Read {
+                        length: data1,
+                        done: 0,
+                    }
+                } else {
+                    Operation::Write {
+                        length: data1,
+                        done: 0,
+                    }
+                };
+                self.start(appid, command_num, operation, Some(file))
+            }
+
+            4 => {
+                if busy {
+                    return ReturnCode::EBUSY;
+                }
+                self.apps
+                    .enter(appid, |app, _| match app.file.take() {
+                        Some(_) => ReturnCode::SUCCESS,
+                        None => ReturnCode::EINVAL,
+                    }).unwrap_or_else(|err| err.into())
+            }
+
+            5 => match self.app_file(appid) {
+                Ok(file) => ReturnCode::SuccessWithValue {
+                    value: file.size as usize,
+                },
+                Err(err) => err,
+            },
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+impl block_storage::Client for Fat<'a> {
+    fn read_done(&self, buffer: &'static mut [u8], result: ReturnCode) {
+        self.buffer.replace(buffer);
+        if result != ReturnCode::SUCCESS {
+            self.finish(result, 0);
+            return;
+        }
+        self.cached.set(Some(self.reading.get()));
+        self.step();
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], result: ReturnCode) {
+        self.buffer.replace(buffer);
+        if result != ReturnCode::SUCCESS {
+            self.fat_copy.set(0);
+            self.finish(result, 0);
+            return;
+        }
+        let is_fat = match (self.volume.get(), self.cached.get()) {
+            (Some(volume), Some(sector)) => {
+                sector >= volume.fat_start && sector < volume.fat_start + volume.fat_size
+            }
+            _ => false,
+        };
+        let copies = self.volume.get().map_or(1, |volume| volume.fat_count);
+        if is_fat && self.fat_copy.get() + 1 < copies {
+            self.fat_copy.set(self.fat_copy.get() + 1);
+            if let Err(error) = self.flush() {
+                self.fat_copy.set(0);
+                self.finish(error, 0);
+            }
+            return;
+        }
+        self.fat_copy.set(0);
+        self.dirty.set(false);
+        self.step();
+    }
+
+    fn erase_done(&self, _result: ReturnCode) {}
+}
@@ -0,0 +1,837 @@
+//! Firmware updates into A/B image slots.
+//!
+//! This capsule receives a new kernel image, or a new image of the apps,
+//! into a staging area in flash, usually external flash, checks that it is
+//! signed, and sets a flag for the bootloader to swap it with the running
+//! image. Images are passed in by a process, so an update can arrive over
+//! any transport a process can use, such as BLE, a UART or USB.
+//!
+//! A process begins an update, writes the image in parts, and finishes it
+//! with the signature of the image. The capsule then reads the staged image
+//! back, computes its SHA-256 digest with a `hil::digest::Digest`, and
+//! checks the ECDSA P-256 signature against the public key of the board with
+//! a `hil::public_key_crypto::signature::SignatureVerify`. Only one process
+//! can update at a time.
+//!
+//! The staging area is the B slot: the bootloader swaps its contents with
+//! the slot the image is for, so that the previous image stays in the
+//! staging area. The flag page, read and written as one flash page, holds
+//! little-endian words:
+//!
+//! | Offset | Contents                                   |
+//! |--------|--------------------------------------------|
+//! | 0      | The magic number 0x50554654                |
+//! | 4      | The state of the update, a `FLAG_` value   |
+//! | 8      | The kind of image, an `IMAGE_` value       |
+//! | 12     | The length of the image in bytes           |
+//! | 16     | The CRC-32 of the bytes 0 to 15            |
+//!
+//! The bootloader swaps the slots when it finds `FLAG_PENDING`, sets
+//! `FLAG_TESTING` and boots the new image. Once the new image works, the
+//! kernel or a process confirms it, which sets `FLAG_CONFIRMED`. If the
+//! bootloader finds `FLAG_TESTING` at boot, the new image failed before it
+//! was confirmed, for example by a panic or a watchdog reset, so the
+//! bootloader swaps the slots back and sets `FLAG_ROLLED_BACK`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! pub static mut UPDATE_PAGE: capsules::qspi_flash::QspiFlashSector =
+//!     capsules::qspi_flash::QspiFlashSector::new();
+//! pub static UPDATE_KEY: [u8; 64] = [...];
+//!
+//! let sha256 = static_init!(
+//!     capsules::sha256::Sha256Software<'static>,
+//!     capsules::sha256::Sha256Software::new()
+//! );
+//! sha256.initialize();
+//! let ecdsa = static_init!(
+//!     capsules::signature::SoftwareSignatureVerify<'static>,
+//!     capsules::signature::SoftwareSignatureVerify::new()
+//! );
+//! ecdsa.initialize();
+//! let firmware_update = static_init!(
+//!     capsules::firmware_update::FirmwareUpdate<
+//!         'static,
+//!         QspiFlash,
+//!         capsules::sha256::Sha256Software<'static>,
+//!         capsules::signature::SoftwareSignatureVerify<'static>,
+//!     >,
+//!     capsules::firmware_update::FirmwareUpdate::new(
+//!         qspi_flash,
+//!         0,   // The first page of the staging area
+//!         128, // The number of pages of the staging area
+//!         128, // The flag page
+//!         &mut UPDATE_PAGE,
+//!         sha256,
+//!         ecdsa,
+//!         &UPDATE_KEY,
+//!         &mut capsules::firmware_update::BUFFER,
+//!         &mut capsules::firmware_update::HASH,
+//!         &mut capsules::firmware_update::SIGNATURE,
+//!         board_kernel.create_grant(&memory_allocation_capability)
+//!     )
+//! );
+//! hil::flash::HasClient::set_client(qspi_flash, firmware_update);
+//! hil::digest::Digest::set_client(sha256, firmware_update);
+//! hil::public_key_crypto::signature::SignatureVerify::set_client(ecdsa, firmware_update);
+//! firmware_update.mount();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil;
+use kernel::hil::digest::Digest;
+use kernel::hil::public_key_crypto::signature::{SignatureAlgorithm, SignatureVerify};
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+use log_storage::crc32;
+
+/// Syscall number
+pub const DRIVER_NUM: usize = 0x50008;
+
+/// The length of the digest of an image
+pub const HASH_LEN: usize = 32;
+/// The length of the signature of an image
+pub const SIGNATURE_LEN: usize = 64;
+
+pub static mut BUFFER: [u8; 256] = [0; 256];
+pub static mut HASH: [u8; HASH_LEN] = [0; HASH_LEN];
+pub static mut SIGNATURE: [u8; SIGNATURE_LEN] = [0; SIGNATURE_LEN];
+
+/// Identifies the flag page
+const MAGIC: u32 = 0x5055_4654;
+
+/// The flag page holds no update
+pub const FLAG_NONE: usize = 0;
+/// A signed image waits in the staging area to be swapped in
+pub const FLAG_PENDING: usize = 1;
+/// The bootloader swapped in the new image, which was not yet confirmed
+pub const FLAG_TESTING: usize = 2;
+/// The new image was confirmed to work
+pub const FLAG_CONFIRMED: usize = 3;
+/// The new image failed, and the bootloader swapped the old one back in
+pub const FLAG_ROLLED_BACK: usize = 4;
+
+/// An image of the kernel
+pub const IMAGE_KERNEL: usize = 0;
+/// An image of the apps
+pub const IMAGE_APPS: usize = 1;
+
+// Commands, which are also passed to the callback
+const COMMAND_WRITE: usize = 2;
+const COMMAND_FINISH: usize = 3;
+const COMMAND_CONFIRM: usize = 5;
+
+fn read_u32(buffer: &[u8], offset: usize) -> u32 {
+    buffer[offset] as u32
+        | (buffer[offset + 1] as u32) << 8
+        | (buffer[offset + 2] as u32) << 16
+        | (buffer[offset + 3] as u32) << 24
+}
+
+fn write_u32(buffer: &mut [u8], offset: usize, value: u32) {
+    for i in 0..4 {
+        buffer[offset + i] = (value >> (8 * i)) as u8;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Unmounted,
+    /// Reading the flag page
+    Mount,
+    Idle,
+    /// Writing a full page of the image to the staging area
+    WritePage,
+    /// Writing the last page of the image, before checking it
+    FlushPage,
+    /// Reading a page of the staged image
+    ReadStaged { page: usize },
+    /// Adding `length` bytes at `offset` of a staged page to the digest
+    AddData {
+        page: usize,
+        offset: usize,
+        length: usize,
+    },
+    Run,
+    Verify,
+    /// Writing the flag page
+    WriteFlag {
+        flag: usize,
+        kind: usize,
+        length: usize,
+    },
+    /// Calling back from a deferred call
+    Done { result: ReturnCode },
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    data: Option<AppSlice<Shared, u8>>,
+    signature: Option<AppSlice<Shared, u8>>,
+}
+
+pub struct FirmwareUpdate<
+    'a,
+    F: hil::flash::Flash + 'static,
+    D: Digest + 'a,
+    V: SignatureVerify + 'a,
+> {
+    flash: &'a F,
+    /// The pages of the staging area, and the flag page
+    staging_first_page: usize,
+    staging_page_count: usize,
+    flag_page: usize,
+    page: TakeCell<'static, F::Page>,
+    page_size: usize,
+    digest: &'a D,
+    verifier: &'a V,
+    /// The public key images are signed with
+    key: &'static [u8],
+    buffer: TakeCell<'static, [u8]>,
+    hash: TakeCell<'static, [u8]>,
+    signature: TakeCell<'static, [u8]>,
+    state: Cell<State>,
+    /// The kind and length of the image received
+    kind: Cell<usize>,
+    length: Cell<usize>,
+    /// The part of the data buffer of the owner not yet copied
+    pending_offset: Cell<usize>,
+    pending_length: Cell<usize>,
+    /// The contents of the flag page
+    flag: Cell<usize>,
+    flag_kind: Cell<usize>,
+    flag_length: Cell<usize>,
+    apps: Grant<App>,
+    /// The process updating or confirming, and its command in progress
+    owner: OptionalCell<AppId>,
+    command: Cell<usize>,
+    deferred_call: DeferredCall,
+}
+
+impl<F: hil::flash::Flash, D: Digest, V: SignatureVerify> FirmwareUpdate<'a, F, D, V> {
+    pub fn new(
+        flash: &'a F,
+        staging_first_page: usize,
+        staging_page_count: usize,
+        flag_page: usize,
+        page: &'static mut F::Page,
+        digest: &'a D,
+        verifier: &'a V,
+        key: &'static [u8],
+        buffer: &'static mut [u8],
+        hash: &'static mut [u8],
+        signature: &'static mut [u8],
+        apps: Grant<App>,
+    ) -> FirmwareUpdate<'a, F, D, V> {
+        let page_size = page.as_mut().len();
+        FirmwareUpdate {
+            flash: flash,
+            staging_first_page: staging_first_page,
+            staging_page_count: staging_page_count,
+            flag_page: flag_page,
+            page: TakeCell::new(page),
+            page_size: page_size,
+            digest: digest,
+            verifier: verifier,
+            key: key,
+            buffer: TakeCell::new(buffer),
+            hash: TakeCell::new(hash),
+            signature: TakeCell::new(signature),
+            state: Cell::new(State::Unmounted),
+            kind: Cell::new(IMAGE_KERNEL),
+            length: Cell::new(0),
+            pending_offset: Cell::new(0),
+            pending_length: Cell::new(0),
+            flag: Cell::new(FLAG_NONE),
+            flag_kind: Cell::new(IMAGE_KERNEL),
+            flag_length: Cell::new(0),
+            apps: apps,
+            owner: OptionalCell::empty(),
+            command: Cell::new(0),
+            deferred_call: DeferredCall::new(),
+        }
+    }
+
+    /// The state of the last update, a `FLAG_` value.
+    pub fn flag(&self) -> usize {
+        self.flag.get()
+    }
+
+    /// Confirm that the image swapped in by the bootloader works, so that
+    /// the bootloader keeps it. Returns EALREADY if there is no image to
+    /// confirm.
+    pub fn confirm(&self) -> ReturnCode {
+        if self.state.get() != State::Idle || self.owner.is_some() {
+            return ReturnCode::EBUSY;
+        }
+        if self.flag.get() != FLAG_TESTING {
+            return ReturnCode::EALREADY;
+        }
+        self.write_flag(
+            FLAG_CONFIRMED,
+            self.flag_kind.get(),
+            self.flag_length.get(),
+        )
+    }
+
+    fn staging_size(&self) -> usize {
+        self.staging_page_count * self.page_size
+    }
+
+    fn write_flag(&self, flag: usize, kind: usize, length: usize) -> ReturnCode {
+        self.page.take().map_or(ReturnCode::EBUSY, |page| {
+            {
+                let contents = page.as_mut();
+                for byte in contents.iter_mut() {
+                    *byte = 0xFF;
+                }
+                write_u32(contents, 0, MAGIC);
+                write_u32(contents, 4, flag as u32);
+                write_u32(contents, 8, kind as u32);
+                write_u32(contents, 12, length as u32);
+                let crc = crc32(&contents[0..16]);
+                write_u32(contents, 16, crc);
+            }
+            let result = self.flash.write_page(self.flag_page, page);
+            if result == ReturnCode::SUCCESS {
+                self.state.set(State::WriteFlag {
+                    flag: flag,
+                    kind: kind,
+                    length: length,
+                });
+            }
+            result
+        })
+    }
+
+    /// Copies the rest of the data of the owner into the page buffer,
+    /// writing the page to the staging area each time it is full.
+    fn copy_data(&self) -> ReturnCode {
+        let page_offset = self.length.get() % self.page_size;
+        let length = cmp::min(self.pending_length.get(), self.page_size - page_offset);
+        let offset = self.pending_offset.get();
+        let copied = self.owner.map_or(false, |appid| {
+            self.apps
+                .enter(*appid, |app, _| {
+                    app.data.as_ref().map_or(false, |data| {
+                        data.as_ref()
+                            .get(offset..offset + length)
+                            .map_or(false, |data| {
+                                self.page.map_or(false, |page| {
+                                    page.as_mut()[page_offset..page_offset + length]
+                                        .copy_from_slice(data);
+                                    true
+                                })
+                            })
+                    })
+                }).unwrap_or(false)
+        });
+        if !copied {
+            return ReturnCode::EINVAL;
+        }
+        self.length.set(self.length.get() + length);
+        self.pending_offset.set(offset + length);
+        self.pending_length.set(self.pending_length.get() - length);
+        if page_offset + length == self.page_size {
+            self.write_staged_page(State::WritePage)
+        } else {
+            self.state.set(State::Done {
+                result: ReturnCode::SUCCESS,
+            });
+            self.deferred_call.set();
+            ReturnCode::SUCCESS
+        }
+    }
+
+    /// Writes the page buffer to the page of the staging area that holds
+    /// the last byte received.
+    fn write_staged_page(&self, state: State) -> ReturnCode {
+        let index = (self.length.get() - 1) / self.page_size;
+        self.page.take().map_or(ReturnCode::EBUSY, |page| {
+            let result = self
+                .flash
+                .write_page(self.staging_first_page + index, page);
+            if result == ReturnCode::SUCCESS {
+                self.state.set(state);
+            }
+            result
+        })
+    }
+
+    /// Writes the rest of the image, and then checks its signature.
+    fn finish_image(&self) -> ReturnCode {
+        let page_offset = self.length.get() % self.page_size;
+        if page_offset == 0 {
+            return self.start_hash();
+        }
+        self.page.map(|page| {
+            for byte in page.as_mut()[page_offset..].iter_mut() {
+                *byte = 0xFF;
+            }
+        });
+        self.write_staged_page(State::FlushPage)
+    }
+
+    fn start_hash(&self) -> ReturnCode {
+        self.digest.clear_data();
+        self.read_staged(0)
+    }
+
+    fn read_staged(&self, index: usize) -> ReturnCode {
+        self.page.take().map_or(ReturnCode::EBUSY, |page| {
+            let result = self
+                .flash
+                .read_page(self.staging_first_page + index, page);
+            if result == ReturnCode::SUCCESS {
+                self.state.set(State::ReadStaged { page: index });
+            }
+            result
+        })
+    }
+
+    /// Adds the next part of the staged page `index`, starting at `offset`,
+    /// to the digest.
+    fn add_data(&self, index: usize, offset: usize) -> ReturnCode {
+        let start = index * self.page_size + offset;
+        self.buffer.take().map_or(ReturnCode::EBUSY, |buffer| {
+            let length = cmp::min(
+                buffer.len(),
+                cmp::min(self.page_size - offset, self.length.get() - start),
+            );
+            self.page.map(|page| {
+                buffer[..length].copy_from_slice(&page.as_mut()[offset..offset + length]);
+            });
+            let (result, buffer) = self.digest.add_data(buffer, length);
+            buffer.map(|buffer| self.buffer.replace(buffer));
+            if result == ReturnCode::SUCCESS {
+                self.state.set(State::AddData {
+                    page: index,
+                    offset: offset,
+                    length: length,
+                });
+            }
+            result
+        })
+    }
+
+    fn run(&self) -> ReturnCode {
+        self.hash.take().map_or(ReturnCode::EBUSY, |hash| {
+            let (result, hash) = self.digest.run(hash);
+            hash.map(|hash| self.hash.replace(hash));
+            if result == ReturnCode::SUCCESS {
+                self.state.set(State::Run);
+            }
+            result
+        })
+    }
+
+    /// Ends the command in progress and calls back the process. An update
+    /// ends with its finish, or with any error.
+    fn finish(&self, result: ReturnCode) {
+        self.state.set(State::Idle);
+        let command = self.command.get();
+        self.owner.map(|appid| {
+            let _ = self.apps.enter(*appid, |app, _| {
+                app.callback
+                    .map(|mut cb| cb.schedule(command, usize::from(result), 0));
+            });
+        });
+        if command != COMMAND_WRITE || result != ReturnCode::SUCCESS {
+            self.owner.clear();
+        }
+    }
+
+    /// Continues with `result` of the next step of the command in progress,
+    /// ending it if the step could not start.
+    fn continue_with(&self, result: ReturnCode) {
+        if result != ReturnCode::SUCCESS {
+            self.finish(ReturnCode::FAIL);
+        }
+    }
+
+    /// Whether `appid` updates. An update of a process that no longer
+    /// exists is dropped.
+    fn owned_by(&self, appid: AppId) -> Result<bool, ReturnCode> {
+        let owner = self.owner.map_or(None, |owner| Some(*owner));
+        match owner {
+            Some(owner) if owner.idx() == appid.idx() => Ok(true),
+            Some(owner) => {
+                if self.apps.enter(owner, |_, _| ()).is_ok() {
+                    Err(ReturnCode::EBUSY)
+                } else {
+                    self.owner.clear();
+                    Ok(false)
+                }
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+impl<F: hil::flash::Flash, D: Digest, V: SignatureVerify> FirmwareUpdate<'static, F, D, V> {
+    /// Reads the flag page. Updates can begin once it was read.
+    pub fn mount(&'static self) -> ReturnCode {
+        if self.state.get() != State::Unmounted {
+            return ReturnCode::EALREADY;
+        }
+        let result = self.deferred_call.register(self);
+        if result != ReturnCode::SUCCESS && result != ReturnCode::EALREADY {
+            return result;
+        }
+        self.page.take().map_or(ReturnCode::EBUSY, |page| {
+            let result = self.flash.read_page(self.flag_page, page);
+            if result == ReturnCode::SUCCESS {
+                self.state.set(State::Mount);
+            }
+            result
+        })
+    }
+}
+
+impl<F: hil::flash::Flash, D: Digest, V: SignatureVerify> Driver
+    for FirmwareUpdate<'a, F, D, V>
+{
+    /// Share buffers with the updater.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: The data of the image to write.
+    /// - `1`: The signature of the image, 64 bytes.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 | 1 => self
+                .apps
+                .enter(appid, |app, _| {
+                    if allow_num == 0 {
+                        app.data = slice;
+                    } else {
+                        app.signature = slice;
+                    }
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Subscribe to the end of commands.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: A write, finish or confirm ended. The callback is passed the
+    ///        command number and its result.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        appid: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Receive and install images.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Begin an update with an image of kind `data1`, 0 for the
+    ///        kernel and 1 for the apps, dropping any image the process
+    ///        began before.
+    /// - `2`: Append the first `data1` bytes of the data buffer to the
+    ///        image.
+    /// - `3`: Finish the update, checking the image against the signature
+    ///        in the signature buffer. The callback is passed EINVAL if the
+    ///        signature is not valid.
+    /// - `4`: Abort the update.
+    /// - `5`: Confirm that the image swapped in by the bootloader works.
+    /// - `6`: Get the state of the last update, a `FLAG_` value.
+    /// - `7`: Get the size of the staging area, the longest image.
+    fn command(&self, command_num: usize, data1: usize, _: usize, appid: AppId) -> ReturnCode {
+        if command_num == 0 {
+            return ReturnCode::SUCCESS;
+        }
+        match self.state.get() {
+            State::Unmounted | State::Mount => return ReturnCode::EOFF,
+            State::Idle => {}
+            _ => return ReturnCode::EBUSY,
+        }
+        let owner = match self.owned_by(appid) {
+            Ok(owner) => owner,
+            Err(err) => return err,
+        };
+        match command_num {
+            1 => {
+                if data1 != IMAGE_KERNEL && data1 != IMAGE_APPS {
+                    return ReturnCode::EINVAL;
+                }
+                self.owner.set(appid);
+                self.kind.set(data1);
+                self.length.set(0);
+                ReturnCode::SUCCESS
+            }
+
+            COMMAND_WRITE => {
+                if !owner {
+                    return ReturnCode::ERESERVE;
+                }
+                if data1 == 0 {
+                    return ReturnCode::EINVAL;
+                }
+                if self.length.get() + data1 > self.staging_size() {
+                    return ReturnCode::ESIZE;
+                }
+                self.command.set(COMMAND_WRITE);
+                self.pending_offset.set(0);
+                self.pending_length.set(data1);
+                let result = self.copy_data();
+                if result != ReturnCode::SUCCESS {
+                    self.owner.clear();
+                }
+                result
+            }
+
+            COMMAND_FINISH => {
+                if !owner {
+                    return ReturnCode::ERESERVE;
+                }
+                if self.length.get() == 0 {
+                    return ReturnCode::EINVAL;
+                }
+                let copied = self
+                    .apps
+                    .enter(appid, |app, _| {
+                        app.signature.as_ref().map_or(false, |data| {
+                            data.as_ref().get(..SIGNATURE_LEN).map_or(false, |data| {
+                                self.signature.map_or(false, |signature| {
+                                    signature[..SIGNATURE_LEN].copy_from_slice(data);
+                                    true
+                                })
+                            })
+                        })
+                    }).unwrap_or(false);
+                if !copied {
+                    return ReturnCode::EINVAL;
+                }
+                self.command.set(COMMAND_FINISH);
+                let result = self.finish_image();
+                if result != ReturnCode::SUCCESS {
+                    self.owner.clear();
+                }
+                result
+            }
+
+            4 => {
+                if !owner {
+                    return ReturnCode::ERESERVE;
+                }
+                self.owner.clear();
+                ReturnCode::SUCCESS
+            }
+
+            COMMAND_CONFIRM => {
+                let result = self.confirm();
+                if result == ReturnCode::SUCCESS {
+                    self.owner.set(appid);
+                    self.command.set(COMMAND_CONFIRM);
+                }
+                result
+            }
+
+            6 => ReturnCode::SuccessWithValue {
+                value: self.flag.get(),
+            },
+
+            7 => ReturnCode::SuccessWithValue {
+                value: self.staging_size(),
+            },
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+impl<F: hil::flash::Flash, D: Digest, V: SignatureVerify> hil::flash::Client<F>
+    for FirmwareUpdate<'a, F, D, V>
+{
+    fn read_complete(&self, page: &'static mut F::Page, error: hil::flash::Error) {
+        match self.state.get() {
+            State::Mount => {
+                {
+                    let contents = page.as_mut();
+                    let valid = error == hil::flash::Error::CommandComplete
+                        && read_u32(contents, 0) == MAGIC
+                        && crc32(&contents[0..16]) == read_u32(contents, 16);
+                    if valid {
+                        self.flag.set(read_u32(contents, 4) as usize);
+                        self.flag_kind.set(read_u32(contents, 8) as usize);
+                        self.flag_length.set(read_u32(contents, 12) as usize);
+                    }
+                }
+                self.page.replace(page);
+                self.state.set(State::Idle);
+            }
+            State::ReadStaged { page: index } => {
+                self.page.replace(page);
+                if error != hil::flash::Error::CommandComplete {
+                    self.finish(ReturnCode::FAIL);
+                } else {
+                    self.continue_with(self.add_data(index, 0));
+                }
+            }
+            _ => {
+                self.page.replace(page);
+            }
+        }
+    }
+
+    fn write_complete(&self, page: &'static mut F::Page, error: hil::flash::Error) {
+        self.page.replace(page);
+        let state = self.state.get();
+        if error != hil::flash::Error::CommandComplete {
+            match state {
+                State::WritePage | State::FlushPage | State::WriteFlag { .. } => {
+                    self.finish(ReturnCode::FAIL)
+                }
+                _ => {}
+            }
+            return;
+        }
+        match state {
+            State::WritePage => {
+                let result = self.copy_data();
+                if result != ReturnCode::SUCCESS {
+                    self.finish(result);
+                }
+            }
+            State::FlushPage => self.continue_with(self.start_hash()),
+            State::WriteFlag { flag, kind, length } => {
+                self.flag.set(flag);
+                self.flag_kind.set(kind);
+                self.flag_length.set(length);
+                self.finish(ReturnCode::SUCCESS);
+            }
+            _ => {}
+        }
+    }
+
+    fn erase_complete(&self, _error: hil::flash::Error) {}
+}
+
+impl<F: hil::flash::Flash, D: Digest, V: SignatureVerify> hil::digest::Client
+    for FirmwareUpdate<'a, F, D, V>
+{
+    fn add_data_done(&self, result: ReturnCode, data: &'static mut [u8]) {
+        self.buffer.replace(data);
+        if let State::AddData {
+            page,
+            offset,
+            length,
+        } = self.state.get()
+        {
+            if result != ReturnCode::SUCCESS {
+                self.finish(ReturnCode::FAIL);
+                return;
+            }
+            let offset = offset + length;
+            if page * self.page_size + offset >= self.length.get() {
+                self.continue_with(self.run());
+            } else if offset == self.page_size {
+                self.continue_with(self.read_staged(page + 1));
+            } else {
+                self.continue_with(self.add_data(page, offset));
+            }
+        }
+    }
+
+    fn hash_done(&self, result: ReturnCode, digest: &'static mut [u8]) {
+        if self.state.get() != State::Run {
+            self.hash.replace(digest);
+            return;
+        }
+        if result != ReturnCode::SUCCESS {
+            self.hash.replace(digest);
+            self.finish(ReturnCode::FAIL);
+            return;
+        }
+        match self.signature.take() {
+            Some(signature) => {
+                // The verifier may be shared with users of other algorithms.
+                let result = self.verifier.set_algorithm(SignatureAlgorithm::EcdsaNistP256);
+                let (result, buffers) = if result == ReturnCode::SUCCESS {
+                    self.verifier.verify(self.key, digest, signature)
+                } else {
+                    (result, Some((digest, signature)))
+                };
+                buffers.map(|(hash, signature)| {
+                    self.hash.replace(hash);
+                    self.signature.replace(signature);
+                });
+                if result == ReturnCode::SUCCESS {
+                    self.state.set(State::Verify);
+                } else {
+                    self.finish(ReturnCode::FAIL);
+                }
+            }
+            None => {
+                self.hash.replace(digest);
+                self.finish(ReturnCode::FAIL);
+            }
+        }
+    }
+
+    /// Images are only hashed with `run`, as the digest is checked with
+    /// the signature.
+    fn verification_done(&self, _result: ReturnCode, compare: &'static mut [u8]) {
+        self.hash.replace(compare);
+    }
+}
+
+impl<F: hil::flash::Flash, D: Digest, V: SignatureVerify>
+    hil::public_key_crypto::signature::SignatureVerifyClient for FirmwareUpdate<'a, F, D, V>
+{
+    fn verification_done(
+        &self,
+        result: ReturnCode,
+        hash: &'static mut [u8],
+        signature: &'static mut [u8],
+    ) {
+        self.hash.replace(hash);
+        self.signature.replace(signature);
+        if self.state.get() != State::Verify {
+            return;
+        }
+        if result != ReturnCode::SUCCESS {
+            self.finish(ReturnCode::EINVAL);
+        } else {
+            let result = self.write_flag(FLAG_PENDING, self.kind.get(), self.length.get());
+            self.continue_with(result);
+        }
+    }
+}
+
+impl<F: hil::flash::Flash, D: Digest, V: SignatureVerify> DeferredCallClient
+    for FirmwareUpdate<'a, F, D, V>
+{
+    fn handle_deferred_call(&self) {
+        if let State::Done { result } = self.state.get() {
+            self.finish(result);
+        }
+    }
+}
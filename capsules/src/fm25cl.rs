@@ -28,15 +28,20 @@
 //! fm25cl_spi.set_client(fm25cl);
 //! ```
 //!
-//! This capsule provides two interfaces:
+//! This capsule provides three interfaces:
 //!
 //! - `hil::nonvolatile_storage::NonvolatileStorage`
+//! - `hil::eeprom::Eeprom`
 //! - `FM25CLCustom`
 //!
 //! The first is the generic interface for nonvolatile storage. This allows
 //! this driver to work with capsules like the `nonvolatile_storage_driver`
-//! that provide virtualization and a userspace interface. The second is a
-//! custom interface that exposes other chip-specific functions.
+//! that provide virtualization and a userspace interface. The second is the
+//! interface for byte-addressable memory, which `EepromFlash` turns into
+//! flash pages for capsules like the `kv_store`. The third is a custom
+//! interface that exposes other chip-specific functions. Only one of the
+//! first two should be used, as each operation is reported to the client of
+//! the interface that started it.
 
 use core::cell::Cell;
 use core::cmp;
@@ -52,6 +57,12 @@ pub static mut KERNEL_RXBUFFER: [u8; 512] = [0; 512];
 
 const SPI_SPEED: u32 = 4000000;
 
+/// The size of the FM25CL64B, in bytes
+pub const SIZE: usize = 8192;
+
+/// The opcode and address sent before the data of reads and writes
+const HEADER_LEN: usize = 3;
+
 #[allow(dead_code)]
 enum Opcodes {
     WriteEnable = 0x06,
@@ -94,6 +105,9 @@ pub struct FM25CL<'a, S: hil::spi::SpiMasterDevice> {
     rxbuffer: TakeCell<'static, [u8]>,
     client: OptionalCell<&'static hil::nonvolatile_storage::NonvolatileStorageClient>,
     client_custom: OptionalCell<&'static FM25CLClient>,
+    eeprom_client: OptionalCell<&'static hil::eeprom::Client>,
+    // Whether the operation in progress was started through `hil::eeprom`
+    eeprom_operation: Cell<bool>,
     client_buffer: TakeCell<'static, [u8]>, // Store buffer and state for passing back to client
     client_write_address: Cell<u16>,
     client_write_len: Cell<u16>,
@@ -113,6 +127,8 @@ impl<S: hil::spi::SpiMasterDevice> FM25CL<'a, S> {
             rxbuffer: TakeCell::new(rxbuffer),
             client: OptionalCell::empty(),
             client_custom: OptionalCell::empty(),
+            eeprom_client: OptionalCell::empty(),
+            eeprom_operation: Cell::new(false),
             client_buffer: TakeCell::empty(),
             client_write_address: Cell::new(0),
             client_write_len: Cell::new(0),
@@ -140,7 +156,7 @@ impl<S: hil::spi::SpiMasterDevice> FM25CL<'a, S> {
             .map_or(ReturnCode::ERESERVE, move |txbuffer| {
                 txbuffer[0] = Opcodes::WriteEnable as u8;
 
-                let write_len = cmp::min(txbuffer.len(), len as usize);
+                let write_len = cmp::min(txbuffer.len() - HEADER_LEN, len as usize);
 
                 // Need to save the buffer passed to us so we can give it back.
                 self.client_buffer.replace(buffer);
@@ -169,11 +185,11 @@ impl<S: hil::spi::SpiMasterDevice> FM25CL<'a, S> {
                         // Save the user buffer for later
                         self.client_buffer.replace(buffer);
 
-                        let read_len = cmp::min(rxbuffer.len() - 3, len as usize);
+                        let read_len = cmp::min(rxbuffer.len() - HEADER_LEN, len as usize);
 
                         self.state.set(State::ReadMemory);
                         self.spi
-                            .read_write_bytes(txbuffer, Some(rxbuffer), read_len + 3)
+                            .read_write_bytes(txbuffer, Some(rxbuffer), read_len + HEADER_LEN)
                     })
             })
     }
@@ -210,8 +226,10 @@ impl<S: hil::spi::SpiMasterDevice> hil::spi::SpiMasterClient for FM25CL<'a, S> {
                     write_buffer[1] = ((self.client_write_address.get() >> 8) & 0xFF) as u8;
                     write_buffer[2] = (self.client_write_address.get() & 0xFF) as u8;
 
-                    let write_len =
-                        cmp::min(write_buffer.len(), self.client_write_len.get() as usize);
+                    let write_len = cmp::min(
+                        write_buffer.len() - HEADER_LEN,
+                        self.client_write_len.get() as usize,
+                    );
 
                     for i in 0..write_len {
                         write_buffer[(i + 3) as usize] = buffer[i as usize];
@@ -224,7 +242,10 @@ impl<S: hil::spi::SpiMasterDevice> hil::spi::SpiMasterClient for FM25CL<'a, S> {
             State::WriteMemory => {
                 self.state.set(State::Idle);
 
-                let write_len = cmp::min(write_buffer.len(), self.client_write_len.get() as usize);
+                let write_len = cmp::min(
+                    write_buffer.len() - HEADER_LEN,
+                    self.client_write_len.get() as usize,
+                );
 
                 // Replace these buffers
                 self.txbuffer.replace(write_buffer);
@@ -234,8 +255,14 @@ impl<S: hil::spi::SpiMasterDevice> hil::spi::SpiMasterClient for FM25CL<'a, S> {
 
                 // Call done with the write() buffer
                 self.client_buffer.take().map(move |buffer| {
-                    self.client
-                        .map(move |client| client.write_done(buffer, write_len));
+                    if self.eeprom_operation.get() {
+                        self.eeprom_client.map(move |client| {
+                            client.write_done(buffer, write_len, ReturnCode::SUCCESS)
+                        });
+                    } else {
+                        self.client
+                            .map(move |client| client.write_done(buffer, write_len));
+                    }
                 });
             }
             State::ReadMemory => {
@@ -246,16 +273,22 @@ impl<S: hil::spi::SpiMasterDevice> hil::spi::SpiMasterClient for FM25CL<'a, S> {
 
                 read_buffer.map(|read_buffer| {
                     self.client_buffer.take().map(move |buffer| {
-                        let read_len = cmp::min(buffer.len(), len);
+                        let read_len = cmp::min(buffer.len(), len - HEADER_LEN);
 
-                        for i in 0..(read_len - 3) {
-                            buffer[i] = read_buffer[i + 3];
+                        for i in 0..read_len {
+                            buffer[i] = read_buffer[i + HEADER_LEN];
                         }
 
                         self.rxbuffer.replace(read_buffer);
 
-                        self.client
-                            .map(move |client| client.read_done(buffer, read_len - 3));
+                        if self.eeprom_operation.get() {
+                            self.eeprom_client.map(move |client| {
+                                client.read_done(buffer, read_len, ReturnCode::SUCCESS)
+                            });
+                        } else {
+                            self.client
+                                .map(move |client| client.read_done(buffer, read_len));
+                        }
                     });
                 });
             }
@@ -295,10 +328,68 @@ impl<S: hil::spi::SpiMasterDevice> hil::nonvolatile_storage::NonvolatileStorage
     }
 
     fn read(&self, buffer: &'static mut [u8], address: usize, length: usize) -> ReturnCode {
+        self.eeprom_operation.set(false);
         self.read(address as u16, buffer, length as u16)
     }
 
     fn write(&self, buffer: &'static mut [u8], address: usize, length: usize) -> ReturnCode {
+        self.eeprom_operation.set(false);
         self.write(address as u16, buffer, length as u16)
     }
 }
+
+impl<S: hil::spi::SpiMasterDevice> FM25CL<'a, S> {
+    /// Checks an operation started through `hil::eeprom`.
+    fn check_eeprom(&self, address: usize, buffer: &[u8], length: usize) -> ReturnCode {
+        if self.state.get() != State::Idle || self.txbuffer.is_none() {
+            ReturnCode::EBUSY
+        } else if buffer.len() < length {
+            ReturnCode::ESIZE
+        } else if address + length > SIZE {
+            ReturnCode::EINVAL
+        } else {
+            ReturnCode::SUCCESS
+        }
+    }
+}
+
+/// Implement the interface for byte-addressable memory. Reads and writes
+/// move at most as many bytes as fit in the SPI buffers besides the opcode
+/// and address.
+impl<S: hil::spi::SpiMasterDevice> hil::eeprom::Eeprom for FM25CL<'a, S> {
+    fn set_client(&self, client: &'static hil::eeprom::Client) {
+        self.eeprom_client.set(client);
+    }
+
+    fn size(&self) -> usize {
+        SIZE
+    }
+
+    fn read(
+        &self,
+        address: usize,
+        buffer: &'static mut [u8],
+        length: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        let result = self.check_eeprom(address, buffer, length);
+        if result != ReturnCode::SUCCESS {
+            return (result, Some(buffer));
+        }
+        self.eeprom_operation.set(true);
+        (self.read(address as u16, buffer, length as u16), None)
+    }
+
+    fn write(
+        &self,
+        address: usize,
+        buffer: &'static mut [u8],
+        length: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        let result = self.check_eeprom(address, buffer, length);
+        if result != ReturnCode::SUCCESS {
+            return (result, Some(buffer));
+        }
+        self.eeprom_operation.set(true);
+        (self.write(address as u16, buffer, length as u16), None)
+    }
+}
@@ -0,0 +1,148 @@
+//! Provides userspace with the state of the battery.
+//!
+//! Apps read the state of charge, the voltage and the charge rate of the
+//! battery, and can subscribe to an alert when the state of charge falls
+//! below a threshold. The threshold is shared: any app can set it, and all
+//! subscribed apps get the alerts.
+//!
+//! Usage
+//! -----
+//!
+//! You need a device that provides the `hil::sensors::FuelGauge` trait.
+//!
+//! ```rust
+//! let fuel_gauge = static_init!(
+//!     capsules::fuel_gauge::FuelGauge<'static>,
+//!     capsules::fuel_gauge::FuelGauge::new(
+//!         max17048,
+//!         board_kernel.create_grant(&memory_allocation_capability)));
+//! hil::sensors::FuelGauge::set_client(max17048, fuel_gauge);
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::sensors;
+use kernel::{AppId, Callback, Driver, Grant, ReturnCode};
+
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = 0x60008;
+
+#[derive(Default)]
+pub struct App {
+    read_callback: Option<Callback>,
+    alert_callback: Option<Callback>,
+    pending: bool,
+}
+
+pub struct FuelGauge<'a> {
+    gauge: &'a sensors::FuelGauge,
+    apps: Grant<App>,
+    busy: Cell<bool>,
+}
+
+impl FuelGauge<'a> {
+    pub fn new(gauge: &'a sensors::FuelGauge, grant: Grant<App>) -> FuelGauge<'a> {
+        FuelGauge {
+            gauge: gauge,
+            apps: grant,
+            busy: Cell::new(false),
+        }
+    }
+
+    fn read(&self, appid: AppId) -> ReturnCode {
+        self.apps
+            .enter(appid, |app, _| {
+                if app.pending {
+                    return ReturnCode::EALREADY;
+                }
+                if !self.busy.get() {
+                    let rcode = self.gauge.read_battery();
+                    if rcode != ReturnCode::SUCCESS {
+                        return rcode;
+                    }
+                    self.busy.set(true);
+                }
+                app.pending = true;
+                ReturnCode::SUCCESS
+            }).unwrap_or_else(|err| err.into())
+    }
+}
+
+impl sensors::FuelGaugeClient for FuelGauge<'a> {
+    fn battery_read(&self, state_of_charge: u32, voltage: u32, rate: i32, result: ReturnCode) {
+        self.busy.set(false);
+        self.apps.each(|app| {
+            if app.pending {
+                app.pending = false;
+                app.read_callback.map(|mut cb| {
+                    if result == ReturnCode::SUCCESS {
+                        cb.schedule(state_of_charge as usize, voltage as usize, rate as usize);
+                    } else {
+                        cb.schedule(isize::from(result) as usize, 0, 0);
+                    }
+                });
+            }
+        });
+    }
+
+    fn low_charge(&self, state_of_charge: u32) {
+        self.apps.each(|app| {
+            app.alert_callback
+                .map(|mut cb| cb.schedule(state_of_charge as usize, 0, 0));
+        });
+    }
+}
+
+impl Driver for FuelGauge<'a> {
+    /// Subscribe to battery readings and alerts.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Called with the state of charge in hundredths of percent, the
+    ///        voltage in millivolts and the charge rate in hundredths of
+    ///        percent per hour when a reading completes. The state of charge
+    ///        is a negative return code if the reading failed.
+    /// - `1`: Called with the state of charge when it fell below the alert
+    ///        threshold.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    app.read_callback = callback;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            1 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    app.alert_callback = callback;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Read the battery.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Read the battery. Returns EALREADY if a reading is requested.
+    /// - `2`: Alert when the state of charge falls below `data` percent, or
+    ///        stop alerting if it is 0.
+    fn command(&self, command_num: usize, data: usize, _: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            1 => self.read(appid),
+
+            2 => self.gauge.set_low_charge_alert(data as u32),
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
@@ -0,0 +1,196 @@
+//! Driver for the HC-SR04 ultrasonic distance sensor.
+//!
+//! A measurement starts with a 10 us pulse on the trigger pin. The sensor
+//! then sends a burst of ultrasound and raises the echo pin until it hears
+//! the echo, so the length of the echo pulse is the round trip time of the
+//! sound. The driver times the pulse with the counter of the alarm at the
+//! interrupts of both edges, and reports ENOACK if the echo pin did not go
+//! low within `TIMEOUT_MS`, which is longer than the round trip to the
+//! farthest object in range. The sensor needs `CYCLE_MS` between
+//! measurements, so that echoes of the previous one have died out; a
+//! measurement requested sooner is started when the cycle ends.
+//!
+//! The resolution is set by the frequency of the alarm: an alarm at 1 MHz or
+//! more resolves millimeters, one at 32 kHz about 5 mm. The echo pin of the
+//! sensor is 5 V and needs a divider on 3.3 V chips.
+//!
+//! Each sensor needs its own virtual alarm, so several sensors can measure
+//! at the same time, although sensors facing the same way hear each other.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let hc_sr04_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     VirtualMuxAlarm::new(mux_alarm));
+//! let hc_sr04 = static_init!(
+//!     capsules::hc_sr04::HcSr04<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::hc_sr04::HcSr04::new(
+//!         &sam4l::gpio::PA[13],
+//!         &sam4l::gpio::PA[14],
+//!         hc_sr04_alarm));
+//! hc_sr04_alarm.set_client(hc_sr04);
+//! sam4l::gpio::PA[14].set_client(hc_sr04);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::hil::gpio;
+use kernel::hil::sensors;
+use kernel::hil::time::{self, Frequency};
+use kernel::ReturnCode;
+
+/// The length of the trigger pulse
+const TRIGGER_US: u32 = 10;
+
+/// The longest time from the trigger to the end of the echo pulse
+const TIMEOUT_MS: u32 = 40;
+
+/// The shortest time from the start of a measurement to the next one
+const CYCLE_MS: u32 = 60;
+
+/// The range of the sensor, in millimeters
+const MIN_DISTANCE_MM: u32 = 20;
+const MAX_DISTANCE_MM: u32 = 4000;
+
+/// The speed of sound in air at 20 degrees C, in millimeters per second
+const SPEED_OF_SOUND: u64 = 343_000;
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    /// Waiting for the echo pin to go high
+    WaitRising,
+    /// Waiting for the echo pin to go low
+    WaitFalling,
+    /// Waiting for the end of the cycle before another measurement
+    Cycle,
+}
+
+pub struct HcSr04<'a, A: time::Alarm> {
+    trigger: &'a gpio::Pin,
+    echo: &'a gpio::Pin,
+    alarm: &'a A,
+    state: Cell<State>,
+    /// The counter of the alarm at the trigger
+    start_time: Cell<u32>,
+    /// The counter of the alarm at the rising edge of the echo pin
+    echo_time: Cell<u32>,
+    /// Whether a measurement was requested during the cycle
+    pending: Cell<bool>,
+    client: OptionalCell<&'static sensors::DistanceClient>,
+}
+
+impl<A: time::Alarm> HcSr04<'a, A> {
+    pub fn new(trigger: &'a gpio::Pin, echo: &'a gpio::Pin, alarm: &'a A) -> HcSr04<'a, A> {
+        trigger.make_output();
+        trigger.clear();
+        echo.make_input();
+        HcSr04 {
+            trigger: trigger,
+            echo: echo,
+            alarm: alarm,
+            state: Cell::new(State::Idle),
+            start_time: Cell::new(0),
+            echo_time: Cell::new(0),
+            pending: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    fn ticks(us: u32) -> u32 {
+        let ticks = us as u64 * A::Frequency::frequency() as u64;
+        ((ticks + 999_999) / 1_000_000) as u32
+    }
+
+    fn start(&self) {
+        self.state.set(State::WaitRising);
+        self.echo.enable_interrupt(0, gpio::InterruptMode::EitherEdge);
+
+        let start = self.alarm.now();
+        self.start_time.set(start);
+        self.alarm.set_alarm(start.wrapping_add(Self::ticks(TIMEOUT_MS * 1000)));
+
+        self.trigger.set();
+        let ticks = Self::ticks(TRIGGER_US);
+        while self.alarm.now().wrapping_sub(start) < ticks {}
+        self.trigger.clear();
+    }
+
+    /// Ends a measurement, and waits for the rest of the cycle.
+    fn finish(&self, distance: u32, result: ReturnCode) {
+        self.echo.disable_interrupt();
+        self.state.set(State::Cycle);
+        self.alarm.set_alarm(self.start_time.get().wrapping_add(Self::ticks(CYCLE_MS * 1000)));
+        self.client.map(|client| client.callback(distance, result));
+    }
+}
+
+impl<A: time::Alarm> time::Client for HcSr04<'a, A> {
+    fn fired(&self) {
+        match self.state.get() {
+            State::WaitRising | State::WaitFalling => self.finish(0, ReturnCode::ENOACK),
+            State::Cycle => {
+                if self.pending.get() {
+                    self.pending.set(false);
+                    self.start();
+                } else {
+                    self.state.set(State::Idle);
+                }
+            }
+            State::Idle => {}
+        }
+    }
+}
+
+impl<A: time::Alarm> gpio::Client for HcSr04<'a, A> {
+    fn fired(&self, _: usize) {
+        let now = self.alarm.now();
+        match self.state.get() {
+            State::WaitRising if self.echo.read() => {
+                self.echo_time.set(now);
+                self.state.set(State::WaitFalling);
+            }
+            State::WaitFalling if !self.echo.read() => {
+                let ticks = now.wrapping_sub(self.echo_time.get()) as u64;
+                // Half of the round trip
+                let distance = ticks * SPEED_OF_SOUND / 2 / A::Frequency::frequency() as u64;
+                if distance > MAX_DISTANCE_MM as u64 {
+                    self.finish(0, ReturnCode::ENOACK);
+                } else {
+                    self.finish(distance as u32, ReturnCode::SUCCESS);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<A: time::Alarm> sensors::Distance for HcSr04<'a, A> {
+    fn set_client(&self, client: &'static sensors::DistanceClient) {
+        self.client.set(client);
+    }
+
+    fn read_distance(&self) -> ReturnCode {
+        match self.state.get() {
+            State::Idle => {
+                self.start();
+                ReturnCode::SUCCESS
+            }
+            State::Cycle if !self.pending.get() => {
+                self.pending.set(true);
+                ReturnCode::SUCCESS
+            }
+            _ => ReturnCode::EBUSY,
+        }
+    }
+
+    fn get_minimum_distance(&self) -> u32 {
+        MIN_DISTANCE_MM
+    }
+
+    fn get_maximum_distance(&self) -> u32 {
+        MAX_DISTANCE_MM
+    }
+}
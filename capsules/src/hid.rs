@@ -0,0 +1,457 @@
+//! A USB Human Interface Device (HID) that sends input reports.
+//!
+//! `UsbHid` is a client of a USB controller that enumerates as an HID with
+//! the report descriptor it is given, so the same capsule can act as a
+//! keyboard, using `KEYBOARD_REPORT_DESCRIPTOR`, or as a device with
+//! vendor-defined reports that a host application reads directly, using
+//! `GENERIC_REPORT_DESCRIPTOR` or a descriptor of its own. Reports are sent
+//! over an interrupt IN endpoint, and must fit in a single 8-byte packet.
+//!
+//! The host sends output reports, such as the state of the LEDs of a
+//! keyboard, with SET_REPORT requests, which are accepted but ignored. The
+//! device has no boot protocol support, so a keyboard is only usable once the
+//! host's HID driver has loaded.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let hid = static_init!(
+//!     capsules::hid::UsbHid<'static, sam4l::usbc::Usbc<'static>>,
+//!     capsules::hid::UsbHid::new(
+//!         &sam4l::usbc::USBC,
+//!         &capsules::hid::KEYBOARD_REPORT_DESCRIPTOR
+//!     )
+//! );
+//! sam4l::usbc::USBC.set_client(hid);
+//! hil::usb::Client::enable(hid);
+//! hil::usb::Client::attach(hid);
+//! ```
+
+use core::cell::Cell;
+use core::cmp::min;
+use kernel::common::cells::{OptionalCell, VolatileCell};
+use kernel::hil;
+use kernel::ReturnCode;
+use usb::ClassRequest;
+use usb::ControlEndpoint;
+use usb::Descriptor;
+use usb::DeviceClass;
+use usb::DeviceDescriptor;
+use usb::EndpointAddress;
+use usb::EndpointDescriptor;
+use usb::InterfaceDescriptor;
+use usb::Recipient;
+use usb::RequestType;
+use usb::SetupData;
+use usb::TransferDirection;
+use usb::TransferType;
+
+const VENDOR_ID: u16 = 0x6667;
+const PRODUCT_ID: u16 = 0xabcf;
+
+static STRINGS: &'static [&'static str] = &[
+    "Tock",     // Manufacturer
+    "Tock HID", // Product
+    "000001",   // Serial number
+];
+
+/// The report descriptor of a keyboard, from Appendix B.1 of the HID 1.11
+/// specification.  Its input reports are 8 bytes long: a byte of modifier
+/// keys, a reserved byte, and the usage IDs of up to 6 pressed keys.
+pub static KEYBOARD_REPORT_DESCRIPTOR: [u8; 63] = [
+    0x05, 0x01, // Usage Page (Generic Desktop)
+    0x09, 0x06, // Usage (Keyboard)
+    0xa1, 0x01, // Collection (Application)
+    0x05, 0x07, //   Usage Page (Key Codes)
+    0x19, 0xe0, //   Usage Minimum (224)
+    0x29, 0xe7, //   Usage Maximum (231)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x25, 0x01, //   Logical Maximum (1)
+    0x75, 0x01, //   Report Size (1)
+    0x95, 0x08, //   Report Count (8)
+    0x81, 0x02, //   Input (Data, Variable, Absolute): modifier byte
+    0x95, 0x01, //   Report Count (1)
+    0x75, 0x08, //   Report Size (8)
+    0x81, 0x01, //   Input (Constant): reserved byte
+    0x95, 0x05, //   Report Count (5)
+    0x75, 0x01, //   Report Size (1)
+    0x05, 0x08, //   Usage Page (LEDs)
+    0x19, 0x01, //   Usage Minimum (1)
+    0x29, 0x05, //   Usage Maximum (5)
+    0x91, 0x02, //   Output (Data, Variable, Absolute): LED report
+    0x95, 0x01, //   Report Count (1)
+    0x75, 0x03, //   Report Size (3)
+    0x91, 0x01, //   Output (Constant): LED report padding
+    0x95, 0x06, //   Report Count (6)
+    0x75, 0x08, //   Report Size (8)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x25, 0x65, //   Logical Maximum (101)
+    0x05, 0x07, //   Usage Page (Key Codes)
+    0x19, 0x00, //   Usage Minimum (0)
+    0x29, 0x65, //   Usage Maximum (101)
+    0x81, 0x00, //   Input (Data, Array): key arrays (6 bytes)
+    0xc0, // End Collection
+];
+
+/// A report descriptor for 8-byte input reports with a vendor-defined
+/// meaning.
+pub static GENERIC_REPORT_DESCRIPTOR: [u8; 21] = [
+    0x06, 0x00, 0xff, // Usage Page (Vendor Defined 0xFF00)
+    0x09, 0x01, // Usage (1)
+    0xa1, 0x01, // Collection (Application)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x26, 0xff, 0x00, //   Logical Maximum (255)
+    0x75, 0x08, //   Report Size (8)
+    0x95, 0x08, //   Report Count (8)
+    0x09, 0x01, //   Usage (1)
+    0x81, 0x02, //   Input (Data, Variable, Absolute)
+    0xc0, // End Collection
+];
+
+const CLASS_HID: u8 = 0x03;
+
+/// Descriptor types defined by the HID specification
+const DESCRIPTOR_TYPE_HID: u8 = 0x21;
+const DESCRIPTOR_TYPE_REPORT: u8 = 0x22;
+
+/// The GET_DESCRIPTOR standard request
+const GET_DESCRIPTOR: u8 = 6;
+
+/// Class-specific requests
+const GET_REPORT: u8 = 0x01;
+const SET_REPORT: u8 = 0x09;
+const SET_IDLE: u8 = 0x0a;
+
+const HID_DESCRIPTOR_LEN: usize = 9;
+
+/// The endpoint that input reports are sent on
+const REPORT_IN: usize = 1;
+
+const N_ENDPOINTS: usize = 2;
+
+/// Size of every packet buffer, and the max packet size of every endpoint
+const PACKET_SIZE: usize = 8;
+
+pub trait HidClient {
+    /// Called when the host has read the report passed to `send_report`.
+    fn report_sent(&self, result: ReturnCode);
+}
+
+pub trait Hid<'a> {
+    fn set_client(&self, client: &'a HidClient);
+
+    /// Send an input report to the host, the next time it polls the device.
+    /// Returns EBUSY if the previous report was not read yet, and ESIZE if
+    /// the report does not fit in a packet.
+    fn send_report(&self, report: &[u8]) -> ReturnCode;
+}
+
+/// The HID descriptor, which follows the interface descriptor and gives the
+/// length of the report descriptor
+struct HidDescriptor {
+    report_descriptor_length: usize,
+}
+
+impl Descriptor for HidDescriptor {
+    fn size(&self) -> usize {
+        HID_DESCRIPTOR_LEN
+    }
+
+    fn write_to_unchecked(&self, buf: &[Cell<u8>]) -> usize {
+        buf[0].set(HID_DESCRIPTOR_LEN as u8);
+        buf[1].set(DESCRIPTOR_TYPE_HID);
+        // HID release number 1.11
+        buf[2].set(0x11);
+        buf[3].set(0x01);
+        // Not localized
+        buf[4].set(0);
+        // A single class descriptor: the report descriptor
+        buf[5].set(1);
+        buf[6].set(DESCRIPTOR_TYPE_REPORT);
+        buf[7].set(self.report_descriptor_length as u8);
+        buf[8].set((self.report_descriptor_length >> 8) as u8);
+        HID_DESCRIPTOR_LEN
+    }
+}
+
+/// The class-specific Control transfers, and the GET_DESCRIPTOR requests
+/// for the class descriptors
+#[derive(Copy, Clone)]
+pub enum Request {
+    Hid,
+    Report,
+    InputReport,
+    /// An output report from the host, which is accepted and ignored
+    SetReport,
+}
+
+pub struct UsbHid<'a, C: 'a> {
+    // The hardware controller
+    controller: &'a C,
+
+    // The report descriptor the device enumerates with
+    report_descriptor: &'a [u8],
+
+    // The default control endpoint
+    ctrl: ControlEndpoint<'a, C, Request>,
+
+    // A packet buffer for each endpoint
+    buffers: [[VolatileCell<u8>; PACKET_SIZE]; N_ENDPOINTS],
+
+    // The last input report, which is also returned by GET_REPORT
+    report: [Cell<u8>; PACKET_SIZE],
+    report_len: Cell<usize>,
+    // The report has not been read by the host yet
+    report_pending: Cell<bool>,
+    delayed_in: Cell<bool>,
+
+    client: OptionalCell<&'a HidClient>,
+}
+
+impl<C: hil::usb::UsbController> UsbHid<'a, C> {
+    pub fn new(controller: &'a C, report_descriptor: &'a [u8]) -> Self {
+        UsbHid {
+            controller: controller,
+            report_descriptor: report_descriptor,
+            ctrl: ControlEndpoint::new(controller),
+            buffers: Default::default(),
+            report: Default::default(),
+            report_len: Cell::new(0),
+            report_pending: Cell::new(false),
+            delayed_in: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    fn write_hid_descriptor(&self, buf: &[Cell<u8>]) -> usize {
+        HidDescriptor {
+            report_descriptor_length: self.report_descriptor.len(),
+        }.write_to(buf)
+    }
+}
+
+impl<C: hil::usb::UsbController> Hid<'a> for UsbHid<'a, C> {
+    fn set_client(&self, client: &'a HidClient) {
+        self.client.set(client);
+    }
+
+    fn send_report(&self, report: &[u8]) -> ReturnCode {
+        if self.report_pending.get() {
+            return ReturnCode::EBUSY;
+        }
+        if report.len() > PACKET_SIZE {
+            return ReturnCode::ESIZE;
+        }
+        for (i, b) in report.iter().enumerate() {
+            self.report[i].set(*b);
+        }
+        self.report_len.set(report.len());
+        self.report_pending.set(true);
+
+        // In case we reported Delay before, alert the controller
+        // that we now have a report to send
+        if self.delayed_in.take() {
+            self.controller.endpoint_bulk_resume(REPORT_IN);
+        }
+        ReturnCode::SUCCESS
+    }
+}
+
+impl<C: hil::usb::UsbController> DeviceClass for UsbHid<'a, C> {
+    type Request = Request;
+
+    fn device_descriptor(&self) -> DeviceDescriptor {
+        DeviceDescriptor {
+            vendor_id: VENDOR_ID,
+            product_id: PRODUCT_ID,
+            manufacturer_string: 1,
+            product_string: 2,
+            serial_number_string: 3,
+            ..Default::default()
+        }
+    }
+
+    fn strings(&self) -> &'static [&'static str] {
+        STRINGS
+    }
+
+    fn num_interfaces(&self) -> u8 {
+        1
+    }
+
+    fn write_configuration_part(&self, index: usize, buf: &[Cell<u8>]) -> Option<usize> {
+        let len = match index {
+            0 => InterfaceDescriptor {
+                num_endpoints: 1,
+                interface_class: CLASS_HID,
+                interface_subclass: 0,
+                interface_protocol: 0,
+                ..Default::default()
+            }.write_to(buf),
+            1 => self.write_hid_descriptor(buf),
+            2 => EndpointDescriptor {
+                endpoint_address: EndpointAddress::new(REPORT_IN, TransferDirection::DeviceToHost),
+                transfer_type: TransferType::Interrupt,
+                max_packet_size: PACKET_SIZE as u16,
+                interval: 10,
+            }.write_to(buf),
+            _ => return None,
+        };
+        Some(len)
+    }
+
+    /// Handle the requests addressed to the interface that are not standard
+    /// device requests: GET_DESCRIPTOR for the class descriptors, and the
+    /// class-specific requests
+    fn class_request(&self, setup_data: SetupData) -> ClassRequest<Request> {
+        match setup_data.request_type.recipient() {
+            Recipient::Interface => {}
+            _ => return ClassRequest::Err(hil::usb::CtrlSetupResult::ErrNonstandardRequest),
+        }
+        match (
+            setup_data.request_type.request_type(),
+            setup_data.request_code,
+        ) {
+            (RequestType::Standard, GET_DESCRIPTOR) => match (setup_data.value >> 8) as u8 {
+                DESCRIPTOR_TYPE_HID => ClassRequest::Read(Request::Hid, setup_data.length),
+                DESCRIPTOR_TYPE_REPORT => ClassRequest::Read(Request::Report, setup_data.length),
+                _ => ClassRequest::Err(hil::usb::CtrlSetupResult::ErrUnrecognizedDescriptorType),
+            },
+            (RequestType::Class, GET_REPORT) => {
+                ClassRequest::Read(Request::InputReport, setup_data.length)
+            }
+            (RequestType::Class, SET_REPORT) => ClassRequest::Write(Request::SetReport),
+            (RequestType::Class, SET_IDLE) => {
+                // Reports are only sent when the app provides them, so there
+                // is nothing to repeat while idle
+                ClassRequest::Ok
+            }
+            _ => ClassRequest::Err(hil::usb::CtrlSetupResult::ErrNonstandardRequest),
+        }
+    }
+
+    /// The report descriptor is serialized a storage buffer at a time, as it
+    /// may not fit in one
+    fn write_request_part(
+        &self,
+        request: Request,
+        index: usize,
+        buf: &[Cell<u8>],
+    ) -> Option<usize> {
+        match (request, index) {
+            (Request::Hid, 0) => Some(self.write_hid_descriptor(buf)),
+            (Request::Report, i) => {
+                let start = i * buf.len();
+                if start >= self.report_descriptor.len() {
+                    return None;
+                }
+                let len = min(buf.len(), self.report_descriptor.len() - start);
+                for j in 0..len {
+                    buf[j].set(self.report_descriptor[start + j]);
+                }
+                Some(len)
+            }
+            (Request::InputReport, 0) => {
+                for (i, b) in self.report.iter().enumerate() {
+                    buf[i].set(b.get());
+                }
+                Some(self.report_len.get())
+            }
+            _ => None,
+        }
+    }
+
+    fn request_out(
+        &self,
+        request: Request,
+        _packet: &[VolatileCell<u8>],
+        _packet_bytes: usize,
+    ) -> hil::usb::CtrlOutResult {
+        match request {
+            Request::SetReport => hil::usb::CtrlOutResult::Ok,
+            _ => hil::usb::CtrlOutResult::Halted,
+        }
+    }
+}
+
+impl<C: hil::usb::UsbController> hil::usb::Client for UsbHid<'a, C> {
+    fn enable(&self) {
+        // Set up the default control endpoint
+        self.controller.endpoint_set_buffer(0, &self.buffers[0]);
+        self.controller
+            .enable_as_device(hil::usb::DeviceSpeed::Full);
+        self.controller.endpoint_ctrl_out_enable(0);
+
+        // Set up the report endpoint.  The controller answers the host
+        // polling it like a Bulk IN endpoint, which for the device is the
+        // same as an Interrupt IN endpoint.
+        self.controller
+            .endpoint_set_buffer(REPORT_IN, &self.buffers[REPORT_IN]);
+        self.controller.endpoint_bulk_in_enable(REPORT_IN);
+    }
+
+    fn attach(&self) {
+        self.controller.attach();
+    }
+
+    fn bus_reset(&self) {
+        // The controller reinitializes the endpoints
+        self.delayed_in.set(false);
+    }
+
+    /// Handle a Control Setup transaction
+    fn ctrl_setup(&self, endpoint: usize) -> hil::usb::CtrlSetupResult {
+        if endpoint != 0 {
+            // We only support the default Control endpoint
+            return hil::usb::CtrlSetupResult::ErrInvalidDeviceIndex;
+        }
+        self.ctrl.ctrl_setup(self, &self.buffers[endpoint])
+    }
+
+    /// Handle a Control In transaction
+    fn ctrl_in(&self, endpoint: usize) -> hil::usb::CtrlInResult {
+        self.ctrl.ctrl_in(self, &self.buffers[endpoint])
+    }
+
+    /// Handle a Control Out transaction
+    fn ctrl_out(&self, endpoint: usize, packet_bytes: u32) -> hil::usb::CtrlOutResult {
+        self.ctrl
+            .ctrl_out(self, &self.buffers[endpoint], packet_bytes)
+    }
+
+    fn ctrl_status(&self, _endpoint: usize) {
+        // Entered Status stage
+    }
+
+    /// Handle the completion of a Control transfer
+    fn ctrl_status_complete(&self, _endpoint: usize) {
+        self.ctrl.ctrl_status_complete();
+    }
+
+    /// Handle a transaction on the report endpoint
+    fn bulk_in(&self, endpoint: usize) -> hil::usb::BulkInResult {
+        if !self.report_pending.get() {
+            // Nothing to send
+            self.delayed_in.set(true);
+            return hil::usb::BulkInResult::Delay;
+        }
+
+        // Write the report into the endpoint buffer
+        let packet_bytes = self.report_len.get();
+        let packet = &self.buffers[endpoint];
+        for i in 0..packet_bytes {
+            packet[i].set(self.report[i].get());
+        }
+        self.report_pending.set(false);
+        self.client
+            .map(|client| client.report_sent(ReturnCode::SUCCESS));
+
+        hil::usb::BulkInResult::Packet(packet_bytes)
+    }
+
+    fn bulk_out(&self, _endpoint: usize, _packet_bytes: u32) -> hil::usb::BulkOutResult {
+        // There are no OUT endpoints
+        hil::usb::BulkOutResult::Error
+    }
+}
@@ -0,0 +1,141 @@
+//! USB HID system call interface
+//!
+//! This capsule lets processes send input reports through a USB HID, such
+//! as the `UsbHid` in `capsules::hid`.  Only one report can be waiting for
+//! the host to read it at a time, whichever process it belongs to.
+//!
+//! ## Instantiation
+//!
+//! ```rust
+//! let hid_driver = static_init!(
+//!     capsules::hid_user::UsbHidDriver<'static>,
+//!     capsules::hid_user::UsbHidDriver::new(hid, board_kernel.create_grant(&grant_cap))
+//! );
+//! capsules::hid::Hid::set_client(hid, hid_driver);
+//! ```
+
+use hid::{Hid, HidClient};
+use kernel::common::cells::OptionalCell;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+/// Syscall number
+pub const DRIVER_NUM: usize = 0x20006;
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    report: Option<AppSlice<Shared, u8>>,
+}
+
+pub struct UsbHidDriver<'a> {
+    hid: &'a Hid<'a>,
+    apps: Grant<App>,
+    // The app whose report is waiting for the host to read it
+    sending_app: OptionalCell<AppId>,
+}
+
+impl UsbHidDriver<'a> {
+    pub fn new(hid: &'a Hid<'a>, apps: Grant<App>) -> Self {
+        UsbHidDriver {
+            hid: hid,
+            apps: apps,
+            sending_app: OptionalCell::empty(),
+        }
+    }
+}
+
+impl Driver for UsbHidDriver<'a> {
+    /// Setup buffers.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Report buffer. Contains the input report to send.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.report = slice;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Setup callback for when the host has read a report. It is
+    ///        passed the result.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// HID control
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Send the first `arg1` bytes of the report buffer as an input
+    ///        report. Returns EBUSY if a report is still waiting for the host
+    ///        to read it, ESIZE if the report is longer than 8 bytes, and
+    ///        EINVAL if the report buffer is missing or shorter than `arg1`.
+    fn command(&self, command_num: usize, arg1: usize, _: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            1 => {
+                if self.sending_app.is_some() {
+                    return ReturnCode::EBUSY;
+                }
+                let result = self
+                    .apps
+                    .enter(appid, |app, _| {
+                        app.report.as_ref().map_or(ReturnCode::EINVAL, |report| {
+                            if arg1 > report.len() {
+                                ReturnCode::EINVAL
+                            } else {
+                                self.hid.send_report(&report.as_ref()[..arg1])
+                            }
+                        })
+                    }).unwrap_or_else(|err| err.into());
+                if result == ReturnCode::SUCCESS {
+                    self.sending_app.set(appid);
+                }
+                result
+            }
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+impl HidClient for UsbHidDriver<'a> {
+    fn report_sent(&self, result: ReturnCode) {
+        self.sending_app.take().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                app.callback
+                    .map(|mut cb| cb.schedule(From::from(result), 0, 0));
+            });
+        });
+    }
+}
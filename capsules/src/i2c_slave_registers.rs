@@ -0,0 +1,183 @@
+//! Makes the board an I2C peripheral with a bank of registers.
+//!
+//! Another MCU, as the I2C master, reads and writes the registers the way
+//! it would those of a sensor or other IC: a write starts with the index
+//! of the first register, followed by the values to write to it and the
+//! registers after it. A read returns the values of the registers starting
+//! at the index of the last write, and moves that index past the registers
+//! read. Indexes past the last register wrap around to the first.
+//!
+//! The kernel sets the registers the master reads with `set_register`, and
+//! learns about the ones the master wrote through `RegistersClient`. The
+//! slave stretches the clock while the registers are copied into the
+//! transmit buffer, so a read always returns their current values.
+//!
+//! Like `i2c_master_slave_driver`, this sits directly above the I2C slave
+//! hardware, as the slave only listens on one address.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let registers = static_init!(
+//!     capsules::i2c_slave_registers::I2CSlaveRegisters<'static>,
+//!     capsules::i2c_slave_registers::I2CSlaveRegisters::new(
+//!         &sam4l::i2c::I2C1,
+//!         &mut capsules::i2c_slave_registers::REGISTERS,
+//!         &mut capsules::i2c_slave_registers::RX_BUF,
+//!         &mut capsules::i2c_slave_registers::TX_BUF,
+//!     )
+//! );
+//! sam4l::i2c::I2C1.set_slave_client(registers);
+//! registers.start(0x40);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil;
+use kernel::ReturnCode;
+
+pub static mut REGISTERS: [u8; 32] = [0; 32];
+pub static mut RX_BUF: [u8; 33] = [0; 33];
+pub static mut TX_BUF: [u8; 32] = [0; 32];
+
+/// Notified when the master writes registers.
+pub trait RegistersClient {
+    /// The master wrote `len` registers starting at `start`, where the
+    /// range may wrap around to the first register.
+    fn registers_written(&self, start: usize, len: usize);
+}
+
+pub struct I2CSlaveRegisters<'a> {
+    i2c: &'a hil::i2c::I2CSlave,
+    registers: TakeCell<'static, [u8]>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    /// The index of the next register the master reads
+    index: Cell<usize>,
+    client: OptionalCell<&'a RegistersClient>,
+}
+
+impl I2CSlaveRegisters<'a> {
+    pub fn new(
+        i2c: &'a hil::i2c::I2CSlave,
+        registers: &'static mut [u8],
+        rx_buffer: &'static mut [u8],
+        tx_buffer: &'static mut [u8],
+    ) -> I2CSlaveRegisters<'a> {
+        I2CSlaveRegisters {
+            i2c: i2c,
+            registers: TakeCell::new(registers),
+            rx_buffer: TakeCell::new(rx_buffer),
+            tx_buffer: TakeCell::new(tx_buffer),
+            index: Cell::new(0),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a RegistersClient) {
+        self.client.set(client);
+    }
+
+    /// Starts acknowledging `address` on the bus.
+    pub fn start(&self, address: u8) {
+        self.i2c.enable();
+        self.i2c.set_address(address);
+        self.rx_buffer.take().map(|buffer| {
+            let len = cmp::min(buffer.len(), 255);
+            self.i2c.write_receive(buffer, len as u8);
+        });
+        self.i2c.listen();
+    }
+
+    /// Stops responding to the master.
+    pub fn stop(&self) {
+        self.i2c.disable();
+    }
+
+    /// Sets the value the master reads from register `index`.
+    pub fn set_register(&self, index: usize, value: u8) -> ReturnCode {
+        self.registers.map_or(ReturnCode::EINVAL, |registers| {
+            if index < registers.len() {
+                registers[index] = value;
+                ReturnCode::SUCCESS
+            } else {
+                ReturnCode::EINVAL
+            }
+        })
+    }
+
+    /// The value of register `index`, as last set or written by the master.
+    pub fn register(&self, index: usize) -> Option<u8> {
+        self.registers
+            .and_then(|registers| registers.get(index).cloned())
+    }
+}
+
+impl hil::i2c::I2CHwSlaveClient for I2CSlaveRegisters<'a> {
+    fn command_complete(
+        &self,
+        buffer: &'static mut [u8],
+        length: u8,
+        transmission_type: hil::i2c::SlaveTransmissionType,
+    ) {
+        match transmission_type {
+            hil::i2c::SlaveTransmissionType::Write => {
+                let length = cmp::min(length as usize, buffer.len());
+                let mut written = None;
+                if length > 0 {
+                    self.registers.map(|registers| {
+                        let start = buffer[0] as usize % registers.len();
+                        for (i, value) in buffer[1..length].iter().enumerate() {
+                            registers[(start + i) % registers.len()] = *value;
+                        }
+                        self.index.set(start);
+                        written = Some((start, length - 1));
+                    });
+                }
+
+                // Ready for the next write
+                let len = cmp::min(buffer.len(), 255);
+                self.i2c.write_receive(buffer, len as u8);
+
+                written.map(|(start, len)| {
+                    if len > 0 {
+                        self.client
+                            .map(|client| client.registers_written(start, len));
+                    }
+                });
+            }
+
+            hil::i2c::SlaveTransmissionType::Read => {
+                self.registers.map(|registers| {
+                    self.index
+                        .set((self.index.get() + length as usize) % registers.len());
+                });
+                // Keep the buffer until the next read, so that its values
+                // are copied when the master asks for them
+                self.tx_buffer.replace(buffer);
+            }
+        }
+    }
+
+    fn read_expected(&self) {
+        self.tx_buffer.take().map(|buffer| {
+            let index = self.index.get();
+            self.registers.map(|registers| {
+                for (i, value) in buffer.iter_mut().enumerate() {
+                    *value = registers[(index + i) % registers.len()];
+                }
+            });
+            let len = cmp::min(buffer.len(), 255);
+            self.i2c.read_send(buffer, len as u8);
+        });
+    }
+
+    fn write_expected(&self) {
+        self.rx_buffer.take().map(|buffer| {
+            let len = cmp::min(buffer.len(), 255);
+            self.i2c.write_receive(buffer, len as u8);
+        });
+    }
+}
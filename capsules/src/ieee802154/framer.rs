@@ -189,13 +189,15 @@ impl FrameInfo {
             // m data is the private payload field
             (
                 private_payload_offset,
-                self.unsecured_length() | private_payload_offset,
+                self.unsecured_length() - private_payload_offset,
             )
         }
     }
 }
 
-fn get_ccm_nonce(device_addr: &[u8; 8], frame_counter: u32, level: SecurityLevel) -> [u8; 13] {
+/// The CCM* nonce of a frame secured by the device with extended address
+/// `device_addr`. Thread secures MLE messages with the same nonce.
+pub fn get_ccm_nonce(device_addr: &[u8; 8], frame_counter: u32, level: SecurityLevel) -> [u8; 13] {
     let mut nonce = [0u8; 13];
     let encode_ccm_nonce = |buf: &mut [u8]| {
         let off = enc_consume!(buf; encode_bytes, device_addr.as_ref());
@@ -298,6 +300,8 @@ pub struct Framer<'a, M: Mac, A: AES128CCM<'a>> {
 
     /// KeyDescriptor lookup procedure
     key_procedure: OptionalCell<&'a KeyProcedure>,
+    /// Frame counter of the next secured outgoing frame
+    frame_counter: Cell<u32>,
     /// DeviceDescriptor lookup procedure
     device_procedure: OptionalCell<&'a DeviceProcedure>,
 
@@ -321,6 +325,7 @@ impl<M: Mac, A: AES128CCM<'a>> Framer<'a, M, A> {
             aes_ccm: aes_ccm,
             data_sequence: Cell::new(0),
             key_procedure: OptionalCell::empty(),
+            frame_counter: Cell::new(0),
             device_procedure: OptionalCell::empty(),
             tx_state: MapCell::new(TxState::Idle),
             tx_client: OptionalCell::empty(),
@@ -583,7 +588,7 @@ impl<M: Mac, A: AES128CCM<'a>> Framer<'a, M, A> {
                                     m_len,
                                     info.mic_len,
                                     level.encryption_needed(),
-                                    true,
+                                    false,
                                 );
                                 match res {
                                     ReturnCode::SUCCESS => (RxState::Decrypting(info), None),
@@ -703,9 +708,14 @@ impl<M: Mac, A: AES128CCM<'a>> MacDevice<'a> for Framer<'a, M, A> {
         // specification.
         let src_addr_long = self.get_address_long();
         let security_desc = security_needed.and_then(|(level, key_id)| {
+            // Every secured frame needs a fresh nonce, so a frame counter is
+            // never reused. Once it is exhausted, no more frames are secured.
+            let frame_counter = self.frame_counter.get();
+            if frame_counter == 0xffffffff {
+                return None;
+            }
             self.lookup_key(level, key_id).map(|key| {
-                // TODO: lookup frame counter for device
-                let frame_counter = 0;
+                self.frame_counter.set(frame_counter + 1);
                 let nonce = get_ccm_nonce(&src_addr_long, frame_counter, level);
                 (
                     Security {
@@ -720,7 +730,8 @@ impl<M: Mac, A: AES128CCM<'a>> MacDevice<'a> for Framer<'a, M, A> {
             })
         });
         if security_needed.is_some() && security_desc.is_none() {
-            // If security was requested, fail when desired key was not found.
+            // If security was requested, fail when desired key was not found
+            // or the frame counter is exhausted.
             return Err(buf);
         }
 
@@ -0,0 +1,762 @@
+//! Keystore system call interface
+//!
+//! This capsule keeps secret keys in flash and lets processes use them by
+//! handle without ever reading them. A key is stored with the package name
+//! of the process that may use it, as in the key-value store, and with its
+//! kind, which decides what it can be used for. A process can import a key,
+//! have the kernel generate one from the CSPRNG, so that the key never
+//! exists in process memory, delete its keys, and compute and check
+//! HMAC-SHA256 codes with them. Other capsules use keys through
+//! `KeyProvider`, for example the AES driver, which encrypts with a key
+//! selected by handle. The board can provision keys for a process with
+//! `provision`.
+//!
+//! Package names are taken from the TBF headers of processes, which anyone
+//! who can install an app can write. The keystore therefore only serves
+//! processes whose app passed the `AppVerifier` the board gave to
+//! `load_processes()`, which checks that the app, header included, is signed
+//! by a key of the board. On boards that load apps without an `AppVerifier`,
+//! no process can use the keystore.
+//!
+//! Keys are only kept in the flash pages of the store, which are outside the
+//! flash regions of processes, and in the page buffer of the capsule, which
+//! is kernel memory outside the RAM regions of processes, so the MPU keeps
+//! processes from reading them. Keys are only copied onto the kernel stack
+//! to be used, and never into buffers shared with processes.
+//!
+//! All keys are kept in one flash page, written as in the key-value store:
+//! a change writes the whole store to the other of two flash pages, with a
+//! sequence number and a CRC-32, so that if the board loses power while
+//! writing, the store is found as it was before the change. Each key takes
+//! `ENTRY_SIZE` bytes of the page, so processes with package names longer
+//! than `MAX_NAME_LEN` bytes cannot use the keystore.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! pub static mut KEYSTORE_PAGE: nrf52::nvmc::NrfPage = nrf52::nvmc::NrfPage::new();
+//!
+//! kernel::procs::load_processes(
+//!     board_kernel,
+//!     &cortexm4::syscall::SysCall::new(),
+//!     chip.mpu(),
+//!     &_sapps as *const u8,
+//!     &mut APP_MEMORY,
+//!     &FAULT_RESPONSE,
+//!     Some(app_verifier),
+//!     &process_management_capability,
+//! );
+//!
+//! let keystore = static_init!(
+//!     capsules::keystore::KeyStore<'static, nrf52::nvmc::Nvmc>,
+//!     capsules::keystore::KeyStore::new(
+//!         &nrf52::nvmc::NVMC,
+//!         KEYSTORE_FIRST_PAGE,
+//!         &mut KEYSTORE_PAGE,
+//!         csprng,
+//!         introspection,
+//!         &process_mgmt_cap,
+//!         board_kernel.create_grant(&memory_allocation_capability)
+//!     )
+//! );
+//! hil::flash::HasClient::set_client(&nrf52::nvmc::NVMC, keystore);
+//! keystore.mount();
+//! aes.set_key_provider(keystore);
+//! ```
+
+use core::cell::Cell;
+use crypto::hmac_sha256::{HmacSha256, DIGEST_LEN};
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil;
+use kernel::hil::csprng::Csprng;
+use kernel::introspection::Introspection;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+use log_storage::crc32;
+
+/// Syscall number
+pub const DRIVER_NUM: usize = 0x40003;
+
+pub const MAX_KEY_LEN: usize = 32;
+pub const MAX_NAME_LEN: usize = 32;
+
+/// Each key: its handle, its kind, its length, the length of the package
+/// name of its process, a byte of padding, `MAX_NAME_LEN` bytes for the name
+/// and `MAX_KEY_LEN` bytes for the key
+pub const ENTRY_SIZE: usize = 8 + MAX_NAME_LEN + MAX_KEY_LEN;
+
+/// The offsets of the fields of an entry
+const KIND_OFFSET: usize = 4;
+const KEY_LEN_OFFSET: usize = 5;
+const NAME_LEN_OFFSET: usize = 6;
+const NAME_OFFSET: usize = 8;
+const KEY_OFFSET: usize = NAME_OFFSET + MAX_NAME_LEN;
+
+/// Identifies a page holding a store
+const MAGIC: u32 = 0x3253_594B;
+
+/// The header of a page: the magic number, the CRC-32 of the rest of the
+/// used part of the page, the sequence number of the page, and the length of
+/// its used part
+const HEADER_SIZE: usize = 16;
+
+// Commands, which are also passed to the callback
+const COMMAND_IMPORT: usize = 1;
+const COMMAND_GENERATE: usize = 2;
+const COMMAND_DELETE: usize = 3;
+
+/// What a key can be used for.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum KeyKind {
+    /// An AES-128 key, 16 bytes
+    Aes128 = 1,
+    /// An HMAC-SHA256 key, 32 bytes
+    HmacSha256 = 2,
+}
+
+impl KeyKind {
+    pub fn from_usize(kind: usize) -> Option<KeyKind> {
+        match kind {
+            1 => Some(KeyKind::Aes128),
+            2 => Some(KeyKind::HmacSha256),
+            _ => None,
+        }
+    }
+
+    /// The length of keys of this kind, in bytes.
+    pub fn key_len(&self) -> usize {
+        match *self {
+            KeyKind::Aes128 => 16,
+            KeyKind::HmacSha256 => 32,
+        }
+    }
+}
+
+/// Gives capsules the keys that processes may use.
+pub trait KeyProvider {
+    /// Copies the key with `handle` into `key`, if it is of `kind` and
+    /// `appid` may use it. Returns FAIL if there is no such key, so that
+    /// processes cannot learn which handles other processes use, and ESIZE
+    /// if `key` is not as long as keys of `kind`.
+    fn copy_key(&self, appid: AppId, handle: usize, kind: KeyKind, key: &mut [u8]) -> ReturnCode;
+}
+
+fn read_u32(buffer: &[u8], offset: usize) -> u32 {
+    buffer[offset] as u32
+        | (buffer[offset + 1] as u32) << 8
+        | (buffer[offset + 2] as u32) << 16
+        | (buffer[offset + 3] as u32) << 24
+}
+
+fn write_u32(buffer: &mut [u8], offset: usize, value: u32) {
+    for i in 0..4 {
+        buffer[offset + i] = (value >> (8 * i)) as u8;
+    }
+}
+
+/// The sequence number of the store in `page`, if it holds one.
+fn page_sequence(page: &[u8]) -> Option<u32> {
+    let used = read_u32(page, 12) as usize;
+    if read_u32(page, 0) != MAGIC
+        || used < HEADER_SIZE
+        || used > page.len()
+        || (used - HEADER_SIZE) % ENTRY_SIZE != 0
+    {
+        return None;
+    }
+    if crc32(&page[8..used]) != read_u32(page, 4) {
+        return None;
+    }
+    Some(read_u32(page, 8))
+}
+
+/// Makes `page` an empty store, unless it holds one.
+fn load_page(page: &mut [u8]) {
+    if page_sequence(page).is_none() {
+        for byte in page.iter_mut() {
+            *byte = 0;
+        }
+        write_u32(page, 0, MAGIC);
+        write_u32(page, 12, HEADER_SIZE as u32);
+    }
+}
+
+fn used(page: &[u8]) -> usize {
+    read_u32(page, 12) as usize
+}
+
+/// The offset of the key with `handle`.
+fn find(page: &[u8], handle: usize) -> Option<usize> {
+    (HEADER_SIZE..used(page))
+        .step_by(ENTRY_SIZE)
+        .find(|&offset| read_u32(page, offset) as usize == handle)
+}
+
+/// An unused handle, one more than the largest in `page`. Handles are never
+/// 0.
+fn next_handle(page: &[u8]) -> Option<u32> {
+    (HEADER_SIZE..used(page))
+        .step_by(ENTRY_SIZE)
+        .map(|offset| read_u32(page, offset))
+        .max()
+        .unwrap_or(0)
+        .checked_add(1)
+}
+
+/// Whether the key at `offset` in `page` belongs to `namespace`.
+fn belongs_to(page: &[u8], offset: usize, namespace: &[u8]) -> bool {
+    let name_len = page[offset + NAME_LEN_OFFSET] as usize;
+    let name = offset + NAME_OFFSET;
+    name_len == namespace.len() && &page[name..name + name_len] == namespace
+}
+
+/// Removes the key at `offset` from `page`, and clears the end of the page
+/// so that no copy of the key is left.
+fn remove(page: &mut [u8], offset: usize) {
+    let used = used(page);
+    for i in offset..used - ENTRY_SIZE {
+        page[i] = page[i + ENTRY_SIZE];
+    }
+    for byte in page[used - ENTRY_SIZE..used].iter_mut() {
+        *byte = 0;
+    }
+    write_u32(page, 12, (used - ENTRY_SIZE) as u32);
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Unmounted,
+    /// Reading the two pages to find the newest store
+    Mount { page: usize },
+    /// Reading the newest store, if it is the first page
+    MountLoad,
+    Idle,
+    /// Writing the changed store to the other page
+    Commit { page: usize, sequence: u32 },
+    /// Reading back the store after a change could not be written
+    Restore,
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    key: Option<AppSlice<Shared, u8>>,
+    data: Option<AppSlice<Shared, u8>>,
+    mac: Option<AppSlice<Shared, u8>>,
+}
+
+pub struct KeyStore<'a, F: hil::flash::Flash + 'static> {
+    flash: &'a F,
+    /// The first of the two flash pages of the store
+    first_page: usize,
+    /// The store, unless it is being read or written
+    page: TakeCell<'static, F::Page>,
+    state: Cell<State>,
+    /// The page holding the newest store, and its sequence number
+    current: Cell<usize>,
+    sequence: Cell<u32>,
+    /// The sequence number of the first page, if it holds a store
+    first_sequence: Cell<Option<u32>>,
+    csprng: &'a Csprng,
+    introspection: &'a Introspection,
+    capability: &'a ProcessManagementCapability,
+    apps: Grant<App>,
+    /// The process whose change is written, the change, and the handle of
+    /// the key changed
+    owner: OptionalCell<AppId>,
+    command: Cell<usize>,
+    handle: Cell<usize>,
+}
+
+impl<F: hil::flash::Flash> KeyStore<'a, F> {
+    pub fn new(
+        flash: &'a F,
+        first_page: usize,
+        page: &'static mut F::Page,
+        csprng: &'a Csprng,
+        introspection: &'a Introspection,
+        capability: &'a ProcessManagementCapability,
+        apps: Grant<App>,
+    ) -> KeyStore<'a, F> {
+        KeyStore {
+            flash: flash,
+            first_page: first_page,
+            page: TakeCell::new(page),
+            state: Cell::new(State::Unmounted),
+            current: Cell::new(1),
+            sequence: Cell::new(0),
+            first_sequence: Cell::new(None),
+            csprng: csprng,
+            introspection: introspection,
+            capability: capability,
+            apps: apps,
+            owner: OptionalCell::empty(),
+            command: Cell::new(0),
+            handle: Cell::new(0),
+        }
+    }
+
+    /// Finds the newest store in flash. Keys can be used once both pages
+    /// were read.
+    pub fn mount(&self) -> ReturnCode {
+        if self.state.get() != State::Unmounted {
+            return ReturnCode::EALREADY;
+        }
+        self.read_page(0, State::Mount { page: 0 })
+    }
+
+    /// Stores `key` of `kind` for the process with package name
+    /// `package_name`, and returns its handle. The key is written to flash
+    /// in the background, and is lost if writing fails.
+    pub fn provision(&self, package_name: &str, kind: KeyKind, key: &[u8]) -> ReturnCode {
+        let result = self.ready();
+        if result != ReturnCode::SUCCESS {
+            return result;
+        }
+        if package_name.is_empty()
+            || package_name.len() > MAX_NAME_LEN
+            || key.len() != kind.key_len()
+        {
+            return ReturnCode::EINVAL;
+        }
+        let handle = match self.insert(package_name.as_bytes(), kind, key) {
+            Ok(handle) => handle,
+            Err(err) => return err,
+        };
+        let result = self.commit();
+        if result != ReturnCode::SUCCESS {
+            return result;
+        }
+        ReturnCode::SuccessWithValue { value: handle }
+    }
+
+    fn read_page(&self, index: usize, state: State) -> ReturnCode {
+        self.page.take().map_or(ReturnCode::EBUSY, |page| {
+            let result = self.flash.read_page(self.first_page + index, page);
+            if result == ReturnCode::SUCCESS {
+                self.state.set(state);
+            }
+            result
+        })
+    }
+
+    fn ready(&self) -> ReturnCode {
+        match self.state.get() {
+            State::Idle => ReturnCode::SUCCESS,
+            State::Unmounted | State::Mount { .. } | State::MountLoad => ReturnCode::EOFF,
+            _ => ReturnCode::EBUSY,
+        }
+    }
+
+    /// The namespace of the keys of `appid`, which is its package name if
+    /// its app passed the `AppVerifier` of the board.
+    fn namespace(&self, appid: AppId) -> Option<&'static [u8]> {
+        self.introspection
+            .verified_process_name(appid, self.capability)
+            .filter(|name| !name.is_empty() && name.len() <= MAX_NAME_LEN)
+            .map(|name| name.as_bytes())
+    }
+
+    /// Copies the key with `handle` into `key` if it is of `kind` and
+    /// belongs to `namespace`.
+    fn copy(&self, namespace: &[u8], handle: usize, kind: KeyKind, key: &mut [u8]) -> ReturnCode {
+        if key.len() != kind.key_len() {
+            return ReturnCode::ESIZE;
+        }
+        self.page.map_or(ReturnCode::EBUSY, |page| {
+            let page = page.as_mut();
+            match find(page, handle) {
+                Some(offset)
+                    if belongs_to(page, offset, namespace)
+                        && page[offset + KIND_OFFSET] == kind as u8 =>
+                {
+                    let start = offset + KEY_OFFSET;
+                    key.copy_from_slice(&page[start..start + kind.key_len()]);
+                    ReturnCode::SUCCESS
+                }
+                _ => ReturnCode::FAIL,
+            }
+        })
+    }
+
+    /// Adds `key` of `kind` for `namespace` to the store in memory, and
+    /// returns its handle.
+    fn insert(&self, namespace: &[u8], kind: KeyKind, key: &[u8]) -> Result<usize, ReturnCode> {
+        self.page.map_or(Err(ReturnCode::EBUSY), |page| {
+            let page = page.as_mut();
+            let offset = used(page);
+            if offset + ENTRY_SIZE > page.len() {
+                return Err(ReturnCode::ENOMEM);
+            }
+            let handle = next_handle(page).ok_or(ReturnCode::ENOMEM)?;
+            for byte in page[offset..offset + ENTRY_SIZE].iter_mut() {
+                *byte = 0;
+            }
+            write_u32(page, offset, handle);
+            page[offset + KIND_OFFSET] = kind as u8;
+            page[offset + KEY_LEN_OFFSET] = key.len() as u8;
+            page[offset + NAME_LEN_OFFSET] = namespace.len() as u8;
+            let name = offset + NAME_OFFSET;
+            page[name..name + namespace.len()].copy_from_slice(namespace);
+            let start = offset + KEY_OFFSET;
+            page[start..start + key.len()].copy_from_slice(key);
+            write_u32(page, 12, (offset + ENTRY_SIZE) as u32);
+            Ok(handle as usize)
+        })
+    }
+
+    /// Writes the changed store to the page that does not hold the newest
+    /// store.
+    fn commit(&self) -> ReturnCode {
+        let index = 1 - self.current.get();
+        let sequence = self.sequence.get().wrapping_add(1);
+        self.page.take().map_or(ReturnCode::EBUSY, |page| {
+            {
+                let contents = page.as_mut();
+                let used = used(contents);
+                write_u32(contents, 8, sequence);
+                let crc = crc32(&contents[8..used]);
+                write_u32(contents, 4, crc);
+            }
+            let result = self.flash.write_page(self.first_page + index, page);
+            if result == ReturnCode::SUCCESS {
+                self.state.set(State::Commit {
+                    page: index,
+                    sequence: sequence,
+                });
+            }
+            result
+        })
+    }
+
+    /// Stores a key of `kind` for `appid`, either the first bytes of its key
+    /// buffer or, if `generate`, random bytes from the CSPRNG.
+    fn add(&self, appid: AppId, kind: usize, generate: bool) -> ReturnCode {
+        let namespace = match self.namespace(appid) {
+            Some(namespace) => namespace,
+            None => return ReturnCode::ENOSUPPORT,
+        };
+        let kind = match KeyKind::from_usize(kind) {
+            Some(kind) => kind,
+            None => return ReturnCode::EINVAL,
+        };
+        let key_len = kind.key_len();
+        let mut key = [0; MAX_KEY_LEN];
+        let key = &mut key[..key_len];
+        if generate {
+            let result = self.csprng.fill_bytes(key);
+            if result != ReturnCode::SUCCESS {
+                return result;
+            }
+        } else {
+            let copied = self
+                .apps
+                .enter(appid, |app, _| {
+                    app.key.as_ref().map_or(false, |slice| {
+                        slice.as_ref().get(..key_len).map_or(false, |data| {
+                            key.copy_from_slice(data);
+                            true
+                        })
+                    })
+                }).unwrap_or(false);
+            if !copied {
+                return ReturnCode::EINVAL;
+            }
+        }
+        let result = match self.insert(namespace, kind, key) {
+            Ok(handle) => {
+                self.handle.set(handle);
+                self.commit()
+            }
+            Err(err) => err,
+        };
+        for byte in key.iter_mut() {
+            *byte = 0;
+        }
+        result
+    }
+
+    /// Removes the key with `handle`, if it belongs to `appid`.
+    fn delete(&self, appid: AppId, handle: usize) -> ReturnCode {
+        let namespace = match self.namespace(appid) {
+            Some(namespace) => namespace,
+            None => return ReturnCode::ENOSUPPORT,
+        };
+        let result = self.page.map_or(ReturnCode::EBUSY, |page| {
+            let page = page.as_mut();
+            match find(page, handle) {
+                Some(offset) if belongs_to(page, offset, namespace) => {
+                    remove(page, offset);
+                    ReturnCode::SUCCESS
+                }
+                _ => ReturnCode::FAIL,
+            }
+        });
+        if result != ReturnCode::SUCCESS {
+            return result;
+        }
+        self.handle.set(handle);
+        self.commit()
+    }
+
+    /// Computes the HMAC-SHA256 code of the first `len` bytes of the data
+    /// buffer of `appid` with the key `handle`, and either writes it to the
+    /// code buffer, or compares it with the code buffer if `check`.
+    fn hmac(&self, appid: AppId, handle: usize, len: usize, check: bool) -> ReturnCode {
+        let namespace = match self.namespace(appid) {
+            Some(namespace) => namespace,
+            None => return ReturnCode::ENOSUPPORT,
+        };
+        let mut key = [0; MAX_KEY_LEN];
+        let result = self.copy(namespace, handle, KeyKind::HmacSha256, &mut key);
+        if result != ReturnCode::SUCCESS {
+            return result;
+        }
+        let mut hmac = HmacSha256::new(&key);
+        for byte in key.iter_mut() {
+            *byte = 0;
+        }
+        self.apps
+            .enter(appid, |app, _| {
+                match app.data.as_ref().and_then(|data| data.as_ref().get(..len)) {
+                    Some(data) => hmac.update(data),
+                    None => return ReturnCode::ESIZE,
+                }
+                let mut code = [0; DIGEST_LEN];
+                hmac.finish(&mut code);
+                let mac = match app
+                    .mac
+                    .as_mut()
+                    .and_then(|mac| mac.as_mut().get_mut(..DIGEST_LEN))
+                {
+                    Some(mac) => mac,
+                    None => return ReturnCode::ESIZE,
+                };
+                if !check {
+                    mac.copy_from_slice(&code);
+                    return ReturnCode::SUCCESS;
+                }
+                // Compare every byte, so that the time taken does not tell
+                // how much of a guessed code is right.
+                let difference = code
+                    .iter()
+                    .zip(mac.iter())
+                    .fold(0, |difference, (a, b)| difference | (a ^ b));
+                if difference == 0 {
+                    ReturnCode::SUCCESS
+                } else {
+                    ReturnCode::FAIL
+                }
+            }).unwrap_or_else(|err| err.into())
+    }
+
+    /// Ends the change in progress and calls back the process.
+    fn finish(&self, result: ReturnCode) {
+        self.state.set(State::Idle);
+        let command = self.command.get();
+        let handle = self.handle.get();
+        self.owner.take().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                app.callback
+                    .map(|mut cb| cb.schedule(command, usize::from(result), handle));
+            });
+        });
+    }
+}
+
+impl<F: hil::flash::Flash> KeyProvider for KeyStore<'a, F> {
+    fn copy_key(&self, appid: AppId, handle: usize, kind: KeyKind, key: &mut [u8]) -> ReturnCode {
+        if self.ready() == ReturnCode::EOFF {
+            return ReturnCode::EOFF;
+        }
+        match self.namespace(appid) {
+            Some(namespace) => self.copy(namespace, handle, kind, key),
+            None => ReturnCode::FAIL,
+        }
+    }
+}
+
+impl<F: hil::flash::Flash> Driver for KeyStore<'a, F> {
+    /// Share buffers with the keystore.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: The key to import.
+    /// - `1`: The data to compute an HMAC-SHA256 code of.
+    /// - `2`: The HMAC-SHA256 code, 32 bytes, which is written to or
+    ///        checked.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 | 1 | 2 => self
+                .apps
+                .enter(appid, |app, _| {
+                    match allow_num {
+                        0 => app.key = slice,
+                        1 => app.data = slice,
+                        _ => app.mac = slice,
+                    }
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Subscribe to the end of changes.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: An import, generation or deletion was written to flash. The
+    ///        callback is passed the command number of the change, its
+    ///        result, and the handle of the key.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        appid: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Add, delete and use keys. Kinds are 1 for AES-128 and 2 for
+    /// HMAC-SHA256 keys.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Import the first bytes of the key buffer as a key of kind
+    ///        `data1`. Returns ENOMEM if the store is full.
+    /// - `2`: Generate a key of kind `data1`. Returns EBUSY if the CSPRNG is
+    ///        not seeded yet.
+    /// - `3`: Delete the key with handle `data1`. Returns FAIL if the
+    ///        process has no such key.
+    /// - `4`: Write the HMAC-SHA256 code of the first `data2` bytes of the
+    ///        data buffer with the key `data1` to the code buffer.
+    /// - `5`: Check the code buffer against the HMAC-SHA256 code of the
+    ///        first `data2` bytes of the data buffer with the key `data1`.
+    ///        Returns FAIL if they differ.
+    /// - `6`: Get the number of keys that can still be stored.
+    fn command(&self, command_num: usize, data1: usize, data2: usize, appid: AppId) -> ReturnCode {
+        if command_num == 0 {
+            return ReturnCode::SUCCESS;
+        }
+        let result = self.ready();
+        if result != ReturnCode::SUCCESS {
+            return result;
+        }
+        match command_num {
+            COMMAND_IMPORT | COMMAND_GENERATE | COMMAND_DELETE => {
+                let result = match command_num {
+                    COMMAND_IMPORT => self.add(appid, data1, false),
+                    COMMAND_GENERATE => self.add(appid, data1, true),
+                    _ => self.delete(appid, data1),
+                };
+                if result == ReturnCode::SUCCESS {
+                    self.owner.set(appid);
+                    self.command.set(command_num);
+                }
+                result
+            }
+
+            4 => self.hmac(appid, data1, data2, false),
+
+            5 => self.hmac(appid, data1, data2, true),
+
+            6 => self.page.map_or(ReturnCode::EBUSY, |page| {
+                let page = page.as_mut();
+                ReturnCode::SuccessWithValue {
+                    value: (page.len() - used(page)) / ENTRY_SIZE,
+                }
+            }),
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+impl<F: hil::flash::Flash> hil::flash::Client<F> for KeyStore<'a, F> {
+    fn read_complete(&self, page: &'static mut F::Page, error: hil::flash::Error) {
+        let sequence = if error == hil::flash::Error::CommandComplete {
+            page_sequence(page.as_mut())
+        } else {
+            None
+        };
+        match self.state.get() {
+            State::Mount { page: 0 } => {
+                self.first_sequence.set(sequence);
+                self.page.replace(page);
+                if self.read_page(1, State::Mount { page: 1 }) != ReturnCode::SUCCESS {
+                    self.state.set(State::Idle);
+                    self.page.map(|page| load_page(page.as_mut()));
+                }
+            }
+            State::Mount { .. } => {
+                let first = self.first_sequence.get();
+                // Sequence numbers wrap around
+                let second_newer = match (first, sequence) {
+                    (_, None) => false,
+                    (None, Some(_)) => true,
+                    (Some(first), Some(second)) => (second.wrapping_sub(first) as i32) > 0,
+                };
+                self.page.replace(page);
+                if second_newer {
+                    self.current.set(1);
+                    self.sequence.set(sequence.unwrap_or(0));
+                    self.state.set(State::Idle);
+                } else if let Some(first) = first {
+                    self.current.set(0);
+                    self.sequence.set(first);
+                    if self.read_page(0, State::MountLoad) != ReturnCode::SUCCESS {
+                        self.state.set(State::Idle);
+                    }
+                } else {
+                    // There is no store yet, and the first one is written to
+                    // the first page
+                    self.page.map(|page| load_page(page.as_mut()));
+                    self.state.set(State::Idle);
+                }
+            }
+            State::MountLoad => {
+                load_page(page.as_mut());
+                self.page.replace(page);
+                self.state.set(State::Idle);
+            }
+            State::Restore => {
+                load_page(page.as_mut());
+                self.page.replace(page);
+                self.finish(ReturnCode::FAIL);
+            }
+            _ => {
+                self.page.replace(page);
+            }
+        }
+    }
+
+    fn write_complete(&self, page: &'static mut F::Page, error: hil::flash::Error) {
+        self.page.replace(page);
+        if let State::Commit { page, sequence } = self.state.get() {
+            if error == hil::flash::Error::CommandComplete {
+                self.current.set(page);
+                self.sequence.set(sequence);
+                self.finish(ReturnCode::SUCCESS);
+            } else if self.read_page(self.current.get(), State::Restore) != ReturnCode::SUCCESS {
+                self.finish(ReturnCode::FAIL);
+            }
+        }
+    }
+
+    fn erase_complete(&self, _error: hil::flash::Error) {}
+}
@@ -0,0 +1,594 @@
+//! Key-value store system call interface
+//!
+//! This capsule lets processes keep small key-value pairs in flash, for
+//! example their configuration, without access to raw flash. Each process
+//! has its own namespace, so processes cannot see or change each other's
+//! pairs. Pairs are stored with the package name of their process, so a
+//! process keeps its pairs across reboots and updates, and processes without
+//! a package name, or with one longer than 255 bytes, cannot use the store.
+//! Since the capsule reads the names of processes, it can only be created
+//! with the `ProcessManagementCapability`.
+//!
+//! Any app can put any package name in its TBF header, so the name alone
+//! does not keep an app from the pairs of another. The store therefore only
+//! serves processes whose app passed the `AppVerifier` the board gave to
+//! `load_processes()`, which checks that the app, header included, is signed
+//! by a key of the board. On boards that load apps without an `AppVerifier`,
+//! no process can use the store.
+//!
+//! All pairs are kept in one flash page, with a copy in memory that values
+//! are read from. A change writes the whole store to the other of two flash
+//! pages, with a sequence number and a CRC-32, so that if the board loses
+//! power while writing, the store is found as it was before the change.
+//! Keys are at most `MAX_KEY_LEN` bytes long and values `MAX_VALUE_LEN`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! pub static mut KV_PAGE: nrf52::nvmc::NrfPage = nrf52::nvmc::NrfPage::new();
+//!
+//! kernel::procs::load_processes(
+//!     board_kernel,
+//!     &cortexm4::syscall::SysCall::new(),
+//!     chip.mpu(),
+//!     &_sapps as *const u8,
+//!     &mut APP_MEMORY,
+//!     &FAULT_RESPONSE,
+//!     Some(app_verifier),
+//!     &process_management_capability,
+//! );
+//!
+//! let kv_store = static_init!(
+//!     capsules::kv_store::KVStore<'static, nrf52::nvmc::Nvmc>,
+//!     capsules::kv_store::KVStore::new(
+//!         &nrf52::nvmc::NVMC,
+//!         KV_FIRST_PAGE,
+//!         &mut KV_PAGE,
+//!         introspection,
+//!         &process_mgmt_cap,
+//!         board_kernel.create_grant(&memory_allocation_capability)
+//!     )
+//! );
+//! hil::flash::HasClient::set_client(&nrf52::nvmc::NVMC, kv_store);
+//! kv_store.mount();
+//! ```
+
+use core::cell::Cell;
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil;
+use kernel::introspection::Introspection;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+use log_storage::crc32;
+
+/// Syscall number
+pub const DRIVER_NUM: usize = 0x50006;
+
+pub const MAX_KEY_LEN: usize = 32;
+pub const MAX_VALUE_LEN: usize = 128;
+
+/// Identifies a page holding a store
+const MAGIC: u32 = 0x3256_4B54;
+
+/// The header of a page: the magic number, the CRC-32 of the rest of the
+/// used part of the page, the sequence number of the page, and the length of
+/// its used part
+const HEADER_SIZE: usize = 16;
+
+/// The header of each pair: the lengths of the package name of its process,
+/// of its key and of its value, which follow it in that order
+const PAIR_HEADER_SIZE: usize = 3;
+
+// Commands, which are also passed to the callback
+const COMMAND_SET: usize = 2;
+const COMMAND_DELETE: usize = 3;
+
+fn read_u32(buffer: &[u8], offset: usize) -> u32 {
+    buffer[offset] as u32
+        | (buffer[offset + 1] as u32) << 8
+        | (buffer[offset + 2] as u32) << 16
+        | (buffer[offset + 3] as u32) << 24
+}
+
+fn write_u32(buffer: &mut [u8], offset: usize, value: u32) {
+    for i in 0..4 {
+        buffer[offset + i] = (value >> (8 * i)) as u8;
+    }
+}
+
+/// The sequence number of the store in `page`, if it holds one.
+fn page_sequence(page: &[u8]) -> Option<u32> {
+    let used = read_u32(page, 12) as usize;
+    if read_u32(page, 0) != MAGIC || used < HEADER_SIZE || used > page.len() {
+        return None;
+    }
+    if crc32(&page[8..used]) != read_u32(page, 4) {
+        return None;
+    }
+    Some(read_u32(page, 8))
+}
+
+/// Makes `page` an empty store, unless it holds one.
+fn load_page(page: &mut [u8]) {
+    if page_sequence(page).is_none() {
+        for byte in page.iter_mut() {
+            *byte = 0;
+        }
+        write_u32(page, 0, MAGIC);
+        write_u32(page, 12, HEADER_SIZE as u32);
+    }
+}
+
+fn used(page: &[u8]) -> usize {
+    read_u32(page, 12) as usize
+}
+
+/// The offset and length of the pair with `key` in `namespace`, including
+/// its header.
+fn find(page: &[u8], namespace: &[u8], key: &[u8]) -> Option<(usize, usize)> {
+    let used = used(page);
+    let mut offset = HEADER_SIZE;
+    while offset + PAIR_HEADER_SIZE <= used {
+        let name_len = page[offset] as usize;
+        let key_len = page[offset + 1] as usize;
+        let value_len = page[offset + 2] as usize;
+        let length = PAIR_HEADER_SIZE + name_len + key_len + value_len;
+        let name_start = offset + PAIR_HEADER_SIZE;
+        let key_start = name_start + name_len;
+        if &page[name_start..key_start] == namespace && &page[key_start..key_start + key_len] == key
+        {
+            return Some((offset, length));
+        }
+        offset += length;
+    }
+    None
+}
+
+/// Removes the `length` bytes at `offset` from the pairs in `page`.
+fn remove(page: &mut [u8], offset: usize, length: usize) {
+    let used = used(page);
+    for i in offset..used - length {
+        page[i] = page[i + length];
+    }
+    write_u32(page, 12, (used - length) as u32);
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Unmounted,
+    /// Reading the two pages to find the newest store
+    Mount { page: usize },
+    /// Reading the newest store, if it is the first page
+    MountLoad,
+    Idle,
+    /// Writing the changed store to the other page
+    Commit { page: usize, sequence: u32 },
+    /// Reading back the store after a change could not be written
+    Restore,
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    key: Option<AppSlice<Shared, u8>>,
+    value: Option<AppSlice<Shared, u8>>,
+}
+
+pub struct KVStore<'a, F: hil::flash::Flash + 'static> {
+    flash: &'a F,
+    /// The first of the two flash pages of the store
+    first_page: usize,
+    /// The store, unless it is being read or written
+    page: TakeCell<'static, F::Page>,
+    state: Cell<State>,
+    /// The page holding the newest store, and its sequence number
+    current: Cell<usize>,
+    sequence: Cell<u32>,
+    /// The sequence number of the first page, if it holds a store
+    first_sequence: Cell<Option<u32>>,
+    introspection: &'a Introspection,
+    capability: &'a ProcessManagementCapability,
+    apps: Grant<App>,
+    /// The process whose change is written, and the change
+    owner: OptionalCell<AppId>,
+    command: Cell<usize>,
+}
+
+impl<F: hil::flash::Flash> KVStore<'a, F> {
+    pub fn new(
+        flash: &'a F,
+        first_page: usize,
+        page: &'static mut F::Page,
+        introspection: &'a Introspection,
+        capability: &'a ProcessManagementCapability,
+        apps: Grant<App>,
+    ) -> KVStore<'a, F> {
+        KVStore {
+            flash: flash,
+            first_page: first_page,
+            page: TakeCell::new(page),
+            state: Cell::new(State::Unmounted),
+            current: Cell::new(1),
+            sequence: Cell::new(0),
+            first_sequence: Cell::new(None),
+            introspection: introspection,
+            capability: capability,
+            apps: apps,
+            owner: OptionalCell::empty(),
+            command: Cell::new(0),
+        }
+    }
+
+    /// Finds the newest store in flash. The store can be used once both
+    /// pages were read.
+    pub fn mount(&self) -> ReturnCode {
+        if self.state.get() != State::Unmounted {
+            return ReturnCode::EALREADY;
+        }
+        self.read_page(0, State::Mount { page: 0 })
+    }
+
+    fn read_page(&self, index: usize, state: State) -> ReturnCode {
+        self.page.take().map_or(ReturnCode::EBUSY, |page| {
+            let result = self.flash.read_page(self.first_page + index, page);
+            if result == ReturnCode::SUCCESS {
+                self.state.set(state);
+            }
+            result
+        })
+    }
+
+    fn ready(&self) -> ReturnCode {
+        match self.state.get() {
+            State::Idle => ReturnCode::SUCCESS,
+            State::Unmounted | State::Mount { .. } | State::MountLoad => ReturnCode::EOFF,
+            _ => ReturnCode::EBUSY,
+        }
+    }
+
+    /// The namespace of the pairs of `appid`, which is its package name if
+    /// its app passed the `AppVerifier` of the board.
+    fn namespace(&self, appid: AppId) -> Option<&'static [u8]> {
+        self.introspection
+            .verified_process_name(appid, self.capability)
+            .filter(|name| !name.is_empty() && name.len() <= u8::max_value() as usize)
+            .map(|name| name.as_bytes())
+    }
+
+    /// Fills `into` from the start of the key or value buffer of the process.
+    /// Returns false if the buffer is too short.
+    fn copy_from_app(&self, appid: AppId, value: bool, into: &mut [u8]) -> bool {
+        self.apps
+            .enter(appid, |app, _| {
+                let slice = if value { &app.value } else { &app.key };
+                slice.as_ref().map_or(false, |slice| {
+                    slice.as_ref().get(..into.len()).map_or(false, |data| {
+                        into.copy_from_slice(data);
+                        true
+                    })
+                })
+            }).unwrap_or(false)
+    }
+
+    /// Writes the changed store to the page that does not hold the newest
+    /// store.
+    fn commit(&self) -> ReturnCode {
+        let index = 1 - self.current.get();
+        let sequence = self.sequence.get().wrapping_add(1);
+        self.page.take().map_or(ReturnCode::EBUSY, |page| {
+            {
+                let contents = page.as_mut();
+                let used = used(contents);
+                write_u32(contents, 8, sequence);
+                let crc = crc32(&contents[8..used]);
+                write_u32(contents, 4, crc);
+            }
+            let result = self.flash.write_page(self.first_page + index, page);
+            if result == ReturnCode::SUCCESS {
+                self.state.set(State::Commit {
+                    page: index,
+                    sequence: sequence,
+                });
+            }
+            result
+        })
+    }
+
+    /// Copies the value of `key` into the value buffer of the process.
+    fn get(&self, appid: AppId, key_len: usize) -> ReturnCode {
+        let namespace = match self.namespace(appid) {
+            Some(namespace) => namespace,
+            None => return ReturnCode::ENOSUPPORT,
+        };
+        let mut key = [0; MAX_KEY_LEN];
+        if key_len == 0
+            || key_len > MAX_KEY_LEN
+            || !self.copy_from_app(appid, false, &mut key[..key_len])
+        {
+            return ReturnCode::EINVAL;
+        }
+        self.page.map_or(ReturnCode::EBUSY, |page| {
+            let page = page.as_mut();
+            let (offset, length) = match find(page, namespace, &key[..key_len]) {
+                Some(pair) => pair,
+                None => return ReturnCode::FAIL,
+            };
+            let value_start = offset + PAIR_HEADER_SIZE + namespace.len() + key_len;
+            let value = &page[value_start..offset + length];
+            self.apps
+                .enter(appid, |app, _| {
+                    app.value
+                        .as_mut()
+                        .and_then(|slice| slice.as_mut().get_mut(..value.len()))
+                        .map_or(ReturnCode::ESIZE, |data| {
+                            data.copy_from_slice(value);
+                            ReturnCode::SuccessWithValue { value: value.len() }
+                        })
+                }).unwrap_or_else(|err| err.into())
+        })
+    }
+
+    /// Stores `value_len` bytes of the value buffer of the process as the
+    /// value of `key`, replacing its value if it has one.
+    fn set(&self, appid: AppId, key_len: usize, value_len: usize) -> ReturnCode {
+        let namespace = match self.namespace(appid) {
+            Some(namespace) => namespace,
+            None => return ReturnCode::ENOSUPPORT,
+        };
+        let mut key = [0; MAX_KEY_LEN];
+        let mut value = [0; MAX_VALUE_LEN];
+        if key_len == 0
+            || key_len > MAX_KEY_LEN
+            || value_len > MAX_VALUE_LEN
+            || !self.copy_from_app(appid, false, &mut key[..key_len])
+            || !self.copy_from_app(appid, true, &mut value[..value_len])
+        {
+            return ReturnCode::EINVAL;
+        }
+        let (key, value) = (&key[..key_len], &value[..value_len]);
+
+        let result = self.page.map_or(ReturnCode::EBUSY, |page| {
+            let page = page.as_mut();
+            let old = find(page, namespace, key);
+            let length = PAIR_HEADER_SIZE + namespace.len() + key_len + value_len;
+            let free = page.len() - used(page) + old.map_or(0, |(_, length)| length);
+            if length > free {
+                return ReturnCode::ENOMEM;
+            }
+            old.map(|(offset, length)| remove(page, offset, length));
+
+            let offset = used(page);
+            page[offset] = namespace.len() as u8;
+            page[offset + 1] = key_len as u8;
+            page[offset + 2] = value_len as u8;
+            let name_start = offset + PAIR_HEADER_SIZE;
+            let key_start = name_start + namespace.len();
+            let value_start = key_start + key_len;
+            page[name_start..key_start].copy_from_slice(namespace);
+            page[key_start..value_start].copy_from_slice(key);
+            page[value_start..value_start + value_len].copy_from_slice(value);
+            write_u32(page, 12, (offset + length) as u32);
+            ReturnCode::SUCCESS
+        });
+        if result != ReturnCode::SUCCESS {
+            return result;
+        }
+        self.commit()
+    }
+
+    /// Removes `key` and its value.
+    fn delete(&self, appid: AppId, key_len: usize) -> ReturnCode {
+        let namespace = match self.namespace(appid) {
+            Some(namespace) => namespace,
+            None => return ReturnCode::ENOSUPPORT,
+        };
+        let mut key = [0; MAX_KEY_LEN];
+        if key_len == 0
+            || key_len > MAX_KEY_LEN
+            || !self.copy_from_app(appid, false, &mut key[..key_len])
+        {
+            return ReturnCode::EINVAL;
+        }
+        let result = self.page.map_or(ReturnCode::EBUSY, |page| {
+            let page = page.as_mut();
+            find(page, namespace, &key[..key_len]).map_or(ReturnCode::FAIL, |(offset, length)| {
+                remove(page, offset, length);
+                ReturnCode::SUCCESS
+            })
+        });
+        if result != ReturnCode::SUCCESS {
+            return result;
+        }
+        self.commit()
+    }
+
+    /// Ends the change in progress and calls back the process.
+    fn finish(&self, result: ReturnCode) {
+        self.state.set(State::Idle);
+        let command = self.command.get();
+        self.owner.take().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                app.callback
+                    .map(|mut cb| cb.schedule(command, usize::from(result), 0));
+            });
+        });
+    }
+}
+
+impl<F: hil::flash::Flash> Driver for KVStore<'a, F> {
+    /// Share buffers with the store.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: The key.
+    /// - `1`: The value, which is stored from or read into.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 | 1 => self
+                .apps
+                .enter(appid, |app, _| {
+                    if allow_num == 0 {
+                        app.key = slice;
+                    } else {
+                        app.value = slice;
+                    }
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Subscribe to the end of changes.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: A set or delete was written to flash. The callback is passed
+    ///        the command number of the change and its result.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        appid: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Get, set and delete pairs. Keys are the first `data1` bytes of the key
+    /// buffer.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Copy the value of the key into the value buffer. Returns the
+    ///        length of the value, FAIL if the key has no value, and ESIZE if
+    ///        the value buffer is too short.
+    /// - `2`: Set the value of the key to the first `data2` bytes of the
+    ///        value buffer. Returns ENOMEM if the store is full.
+    /// - `3`: Delete the key and its value. Returns FAIL if the key has no
+    ///        value.
+    /// - `4`: Get the number of bytes free in the store. Each pair takes 3
+    ///        bytes besides the package name of its process, its key and its
+    ///        value.
+    fn command(&self, command_num: usize, data1: usize, data2: usize, appid: AppId) -> ReturnCode {
+        if command_num == 0 {
+            return ReturnCode::SUCCESS;
+        }
+        let result = self.ready();
+        if result != ReturnCode::SUCCESS {
+            return result;
+        }
+        match command_num {
+            1 => self.get(appid, data1),
+
+            COMMAND_SET | COMMAND_DELETE => {
+                let result = if command_num == COMMAND_SET {
+                    self.set(appid, data1, data2)
+                } else {
+                    self.delete(appid, data1)
+                };
+                if result == ReturnCode::SUCCESS {
+                    self.owner.set(appid);
+                    self.command.set(command_num);
+                }
+                result
+            }
+
+            4 => self.page.map_or(ReturnCode::EBUSY, |page| {
+                let page = page.as_mut();
+                ReturnCode::SuccessWithValue {
+                    value: page.len() - used(page),
+                }
+            }),
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+impl<F: hil::flash::Flash> hil::flash::Client<F> for KVStore<'a, F> {
+    fn read_complete(&self, page: &'static mut F::Page, error: hil::flash::Error) {
+        let sequence = if error == hil::flash::Error::CommandComplete {
+            page_sequence(page.as_mut())
+        } else {
+            None
+        };
+        match self.state.get() {
+            State::Mount { page: 0 } => {
+                self.first_sequence.set(sequence);
+                self.page.replace(page);
+                if self.read_page(1, State::Mount { page: 1 }) != ReturnCode::SUCCESS {
+                    self.state.set(State::Idle);
+                    self.page.map(|page| load_page(page.as_mut()));
+                }
+            }
+            State::Mount { .. } => {
+                let first = self.first_sequence.get();
+                // Sequence numbers wrap around
+                let second_newer = match (first, sequence) {
+                    (_, None) => false,
+                    (None, Some(_)) => true,
+                    (Some(first), Some(second)) => (second.wrapping_sub(first) as i32) > 0,
+                };
+                self.page.replace(page);
+                if second_newer {
+                    self.current.set(1);
+                    self.sequence.set(sequence.unwrap_or(0));
+                    self.state.set(State::Idle);
+                } else if let Some(first) = first {
+                    self.current.set(0);
+                    self.sequence.set(first);
+                    if self.read_page(0, State::MountLoad) != ReturnCode::SUCCESS {
+                        self.state.set(State::Idle);
+                    }
+                } else {
+                    // There is no store yet, and the first one is written to
+                    // the first page
+                    self.page.map(|page| load_page(page.as_mut()));
+                    self.state.set(State::Idle);
+                }
+            }
+            State::MountLoad => {
+                load_page(page.as_mut());
+                self.page.replace(page);
+                self.state.set(State::Idle);
+            }
+            State::Restore => {
+                load_page(page.as_mut());
+                self.page.replace(page);
+                self.finish(ReturnCode::FAIL);
+            }
+            _ => {
+                self.page.replace(page);
+            }
+        }
+    }
+
+    fn write_complete(&self, page: &'static mut F::Page, error: hil::flash::Error) {
+        self.page.replace(page);
+        if let State::Commit { page, sequence } = self.state.get() {
+            if error == hil::flash::Error::CommandComplete {
+                self.current.set(page);
+                self.sequence.set(sequence);
+                self.finish(ReturnCode::SUCCESS);
+            } else if self.read_page(self.current.get(), State::Restore) != ReturnCode::SUCCESS {
+                self.finish(ReturnCode::FAIL);
+            }
+        }
+    }
+
+    fn erase_complete(&self, _error: hil::flash::Error) {}
+}
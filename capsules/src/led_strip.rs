@@ -0,0 +1,241 @@
+//! Provides userspace with an addressable LED strip, such as WS2812 LEDs.
+//!
+//! Apps keep the colors of the LEDs in a buffer they share, three bytes for
+//! red, green and blue per LED, which they write themselves or one pixel at a
+//! time with a command, and then show the first LEDs of it on the strip.
+//! Colors are gamma corrected while they are copied to the strip, so that
+//! evenly spaced values look evenly spaced in brightness, unless the app
+//! turns the correction off.
+//!
+//! The strip shows the colors of one app at a time; while colors are being
+//! shown, other apps get EBUSY.
+//!
+//! Usage
+//! -----
+//!
+//! You need a device that provides the `hil::led_strip::LedStrip` trait, and
+//! a buffer of three bytes per LED.
+//!
+//! ```rust
+//! static mut LED_STRIP_BUF: [u8; 8 * 3] = [0; 8 * 3];
+//!
+//! let led_strip = static_init!(
+//!     capsules::led_strip::LedStripDriver<'static>,
+//!     capsules::led_strip::LedStripDriver::new(
+//!         &nrf52::pwm::PWM1,
+//!         &mut LED_STRIP_BUF,
+//!         board_kernel.create_grant(&memory_allocation_capability)));
+//! hil::led_strip::LedStrip::set_client(&nrf52::pwm::PWM1, led_strip);
+//! ```
+
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::led_strip;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = 0x0000F;
+
+/// Gamma correction of each color byte, with a gamma of 2.8.
+static GAMMA: [u8; 256] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 3, 3, 3, 3, 3, 3, 3, 4, 4, 4, 4, 4, 5, 5, 5,
+    5, 6, 6, 6, 6, 7, 7, 7, 7, 8, 8, 8, 9, 9, 9, 10, 10, 10, 11, 11, 11, 12, 12, 13, 13, 13, 14, 14,
+    15, 15, 16, 16, 17, 17, 18, 18, 19, 19, 20, 20, 21, 21, 22, 22, 23, 24, 24, 25, 25, 26, 27, 27,
+    28, 29, 29, 30, 31, 32, 32, 33, 34, 35, 35, 36, 37, 38, 39, 39, 40, 41, 42, 43, 44, 45, 46, 47,
+    48, 49, 50, 50, 51, 52, 54, 55, 56, 57, 58, 59, 60, 61, 62, 63, 64, 66, 67, 68, 69, 70, 72, 73,
+    74, 75, 77, 78, 79, 81, 82, 83, 85, 86, 87, 89, 90, 92, 93, 95, 96, 98, 99, 101, 102, 104, 105,
+    107, 109, 110, 112, 114, 115, 117, 119, 120, 122, 124, 126, 127, 129, 131, 133, 135, 137, 138,
+    140, 142, 144, 146, 148, 150, 152, 154, 156, 158, 160, 162, 164, 167, 169, 171, 173, 175, 177,
+    180, 182, 184, 186, 189, 191, 193, 196, 198, 200, 203, 205, 208, 210, 213, 215, 218, 220, 223,
+    225, 228, 231, 233, 236, 239, 241, 244, 247, 249, 252, 255,
+];
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    colors: Option<AppSlice<Shared, u8>>,
+    /// Whether the app turned gamma correction off
+    raw: bool,
+}
+
+pub struct LedStripDriver<'a> {
+    strip: &'a led_strip::LedStrip,
+    apps: Grant<App>,
+    /// The colors passed to the strip, absent while they are shown
+    buffer: TakeCell<'static, [u8]>,
+    /// The number of LEDs the buffer holds
+    buffer_leds: usize,
+    /// The app whose colors are shown
+    showing: OptionalCell<AppId>,
+}
+
+impl LedStripDriver<'a> {
+    pub fn new(
+        strip: &'a led_strip::LedStrip,
+        buffer: &'static mut [u8],
+        grant: Grant<App>,
+    ) -> LedStripDriver<'a> {
+        LedStripDriver {
+            strip: strip,
+            apps: grant,
+            buffer_leds: buffer.len() / 3,
+            buffer: TakeCell::new(buffer),
+            showing: OptionalCell::empty(),
+        }
+    }
+
+    /// The number of LEDs of the strip that fit the buffer.
+    fn led_count(&self) -> usize {
+        self.strip.led_count().min(self.buffer_leds)
+    }
+
+    fn set_pixel(&self, appid: AppId, index: usize, rgb: usize) -> ReturnCode {
+        if index >= self.led_count() || rgb > 0xFFFFFF {
+            return ReturnCode::EINVAL;
+        }
+        self.apps
+            .enter(appid, |app, _| {
+                app.colors.as_mut().map_or(ReturnCode::ENOMEM, |colors| {
+                    let colors = colors.as_mut();
+                    if colors.len() < (index + 1) * 3 {
+                        return ReturnCode::EINVAL;
+                    }
+                    colors[index * 3] = (rgb >> 16) as u8;
+                    colors[index * 3 + 1] = (rgb >> 8) as u8;
+                    colors[index * 3 + 2] = rgb as u8;
+                    ReturnCode::SUCCESS
+                })
+            }).unwrap_or_else(|err| err.into())
+    }
+
+    fn show(&self, appid: AppId, count: usize) -> ReturnCode {
+        if count == 0 || count > self.led_count() {
+            return ReturnCode::EINVAL;
+        }
+        self.buffer.take().map_or(ReturnCode::EBUSY, |buffer| {
+            let rcode = self
+                .apps
+                .enter(appid, |app, _| {
+                    let raw = app.raw;
+                    app.colors.as_ref().map_or(ReturnCode::ENOMEM, |colors| {
+                        if colors.len() < count * 3 {
+                            return ReturnCode::EINVAL;
+                        }
+                        for (out, color) in buffer[..count * 3].iter_mut().zip(colors.iter()) {
+                            *out = if raw { *color } else { GAMMA[*color as usize] };
+                        }
+                        ReturnCode::SUCCESS
+                    })
+                }).unwrap_or_else(|err| err.into());
+            if rcode != ReturnCode::SUCCESS {
+                self.buffer.replace(buffer);
+                return rcode;
+            }
+
+            let (rcode, buffer) = self.strip.show(buffer, count);
+            if let Some(buffer) = buffer {
+                self.buffer.replace(buffer);
+            }
+            if rcode == ReturnCode::SUCCESS {
+                self.showing.set(appid);
+            }
+            rcode
+        })
+    }
+
+    fn set_gamma(&self, appid: AppId, enabled: bool) -> ReturnCode {
+        self.apps
+            .enter(appid, |app, _| {
+                app.raw = !enabled;
+                ReturnCode::SUCCESS
+            }).unwrap_or_else(|err| err.into())
+    }
+}
+
+impl led_strip::Client for LedStripDriver<'a> {
+    fn show_done(&self, colors: &'static mut [u8], result: ReturnCode) {
+        self.buffer.replace(colors);
+        self.showing.take().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                app.callback
+                    .map(|mut cb| cb.schedule(isize::from(result) as usize, 0, 0));
+            });
+        });
+    }
+}
+
+impl Driver for LedStripDriver<'a> {
+    /// Share the colors of the LEDs.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: The colors, three bytes for red, green and blue per LED.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.colors = slice;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Subscribe to the end of `show`.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Called with the result once the colors the app showed are on
+    ///        the strip.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Set pixels and show them.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Return the number of LEDs.
+    /// - `1`: Set the color of LED `data` in the `allow` buffer to `data2`, as
+    ///        0xRRGGBB.
+    /// - `2`: Show the colors of the first `data` LEDs of the `allow` buffer.
+    /// - `3`: Turn gamma correction on if `data` is 1, or off if it is 0.
+    fn command(&self, command_num: usize, data: usize, data2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SuccessWithValue {
+                value: self.led_count(),
+            },
+
+            1 => self.set_pixel(appid, data, data2),
+
+            2 => self.show(appid, data),
+
+            3 => match data {
+                0 => self.set_gamma(appid, false),
+                1 => self.set_gamma(appid, true),
+                _ => ReturnCode::EINVAL,
+            },
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
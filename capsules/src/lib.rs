@@ -14,51 +14,118 @@ pub mod test;
 pub mod net;
 
 pub mod adc;
+pub mod aes;
 pub mod aes_ccm;
+pub mod aes_gcm;
 pub mod alarm;
 pub mod ambient_light;
 pub mod analog_comparator;
+pub mod apds9960;
 pub mod app_flash_driver;
+pub mod app_verifier;
+pub mod audio_playback;
 pub mod ble_advertising_driver;
+pub mod ble_privacy;
 pub mod button;
+pub mod buzzer;
+pub mod can;
+pub mod cdc;
+pub mod checkpoint;
 pub mod console;
+pub mod crash_log;
 pub mod crc;
+pub mod crypto;
+pub mod csprng;
 pub mod dac;
+pub mod dac_waveform;
 pub mod debug_process_restart;
+pub mod distance;
+pub mod ds18b20;
+pub mod ds3231;
+pub mod eeprom_flash;
+pub mod enc28j60;
+pub mod fat;
+pub mod firmware_update;
 pub mod fm25cl;
+pub mod fuel_gauge;
 pub mod fxos8700cq;
 pub mod gpio;
 pub mod gpio_async;
+pub mod hc_sr04;
+pub mod hid;
+pub mod hid_user;
 pub mod humidity;
 pub mod i2c_master_slave_driver;
+pub mod i2c_slave_registers;
 pub mod ieee802154;
 pub mod isl29035;
+pub mod keystore;
+pub mod kv_store;
 pub mod led;
+pub mod led_strip;
+pub mod log_storage;
+pub mod log_storage_driver;
+pub mod lorawan;
 pub mod lps25hb;
+pub mod lsm6ds;
 pub mod ltc294x;
+pub mod max17048;
 pub mod max17205;
 pub mod mcp230xx;
+pub mod mcp2515;
+pub mod mcp4725;
+pub mod mem_stats;
+pub mod microphone;
+pub mod motion;
 pub mod mx25r6435f;
 pub mod ninedof;
+pub mod ninedof_stream;
+pub mod nonvolatile_counter;
 pub mod nonvolatile_storage_driver;
 pub mod nonvolatile_to_pages;
 pub mod nrf51822_serialization;
+pub mod onewire_gpio;
+pub mod panic_screen;
 pub mod pca9544a;
+pub mod process_console;
+pub mod process_load_console;
+pub mod proximity;
+pub mod qdec;
+pub mod qspi_flash;
+pub mod radio_sniffer;
+pub mod radio_test_console;
 pub mod rf233;
 pub mod rf233_const;
 pub mod rng;
 pub mod sdcard;
 pub mod segger_rtt;
+pub mod sensor_stream;
+pub mod servo;
+pub mod sha256;
 pub mod si7021;
+pub mod signature;
+pub mod sleep_stats;
+pub mod smbus;
 pub mod spi;
+pub mod sx127x;
 pub mod temperature;
+pub mod timestamp;
 pub mod tmp006;
+pub mod touch;
+pub mod touch_gpio;
 pub mod tsl2561;
 pub mod usb;
+pub mod usb_app_loader;
 pub mod usb_user;
 pub mod usbc_client;
+pub mod virtual_aes_ccm;
 pub mod virtual_alarm;
+pub mod virtual_analog_comparator;
+pub mod virtual_dma;
 pub mod virtual_flash;
 pub mod virtual_i2c;
 pub mod virtual_spi;
 pub mod virtual_uart;
+pub mod wall_clock;
+pub mod wall_clock_counter;
+pub mod ws2812_spi;
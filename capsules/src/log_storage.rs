@@ -0,0 +1,716 @@
+//! Log storage over flash pages.
+//!
+//! This capsule implements `hil::log::LogRead` and `hil::log::LogWrite` on a
+//! range of pages of a `hil::flash::Flash`, for example to store sensor
+//! readings until they can be sent elsewhere.
+//!
+//! Entries are appended to the newest page, which is kept in memory and
+//! written to flash when it is full or when the log is synced. Each page
+//! starts with its position in the log, and the pages are used in turn, so
+//! every page is erased equally often. A circular log then overwrites its
+//! oldest page, while a linear log is full. Each entry is stored with its
+//! length and a CRC-32 of its data, and corrupted entries are skipped when
+//! reading, along with the rest of their page. The ID of an entry is its
+//! position in the log, and keeps growing when the log wraps around.
+//!
+//! When the board boots, `mount` reads each page to find the newest one, and
+//! the log cannot be used until it is done. Entries in the newest page are
+//! lost if the board resets before the log is synced, and as writing a page
+//! erases it first, syncing often wears the flash.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! pub static mut LOG_WRITE_PAGE: nrf52::nvmc::NrfPage = nrf52::nvmc::NrfPage::new();
+//! pub static mut LOG_READ_PAGE: nrf52::nvmc::NrfPage = nrf52::nvmc::NrfPage::new();
+//!
+//! let log = static_init!(
+//!     capsules::log_storage::LogStorage<'static, nrf52::nvmc::Nvmc>,
+//!     capsules::log_storage::LogStorage::new(
+//!         &nrf52::nvmc::NVMC,
+//!         LOG_FIRST_PAGE,
+//!         LOG_PAGE_COUNT,
+//!         true,
+//!         &mut LOG_WRITE_PAGE,
+//!         &mut LOG_READ_PAGE
+//!     )
+//! );
+//! hil::flash::HasClient::set_client(&nrf52::nvmc::NVMC, log);
+//! log.mount();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil;
+use kernel::hil::log::{LogRead, LogReadClient, LogWrite, LogWriteClient};
+use kernel::ReturnCode;
+
+/// The size of the header of each page: its position in the log, and the
+/// complement of the position
+const PAGE_HEADER_SIZE: usize = 8;
+
+/// The size of the header of each entry: its length, and the CRC-32 of its
+/// data
+const ENTRY_HEADER_SIZE: usize = 6;
+
+fn read_u32(buffer: &[u8], offset: usize) -> u32 {
+    buffer[offset] as u32
+        | (buffer[offset + 1] as u32) << 8
+        | (buffer[offset + 2] as u32) << 16
+        | (buffer[offset + 3] as u32) << 24
+}
+
+fn write_u32(buffer: &mut [u8], offset: usize, value: u32) {
+    for i in 0..4 {
+        buffer[offset + i] = (value >> (8 * i)) as u8;
+    }
+}
+
+/// The CRC-32 of `data`, as used by Ethernet and zip files.
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFF;
+    for byte in data.iter() {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// The position in the log of `page`, if it has a valid header.
+fn page_position(page: &[u8]) -> Option<usize> {
+    let position = read_u32(page, 0);
+    if read_u32(page, 4) == !position {
+        Some(position as usize)
+    } else {
+        None
+    }
+}
+
+/// Clears `page` and gives it the header of the page at `position`.
+fn init_page(page: &mut [u8], position: usize) {
+    for byte in page.iter_mut() {
+        *byte = 0;
+    }
+    write_u32(page, 0, position as u32);
+    write_u32(page, 4, !(position as u32));
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Entry {
+    /// An entry with this many bytes of data
+    Data(usize),
+    /// There are no more entries in the page
+    End,
+    /// The entry is damaged, and so is the rest of the page
+    Corrupt,
+}
+
+/// The entry at `offset` in `page`.
+fn entry_at(page: &[u8], offset: usize) -> Entry {
+    if offset + ENTRY_HEADER_SIZE > page.len() {
+        return Entry::End;
+    }
+    let length = page[offset] as usize | (page[offset + 1] as usize) << 8;
+    if length == 0 || length == 0xFFFF {
+        // Cleared or erased
+        return Entry::End;
+    }
+    let data = offset + ENTRY_HEADER_SIZE;
+    if data + length > page.len() || crc32(&page[data..data + length]) != read_u32(page, offset + 2)
+    {
+        return Entry::Corrupt;
+    }
+    Entry::Data(length)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Unmounted,
+    /// Reading the header of each page to find the newest one
+    Mount { page: usize },
+    /// Reading the newest page into the write buffer
+    MountHead,
+    Idle,
+    /// Reading the page of the next entry
+    Read,
+    /// Writing the full newest page before starting the next one
+    Append,
+    Sync,
+    Erase { page: usize },
+    /// An operation ended without using the flash, and the client is called
+    /// back from a deferred call
+    ReadDone { length: usize, result: ReturnCode },
+    AppendDone,
+    SyncDone,
+}
+
+pub struct LogStorage<'a, F: hil::flash::Flash + 'static> {
+    flash: &'a F,
+    /// The flash pages of the log
+    first_page: usize,
+    page_count: usize,
+    page_size: usize,
+    /// Whether the oldest entries are overwritten when the log is full
+    circular: bool,
+    /// The newest page, which entries are appended to
+    write_page: TakeCell<'static, F::Page>,
+    /// The page entries were last read from, and its position in the log
+    read_page: TakeCell<'static, F::Page>,
+    read_page_position: Cell<Option<usize>>,
+    state: Cell<State>,
+    deferred_call: DeferredCall,
+    read_client: OptionalCell<&'static LogReadClient>,
+    append_client: OptionalCell<&'static LogWriteClient>,
+    /// The position of the newest page, and the offset in it of the next
+    /// entry
+    head: Cell<usize>,
+    append_offset: Cell<usize>,
+    /// Whether the newest page is stored in flash as it is in memory
+    synced: Cell<bool>,
+    /// The IDs of the oldest entry and the next one to read
+    start: Cell<usize>,
+    read_position: Cell<usize>,
+    /// The newest and oldest pages found while mounting
+    newest: Cell<Option<usize>>,
+    oldest: Cell<Option<usize>>,
+    /// The buffer of the client's read or append, and its length
+    buffer: TakeCell<'static, [u8]>,
+    length: Cell<usize>,
+    records_lost: Cell<bool>,
+}
+
+impl<F: hil::flash::Flash> LogStorage<'a, F> {
+    pub fn new(
+        flash: &'a F,
+        first_page: usize,
+        page_count: usize,
+        circular: bool,
+        write_page: &'static mut F::Page,
+        read_page: &'static mut F::Page,
+    ) -> LogStorage<'a, F> {
+        let page_size = write_page.as_mut().len();
+        LogStorage {
+            flash: flash,
+            first_page: first_page,
+            page_count: page_count,
+            page_size: page_size,
+            circular: circular,
+            write_page: TakeCell::new(write_page),
+            read_page: TakeCell::new(read_page),
+            read_page_position: Cell::new(None),
+            state: Cell::new(State::Unmounted),
+            deferred_call: DeferredCall::new(),
+            read_client: OptionalCell::empty(),
+            append_client: OptionalCell::empty(),
+            head: Cell::new(0),
+            append_offset: Cell::new(PAGE_HEADER_SIZE),
+            synced: Cell::new(true),
+            start: Cell::new(PAGE_HEADER_SIZE),
+            read_position: Cell::new(PAGE_HEADER_SIZE),
+            newest: Cell::new(None),
+            oldest: Cell::new(None),
+            buffer: TakeCell::empty(),
+            length: Cell::new(0),
+            records_lost: Cell::new(false),
+        }
+    }
+
+    /// The index among the flash pages of the log of the page at `position`
+    /// in the log.
+    fn page_index(&self, position: usize) -> usize {
+        (position / self.page_size) % self.page_count
+    }
+
+    fn ready(&self) -> ReturnCode {
+        match self.state.get() {
+            State::Idle => ReturnCode::SUCCESS,
+            State::Unmounted | State::Mount { .. } | State::MountHead => ReturnCode::EOFF,
+            _ => ReturnCode::EBUSY,
+        }
+    }
+
+    /// Reads the page at `page` in the flash pages of the log into the read
+    /// buffer.
+    fn read_flash_page(&self, page: usize, state: State) -> ReturnCode {
+        self.read_page.take().map_or(ReturnCode::EBUSY, |read_page| {
+            self.read_page_position.set(None);
+            let result = self.flash.read_page(self.first_page + page, read_page);
+            if result == ReturnCode::SUCCESS {
+                self.state.set(state);
+            }
+            result
+        })
+    }
+
+    /// Writes the newest page to flash.
+    fn write_head(&self, state: State) -> ReturnCode {
+        self.write_page.take().map_or(ReturnCode::EBUSY, |write_page| {
+            let result = self
+                .flash
+                .write_page(self.first_page + self.page_index(self.head.get()), write_page);
+            if result == ReturnCode::SUCCESS {
+                self.state.set(state);
+            }
+            result
+        })
+    }
+
+    /// Sets up the log once the header of each page was read.
+    fn mount_done(&self) {
+        let newest = match self.newest.get() {
+            Some(newest) => newest,
+            None => {
+                // The log is empty
+                self.write_page.map(|page| init_page(page.as_mut(), 0));
+                self.state.set(State::Idle);
+                return;
+            }
+        };
+        // Pages older than the one the newest page would overwrite are left
+        // over from an earlier log
+        let oldest_kept = (newest + self.page_size).saturating_sub(self.get_size());
+        let oldest = cmp::max(self.oldest.get().unwrap_or(newest), oldest_kept);
+        self.head.set(newest);
+        self.start.set(oldest + PAGE_HEADER_SIZE);
+        self.read_position.set(oldest + PAGE_HEADER_SIZE);
+
+        let result = self.write_page.take().map_or(ReturnCode::FAIL, |write_page| {
+            self.flash
+                .read_page(self.first_page + self.page_index(newest), write_page)
+        });
+        if result == ReturnCode::SUCCESS {
+            self.state.set(State::MountHead);
+        } else {
+            self.state.set(State::Idle);
+            self.next_page();
+        }
+    }
+
+    /// Starts a new newest page after the current one, dropping the oldest
+    /// page if the log is full.
+    fn next_page(&self) {
+        let head = self.head.get() + self.page_size;
+        self.head.set(head);
+        self.append_offset.set(PAGE_HEADER_SIZE);
+        self.write_page.map(|page| init_page(page.as_mut(), head));
+        self.synced.set(true);
+
+        let oldest_kept = (head + self.page_size).saturating_sub(self.get_size());
+        if self.start.get() < oldest_kept + PAGE_HEADER_SIZE {
+            self.start.set(oldest_kept + PAGE_HEADER_SIZE);
+            self.records_lost.set(true);
+        }
+    }
+
+    /// Copies the client's entry into the newest page, which has room for
+    /// it.
+    fn append_entry(&self) {
+        let length = self.length.get();
+        let offset = self.append_offset.get();
+        self.buffer.map(|buffer| {
+            self.write_page.map(|page| {
+                let page = page.as_mut();
+                let data = &buffer[..length];
+                page[offset] = length as u8;
+                page[offset + 1] = (length >> 8) as u8;
+                write_u32(page, offset + 2, crc32(data));
+                let start = offset + ENTRY_HEADER_SIZE;
+                page[start..start + length].copy_from_slice(data);
+            });
+        });
+        self.append_offset.set(offset + ENTRY_HEADER_SIZE + length);
+        self.synced.set(false);
+    }
+
+    /// Looks at the entry at `offset` in `page`, the page at `position` in
+    /// the log, and copies its data into the client's buffer if it fits.
+    fn read_entry(&self, page: &mut F::Page, position: usize, offset: usize) -> Entry {
+        let page = page.as_mut();
+        if page_position(page) != Some(position) {
+            // The page was erased or damaged
+            return Entry::End;
+        }
+        let entry = entry_at(page, offset);
+        if let Entry::Data(length) = entry {
+            if length <= self.length.get() {
+                let data = offset + ENTRY_HEADER_SIZE;
+                self.buffer.map(|buffer| {
+                    buffer[..length].copy_from_slice(&page[data..data + length]);
+                });
+            }
+        }
+        entry
+    }
+
+    /// Continues reading the next entry, until its page has to be read from
+    /// flash.
+    fn read_step(&self) {
+        loop {
+            let position = cmp::max(self.read_position.get(), self.start.get());
+            if position >= self.log_end() {
+                self.read_position.set(position);
+                return self.finish_read(0, ReturnCode::FAIL);
+            }
+            let page_position = position - position % self.page_size;
+            let offset = cmp::max(position - page_position, PAGE_HEADER_SIZE);
+
+            let entry = if page_position == self.head.get() {
+                self.write_page
+                    .map(|page| self.read_entry(page, page_position, offset))
+            } else if self.read_page_position.get() == Some(page_position) {
+                self.read_page
+                    .map(|page| self.read_entry(page, page_position, offset))
+            } else {
+                self.read_position.set(page_position + offset);
+                let result = self.read_flash_page(self.page_index(page_position), State::Read);
+                if result != ReturnCode::SUCCESS {
+                    self.finish_read(0, result);
+                }
+                return;
+            };
+
+            match entry.unwrap_or(Entry::End) {
+                Entry::Data(length) if length > self.length.get() => {
+                    self.read_position.set(page_position + offset);
+                    return self.finish_read(length, ReturnCode::ESIZE);
+                }
+                Entry::Data(length) => {
+                    self.read_position
+                        .set(page_position + offset + ENTRY_HEADER_SIZE + length);
+                    return self.finish_read(length, ReturnCode::SUCCESS);
+                }
+                Entry::End | Entry::Corrupt => {
+                    self.read_position
+                        .set(page_position + self.page_size + PAGE_HEADER_SIZE);
+                }
+            }
+        }
+    }
+
+    fn finish_read(&self, length: usize, result: ReturnCode) {
+        self.state.set(State::ReadDone {
+            length: length,
+            result: result,
+        });
+        self.deferred_call.set();
+    }
+
+    fn erase_flash_page(&self, page: usize) -> ReturnCode {
+        let result = self.flash.erase_page(self.first_page + page);
+        if result == ReturnCode::SUCCESS {
+            self.state.set(State::Erase { page: page });
+        }
+        result
+    }
+}
+
+impl<F: hil::flash::Flash> LogStorage<'static, F> {
+    /// Finds the entries stored in flash. The log can be used once each
+    /// page was read.
+    pub fn mount(&'static self) -> ReturnCode {
+        if self.state.get() != State::Unmounted {
+            return ReturnCode::EALREADY;
+        }
+        let result = self.deferred_call.register(self);
+        if result != ReturnCode::SUCCESS && result != ReturnCode::EALREADY {
+            return result;
+        }
+        self.read_flash_page(0, State::Mount { page: 0 })
+    }
+}
+
+impl<F: hil::flash::Flash> LogRead for LogStorage<'a, F> {
+    fn set_read_client(&self, client: &'static LogReadClient) {
+        self.read_client.set(client);
+    }
+
+    fn log_start(&self) -> usize {
+        self.start.get()
+    }
+
+    fn log_end(&self) -> usize {
+        self.head.get() + self.append_offset.get()
+    }
+
+    fn next_read_entry_id(&self) -> usize {
+        cmp::max(self.read_position.get(), self.start.get())
+    }
+
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        length: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        let result = self.ready();
+        if result != ReturnCode::SUCCESS {
+            return (result, Some(buffer));
+        }
+        if length > buffer.len() {
+            return (ReturnCode::EINVAL, Some(buffer));
+        }
+        self.buffer.replace(buffer);
+        self.length.set(length);
+        self.state.set(State::Read);
+        self.read_step();
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn seek(&self, entry_id: usize) -> ReturnCode {
+        let result = self.ready();
+        if result != ReturnCode::SUCCESS {
+            return result;
+        }
+        if entry_id < self.log_start() || entry_id > self.log_end() {
+            return ReturnCode::EINVAL;
+        }
+        self.read_position.set(entry_id);
+        ReturnCode::SUCCESS
+    }
+
+    fn get_size(&self) -> usize {
+        self.page_count * self.page_size
+    }
+}
+
+impl<F: hil::flash::Flash> LogWrite for LogStorage<'a, F> {
+    fn set_append_client(&self, client: &'static LogWriteClient) {
+        self.append_client.set(client);
+    }
+
+    fn append(
+        &self,
+        buffer: &'static mut [u8],
+        length: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        let result = self.ready();
+        if result != ReturnCode::SUCCESS {
+            return (result, Some(buffer));
+        }
+        if length > buffer.len() {
+            return (ReturnCode::EINVAL, Some(buffer));
+        }
+        if length == 0
+            || length >= 0xFFFF
+            || ENTRY_HEADER_SIZE + length > self.page_size - PAGE_HEADER_SIZE
+        {
+            return (ReturnCode::ESIZE, Some(buffer));
+        }
+        self.buffer.replace(buffer);
+        self.length.set(length);
+        self.records_lost.set(false);
+
+        if self.append_offset.get() + ENTRY_HEADER_SIZE + length <= self.page_size {
+            self.append_entry();
+            self.state.set(State::AppendDone);
+            self.deferred_call.set();
+            return (ReturnCode::SUCCESS, None);
+        }
+
+        // The newest page is full, and the next one holds the oldest entries
+        let start_page = self.start.get() - self.start.get() % self.page_size;
+        let full = self.head.get() + self.page_size >= start_page + self.get_size();
+        if full && !self.circular {
+            return (ReturnCode::ENOMEM, self.buffer.take());
+        }
+        if self.synced.get() {
+            self.next_page();
+            self.append_entry();
+            self.state.set(State::AppendDone);
+            self.deferred_call.set();
+            return (ReturnCode::SUCCESS, None);
+        }
+        let result = self.write_head(State::Append);
+        if result != ReturnCode::SUCCESS {
+            return (result, self.buffer.take());
+        }
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn sync(&self) -> ReturnCode {
+        let result = self.ready();
+        if result != ReturnCode::SUCCESS {
+            return result;
+        }
+        if self.synced.get() {
+            self.state.set(State::SyncDone);
+            self.deferred_call.set();
+            return ReturnCode::SUCCESS;
+        }
+        self.write_head(State::Sync)
+    }
+
+    fn erase(&self) -> ReturnCode {
+        let result = self.ready();
+        if result != ReturnCode::SUCCESS {
+            return result;
+        }
+        self.erase_flash_page(0)
+    }
+}
+
+impl<F: hil::flash::Flash> hil::flash::Client<F> for LogStorage<'a, F> {
+    fn read_complete(&self, page: &'static mut F::Page, error: hil::flash::Error) {
+        match self.state.get() {
+            State::Mount { page: index } => {
+                if error == hil::flash::Error::CommandComplete {
+                    // Only pages in their place in the log are part of it
+                    page_position(page.as_mut())
+                        .filter(|position| self.page_index(*position) == index)
+                        .map(|position| {
+                            self.newest
+                                .set(Some(self.newest.get().map_or(position, |newest| {
+                                    cmp::max(newest, position)
+                                })));
+                            self.oldest
+                                .set(Some(self.oldest.get().map_or(position, |oldest| {
+                                    cmp::min(oldest, position)
+                                })));
+                        });
+                }
+                self.read_page.replace(page);
+                if index + 1 < self.page_count {
+                    if self.read_flash_page(index + 1, State::Mount { page: index + 1 })
+                        != ReturnCode::SUCCESS
+                    {
+                        self.mount_done();
+                    }
+                } else {
+                    self.mount_done();
+                }
+            }
+            State::MountHead => {
+                // Find the end of the entries of the newest page
+                let mut offset = PAGE_HEADER_SIZE;
+                if error == hil::flash::Error::CommandComplete {
+                    let contents = page.as_mut();
+                    while let Entry::Data(length) = entry_at(contents, offset) {
+                        offset += ENTRY_HEADER_SIZE + length;
+                    }
+                    for byte in contents[offset..].iter_mut() {
+                        *byte = 0;
+                    }
+                }
+                self.write_page.replace(page);
+                self.state.set(State::Idle);
+                if error == hil::flash::Error::CommandComplete {
+                    self.append_offset.set(offset);
+                } else {
+                    // Leave the page that could not be read alone
+                    self.next_page();
+                }
+            }
+            State::Read => {
+                self.read_page.replace(page);
+                if error == hil::flash::Error::CommandComplete {
+                    let position = self.read_position.get();
+                    self.read_page_position
+                        .set(Some(position - position % self.page_size));
+                    self.read_step();
+                } else {
+                    self.finish_read(0, ReturnCode::FAIL);
+                }
+            }
+            _ => {
+                self.read_page.replace(page);
+            }
+        }
+    }
+
+    fn write_complete(&self, page: &'static mut F::Page, error: hil::flash::Error) {
+        self.write_page.replace(page);
+        let result = if error == hil::flash::Error::CommandComplete {
+            ReturnCode::SUCCESS
+        } else {
+            ReturnCode::FAIL
+        };
+        match self.state.get() {
+            State::Append => {
+                self.state.set(State::Idle);
+                if result == ReturnCode::SUCCESS {
+                    self.next_page();
+                    self.append_entry();
+                }
+                let length = self.length.get();
+                let records_lost = self.records_lost.get();
+                self.buffer.take().map(|buffer| {
+                    self.append_client.map(move |client| {
+                        client.append_done(buffer, length, records_lost, result);
+                    });
+                });
+            }
+            State::Sync => {
+                self.state.set(State::Idle);
+                self.synced.set(result == ReturnCode::SUCCESS);
+                self.append_client.map(|client| client.sync_done(result));
+            }
+            _ => {}
+        }
+    }
+
+    fn erase_complete(&self, error: hil::flash::Error) {
+        if let State::Erase { page } = self.state.get() {
+            let result = if error != hil::flash::Error::CommandComplete {
+                ReturnCode::FAIL
+            } else if page + 1 < self.page_count {
+                match self.erase_flash_page(page + 1) {
+                    ReturnCode::SUCCESS => return,
+                    result => result,
+                }
+            } else {
+                // Entry IDs keep growing, so that readers do not take new
+                // entries for old ones
+                self.head.set(self.head.get() + self.page_size);
+                self.append_offset.set(PAGE_HEADER_SIZE);
+                self.write_page
+                    .map(|page| init_page(page.as_mut(), self.head.get()));
+                self.synced.set(true);
+                self.start.set(self.head.get() + PAGE_HEADER_SIZE);
+                self.read_position.set(self.start.get());
+                self.read_page_position.set(None);
+                ReturnCode::SUCCESS
+            };
+            self.state.set(State::Idle);
+            self.append_client.map(|client| client.erase_done(result));
+        }
+    }
+}
+
+impl<F: hil::flash::Flash> DeferredCallClient for LogStorage<'a, F> {
+    fn handle_deferred_call(&self) {
+        let state = self.state.get();
+        match state {
+            State::ReadDone { length, result } => {
+                self.state.set(State::Idle);
+                self.buffer.take().map(|buffer| {
+                    self.read_client
+                        .map(move |client| client.read_done(buffer, length, result));
+                });
+            }
+            State::AppendDone => {
+                self.state.set(State::Idle);
+                let length = self.length.get();
+                let records_lost = self.records_lost.get();
+                self.buffer.take().map(|buffer| {
+                    self.append_client.map(move |client| {
+                        client.append_done(buffer, length, records_lost, ReturnCode::SUCCESS);
+                    });
+                });
+            }
+            State::SyncDone => {
+                self.state.set(State::Idle);
+                self.append_client
+                    .map(|client| client.sync_done(ReturnCode::SUCCESS));
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,311 @@
+//! Log storage system call interface
+//!
+//! This capsule gives processes access to a `hil::log` log, such as
+//! `LogStorage`. Processes append entries to the shared log and read them
+//! back, each with its own read position, so that several processes can
+//! read the log without disturbing each other. Entries are copied through a
+//! kernel buffer, which limits their length to `BUFFER_LEN`. One operation
+//! runs at a time, for any process.
+//!
+//! ## Instantiation
+//!
+//! ```rust
+//! let log_driver = static_init!(
+//!     capsules::log_storage_driver::LogStorageDriver<
+//!         'static,
+//!         capsules::log_storage::LogStorage<'static, nrf52::nvmc::Nvmc>,
+//!     >,
+//!     capsules::log_storage_driver::LogStorageDriver::new(
+//!         log,
+//!         &mut capsules::log_storage_driver::BUFFER,
+//!         board_kernel.create_grant(&grant_cap)
+//!     )
+//! );
+//! hil::log::LogRead::set_read_client(log, log_driver);
+//! hil::log::LogWrite::set_append_client(log, log_driver);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::log::{LogRead, LogReadClient, LogWrite, LogWriteClient};
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+/// Syscall number
+pub const DRIVER_NUM: usize = 0x50005;
+
+/// The longest entry processes can append or read
+pub const BUFFER_LEN: usize = 256;
+
+pub static mut BUFFER: [u8; BUFFER_LEN] = [0; BUFFER_LEN];
+
+// Commands, which are also passed to the callback
+const COMMAND_READ: usize = 1;
+const COMMAND_APPEND: usize = 2;
+const COMMAND_SYNC: usize = 3;
+const COMMAND_ERASE: usize = 4;
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    read_buffer: Option<AppSlice<Shared, u8>>,
+    write_buffer: Option<AppSlice<Shared, u8>>,
+    // The ID of the next entry the process reads, or None to read from the
+    // start of the log
+    read_position: Option<usize>,
+}
+
+pub struct LogStorageDriver<'a, L: LogRead + LogWrite + 'a> {
+    log: &'a L,
+    apps: Grant<App>,
+    buffer: TakeCell<'static, [u8]>,
+    // The process whose operation is in progress, and the operation
+    owner: OptionalCell<AppId>,
+    command: Cell<usize>,
+}
+
+impl<L: LogRead + LogWrite> LogStorageDriver<'a, L> {
+    pub fn new(
+        log: &'a L,
+        buffer: &'static mut [u8],
+        apps: Grant<App>,
+    ) -> LogStorageDriver<'a, L> {
+        LogStorageDriver {
+            log: log,
+            apps: apps,
+            buffer: TakeCell::new(buffer),
+            owner: OptionalCell::empty(),
+            command: Cell::new(0),
+        }
+    }
+
+    /// The ID of the next entry the process reads.
+    fn read_position(&self, app: &App) -> usize {
+        cmp::max(app.read_position.unwrap_or(0), self.log.log_start())
+    }
+
+    fn read(&self, appid: AppId, length: usize) -> ReturnCode {
+        let position = self
+            .apps
+            .enter(appid, |app, _| {
+                let fits = app
+                    .read_buffer
+                    .as_ref()
+                    .map_or(false, |buffer| buffer.len() >= length);
+                if fits {
+                    Ok(self.read_position(app))
+                } else {
+                    Err(ReturnCode::EINVAL)
+                }
+            }).unwrap_or_else(|err| Err(err.into()));
+        let position = match position {
+            Ok(position) => position,
+            Err(err) => return err,
+        };
+        let result = self.log.seek(position);
+        if result != ReturnCode::SUCCESS {
+            return result;
+        }
+        self.buffer.take().map_or(ReturnCode::EBUSY, |buffer| {
+            let length = cmp::min(length, buffer.len());
+            let (result, buffer) = self.log.read(buffer, length);
+            buffer.map(|buffer| self.buffer.replace(buffer));
+            result
+        })
+    }
+
+    fn append(&self, appid: AppId, length: usize) -> ReturnCode {
+        self.buffer.take().map_or(ReturnCode::EBUSY, |buffer| {
+            let copied = self
+                .apps
+                .enter(appid, |app, _| {
+                    app.write_buffer.as_ref().map_or(false, |data| {
+                        match (data.as_ref().get(..length), buffer.get_mut(..length)) {
+                            (Some(data), Some(entry)) => {
+                                entry.copy_from_slice(data);
+                                true
+                            }
+                            _ => false,
+                        }
+                    })
+                }).unwrap_or(false);
+            if !copied {
+                self.buffer.replace(buffer);
+                return ReturnCode::EINVAL;
+            }
+            let (result, buffer) = self.log.append(buffer, length);
+            buffer.map(|buffer| self.buffer.replace(buffer));
+            result
+        })
+    }
+
+    /// Ends the operation in progress and calls back the process.
+    fn finish(&self, result: ReturnCode, value: usize) {
+        let command = self.command.get();
+        self.owner.take().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                app.callback
+                    .map(|mut cb| cb.schedule(command, usize::from(result), value));
+            });
+        });
+    }
+}
+
+impl<L: LogRead + LogWrite> Driver for LogStorageDriver<'a, L> {
+    /// Share buffers with the log.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: The buffer that entries are read into.
+    /// - `1`: The buffer that entries are appended from.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 | 1 => self
+                .apps
+                .enter(appid, |app, _| {
+                    if allow_num == 0 {
+                        app.read_buffer = slice;
+                    } else {
+                        app.write_buffer = slice;
+                    }
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Subscribe to the end of operations.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: A read, append, sync or erase ended. The callback is passed the
+    ///        command number of the operation, its result, and for reads the
+    ///        length of the entry, and for appends 1 if older entries were
+    ///        overwritten and 0 otherwise.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        appid: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Read, append and move through the log.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Read the next entry, of up to `data1` bytes, into the read
+    ///        buffer.
+    /// - `2`: Append the first `data1` bytes of the write buffer as an entry.
+    /// - `3`: Sync the log, so that the entries appended are kept if the
+    ///        board resets.
+    /// - `4`: Erase the log.
+    /// - `5`: Move the read position of the process to the entry `data1`.
+    /// - `6`: Get the ID of the oldest entry.
+    /// - `7`: Get the ID the next entry appended will get.
+    /// - `8`: Get the ID of the next entry the process reads.
+    fn command(&self, command_num: usize, data1: usize, _: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            COMMAND_READ | COMMAND_APPEND | COMMAND_SYNC | COMMAND_ERASE => {
+                if self.owner.is_some() {
+                    return ReturnCode::EBUSY;
+                }
+                let result = match command_num {
+                    COMMAND_READ => self.read(appid, data1),
+                    COMMAND_APPEND => self.append(appid, data1),
+                    COMMAND_SYNC => self.log.sync(),
+                    _ => self.log.erase(),
+                };
+                if result == ReturnCode::SUCCESS {
+                    self.owner.set(appid);
+                    self.command.set(command_num);
+                }
+                result
+            }
+
+            5 => {
+                if data1 < self.log.log_start() || data1 > self.log.log_end() {
+                    return ReturnCode::EINVAL;
+                }
+                self.apps
+                    .enter(appid, |app, _| {
+                        app.read_position = Some(data1);
+                        ReturnCode::SUCCESS
+                    }).unwrap_or_else(|err| err.into())
+            }
+
+            6 => ReturnCode::SuccessWithValue {
+                value: self.log.log_start(),
+            },
+
+            7 => ReturnCode::SuccessWithValue {
+                value: self.log.log_end(),
+            },
+
+            8 => self
+                .apps
+                .enter(appid, |app, _| ReturnCode::SuccessWithValue {
+                    value: self.read_position(app),
+                }).unwrap_or_else(|err| err.into()),
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+impl<L: LogRead + LogWrite> LogReadClient for LogStorageDriver<'a, L> {
+    fn read_done(&self, buffer: &'static mut [u8], length: usize, result: ReturnCode) {
+        self.owner.map(|appid| {
+            let _ = self.apps.enter(*appid, |app, _| {
+                if result == ReturnCode::SUCCESS {
+                    app.read_buffer.as_mut().map(|data| {
+                        data.as_mut().get_mut(..length).map(|data| {
+                            data.copy_from_slice(&buffer[..length]);
+                        });
+                    });
+                }
+                app.read_position = Some(self.log.next_read_entry_id());
+            });
+        });
+        self.buffer.replace(buffer);
+        self.finish(result, length);
+    }
+}
+
+impl<L: LogRead + LogWrite> LogWriteClient for LogStorageDriver<'a, L> {
+    fn append_done(
+        &self,
+        buffer: &'static mut [u8],
+        _length: usize,
+        records_lost: bool,
+        result: ReturnCode,
+    ) {
+        self.buffer.replace(buffer);
+        self.finish(result, records_lost as usize);
+    }
+
+    fn sync_done(&self, result: ReturnCode) {
+        self.finish(result, 0);
+    }
+
+    fn erase_done(&self, result: ReturnCode) {
+        self.finish(result, 0);
+    }
+}
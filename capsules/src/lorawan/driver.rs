@@ -0,0 +1,247 @@
+//! LoRaWAN userspace interface.
+//!
+//! Implements a userspace interface for joining a LoRaWAN network and
+//! sending uplinks to it. The kernel has a single `LoRaWAN` MAC, which
+//! belongs to the process that joins the network with it.
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+use lorawan::mac::{self, Key, LoRaWAN};
+
+/// Syscall number
+pub const DRIVER_NUM: usize = 0x30005;
+
+/// Length of the join configuration: the DevEUI, the AppEUI, and the AppKey.
+const JOIN_CONFIG_LEN: usize = 32;
+
+/// Requests passed to the request callback.
+mod request {
+    pub const JOIN: usize = 0;
+    pub const SEND: usize = 1;
+}
+
+#[derive(Default)]
+pub struct App {
+    rx_callback: Option<Callback>,
+    request_callback: Option<Callback>,
+    app_read: Option<AppSlice<Shared, u8>>,
+    app_write: Option<AppSlice<Shared, u8>>,
+    app_cfg: Option<AppSlice<Shared, u8>>,
+}
+
+pub struct LoRaWANDriver<'a> {
+    /// The MAC shared by all apps
+    mac: &'a LoRaWAN<'a>,
+
+    /// Grant of apps that use this driver.
+    apps: Grant<App>,
+    /// ID of the app that the MAC belongs to.
+    owner: Cell<Option<AppId>>,
+}
+
+impl<'a> LoRaWANDriver<'a> {
+    pub fn new(mac: &'a LoRaWAN<'a>, grant: Grant<App>) -> LoRaWANDriver<'a> {
+        LoRaWANDriver {
+            mac: mac,
+            apps: grant,
+            owner: Cell::new(None),
+        }
+    }
+
+    /// Utility function to perform an action on an app in a system call.
+    #[inline]
+    fn do_with_app<F>(&self, appid: AppId, closure: F) -> ReturnCode
+    where
+        F: FnOnce(&mut App) -> ReturnCode,
+    {
+        self.apps
+            .enter(appid, |app, _| closure(app))
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// Utility function to perform an action on the app that the MAC
+    /// belongs to.
+    #[inline]
+    fn do_with_owner<F>(&self, closure: F)
+    where
+        F: FnOnce(&mut App),
+    {
+        self.owner.get().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| closure(app));
+        });
+    }
+
+    /// Sends the first `len` bytes of the write buffer of the owner.
+    fn send(&self, appid: AppId, port: usize, len: usize, confirmed: bool) -> ReturnCode {
+        if self.owner.get().map_or(true, |owner| owner.idx() != appid.idx()) {
+            return ReturnCode::ERESERVE;
+        }
+        if port > 0xff {
+            return ReturnCode::EINVAL;
+        }
+        self.do_with_app(appid, |app| {
+            app.app_write.as_ref().map_or(ReturnCode::EINVAL, |buf| {
+                if len > buf.len() {
+                    return ReturnCode::EINVAL;
+                }
+                self.mac.send(port as u8, &buf.as_ref()[..len], confirmed)
+            })
+        })
+    }
+
+    fn request_done(&self, request: usize, result: ReturnCode) {
+        self.do_with_owner(|app| {
+            app.request_callback
+                .map(|mut cb| cb.schedule(request, result.into(), 0));
+        });
+    }
+}
+
+impl<'a> Driver for LoRaWANDriver<'a> {
+    /// Setup buffers to read/write from.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Read buffer. Will contain the payloads of downlinks.
+    /// - `1`: Write buffer. Contains the payloads of uplinks.
+    /// - `2`: Join configuration buffer. Contains the DevEUI and the AppEUI,
+    ///        most significant byte first, followed by the AppKey.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 | 1 | 2 => self.do_with_app(appid, |app| {
+                match allow_num {
+                    0 => app.app_read = slice,
+                    1 => app.app_write = slice,
+                    2 => app.app_cfg = slice,
+                    _ => {}
+                }
+                ReturnCode::SUCCESS
+            }),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Setup callback for when a downlink is received. It is passed
+    ///        the port, the number of bytes written into the read buffer, and
+    ///        the signal strength in dBm.
+    /// - `1`: Setup callback for when a request completes. It is passed the
+    ///        request (0 join, 1 send) and the result.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 | 1 => self.do_with_app(app_id, |app| {
+                match subscribe_num {
+                    0 => app.rx_callback = callback,
+                    1 => app.request_callback = callback,
+                    _ => {}
+                }
+                ReturnCode::SUCCESS
+            }),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// LoRaWAN control
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Join the network with the join configuration buffer and the
+    ///        DevNonce `arg1`. Returns EINVAL if the configuration buffer is
+    ///        too short, and EBUSY if the MAC belongs to another app.
+    /// - `2`: Send the first `arg2` bytes of the write buffer as an
+    ///        unconfirmed uplink on port `arg1`.
+    /// - `3`: Send the first `arg2` bytes of the write buffer as a confirmed
+    ///        uplink on port `arg1`.
+    /// - `4`: Set the data rate of uplinks to `arg1`, from 0 (SF12) to 5
+    ///        (SF7).
+    /// - `5`: Get the longest payload that can be sent at the current data
+    ///        rate.
+    ///
+    /// Commands 2 and 3 return ERESERVE if the MAC does not belong to the
+    /// app, EOFF if it has not joined, and EBUSY if a request is in
+    /// progress.
+    fn command(&self, command_num: usize, arg1: usize, arg2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            1 => {
+                match self.owner.get() {
+                    Some(owner) if owner.idx() != appid.idx() => return ReturnCode::EBUSY,
+                    _ => {}
+                }
+                let result = self.do_with_app(appid, |app| {
+                    app.app_cfg.as_ref().map_or(ReturnCode::EINVAL, |cfg| {
+                        if cfg.len() < JOIN_CONFIG_LEN {
+                            return ReturnCode::EINVAL;
+                        }
+                        let cfg = cfg.as_ref();
+                        let mut dev_eui = [0; 8];
+                        let mut app_eui = [0; 8];
+                        let mut app_key: Key = [0; 16];
+                        dev_eui.copy_from_slice(&cfg[0..8]);
+                        app_eui.copy_from_slice(&cfg[8..16]);
+                        app_key.copy_from_slice(&cfg[16..32]);
+                        self.mac.join(&dev_eui, &app_eui, &app_key, arg1 as u16)
+                    })
+                });
+                if result == ReturnCode::SUCCESS {
+                    self.owner.set(Some(appid));
+                }
+                result
+            }
+
+            2 => self.send(appid, arg1, arg2, false),
+
+            3 => self.send(appid, arg1, arg2, true),
+
+            4 => self.mac.set_data_rate(arg1),
+
+            5 => ReturnCode::SuccessWithValue {
+                value: self.mac.max_payload_len(),
+            },
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+impl<'a> mac::Client for LoRaWANDriver<'a> {
+    fn joined(&self, result: ReturnCode) {
+        self.request_done(request::JOIN, result);
+        if result != ReturnCode::SUCCESS {
+            self.owner.set(None);
+        }
+    }
+
+    fn sent(&self, result: ReturnCode) {
+        self.request_done(request::SEND, result);
+    }
+
+    fn received(&self, port: u8, payload: &[u8], rssi: i16, _snr: i8) {
+        self.do_with_owner(|app| {
+            let mut len = 0;
+            app.app_read.as_mut().map(|rbuf| {
+                let rbuf = rbuf.as_mut();
+                len = cmp::min(rbuf.len(), payload.len());
+                rbuf[..len].copy_from_slice(&payload[..len]);
+            });
+            app.rx_callback
+                .map(|mut cb| cb.schedule(port as usize, len, rssi as usize));
+        });
+    }
+}
@@ -0,0 +1,1037 @@
+//! A LoRaWAN 1.0 Class A end device for the EU868 band.
+//!
+//! `LoRaWANMac` joins a network with over-the-air activation, sends
+//! confirmed and unconfirmed uplinks, and listens for a downlink in the two
+//! receive windows that follow each uplink, as Class A devices do. The first
+//! window (RX1) opens on the frequency and data rate of the uplink, and the
+//! second (RX2) on 869.525 MHz with the data rate that the join accept sets.
+//! The windows are opened by a virtual alarm.
+//!
+//! Frames are authenticated with AES-CMAC, which is computed with the CBC
+//! mode of an `AES128` implementation, and their payloads are encrypted with
+//! its CTR mode. Join accepts are decrypted, and the session keys derived,
+//! with single-block CBC operations, which are the same as ECB.
+//!
+//! Uplinks rotate through the three default channels of EU868, at the data
+//! rate set with `set_data_rate`. Confirmed uplinks that are not acknowledged
+//! are sent again, at most `MAX_RETRANSMISSIONS` times. The MAC does not
+//! implement MAC commands, the channels of the CFList, adaptive data rate, or
+//! duty cycle limits, which are left to the application. Downlinks on port
+//! 0, which only carry MAC commands, are dropped.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let lorawan = static_init!(
+//!     capsules::lorawan::mac::LoRaWANMac<
+//!         'static,
+//!         VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+//!         sam4l::aes::Aes<'static>,
+//!     >,
+//!     capsules::lorawan::mac::LoRaWANMac::new(
+//!         sx127x,
+//!         lorawan_virtual_alarm,
+//!         &sam4l::aes::AES,
+//!         &mut capsules::lorawan::mac::TX_FRAME,
+//!         &mut capsules::lorawan::mac::RX_FRAME,
+//!         &mut capsules::lorawan::mac::CRYPT_BUF
+//!     )
+//! );
+//! hil::lora::Lora::set_client(sx127x, lorawan);
+//! lorawan_virtual_alarm.set_client(lorawan);
+//! sam4l::aes::AES.set_client(lorawan);
+//! sam4l::aes::AES.enable();
+//! ```
+
+use core::cell::Cell;
+use core::cmp::max;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::lora::{self, Bandwidth, CodingRate, Config, MAX_PAYLOAD_LEN};
+use kernel::hil::symmetric_encryption::{
+    self, AES128Ctr, AES128, AES128CBC, AES128_BLOCK_SIZE, AES128_KEY_SIZE,
+};
+use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::ReturnCode;
+
+/// Room for a block before the longest frame, rounded up to whole blocks
+const CRYPT_BUF_LEN: usize = AES128_BLOCK_SIZE + MAX_PAYLOAD_LEN + 1;
+
+pub static mut TX_FRAME: [u8; MAX_PAYLOAD_LEN] = [0; MAX_PAYLOAD_LEN];
+pub static mut RX_FRAME: [u8; MAX_PAYLOAD_LEN] = [0; MAX_PAYLOAD_LEN];
+pub static mut CRYPT_BUF: [u8; CRYPT_BUF_LEN] = [0; CRYPT_BUF_LEN];
+
+pub type Key = [u8; AES128_KEY_SIZE];
+type Block = [u8; AES128_BLOCK_SIZE];
+
+/// Message types, in the upper three bits of the MAC header
+mod mtype {
+    pub const JOIN_REQUEST: u8 = 0x00;
+    pub const JOIN_ACCEPT: u8 = 0x20;
+    pub const UNCONFIRMED_UP: u8 = 0x40;
+    pub const UNCONFIRMED_DOWN: u8 = 0x60;
+    pub const CONFIRMED_UP: u8 = 0x80;
+    pub const CONFIRMED_DOWN: u8 = 0xa0;
+    pub const MASK: u8 = 0xe0;
+}
+
+/// The ACK bit of the frame control byte
+const FCTRL_ACK: u8 = 0x20;
+const FCTRL_FOPTS_LEN: u8 = 0x0f;
+
+/// The length of a message integrity code
+const MIC_LEN: usize = 4;
+
+/// The length of a join request, with its MIC
+const JOIN_REQUEST_LEN: usize = 23;
+
+/// The lengths of a join accept, without and with a CFList
+const JOIN_ACCEPT_LEN: usize = 17;
+const JOIN_ACCEPT_CFLIST_LEN: usize = 33;
+
+/// The length of the frame header of data frames without options: the MAC
+/// header, the device address, the frame control byte, and the frame counter
+const FHDR_LEN: usize = 8;
+
+/// The highest application port; higher ports are reserved
+const MAX_PORT: u8 = 223;
+
+/// Delays of the receive windows after the end of a transmission
+const JOIN_ACCEPT_DELAY1_MS: u32 = 5000;
+const JOIN_ACCEPT_DELAY2_MS: u32 = 6000;
+const RECEIVE_DELAY1_MS: u32 = 1000;
+
+/// How long before the end of its delay the radio is set up for a window
+const RX_WINDOW_MARGIN_MS: u32 = 5;
+
+/// How long the radio listens for a preamble in a receive window
+const RX_SYMBOL_TIMEOUT: u16 = 16;
+
+/// How long to wait before sending a confirmed uplink again
+const ACK_TIMEOUT_MS: u32 = 2000;
+
+/// How many times a confirmed uplink is sent again before giving up
+pub const MAX_RETRANSMISSIONS: usize = 3;
+
+/// The default channels of EU868
+const CHANNELS: [u32; 3] = [868_100_000, 868_300_000, 868_500_000];
+
+/// The frequency and default data rate of RX2 in EU868
+const RX2_FREQUENCY: u32 = 869_525_000;
+const RX2_DEFAULT_DATA_RATE: usize = 0;
+
+/// The spreading factors of data rates DR0 to DR5 in EU868, which all use
+/// a bandwidth of 125 kHz
+const SPREADING_FACTORS: [u8; 6] = [12, 11, 10, 9, 8, 7];
+
+/// The longest application payload at each data rate
+const MAX_APP_PAYLOAD_LEN: [usize; 6] = [51, 51, 51, 115, 222, 222];
+
+const DEFAULT_DATA_RATE: usize = 5;
+
+const TX_POWER: i8 = 14;
+const PREAMBLE_LEN: u16 = 8;
+const SYNC_WORD: u8 = 0x34;
+
+/// The direction of a data frame, as used in the crypto blocks
+#[derive(Copy, Clone, PartialEq)]
+enum Direction {
+    Up = 0,
+    Down = 1,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Request {
+    Join,
+    Uplink { confirmed: bool },
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum State {
+    Idle,
+
+    /// Waiting for the AES implementation
+    AppKeySubkey,
+    JoinRequestMic,
+    JoinAcceptDecrypt(usize),
+    JoinAcceptMic,
+    DeriveNwkSKey,
+    DeriveAppSKey,
+    NwkSKeySubkey,
+    UplinkEncrypt,
+    UplinkMic,
+    DownlinkMic,
+    DownlinkDecrypt,
+
+    /// Waiting for the radio or the alarm
+    Transmitting,
+    WaitRx1,
+    Rx1,
+    WaitRx2,
+    Rx2,
+    WaitRetransmission,
+}
+
+/// A frame received in a receive window
+#[derive(Copy, Clone)]
+struct Downlink {
+    len: usize,
+    rssi: i16,
+    snr: i8,
+    fcnt: u32,
+    fctrl: u8,
+}
+
+/// The session of a joined device
+#[derive(Copy, Clone)]
+struct Session {
+    dev_addr: u32,
+    fcnt_up: u32,
+    /// The lowest frame counter that a downlink may have
+    fcnt_down: u32,
+    rx1_dr_offset: usize,
+    rx2_data_rate: usize,
+    rx_delay_ms: u32,
+}
+
+/// The `Client` trait is implemented by the user of a `LoRaWAN` MAC.
+pub trait Client {
+    /// Called once the device has joined the network, with ENOACK if no
+    /// valid join accept was received.
+    fn joined(&self, result: ReturnCode);
+
+    /// Called once an uplink was sent, and for confirmed uplinks,
+    /// acknowledged. The result is ENOACK if a confirmed uplink was not
+    /// acknowledged, and FAIL if the radio could not send it.
+    fn sent(&self, result: ReturnCode);
+
+    /// Called when a downlink with an application payload is received.
+    fn received(&self, port: u8, payload: &[u8], rssi: i16, snr: i8);
+}
+
+/// The interface of a LoRaWAN Class A end device.
+pub trait LoRaWAN<'a> {
+    fn set_client(&self, client: &'a Client);
+
+    /// Sets the data rate of uplinks, from DR0 (SF12) to DR5 (SF7). Returns
+    /// EINVAL for other data rates.
+    fn set_data_rate(&self, data_rate: usize) -> ReturnCode;
+
+    /// Joins a network with over-the-air activation. The EUIs are given most
+    /// significant byte first, as they are usually printed. The network
+    /// rejects join requests with a DevNonce that the device used before, so
+    /// the caller chooses it; LoRaWAN 1.0.4 makes it a counter that survives
+    /// resets. Returns EBUSY if a request is in progress.
+    fn join(
+        &self,
+        dev_eui: &[u8; 8],
+        app_eui: &[u8; 8],
+        app_key: &Key,
+        dev_nonce: u16,
+    ) -> ReturnCode;
+
+    fn is_joined(&self) -> bool;
+
+    /// The longest payload that can be sent at the current data rate.
+    fn max_payload_len(&self) -> usize;
+
+    /// Sends `payload` on the application port `port`, which is copied
+    /// before this returns. Returns EOFF if the device has not joined,
+    /// EBUSY if a request is in progress, EINVAL if the port is 0 or
+    /// reserved, and ESIZE if the payload is longer than
+    /// `max_payload_len`.
+    fn send(&self, port: u8, payload: &[u8], confirmed: bool) -> ReturnCode;
+}
+
+/// Multiplies a block by x in GF(2^128), which derives the CMAC subkeys
+fn double(block: &Block) -> Block {
+    let mut result = [0; AES128_BLOCK_SIZE];
+    for i in 0..AES128_BLOCK_SIZE {
+        let carry = if i + 1 < AES128_BLOCK_SIZE {
+            block[i + 1] >> 7
+        } else {
+            0
+        };
+        result[i] = block[i] << 1 | carry;
+    }
+    if block[0] & 0x80 != 0 {
+        result[AES128_BLOCK_SIZE - 1] ^= 0x87;
+    }
+    result
+}
+
+/// Rounds `len` up to whole blocks
+fn round_up(len: usize) -> usize {
+    (len + AES128_BLOCK_SIZE - 1) / AES128_BLOCK_SIZE * AES128_BLOCK_SIZE
+}
+
+/// The block that starts the MIC computation (B0) or the counter (A1) of a
+/// data frame
+fn data_block(first: u8, dir: Direction, dev_addr: u32, fcnt: u32, last: u8) -> Block {
+    let mut block = [0; AES128_BLOCK_SIZE];
+    block[0] = first;
+    block[5] = dir as u8;
+    block[6..10].copy_from_slice(&[
+        dev_addr as u8,
+        (dev_addr >> 8) as u8,
+        (dev_addr >> 16) as u8,
+        (dev_addr >> 24) as u8,
+    ]);
+    block[10..14].copy_from_slice(&[
+        fcnt as u8,
+        (fcnt >> 8) as u8,
+        (fcnt >> 16) as u8,
+        (fcnt >> 24) as u8,
+    ]);
+    block[15] = last;
+    block
+}
+
+pub struct LoRaWANMac<'a, A: Alarm + 'a, E: AES128<'a> + AES128Ctr + AES128CBC + 'a> {
+    radio: &'a lora::Lora,
+    alarm: &'a A,
+    aes: &'a E,
+    client: OptionalCell<&'a Client>,
+    state: Cell<State>,
+    request: Cell<Request>,
+
+    /// The frame being sent, which is kept to send it again
+    tx_frame: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+    tx_fcnt: Cell<u32>,
+    /// The frame received in a receive window
+    rx_frame: TakeCell<'static, [u8]>,
+    downlink: Cell<Downlink>,
+    /// The receive window that the downlink was received in
+    window: Cell<State>,
+    crypt_buf: TakeCell<'a, [u8]>,
+
+    data_rate: Cell<usize>,
+    channel: Cell<usize>,
+    /// When the last transmission ended
+    tx_end: Cell<u32>,
+    retransmissions: Cell<usize>,
+    /// Whether the next uplink acknowledges a confirmed downlink
+    ack_pending: Cell<bool>,
+
+    app_key: Cell<Key>,
+    /// The first CMAC subkey of the AppKey and of the NwkSKey
+    app_key_k1: Cell<Block>,
+    nwk_skey_k1: Cell<Block>,
+    nwk_skey: Cell<Key>,
+    app_skey: Cell<Key>,
+    dev_nonce: Cell<u16>,
+    session: Cell<Option<Session>>,
+    /// The session of a join accept, until its keys are derived
+    pending_session: Cell<Option<Session>>,
+}
+
+impl<A: Alarm, E: AES128<'a> + AES128Ctr + AES128CBC> LoRaWANMac<'a, A, E> {
+    pub fn new(
+        radio: &'a lora::Lora,
+        alarm: &'a A,
+        aes: &'a E,
+        tx_frame: &'static mut [u8],
+        rx_frame: &'static mut [u8],
+        crypt_buf: &'a mut [u8],
+    ) -> LoRaWANMac<'a, A, E> {
+        LoRaWANMac {
+            radio: radio,
+            alarm: alarm,
+            aes: aes,
+            client: OptionalCell::empty(),
+            state: Cell::new(State::Idle),
+            request: Cell::new(Request::Join),
+            tx_frame: TakeCell::new(tx_frame),
+            tx_len: Cell::new(0),
+            tx_fcnt: Cell::new(0),
+            rx_frame: TakeCell::new(rx_frame),
+            downlink: Cell::new(Downlink {
+                len: 0,
+                rssi: 0,
+                snr: 0,
+                fcnt: 0,
+                fctrl: 0,
+            }),
+            window: Cell::new(State::Rx1),
+            crypt_buf: TakeCell::new(crypt_buf),
+            data_rate: Cell::new(DEFAULT_DATA_RATE),
+            channel: Cell::new(0),
+            tx_end: Cell::new(0),
+            retransmissions: Cell::new(0),
+            ack_pending: Cell::new(false),
+            app_key: Cell::new(Default::default()),
+            app_key_k1: Cell::new(Default::default()),
+            nwk_skey_k1: Cell::new(Default::default()),
+            nwk_skey: Cell::new(Default::default()),
+            app_skey: Cell::new(Default::default()),
+            dev_nonce: Cell::new(0),
+            session: Cell::new(None),
+            pending_session: Cell::new(None),
+        }
+    }
+
+    fn set_alarm(&self, from: u32, ms: u32) {
+        let tics = <A::Frequency>::frequency() / 1000 * ms;
+        self.alarm.set_alarm(from.wrapping_add(tics));
+    }
+
+    /// Runs AES over `crypt_buf[start..stop]` with `key` and `iv`, and moves
+    /// to `state`. CBC is used if `cbc` is set, and CTR otherwise.
+    fn crypt(&self, state: State, key: &Key, iv: &Block, cbc: bool, start: usize, stop: usize) {
+        let result = self.crypt_buf.take().map_or(ReturnCode::ENOMEM, |crypt_buf| {
+            self.aes.set_key(key);
+            self.aes.set_iv(iv);
+            if cbc {
+                self.aes.set_mode_aes128cbc(true);
+            } else {
+                self.aes.set_mode_aes128ctr(true);
+            }
+            self.aes.start_message();
+            match self.aes.crypt(None, crypt_buf, start, stop) {
+                None => ReturnCode::SUCCESS,
+                Some((result, _, crypt_buf)) => {
+                    self.crypt_buf.replace(crypt_buf);
+                    result
+                }
+            }
+        });
+        if result == ReturnCode::SUCCESS {
+            self.state.set(state);
+        } else {
+            self.finish(ReturnCode::FAIL);
+        }
+    }
+
+    /// Encrypts the block at `offset` of the crypt buffer on its own
+    fn encrypt_block(&self, state: State, key: &Key, offset: usize) {
+        self.crypt(state, key, &[0; AES128_BLOCK_SIZE], true, offset, offset + AES128_BLOCK_SIZE);
+    }
+
+    /// Computes the first CMAC subkey of `key`, which is derived from the
+    /// encryption of the zero block
+    fn compute_subkey(&self, state: State, key: &Key) {
+        self.crypt_buf
+            .map(|buf| buf[..AES128_BLOCK_SIZE].iter_mut().for_each(|b| *b = 0));
+        self.encrypt_block(state, key, 0);
+    }
+
+    /// Computes the CMAC of the first `len` bytes of the crypt buffer, which
+    /// ends up in the first bytes of the last block. `k1` is the first
+    /// subkey of `key`.
+    fn cmac(&self, state: State, key: &Key, k1: &Block, len: usize) {
+        let end = round_up(max(len, 1));
+        self.crypt_buf.map(|buf| {
+            let subkey = if len == end {
+                *k1
+            } else {
+                buf[len] = 0x80;
+                buf[len + 1..end].iter_mut().for_each(|b| *b = 0);
+                double(k1)
+            };
+            buf[end - AES128_BLOCK_SIZE..end]
+                .iter_mut()
+                .zip(subkey.iter())
+                .for_each(|(b, k)| *b ^= *k);
+        });
+        self.crypt(state, key, &[0; AES128_BLOCK_SIZE], true, 0, end);
+    }
+
+    /// The MIC computed by `cmac` over `len` bytes
+    fn computed_mic(&self, len: usize) -> [u8; MIC_LEN] {
+        let mut mic = [0; MIC_LEN];
+        let offset = round_up(max(len, 1)) - AES128_BLOCK_SIZE;
+        self.crypt_buf
+            .map(|buf| mic.copy_from_slice(&buf[offset..offset + MIC_LEN]));
+        mic
+    }
+
+    /// Computes the MIC of the first `len` bytes of `frame`, a data frame
+    fn data_mic(&self, state: State, frame: &[u8], len: usize, dir: Direction, fcnt: u32) {
+        let dev_addr = self.session.get().map_or(0, |session| session.dev_addr);
+        let b0 = data_block(0x49, dir, dev_addr, fcnt, len as u8);
+        self.crypt_buf.map(|buf| {
+            buf[..AES128_BLOCK_SIZE].copy_from_slice(&b0);
+            buf[AES128_BLOCK_SIZE..AES128_BLOCK_SIZE + len].copy_from_slice(&frame[..len]);
+        });
+        self.cmac(
+            state,
+            &self.nwk_skey.get(),
+            &self.nwk_skey_k1.get(),
+            AES128_BLOCK_SIZE + len,
+        );
+    }
+
+    /// Encrypts or decrypts `payload` with the AppSKey, in the crypt buffer
+    fn crypt_payload(&self, state: State, payload: &[u8], dir: Direction, fcnt: u32) {
+        let dev_addr = self.session.get().map_or(0, |session| session.dev_addr);
+        let a1 = data_block(0x01, dir, dev_addr, fcnt, 1);
+        let end = round_up(payload.len());
+        self.crypt_buf.map(|buf| {
+            buf[..payload.len()].copy_from_slice(payload);
+            buf[payload.len()..end].iter_mut().for_each(|b| *b = 0);
+        });
+        self.crypt(state, &self.app_skey.get(), &a1, false, 0, end);
+    }
+
+    /// The radio configuration of uplinks, or of a receive window
+    fn radio_config(&self, frequency: u32, data_rate: usize, downlink: bool) -> Config {
+        Config {
+            frequency: frequency,
+            spreading_factor: SPREADING_FACTORS[data_rate],
+            bandwidth: Bandwidth::Khz125,
+            coding_rate: CodingRate::Cr4_5,
+            tx_power: TX_POWER,
+            preamble_len: PREAMBLE_LEN,
+            sync_word: SYNC_WORD,
+            invert_iq: downlink,
+        }
+    }
+
+    /// Sends the frame in the transmit buffer on the next channel
+    fn transmit(&self) {
+        self.channel.set((self.channel.get() + 1) % CHANNELS.len());
+        let config = self.radio_config(CHANNELS[self.channel.get()], self.data_rate.get(), false);
+        let mut result = self.radio.configure(config);
+        if result == ReturnCode::SUCCESS {
+            let len = self.tx_len.get();
+            result = self
+                .tx_frame
+                .map_or(ReturnCode::ENOMEM, |frame| self.radio.transmit(&frame[..len]));
+        }
+        if result == ReturnCode::SUCCESS {
+            self.state.set(State::Transmitting);
+        } else {
+            self.finish(ReturnCode::FAIL);
+        }
+    }
+
+    /// The delays of RX1 and RX2 for the current request
+    fn rx_delays(&self) -> (u32, u32) {
+        match self.request.get() {
+            Request::Join => (JOIN_ACCEPT_DELAY1_MS, JOIN_ACCEPT_DELAY2_MS),
+            Request::Uplink { .. } => {
+                let delay = self
+                    .session
+                    .get()
+                    .map_or(RECEIVE_DELAY1_MS, |session| session.rx_delay_ms);
+                (delay, delay + 1000)
+            }
+        }
+    }
+
+    fn open_rx_window(&self, state: State) {
+        let (rx1_dr_offset, rx2_data_rate) = self.session.get().map_or(
+            (0, RX2_DEFAULT_DATA_RATE),
+            |session| (session.rx1_dr_offset, session.rx2_data_rate),
+        );
+        let config = if state == State::Rx1 {
+            let data_rate = self.data_rate.get().saturating_sub(rx1_dr_offset);
+            self.radio_config(CHANNELS[self.channel.get()], data_rate, true)
+        } else {
+            self.radio_config(RX2_FREQUENCY, rx2_data_rate, true)
+        };
+        let mut result = self.radio.configure(config);
+        if result == ReturnCode::SUCCESS {
+            result = self.radio.receive(RX_SYMBOL_TIMEOUT);
+        }
+        if result == ReturnCode::SUCCESS {
+            self.state.set(state);
+        } else {
+            self.rx_window_closed(state);
+        }
+    }
+
+    /// A receive window closed without a valid downlink
+    fn rx_window_closed(&self, window: State) {
+        if window == State::Rx1 {
+            let (_, delay2) = self.rx_delays();
+            self.state.set(State::WaitRx2);
+            self.set_alarm(self.tx_end.get(), delay2 - RX_WINDOW_MARGIN_MS);
+            return;
+        }
+        match self.request.get() {
+            Request::Join => self.finish(ReturnCode::ENOACK),
+            Request::Uplink { confirmed: false } => self.finish(ReturnCode::SUCCESS),
+            Request::Uplink { confirmed: true } => self.retransmit(),
+        }
+    }
+
+    /// Sends an unacknowledged confirmed uplink again, if it may be
+    fn retransmit(&self) {
+        if self.retransmissions.get() < MAX_RETRANSMISSIONS {
+            self.retransmissions.set(self.retransmissions.get() + 1);
+            self.state.set(State::WaitRetransmission);
+            self.set_alarm(self.alarm.now(), ACK_TIMEOUT_MS);
+        } else {
+            self.finish(ReturnCode::ENOACK);
+        }
+    }
+
+    /// Ends the current request
+    fn finish(&self, result: ReturnCode) {
+        self.state.set(State::Idle);
+        match self.request.get() {
+            Request::Join => {
+                self.client.map(|client| client.joined(result));
+            }
+            Request::Uplink { .. } => {
+                self.client.map(|client| client.sent(result));
+            }
+        }
+    }
+
+    /// Checks a frame received in a receive window, and starts verifying
+    /// its MIC if it could be for this device
+    fn downlink_received(&self, window: State) {
+        let downlink = self.downlink.get();
+        let len = downlink.len;
+        let frame_type = self.rx_frame.map_or(0xff, |frame| frame[0] & mtype::MASK);
+
+        match self.request.get() {
+            Request::Join => {
+                if frame_type != mtype::JOIN_ACCEPT
+                    || (len != JOIN_ACCEPT_LEN && len != JOIN_ACCEPT_CFLIST_LEN)
+                {
+                    return self.rx_window_closed(window);
+                }
+                // The join accept is encrypted with the AES decryption, so
+                // the AES encryption decrypts it
+                self.rx_frame.map(|frame| {
+                    self.crypt_buf
+                        .map(|buf| buf[..len - 1].copy_from_slice(&frame[1..len]));
+                });
+                self.encrypt_block(State::JoinAcceptDecrypt(0), &self.app_key.get(), 0);
+            }
+            Request::Uplink { .. } => {
+                let session = match self.session.get() {
+                    Some(session) => session,
+                    None => return self.rx_window_closed(window),
+                };
+                let header = self.rx_frame.map_or(None, |frame| {
+                    if len < FHDR_LEN + MIC_LEN {
+                        return None;
+                    }
+                    let dev_addr = frame[1] as u32
+                        | (frame[2] as u32) << 8
+                        | (frame[3] as u32) << 16
+                        | (frame[4] as u32) << 24;
+                    let fcnt = frame[6] as u16 | (frame[7] as u16) << 8;
+                    Some((dev_addr, frame[5], fcnt))
+                });
+                let (dev_addr, fctrl, fcnt) = match header {
+                    Some(header) => header,
+                    None => return self.rx_window_closed(window),
+                };
+                if (frame_type != mtype::UNCONFIRMED_DOWN && frame_type != mtype::CONFIRMED_DOWN)
+                    || dev_addr != session.dev_addr
+                {
+                    return self.rx_window_closed(window);
+                }
+
+                // Only the lower 16 bits of the frame counter are sent
+                let mut fcnt32 = session.fcnt_down & !0xffff | fcnt as u32;
+                if fcnt32 < session.fcnt_down {
+                    fcnt32 = fcnt32.wrapping_add(0x10000);
+                }
+                self.downlink.set(Downlink {
+                    fcnt: fcnt32,
+                    fctrl: fctrl,
+                    ..downlink
+                });
+                self.rx_frame.map(|frame| {
+                    self.data_mic(State::DownlinkMic, frame, len - MIC_LEN, Direction::Down, fcnt32)
+                });
+            }
+        }
+    }
+
+    /// Whether the MIC at the end of the received frame is the computed one
+    fn downlink_mic_valid(&self, len: usize) -> bool {
+        let mic = self.computed_mic(len);
+        let frame_len = self.downlink.get().len;
+        self.rx_frame
+            .map_or(false, |frame| frame[frame_len - MIC_LEN..frame_len] == mic)
+    }
+
+    fn join_accepted(&self) {
+        let dev_nonce = self.dev_nonce.get();
+        let session = self.rx_frame.map_or(None, |frame| {
+            // AppNonce, NetID, and DevNonce make the key derivation blocks
+            self.crypt_buf.map(|buf| {
+                for (i, block) in buf[..2 * AES128_BLOCK_SIZE]
+                    .chunks_mut(AES128_BLOCK_SIZE)
+                    .enumerate()
+                {
+                    block.iter_mut().for_each(|b| *b = 0);
+                    block[0] = i as u8 + 1;
+                    block[1..7].copy_from_slice(&frame[1..7]);
+                    block[7] = dev_nonce as u8;
+                    block[8] = (dev_nonce >> 8) as u8;
+                }
+            });
+            let rx_delay = match frame[12] & 0x0f {
+                0 => 1,
+                delay => delay as u32,
+            };
+            Some(Session {
+                dev_addr: frame[7] as u32
+                    | (frame[8] as u32) << 8
+                    | (frame[9] as u32) << 16
+                    | (frame[10] as u32) << 24,
+                fcnt_up: 0,
+                fcnt_down: 0,
+                rx1_dr_offset: (frame[11] >> 4 & 0x07) as usize,
+                rx2_data_rate: (frame[11] & 0x0f) as usize,
+                rx_delay_ms: rx_delay * 1000,
+            })
+        });
+        match session {
+            Some(mut session) => {
+                if session.rx2_data_rate >= SPREADING_FACTORS.len() {
+                    session.rx2_data_rate = RX2_DEFAULT_DATA_RATE;
+                }
+                // The session only becomes valid once its keys are derived
+                self.pending_session.set(Some(session));
+                self.encrypt_block(State::DeriveNwkSKey, &self.app_key.get(), 0);
+            }
+            None => self.finish(ReturnCode::FAIL),
+        }
+    }
+
+    /// Handles a downlink with a valid MIC
+    fn data_downlink_valid(&self) {
+        let downlink = self.downlink.get();
+        self.session.get().map(|mut session| {
+            session.fcnt_down = downlink.fcnt.wrapping_add(1);
+            self.session.set(Some(session));
+        });
+        let frame_type = self.rx_frame.map_or(0, |frame| frame[0] & mtype::MASK);
+        self.ack_pending.set(frame_type == mtype::CONFIRMED_DOWN);
+
+        let payload_start = FHDR_LEN + (downlink.fctrl & FCTRL_FOPTS_LEN) as usize;
+        let payload_end = downlink.len - MIC_LEN;
+        let port = self.rx_frame.map_or(0, |frame| {
+            if payload_start < payload_end {
+                frame[payload_start]
+            } else {
+                0
+            }
+        });
+        if port == 0 || payload_start + 1 >= payload_end {
+            return self.uplink_answered();
+        }
+        self.rx_frame.map(|frame| {
+            self.crypt_payload(
+                State::DownlinkDecrypt,
+                &frame[payload_start + 1..payload_end],
+                Direction::Down,
+                downlink.fcnt,
+            );
+        });
+    }
+
+    /// Ends an uplink that a valid downlink answered
+    fn uplink_answered(&self) {
+        let acked = self.downlink.get().fctrl & FCTRL_ACK != 0;
+        match self.request.get() {
+            // The network did not receive the uplink
+            Request::Uplink { confirmed: true } if !acked => self.retransmit(),
+            _ => self.finish(ReturnCode::SUCCESS),
+        }
+    }
+}
+
+impl<A: Alarm, E: AES128<'a> + AES128Ctr + AES128CBC> LoRaWAN<'a> for LoRaWANMac<'a, A, E> {
+    fn set_client(&self, client: &'a Client) {
+        self.client.set(client);
+    }
+
+    fn set_data_rate(&self, data_rate: usize) -> ReturnCode {
+        if data_rate >= SPREADING_FACTORS.len() {
+            return ReturnCode::EINVAL;
+        }
+        self.data_rate.set(data_rate);
+        ReturnCode::SUCCESS
+    }
+
+    fn join(
+        &self,
+        dev_eui: &[u8; 8],
+        app_eui: &[u8; 8],
+        app_key: &Key,
+        dev_nonce: u16,
+    ) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        let written = self.tx_frame.map_or(false, |frame| {
+            frame[0] = mtype::JOIN_REQUEST;
+            // EUIs are sent least significant byte first
+            for i in 0..8 {
+                frame[1 + i] = app_eui[7 - i];
+                frame[9 + i] = dev_eui[7 - i];
+            }
+            frame[17] = dev_nonce as u8;
+            frame[18] = (dev_nonce >> 8) as u8;
+            true
+        });
+        if !written {
+            return ReturnCode::EBUSY;
+        }
+        self.session.set(None);
+        self.ack_pending.set(false);
+        self.app_key.set(*app_key);
+        self.dev_nonce.set(dev_nonce);
+        self.tx_len.set(JOIN_REQUEST_LEN);
+        self.request.set(Request::Join);
+        self.compute_subkey(State::AppKeySubkey, app_key);
+        ReturnCode::SUCCESS
+    }
+
+    fn is_joined(&self) -> bool {
+        self.session.get().is_some()
+    }
+
+    fn max_payload_len(&self) -> usize {
+        MAX_APP_PAYLOAD_LEN[self.data_rate.get()]
+    }
+
+    fn send(&self, port: u8, payload: &[u8], confirmed: bool) -> ReturnCode {
+        let session = match self.session.get() {
+            Some(session) => session,
+            None => return ReturnCode::EOFF,
+        };
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        if port == 0 || port > MAX_PORT {
+            return ReturnCode::EINVAL;
+        }
+        if payload.len() > self.max_payload_len() {
+            return ReturnCode::ESIZE;
+        }
+
+        let fcnt = session.fcnt_up;
+        let written = self.tx_frame.map_or(false, |frame| {
+            frame[0] = if confirmed {
+                mtype::CONFIRMED_UP
+            } else {
+                mtype::UNCONFIRMED_UP
+            };
+            frame[1] = session.dev_addr as u8;
+            frame[2] = (session.dev_addr >> 8) as u8;
+            frame[3] = (session.dev_addr >> 16) as u8;
+            frame[4] = (session.dev_addr >> 24) as u8;
+            frame[5] = if self.ack_pending.get() { FCTRL_ACK } else { 0 };
+            frame[6] = fcnt as u8;
+            frame[7] = (fcnt >> 8) as u8;
+            frame[FHDR_LEN] = port;
+            true
+        });
+        if !written {
+            return ReturnCode::EBUSY;
+        }
+        self.ack_pending.set(false);
+        self.session.set(Some(Session {
+            fcnt_up: fcnt.wrapping_add(1),
+            ..session
+        }));
+        self.tx_len.set(FHDR_LEN + 1 + payload.len() + MIC_LEN);
+        self.tx_fcnt.set(fcnt);
+        self.retransmissions.set(0);
+        self.request.set(Request::Uplink {
+            confirmed: confirmed,
+        });
+
+        if payload.len() == 0 {
+            self.tx_frame.map(|frame| {
+                self.data_mic(State::UplinkMic, frame, FHDR_LEN + 1, Direction::Up, fcnt)
+            });
+        } else {
+            self.crypt_payload(State::UplinkEncrypt, payload, Direction::Up, fcnt);
+        }
+        ReturnCode::SUCCESS
+    }
+}
+
+impl<A: Alarm, E: AES128<'a> + AES128Ctr + AES128CBC> symmetric_encryption::Client<'a>
+    for LoRaWANMac<'a, A, E>
+{
+    fn crypt_done(&self, _: Option<&'a mut [u8]>, crypt_buf: &'a mut [u8]) {
+        self.crypt_buf.replace(crypt_buf);
+        let first_block = || {
+            let mut block = [0; AES128_BLOCK_SIZE];
+            self.crypt_buf
+                .map(|buf| block.copy_from_slice(&buf[..AES128_BLOCK_SIZE]));
+            block
+        };
+
+        match self.state.get() {
+            State::AppKeySubkey => {
+                self.app_key_k1.set(double(&first_block()));
+                self.tx_frame.map(|frame| {
+                    let len = JOIN_REQUEST_LEN - MIC_LEN;
+                    self.crypt_buf
+                        .map(|buf| buf[..len].copy_from_slice(&frame[..len]));
+                });
+                self.cmac(
+                    State::JoinRequestMic,
+                    &self.app_key.get(),
+                    &self.app_key_k1.get(),
+                    JOIN_REQUEST_LEN - MIC_LEN,
+                );
+            }
+            State::JoinRequestMic => {
+                let len = JOIN_REQUEST_LEN - MIC_LEN;
+                let mic = self.computed_mic(len);
+                self.tx_frame
+                    .map(|frame| frame[len..JOIN_REQUEST_LEN].copy_from_slice(&mic));
+                self.transmit();
+            }
+            State::JoinAcceptDecrypt(block) => {
+                let len = self.downlink.get().len;
+                let offset = (block + 1) * AES128_BLOCK_SIZE;
+                if offset < len - 1 {
+                    return self.encrypt_block(
+                        State::JoinAcceptDecrypt(block + 1),
+                        &self.app_key.get(),
+                        offset,
+                    );
+                }
+                // The MIC is computed over the MAC header and the decrypted
+                // fields
+                self.rx_frame.map(|frame| {
+                    self.crypt_buf.map(|buf| {
+                        frame[1..len].copy_from_slice(&buf[..len - 1]);
+                        buf[..len - MIC_LEN].copy_from_slice(&frame[..len - MIC_LEN]);
+                    });
+                });
+                self.cmac(
+                    State::JoinAcceptMic,
+                    &self.app_key.get(),
+                    &self.app_key_k1.get(),
+                    len - MIC_LEN,
+                );
+            }
+            State::JoinAcceptMic => {
+                let len = self.downlink.get().len;
+                if self.downlink_mic_valid(len - MIC_LEN) {
+                    self.join_accepted();
+                } else {
+                    self.rx_window_closed(self.window.get());
+                }
+            }
+            State::DeriveNwkSKey => {
+                self.nwk_skey.set(first_block());
+                self.encrypt_block(State::DeriveAppSKey, &self.app_key.get(), AES128_BLOCK_SIZE);
+            }
+            State::DeriveAppSKey => {
+                let mut app_skey = [0; AES128_BLOCK_SIZE];
+                self.crypt_buf.map(|buf| {
+                    app_skey.copy_from_slice(&buf[AES128_BLOCK_SIZE..2 * AES128_BLOCK_SIZE])
+                });
+                self.app_skey.set(app_skey);
+                self.compute_subkey(State::NwkSKeySubkey, &self.nwk_skey.get());
+            }
+            State::NwkSKeySubkey => {
+                self.nwk_skey_k1.set(double(&first_block()));
+                self.session.set(self.pending_session.get());
+                self.finish(ReturnCode::SUCCESS);
+            }
+            State::UplinkEncrypt => {
+                let payload_len = self.tx_len.get() - FHDR_LEN - 1 - MIC_LEN;
+                let fcnt = self.tx_fcnt.get();
+                self.tx_frame.map(|frame| {
+                    self.crypt_buf.map(|buf| {
+                        frame[FHDR_LEN + 1..FHDR_LEN + 1 + payload_len]
+                            .copy_from_slice(&buf[..payload_len])
+                    });
+                    self.data_mic(
+                        State::UplinkMic,
+                        frame,
+                        FHDR_LEN + 1 + payload_len,
+                        Direction::Up,
+                        fcnt,
+                    );
+                });
+            }
+            State::UplinkMic => {
+                let len = self.tx_len.get();
+                let mic = self.computed_mic(AES128_BLOCK_SIZE + len - MIC_LEN);
+                self.tx_frame
+                    .map(|frame| frame[len - MIC_LEN..len].copy_from_slice(&mic));
+                self.transmit();
+            }
+            State::DownlinkMic => {
+                let window = self.window.get();
+                let len = self.downlink.get().len;
+                if self.downlink_mic_valid(AES128_BLOCK_SIZE + len - MIC_LEN) {
+                    self.data_downlink_valid();
+                } else {
+                    self.rx_window_closed(window);
+                }
+            }
+            State::DownlinkDecrypt => {
+                let downlink = self.downlink.get();
+                let payload_start = FHDR_LEN + (downlink.fctrl & FCTRL_FOPTS_LEN) as usize + 1;
+                let payload_len = downlink.len - MIC_LEN - payload_start;
+                let port = self.rx_frame.map_or(0, |frame| frame[payload_start - 1]);
+                self.crypt_buf.map(|buf| {
+                    self.client.map(|client| {
+                        client.received(port, &buf[..payload_len], downlink.rssi, downlink.snr)
+                    });
+                });
+                self.uplink_answered();
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<A: Alarm, E: AES128<'a> + AES128Ctr + AES128CBC> lora::Client for LoRaWANMac<'a, A, E> {
+    fn transmit_done(&self, result: ReturnCode) {
+        if self.state.get() != State::Transmitting {
+            return;
+        }
+        if result != ReturnCode::SUCCESS {
+            return self.finish(ReturnCode::FAIL);
+        }
+        let (delay1, _) = self.rx_delays();
+        self.tx_end.set(self.alarm.now());
+        self.state.set(State::WaitRx1);
+        self.set_alarm(self.tx_end.get(), delay1 - RX_WINDOW_MARGIN_MS);
+    }
+
+    fn receive_done(&self, payload: &[u8], rssi: i16, snr: i8, result: ReturnCode) {
+        let window = self.state.get();
+        if window != State::Rx1 && window != State::Rx2 {
+            return;
+        }
+        if result != ReturnCode::SUCCESS || payload.len() == 0 {
+            return self.rx_window_closed(window);
+        }
+        self.window.set(window);
+        self.rx_frame
+            .map(|frame| frame[..payload.len()].copy_from_slice(payload));
+        self.downlink.set(Downlink {
+            len: payload.len(),
+            rssi: rssi,
+            snr: snr,
+            fcnt: 0,
+            fctrl: 0,
+        });
+        self.downlink_received(window);
+    }
+}
+
+impl<A: Alarm, E: AES128<'a> + AES128Ctr + AES128CBC> time::Client for LoRaWANMac<'a, A, E> {
+    fn fired(&self) {
+        match self.state.get() {
+            State::WaitRx1 => self.open_rx_window(State::Rx1),
+            State::WaitRx2 => self.open_rx_window(State::Rx2),
+            State::WaitRetransmission => self.transmit(),
+            _ => {}
+        }
+    }
+}
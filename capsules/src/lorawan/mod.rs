@@ -0,0 +1,5 @@
+pub mod driver;
+pub mod mac;
+
+pub use self::driver::LoRaWANDriver;
+pub use self::driver::DRIVER_NUM;
@@ -0,0 +1,720 @@
+//! Driver for the LSM6DS3 and LSM6DSOX accelerometers and gyroscopes.
+//!
+//! <https://www.st.com/resource/en/datasheet/lsm6ds3.pdf>
+//! <https://www.st.com/resource/en/datasheet/lsm6dsox.pdf>
+//!
+//! The driver talks to the IMU over I2C or SPI, and finds out which of the
+//! two it is from the WHO_AM_I register when it is configured. It
+//! implements three interfaces:
+//!
+//! - `hil::sensors::NineDof`: one-shot readings of the accelerometer, in
+//!   milli-g at +/-2 g, and of the gyroscope, in millidegrees per second at
+//!   250 dps. Both run at 104 Hz once configured, so a reading is the last
+//!   output of the IMU.
+//! - `hil::sensors::StreamingSensor`: accelerometer samples batched in the
+//!   hardware FIFO of the IMU, which raises its interrupt pin at the
+//!   watermark. The driver then drains the FIFO into its own buffer of
+//!   `FIFO_SAMPLES` samples, which bounds the watermark.
+//! - `hil::sensors::MotionDetector`: wake-on-motion. The gyroscope is
+//!   turned off and the accelerometer runs at 26 Hz in low-power mode, and
+//!   the IMU raises its interrupt pin when the acceleration changes by more
+//!   than the threshold, at a resolution of 31.25 mg.
+//!
+//! Streaming and wake-on-motion set the accelerometer differently, so only
+//! one of them runs at a time. Both need the INT1 pin of the IMU connected.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let lsm6ds_i2c = static_init!(I2CDevice, I2CDevice::new(i2c_bus, 0x6a));
+//! let lsm6ds = static_init!(
+//!     capsules::lsm6ds::Lsm6ds<'static>,
+//!     capsules::lsm6ds::Lsm6ds::new_i2c(
+//!         lsm6ds_i2c,
+//!         Some(&nrf5x::gpio::PORT[21]), // INT1
+//!         &mut capsules::lsm6ds::BUFFER,
+//!         &mut capsules::lsm6ds::FIFO));
+//! lsm6ds_i2c.set_client(lsm6ds);
+//! nrf5x::gpio::PORT[21].set_client(lsm6ds);
+//! lsm6ds.configure();
+//! ```
+//!
+//! Over SPI, the driver needs a second buffer for the bytes it reads:
+//!
+//! ```rust
+//! let lsm6ds = static_init!(
+//!     capsules::lsm6ds::Lsm6ds<'static>,
+//!     capsules::lsm6ds::Lsm6ds::new_spi(
+//!         lsm6ds_spi,
+//!         Some(&nrf5x::gpio::PORT[21]),
+//!         &mut capsules::lsm6ds::BUFFER,
+//!         &mut capsules::lsm6ds::READ_BUFFER,
+//!         &mut capsules::lsm6ds::FIFO));
+//! lsm6ds_spi.set_client(lsm6ds);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::gpio;
+use kernel::hil::i2c;
+use kernel::hil::sensors;
+use kernel::hil::spi;
+use kernel::ReturnCode;
+
+/// The samples the driver buffers while it drains the hardware FIFO
+pub const FIFO_SAMPLES: usize = 32;
+
+const BUFFER_LEN: usize = 64;
+
+pub static mut BUFFER: [u8; BUFFER_LEN] = [0; BUFFER_LEN];
+pub static mut READ_BUFFER: [u8; BUFFER_LEN] = [0; BUFFER_LEN];
+pub static mut FIFO: [i16; 3 * FIFO_SAMPLES] = [0; 3 * FIFO_SAMPLES];
+
+// Registers of both IMUs
+const INT1_CTRL: u8 = 0x0d;
+const WHO_AM_I: u8 = 0x0f;
+const CTRL1_XL: u8 = 0x10;
+const CTRL2_G: u8 = 0x11;
+const CTRL3_C: u8 = 0x12;
+const CTRL6_C: u8 = 0x15;
+const WAKE_UP_SRC: u8 = 0x1b;
+const OUTX_L_G: u8 = 0x22;
+const OUTX_L_XL: u8 = 0x28;
+const FIFO_STATUS1: u8 = 0x3a;
+const TAP_CFG: u8 = 0x58;
+const WAKE_UP_THS: u8 = 0x5b;
+const WAKE_UP_DUR: u8 = 0x5c;
+const MD1_CFG: u8 = 0x5e;
+
+// Registers of the LSM6DS3 FIFO
+const DS3_FIFO_CTRL1: u8 = 0x06;
+const DS3_FIFO_CTRL2: u8 = 0x07;
+const DS3_FIFO_CTRL3: u8 = 0x08;
+const DS3_FIFO_CTRL5: u8 = 0x0a;
+const DS3_FIFO_DATA_OUT_L: u8 = 0x3e;
+
+// Registers of the LSM6DSOX FIFO and interrupts
+const DSOX_FIFO_CTRL1: u8 = 0x07;
+const DSOX_FIFO_CTRL2: u8 = 0x08;
+const DSOX_FIFO_CTRL3: u8 = 0x09;
+const DSOX_FIFO_CTRL4: u8 = 0x0a;
+const DSOX_TAP_CFG0: u8 = 0x56;
+const DSOX_FIFO_DATA_OUT_TAG: u8 = 0x78;
+
+const WHO_AM_I_LSM6DS3: u8 = 0x69;
+const WHO_AM_I_LSM6DSOX: u8 = 0x6c;
+
+/// The output data rates of the accelerometer, by ODR code minus one
+const RATES: [u32; 8] = [12, 26, 52, 104, 208, 416, 833, 1660];
+
+/// The ODR code of the normal 104 Hz, and of wake-on-motion at 26 Hz
+const ODR_104HZ: u8 = 4;
+const ODR_26HZ: u8 = 2;
+
+/// The highest streaming rate, which a 400 kHz I2C bus keeps up with
+const MAX_RATE: u32 = 833;
+
+/// The register writes of a configuration step
+const MAX_WRITES: usize = 8;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Variant {
+    Lsm6ds3,
+    Lsm6dsox,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    /// Not configured yet
+    Off,
+    Idle,
+    Identify,
+    /// Writing a list of registers
+    Writing,
+    ReadAccel,
+    ReadGyro,
+    ReadWakeUp,
+    ReadFifoStatus,
+    /// Reading samples from the FIFO: the number in this read, and the
+    /// number left after it
+    ReadFifo(usize, usize),
+}
+
+/// What the accelerometer is set up for
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Normal,
+    Streaming,
+    Motion,
+}
+
+/// The little-endian 16-bit value at `index` of the values read into
+/// `buffer`, which start at index 1.
+fn value(buffer: &[u8], index: usize) -> i16 {
+    (buffer[1 + 2 * index] as i16) | ((buffer[2 + 2 * index] as i16) << 8)
+}
+
+enum Bus<'a> {
+    I2C(&'a i2c::I2CDevice),
+    Spi(&'a spi::SpiMasterDevice, TakeCell<'static, [u8]>),
+}
+
+pub struct Lsm6ds<'a> {
+    bus: Bus<'a>,
+    int_pin: Option<&'a gpio::Pin>,
+    variant: Cell<Variant>,
+    state: Cell<State>,
+    mode: Cell<Mode>,
+    buffer: TakeCell<'static, [u8]>,
+    /// The register writes in progress, and the next one
+    writes: Cell<[(u8, u8); MAX_WRITES]>,
+    write_count: Cell<usize>,
+    write_index: Cell<usize>,
+    /// Whether the interrupt pin fired while the bus was busy
+    interrupt_pending: Cell<bool>,
+    /// Whether streaming stopped while the bus was busy
+    stop_pending: Cell<bool>,
+    fifo: TakeCell<'static, [i16]>,
+    fifo_count: Cell<usize>,
+    stream_identifier: Cell<usize>,
+    ninedof_client: OptionalCell<&'static sensors::NineDofClient>,
+    stream_client: OptionalCell<&'static sensors::StreamingSensorClient>,
+    motion_client: OptionalCell<&'static sensors::MotionDetectorClient>,
+}
+
+impl Lsm6ds<'a> {
+    pub fn new_i2c(
+        i2c: &'a i2c::I2CDevice,
+        int_pin: Option<&'a gpio::Pin>,
+        buffer: &'static mut [u8],
+        fifo: &'static mut [i16],
+    ) -> Lsm6ds<'a> {
+        Lsm6ds::new(Bus::I2C(i2c), int_pin, buffer, fifo)
+    }
+
+    pub fn new_spi(
+        spi: &'a spi::SpiMasterDevice,
+        int_pin: Option<&'a gpio::Pin>,
+        buffer: &'static mut [u8],
+        read_buffer: &'static mut [u8],
+        fifo: &'static mut [i16],
+    ) -> Lsm6ds<'a> {
+        Lsm6ds::new(Bus::Spi(spi, TakeCell::new(read_buffer)), int_pin, buffer, fifo)
+    }
+
+    fn new(
+        bus: Bus<'a>,
+        int_pin: Option<&'a gpio::Pin>,
+        buffer: &'static mut [u8],
+        fifo: &'static mut [i16],
+    ) -> Lsm6ds<'a> {
+        Lsm6ds {
+            bus: bus,
+            int_pin: int_pin,
+            variant: Cell::new(Variant::Lsm6ds3),
+            state: Cell::new(State::Off),
+            mode: Cell::new(Mode::Normal),
+            buffer: TakeCell::new(buffer),
+            writes: Cell::new([(0, 0); MAX_WRITES]),
+            write_count: Cell::new(0),
+            write_index: Cell::new(0),
+            interrupt_pending: Cell::new(false),
+            stop_pending: Cell::new(false),
+            fifo: TakeCell::new(fifo),
+            fifo_count: Cell::new(0),
+            stream_identifier: Cell::new(0),
+            ninedof_client: OptionalCell::empty(),
+            stream_client: OptionalCell::empty(),
+            motion_client: OptionalCell::empty(),
+        }
+    }
+
+    /// Identifies the IMU and starts the accelerometer and gyroscope at
+    /// 104 Hz. The other calls return EOFF until this is done.
+    pub fn configure(&self) -> ReturnCode {
+        if self.state.get() != State::Off {
+            return ReturnCode::EALREADY;
+        }
+        if let Bus::Spi(spi, _) = self.bus {
+            spi.configure(
+                spi::ClockPolarity::IdleHigh,
+                spi::ClockPhase::SampleTrailing,
+                4_000_000,
+            );
+        }
+        self.int_pin.map(|pin| {
+            pin.make_input();
+            pin.enable_interrupt(0, gpio::InterruptMode::RisingEdge);
+        });
+        self.read(State::Identify, WHO_AM_I, 1);
+        ReturnCode::SUCCESS
+    }
+
+    /// Reads `len` registers from `register`. The values are passed to
+    /// `transfer_done` from index 1 of the buffer, over both buses.
+    fn read(&self, state: State, register: u8, len: usize) {
+        self.state.set(state);
+        self.buffer.take().map(|buffer| {
+            buffer[0] = register;
+            match self.bus {
+                Bus::I2C(i2c) => i2c.write_read(buffer, 1, len as u8),
+                Bus::Spi(spi, ref read_buffer) => {
+                    buffer[0] |= 0x80;
+                    read_buffer.take().map(|read_buffer| {
+                        spi.read_write_bytes(buffer, Some(read_buffer), len + 1);
+                    });
+                }
+            }
+        });
+    }
+
+    fn write(&self, register: u8, value: u8) {
+        self.buffer.take().map(|buffer| {
+            buffer[0] = register;
+            buffer[1] = value;
+            match self.bus {
+                Bus::I2C(i2c) => i2c.write(buffer, 2),
+                Bus::Spi(spi, _) => {
+                    spi.read_write_bytes(buffer, None, 2);
+                }
+            }
+        });
+    }
+
+    /// Writes a list of registers in order, and then returns to idle.
+    fn write_registers(&self, writes: &[(u8, u8)]) {
+        let mut list = [(0, 0); MAX_WRITES];
+        list[..writes.len()].copy_from_slice(writes);
+        self.writes.set(list);
+        self.write_count.set(writes.len());
+        self.write_index.set(1);
+        self.state.set(State::Writing);
+        self.write(writes[0].0, writes[0].1);
+    }
+
+    fn idle(&self) {
+        self.state.set(State::Idle);
+        if self.stop_pending.get() {
+            self.stop_pending.set(false);
+            self.interrupt_pending.set(false);
+            self.stop_fifo();
+        } else if self.interrupt_pending.get() {
+            self.interrupt_pending.set(false);
+            self.handle_interrupt();
+        }
+    }
+
+    fn handle_interrupt(&self) {
+        match self.mode.get() {
+            Mode::Motion => self.read(State::ReadWakeUp, WAKE_UP_SRC, 1),
+            Mode::Streaming => self.read(State::ReadFifoStatus, FIFO_STATUS1, 2),
+            Mode::Normal => {}
+        }
+    }
+
+    fn stop_fifo(&self) {
+        let fifo_mode = match self.variant.get() {
+            Variant::Lsm6ds3 => DS3_FIFO_CTRL5,
+            Variant::Lsm6dsox => DSOX_FIFO_CTRL4,
+        };
+        self.write_registers(&[
+            (INT1_CTRL, 0x00),
+            (fifo_mode, 0x00),
+            (CTRL1_XL, ODR_104HZ << 4),
+        ]);
+    }
+
+    /// The bytes of a sample in the FIFO
+    fn sample_len(&self) -> usize {
+        match self.variant.get() {
+            Variant::Lsm6ds3 => 6,
+            // A tag, then the sample
+            Variant::Lsm6dsox => 7,
+        }
+    }
+
+    fn read_fifo(&self, samples: usize) {
+        let chunk = (BUFFER_LEN - 1) / self.sample_len();
+        let count = if samples < chunk { samples } else { chunk };
+        let register = match self.variant.get() {
+            // The address rolls back to FIFO_DATA_OUT_L after each word
+            Variant::Lsm6ds3 => DS3_FIFO_DATA_OUT_L,
+            Variant::Lsm6dsox => DSOX_FIFO_DATA_OUT_TAG,
+        };
+        self.read(State::ReadFifo(count, samples - count), register, count * self.sample_len());
+    }
+
+    /// Passes the samples in `fifo`, the buffer of the driver, to the client.
+    fn deliver(&self, fifo: &[i16]) {
+        let count = self.fifo_count.get();
+        self.fifo_count.set(0);
+        self.stream_client.map(|client| {
+            client.samples_ready(self.stream_identifier.get(), &fifo[..3 * count], 0)
+        });
+    }
+
+    fn transfer_done(&self, buffer: &'static mut [u8]) {
+        let state = self.state.get();
+
+        match state {
+            State::Identify => {
+                let variant = match buffer[1] {
+                    WHO_AM_I_LSM6DS3 => Some(Variant::Lsm6ds3),
+                    WHO_AM_I_LSM6DSOX => Some(Variant::Lsm6dsox),
+                    _ => None,
+                };
+                self.buffer.replace(buffer);
+                match variant {
+                    Some(variant) => {
+                        self.variant.set(variant);
+                        self.write_registers(&[
+                            // Block data update, address auto-increment
+                            (CTRL3_C, 0x44),
+                            (CTRL1_XL, ODR_104HZ << 4),
+                            (CTRL2_G, ODR_104HZ << 4),
+                        ]);
+                    }
+                    None => self.state.set(State::Off),
+                }
+            }
+
+            State::Writing => {
+                self.buffer.replace(buffer);
+                let index = self.write_index.get();
+                if index < self.write_count.get() {
+                    self.write_index.set(index + 1);
+                    let (register, value) = self.writes.get()[index];
+                    self.write(register, value);
+                } else {
+                    self.idle();
+                }
+            }
+
+            State::ReadAccel | State::ReadGyro => {
+                // 0.061 mg or 8.75 mdps per LSB
+                let (x, y, z) = if state == State::ReadAccel {
+                    (
+                        value(buffer, 0) as isize * 61 / 1000,
+                        value(buffer, 1) as isize * 61 / 1000,
+                        value(buffer, 2) as isize * 61 / 1000,
+                    )
+                } else {
+                    (
+                        value(buffer, 0) as isize * 875 / 100,
+                        value(buffer, 1) as isize * 875 / 100,
+                        value(buffer, 2) as isize * 875 / 100,
+                    )
+                };
+                self.buffer.replace(buffer);
+                self.idle();
+                self.ninedof_client
+                    .map(|client| client.callback(x as usize, y as usize, z as usize));
+            }
+
+            State::ReadWakeUp => {
+                let motion = buffer[1] & 0x08 != 0;
+                self.buffer.replace(buffer);
+                self.idle();
+                if motion {
+                    self.motion_client.map(|client| client.motion_detected());
+                }
+            }
+
+            State::ReadFifoStatus => {
+                let samples = match self.variant.get() {
+                    Variant::Lsm6ds3 => {
+                        (buffer[1] as usize | ((buffer[2] as usize & 0x0f) << 8)) / 3
+                    }
+                    Variant::Lsm6dsox => buffer[1] as usize | ((buffer[2] as usize & 0x03) << 8),
+                };
+                self.buffer.replace(buffer);
+                if samples > 0 {
+                    self.read_fifo(samples);
+                } else {
+                    self.idle();
+                }
+            }
+
+            State::ReadFifo(samples, remaining) => {
+                let sample_len = self.sample_len();
+                let offset = sample_len - 6;
+                self.fifo.map(|fifo| {
+                    for sample in 0..samples {
+                        if self.fifo_count.get() == FIFO_SAMPLES {
+                            self.deliver(fifo);
+                        }
+                        let start = 1 + sample * sample_len;
+                        // Only accelerometer samples are batched, tag 2
+                        if offset == 1 && buffer[start] >> 3 != 0x02 {
+                            continue;
+                        }
+                        let count = self.fifo_count.get();
+                        for axis in 0..3 {
+                            let raw = value(&buffer[start + offset - 1..], axis);
+                            fifo[3 * count + axis] = (raw as i32 * 61 / 1000) as i16;
+                        }
+                        self.fifo_count.set(count + 1);
+                    }
+                    if remaining == 0 {
+                        self.deliver(fifo);
+                    }
+                });
+                self.buffer.replace(buffer);
+
+                if remaining > 0 && self.mode.get() == Mode::Streaming {
+                    self.read_fifo(remaining);
+                } else {
+                    self.idle();
+                }
+            }
+
+            State::Off | State::Idle => {
+                self.buffer.replace(buffer);
+            }
+        }
+    }
+
+    /// Checks that the driver can start an operation on the bus.
+    fn check_ready(&self) -> ReturnCode {
+        match self.state.get() {
+            State::Off => ReturnCode::EOFF,
+            State::Idle => ReturnCode::SUCCESS,
+            _ => ReturnCode::EBUSY,
+        }
+    }
+}
+
+impl i2c::I2CClient for Lsm6ds<'a> {
+    fn command_complete(&self, buffer: &'static mut [u8], _error: i2c::Error) {
+        // Move the values read after the register address, as over SPI
+        let mut index = buffer.len() - 1;
+        while index > 0 {
+            buffer[index] = buffer[index - 1];
+            index -= 1;
+        }
+        self.transfer_done(buffer);
+    }
+}
+
+impl spi::SpiMasterClient for Lsm6ds<'a> {
+    fn read_write_done(
+        &self,
+        write_buffer: &'static mut [u8],
+        read_buffer: Option<&'static mut [u8]>,
+        len: usize,
+    ) {
+        if let Bus::Spi(_, ref read_cell) = self.bus {
+            read_buffer.map(|read_buffer| {
+                write_buffer[1..len].copy_from_slice(&read_buffer[1..len]);
+                read_cell.replace(read_buffer);
+            });
+        }
+        self.transfer_done(write_buffer);
+    }
+}
+
+impl gpio::Client for Lsm6ds<'a> {
+    fn fired(&self, _: usize) {
+        if self.state.get() == State::Idle {
+            self.handle_interrupt();
+        } else {
+            self.interrupt_pending.set(true);
+        }
+    }
+}
+
+impl sensors::NineDof for Lsm6ds<'a> {
+    fn set_client(&self, client: &'static sensors::NineDofClient) {
+        self.ninedof_client.set(client);
+    }
+
+    fn read_accelerometer(&self) -> ReturnCode {
+        let rcode = self.check_ready();
+        if rcode == ReturnCode::SUCCESS {
+            self.read(State::ReadAccel, OUTX_L_XL, 6);
+        }
+        rcode
+    }
+
+    fn read_gyroscope(&self) -> ReturnCode {
+        let rcode = self.check_ready();
+        if rcode != ReturnCode::SUCCESS {
+            return rcode;
+        }
+        if self.mode.get() == Mode::Motion {
+            // The gyroscope is off
+            return ReturnCode::EOFF;
+        }
+        self.read(State::ReadGyro, OUTX_L_G, 6);
+        ReturnCode::SUCCESS
+    }
+}
+
+impl sensors::StreamingSensor for Lsm6ds<'a> {
+    fn set_client(&self, client: &'static sensors::StreamingSensorClient) {
+        self.stream_client.set(client);
+    }
+
+    fn sample_size(&self) -> usize {
+        3
+    }
+
+    fn get_maximum_rate(&self) -> u32 {
+        MAX_RATE
+    }
+
+    fn get_fifo_size(&self) -> usize {
+        FIFO_SAMPLES
+    }
+
+    fn start(&self, identifier: usize, rate: u32, watermark: usize) -> ReturnCode {
+        if self.int_pin.is_none() {
+            return ReturnCode::ENODEVICE;
+        }
+        if self.mode.get() != Mode::Normal {
+            return ReturnCode::EBUSY;
+        }
+        if rate == 0 || watermark == 0 || watermark > FIFO_SAMPLES {
+            return ReturnCode::EINVAL;
+        }
+        let rcode = self.check_ready();
+        if rcode != ReturnCode::SUCCESS {
+            return rcode;
+        }
+
+        // The fastest rate that does not exceed the request, but at least
+        // the slowest one
+        let index = RATES
+            .iter()
+            .take_while(|&&odr| odr <= rate && odr <= MAX_RATE)
+            .count();
+        let index = if index == 0 { 0 } else { index - 1 };
+        let odr = index as u8 + 1;
+
+        self.stream_identifier.set(identifier);
+        self.fifo_count.set(0);
+        self.mode.set(Mode::Streaming);
+        match self.variant.get() {
+            Variant::Lsm6ds3 => {
+                // The watermark counts 16-bit words
+                let words = 3 * watermark;
+                self.write_registers(&[
+                    (CTRL1_XL, odr << 4),
+                    (DS3_FIFO_CTRL1, words as u8),
+                    (DS3_FIFO_CTRL2, (words >> 8) as u8 & 0x0f),
+                    // Accelerometer without decimation, no gyroscope
+                    (DS3_FIFO_CTRL3, 0x01),
+                    // Bypass mode empties the FIFO, then continuous mode
+                    (DS3_FIFO_CTRL5, 0x00),
+                    (DS3_FIFO_CTRL5, (odr << 3) | 0x06),
+                    (INT1_CTRL, 0x08),
+                ]);
+            }
+            Variant::Lsm6dsox => {
+                self.write_registers(&[
+                    (CTRL1_XL, odr << 4),
+                    (DSOX_FIFO_CTRL1, watermark as u8),
+                    (DSOX_FIFO_CTRL2, (watermark >> 8) as u8 & 0x01),
+                    // Batch the accelerometer at its rate, not the gyroscope
+                    (DSOX_FIFO_CTRL3, odr),
+                    (DSOX_FIFO_CTRL4, 0x00),
+                    (DSOX_FIFO_CTRL4, 0x06),
+                    (INT1_CTRL, 0x08),
+                ]);
+            }
+        }
+        ReturnCode::SuccessWithValue {
+            value: RATES[index] as usize,
+        }
+    }
+
+    fn stop(&self) -> ReturnCode {
+        if self.mode.get() != Mode::Streaming {
+            return ReturnCode::EOFF;
+        }
+        self.mode.set(Mode::Normal);
+        self.fifo_count.set(0);
+        match self.state.get() {
+            State::Idle => self.stop_fifo(),
+            // Stopped when the bus is free
+            _ => self.stop_pending.set(true),
+        }
+        ReturnCode::SUCCESS
+    }
+
+    fn flush(&self) -> ReturnCode {
+        if self.mode.get() != Mode::Streaming {
+            return ReturnCode::EOFF;
+        }
+        match self.state.get() {
+            State::Idle => self.handle_interrupt(),
+            // Drained when the bus is free
+            _ => self.interrupt_pending.set(true),
+        }
+        ReturnCode::SUCCESS
+    }
+}
+
+impl sensors::MotionDetector for Lsm6ds<'a> {
+    fn set_client(&self, client: &'static sensors::MotionDetectorClient) {
+        self.motion_client.set(client);
+    }
+
+    fn enable_motion_detection(&self, threshold: u32) -> ReturnCode {
+        if self.int_pin.is_none() {
+            return ReturnCode::ENODEVICE;
+        }
+        if self.mode.get() != Mode::Normal {
+            return ReturnCode::EBUSY;
+        }
+        let rcode = self.check_ready();
+        if rcode != ReturnCode::SUCCESS {
+            return rcode;
+        }
+
+        // 1/64 of the full scale of 2 g per LSB
+        let ths = cmp::max(1, cmp::min(threshold * 64 / 2000, 0x3f)) as u8;
+
+        // The latch bit is in TAP_CFG0 on the LSM6DSOX, and in the register
+        // that enables the interrupts on the LSM6DS3
+        let (latch, enable) = match self.variant.get() {
+            Variant::Lsm6ds3 => ((TAP_CFG, 0x01), 0x81),
+            Variant::Lsm6dsox => ((DSOX_TAP_CFG0, 0x01), 0x80),
+        };
+        self.mode.set(Mode::Motion);
+        self.write_registers(&[
+            (CTRL2_G, 0x00),
+            (CTRL1_XL, ODR_26HZ << 4),
+            // Low-power mode of the accelerometer
+            (CTRL6_C, 0x10),
+            (WAKE_UP_DUR, 0x00),
+            (WAKE_UP_THS, ths),
+            // Latch the wake-up interrupt until WAKE_UP_SRC is read
+            latch,
+            (TAP_CFG, enable),
+            (MD1_CFG, 0x20),
+        ]);
+        ReturnCode::SUCCESS
+    }
+
+    fn disable_motion_detection(&self) -> ReturnCode {
+        if self.mode.get() != Mode::Motion {
+            return ReturnCode::EOFF;
+        }
+        let rcode = self.check_ready();
+        if rcode != ReturnCode::SUCCESS {
+            return rcode;
+        }
+        self.mode.set(Mode::Normal);
+        self.write_registers(&[
+            (MD1_CFG, 0x00),
+            (TAP_CFG, 0x00),
+            (CTRL6_C, 0x00),
+            (CTRL1_XL, ODR_104HZ << 4),
+            (CTRL2_G, ODR_104HZ << 4),
+        ]);
+        ReturnCode::SUCCESS
+    }
+}
@@ -0,0 +1,271 @@
+//! Driver for the Maxim MAX17048 fuel gauge.
+//!
+//! <https://datasheets.maximintegrated.com/en/ds/MAX17048-MAX17049.pdf>
+//!
+//! > The MAX17048/MAX17049 ICs are tiny, micropower current fuel gauges for
+//! > lithium-ion (Li+) batteries in handheld and portable equipment. The
+//! > MAX17048 operates with a single lithium cell and the MAX17049 with two
+//! > lithium cells in series.
+//!
+//! The gauge estimates the state of charge from the cell voltage alone, so
+//! it needs no sense resistor. It implements `hil::sensors::FuelGauge`.
+//!
+//! The low charge alert uses the active-low ALRT output, which is optional.
+//! When it fires, the driver reads the state of charge, clears the alert in
+//! the STATUS and CONFIG registers, and then calls the client.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let max17048_i2c = static_init!(
+//!     capsules::virtual_i2c::I2CDevice,
+//!     capsules::virtual_i2c::I2CDevice::new(i2c_bus, 0x36));
+//! let max17048 = static_init!(
+//!     capsules::max17048::Max17048<'static>,
+//!     capsules::max17048::Max17048::new(
+//!         max17048_i2c,
+//!         Some(&sam4l::gpio::PA[8]), // ALRT
+//!         &mut capsules::max17048::BUFFER));
+//! max17048_i2c.set_client(max17048);
+//! sam4l::gpio::PA[8].set_client(max17048);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::gpio;
+use kernel::hil::i2c;
+use kernel::hil::sensors;
+use kernel::ReturnCode;
+
+pub static mut BUFFER: [u8; 4] = [0; 4];
+
+#[allow(dead_code)]
+enum Registers {
+    VCell = 0x02,  // Cell voltage, LSB = 78.125 uV
+    Soc = 0x04,    // State of charge, LSB = 1/256 %
+    Mode = 0x06,
+    Version = 0x08,
+    Config = 0x0c, // RCOMP, then SLEEP, ALSC, ALRT and ATHD
+    CRate = 0x16,  // Charge rate, LSB = 0.208 %/hr
+    Status = 0x1a, // Alert flags in the high byte
+}
+
+/// The default RCOMP, the compensation of the model of the cell
+const RCOMP: u8 = 0x97;
+
+/// The HD bit of STATUS, set when the state of charge crossed the threshold
+const STATUS_HD: u8 = 1 << 4;
+
+/// The alert thresholds the gauge supports, in percent
+const MAX_ALERT_PERCENT: u32 = 32;
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    ReadVCellSoc,
+    ReadCRate,
+    WriteConfig,
+    AlertStatus,
+    AlertSoc,
+    AlertClearStatus,
+    AlertClearConfig,
+}
+
+pub struct Max17048<'a> {
+    i2c: &'a i2c::I2CDevice,
+    alert_pin: Option<&'a gpio::Pin>,
+    state: Cell<State>,
+    buffer: TakeCell<'static, [u8]>,
+    /// The ATHD field of CONFIG, 32 minus the threshold in percent
+    athd: Cell<u8>,
+    /// The last readings, in hundredths of percent and millivolts
+    soc: Cell<u32>,
+    voltage: Cell<u32>,
+    /// Whether an alert fired while the bus was busy
+    alert_pending: Cell<bool>,
+    /// Whether the alert being cleared is a low charge, for the client
+    low_charge: Cell<bool>,
+    client: OptionalCell<&'static sensors::FuelGaugeClient>,
+}
+
+impl Max17048<'a> {
+    pub fn new(
+        i2c: &'a i2c::I2CDevice,
+        alert_pin: Option<&'a gpio::Pin>,
+        buffer: &'static mut [u8],
+    ) -> Max17048<'a> {
+        Max17048 {
+            i2c: i2c,
+            alert_pin: alert_pin,
+            state: Cell::new(State::Idle),
+            buffer: TakeCell::new(buffer),
+            // 4 %, the default
+            athd: Cell::new(0x1c),
+            soc: Cell::new(0),
+            voltage: Cell::new(0),
+            alert_pending: Cell::new(false),
+            low_charge: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    fn read(&self, state: State, register: Registers, len: u8) -> ReturnCode {
+        self.buffer.take().map_or(ReturnCode::EBUSY, |buffer| {
+            self.i2c.enable();
+            buffer[0] = register as u8;
+            self.i2c.write_read(buffer, 1, len);
+            self.state.set(state);
+            ReturnCode::SUCCESS
+        })
+    }
+
+    fn write(&self, state: State, register: Registers, value: [u8; 2]) -> ReturnCode {
+        self.buffer.take().map_or(ReturnCode::EBUSY, |buffer| {
+            self.i2c.enable();
+            buffer[0] = register as u8;
+            buffer[1] = value[0];
+            buffer[2] = value[1];
+            self.i2c.write(buffer, 3);
+            self.state.set(state);
+            ReturnCode::SUCCESS
+        })
+    }
+
+    fn idle(&self) {
+        self.i2c.disable();
+        self.state.set(State::Idle);
+        if self.alert_pending.get() {
+            self.alert_pending.set(false);
+            self.read(State::AlertStatus, Registers::Status, 2);
+        }
+    }
+
+    fn soc_from_raw(buffer: &[u8]) -> u32 {
+        (((buffer[0] as u32) << 8) | buffer[1] as u32) * 100 / 256
+    }
+}
+
+impl i2c::I2CClient for Max17048<'a> {
+    fn command_complete(&self, buffer: &'static mut [u8], error: i2c::Error) {
+        if error != i2c::Error::CommandComplete {
+            let state = self.state.get();
+            self.buffer.replace(buffer);
+            self.idle();
+            if state == State::ReadVCellSoc || state == State::ReadCRate {
+                self.client
+                    .map(|client| client.battery_read(0, 0, 0, ReturnCode::FAIL));
+            }
+            return;
+        }
+
+        match self.state.get() {
+            State::ReadVCellSoc => {
+                // 78.125 uV is 5/64 mV
+                let vcell = ((buffer[0] as u32) << 8) | buffer[1] as u32;
+                self.voltage.set(vcell * 5 / 64);
+                self.soc.set(Max17048::soc_from_raw(&buffer[2..4]));
+                self.buffer.replace(buffer);
+                self.read(State::ReadCRate, Registers::CRate, 2);
+            }
+            State::ReadCRate => {
+                let crate_raw = (((buffer[0] as u16) << 8) | buffer[1] as u16) as i16;
+                self.buffer.replace(buffer);
+                self.idle();
+                let rate = crate_raw as i32 * 208 / 10;
+                self.client.map(|client| {
+                    client.battery_read(
+                        self.soc.get(),
+                        self.voltage.get(),
+                        rate,
+                        ReturnCode::SUCCESS,
+                    )
+                });
+            }
+            State::WriteConfig => {
+                self.buffer.replace(buffer);
+                self.idle();
+            }
+            State::AlertStatus => {
+                let low = buffer[0] & STATUS_HD != 0;
+                self.buffer.replace(buffer);
+                self.low_charge.set(low);
+                if low {
+                    self.read(State::AlertSoc, Registers::Soc, 2);
+                } else {
+                    self.write(
+                        State::AlertClearConfig,
+                        Registers::Config,
+                        [RCOMP, self.athd.get()],
+                    );
+                }
+            }
+            State::AlertSoc => {
+                self.soc.set(Max17048::soc_from_raw(buffer));
+                self.buffer.replace(buffer);
+                self.write(State::AlertClearStatus, Registers::Status, [0, 0]);
+            }
+            State::AlertClearStatus => {
+                self.buffer.replace(buffer);
+                // Writing ALRT as 0 releases the ALRT pin
+                self.write(State::AlertClearConfig, Registers::Config, [RCOMP, self.athd.get()]);
+            }
+            State::AlertClearConfig => {
+                self.buffer.replace(buffer);
+                self.idle();
+                if self.low_charge.get() {
+                    self.low_charge.set(false);
+                    self.client.map(|client| client.low_charge(self.soc.get()));
+                }
+            }
+            State::Idle => {
+                self.buffer.replace(buffer);
+            }
+        }
+    }
+}
+
+impl gpio::Client for Max17048<'a> {
+    fn fired(&self, _: usize) {
+        if self.state.get() == State::Idle {
+            self.read(State::AlertStatus, Registers::Status, 2);
+        } else {
+            self.alert_pending.set(true);
+        }
+    }
+}
+
+impl sensors::FuelGauge for Max17048<'a> {
+    fn set_client(&self, client: &'static sensors::FuelGaugeClient) {
+        self.client.set(client);
+    }
+
+    fn read_battery(&self) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        self.read(State::ReadVCellSoc, Registers::VCell, 4)
+    }
+
+    fn set_low_charge_alert(&self, percent: u32) -> ReturnCode {
+        let pin = match self.alert_pin {
+            Some(pin) => pin,
+            None => return ReturnCode::ENODEVICE,
+        };
+        if percent > MAX_ALERT_PERCENT {
+            return ReturnCode::EINVAL;
+        }
+        if percent == 0 {
+            pin.disable_interrupt();
+            return ReturnCode::SUCCESS;
+        }
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+
+        self.athd.set((MAX_ALERT_PERCENT - percent) as u8);
+        pin.make_input();
+        pin.enable_interrupt(0, gpio::InterruptMode::FallingEdge);
+        self.write(State::WriteConfig, Registers::Config, [RCOMP, self.athd.get()])
+    }
+}
@@ -0,0 +1,689 @@
+//! Driver for the MCP2515 CAN controller.
+//!
+//! <http://www.microchip.com/wwwproducts/en/MCP2515>
+//!
+//! The MCP2515 is a standalone CAN 2.0B controller with an SPI interface. It
+//! has three transmit buffers and two receive buffers, and signals received
+//! frames, sent frames and errors on its INT pin. This driver implements
+//! `hil::can::Can`, using one transmit buffer and both receive buffers,
+//! with receive buffer 0 rolling over into receive buffer 1 when it is full.
+//!
+//! The controller has two acceptance masks, so it accepts at most two
+//! filters, one for each receive buffer.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let mcp2515_spi = static_init!(
+//!     capsules::virtual_spi::VirtualSpiMasterDevice<'static, sam4l::spi::SpiHw>,
+//!     capsules::virtual_spi::VirtualSpiMasterDevice::new(mux_spi, 2)
+//! );
+//! let mcp2515 = static_init!(
+//!     capsules::mcp2515::MCP2515<
+//!         'static,
+//!         capsules::virtual_spi::VirtualSpiMasterDevice<'static, sam4l::spi::SpiHw>,
+//!     >,
+//!     capsules::mcp2515::MCP2515::new(
+//!         mcp2515_spi,
+//!         &sam4l::gpio::PA[20],
+//!         16_000_000,
+//!         &mut capsules::mcp2515::TX_BUF,
+//!         &mut capsules::mcp2515::RX_BUF
+//!     )
+//! );
+//! mcp2515_spi.set_client(mcp2515);
+//! sam4l::gpio::PA[20].set_client(mcp2515);
+//! hil::can::Can::set_bitrate(mcp2515, 500_000);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::can::{self, ErrorState, Filter, Frame, Id, MAX_DATA_LEN};
+use kernel::hil::gpio;
+use kernel::hil::spi;
+use kernel::ReturnCode;
+
+/// The longest SPI transaction: an instruction, an address, and three
+/// acceptance filters
+const BUF_LEN: usize = 14;
+
+pub static mut TX_BUF: [u8; BUF_LEN] = [0; BUF_LEN];
+pub static mut RX_BUF: [u8; BUF_LEN] = [0; BUF_LEN];
+
+const SPI_SPEED: u32 = 4000000;
+
+/// How many times to check that the controller came out of reset before
+/// giving up on it
+const RESET_RETRIES: usize = 100;
+
+#[allow(dead_code)]
+enum Instruction {
+    Reset = 0xc0,
+    Read = 0x03,
+    ReadRxBuffer0 = 0x90,
+    ReadRxBuffer1 = 0x94,
+    Write = 0x02,
+    LoadTxBuffer0 = 0x40,
+    RequestToSend0 = 0x81,
+    BitModify = 0x05,
+}
+
+#[allow(dead_code)]
+enum Register {
+    RXF0SIDH = 0x00,
+    RXF3SIDH = 0x10,
+    RXM0SIDH = 0x20,
+    CANSTAT = 0x0e,
+    CANCTRL = 0x0f,
+    CNF3 = 0x28,
+    CANINTF = 0x2c,
+    EFLG = 0x2d,
+    TXB0CTRL = 0x30,
+    RXB0CTRL = 0x60,
+    RXB1CTRL = 0x70,
+}
+
+/// CANCTRL and CANSTAT operation modes
+const MODE_NORMAL: u8 = 0x00;
+const MODE_CONFIGURATION: u8 = 0x80;
+
+/// CANINTE and CANINTF bits
+const RX0IF: u8 = 0x01;
+const RX1IF: u8 = 0x02;
+const TX0IF: u8 = 0x04;
+const ERRIF: u8 = 0x20;
+
+/// EFLG bits
+const RX1OVR: u8 = 0x80;
+const RX0OVR: u8 = 0x40;
+const TXBO: u8 = 0x20;
+const TXEP: u8 = 0x10;
+const RXEP: u8 = 0x08;
+
+/// TXBnCTRL bits
+const TXREQ: u8 = 0x08;
+
+/// RXBnCTRL bits
+const RXM_ANY: u8 = 0x60;
+const BUKT: u8 = 0x04;
+
+/// SIDL bits
+const SRR: u8 = 0x10;
+const EXIDE: u8 = 0x08;
+
+/// DLC bits
+const RTR: u8 = 0x40;
+
+/// Bit timing: each bit is 16 time quanta, with a propagation segment of 5,
+/// a phase segment 1 of 6 and a phase segment 2 of 4, sampling at 75%.
+const CNF2: u8 = 0x80 | (6 - 1) << 3 | (5 - 1);
+const CNF3: u8 = 4 - 1;
+const TIME_QUANTA: u32 = 16;
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Disabled,
+
+    // Enabling
+    Reset,
+    WaitConfigurationMode,
+    WriteTiming,
+    WriteFilters0,
+    WriteFilters1,
+    WriteMasks,
+    WriteRxCtrl0,
+    WriteRxCtrl1,
+    WriteNormalMode,
+
+    Idle,
+
+    // Handling interrupts
+    ReadFlags,
+    ReadFrame,
+    ClearTxFlag,
+    ClearOverflow { eflg: u8 },
+    ClearErrorFlag { eflg: u8 },
+    AbortSend,
+
+    // Sending
+    LoadFrame,
+    RequestSend,
+
+    // Disabling
+    WriteConfigurationMode,
+}
+
+/// Write `id` in the layout of the SIDH, SIDL, EID8 and EID0 registers
+fn encode_id(id: Id, buf: &mut [u8]) {
+    match id {
+        Id::Standard(sid) => {
+            buf[0] = (sid >> 3) as u8;
+            buf[1] = ((sid & 0x7) << 5) as u8;
+            buf[2] = 0;
+            buf[3] = 0;
+        }
+        Id::Extended(id) => {
+            let sid = id >> 18;
+            let eid = id & 0x3ffff;
+            buf[0] = (sid >> 3) as u8;
+            buf[1] = ((sid & 0x7) << 5) as u8 | EXIDE | (eid >> 16) as u8;
+            buf[2] = (eid >> 8) as u8;
+            buf[3] = eid as u8;
+        }
+    }
+}
+
+/// Read an identifier from the layout of the SIDH, SIDL, EID8 and EID0
+/// registers
+fn decode_id(buf: &[u8]) -> Id {
+    let sid = (buf[0] as u16) << 3 | (buf[1] >> 5) as u16;
+    if buf[1] & EXIDE != 0 {
+        Id::Extended(
+            (sid as u32) << 18
+                | ((buf[1] & 0x3) as u32) << 16
+                | (buf[2] as u32) << 8
+                | buf[3] as u32,
+        )
+    } else {
+        Id::Standard(sid)
+    }
+}
+
+/// Write the acceptance filter and mask registers for `filter`
+fn encode_filter(filter: Filter, filter_buf: &mut [u8], mask_buf: &mut [u8]) {
+    encode_id(filter.id, filter_buf);
+    let mask = match filter.id {
+        Id::Standard(_) => Id::Standard(filter.mask as u16 & 0x7ff),
+        Id::Extended(_) => Id::Extended(filter.mask & 0x1fffffff),
+    };
+    encode_id(mask, mask_buf);
+}
+
+pub struct MCP2515<'a, S: spi::SpiMasterDevice + 'a> {
+    spi: &'a S,
+    int_pin: &'a gpio::Pin,
+    oscillator_frequency: u32,
+    state: Cell<State>,
+    client: OptionalCell<&'static can::Client>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    rx_buffer: TakeCell<'static, [u8]>,
+
+    // Configuration, written when the controller is enabled
+    baud_rate_prescaler: Cell<u8>,
+    filters: [Cell<Option<Filter>>; 2],
+
+    retries: Cell<usize>,
+    interrupt_pending: Cell<bool>,
+    error_state: Cell<ErrorState>,
+    // The frame to load into the transmit buffer
+    tx_frame: Cell<Option<Frame>>,
+    // Whether the transmit buffer holds a frame that is being sent
+    sending: Cell<bool>,
+}
+
+impl<S: spi::SpiMasterDevice> MCP2515<'a, S> {
+    /// Create the driver for a controller with an oscillator of
+    /// `oscillator_frequency` Hz. The bit rate is 1/32 of that frequency
+    /// until it is set.
+    pub fn new(
+        spi: &'a S,
+        int_pin: &'a gpio::Pin,
+        oscillator_frequency: u32,
+        tx_buffer: &'static mut [u8],
+        rx_buffer: &'static mut [u8],
+    ) -> MCP2515<'a, S> {
+        MCP2515 {
+            spi: spi,
+            int_pin: int_pin,
+            oscillator_frequency: oscillator_frequency,
+            state: Cell::new(State::Disabled),
+            client: OptionalCell::empty(),
+            tx_buffer: TakeCell::new(tx_buffer),
+            rx_buffer: TakeCell::new(rx_buffer),
+            baud_rate_prescaler: Cell::new(0),
+            filters: [Cell::new(None), Cell::new(None)],
+            retries: Cell::new(0),
+            interrupt_pending: Cell::new(false),
+            error_state: Cell::new(ErrorState::Active),
+            tx_frame: Cell::new(None),
+            sending: Cell::new(false),
+        }
+    }
+
+    /// Start an SPI transaction of the bytes that `fill` writes to the
+    /// transmit buffer, and move to `state`
+    fn transfer<F>(&self, state: State, fill: F) -> ReturnCode
+    where
+        F: FnOnce(&mut [u8]) -> usize,
+    {
+        self.tx_buffer
+            .take()
+            .map_or(ReturnCode::EBUSY, |tx_buffer| {
+                self.rx_buffer.take().map_or(ReturnCode::EBUSY, move |rx_buffer| {
+                    let len = fill(tx_buffer);
+                    self.state.set(state);
+                    self.spi.read_write_bytes(tx_buffer, Some(rx_buffer), len)
+                })
+            })
+    }
+
+    /// Write `value` to the register at `address`
+    fn write_register(&self, state: State, address: Register, value: u8) -> ReturnCode {
+        self.transfer(state, |buf| {
+            buf[0] = Instruction::Write as u8;
+            buf[1] = address as u8;
+            buf[2] = value;
+            3
+        })
+    }
+
+    /// Clear the bits in `mask` of the register at `address`
+    fn clear_bits(&self, state: State, address: Register, mask: u8) -> ReturnCode {
+        self.transfer(state, |buf| {
+            buf[0] = Instruction::BitModify as u8;
+            buf[1] = address as u8;
+            buf[2] = mask;
+            buf[3] = 0;
+            4
+        })
+    }
+
+    fn read_canstat(&self) -> ReturnCode {
+        self.transfer(State::WaitConfigurationMode, |buf| {
+            buf[0] = Instruction::Read as u8;
+            buf[1] = Register::CANSTAT as u8;
+            3
+        })
+    }
+
+    /// The filter for each receive buffer: the second receive buffer uses
+    /// the first filter if there is only one
+    fn buffer_filter(&self, buffer: usize) -> Option<Filter> {
+        self.filters[buffer]
+            .get()
+            .or_else(|| self.filters[0].get())
+    }
+
+    /// Write three acceptance filters, each set to the filter of the receive
+    /// buffer that they belong to
+    fn write_filters(&self, state: State, address: Register, buffers: [usize; 3]) {
+        self.transfer(state, |buf| {
+            buf[0] = Instruction::Write as u8;
+            buf[1] = address as u8;
+            let mut mask = [0; 4];
+            for (i, &buffer) in buffers.iter().enumerate() {
+                let filter_buf = &mut buf[2 + 4 * i..6 + 4 * i];
+                match self.buffer_filter(buffer) {
+                    Some(filter) => encode_filter(filter, filter_buf, &mut mask),
+                    None => filter_buf.copy_from_slice(&[0; 4]),
+                }
+            }
+            BUF_LEN
+        });
+    }
+
+    /// The value of RXBnCTRL for a receive buffer
+    fn rx_ctrl(&self, buffer: usize) -> u8 {
+        let mode = if self.filters[0].get().is_some() {
+            0
+        } else {
+            RXM_ANY
+        };
+        if buffer == 0 {
+            mode | BUKT
+        } else {
+            mode
+        }
+    }
+
+    /// Start the next operation, if the controller is idle
+    fn run_next(&self) {
+        if self.state.get() != State::Idle {
+            return;
+        }
+        if self.interrupt_pending.take() {
+            self.transfer(State::ReadFlags, |buf| {
+                buf[0] = Instruction::Read as u8;
+                buf[1] = Register::CANINTF as u8;
+                4
+            });
+        } else if let Some(frame) = self.tx_frame.take() {
+            self.sending.set(true);
+            self.transfer(State::LoadFrame, |buf| {
+                buf[0] = Instruction::LoadTxBuffer0 as u8;
+                encode_id(frame.id, &mut buf[1..5]);
+                buf[5] = frame.len as u8 | if frame.remote { RTR } else { 0 };
+                buf[6..6 + frame.len].copy_from_slice(&frame.data[..frame.len]);
+                6 + frame.len
+            });
+        }
+    }
+
+    /// Go back to reading the interrupt flags, until there are none left
+    fn handle_next_interrupt(&self) {
+        self.state.set(State::Idle);
+        self.interrupt_pending.set(true);
+        self.run_next();
+    }
+
+    fn handle_flags(&self, flags: u8, eflg: u8) {
+        if flags & RX0IF != 0 || flags & RX1IF != 0 {
+            // Reading a receive buffer with this instruction clears its flag
+            let instruction = if flags & RX0IF != 0 {
+                Instruction::ReadRxBuffer0
+            } else {
+                Instruction::ReadRxBuffer1
+            };
+            self.transfer(State::ReadFrame, |buf| {
+                buf[0] = instruction as u8;
+                BUF_LEN
+            });
+        } else if flags & TX0IF != 0 {
+            self.clear_bits(State::ClearTxFlag, Register::CANINTF, TX0IF);
+        } else if flags & ERRIF != 0 {
+            self.clear_bits(
+                State::ClearOverflow { eflg: eflg },
+                Register::EFLG,
+                RX0OVR | RX1OVR,
+            );
+        } else {
+            self.state.set(State::Idle);
+            self.run_next();
+        }
+    }
+
+    fn handle_frame(&self, buf: &[u8]) {
+        let id = decode_id(&buf[1..5]);
+        let len = (buf[5] & 0xf) as usize;
+        let mut frame = Frame {
+            id: id,
+            remote: match id {
+                Id::Standard(_) => buf[2] & SRR != 0,
+                Id::Extended(_) => buf[5] & RTR != 0,
+            },
+            len: if len > MAX_DATA_LEN { MAX_DATA_LEN } else { len },
+            data: [0; MAX_DATA_LEN],
+        };
+        frame.data.copy_from_slice(&buf[6..6 + MAX_DATA_LEN]);
+        self.client.map(|client| client.frame_received(&frame));
+    }
+
+    fn handle_error_flags(&self, eflg: u8) {
+        let error_state = if eflg & TXBO != 0 {
+            ErrorState::BusOff
+        } else if eflg & (TXEP | RXEP) != 0 {
+            ErrorState::Passive
+        } else {
+            ErrorState::Active
+        };
+        if error_state != self.error_state.get() {
+            self.error_state.set(error_state);
+            self.client
+                .map(|client| client.error_state_changed(error_state));
+        }
+
+        if error_state == ErrorState::BusOff && self.sending.get() {
+            // The controller would send the frame once it is back on the
+            // bus, but report it as failed instead
+            self.clear_bits(State::AbortSend, Register::TXB0CTRL, TXREQ);
+        } else {
+            self.handle_next_interrupt();
+        }
+    }
+
+    fn frame_sent(&self, result: ReturnCode) {
+        self.sending.set(false);
+        self.client.map(|client| client.frame_sent(result));
+    }
+}
+
+impl<S: spi::SpiMasterDevice> can::Can for MCP2515<'a, S> {
+    fn set_client(&self, client: &'static can::Client) {
+        self.client.set(client);
+    }
+
+    fn set_bitrate(&self, bitrate: u32) -> ReturnCode {
+        if self.state.get() != State::Disabled {
+            return ReturnCode::EBUSY;
+        }
+        // The time quantum is 2 * (BRP + 1) oscillator periods
+        let quanta_frequency = bitrate * TIME_QUANTA * 2;
+        if bitrate == 0 || self.oscillator_frequency % quanta_frequency != 0 {
+            return ReturnCode::EINVAL;
+        }
+        let prescaler = self.oscillator_frequency / quanta_frequency;
+        if prescaler == 0 || prescaler > 64 {
+            return ReturnCode::EINVAL;
+        }
+        self.baud_rate_prescaler.set(prescaler as u8 - 1);
+        ReturnCode::SUCCESS
+    }
+
+    fn set_filters(&self, filters: &[Filter]) -> ReturnCode {
+        if self.state.get() != State::Disabled {
+            return ReturnCode::EBUSY;
+        }
+        if filters.len() > self.filters.len() {
+            return ReturnCode::ESIZE;
+        }
+        for (i, filter) in self.filters.iter().enumerate() {
+            filter.set(filters.get(i).cloned());
+        }
+        ReturnCode::SUCCESS
+    }
+
+    fn enable(&self) -> ReturnCode {
+        if self.state.get() != State::Disabled {
+            return ReturnCode::EALREADY;
+        }
+        self.spi.configure(
+            spi::ClockPolarity::IdleLow,
+            spi::ClockPhase::SampleLeading,
+            SPI_SPEED,
+        );
+        self.int_pin.make_input();
+        self.int_pin
+            .enable_interrupt(0, gpio::InterruptMode::FallingEdge);
+
+        self.retries.set(RESET_RETRIES);
+        self.error_state.set(ErrorState::Active);
+        // The controller is in configuration mode after a reset
+        self.transfer(State::Reset, |buf| {
+            buf[0] = Instruction::Reset as u8;
+            1
+        })
+    }
+
+    fn disable(&self) -> ReturnCode {
+        match self.state.get() {
+            State::Disabled => ReturnCode::EALREADY,
+            State::Idle => {
+                self.int_pin.disable_interrupt();
+                self.tx_frame.set(None);
+                self.sending.set(false);
+                self.interrupt_pending.set(false);
+                self.write_register(
+                    State::WriteConfigurationMode,
+                    Register::CANCTRL,
+                    MODE_CONFIGURATION,
+                )
+            }
+            _ => ReturnCode::EBUSY,
+        }
+    }
+
+    fn send(&self, frame: &Frame) -> ReturnCode {
+        match self.state.get() {
+            State::Disabled
+            | State::Reset
+            | State::WaitConfigurationMode
+            | State::WriteConfigurationMode => return ReturnCode::EOFF,
+            _ => {}
+        }
+        if frame.len > MAX_DATA_LEN {
+            return ReturnCode::ESIZE;
+        }
+        if self.sending.get() || self.tx_frame.get().is_some() {
+            return ReturnCode::EBUSY;
+        }
+        self.tx_frame.set(Some(*frame));
+        self.run_next();
+        ReturnCode::SUCCESS
+    }
+
+    fn error_state(&self) -> ErrorState {
+        self.error_state.get()
+    }
+}
+
+impl<S: spi::SpiMasterDevice> spi::SpiMasterClient for MCP2515<'a, S> {
+    fn read_write_done(
+        &self,
+        write_buffer: &'static mut [u8],
+        read_buffer: Option<&'static mut [u8]>,
+        _len: usize,
+    ) {
+        self.tx_buffer.replace(write_buffer);
+        let read_buffer = match read_buffer {
+            Some(read_buffer) => read_buffer,
+            None => return,
+        };
+        let (byte2, byte3) = (read_buffer[2], read_buffer[3]);
+
+        match self.state.get() {
+            State::Disabled | State::Idle => {
+                self.rx_buffer.replace(read_buffer);
+            }
+
+            State::Reset => {
+                self.rx_buffer.replace(read_buffer);
+                self.read_canstat();
+            }
+            State::WaitConfigurationMode => {
+                self.rx_buffer.replace(read_buffer);
+                if byte2 & 0xe0 == MODE_CONFIGURATION {
+                    self.transfer(State::WriteTiming, |buf| {
+                        // CNF3, CNF2, CNF1 and CANINTE are consecutive
+                        buf[0] = Instruction::Write as u8;
+                        buf[1] = Register::CNF3 as u8;
+                        buf[2] = CNF3;
+                        buf[3] = CNF2;
+                        buf[4] = self.baud_rate_prescaler.get();
+                        buf[5] = RX0IF | RX1IF | TX0IF | ERRIF;
+                        6
+                    });
+                } else if self.retries.get() > 0 {
+                    self.retries.set(self.retries.get() - 1);
+                    self.read_canstat();
+                } else {
+                    // There is no controller answering
+                    self.int_pin.disable_interrupt();
+                    self.state.set(State::Disabled);
+                }
+            }
+            State::WriteTiming => {
+                self.rx_buffer.replace(read_buffer);
+                // Filters 0 and 1 belong to receive buffer 0, and filters 2
+                // to 5 to receive buffer 1
+                self.write_filters(State::WriteFilters0, Register::RXF0SIDH, [0, 0, 1]);
+            }
+            State::WriteFilters0 => {
+                self.rx_buffer.replace(read_buffer);
+                self.write_filters(State::WriteFilters1, Register::RXF3SIDH, [1, 1, 1]);
+            }
+            State::WriteFilters1 => {
+                self.rx_buffer.replace(read_buffer);
+                self.transfer(State::WriteMasks, |buf| {
+                    buf[0] = Instruction::Write as u8;
+                    buf[1] = Register::RXM0SIDH as u8;
+                    let mut filter = [0; 4];
+                    for buffer in 0..2 {
+                        let mask_buf = &mut buf[2 + 4 * buffer..6 + 4 * buffer];
+                        match self.buffer_filter(buffer) {
+                            Some(f) => encode_filter(f, &mut filter, mask_buf),
+                            None => mask_buf.copy_from_slice(&[0; 4]),
+                        }
+                    }
+                    10
+                });
+            }
+            State::WriteMasks => {
+                self.rx_buffer.replace(read_buffer);
+                self.write_register(State::WriteRxCtrl0, Register::RXB0CTRL, self.rx_ctrl(0));
+            }
+            State::WriteRxCtrl0 => {
+                self.rx_buffer.replace(read_buffer);
+                self.write_register(State::WriteRxCtrl1, Register::RXB1CTRL, self.rx_ctrl(1));
+            }
+            State::WriteRxCtrl1 => {
+                self.rx_buffer.replace(read_buffer);
+                self.write_register(State::WriteNormalMode, Register::CANCTRL, MODE_NORMAL);
+            }
+            State::WriteNormalMode => {
+                self.rx_buffer.replace(read_buffer);
+                // Check for interrupts that happened while enabling
+                self.handle_next_interrupt();
+            }
+
+            State::ReadFlags => {
+                self.rx_buffer.replace(read_buffer);
+                self.handle_flags(byte2, byte3);
+            }
+            State::ReadFrame => {
+                self.state.set(State::Idle);
+                self.interrupt_pending.set(true);
+                self.handle_frame(read_buffer);
+                self.rx_buffer.replace(read_buffer);
+                self.run_next();
+            }
+            State::ClearTxFlag => {
+                self.rx_buffer.replace(read_buffer);
+                self.state.set(State::Idle);
+                self.interrupt_pending.set(true);
+                self.frame_sent(ReturnCode::SUCCESS);
+                self.run_next();
+            }
+            State::ClearOverflow { eflg } => {
+                self.rx_buffer.replace(read_buffer);
+                self.clear_bits(State::ClearErrorFlag { eflg: eflg }, Register::CANINTF, ERRIF);
+            }
+            State::ClearErrorFlag { eflg } => {
+                self.rx_buffer.replace(read_buffer);
+                self.handle_error_flags(eflg);
+            }
+            State::AbortSend => {
+                self.rx_buffer.replace(read_buffer);
+                self.state.set(State::Idle);
+                self.interrupt_pending.set(true);
+                self.frame_sent(ReturnCode::FAIL);
+                self.run_next();
+            }
+
+            State::LoadFrame => {
+                self.rx_buffer.replace(read_buffer);
+                self.transfer(State::RequestSend, |buf| {
+                    buf[0] = Instruction::RequestToSend0 as u8;
+                    1
+                });
+            }
+            State::RequestSend => {
+                self.rx_buffer.replace(read_buffer);
+                self.state.set(State::Idle);
+                self.run_next();
+            }
+
+            State::WriteConfigurationMode => {
+                self.rx_buffer.replace(read_buffer);
+                self.state.set(State::Disabled);
+            }
+        }
+    }
+}
+
+impl<S: spi::SpiMasterDevice> gpio::Client for MCP2515<'a, S> {
+    fn fired(&self, _: usize) {
+        self.interrupt_pending.set(true);
+        self.run_next();
+    }
+}
@@ -0,0 +1,90 @@
+//! Driver for the Microchip MCP4725 I2C DAC.
+//!
+//! <http://www.microchip.com/wwwproducts/en/MCP4725>
+//!
+//! > The MCP4725 is a low-power, high accuracy, single channel, 12-bit buffered
+//! > voltage output Digital-to-Analog Convertor (DAC) with non-volatile memory
+//! > (EEPROM). Its on-board precision output amplifier allows it to achieve
+//! > rail-to-rail analog output swing.
+//!
+//! Values are sent with the fast write command, which updates the output
+//! without touching the EEPROM. A transfer takes about 60 µs at 400 kHz, and
+//! `set_value` returns EBUSY until the previous one finished, so with
+//! `DacWaveform` this DAC can play waveforms of a few kHz.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let mcp4725_i2c = static_init!(
+//!     capsules::virtual_i2c::I2CDevice,
+//!     capsules::virtual_i2c::I2CDevice::new(i2c_bus, 0x60));
+//! let mcp4725 = static_init!(
+//!     capsules::mcp4725::MCP4725<'static>,
+//!     capsules::mcp4725::MCP4725::new(mcp4725_i2c, &mut capsules::mcp4725::BUFFER));
+//! mcp4725_i2c.set_client(mcp4725);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::TakeCell;
+use kernel::hil::dac;
+use kernel::hil::i2c;
+use kernel::ReturnCode;
+
+pub static mut BUFFER: [u8; 2] = [0; 2];
+
+const DAC_BITS: usize = 12;
+
+pub struct MCP4725<'a> {
+    i2c: &'a i2c::I2CDevice,
+    buffer: TakeCell<'static, [u8]>,
+    enabled: Cell<bool>,
+}
+
+impl MCP4725<'a> {
+    pub fn new(i2c: &'a i2c::I2CDevice, buffer: &'static mut [u8]) -> MCP4725<'a> {
+        MCP4725 {
+            i2c: i2c,
+            buffer: TakeCell::new(buffer),
+            enabled: Cell::new(false),
+        }
+    }
+}
+
+impl dac::DacChannel for MCP4725<'a> {
+    fn initialize(&self) -> ReturnCode {
+        // The DAC needs no configuration, and the fast write command also
+        // takes it out of power-down mode.
+        self.enabled.set(true);
+        ReturnCode::SUCCESS
+    }
+
+    fn set_value(&self, value: usize) -> ReturnCode {
+        if !self.enabled.get() {
+            return ReturnCode::EOFF;
+        }
+        if value >= 1 << DAC_BITS {
+            return ReturnCode::EINVAL;
+        }
+        self.buffer.take().map_or(ReturnCode::EBUSY, |buffer| {
+            // Fast write: the command and power-down bits are 0, followed
+            // by the 12-bit value
+            buffer[0] = (value >> 8) as u8 & 0x0F;
+            buffer[1] = value as u8;
+            self.i2c.enable();
+            self.i2c.write(buffer, 2);
+            ReturnCode::SUCCESS
+        })
+    }
+
+    fn get_resolution_bits(&self) -> usize {
+        DAC_BITS
+    }
+}
+
+impl i2c::I2CClient for MCP4725<'a> {
+    fn command_complete(&self, buffer: &'static mut [u8], _error: i2c::Error) {
+        self.i2c.disable();
+        self.buffer.replace(buffer);
+    }
+}
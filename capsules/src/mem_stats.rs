@@ -0,0 +1,126 @@
+//! Reports the memory layout and usage of processes to userspace.
+//!
+//! Every process can read the statistics of its own memory. The board can
+//! name a manager process, which can also read the statistics of every other
+//! process, for example to report them or to find a process that is running
+//! out of memory.
+//!
+//! Since the driver reads the state of all processes, it can only be created
+//! with the `ProcessManagementCapability`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let mem_stats = static_init!(
+//!     capsules::mem_stats::MemStats<'static>,
+//!     capsules::mem_stats::MemStats::new(introspection, Some("manager"), &process_mgmt_cap)
+//! );
+//! ```
+
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::introspection::Introspection;
+use kernel::{AppId, Driver, ReturnCode};
+
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = 0x10003;
+
+pub struct MemStats<'a> {
+    introspection: &'a Introspection,
+    /// Package name of the process that may read the statistics of all
+    /// processes.
+    manager: Option<&'static str>,
+    capability: &'a ProcessManagementCapability,
+}
+
+impl MemStats<'a> {
+    pub fn new(
+        introspection: &'a Introspection,
+        manager: Option<&'static str>,
+        capability: &'a ProcessManagementCapability,
+    ) -> MemStats<'a> {
+        MemStats {
+            introspection: introspection,
+            manager: manager,
+            capability: capability,
+        }
+    }
+
+    fn is_manager(&self, appid: AppId) -> bool {
+        self.manager.map_or(false, |manager| {
+            self.introspection.process_name(appid, self.capability) == manager
+        })
+    }
+
+    /// Returns one statistic of the memory of `app`.
+    fn statistic(&self, app: AppId, statistic: usize) -> ReturnCode {
+        let (flash_start, flash_end) = self.introspection.app_flash_region(app, self.capability);
+        let (ram_start, ram_end) = self.introspection.app_ram_region(app, self.capability);
+        let (app_break, kernel_break) = self
+            .introspection
+            .app_memory_breaks(app, self.capability);
+        let value = match statistic {
+            0 => flash_start,
+            1 => flash_end,
+            2 => ram_start,
+            3 => ram_end,
+            4 => app_break,
+            5 => ram_end - kernel_break,
+            6 => kernel_break - app_break,
+            _ => return ReturnCode::EINVAL,
+        };
+        ReturnCode::SuccessWithValue { value: value }
+    }
+}
+
+impl Driver for MemStats<'a> {
+    /// Read memory statistics.
+    ///
+    /// The statistic to read is one of:
+    ///
+    /// - `0`: Start of the flash region.
+    /// - `1`: End of the flash region.
+    /// - `2`: Start of the RAM region.
+    /// - `3`: End of the RAM region.
+    /// - `4`: Current heap break.
+    /// - `5`: Bytes used by grants.
+    /// - `6`: Bytes left between the heap break and the grants.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Get statistic `data` of the calling process.
+    /// - `2`: Get statistic `data` of the process in slot `data2`. Only the
+    ///   manager process may use this, other processes get `EPERM`. Returns
+    ///   `EINVAL` if there is no process in the slot.
+    /// - `3`: Get the number of process slots. Only the manager process may
+    ///   use this.
+    fn command(&self, command_num: usize, data: usize, data2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            1 => self.statistic(appid, data),
+
+            2 => {
+                if !self.is_manager(appid) {
+                    return ReturnCode::EPERM;
+                }
+                match self.introspection.app_id(data2, self.capability) {
+                    Some(app) => self.statistic(app, data),
+                    None => ReturnCode::EINVAL,
+                }
+            }
+
+            3 => {
+                if !self.is_manager(appid) {
+                    return ReturnCode::EPERM;
+                }
+                ReturnCode::SuccessWithValue {
+                    value: self.introspection.number_process_slots(self.capability),
+                }
+            }
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
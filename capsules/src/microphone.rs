@@ -0,0 +1,246 @@
+//! Microphone system call interface
+//!
+//! This capsule streams 16-bit PCM samples from a `hil::pdm::Pdm`
+//! microphone controller to a process, for example for keyword detection.
+//! The samples arrive in frames of `FRAME_LEN` samples. The process shares a
+//! buffer that holds two frames, and the capsule copies the frames into its
+//! two halves in turn, calling back the process after each frame. The process
+//! handles one half while the other is being written. One process samples at
+//! a time.
+//!
+//! ## Instantiation
+//!
+//! ```rust
+//! let microphone = static_init!(
+//!     capsules::microphone::Microphone<'static>,
+//!     capsules::microphone::Microphone::new(
+//!         &nrf52::pdm::PDM,
+//!         &mut capsules::microphone::BUFFER1,
+//!         &mut capsules::microphone::BUFFER2,
+//!         board_kernel.create_grant(&grant_cap)
+//!     )
+//! );
+//! hil::pdm::Pdm::set_client(&nrf52::pdm::PDM, microphone);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::pdm;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+/// Syscall number
+pub const DRIVER_NUM: usize = 0x0000A;
+
+/// The number of samples in each frame, 16 ms at 16 kHz
+pub const FRAME_LEN: usize = 256;
+
+pub static mut BUFFER1: [i16; FRAME_LEN] = [0; FRAME_LEN];
+pub static mut BUFFER2: [i16; FRAME_LEN] = [0; FRAME_LEN];
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    frames: Option<AppSlice<Shared, u8>>,
+}
+
+pub struct Microphone<'a> {
+    pdm: &'a pdm::Pdm,
+    apps: Grant<App>,
+    buffer1: TakeCell<'static, [i16]>,
+    buffer2: TakeCell<'static, [i16]>,
+    // The app that is sampling
+    owner: OptionalCell<AppId>,
+    // The number of frames copied to the owner
+    frame: Cell<usize>,
+}
+
+impl Microphone<'a> {
+    pub fn new(
+        pdm: &'a pdm::Pdm,
+        buffer1: &'static mut [i16],
+        buffer2: &'static mut [i16],
+        apps: Grant<App>,
+    ) -> Microphone<'a> {
+        Microphone {
+            pdm: pdm,
+            apps: apps,
+            buffer1: TakeCell::new(buffer1),
+            buffer2: TakeCell::new(buffer2),
+            owner: OptionalCell::empty(),
+            frame: Cell::new(0),
+        }
+    }
+
+    fn start(&self, appid: AppId) -> ReturnCode {
+        if self.owner.is_some() {
+            return ReturnCode::EBUSY;
+        }
+        let valid = self
+            .apps
+            .enter(appid, |app, _| {
+                app.frames
+                    .as_ref()
+                    .map_or(false, |frames| frames.len() >= 4 * FRAME_LEN)
+            }).unwrap_or(false);
+        if !valid {
+            return ReturnCode::EINVAL;
+        }
+        // The buffers are missing while the controller stops
+        let (buffer1, buffer2) = match (self.buffer1.take(), self.buffer2.take()) {
+            (Some(buffer1), Some(buffer2)) => (buffer1, buffer2),
+            (buffer1, buffer2) => {
+                self.buffer1.put(buffer1);
+                self.buffer2.put(buffer2);
+                return ReturnCode::EBUSY;
+            }
+        };
+
+        let (result, buffer1, buffer2) = self.pdm.start(buffer1, buffer2);
+        if result == ReturnCode::SUCCESS {
+            self.owner.set(appid);
+            self.frame.set(0);
+        } else {
+            self.buffer1.put(buffer1);
+            self.buffer2.put(buffer2);
+        }
+        result
+    }
+
+    fn put_buffer(&self, buffer: &'static mut [i16]) {
+        if self.buffer1.is_none() {
+            self.buffer1.replace(buffer);
+        } else {
+            self.buffer2.replace(buffer);
+        }
+    }
+}
+
+impl Driver for Microphone<'a> {
+    /// Share the buffer that frames are written into.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Room for two frames of little-endian 16-bit samples.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.frames = slice;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Subscribe to frames.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: A frame was written. The callback is passed the half of the
+    ///        buffer it was written into, 0 or 1, and the number of the
+    ///        frame, counting from 0, so that the process can tell whether it
+    ///        missed frames.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        appid: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Control sampling.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Set the sample rate to `data1` samples per second. Returns the
+    ///        sample rate the controller actually produces.
+    /// - `2`: Start sampling. Returns EBUSY if a process is sampling.
+    /// - `3`: Stop sampling.
+    /// - `4`: Get the number of samples in a frame.
+    fn command(&self, command_num: usize, data1: usize, _: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            1 => {
+                let result = self.pdm.set_sample_rate(data1 as u32);
+                if result != ReturnCode::SUCCESS {
+                    return result;
+                }
+                ReturnCode::SuccessWithValue {
+                    value: self.pdm.sample_rate() as usize,
+                }
+            }
+
+            2 => self.start(appid),
+
+            3 => {
+                if !self.owner.map_or(false, |owner| owner.idx() == appid.idx()) {
+                    return ReturnCode::EOFF;
+                }
+                // No more frames are copied to the process, even before the
+                // controller has stopped
+                self.owner.clear();
+                self.pdm.stop()
+            }
+
+            4 => ReturnCode::SuccessWithValue { value: FRAME_LEN },
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+impl pdm::Client for Microphone<'a> {
+    fn frame_done(&self, buffer: &'static mut [i16]) {
+        let frame = self.frame.get();
+        let half = frame % 2;
+        let copied = self.owner.map_or(false, |appid| {
+            self.apps
+                .enter(*appid, |app, _| {
+                    app.frames.as_mut().map(|frames| {
+                        // The process may have shared a shorter buffer since
+                        // it started sampling
+                        let start = half * 2 * FRAME_LEN;
+                        frames.as_mut().get_mut(start..start + 2 * FRAME_LEN).map(|bytes| {
+                            for (bytes, sample) in bytes.chunks_mut(2).zip(buffer.iter()) {
+                                bytes[0] = *sample as u8;
+                                bytes[1] = (*sample >> 8) as u8;
+                            }
+                        });
+                    });
+                    app.callback.map(|mut cb| cb.schedule(half, frame, 0));
+                }).is_ok()
+        });
+
+        if copied {
+            self.frame.set(frame.wrapping_add(1));
+        } else if self.owner.is_some() {
+            // The process is gone
+            self.owner.clear();
+            self.pdm.stop();
+        }
+        let (_, buffer) = self.pdm.provide_buffer(buffer);
+        buffer.map(|buffer| self.put_buffer(buffer));
+    }
+
+    fn stopped(&self, buffer1: Option<&'static mut [i16]>, buffer2: Option<&'static mut [i16]>) {
+        buffer1.map(|buffer| self.put_buffer(buffer));
+        buffer2.map(|buffer| self.put_buffer(buffer));
+    }
+}
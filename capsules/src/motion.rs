@@ -0,0 +1,99 @@
+//! Provides userspace with wake-on-motion events.
+//!
+//! Apps that only need to run when the device moves, such as asset trackers,
+//! enable motion detection and sleep until the callback. The sensor detects
+//! motion by itself in a low-power mode, so the rest of the system sleeps in
+//! the meantime. Motion detection is shared: any app can enable it, which
+//! sets the threshold for all, or disable it, and all subscribed apps get the
+//! events.
+//!
+//! Usage
+//! -----
+//!
+//! You need a device that provides the `hil::sensors::MotionDetector` trait.
+//!
+//! ```rust
+//! let motion = static_init!(
+//!     capsules::motion::Motion<'static>,
+//!     capsules::motion::Motion::new(
+//!         lsm6ds,
+//!         board_kernel.create_grant(&memory_allocation_capability)));
+//! hil::sensors::MotionDetector::set_client(lsm6ds, motion);
+//! ```
+
+use kernel::hil::sensors;
+use kernel::{AppId, Callback, Driver, Grant, ReturnCode};
+
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = 0x60007;
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+}
+
+pub struct Motion<'a> {
+    detector: &'a sensors::MotionDetector,
+    apps: Grant<App>,
+}
+
+impl Motion<'a> {
+    pub fn new(detector: &'a sensors::MotionDetector, grant: Grant<App>) -> Motion<'a> {
+        Motion {
+            detector: detector,
+            apps: grant,
+        }
+    }
+}
+
+impl sensors::MotionDetectorClient for Motion<'a> {
+    fn motion_detected(&self) {
+        self.apps.each(|app| {
+            app.callback.map(|mut cb| cb.schedule(0, 0, 0));
+        });
+    }
+}
+
+impl Driver for Motion<'a> {
+    /// Subscribe to motion events.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Called when the sensor detected motion.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Control motion detection.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Enable motion detection, for changes of the acceleration by
+    ///        more than `data` milli-g.
+    /// - `2`: Disable motion detection.
+    fn command(&self, command_num: usize, data: usize, _: usize, _: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            1 => self.detector.enable_motion_detection(data as u32),
+
+            2 => self.detector.disable_motion_detection(),
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
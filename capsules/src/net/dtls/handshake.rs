@@ -0,0 +1,263 @@
+//! Implements encoding and decoding of the DTLS 1.2 handshake messages used
+//! by a client with a pre-shared key, as described in RFC 6347, Section 4.2,
+//! and RFC 4279.
+//!
+//! Every handshake message starts with a 12-byte header, which extends the
+//! TLS handshake header with a message sequence number and the position of
+//! the fragment within the message. The messages encoded here are always
+//! sent in a single fragment.
+//!
+//! With a pre-shared key and no server certificate, the client side of the
+//! handshake is:
+//!
+//! ```text
+//! Client                                   Server
+//! ClientHello           -------->
+//!                       <--------   HelloVerifyRequest (with cookie)
+//! ClientHello (with cookie) ---->
+//!                                          ServerHello
+//!                       <--------      ServerHelloDone
+//! ClientKeyExchange
+//! [ChangeCipherSpec]
+//! Finished              -------->
+//!                                   [ChangeCipherSpec]
+//!                       <--------             Finished
+//! ```
+//!
+//! The `verify_data` of the `Finished` messages is computed with the TLS
+//! pseudorandom function, in the `prf` module.
+
+use net::dtls::prf::VERIFY_DATA_LEN;
+use net::dtls::record::DTLS_1_2;
+use net::stream::SResult;
+use net::stream::{decode_bytes, decode_u16, decode_u8, encode_bytes, encode_u16, encode_u8};
+
+/// Length of a handshake message header.
+pub const HANDSHAKE_HDR_LEN: usize = 12;
+
+/// Length of the random values exchanged in the hello messages.
+pub const RANDOM_LEN: usize = 32;
+
+/// The largest cookie a server may send in a `HelloVerifyRequest`.
+pub const MAX_COOKIE_LEN: usize = 255;
+
+/// The cipher suite offered by the client (RFC 6655).
+pub const TLS_PSK_WITH_AES_128_CCM_8: u16 = 0xc0a8;
+
+/// The only compression method offered by the client.
+const COMPRESSION_NULL: u8 = 0;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum HandshakeType {
+    HelloRequest = 0,
+    ClientHello = 1,
+    ServerHello = 2,
+    HelloVerifyRequest = 3,
+    ServerKeyExchange = 12,
+    ServerHelloDone = 14,
+    ClientKeyExchange = 16,
+    Finished = 20,
+}
+
+impl HandshakeType {
+    pub fn from_u8(msg_type: u8) -> Option<HandshakeType> {
+        match msg_type {
+            0 => Some(HandshakeType::HelloRequest),
+            1 => Some(HandshakeType::ClientHello),
+            2 => Some(HandshakeType::ServerHello),
+            3 => Some(HandshakeType::HelloVerifyRequest),
+            12 => Some(HandshakeType::ServerKeyExchange),
+            14 => Some(HandshakeType::ServerHelloDone),
+            16 => Some(HandshakeType::ClientKeyExchange),
+            20 => Some(HandshakeType::Finished),
+            _ => None,
+        }
+    }
+}
+
+fn encode_u24(buf: &mut [u8], b: u32) -> SResult {
+    stream_len_cond!(buf, 3);
+    buf[0] = (b >> 16) as u8;
+    buf[1] = (b >> 8) as u8;
+    buf[2] = b as u8;
+    stream_done!(3);
+}
+
+fn decode_u24(buf: &[u8]) -> SResult<u32> {
+    stream_len_cond!(buf, 3);
+    stream_done!(3, (buf[0] as u32) << 16 | (buf[1] as u32) << 8 | (buf[2] as u32));
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct HandshakeHeader {
+    pub msg_type: HandshakeType,
+    /// Length of the whole message body. Only the lower 24 bits are used, as
+    /// for the fragment fields.
+    pub len: u32,
+    pub message_seq: u16,
+    pub fragment_offset: u32,
+    pub fragment_len: u32,
+}
+
+impl HandshakeHeader {
+    /// Creates the header of a message sent in a single fragment.
+    pub fn new(msg_type: HandshakeType, message_seq: u16, len: u32) -> HandshakeHeader {
+        HandshakeHeader {
+            msg_type: msg_type,
+            len: len,
+            message_seq: message_seq,
+            fragment_offset: 0,
+            fragment_len: len,
+        }
+    }
+
+    /// Whether the fragment following the header is only part of the message.
+    pub fn is_fragmented(&self) -> bool {
+        self.fragment_offset != 0 || self.fragment_len != self.len
+    }
+
+    pub fn encode(&self, buf: &mut [u8]) -> SResult {
+        stream_len_cond!(buf, HANDSHAKE_HDR_LEN);
+        let mut off = enc_consume!(buf; encode_u8, self.msg_type as u8);
+        off = enc_consume!(buf, off; encode_u24, self.len);
+        off = enc_consume!(buf, off; encode_u16, self.message_seq);
+        off = enc_consume!(buf, off; encode_u24, self.fragment_offset);
+        off = enc_consume!(buf, off; encode_u24, self.fragment_len);
+        stream_done!(off);
+    }
+
+    /// Reads a header from `buf`. Returns `SResult::Error` if the message
+    /// type is unknown, the fragment extends past the message, or `buf` is
+    /// shorter than the fragment.
+    pub fn decode(buf: &[u8]) -> SResult<HandshakeHeader> {
+        let (off, msg_type) = dec_try!(buf; decode_u8);
+        let msg_type = stream_from_option!(HandshakeType::from_u8(msg_type));
+        let (off, len) = dec_try!(buf, off; decode_u24);
+        let (off, message_seq) = dec_try!(buf, off; decode_u16);
+        let (off, fragment_offset) = dec_try!(buf, off; decode_u24);
+        let (off, fragment_len) = dec_try!(buf, off; decode_u24);
+        stream_cond!(fragment_offset + fragment_len <= len);
+        stream_cond!(off + (fragment_len as usize) <= buf.len());
+        stream_done!(
+            off,
+            HandshakeHeader {
+                msg_type: msg_type,
+                len: len,
+                message_seq: message_seq,
+                fragment_offset: fragment_offset,
+                fragment_len: fragment_len,
+            }
+        );
+    }
+}
+
+/// Writes a `ClientHello` offering `TLS_PSK_WITH_AES_128_CCM_8` into `buf`,
+/// including its handshake header. `cookie` is empty for the first
+/// `ClientHello`, and is the cookie of the `HelloVerifyRequest` for the
+/// second one.
+pub fn encode_client_hello(
+    buf: &mut [u8],
+    message_seq: u16,
+    random: &[u8; RANDOM_LEN],
+    cookie: &[u8],
+) -> SResult {
+    stream_cond!(cookie.len() <= MAX_COOKIE_LEN);
+    // version, random, session ID, cookie, cipher suites and compression
+    // methods, each variable-length field preceded by its length
+    let body_len = 2 + RANDOM_LEN + 1 + 1 + cookie.len() + 2 + 2 + 1 + 1;
+    stream_len_cond!(buf, HANDSHAKE_HDR_LEN + body_len);
+
+    let hdr = HandshakeHeader::new(HandshakeType::ClientHello, message_seq, body_len as u32);
+    let mut off = enc_consume!(buf; hdr; encode);
+    off = enc_consume!(buf, off; encode_u16, DTLS_1_2);
+    off = enc_consume!(buf, off; encode_bytes, random);
+    // An empty session ID, as sessions are not resumed
+    off = enc_consume!(buf, off; encode_u8, 0);
+    off = enc_consume!(buf, off; encode_u8, cookie.len() as u8);
+    off = enc_consume!(buf, off; encode_bytes, cookie);
+    off = enc_consume!(buf, off; encode_u16, 2);
+    off = enc_consume!(buf, off; encode_u16, TLS_PSK_WITH_AES_128_CCM_8);
+    off = enc_consume!(buf, off; encode_u8, 1);
+    off = enc_consume!(buf, off; encode_u8, COMPRESSION_NULL);
+    stream_done!(off);
+}
+
+/// Reads the body of a `HelloVerifyRequest`, and returns the cookie in it.
+pub fn decode_hello_verify_request(buf: &[u8]) -> SResult<&[u8]> {
+    let (off, _version) = dec_try!(buf; decode_u16);
+    let (off, cookie_len) = dec_try!(buf, off; decode_u8);
+    let end = off + cookie_len as usize;
+    stream_len_cond!(buf, end);
+    stream_done!(end, &buf[off..end]);
+}
+
+/// The fields of a `ServerHello` needed to continue the handshake.
+#[derive(Copy, Clone)]
+pub struct ServerHello {
+    pub version: u16,
+    pub random: [u8; RANDOM_LEN],
+    pub cipher_suite: u16,
+}
+
+/// Reads the body of a `ServerHello`. Returns `SResult::Error` if the server
+/// chose a version other than DTLS 1.2, a cipher suite that was not offered,
+/// or compression. The session ID and any extensions are skipped.
+pub fn decode_server_hello(buf: &[u8]) -> SResult<ServerHello> {
+    let mut hello = ServerHello {
+        version: 0,
+        random: [0; RANDOM_LEN],
+        cipher_suite: 0,
+    };
+    let (off, version) = dec_try!(buf; decode_u16);
+    stream_cond!(version == DTLS_1_2);
+    hello.version = version;
+    let off = dec_consume!(buf, off; decode_bytes, &mut hello.random);
+    let (off, session_id_len) = dec_try!(buf, off; decode_u8);
+    let off = off + session_id_len as usize;
+    stream_len_cond!(buf, off);
+    let (off, cipher_suite) = dec_try!(buf, off; decode_u16);
+    stream_cond!(cipher_suite == TLS_PSK_WITH_AES_128_CCM_8);
+    hello.cipher_suite = cipher_suite;
+    let (off, compression) = dec_try!(buf, off; decode_u8);
+    stream_cond!(compression == COMPRESSION_NULL);
+    stream_done!(off, hello);
+}
+
+/// Writes a `ClientKeyExchange` carrying the PSK identity into `buf`,
+/// including its handshake header (RFC 4279, Section 2).
+pub fn encode_client_key_exchange(
+    buf: &mut [u8],
+    message_seq: u16,
+    psk_identity: &[u8],
+) -> SResult {
+    stream_cond!(psk_identity.len() <= u16::max_value() as usize);
+    let body_len = 2 + psk_identity.len();
+    stream_len_cond!(buf, HANDSHAKE_HDR_LEN + body_len);
+
+    let hdr = HandshakeHeader::new(HandshakeType::ClientKeyExchange, message_seq, body_len as u32);
+    let mut off = enc_consume!(buf; hdr; encode);
+    off = enc_consume!(buf, off; encode_u16, psk_identity.len() as u16);
+    off = enc_consume!(buf, off; encode_bytes, psk_identity);
+    stream_done!(off);
+}
+
+/// Writes a `Finished` carrying `verify_data` into `buf`, including its
+/// handshake header.
+pub fn encode_finished(
+    buf: &mut [u8],
+    message_seq: u16,
+    verify_data: &[u8; VERIFY_DATA_LEN],
+) -> SResult {
+    stream_len_cond!(buf, HANDSHAKE_HDR_LEN + VERIFY_DATA_LEN);
+
+    let hdr = HandshakeHeader::new(HandshakeType::Finished, message_seq, VERIFY_DATA_LEN as u32);
+    let mut off = enc_consume!(buf; hdr; encode);
+    off = enc_consume!(buf, off; encode_bytes, verify_data);
+    stream_done!(off);
+}
+
+/// Reads the body of a `Finished`, and returns the `verify_data` in it.
+pub fn decode_finished(buf: &[u8]) -> SResult<&[u8]> {
+    stream_cond!(buf.len() == VERIFY_DATA_LEN);
+    stream_done!(VERIFY_DATA_LEN, &buf[..VERIFY_DATA_LEN]);
+}
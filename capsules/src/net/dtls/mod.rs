@@ -0,0 +1,23 @@
+//! Modules for Datagram Transport Layer Security (DTLS) 1.2, as described in
+//! RFC 6347.
+//!
+//! DTLS secures datagrams exchanged over UDP. Each datagram carries one or
+//! more records, and the handshake that establishes a session is itself
+//! carried in records of the handshake content type. The `record` module
+//! encodes and decodes record headers, and the `handshake` module encodes
+//! and decodes the handshake messages of a client using a pre-shared key
+//! (PSK) with the `TLS_PSK_WITH_AES_128_CCM_8` cipher suite (RFC 6655). The
+//! `prf` module derives the keys of a session and the contents of the
+//! `Finished` messages with the TLS pseudorandom function.
+//!
+//! The `session` module runs the handshake as a client, computing the
+//! pseudorandom function with a `hil::digest::Digest`, and then protects
+//! application data with an `AES128CCM`. TLS builds the 12-byte CCM nonce of
+//! each record from a 4-byte implicit IV and an 8-byte explicit nonce.
+//!
+//! There is no CoAP capsule to use a session yet.
+
+pub mod handshake;
+pub mod prf;
+pub mod record;
+pub mod session;
@@ -0,0 +1,136 @@
+//! Implements the TLS 1.2 pseudorandom function (PRF), as described in RFC
+//! 5246, Section 5, which DTLS 1.2 uses unchanged, and the secrets derived
+//! with it.
+//!
+//! With the cipher suites of TLS 1.2, the PRF is built on HMAC-SHA256:
+//!
+//! ```text
+//! PRF(secret, label, seed) = P_SHA256(secret, label + seed)
+//! P_SHA256(secret, seed) = HMAC(secret, A(1) + seed) +
+//!                          HMAC(secret, A(2) + seed) + ...
+//! A(0) = seed
+//! A(i) = HMAC(secret, A(i - 1))
+//! ```
+//!
+//! where `+` concatenates, and the output is cut to the length needed.
+//!
+//! `session::DTLSSession` computes the PRF one HMAC at a time with a
+//! `hil::digest::Digest`, so that it can use hardware HMAC and does not block
+//! the kernel, and uses the lengths and labels defined here. The functions
+//! here compute the same values at once with the software HMAC-SHA256 of the
+//! `crypto` module, for code that has no `Digest` to share.
+
+use crypto::hmac_sha256::{HmacSha256, DIGEST_LEN};
+use net::stream::SResult;
+use net::stream::{encode_bytes, encode_u16};
+
+/// Length of the master secret of a session.
+pub const MASTER_SECRET_LEN: usize = 48;
+
+/// Length of the `verify_data` of a `Finished` message.
+pub const VERIFY_DATA_LEN: usize = 12;
+
+/// Length of the key block of `TLS_PSK_WITH_AES_128_CCM_8`: a 16-byte key
+/// and a 4-byte implicit IV for each direction.
+pub const KEY_BLOCK_LEN: usize = 2 * 16 + 2 * 4;
+
+pub const LABEL_MASTER_SECRET: &[u8] = b"master secret";
+pub const LABEL_KEY_EXPANSION: &[u8] = b"key expansion";
+pub const LABEL_CLIENT_FINISHED: &[u8] = b"client finished";
+pub const LABEL_SERVER_FINISHED: &[u8] = b"server finished";
+
+/// Fills `output` with `PRF(secret, label, seed)`, where the seed is the
+/// concatenation of the parts in `seed`.
+pub fn prf(secret: &[u8], label: &[u8], seed: &[&[u8]], output: &mut [u8]) {
+    let key = HmacSha256::new(secret);
+
+    let mut a = [0; DIGEST_LEN];
+    let mut hmac = key.clone();
+    hmac.update(label);
+    for part in seed.iter() {
+        hmac.update(part);
+    }
+    hmac.finish(&mut a);
+
+    for chunk in output.chunks_mut(DIGEST_LEN) {
+        let mut block = [0; DIGEST_LEN];
+        let mut hmac = key.clone();
+        hmac.update(&a);
+        hmac.update(label);
+        for part in seed.iter() {
+            hmac.update(part);
+        }
+        hmac.finish(&mut block);
+        let len = chunk.len();
+        chunk.copy_from_slice(&block[..len]);
+
+        let mut hmac = key.clone();
+        hmac.update(&a);
+        hmac.finish(&mut a);
+    }
+}
+
+/// Writes the premaster secret for a pre-shared key `psk` into `buf`
+/// (RFC 4279, Section 2): the length of the key, as many zero bytes, and
+/// the length and bytes of the key.
+pub fn encode_psk_premaster_secret(buf: &mut [u8], psk: &[u8]) -> SResult {
+    stream_cond!(psk.len() <= u16::max_value() as usize);
+    let len = 2 + psk.len() + 2 + psk.len();
+    stream_len_cond!(buf, len);
+    let mut off = enc_consume!(buf; encode_u16, psk.len() as u16);
+    for byte in buf[off..off + psk.len()].iter_mut() {
+        *byte = 0;
+    }
+    off += psk.len();
+    off = enc_consume!(buf, off; encode_u16, psk.len() as u16);
+    off = enc_consume!(buf, off; encode_bytes, psk);
+    stream_done!(off);
+}
+
+/// Derives the master secret of a session from the premaster secret and
+/// the random values of the `ClientHello` and `ServerHello`.
+pub fn master_secret(
+    premaster_secret: &[u8],
+    client_random: &[u8],
+    server_random: &[u8],
+    master_secret: &mut [u8; MASTER_SECRET_LEN],
+) {
+    prf(
+        premaster_secret,
+        LABEL_MASTER_SECRET,
+        &[client_random, server_random],
+        master_secret,
+    );
+}
+
+/// Derives the keys of a session from its master secret. The key block
+/// holds the client write key, the server write key, the client write IV
+/// and the server write IV, in this order. Note that the random values are
+/// in the reverse order from `master_secret`.
+pub fn key_block(
+    master_secret: &[u8; MASTER_SECRET_LEN],
+    client_random: &[u8],
+    server_random: &[u8],
+    key_block: &mut [u8; KEY_BLOCK_LEN],
+) {
+    prf(
+        master_secret,
+        LABEL_KEY_EXPANSION,
+        &[server_random, client_random],
+        key_block,
+    );
+}
+
+/// Computes the `verify_data` of a `Finished` message. `label` is
+/// `LABEL_CLIENT_FINISHED` or `LABEL_SERVER_FINISHED`, and `handshake_hash`
+/// is the SHA-256 digest of the handshake messages exchanged so far,
+/// including their DTLS headers, but without the `HelloVerifyRequest` and
+/// the `ClientHello` it answered (RFC 6347, Section 4.2.6).
+pub fn verify_data(
+    master_secret: &[u8; MASTER_SECRET_LEN],
+    label: &[u8],
+    handshake_hash: &[u8],
+    verify_data: &mut [u8; VERIFY_DATA_LEN],
+) {
+    prf(master_secret, label, &[handshake_hash], verify_data);
+}
@@ -0,0 +1,150 @@
+//! Implements encoding and decoding of DTLS 1.2 record headers, as described
+//! in RFC 6347, Section 4.1.
+//!
+//! A record header is 13 bytes long:
+//!
+//! ```text
+//!  0                   1                   2                   3
+//!  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! |  Content Type |            Version            |     Epoch     |
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! |     Epoch     |                Sequence Number                |
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! |                Sequence Number                |    Length     |
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! |    Length     |
+//! +-+-+-+-+-+-+-+-+
+//! ```
+//!
+//! The fields are in network byte order. The epoch is incremented each time
+//! the cipher state changes, and the sequence number counts the records sent
+//! in an epoch.
+
+use net::stream::SResult;
+use net::stream::{decode_u16, decode_u8, encode_u16, encode_u8};
+
+/// Version field of a DTLS 1.2 record: the one's complement of 1.2.
+pub const DTLS_1_2: u16 = 0xfefd;
+
+/// Length of a record header.
+pub const RECORD_HDR_LEN: usize = 13;
+
+/// The largest sequence number, which is 48 bits long.
+pub const MAX_SEQ_NUM: u64 = (1 << 48) - 1;
+
+/// Type of the data carried in a record.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ContentType {
+    ChangeCipherSpec = 20,
+    Alert = 21,
+    Handshake = 22,
+    ApplicationData = 23,
+}
+
+impl ContentType {
+    pub fn from_u8(content_type: u8) -> Option<ContentType> {
+        match content_type {
+            20 => Some(ContentType::ChangeCipherSpec),
+            21 => Some(ContentType::Alert),
+            22 => Some(ContentType::Handshake),
+            23 => Some(ContentType::ApplicationData),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct RecordHeader {
+    pub content_type: ContentType,
+    pub version: u16,
+    pub epoch: u16,
+    /// Only the lower 48 bits are used.
+    pub seq_num: u64,
+    /// Length of the record fragment following the header.
+    pub len: u16,
+}
+
+impl RecordHeader {
+    pub fn new(content_type: ContentType, epoch: u16, seq_num: u64, len: u16) -> RecordHeader {
+        RecordHeader {
+            content_type: content_type,
+            version: DTLS_1_2,
+            epoch: epoch,
+            seq_num: seq_num & MAX_SEQ_NUM,
+            len: len,
+        }
+    }
+
+    /// Writes the header into `buf`.
+    pub fn encode(&self, buf: &mut [u8]) -> SResult {
+        stream_len_cond!(buf, RECORD_HDR_LEN);
+        let mut off = enc_consume!(buf; encode_u8, self.content_type as u8);
+        off = enc_consume!(buf, off; encode_u16, self.version);
+        off = enc_consume!(buf, off; encode_u16, self.epoch);
+        off = enc_consume!(buf, off; encode_u16, (self.seq_num >> 32) as u16);
+        off = enc_consume!(buf, off; encode_u16, (self.seq_num >> 16) as u16);
+        off = enc_consume!(buf, off; encode_u16, self.seq_num as u16);
+        off = enc_consume!(buf, off; encode_u16, self.len);
+        stream_done!(off);
+    }
+
+    /// Reads a header from `buf`. Returns `SResult::Error` if the content
+    /// type is unknown, the version is not DTLS 1.2, or `buf` is shorter than
+    /// the record length.
+    pub fn decode(buf: &[u8]) -> SResult<RecordHeader> {
+        let (off, content_type) = dec_try!(buf; decode_u8);
+        let content_type = stream_from_option!(ContentType::from_u8(content_type));
+        let (off, version) = dec_try!(buf, off; decode_u16);
+        stream_cond!(version == DTLS_1_2);
+        let (off, epoch) = dec_try!(buf, off; decode_u16);
+        let (off, seq_hi) = dec_try!(buf, off; decode_u16);
+        let (off, seq_mid) = dec_try!(buf, off; decode_u16);
+        let (off, seq_lo) = dec_try!(buf, off; decode_u16);
+        let (off, len) = dec_try!(buf, off; decode_u16);
+        stream_cond!(off + (len as usize) <= buf.len());
+
+        let seq_num = (seq_hi as u64) << 32 | (seq_mid as u64) << 16 | (seq_lo as u64);
+        stream_done!(
+            off,
+            RecordHeader {
+                content_type: content_type,
+                version: version,
+                epoch: epoch,
+                seq_num: seq_num,
+                len: len,
+            }
+        );
+    }
+}
+
+/// Iterates over the records in a datagram, yielding each header and the
+/// fragment that follows it. Iteration stops at the first malformed record.
+pub struct Records<'a> {
+    buf: &'a [u8],
+}
+
+impl Records<'a> {
+    pub fn new(buf: &'a [u8]) -> Records<'a> {
+        Records { buf: buf }
+    }
+}
+
+impl Iterator for Records<'a> {
+    type Item = (RecordHeader, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match RecordHeader::decode(self.buf) {
+            SResult::Done(off, hdr) => {
+                let end = off + hdr.len as usize;
+                let fragment = &self.buf[off..end];
+                self.buf = &self.buf[end..];
+                Some((hdr, fragment))
+            }
+            _ => {
+                self.buf = &[];
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,1345 @@
+//! A DTLS 1.2 client session with a pre-shared key, over UDP.
+//!
+//! `DTLSSession` runs the handshake of the `handshake` module with a server,
+//! and then protects application data in both directions with the
+//! `TLS_PSK_WITH_AES_128_CCM_8` cipher suite (RFC 6655). It is meant for
+//! protocols like CoAP, whose messages fit in a single datagram.
+//!
+//! The pseudorandom function of the `prf` module and the hash of the
+//! handshake messages are computed with a `hil::digest::Digest` that
+//! supports SHA-256 and HMAC-SHA256, such as `capsules::sha256::Sha256Software`
+//! or a hardware implementation, so the handshake does not block the kernel
+//! while keys are derived. The digest must not be used by anyone else while
+//! a handshake is running. Records are protected with an `AES128CCM`, usually
+//! a `VirtualAES128CCM`, with a 12-byte nonce made of the 4-byte IV of the
+//! direction and the epoch and sequence number of the record, which are sent
+//! as the explicit part of the nonce. Its crypt buffer must hold a record of
+//! the largest message plus three blocks.
+//!
+//! The handshake is retransmitted flight by flight, starting after
+//! `INITIAL_RETRY_MS` and doubling the wait every time, and fails after
+//! `MAX_RETRIES` retransmissions without an answer (RFC 6347, Section 4.2.4).
+//! Handshake messages from the server that are fragmented or arrive out of
+//! order are dropped, and the retransmissions of the client make the server
+//! send them again. Only one record is encrypted or decrypted at a time:
+//! records received while the session is busy are dropped, and `send`
+//! returns `EBUSY`. Records older than the newest one received are dropped
+//! as replays.
+//!
+//! Datagrams are sent through a `UDPSender` that can be shared with the UDP
+//! driver, whose client then also receives their `send_done` callbacks.
+//! Received UDP datagrams that do not come from the server to the local port
+//! of the session are passed on to the client set with `set_next_client`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! pub static PSK_IDENTITY: &[u8] = b"tock";
+//! pub static PSK: [u8; 16] = [...];
+//!
+//! let dtls_aes_ccm = static_init!(
+//!     capsules::virtual_aes_ccm::VirtualAES128CCM<'static, Ccm>,
+//!     capsules::virtual_aes_ccm::VirtualAES128CCM::new(mux_aes_ccm)
+//! );
+//! let dtls = static_init!(
+//!     capsules::net::dtls::session::DTLSSession<
+//!         'static,
+//!         VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+//!         capsules::virtual_aes_ccm::VirtualAES128CCM<'static, Ccm>,
+//!         capsules::sha256::Sha256Software<'static>,
+//!         sam4l::trng::Trng<'static>,
+//!     >,
+//!     capsules::net::dtls::session::DTLSSession::new(
+//!         udp_send,
+//!         dtls_virtual_alarm,
+//!         dtls_aes_ccm,
+//!         sha256,
+//!         &sam4l::trng::TRNG,
+//!         PSK_IDENTITY,
+//!         &PSK,
+//!         &mut capsules::net::dtls::session::TX_BUF,
+//!         &mut capsules::net::dtls::session::RX_BUF,
+//!         &mut capsules::net::dtls::session::TRANSCRIPT,
+//!         &mut capsules::net::dtls::session::HMAC_BUF,
+//!         &mut capsules::net::dtls::session::DIGEST
+//!     )
+//! );
+//! dtls_virtual_alarm.set_client(dtls);
+//! dtls_aes_ccm.set_client(dtls);
+//! hil::digest::Digest::set_client(sha256, dtls);
+//! hil::rng::Rng::set_client(&sam4l::trng::TRNG, dtls);
+//! dtls.set_next_client(udp_driver);
+//! udp_recv.set_client(dtls);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::digest::{self, Digest, HmacSha256, Sha256};
+use kernel::hil::rng::{self, Rng};
+use kernel::hil::symmetric_encryption::{CCMClient, AES128CCM};
+use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::ReturnCode;
+use net::dtls::handshake::{self, HandshakeHeader, HandshakeType, HANDSHAKE_HDR_LEN, RANDOM_LEN};
+use net::dtls::prf::{self, KEY_BLOCK_LEN, MASTER_SECRET_LEN, VERIFY_DATA_LEN};
+use net::dtls::record::{ContentType, RecordHeader, Records, MAX_SEQ_NUM, RECORD_HDR_LEN};
+use net::ipv6::ip_utils::IPAddr;
+use net::stream::SResult;
+use net::stream::{encode_u16, encode_u8};
+use net::udp::udp_recv::UDPRecvClient;
+use net::udp::udp_send::UDPSender;
+
+/// How long to wait for the answer to the first transmission of a flight,
+/// doubled for each retransmission.
+const INITIAL_RETRY_MS: u32 = 1000;
+
+/// How many times a flight is sent again before the handshake fails.
+const MAX_RETRIES: usize = 6;
+
+/// Length of the write key of each direction.
+const KEY_LEN: usize = 16;
+
+/// Length of the implicit part of the nonce, the write IV of each direction.
+const IV_LEN: usize = 4;
+
+/// Length of the explicit part of the nonce, sent before the ciphertext.
+const EXPLICIT_NONCE_LEN: usize = 8;
+
+/// Length of the authentication tag of `AES_128_CCM_8`.
+const MAC_LEN: usize = 8;
+
+/// Length of the additional data authenticated with a record: its sequence
+/// number, content type, version and plaintext length.
+const AAD_LEN: usize = 13;
+
+/// Length of a SHA-256 digest.
+const HASH_LEN: usize = 32;
+
+/// The longest pre-shared key supported.
+pub const MAX_PSK_LEN: usize = 64;
+
+/// Bytes a protected record adds to the data it carries.
+pub const RECORD_OVERHEAD: usize = RECORD_HDR_LEN + EXPLICIT_NONCE_LEN + MAC_LEN;
+
+/// The longest label of the pseudorandom function.
+const MAX_LABEL_LEN: usize = 15;
+
+pub static mut TX_BUF: [u8; 256] = [0; 256];
+pub static mut RX_BUF: [u8; 256] = [0; 256];
+pub static mut TRANSCRIPT: [u8; 512] = [0; 512];
+pub static mut HMAC_BUF: [u8; HASH_LEN + MAX_LABEL_LEN + 2 * RANDOM_LEN] =
+    [0; HASH_LEN + MAX_LABEL_LEN + 2 * RANDOM_LEN];
+pub static mut DIGEST: [u8; HASH_LEN] = [0; HASH_LEN];
+
+/// The only message of the change cipher spec protocol.
+const CHANGE_CIPHER_SPEC: u8 = 1;
+
+const ALERT_LEVEL_FATAL: u8 = 2;
+const ALERT_CLOSE_NOTIFY: u8 = 0;
+
+/// Writes the explicit nonce of a record with header `hdr`: its epoch and
+/// sequence number.
+fn encode_explicit_nonce(buf: &mut [u8], hdr: &RecordHeader) -> SResult {
+    stream_len_cond!(buf, EXPLICIT_NONCE_LEN);
+    let mut off = enc_consume!(buf; encode_u16, hdr.epoch);
+    off = enc_consume!(buf, off; encode_u16, (hdr.seq_num >> 32) as u16);
+    off = enc_consume!(buf, off; encode_u16, (hdr.seq_num >> 16) as u16);
+    off = enc_consume!(buf, off; encode_u16, hdr.seq_num as u16);
+    stream_done!(off);
+}
+
+/// Writes the additional data of a record with header `hdr`, whose length
+/// is that of the plaintext (RFC 5246, Section 6.2.3.3).
+fn encode_aad(buf: &mut [u8], hdr: &RecordHeader) -> SResult {
+    stream_len_cond!(buf, AAD_LEN);
+    let mut off = enc_consume!(buf; encode_explicit_nonce, hdr);
+    off = enc_consume!(buf, off; encode_u8, hdr.content_type as u8);
+    off = enc_consume!(buf, off; encode_u16, hdr.version);
+    off = enc_consume!(buf, off; encode_u16, hdr.len);
+    stream_done!(off);
+}
+
+/// Writes the header and explicit nonce of a record of the client at `off`,
+/// once its plaintext, of the length in `hdr`, is encrypted.
+fn encode_protected_header(buf: &mut [u8], off: usize, hdr: &RecordHeader) {
+    let protected_hdr = RecordHeader {
+        len: EXPLICIT_NONCE_LEN as u16 + hdr.len + MAC_LEN as u16,
+        ..*hdr
+    };
+    let _ = protected_hdr.encode(&mut buf[off..]);
+    let _ = encode_explicit_nonce(&mut buf[off + RECORD_HDR_LEN..], hdr);
+}
+
+/// The `DTLSClient` trait is implemented by the user of a `DTLS` session to
+/// learn when the handshake completes, and to receive application data.
+pub trait DTLSClient {
+    /// Called once the handshake completes, fails, or times out.
+    fn connected(&self, result: ReturnCode);
+
+    /// Called once the data passed to `send` has been protected and handed
+    /// to the UDP layer.
+    fn send_done(&self, result: ReturnCode);
+
+    /// Called with the application data of each record from the server.
+    fn received(&self, data: &[u8]);
+
+    /// Called when the server closes the session, or sends a fatal alert.
+    fn disconnected(&self, result: ReturnCode);
+}
+
+/// The interface of a DTLS client session.
+pub trait DTLS<'a> {
+    fn set_client(&self, client: &'a DTLSClient);
+
+    /// Starts a handshake with the server at `server` and `server_port` from
+    /// `local_port`. Returns `EALREADY` if the session is connected or
+    /// connecting, and `EINVAL` if a port is 0 or the pre-shared key is
+    /// longer than `MAX_PSK_LEN`.
+    fn connect(&self, server: IPAddr, server_port: u16, local_port: u16) -> ReturnCode;
+
+    /// Protects `data` and sends it to the server. Returns `ERESERVE` if the
+    /// session is not connected, `EBUSY` if a record is being protected or
+    /// checked, and `ESIZE` if the record does not fit in the transmit
+    /// buffer.
+    fn send(&self, data: &[u8]) -> ReturnCode;
+
+    /// Forgets the session, without telling the server.
+    fn close(&self);
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum State {
+    Closed,
+    /// Waiting for the random value of the `ClientHello`.
+    Random,
+    /// Waiting for a `HelloVerifyRequest` or the `ServerHello`.
+    ServerHello,
+    /// Waiting for the `ServerHelloDone`.
+    ServerHelloDone,
+    /// Deriving the keys and the contents of the `Finished` messages.
+    Keys,
+    /// Waiting for the `ChangeCipherSpec` and `Finished` of the server.
+    ServerFinished,
+    Connected,
+}
+
+/// The values derived one after the other once the server's flight is
+/// complete.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Derive {
+    MasterSecret,
+    KeyBlock,
+    /// The hash of the handshake up to the `ClientKeyExchange`.
+    ClientHash,
+    ClientFinished,
+    /// The hash of the handshake up to the `Finished` of the client.
+    ServerHash,
+    ServerFinished,
+}
+
+/// Where the pseudorandom function is, as it computes one HMAC after the
+/// other.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Prf {
+    /// Computing A(1) from the label and seed.
+    FirstA,
+    /// Computing a block of output from A(i), the label and the seed.
+    Block,
+    /// Computing A(i + 1) from A(i).
+    NextA,
+}
+
+/// The record the AES-CCM implementation is working on.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Crypt {
+    Idle,
+    /// The `Finished` of the client, in a record with `seq_num` at
+    /// `finished_off`, which ends a flight of `len` bytes.
+    Flight {
+        len: usize,
+        finished_off: usize,
+        seq_num: u64,
+    },
+    /// Application data of `len` bytes to send, in a record with `seq_num`.
+    Send {
+        len: usize,
+        seq_num: u64,
+    },
+    /// A record from the server.
+    Receive {
+        content_type: ContentType,
+        seq_num: u64,
+        len: usize,
+    },
+}
+
+pub struct DTLSSession<'a, A: Alarm + 'a, C: AES128CCM<'a> + 'a, D: Digest + 'a, R: Rng<'a> + 'a> {
+    udp_send: &'a UDPSender<'a>,
+    alarm: &'a A,
+    aes_ccm: &'a C,
+    digest: &'a D,
+    rng: &'a R,
+    psk_identity: &'a [u8],
+    psk: &'a [u8],
+    tx_buf: TakeCell<'static, [u8]>,
+    rx_buf: TakeCell<'static, [u8]>,
+    /// The handshake messages exchanged since the last `ClientHello`, which
+    /// begins it.
+    transcript: TakeCell<'static, [u8]>,
+    transcript_len: Cell<usize>,
+    /// Length of the `ClientHello` at the start of the transcript.
+    hello_len: Cell<usize>,
+    /// Offset of the `ClientKeyExchange` in the transcript, which is followed
+    /// by the `Finished` of the client.
+    flight_off: Cell<usize>,
+    hmac_buf: TakeCell<'static, [u8]>,
+    digest_buf: TakeCell<'static, [u8]>,
+
+    state: Cell<State>,
+    crypt: Cell<Crypt>,
+    retries: Cell<usize>,
+    server: Cell<IPAddr>,
+    server_port: Cell<u16>,
+    local_port: Cell<u16>,
+
+    client_random: Cell<[u8; RANDOM_LEN]>,
+    /// Bytes of the client random received from the RNG so far.
+    random_len: Cell<usize>,
+    server_random: Cell<[u8; RANDOM_LEN]>,
+    /// The `message_seq` of the next handshake message of the client.
+    message_seq: Cell<u16>,
+    /// The `message_seq` of the next handshake message expected from the
+    /// server.
+    next_receive_seq: Cell<u16>,
+    /// The next sequence number of the records sent in epochs 0 and 1.
+    write_seq: [Cell<u64>; 2],
+    /// The epoch of the records accepted from the server.
+    read_epoch: Cell<u16>,
+    /// The sequence number of the newest record accepted in epoch 1.
+    read_seq: Cell<Option<u64>>,
+
+    derive: Cell<Option<Derive>>,
+    prf: Cell<Prf>,
+    /// Bytes of output of the pseudorandom function computed so far.
+    prf_len: Cell<usize>,
+    /// Length of the label and seed of the pseudorandom function.
+    prf_seed_len: Cell<usize>,
+    prf_output: Cell<[u8; MASTER_SECRET_LEN]>,
+    master_secret: Cell<[u8; MASTER_SECRET_LEN]>,
+    key_block: Cell<[u8; KEY_BLOCK_LEN]>,
+    handshake_hash: Cell<[u8; HASH_LEN]>,
+    /// The `verify_data` expected in the `Finished` of the server.
+    server_verify_data: Cell<[u8; VERIFY_DATA_LEN]>,
+
+    next_client: OptionalCell<&'a UDPRecvClient>,
+    client: OptionalCell<&'a DTLSClient>,
+}
+
+impl<A: Alarm, C: AES128CCM<'a>, D: Digest + Sha256 + HmacSha256, R: Rng<'a>>
+    DTLSSession<'a, A, C, D, R>
+{
+    pub fn new(
+        udp_send: &'a UDPSender<'a>,
+        alarm: &'a A,
+        aes_ccm: &'a C,
+        digest: &'a D,
+        rng: &'a R,
+        psk_identity: &'a [u8],
+        psk: &'a [u8],
+        tx_buf: &'static mut [u8],
+        rx_buf: &'static mut [u8],
+        transcript: &'static mut [u8],
+        hmac_buf: &'static mut [u8],
+        digest_buf: &'static mut [u8],
+    ) -> DTLSSession<'a, A, C, D, R> {
+        DTLSSession {
+            udp_send: udp_send,
+            alarm: alarm,
+            aes_ccm: aes_ccm,
+            digest: digest,
+            rng: rng,
+            psk_identity: psk_identity,
+            psk: psk,
+            tx_buf: TakeCell::new(tx_buf),
+            rx_buf: TakeCell::new(rx_buf),
+            transcript: TakeCell::new(transcript),
+            transcript_len: Cell::new(0),
+            hello_len: Cell::new(0),
+            flight_off: Cell::new(0),
+            hmac_buf: TakeCell::new(hmac_buf),
+            digest_buf: TakeCell::new(digest_buf),
+            state: Cell::new(State::Closed),
+            crypt: Cell::new(Crypt::Idle),
+            retries: Cell::new(0),
+            server: Cell::new(IPAddr::new()),
+            server_port: Cell::new(0),
+            local_port: Cell::new(0),
+            client_random: Cell::new([0; RANDOM_LEN]),
+            random_len: Cell::new(0),
+            server_random: Cell::new([0; RANDOM_LEN]),
+            message_seq: Cell::new(0),
+            next_receive_seq: Cell::new(0),
+            write_seq: [Cell::new(0), Cell::new(0)],
+            read_epoch: Cell::new(0),
+            read_seq: Cell::new(None),
+            derive: Cell::new(None),
+            prf: Cell::new(Prf::FirstA),
+            prf_len: Cell::new(0),
+            prf_seed_len: Cell::new(0),
+            prf_output: Cell::new([0; MASTER_SECRET_LEN]),
+            master_secret: Cell::new([0; MASTER_SECRET_LEN]),
+            key_block: Cell::new([0; KEY_BLOCK_LEN]),
+            handshake_hash: Cell::new([0; HASH_LEN]),
+            server_verify_data: Cell::new([0; VERIFY_DATA_LEN]),
+            next_client: OptionalCell::empty(),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Sets the client that receives UDP datagrams that are not DTLS records
+    /// from the server.
+    pub fn set_next_client(&self, client: &'a UDPRecvClient) {
+        self.next_client.set(client);
+    }
+
+    fn set_timeout(&self, ms: u32) {
+        let tics = A::Frequency::frequency() / 1000 * ms;
+        self.alarm.set_alarm(self.alarm.now().wrapping_add(tics));
+    }
+
+    /// Waits for the answer to the flight just sent.
+    fn wait_for_answer(&self) {
+        self.set_timeout(INITIAL_RETRY_MS << self.retries.get());
+    }
+
+    fn transmit(&self, buf: &[u8]) -> ReturnCode {
+        self.udp_send.send_to(
+            self.server.get(),
+            self.server_port.get(),
+            self.local_port.get(),
+            buf,
+        )
+    }
+
+    /// The header of the next record sent in `epoch`.
+    fn next_record(&self, content_type: ContentType, epoch: u16, len: usize) -> RecordHeader {
+        let seq = &self.write_seq[epoch as usize];
+        let seq_num = seq.get();
+        seq.set(seq_num + 1);
+        RecordHeader::new(content_type, epoch, seq_num, len as u16)
+    }
+
+    /// Forgets the session and its keys.
+    fn reset(&self) {
+        self.state.set(State::Closed);
+        self.derive.set(None);
+        self.alarm.disable();
+        self.master_secret.set([0; MASTER_SECRET_LEN]);
+        self.key_block.set([0; KEY_BLOCK_LEN]);
+        self.prf_output.set([0; MASTER_SECRET_LEN]);
+    }
+
+    /// Ends a handshake that cannot complete.
+    fn fail(&self, result: ReturnCode) {
+        self.reset();
+        self.client.map(|client| client.connected(result));
+    }
+
+    /// Ends a session that the server closed.
+    fn disconnect(&self, result: ReturnCode) {
+        match self.state.get() {
+            State::Closed => {}
+            State::Connected => {
+                self.reset();
+                self.client.map(|client| client.disconnected(result));
+            }
+            _ => self.fail(ReturnCode::FAIL),
+        }
+    }
+
+    // Handshake messages
+
+    /// Adds a handshake message to the transcript.
+    fn append(&self, message: &[u8]) -> ReturnCode {
+        let len = self.transcript_len.get();
+        self.transcript.map_or(ReturnCode::ENOMEM, |transcript| {
+            if len + message.len() > transcript.len() {
+                return ReturnCode::ESIZE;
+            }
+            transcript[len..len + message.len()].copy_from_slice(message);
+            self.transcript_len.set(len + message.len());
+            ReturnCode::SUCCESS
+        })
+    }
+
+    /// Starts the transcript with a `ClientHello` with `cookie`.
+    fn start_transcript(&self, cookie: &[u8]) -> ReturnCode {
+        let message_seq = self.message_seq.get();
+        let random = self.client_random.get();
+        let result = self.transcript.map_or(ReturnCode::ENOMEM, |transcript| {
+            match handshake::encode_client_hello(transcript, message_seq, &random, cookie) {
+                SResult::Done(len, ()) => {
+                    self.hello_len.set(len);
+                    self.transcript_len.set(len);
+                    ReturnCode::SUCCESS
+                }
+                _ => ReturnCode::ESIZE,
+            }
+        });
+        if result == ReturnCode::SUCCESS {
+            self.message_seq.set(message_seq + 1);
+        }
+        result
+    }
+
+    /// Sends the `ClientHello` at the start of the transcript in a record.
+    fn send_hello(&self) -> ReturnCode {
+        let hello_len = self.hello_len.get();
+        let hdr = self.next_record(ContentType::Handshake, 0, hello_len);
+        self.tx_buf.map_or(ReturnCode::ENOMEM, |buf| {
+            self.transcript.map_or(ReturnCode::ENOMEM, |transcript| {
+                let len = RECORD_HDR_LEN + hello_len;
+                if len > buf.len() {
+                    return ReturnCode::ESIZE;
+                }
+                let _ = hdr.encode(buf);
+                buf[RECORD_HDR_LEN..len].copy_from_slice(&transcript[..hello_len]);
+                self.transmit(&buf[..len])
+            })
+        })
+    }
+
+    /// Adds the `ClientKeyExchange` to the transcript, and starts deriving
+    /// the keys of the session from it.
+    fn start_keys(&self) {
+        let message_seq = self.message_seq.get();
+        let len = self.transcript_len.get();
+        let result = self.transcript.map_or(ReturnCode::ENOMEM, |transcript| {
+            match handshake::encode_client_key_exchange(
+                &mut transcript[len..],
+                message_seq,
+                self.psk_identity,
+            ) {
+                SResult::Done(cke_len, ()) => {
+                    self.flight_off.set(len);
+                    self.transcript_len.set(len + cke_len);
+                    ReturnCode::SUCCESS
+                }
+                _ => ReturnCode::ESIZE,
+            }
+        });
+        if result != ReturnCode::SUCCESS {
+            self.fail(result);
+            return;
+        }
+        self.message_seq.set(message_seq + 1);
+        self.state.set(State::Keys);
+        self.alarm.disable();
+        self.start_derive(Derive::MasterSecret);
+    }
+
+    /// Handles a handshake message from the server in epoch 0.
+    fn receive_handshake(&self, hdr: HandshakeHeader, message: &[u8]) {
+        let body = &message[HANDSHAKE_HDR_LEN..];
+        if hdr.is_fragmented() {
+            return;
+        }
+        let state = self.state.get();
+        if hdr.msg_type == HandshakeType::HelloVerifyRequest && state == State::ServerHello {
+            // The `ClientHello` with the cookie starts the handshake over.
+            let cookie = match handshake::decode_hello_verify_request(body).done() {
+                Some((_, cookie)) => cookie,
+                None => return,
+            };
+            let result = self.start_transcript(cookie);
+            if result != ReturnCode::SUCCESS {
+                self.fail(result);
+                return;
+            }
+            self.next_receive_seq.set(hdr.message_seq.wrapping_add(1));
+            self.retries.set(0);
+            let _ = self.send_hello();
+            self.wait_for_answer();
+            return;
+        }
+        if hdr.message_seq != self.next_receive_seq.get() {
+            return;
+        }
+        match (state, hdr.msg_type) {
+            (State::ServerHello, HandshakeType::ServerHello) => {
+                let hello = match handshake::decode_server_hello(body).done() {
+                    Some((_, hello)) => hello,
+                    None => {
+                        self.fail(ReturnCode::ENOSUPPORT);
+                        return;
+                    }
+                };
+                self.server_random.set(hello.random);
+                self.state.set(State::ServerHelloDone);
+            }
+            (State::ServerHelloDone, HandshakeType::ServerKeyExchange) => {
+                // The PSK identity hint is not used.
+            }
+            (State::ServerHelloDone, HandshakeType::ServerHelloDone) => {}
+            _ => return,
+        }
+        let result = self.append(message);
+        if result != ReturnCode::SUCCESS {
+            self.fail(result);
+            return;
+        }
+        self.next_receive_seq.set(hdr.message_seq.wrapping_add(1));
+        if hdr.msg_type == HandshakeType::ServerHelloDone {
+            self.start_keys();
+        }
+    }
+
+    /// Handles a record of handshake messages from the server in epoch 0.
+    fn receive_handshake_record(&self, fragment: &[u8]) {
+        let mut off = 0;
+        while off < fragment.len() {
+            let hdr = match HandshakeHeader::decode(&fragment[off..]).done() {
+                Some((_, hdr)) => hdr,
+                None => return,
+            };
+            let end = off + HANDSHAKE_HDR_LEN + hdr.fragment_len as usize;
+            self.receive_handshake(hdr, &fragment[off..end]);
+            off = end;
+        }
+    }
+
+    // Deriving keys
+
+    /// Starts computing `derive` with the digest.
+    fn start_derive(&self, derive: Derive) {
+        self.derive.set(Some(derive));
+        let result = match derive {
+            Derive::MasterSecret => {
+                let mut premaster_secret = [0; 4 + 2 * MAX_PSK_LEN];
+                let len = match prf::encode_psk_premaster_secret(&mut premaster_secret, self.psk) {
+                    SResult::Done(len, ()) => len,
+                    _ => 0,
+                };
+                let client_random = self.client_random.get();
+                let server_random = self.server_random.get();
+                self.start_prf(
+                    &premaster_secret[..len],
+                    prf::LABEL_MASTER_SECRET,
+                    &[&client_random, &server_random],
+                )
+            }
+            Derive::KeyBlock => {
+                let client_random = self.client_random.get();
+                let server_random = self.server_random.get();
+                self.start_prf(
+                    &self.master_secret.get(),
+                    prf::LABEL_KEY_EXPANSION,
+                    &[&server_random, &client_random],
+                )
+            }
+            Derive::ClientHash | Derive::ServerHash => self.start_hash(),
+            Derive::ClientFinished => self.start_prf(
+                &self.master_secret.get(),
+                prf::LABEL_CLIENT_FINISHED,
+                &[&self.handshake_hash.get()],
+            ),
+            Derive::ServerFinished => self.start_prf(
+                &self.master_secret.get(),
+                prf::LABEL_SERVER_FINISHED,
+                &[&self.handshake_hash.get()],
+            ),
+        };
+        if result != ReturnCode::SUCCESS {
+            self.fail(result);
+        }
+    }
+
+    /// Starts hashing the transcript with SHA-256.
+    fn start_hash(&self) -> ReturnCode {
+        let result = self.digest.set_mode_sha256();
+        if result != ReturnCode::SUCCESS {
+            return result;
+        }
+        self.transcript
+            .take()
+            .map_or(ReturnCode::ENOMEM, |transcript| {
+                let (result, transcript) =
+                    self.digest.add_data(transcript, self.transcript_len.get());
+                transcript.map(|transcript| self.transcript.replace(transcript));
+                result
+            })
+    }
+
+    /// Starts computing `PRF(secret, label, seed)`, where the seed is the
+    /// concatenation of the parts in `seed`. The label and seed are kept
+    /// after room for A(i) in the HMAC buffer.
+    fn start_prf(&self, secret: &[u8], label: &[u8], seed: &[&[u8]]) -> ReturnCode {
+        let result = self.digest.set_mode_hmacsha256(secret);
+        if result != ReturnCode::SUCCESS {
+            return result;
+        }
+        let seed_len = self.hmac_buf.map_or(0, |buf| {
+            // A(1) is computed from the label and seed alone, so they are
+            // at the start of the buffer until then.
+            let mut len = 0;
+            for part in [label].iter().chain(seed.iter()) {
+                buf[len..len + part.len()].copy_from_slice(part);
+                len += part.len();
+            }
+            len
+        });
+        self.prf.set(Prf::FirstA);
+        self.prf_len.set(0);
+        self.prf_seed_len.set(seed_len);
+        self.add_hmac_data(seed_len)
+    }
+
+    fn add_hmac_data(&self, len: usize) -> ReturnCode {
+        self.hmac_buf.take().map_or(ReturnCode::ENOMEM, |buf| {
+            let (result, buf) = self.digest.add_data(buf, len);
+            buf.map(|buf| self.hmac_buf.replace(buf));
+            result
+        })
+    }
+
+    /// Handles the next HMAC computed for the pseudorandom function, and
+    /// returns whether all of its output is computed. `out_len` is the
+    /// length of the output needed.
+    fn prf_step(&self, digest: &[u8], out_len: usize) -> Result<bool, ReturnCode> {
+        let seed_len = self.prf_seed_len.get();
+        let next = match self.prf.get() {
+            Prf::FirstA => {
+                self.hmac_buf.map(|buf| {
+                    for i in (0..seed_len).rev() {
+                        buf[HASH_LEN + i] = buf[i];
+                    }
+                    buf[..HASH_LEN].copy_from_slice(&digest[..HASH_LEN]);
+                });
+                Prf::Block
+            }
+            Prf::Block => {
+                let off = self.prf_len.get();
+                let len = cmp::min(HASH_LEN, out_len - off);
+                let mut output = self.prf_output.get();
+                output[off..off + len].copy_from_slice(&digest[..len]);
+                self.prf_output.set(output);
+                self.prf_len.set(off + len);
+                if off + len == out_len {
+                    return Ok(true);
+                }
+                Prf::NextA
+            }
+            Prf::NextA => {
+                self.hmac_buf
+                    .map(|buf| buf[..HASH_LEN].copy_from_slice(&digest[..HASH_LEN]));
+                Prf::Block
+            }
+        };
+        self.prf.set(next);
+        let len = match next {
+            Prf::Block => HASH_LEN + seed_len,
+            _ => HASH_LEN,
+        };
+        match self.add_hmac_data(len) {
+            ReturnCode::SUCCESS => Ok(false),
+            result => Err(result),
+        }
+    }
+
+    /// Stores the value computed for `derive`, and starts computing the next
+    /// one.
+    fn derived(&self, derive: Derive, digest: &[u8]) {
+        let output = self.prf_output.get();
+        let next = match derive {
+            Derive::MasterSecret => {
+                self.master_secret.set(output);
+                Derive::KeyBlock
+            }
+            Derive::KeyBlock => {
+                let mut key_block = [0; KEY_BLOCK_LEN];
+                key_block.copy_from_slice(&output[..KEY_BLOCK_LEN]);
+                self.key_block.set(key_block);
+                Derive::ClientHash
+            }
+            Derive::ClientHash => {
+                let mut hash = [0; HASH_LEN];
+                hash.copy_from_slice(&digest[..HASH_LEN]);
+                self.handshake_hash.set(hash);
+                Derive::ClientFinished
+            }
+            Derive::ClientFinished => {
+                let mut verify_data = [0; VERIFY_DATA_LEN];
+                verify_data.copy_from_slice(&output[..VERIFY_DATA_LEN]);
+                let message_seq = self.message_seq.get();
+                let len = self.transcript_len.get();
+                let result = self.transcript.map_or(ReturnCode::ENOMEM, |transcript| {
+                    match handshake::encode_finished(
+                        &mut transcript[len..],
+                        message_seq,
+                        &verify_data,
+                    ) {
+                        SResult::Done(finished_len, ()) => {
+                            self.transcript_len.set(len + finished_len);
+                            ReturnCode::SUCCESS
+                        }
+                        _ => ReturnCode::ESIZE,
+                    }
+                });
+                if result != ReturnCode::SUCCESS {
+                    self.fail(result);
+                    return;
+                }
+                self.message_seq.set(message_seq + 1);
+                Derive::ServerHash
+            }
+            Derive::ServerHash => {
+                let mut hash = [0; HASH_LEN];
+                hash.copy_from_slice(&digest[..HASH_LEN]);
+                self.handshake_hash.set(hash);
+                Derive::ServerFinished
+            }
+            Derive::ServerFinished => {
+                let mut verify_data = [0; VERIFY_DATA_LEN];
+                verify_data.copy_from_slice(&output[..VERIFY_DATA_LEN]);
+                self.server_verify_data.set(verify_data);
+                self.derive.set(None);
+                self.state.set(State::ServerFinished);
+                self.retries.set(0);
+                self.send_flight();
+                return;
+            }
+        };
+        self.start_derive(next);
+    }
+
+    // Records
+
+    /// The write key and IV of the client, or those of the server.
+    fn keys(&self, server: bool) -> ([u8; KEY_LEN], [u8; IV_LEN]) {
+        let key_block = self.key_block.get();
+        let (key_off, iv_off) = if server {
+            (KEY_LEN, 2 * KEY_LEN + IV_LEN)
+        } else {
+            (0, 2 * KEY_LEN)
+        };
+        let mut key = [0; KEY_LEN];
+        let mut iv = [0; IV_LEN];
+        key.copy_from_slice(&key_block[key_off..key_off + KEY_LEN]);
+        iv.copy_from_slice(&key_block[iv_off..iv_off + IV_LEN]);
+        (key, iv)
+    }
+
+    /// Sets the key and nonce to protect or check a record of the client or
+    /// the server, with the explicit nonce sent in the record.
+    fn set_record_keys(&self, server: bool, explicit_nonce: &[u8]) -> ReturnCode {
+        let (key, iv) = self.keys(server);
+        let mut nonce = [0; IV_LEN + EXPLICIT_NONCE_LEN];
+        nonce[..IV_LEN].copy_from_slice(&iv);
+        nonce[IV_LEN..].copy_from_slice(&explicit_nonce[..EXPLICIT_NONCE_LEN]);
+        if self.aes_ccm.set_key(&key) != ReturnCode::SUCCESS
+            || self.aes_ccm.set_nonce(&nonce) != ReturnCode::SUCCESS
+        {
+            ReturnCode::FAIL
+        } else {
+            ReturnCode::SUCCESS
+        }
+    }
+
+    /// Starts encrypting a record of the client that starts at `off` in
+    /// `buf`, and whose plaintext of `hdr.len` bytes follows the room for
+    /// its header and explicit nonce. The additional data is written just
+    /// before the plaintext, over that room, so the record header and the
+    /// explicit nonce are written with `encode_protected_header` once the
+    /// record is encrypted.
+    fn protect(
+        &self,
+        buf: &'static mut [u8],
+        off: usize,
+        hdr: &RecordHeader,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        let m_off = off + RECORD_HDR_LEN + EXPLICIT_NONCE_LEN;
+        let aad_off = m_off - AAD_LEN;
+        let _ = encode_aad(&mut buf[aad_off..], hdr);
+        let result = self.set_record_keys(false, &buf[aad_off..m_off]);
+        if result != ReturnCode::SUCCESS {
+            return (result, Some(buf));
+        }
+        self.aes_ccm
+            .crypt(buf, aad_off, m_off, hdr.len as usize, MAC_LEN, true, true)
+    }
+
+    /// Sends the flight of the client that ends the handshake, in one
+    /// datagram: the `ClientKeyExchange` and the `ChangeCipherSpec` in
+    /// epoch 0, and the `Finished` in epoch 1. Each retransmission uses new
+    /// sequence numbers, so the `Finished` is encrypted again.
+    fn send_flight(&self) {
+        if self.crypt.get() != Crypt::Idle {
+            // Try again once the record being worked on is done.
+            self.wait_for_answer();
+            return;
+        }
+        let flight_off = self.flight_off.get();
+        let transcript_len = self.transcript_len.get();
+        let finished_msg_off = transcript_len - HANDSHAKE_HDR_LEN - VERIFY_DATA_LEN;
+        let cke_len = finished_msg_off - flight_off;
+        let ccs_off = RECORD_HDR_LEN + cke_len;
+        let finished_off = ccs_off + RECORD_HDR_LEN + 1;
+        let plaintext_off = finished_off + RECORD_HDR_LEN + EXPLICIT_NONCE_LEN;
+        let len = plaintext_off + HANDSHAKE_HDR_LEN + VERIFY_DATA_LEN + MAC_LEN;
+
+        let cke_hdr = self.next_record(ContentType::Handshake, 0, cke_len);
+        let ccs_hdr = self.next_record(ContentType::ChangeCipherSpec, 0, 1);
+        let finished_hdr = self.next_record(
+            ContentType::Handshake,
+            1,
+            HANDSHAKE_HDR_LEN + VERIFY_DATA_LEN,
+        );
+
+        let result = self.tx_buf.take().map_or(ReturnCode::ENOMEM, |buf| {
+            if len > buf.len() {
+                self.tx_buf.replace(buf);
+                return ReturnCode::ESIZE;
+            }
+            let _ = cke_hdr.encode(buf);
+            let _ = ccs_hdr.encode(&mut buf[ccs_off..]);
+            buf[ccs_off + RECORD_HDR_LEN] = CHANGE_CIPHER_SPEC;
+            self.transcript.map(|transcript| {
+                buf[RECORD_HDR_LEN..ccs_off]
+                    .copy_from_slice(&transcript[flight_off..finished_msg_off]);
+                buf[plaintext_off..len - MAC_LEN]
+                    .copy_from_slice(&transcript[finished_msg_off..transcript_len]);
+            });
+            let (result, buf) = self.protect(buf, finished_off, &finished_hdr);
+            match buf {
+                Some(buf) => self.tx_buf.replace(buf),
+                None => self.crypt.set(Crypt::Flight {
+                    len: len,
+                    finished_off: finished_off,
+                    seq_num: finished_hdr.seq_num,
+                }),
+            };
+            result
+        });
+        if result != ReturnCode::SUCCESS {
+            self.fail(result);
+        }
+    }
+
+    /// Starts checking and decrypting a protected record from the server.
+    /// The record is dropped if another one is being worked on, or if it is
+    /// not newer than the last one accepted.
+    fn receive_protected(&self, hdr: RecordHeader, fragment: &[u8]) {
+        if self.crypt.get() != Crypt::Idle
+            || fragment.len() < EXPLICIT_NONCE_LEN + MAC_LEN
+            || self
+                .read_seq
+                .get()
+                .map_or(false, |seq_num| hdr.seq_num <= seq_num)
+        {
+            return;
+        }
+        let len = fragment.len() - EXPLICIT_NONCE_LEN - MAC_LEN;
+        if self.set_record_keys(true, fragment) != ReturnCode::SUCCESS {
+            return;
+        }
+        self.rx_buf.take().map(|buf| {
+            if AAD_LEN + len + MAC_LEN > buf.len() {
+                self.rx_buf.replace(buf);
+                return;
+            }
+            let plaintext_hdr = RecordHeader {
+                len: len as u16,
+                ..hdr
+            };
+            let _ = encode_aad(buf, &plaintext_hdr);
+            buf[AAD_LEN..AAD_LEN + len + MAC_LEN].copy_from_slice(&fragment[EXPLICIT_NONCE_LEN..]);
+            let (_, buf) = self
+                .aes_ccm
+                .crypt(buf, 0, AAD_LEN, len, MAC_LEN, true, false);
+            match buf {
+                Some(buf) => self.rx_buf.replace(buf),
+                None => self.crypt.set(Crypt::Receive {
+                    content_type: hdr.content_type,
+                    seq_num: hdr.seq_num,
+                    len: len,
+                }),
+            };
+        });
+    }
+
+    /// Handles the plaintext of a protected record from the server.
+    fn receive_plaintext(&self, content_type: ContentType, data: &[u8]) {
+        match (self.state.get(), content_type) {
+            (State::ServerFinished, ContentType::Handshake) => {
+                let hdr = match HandshakeHeader::decode(data).done() {
+                    Some((_, hdr)) => hdr,
+                    None => return,
+                };
+                if hdr.msg_type != HandshakeType::Finished
+                    || hdr.is_fragmented()
+                    || hdr.message_seq != self.next_receive_seq.get()
+                {
+                    return;
+                }
+                let verify_data =
+                    match handshake::decode_finished(&data[HANDSHAKE_HDR_LEN..]).done() {
+                        Some((_, verify_data)) => verify_data,
+                        None => return,
+                    };
+                // Compare every byte, so that the time taken does not tell
+                // how much of a forged message is right.
+                let difference = verify_data
+                    .iter()
+                    .zip(self.server_verify_data.get().iter())
+                    .fold(0, |difference, (a, b)| difference | (a ^ b));
+                if difference != 0 {
+                    self.fail(ReturnCode::FAIL);
+                    return;
+                }
+                self.state.set(State::Connected);
+                self.alarm.disable();
+                self.client
+                    .map(|client| client.connected(ReturnCode::SUCCESS));
+            }
+            (State::Connected, ContentType::ApplicationData) => {
+                self.client.map(|client| client.received(data));
+            }
+            (_, ContentType::Alert) => self.receive_alert(data),
+            _ => {}
+        }
+    }
+
+    fn receive_alert(&self, alert: &[u8]) {
+        if alert.len() != 2 {
+            return;
+        }
+        if alert[1] == ALERT_CLOSE_NOTIFY {
+            self.disconnect(ReturnCode::SUCCESS);
+        } else if alert[0] == ALERT_LEVEL_FATAL {
+            self.disconnect(ReturnCode::FAIL);
+        }
+    }
+
+    fn receive_record(&self, hdr: RecordHeader, fragment: &[u8]) {
+        if hdr.epoch != self.read_epoch.get() {
+            return;
+        }
+        if hdr.epoch == 1 {
+            self.receive_protected(hdr, fragment);
+            return;
+        }
+        match hdr.content_type {
+            ContentType::Handshake => self.receive_handshake_record(fragment),
+            ContentType::ChangeCipherSpec => {
+                if self.state.get() == State::ServerFinished && fragment == [CHANGE_CIPHER_SPEC] {
+                    self.read_epoch.set(1);
+                }
+            }
+            ContentType::Alert => self.receive_alert(fragment),
+            ContentType::ApplicationData => {}
+        }
+    }
+}
+
+impl<A: Alarm, C: AES128CCM<'a>, D: Digest + Sha256 + HmacSha256, R: Rng<'a>> DTLS<'a>
+    for DTLSSession<'a, A, C, D, R>
+{
+    fn set_client(&self, client: &'a DTLSClient) {
+        self.client.set(client);
+    }
+
+    fn connect(&self, server: IPAddr, server_port: u16, local_port: u16) -> ReturnCode {
+        if self.state.get() != State::Closed {
+            return ReturnCode::EALREADY;
+        }
+        if server_port == 0 || local_port == 0 || self.psk.len() > MAX_PSK_LEN {
+            return ReturnCode::EINVAL;
+        }
+        self.server.set(server);
+        self.server_port.set(server_port);
+        self.local_port.set(local_port);
+        self.random_len.set(0);
+        self.message_seq.set(0);
+        self.next_receive_seq.set(0);
+        self.write_seq[0].set(0);
+        self.write_seq[1].set(0);
+        self.read_epoch.set(0);
+        self.read_seq.set(None);
+        self.retries.set(0);
+        self.state.set(State::Random);
+        let result = self.rng.get();
+        if result != ReturnCode::SUCCESS {
+            self.state.set(State::Closed);
+        }
+        result
+    }
+
+    fn send(&self, data: &[u8]) -> ReturnCode {
+        if self.state.get() != State::Connected {
+            return ReturnCode::ERESERVE;
+        }
+        if self.crypt.get() != Crypt::Idle {
+            return ReturnCode::EBUSY;
+        }
+        if self.write_seq[1].get() > MAX_SEQ_NUM {
+            // A sequence number must not be used twice with the same key.
+            return ReturnCode::FAIL;
+        }
+        let len = RECORD_OVERHEAD + data.len();
+        self.tx_buf.take().map_or(ReturnCode::ENOMEM, |buf| {
+            if len > buf.len() {
+                self.tx_buf.replace(buf);
+                return ReturnCode::ESIZE;
+            }
+            let hdr = self.next_record(ContentType::ApplicationData, 1, data.len());
+            buf[RECORD_HDR_LEN + EXPLICIT_NONCE_LEN..len - MAC_LEN].copy_from_slice(data);
+            let (result, buf) = self.protect(buf, 0, &hdr);
+            match buf {
+                Some(buf) => self.tx_buf.replace(buf),
+                None => self.crypt.set(Crypt::Send {
+                    len: data.len(),
+                    seq_num: hdr.seq_num,
+                }),
+            };
+            result
+        })
+    }
+
+    fn close(&self) {
+        self.reset();
+    }
+}
+
+impl<A: Alarm, C: AES128CCM<'a>, D: Digest + Sha256 + HmacSha256, R: Rng<'a>> rng::Client
+    for DTLSSession<'a, A, C, D, R>
+{
+    fn randomness_available(
+        &self,
+        randomness: &mut Iterator<Item = u32>,
+        error: ReturnCode,
+    ) -> rng::Continue {
+        if self.state.get() != State::Random {
+            return rng::Continue::Done;
+        }
+        if error != ReturnCode::SUCCESS {
+            self.fail(error);
+            return rng::Continue::Done;
+        }
+        let mut random = self.client_random.get();
+        let mut len = self.random_len.get();
+        while len < RANDOM_LEN {
+            match randomness.next() {
+                Some(word) => {
+                    for i in 0..4 {
+                        random[len + i] = (word >> (8 * i)) as u8;
+                    }
+                    len += 4;
+                }
+                None => break,
+            }
+        }
+        self.client_random.set(random);
+        self.random_len.set(len);
+        if len < RANDOM_LEN {
+            return rng::Continue::More;
+        }
+
+        let result = self.start_transcript(&[]);
+        if result != ReturnCode::SUCCESS {
+            self.fail(result);
+            return rng::Continue::Done;
+        }
+        self.state.set(State::ServerHello);
+        // A `ClientHello` that cannot be sent now is sent again later.
+        let _ = self.send_hello();
+        self.wait_for_answer();
+        rng::Continue::Done
+    }
+}
+
+impl<A: Alarm, C: AES128CCM<'a>, D: Digest + Sha256 + HmacSha256, R: Rng<'a>> digest::Client
+    for DTLSSession<'a, A, C, D, R>
+{
+    fn add_data_done(&self, result: ReturnCode, data: &'static mut [u8]) {
+        if self.transcript.is_none() {
+            self.transcript.replace(data);
+        } else {
+            self.hmac_buf.replace(data);
+        }
+        if self.derive.get().is_none() {
+            return;
+        }
+        if result != ReturnCode::SUCCESS {
+            self.fail(result);
+            return;
+        }
+        let result = self.digest_buf.take().map_or(ReturnCode::ENOMEM, |digest| {
+            let (result, digest) = self.digest.run(digest);
+            digest.map(|digest| self.digest_buf.replace(digest));
+            result
+        });
+        if result != ReturnCode::SUCCESS {
+            self.fail(result);
+        }
+    }
+
+    fn hash_done(&self, result: ReturnCode, digest: &'static mut [u8]) {
+        let derive = match self.derive.get() {
+            Some(derive) => derive,
+            None => {
+                self.digest_buf.replace(digest);
+                return;
+            }
+        };
+        if result != ReturnCode::SUCCESS {
+            self.digest_buf.replace(digest);
+            self.fail(result);
+            return;
+        }
+        let out_len = match derive {
+            Derive::MasterSecret => MASTER_SECRET_LEN,
+            Derive::KeyBlock => KEY_BLOCK_LEN,
+            Derive::ClientFinished | Derive::ServerFinished => VERIFY_DATA_LEN,
+            Derive::ClientHash | Derive::ServerHash => 0,
+        };
+        let done = if out_len == 0 {
+            Ok(true)
+        } else {
+            self.prf_step(digest, out_len)
+        };
+        match done {
+            Ok(true) => self.derived(derive, digest),
+            Ok(false) => {}
+            Err(result) => self.fail(result),
+        }
+        self.digest_buf.replace(digest);
+    }
+
+    fn verification_done(&self, _result: ReturnCode, compare: &'static mut [u8]) {
+        self.digest_buf.replace(compare);
+    }
+}
+
+impl<A: Alarm, C: AES128CCM<'a>, D: Digest + Sha256 + HmacSha256, R: Rng<'a>> CCMClient
+    for DTLSSession<'a, A, C, D, R>
+{
+    fn crypt_done(&self, buf: &'static mut [u8], res: ReturnCode, tag_is_valid: bool) {
+        let crypt = self.crypt.get();
+        self.crypt.set(Crypt::Idle);
+        match crypt {
+            Crypt::Idle => {
+                if self.tx_buf.is_none() {
+                    self.tx_buf.replace(buf);
+                } else {
+                    self.rx_buf.replace(buf);
+                }
+            }
+            Crypt::Flight {
+                len,
+                finished_off,
+                seq_num,
+            } => {
+                let result = if self.state.get() != State::ServerFinished {
+                    ReturnCode::SUCCESS
+                } else if res != ReturnCode::SUCCESS {
+                    res
+                } else {
+                    let hdr = RecordHeader::new(
+                        ContentType::Handshake,
+                        1,
+                        seq_num,
+                        (HANDSHAKE_HDR_LEN + VERIFY_DATA_LEN) as u16,
+                    );
+                    encode_protected_header(buf, finished_off, &hdr);
+                    // A flight that cannot be sent now is sent again later.
+                    let _ = self.transmit(&buf[..len]);
+                    self.wait_for_answer();
+                    ReturnCode::SUCCESS
+                };
+                self.tx_buf.replace(buf);
+                if result != ReturnCode::SUCCESS {
+                    self.fail(result);
+                }
+            }
+            Crypt::Send { len, seq_num } => {
+                let result = if res != ReturnCode::SUCCESS {
+                    res
+                } else if self.state.get() != State::Connected {
+                    ReturnCode::ERESERVE
+                } else {
+                    let hdr =
+                        RecordHeader::new(ContentType::ApplicationData, 1, seq_num, len as u16);
+                    encode_protected_header(buf, 0, &hdr);
+                    self.transmit(&buf[..RECORD_OVERHEAD + len])
+                };
+                self.tx_buf.replace(buf);
+                self.client.map(|client| client.send_done(result));
+            }
+            Crypt::Receive {
+                content_type,
+                seq_num,
+                len,
+            } => {
+                if res == ReturnCode::SUCCESS && tag_is_valid && self.state.get() != State::Closed {
+                    self.read_seq.set(Some(seq_num));
+                    self.receive_plaintext(content_type, &buf[AAD_LEN..AAD_LEN + len]);
+                }
+                self.rx_buf.replace(buf);
+            }
+        }
+    }
+}
+
+impl<A: Alarm, C: AES128CCM<'a>, D: Digest + Sha256 + HmacSha256, R: Rng<'a>> time::Client
+    for DTLSSession<'a, A, C, D, R>
+{
+    fn fired(&self) {
+        let state = self.state.get();
+        match state {
+            State::ServerHello | State::ServerHelloDone | State::ServerFinished => {}
+            _ => return,
+        }
+        if self.retries.get() >= MAX_RETRIES {
+            self.fail(ReturnCode::FAIL);
+            return;
+        }
+        self.retries.set(self.retries.get() + 1);
+        if state == State::ServerFinished {
+            self.send_flight();
+        } else {
+            let _ = self.send_hello();
+            self.wait_for_answer();
+        }
+    }
+}
+
+impl<A: Alarm, C: AES128CCM<'a>, D: Digest + Sha256 + HmacSha256, R: Rng<'a>> UDPRecvClient
+    for DTLSSession<'a, A, C, D, R>
+{
+    fn receive(
+        &self,
+        src_addr: IPAddr,
+        dst_addr: IPAddr,
+        src_port: u16,
+        dst_port: u16,
+        payload: &[u8],
+    ) {
+        if self.state.get() == State::Closed
+            || dst_port != self.local_port.get()
+            || src_port != self.server_port.get()
+            || src_addr.0 != self.server.get().0
+        {
+            self.next_client.map(|client| {
+                client.receive(src_addr, dst_addr, src_port, dst_port, payload);
+            });
+            return;
+        }
+        for (hdr, fragment) in Records::new(payload) {
+            self.receive_record(hdr, fragment);
+            if self.state.get() == State::Closed {
+                break;
+            }
+        }
+    }
+}
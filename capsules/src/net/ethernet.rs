@@ -0,0 +1,229 @@
+//! A network interface that sends and receives IPv6 packets over an Ethernet
+//! MAC, framed as described in RFC 2464.
+//!
+//! `EthernetInterface` implements `IP6Sender`, like the 6LoWPAN and SLIP
+//! interfaces do, and passes the IPv6 packets it receives to a
+//! `SixlowpanRxClient`. The upper layers of the stack, and the
+//! `IP6Forwarder`, can therefore be used with any of these interfaces.
+//! Frames of other EtherTypes are dropped.
+//!
+//! The interface does not implement Neighbor Discovery. Multicast packets are
+//! sent to the matching Ethernet multicast address, and packets for
+//! link-local addresses that were formed from a MAC address are sent to that
+//! MAC address. Everything else is sent to the gateway. Hosts on the link
+//! need a static neighbor entry for the address of the interface, for
+//! example with `ip -6 neigh add`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! static mut ETH_PAYLOAD: [u8; 1232] = [0; 1232];
+//! static mut ETH_TX_BUF: [u8; 1514] = [0; 1514];
+//!
+//! let eth_pyld: IPPayload = IPPayload {
+//!     header: TransportHeader::UDP(UDPHeader::new()),
+//!     payload: &mut ETH_PAYLOAD,
+//! };
+//! let eth_dg = static_init!(IP6Packet<'static>, IP6Packet::new(eth_pyld));
+//! let eth = static_init!(
+//!     capsules::net::ethernet::EthernetInterface<'static>,
+//!     capsules::net::ethernet::EthernetInterface::new(enc28j60, eth_dg, &mut ETH_TX_BUF)
+//! );
+//! hil::ethernet::Ethernet::set_client(enc28j60, eth);
+//! eth.set_addr(LOCAL_IP_IFACES[0]);
+//! hil::ethernet::Ethernet::enable(enc28j60);
+//! ```
+
+use core::cell::Cell;
+use core::cmp::min;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::ethernet::{self, MacAddress, BROADCAST_ADDRESS, HEADER_LEN};
+use kernel::ReturnCode;
+use net::ieee802154;
+use net::ipv6::ip_utils::IPAddr;
+use net::ipv6::ipv6::{IP6Header, IP6Packet, TransportHeader};
+use net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use net::sixlowpan::sixlowpan_state::SixlowpanRxClient;
+
+/// The EtherType of IPv6 packets.
+pub const ETHERTYPE_IPV6: u16 = 0x86dd;
+
+/// Length of an IPv6 header.
+const IP6_HEADER_LEN: usize = 40;
+
+/// Offset of the hop limit in an IPv6 header.
+const HOP_LIMIT_OFFSET: usize = 7;
+
+/// The MAC address that an EUI-64 interface identifier was formed from, if
+/// it was formed from one.
+fn eui64_to_mac(eui64: &[u8]) -> Option<MacAddress> {
+    if eui64[3] == 0xff && eui64[4] == 0xfe {
+        Some([
+            eui64[0] ^ 0x02,
+            eui64[1],
+            eui64[2],
+            eui64[5],
+            eui64[6],
+            eui64[7],
+        ])
+    } else {
+        None
+    }
+}
+
+pub struct EthernetInterface<'a> {
+    mac: &'a ethernet::Ethernet,
+    ip6_packet: TakeCell<'static, IP6Packet<'static>>,
+    src_addr: Cell<IPAddr>,
+    gateway: Cell<MacAddress>,
+    /// Holds the frame being sent, which the MAC copies.
+    tx_buf: TakeCell<'static, [u8]>,
+    client: OptionalCell<&'a IP6SendClient>,
+    rx_client: OptionalCell<&'a SixlowpanRxClient>,
+}
+
+impl<'a> EthernetInterface<'a> {
+    pub fn new(
+        mac: &'a ethernet::Ethernet,
+        ip6_packet: &'static mut IP6Packet<'static>,
+        tx_buf: &'static mut [u8],
+    ) -> EthernetInterface<'a> {
+        EthernetInterface {
+            mac: mac,
+            ip6_packet: TakeCell::new(ip6_packet),
+            src_addr: Cell::new(IPAddr::new()),
+            gateway: Cell::new(BROADCAST_ADDRESS),
+            tx_buf: TakeCell::new(tx_buf),
+            client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+        }
+    }
+
+    /// Sets the client that receives the packets read from the MAC.
+    pub fn set_receive_client(&self, client: &'a SixlowpanRxClient) {
+        self.rx_client.set(client);
+    }
+
+    /// The MAC address to send a packet for `dst_addr` to.
+    fn next_hop(&self, dst_addr: IPAddr) -> MacAddress {
+        if dst_addr.is_multicast() {
+            [
+                0x33,
+                0x33,
+                dst_addr.0[12],
+                dst_addr.0[13],
+                dst_addr.0[14],
+                dst_addr.0[15],
+            ]
+        } else if dst_addr.is_unicast_link_local() {
+            eui64_to_mac(&dst_addr.0[8..16]).unwrap_or(self.gateway.get())
+        } else {
+            self.gateway.get()
+        }
+    }
+
+    /// Writes the Ethernet header for a packet of `len` bytes to `dst_addr`,
+    /// followed by the packet, and sends the frame. `write` writes the packet
+    /// into the slice it is passed.
+    fn transmit<F>(&self, dst_addr: IPAddr, len: usize, write: F) -> ReturnCode
+    where
+        F: FnOnce(&mut [u8]) -> ReturnCode,
+    {
+        self.tx_buf.map_or(ReturnCode::EBUSY, |tx_buf| {
+            if tx_buf.len() < HEADER_LEN + len {
+                return ReturnCode::ESIZE;
+            }
+            tx_buf[0..6].copy_from_slice(&self.next_hop(dst_addr));
+            tx_buf[6..12].copy_from_slice(&self.mac.get_address());
+            tx_buf[12] = (ETHERTYPE_IPV6 >> 8) as u8;
+            tx_buf[13] = ETHERTYPE_IPV6 as u8;
+            let result = write(&mut tx_buf[HEADER_LEN..HEADER_LEN + len]);
+            if result != ReturnCode::SUCCESS {
+                return result;
+            }
+            self.mac.transmit(&tx_buf[..HEADER_LEN + len])
+        })
+    }
+}
+
+impl<'a> IP6Sender<'a> for EthernetInterface<'a> {
+    fn set_client(&self, client: &'a IP6SendClient) {
+        self.client.set(client);
+    }
+
+    fn set_addr(&self, src_addr: IPAddr) {
+        self.src_addr.set(src_addr);
+    }
+
+    /// The gateway is given as the EUI-64 formed from its MAC address.
+    fn set_gateway(&self, gateway: ieee802154::MacAddress) {
+        if let ieee802154::MacAddress::Long(eui64) = gateway {
+            eui64_to_mac(&eui64).map(|mac| self.gateway.set(mac));
+        }
+    }
+
+    fn set_header(&mut self, ip6_header: IP6Header) {
+        self.ip6_packet
+            .map(|ip6_packet| ip6_packet.header = ip6_header);
+    }
+
+    fn send_to(
+        &self,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        payload: &[u8],
+    ) -> ReturnCode {
+        self.ip6_packet.map_or(ReturnCode::ENOMEM, |ip6_packet| {
+            if payload.len() > ip6_packet.payload.payload.len() {
+                return ReturnCode::ESIZE;
+            }
+            ip6_packet.header = IP6Header::default();
+            ip6_packet.header.src_addr = self.src_addr.get();
+            ip6_packet.header.dst_addr = dst;
+            ip6_packet.set_payload(transport_header, payload);
+            ip6_packet.set_transport_checksum();
+
+            let len = ip6_packet.get_total_len() as usize;
+            self.transmit(dst, len, |buf| match ip6_packet.encode(buf).done() {
+                Some(_) => ReturnCode::SUCCESS,
+                None => ReturnCode::ESIZE,
+            })
+        })
+    }
+
+    fn forward(&self, packet: &[u8]) -> ReturnCode {
+        let dst = match IP6Header::decode(packet).done() {
+            Some((_, ip6_header)) => ip6_header.get_dst_addr(),
+            None => return ReturnCode::EINVAL,
+        };
+        self.transmit(dst, packet.len(), |buf| {
+            buf.copy_from_slice(packet);
+            buf[HOP_LIMIT_OFFSET] = buf[HOP_LIMIT_OFFSET].saturating_sub(1);
+            ReturnCode::SUCCESS
+        })
+    }
+}
+
+impl<'a> ethernet::Client for EthernetInterface<'a> {
+    fn transmit_done(&self, result: ReturnCode) {
+        self.client.map(|client| client.send_done(result));
+    }
+
+    fn frame_received(&self, frame: &[u8]) {
+        if frame.len() < HEADER_LEN {
+            return;
+        }
+        let ethertype = (frame[12] as u16) << 8 | frame[13] as u16;
+        if ethertype != ETHERTYPE_IPV6 || frame.len() < HEADER_LEN + IP6_HEADER_LEN {
+            return;
+        }
+        // Short frames are padded, so the length of the packet comes from its
+        // header.
+        let packet = &frame[HEADER_LEN..];
+        let payload_len = (packet[4] as usize) << 8 | packet[5] as usize;
+        let len = min(IP6_HEADER_LEN + payload_len, packet.len());
+        self.rx_client
+            .map(|client| client.receive(packet, len, ReturnCode::SUCCESS));
+    }
+}
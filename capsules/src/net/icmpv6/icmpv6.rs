@@ -185,14 +185,14 @@ impl ICMP6Header {
                 icmp_header.set_options(ICMP6HeaderOptions::Type3 { unused });
             }
             ICMP6Type::Type128 => {
-                let (_off, id) = dec_try!(buf, off; decode_u16);
+                let (off, id) = dec_try!(buf, off; decode_u16);
                 let id = u16::from_be(id);
                 let (_off, seqno) = dec_try!(buf, off; decode_u16);
                 let seqno = u16::from_be(seqno);
                 icmp_header.set_options(ICMP6HeaderOptions::Type128 { id, seqno });
             }
             ICMP6Type::Type129 => {
-                let (_off, id) = dec_try!(buf, off; decode_u16);
+                let (off, id) = dec_try!(buf, off; decode_u16);
                 let id = u16::from_be(id);
                 let (_off, seqno) = dec_try!(buf, off; decode_u16);
                 let seqno = u16::from_be(seqno);
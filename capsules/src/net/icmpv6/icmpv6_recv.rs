@@ -0,0 +1,81 @@
+//! This file contains the definition and implementation of a simple ICMPv6
+//! receiving interface. The [ICMP6RecvStruct](struct.ICMP6RecvStruct.html) is
+//! set as the ICMPv6 client of an `IP6Receiver`. It answers echo requests
+//! itself, so that a border router or host can ping the device, and passes
+//! all other ICMPv6 messages to its
+//! [ICMP6RecvClient](trait.ICMP6RecvClient.html).
+//!
+//! Echo replies are sent with an `ICMP6Sender`, which usually shares its
+//! `IP6Sender` with UDP. If the `IP6Sender` is busy sending another packet,
+//! the echo request is dropped, as if it had been lost on the way.
+
+use kernel::common::cells::OptionalCell;
+use net::icmpv6::icmpv6::{ICMP6Header, ICMP6HeaderOptions, ICMP6Type};
+use net::icmpv6::icmpv6_send::ICMP6Sender;
+use net::ipv6::ip_utils::IPAddr;
+use net::ipv6::ipv6::IP6Header;
+use net::ipv6::ipv6_recv::IP6RecvClient;
+
+/// A trait for a client of an `ICMP6RecvStruct`.
+pub trait ICMP6RecvClient {
+    /// Called for each received ICMPv6 message that is not an echo request.
+    ///
+    /// # Arguments
+    ///
+    /// `src_addr` - The address of the sender
+    /// `icmp_header` - The ICMPv6 header of the message
+    /// `payload` - The body of the message, after the ICMPv6 header
+    fn receive(&self, src_addr: IPAddr, icmp_header: ICMP6Header, payload: &[u8]);
+}
+
+/// A struct that receives ICMPv6 packets and answers echo requests.
+pub struct ICMP6RecvStruct<'a> {
+    sender: OptionalCell<&'a ICMP6Sender<'a>>,
+    client: OptionalCell<&'a ICMP6RecvClient>,
+}
+
+impl ICMP6RecvStruct<'a> {
+    pub fn new() -> ICMP6RecvStruct<'a> {
+        ICMP6RecvStruct {
+            sender: OptionalCell::empty(),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Sets the `ICMP6Sender` used to answer echo requests. Without one,
+    /// echo requests are dropped.
+    pub fn set_sender(&self, sender: &'a ICMP6Sender<'a>) {
+        self.sender.set(sender);
+    }
+
+    pub fn set_client(&self, client: &'a ICMP6RecvClient) {
+        self.client.set(client);
+    }
+}
+
+impl IP6RecvClient for ICMP6RecvStruct<'a> {
+    fn receive(&self, ip_header: IP6Header, payload: &[u8]) {
+        let icmp_header = match ICMP6Header::decode(payload).done() {
+            Some((_, icmp_header)) => icmp_header,
+            None => return,
+        };
+        let body = &payload[icmp_header.get_hdr_size()..];
+
+        match icmp_header.get_options() {
+            ICMP6HeaderOptions::Type128 { id, seqno } => {
+                // The reply echoes the identifier, sequence number and data
+                // of the request.
+                let mut reply = ICMP6Header::new(ICMP6Type::Type129);
+                reply.set_options(ICMP6HeaderOptions::Type129 { id, seqno });
+                self.sender.map(|sender| {
+                    sender.send(ip_header.get_src_addr(), reply, body);
+                });
+            }
+            _ => {
+                self.client.map(|client| {
+                    client.receive(ip_header.get_src_addr(), icmp_header, body);
+                });
+            }
+        }
+    }
+}
@@ -45,7 +45,7 @@ pub trait ICMP6Sender<'a> {
     /// This function returns a code reporting either success or any
     /// synchronous errors. Note that any asynchronous errors are returned
     /// via the callback.
-    fn send(&self, dest: IPAddr, icmp_header: ICMP6Header, buf: &'a [u8]) -> ReturnCode;
+    fn send(&self, dest: IPAddr, icmp_header: ICMP6Header, buf: &[u8]) -> ReturnCode;
 }
 
 /// A struct that implements the `ICMP6Sender` trait.
@@ -68,7 +68,7 @@ impl<T: IP6Sender<'a>> ICMP6Sender<'a> for ICMP6SendStruct<'a, T> {
         self.client.set(client);
     }
 
-    fn send(&self, dest: IPAddr, mut icmp_header: ICMP6Header, buf: &'a [u8]) -> ReturnCode {
+    fn send(&self, dest: IPAddr, mut icmp_header: ICMP6Header, buf: &[u8]) -> ReturnCode {
         let total_len = buf.len() + icmp_header.get_hdr_size();
         icmp_header.set_len(total_len as u16);
         let transport_header = TransportHeader::ICMP(icmp_header);
@@ -4,6 +4,7 @@
 
 use net::icmpv6::icmpv6::{ICMP6Header, ICMP6HeaderOptions};
 use net::ipv6::ipv6::IP6Header;
+use net::tcp::tcp::TCPHeader;
 use net::udp::udp::UDPHeader;
 
 #[derive(Copy, Clone, PartialEq)]
@@ -192,6 +193,38 @@ pub fn compute_icmp_checksum(
     sum as u16
 }
 
+/// Computes the checksum of a TCP segment. `payload` is the data of the
+/// segment, after the header. As the header is never sent with options, any
+/// options of a received segment must be included in `payload`.
+pub fn compute_tcp_checksum(ip6_header: &IP6Header, tcp_header: &TCPHeader, payload: &[u8]) -> u16 {
+    let mut sum: u32 = 0;
+
+    // add ipv6 pseudo-header
+    sum += compute_ipv6_ph_sum(ip6_header);
+
+    // add tcp header
+    sum += tcp_header.src_port as u32;
+    sum += tcp_header.dst_port as u32;
+    sum += tcp_header.seq_num >> 16;
+    sum += tcp_header.seq_num & 0xffff;
+    sum += tcp_header.ack_num >> 16;
+    sum += tcp_header.ack_num & 0xffff;
+    sum += tcp_header.offset_and_control as u32;
+    sum += tcp_header.window as u32;
+    sum += tcp_header.cksum as u32;
+    sum += tcp_header.urg_ptr as u32;
+
+    // add tcp payload
+    sum += compute_sum(payload, payload.len() as u16);
+
+    // carry overflow
+    while sum > 0xffff {
+        sum = (sum >> 16) + (sum & 0xffff);
+    }
+
+    !sum as u16
+}
+
 pub fn compute_ipv6_ph_sum(ip6_header: &IP6Header) -> u32 {
     let mut sum: u32 = 0;
 
@@ -209,7 +242,7 @@ pub fn compute_ipv6_ph_sum(ip6_header: &IP6Header) -> u32 {
         i += 2;
     }
 
-    sum += ip6_header.payload_len as u32;
+    sum += ip6_header.get_payload_len() as u32;
     sum += ip6_header.next_header as u32;
 
     sum
@@ -221,7 +254,12 @@ pub fn compute_sum(buf: &[u8], len: u16) -> u32 {
     let mut i: usize = 0;
     while i < (len as usize) {
         let msb = (buf[i] as u32) << 8;
-        let lsb = buf[i + 1] as u32;
+        // An odd trailing byte is padded with zero
+        let lsb = if i + 1 < len as usize {
+            buf[i + 1] as u32
+        } else {
+            0
+        };
         sum += msb + lsb;
         i += 2;
     }
@@ -66,11 +66,12 @@
 
 use kernel::ReturnCode;
 use net::icmpv6::icmpv6::ICMP6Header;
-use net::ipv6::ip_utils::{compute_icmp_checksum, compute_udp_checksum, ip6_nh, IPAddr};
+use net::ipv6::ip_utils::{compute_icmp_checksum, compute_tcp_checksum, compute_udp_checksum};
+use net::ipv6::ip_utils::{ip6_nh, IPAddr};
 use net::stream::SResult;
 use net::stream::{decode_bytes, decode_u16, decode_u8};
 use net::stream::{encode_bytes, encode_u16, encode_u8};
-use net::tcp::TCPHeader;
+use net::tcp::tcp::{TCPHeader, TCP_HDR_LEN};
 use net::udp::udp::UDPHeader;
 
 pub const UDP_HDR_LEN: usize = 8;
@@ -293,6 +294,16 @@ impl IP6Header {
                 }
                 ReturnCode::SUCCESS
             }
+            ip6_nh::TCP => {
+                let checksum = match TCPHeader::decode(buf).done() {
+                    Some((_offset, hdr)) => compute_tcp_checksum(&self, &hdr, &buf[TCP_HDR_LEN..]),
+                    None => 0xffff, //Will be dropped, as ones comp -0 checksum is invalid
+                };
+                if checksum != 0 {
+                    return ReturnCode::FAIL; //Incorrect cksum
+                }
+                ReturnCode::SUCCESS
+            }
             _ => ReturnCode::ENOSUPPORT,
         }
     }
@@ -361,7 +372,12 @@ impl IPPayload<'a> {
                 self.header = transport_header;
                 (ip6_nh::ICMP, length)
             }
-            _ => (ip6_nh::NO_NEXT, payload.len() as u16),
+            TransportHeader::TCP(mut tcp_header) => {
+                let length = (payload.len() + tcp_header.get_hdr_size()) as u16;
+                tcp_header.set_len(length);
+                self.header = TransportHeader::TCP(tcp_header);
+                (ip6_nh::TCP, length)
+            }
         }
     }
 
@@ -380,9 +396,7 @@ impl IPPayload<'a> {
         let (offset, _) = match self.header {
             TransportHeader::UDP(udp_header) => udp_header.encode(buf, offset).done().unwrap(),
             TransportHeader::ICMP(icmp_header) => icmp_header.encode(buf, offset).done().unwrap(),
-            _ => {
-                unimplemented!();
-            }
+            TransportHeader::TCP(tcp_header) => tcp_header.encode(buf, offset).done().unwrap(),
         };
         let payload_length = self.get_payload_length();
         let offset = enc_consume!(buf, offset; encode_bytes, &self.payload[..payload_length]);
@@ -397,8 +411,8 @@ impl IPPayload<'a> {
             TransportHeader::ICMP(icmp_header) => {
                 icmp_header.get_len() as usize - icmp_header.get_hdr_size()
             }
-            _ => {
-                unimplemented!();
+            TransportHeader::TCP(tcp_header) => {
+                tcp_header.get_len() as usize - tcp_header.get_hdr_size()
             }
         }
     }
@@ -446,7 +460,7 @@ impl IP6Packet<'a> {
         let transport_hdr_size = match self.payload.header {
             TransportHeader::UDP(udp_hdr) => udp_hdr.get_hdr_size(),
             TransportHeader::ICMP(icmp_header) => icmp_header.get_hdr_size(),
-            _ => unimplemented!(),
+            TransportHeader::TCP(tcp_header) => tcp_header.get_hdr_size(),
         };
         40 + transport_hdr_size
     }
@@ -472,8 +486,11 @@ impl IP6Packet<'a> {
                 let cksum = compute_icmp_checksum(&self.header, &icmp_header, self.payload.payload);
                 icmp_header.set_cksum(cksum);
             }
-            _ => {
-                unimplemented!();
+            TransportHeader::TCP(ref mut tcp_header) => {
+                let length = tcp_header.get_len() as usize - tcp_header.get_hdr_size();
+                let payload = &self.payload.payload[..length];
+                let cksum = compute_tcp_checksum(&self.header, &tcp_header, payload);
+                tcp_header.set_cksum(cksum);
             }
         }
     }
@@ -498,7 +515,49 @@ impl IP6Packet<'a> {
         self.header.set_payload_len(payload_len);
     }
 
-    // TODO: Do we need a decode equivalent? I don't think so, but we might
+    /// This function sets the header and payload of the `IP6Packet` from an
+    /// encoded IPv6 packet, for example one received on another interface
+    /// that is to be forwarded. The transport checksum is kept as is.
+    ///
+    /// # Arguments
+    ///
+    /// `buf` - The serialized IPv6 packet
+    ///
+    /// # Return Value
+    ///
+    /// `SResult` - The length of the packet wrapped in an SResult. Only UDP,
+    /// TCP and ICMPv6 packets whose transport header can be decoded are
+    /// supported, and TCP segments with options are not. An error is also
+    /// returned if the payload does not fit into the `IPPayload`.
+    pub fn decode(&mut self, buf: &[u8]) -> SResult<usize> {
+        let (off, ip6_header) = dec_try!(buf; IP6Header::decode);
+        let len = off + ip6_header.get_payload_len() as usize;
+        stream_len_cond!(buf, len);
+        let transport = &buf[off..len];
+
+        let (hdr_size, transport_header) = match ip6_header.get_next_header() {
+            ip6_nh::UDP => {
+                let (hdr_size, udp_header) = dec_try!(transport; UDPHeader::decode);
+                (hdr_size, TransportHeader::UDP(udp_header))
+            }
+            ip6_nh::TCP => {
+                let (hdr_size, tcp_header) = dec_try!(transport; TCPHeader::decode);
+                stream_cond!(hdr_size == TCP_HDR_LEN);
+                (hdr_size, TransportHeader::TCP(tcp_header))
+            }
+            ip6_nh::ICMP => {
+                let (_, icmp_header) = dec_try!(transport; ICMP6Header::decode);
+                (icmp_header.get_hdr_size(), TransportHeader::ICMP(icmp_header))
+            }
+            _ => stream_err!(),
+        };
+        stream_cond!(hdr_size <= transport.len());
+        stream_cond!(transport.len() - hdr_size <= self.payload.payload.len());
+
+        self.header = ip6_header;
+        self.set_payload(transport_header, &transport[hdr_size..]);
+        stream_done!(len, len);
+    }
 
     pub fn encode(&self, buf: &mut [u8]) -> SResult<usize> {
         let ip6_header = self.header;
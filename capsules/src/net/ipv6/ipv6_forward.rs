@@ -0,0 +1,83 @@
+//! This file contains a simple IPv6 forwarder, which lets a device route
+//! packets between two network interfaces, for example to act as a border
+//! router between an 802.15.4 network and a host connected over SLIP.
+//!
+//! An `IP6Forwarder` is set as the receive client of one interface. Packets
+//! addressed to one of the local addresses of the device, or to a multicast
+//! address, are passed to its client, usually the `IP6RecvStruct` that is
+//! shared by all interfaces. Other packets are forwarded over the
+//! `IP6Sender` of the other interface, unless their hop limit is exhausted
+//! or their destination is link-local. ICMPv6 errors are not sent for
+//! dropped packets.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let radio_forwarder = static_init!(
+//!     capsules::net::ipv6::ipv6_forward::IP6Forwarder<'static>,
+//!     capsules::net::ipv6::ipv6_forward::IP6Forwarder::new(slip, &LOCAL_IP_IFACES)
+//! );
+//! let slip_forwarder = static_init!(
+//!     capsules::net::ipv6::ipv6_forward::IP6Forwarder<'static>,
+//!     capsules::net::ipv6::ipv6_forward::IP6Forwarder::new(ip_send, &LOCAL_IP_IFACES)
+//! );
+//! sixlowpan_state.set_rx_client(radio_forwarder);
+//! slip.set_receive_client(slip_forwarder);
+//! radio_forwarder.set_client(ip_receive);
+//! slip_forwarder.set_client(ip_receive);
+//! ```
+
+use kernel::common::cells::OptionalCell;
+use kernel::ReturnCode;
+use net::ipv6::ip_utils::IPAddr;
+use net::ipv6::ipv6::IP6Header;
+use net::ipv6::ipv6_send::IP6Sender;
+use net::sixlowpan::sixlowpan_state::SixlowpanRxClient;
+
+pub struct IP6Forwarder<'a> {
+    /// The interface that packets for other hosts are forwarded over.
+    next_iface: &'a IP6Sender<'a>,
+    /// The addresses of the device.
+    local_addrs: &'a [IPAddr],
+    client: OptionalCell<&'a SixlowpanRxClient>,
+}
+
+impl<'a> IP6Forwarder<'a> {
+    pub fn new(next_iface: &'a IP6Sender<'a>, local_addrs: &'a [IPAddr]) -> IP6Forwarder<'a> {
+        IP6Forwarder {
+            next_iface: next_iface,
+            local_addrs: local_addrs,
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Sets the client that receives packets for this device.
+    pub fn set_client(&self, client: &'a SixlowpanRxClient) {
+        self.client.set(client);
+    }
+
+    fn is_local(&self, addr: IPAddr) -> bool {
+        addr.is_multicast() || self.local_addrs.iter().any(|local| *local == addr)
+    }
+}
+
+impl<'a> SixlowpanRxClient for IP6Forwarder<'a> {
+    fn receive(&self, buf: &[u8], len: usize, result: ReturnCode) {
+        if len > buf.len() || result != ReturnCode::SUCCESS {
+            return;
+        }
+        let ip6_header = match IP6Header::decode(&buf[..len]).done() {
+            Some((_, ip6_header)) => ip6_header,
+            None => return,
+        };
+        let dst_addr = ip6_header.get_dst_addr();
+        if self.is_local(dst_addr) {
+            self.client.map(|client| client.receive(buf, len, result));
+        } else if !dst_addr.is_unicast_link_local() && ip6_header.get_hop_limit() > 1 {
+            // A packet that cannot be sent now is dropped, as if it were lost
+            // on the link.
+            let _ = self.next_iface.forward(&buf[..len]);
+        }
+    }
+}
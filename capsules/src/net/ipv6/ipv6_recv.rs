@@ -1,5 +1,6 @@
 use kernel::common::cells::OptionalCell;
 use kernel::ReturnCode;
+use net::ipv6::ip_utils::ip6_nh;
 use net::ipv6::ipv6::IP6Header;
 use net::sixlowpan::sixlowpan_state::SixlowpanRxClient;
 
@@ -20,8 +21,11 @@ use net::sixlowpan::sixlowpan_state::SixlowpanRxClient;
 - The udp_mac MacUser has a single receive client, which is the `sixlowpan_state` struct
 - `sixlowpan_state` has a single rx_client, which in our case is a single struct that
   implements the `ip_receive ` trait.
-- the `ip_receive` implementing struct (`IP6RecvStruct`) has a single client, which is
-  udp_recv, a `UDPReceive` struct.
+- the `ip_receive` implementing struct (`IP6RecvStruct`) passes ICMPv6 packets to its
+  ICMPv6 client, icmp_recv, an `ICMP6RecvStruct` struct that answers echo requests,
+  TCP packets to its TCP client, a `TCPSocketStruct` that implements a single
+  TCP connection, and all other packets to its client, udp_recv, a `UDPReceive`
+  struct.
 - The UDPReceive struct is a field of the UDPDriver, which ultimately passes the
   packets up to userland.
 */
@@ -37,23 +41,43 @@ pub trait IP6RecvClient {
 /// The receiver should drop any packets with destination addresses
 /// that are not among the local addresses of this device.
 pub trait IP6Receiver<'a> {
+    /// Set the client that receives all packets other than ICMPv6 and TCP
+    /// packets.
     fn set_client(&self, client: &'a IP6RecvClient);
+
+    /// Set the client that receives ICMPv6 packets.
+    fn set_icmp_client(&self, client: &'a IP6RecvClient);
+
+    /// Set the client that receives TCP packets.
+    fn set_tcp_client(&self, client: &'a IP6RecvClient);
 }
 
 pub struct IP6RecvStruct<'a> {
     client: OptionalCell<&'a IP6RecvClient>,
+    icmp_client: OptionalCell<&'a IP6RecvClient>,
+    tcp_client: OptionalCell<&'a IP6RecvClient>,
 }
 
 impl<'a> IP6Receiver<'a> for IP6RecvStruct<'a> {
     fn set_client(&self, client: &'a IP6RecvClient) {
         self.client.set(client);
     }
+
+    fn set_icmp_client(&self, client: &'a IP6RecvClient) {
+        self.icmp_client.set(client);
+    }
+
+    fn set_tcp_client(&self, client: &'a IP6RecvClient) {
+        self.tcp_client.set(client);
+    }
 }
 
 impl<'a> IP6RecvStruct<'a> {
     pub fn new() -> IP6RecvStruct<'a> {
         IP6RecvStruct {
             client: OptionalCell::empty(),
+            icmp_client: OptionalCell::empty(),
+            tcp_client: OptionalCell::empty(),
         }
     }
 }
@@ -71,11 +95,15 @@ impl<'a> SixlowpanRxClient for IP6RecvStruct<'a> {
                     debug!("dropped!: {:?}", checksum_result);
                     return; //Dropped.
                 }
-                // Note: Protocols for which checksum verification is not implemented (TCP, etc.)
+                // Note: Protocols for which checksum verification is not implemented
                 // are automatically assumed as fine, rather than dropped
 
-                self.client
-                    .map(|client| client.receive(ip6_header, &buf[offset..len]));
+                let client = match ip6_header.get_next_header() {
+                    ip6_nh::ICMP => &self.icmp_client,
+                    ip6_nh::TCP => &self.tcp_client,
+                    _ => &self.client,
+                };
+                client.map(|client| client.receive(ip6_header, &buf[offset..len]));
             }
             None => {
                 // TODO: Report the error somewhere...
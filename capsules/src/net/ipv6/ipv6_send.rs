@@ -5,7 +5,8 @@
 //! when a transmission has completed.
 //!
 //! This file also includes an implementation of the `IP6Sender` trait, which
-//! sends an IPv6 packet using 6LoWPAN.
+//! sends an IPv6 packet using 6LoWPAN. An `IP6Sender` is the sending side of
+//! a network interface; the SLIP interface in `net::slip` is another one.
 
 // Additional Work and Known Problems
 // ----------------------------------
@@ -24,8 +25,12 @@ use kernel::ReturnCode;
 use net::ieee802154::MacAddress;
 use net::ipv6::ip_utils::IPAddr;
 use net::ipv6::ipv6::{IP6Header, IP6Packet, TransportHeader};
+use net::sixlowpan::sixlowpan_compression;
 use net::sixlowpan::sixlowpan_state::TxState;
 
+/// The 802.15.4 short address that all devices on the PAN receive.
+const BROADCAST_MAC_ADDR: u16 = 0xffff;
+
 /// This trait must be implemented by upper layers in order to receive
 /// the `send_done` callback when a transmission has completed. The upper
 /// layer must then call `IP6Sender.set_client` in order to receive this
@@ -78,6 +83,14 @@ pub trait IP6Sender<'a> {
     /// `payload` - The transport payload for the packet being sent
     fn send_to(&self, dst: IPAddr, transport_header: TransportHeader, payload: &[u8])
         -> ReturnCode;
+
+    /// This method sends an encoded IPv6 packet received on another
+    /// interface, with its hop limit decremented. The caller checks that the
+    /// hop limit is greater than 1.
+    ///
+    /// # Arguments
+    /// `packet` - The serialized IPv6 packet to forward
+    fn forward(&self, packet: &[u8]) -> ReturnCode;
 }
 
 /// This struct is a specific implementation of the `IP6Sender` trait. This
@@ -93,8 +106,9 @@ pub struct IP6SendStruct<'a, A: time::Alarm> {
     tx_buf: TakeCell<'static, [u8]>,
     sixlowpan: TxState<'a>,
     radio: &'a MacDevice<'a>,
-    dst_mac_addr: MacAddress,
     src_mac_addr: MacAddress,
+    /// Whether a packet is being sent, which takes several fragments.
+    busy: Cell<bool>,
     client: OptionalCell<&'a IP6SendClient>,
 }
 
@@ -122,14 +136,53 @@ impl<A: time::Alarm> IP6Sender<'a> for IP6SendStruct<'a, A> {
         transport_header: TransportHeader,
         payload: &[u8],
     ) -> ReturnCode {
+        // Another packet would overwrite the one whose fragments are being
+        // sent.
+        if self.busy.get() {
+            return ReturnCode::EBUSY;
+        }
+        self.busy.set(true);
         self.sixlowpan.init(
             self.src_mac_addr,
-            self.dst_mac_addr,
+            self.next_hop(dst),
             self.radio.get_pan(),
             None,
         );
         self.init_packet(dst, transport_header, payload);
         let ret = self.send_next_fragment();
+        if ret != ReturnCode::SUCCESS {
+            self.busy.set(false);
+        }
+        ret
+    }
+
+    fn forward(&self, packet: &[u8]) -> ReturnCode {
+        if self.busy.get() {
+            return ReturnCode::EBUSY;
+        }
+        // 6LoWPAN compresses the packet from its decoded form.
+        let dst = self.ip6_packet.map_or(None, |ip6_packet| {
+            ip6_packet.decode(packet).done().map(|_| {
+                let hop_limit = ip6_packet.header.get_hop_limit();
+                ip6_packet.header.set_hop_limit(hop_limit.saturating_sub(1));
+                ip6_packet.header.get_dst_addr()
+            })
+        });
+        let dst = match dst {
+            Some(dst) => dst,
+            None => return ReturnCode::EINVAL,
+        };
+        self.busy.set(true);
+        self.sixlowpan.init(
+            self.src_mac_addr,
+            self.next_hop(dst),
+            self.radio.get_pan(),
+            None,
+        );
+        let ret = self.send_next_fragment();
+        if ret != ReturnCode::SUCCESS {
+            self.busy.set(false);
+        }
         ret
     }
 }
@@ -152,12 +205,27 @@ impl<A: time::Alarm> IP6SendStruct<'a, A> {
             tx_buf: TakeCell::new(tx_buf),
             sixlowpan: sixlowpan,
             radio: radio,
-            dst_mac_addr: dst_mac_addr,
             src_mac_addr: src_mac_addr,
+            busy: Cell::new(false),
             client: OptionalCell::empty(),
         }
     }
 
+    /// The MAC address to send a packet for `dst_addr` to. Multicast packets
+    /// are broadcast, and link-local addresses are derived from the MAC
+    /// address of their interface. Everything else goes to the gateway.
+    fn next_hop(&self, dst_addr: IPAddr) -> MacAddress {
+        if dst_addr.is_multicast() {
+            MacAddress::Short(BROADCAST_MAC_ADDR)
+        } else if dst_addr.is_unicast_link_local() {
+            let mut iid = [0; 8];
+            iid.copy_from_slice(&dst_addr.0[8..16]);
+            sixlowpan_compression::compute_mac_addr(&iid)
+        } else {
+            self.gateway.get()
+        }
+    }
+
     fn init_packet(&self, dst_addr: IPAddr, transport_header: TransportHeader, payload: &[u8]) {
         self.ip6_packet.map(|ip6_packet| {
             ip6_packet.header = IP6Header::default();
@@ -214,6 +282,7 @@ impl<A: time::Alarm> IP6SendStruct<'a, A> {
     }
 
     fn send_completed(&self, result: ReturnCode) {
+        self.busy.set(false);
         self.client.map(move |client| client.send_done(result));
     }
 }
@@ -1,4 +1,5 @@
 pub mod ip_utils;
 pub mod ipv6;
+pub mod ipv6_forward;
 pub mod ipv6_recv;
 pub mod ipv6_send;
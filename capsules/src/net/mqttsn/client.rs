@@ -0,0 +1,630 @@
+//! An MQTT-SN client that talks to a gateway over UDP.
+//!
+//! `MQTTSNStruct` implements the client side of MQTT-SN version 1.2: it
+//! connects to a gateway with a clean session and without a will, registers
+//! topic names, publishes with QoS 0 and 1, subscribes to topic names with
+//! QoS 0 and 1, keeps the connection alive with PINGREQ messages, and
+//! disconnects.
+//!
+//! MQTT-SN has at most one request waiting for its acknowledgement from the
+//! gateway, so the client only accepts a new request once the last one is
+//! acknowledged. A request that is not acknowledged is sent again every
+//! `RETRY_MS`, and if none of `MAX_RETRIES` retransmissions is acknowledged,
+//! the request fails and the client assumes that the gateway is gone, as
+//! the specification requires. A datagram that cannot be sent because the
+//! IPv6 sender is busy is treated the same way as one that is lost.
+//!
+//! Messages are sent through a `UDPSender` that can be shared with the UDP
+//! driver, whose client then also receives their `send_done` callbacks.
+//! Received UDP datagrams that do not come from the gateway to the local
+//! port of the client are passed on to the client set with
+//! `set_next_client`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! static mut MQTTSN_BUF: [u8; 128] = [0; 128];
+//!
+//! let mqttsn = static_init!(
+//!     capsules::net::mqttsn::client::MQTTSNStruct<
+//!         'static,
+//!         VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+//!     >,
+//!     capsules::net::mqttsn::client::MQTTSNStruct::new(
+//!         udp_send,
+//!         mqttsn_virtual_alarm,
+//!         &mut MQTTSN_BUF
+//!     )
+//! );
+//! mqttsn_virtual_alarm.set_client(mqttsn);
+//! mqttsn.set_next_client(udp_driver);
+//! udp_recv.set_client(mqttsn);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::ReturnCode;
+use net::ipv6::ip_utils::IPAddr;
+use net::mqttsn::message::{self, flags, return_code, Message, MAX_CLIENT_ID_LEN};
+use net::stream::SResult;
+use net::udp::udp_recv::UDPRecvClient;
+use net::udp::udp_send::UDPSender;
+
+/// How long to wait for the acknowledgement of a request before sending it
+/// again (T_retry).
+const RETRY_MS: u32 = 10_000;
+
+/// How many times a request is sent again before giving up (N_retry).
+const MAX_RETRIES: usize = 3;
+
+/// Length of the acknowledgements sent by the client.
+const ACK_LEN: usize = 7;
+
+/// The `MQTTSNClient` trait is implemented by the user of an `MQTTSN` client
+/// to receive the acknowledgements of its requests, and the messages that
+/// the gateway publishes to it.
+pub trait MQTTSNClient {
+    /// Called once the gateway accepts or rejects the connection, or the
+    /// connection attempt times out.
+    fn connected(&self, result: ReturnCode);
+
+    /// Called once the gateway registers a topic name requested with
+    /// `register`, with the ID the topic is published with.
+    fn registered(&self, result: ReturnCode, topic_id: u16);
+
+    /// Called once a QoS 1 message is acknowledged by the gateway.
+    fn published(&self, result: ReturnCode);
+
+    /// Called once the gateway accepts or rejects a subscription. The topic
+    /// ID is 0 for topic names with wildcards, whose topics are registered
+    /// by the gateway before it publishes to them.
+    fn subscribed(&self, result: ReturnCode, topic_id: u16);
+
+    /// Called when the gateway registers the ID of a topic that matches a
+    /// subscription.
+    fn topic_registered(&self, topic_id: u16, topic_name: &[u8]);
+
+    /// Called when the gateway publishes `data` to a subscribed topic.
+    fn received(&self, topic_id: u16, data: &[u8]);
+
+    /// Called once the client is disconnected, with `FAIL` if the gateway
+    /// closed the connection or stopped responding.
+    fn disconnected(&self, result: ReturnCode);
+}
+
+/// The interface of an MQTT-SN client. All requests except for QoS 0
+/// publishes are completed with a callback to the `MQTTSNClient`.
+pub trait MQTTSN<'a> {
+    fn set_client(&self, client: &'a MQTTSNClient);
+
+    /// Connects to the gateway at `gateway` and `gateway_port` from
+    /// `local_port`. The gateway considers the client gone if it does not
+    /// hear from it for `keep_alive` seconds, 0 meaning forever. Returns
+    /// `EALREADY` if the client is connected or connecting, and `EINVAL` if
+    /// a port is 0 or the client ID is empty or longer than 23 bytes.
+    fn connect(
+        &self,
+        gateway: IPAddr,
+        gateway_port: u16,
+        local_port: u16,
+        client_id: &[u8],
+        keep_alive: u16,
+    ) -> ReturnCode;
+
+    /// Requests a topic ID to publish `topic_name` with.
+    fn register(&self, topic_name: &[u8]) -> ReturnCode;
+
+    /// Publishes `data` to the topic `topic_id` with QoS 0 or 1. QoS 0
+    /// messages are not acknowledged, so the return value is the only
+    /// result for them.
+    fn publish(&self, topic_id: u16, qos: u8, data: &[u8]) -> ReturnCode;
+
+    /// Subscribes to `topic_name`, which may contain wildcards, with QoS 0
+    /// or 1.
+    fn subscribe(&self, topic_name: &[u8], qos: u8) -> ReturnCode;
+
+    /// Disconnects from the gateway.
+    fn disconnect(&self) -> ReturnCode;
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum State {
+    Disconnected,
+    Connecting,
+    Connected,
+    Disconnecting,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Request {
+    Connect,
+    Register,
+    Publish,
+    Subscribe,
+    Ping,
+    Disconnect,
+}
+
+/// A request waiting for its acknowledgement. The encoded request is kept
+/// in the transmit buffer.
+#[derive(Copy, Clone, Debug)]
+struct Pending {
+    request: Request,
+    msg_id: u16,
+    len: usize,
+    retries: usize,
+}
+
+pub struct MQTTSNStruct<'a, A: Alarm + 'a> {
+    udp_send: &'a UDPSender<'a>,
+    alarm: &'a A,
+    tx_buf: TakeCell<'static, [u8]>,
+    state: Cell<State>,
+    pending: Cell<Option<Pending>>,
+    gateway: Cell<IPAddr>,
+    gateway_port: Cell<u16>,
+    local_port: Cell<u16>,
+    /// Keep alive duration in seconds.
+    keep_alive: Cell<u16>,
+    /// The last message ID used.
+    msg_id: Cell<u16>,
+    next_client: OptionalCell<&'a UDPRecvClient>,
+    client: OptionalCell<&'a MQTTSNClient>,
+}
+
+impl<A: Alarm> MQTTSNStruct<'a, A> {
+    pub fn new(
+        udp_send: &'a UDPSender<'a>,
+        alarm: &'a A,
+        tx_buf: &'static mut [u8],
+    ) -> MQTTSNStruct<'a, A> {
+        MQTTSNStruct {
+            udp_send: udp_send,
+            alarm: alarm,
+            tx_buf: TakeCell::new(tx_buf),
+            state: Cell::new(State::Disconnected),
+            pending: Cell::new(None),
+            gateway: Cell::new(IPAddr::new()),
+            gateway_port: Cell::new(0),
+            local_port: Cell::new(0),
+            keep_alive: Cell::new(0),
+            msg_id: Cell::new(0),
+            next_client: OptionalCell::empty(),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Sets the client that receives UDP datagrams that are not MQTT-SN
+    /// messages from the gateway.
+    pub fn set_next_client(&self, client: &'a UDPRecvClient) {
+        self.next_client.set(client);
+    }
+
+    fn set_timeout(&self, ms: u32) {
+        let tics = A::Frequency::frequency() / 1000 * ms;
+        self.alarm.set_alarm(self.alarm.now().wrapping_add(tics));
+    }
+
+    fn next_msg_id(&self) -> u16 {
+        // Message ID 0 is reserved for messages without acknowledgement.
+        let msg_id = match self.msg_id.get().wrapping_add(1) {
+            0 => 1,
+            msg_id => msg_id,
+        };
+        self.msg_id.set(msg_id);
+        msg_id
+    }
+
+    fn transmit(&self, buf: &[u8]) -> ReturnCode {
+        self.udp_send.send_to(
+            self.gateway.get(),
+            self.gateway_port.get(),
+            self.local_port.get(),
+            buf,
+        )
+    }
+
+    /// Sends a message that needs no acknowledgement.
+    fn send_message(&self, message: Message) -> ReturnCode {
+        let mut buf = [0; ACK_LEN];
+        match message.encode(&mut buf) {
+            SResult::Done(len, ()) => self.transmit(&buf[..len]),
+            _ => ReturnCode::ESIZE,
+        }
+    }
+
+    /// Sends a request, and waits for its acknowledgement. Returns `EBUSY` if
+    /// another request is waiting for its acknowledgement, and `ESIZE` if
+    /// the request does not fit into the transmit buffer.
+    fn send_request(&self, request: Request, msg_id: u16, message: Message) -> ReturnCode {
+        if self.pending.get().is_some() {
+            return ReturnCode::EBUSY;
+        }
+        let result = self.tx_buf.map_or(ReturnCode::ENOMEM, |buf| {
+            match message.encode(buf) {
+                SResult::Done(len, ()) => {
+                    // A request that cannot be sent now is sent again later.
+                    let _ = self.transmit(&buf[..len]);
+                    self.pending.set(Some(Pending {
+                        request: request,
+                        msg_id: msg_id,
+                        len: len,
+                        retries: 0,
+                    }));
+                    ReturnCode::SUCCESS
+                }
+                _ => ReturnCode::ESIZE,
+            }
+        });
+        if result == ReturnCode::SUCCESS {
+            self.set_timeout(RETRY_MS);
+        }
+        result
+    }
+
+    /// Takes the pending request if it is a `request` with `msg_id`, which
+    /// an acknowledgement from the gateway answers.
+    fn acknowledge(&self, request: Request, msg_id: u16) -> bool {
+        match self.pending.get() {
+            Some(pending) if pending.request == request && pending.msg_id == msg_id => {
+                self.pending.set(None);
+                self.idle();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Waits until the next PINGREQ is due, if connected.
+    fn idle(&self) {
+        let keep_alive = self.keep_alive.get() as u32;
+        if self.state.get() == State::Connected && keep_alive > 0 {
+            self.set_timeout(keep_alive * 1000);
+        } else {
+            self.alarm.disable();
+        }
+    }
+
+    fn close(&self) {
+        self.state.set(State::Disconnected);
+        self.pending.set(None);
+        self.alarm.disable();
+    }
+
+    /// Gives up on the pending request once it is sent `MAX_RETRIES` times
+    /// without being acknowledged.
+    fn give_up(&self, request: Request) {
+        self.close();
+        self.client.map(|client| match request {
+            Request::Connect => client.connected(ReturnCode::FAIL),
+            Request::Disconnect => client.disconnected(ReturnCode::SUCCESS),
+            Request::Register => client.registered(ReturnCode::FAIL, 0),
+            Request::Publish => client.published(ReturnCode::FAIL),
+            Request::Subscribe => client.subscribed(ReturnCode::FAIL, 0),
+            Request::Ping => {}
+        });
+        match request {
+            Request::Connect | Request::Disconnect => {}
+            _ => {
+                self.client.map(|client| client.disconnected(ReturnCode::FAIL));
+            }
+        }
+    }
+
+    fn receive_message(&self, message: Message) {
+        match message {
+            Message::ConnAck { return_code } => {
+                if self.state.get() != State::Connecting
+                    || !self.acknowledge(Request::Connect, 0)
+                {
+                    return;
+                }
+                let result = message::to_result(return_code);
+                if result == ReturnCode::SUCCESS {
+                    self.state.set(State::Connected);
+                    self.idle();
+                } else {
+                    self.close();
+                }
+                self.client.map(|client| client.connected(result));
+            }
+            Message::RegAck {
+                topic_id,
+                msg_id,
+                return_code,
+            } => {
+                if self.acknowledge(Request::Register, msg_id) {
+                    let result = message::to_result(return_code);
+                    self.client.map(|client| client.registered(result, topic_id));
+                }
+            }
+            Message::PubAck {
+                msg_id,
+                return_code,
+                ..
+            } => {
+                if self.acknowledge(Request::Publish, msg_id) {
+                    let result = message::to_result(return_code);
+                    self.client.map(|client| client.published(result));
+                }
+            }
+            Message::SubAck {
+                topic_id,
+                msg_id,
+                return_code,
+                ..
+            } => {
+                if self.acknowledge(Request::Subscribe, msg_id) {
+                    let result = message::to_result(return_code);
+                    self.client.map(|client| client.subscribed(result, topic_id));
+                }
+            }
+            Message::PingResp => {
+                self.acknowledge(Request::Ping, 0);
+            }
+            Message::Register {
+                topic_id,
+                msg_id,
+                topic_name,
+            } => {
+                if self.state.get() != State::Connected {
+                    return;
+                }
+                let _ = self.send_message(Message::RegAck {
+                    topic_id: topic_id,
+                    msg_id: msg_id,
+                    return_code: return_code::ACCEPTED,
+                });
+                self.client
+                    .map(|client| client.topic_registered(topic_id, topic_name));
+            }
+            Message::Publish {
+                flags,
+                topic_id,
+                msg_id,
+                data,
+            } => {
+                if self.state.get() != State::Connected {
+                    return;
+                }
+                // Subscriptions are at most QoS 1, so the gateway does not
+                // publish with QoS 2.
+                match flags::get_qos(flags) {
+                    0 => {}
+                    1 => {
+                        let _ = self.send_message(Message::PubAck {
+                            topic_id: topic_id,
+                            msg_id: msg_id,
+                            return_code: return_code::ACCEPTED,
+                        });
+                    }
+                    _ => return,
+                }
+                self.client.map(|client| client.received(topic_id, data));
+            }
+            Message::PingReq => {
+                let _ = self.send_message(Message::PingResp);
+            }
+            Message::Disconnect => match self.state.get() {
+                State::Disconnected => {}
+                State::Connecting => {
+                    self.close();
+                    self.client.map(|client| client.connected(ReturnCode::FAIL));
+                }
+                State::Connected => {
+                    self.close();
+                    self.client
+                        .map(|client| client.disconnected(ReturnCode::FAIL));
+                }
+                State::Disconnecting => {
+                    self.close();
+                    self.client
+                        .map(|client| client.disconnected(ReturnCode::SUCCESS));
+                }
+            },
+            Message::Connect { .. } | Message::Subscribe { .. } => {}
+        }
+    }
+}
+
+impl<A: Alarm> MQTTSN<'a> for MQTTSNStruct<'a, A> {
+    fn set_client(&self, client: &'a MQTTSNClient) {
+        self.client.set(client);
+    }
+
+    fn connect(
+        &self,
+        gateway: IPAddr,
+        gateway_port: u16,
+        local_port: u16,
+        client_id: &[u8],
+        keep_alive: u16,
+    ) -> ReturnCode {
+        if self.state.get() != State::Disconnected {
+            return ReturnCode::EALREADY;
+        }
+        if gateway_port == 0
+            || local_port == 0
+            || client_id.len() == 0
+            || client_id.len() > MAX_CLIENT_ID_LEN
+        {
+            return ReturnCode::EINVAL;
+        }
+        self.gateway.set(gateway);
+        self.gateway_port.set(gateway_port);
+        self.local_port.set(local_port);
+        self.keep_alive.set(keep_alive);
+        let result = self.send_request(
+            Request::Connect,
+            0,
+            Message::Connect {
+                flags: flags::CLEAN_SESSION,
+                duration: keep_alive,
+                client_id: client_id,
+            },
+        );
+        if result == ReturnCode::SUCCESS {
+            self.state.set(State::Connecting);
+        }
+        result
+    }
+
+    fn register(&self, topic_name: &[u8]) -> ReturnCode {
+        if self.state.get() != State::Connected {
+            return ReturnCode::ERESERVE;
+        }
+        if self.pending.get().is_some() {
+            return ReturnCode::EBUSY;
+        }
+        let msg_id = self.next_msg_id();
+        self.send_request(
+            Request::Register,
+            msg_id,
+            Message::Register {
+                topic_id: 0,
+                msg_id: msg_id,
+                topic_name: topic_name,
+            },
+        )
+    }
+
+    fn publish(&self, topic_id: u16, qos: u8, data: &[u8]) -> ReturnCode {
+        if self.state.get() != State::Connected {
+            return ReturnCode::ERESERVE;
+        }
+        if self.pending.get().is_some() {
+            return ReturnCode::EBUSY;
+        }
+        let flags = flags::qos(qos) | flags::TOPIC_NORMAL;
+        match qos {
+            0 => {
+                let result = self.tx_buf.map_or(ReturnCode::ENOMEM, |buf| {
+                    let message = Message::Publish {
+                        flags: flags,
+                        topic_id: topic_id,
+                        msg_id: 0,
+                        data: data,
+                    };
+                    match message.encode(buf) {
+                        SResult::Done(len, ()) => self.transmit(&buf[..len]),
+                        _ => ReturnCode::ESIZE,
+                    }
+                });
+                if result == ReturnCode::SUCCESS {
+                    self.idle();
+                }
+                result
+            }
+            1 => {
+                let msg_id = self.next_msg_id();
+                self.send_request(
+                    Request::Publish,
+                    msg_id,
+                    Message::Publish {
+                        flags: flags,
+                        topic_id: topic_id,
+                        msg_id: msg_id,
+                        data: data,
+                    },
+                )
+            }
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn subscribe(&self, topic_name: &[u8], qos: u8) -> ReturnCode {
+        if self.state.get() != State::Connected {
+            return ReturnCode::ERESERVE;
+        }
+        if self.pending.get().is_some() {
+            return ReturnCode::EBUSY;
+        }
+        if qos > 1 {
+            return ReturnCode::ENOSUPPORT;
+        }
+        let msg_id = self.next_msg_id();
+        self.send_request(
+            Request::Subscribe,
+            msg_id,
+            Message::Subscribe {
+                flags: flags::qos(qos) | flags::TOPIC_NORMAL,
+                msg_id: msg_id,
+                topic_name: topic_name,
+            },
+        )
+    }
+
+    fn disconnect(&self) -> ReturnCode {
+        if self.state.get() != State::Connected {
+            return ReturnCode::ERESERVE;
+        }
+        // A keep alive ping is no longer needed.
+        match self.pending.get() {
+            Some(pending) if pending.request != Request::Ping => return ReturnCode::EBUSY,
+            _ => self.pending.set(None),
+        }
+        let result = self.send_request(Request::Disconnect, 0, Message::Disconnect);
+        if result == ReturnCode::SUCCESS {
+            self.state.set(State::Disconnecting);
+        } else {
+            self.idle();
+        }
+        result
+    }
+}
+
+impl<A: Alarm> time::Client for MQTTSNStruct<'a, A> {
+    fn fired(&self) {
+        match self.pending.get() {
+            Some(pending) if pending.retries < MAX_RETRIES => {
+                self.pending.set(Some(Pending {
+                    retries: pending.retries + 1,
+                    ..pending
+                }));
+                self.tx_buf.map(|buf| {
+                    if pending.request == Request::Publish
+                        || pending.request == Request::Subscribe
+                    {
+                        message::set_dup(buf);
+                    }
+                    let _ = self.transmit(&buf[..pending.len]);
+                });
+                self.set_timeout(RETRY_MS);
+            }
+            Some(pending) => self.give_up(pending.request),
+            None => {
+                if self.state.get() == State::Connected {
+                    let _ = self.send_request(Request::Ping, 0, Message::PingReq);
+                }
+            }
+        }
+    }
+}
+
+impl<A: Alarm> UDPRecvClient for MQTTSNStruct<'a, A> {
+    fn receive(
+        &self,
+        src_addr: IPAddr,
+        dst_addr: IPAddr,
+        src_port: u16,
+        dst_port: u16,
+        payload: &[u8],
+    ) {
+        if self.state.get() == State::Disconnected
+            || dst_port != self.local_port.get()
+            || src_port != self.gateway_port.get()
+            || src_addr.0 != self.gateway.get().0
+        {
+            self.next_client.map(|client| {
+                client.receive(src_addr, dst_addr, src_port, dst_port, payload);
+            });
+            return;
+        }
+        match Message::decode(payload).done() {
+            Some((_, message)) => self.receive_message(message),
+            None => {}
+        }
+    }
+}
@@ -0,0 +1,305 @@
+//! MQTT-SN userspace interface.
+//!
+//! Implements a userspace interface for connecting to an MQTT-SN gateway,
+//! registering topics, publishing to them and subscribing to them. The
+//! kernel has a single `MQTTSN` client, which belongs to one process from
+//! the moment it connects until it is disconnected.
+
+use core::cell::Cell;
+use core::{cmp, mem};
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+use net::ipv6::ip_utils::IPAddr;
+use net::mqttsn::client::{MQTTSNClient, MQTTSN};
+
+/// Syscall number
+pub const DRIVER_NUM: usize = 0x30004;
+
+/// Length of the gateway endpoint at the start of the configuration buffer:
+/// an IPv6 address followed by a port in network byte order.
+const ENDPOINT_LEN: usize = mem::size_of::<IPAddr>() + 2;
+
+/// Events passed to the receive callback.
+mod rx_event {
+    pub const PUBLISH: usize = 0;
+    pub const TOPIC_REGISTERED: usize = 1;
+}
+
+/// Requests passed to the request callback.
+mod request {
+    pub const CONNECT: usize = 0;
+    pub const REGISTER: usize = 1;
+    pub const PUBLISH: usize = 2;
+    pub const SUBSCRIBE: usize = 3;
+    pub const DISCONNECT: usize = 4;
+}
+
+#[derive(Default)]
+pub struct App {
+    rx_callback: Option<Callback>,
+    request_callback: Option<Callback>,
+    app_read: Option<AppSlice<Shared, u8>>,
+    app_write: Option<AppSlice<Shared, u8>>,
+    app_cfg: Option<AppSlice<Shared, u8>>,
+}
+
+pub struct MQTTSNDriver<'a> {
+    /// The client shared by all apps
+    mqttsn: &'a MQTTSN<'a>,
+
+    /// Grant of apps that use this driver.
+    apps: Grant<App>,
+    /// ID of the app that the client belongs to.
+    owner: Cell<Option<AppId>>,
+}
+
+impl<'a> MQTTSNDriver<'a> {
+    pub fn new(mqttsn: &'a MQTTSN<'a>, grant: Grant<App>) -> MQTTSNDriver<'a> {
+        MQTTSNDriver {
+            mqttsn: mqttsn,
+            apps: grant,
+            owner: Cell::new(None),
+        }
+    }
+
+    /// Utility function to perform an action on an app in a system call.
+    #[inline]
+    fn do_with_app<F>(&self, appid: AppId, closure: F) -> ReturnCode
+    where
+        F: FnOnce(&mut App) -> ReturnCode,
+    {
+        self.apps
+            .enter(appid, |app, _| closure(app))
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// Utility function to perform an action on the app that the client
+    /// belongs to.
+    #[inline]
+    fn do_with_owner<F>(&self, closure: F)
+    where
+        F: FnOnce(&mut App),
+    {
+        self.owner.get().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| closure(app));
+        });
+    }
+
+    /// Performs a request of the owner with the first `len` bytes of its
+    /// write buffer.
+    fn do_with_write<F>(&self, appid: AppId, len: usize, request: F) -> ReturnCode
+    where
+        F: FnOnce(&[u8]) -> ReturnCode,
+    {
+        if self.owner.get() != Some(appid) {
+            return ReturnCode::ERESERVE;
+        }
+        self.do_with_app(appid, |app| {
+            app.app_write.as_ref().map_or(ReturnCode::EINVAL, |buf| {
+                if len > buf.len() {
+                    return ReturnCode::EINVAL;
+                }
+                request(&buf.as_ref()[..len])
+            })
+        })
+    }
+
+    /// Copies `data` into the read buffer of the owner, and tells it about
+    /// `event`.
+    fn deliver(&self, event: usize, topic_id: u16, data: &[u8]) {
+        self.do_with_owner(|app| {
+            let mut len = 0;
+            app.app_read.as_mut().map(|rbuf| {
+                let rbuf = rbuf.as_mut();
+                len = cmp::min(rbuf.len(), data.len());
+                rbuf[..len].copy_from_slice(&data[..len]);
+            });
+            app.rx_callback
+                .map(|mut cb| cb.schedule(event, len, topic_id as usize));
+        });
+    }
+
+    fn request_done(&self, request: usize, result: ReturnCode, topic_id: u16) {
+        self.do_with_owner(|app| {
+            app.request_callback
+                .map(|mut cb| cb.schedule(request, result.into(), topic_id as usize));
+        });
+    }
+
+    #[inline]
+    fn parse_endpoint(&self, buf: &[u8]) -> (IPAddr, u16) {
+        let (a, p) = buf.split_at(mem::size_of::<IPAddr>());
+        let mut addr = IPAddr::new();
+        addr.0.copy_from_slice(a);
+        (addr, ((p[0] as u16) << 8) + (p[1] as u16))
+    }
+}
+
+impl<'a> Driver for MQTTSNDriver<'a> {
+    /// Setup buffers to read/write from.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Read buffer. Will contain the data of received messages, or the
+    ///        name of a topic registered by the gateway.
+    /// - `1`: Write buffer. Contains topic names to register or subscribe to,
+    ///        and data to publish.
+    /// - `2`: Configuration buffer. Contains the address and port of the
+    ///        gateway, followed by the client ID.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 | 1 | 2 => self.do_with_app(appid, |app| {
+                match allow_num {
+                    0 => app.app_read = slice,
+                    1 => app.app_write = slice,
+                    2 => app.app_cfg = slice,
+                    _ => {}
+                }
+                ReturnCode::SUCCESS
+            }),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Setup callback for when a message is received, or the gateway
+    ///        registers a topic. It is passed the event (0 for a message, 1
+    ///        for a topic registration), the number of bytes written into the
+    ///        read buffer, and the topic ID.
+    /// - `1`: Setup callback for when a request completes. It is passed the
+    ///        request (0 connect, 1 register, 2 publish, 3 subscribe, 4
+    ///        disconnect), the result, and for register and subscribe
+    ///        requests, the topic ID.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 | 1 => self.do_with_app(app_id, |app| {
+                match subscribe_num {
+                    0 => app.rx_callback = callback,
+                    1 => app.request_callback = callback,
+                    _ => {}
+                }
+                ReturnCode::SUCCESS
+            }),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// MQTT-SN control
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Connect from the local port `arg1` to the gateway in the
+    ///        configuration buffer, with a keep alive duration of `arg2`
+    ///        seconds. Returns EINVAL if the configuration buffer cannot be
+    ///        parsed, and EBUSY if the client belongs to another app.
+    /// - `2`: Register the topic name in the first `arg1` bytes of the write
+    ///        buffer.
+    /// - `3`: Publish the first `arg2` bytes of the write buffer to the topic
+    ///        ID in the lower 16 bits of `arg1`, with the QoS level in the
+    ///        upper bits.
+    /// - `4`: Subscribe to the topic name in the first `arg1` bytes of the
+    ///        write buffer, with QoS level `arg2`.
+    /// - `5`: Disconnect.
+    ///
+    /// Commands 2 to 5 return ERESERVE if the client does not belong to the
+    /// app or is not connected, and EBUSY if a request is still waiting for
+    /// its acknowledgement.
+    fn command(&self, command_num: usize, arg1: usize, arg2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            1 => {
+                match self.owner.get() {
+                    Some(owner) if owner != appid => return ReturnCode::EBUSY,
+                    _ => {}
+                }
+                let result = self.do_with_app(appid, |app| {
+                    app.app_cfg.as_ref().map_or(ReturnCode::EINVAL, |cfg| {
+                        if cfg.len() <= ENDPOINT_LEN {
+                            return ReturnCode::EINVAL;
+                        }
+                        let cfg = cfg.as_ref();
+                        let (gateway, gateway_port) = self.parse_endpoint(&cfg[..ENDPOINT_LEN]);
+                        self.mqttsn.connect(
+                            gateway,
+                            gateway_port,
+                            arg1 as u16,
+                            &cfg[ENDPOINT_LEN..],
+                            arg2 as u16,
+                        )
+                    })
+                });
+                if result == ReturnCode::SUCCESS {
+                    self.owner.set(Some(appid));
+                }
+                result
+            }
+
+            2 => self.do_with_write(appid, arg1, |topic_name| self.mqttsn.register(topic_name)),
+
+            3 => self.do_with_write(appid, arg2, |data| {
+                self.mqttsn.publish(arg1 as u16, (arg1 >> 16) as u8, data)
+            }),
+
+            4 => self.do_with_write(appid, arg1, |topic_name| {
+                self.mqttsn.subscribe(topic_name, arg2 as u8)
+            }),
+
+            5 => {
+                if self.owner.get() != Some(appid) {
+                    return ReturnCode::ERESERVE;
+                }
+                self.mqttsn.disconnect()
+            }
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+impl<'a> MQTTSNClient for MQTTSNDriver<'a> {
+    fn connected(&self, result: ReturnCode) {
+        self.request_done(request::CONNECT, result, 0);
+        if result != ReturnCode::SUCCESS {
+            self.owner.set(None);
+        }
+    }
+
+    fn registered(&self, result: ReturnCode, topic_id: u16) {
+        self.request_done(request::REGISTER, result, topic_id);
+    }
+
+    fn published(&self, result: ReturnCode) {
+        self.request_done(request::PUBLISH, result, 0);
+    }
+
+    fn subscribed(&self, result: ReturnCode, topic_id: u16) {
+        self.request_done(request::SUBSCRIBE, result, topic_id);
+    }
+
+    fn topic_registered(&self, topic_id: u16, topic_name: &[u8]) {
+        self.deliver(rx_event::TOPIC_REGISTERED, topic_id, topic_name);
+    }
+
+    fn received(&self, topic_id: u16, data: &[u8]) {
+        self.deliver(rx_event::PUBLISH, topic_id, data);
+    }
+
+    fn disconnected(&self, result: ReturnCode) {
+        self.request_done(request::DISCONNECT, result, 0);
+        self.owner.set(None);
+    }
+}
@@ -0,0 +1,380 @@
+//! Implements encoding and decoding of the MQTT-SN messages used by a
+//! client, as described in the MQTT-SN Protocol Specification, Version 1.2.
+//!
+//! A message consists of a header, made of the length of the whole message
+//! and the message type, followed by the fields of the message type:
+//!
+//!     1. Length  - One byte, or three bytes starting with 0x01 for messages
+//!                  of 256 bytes or more. The length includes the header.
+//!     2. MsgType - One byte.
+//!     3. Message - The variable part of the message.
+//!
+//! Only the messages a client sends or receives after it has connected
+//! without a will are implemented. Topic names and data are returned as
+//! slices of the buffer passed to `Message::decode`.
+
+use kernel::ReturnCode;
+use net::stream::SResult;
+use net::stream::{decode_u16, decode_u8, encode_bytes, encode_u16, encode_u8};
+
+/// Protocol ID of MQTT-SN version 1.2 in the CONNECT message.
+const PROTOCOL_ID: u8 = 0x01;
+
+/// The first byte of a three-byte length field.
+const LONG_LENGTH: u8 = 0x01;
+
+/// The longest client ID a gateway must accept.
+pub const MAX_CLIENT_ID_LEN: usize = 23;
+
+/// Bits of the flags field.
+pub mod flags {
+    pub const DUP: u8 = 1 << 7;
+    pub const QOS_MASK: u8 = 0b11 << 5;
+    pub const QOS_SHIFT: u8 = 5;
+    pub const RETAIN: u8 = 1 << 4;
+    pub const WILL: u8 = 1 << 3;
+    pub const CLEAN_SESSION: u8 = 1 << 2;
+    pub const TOPIC_ID_TYPE_MASK: u8 = 0b11;
+    /// The topic is a registered topic ID, or for SUBSCRIBE, a topic name.
+    pub const TOPIC_NORMAL: u8 = 0b00;
+    pub const TOPIC_PREDEFINED: u8 = 0b01;
+    pub const TOPIC_SHORT: u8 = 0b10;
+
+    /// Flags with the QoS level `qos`, which is 0, 1 or 2.
+    pub fn qos(qos: u8) -> u8 {
+        (qos << QOS_SHIFT) & QOS_MASK
+    }
+
+    /// The QoS level of `flags`.
+    pub fn get_qos(flags: u8) -> u8 {
+        (flags & QOS_MASK) >> QOS_SHIFT
+    }
+}
+
+/// Return codes of CONNACK, REGACK, PUBACK and SUBACK messages.
+pub mod return_code {
+    pub const ACCEPTED: u8 = 0x00;
+    pub const CONGESTION: u8 = 0x01;
+    pub const INVALID_TOPIC_ID: u8 = 0x02;
+    pub const NOT_SUPPORTED: u8 = 0x03;
+}
+
+/// Maps the return code of an acknowledgement from the gateway to a
+/// `ReturnCode`.
+pub fn to_result(code: u8) -> ReturnCode {
+    match code {
+        return_code::ACCEPTED => ReturnCode::SUCCESS,
+        return_code::CONGESTION => ReturnCode::EBUSY,
+        return_code::INVALID_TOPIC_ID => ReturnCode::EINVAL,
+        return_code::NOT_SUPPORTED => ReturnCode::ENOSUPPORT,
+        _ => ReturnCode::FAIL,
+    }
+}
+
+/// Sets the DUP flag of an encoded PUBLISH or SUBSCRIBE message in `buf`,
+/// which marks it as a retransmission.
+pub fn set_dup(buf: &mut [u8]) {
+    // The flags field follows the length and message type fields.
+    let flags_offset = if buf[0] == LONG_LENGTH { 4 } else { 2 };
+    if buf.len() > flags_offset {
+        buf[flags_offset] |= flags::DUP;
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MsgType {
+    Connect = 0x04,
+    ConnAck = 0x05,
+    Register = 0x0a,
+    RegAck = 0x0b,
+    Publish = 0x0c,
+    PubAck = 0x0d,
+    Subscribe = 0x12,
+    SubAck = 0x13,
+    PingReq = 0x16,
+    PingResp = 0x17,
+    Disconnect = 0x18,
+}
+
+impl MsgType {
+    pub fn from_u8(msg_type: u8) -> Option<MsgType> {
+        match msg_type {
+            0x04 => Some(MsgType::Connect),
+            0x05 => Some(MsgType::ConnAck),
+            0x0a => Some(MsgType::Register),
+            0x0b => Some(MsgType::RegAck),
+            0x0c => Some(MsgType::Publish),
+            0x0d => Some(MsgType::PubAck),
+            0x12 => Some(MsgType::Subscribe),
+            0x13 => Some(MsgType::SubAck),
+            0x16 => Some(MsgType::PingReq),
+            0x17 => Some(MsgType::PingResp),
+            0x18 => Some(MsgType::Disconnect),
+            _ => None,
+        }
+    }
+}
+
+pub enum Message<'a> {
+    Connect {
+        flags: u8,
+        /// Keep alive duration in seconds.
+        duration: u16,
+        client_id: &'a [u8],
+    },
+    ConnAck {
+        return_code: u8,
+    },
+    Register {
+        topic_id: u16,
+        msg_id: u16,
+        topic_name: &'a [u8],
+    },
+    RegAck {
+        topic_id: u16,
+        msg_id: u16,
+        return_code: u8,
+    },
+    Publish {
+        flags: u8,
+        topic_id: u16,
+        msg_id: u16,
+        data: &'a [u8],
+    },
+    PubAck {
+        topic_id: u16,
+        msg_id: u16,
+        return_code: u8,
+    },
+    Subscribe {
+        flags: u8,
+        msg_id: u16,
+        topic_name: &'a [u8],
+    },
+    SubAck {
+        flags: u8,
+        topic_id: u16,
+        msg_id: u16,
+        return_code: u8,
+    },
+    PingReq,
+    PingResp,
+    Disconnect,
+}
+
+impl Message<'a> {
+    pub fn msg_type(&self) -> MsgType {
+        match *self {
+            Message::Connect { .. } => MsgType::Connect,
+            Message::ConnAck { .. } => MsgType::ConnAck,
+            Message::Register { .. } => MsgType::Register,
+            Message::RegAck { .. } => MsgType::RegAck,
+            Message::Publish { .. } => MsgType::Publish,
+            Message::PubAck { .. } => MsgType::PubAck,
+            Message::Subscribe { .. } => MsgType::Subscribe,
+            Message::SubAck { .. } => MsgType::SubAck,
+            Message::PingReq => MsgType::PingReq,
+            Message::PingResp => MsgType::PingResp,
+            Message::Disconnect => MsgType::Disconnect,
+        }
+    }
+
+    /// Length of the variable part of the message.
+    fn body_len(&self) -> usize {
+        match *self {
+            Message::Connect { client_id, .. } => 4 + client_id.len(),
+            Message::ConnAck { .. } => 1,
+            Message::Register { topic_name, .. } => 4 + topic_name.len(),
+            Message::RegAck { .. } | Message::PubAck { .. } => 5,
+            Message::Publish { data, .. } => 5 + data.len(),
+            Message::Subscribe { topic_name, .. } => 3 + topic_name.len(),
+            Message::SubAck { .. } => 6,
+            Message::PingReq | Message::PingResp | Message::Disconnect => 0,
+        }
+    }
+
+    /// Serializes the message into `buf`.
+    pub fn encode(&self, buf: &mut [u8]) -> SResult {
+        let body_len = self.body_len();
+        let mut off = if body_len + 2 < 256 {
+            enc_consume!(buf; encode_u8, (body_len + 2) as u8)
+        } else {
+            stream_cond!(body_len + 4 <= u16::max_value() as usize);
+            let off = enc_consume!(buf; encode_u8, LONG_LENGTH);
+            enc_consume!(buf, off; encode_u16, (body_len + 4) as u16)
+        };
+        off = enc_consume!(buf, off; encode_u8, self.msg_type() as u8);
+
+        match *self {
+            Message::Connect {
+                flags,
+                duration,
+                client_id,
+            } => {
+                off = enc_consume!(buf, off; encode_u8, flags);
+                off = enc_consume!(buf, off; encode_u8, PROTOCOL_ID);
+                off = enc_consume!(buf, off; encode_u16, duration);
+                off = enc_consume!(buf, off; encode_bytes, client_id);
+            }
+            Message::ConnAck { return_code } => {
+                off = enc_consume!(buf, off; encode_u8, return_code);
+            }
+            Message::Register {
+                topic_id,
+                msg_id,
+                topic_name,
+            } => {
+                off = enc_consume!(buf, off; encode_u16, topic_id);
+                off = enc_consume!(buf, off; encode_u16, msg_id);
+                off = enc_consume!(buf, off; encode_bytes, topic_name);
+            }
+            Message::RegAck {
+                topic_id,
+                msg_id,
+                return_code,
+            }
+            | Message::PubAck {
+                topic_id,
+                msg_id,
+                return_code,
+            } => {
+                off = enc_consume!(buf, off; encode_u16, topic_id);
+                off = enc_consume!(buf, off; encode_u16, msg_id);
+                off = enc_consume!(buf, off; encode_u8, return_code);
+            }
+            Message::Publish {
+                flags,
+                topic_id,
+                msg_id,
+                data,
+            } => {
+                off = enc_consume!(buf, off; encode_u8, flags);
+                off = enc_consume!(buf, off; encode_u16, topic_id);
+                off = enc_consume!(buf, off; encode_u16, msg_id);
+                off = enc_consume!(buf, off; encode_bytes, data);
+            }
+            Message::Subscribe {
+                flags,
+                msg_id,
+                topic_name,
+            } => {
+                off = enc_consume!(buf, off; encode_u8, flags);
+                off = enc_consume!(buf, off; encode_u16, msg_id);
+                off = enc_consume!(buf, off; encode_bytes, topic_name);
+            }
+            Message::SubAck {
+                flags,
+                topic_id,
+                msg_id,
+                return_code,
+            } => {
+                off = enc_consume!(buf, off; encode_u8, flags);
+                off = enc_consume!(buf, off; encode_u16, topic_id);
+                off = enc_consume!(buf, off; encode_u16, msg_id);
+                off = enc_consume!(buf, off; encode_u8, return_code);
+            }
+            Message::PingReq | Message::PingResp | Message::Disconnect => {}
+        }
+        stream_done!(off);
+    }
+
+    /// Deserializes a message from `buf`. `SResult::Error` is returned if the
+    /// length field does not match `buf`, or the message type is not
+    /// implemented. Optional fields of PINGREQ and DISCONNECT messages are
+    /// ignored.
+    pub fn decode(buf: &'a [u8]) -> SResult<Message<'a>> {
+        let (off, len) = dec_try!(buf; decode_u8);
+        let (off, len) = if len == LONG_LENGTH {
+            let (off, len) = dec_try!(buf, off; decode_u16);
+            (off, len as usize)
+        } else {
+            (off, len as usize)
+        };
+        stream_cond!(len == buf.len() && len > off);
+        let (off, msg_type) = dec_try!(buf, off; decode_u8);
+        let msg_type = stream_from_option!(MsgType::from_u8(msg_type));
+
+        let message = match msg_type {
+            MsgType::Connect => {
+                let (off, flags) = dec_try!(buf, off; decode_u8);
+                let (off, protocol_id) = dec_try!(buf, off; decode_u8);
+                stream_cond!(protocol_id == PROTOCOL_ID);
+                let (off, duration) = dec_try!(buf, off; decode_u16);
+                Message::Connect {
+                    flags: flags,
+                    duration: duration,
+                    client_id: &buf[off..],
+                }
+            }
+            MsgType::ConnAck => {
+                let (_, return_code) = dec_try!(buf, off; decode_u8);
+                Message::ConnAck {
+                    return_code: return_code,
+                }
+            }
+            MsgType::Register => {
+                let (off, topic_id) = dec_try!(buf, off; decode_u16);
+                let (off, msg_id) = dec_try!(buf, off; decode_u16);
+                Message::Register {
+                    topic_id: topic_id,
+                    msg_id: msg_id,
+                    topic_name: &buf[off..],
+                }
+            }
+            MsgType::RegAck | MsgType::PubAck => {
+                let (off, topic_id) = dec_try!(buf, off; decode_u16);
+                let (off, msg_id) = dec_try!(buf, off; decode_u16);
+                let (_, return_code) = dec_try!(buf, off; decode_u8);
+                if msg_type == MsgType::RegAck {
+                    Message::RegAck {
+                        topic_id: topic_id,
+                        msg_id: msg_id,
+                        return_code: return_code,
+                    }
+                } else {
+                    Message::PubAck {
+                        topic_id: topic_id,
+                        msg_id: msg_id,
+                        return_code: return_code,
+                    }
+                }
+            }
+            MsgType::Publish => {
+                let (off, flags) = dec_try!(buf, off; decode_u8);
+                let (off, topic_id) = dec_try!(buf, off; decode_u16);
+                let (off, msg_id) = dec_try!(buf, off; decode_u16);
+                Message::Publish {
+                    flags: flags,
+                    topic_id: topic_id,
+                    msg_id: msg_id,
+                    data: &buf[off..],
+                }
+            }
+            MsgType::Subscribe => {
+                let (off, flags) = dec_try!(buf, off; decode_u8);
+                let (off, msg_id) = dec_try!(buf, off; decode_u16);
+                Message::Subscribe {
+                    flags: flags,
+                    msg_id: msg_id,
+                    topic_name: &buf[off..],
+                }
+            }
+            MsgType::SubAck => {
+                let (off, flags) = dec_try!(buf, off; decode_u8);
+                let (off, topic_id) = dec_try!(buf, off; decode_u16);
+                let (off, msg_id) = dec_try!(buf, off; decode_u16);
+                let (_, return_code) = dec_try!(buf, off; decode_u8);
+                Message::SubAck {
+                    flags: flags,
+                    topic_id: topic_id,
+                    msg_id: msg_id,
+                    return_code: return_code,
+                }
+            }
+            MsgType::PingReq => Message::PingReq,
+            MsgType::PingResp => Message::PingResp,
+            MsgType::Disconnect => Message::Disconnect,
+        };
+        stream_done!(len, message);
+    }
+}
@@ -0,0 +1,6 @@
+pub mod client;
+pub mod driver;
+pub mod message;
+
+pub use self::driver::MQTTSNDriver;
+pub use self::driver::DRIVER_NUM;
@@ -126,6 +126,18 @@ pub fn compute_iid(mac_addr: &MacAddress) -> [u8; 8] {
     }
 }
 
+/// Computes the MAC address that a LoWPAN Interface Identifier was derived
+/// from. This is the inverse of `compute_iid`.
+pub fn compute_mac_addr(iid: &[u8; 8]) -> MacAddress {
+    if iid[0..6] == iphc::MAC_BASE[0..6] {
+        MacAddress::Short((iid[6] as u16) << 8 | (iid[7] as u16))
+    } else {
+        let mut long_addr: [u8; 8] = *iid;
+        long_addr[0] ^= iphc::MAC_UL;
+        MacAddress::Long(long_addr)
+    }
+}
+
 impl ContextStore for Context {
     fn get_context_from_addr(&self, ip_addr: IPAddr) -> Option<Context> {
         if util::matches_prefix(&ip_addr.0, &self.prefix, self.prefix_len) {
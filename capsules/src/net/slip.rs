@@ -0,0 +1,315 @@
+//! A network interface that sends and receives IPv6 packets over a UART
+//! using SLIP framing (RFC 1055).
+//!
+//! SLIP ends every packet with an END byte, and escapes END and ESC bytes in
+//! the packet with two-byte sequences. The interface also starts every packet
+//! with an END byte, which flushes any noise the host received on the line.
+//! A host such as Linux attaches to the other end with `slattach`, which
+//! makes the board reachable as a network interface, and together with an
+//! `IP6Forwarder` lets a board with a radio serve as a border router.
+//!
+//! `SLIPInterface` implements `IP6Sender`, like the 6LoWPAN interface does,
+//! and passes received packets to a `SixlowpanRxClient`, like 6LoWPAN does
+//! once it has reassembled and decompressed a packet. The upper layers of the
+//! stack can therefore be used with either interface. SLIP has no link-layer
+//! addresses, so the gateway set on the interface is ignored.
+//!
+//! The UART must not be shared with the console, as console output would
+//! corrupt the packets. It is read one byte at a time.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! static mut SLIP_PAYLOAD: [u8; 1232] = [0; 1232];
+//! static mut SLIP_TX_BUF: [u8; 2 * 1280 + 2] = [0; 2 * 1280 + 2];
+//! static mut SLIP_RX_BUF: [u8; 1280] = [0; 1280];
+//! static mut SLIP_RX_BYTE: [u8; 1] = [0; 1];
+//!
+//! let slip_pyld: IPPayload = IPPayload {
+//!     header: TransportHeader::UDP(UDPHeader::new()),
+//!     payload: &mut SLIP_PAYLOAD,
+//! };
+//! let slip_dg = static_init!(IP6Packet<'static>, IP6Packet::new(slip_pyld));
+//! let slip = static_init!(
+//!     capsules::net::slip::SLIPInterface<'static>,
+//!     capsules::net::slip::SLIPInterface::new(
+//!         &sam4l::usart::USART0,
+//!         slip_dg,
+//!         &mut SLIP_TX_BUF,
+//!         &mut SLIP_RX_BUF,
+//!         &mut SLIP_RX_BYTE
+//!     )
+//! );
+//! hil::uart::UART::set_client(&sam4l::usart::USART0, slip);
+//! slip.set_addr(LOCAL_IP_IFACES[0]);
+//! slip.start(115200);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::uart;
+use kernel::ReturnCode;
+use net::ieee802154::MacAddress;
+use net::ipv6::ip_utils::IPAddr;
+use net::ipv6::ipv6::{IP6Header, IP6Packet, TransportHeader};
+use net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use net::sixlowpan::sixlowpan_state::SixlowpanRxClient;
+
+/// Ends a packet.
+pub const END: u8 = 0xc0;
+/// Starts an escape sequence.
+pub const ESC: u8 = 0xdb;
+/// Escaped END byte.
+pub const ESC_END: u8 = 0xdc;
+/// Escaped ESC byte.
+pub const ESC_ESC: u8 = 0xdd;
+
+/// Offset of the hop limit in an IPv6 header.
+const HOP_LIMIT_OFFSET: usize = 7;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum RxState {
+    Normal,
+    /// The last byte was ESC.
+    Escaped,
+    /// The packet is malformed or too long, and is dropped at the next END.
+    Dropping,
+}
+
+pub struct SLIPInterface<'a> {
+    uart: &'a uart::UART,
+    ip6_packet: TakeCell<'static, IP6Packet<'static>>,
+    src_addr: Cell<IPAddr>,
+    /// Holds the frame being sent. A packet of `n` bytes takes up to
+    /// `2 * n + 2` bytes once framed.
+    tx_buf: TakeCell<'static, [u8]>,
+    /// Holds the packet being received.
+    rx_buf: TakeCell<'static, [u8]>,
+    rx_len: Cell<usize>,
+    rx_state: Cell<RxState>,
+    /// Single byte buffer the UART receives into.
+    rx_byte: TakeCell<'static, [u8]>,
+    client: OptionalCell<&'a IP6SendClient>,
+    rx_client: OptionalCell<&'a SixlowpanRxClient>,
+}
+
+impl<'a> SLIPInterface<'a> {
+    pub fn new(
+        uart: &'a uart::UART,
+        ip6_packet: &'static mut IP6Packet<'static>,
+        tx_buf: &'static mut [u8],
+        rx_buf: &'static mut [u8],
+        rx_byte: &'static mut [u8],
+    ) -> SLIPInterface<'a> {
+        SLIPInterface {
+            uart: uart,
+            ip6_packet: TakeCell::new(ip6_packet),
+            src_addr: Cell::new(IPAddr::new()),
+            tx_buf: TakeCell::new(tx_buf),
+            rx_buf: TakeCell::new(rx_buf),
+            rx_len: Cell::new(0),
+            rx_state: Cell::new(RxState::Normal),
+            rx_byte: TakeCell::new(rx_byte),
+            client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+        }
+    }
+
+    /// Sets the client that receives the packets read from the UART.
+    pub fn set_receive_client(&self, client: &'a SixlowpanRxClient) {
+        self.rx_client.set(client);
+    }
+
+    /// Configures the UART for `baud_rate`, and starts receiving packets.
+    pub fn start(&self, baud_rate: u32) -> ReturnCode {
+        let result = self.uart.configure(uart::UARTParameters {
+            baud_rate: baud_rate,
+            stop_bits: uart::StopBits::One,
+            parity: uart::Parity::None,
+            hw_flow_control: false,
+        });
+        if result != ReturnCode::SUCCESS {
+            return result;
+        }
+        self.rx_byte.take().map_or(ReturnCode::EALREADY, |rx_byte| {
+            self.uart.receive(rx_byte, 1);
+            ReturnCode::SUCCESS
+        })
+    }
+
+    /// Frames the packet of `len` bytes at the end of the transmit buffer and
+    /// sends it. `write` writes the packet into the slice it is passed.
+    fn transmit<F>(&self, len: usize, write: F) -> ReturnCode
+    where
+        F: FnOnce(&mut [u8]) -> ReturnCode,
+    {
+        let tx_buf = match self.tx_buf.take() {
+            Some(tx_buf) => tx_buf,
+            None => return ReturnCode::EBUSY,
+        };
+        if tx_buf.len() < 2 * len + 2 {
+            self.tx_buf.replace(tx_buf);
+            return ReturnCode::ESIZE;
+        }
+        let start = tx_buf.len() - len;
+        let result = write(&mut tx_buf[start..]);
+        if result != ReturnCode::SUCCESS {
+            self.tx_buf.replace(tx_buf);
+            return result;
+        }
+        let frame_len = frame(tx_buf, start);
+        self.uart.transmit(tx_buf, frame_len);
+        ReturnCode::SUCCESS
+    }
+
+    fn receive_byte(&self, byte: u8) {
+        let state = self.rx_state.get();
+        let byte = match (state, byte) {
+            (_, END) => {
+                let len = self.rx_len.get();
+                if state == RxState::Normal && len > 0 {
+                    self.rx_buf.map(|rx_buf| {
+                        self.rx_client
+                            .map(|client| client.receive(rx_buf, len, ReturnCode::SUCCESS));
+                    });
+                }
+                self.rx_len.set(0);
+                self.rx_state.set(RxState::Normal);
+                return;
+            }
+            (RxState::Dropping, _) => return,
+            (RxState::Normal, ESC) => {
+                self.rx_state.set(RxState::Escaped);
+                return;
+            }
+            (RxState::Normal, byte) => byte,
+            (RxState::Escaped, ESC_END) => END,
+            (RxState::Escaped, ESC_ESC) => ESC,
+            (RxState::Escaped, _) => {
+                self.rx_state.set(RxState::Dropping);
+                return;
+            }
+        };
+
+        let len = self.rx_len.get();
+        let stored = self.rx_buf.map_or(false, |rx_buf| {
+            if len < rx_buf.len() {
+                rx_buf[len] = byte;
+                true
+            } else {
+                false
+            }
+        });
+        if stored {
+            self.rx_len.set(len + 1);
+            self.rx_state.set(RxState::Normal);
+        } else {
+            self.rx_state.set(RxState::Dropping);
+        }
+    }
+}
+
+/// Frames the packet that fills `buf` from `start` in place, and returns the
+/// length of the frame, which begins at the start of `buf`. `start` must be
+/// at least the length of the packet plus 2, so that the frame never
+/// overwrites bytes of the packet that have not been framed yet.
+fn frame(buf: &mut [u8], start: usize) -> usize {
+    let mut out = 0;
+    buf[out] = END;
+    out += 1;
+    for i in start..buf.len() {
+        let byte = buf[i];
+        match byte {
+            END => {
+                buf[out] = ESC;
+                buf[out + 1] = ESC_END;
+                out += 2;
+            }
+            ESC => {
+                buf[out] = ESC;
+                buf[out + 1] = ESC_ESC;
+                out += 2;
+            }
+            _ => {
+                buf[out] = byte;
+                out += 1;
+            }
+        }
+    }
+    buf[out] = END;
+    out + 1
+}
+
+impl<'a> IP6Sender<'a> for SLIPInterface<'a> {
+    fn set_client(&self, client: &'a IP6SendClient) {
+        self.client.set(client);
+    }
+
+    fn set_addr(&self, src_addr: IPAddr) {
+        self.src_addr.set(src_addr);
+    }
+
+    fn set_gateway(&self, _gateway: MacAddress) {}
+
+    fn set_header(&mut self, ip6_header: IP6Header) {
+        self.ip6_packet
+            .map(|ip6_packet| ip6_packet.header = ip6_header);
+    }
+
+    fn send_to(
+        &self,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        payload: &[u8],
+    ) -> ReturnCode {
+        self.ip6_packet.map_or(ReturnCode::ENOMEM, |ip6_packet| {
+            if payload.len() > ip6_packet.payload.payload.len() {
+                return ReturnCode::ESIZE;
+            }
+            ip6_packet.header = IP6Header::default();
+            ip6_packet.header.src_addr = self.src_addr.get();
+            ip6_packet.header.dst_addr = dst;
+            ip6_packet.set_payload(transport_header, payload);
+            ip6_packet.set_transport_checksum();
+
+            let len = ip6_packet.get_total_len() as usize;
+            self.transmit(len, |buf| match ip6_packet.encode(buf).done() {
+                Some(_) => ReturnCode::SUCCESS,
+                None => ReturnCode::ESIZE,
+            })
+        })
+    }
+
+    fn forward(&self, packet: &[u8]) -> ReturnCode {
+        if packet.len() <= HOP_LIMIT_OFFSET {
+            return ReturnCode::EINVAL;
+        }
+        self.transmit(packet.len(), |buf| {
+            buf.copy_from_slice(packet);
+            buf[HOP_LIMIT_OFFSET] = buf[HOP_LIMIT_OFFSET].saturating_sub(1);
+            ReturnCode::SUCCESS
+        })
+    }
+}
+
+impl<'a> uart::Client for SLIPInterface<'a> {
+    fn transmit_complete(&self, tx_buffer: &'static mut [u8], error: uart::Error) {
+        self.tx_buf.replace(tx_buffer);
+        let result = match error {
+            uart::Error::CommandComplete => ReturnCode::SUCCESS,
+            _ => ReturnCode::FAIL,
+        };
+        self.client.map(|client| client.send_done(result));
+    }
+
+    fn receive_complete(&self, rx_buffer: &'static mut [u8], rx_len: usize, error: uart::Error) {
+        match error {
+            uart::Error::CommandComplete if rx_len == 1 => self.receive_byte(rx_buffer[0]),
+            uart::Error::CommandComplete | uart::Error::Aborted => {}
+            // Drop the packet the corrupted byte belongs to.
+            _ => self.rx_state.set(RxState::Dropping),
+        }
+        self.uart.receive(rx_buffer, 1);
+    }
+}
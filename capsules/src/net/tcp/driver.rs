@@ -0,0 +1,344 @@
+//! TCP userspace interface for a single connection.
+//!
+//! Implements a userspace interface for opening a TCP connection, either to
+//! a remote endpoint or by waiting for one on a local port, and for sending
+//! and receiving data over it. The kernel has a single `TCPSocket`, which
+//! belongs to one process from the moment it connects or listens until the
+//! connection is closed.
+//!
+//! Data to send can be longer than a TCP segment. The driver sends it one
+//! segment at a time, and calls back once all of it is acknowledged.
+
+use core::cell::Cell;
+use core::{cmp, mem};
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+use net::ipv6::ip_utils::IPAddr;
+use net::tcp::tcp_socket::{TCPClient, TCPSocket};
+
+/// Syscall number
+pub const DRIVER_NUM: usize = 0x30003;
+
+/// Length of an endpoint in the endpoint buffer: an IPv6 address followed by
+/// a port in network byte order.
+const ENDPOINT_LEN: usize = mem::size_of::<IPAddr>() + 2;
+
+/// Events passed to the connection callback.
+mod conn_event {
+    pub const CONNECTED: usize = 0;
+    pub const CLOSED: usize = 1;
+}
+
+#[derive(Default)]
+pub struct App {
+    rx_callback: Option<Callback>,
+    tx_callback: Option<Callback>,
+    conn_callback: Option<Callback>,
+    app_read: Option<AppSlice<Shared, u8>>,
+    app_write: Option<AppSlice<Shared, u8>>,
+    app_cfg: Option<AppSlice<Shared, u8>>,
+    /// Number of bytes of the write buffer being sent.
+    tx_len: usize,
+    /// Number of those bytes that have been acknowledged.
+    tx_offset: usize,
+}
+
+pub struct TCPDriver<'a> {
+    /// The connection shared by all apps
+    socket: &'a TCPSocket<'a>,
+
+    /// Grant of apps that use this driver.
+    apps: Grant<App>,
+    /// ID of the app that the socket belongs to.
+    owner: Cell<Option<AppId>>,
+    /// Whether the socket of the owner is connected.
+    connected: Cell<bool>,
+}
+
+impl<'a> TCPDriver<'a> {
+    pub fn new(socket: &'a TCPSocket<'a>, grant: Grant<App>) -> TCPDriver<'a> {
+        TCPDriver {
+            socket: socket,
+            apps: grant,
+            owner: Cell::new(None),
+            connected: Cell::new(false),
+        }
+    }
+
+    /// Utility function to perform an action on an app in a system call.
+    #[inline]
+    fn do_with_app<F>(&self, appid: AppId, closure: F) -> ReturnCode
+    where
+        F: FnOnce(&mut App) -> ReturnCode,
+    {
+        self.apps
+            .enter(appid, |app, _| closure(app))
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// Utility function to perform an action on the app that the socket
+    /// belongs to.
+    #[inline]
+    fn do_with_owner<F>(&self, closure: F)
+    where
+        F: FnOnce(&mut App),
+    {
+        self.owner.get().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| closure(app));
+        });
+    }
+
+    /// Opens the socket with `open` for `appid`, unless it belongs to
+    /// another app.
+    fn claim<F>(&self, appid: AppId, open: F) -> ReturnCode
+    where
+        F: FnOnce() -> ReturnCode,
+    {
+        let owned = match self.owner.get() {
+            Some(owner) if owner != appid => return ReturnCode::EBUSY,
+            Some(_) => true,
+            None => false,
+        };
+        self.owner.set(Some(appid));
+        let result = open();
+        if result != ReturnCode::SUCCESS && !owned {
+            self.owner.set(None);
+        }
+        result
+    }
+
+    fn release(&self) {
+        self.owner.set(None);
+        self.connected.set(false);
+    }
+
+    /// Sends the next segment of the data in the write buffer of `app`.
+    fn send_next(&self, app: &mut App) -> ReturnCode {
+        let start = app.tx_offset;
+        let end = cmp::min(app.tx_len, start + self.socket.get_mss());
+        app.app_write
+            .as_ref()
+            .map_or(ReturnCode::EINVAL, |payload| {
+                if end > payload.len() {
+                    return ReturnCode::EINVAL;
+                }
+                self.socket.send(&payload.as_ref()[start..end])
+            })
+    }
+
+    #[inline]
+    fn parse_endpoint(&self, buf: &[u8]) -> Option<(IPAddr, u16)> {
+        if buf.len() != ENDPOINT_LEN {
+            return None;
+        }
+        let (a, p) = buf.split_at(mem::size_of::<IPAddr>());
+        let mut addr = IPAddr::new();
+        addr.0.copy_from_slice(a);
+        Some((addr, ((p[0] as u16) << 8) + (p[1] as u16)))
+    }
+}
+
+impl<'a> Driver for TCPDriver<'a> {
+    /// Setup buffers to read/write from.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Read buffer. Will contain the received data.
+    /// - `1`: Write buffer. Contains the data to be sent.
+    /// - `2`: Endpoint buffer. Contains the remote address and port to
+    ///        connect to, and receives those of the peer once a connection is
+    ///        established.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 | 1 | 2 => self.do_with_app(appid, |app| {
+                match allow_num {
+                    0 => app.app_read = slice,
+                    1 => app.app_write = slice,
+                    2 => app.app_cfg = slice,
+                    _ => {}
+                }
+                ReturnCode::SUCCESS
+            }),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Setup callback for when data is received. It is passed the
+    ///        number of bytes written into the read buffer.
+    /// - `1`: Setup callback for when sending is done. It is passed the result
+    ///        and the number of bytes that were acknowledged.
+    /// - `2`: Setup callback for when the connection is established or
+    ///        closed. It is passed the event (0 for established, 1 for closed)
+    ///        and the result.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 | 1 | 2 => self.do_with_app(app_id, |app| {
+                match subscribe_num {
+                    0 => app.rx_callback = callback,
+                    1 => app.tx_callback = callback,
+                    2 => app.conn_callback = callback,
+                    _ => {}
+                }
+                ReturnCode::SUCCESS
+            }),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// TCP control
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Connect from the local port `arg1` to the endpoint in the
+    ///        endpoint buffer. Returns EINVAL if the endpoint cannot be parsed
+    ///        or a port is 0, and EBUSY if the socket is in use.
+    /// - `2`: Listen for a connection to the local port `arg1`. Returns
+    ///        EINVAL if the port is 0, and EBUSY if the socket is in use.
+    /// - `3`: Send the first `arg1` bytes of the write buffer. Returns
+    ///        ERESERVE if the app is not connected, EBUSY if it is still
+    ///        sending, and EINVAL if `arg1` is 0 or longer than the write
+    ///        buffer.
+    /// - `4`: Close the connection, or stop listening. Returns ERESERVE if
+    ///        the socket does not belong to the app, and EBUSY if data is
+    ///        still being sent.
+    fn command(&self, command_num: usize, arg1: usize, _: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            1 => self.do_with_app(appid, |app| {
+                let remote = app
+                    .app_cfg
+                    .as_ref()
+                    .and_then(|cfg| self.parse_endpoint(cfg.as_ref()));
+                let (remote_addr, remote_port) = match remote {
+                    Some(remote) => remote,
+                    None => return ReturnCode::EINVAL,
+                };
+                self.claim(appid, || {
+                    self.socket
+                        .connect(arg1 as u16, remote_addr, remote_port)
+                })
+            }),
+
+            2 => self.claim(appid, || self.socket.listen(arg1 as u16)),
+
+            3 => self.do_with_app(appid, |app| {
+                if self.owner.get() != Some(appid) || !self.connected.get() {
+                    return ReturnCode::ERESERVE;
+                }
+                if app.tx_offset < app.tx_len {
+                    return ReturnCode::EBUSY;
+                }
+                if arg1 == 0 || app.app_write.as_ref().map_or(0, |buf| buf.len()) < arg1 {
+                    return ReturnCode::EINVAL;
+                }
+                app.tx_len = arg1;
+                app.tx_offset = 0;
+                let result = self.send_next(app);
+                if result != ReturnCode::SUCCESS {
+                    app.tx_len = 0;
+                }
+                result
+            }),
+
+            4 => {
+                if self.owner.get() != Some(appid) {
+                    return ReturnCode::ERESERVE;
+                }
+                let result = self.socket.close();
+                // Without a connection, nothing is left to wait for.
+                if result == ReturnCode::SUCCESS && !self.connected.get() {
+                    self.release();
+                }
+                result
+            }
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+impl<'a> TCPClient for TCPDriver<'a> {
+    fn connected(&self, result: ReturnCode) {
+        let (remote_addr, remote_port) = self.socket.get_remote();
+        self.do_with_owner(|app| {
+            if result == ReturnCode::SUCCESS {
+                // Tell the app who it is connected to.
+                app.app_cfg.as_mut().map(|cfg| {
+                    if cfg.len() == ENDPOINT_LEN {
+                        let cfg = cfg.as_mut();
+                        cfg[..mem::size_of::<IPAddr>()].copy_from_slice(&remote_addr.0);
+                        cfg[ENDPOINT_LEN - 2] = (remote_port >> 8) as u8;
+                        cfg[ENDPOINT_LEN - 1] = remote_port as u8;
+                    }
+                });
+            }
+            app.conn_callback
+                .map(|mut cb| cb.schedule(conn_event::CONNECTED, result.into(), 0));
+        });
+        if result == ReturnCode::SUCCESS {
+            self.connected.set(true);
+        } else {
+            self.release();
+        }
+    }
+
+    fn received(&self, data: &[u8]) -> usize {
+        let mut taken = 0;
+        self.do_with_owner(|app| {
+            app.app_read.as_mut().map(|rbuf| {
+                let rbuf = rbuf.as_mut();
+                taken = cmp::min(rbuf.len(), data.len());
+                rbuf[..taken].copy_from_slice(&data[..taken]);
+            });
+            if taken > 0 {
+                app.rx_callback.map(|mut cb| cb.schedule(taken, 0, 0));
+            }
+        });
+        taken
+    }
+
+    fn send_done(&self, result: ReturnCode) {
+        self.do_with_owner(|app| {
+            let mut result = result;
+            if result == ReturnCode::SUCCESS {
+                app.tx_offset = cmp::min(app.tx_len, app.tx_offset + self.socket.get_mss());
+                if app.tx_offset < app.tx_len {
+                    result = self.send_next(app);
+                    if result == ReturnCode::SUCCESS {
+                        return;
+                    }
+                }
+            }
+            let sent = app.tx_offset;
+            app.tx_len = 0;
+            app.tx_offset = 0;
+            app.tx_callback
+                .map(|mut cb| cb.schedule(result.into(), sent, 0));
+        });
+    }
+
+    fn closed(&self, result: ReturnCode) {
+        self.do_with_owner(|app| {
+            app.tx_len = 0;
+            app.tx_offset = 0;
+            app.conn_callback
+                .map(|mut cb| cb.schedule(conn_event::CLOSED, result.into(), 0));
+        });
+        self.release();
+    }
+}
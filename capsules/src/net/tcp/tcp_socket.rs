@@ -0,0 +1,649 @@
+//! This file contains the definition and implementation of a minimal TCP
+//! interface for a single connection. The [TCPSocket](trait.TCPSocket.html)
+//! trait provides an interface to open a connection, either actively with
+//! `connect` or passively with `listen`, to send data over it and to close
+//! it, and the [TCPClient](trait.TCPClient.html) trait is implemented by the
+//! upper layer to learn about the progress of the connection and to receive
+//! data.
+//!
+//! The implementation keeps things small:
+//!
+//! - At most one segment of data is in flight, and it is at most as long as
+//!   the transmit buffer, which is also advertised as the receive window. No
+//!   options are sent or interpreted, so there is no window scaling either.
+//! - Segments are only accepted in order. Anything else is acknowledged
+//!   again, and left for the peer to retransmit.
+//! - Received data is handed to the client as soon as it arrives. Whatever
+//!   the client does not take is not acknowledged, and sent again by the
+//!   peer.
+//! - Segments are retransmitted with a fixed initial timeout that doubles
+//!   with every retransmission, without measuring round-trip times.
+//! - Once the peer closes its side of the connection, the socket closes its
+//!   own side as soon as the data in flight is acknowledged.
+//! - Initial sequence numbers are taken from the alarm clock.
+//!
+//! Segments are sent through an `IP6Sender` that is usually shared with UDP,
+//! whose client then also receives their `send_done` callbacks. If the
+//! sender is busy with another packet, sending is retried shortly after.
+//! Acknowledgements are not retried, as the peer sends the segment again.
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::ReturnCode;
+use net::ipv6::ip_utils::{ip6_nh, IPAddr};
+use net::ipv6::ipv6::{IP6Header, TransportHeader};
+use net::ipv6::ipv6_recv::IP6RecvClient;
+use net::ipv6::ipv6_send::IP6Sender;
+use net::tcp::tcp::{tcp_flags, TCPHeader};
+
+/// Retransmission timeout of the first retransmission of a segment. It is
+/// doubled for every further retransmission.
+const INITIAL_RTO_MS: u32 = 1000;
+/// Number of retransmissions of a segment after which the connection fails.
+const MAX_RETRANSMISSIONS: u32 = 5;
+/// How long to wait before sending a segment again when the `IP6Sender` was
+/// busy with another packet.
+const BUSY_RETRY_MS: u32 = 50;
+/// How long to stay in TIME-WAIT. This is much shorter than the two maximum
+/// segment lifetimes of RFC 793, so that the socket can be reused quickly.
+const TIME_WAIT_MS: u32 = 2000;
+
+/// The client of a `TCPSocket` implements this trait to learn about the
+/// progress of the connection and to receive data.
+pub trait TCPClient {
+    /// Called when the connection is established, or when `connect` failed
+    /// because the peer refused the connection or did not answer.
+    fn connected(&self, result: ReturnCode);
+
+    /// Called for data received in order. Returns how many bytes of `data`
+    /// the client took. The rest is not acknowledged, so the peer sends it
+    /// again later.
+    fn received(&self, data: &[u8]) -> usize;
+
+    /// Called when the data passed to `send` has been acknowledged, or the
+    /// connection failed before it was.
+    fn send_done(&self, result: ReturnCode);
+
+    /// Called when the connection is closed, with `SUCCESS` if both sides
+    /// closed it, or `FAIL` if it was reset or the peer stopped answering.
+    fn closed(&self, result: ReturnCode);
+}
+
+/// A single TCP connection.
+pub trait TCPSocket<'a> {
+    fn set_client(&self, client: &'a TCPClient);
+
+    /// Opens a connection from `local_port` to the given remote address and
+    /// port. The client is told with `connected` once it is established.
+    ///
+    /// # Return Value
+    /// Returns `EBUSY` if the socket is in use, and `EINVAL` if a port is 0.
+    fn connect(&self, local_port: u16, remote_addr: IPAddr, remote_port: u16) -> ReturnCode;
+
+    /// Waits for a connection to `local_port`. The client is told with
+    /// `connected` once one is established.
+    ///
+    /// # Return Value
+    /// Returns `EBUSY` if the socket is in use, and `EINVAL` if the port is
+    /// 0.
+    fn listen(&self, local_port: u16) -> ReturnCode;
+
+    /// Sends `data`, which must be at most `get_mss()` bytes long, over the
+    /// established connection. The client is told with `send_done` once the
+    /// data is acknowledged.
+    ///
+    /// # Return Value
+    /// Returns `ERESERVE` if no connection is established, `EBUSY` if data
+    /// is still in flight, and `ESIZE` if `data` is too long.
+    fn send(&self, data: &[u8]) -> ReturnCode;
+
+    /// Closes the connection, once all data in flight is acknowledged, or
+    /// stops waiting for a connection. The client is told with `closed` once
+    /// a connection is closed.
+    ///
+    /// # Return Value
+    /// Returns `EBUSY` if data is still in flight, and `EALREADY` if the
+    /// socket is closed or closing.
+    fn close(&self) -> ReturnCode;
+
+    /// Returns the maximum number of bytes that can be passed to `send`.
+    fn get_mss(&self) -> usize;
+
+    /// Returns the address and port of the peer of the connection.
+    fn get_remote(&self) -> (IPAddr, u16);
+}
+
+/// The states of a connection, as in RFC 793.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum State {
+    Closed,
+    Listen,
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+}
+
+/// Client callbacks that are made once a received segment is processed.
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum Event {
+    None,
+    Connected,
+    SendDone,
+    Closed,
+}
+
+/// This is a specific instantiation of the `TCPSocket` trait. It sends
+/// segments with an `IP6Sender`, and must be set as the TCP client of an
+/// `IP6Receiver`.
+pub struct TCPSocketStruct<'a, A: Alarm, T: IP6Sender<'a>> {
+    ip_send_struct: &'a T,
+    alarm: &'a A,
+    state: Cell<State>,
+    local_port: Cell<u16>,
+    remote_addr: Cell<IPAddr>,
+    remote_port: Cell<u16>,
+    /// Oldest unacknowledged sequence number.
+    snd_una: Cell<u32>,
+    /// Next sequence number to send.
+    snd_nxt: Cell<u32>,
+    /// Next sequence number expected from the peer.
+    rcv_nxt: Cell<u32>,
+    /// Data in flight is `tx_buf[tx_start..tx_len]`, whose first byte has
+    /// sequence number `snd_una`.
+    tx_buf: TakeCell<'static, [u8]>,
+    tx_start: Cell<usize>,
+    tx_len: Cell<usize>,
+    mss: usize,
+    retransmissions: Cell<u32>,
+    /// Whether the last attempt to send a segment found the `IP6Sender`
+    /// busy.
+    sender_busy: Cell<bool>,
+    client: OptionalCell<&'a TCPClient>,
+}
+
+impl<A: Alarm, T: IP6Sender<'a>> TCPSocketStruct<'a, A, T> {
+    pub fn new(
+        ip_send_struct: &'a T,
+        alarm: &'a A,
+        tx_buf: &'static mut [u8],
+    ) -> TCPSocketStruct<'a, A, T> {
+        let mss = tx_buf.len();
+        TCPSocketStruct {
+            ip_send_struct: ip_send_struct,
+            alarm: alarm,
+            state: Cell::new(State::Closed),
+            local_port: Cell::new(0),
+            remote_addr: Cell::new(IPAddr::new()),
+            remote_port: Cell::new(0),
+            snd_una: Cell::new(0),
+            snd_nxt: Cell::new(0),
+            rcv_nxt: Cell::new(0),
+            tx_buf: TakeCell::new(tx_buf),
+            tx_start: Cell::new(0),
+            tx_len: Cell::new(0),
+            mss: mss,
+            retransmissions: Cell::new(0),
+            sender_busy: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    fn set_timeout(&self, ms: u32) {
+        let tics = A::Frequency::frequency() / 1000 * ms;
+        self.alarm.set_alarm(self.alarm.now().wrapping_add(tics));
+    }
+
+    /// Starts opening a connection by sending a SYN, or a SYN-ACK in
+    /// `SynReceived`.
+    fn open(&self, state: State) {
+        let iss = self.alarm.now();
+        self.snd_una.set(iss);
+        self.snd_nxt.set(iss.wrapping_add(1));
+        self.tx_start.set(0);
+        self.tx_len.set(0);
+        self.retransmissions.set(0);
+        self.state.set(state);
+        self.transmit();
+    }
+
+    /// Sends the oldest unacknowledged segment, and sets the timer to send it
+    /// again.
+    fn transmit(&self) {
+        let (flags, len) = match self.state.get() {
+            State::SynSent => (tcp_flags::SYN, 0),
+            State::SynReceived => (tcp_flags::SYN | tcp_flags::ACK, 0),
+            State::FinWait1 | State::Closing | State::LastAck => {
+                (tcp_flags::FIN | tcp_flags::ACK, 0)
+            }
+            _ => (tcp_flags::PSH | tcp_flags::ACK, self.tx_len.get()),
+        };
+        let start = cmp::min(self.tx_start.get(), len);
+        let result = self.tx_buf.map_or(ReturnCode::ENOMEM, |tx_buf| {
+            self.send_segment(flags, self.snd_una.get(), &tx_buf[start..len])
+        });
+        if result == ReturnCode::EBUSY {
+            self.sender_busy.set(true);
+            self.set_timeout(BUSY_RETRY_MS);
+        } else {
+            self.sender_busy.set(false);
+            self.set_timeout(INITIAL_RTO_MS << self.retransmissions.get());
+        }
+    }
+
+    fn send_segment(&self, flags: u8, seq_num: u32, data: &[u8]) -> ReturnCode {
+        let mut tcp_header = TCPHeader::new();
+        tcp_header.set_src_port(self.local_port.get());
+        tcp_header.set_dst_port(self.remote_port.get());
+        tcp_header.set_seq_num(seq_num);
+        if flags & tcp_flags::ACK != 0 {
+            tcp_header.set_ack_num(self.rcv_nxt.get());
+        }
+        tcp_header.set_flags(flags);
+        tcp_header.set_window(cmp::min(self.mss, 0xffff) as u16);
+        self.ip_send_struct.send_to(
+            self.remote_addr.get(),
+            TransportHeader::TCP(tcp_header),
+            data,
+        )
+    }
+
+    fn send_ack(&self) {
+        self.send_segment(tcp_flags::ACK, self.snd_nxt.get(), &[]);
+    }
+
+    /// Answers a segment that does not belong to the connection with a reset
+    /// (RFC 793, Section 3.4).
+    fn send_reset(&self, src_addr: IPAddr, tcp_header: &TCPHeader, data_len: usize) {
+        if tcp_header.has_flags(tcp_flags::RST) {
+            return;
+        }
+        let mut reset = TCPHeader::new();
+        reset.set_src_port(tcp_header.get_dst_port());
+        reset.set_dst_port(tcp_header.get_src_port());
+        if tcp_header.has_flags(tcp_flags::ACK) {
+            reset.set_seq_num(tcp_header.get_ack_num());
+            reset.set_flags(tcp_flags::RST);
+        } else {
+            let mut seg_len = data_len as u32;
+            if tcp_header.has_flags(tcp_flags::SYN) {
+                seg_len += 1;
+            }
+            if tcp_header.has_flags(tcp_flags::FIN) {
+                seg_len += 1;
+            }
+            reset.set_ack_num(tcp_header.get_seq_num().wrapping_add(seg_len));
+            reset.set_flags(tcp_flags::RST | tcp_flags::ACK);
+        }
+        self.ip_send_struct
+            .send_to(src_addr, TransportHeader::TCP(reset), &[]);
+    }
+
+    /// Sends a FIN once all data has been acknowledged.
+    fn send_fin(&self, state: State) {
+        self.snd_nxt.set(self.snd_nxt.get().wrapping_add(1));
+        self.retransmissions.set(0);
+        self.state.set(state);
+        self.transmit();
+    }
+
+    /// Aborts the connection after a reset, or after the peer stopped
+    /// answering, and tells the client.
+    fn fail(&self) {
+        let state = self.state.get();
+        let tx_pending = self.tx_len.get() > 0;
+        self.alarm.disable();
+        self.tx_start.set(0);
+        self.tx_len.set(0);
+        self.state.set(State::Closed);
+        match state {
+            State::SynSent => {
+                self.client.map(|client| client.connected(ReturnCode::FAIL));
+            }
+            State::SynReceived => {
+                // A passive open waits for the next connection.
+                self.state.set(State::Listen);
+            }
+            State::TimeWait => {}
+            _ => {
+                if tx_pending {
+                    self.client.map(|client| client.send_done(ReturnCode::FAIL));
+                }
+                self.client.map(|client| client.closed(ReturnCode::FAIL));
+            }
+        }
+    }
+
+    fn is_for_connection(&self, src_addr: IPAddr, tcp_header: &TCPHeader) -> bool {
+        src_addr == self.remote_addr.get()
+            && tcp_header.get_src_port() == self.remote_port.get()
+            && tcp_header.get_dst_port() == self.local_port.get()
+    }
+
+    fn receive_listen(&self, src_addr: IPAddr, tcp_header: &TCPHeader, data_len: usize) {
+        if tcp_header.has_flags(tcp_flags::RST) {
+            return;
+        }
+        if tcp_header.get_dst_port() != self.local_port.get()
+            || tcp_header.has_flags(tcp_flags::ACK)
+        {
+            self.send_reset(src_addr, tcp_header, data_len);
+            return;
+        }
+        if !tcp_header.has_flags(tcp_flags::SYN) {
+            return;
+        }
+        // Data sent with the SYN is not kept, so the peer sends it again.
+        self.remote_addr.set(src_addr);
+        self.remote_port.set(tcp_header.get_src_port());
+        self.rcv_nxt.set(tcp_header.get_seq_num().wrapping_add(1));
+        self.open(State::SynReceived);
+    }
+
+    fn receive_syn_sent(&self, tcp_header: &TCPHeader) {
+        if tcp_header.has_flags(tcp_flags::ACK) && tcp_header.get_ack_num() != self.snd_nxt.get()
+        {
+            self.send_reset(self.remote_addr.get(), tcp_header, 0);
+            return;
+        }
+        if tcp_header.has_flags(tcp_flags::RST) {
+            if tcp_header.has_flags(tcp_flags::ACK) {
+                self.fail();
+            }
+            return;
+        }
+        // A SYN without an ACK would be a simultaneous open, which is not
+        // supported.
+        if !tcp_header.has_flags(tcp_flags::SYN | tcp_flags::ACK) {
+            return;
+        }
+        self.alarm.disable();
+        self.snd_una.set(tcp_header.get_ack_num());
+        self.rcv_nxt.set(tcp_header.get_seq_num().wrapping_add(1));
+        self.state.set(State::Established);
+        self.send_ack();
+        self.client.map(|client| client.connected(ReturnCode::SUCCESS));
+    }
+
+    /// Processes the acknowledgement of a segment in a synchronized state,
+    /// and returns the client callback to make for it.
+    fn receive_ack(&self, ack_num: u32) -> Event {
+        let snd_una = self.snd_una.get();
+        let acked = ack_num.wrapping_sub(snd_una);
+        if acked == 0 || acked > self.snd_nxt.get().wrapping_sub(snd_una) {
+            // A duplicate, or an acknowledgement of something not yet sent
+            return Event::None;
+        }
+        self.snd_una.set(ack_num);
+        if ack_num != self.snd_nxt.get() {
+            // Part of the data in flight was acknowledged.
+            self.tx_start.set(self.tx_start.get() + acked as usize);
+            return Event::None;
+        }
+
+        self.alarm.disable();
+        self.retransmissions.set(0);
+        match self.state.get() {
+            State::SynReceived => {
+                self.state.set(State::Established);
+                Event::Connected
+            }
+            State::Established | State::CloseWait => {
+                if self.tx_len.get() == 0 {
+                    return Event::None;
+                }
+                self.tx_start.set(0);
+                self.tx_len.set(0);
+                if self.state.get() == State::CloseWait {
+                    self.send_fin(State::LastAck);
+                }
+                Event::SendDone
+            }
+            State::FinWait1 => {
+                self.state.set(State::FinWait2);
+                Event::None
+            }
+            State::Closing => self.time_wait(),
+            State::LastAck => {
+                self.state.set(State::Closed);
+                Event::Closed
+            }
+            _ => Event::None,
+        }
+    }
+
+    fn receive_synchronized(&self, src_addr: IPAddr, tcp_header: &TCPHeader, data: &[u8]) {
+        if !self.is_for_connection(src_addr, tcp_header) {
+            self.send_reset(src_addr, tcp_header, data.len());
+            return;
+        }
+        if tcp_header.get_seq_num() != self.rcv_nxt.get() {
+            // Only segments that arrive in order are accepted.
+            if !tcp_header.has_flags(tcp_flags::RST) {
+                self.send_ack();
+            }
+            return;
+        }
+        if tcp_header.has_flags(tcp_flags::RST) {
+            self.fail();
+            return;
+        }
+        if tcp_header.has_flags(tcp_flags::SYN) {
+            self.send_segment(tcp_flags::RST, self.snd_nxt.get(), &[]);
+            self.fail();
+            return;
+        }
+        if !tcp_header.has_flags(tcp_flags::ACK) {
+            return;
+        }
+
+        let mut event = self.receive_ack(tcp_header.get_ack_num());
+        let mut ack_needed = false;
+        let mut fin = tcp_header.has_flags(tcp_flags::FIN);
+        match self.state.get() {
+            State::Established | State::FinWait1 | State::FinWait2 if data.len() > 0 => {
+                let taken = self
+                    .client
+                    .map_or(0, |client| cmp::min(client.received(data), data.len()));
+                self.rcv_nxt
+                    .set(self.rcv_nxt.get().wrapping_add(taken as u32));
+                // A FIN that follows data the client did not take is not
+                // received either.
+                fin = fin && taken == data.len();
+                ack_needed = true;
+            }
+            _ => {}
+        }
+
+        if fin {
+            match self.state.get() {
+                State::Established => {
+                    self.rcv_nxt.set(self.rcv_nxt.get().wrapping_add(1));
+                    if self.tx_len.get() == 0 {
+                        // The FIN acknowledges the peer's FIN.
+                        self.send_fin(State::LastAck);
+                        ack_needed = false;
+                    } else {
+                        self.state.set(State::CloseWait);
+                        ack_needed = true;
+                    }
+                }
+                State::FinWait1 => {
+                    // Our FIN has not been acknowledged yet.
+                    self.rcv_nxt.set(self.rcv_nxt.get().wrapping_add(1));
+                    self.state.set(State::Closing);
+                    ack_needed = true;
+                }
+                State::FinWait2 => {
+                    self.rcv_nxt.set(self.rcv_nxt.get().wrapping_add(1));
+                    event = self.time_wait();
+                    ack_needed = true;
+                }
+                _ => {
+                    // A retransmitted FIN, whose acknowledgement was lost.
+                    ack_needed = true;
+                }
+            }
+        }
+
+        if ack_needed {
+            self.send_ack();
+        }
+        match event {
+            Event::None => {}
+            Event::Connected => {
+                self.client.map(|client| client.connected(ReturnCode::SUCCESS));
+            }
+            Event::SendDone => {
+                self.client.map(|client| client.send_done(ReturnCode::SUCCESS));
+            }
+            Event::Closed => {
+                self.client.map(|client| client.closed(ReturnCode::SUCCESS));
+            }
+        }
+    }
+
+    /// Enters TIME-WAIT, in which the connection is closed for the client,
+    /// but the socket still acknowledges a retransmitted FIN of the peer.
+    fn time_wait(&self) -> Event {
+        self.state.set(State::TimeWait);
+        self.set_timeout(TIME_WAIT_MS);
+        Event::Closed
+    }
+}
+
+impl<A: Alarm, T: IP6Sender<'a>> TCPSocket<'a> for TCPSocketStruct<'a, A, T> {
+    fn set_client(&self, client: &'a TCPClient) {
+        self.client.set(client);
+    }
+
+    fn connect(&self, local_port: u16, remote_addr: IPAddr, remote_port: u16) -> ReturnCode {
+        if self.state.get() != State::Closed {
+            return ReturnCode::EBUSY;
+        }
+        if local_port == 0 || remote_port == 0 {
+            return ReturnCode::EINVAL;
+        }
+        self.local_port.set(local_port);
+        self.remote_addr.set(remote_addr);
+        self.remote_port.set(remote_port);
+        self.rcv_nxt.set(0);
+        self.open(State::SynSent);
+        ReturnCode::SUCCESS
+    }
+
+    fn listen(&self, local_port: u16) -> ReturnCode {
+        if self.state.get() != State::Closed {
+            return ReturnCode::EBUSY;
+        }
+        if local_port == 0 {
+            return ReturnCode::EINVAL;
+        }
+        self.local_port.set(local_port);
+        self.state.set(State::Listen);
+        ReturnCode::SUCCESS
+    }
+
+    fn send(&self, data: &[u8]) -> ReturnCode {
+        if self.state.get() != State::Established {
+            return ReturnCode::ERESERVE;
+        }
+        if self.tx_len.get() > 0 {
+            return ReturnCode::EBUSY;
+        }
+        if data.len() > self.mss {
+            return ReturnCode::ESIZE;
+        }
+        if data.len() == 0 {
+            return ReturnCode::EINVAL;
+        }
+        self.tx_buf.map(|tx_buf| tx_buf[..data.len()].copy_from_slice(data));
+        self.tx_start.set(0);
+        self.tx_len.set(data.len());
+        self.snd_nxt
+            .set(self.snd_una.get().wrapping_add(data.len() as u32));
+        self.retransmissions.set(0);
+        self.transmit();
+        ReturnCode::SUCCESS
+    }
+
+    fn close(&self) -> ReturnCode {
+        match self.state.get() {
+            State::Listen | State::SynSent => {
+                self.alarm.disable();
+                self.state.set(State::Closed);
+                ReturnCode::SUCCESS
+            }
+            State::SynReceived | State::Established => {
+                if self.tx_len.get() > 0 {
+                    return ReturnCode::EBUSY;
+                }
+                self.send_fin(State::FinWait1);
+                ReturnCode::SUCCESS
+            }
+            _ => ReturnCode::EALREADY,
+        }
+    }
+
+    fn get_mss(&self) -> usize {
+        self.mss
+    }
+
+    fn get_remote(&self) -> (IPAddr, u16) {
+        (self.remote_addr.get(), self.remote_port.get())
+    }
+}
+
+impl<A: Alarm, T: IP6Sender<'a>> IP6RecvClient for TCPSocketStruct<'a, A, T> {
+    fn receive(&self, ip_header: IP6Header, payload: &[u8]) {
+        if ip_header.get_next_header() != ip6_nh::TCP {
+            return;
+        }
+        let (offset, tcp_header) = match TCPHeader::decode(payload).done() {
+            Some(decoded) => decoded,
+            None => return,
+        };
+        let src_addr = ip_header.get_src_addr();
+        let data = &payload[offset..];
+        match self.state.get() {
+            State::Closed => self.send_reset(src_addr, &tcp_header, data.len()),
+            State::Listen => self.receive_listen(src_addr, &tcp_header, data.len()),
+            State::SynSent => {
+                if self.is_for_connection(src_addr, &tcp_header) {
+                    self.receive_syn_sent(&tcp_header);
+                } else {
+                    self.send_reset(src_addr, &tcp_header, data.len());
+                }
+            }
+            _ => self.receive_synchronized(src_addr, &tcp_header, data),
+        }
+    }
+}
+
+impl<A: Alarm, T: IP6Sender<'a>> time::Client for TCPSocketStruct<'a, A, T> {
+    fn fired(&self) {
+        match self.state.get() {
+            State::Closed | State::Listen | State::FinWait2 => {}
+            State::TimeWait => self.state.set(State::Closed),
+            State::Established | State::CloseWait if self.tx_len.get() == 0 => {}
+            _ => {
+                if self.sender_busy.get() {
+                    self.transmit();
+                } else if self.retransmissions.get() >= MAX_RETRANSMISSIONS {
+                    self.fail();
+                } else {
+                    self.retransmissions
+                        .set(self.retransmissions.get() + 1);
+                    self.transmit();
+                }
+            }
+        }
+    }
+}
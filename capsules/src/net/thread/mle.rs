@@ -0,0 +1,285 @@
+//! Implements encoding and decoding of Mesh Link Establishment (MLE)
+//! messages as outlined in Chapter 4 of the Thread 1.1.1 Specification.
+//!
+//! MLE for network attaching comprises a four-step handshake that works
+//! as follows:
+//!
+//!     1. A child device multicasts a Parent Request MLE command.
+//!     2. Each potential parent device on the network unicasts a Parent
+//!        Response MLE command.
+//!     3. The child device selects a parent based on a hierarchy of
+//!        connectivity metrics and unicasts a Child ID Request MLE
+//!        command.
+//!     4. The selected parent unicasts a Child ID Response MLE command.
+//!
+//! MLE messages are UDP datagrams sent to port 19788 between link-local
+//! addresses. Except for discovery messages, they are secured with
+//! AES-CCM* at the MLE layer, and are laid out as follows:
+//!
+//!     1. Security suite            - One byte, 0 for secured messages.
+//!     2. Auxiliary security header - The 802.15.4 auxiliary security
+//!                                    header, with a key identifier made
+//!                                    of the key sequence and key index.
+//!     3. Command type              - One byte.
+//!     4. TLVs                      - The parameters of the command.
+//!     5. MIC                       - Four bytes.
+//!
+//! The command type and the TLVs are encrypted. The authenticated data is
+//! the source IPv6 address, the destination IPv6 address and the
+//! auxiliary security header, in that order. The nonce is formed as for
+//! 802.15.4 frames, from the extended address of the sender, which is the
+//! interface identifier of its link-local address.
+
+use net::ieee802154::{KeyId, MacAddress, Security, SecurityLevel};
+use net::ipv6::ip_utils::IPAddr;
+use net::sixlowpan::sixlowpan_compression;
+use net::stream::SResult;
+use net::stream::{decode_u8, encode_u8};
+use net::thread::tlv::{Tlv, TlvType};
+
+/// UDP port that MLE messages are sent from and to.
+pub const MLE_PORT: u16 = 19788;
+
+/// Security suite of messages secured at the MLE layer.
+pub const SECURITY_SUITE_ENABLED: u8 = 0;
+/// Security suite of unsecured messages, only used for discovery.
+pub const SECURITY_SUITE_DISABLED: u8 = 255;
+
+/// Security level of MLE messages, which Thread also uses for 802.15.4
+/// frames.
+pub const SECURITY_LEVEL: SecurityLevel = SecurityLevel::EncMic32;
+/// Length of the MIC of MLE messages.
+pub const MIC_LEN: usize = 4;
+
+/// Value of the Version TLV for Thread 1.1.
+pub const THREAD_VERSION: u16 = 2;
+
+/// TLVs that a child requests in its Child ID Request.
+const CHILD_ID_REQUEST_TLVS: [u8; 2] = [TlvType::Address16 as u8, TlvType::NetworkData as u8];
+
+/// MLE command types (Section 4.4).
+#[repr(u8)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Command {
+    LinkRequest = 0,
+    LinkAccept = 1,
+    LinkAcceptAndRequest = 2,
+    LinkReject = 3,
+    Advertisement = 4,
+    Update = 5,
+    UpdateRequest = 6,
+    DataRequest = 7,
+    DataResponse = 8,
+    ParentRequest = 9,
+    ParentResponse = 10,
+    ChildIdRequest = 11,
+    ChildIdResponse = 12,
+    ChildUpdateRequest = 13,
+    ChildUpdateResponse = 14,
+    Announce = 15,
+    DiscoveryRequest = 16,
+    DiscoveryResponse = 17,
+}
+
+impl Command {
+    pub fn from_u8(command: u8) -> Option<Command> {
+        match command {
+            0 => Some(Command::LinkRequest),
+            1 => Some(Command::LinkAccept),
+            2 => Some(Command::LinkAcceptAndRequest),
+            3 => Some(Command::LinkReject),
+            4 => Some(Command::Advertisement),
+            5 => Some(Command::Update),
+            6 => Some(Command::UpdateRequest),
+            7 => Some(Command::DataRequest),
+            8 => Some(Command::DataResponse),
+            9 => Some(Command::ParentRequest),
+            10 => Some(Command::ParentResponse),
+            11 => Some(Command::ChildIdRequest),
+            12 => Some(Command::ChildIdResponse),
+            13 => Some(Command::ChildUpdateRequest),
+            14 => Some(Command::ChildUpdateResponse),
+            15 => Some(Command::Announce),
+            16 => Some(Command::DiscoveryRequest),
+            17 => Some(Command::DiscoveryResponse),
+            _ => None,
+        }
+    }
+}
+
+/// The key index of a key sequence, which identifies the key in both
+/// 802.15.4 frames and MLE messages (Section 7.2.2.2).
+pub fn key_index(key_sequence: u32) -> u8 {
+    ((key_sequence & 0x7f) + 1) as u8
+}
+
+/// The key identifier of MLE messages secured with the key of
+/// `key_sequence`. The key source is the key sequence in network byte
+/// order.
+pub fn key_id(key_sequence: u32) -> KeyId {
+    // `KeyId` encodes its key source in reverse byte order.
+    let key_source = [
+        key_sequence as u8,
+        (key_sequence >> 8) as u8,
+        (key_sequence >> 16) as u8,
+        (key_sequence >> 24) as u8,
+    ];
+    KeyId::Source4Index(key_source, key_index(key_sequence))
+}
+
+/// The key sequence of an MLE key identifier.
+pub fn key_sequence(key_id: &KeyId) -> Option<u32> {
+    match *key_id {
+        KeyId::Source4Index(ref key_source, _) => Some(
+            (key_source[0] as u32)
+                | (key_source[1] as u32) << 8
+                | (key_source[2] as u32) << 16
+                | (key_source[3] as u32) << 24,
+        ),
+        _ => None,
+    }
+}
+
+/// The auxiliary security header of a message sent with `frame_counter`
+/// and the key of `key_sequence`.
+pub fn security_header(key_sequence: u32, frame_counter: u32) -> Security {
+    Security {
+        level: SECURITY_LEVEL,
+        asn_in_nonce: false,
+        frame_counter: Some(frame_counter),
+        key_id: key_id(key_sequence),
+    }
+}
+
+/// The extended address of the interface with link-local address `addr`.
+pub fn ext_addr(addr: &IPAddr) -> [u8; 8] {
+    let mut ext_addr = [0; 8];
+    ext_addr.copy_from_slice(&addr.0[8..16]);
+    // Interface identifiers have the universal/local bit inverted
+    ext_addr[0] ^= 0x02;
+    ext_addr
+}
+
+/// The link-local address of the interface with extended address
+/// `ext_addr`.
+pub fn link_local_addr(ext_addr: &[u8; 8]) -> IPAddr {
+    let mut addr = IPAddr::new();
+    addr.set_unicast_link_local();
+    addr.0[8..16].copy_from_slice(&sixlowpan_compression::compute_iid(&MacAddress::Long(
+        *ext_addr,
+    )));
+    addr
+}
+
+/// Encodes the security suite and, for secured messages, the auxiliary
+/// security header.
+pub fn encode_header(buf: &mut [u8], security: Option<Security>) -> SResult {
+    match security {
+        Some(security) => {
+            let offset = enc_consume!(buf; encode_u8, SECURITY_SUITE_ENABLED);
+            let offset = enc_consume!(buf, offset; security; encode);
+            stream_done!(offset)
+        }
+        None => {
+            let offset = enc_consume!(buf; encode_u8, SECURITY_SUITE_DISABLED);
+            stream_done!(offset)
+        }
+    }
+}
+
+/// Decodes the security suite and, for secured messages, the auxiliary
+/// security header.
+pub fn decode_header(buf: &[u8]) -> SResult<Option<Security>> {
+    let (offset, security_suite) = dec_try!(buf; decode_u8);
+    match security_suite {
+        SECURITY_SUITE_ENABLED => {
+            let (offset, security) = dec_try!(buf, offset; Security::decode);
+            stream_done!(offset, Some(security))
+        }
+        SECURITY_SUITE_DISABLED => stream_done!(offset, None),
+        _ => stream_err!(),
+    }
+}
+
+/// Encodes the command type and TLVs of a Parent Request (Section 4.7.1.1).
+/// `scan_mask` selects whether only routers, or also router-eligible end
+/// devices, respond.
+pub fn encode_parent_request(
+    buf: &mut [u8],
+    mode: u8,
+    challenge: [u8; 8],
+    scan_mask: u8,
+) -> SResult {
+    let offset = enc_consume!(buf; encode_u8, Command::ParentRequest as u8);
+    let offset = enc_consume!(buf, offset; Tlv::Mode(mode); encode);
+    let offset = enc_consume!(buf, offset; Tlv::Challenge(challenge); encode);
+    let offset = enc_consume!(buf, offset; Tlv::ScanMask(scan_mask); encode);
+    let offset = enc_consume!(buf, offset; Tlv::Version(THREAD_VERSION); encode);
+    stream_done!(offset)
+}
+
+/// Encodes the command type and TLVs of a Child ID Request (Section
+/// 4.7.1.4). `response` is the challenge of the selected parent, and
+/// `timeout` the child timeout in seconds.
+pub fn encode_child_id_request(
+    buf: &mut [u8],
+    mode: u8,
+    timeout: u32,
+    response: [u8; 8],
+    link_frame_counter: u32,
+    mle_frame_counter: u32,
+) -> SResult {
+    let offset = enc_consume!(buf; encode_u8, Command::ChildIdRequest as u8);
+    let offset = enc_consume!(buf, offset; Tlv::Response(response); encode);
+    let offset =
+        enc_consume!(buf, offset; Tlv::LinkLayerFrameCounter(link_frame_counter); encode);
+    let offset = enc_consume!(buf, offset; Tlv::MleFrameCounter(mle_frame_counter); encode);
+    let offset = enc_consume!(buf, offset; Tlv::Mode(mode); encode);
+    let offset = enc_consume!(buf, offset; Tlv::Timeout(timeout); encode);
+    let offset = enc_consume!(buf, offset; Tlv::Version(THREAD_VERSION); encode);
+    let offset = enc_consume!(buf, offset; Tlv::TlvRequest(&CHILD_ID_REQUEST_TLVS); encode);
+    stream_done!(offset)
+}
+
+/// Decodes the command type of a message, and returns it with an iterator
+/// over its TLVs.
+pub fn decode_command(buf: &'a [u8]) -> SResult<(Command, Tlvs<'a>)> {
+    let (offset, command) = dec_try!(buf; decode_u8);
+    let command = stream_from_option!(Command::from_u8(command));
+    stream_done!(
+        buf.len(),
+        (
+            command,
+            Tlvs {
+                buf: &buf[offset..],
+            }
+        )
+    )
+}
+
+/// Iterator over the TLVs of a message. TLVs of types that are not
+/// implemented are skipped, and iteration stops at the first malformed TLV.
+pub struct Tlvs<'a> {
+    buf: &'a [u8],
+}
+
+impl Iterator for Tlvs<'a> {
+    type Item = Tlv<'a>;
+
+    fn next(&mut self) -> Option<Tlv<'a>> {
+        while self.buf.len() >= 2 {
+            let tlv_len = 2 + self.buf[1] as usize;
+            if tlv_len > self.buf.len() {
+                break;
+            }
+            let buf = self.buf;
+            let (tlv_buf, rest) = buf.split_at(tlv_len);
+            self.buf = rest;
+            if let Some((_, tlv)) = Tlv::decode(tlv_buf).done() {
+                return Some(tlv);
+            }
+        }
+        self.buf = &[];
+        None
+    }
+}
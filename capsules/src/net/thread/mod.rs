@@ -1 +1,3 @@
+pub mod mle;
+pub mod sed;
 pub mod tlv;
@@ -0,0 +1,646 @@
+//! Attaches a device to a Thread network as a Sleepy End Device (SED).
+//!
+//! `SleepyEndDevice` runs the MLE attach handshake described in the `mle`
+//! module. It multicasts a Parent Request to the routers of the network, and
+//! if none responds, to the router-eligible end devices as well. It then
+//! selects the parent with the best link among the responses, requests a
+//! child ID from it, and takes the short address (RLOC16) that the parent
+//! assigns as its own.
+//!
+//! It also implements the 802.15.4 key and device lookup procedures of the
+//! `Framer`, so that frames exchanged with the parent are secured with the
+//! MAC key of the network.
+//!
+//! Thread derives the MAC and MLE keys from the master key of the network
+//! and the key sequence with HMAC-SHA256. `set_master_key` derives them,
+//! and `set_keys` takes keys that were derived elsewhere.
+//!
+//! MLE messages are sent through a `UDPSender` that can be shared with the
+//! UDP driver, whose client then also receives their `send_done` callbacks.
+//! The IPv6 source address of the sender must be the link-local address of
+//! the extended address of the device. Received UDP datagrams for ports
+//! other than the MLE port are passed on to the client set with
+//! `set_next_client`.
+//!
+//! Not implemented yet are polling the parent for frames it buffered for the
+//! child, Child Update messages, key rotation, and checking the frame
+//! counters of received MLE messages. Without data polling, the parent keeps
+//! the frames it buffers for the device until they expire, so the device does
+//! not receive data from the network yet.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! static mut MLE_BUF: [u8; 200] = [0; 200];
+//!
+//! let sed = static_init!(
+//!     capsules::net::thread::sed::SleepyEndDevice<
+//!         'static,
+//!         VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+//!     >,
+//!     capsules::net::thread::sed::SleepyEndDevice::new(
+//!         mac_device,
+//!         udp_send,
+//!         mle_aes_ccm,
+//!         sed_virtual_alarm,
+//!         rng,
+//!         &mut MLE_BUF,
+//!         240
+//!     )
+//! );
+//! sed_virtual_alarm.set_client(sed);
+//! mle_aes_ccm.set_client(sed);
+//! sed.set_next_client(udp_driver);
+//! udp_recv.set_client(sed);
+//! mac_device.set_key_procedure(sed);
+//! mac_device.set_device_procedure(sed);
+//! sed.set_master_key(0, &MASTER_KEY);
+//! sed.attach();
+//! ```
+
+use core::cell::Cell;
+use crypto::hmac_sha256::{HmacSha256, DIGEST_LEN};
+use ieee802154::device::MacDevice;
+use ieee802154::framer::{get_ccm_nonce, DeviceProcedure, KeyProcedure};
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::rng::Random;
+use kernel::hil::symmetric_encryption::{CCMClient, AES128CCM};
+use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::ReturnCode;
+use net::ieee802154::{KeyId, MacAddress, SecurityLevel};
+use net::ipv6::ip_utils::IPAddr;
+use net::stream::SResult;
+use net::thread::mle::{self, Command, Tlvs};
+use net::thread::tlv::{LinkMode, MulticastResponder, Tlv};
+use net::udp::udp_recv::UDPRecvClient;
+use net::udp::udp_send::UDPSender;
+
+/// Mode TLV of a sleepy end device: the receiver is off when idle, and the
+/// device needs neither the full network data nor routing.
+const MODE: u8 = LinkMode::SecureDataRequests as u8;
+
+/// How long to wait for Parent Responses from routers, and from
+/// router-eligible end devices as well (Section 4.7.2).
+const PARENT_RESPONSE_ROUTERS_MS: u32 = 750;
+const PARENT_RESPONSE_REEDS_MS: u32 = 1250;
+/// How long to wait for the Child ID Response.
+const CHILD_ID_RESPONSE_MS: u32 = 1250;
+
+/// Link-local all-routers multicast address, ff02::2.
+const ALL_ROUTERS: IPAddr = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x02]);
+
+/// A message is secured in place in the buffer, behind the source and
+/// destination addresses that are authenticated with it. The security suite
+/// byte takes the place of the last byte of the destination address once
+/// the message is secured, and before it is verified.
+const ADDRS_LEN: usize = 32;
+const MESSAGE_OFFSET: usize = ADDRS_LEN - 1;
+
+/// Told when attaching to a Thread network is done.
+pub trait ThreadClient {
+    /// Attaching finished. On success, `rloc16` is the short address that
+    /// the parent assigned to the device.
+    fn attach_done(&self, result: ReturnCode, rloc16: u16);
+}
+
+/// The keys of the network, derived from its master key.
+#[derive(Copy, Clone)]
+struct Keys {
+    key_sequence: u32,
+    mac_key: [u8; 16],
+    mle_key: [u8; 16],
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum State {
+    Detached,
+    /// Waiting for Parent Responses, from routers only or also from
+    /// router-eligible end devices.
+    ParentRequest { reeds: bool },
+    ChildIdRequest,
+    Child { rloc16: u16 },
+}
+
+/// A device that responded to the Parent Request.
+#[derive(Copy, Clone)]
+struct Parent {
+    ext_addr: [u8; 8],
+    rloc16: u16,
+    /// The challenge that the Child ID Request must answer.
+    challenge: [u8; 8],
+    link_margin: u8,
+    priority: i8,
+}
+
+impl Parent {
+    /// Link quality of the link margin, as in Section 4.4.5.
+    fn link_quality(&self) -> u8 {
+        match self.link_margin {
+            0..=2 => 0,
+            3..=10 => 1,
+            11..=20 => 2,
+            _ => 3,
+        }
+    }
+
+    fn is_better_than(&self, other: &Parent) -> bool {
+        (self.link_quality(), self.priority) > (other.link_quality(), other.priority)
+    }
+}
+
+/// The message in the buffer that is being secured or verified.
+#[derive(Copy, Clone)]
+enum Crypt {
+    Idle,
+    Securing { dst: IPAddr, len: usize },
+    Verifying { src: IPAddr, command_offset: usize, len: usize },
+}
+
+pub struct SleepyEndDevice<'a, A: Alarm + 'a> {
+    mac: &'a MacDevice<'a>,
+    udp_send: &'a UDPSender<'a>,
+    aes_ccm: &'a AES128CCM<'a>,
+    alarm: &'a A,
+    rng: &'a Random<'a>,
+    buffer: TakeCell<'static, [u8]>,
+    crypt: Cell<Crypt>,
+    state: Cell<State>,
+    /// Child timeout in seconds.
+    timeout: u32,
+    keys: Cell<Option<Keys>>,
+    mle_frame_counter: Cell<u32>,
+    /// The challenge of the last Parent Request.
+    challenge: Cell<[u8; 8]>,
+    /// The best parent found, and once attached, the parent.
+    parent: Cell<Option<Parent>>,
+    next_client: OptionalCell<&'a UDPRecvClient>,
+    client: OptionalCell<&'a ThreadClient>,
+}
+
+impl<A: Alarm> SleepyEndDevice<'a, A> {
+    pub fn new(
+        mac: &'a MacDevice<'a>,
+        udp_send: &'a UDPSender<'a>,
+        aes_ccm: &'a AES128CCM<'a>,
+        alarm: &'a A,
+        rng: &'a Random<'a>,
+        buffer: &'static mut [u8],
+        timeout: u32,
+    ) -> SleepyEndDevice<'a, A> {
+        SleepyEndDevice {
+            mac: mac,
+            udp_send: udp_send,
+            aes_ccm: aes_ccm,
+            alarm: alarm,
+            rng: rng,
+            buffer: TakeCell::new(buffer),
+            crypt: Cell::new(Crypt::Idle),
+            state: Cell::new(State::Detached),
+            timeout: timeout,
+            keys: Cell::new(None),
+            mle_frame_counter: Cell::new(0),
+            challenge: Cell::new([0; 8]),
+            parent: Cell::new(None),
+            next_client: OptionalCell::empty(),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a ThreadClient) {
+        self.client.set(client);
+    }
+
+    /// Sets the client that receives UDP datagrams that are not MLE
+    /// messages.
+    pub fn set_next_client(&self, client: &'a UDPRecvClient) {
+        self.next_client.set(client);
+    }
+
+    /// Derives the MAC and MLE keys of `key_sequence` from the master key of
+    /// the network, and sets them.
+    ///
+    /// The keys are the HMAC-SHA256 code of the key sequence, in big-endian
+    /// order, followed by "Thread", with the master key as the HMAC key. The
+    /// first half of the code is the MLE key and the second the MAC key.
+    pub fn set_master_key(&self, key_sequence: u32, master_key: &[u8; 16]) {
+        let sequence = [
+            (key_sequence >> 24) as u8,
+            (key_sequence >> 16) as u8,
+            (key_sequence >> 8) as u8,
+            key_sequence as u8,
+        ];
+        let mut hmac = HmacSha256::new(master_key);
+        hmac.update(&sequence);
+        hmac.update(b"Thread");
+        let mut code = [0; DIGEST_LEN];
+        hmac.finish(&mut code);
+
+        let mut mle_key = [0; 16];
+        let mut mac_key = [0; 16];
+        mle_key.copy_from_slice(&code[..16]);
+        mac_key.copy_from_slice(&code[16..]);
+        self.set_keys(key_sequence, mac_key, mle_key);
+    }
+
+    /// Sets the MAC and MLE keys of `key_sequence`, derived from the master
+    /// key of the network.
+    pub fn set_keys(&self, key_sequence: u32, mac_key: [u8; 16], mle_key: [u8; 16]) {
+        self.keys.set(Some(Keys {
+            key_sequence: key_sequence,
+            mac_key: mac_key,
+            mle_key: mle_key,
+        }));
+    }
+
+    /// The short address assigned by the parent, if attached.
+    pub fn rloc16(&self) -> Option<u16> {
+        match self.state.get() {
+            State::Child { rloc16 } => Some(rloc16),
+            _ => None,
+        }
+    }
+
+    /// Start attaching to the network. Returns `EALREADY` if the device is
+    /// attached or attaching, and `EINVAL` if the keys have not been set.
+    pub fn attach(&self) -> ReturnCode {
+        if self.state.get() != State::Detached {
+            return ReturnCode::EALREADY;
+        }
+        if self.keys.get().is_none() {
+            return ReturnCode::EINVAL;
+        }
+        let mut challenge = [0; 8];
+        let mut random = 0;
+        for (i, byte) in challenge.iter_mut().enumerate() {
+            if i % 4 == 0 {
+                random = self.rng.random();
+            }
+            *byte = (random >> (8 * (i % 4))) as u8;
+        }
+        self.challenge.set(challenge);
+        self.parent.set(None);
+        self.send_parent_request(false);
+        ReturnCode::SUCCESS
+    }
+
+    fn set_timeout(&self, ms: u32) {
+        let tics = A::Frequency::frequency() / 1000 * ms;
+        self.alarm.set_alarm(self.alarm.now().wrapping_add(tics));
+    }
+
+    fn finish(&self, result: ReturnCode, rloc16: u16) {
+        self.client.map(|client| client.attach_done(result, rloc16));
+    }
+
+    fn send_parent_request(&self, reeds: bool) {
+        let scan_mask = if reeds {
+            MulticastResponder::Router as u8 | MulticastResponder::EndDevice as u8
+        } else {
+            MulticastResponder::Router as u8
+        };
+        let challenge = self.challenge.get();
+        self.state.set(State::ParentRequest { reeds: reeds });
+        // A request that cannot be sent is treated as lost, and the timeout
+        // moves the handshake on.
+        self.send_message(ALL_ROUTERS, |buf| {
+            mle::encode_parent_request(buf, MODE, challenge, scan_mask)
+        });
+        self.set_timeout(if reeds {
+            PARENT_RESPONSE_REEDS_MS
+        } else {
+            PARENT_RESPONSE_ROUTERS_MS
+        });
+    }
+
+    fn send_child_id_request(&self, parent: Parent) {
+        let timeout = self.timeout;
+        let mle_frame_counter = self.mle_frame_counter.get();
+        self.state.set(State::ChildIdRequest);
+        // No frames are secured with the MAC key before the device is
+        // attached, so the link-layer frame counter is still 0.
+        self.send_message(mle::link_local_addr(&parent.ext_addr), |buf| {
+            mle::encode_child_id_request(
+                buf,
+                MODE,
+                timeout,
+                parent.challenge,
+                0,
+                mle_frame_counter,
+            )
+        });
+        self.set_timeout(CHILD_ID_RESPONSE_MS);
+    }
+
+    /// Encode a message with `encode`, secure it, and send it to `dst` once
+    /// it is secured.
+    fn send_message<F>(&self, dst: IPAddr, encode: F) -> ReturnCode
+    where
+        F: FnOnce(&mut [u8]) -> SResult,
+    {
+        match self.crypt.get() {
+            Crypt::Idle => {}
+            _ => return ReturnCode::EBUSY,
+        }
+        let keys = match self.keys.get() {
+            Some(keys) => keys,
+            None => return ReturnCode::EINVAL,
+        };
+        let buf = match self.buffer.take() {
+            Some(buf) => buf,
+            None => return ReturnCode::EBUSY,
+        };
+
+        let frame_counter = self.mle_frame_counter.get();
+        let security = mle::security_header(keys.key_sequence, frame_counter);
+        let end = buf.len() - mle::MIC_LEN;
+        let header = mle::encode_header(&mut buf[MESSAGE_OFFSET..end], Some(security));
+        let command_offset = match header.done() {
+            Some((offset, _)) => MESSAGE_OFFSET + offset,
+            None => {
+                self.buffer.replace(buf);
+                return ReturnCode::ESIZE;
+            }
+        };
+        let m_len = match encode(&mut buf[command_offset..end]).done() {
+            Some((len, _)) => len,
+            None => {
+                self.buffer.replace(buf);
+                return ReturnCode::ESIZE;
+            }
+        };
+
+        let ext_addr = self.mac.get_address_long();
+        let src = mle::link_local_addr(&ext_addr);
+        buf[0..16].copy_from_slice(&src.0);
+        buf[16..ADDRS_LEN].copy_from_slice(&dst.0);
+        let nonce = get_ccm_nonce(&ext_addr, frame_counter, mle::SECURITY_LEVEL);
+        if self.aes_ccm.set_key(&keys.mle_key) != ReturnCode::SUCCESS
+            || self.aes_ccm.set_nonce(&nonce) != ReturnCode::SUCCESS
+        {
+            self.buffer.replace(buf);
+            return ReturnCode::FAIL;
+        }
+        self.mle_frame_counter.set(frame_counter + 1);
+
+        let (res, buf) = self
+            .aes_ccm
+            .crypt(buf, 0, command_offset, m_len, mle::MIC_LEN, true, true);
+        if res == ReturnCode::SUCCESS {
+            self.crypt.set(Crypt::Securing {
+                dst: dst,
+                len: command_offset + m_len + mle::MIC_LEN,
+            });
+        }
+        buf.map(|buf| self.buffer.replace(buf));
+        res
+    }
+
+    /// Start verifying and decrypting a received MLE message. Returns the
+    /// buffer if the message is dropped.
+    fn verify_message(
+        &self,
+        buf: &'static mut [u8],
+        src: IPAddr,
+        dst: IPAddr,
+        message: &[u8],
+    ) -> Option<&'static mut [u8]> {
+        let keys = match self.keys.get() {
+            Some(keys) => keys,
+            None => return Some(buf),
+        };
+        let len = MESSAGE_OFFSET + message.len();
+        if len > buf.len() {
+            return Some(buf);
+        }
+        buf[MESSAGE_OFFSET..len].copy_from_slice(message);
+
+        let header = mle::decode_header(&buf[MESSAGE_OFFSET..len]);
+        let (command_offset, security) = match header.done() {
+            Some((offset, Some(security))) => (MESSAGE_OFFSET + offset, security),
+            // Unsecured messages are only used for discovery.
+            _ => return Some(buf),
+        };
+        let frame_counter = match security.frame_counter {
+            Some(frame_counter) => frame_counter,
+            None => return Some(buf),
+        };
+        if security.level != mle::SECURITY_LEVEL
+            || mle::key_sequence(&security.key_id) != Some(keys.key_sequence)
+            || len < command_offset + mle::MIC_LEN
+        {
+            return Some(buf);
+        }
+        let m_len = len - command_offset - mle::MIC_LEN;
+
+        buf[0..16].copy_from_slice(&src.0);
+        buf[16..ADDRS_LEN].copy_from_slice(&dst.0);
+        let nonce = get_ccm_nonce(&mle::ext_addr(&src), frame_counter, security.level);
+        if self.aes_ccm.set_key(&keys.mle_key) != ReturnCode::SUCCESS
+            || self.aes_ccm.set_nonce(&nonce) != ReturnCode::SUCCESS
+        {
+            return Some(buf);
+        }
+        let (res, buf) = self
+            .aes_ccm
+            .crypt(buf, 0, command_offset, m_len, mle::MIC_LEN, true, false);
+        if res == ReturnCode::SUCCESS {
+            self.crypt.set(Crypt::Verifying {
+                src: src,
+                command_offset: command_offset,
+                len: command_offset + m_len,
+            });
+        }
+        buf
+    }
+
+    /// Handle a verified and decrypted MLE message.
+    fn receive_message(&self, src: IPAddr, message: &[u8]) {
+        let (command, tlvs) = match mle::decode_command(message).done() {
+            Some((_, command)) => command,
+            None => return,
+        };
+        match (command, self.state.get()) {
+            (Command::ParentResponse, State::ParentRequest { .. }) => {
+                self.parent_response(src, tlvs)
+            }
+            (Command::ChildIdResponse, State::ChildIdRequest) => self.child_id_response(src, tlvs),
+            _ => {}
+        }
+    }
+
+    fn parent_response(&self, src: IPAddr, tlvs: Tlvs) {
+        let mut response = None;
+        let mut rloc16 = None;
+        let mut challenge = None;
+        let mut link_margin = 0;
+        let mut priority = 0;
+        for tlv in tlvs {
+            match tlv {
+                Tlv::Response(value) => response = Some(value),
+                Tlv::SourceAddress(value) => rloc16 = Some(value),
+                Tlv::Challenge(value) => challenge = Some(value),
+                Tlv::LinkMargin(value) => link_margin = value,
+                Tlv::Connectivity { parent_priority, .. } => {
+                    // The priority is a signed two-bit value in the top bits.
+                    priority = (parent_priority as i8) >> 6;
+                }
+                _ => {}
+            }
+        }
+        if response != Some(self.challenge.get()) {
+            return;
+        }
+        if let (Some(rloc16), Some(challenge)) = (rloc16, challenge) {
+            let candidate = Parent {
+                ext_addr: mle::ext_addr(&src),
+                rloc16: rloc16,
+                challenge: challenge,
+                link_margin: link_margin,
+                priority: priority,
+            };
+            let better = self
+                .parent
+                .get()
+                .map_or(true, |parent| candidate.is_better_than(&parent));
+            if better {
+                self.parent.set(Some(candidate));
+            }
+        }
+    }
+
+    fn child_id_response(&self, src: IPAddr, tlvs: Tlvs) {
+        let parent = match self.parent.get() {
+            Some(parent) => parent,
+            None => return,
+        };
+        if mle::ext_addr(&src) != parent.ext_addr {
+            return;
+        }
+        let mut rloc16 = None;
+        for tlv in tlvs {
+            match tlv {
+                Tlv::Address16(value) => rloc16 = Some(value),
+                Tlv::SourceAddress(value) => {
+                    if value != parent.rloc16 {
+                        return;
+                    }
+                }
+                _ => {}
+            }
+        }
+        if let Some(rloc16) = rloc16 {
+            self.alarm.disable();
+            self.state.set(State::Child { rloc16: rloc16 });
+            self.mac.set_address(rloc16);
+            self.mac.config_commit();
+            self.finish(ReturnCode::SUCCESS, rloc16);
+        }
+    }
+}
+
+impl<A: Alarm> time::Client for SleepyEndDevice<'a, A> {
+    fn fired(&self) {
+        match self.state.get() {
+            State::ParentRequest { reeds } => match self.parent.get() {
+                Some(parent) => self.send_child_id_request(parent),
+                None if !reeds => self.send_parent_request(true),
+                None => {
+                    self.state.set(State::Detached);
+                    self.finish(ReturnCode::FAIL, 0);
+                }
+            },
+            State::ChildIdRequest => {
+                self.state.set(State::Detached);
+                self.parent.set(None);
+                self.finish(ReturnCode::FAIL, 0);
+            }
+            State::Detached | State::Child { .. } => {}
+        }
+    }
+}
+
+impl<A: Alarm> CCMClient for SleepyEndDevice<'a, A> {
+    fn crypt_done(&self, buf: &'static mut [u8], res: ReturnCode, tag_is_valid: bool) {
+        let crypt = self.crypt.get();
+        self.crypt.set(Crypt::Idle);
+        match crypt {
+            Crypt::Securing { dst, len } => {
+                if res == ReturnCode::SUCCESS {
+                    buf[MESSAGE_OFFSET] = mle::SECURITY_SUITE_ENABLED;
+                    self.udp_send
+                        .send_to(dst, mle::MLE_PORT, mle::MLE_PORT, &buf[MESSAGE_OFFSET..len]);
+                }
+            }
+            Crypt::Verifying {
+                src,
+                command_offset,
+                len,
+            } => {
+                if res == ReturnCode::SUCCESS && tag_is_valid {
+                    self.receive_message(src, &buf[command_offset..len]);
+                }
+            }
+            Crypt::Idle => {}
+        }
+        self.buffer.replace(buf);
+    }
+}
+
+impl<A: Alarm> UDPRecvClient for SleepyEndDevice<'a, A> {
+    fn receive(
+        &self,
+        src_addr: IPAddr,
+        dst_addr: IPAddr,
+        src_port: u16,
+        dst_port: u16,
+        payload: &[u8],
+    ) {
+        if dst_port != mle::MLE_PORT {
+            self.next_client.map(|client| {
+                client.receive(src_addr, dst_addr, src_port, dst_port, payload);
+            });
+            return;
+        }
+        // MLE messages only come from neighbors. Messages that arrive while
+        // another is being secured or verified are dropped.
+        if !src_addr.is_unicast_link_local() {
+            return;
+        }
+        if let Crypt::Idle = self.crypt.get() {
+            self.buffer.take().map(|buf| {
+                if let Some(buf) = self.verify_message(buf, src_addr, dst_addr, payload) {
+                    self.buffer.replace(buf);
+                }
+            });
+        }
+    }
+}
+
+impl<A: Alarm> KeyProcedure for SleepyEndDevice<'a, A> {
+    /// Thread secures 802.15.4 frames with the MAC key, which is identified
+    /// by the key index of its key sequence.
+    fn lookup_key(&self, _level: SecurityLevel, key_id: KeyId) -> Option<([u8; 16])> {
+        self.keys.get().and_then(|keys| match key_id {
+            KeyId::Index(index) if index == mle::key_index(keys.key_sequence) => {
+                Some(keys.mac_key)
+            }
+            _ => None,
+        })
+    }
+}
+
+impl<A: Alarm> DeviceProcedure for SleepyEndDevice<'a, A> {
+    /// The only device a sleepy end device exchanges secured frames with is
+    /// its parent.
+    fn lookup_addr_long(&self, addr: MacAddress) -> Option<([u8; 8])> {
+        if self.rloc16().is_none() {
+            return None;
+        }
+        self.parent.get().and_then(|parent| match addr {
+            MacAddress::Long(ext_addr) if ext_addr == parent.ext_addr => Some(ext_addr),
+            MacAddress::Short(rloc16) if rloc16 == parent.rloc16 => Some(parent.ext_addr),
+            _ => None,
+        })
+    }
+}
@@ -1,5 +1,5 @@
 use kernel::common::cells::OptionalCell;
-use net::ipv6::ip_utils::IPAddr;
+use net::ipv6::ip_utils::{ip6_nh, IPAddr};
 use net::ipv6::ipv6::IP6Header;
 use net::ipv6::ipv6_recv::IP6RecvClient;
 use net::udp::udp::UDPHeader;
@@ -41,6 +41,9 @@ impl<'a> UDPReceiver<'a> {
 
 impl<'a> IP6RecvClient for UDPReceiver<'a> {
     fn receive(&self, ip_header: IP6Header, payload: &[u8]) {
+        if ip_header.get_next_header() != ip6_nh::UDP {
+            return;
+        }
         match UDPHeader::decode(payload).done() {
             Some((offset, udp_header)) => {
                 let len = udp_header.get_len() as usize;
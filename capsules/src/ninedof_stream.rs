@@ -0,0 +1,228 @@
+//! Streams samples of a one-shot 9DOF sensor.
+//!
+//! This implements `hil::sensors::StreamingSensor` on top of any
+//! `hil::sensors::NineDof` sensor, for sensors whose driver has no FIFO or
+//! data-ready support. An alarm starts a one-shot reading of one of the
+//! three vectors at the rate, and the readings are buffered in a software
+//! FIFO until the watermark. A reading that is still in progress when the
+//! next one is due is counted as dropped, so the rate a sensor keeps up with
+//! depends on its bus and conversion time.
+//!
+//! The adapter is the client of the sensor, so the sensor cannot also serve
+//! the `ninedof` syscall driver.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let accel_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     VirtualMuxAlarm::new(mux_alarm));
+//! let accel_stream = static_init!(
+//!     capsules::ninedof_stream::NineDofStream<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::ninedof_stream::NineDofStream::new(
+//!         fxos8700,
+//!         capsules::ninedof_stream::Vector::Accelerometer,
+//!         accel_alarm,
+//!         &mut capsules::ninedof_stream::FIFO));
+//! hil::sensors::NineDof::set_client(fxos8700, accel_stream);
+//! accel_alarm.set_client(accel_stream);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::sensors;
+use kernel::hil::time::{self, Frequency};
+use kernel::ReturnCode;
+
+/// The number of samples the FIFO holds
+pub const FIFO_SAMPLES: usize = 32;
+
+/// The values of a sample, one per axis
+const SAMPLE_SIZE: usize = 3;
+
+/// The highest rate, which one-shot readings over I2C keep up with
+const MAX_RATE: u32 = 200;
+
+pub static mut FIFO: [i16; SAMPLE_SIZE * FIFO_SAMPLES] = [0; SAMPLE_SIZE * FIFO_SAMPLES];
+
+/// The vector that is streamed
+#[derive(Clone, Copy, PartialEq)]
+pub enum Vector {
+    Accelerometer,
+    Magnetometer,
+    Gyroscope,
+}
+
+pub struct NineDofStream<'a, A: time::Alarm> {
+    sensor: &'a sensors::NineDof,
+    vector: Vector,
+    alarm: &'a A,
+    client: OptionalCell<&'static sensors::StreamingSensorClient>,
+    fifo: TakeCell<'static, [i16]>,
+    /// The number of samples in the FIFO
+    count: Cell<usize>,
+    dropped: Cell<usize>,
+    watermark: Cell<usize>,
+    identifier: Cell<usize>,
+    streaming: Cell<bool>,
+    /// Whether a reading is in progress
+    reading: Cell<bool>,
+    /// The alarm ticks between samples, and the time of the last one
+    interval: Cell<u32>,
+    tics: Cell<u32>,
+}
+
+impl<A: time::Alarm> NineDofStream<'a, A> {
+    pub fn new(
+        sensor: &'a sensors::NineDof,
+        vector: Vector,
+        alarm: &'a A,
+        fifo: &'static mut [i16],
+    ) -> NineDofStream<'a, A> {
+        NineDofStream {
+            sensor: sensor,
+            vector: vector,
+            alarm: alarm,
+            client: OptionalCell::empty(),
+            fifo: TakeCell::new(fifo),
+            count: Cell::new(0),
+            dropped: Cell::new(0),
+            watermark: Cell::new(1),
+            identifier: Cell::new(0),
+            streaming: Cell::new(false),
+            reading: Cell::new(false),
+            interval: Cell::new(0),
+            tics: Cell::new(0),
+        }
+    }
+
+    fn read(&self) -> ReturnCode {
+        match self.vector {
+            Vector::Accelerometer => self.sensor.read_accelerometer(),
+            Vector::Magnetometer => self.sensor.read_magnetometer(),
+            Vector::Gyroscope => self.sensor.read_gyroscope(),
+        }
+    }
+
+    fn deliver(&self) {
+        let count = self.count.get();
+        let dropped = self.dropped.get();
+        self.count.set(0);
+        self.dropped.set(0);
+        self.fifo.map(|fifo| {
+            self.client.map(|client| {
+                client.samples_ready(self.identifier.get(), &fifo[..count * SAMPLE_SIZE], dropped)
+            });
+        });
+    }
+}
+
+impl<A: time::Alarm> time::Client for NineDofStream<'a, A> {
+    fn fired(&self) {
+        if !self.streaming.get() {
+            return;
+        }
+        let tics = self.tics.get().wrapping_add(self.interval.get());
+        self.tics.set(tics);
+        self.alarm.set_alarm(tics);
+
+        if self.reading.get() {
+            self.dropped.set(self.dropped.get() + 1);
+        } else if self.read() == ReturnCode::SUCCESS {
+            self.reading.set(true);
+        } else {
+            self.dropped.set(self.dropped.get() + 1);
+        }
+    }
+}
+
+impl<A: time::Alarm> sensors::NineDofClient for NineDofStream<'a, A> {
+    fn callback(&self, x: usize, y: usize, z: usize) {
+        self.reading.set(false);
+        if !self.streaming.get() {
+            return;
+        }
+
+        let count = self.count.get();
+        if count == FIFO_SAMPLES {
+            self.dropped.set(self.dropped.get() + 1);
+            return;
+        }
+        self.fifo.map(|fifo| {
+            let sample = &mut fifo[count * SAMPLE_SIZE..(count + 1) * SAMPLE_SIZE];
+            sample[0] = x as i16;
+            sample[1] = y as i16;
+            sample[2] = z as i16;
+        });
+        self.count.set(count + 1);
+
+        if count + 1 >= self.watermark.get() {
+            self.deliver();
+        }
+    }
+}
+
+impl<A: time::Alarm> sensors::StreamingSensor for NineDofStream<'a, A> {
+    fn set_client(&self, client: &'static sensors::StreamingSensorClient) {
+        self.client.set(client);
+    }
+
+    fn sample_size(&self) -> usize {
+        SAMPLE_SIZE
+    }
+
+    fn get_maximum_rate(&self) -> u32 {
+        MAX_RATE
+    }
+
+    fn get_fifo_size(&self) -> usize {
+        FIFO_SAMPLES
+    }
+
+    fn start(&self, identifier: usize, rate: u32, watermark: usize) -> ReturnCode {
+        if self.streaming.get() {
+            return ReturnCode::EBUSY;
+        }
+        if rate == 0 || watermark == 0 || watermark > FIFO_SAMPLES {
+            return ReturnCode::EINVAL;
+        }
+        let rate = if rate > MAX_RATE { MAX_RATE } else { rate };
+        // Round the interval up, so the rate does not exceed the request
+        let frequency = A::Frequency::frequency();
+        let interval = (frequency + rate - 1) / rate;
+
+        self.identifier.set(identifier);
+        self.watermark.set(watermark);
+        self.count.set(0);
+        self.dropped.set(0);
+        self.interval.set(interval);
+        self.streaming.set(true);
+        let tics = self.alarm.now().wrapping_add(interval);
+        self.tics.set(tics);
+        self.alarm.set_alarm(tics);
+        ReturnCode::SuccessWithValue {
+            value: (frequency / interval) as usize,
+        }
+    }
+
+    fn stop(&self) -> ReturnCode {
+        if !self.streaming.get() {
+            return ReturnCode::EOFF;
+        }
+        self.alarm.disable();
+        self.streaming.set(false);
+        self.count.set(0);
+        ReturnCode::SUCCESS
+    }
+
+    fn flush(&self) -> ReturnCode {
+        if !self.streaming.get() {
+            return ReturnCode::EOFF;
+        }
+        if self.count.get() > 0 || self.dropped.get() > 0 {
+            self.deliver();
+        }
+        ReturnCode::SUCCESS
+    }
+}
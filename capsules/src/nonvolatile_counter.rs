@@ -0,0 +1,442 @@
+//! Counter in flash that survives resets and power loss.
+//!
+//! This capsule keeps a counter that only counts up, for sequence numbers or
+//! for counting failed unlock attempts, in two flash pages. The counter is
+//! available to the kernel through `hil::nonvolatile_counter` and to
+//! processes as a syscall driver. Increments of the kernel and of processes
+//! are queued and run one at a time, so each value is returned once.
+//!
+//! Each increment writes the new value, with a CRC-32, to the page that does
+//! not hold the current value. If the board loses power while writing, the
+//! other page still holds the old value, and at boot the larger valid value
+//! of the two pages is used. A value is only returned once it is stored, so
+//! a returned value is never returned again. Each increment erases a page,
+//! so the counter should not be incremented more often than the flash
+//! endurance allows.
+//!
+//! Processes could otherwise wear out the flash, or run up a counter the
+//! kernel counts failed unlock attempts with, so their increments are rate
+//! limited: all processes together can start one increment per interval
+//! that the board chooses. Increments of the kernel are not limited.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! pub static mut COUNTER_PAGE: nrf52::nvmc::NrfPage = nrf52::nvmc::NrfPage::new();
+//!
+//! let counter_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, nrf5x::rtc::Rtc>,
+//!     VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! let counter = static_init!(
+//!     capsules::nonvolatile_counter::NonvolatileCounter<
+//!         'static,
+//!         nrf52::nvmc::Nvmc,
+//!         VirtualMuxAlarm<'static, nrf5x::rtc::Rtc>,
+//!     >,
+//!     capsules::nonvolatile_counter::NonvolatileCounter::new(
+//!         &nrf52::nvmc::NVMC,
+//!         COUNTER_FIRST_PAGE,
+//!         &mut COUNTER_PAGE,
+//!         counter_alarm,
+//!         60 * 60 * 1000, // One increment of processes an hour
+//!         board_kernel.create_grant(&memory_allocation_capability)
+//!     )
+//! );
+//! hil::flash::HasClient::set_client(&nrf52::nvmc::NVMC, counter);
+//! counter.mount();
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil;
+use kernel::hil::nonvolatile_counter::NonvolatileCounterClient;
+use kernel::hil::time::{Frequency, Time64};
+use kernel::{AppId, Callback, Driver, Grant, ReturnCode};
+use log_storage::crc32;
+
+/// Syscall number
+pub const DRIVER_NUM: usize = 0x50007;
+
+/// Identifies a page holding a value of the counter
+const MAGIC: u32 = 0x544E_4331;
+
+fn read_u32(buffer: &[u8], offset: usize) -> u32 {
+    buffer[offset] as u32
+        | (buffer[offset + 1] as u32) << 8
+        | (buffer[offset + 2] as u32) << 16
+        | (buffer[offset + 3] as u32) << 24
+}
+
+fn write_u32(buffer: &mut [u8], offset: usize, value: u32) {
+    for i in 0..4 {
+        buffer[offset + i] = (value >> (8 * i)) as u8;
+    }
+}
+
+/// The value stored in `page`, if it holds one. A page holds the magic
+/// number, the value, and the CRC-32 of both.
+fn page_value(page: &[u8]) -> Option<u32> {
+    if read_u32(page, 0) != MAGIC || crc32(&page[0..8]) != read_u32(page, 8) {
+        return None;
+    }
+    Some(read_u32(page, 4))
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Unmounted,
+    /// Reading the two pages to find the current value
+    Mount { page: usize },
+    Idle,
+    /// Writing `value` to `page`
+    Commit { page: usize, value: u32 },
+}
+
+#[derive(Clone, Copy)]
+enum User {
+    App { app_id: AppId },
+    Kernel,
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    pending_increment: bool,
+}
+
+pub struct NonvolatileCounter<'a, F: hil::flash::Flash + 'static, T: Time64 + 'a> {
+    flash: &'a F,
+    /// The first of the two flash pages of the counter
+    first_page: usize,
+    /// The buffer for the page read or written
+    page: TakeCell<'static, F::Page>,
+    state: Cell<State>,
+    value: Cell<u32>,
+    /// The page holding the current value
+    current: Cell<usize>,
+    /// The value in the first page, if it holds one
+    first_value: Cell<Option<u32>>,
+    apps: Grant<App>,
+    time: &'a T,
+    /// The ticks of `time` that must pass between increments of processes,
+    /// and when the last one was started
+    app_interval: u64,
+    last_app_increment: Cell<Option<u64>>,
+    /// Who asked for the increment in progress
+    current_user: OptionalCell<User>,
+    kernel_client: OptionalCell<&'static NonvolatileCounterClient>,
+    /// Whether the kernel waits for an increment
+    kernel_pending_increment: Cell<bool>,
+}
+
+impl<F: hil::flash::Flash, T: Time64> NonvolatileCounter<'a, F, T> {
+    /// Processes can start an increment every `app_interval_ms` milliseconds.
+    pub fn new(
+        flash: &'a F,
+        first_page: usize,
+        page: &'static mut F::Page,
+        time: &'a T,
+        app_interval_ms: u32,
+        apps: Grant<App>,
+    ) -> NonvolatileCounter<'a, F, T> {
+        NonvolatileCounter {
+            flash: flash,
+            first_page: first_page,
+            page: TakeCell::new(page),
+            state: Cell::new(State::Unmounted),
+            value: Cell::new(0),
+            current: Cell::new(1),
+            first_value: Cell::new(None),
+            apps: apps,
+            time: time,
+            app_interval: app_interval_ms as u64 * T::Frequency::frequency() as u64 / 1000,
+            last_app_increment: Cell::new(None),
+            current_user: OptionalCell::empty(),
+            kernel_client: OptionalCell::empty(),
+            kernel_pending_increment: Cell::new(false),
+        }
+    }
+
+    /// Reads the current value from flash. The counter can be used once both
+    /// pages were read.
+    pub fn mount(&self) -> ReturnCode {
+        if self.state.get() != State::Unmounted {
+            return ReturnCode::EALREADY;
+        }
+        self.read_page(0)
+    }
+
+    fn read_page(&self, index: usize) -> ReturnCode {
+        self.page.take().map_or(ReturnCode::EBUSY, |page| {
+            let result = self.flash.read_page(self.first_page + index, page);
+            if result == ReturnCode::SUCCESS {
+                self.state.set(State::Mount { page: index });
+            }
+            result
+        })
+    }
+
+    fn mounted(&self) -> bool {
+        match self.state.get() {
+            State::Unmounted | State::Mount { .. } => false,
+            _ => true,
+        }
+    }
+
+    /// Starts an increment, or queues it if one is in progress.
+    fn enqueue_increment(&self, user: User) -> ReturnCode {
+        if !self.mounted() {
+            return ReturnCode::EOFF;
+        }
+        if self.current_user.is_none() {
+            return self.start_increment(user);
+        }
+        match user {
+            User::Kernel => {
+                if self.kernel_pending_increment.get() {
+                    ReturnCode::EBUSY
+                } else {
+                    self.kernel_pending_increment.set(true);
+                    ReturnCode::SUCCESS
+                }
+            }
+            User::App { app_id } => self
+                .apps
+                .enter(app_id, |app, _| {
+                    if app.pending_increment {
+                        ReturnCode::EBUSY
+                    } else {
+                        app.pending_increment = true;
+                        ReturnCode::SUCCESS
+                    }
+                }).unwrap_or_else(|err| err.into()),
+        }
+    }
+
+    /// Writes the next value to the page that does not hold the current
+    /// value.
+    fn start_increment(&self, user: User) -> ReturnCode {
+        let value = match self.value.get().checked_add(1) {
+            Some(value) => value,
+            None => return ReturnCode::ESIZE,
+        };
+        let index = 1 - self.current.get();
+        self.page.take().map_or(ReturnCode::EBUSY, |page| {
+            {
+                let contents = page.as_mut();
+                write_u32(contents, 0, MAGIC);
+                write_u32(contents, 4, value);
+                let crc = crc32(&contents[0..8]);
+                write_u32(contents, 8, crc);
+            }
+            let result = self.flash.write_page(self.first_page + index, page);
+            if result == ReturnCode::SUCCESS {
+                self.state.set(State::Commit {
+                    page: index,
+                    value: value,
+                });
+                self.current_user.set(user);
+            }
+            result
+        })
+    }
+
+    fn notify(&self, user: User, result: ReturnCode) {
+        let value = self.value.get() as usize;
+        match user {
+            User::Kernel => {
+                self.kernel_client
+                    .map(|client| client.increment_done(value, result));
+            }
+            User::App { app_id } => {
+                let _ = self.apps.enter(app_id, |app, _| {
+                    app.callback
+                        .map(|mut cb| cb.schedule(usize::from(result), value, 0));
+                });
+            }
+        }
+    }
+
+    /// Starts the next queued increment. Increments that cannot be started
+    /// are ended with their error.
+    fn check_queue(&self) {
+        if self.kernel_pending_increment.get() {
+            self.kernel_pending_increment.set(false);
+            let result = self.start_increment(User::Kernel);
+            if result == ReturnCode::SUCCESS {
+                return;
+            }
+            self.notify(User::Kernel, result);
+        }
+        for cntr in self.apps.iter() {
+            let app_id = cntr.enter(|app, _| {
+                if app.pending_increment {
+                    app.pending_increment = false;
+                    Some(app.appid())
+                } else {
+                    None
+                }
+            });
+            if let Some(app_id) = app_id {
+                let user = User::App { app_id: app_id };
+                let result = self.start_increment(user);
+                if result == ReturnCode::SUCCESS {
+                    return;
+                }
+                self.notify(user, result);
+            }
+        }
+    }
+}
+
+impl<F: hil::flash::Flash, T: Time64> hil::nonvolatile_counter::NonvolatileCounter
+    for NonvolatileCounter<'a, F, T>
+{
+    fn set_client(&self, client: &'static NonvolatileCounterClient) {
+        self.kernel_client.set(client);
+    }
+
+    fn get(&self) -> Option<usize> {
+        if self.mounted() {
+            Some(self.value.get() as usize)
+        } else {
+            None
+        }
+    }
+
+    fn increment(&self) -> ReturnCode {
+        self.enqueue_increment(User::Kernel)
+    }
+}
+
+impl<F: hil::flash::Flash, T: Time64> Driver for NonvolatileCounter<'a, F, T> {
+    /// Subscribe to the end of increments.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: An increment of the process ended. The callback is passed the
+    ///        result and the value of the counter.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        appid: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Read and increment the counter.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Get the value of the counter.
+    /// - `2`: Increment the counter. Returns EBUSY if the process already
+    ///        waits for an increment, or if an increment of a process was
+    ///        started less than the interval of the board ago.
+    fn command(&self, command_num: usize, _: usize, _: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            1 => {
+                if self.mounted() {
+                    ReturnCode::SuccessWithValue {
+                        value: self.value.get() as usize,
+                    }
+                } else {
+                    ReturnCode::EOFF
+                }
+            }
+
+            2 => {
+                let now = self.time.now64();
+                let limited = self
+                    .last_app_increment
+                    .get()
+                    .map_or(false, |last| now - last < self.app_interval);
+                if limited {
+                    return ReturnCode::EBUSY;
+                }
+                let result = self.enqueue_increment(User::App { app_id: appid });
+                if result == ReturnCode::SUCCESS {
+                    self.last_app_increment.set(Some(now));
+                }
+                result
+            }
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+impl<F: hil::flash::Flash, T: Time64> hil::flash::Client<F> for NonvolatileCounter<'a, F, T> {
+    fn read_complete(&self, page: &'static mut F::Page, error: hil::flash::Error) {
+        let value = if error == hil::flash::Error::CommandComplete {
+            page_value(page.as_mut())
+        } else {
+            None
+        };
+        self.page.replace(page);
+        if let State::Mount { page } = self.state.get() {
+            if page == 0 {
+                self.first_value.set(value);
+                if self.read_page(1) == ReturnCode::SUCCESS {
+                    return;
+                }
+            }
+            let first = self.first_value.get();
+            let second = if page == 1 { value } else { None };
+            match (first, second) {
+                (Some(first), Some(second)) if second > first => {
+                    self.value.set(second);
+                    self.current.set(1);
+                }
+                (None, Some(second)) => {
+                    self.value.set(second);
+                    self.current.set(1);
+                }
+                (Some(first), _) => {
+                    self.value.set(first);
+                    self.current.set(0);
+                }
+                // Neither page holds a value, so the counter starts at zero
+                // and its first value is written to the first page
+                (None, None) => {
+                    self.value.set(0);
+                    self.current.set(1);
+                }
+            }
+            self.state.set(State::Idle);
+        }
+    }
+
+    fn write_complete(&self, page: &'static mut F::Page, error: hil::flash::Error) {
+        self.page.replace(page);
+        if let State::Commit { page, value } = self.state.get() {
+            self.state.set(State::Idle);
+            let result = if error == hil::flash::Error::CommandComplete {
+                self.value.set(value);
+                self.current.set(page);
+                ReturnCode::SUCCESS
+            } else {
+                ReturnCode::FAIL
+            };
+            self.current_user
+                .take()
+                .map(|user| self.notify(user, result));
+            self.check_queue();
+        }
+    }
+
+    fn erase_complete(&self, _error: hil::flash::Error) {}
+}
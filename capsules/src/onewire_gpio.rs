@@ -0,0 +1,423 @@
+//! 1-Wire bus master bit-banged over a GPIO pin.
+//!
+//! The data line needs an external pull-up. The pin emulates an open-drain
+//! output: it is an output driven low to pull the line low, and an input to
+//! release it.
+//!
+//! Bits are sent in time slots of about 70 us that the master starts by
+//! pulling the line low. The hardware alarm times them: the few
+//! microseconds before the line is released or sampled are waited for by
+//! polling the counter of the alarm, and the rest of each slot by setting
+//! the alarm, so the kernel keeps running between bits. The alarm must
+//! count at 1 MHz or more for this, such as a 16 MHz timer; an alarm that
+//! also serves other clients works, as long as their callbacks are short.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let onewire_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, tm4c129x::gpt::AlarmTimer>,
+//!     VirtualMuxAlarm::new(mux_alarm));
+//! let onewire = static_init!(
+//!     capsules::onewire_gpio::OneWireGpio<'static,
+//!         VirtualMuxAlarm<'static, tm4c129x::gpt::AlarmTimer>>,
+//!     capsules::onewire_gpio::OneWireGpio::new(&tm4c129x::gpio::PA[4],
+//!                                              onewire_alarm));
+//! onewire_alarm.set_client(onewire);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::gpio;
+use kernel::hil::onewire;
+use kernel::hil::time::{self, Frequency};
+use kernel::ReturnCode;
+
+/// Timing of the standard speed, in microseconds
+const RESET_LOW_US: u32 = 480;
+const PRESENCE_SAMPLE_US: u32 = 70;
+const RESET_RECOVERY_US: u32 = 410;
+const SLOT_START_US: u32 = 6;
+const READ_SAMPLE_US: u32 = 9;
+const WRITE_ZERO_LOW_US: u32 = 60;
+const SLOT_US: u32 = 70;
+const RECOVERY_US: u32 = 10;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Op {
+    Idle,
+    Reset,
+    Write,
+    Read,
+    /// The reset, then the Search ROM command, then the ROM code
+    SearchReset,
+    SearchCommand,
+    SearchRom,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    ResetLow,
+    PresenceSample,
+    ResetRecovery,
+    /// A 0 is being written, and the line is released after this
+    WriteZeroLow,
+    /// The rest of a slot, after which the next one starts
+    SlotEnd,
+}
+
+/// The three slots for each bit of the ROM code during a search
+#[derive(Clone, Copy, PartialEq)]
+enum SearchStep {
+    ReadBit,
+    ReadComplement,
+    WriteDirection,
+}
+
+pub struct OneWireGpio<'a, A: time::Alarm> {
+    pin: &'a gpio::Pin,
+    alarm: &'a A,
+    op: Cell<Op>,
+    state: Cell<State>,
+    /// Whether a device answered the last reset
+    present: Cell<bool>,
+    /// The bit of the transfer the next slot is for
+    bit: Cell<usize>,
+    len: Cell<usize>,
+    buffer: TakeCell<'static, [u8]>,
+
+    /// The ROM code found so far
+    rom: Cell<u64>,
+    search_step: Cell<SearchStep>,
+    /// The value of the current ROM bit read in the first slot
+    id_bit: Cell<bool>,
+    /// The position of the last discrepancy where the previous search took
+    /// the 0 branch, counting from 1, or 0 if there was none
+    last_discrepancy: Cell<usize>,
+    /// The same for the search in progress
+    last_zero: Cell<usize>,
+    last_device: Cell<bool>,
+
+    client: OptionalCell<&'static onewire::Client>,
+}
+
+impl<A: time::Alarm> OneWireGpio<'a, A> {
+    pub fn new(pin: &'a gpio::Pin, alarm: &'a A) -> OneWireGpio<'a, A> {
+        OneWireGpio {
+            pin: pin,
+            alarm: alarm,
+            op: Cell::new(Op::Idle),
+            state: Cell::new(State::Idle),
+            present: Cell::new(false),
+            bit: Cell::new(0),
+            len: Cell::new(0),
+            buffer: TakeCell::empty(),
+            rom: Cell::new(0),
+            search_step: Cell::new(SearchStep::ReadBit),
+            id_bit: Cell::new(false),
+            last_discrepancy: Cell::new(0),
+            last_zero: Cell::new(0),
+            last_device: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    fn ticks(us: u32) -> u32 {
+        let ticks = us as u64 * A::Frequency::frequency() as u64;
+        ((ticks + 999_999) / 1_000_000) as u32
+    }
+
+    fn set_alarm_us(&self, us: u32) {
+        let tics = self.alarm.now().wrapping_add(Self::ticks(us));
+        self.alarm.set_alarm(tics);
+    }
+
+    fn spin_us(&self, us: u32) {
+        let start = self.alarm.now();
+        let ticks = Self::ticks(us);
+        while self.alarm.now().wrapping_sub(start) < ticks {}
+    }
+
+    fn pull_low(&self) {
+        self.pin.make_output();
+        self.pin.clear();
+    }
+
+    fn release(&self) {
+        self.pin.make_input();
+    }
+
+    fn start_reset(&self) {
+        self.pull_low();
+        self.state.set(State::ResetLow);
+        self.set_alarm_us(RESET_LOW_US);
+    }
+
+    fn write_slot(&self, bit: bool) {
+        self.pull_low();
+        if bit {
+            self.spin_us(SLOT_START_US);
+            self.release();
+            self.state.set(State::SlotEnd);
+            self.set_alarm_us(SLOT_US - SLOT_START_US);
+        } else {
+            self.state.set(State::WriteZeroLow);
+            self.set_alarm_us(WRITE_ZERO_LOW_US);
+        }
+    }
+
+    fn read_slot(&self) -> bool {
+        self.pull_low();
+        self.spin_us(SLOT_START_US);
+        self.release();
+        self.spin_us(READ_SAMPLE_US);
+        let bit = self.pin.read();
+        self.state.set(State::SlotEnd);
+        self.set_alarm_us(SLOT_US - SLOT_START_US - READ_SAMPLE_US);
+        bit
+    }
+
+    /// Starts the next slot of the operation, or ends it.
+    fn next_slot(&self) {
+        let bit = self.bit.get();
+        match self.op.get() {
+            Op::Write => {
+                if bit < self.len.get() * 8 {
+                    self.bit.set(bit + 1);
+                    let value = self.buffer.map_or(false, |buffer| {
+                        buffer[bit / 8] & 1 << (bit % 8) != 0
+                    });
+                    self.write_slot(value);
+                } else {
+                    self.finish(ReturnCode::SUCCESS);
+                }
+            }
+            Op::Read => {
+                if bit < self.len.get() * 8 {
+                    self.bit.set(bit + 1);
+                    let value = self.read_slot();
+                    self.buffer.map(|buffer| {
+                        if value {
+                            buffer[bit / 8] |= 1 << (bit % 8);
+                        } else {
+                            buffer[bit / 8] &= !(1 << (bit % 8));
+                        }
+                    });
+                } else {
+                    self.finish(ReturnCode::SUCCESS);
+                }
+            }
+            Op::SearchCommand => {
+                if bit < 8 {
+                    self.bit.set(bit + 1);
+                    self.write_slot(onewire::SEARCH_ROM & 1 << bit != 0);
+                } else {
+                    self.op.set(Op::SearchRom);
+                    self.bit.set(0);
+                    self.search_step.set(SearchStep::ReadBit);
+                    self.last_zero.set(0);
+                    self.next_slot();
+                }
+            }
+            Op::SearchRom => self.search_slot(),
+            _ => {}
+        }
+    }
+
+    /// Finds one bit of the ROM code, following the search algorithm of
+    /// Maxim application note 187.
+    fn search_slot(&self) {
+        let bit = self.bit.get();
+        if bit == 64 {
+            self.last_discrepancy.set(self.last_zero.get());
+            if self.last_zero.get() == 0 {
+                self.last_device.set(true);
+            }
+            self.finish(ReturnCode::SUCCESS);
+            return;
+        }
+
+        match self.search_step.get() {
+            SearchStep::ReadBit => {
+                self.id_bit.set(self.read_slot());
+                self.search_step.set(SearchStep::ReadComplement);
+            }
+            SearchStep::ReadComplement => {
+                let id_bit = self.id_bit.get();
+                let complement = self.read_slot();
+                if id_bit && complement {
+                    // No device is taking part in the search anymore
+                    self.finish(ReturnCode::ENODEVICE);
+                    return;
+                }
+                let position = bit + 1;
+                let direction = if id_bit != complement {
+                    id_bit
+                } else if position < self.last_discrepancy.get() {
+                    self.rom.get() & 1 << bit != 0
+                } else {
+                    position == self.last_discrepancy.get()
+                };
+                if id_bit == complement && !direction {
+                    self.last_zero.set(position);
+                }
+                let rom = self.rom.get() & !(1 << bit);
+                self.rom.set(rom | (direction as u64) << bit);
+                self.search_step.set(SearchStep::WriteDirection);
+            }
+            SearchStep::WriteDirection => {
+                self.bit.set(bit + 1);
+                self.search_step.set(SearchStep::ReadBit);
+                self.write_slot(self.rom.get() & 1 << bit != 0);
+            }
+        }
+    }
+
+    fn finish(&self, result: ReturnCode) {
+        let op = self.op.get();
+        self.op.set(Op::Idle);
+        self.state.set(State::Idle);
+        match op {
+            Op::Write => {
+                self.buffer.take().map(|buffer| {
+                    self.client
+                        .map(move |client| client.write_done(buffer, result));
+                });
+            }
+            Op::Read => {
+                self.buffer.take().map(|buffer| {
+                    self.client
+                        .map(move |client| client.read_done(buffer, result));
+                });
+            }
+            Op::SearchReset | Op::SearchCommand | Op::SearchRom => {
+                let rom = self.rom.get();
+                let mut bytes = [0; 8];
+                for i in 0..8 {
+                    bytes[i] = (rom >> (8 * i)) as u8;
+                }
+                let result = if result == ReturnCode::SUCCESS && onewire::crc8(&bytes) != 0 {
+                    ReturnCode::FAIL
+                } else {
+                    result
+                };
+                if result != ReturnCode::SUCCESS {
+                    // Start over with the next search
+                    self.last_discrepancy.set(0);
+                    self.last_device.set(false);
+                }
+                let last = self.last_device.get();
+                self.client
+                    .map(|client| client.search_done(rom, last, result));
+            }
+            _ => {}
+        }
+    }
+
+    fn start_transfer(
+        &self,
+        op: Op,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.op.get() != Op::Idle {
+            return (ReturnCode::EBUSY, Some(buffer));
+        }
+        if len > buffer.len() {
+            return (ReturnCode::ESIZE, Some(buffer));
+        }
+        self.op.set(op);
+        self.bit.set(0);
+        self.len.set(len);
+        self.buffer.replace(buffer);
+        self.next_slot();
+        (ReturnCode::SUCCESS, None)
+    }
+}
+
+impl<A: time::Alarm> onewire::OneWire for OneWireGpio<'a, A> {
+    fn set_client(&self, client: &'static onewire::Client) {
+        self.client.set(client);
+    }
+
+    fn reset(&self) -> ReturnCode {
+        if self.op.get() != Op::Idle {
+            return ReturnCode::EBUSY;
+        }
+        self.op.set(Op::Reset);
+        self.start_reset();
+        ReturnCode::SUCCESS
+    }
+
+    fn write(
+        &self,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        self.start_transfer(Op::Write, buffer, len)
+    }
+
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        self.start_transfer(Op::Read, buffer, len)
+    }
+
+    fn search(&self, restart: bool) -> ReturnCode {
+        if self.op.get() != Op::Idle {
+            return ReturnCode::EBUSY;
+        }
+        if restart || self.last_device.get() {
+            self.last_discrepancy.set(0);
+            self.last_device.set(false);
+        }
+        self.op.set(Op::SearchReset);
+        self.start_reset();
+        ReturnCode::SUCCESS
+    }
+}
+
+impl<A: time::Alarm> time::Client for OneWireGpio<'a, A> {
+    fn fired(&self) {
+        match self.state.get() {
+            State::Idle => {}
+            State::ResetLow => {
+                self.release();
+                self.state.set(State::PresenceSample);
+                self.set_alarm_us(PRESENCE_SAMPLE_US);
+            }
+            State::PresenceSample => {
+                // Devices answer by holding the line low
+                self.present.set(!self.pin.read());
+                self.state.set(State::ResetRecovery);
+                self.set_alarm_us(RESET_RECOVERY_US);
+            }
+            State::ResetRecovery => {
+                let present = self.present.get();
+                self.bit.set(0);
+                if self.op.get() == Op::SearchReset {
+                    if present {
+                        self.op.set(Op::SearchCommand);
+                        self.next_slot();
+                    } else {
+                        self.finish(ReturnCode::ENODEVICE);
+                    }
+                } else {
+                    self.op.set(Op::Idle);
+                    self.state.set(State::Idle);
+                    self.client.map(|client| client.reset_done(present));
+                }
+            }
+            State::WriteZeroLow => {
+                self.release();
+                self.state.set(State::SlotEnd);
+                self.set_alarm_us(RECOVERY_US);
+            }
+            State::SlotEnd => self.next_slot(),
+        }
+    }
+}
@@ -0,0 +1,344 @@
+//! Shows the output of a kernel panic on a screen, for devices without a
+//! serial port in the field.
+//!
+//! `PanicScreen` is a `kernel::debug::PanicSink`. When the kernel panics it
+//! clears the screen and draws the panic output, the message and the state
+//! of the processes, as text in a 5x7 font, from the top left. What does not
+//! fit on the screen is dropped, so the start of the output, with the
+//! message, stays visible. Since interrupts are not serviced after a panic,
+//! the board provides a `poll` function that runs the interrupt handlers of
+//! the screen and its bus once they are pending.
+//!
+//! The screen can have other users while the kernel runs: `PanicScreen` is
+//! the client of the screen, and passes the callbacks on to the client set
+//! with `set_client` until the kernel panics.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let panic_screen = static_init!(
+//!     capsules::panic_screen::PanicScreen<'static>,
+//!     capsules::panic_screen::PanicScreen::new(
+//!         screen,
+//!         &mut capsules::panic_screen::BUFFER,
+//!         &poll_screen));
+//! screen.set_client(panic_screen);
+//! panic_screen.set_client(screen_driver);
+//! kernel::debug::add_panic_sink(panic_screen);
+//! ```
+
+use core::cell::Cell;
+use core::panic::PanicInfo;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::debug::PanicSink;
+use kernel::hil::screen::{self, ScreenPixelFormat};
+use kernel::ReturnCode;
+
+/// The size of a character, with a column and a row of spacing.
+const CHAR_WIDTH: usize = 6;
+const CHAR_HEIGHT: usize = 8;
+
+/// A character, in the largest pixel format.
+pub static mut BUFFER: [u8; CHAR_WIDTH * CHAR_HEIGHT * 2] = [0; CHAR_WIDTH * CHAR_HEIGHT * 2];
+
+/// The polls to wait for an operation of the screen, after which it is
+/// considered hung, so the panic LED still blinks.
+const MAX_POLLS: usize = 1_000_000;
+
+pub struct PanicScreen<'a> {
+    screen: &'a screen::Screen,
+    buffer: TakeCell<'static, [u8]>,
+    poll: &'a Fn(),
+    client: OptionalCell<&'static screen::ScreenClient>,
+    panicking: Cell<bool>,
+    /// Whether a write of the panic output is in progress
+    writing: Cell<bool>,
+    /// Whether the last operation of the panic output completed
+    done: Cell<bool>,
+    /// The position of the next character
+    column: Cell<usize>,
+    row: Cell<usize>,
+}
+
+impl PanicScreen<'a> {
+    pub fn new(
+        screen: &'a screen::Screen,
+        buffer: &'static mut [u8],
+        poll: &'a Fn(),
+    ) -> PanicScreen<'a> {
+        PanicScreen {
+            screen: screen,
+            buffer: TakeCell::new(buffer),
+            poll: poll,
+            client: OptionalCell::empty(),
+            panicking: Cell::new(false),
+            writing: Cell::new(false),
+            done: Cell::new(false),
+            column: Cell::new(0),
+            row: Cell::new(0),
+        }
+    }
+
+    /// Sets the client that receives the callbacks of the screen until the
+    /// kernel panics.
+    pub fn set_client(&self, client: &'static screen::ScreenClient) {
+        self.client.set(client);
+    }
+
+    /// The number of columns and rows of characters.
+    fn size(&self) -> (usize, usize) {
+        let (width, height) = self.screen.get_resolution();
+        (width / CHAR_WIDTH, height / CHAR_HEIGHT)
+    }
+
+    /// Polls until the operation in progress completes. Returns whether it
+    /// completed.
+    fn wait(&self) -> bool {
+        for _ in 0..MAX_POLLS {
+            if self.done.get() {
+                return true;
+            }
+            (self.poll)();
+        }
+        false
+    }
+
+    /// Draws a character at a position, in characters.
+    fn draw(&self, column: usize, row: usize, c: char) {
+        let format = self.screen.get_pixel_format();
+        let len = CHAR_WIDTH * CHAR_HEIGHT * format.bits_per_pixel() / 8;
+        let buffer = match self.buffer.take() {
+            Some(buffer) => buffer,
+            None => return,
+        };
+        if buffer.len() < len {
+            self.buffer.replace(buffer);
+            return;
+        }
+        render(&mut buffer[..len], format, glyph(c));
+
+        // An operation from before the panic may still be in progress
+        let mut polls = 0;
+        let rcode = loop {
+            self.done.set(false);
+            let rcode = self.screen.set_write_frame(
+                column * CHAR_WIDTH,
+                row * CHAR_HEIGHT,
+                CHAR_WIDTH,
+                CHAR_HEIGHT,
+            );
+            if rcode != ReturnCode::EBUSY || polls == MAX_POLLS {
+                break rcode;
+            }
+            (self.poll)();
+            polls += 1;
+        };
+        if rcode != ReturnCode::SUCCESS || !self.wait() {
+            self.buffer.replace(buffer);
+            return;
+        }
+
+        self.done.set(false);
+        self.writing.set(true);
+        if self.screen.write(buffer, len) == ReturnCode::SUCCESS {
+            self.wait();
+        } else {
+            self.writing.set(false);
+        }
+    }
+}
+
+/// The columns of a character in the font, least significant bit at the top.
+/// Characters that are not in the font show as `?`.
+fn glyph(c: char) -> &'static [u8; 5] {
+    let index = c as usize;
+    if index >= 0x20 && index < 0x20 + FONT.len() {
+        &FONT[index - 0x20]
+    } else {
+        &FONT['?' as usize - 0x20]
+    }
+}
+
+/// Draws a glyph, lit on black, into the pixels of a character.
+fn render(buffer: &mut [u8], format: ScreenPixelFormat, glyph: &[u8; 5]) {
+    for byte in buffer.iter_mut() {
+        *byte = 0;
+    }
+    for y in 0..CHAR_HEIGHT {
+        for x in 0..CHAR_WIDTH {
+            let lit = x < glyph.len() && (glyph[x] >> y) & 1 != 0;
+            if !lit {
+                continue;
+            }
+            let pixel = y * CHAR_WIDTH + x;
+            match format {
+                ScreenPixelFormat::Mono => buffer[pixel / 8] |= 0x80 >> (pixel % 8),
+                ScreenPixelFormat::Rgb565 => {
+                    buffer[2 * pixel] = 0xff;
+                    buffer[2 * pixel + 1] = 0xff;
+                }
+            }
+        }
+    }
+}
+
+impl PanicSink for PanicScreen<'a> {
+    fn start(&self, _panic_info: &PanicInfo) {
+        self.panicking.set(true);
+        self.column.set(0);
+        self.row.set(0);
+        let (columns, rows) = self.size();
+        for row in 0..rows {
+            for column in 0..columns {
+                self.draw(column, row, ' ');
+            }
+        }
+    }
+
+    fn write(&self, s: &str) {
+        let (columns, rows) = self.size();
+        for c in s.chars() {
+            match c {
+                '\r' => self.column.set(0),
+                '\n' => {
+                    self.column.set(0);
+                    self.row.set(self.row.get() + 1);
+                }
+                _ => {
+                    if self.column.get() >= columns {
+                        self.column.set(0);
+                        self.row.set(self.row.get() + 1);
+                    }
+                    if self.row.get() >= rows {
+                        return;
+                    }
+                    let c = if c == '\t' { ' ' } else { c };
+                    self.draw(self.column.get(), self.row.get(), c);
+                    self.column.set(self.column.get() + 1);
+                }
+            }
+        }
+    }
+}
+
+impl screen::ScreenClient for PanicScreen<'a> {
+    fn command_complete(&self, result: ReturnCode) {
+        if self.panicking.get() {
+            self.done.set(true);
+        } else {
+            self.client.map(|client| client.command_complete(result));
+        }
+    }
+
+    fn write_complete(&self, buffer: &'static mut [u8], result: ReturnCode) {
+        if !self.panicking.get() {
+            self.client.map(move |client| client.write_complete(buffer, result));
+        } else if self.writing.get() {
+            self.writing.set(false);
+            self.buffer.replace(buffer);
+            self.done.set(true);
+        }
+        // Otherwise it is a write from before the panic, which nobody
+        // waits for anymore.
+    }
+}
+
+/// A 5x7 font of the printable ASCII characters, from 0x20.
+const FONT: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x00, 0x00, 0x5f, 0x00, 0x00], // !
+    [0x00, 0x07, 0x00, 0x07, 0x00], // "
+    [0x14, 0x7f, 0x14, 0x7f, 0x14], // #
+    [0x24, 0x2a, 0x7f, 0x2a, 0x12], // $
+    [0x23, 0x13, 0x08, 0x64, 0x62], // %
+    [0x36, 0x49, 0x55, 0x22, 0x50], // &
+    [0x00, 0x05, 0x03, 0x00, 0x00], // '
+    [0x00, 0x1c, 0x22, 0x41, 0x00], // (
+    [0x00, 0x41, 0x22, 0x1c, 0x00], // )
+    [0x14, 0x08, 0x3e, 0x08, 0x14], // *
+    [0x08, 0x08, 0x3e, 0x08, 0x08], // +
+    [0x00, 0x50, 0x30, 0x00, 0x00], // ,
+    [0x08, 0x08, 0x08, 0x08, 0x08], // -
+    [0x00, 0x60, 0x60, 0x00, 0x00], // .
+    [0x20, 0x10, 0x08, 0x04, 0x02], // /
+    [0x3e, 0x51, 0x49, 0x45, 0x3e], // 0
+    [0x00, 0x42, 0x7f, 0x40, 0x00], // 1
+    [0x42, 0x61, 0x51, 0x49, 0x46], // 2
+    [0x21, 0x41, 0x45, 0x4b, 0x31], // 3
+    [0x18, 0x14, 0x12, 0x7f, 0x10], // 4
+    [0x27, 0x45, 0x45, 0x45, 0x39], // 5
+    [0x3c, 0x4a, 0x49, 0x49, 0x30], // 6
+    [0x01, 0x71, 0x09, 0x05, 0x03], // 7
+    [0x36, 0x49, 0x49, 0x49, 0x36], // 8
+    [0x06, 0x49, 0x49, 0x29, 0x1e], // 9
+    [0x00, 0x36, 0x36, 0x00, 0x00], // :
+    [0x00, 0x56, 0x36, 0x00, 0x00], // ;
+    [0x08, 0x14, 0x22, 0x41, 0x00], // <
+    [0x14, 0x14, 0x14, 0x14, 0x14], // =
+    [0x00, 0x41, 0x22, 0x14, 0x08], // >
+    [0x02, 0x01, 0x51, 0x09, 0x06], // ?
+    [0x32, 0x49, 0x79, 0x41, 0x3e], // @
+    [0x7e, 0x11, 0x11, 0x11, 0x7e], // A
+    [0x7f, 0x49, 0x49, 0x49, 0x36], // B
+    [0x3e, 0x41, 0x41, 0x41, 0x22], // C
+    [0x7f, 0x41, 0x41, 0x22, 0x1c], // D
+    [0x7f, 0x49, 0x49, 0x49, 0x41], // E
+    [0x7f, 0x09, 0x09, 0x09, 0x01], // F
+    [0x3e, 0x41, 0x49, 0x49, 0x7a], // G
+    [0x7f, 0x08, 0x08, 0x08, 0x7f], // H
+    [0x00, 0x41, 0x7f, 0x41, 0x00], // I
+    [0x20, 0x40, 0x41, 0x3f, 0x01], // J
+    [0x7f, 0x08, 0x14, 0x22, 0x41], // K
+    [0x7f, 0x40, 0x40, 0x40, 0x40], // L
+    [0x7f, 0x02, 0x0c, 0x02, 0x7f], // M
+    [0x7f, 0x04, 0x08, 0x10, 0x7f], // N
+    [0x3e, 0x41, 0x41, 0x41, 0x3e], // O
+    [0x7f, 0x09, 0x09, 0x09, 0x06], // P
+    [0x3e, 0x41, 0x51, 0x21, 0x5e], // Q
+    [0x7f, 0x09, 0x19, 0x29, 0x46], // R
+    [0x46, 0x49, 0x49, 0x49, 0x31], // S
+    [0x01, 0x01, 0x7f, 0x01, 0x01], // T
+    [0x3f, 0x40, 0x40, 0x40, 0x3f], // U
+    [0x1f, 0x20, 0x40, 0x20, 0x1f], // V
+    [0x3f, 0x40, 0x38, 0x40, 0x3f], // W
+    [0x63, 0x14, 0x08, 0x14, 0x63], // X
+    [0x07, 0x08, 0x70, 0x08, 0x07], // Y
+    [0x61, 0x51, 0x49, 0x45, 0x43], // Z
+    [0x00, 0x7f, 0x41, 0x41, 0x00], // [
+    [0x02, 0x04, 0x08, 0x10, 0x20], // \
+    [0x00, 0x41, 0x41, 0x7f, 0x00], // ]
+    [0x04, 0x02, 0x01, 0x02, 0x04], // ^
+    [0x40, 0x40, 0x40, 0x40, 0x40], // _
+    [0x00, 0x01, 0x02, 0x04, 0x00], // `
+    [0x20, 0x54, 0x54, 0x54, 0x78], // a
+    [0x7f, 0x48, 0x44, 0x44, 0x38], // b
+    [0x38, 0x44, 0x44, 0x44, 0x20], // c
+    [0x38, 0x44, 0x44, 0x48, 0x7f], // d
+    [0x38, 0x54, 0x54, 0x54, 0x18], // e
+    [0x08, 0x7e, 0x09, 0x01, 0x02], // f
+    [0x0c, 0x52, 0x52, 0x52, 0x3e], // g
+    [0x7f, 0x08, 0x04, 0x04, 0x78], // h
+    [0x00, 0x44, 0x7d, 0x40, 0x00], // i
+    [0x20, 0x40, 0x44, 0x3d, 0x00], // j
+    [0x7f, 0x10, 0x28, 0x44, 0x00], // k
+    [0x00, 0x41, 0x7f, 0x40, 0x00], // l
+    [0x7c, 0x04, 0x18, 0x04, 0x78], // m
+    [0x7c, 0x08, 0x04, 0x04, 0x78], // n
+    [0x38, 0x44, 0x44, 0x44, 0x38], // o
+    [0x7c, 0x14, 0x14, 0x14, 0x08], // p
+    [0x08, 0x14, 0x14, 0x18, 0x7c], // q
+    [0x7c, 0x08, 0x04, 0x04, 0x08], // r
+    [0x48, 0x54, 0x54, 0x54, 0x20], // s
+    [0x04, 0x3f, 0x44, 0x40, 0x20], // t
+    [0x3c, 0x40, 0x40, 0x20, 0x7c], // u
+    [0x1c, 0x20, 0x40, 0x20, 0x1c], // v
+    [0x3c, 0x40, 0x30, 0x40, 0x3c], // w
+    [0x44, 0x28, 0x10, 0x28, 0x44], // x
+    [0x0c, 0x50, 0x50, 0x50, 0x3c], // y
+    [0x44, 0x64, 0x54, 0x4c, 0x44], // z
+    [0x00, 0x08, 0x36, 0x41, 0x00], // {
+    [0x00, 0x00, 0x7f, 0x00, 0x00], // |
+    [0x00, 0x41, 0x36, 0x08, 0x00], // }
+    [0x08, 0x04, 0x08, 0x10, 0x08], // ~
+];
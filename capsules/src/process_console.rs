@@ -0,0 +1,494 @@
+//! Console to inspect processes over a UART.
+//!
+//! Accepts the following commands, terminated by a newline:
+//!
+//! * `list`: print one line per process with its name, state, the CPU time
+//!   it has used and how many bytes of its RAM are in use, plus an energy
+//!   estimate if the board provides an `EnergyModel`
+//! * `stop <name>`: stop scheduling the process
+//! * `start <name>`: resume a stopped process, or restart a process that is
+//!   in the fault state
+//! * `fault <name>`: put the process in the fault state, as if it had
+//!   faulted, so its fault response decides what happens next
+//! * `terminate <name>`: stop the process for good, until it is started
+//!   again
+//! * `profile`: if the board set up a `kernel::profiling::Profiler`, print
+//!   for servicing interrupts, for running each process and for the
+//!   syscalls to each driver the number of samples, their mean and largest
+//!   cycle counts, and a histogram of the cycle counts
+//! * `profile reset`: clear the statistics of the profiler
+//! * `latency`: if the architecture measures interrupt latency, print for
+//!   each interrupt that was serviced the same statistics of the cycles from
+//!   its interrupt handler to the kernel servicing it
+//! * `latency reset`: clear the statistics of the interrupt latency
+//! * `sleep`: if the chip keeps sleep statistics, print how long it spent
+//!   in each sleep mode and awake, and how many times each interrupt woke
+//!   it up
+//! * `sleep reset`: clear the sleep statistics
+//!
+//! Since the console exposes and changes the state of all processes, it can
+//! only be created with the `ProcessManagementCapability`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let introspection = static_init!(
+//!     kernel::introspection::Introspection,
+//!     kernel::introspection::Introspection::new(board_kernel)
+//! );
+//! let energy_model = static_init!(
+//!     kernel::introspection::ConstantPowerModel,
+//!     kernel::introspection::ConstantPowerModel::new(6_000)
+//! );
+//! let process_console = static_init!(
+//!     capsules::process_console::ProcessConsole<'static, UartDevice>,
+//!     capsules::process_console::ProcessConsole::new(
+//!         introspection,
+//!         uart_device,
+//!         &mut capsules::process_console::WRITE_BUF,
+//!         &mut capsules::process_console::READ_BUF,
+//!         Some(energy_model),
+//!         Some(profiler),
+//!         Some(&cortexm4::interrupt_latency::INTERRUPT_LATENCY),
+//!         Some(&sam4l::pm::SLEEP_STATS),
+//!         &process_mgmt_cap,
+//!     )
+//! );
+//! uart_device.set_client(process_console);
+//! process_console.start();
+//! ```
+
+use core::cell::Cell;
+use core::fmt::{self, Write};
+use core::str;
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::uart::{self, UART};
+use kernel::introspection::{EnergyModel, Introspection};
+use kernel::procs::State;
+use kernel::profiling::{InterruptLatency, Profiler, Stats};
+use kernel::{AppId, SleepMode, SleepStatistics};
+
+pub static mut WRITE_BUF: [u8; 128] = [0; 128];
+pub static mut READ_BUF: [u8; 1] = [0; 1];
+
+const MAX_COMMAND_LEN: usize = 32;
+
+/// Writes formatted text into a byte buffer, truncating what does not fit.
+struct BufferWriter<'b> {
+    buffer: &'b mut [u8],
+    len: usize,
+}
+
+impl fmt::Write for BufferWriter<'b> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let bytes = s.as_bytes();
+        let len = bytes.len().min(self.buffer.len() - self.len);
+        self.buffer[self.len..self.len + len].copy_from_slice(&bytes[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+pub struct ProcessConsole<'a, U: UART> {
+    introspection: &'a Introspection,
+    uart: &'a U,
+    tx_buffer: TakeCell<'static, [u8]>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    command: Cell<[u8; MAX_COMMAND_LEN]>,
+    command_len: Cell<usize>,
+    /// Slot of the next process to print while listing processes.
+    list_index: OptionalCell<usize>,
+    /// Row to print next while printing the profile: the interrupts, then
+    /// one row per process slot, then one per driver.
+    profile_index: OptionalCell<usize>,
+    /// Interrupt to print next while printing the interrupt latency.
+    latency_index: OptionalCell<usize>,
+    /// Row to print next while printing the sleep statistics: the sleep
+    /// modes, the time awake, then one row per interrupt.
+    sleep_index: OptionalCell<usize>,
+    energy_model: Option<&'a EnergyModel>,
+    profiler: Option<&'a Profiler>,
+    interrupt_latency: Option<&'a InterruptLatency>,
+    sleep_stats: Option<&'a SleepStatistics>,
+    capability: &'a ProcessManagementCapability,
+}
+
+impl<U: UART> ProcessConsole<'a, U> {
+    pub fn new(
+        introspection: &'a Introspection,
+        uart: &'a U,
+        tx_buffer: &'static mut [u8],
+        rx_buffer: &'static mut [u8],
+        energy_model: Option<&'a EnergyModel>,
+        profiler: Option<&'a Profiler>,
+        interrupt_latency: Option<&'a InterruptLatency>,
+        sleep_stats: Option<&'a SleepStatistics>,
+        capability: &'a ProcessManagementCapability,
+    ) -> ProcessConsole<'a, U> {
+        ProcessConsole {
+            introspection: introspection,
+            uart: uart,
+            tx_buffer: TakeCell::new(tx_buffer),
+            rx_buffer: TakeCell::new(rx_buffer),
+            command: Cell::new([0; MAX_COMMAND_LEN]),
+            command_len: Cell::new(0),
+            list_index: OptionalCell::empty(),
+            profile_index: OptionalCell::empty(),
+            latency_index: OptionalCell::empty(),
+            sleep_index: OptionalCell::empty(),
+            energy_model: energy_model,
+            profiler: profiler,
+            interrupt_latency: interrupt_latency,
+            sleep_stats: sleep_stats,
+            capability: capability,
+        }
+    }
+
+    /// Starts listening for commands.
+    pub fn start(&self) {
+        self.rx_buffer.take().map(|buf| self.uart.receive(buf, 1));
+    }
+
+    /// Format a line into the transmit buffer and send it.
+    fn print(&self, args: fmt::Arguments) {
+        self.tx_buffer.take().map(|buf| {
+            let len = {
+                let mut writer = BufferWriter {
+                    buffer: &mut *buf,
+                    len: 0,
+                };
+                let _ = writer.write_fmt(args);
+                writer.len
+            };
+            self.uart.transmit(buf, len);
+        });
+    }
+
+    fn execute(&self, command: &[u8]) {
+        let (verb, argument) = match command.iter().position(|&c| c == b' ') {
+            Some(space) => (&command[..space], &command[space + 1..]),
+            None => (command, &command[command.len()..]),
+        };
+        match verb {
+            b"list" => {
+                self.print(format_args!(
+                    " PID Name                 State      CPU (ms)   Used/ Alloc\r\n"
+                ));
+                self.list_index.set(0);
+            }
+            b"stop" | b"start" | b"fault" | b"terminate" if argument.len() > 0 => {
+                let name = str::from_utf8(argument).unwrap_or("");
+                match self.introspection.app_by_name(name, self.capability) {
+                    Some(app) => {
+                        self.change_state(verb, app);
+                        self.print(format_args!("{} {}\r\n", name, past_tense(verb)));
+                    }
+                    None => self.print(format_args!("no process named {}\r\n", name)),
+                }
+            }
+            b"profile" => match (self.profiler, argument) {
+                (None, _) => self.print(format_args!("no profiler\r\n")),
+                (Some(_), b"") => {
+                    self.print(format_args!(
+                        "Name                    Count     Mean        Max   <256    <1K    <4K   \
+                         <16K   <64K  <256K    <1M   more\r\n"
+                    ));
+                    self.profile_index.set(0);
+                }
+                (Some(profiler), b"reset") => {
+                    profiler.reset();
+                    self.print(format_args!("profile reset\r\n"));
+                }
+                (Some(_), _) => self.print(format_args!("usage: profile [reset]\r\n")),
+            },
+            b"latency" => match (self.interrupt_latency, argument) {
+                (None, _) => self.print(format_args!("no interrupt latency\r\n")),
+                (Some(_), b"") => {
+                    self.print(format_args!(
+                        "IRQ                     Count     Mean        Max   <256    <1K    <4K   \
+                         <16K   <64K  <256K    <1M   more\r\n"
+                    ));
+                    self.latency_index.set(0);
+                }
+                (Some(latency), b"reset") => {
+                    latency.reset();
+                    self.print(format_args!("latency reset\r\n"));
+                }
+                (Some(_), _) => self.print(format_args!("usage: latency [reset]\r\n")),
+            },
+            b"sleep" => match (self.sleep_stats, argument) {
+                (None, _) => self.print(format_args!("no sleep statistics\r\n")),
+                (Some(_), b"") => {
+                    self.print(format_args!("Mode                    Count  Time (ms)    %\r\n"));
+                    self.sleep_index.set(0);
+                }
+                (Some(stats), b"reset") => {
+                    stats.reset();
+                    self.print(format_args!("sleep statistics reset\r\n"));
+                }
+                (Some(_), _) => self.print(format_args!("usage: sleep [reset]\r\n")),
+            },
+            b"" => {}
+            _ => self.print(format_args!(
+                "usage: list | stop <name> | start <name> | fault <name> | terminate <name> | \
+                 profile [reset] | latency [reset] | sleep [reset]\r\n"
+            )),
+        }
+    }
+
+    fn change_state(&self, verb: &[u8], app: AppId) {
+        match verb {
+            b"stop" => self.introspection.stop_app(app, self.capability),
+            b"start" => match self.introspection.process_state(app, self.capability) {
+                Some(State::Fault) => self.introspection.restart_app(app, self.capability),
+                _ => self.introspection.resume_app(app, self.capability),
+            },
+            b"fault" => self.introspection.fault_app(app, self.capability),
+            b"terminate" => self.introspection.terminate_app(app, self.capability),
+            _ => {}
+        }
+    }
+
+    /// Print the next process while listing processes. Empty slots are
+    /// skipped, and listing ends after the last slot.
+    fn list_next(&self) {
+        self.list_index.take().map(|first| {
+            let slots = self.introspection.number_process_slots(self.capability);
+            for index in first..slots {
+                if let Some(app) = self.introspection.app_id(index, self.capability) {
+                    let name = self.introspection.process_name(app, self.capability);
+                    let state = self.introspection.process_state(app, self.capability);
+                    let cpu_time_us = self.introspection.app_cpu_time_us(app, self.capability);
+                    let (allocated, used) =
+                        self.introspection.app_memory_usage(app, self.capability);
+                    match self.energy_model {
+                        Some(model) => self.print(format_args!(
+                            "{:4} {:20} {:10} {:8} {:6}/{:6} {} uJ\r\n",
+                            index,
+                            name,
+                            state.map_or("", |state| state_name(state)),
+                            cpu_time_us / 1000,
+                            used,
+                            allocated,
+                            model.energy_uj(cpu_time_us)
+                        )),
+                        None => self.print(format_args!(
+                            "{:4} {:20} {:10} {:8} {:6}/{:6}\r\n",
+                            index,
+                            name,
+                            state.map_or("", |state| state_name(state)),
+                            cpu_time_us / 1000,
+                            used,
+                            allocated
+                        )),
+                    }
+                    self.list_index.set(index + 1);
+                    break;
+                }
+            }
+        });
+    }
+
+    /// Print the next row while printing the profile. Process slots that
+    /// are empty or have no samples are skipped, and printing ends after the
+    /// last driver.
+    fn profile_next(&self) {
+        self.profile_index.take().map(|first| {
+            self.profiler.map(|profiler| {
+                let slots = self.introspection.number_process_slots(self.capability);
+                let mut row = first;
+                loop {
+                    if row == 0 {
+                        self.print_stats("interrupts", profiler.interrupt_stats());
+                    } else if row <= slots {
+                        let stats = profiler.process_stats(row - 1).filter(|stats| stats.count > 0);
+                        match (self.introspection.app_id(row - 1, self.capability), stats) {
+                            (Some(app), Some(stats)) => self.print_stats(
+                                self.introspection.process_name(app, self.capability),
+                                stats,
+                            ),
+                            _ => {
+                                row += 1;
+                                continue;
+                            }
+                        }
+                    } else {
+                        match profiler.driver_stats(row - 1 - slots) {
+                            Some((driver_num, stats)) => {
+                                let mut name = [0; 20];
+                                let name = format_into(
+                                    &mut name,
+                                    format_args!("driver 0x{:05x}", driver_num),
+                                );
+                                self.print_stats(name, stats);
+                            }
+                            None => break,
+                        }
+                    }
+                    self.profile_index.set(row + 1);
+                    break;
+                }
+            });
+        });
+    }
+
+    /// Print the latency of the next interrupt that was serviced while
+    /// printing the interrupt latency.
+    fn latency_next(&self) {
+        self.latency_index.take().map(|first| {
+            self.interrupt_latency.map(|latency| {
+                for interrupt in first..latency.number_interrupts() {
+                    let stats = latency
+                        .latency_stats(interrupt)
+                        .filter(|stats| stats.count > 0);
+                    if let Some(stats) = stats {
+                        let mut name = [0; 20];
+                        let name = format_into(&mut name, format_args!("{}", interrupt));
+                        self.print_stats(name, stats);
+                        self.latency_index.set(interrupt + 1);
+                        break;
+                    }
+                }
+            });
+        });
+    }
+
+    /// Print the next row while printing the sleep statistics. Interrupts
+    /// that never woke the chip up are skipped.
+    fn sleep_next(&self) {
+        self.sleep_index.take().map(|first| {
+            self.sleep_stats.map(|stats| {
+                let elapsed_us = stats.elapsed_us();
+                let percent = |time_us: u64| {
+                    if elapsed_us == 0 {
+                        0
+                    } else {
+                        time_us * 100 / elapsed_us
+                    }
+                };
+                let sleep_us = stats.sleep_time_us(SleepMode::Sleep);
+                let deep_sleep_us = stats.sleep_time_us(SleepMode::DeepSleep);
+                match first {
+                    0 | 1 => {
+                        let (name, mode, time_us) = if first == 0 {
+                            ("Sleep", SleepMode::Sleep, sleep_us)
+                        } else {
+                            ("Deep sleep", SleepMode::DeepSleep, deep_sleep_us)
+                        };
+                        self.print(format_args!(
+                            "{:20} {:8} {:10} {:3}%\r\n",
+                            name,
+                            stats.sleep_count(mode),
+                            time_us / 1000,
+                            percent(time_us)
+                        ));
+                        self.sleep_index.set(first + 1);
+                    }
+                    2 => {
+                        let awake_us = elapsed_us.saturating_sub(sleep_us + deep_sleep_us);
+                        self.print(format_args!(
+                            "{:20} {:8} {:10} {:3}%\r\n",
+                            "Awake",
+                            "",
+                            awake_us / 1000,
+                            percent(awake_us)
+                        ));
+                        self.sleep_index.set(first + 1);
+                    }
+                    _ => {
+                        for interrupt in first - 3..stats.number_interrupts() {
+                            let wakeups = stats.wakeups(interrupt);
+                            if wakeups > 0 {
+                                self.print(format_args!(
+                                    "Wakeups by IRQ {:<5} {:8}\r\n",
+                                    interrupt, wakeups
+                                ));
+                                self.sleep_index.set(interrupt + 4);
+                                break;
+                            }
+                        }
+                    }
+                }
+            });
+        });
+    }
+
+    fn print_stats(&self, name: &str, stats: Stats) {
+        let histogram = stats.histogram;
+        self.print(format_args!(
+            "{:20} {:8} {:8} {:10} {:6} {:6} {:6} {:6} {:6} {:6} {:6} {:6}\r\n",
+            name,
+            stats.count,
+            stats.mean(),
+            stats.max,
+            histogram[0],
+            histogram[1],
+            histogram[2],
+            histogram[3],
+            histogram[4],
+            histogram[5],
+            histogram[6],
+            histogram[7]
+        ));
+    }
+}
+
+/// Formats `args` into `buffer`, truncated to fit, for a column of a row.
+fn format_into(buffer: &'b mut [u8], args: fmt::Arguments) -> &'b str {
+    let len = {
+        let mut writer = BufferWriter {
+            buffer: &mut *buffer,
+            len: 0,
+        };
+        let _ = writer.write_fmt(args);
+        writer.len
+    };
+    str::from_utf8(&buffer[..len]).unwrap_or("")
+}
+
+fn state_name(state: State) -> &'static str {
+    match state {
+        State::Running => "Running",
+        State::Yielded => "Yielded",
+        State::StoppedRunning | State::StoppedYielded => "Stopped",
+        State::Fault => "Fault",
+    }
+}
+
+fn past_tense(verb: &[u8]) -> &'static str {
+    match verb {
+        b"stop" => "stopped",
+        b"start" => "started",
+        b"fault" => "faulted",
+        _ => "terminated",
+    }
+}
+
+impl<U: UART> uart::Client for ProcessConsole<'a, U> {
+    fn transmit_complete(&self, buffer: &'static mut [u8], _error: uart::Error) {
+        self.tx_buffer.replace(buffer);
+        self.list_next();
+        self.profile_next();
+        self.latency_next();
+        self.sleep_next();
+    }
+
+    fn receive_complete(&self, buffer: &'static mut [u8], rx_len: usize, error: uart::Error) {
+        if rx_len > 0 && error == uart::Error::CommandComplete {
+            let c = buffer[0];
+            let mut command = self.command.get();
+            let len = self.command_len.get();
+            if c == b'\r' || c == b'\n' {
+                self.command_len.set(0);
+                self.execute(&command[..len]);
+            } else if len < MAX_COMMAND_LEN {
+                command[len] = c;
+                self.command.set(command);
+                self.command_len.set(len + 1);
+            }
+        }
+        self.uart.receive(buffer, 1);
+    }
+}
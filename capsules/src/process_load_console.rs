@@ -0,0 +1,157 @@
+//! Console to load newly flashed processes over a UART, without rebooting.
+//!
+//! Accepts the following command, terminated by a newline:
+//!
+//! * `load`: scan the app flash area and start the processes that are not
+//!   loaded yet
+//!
+//! The command is answered with the number of processes that were started, or
+//! an error. Starting processes requires the `ProcessManagementCapability`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let process_loader = static_init!(
+//!     kernel::procs::DynamicProcessLoader<cortexm4::syscall::SysCall, cortexm4::mpu::MPU>,
+//!     kernel::procs::DynamicProcessLoader::new(
+//!         board_kernel,
+//!         &cortexm4::syscall::SysCall::new(),
+//!         chip.mpu(),
+//!         &_sapps as *const u8,
+//!         &FAULT_RESPONSE,
+//!         None,
+//!     )
+//! );
+//! let load_console = static_init!(
+//!     capsules::process_load_console::ProcessLoadConsole<'static, UartDevice>,
+//!     capsules::process_load_console::ProcessLoadConsole::new(
+//!         process_loader,
+//!         uart_device,
+//!         &mut capsules::process_load_console::WRITE_BUF,
+//!         &mut capsules::process_load_console::READ_BUF,
+//!         &process_mgmt_cap,
+//!     )
+//! );
+//! uart_device.set_client(load_console);
+//! load_console.start();
+//! ```
+
+use core::cell::Cell;
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::common::cells::TakeCell;
+use kernel::hil::uart::{self, UART};
+use kernel::procs::ProcessLoader;
+use kernel::ReturnCode;
+
+pub static mut WRITE_BUF: [u8; 32] = [0; 32];
+pub static mut READ_BUF: [u8; 1] = [0; 1];
+
+const MAX_COMMAND_LEN: usize = 8;
+
+pub struct ProcessLoadConsole<'a, U: UART> {
+    loader: &'a ProcessLoader,
+    uart: &'a U,
+    tx_buffer: TakeCell<'static, [u8]>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    command: Cell<[u8; MAX_COMMAND_LEN]>,
+    command_len: Cell<usize>,
+    capability: &'a ProcessManagementCapability,
+}
+
+impl<U: UART> ProcessLoadConsole<'a, U> {
+    pub fn new(
+        loader: &'a ProcessLoader,
+        uart: &'a U,
+        tx_buffer: &'static mut [u8],
+        rx_buffer: &'static mut [u8],
+        capability: &'a ProcessManagementCapability,
+    ) -> ProcessLoadConsole<'a, U> {
+        ProcessLoadConsole {
+            loader: loader,
+            uart: uart,
+            tx_buffer: TakeCell::new(tx_buffer),
+            rx_buffer: TakeCell::new(rx_buffer),
+            command: Cell::new([0; MAX_COMMAND_LEN]),
+            command_len: Cell::new(0),
+            capability: capability,
+        }
+    }
+
+    /// Starts listening for commands.
+    pub fn start(&self) {
+        self.rx_buffer.take().map(|buf| self.uart.receive(buf, 1));
+    }
+
+    fn write(&self, msg: &[u8]) {
+        self.tx_buffer.take().map(|buf| {
+            let len = msg.len().min(buf.len());
+            buf[..len].copy_from_slice(&msg[..len]);
+            self.uart.transmit(buf, len);
+        });
+    }
+
+    fn execute(&self, command: &[u8]) {
+        match command {
+            b"load" => {}
+            b"" => return,
+            _ => {
+                self.write(b"usage: load\r\n");
+                return;
+            }
+        }
+        match self.loader.load_new_processes(self.capability) {
+            ReturnCode::SuccessWithValue { value } => {
+                let mut msg = *b"loaded          ";
+                let len = 7 + format_number(value, &mut msg[7..14]);
+                msg[len] = b'\r';
+                msg[len + 1] = b'\n';
+                self.write(&msg[..len + 2]);
+            }
+            ReturnCode::ENOMEM => self.write(b"error: no free process slot\r\n"),
+            _ => self.write(b"error: loading failed\r\n"),
+        }
+    }
+}
+
+// Writes `value` in decimal to the start of `buf`, returning the number of
+// digits written
+fn format_number(mut value: usize, buf: &mut [u8]) -> usize {
+    let mut digits = [0; 20];
+    let mut len = 0;
+    loop {
+        digits[len] = b'0' + (value % 10) as u8;
+        len += 1;
+        value /= 10;
+        if value == 0 || len == buf.len() {
+            break;
+        }
+    }
+    for i in 0..len {
+        buf[i] = digits[len - 1 - i];
+    }
+    len
+}
+
+impl<U: UART> uart::Client for ProcessLoadConsole<'a, U> {
+    fn transmit_complete(&self, buffer: &'static mut [u8], _error: uart::Error) {
+        self.tx_buffer.replace(buffer);
+    }
+
+    fn receive_complete(&self, buffer: &'static mut [u8], rx_len: usize, error: uart::Error) {
+        if rx_len > 0 && error == uart::Error::CommandComplete {
+            let c = buffer[0];
+            let mut command = self.command.get();
+            let len = self.command_len.get();
+            if c == b'\r' || c == b'\n' {
+                self.command_len.set(0);
+                self.execute(&command[..len]);
+            } else if len < MAX_COMMAND_LEN {
+                command[len] = c;
+                self.command.set(command);
+                self.command_len.set(len + 1);
+            }
+        }
+        self.uart.receive(buffer, 1);
+    }
+}
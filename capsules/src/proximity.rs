@@ -0,0 +1,219 @@
+//! Provides userspace with proximity readings and gestures.
+//!
+//! Apps read the proximity once, or once it leaves a range, which the sensor
+//! checks by itself so the app and the CPU can sleep until then. Apps can
+//! also subscribe to gestures, which the sensor recognizes while any app
+//! enabled them.
+//!
+//! Readings are shared: apps that request a plain reading while one is in
+//! progress get its result. A reading on a range has the sensor to itself.
+//!
+//! Usage
+//! -----
+//!
+//! You need a device that provides the `hil::sensors::Proximity` trait, and
+//! optionally the `hil::sensors::GestureSensor` trait.
+//!
+//! ```rust
+//! let proximity = static_init!(
+//!     capsules::proximity::ProximitySensor<'static>,
+//!     capsules::proximity::ProximitySensor::new(
+//!         apds9960,
+//!         Some(apds9960),
+//!         board_kernel.create_grant(&memory_allocation_capability)));
+//! hil::sensors::Proximity::set_client(apds9960, proximity);
+//! hil::sensors::GestureSensor::set_client(apds9960, proximity);
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::sensors;
+use kernel::{AppId, Callback, Driver, Grant, ReturnCode};
+
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = 0x60009;
+
+#[derive(Default)]
+pub struct App {
+    proximity_callback: Option<Callback>,
+    gesture_callback: Option<Callback>,
+    /// Whether the app waits for a reading
+    pending: bool,
+    /// Whether the app enabled gestures
+    gestures: bool,
+}
+
+pub struct ProximitySensor<'a> {
+    sensor: &'a sensors::Proximity,
+    gesture_sensor: Option<&'a sensors::GestureSensor>,
+    apps: Grant<App>,
+    /// Whether a reading is in progress, and whether it is on a range
+    busy: Cell<bool>,
+    on_interrupt: Cell<bool>,
+    gestures_enabled: Cell<bool>,
+}
+
+impl ProximitySensor<'a> {
+    pub fn new(
+        sensor: &'a sensors::Proximity,
+        gesture_sensor: Option<&'a sensors::GestureSensor>,
+        grant: Grant<App>,
+    ) -> ProximitySensor<'a> {
+        ProximitySensor {
+            sensor: sensor,
+            gesture_sensor: gesture_sensor,
+            apps: grant,
+            busy: Cell::new(false),
+            on_interrupt: Cell::new(false),
+            gestures_enabled: Cell::new(false),
+        }
+    }
+
+    fn read(&self, appid: AppId, range: Option<(u8, u8)>) -> ReturnCode {
+        self.apps
+            .enter(appid, |app, _| {
+                if app.pending {
+                    return ReturnCode::EALREADY;
+                }
+                if self.busy.get() && (range.is_some() || self.on_interrupt.get()) {
+                    return ReturnCode::EBUSY;
+                }
+                if !self.busy.get() {
+                    let rcode = match range {
+                        Some((low, high)) => self.sensor.read_proximity_on_interrupt(low, high),
+                        None => self.sensor.read_proximity(),
+                    };
+                    if rcode != ReturnCode::SUCCESS {
+                        return rcode;
+                    }
+                    self.busy.set(true);
+                    self.on_interrupt.set(range.is_some());
+                }
+                app.pending = true;
+                ReturnCode::SUCCESS
+            }).unwrap_or_else(|err| err.into())
+    }
+
+    /// Enables or disables gestures in the sensor, depending on whether any
+    /// app enabled them.
+    fn update_gestures(&self, gesture_sensor: &sensors::GestureSensor) -> ReturnCode {
+        let enabled = Cell::new(false);
+        self.apps.each(|app| {
+            if app.gestures {
+                enabled.set(true);
+            }
+        });
+        if enabled.get() == self.gestures_enabled.get() {
+            return ReturnCode::SUCCESS;
+        }
+
+        let rcode = if enabled.get() {
+            gesture_sensor.enable_gestures()
+        } else {
+            gesture_sensor.disable_gestures()
+        };
+        if rcode == ReturnCode::SUCCESS {
+            self.gestures_enabled.set(enabled.get());
+        }
+        rcode
+    }
+
+    fn set_gestures(&self, appid: AppId, enabled: bool) -> ReturnCode {
+        self.gesture_sensor.map_or(ReturnCode::ENODEVICE, |gesture_sensor| {
+            let rcode = self
+                .apps
+                .enter(appid, |app, _| {
+                    app.gestures = enabled;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into());
+            if rcode != ReturnCode::SUCCESS {
+                return rcode;
+            }
+            self.update_gestures(gesture_sensor)
+        })
+    }
+}
+
+impl sensors::ProximityClient for ProximitySensor<'a> {
+    fn callback(&self, proximity: u8) {
+        self.busy.set(false);
+        self.apps.each(|app| {
+            if app.pending {
+                app.pending = false;
+                app.proximity_callback.map(|mut cb| cb.schedule(proximity as usize, 0, 0));
+            }
+        });
+    }
+}
+
+impl sensors::GestureClient for ProximitySensor<'a> {
+    fn gesture(&self, gesture: sensors::Gesture) {
+        self.apps.each(|app| {
+            if app.gestures {
+                app.gesture_callback.map(|mut cb| cb.schedule(gesture as usize, 0, 0));
+            }
+        });
+    }
+}
+
+impl Driver for ProximitySensor<'a> {
+    /// Subscribe to proximity readings and gestures.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Called with the proximity, from 0 for far to 255 for close,
+    ///        when a reading the app requested completes.
+    /// - `1`: Called with the gesture: 1 for up, 2 for down, 3 for left and
+    ///        4 for right.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    app.proximity_callback = callback;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            1 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    app.gesture_callback = callback;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Read the proximity and control gestures.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Read the proximity.
+    /// - `2`: Read the proximity once it is below `data` or above `data2`.
+    /// - `3`: Enable gestures for the app.
+    /// - `4`: Disable gestures for the app.
+    fn command(&self, command_num: usize, data: usize, data2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            1 => self.read(appid, None),
+
+            2 => {
+                if data > 255 || data2 > 255 {
+                    return ReturnCode::EINVAL;
+                }
+                self.read(appid, Some((data as u8, data2 as u8)))
+            }
+
+            3 => self.set_gestures(appid, true),
+
+            4 => self.set_gestures(appid, false),
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
@@ -0,0 +1,184 @@
+//! Provides userspace with access to a quadrature decoder.
+//!
+//! Apps read the position of an encoder, for example on a wheel, and its
+//! velocity, and can subscribe to changes of the position and to index
+//! marks instead of polling. The velocity is the change of the position
+//! over the last 100 ms, measured with an alarm while the decoder is
+//! enabled. The decoder is shared: any app can enable it or set the
+//! position, and all subscribed apps get the events.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let qdec_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, nrf5x::rtc::Rtc>,
+//!     VirtualMuxAlarm::new(mux_alarm));
+//! let qdec = static_init!(
+//!     capsules::qdec::QdecDriver<'static, VirtualMuxAlarm<'static, nrf5x::rtc::Rtc>>,
+//!     capsules::qdec::QdecDriver::new(
+//!         &nrf52::qdec::QDEC,
+//!         qdec_alarm,
+//!         board_kernel.create_grant(&memory_allocation_capability)));
+//! hil::qdec::Qdec::set_client(&nrf52::qdec::QDEC, qdec);
+//! qdec_alarm.set_client(qdec);
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::qdec;
+use kernel::hil::time::{self, Frequency};
+use kernel::{AppId, Callback, Driver, Grant, ReturnCode};
+
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = 0x0000C;
+
+/// The period over which the velocity is measured
+const VELOCITY_PERIOD_MS: u32 = 100;
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+}
+
+pub struct QdecDriver<'a, A: time::Alarm> {
+    qdec: &'a qdec::Qdec,
+    alarm: &'a A,
+    /// The position at the last velocity measurement
+    last_position: Cell<i32>,
+    /// Counts per second
+    velocity: Cell<i32>,
+    apps: Grant<App>,
+}
+
+impl<A: time::Alarm> QdecDriver<'a, A> {
+    pub fn new(qdec: &'a qdec::Qdec, alarm: &'a A, grant: Grant<App>) -> QdecDriver<'a, A> {
+        QdecDriver {
+            qdec: qdec,
+            alarm: alarm,
+            last_position: Cell::new(0),
+            velocity: Cell::new(0),
+            apps: grant,
+        }
+    }
+
+    fn enable(&self) -> ReturnCode {
+        let rcode = self.qdec.enable();
+        if rcode == ReturnCode::SUCCESS {
+            self.last_position.set(self.qdec.get_position());
+            self.velocity.set(0);
+            self.schedule(self.alarm.now());
+        }
+        rcode
+    }
+
+    fn disable(&self) -> ReturnCode {
+        let rcode = self.qdec.disable();
+        if rcode == ReturnCode::SUCCESS {
+            self.alarm.disable();
+            self.velocity.set(0);
+        }
+        rcode
+    }
+
+    fn set_position(&self, position: i32) {
+        self.qdec.set_position(position);
+        self.last_position.set(position);
+    }
+
+    fn schedule(&self, from: u32) {
+        let interval = VELOCITY_PERIOD_MS * A::Frequency::frequency() / 1000;
+        self.alarm.set_alarm(from.wrapping_add(interval));
+    }
+
+    fn notify(&self, event: usize, position: i32) {
+        self.apps.each(|app| {
+            app.callback
+                .map(|mut cb| cb.schedule(event, position as usize, 0));
+        });
+    }
+}
+
+impl<A: time::Alarm> qdec::Client for QdecDriver<'a, A> {
+    fn position_changed(&self, position: i32) {
+        self.notify(0, position);
+    }
+
+    fn index(&self, position: i32) {
+        self.notify(1, position);
+    }
+}
+
+impl<A: time::Alarm> time::Client for QdecDriver<'a, A> {
+    fn fired(&self) {
+        if !self.qdec.is_enabled() {
+            return;
+        }
+        let position = self.qdec.get_position();
+        let counts = position.wrapping_sub(self.last_position.get());
+        self.last_position.set(position);
+        self.velocity
+            .set(counts.saturating_mul((1000 / VELOCITY_PERIOD_MS) as i32));
+        let now = self.alarm.get_alarm();
+        self.schedule(now);
+    }
+}
+
+impl<A: time::Alarm> Driver for QdecDriver<'a, A> {
+    /// Subscribe to changes of the position.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Called with 0 and the position when the position changed, and
+    ///        with 1 and the position when the encoder passed its index mark.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Control the decoder.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Enable the decoder.
+    /// - `2`: Disable the decoder.
+    /// - `3`: Get the position, in counts.
+    /// - `4`: Set the position to `data`.
+    /// - `5`: Get the velocity, in counts per second.
+    fn command(&self, command_num: usize, data: usize, _: usize, _: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            1 => self.enable(),
+
+            2 => self.disable(),
+
+            3 => ReturnCode::SuccessWithValue {
+                value: self.qdec.get_position() as usize,
+            },
+
+            4 => {
+                self.set_position(data as i32);
+                ReturnCode::SUCCESS
+            }
+
+            5 => ReturnCode::SuccessWithValue {
+                value: self.velocity.get() as usize,
+            },
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
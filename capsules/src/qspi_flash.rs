@@ -0,0 +1,332 @@
+//! Flash pages on external flash behind a QSPI controller.
+//!
+//! This capsule implements `hil::flash::Flash` on top of a `hil::qspi::Qspi`
+//! controller, so that external NOR flash can be used like internal flash,
+//! for example through `NonvolatileToPages` by the nonvolatile storage
+//! driver. A page is a 4 KB sector, because that is the smallest size that
+//! can be erased. Sectors are read and programmed 256 bytes at a time
+//! through a word-aligned buffer, and writing a sector erases it first.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! nrf52::qspi::QSPI.initialize(sck, csn, [io0, io1, io2, io3], 8 * 1024 * 1024);
+//! let qspi_flash = static_init!(
+//!     capsules::qspi_flash::QspiFlash<'static, nrf52::qspi::Qspi>,
+//!     capsules::qspi_flash::QspiFlash::new(
+//!         &nrf52::qspi::QSPI,
+//!         &mut capsules::qspi_flash::BUFFER.0
+//!     )
+//! );
+//! hil::qspi::Qspi::set_client(&nrf52::qspi::QSPI, qspi_flash);
+//!
+//! let nv_to_page = static_init!(
+//!     capsules::nonvolatile_to_pages::NonvolatileToPages<
+//!         'static,
+//!         capsules::qspi_flash::QspiFlash<'static, nrf52::qspi::Qspi>,
+//!     >,
+//!     capsules::nonvolatile_to_pages::NonvolatileToPages::new(
+//!         qspi_flash,
+//!         static_init!(
+//!             capsules::qspi_flash::QspiFlashSector,
+//!             capsules::qspi_flash::QspiFlashSector::new()
+//!         )
+//!     )
+//! );
+//! hil::flash::HasClient::set_client(qspi_flash, nv_to_page);
+//! ```
+
+use core::cell::Cell;
+use core::ops::{Index, IndexMut};
+use kernel::common::cells::OptionalCell;
+use kernel::common::cells::TakeCell;
+use kernel::hil;
+use kernel::hil::qspi::{PAGE_SIZE, SECTOR_SIZE};
+use kernel::ReturnCode;
+
+/// A word-aligned buffer of one flash page, as QSPI controllers move words
+#[repr(align(4))]
+pub struct Buffer(pub [u8; PAGE_SIZE]);
+
+pub static mut BUFFER: Buffer = Buffer([0; PAGE_SIZE]);
+
+/// The number of flash pages in a sector
+const PAGES_PER_SECTOR: usize = SECTOR_SIZE / PAGE_SIZE;
+
+/// This is a wrapper around a u8 array that is sized to a single sector of
+/// external flash.
+///
+/// An example looks like:
+///
+/// ```
+/// static mut PAGEBUFFER: QspiFlashSector = QspiFlashSector::new();
+/// ```
+pub struct QspiFlashSector(pub [u8; SECTOR_SIZE]);
+
+impl QspiFlashSector {
+    pub const fn new() -> QspiFlashSector {
+        QspiFlashSector([0; SECTOR_SIZE])
+    }
+}
+
+impl Index<usize> for QspiFlashSector {
+    type Output = u8;
+
+    fn index(&self, idx: usize) -> &u8 {
+        &self.0[idx]
+    }
+}
+
+impl IndexMut<usize> for QspiFlashSector {
+    fn index_mut(&mut self, idx: usize) -> &mut u8 {
+        &mut self.0[idx]
+    }
+}
+
+impl AsMut<[u8]> for QspiFlashSector {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    Erase,
+    Write { sector_index: usize },
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    ReadSector { sector_index: usize, page_index: usize },
+    EraseSector { operation: Operation },
+    WriteSector { sector_index: usize, page_index: usize },
+}
+
+pub struct QspiFlash<'a, Q: hil::qspi::Qspi + 'a> {
+    qspi: &'a Q,
+    state: Cell<State>,
+    buffer: TakeCell<'static, [u8]>,
+    client: OptionalCell<&'a hil::flash::Client<QspiFlash<'a, Q>>>,
+    client_sector: TakeCell<'static, QspiFlashSector>,
+}
+
+impl<Q: hil::qspi::Qspi + 'a> QspiFlash<'a, Q> {
+    pub fn new(qspi: &'a Q, buffer: &'static mut [u8]) -> QspiFlash<'a, Q> {
+        QspiFlash {
+            qspi: qspi,
+            state: Cell::new(State::Idle),
+            buffer: TakeCell::new(buffer),
+            client: OptionalCell::empty(),
+            client_sector: TakeCell::empty(),
+        }
+    }
+
+    fn check_sector(&self, sector_index: usize) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        if (sector_index + 1) * SECTOR_SIZE > self.qspi.size() {
+            return ReturnCode::EINVAL;
+        }
+        ReturnCode::SUCCESS
+    }
+
+    fn read_sector(&self, sector_index: usize, sector: &'static mut QspiFlashSector) -> ReturnCode {
+        let result = self.check_sector(sector_index);
+        if result != ReturnCode::SUCCESS {
+            return result;
+        }
+        let result = self.read_chunk(sector_index, 0);
+        if result == ReturnCode::SUCCESS {
+            self.client_sector.replace(sector);
+        }
+        result
+    }
+
+    fn write_sector(
+        &self,
+        sector_index: usize,
+        sector: &'static mut QspiFlashSector,
+    ) -> ReturnCode {
+        let result = self.check_sector(sector_index);
+        if result != ReturnCode::SUCCESS {
+            return result;
+        }
+        let result = self.erase_sector(
+            sector_index,
+            Operation::Write {
+                sector_index: sector_index,
+            },
+        );
+        if result == ReturnCode::SUCCESS {
+            self.client_sector.replace(sector);
+        }
+        result
+    }
+
+    fn erase_sector(&self, sector_index: usize, operation: Operation) -> ReturnCode {
+        let result = self.qspi.erase_sector(sector_index * SECTOR_SIZE);
+        if result == ReturnCode::SUCCESS {
+            self.state.set(State::EraseSector {
+                operation: operation,
+            });
+        }
+        result
+    }
+
+    /// Read a flash page into the buffer.
+    fn read_chunk(&self, sector_index: usize, page_index: usize) -> ReturnCode {
+        self.buffer.take().map_or(ReturnCode::EBUSY, |buffer| {
+            let address = sector_index * SECTOR_SIZE + page_index * PAGE_SIZE;
+            let (result, buffer) = self.qspi.read(address, buffer, PAGE_SIZE);
+            self.buffer.put(buffer);
+            if result == ReturnCode::SUCCESS {
+                self.state.set(State::ReadSector {
+                    sector_index: sector_index,
+                    page_index: page_index,
+                });
+            }
+            result
+        })
+    }
+
+    /// Program a flash page from the client's sector.
+    fn program_chunk(&self, sector_index: usize, page_index: usize) -> ReturnCode {
+        self.buffer.take().map_or(ReturnCode::EBUSY, |buffer| {
+            self.client_sector.map(|sector| {
+                let start = page_index * PAGE_SIZE;
+                buffer[..PAGE_SIZE].copy_from_slice(&sector.0[start..start + PAGE_SIZE]);
+            });
+            let address = sector_index * SECTOR_SIZE + page_index * PAGE_SIZE;
+            let (result, buffer) = self.qspi.page_program(address, buffer, PAGE_SIZE);
+            self.buffer.put(buffer);
+            if result == ReturnCode::SUCCESS {
+                self.state.set(State::WriteSector {
+                    sector_index: sector_index,
+                    page_index: page_index,
+                });
+            }
+            result
+        })
+    }
+
+    /// Ends the operation in progress and tells the client.
+    fn finish(&self, state: State, error: hil::flash::Error) {
+        self.state.set(State::Idle);
+        self.client.map(|client| match state {
+            State::ReadSector { .. } => {
+                self.client_sector.take().map(|sector| {
+                    client.read_complete(sector, error);
+                });
+            }
+            State::EraseSector {
+                operation: Operation::Erase,
+            } => {
+                client.erase_complete(error);
+            }
+            State::EraseSector { .. } | State::WriteSector { .. } => {
+                self.client_sector.take().map(|sector| {
+                    client.write_complete(sector, error);
+                });
+            }
+            State::Idle => {}
+        });
+    }
+
+    /// Continues with `result` of the next step of the operation in
+    /// `state`, ending the operation if the step could not start.
+    fn continue_with(&self, state: State, result: ReturnCode) {
+        if result != ReturnCode::SUCCESS {
+            self.finish(state, hil::flash::Error::FlashError);
+        }
+    }
+}
+
+impl<Q: hil::qspi::Qspi + 'a> hil::qspi::Client for QspiFlash<'a, Q> {
+    fn ready(&self, _result: ReturnCode) {}
+
+    fn read_done(&self, buffer: &'static mut [u8], _length: usize, result: ReturnCode) {
+        let state = self.state.get();
+        if let State::ReadSector {
+            sector_index,
+            page_index,
+        } = state
+        {
+            self.client_sector.map(|sector| {
+                let start = page_index * PAGE_SIZE;
+                sector.0[start..start + PAGE_SIZE].copy_from_slice(&buffer[..PAGE_SIZE]);
+            });
+            self.buffer.replace(buffer);
+            if result != ReturnCode::SUCCESS {
+                self.finish(state, hil::flash::Error::FlashError);
+            } else if page_index + 1 < PAGES_PER_SECTOR {
+                self.continue_with(state, self.read_chunk(sector_index, page_index + 1));
+            } else {
+                self.finish(state, hil::flash::Error::CommandComplete);
+            }
+        } else {
+            self.buffer.replace(buffer);
+        }
+    }
+
+    fn program_done(&self, buffer: &'static mut [u8], _length: usize, result: ReturnCode) {
+        self.buffer.replace(buffer);
+        let state = self.state.get();
+        if let State::WriteSector {
+            sector_index,
+            page_index,
+        } = state
+        {
+            if result != ReturnCode::SUCCESS {
+                self.finish(state, hil::flash::Error::FlashError);
+            } else if page_index + 1 < PAGES_PER_SECTOR {
+                self.continue_with(state, self.program_chunk(sector_index, page_index + 1));
+            } else {
+                self.finish(state, hil::flash::Error::CommandComplete);
+            }
+        }
+    }
+
+    fn erase_done(&self, result: ReturnCode) {
+        let state = self.state.get();
+        if let State::EraseSector { operation } = state {
+            if result != ReturnCode::SUCCESS {
+                self.finish(state, hil::flash::Error::FlashError);
+            } else if let Operation::Write { sector_index } = operation {
+                self.continue_with(state, self.program_chunk(sector_index, 0));
+            } else {
+                self.finish(state, hil::flash::Error::CommandComplete);
+            }
+        }
+    }
+}
+
+impl<Q: hil::qspi::Qspi + 'a, C: hil::flash::Client<Self>> hil::flash::HasClient<'a, C>
+    for QspiFlash<'a, Q>
+{
+    fn set_client(&self, client: &'a C) {
+        self.client.set(client);
+    }
+}
+
+impl<Q: hil::qspi::Qspi + 'a> hil::flash::Flash for QspiFlash<'a, Q> {
+    type Page = QspiFlashSector;
+
+    fn read_page(&self, page_number: usize, buf: &'static mut Self::Page) -> ReturnCode {
+        self.read_sector(page_number, buf)
+    }
+
+    fn write_page(&self, page_number: usize, buf: &'static mut Self::Page) -> ReturnCode {
+        self.write_sector(page_number, buf)
+    }
+
+    fn erase_page(&self, page_number: usize) -> ReturnCode {
+        let result = self.check_sector(page_number);
+        if result != ReturnCode::SUCCESS {
+            return result;
+        }
+        self.erase_sector(page_number, Operation::Erase)
+    }
+}
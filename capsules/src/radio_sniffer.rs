@@ -0,0 +1,177 @@
+//! Streams frames captured by a radio in sniffer mode over a UART.
+//!
+//! Every captured frame is written as one line of text containing a timestamp
+//! (in ticks of the provided alarm), the RSSI, whether the CRC was valid and
+//! the frame bytes in hexadecimal:
+//!
+//! ```text
+//! 0001a2f3 -63 1 40 25 d6 be 89 8e 02 01 06 ...
+//! ```
+//!
+//! Frames that arrive while the previous line is still being written are
+//! dropped. The number of dropped frames is reported in a line starting with
+//! `#` before the next captured frame.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let sniffer = static_init!(
+//!     capsules::radio_sniffer::RadioSniffer<
+//!         'static,
+//!         nrf52::radio::Radio,
+//!         nrf52::uart::Uarte,
+//!         VirtualMuxAlarm<'static, nrf5x::rtc::Rtc>,
+//!     >,
+//!     capsules::radio_sniffer::RadioSniffer::new(
+//!         &nrf52::radio::RADIO,
+//!         &nrf52::uart::UARTE0,
+//!         sniffer_virtual_alarm,
+//!         &mut capsules::radio_sniffer::BUF,
+//!     )
+//! );
+//! kernel::hil::sniffer::Sniffer::set_sniffer_client(&nrf52::radio::RADIO, sniffer);
+//! kernel::hil::uart::UART::set_client(&nrf52::uart::UARTE0, sniffer);
+//! sniffer.start(kernel::hil::sniffer::SnifferConfig {
+//!     access_address: 0x8e89bed6,
+//!     frequency: 2,
+//!     data_rate: kernel::hil::sniffer::DataRate::Ble1Mbit,
+//!     whitening: Some(37),
+//! });
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::TakeCell;
+use kernel::hil::sniffer::{Sniffer, SnifferClient, SnifferConfig};
+use kernel::hil::time::Alarm;
+use kernel::hil::uart::{self, UART};
+use kernel::ReturnCode;
+
+/// Large enough for the longest frame (257 bytes) in hex plus the line headers.
+pub static mut BUF: [u8; 832] = [0; 832];
+
+const HEX: &[u8; 16] = b"0123456789abcdef";
+
+pub struct RadioSniffer<'a, R: Sniffer, U: UART, A: Alarm> {
+    radio: &'a R,
+    uart: &'a U,
+    alarm: &'a A,
+    tx_buffer: TakeCell<'static, [u8]>,
+    dropped: Cell<u32>,
+}
+
+impl<R: Sniffer, U: UART, A: Alarm> RadioSniffer<'a, R, U, A> {
+    pub fn new(
+        radio: &'a R,
+        uart: &'a U,
+        alarm: &'a A,
+        tx_buffer: &'static mut [u8],
+    ) -> RadioSniffer<'a, R, U, A> {
+        RadioSniffer {
+            radio: radio,
+            uart: uart,
+            alarm: alarm,
+            tx_buffer: TakeCell::new(tx_buffer),
+            dropped: Cell::new(0),
+        }
+    }
+
+    pub fn start(&self, config: SnifferConfig) -> ReturnCode {
+        self.radio.start_sniffing(config)
+    }
+
+    pub fn stop(&self) -> ReturnCode {
+        self.radio.stop_sniffing()
+    }
+}
+
+/// Helper to write text into the output buffer, silently truncating at its end.
+struct LineWriter<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+}
+
+impl LineWriter<'b> {
+    fn push(&mut self, byte: u8) {
+        if self.len < self.buf.len() {
+            self.buf[self.len] = byte;
+            self.len += 1;
+        }
+    }
+
+    fn hex_u8(&mut self, val: u8) {
+        self.push(HEX[(val >> 4) as usize]);
+        self.push(HEX[(val & 0xf) as usize]);
+    }
+
+    fn hex_u32(&mut self, val: u32) {
+        for shift in [24, 16, 8, 0].iter() {
+            self.hex_u8((val >> shift) as u8);
+        }
+    }
+
+    fn decimal(&mut self, val: u32) {
+        let mut digits = [0; 10];
+        let mut n = val;
+        let mut i = 0;
+        loop {
+            digits[i] = b'0' + (n % 10) as u8;
+            n /= 10;
+            i += 1;
+            if n == 0 {
+                break;
+            }
+        }
+        while i > 0 {
+            i -= 1;
+            self.push(digits[i]);
+        }
+    }
+}
+
+impl<R: Sniffer, U: UART, A: Alarm> SnifferClient for RadioSniffer<'a, R, U, A> {
+    fn frame_captured(&self, frame: &[u8], crc_ok: bool, rssi: u8) {
+        let now = self.alarm.now();
+        match self.tx_buffer.take() {
+            None => self.dropped.set(self.dropped.get() + 1),
+            Some(buf) => {
+                let len = {
+                    let mut line = LineWriter {
+                        buf: &mut buf[..],
+                        len: 0,
+                    };
+                    let dropped = self.dropped.replace(0);
+                    if dropped > 0 {
+                        line.push(b'#');
+                        line.push(b' ');
+                        line.decimal(dropped);
+                        line.push(b'\r');
+                        line.push(b'\n');
+                    }
+                    line.hex_u32(now);
+                    line.push(b' ');
+                    line.push(b'-');
+                    line.decimal(rssi as u32);
+                    line.push(b' ');
+                    line.push(if crc_ok { b'1' } else { b'0' });
+                    for byte in frame.iter() {
+                        line.push(b' ');
+                        line.hex_u8(*byte);
+                    }
+                    line.push(b'\r');
+                    line.push(b'\n');
+                    line.len
+                };
+                self.uart.transmit(buf, len);
+            }
+        }
+    }
+}
+
+impl<R: Sniffer, U: UART, A: Alarm> uart::Client for RadioSniffer<'a, R, U, A> {
+    fn transmit_complete(&self, buffer: &'static mut [u8], _error: uart::Error) {
+        self.tx_buffer.replace(buffer);
+    }
+
+    fn receive_complete(&self, _buffer: &'static mut [u8], _rx_len: usize, _error: uart::Error) {}
+}
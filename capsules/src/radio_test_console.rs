@@ -0,0 +1,150 @@
+//! Console to start and stop radio test modes over a UART.
+//!
+//! Accepts the following commands, terminated by a newline:
+//!
+//! * `carrier <frequency>`: transmit an unmodulated carrier
+//! * `prbs <frequency>`: transmit a PRBS9 pattern
+//! * `stop`: stop the current test
+//!
+//! where `<frequency>` is in MHz above 2400 MHz, e.g. `carrier 2` for 2402 MHz.
+//! Each command is answered with `ok` or an error.
+//!
+//! Because test modes disrupt other devices, the console can only be created
+//! with the `RadioTestCapability`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! struct RadioTestCap;
+//! unsafe impl capabilities::RadioTestCapability for RadioTestCap {}
+//!
+//! let radio_test = static_init!(
+//!     capsules::radio_test_console::RadioTestConsole<
+//!         'static,
+//!         nrf52::radio::Radio,
+//!         nrf52::uart::Uarte,
+//!     >,
+//!     capsules::radio_test_console::RadioTestConsole::new(
+//!         &nrf52::radio::RADIO,
+//!         &nrf52::uart::UARTE0,
+//!         &mut capsules::radio_test_console::WRITE_BUF,
+//!         &mut capsules::radio_test_console::READ_BUF,
+//!         &RadioTestCap,
+//!     )
+//! );
+//! kernel::hil::uart::UART::set_client(&nrf52::uart::UARTE0, radio_test);
+//! radio_test.start();
+//! ```
+
+use core::cell::Cell;
+use kernel::capabilities::RadioTestCapability;
+use kernel::common::cells::TakeCell;
+use kernel::hil::radio_test::RadioTest;
+use kernel::hil::uart::{self, UART};
+use kernel::ReturnCode;
+
+pub static mut WRITE_BUF: [u8; 32] = [0; 32];
+pub static mut READ_BUF: [u8; 1] = [0; 1];
+
+const MAX_COMMAND_LEN: usize = 16;
+
+pub struct RadioTestConsole<'a, R: RadioTest, U: UART> {
+    radio: &'a R,
+    uart: &'a U,
+    tx_buffer: TakeCell<'static, [u8]>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    command: Cell<[u8; MAX_COMMAND_LEN]>,
+    command_len: Cell<usize>,
+    capability: &'a RadioTestCapability,
+}
+
+impl<R: RadioTest, U: UART> RadioTestConsole<'a, R, U> {
+    pub fn new(
+        radio: &'a R,
+        uart: &'a U,
+        tx_buffer: &'static mut [u8],
+        rx_buffer: &'static mut [u8],
+        capability: &'a RadioTestCapability,
+    ) -> RadioTestConsole<'a, R, U> {
+        RadioTestConsole {
+            radio: radio,
+            uart: uart,
+            tx_buffer: TakeCell::new(tx_buffer),
+            rx_buffer: TakeCell::new(rx_buffer),
+            command: Cell::new([0; MAX_COMMAND_LEN]),
+            command_len: Cell::new(0),
+            capability: capability,
+        }
+    }
+
+    /// Starts listening for commands.
+    pub fn start(&self) {
+        self.rx_buffer.take().map(|buf| self.uart.receive(buf, 1));
+    }
+
+    fn write(&self, msg: &[u8]) {
+        self.tx_buffer.take().map(|buf| {
+            let len = msg.len().min(buf.len());
+            buf[..len].copy_from_slice(&msg[..len]);
+            self.uart.transmit(buf, len);
+        });
+    }
+
+    fn execute(&self, command: &[u8]) {
+        let mut words = command.split(|c| *c == b' ').filter(|w| !w.is_empty());
+        let result = match (words.next(), words.next().and_then(parse_frequency)) {
+            (Some(b"carrier"), Some(frequency)) => {
+                self.radio.start_constant_carrier(frequency, self.capability)
+            }
+            (Some(b"prbs"), Some(frequency)) => self.radio.start_prbs9(frequency, self.capability),
+            (Some(b"stop"), None) => self.radio.stop_test(),
+            (None, None) => return,
+            _ => ReturnCode::EINVAL,
+        };
+        match result {
+            ReturnCode::SUCCESS => self.write(b"ok\r\n"),
+            ReturnCode::EBUSY => self.write(b"error: radio busy\r\n"),
+            ReturnCode::EALREADY => self.write(b"error: no test running\r\n"),
+            _ => self.write(b"usage: carrier|prbs <MHz>, stop\r\n"),
+        }
+    }
+}
+
+// Parses a decimal frequency offset in MHz, valid from 0 to 100 (2400-2500 MHz)
+fn parse_frequency(word: &[u8]) -> Option<u8> {
+    let mut value: u32 = 0;
+    for c in word.iter() {
+        if *c < b'0' || *c > b'9' {
+            return None;
+        }
+        value = value * 10 + (*c - b'0') as u32;
+        if value > 100 {
+            return None;
+        }
+    }
+    Some(value as u8)
+}
+
+impl<R: RadioTest, U: UART> uart::Client for RadioTestConsole<'a, R, U> {
+    fn transmit_complete(&self, buffer: &'static mut [u8], _error: uart::Error) {
+        self.tx_buffer.replace(buffer);
+    }
+
+    fn receive_complete(&self, buffer: &'static mut [u8], rx_len: usize, error: uart::Error) {
+        if rx_len > 0 && error == uart::Error::CommandComplete {
+            let c = buffer[0];
+            let mut command = self.command.get();
+            let len = self.command_len.get();
+            if c == b'\r' || c == b'\n' {
+                self.command_len.set(0);
+                self.execute(&command[..len]);
+            } else if len < MAX_COMMAND_LEN {
+                command[len] = c;
+                self.command.set(command);
+                self.command_len.set(len + 1);
+            }
+        }
+        self.uart.receive(buffer, 1);
+    }
+}
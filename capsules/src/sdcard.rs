@@ -1,6 +1,8 @@
 //! Provides driver for accessing an SD Card and a userspace Driver.
 //!
-//! This allows initialization and block reads or writes on top of SPI.
+//! This allows initialization and block reads, writes or erases on top of SPI.
+//! Other capsules, such as filesystems, can use the SD card through
+//! `hil::block_storage::BlockStorage` once it is initialized.
 //!
 //! Usage
 //! -----
@@ -30,6 +32,14 @@
 //!     capsules::sdcard::SDCardDriver::new(sdcard, &mut capsules::sdcard::KERNEL_BUFFER));
 //! sdcard.set_client(sdcard_driver);
 //! ```
+//!
+//! A capsule using the SD card as a block device instead sets itself as its
+//! block storage client, and receives the callbacks of the block operations:
+//!
+//! ```rust
+//! hil::block_storage::BlockStorage::set_client(sdcard, filesystem);
+//! sdcard.initialize();
+//! ```
 
 // Resources for SD Card API:
 //  * elm-chan.org/docs/mmc/mmc_e.html
@@ -76,6 +86,12 @@ pub struct SDCard<'a, A: hil::time::Alarm> {
     client: OptionalCell<&'static SDCardClient>,
     client_buffer: TakeCell<'static, [u8]>,
     client_offset: Cell<usize>,
+
+    block_client: OptionalCell<&'static hil::block_storage::Client>,
+    transaction: Cell<Transaction>,
+    total_size: Cell<u64>,
+    write_sector: Cell<u32>,
+    write_remaining: Cell<u32>,
 }
 
 /// SD card command codes
@@ -92,6 +108,9 @@ enum SDCmd {
     CMD18_ReadMultiple = 18,              //         Read multiple blocks
     CMD24_WriteSingle = 24,               //          Write single block
     CMD25_WriteMultiple = 25,             //        Write multiple blocks
+    CMD32_EraseStart = 32,                //           Set first block to erase
+    CMD33_EraseEnd = 33,                  //             Set last block to erase
+    CMD38_Erase = 38,                     //                Erase selected blocks
     CMD55_ManufSpecificCommand = 55,      // Next command will be manufacturer specific
     CMD58_ReadOCR = 58,                   //              Read operation condition register (OCR)
     ACMD41_ManufSpecificInit = 0x80 + 41, // Manufacturer specific Init
@@ -131,10 +150,15 @@ enum SpiState {
     ReceivedBlock { count: u32 },
     ReadBlocksComplete,
 
-    StartWriteBlocks { count: u32 },
+    StartWriteBlock,
     WriteBlockResponse,
     WriteBlockBusy,
     WaitWriteBlockBusy,
+
+    EraseSetStart { end: u32 },
+    EraseSetEnd,
+    EraseResponse,
+    WaitEraseBusy,
 }
 
 /// Alarm states
@@ -152,6 +176,7 @@ enum AlarmState {
     WaitForDataBlocks { count: u32 },
 
     WaitForWriteBusy,
+    WaitForEraseBusy,
 }
 
 /// Error codes returned if an SD card transaction fails
@@ -162,6 +187,16 @@ enum ErrorCode {
     ReadFailure = -3,
     WriteFailure = -4,
     TimeoutFailure = -5,
+    EraseFailure = -6,
+}
+
+/// Block operations in progress, whose buffer is returned if they fail
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Transaction {
+    None,
+    Read,
+    Write,
+    Erase,
 }
 
 /// SD card types, determined during initialization
@@ -237,6 +272,11 @@ impl<A: hil::time::Alarm> SDCard<'a, A> {
             client: OptionalCell::empty(),
             client_buffer: TakeCell::empty(),
             client_offset: Cell::new(0),
+            block_client: OptionalCell::empty(),
+            transaction: Cell::new(Transaction::None),
+            total_size: Cell::new(0),
+            write_sector: Cell::new(0),
+            write_remaining: Cell::new(0),
         }
     }
 
@@ -401,6 +441,76 @@ impl<A: hil::time::Alarm> SDCard<'a, A> {
         (r1, r2, r3)
     }
 
+    /// convert block address to byte address for non-block access cards
+    fn block_address(&self, sector: u32) -> u32 {
+        if self.card_type.get() == SDCardType::SDv2BlockAddressable {
+            sector
+        } else {
+            sector * 512
+        }
+    }
+
+    /// pass a finished read to the client that started it
+    fn read_complete(&self, buffer: &'static mut [u8], len: usize) {
+        if self.transaction.get() == Transaction::Read {
+            self.transaction.set(Transaction::None);
+            self.block_client.map(move |client| {
+                client.read_done(buffer, ReturnCode::SUCCESS);
+            });
+        } else {
+            self.client.map(move |client| {
+                client.read_done(buffer, len);
+            });
+        }
+    }
+
+    /// pass a finished write to the client that started it
+    fn write_complete(&self, buffer: &'static mut [u8]) {
+        if self.transaction.get() == Transaction::Write {
+            self.transaction.set(Transaction::None);
+            self.block_client.map(move |client| {
+                client.write_done(buffer, ReturnCode::SUCCESS);
+            });
+        } else {
+            self.client.map(move |client| {
+                client.write_done(buffer);
+            });
+        }
+    }
+
+    /// tell the client that started a transaction that it failed, returning
+    /// the buffer to block storage clients
+    fn report_error(&self, error: ErrorCode) {
+        let transaction = self.transaction.get();
+        self.transaction.set(Transaction::None);
+        match transaction {
+            Transaction::None => {
+                self.client.map(move |client| {
+                    client.error(error as u32);
+                });
+            }
+            Transaction::Read => {
+                self.client_buffer.take().map(|buffer| {
+                    self.block_client.map(move |client| {
+                        client.read_done(buffer, ReturnCode::FAIL);
+                    });
+                });
+            }
+            Transaction::Write => {
+                self.client_buffer.take().map(|buffer| {
+                    self.block_client.map(move |client| {
+                        client.write_done(buffer, ReturnCode::FAIL);
+                    });
+                });
+            }
+            Transaction::Erase => {
+                self.block_client.map(move |client| {
+                    client.erase_done(ReturnCode::FAIL);
+                });
+            }
+        }
+    }
+
     /// updates SD card state on SPI transaction returns
     fn process_spi_states(
         &self,
@@ -444,9 +554,7 @@ impl<A: hil::time::Alarm> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(ErrorCode::InitializationFailure as u32);
-                    });
+                    self.report_error(ErrorCode::InitializationFailure);
                 }
             }
 
@@ -515,9 +623,7 @@ impl<A: hil::time::Alarm> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(ErrorCode::InitializationFailure as u32);
-                    });
+                    self.report_error(ErrorCode::InitializationFailure);
                 }
             }
 
@@ -544,9 +650,7 @@ impl<A: hil::time::Alarm> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(ErrorCode::InitializationFailure as u32);
-                    });
+                    self.report_error(ErrorCode::InitializationFailure);
                 }
             }
 
@@ -612,9 +716,7 @@ impl<A: hil::time::Alarm> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(ErrorCode::InitializationFailure as u32);
-                    });
+                    self.report_error(ErrorCode::InitializationFailure);
                 }
             }
 
@@ -651,9 +753,7 @@ impl<A: hil::time::Alarm> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(ErrorCode::InitializationFailure as u32);
-                    });
+                    self.report_error(ErrorCode::InitializationFailure);
                 }
             }
 
@@ -674,9 +774,7 @@ impl<A: hil::time::Alarm> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(ErrorCode::InitializationFailure as u32);
-                    });
+                    self.report_error(ErrorCode::InitializationFailure);
                 }
             }
 
@@ -724,6 +822,7 @@ impl<A: hil::time::Alarm> SDCard<'a, A> {
                     // initialization complete
                     self.state.set(SpiState::Idle);
                     self.is_initialized.set(true);
+                    self.total_size.set(total_size);
 
                     // perform callback
                     self.client.map(move |client| {
@@ -736,9 +835,7 @@ impl<A: hil::time::Alarm> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(ErrorCode::InitializationFailure as u32);
-                    });
+                    self.report_error(ErrorCode::InitializationFailure);
                 }
             }
 
@@ -763,9 +860,7 @@ impl<A: hil::time::Alarm> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(ErrorCode::ReadFailure as u32);
-                    });
+                    self.report_error(ErrorCode::ReadFailure);
                 }
             }
 
@@ -794,9 +889,7 @@ impl<A: hil::time::Alarm> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(ErrorCode::ReadFailure as u32);
-                    });
+                    self.report_error(ErrorCode::ReadFailure);
                 }
             }
 
@@ -820,9 +913,7 @@ impl<A: hil::time::Alarm> SDCard<'a, A> {
 
                         // callback
                         let read_len = cmp::min(read_buffer.len(), cmp::min(buffer.len(), 512));
-                        self.client.map(move |client| {
-                            client.read_done(buffer, read_len);
-                        });
+                        self.read_complete(buffer, read_len);
                     });
                 });
             }
@@ -853,9 +944,7 @@ impl<A: hil::time::Alarm> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(ErrorCode::ReadFailure as u32);
-                    });
+                    self.report_error(ErrorCode::ReadFailure);
                 }
             }
 
@@ -904,9 +993,7 @@ impl<A: hil::time::Alarm> SDCard<'a, A> {
 
                     // read finished, perform callback
                     self.client_buffer.take().map(move |buffer| {
-                        self.client.map(move |client| {
-                            client.read_done(buffer, self.client_offset.get());
-                        });
+                        self.read_complete(buffer, self.client_offset.get());
                     });
                 } else {
                     // error, send callback and quit
@@ -915,63 +1002,55 @@ impl<A: hil::time::Alarm> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(ErrorCode::ReadFailure as u32);
-                    });
+                    self.report_error(ErrorCode::ReadFailure);
                 }
             }
 
-            SpiState::StartWriteBlocks { count } => {
+            SpiState::StartWriteBlock => {
                 // check response
                 let (r1, _, _) = self.get_response(SDResponse::R1_Status, read_buffer);
 
                 if r1 == SUCCESS_STATUS {
-                    if count <= 1 {
-                        let bytes_written = self.client_buffer.map_or(0, |buffer| {
-                            // copy over data from client buffer
-                            // Limit to minimum length between write_buffer,
-                            // buffer, and 512 (block size)
-                            for (write_byte, &client_byte) in
-                                write_buffer.iter_mut().skip(1).zip(buffer.iter()).take(512)
-                            {
-                                *write_byte = client_byte;
-                            }
-
-                            // calculate number of bytes written
-                            cmp::min(write_buffer.len(), cmp::min(buffer.len(), 512))
-                        });
-
-                        // set a known value for remaining bytes
-                        for write_byte in write_buffer
+                    let offset = self.client_offset.get();
+                    let bytes_written = self.client_buffer.map_or(0, |buffer| {
+                        // copy over data of the current block from client
+                        // buffer
+                        // Limit to minimum length between write_buffer,
+                        // buffer, and 512 (block size)
+                        for (write_byte, &client_byte) in write_buffer
                             .iter_mut()
                             .skip(1)
-                            .skip(bytes_written)
+                            .zip(buffer.iter().skip(offset))
                             .take(512)
                         {
-                            *write_byte = 0xFF;
+                            *write_byte = client_byte;
                         }
 
-                        // set up remainder of data packet
-                        write_buffer[0] = DATA_TOKEN; // Data token
-                        write_buffer[513] = 0xFF; // dummy CRC
-                        write_buffer[514] = 0xFF; // dummy CRC
+                        // calculate number of bytes written
+                        cmp::min(
+                            write_buffer.len(),
+                            cmp::min(buffer.len().saturating_sub(offset), 512),
+                        )
+                    });
 
-                        // write data packet
-                        self.state.set(SpiState::WriteBlockResponse);
-                        self.write_bytes(write_buffer, read_buffer, 515);
-                    } else {
-                        // multi-block SD card writes are unimplemented
-                        // This should have returned an error already, but if
-                        // we got here somehow, return an error and quit now
-                        self.txbuffer.replace(write_buffer);
-                        self.rxbuffer.replace(read_buffer);
-                        self.state.set(SpiState::Idle);
-                        self.alarm_state.set(AlarmState::Idle);
-                        self.alarm_count.set(0);
-                        self.client.map(move |client| {
-                            client.error(ErrorCode::WriteFailure as u32);
-                        });
+                    // set a known value for remaining bytes
+                    for write_byte in write_buffer
+                        .iter_mut()
+                        .skip(1)
+                        .skip(bytes_written)
+                        .take(512)
+                    {
+                        *write_byte = 0xFF;
                     }
+
+                    // set up remainder of data packet
+                    write_buffer[0] = DATA_TOKEN; // Data token
+                    write_buffer[513] = 0xFF; // dummy CRC
+                    write_buffer[514] = 0xFF; // dummy CRC
+
+                    // write data packet
+                    self.state.set(SpiState::WriteBlockResponse);
+                    self.write_bytes(write_buffer, read_buffer, 515);
                 } else {
                     // error, send callback and quit
                     self.txbuffer.replace(write_buffer);
@@ -979,9 +1058,7 @@ impl<A: hil::time::Alarm> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(ErrorCode::WriteFailure as u32);
-                    });
+                    self.report_error(ErrorCode::WriteFailure);
                 }
             }
 
@@ -1003,35 +1080,132 @@ impl<A: hil::time::Alarm> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.map(move |client| {
-                        client.error(ErrorCode::WriteFailure as u32);
-                    });
+                    self.report_error(ErrorCode::WriteFailure);
                 }
             }
 
             SpiState::WaitWriteBlockBusy => {
                 // check if line is still held low (busy state)
                 if read_buffer[0] != 0x00 {
+                    self.alarm_count.set(0);
+                    let remaining = self.write_remaining.get().saturating_sub(1);
+                    if remaining > 0 {
+                        // write the next block
+                        let sector = self.write_sector.get() + 1;
+                        self.write_sector.set(sector);
+                        self.write_remaining.set(remaining);
+                        self.client_offset.set(self.client_offset.get() + 512);
+                        self.state.set(SpiState::StartWriteBlock);
+                        self.send_command(
+                            SDCmd::CMD24_WriteSingle,
+                            self.block_address(sector),
+                            write_buffer,
+                            read_buffer,
+                            10,
+                        );
+                    } else {
+                        // replace buffers
+                        self.txbuffer.replace(write_buffer);
+                        self.rxbuffer.replace(read_buffer);
+
+                        // write finished, perform callback
+                        self.state.set(SpiState::Idle);
+                        self.client_buffer.take().map(move |buffer| {
+                            self.write_complete(buffer);
+                        });
+                    }
+                } else {
                     // replace buffers
                     self.txbuffer.replace(write_buffer);
                     self.rxbuffer.replace(read_buffer);
 
-                    // read finished, perform callback
+                    // try again after 1 ms
+                    self.alarm_state.set(AlarmState::WaitForWriteBusy);
+                    let interval = (1 as u32) * <A::Frequency>::frequency() / 1000;
+                    let tics = self.alarm.now().wrapping_add(interval);
+                    self.alarm.set_alarm(tics);
+                }
+            }
+
+            SpiState::EraseSetStart { end } => {
+                // check response
+                let (r1, _, _) = self.get_response(SDResponse::R1_Status, read_buffer);
+
+                if r1 == SUCCESS_STATUS {
+                    // set the last block to erase
+                    self.state.set(SpiState::EraseSetEnd);
+                    self.send_command(SDCmd::CMD33_EraseEnd, end, write_buffer, read_buffer, 10);
+                } else {
+                    // error, send callback and quit
+                    self.txbuffer.replace(write_buffer);
+                    self.rxbuffer.replace(read_buffer);
                     self.state.set(SpiState::Idle);
+                    self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client_buffer.take().map(move |buffer| {
-                        self.client.map(move |client| {
-                            client.write_done(buffer);
-                        });
+                    self.report_error(ErrorCode::EraseFailure);
+                }
+            }
+
+            SpiState::EraseSetEnd => {
+                // check response
+                let (r1, _, _) = self.get_response(SDResponse::R1_Status, read_buffer);
+
+                if r1 == SUCCESS_STATUS {
+                    // erase the selected blocks
+                    self.state.set(SpiState::EraseResponse);
+                    self.send_command(SDCmd::CMD38_Erase, 0x0, write_buffer, read_buffer, 10);
+                } else {
+                    // error, send callback and quit
+                    self.txbuffer.replace(write_buffer);
+                    self.rxbuffer.replace(read_buffer);
+                    self.state.set(SpiState::Idle);
+                    self.alarm_state.set(AlarmState::Idle);
+                    self.alarm_count.set(0);
+                    self.report_error(ErrorCode::EraseFailure);
+                }
+            }
+
+            SpiState::EraseResponse => {
+                // check response
+                let (r1, _, _) = self.get_response(SDResponse::R1_Status, read_buffer);
+
+                if r1 == SUCCESS_STATUS {
+                    // the card holds the line low while it erases
+                    self.state.set(SpiState::WaitEraseBusy);
+                    self.read_bytes(write_buffer, read_buffer, 1);
+                } else {
+                    // error, send callback and quit
+                    self.txbuffer.replace(write_buffer);
+                    self.rxbuffer.replace(read_buffer);
+                    self.state.set(SpiState::Idle);
+                    self.alarm_state.set(AlarmState::Idle);
+                    self.alarm_count.set(0);
+                    self.report_error(ErrorCode::EraseFailure);
+                }
+            }
+
+            SpiState::WaitEraseBusy => {
+                // check if line is still held low (busy state)
+                if read_buffer[0] != 0x00 {
+                    // replace buffers
+                    self.txbuffer.replace(write_buffer);
+                    self.rxbuffer.replace(read_buffer);
+
+                    // erase finished, perform callback
+                    self.state.set(SpiState::Idle);
+                    self.alarm_count.set(0);
+                    self.transaction.set(Transaction::None);
+                    self.block_client.map(|client| {
+                        client.erase_done(ReturnCode::SUCCESS);
                     });
                 } else {
                     // replace buffers
                     self.txbuffer.replace(write_buffer);
                     self.rxbuffer.replace(read_buffer);
 
-                    // try again after 1 ms
-                    self.alarm_state.set(AlarmState::WaitForWriteBusy);
-                    let interval = (1 as u32) * <A::Frequency>::frequency() / 1000;
+                    // erasing takes longer than writing, try again after 10 ms
+                    self.alarm_state.set(AlarmState::WaitForEraseBusy);
+                    let interval = (10 as u32) * <A::Frequency>::frequency() / 1000;
                     let tics = self.alarm.now().wrapping_add(interval);
                     self.alarm.set_alarm(tics);
                 }
@@ -1056,9 +1230,7 @@ impl<A: hil::time::Alarm> SDCard<'a, A> {
             self.state.set(SpiState::Idle);
             self.alarm_state.set(AlarmState::Idle);
             self.alarm_count.set(0);
-            self.client.map(move |client| {
-                client.error(ErrorCode::TimeoutFailure as u32);
-            });
+            self.report_error(ErrorCode::TimeoutFailure);
         } else {
             self.alarm_count.set(repeats + 1);
         }
@@ -1174,6 +1346,19 @@ impl<A: hil::time::Alarm> SDCard<'a, A> {
                 self.alarm_state.set(AlarmState::Idle);
             }
 
+            AlarmState::WaitForEraseBusy => {
+                // check if the erase has finished
+                self.txbuffer.take().map(|write_buffer| {
+                    self.rxbuffer.take().map(move |read_buffer| {
+                        // check if sd card is busy
+                        self.state.set(SpiState::WaitEraseBusy);
+                        self.read_bytes(write_buffer, read_buffer, 1);
+                    });
+                });
+
+                self.alarm_state.set(AlarmState::Idle);
+            }
+
             AlarmState::Idle => {
                 // receiving an event from Idle means something was killed
                 // do nothing
@@ -1228,6 +1413,28 @@ impl<A: hil::time::Alarm> SDCard<'a, A> {
         }
     }
 
+    /// check that a block operation can start, so that it does not fail
+    ///  after taking the client's buffer
+    fn check_blocks(&self, block: usize, count: usize, buffer_len: Option<usize>) -> ReturnCode {
+        if !self.is_installed() || !self.is_initialized() {
+            return ReturnCode::EOFF;
+        }
+        if self.state.get() != SpiState::Idle
+            || self.alarm_state.get() != AlarmState::Idle
+            || self.txbuffer.is_none()
+            || self.rxbuffer.is_none()
+        {
+            return ReturnCode::EBUSY;
+        }
+        if buffer_len.map_or(false, |len| len < count * 512) {
+            return ReturnCode::ESIZE;
+        }
+        if count == 0 || (block + count) as u64 * 512 > self.total_size.get() {
+            return ReturnCode::EINVAL;
+        }
+        ReturnCode::SUCCESS
+    }
+
     pub fn read_blocks(&self, buffer: &'static mut [u8], sector: u32, count: u32) -> ReturnCode {
         // only if initialized and installed
         if self.is_installed() {
@@ -1240,12 +1447,7 @@ impl<A: hil::time::Alarm> SDCard<'a, A> {
                             self.client_buffer.replace(buffer);
                             self.client_offset.set(0);
 
-                            // convert block address to byte address for non-block
-                            //  access cards
-                            let mut address = sector;
-                            if self.card_type.get() != SDCardType::SDv2BlockAddressable {
-                                address *= 512;
-                            }
+                            let address = self.block_address(sector);
 
                             self.state.set(SpiState::StartReadBlocks { count: count });
                             if count == 1 {
@@ -1292,29 +1494,21 @@ impl<A: hil::time::Alarm> SDCard<'a, A> {
                             self.client_buffer.replace(buffer);
                             self.client_offset.set(0);
 
-                            // convert block address to byte address for non-block
-                            //  access cards
-                            let mut address = sector;
-                            if self.card_type.get() != SDCardType::SDv2BlockAddressable {
-                                address *= 512;
-                            }
+                            // blocks are written one at a time
+                            self.write_sector.set(sector);
+                            self.write_remaining.set(count);
 
-                            self.state.set(SpiState::StartWriteBlocks { count: count });
-                            if count == 1 {
-                                self.send_command(
-                                    SDCmd::CMD24_WriteSingle,
-                                    address,
-                                    txbuffer,
-                                    rxbuffer,
-                                    10,
-                                );
+                            self.state.set(SpiState::StartWriteBlock);
+                            self.send_command(
+                                SDCmd::CMD24_WriteSingle,
+                                self.block_address(sector),
+                                txbuffer,
+                                rxbuffer,
+                                10,
+                            );
 
-                                // command started successfully
-                                ReturnCode::SUCCESS
-                            } else {
-                                // can't write multiple blocks yet
-                                ReturnCode::ENOSUPPORT
-                            }
+                            // command started successfully
+                            ReturnCode::SUCCESS
                         })
                 })
             } else {
@@ -1328,6 +1522,84 @@ impl<A: hil::time::Alarm> SDCard<'a, A> {
     }
 }
 
+/// Block device interface, used by kernel capsules such as filesystems
+impl<A: hil::time::Alarm> hil::block_storage::BlockStorage for SDCard<'a, A> {
+    fn set_client(&self, client: &'static hil::block_storage::Client) {
+        self.block_client.set(client);
+    }
+
+    fn block_size(&self) -> usize {
+        512
+    }
+
+    fn block_count(&self) -> usize {
+        if self.is_installed() && self.is_initialized() {
+            (self.total_size.get() / 512) as usize
+        } else {
+            0
+        }
+    }
+
+    fn read_blocks(
+        &self,
+        block: usize,
+        count: usize,
+        buffer: &'static mut [u8],
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        let result = self.check_blocks(block, count, Some(buffer.len()));
+        if result != ReturnCode::SUCCESS {
+            return (result, Some(buffer));
+        }
+        self.transaction.set(Transaction::Read);
+        (self.read_blocks(buffer, block as u32, count as u32), None)
+    }
+
+    fn write_blocks(
+        &self,
+        block: usize,
+        count: usize,
+        buffer: &'static mut [u8],
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        let result = self.check_blocks(block, count, Some(buffer.len()));
+        if result != ReturnCode::SUCCESS {
+            return (result, Some(buffer));
+        }
+        self.transaction.set(Transaction::Write);
+        (self.write_blocks(buffer, block as u32, count as u32), None)
+    }
+
+    fn erase_blocks(&self, block: usize, count: usize) -> ReturnCode {
+        let result = self.check_blocks(block, count, None);
+        if result != ReturnCode::SUCCESS {
+            return result;
+        }
+        if self.card_type.get() == SDCardType::MMC {
+            // MMC cards select the blocks to erase with other commands
+            return ReturnCode::ENOSUPPORT;
+        }
+        self.txbuffer.take().map_or(ReturnCode::EBUSY, |txbuffer| {
+            self.rxbuffer
+                .take()
+                .map_or(ReturnCode::EBUSY, move |rxbuffer| {
+                    let start = block as u32;
+                    let end = start + count as u32 - 1;
+                    self.transaction.set(Transaction::Erase);
+                    self.state.set(SpiState::EraseSetStart {
+                        end: self.block_address(end),
+                    });
+                    self.send_command(
+                        SDCmd::CMD32_EraseStart,
+                        self.block_address(start),
+                        txbuffer,
+                        rxbuffer,
+                        10,
+                    );
+                    ReturnCode::SUCCESS
+                })
+        })
+    }
+}
+
 /// Handle callbacks from the SPI peripheral
 impl<A: hil::time::Alarm> hil::spi::SpiMasterClient for SDCard<'a, A> {
     fn read_write_done(
@@ -1359,9 +1631,7 @@ impl<A: hil::time::Alarm> hil::gpio::Client for SDCard<'a, A> {
             //  send an error callback
             self.state.set(SpiState::Idle);
             self.alarm_state.set(AlarmState::Idle);
-            self.client.map(move |client| {
-                client.error(ErrorCode::CardStateChanged as u32);
-            });
+            self.report_error(ErrorCode::CardStateChanged);
         }
 
         // either the card is new or gone, in either case it isn't initialized
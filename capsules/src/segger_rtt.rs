@@ -21,15 +21,24 @@
 //! Notes
 //! -----
 //!
-//! This capsule requires a timer, but the timer is only there to defer the
-//! `transmit_complete` callback until the next scheduler loop. In the future,
-//! if there is support for software interrupts or deferred calls in capsules,
-//! this timer should be removed.
+//! This capsule requires a timer. It defers the `transmit_complete` callback
+//! until the next scheduler loop, and polls the buffers, since the host
+//! doesn't signal when it has read from the up buffer or written to the down
+//! buffer.
 //!
-//! Todo
-//! ----
+//! What happens to output that doesn't fit in the up buffer depends on the
+//! mode in the `flags` of the buffer, which the host can change: in the
+//! default trim mode, what fits is written and the rest is dropped, in skip
+//! mode, a transmission that doesn't fit is dropped whole, and in blocking
+//! mode, the transmission completes once the host has made room for all of
+//! it. Blocking mode stalls the console if no debugger reads the buffer.
 //!
-//! - Implement receive functionality.
+//! Received bytes are read from the down buffer, so `SeggerRtt` works as the
+//! UART of a console or of a `UartMux`, on boards whose UART pins aren't
+//! reachable.
+//!
+//! The RTT channel can also receive the output of a kernel panic, since
+//! `SeggerRtt` implements `kernel::debug::PanicSink`.
 //!
 //! Usage
 //! -----
@@ -90,7 +99,11 @@
 //! console.initialize();
 //! ```
 
+use core::cell::Cell;
+use core::cmp;
+use core::ptr;
 use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::debug::PanicSink;
 use kernel::hil;
 use kernel::hil::time::Frequency;
 use kernel::ReturnCode;
@@ -101,6 +114,17 @@ pub static mut UP_BUFFER: [u8; 1024] = [0; 1024];
 /// Buffer for receiving messages from the host.
 pub static mut DOWN_BUFFER: [u8; 32] = [0; 32];
 
+/// The modes of the up buffer, in the low bits of its `flags`.
+const MODE_MASK: u32 = 0b11;
+const MODE_NO_BLOCK_SKIP: u32 = 0;
+const MODE_NO_BLOCK_TRIM: u32 = 1;
+const MODE_BLOCK_IF_FIFO_FULL: u32 = 2;
+
+/// The delay of the `transmit_complete` callback.
+const TX_DELAY_US: u32 = 100;
+/// How often the buffers are checked while waiting for the host.
+const POLL_INTERVAL_US: u32 = 10_000;
+
 /// This structure is defined by the segger RTT protocol. It must exist in
 /// memory in exactly this form so that the segger JTAG tool can find it in the
 /// chip's memory and read and write messages to the appropriate buffers.
@@ -138,15 +162,15 @@ impl SeggerRttMemory {
             up_buffer: SeggerRttBuffer {
                 name: up_buffer_name.as_ptr(),
                 buffer: up_buffer.as_ptr(),
-                length: 1024,
+                length: up_buffer.len() as u32,
                 write_position: 0,
                 read_position: 0,
-                flags: 0,
+                flags: MODE_NO_BLOCK_TRIM,
             },
             down_buffer: SeggerRttBuffer {
                 name: down_buffer_name.as_ptr(),
                 buffer: down_buffer.as_ptr(),
-                length: 32,
+                length: down_buffer.len() as u32,
                 write_position: 0,
                 read_position: 0,
                 flags: 0,
@@ -159,9 +183,16 @@ pub struct SeggerRtt<'a, A: hil::time::Alarm> {
     alarm: &'a A, // Dummy alarm so we can get a callback.
     config: TakeCell<'static, SeggerRttMemory>,
     up_buffer: TakeCell<'static, [u8]>,
-    _down_buffer: TakeCell<'static, [u8]>,
+    down_buffer: TakeCell<'static, [u8]>,
     client: OptionalCell<&'static hil::uart::Client>,
     client_buffer: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+    /// How many bytes of the transmission are in the up buffer.
+    tx_position: Cell<usize>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    rx_len: Cell<usize>,
+    rx_position: Cell<usize>,
+    rx_aborted: Cell<bool>,
 }
 
 impl<A: hil::time::Alarm> SeggerRtt<'a, A> {
@@ -175,11 +206,109 @@ impl<A: hil::time::Alarm> SeggerRtt<'a, A> {
             alarm: alarm,
             config: TakeCell::new(config),
             up_buffer: TakeCell::new(up_buffer),
-            _down_buffer: TakeCell::new(down_buffer),
+            down_buffer: TakeCell::new(down_buffer),
             client: OptionalCell::empty(),
             client_buffer: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            tx_position: Cell::new(0),
+            rx_buffer: TakeCell::empty(),
+            rx_len: Cell::new(0),
+            rx_position: Cell::new(0),
+            rx_aborted: Cell::new(false),
         }
     }
+
+    fn schedule(&self, us: u32) {
+        let interval = us * <A::Frequency>::frequency() / 1000000;
+        let tics = self.alarm.now().wrapping_add(interval);
+        self.alarm.set_alarm(tics);
+    }
+
+    /// The mode of the up buffer, which the host may change at any time.
+    fn mode(&self) -> u32 {
+        self.config.map_or(MODE_NO_BLOCK_TRIM, |config| unsafe {
+            ptr::read_volatile(&config.up_buffer.flags) & MODE_MASK
+        })
+    }
+
+    /// Copy as much of `data` into the up buffer as the mode allows, and
+    /// return how many bytes were copied. Once the `write_position` is
+    /// incremented the RTT listener will go ahead and read it.
+    fn write_up_buffer(&self, data: &[u8]) -> usize {
+        let mode = self.mode();
+        self.up_buffer.map_or(0, |buffer| {
+            self.config.map_or(0, |config| {
+                let index = config.up_buffer.write_position as usize;
+                let buffer_len = config.up_buffer.length as usize;
+                let read_position =
+                    unsafe { ptr::read_volatile(&config.up_buffer.read_position) } as usize;
+
+                // One byte stays free, so that a full buffer differs from an
+                // empty one.
+                let room = (read_position + buffer_len - index - 1) % buffer_len;
+                let len = if mode == MODE_NO_BLOCK_SKIP && data.len() > room {
+                    0
+                } else {
+                    cmp::min(data.len(), room)
+                };
+
+                for i in 0..len {
+                    buffer[(i + index) % buffer_len] = data[i];
+                }
+
+                unsafe {
+                    ptr::write_volatile(
+                        &mut config.up_buffer.write_position,
+                        ((index + len) % buffer_len) as u32,
+                    );
+                }
+                len
+            })
+        })
+    }
+
+    /// Copy what the host wrote into the down buffer into `data`, and return
+    /// how many bytes were copied.
+    fn read_down_buffer(&self, data: &mut [u8]) -> usize {
+        self.down_buffer.map_or(0, |buffer| {
+            self.config.map_or(0, |config| {
+                let mut index = config.down_buffer.read_position as usize;
+                let buffer_len = config.down_buffer.length as usize;
+                let write_position =
+                    unsafe { ptr::read_volatile(&config.down_buffer.write_position) } as usize;
+
+                let mut len = 0;
+                while index != write_position && len < data.len() {
+                    data[len] = unsafe { ptr::read_volatile(&buffer[index]) };
+                    index = (index + 1) % buffer_len;
+                    len += 1;
+                }
+
+                unsafe {
+                    ptr::write_volatile(&mut config.down_buffer.read_position, index as u32);
+                }
+                len
+            })
+        })
+    }
+
+    /// Write more of the current transmission into the up buffer.
+    fn continue_transmit(&self) {
+        self.client_buffer.map(|buffer| {
+            let position = self.tx_position.get();
+            let written = self.write_up_buffer(&buffer[position..self.tx_len.get()]);
+            self.tx_position.set(position + written);
+        });
+    }
+
+    /// Read more of the current reception from the down buffer.
+    fn continue_receive(&self) {
+        self.rx_buffer.map(|buffer| {
+            let position = self.rx_position.get();
+            let read = self.read_down_buffer(&mut buffer[position..self.rx_len.get()]);
+            self.rx_position.set(position + read);
+        });
+    }
 }
 
 impl<A: hil::time::Alarm> hil::uart::UART for SeggerRtt<'a, A> {
@@ -192,44 +321,78 @@ impl<A: hil::time::Alarm> hil::uart::UART for SeggerRtt<'a, A> {
     }
 
     fn transmit(&self, tx_data: &'static mut [u8], tx_len: usize) {
-        self.up_buffer.map(|buffer| {
-            self.config.map(|config| {
-                // Copy the incoming data into the buffer. Once we increment
-                // the `write_position` the RTT listener will go ahead and read
-                // the message from us.
-                let mut index = config.up_buffer.write_position as usize;
-                let buffer_len = config.up_buffer.length as usize;
-
-                for i in 0..tx_len {
-                    buffer[(i + index) % buffer_len] = tx_data[i];
-                }
-
-                index = (index + tx_len) % buffer_len;
-                config.up_buffer.write_position = index as u32;
-            });
-        });
-
         // Save the client buffer so we can pass it back with the callback.
         self.client_buffer.replace(tx_data);
+        self.tx_len.set(tx_len);
+        self.tx_position.set(0);
+        self.continue_transmit();
 
         // Start a short timer so that we get a callback and can issue the
         // callback to the client.
-        let interval = (100 as u32) * <A::Frequency>::frequency() / 1000000;
-        let tics = self.alarm.now().wrapping_add(interval);
-        self.alarm.set_alarm(tics);
+        self.schedule(TX_DELAY_US);
     }
 
-    fn receive(&self, _rx_buf: &'static mut [u8], _rx_len: usize) {}
+    fn receive(&self, rx_buf: &'static mut [u8], rx_len: usize) {
+        self.rx_len.set(cmp::min(rx_len, rx_buf.len()));
+        self.rx_position.set(0);
+        self.rx_aborted.set(false);
+        self.rx_buffer.replace(rx_buf);
+        self.schedule(TX_DELAY_US);
+    }
 
-    fn abort_receive(&self) {}
+    fn abort_receive(&self) {
+        if self.rx_buffer.is_some() {
+            self.rx_aborted.set(true);
+            self.schedule(TX_DELAY_US);
+        }
+    }
 }
 
 impl<A: hil::time::Alarm> hil::time::Client for SeggerRtt<'a, A> {
     fn fired(&self) {
+        // In blocking mode, a transmission waits until the host has read
+        // enough of the up buffer to take all of it.
+        self.continue_transmit();
+        let tx_done = self.tx_position.get() == self.tx_len.get()
+            || self.mode() != MODE_BLOCK_IF_FIFO_FULL;
+        let tx_buffer = if tx_done {
+            self.client_buffer.take()
+        } else {
+            None
+        };
+
+        self.continue_receive();
+        let rx_error = if self.rx_aborted.get() {
+            Some(hil::uart::Error::Aborted)
+        } else if self.rx_position.get() == self.rx_len.get() {
+            Some(hil::uart::Error::CommandComplete)
+        } else {
+            None
+        };
+        let rx_buffer = rx_error.and_then(|_| self.rx_buffer.take());
+
+        if self.client_buffer.is_some() || self.rx_buffer.is_some() {
+            self.schedule(POLL_INTERVAL_US);
+        }
+
         self.client.map(|client| {
-            self.client_buffer.take().map(|buffer| {
+            tx_buffer.map(|buffer| {
                 client.transmit_complete(buffer, hil::uart::Error::CommandComplete);
             });
+            rx_buffer.map(|buffer| {
+                self.rx_aborted.set(false);
+                client.receive_complete(
+                    buffer,
+                    self.rx_position.get(),
+                    rx_error.unwrap_or(hil::uart::Error::CommandComplete),
+                );
+            });
         });
     }
 }
+
+impl<A: hil::time::Alarm> PanicSink for SeggerRtt<'a, A> {
+    fn write(&self, s: &str) {
+        self.write_up_buffer(s.as_bytes());
+    }
+}
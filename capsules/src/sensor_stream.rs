@@ -0,0 +1,300 @@
+//! Provides userspace with batches of samples from streaming sensors.
+//!
+//! The driver serves a list of `hil::sensors::StreamingSensor` sources, such
+//! as IMUs with a hardware FIFO, or one-shot sensors behind
+//! `ninedof_stream`. An app sets the watermark of a source, starts it at an
+//! output data rate, and gets a callback per batch of `watermark` samples
+//! instead of one per sample. Like the microphone driver, the app shares a
+//! buffer of two batches per source, and the driver copies the batches into
+//! its two halves in turn, so the app handles one half while the other is
+//! written. Each source streams to one app at a time.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let stream_sources = static_init!(
+//!     [&'static kernel::hil::sensors::StreamingSensor; 1],
+//!     [accel_stream]);
+//! let sensor_stream = static_init!(
+//!     capsules::sensor_stream::SensorStream<'static>,
+//!     capsules::sensor_stream::SensorStream::new(
+//!         stream_sources,
+//!         board_kernel.create_grant(&memory_allocation_capability)));
+//! for source in stream_sources.iter() {
+//!     source.set_client(sensor_stream);
+//! }
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::hil::sensors;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = 0x60006;
+
+/// The most sources the driver serves
+pub const MAX_SOURCES: usize = 4;
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    /// The buffer for the batches of each source
+    buffers: [Option<AppSlice<Shared, u8>>; MAX_SOURCES],
+}
+
+/// The state of a source
+struct Stream {
+    /// The app the source streams to
+    owner: OptionalCell<AppId>,
+    watermark: Cell<usize>,
+    /// The number of batches copied to the owner
+    batch: Cell<usize>,
+    /// The number of samples lost since the source started
+    dropped: Cell<usize>,
+}
+
+impl Stream {
+    fn new() -> Stream {
+        Stream {
+            owner: OptionalCell::empty(),
+            watermark: Cell::new(1),
+            batch: Cell::new(0),
+            dropped: Cell::new(0),
+        }
+    }
+}
+
+pub struct SensorStream<'a> {
+    sources: &'a [&'a sensors::StreamingSensor],
+    streams: [Stream; MAX_SOURCES],
+    apps: Grant<App>,
+}
+
+impl SensorStream<'a> {
+    pub fn new(
+        sources: &'a [&'a sensors::StreamingSensor],
+        grant: Grant<App>,
+    ) -> SensorStream<'a> {
+        SensorStream {
+            sources: sources,
+            streams: [Stream::new(), Stream::new(), Stream::new(), Stream::new()],
+            apps: grant,
+        }
+    }
+
+    fn source_count(&self) -> usize {
+        if self.sources.len() < MAX_SOURCES {
+            self.sources.len()
+        } else {
+            MAX_SOURCES
+        }
+    }
+
+    /// The size of a batch of a source in the buffer of an app, in bytes.
+    fn batch_len(&self, source: usize) -> usize {
+        self.streams[source].watermark.get() * self.sources[source].sample_size() * 2
+    }
+
+    fn is_owner(&self, source: usize, appid: AppId) -> bool {
+        self.streams[source]
+            .owner
+            .map_or(false, |owner| owner.idx() == appid.idx())
+    }
+
+    fn set_watermark(&self, source: usize, watermark: usize) -> ReturnCode {
+        let stream = &self.streams[source];
+        if stream.owner.is_some() {
+            return ReturnCode::EBUSY;
+        }
+        if watermark == 0 || watermark > self.sources[source].get_fifo_size() {
+            return ReturnCode::EINVAL;
+        }
+        stream.watermark.set(watermark);
+        ReturnCode::SUCCESS
+    }
+
+    fn start(&self, source: usize, rate: u32, appid: AppId) -> ReturnCode {
+        let stream = &self.streams[source];
+        if stream.owner.is_some() {
+            return ReturnCode::EBUSY;
+        }
+        let batch_len = self.batch_len(source);
+        let valid = self
+            .apps
+            .enter(appid, |app, _| {
+                app.buffers[source]
+                    .as_ref()
+                    .map_or(false, |buffer| buffer.len() >= 2 * batch_len)
+            }).unwrap_or(false);
+        if !valid {
+            return ReturnCode::EINVAL;
+        }
+
+        let result = self.sources[source].start(source, rate, stream.watermark.get());
+        if let ReturnCode::SuccessWithValue { .. } = result {
+            stream.owner.set(appid);
+            stream.batch.set(0);
+            stream.dropped.set(0);
+        }
+        result
+    }
+
+    fn stop(&self, source: usize, appid: AppId) -> ReturnCode {
+        if !self.is_owner(source, appid) {
+            return ReturnCode::EOFF;
+        }
+        self.streams[source].owner.clear();
+        self.sources[source].stop()
+    }
+}
+
+impl Driver for SensorStream<'a> {
+    /// Share the buffer that the batches of a source are written into.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - The source: room for two batches of samples, each value a
+    ///   little-endian 16-bit signed number.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        if allow_num >= self.source_count() {
+            return ReturnCode::ENOSUPPORT;
+        }
+        self.apps
+            .enter(appid, |app, _| {
+                app.buffers[allow_num] = slice;
+                ReturnCode::SUCCESS
+            }).unwrap_or_else(|err| err.into())
+    }
+
+    /// Subscribe to batches.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: A batch was written. The callback is passed the source, the
+    ///        number of the batch, counting from 0, whose parity is the half
+    ///        of the buffer it was written into, and the number of samples
+    ///        in it, which is the watermark unless the source was flushed.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        appid: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Control streaming.
+    ///
+    /// `data` is the source for all commands but 0. They return EINVAL if
+    /// the source does not exist.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check and get the number of sources.
+    /// - `1`: Get the number of values in a sample.
+    /// - `2`: Get the highest rate, in samples per second.
+    /// - `3`: Get the size of the FIFO, the largest watermark.
+    /// - `4`: Set the watermark to `data2` samples. Returns EBUSY while the
+    ///        source streams.
+    /// - `5`: Start streaming at `data2` samples per second. Returns the
+    ///        actual rate, or EBUSY if the source streams.
+    /// - `6`: Stop streaming.
+    /// - `7`: Deliver the buffered samples now, as a short batch.
+    /// - `8`: Get the number of samples lost since the source started.
+    fn command(&self, command_num: usize, data: usize, data2: usize, appid: AppId) -> ReturnCode {
+        if command_num == 0 {
+            return ReturnCode::SuccessWithValue {
+                value: self.source_count(),
+            };
+        }
+        if data >= self.source_count() {
+            return ReturnCode::EINVAL;
+        }
+        let source = self.sources[data];
+
+        match command_num {
+            1 => ReturnCode::SuccessWithValue {
+                value: source.sample_size(),
+            },
+
+            2 => ReturnCode::SuccessWithValue {
+                value: source.get_maximum_rate() as usize,
+            },
+
+            3 => ReturnCode::SuccessWithValue {
+                value: source.get_fifo_size(),
+            },
+
+            4 => self.set_watermark(data, data2),
+
+            5 => self.start(data, data2 as u32, appid),
+
+            6 => self.stop(data, appid),
+
+            7 => {
+                if !self.is_owner(data, appid) {
+                    return ReturnCode::EOFF;
+                }
+                source.flush()
+            }
+
+            8 => ReturnCode::SuccessWithValue {
+                value: self.streams[data].dropped.get(),
+            },
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+impl sensors::StreamingSensorClient for SensorStream<'a> {
+    fn samples_ready(&self, identifier: usize, samples: &[i16], dropped: usize) {
+        let source = identifier;
+        let stream = &self.streams[source];
+        stream.dropped.set(stream.dropped.get() + dropped);
+
+        let batch = stream.batch.get();
+        let batch_len = self.batch_len(source);
+        let copied = stream.owner.map_or(false, |appid| {
+            self.apps
+                .enter(*appid, |app, _| {
+                    app.buffers[source].as_mut().map(|buffer| {
+                        // The app may have shared a shorter buffer since it
+                        // started streaming
+                        let start = (batch % 2) * batch_len;
+                        buffer.as_mut().get_mut(start..start + batch_len).map(|bytes| {
+                            for (bytes, value) in bytes.chunks_mut(2).zip(samples.iter()) {
+                                bytes[0] = *value as u8;
+                                bytes[1] = (*value >> 8) as u8;
+                            }
+                        });
+                    });
+                    let count = samples.len() / self.sources[source].sample_size();
+                    app.callback.map(|mut cb| cb.schedule(source, batch, count));
+                }).is_ok()
+        });
+
+        if copied {
+            stream.batch.set(batch.wrapping_add(1));
+        } else if stream.owner.is_some() {
+            // The app is gone
+            stream.owner.clear();
+            self.sources[source].stop();
+        }
+    }
+}
@@ -0,0 +1,254 @@
+//! Provides userspace with control of servos and motors over PWM channels.
+//!
+//! An app claims the channels it drives, and other apps cannot change them
+//! until it releases them or exits. All channels of the group run at the
+//! frequency the board chooses, typically 50 Hz for hobby servos, which are
+//! set by the width of their pulses, and tens of kHz for motor drivers,
+//! which are set by their duty cycle. Two channels can be set together, so
+//! they change in the same period, and an odd channel can output the
+//! inverse of the even channel before it, for example to drive a half
+//! bridge.
+//!
+//! The outputs start when a channel is first claimed. A channel claimed by an
+//! app that exits keeps its output until another app claims it.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let servo = static_init!(
+//!     capsules::servo::Servo<'static>,
+//!     capsules::servo::Servo::new(
+//!         &nrf52::pwm::PWM0,
+//!         50,
+//!         board_kernel.create_grant(&memory_allocation_capability)));
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::hil;
+use kernel::{AppId, Driver, Grant, ReturnCode};
+
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = 0x0000B;
+
+/// The most channels the driver controls.
+const MAX_CHANNELS: usize = 8;
+
+/// Duty cycles from apps are in thousandths.
+const DUTY_CYCLE_SCALE: usize = 1000;
+
+#[derive(Default)]
+pub struct App {
+    /// The channels the app claimed, one bit per channel
+    claimed: usize,
+}
+
+pub struct Servo<'a> {
+    pwm: &'a hil::pwm::Pwm,
+    frequency_hz: usize,
+    /// The frequency the PWM produces, 0 if stopped
+    actual_frequency_hz: Cell<usize>,
+    apps: Grant<App>,
+}
+
+impl Servo<'a> {
+    pub fn new(pwm: &'a hil::pwm::Pwm, frequency_hz: usize, grant: Grant<App>) -> Servo<'a> {
+        Servo {
+            pwm: pwm,
+            frequency_hz: frequency_hz,
+            actual_frequency_hz: Cell::new(0),
+            apps: grant,
+        }
+    }
+
+    fn channel_count(&self) -> usize {
+        cmp::min(self.pwm.channel_count(), MAX_CHANNELS)
+    }
+
+    /// The app that claimed `channel`, if any.
+    fn owner(&self, channel: usize) -> Option<AppId> {
+        self.apps
+            .iter()
+            .map(|cntr| {
+                cntr.enter(|app, _| {
+                    if app.claimed & (1 << channel) != 0 {
+                        Some(app.appid())
+                    } else {
+                        None
+                    }
+                })
+            }).find(|owner| owner.is_some())
+            .and_then(|owner| owner)
+    }
+
+    fn claimed_by(&self, channel: usize, appid: AppId) -> bool {
+        channel < self.channel_count() && self.owner(channel) == Some(appid)
+    }
+
+    fn claim(&self, channel: usize, appid: AppId) -> ReturnCode {
+        if channel >= self.channel_count() {
+            return ReturnCode::EINVAL;
+        }
+        match self.owner(channel) {
+            Some(owner) if owner == appid => return ReturnCode::EALREADY,
+            Some(_) => return ReturnCode::EBUSY,
+            None => {}
+        }
+
+        if self.actual_frequency_hz.get() == 0 {
+            match self.pwm.start(self.frequency_hz) {
+                ReturnCode::SuccessWithValue { value } => self.actual_frequency_hz.set(value),
+                rcode => return rcode,
+            }
+        } else {
+            // The channel may still have the output of an app that exited
+            if channel % 2 == 1 {
+                self.pwm.set_complementary(channel, false);
+            }
+            self.pwm.set_duty_cycle(channel, 0);
+        }
+
+        self.apps
+            .enter(appid, |app, _| {
+                app.claimed |= 1 << channel;
+                ReturnCode::SUCCESS
+            }).unwrap_or_else(|err| err.into())
+    }
+
+    fn release(&self, channel: usize, appid: AppId) -> ReturnCode {
+        if !self.claimed_by(channel, appid) {
+            return ReturnCode::ERESERVE;
+        }
+        if channel % 2 == 1 {
+            self.pwm.set_complementary(channel, false);
+        }
+        self.pwm.set_duty_cycle(channel, 0);
+        let _ = self.apps.enter(appid, |app, _| app.claimed &= !(1 << channel));
+
+        let claimed = (0..self.channel_count()).any(|channel| self.owner(channel).is_some());
+        if !claimed {
+            self.pwm.stop();
+            self.actual_frequency_hz.set(0);
+        }
+        ReturnCode::SUCCESS
+    }
+
+    /// The duty cycle of a pulse `width_us` long.
+    fn pulse_duty_cycle(&self, width_us: usize) -> Option<usize> {
+        let duty_cycle = width_us as u64
+            * self.actual_frequency_hz.get() as u64
+            * self.pwm.get_maximum_duty_cycle() as u64
+            / 1_000_000;
+        if duty_cycle > self.pwm.get_maximum_duty_cycle() as u64 {
+            None
+        } else {
+            Some(duty_cycle as usize)
+        }
+    }
+
+    /// The duty cycle of `thousandths` of the period.
+    fn scaled_duty_cycle(&self, thousandths: usize) -> Option<usize> {
+        if thousandths > DUTY_CYCLE_SCALE {
+            None
+        } else {
+            Some(thousandths * self.pwm.get_maximum_duty_cycle() / DUTY_CYCLE_SCALE)
+        }
+    }
+
+    fn set_duty_cycles(&self, channel: usize, duty_cycles: &[Option<usize>]) -> ReturnCode {
+        let mut all = [None; MAX_CHANNELS];
+        if channel + duty_cycles.len() > all.len() {
+            return ReturnCode::EINVAL;
+        }
+        for (i, duty_cycle) in duty_cycles.iter().enumerate() {
+            match *duty_cycle {
+                Some(duty_cycle) => all[channel + i] = Some(duty_cycle),
+                None => return ReturnCode::EINVAL,
+            }
+        }
+        self.pwm.set_duty_cycles(&all[..channel + duty_cycles.len()])
+    }
+}
+
+impl Driver for Servo<'a> {
+    /// Control the PWM channels.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Returns the number of channels, which also checks for the
+    ///        driver.
+    /// - `1`: Claim channel `data`. Returns EBUSY if another app claimed it.
+    /// - `2`: Release channel `data`, whose output goes low.
+    /// - `3`: Set channel `data` to output pulses `data2` µs long. Returns
+    ///        EINVAL if the pulses are longer than the period.
+    /// - `4`: Set the duty cycle of channel `data` to `data2` thousandths.
+    /// - `5`: Set the duty cycles of channels `data` and `data` + 1 together,
+    ///        to the thousandths in the lower and upper 16 bits of `data2`.
+    /// - `6`: Make the odd channel `data` output the inverse of the channel
+    ///        before it if `data2` is 1, or independent again if it is 0.
+    ///        The app must have claimed both.
+    /// - `7`: Get the frequency of the outputs in Hz, which is 0 until a
+    ///        channel is claimed.
+    ///
+    /// The commands on channels return ERESERVE if the app did not claim
+    /// them.
+    fn command(&self, command_num: usize, data: usize, data2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SuccessWithValue {
+                value: self.channel_count(),
+            },
+
+            1 => self.claim(data, appid),
+
+            2 => self.release(data, appid),
+
+            3 => {
+                if !self.claimed_by(data, appid) {
+                    return ReturnCode::ERESERVE;
+                }
+                self.pulse_duty_cycle(data2)
+                    .map_or(ReturnCode::EINVAL, |duty_cycle| {
+                        self.pwm.set_duty_cycle(data, duty_cycle)
+                    })
+            }
+
+            4 => {
+                if !self.claimed_by(data, appid) {
+                    return ReturnCode::ERESERVE;
+                }
+                self.scaled_duty_cycle(data2)
+                    .map_or(ReturnCode::EINVAL, |duty_cycle| {
+                        self.pwm.set_duty_cycle(data, duty_cycle)
+                    })
+            }
+
+            5 => {
+                if !self.claimed_by(data, appid) || !self.claimed_by(data + 1, appid) {
+                    return ReturnCode::ERESERVE;
+                }
+                let duty_cycles = [
+                    self.scaled_duty_cycle(data2 & 0xFFFF),
+                    self.scaled_duty_cycle(data2 >> 16),
+                ];
+                self.set_duty_cycles(data, &duty_cycles)
+            }
+
+            6 => {
+                if data % 2 == 0 {
+                    return ReturnCode::EINVAL;
+                }
+                if !self.claimed_by(data, appid) || !self.claimed_by(data - 1, appid) {
+                    return ReturnCode::ERESERVE;
+                }
+                self.pwm.set_complementary(data, data2 != 0)
+            }
+
+            7 => ReturnCode::SuccessWithValue {
+                value: self.actual_frequency_hz.get(),
+            },
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
@@ -0,0 +1,221 @@
+//! SHA-256 and HMAC-SHA256 digests in software.
+//!
+//! This implements `hil::digest::Digest` with the software algorithms of
+//! the `crypto` module, for chips without a hash accelerator, such as the
+//! SAM4L and the nRF51 and nRF52. Data is hashed as soon as it is added, and
+//! the client is called back from a deferred call, so hashing a large
+//! message in one part delays other work of the kernel.
+//!
+//! It computes SHA-256 digests until `set_mode_hmacsha256` selects
+//! HMAC-SHA256 with a key, which is then used for every message until the
+//! mode is changed again.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let sha256 = static_init!(
+//!     capsules::sha256::Sha256Software<'static>,
+//!     capsules::sha256::Sha256Software::new()
+//! );
+//! sha256.initialize();
+//! hil::digest::Digest::set_client(sha256, client);
+//! ```
+
+use core::cell::Cell;
+use crypto::hmac_sha256::HmacSha256;
+use crypto::sha256::{self, Sha256};
+use kernel::common::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::common::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::digest;
+use kernel::ReturnCode;
+
+/// The state of a message, in either mode.
+#[derive(Clone)]
+enum Hasher {
+    Sha256(Sha256),
+    HmacSha256(HmacSha256),
+}
+
+impl Hasher {
+    fn update(&mut self, data: &[u8]) {
+        match *self {
+            Hasher::Sha256(ref mut sha) => sha.update(data),
+            Hasher::HmacSha256(ref mut hmac) => hmac.update(data),
+        }
+    }
+
+    fn finish(self, digest: &mut [u8]) {
+        match self {
+            Hasher::Sha256(sha) => sha.finish(digest),
+            Hasher::HmacSha256(hmac) => hmac.finish(digest),
+        }
+    }
+}
+
+/// The operation to complete in the deferred call.
+#[derive(Clone, Copy, PartialEq)]
+enum Pending {
+    Idle,
+    AddData,
+    Run,
+    Verify { equal: bool },
+}
+
+pub struct Sha256Software<'a> {
+    client: OptionalCell<&'a digest::Client>,
+    /// The state of an empty message in the current mode
+    initial: MapCell<Hasher>,
+    /// The state of the message added so far
+    hasher: MapCell<Hasher>,
+    /// The buffer of the operation to complete
+    buffer: TakeCell<'static, [u8]>,
+    pending: Cell<Pending>,
+    deferred_call: DeferredCall,
+}
+
+impl Sha256Software<'a> {
+    pub fn new() -> Sha256Software<'a> {
+        Sha256Software {
+            client: OptionalCell::empty(),
+            initial: MapCell::new(Hasher::Sha256(Sha256::new())),
+            hasher: MapCell::new(Hasher::Sha256(Sha256::new())),
+            buffer: TakeCell::empty(),
+            pending: Cell::new(Pending::Idle),
+            deferred_call: DeferredCall::new(),
+        }
+    }
+
+    fn set_mode(&self, initial: Hasher) -> ReturnCode {
+        if self.pending.get() != Pending::Idle {
+            return ReturnCode::EBUSY;
+        }
+        self.hasher.replace(initial.clone());
+        self.initial.replace(initial);
+        ReturnCode::SUCCESS
+    }
+
+    /// Finish the message into `digest`, and start a new one.
+    fn finish(&self, digest: &mut [u8]) {
+        let hasher = self.initial.map(|initial| initial.clone());
+        hasher.map(|hasher| {
+            self.hasher
+                .replace(hasher)
+                .map(|message| message.finish(digest));
+        });
+    }
+
+    fn complete_later(&self, pending: Pending, buffer: &'static mut [u8]) {
+        self.buffer.replace(buffer);
+        self.pending.set(pending);
+        self.deferred_call.set();
+    }
+}
+
+impl Sha256Software<'static> {
+    /// Registers the deferred call that completes operations. The digest
+    /// can be used once this returns SUCCESS.
+    pub fn initialize(&'static self) -> ReturnCode {
+        self.deferred_call.register(self)
+    }
+}
+
+impl digest::Digest for Sha256Software<'a> {
+    fn set_client(&self, client: &'static digest::Client) {
+        self.client.set(client);
+    }
+
+    fn digest_len(&self) -> usize {
+        sha256::DIGEST_LEN
+    }
+
+    fn add_data(
+        &self,
+        data: &'static mut [u8],
+        length: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.pending.get() != Pending::Idle {
+            return (ReturnCode::EBUSY, Some(data));
+        }
+        if length > data.len() {
+            return (ReturnCode::EINVAL, Some(data));
+        }
+        self.hasher.map(|hasher| hasher.update(&data[..length]));
+        self.complete_later(Pending::AddData, data);
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn run(&self, digest: &'static mut [u8]) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.pending.get() != Pending::Idle {
+            return (ReturnCode::EBUSY, Some(digest));
+        }
+        if digest.len() < sha256::DIGEST_LEN {
+            return (ReturnCode::ESIZE, Some(digest));
+        }
+        self.finish(digest);
+        self.complete_later(Pending::Run, digest);
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn verify(&self, compare: &'static mut [u8]) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.pending.get() != Pending::Idle {
+            return (ReturnCode::EBUSY, Some(compare));
+        }
+        if compare.len() < sha256::DIGEST_LEN {
+            return (ReturnCode::ESIZE, Some(compare));
+        }
+        let mut digest = [0; sha256::DIGEST_LEN];
+        self.finish(&mut digest);
+        // Compare every byte, so that the time taken does not tell how much
+        // of a guessed code is right.
+        let difference = digest
+            .iter()
+            .zip(compare.iter())
+            .fold(0, |difference, (a, b)| difference | (a ^ b));
+        self.complete_later(
+            Pending::Verify {
+                equal: difference == 0,
+            },
+            compare,
+        );
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn clear_data(&self) {
+        let hasher = self.initial.map(|initial| initial.clone());
+        hasher.map(|hasher| self.hasher.replace(hasher));
+    }
+}
+
+impl digest::Sha256 for Sha256Software<'a> {
+    fn set_mode_sha256(&self) -> ReturnCode {
+        self.set_mode(Hasher::Sha256(Sha256::new()))
+    }
+}
+
+impl digest::HmacSha256 for Sha256Software<'a> {
+    /// Keys of any length are supported.
+    fn set_mode_hmacsha256(&self, key: &[u8]) -> ReturnCode {
+        self.set_mode(Hasher::HmacSha256(HmacSha256::new(key)))
+    }
+}
+
+impl DeferredCallClient for Sha256Software<'a> {
+    fn handle_deferred_call(&self) {
+        let pending = self.pending.get();
+        self.pending.set(Pending::Idle);
+        self.buffer.take().map(|buffer| {
+            self.client.map(move |client| match pending {
+                Pending::Idle => {}
+                Pending::AddData => client.add_data_done(ReturnCode::SUCCESS, buffer),
+                Pending::Run => client.hash_done(ReturnCode::SUCCESS, buffer),
+                Pending::Verify { equal: true } => {
+                    client.verification_done(ReturnCode::SUCCESS, buffer)
+                }
+                Pending::Verify { equal: false } => {
+                    client.verification_done(ReturnCode::FAIL, buffer)
+                }
+            });
+        });
+    }
+}
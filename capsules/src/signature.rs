@@ -0,0 +1,144 @@
+//! Signature verification in software.
+//!
+//! This implements `hil::public_key_crypto::signature::SignatureVerify`
+//! with the software algorithms of the `crypto` module, for chips without a
+//! public key accelerator. It checks ECDSA P-256 signatures until
+//! `set_algorithm` selects Ed25519. It does not implement `SignatureSign`,
+//! as the software algorithms do not run in constant time and would leak a
+//! private key.
+//!
+//! A signature is checked in a deferred call, so `verify` returns at once,
+//! but checking takes millions of cycles on a Cortex-M4, during which the
+//! kernel does nothing else. `verify_signature` checks a signature
+//! synchronously, for users that cannot wait for a callback, such as the
+//! app verifier.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let ecdsa = static_init!(
+//!     capsules::signature::SoftwareSignatureVerify<'static>,
+//!     capsules::signature::SoftwareSignatureVerify::new()
+//! );
+//! ecdsa.initialize();
+//! hil::public_key_crypto::signature::SignatureVerify::set_client(ecdsa, client);
+//! ```
+
+use core::cell::Cell;
+use crypto::{ed25519, p256};
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::public_key_crypto::signature::{
+    SignatureAlgorithm, SignatureVerify, SignatureVerifyClient,
+};
+use kernel::ReturnCode;
+
+/// Return whether `signature` is a valid `algorithm` signature by `key` of
+/// the message with digest `hash`, or of the message `hash` for Ed25519.
+pub fn verify_signature(
+    algorithm: SignatureAlgorithm,
+    key: &[u8],
+    hash: &[u8],
+    signature: &[u8],
+) -> bool {
+    match algorithm {
+        SignatureAlgorithm::Ed25519 => ed25519::verify(key, hash, signature),
+        SignatureAlgorithm::EcdsaNistP256 => p256::verify(key, hash, signature),
+    }
+}
+
+/// Whether the key, digest and signature have the lengths `algorithm` uses.
+fn lengths_valid(algorithm: SignatureAlgorithm, key: &[u8], hash: &[u8], signature: &[u8]) -> bool {
+    match algorithm {
+        SignatureAlgorithm::Ed25519 => {
+            key.len() == ed25519::PUBLIC_KEY_LEN && signature.len() == ed25519::SIGNATURE_LEN
+        }
+        SignatureAlgorithm::EcdsaNistP256 => {
+            key.len() == p256::PUBLIC_KEY_LEN
+                && hash.len() == p256::DIGEST_LEN
+                && signature.len() == p256::SIGNATURE_LEN
+        }
+    }
+}
+
+pub struct SoftwareSignatureVerify<'a> {
+    client: OptionalCell<&'a SignatureVerifyClient>,
+    algorithm: Cell<SignatureAlgorithm>,
+    /// The key and buffers of the verification in progress
+    key: OptionalCell<&'static [u8]>,
+    hash: TakeCell<'static, [u8]>,
+    signature: TakeCell<'static, [u8]>,
+    deferred_call: DeferredCall,
+}
+
+impl SoftwareSignatureVerify<'a> {
+    pub fn new() -> SoftwareSignatureVerify<'a> {
+        SoftwareSignatureVerify {
+            client: OptionalCell::empty(),
+            algorithm: Cell::new(SignatureAlgorithm::EcdsaNistP256),
+            key: OptionalCell::empty(),
+            hash: TakeCell::empty(),
+            signature: TakeCell::empty(),
+            deferred_call: DeferredCall::new(),
+        }
+    }
+}
+
+impl SoftwareSignatureVerify<'static> {
+    /// Registers the deferred call that checks signatures. Signatures can
+    /// be checked once this returns SUCCESS.
+    pub fn initialize(&'static self) -> ReturnCode {
+        self.deferred_call.register(self)
+    }
+}
+
+impl SignatureVerify for SoftwareSignatureVerify<'a> {
+    fn set_client(&self, client: &'static SignatureVerifyClient) {
+        self.client.set(client);
+    }
+
+    fn set_algorithm(&self, algorithm: SignatureAlgorithm) -> ReturnCode {
+        if self.hash.is_some() {
+            return ReturnCode::EBUSY;
+        }
+        self.algorithm.set(algorithm);
+        ReturnCode::SUCCESS
+    }
+
+    fn verify(
+        &self,
+        key: &'static [u8],
+        hash: &'static mut [u8],
+        signature: &'static mut [u8],
+    ) -> (ReturnCode, Option<(&'static mut [u8], &'static mut [u8])>) {
+        if self.hash.is_some() {
+            return (ReturnCode::EBUSY, Some((hash, signature)));
+        }
+        if !lengths_valid(self.algorithm.get(), key, hash, signature) {
+            return (ReturnCode::EINVAL, Some((hash, signature)));
+        }
+        self.key.set(key);
+        self.hash.replace(hash);
+        self.signature.replace(signature);
+        self.deferred_call.set();
+        (ReturnCode::SUCCESS, None)
+    }
+}
+
+impl DeferredCallClient for SoftwareSignatureVerify<'a> {
+    fn handle_deferred_call(&self) {
+        let key = self.key.take();
+        let hash = self.hash.take();
+        let signature = self.signature.take();
+        if let (Some(key), Some(hash), Some(signature)) = (key, hash, signature) {
+            let result = if verify_signature(self.algorithm.get(), key, hash, signature) {
+                ReturnCode::SUCCESS
+            } else {
+                ReturnCode::FAIL
+            };
+            self.client
+                .map(move |client| client.verification_done(result, hash, signature));
+        }
+    }
+}
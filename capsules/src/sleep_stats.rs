@@ -0,0 +1,96 @@
+//! Reports how the chip sleeps to userspace.
+//!
+//! Apps read how long the chip spent in each sleep mode, how many times it
+//! slept in each, and how many times each interrupt woke it up, to find out
+//! why a board does not reach its power targets. Any app can also reset the
+//! statistics, to measure from a point on.
+//!
+//! Usage
+//! -----
+//!
+//! You need a chip that keeps `kernel::SleepStats`.
+//!
+//! ```rust
+//! let sleep_stats = static_init!(
+//!     capsules::sleep_stats::SleepStatsDriver<'static>,
+//!     capsules::sleep_stats::SleepStatsDriver::new(&sam4l::pm::SLEEP_STATS)
+//! );
+//! ```
+
+use kernel::{AppId, Driver, ReturnCode, SleepMode, SleepStatistics};
+
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = 0x10004;
+
+pub struct SleepStatsDriver<'a> {
+    stats: &'a SleepStatistics,
+}
+
+/// Returns the sleep mode numbered `mode` by the driver.
+fn sleep_mode(mode: usize) -> Option<SleepMode> {
+    match mode {
+        0 => Some(SleepMode::Sleep),
+        1 => Some(SleepMode::DeepSleep),
+        _ => None,
+    }
+}
+
+impl SleepStatsDriver<'a> {
+    pub fn new(stats: &'a SleepStatistics) -> SleepStatsDriver<'a> {
+        SleepStatsDriver { stats: stats }
+    }
+}
+
+impl Driver for SleepStatsDriver<'a> {
+    /// Read sleep statistics.
+    ///
+    /// Sleep modes are `0` for sleep, where only the CPU stops, and `1` for
+    /// deep sleep.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Get the milliseconds counted since the statistics were reset,
+    ///        asleep or awake.
+    /// - `2`: Get the milliseconds spent asleep in sleep mode `data`.
+    /// - `3`: Get the number of times the chip slept in sleep mode `data`.
+    /// - `4`: Get the number of times interrupt `data` woke the chip up.
+    /// - `5`: Reset the statistics.
+    fn command(&self, command_num: usize, data: usize, _: usize, _: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            1 => ReturnCode::SuccessWithValue {
+                value: (self.stats.elapsed_us() / 1000) as usize,
+            },
+
+            2 => sleep_mode(data).map_or(ReturnCode::EINVAL, |mode| {
+                ReturnCode::SuccessWithValue {
+                    value: (self.stats.sleep_time_us(mode) / 1000) as usize,
+                }
+            }),
+
+            3 => sleep_mode(data).map_or(ReturnCode::EINVAL, |mode| {
+                ReturnCode::SuccessWithValue {
+                    value: self.stats.sleep_count(mode) as usize,
+                }
+            }),
+
+            4 => {
+                if data >= self.stats.number_interrupts() {
+                    return ReturnCode::EINVAL;
+                }
+                ReturnCode::SuccessWithValue {
+                    value: self.stats.wakeups(data) as usize,
+                }
+            }
+
+            5 => {
+                self.stats.reset();
+                ReturnCode::SUCCESS
+            }
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
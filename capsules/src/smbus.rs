@@ -0,0 +1,382 @@
+//! SMBus and PMBus transactions on top of an I2C device.
+//!
+//! Many power management ICs and battery gauges speak SMBus rather than
+//! plain I2C: registers are addressed by a command code, block transfers
+//! carry their length in the first byte, and a Packet Error Code (PEC), a
+//! CRC-8 over the whole transaction, may follow the data. PMBus devices use
+//! the same transactions. `SMBusDevice` implements these for a single
+//! device, so its driver only deals with command codes and data:
+//!
+//! - `write` sends a command code followed by `len` bytes, which is Send
+//!   Byte, Write Byte or Write Word for a `len` of 0, 1 or 2.
+//! - `read` sends a command code and reads `len` bytes, which is Read Byte
+//!   or Read Word for a `len` of 1 or 2.
+//! - `block_write` and `block_read` transfer up to `BLOCK_MAX` bytes with
+//!   their count.
+//!
+//! Words are little endian on SMBus. With PEC enabled, it is appended to
+//! writes and checked on reads; a read with a wrong PEC fails with `FAIL`.
+//! Data is passed in and returned at the start of the buffer, which must
+//! have room for the command code, count and PEC around it, so buffers of
+//! `BUFFER_LEN` bytes work for any transaction.
+//!
+//! Devices signal that they need attention by pulling the shared SMBALERT#
+//! line low. `SMBusAlert` watches that line and asks the Alert Response
+//! Address which device pulled it, until the line is released, and passes
+//! each address to its client.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let gauge_i2c = static_init!(
+//!     capsules::virtual_i2c::I2CDevice,
+//!     capsules::virtual_i2c::I2CDevice::new(i2c_mux, 0x0B));
+//! let gauge_smbus = static_init!(
+//!     capsules::smbus::SMBusDevice<'static>,
+//!     capsules::smbus::SMBusDevice::new(gauge_i2c, 0x0B));
+//! gauge_i2c.set_client(gauge_smbus);
+//! gauge_smbus.set_pec(true);
+//! gauge_smbus.set_client(gauge);
+//!
+//! let ara_i2c = static_init!(
+//!     capsules::virtual_i2c::I2CDevice,
+//!     capsules::virtual_i2c::I2CDevice::new(i2c_mux,
+//!         capsules::smbus::ALERT_RESPONSE_ADDRESS));
+//! let alert = static_init!(
+//!     capsules::smbus::SMBusAlert<'static>,
+//!     capsules::smbus::SMBusAlert::new(ara_i2c, &sam4l::gpio::PA[16],
+//!                                      &mut capsules::smbus::ALERT_BUFFER));
+//! ara_i2c.set_client(alert);
+//! sam4l::gpio::PA[16].set_client(alert);
+//! alert.set_client(gauge);
+//! alert.start();
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::gpio;
+use kernel::hil::i2c;
+use kernel::ReturnCode;
+
+/// The most data bytes in a block transfer.
+pub const BLOCK_MAX: usize = 32;
+
+/// Command code, count, data and PEC of the largest transaction.
+pub const BUFFER_LEN: usize = BLOCK_MAX + 3;
+
+/// The address alerting devices answer a read from with their own.
+pub const ALERT_RESPONSE_ADDRESS: u8 = 0x0C;
+
+pub static mut ALERT_BUFFER: [u8; 2] = [0; 2];
+
+/// Adds `byte` to the CRC-8 (polynomial x^8 + x^2 + x + 1) used as PEC.
+fn crc8(mut crc: u8, byte: u8) -> u8 {
+    crc ^= byte;
+    for _ in 0..8 {
+        crc = if crc & 0x80 != 0 {
+            (crc << 1) ^ 0x07
+        } else {
+            crc << 1
+        };
+    }
+    crc
+}
+
+/// The PEC of `bytes`, which include the address bytes of the transaction
+/// as they appear on the bus.
+pub fn pec(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |crc, byte| crc8(crc, *byte))
+}
+
+pub trait SMBusClient {
+    /// A transaction finished. For reads, the `len` bytes read are at the
+    /// start of `buffer`; for writes, `len` is the number of bytes written.
+    fn command_complete(&self, buffer: &'static mut [u8], len: usize, error: ReturnCode);
+}
+
+pub trait SMBusAlertClient {
+    /// The device at the 7-bit `address` pulled SMBALERT#.
+    fn alert(&self, address: u8);
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Op {
+    Idle,
+    Write(usize),
+    Read(u8, usize),
+    BlockRead(u8),
+}
+
+pub struct SMBusDevice<'a> {
+    i2c: &'a i2c::I2CDevice,
+    /// The 7-bit address of the device, which is part of the PEC
+    address: u8,
+    pec: Cell<bool>,
+    op: Cell<Op>,
+    client: OptionalCell<&'a SMBusClient>,
+}
+
+impl SMBusDevice<'a> {
+    pub fn new(i2c: &'a i2c::I2CDevice, address: u8) -> SMBusDevice<'a> {
+        SMBusDevice {
+            i2c: i2c,
+            address: address,
+            pec: Cell::new(false),
+            op: Cell::new(Op::Idle),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a SMBusClient) {
+        self.client.set(client);
+    }
+
+    /// Appends a PEC to writes and expects one after reads.
+    pub fn set_pec(&self, enable: bool) {
+        self.pec.set(enable);
+    }
+
+    fn pec_len(&self) -> usize {
+        if self.pec.get() {
+            1
+        } else {
+            0
+        }
+    }
+
+    /// Sends `command` followed by `buffer[..len]`.
+    pub fn write(
+        &self,
+        command: u8,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        self.start_write(command, None, buffer, len)
+    }
+
+    /// Sends `command` followed by the count and `buffer[..len]`.
+    pub fn block_write(
+        &self,
+        command: u8,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if len > BLOCK_MAX {
+            return (ReturnCode::ESIZE, Some(buffer));
+        }
+        self.start_write(command, Some(len as u8), buffer, len)
+    }
+
+    fn start_write(
+        &self,
+        command: u8,
+        count: Option<u8>,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.op.get() != Op::Idle {
+            return (ReturnCode::EBUSY, Some(buffer));
+        }
+        let header = if count.is_some() { 2 } else { 1 };
+        let total = header + len + self.pec_len();
+        if total > buffer.len() || total > 255 {
+            return (ReturnCode::ESIZE, Some(buffer));
+        }
+
+        for i in (0..len).rev() {
+            buffer[header + i] = buffer[i];
+        }
+        buffer[0] = command;
+        count.map(|count| buffer[1] = count);
+        if self.pec.get() {
+            let crc = buffer[..header + len]
+                .iter()
+                .fold(crc8(0, self.address << 1), |crc, byte| crc8(crc, *byte));
+            buffer[header + len] = crc;
+        }
+
+        self.op.set(Op::Write(len));
+        self.i2c.enable();
+        self.i2c.write(buffer, total as u8);
+        (ReturnCode::SUCCESS, None)
+    }
+
+    /// Sends `command` and reads `len` bytes into the start of `buffer`.
+    pub fn read(
+        &self,
+        command: u8,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.op.get() != Op::Idle {
+            return (ReturnCode::EBUSY, Some(buffer));
+        }
+        let total = len + self.pec_len();
+        if len == 0 || total > buffer.len() || total > 255 {
+            return (ReturnCode::ESIZE, Some(buffer));
+        }
+
+        buffer[0] = command;
+        self.op.set(Op::Read(command, len));
+        self.i2c.enable();
+        self.i2c.write_read(buffer, 1, total as u8);
+        (ReturnCode::SUCCESS, None)
+    }
+
+    /// Sends `command` and reads a block into the start of `buffer`.
+    ///
+    /// The I2C HIL reads a fixed number of bytes, so this reads as many as
+    /// the largest block, and the device sends 0xFF after the end of a
+    /// shorter one. The buffer must be `BUFFER_LEN` bytes long.
+    pub fn block_read(
+        &self,
+        command: u8,
+        buffer: &'static mut [u8],
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.op.get() != Op::Idle {
+            return (ReturnCode::EBUSY, Some(buffer));
+        }
+        let total = 1 + BLOCK_MAX + self.pec_len();
+        if total > buffer.len() {
+            return (ReturnCode::ESIZE, Some(buffer));
+        }
+
+        buffer[0] = command;
+        self.op.set(Op::BlockRead(command));
+        self.i2c.enable();
+        self.i2c.write_read(buffer, 1, total as u8);
+        (ReturnCode::SUCCESS, None)
+    }
+
+    /// Whether `received`, which was read after sending `command`, ends with
+    /// its PEC.
+    fn check_pec(&self, command: u8, received: &[u8]) -> bool {
+        let (data, pec) = received.split_at(received.len() - 1);
+        let crc = [self.address << 1, command, self.address << 1 | 1]
+            .iter()
+            .chain(data.iter())
+            .fold(0, |crc, byte| crc8(crc, *byte));
+        crc == pec[0]
+    }
+
+    /// Checks what was read and moves the data to the start of the buffer.
+    fn finish_read(&self, buffer: &mut [u8], op: Op) -> (usize, ReturnCode) {
+        match op {
+            Op::Read(command, len) => {
+                if self.pec.get() && !self.check_pec(command, &buffer[..len + 1]) {
+                    return (0, ReturnCode::FAIL);
+                }
+                (len, ReturnCode::SUCCESS)
+            }
+            Op::BlockRead(command) => {
+                let len = buffer[0] as usize;
+                if len > BLOCK_MAX {
+                    return (0, ReturnCode::ESIZE);
+                }
+                if self.pec.get() && !self.check_pec(command, &buffer[..len + 2]) {
+                    return (0, ReturnCode::FAIL);
+                }
+                for i in 0..len {
+                    buffer[i] = buffer[i + 1];
+                }
+                (len, ReturnCode::SUCCESS)
+            }
+            _ => (0, ReturnCode::FAIL),
+        }
+    }
+}
+
+impl i2c::I2CClient for SMBusDevice<'a> {
+    fn command_complete(&self, buffer: &'static mut [u8], error: i2c::Error) {
+        let op = self.op.get();
+        self.op.set(Op::Idle);
+        self.i2c.disable();
+
+        let (len, rcode) = match error {
+            i2c::Error::CommandComplete => match op {
+                Op::Write(len) => (len, ReturnCode::SUCCESS),
+                _ => self.finish_read(buffer, op),
+            },
+            i2c::Error::AddressNak | i2c::Error::DataNak => (0, ReturnCode::ENOACK),
+            _ => (0, ReturnCode::FAIL),
+        };
+        self.client.map(move |client| {
+            client.command_complete(buffer, len, rcode);
+        });
+    }
+}
+
+/// Finds the devices that pull SMBALERT#.
+pub struct SMBusAlert<'a> {
+    /// The device at `ALERT_RESPONSE_ADDRESS`
+    i2c: &'a i2c::I2CDevice,
+    /// SMBALERT#, which is active low
+    pin: &'a gpio::Pin,
+    buffer: TakeCell<'static, [u8]>,
+    client: OptionalCell<&'a SMBusAlertClient>,
+}
+
+impl SMBusAlert<'a> {
+    pub fn new(
+        i2c: &'a i2c::I2CDevice,
+        pin: &'a gpio::Pin,
+        buffer: &'static mut [u8],
+    ) -> SMBusAlert<'a> {
+        SMBusAlert {
+            i2c: i2c,
+            pin: pin,
+            buffer: TakeCell::new(buffer),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a SMBusAlertClient) {
+        self.client.set(client);
+    }
+
+    /// Starts watching SMBALERT#, and handles an alert that is already
+    /// pending.
+    pub fn start(&self) {
+        self.pin.make_input();
+        self.pin.enable_interrupt(0, gpio::InterruptMode::FallingEdge);
+        if !self.pin.read() {
+            self.respond();
+        }
+    }
+
+    pub fn stop(&self) {
+        self.pin.disable_interrupt();
+    }
+
+    /// Asks which device is alerting, unless the question is in flight.
+    fn respond(&self) {
+        self.buffer.take().map(|buffer| {
+            self.i2c.enable();
+            self.i2c.read(buffer, 1);
+        });
+    }
+}
+
+impl gpio::Client for SMBusAlert<'a> {
+    fn fired(&self, _: usize) {
+        self.respond();
+    }
+}
+
+impl i2c::I2CClient for SMBusAlert<'a> {
+    fn command_complete(&self, buffer: &'static mut [u8], error: i2c::Error) {
+        self.i2c.disable();
+        let address = buffer[0] >> 1;
+        self.buffer.replace(buffer);
+
+        if error == i2c::Error::CommandComplete {
+            self.client.map(|client| client.alert(address));
+            // Devices with the lowest address win the arbitration for the
+            // response, and others keep the line low until they are asked
+            if !self.pin.read() {
+                self.respond();
+            }
+        }
+    }
+}
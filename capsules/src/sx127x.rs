@@ -0,0 +1,529 @@
+//! Driver for the SX1276/77/78/79 LoRa transceivers.
+//!
+//! <https://www.semtech.com/products/wireless-rf/lora-transceivers/SX1276>
+//!
+//! The SX127x radios have an SPI interface, a 256-byte FIFO that holds the
+//! packet being sent or received, and DIO pins that signal the end of an
+//! operation. This driver implements `hil::lora::Lora` in the LoRa mode of
+//! the radio, transmitting through the PA_BOOST pin, with DIO0 signalling
+//! that a packet was sent or received and DIO1 that a reception timed out.
+//!
+//! The whole configuration is written before each transmission or reception,
+//! and the radio sleeps in between.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let sx127x_spi = static_init!(
+//!     capsules::virtual_spi::VirtualSpiMasterDevice<'static, sam4l::spi::SpiHw>,
+//!     capsules::virtual_spi::VirtualSpiMasterDevice::new(mux_spi, 2)
+//! );
+//! let sx127x = static_init!(
+//!     capsules::sx127x::SX127x<
+//!         'static,
+//!         capsules::virtual_spi::VirtualSpiMasterDevice<'static, sam4l::spi::SpiHw>,
+//!     >,
+//!     capsules::sx127x::SX127x::new(
+//!         sx127x_spi,
+//!         &sam4l::gpio::PA[20],
+//!         &sam4l::gpio::PA[21],
+//!         &mut capsules::sx127x::TX_BUF,
+//!         &mut capsules::sx127x::RX_BUF
+//!     )
+//! );
+//! sx127x_spi.set_client(sx127x);
+//! sam4l::gpio::PA[20].set_client(sx127x);
+//! sam4l::gpio::PA[21].set_client(sx127x);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::gpio;
+use kernel::hil::lora::{self, Bandwidth, CodingRate, Config, MAX_PAYLOAD_LEN};
+use kernel::hil::spi;
+use kernel::ReturnCode;
+
+/// The longest SPI transaction: an address and the whole FIFO
+const BUF_LEN: usize = MAX_PAYLOAD_LEN + 1;
+
+pub static mut TX_BUF: [u8; BUF_LEN] = [0; BUF_LEN];
+pub static mut RX_BUF: [u8; BUF_LEN] = [0; BUF_LEN];
+
+const SPI_SPEED: u32 = 4000000;
+
+/// The address byte of a write has this bit set
+const WRITE: u8 = 0x80;
+
+/// Registers in LoRa mode
+#[allow(dead_code)]
+enum Register {
+    Fifo = 0x00,
+    OpMode = 0x01,
+    FrfMsb = 0x06,
+    FrfMid = 0x07,
+    FrfLsb = 0x08,
+    PaConfig = 0x09,
+    FifoAddrPtr = 0x0d,
+    FifoTxBaseAddr = 0x0e,
+    FifoRxBaseAddr = 0x0f,
+    IrqFlags = 0x12,
+    RxNbBytes = 0x13,
+    PktSnrValue = 0x19,
+    PktRssiValue = 0x1a,
+    ModemConfig1 = 0x1d,
+    ModemConfig2 = 0x1e,
+    SymbTimeoutLsb = 0x1f,
+    PreambleMsb = 0x20,
+    PreambleLsb = 0x21,
+    PayloadLength = 0x22,
+    ModemConfig3 = 0x26,
+    InvertIQ = 0x33,
+    SyncWord = 0x39,
+    InvertIQ2 = 0x3b,
+    DioMapping1 = 0x40,
+    PaDac = 0x4d,
+}
+
+/// RegOpMode bits
+const LONG_RANGE_MODE: u8 = 0x80;
+const LOW_FREQUENCY_MODE: u8 = 0x08;
+const MODE_SLEEP: u8 = 0x00;
+const MODE_STANDBY: u8 = 0x01;
+const MODE_TX: u8 = 0x03;
+const MODE_RX_SINGLE: u8 = 0x06;
+
+/// Frequencies below this use the low frequency port of the radio
+const LOW_FREQUENCY_LIMIT: u32 = 525_000_000;
+
+/// RegIrqFlags bits
+const RX_DONE: u8 = 0x40;
+const PAYLOAD_CRC_ERROR: u8 = 0x20;
+
+/// RegPaConfig and RegPaDac values
+const PA_BOOST: u8 = 0x80;
+const PA_DAC_DEFAULT: u8 = 0x84;
+const PA_DAC_20DBM: u8 = 0x87;
+const MIN_TX_POWER: i8 = 2;
+const MAX_TX_POWER: i8 = 20;
+
+/// RegModemConfig2 bits
+const RX_PAYLOAD_CRC_ON: u8 = 0x04;
+
+/// RegModemConfig3 bits
+const LOW_DATA_RATE_OPTIMIZE: u8 = 0x08;
+const AGC_AUTO_ON: u8 = 0x04;
+
+/// Symbols longer than this need the low data rate optimization
+const LOW_DATA_RATE_SYMBOL_US: u32 = 16_000;
+
+/// RegInvertIQ and RegInvertIQ2 values
+const INVERT_IQ_OFF: (u8, u8) = (0x27, 0x1d);
+const INVERT_IQ_ON: (u8, u8) = (0x66, 0x19);
+
+/// RegDioMapping1 values: DIO0 signals TxDone or RxDone, and DIO1 RxTimeout
+const DIO_MAPPING_TX: u8 = 0x40;
+const DIO_MAPPING_RX: u8 = 0x00;
+
+/// The offset of RSSI values in the high and low frequency bands
+const RSSI_OFFSET_HF: i16 = -157;
+const RSSI_OFFSET_LF: i16 = -164;
+
+/// The longest symbol timeout the radio supports
+const MAX_SYMBOL_TIMEOUT: u16 = 0x3ff;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    Transmit,
+    Receive,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+
+    /// Writing the register at the given index of the setup of an operation
+    Setup(Operation, usize),
+    WriteFifo,
+    StartTransmit,
+    StartReceive,
+
+    /// Waiting for a DIO interrupt
+    Transmitting,
+    Receiving,
+
+    ReadIrqFlags,
+    ReadPacketStatus,
+    SetFifoPointer,
+    ReadFifo,
+    ClearIrqFlags,
+    Sleep,
+}
+
+pub struct SX127x<'a, S: spi::SpiMasterDevice + 'a> {
+    spi: &'a S,
+    dio0: &'a gpio::Pin,
+    dio1: &'a gpio::Pin,
+    state: Cell<State>,
+    client: OptionalCell<&'static lora::Client>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    rx_buffer: TakeCell<'static, [u8]>,
+
+    config: Cell<Config>,
+    operation: Cell<Operation>,
+    // The length of the packet to send, which is in the transmit buffer
+    tx_len: Cell<usize>,
+    symbol_timeout: Cell<u16>,
+
+    // The reception that ended
+    irq_flags: Cell<u8>,
+    rx_len: Cell<usize>,
+    rssi: Cell<i16>,
+    snr: Cell<i8>,
+}
+
+impl<S: spi::SpiMasterDevice> SX127x<'a, S> {
+    pub fn new(
+        spi: &'a S,
+        dio0: &'a gpio::Pin,
+        dio1: &'a gpio::Pin,
+        tx_buffer: &'static mut [u8],
+        rx_buffer: &'static mut [u8],
+    ) -> SX127x<'a, S> {
+        SX127x {
+            spi: spi,
+            dio0: dio0,
+            dio1: dio1,
+            state: Cell::new(State::Idle),
+            client: OptionalCell::empty(),
+            tx_buffer: TakeCell::new(tx_buffer),
+            rx_buffer: TakeCell::new(rx_buffer),
+            config: Cell::new(Config {
+                frequency: 868_100_000,
+                spreading_factor: 7,
+                bandwidth: Bandwidth::Khz125,
+                coding_rate: CodingRate::Cr4_5,
+                tx_power: 14,
+                preamble_len: 8,
+                sync_word: 0x12,
+                invert_iq: false,
+            }),
+            operation: Cell::new(Operation::Transmit),
+            tx_len: Cell::new(0),
+            symbol_timeout: Cell::new(0),
+            irq_flags: Cell::new(0),
+            rx_len: Cell::new(0),
+            rssi: Cell::new(0),
+            snr: Cell::new(0),
+        }
+    }
+
+    /// Start an SPI transaction of the `len` bytes that `fill` writes to the
+    /// transmit buffer, and move to `state`
+    fn transfer<F>(&self, state: State, fill: F)
+    where
+        F: FnOnce(&mut [u8]) -> usize,
+    {
+        self.tx_buffer.take().map(|tx_buffer| {
+            self.rx_buffer.take().map(move |rx_buffer| {
+                let len = fill(tx_buffer);
+                self.state.set(state);
+                self.spi.read_write_bytes(tx_buffer, Some(rx_buffer), len);
+            });
+        });
+    }
+
+    fn write_register(&self, state: State, register: Register, value: u8) {
+        self.transfer(state, |buf| {
+            buf[0] = register as u8 | WRITE;
+            buf[1] = value;
+            2
+        });
+    }
+
+    /// Read `len` consecutive registers, starting at `register`
+    fn read_registers(&self, state: State, register: Register, len: usize) {
+        self.transfer(state, |buf| {
+            buf[0] = register as u8;
+            1 + len
+        });
+    }
+
+    fn op_mode(&self, mode: u8) -> u8 {
+        if self.config.get().frequency < LOW_FREQUENCY_LIMIT {
+            LONG_RANGE_MODE | LOW_FREQUENCY_MODE | mode
+        } else {
+            LONG_RANGE_MODE | mode
+        }
+    }
+
+    /// The register write at `index` of the setup of `operation`, or `None`
+    /// if the setup is complete
+    fn setup_write(&self, operation: Operation, index: usize) -> Option<(Register, u8)> {
+        let config = self.config.get();
+        let frf = ((config.frequency as u64) << 19) / 32_000_000;
+        let (pa_config, pa_dac) = if config.tx_power > 17 {
+            (PA_BOOST | 0x70 | (config.tx_power - 5) as u8, PA_DAC_20DBM)
+        } else {
+            (PA_BOOST | 0x70 | (config.tx_power - 2) as u8, PA_DAC_DEFAULT)
+        };
+        let bandwidth = match config.bandwidth {
+            Bandwidth::Khz125 => 0x70,
+            Bandwidth::Khz250 => 0x80,
+            Bandwidth::Khz500 => 0x90,
+        };
+        let coding_rate = match config.coding_rate {
+            CodingRate::Cr4_5 => 0x02,
+            CodingRate::Cr4_6 => 0x04,
+            CodingRate::Cr4_7 => 0x06,
+            CodingRate::Cr4_8 => 0x08,
+        };
+        let symbol_timeout = self.symbol_timeout.get();
+        let low_data_rate = if config.symbol_us() > LOW_DATA_RATE_SYMBOL_US {
+            LOW_DATA_RATE_OPTIMIZE
+        } else {
+            0
+        };
+        let (invert_iq, invert_iq2) = if config.invert_iq {
+            INVERT_IQ_ON
+        } else {
+            INVERT_IQ_OFF
+        };
+        let transmit = operation == Operation::Transmit;
+
+        let write = match index {
+            // The radio only switches to LoRa mode while sleeping
+            0 => (Register::OpMode, self.op_mode(MODE_SLEEP)),
+            1 => (Register::OpMode, self.op_mode(MODE_STANDBY)),
+            2 => (Register::FrfMsb, (frf >> 16) as u8),
+            3 => (Register::FrfMid, (frf >> 8) as u8),
+            4 => (Register::FrfLsb, frf as u8),
+            5 => (Register::PaConfig, pa_config),
+            6 => (Register::PaDac, pa_dac),
+            7 => (Register::ModemConfig1, bandwidth | coding_rate),
+            8 => (
+                Register::ModemConfig2,
+                config.spreading_factor << 4 | RX_PAYLOAD_CRC_ON | (symbol_timeout >> 8) as u8,
+            ),
+            9 => (Register::SymbTimeoutLsb, symbol_timeout as u8),
+            10 => (Register::ModemConfig3, low_data_rate | AGC_AUTO_ON),
+            11 => (Register::PreambleMsb, (config.preamble_len >> 8) as u8),
+            12 => (Register::PreambleLsb, config.preamble_len as u8),
+            13 => (Register::SyncWord, config.sync_word),
+            14 => (Register::InvertIQ, invert_iq),
+            15 => (Register::InvertIQ2, invert_iq2),
+            16 => (Register::IrqFlags, 0xff),
+            17 if transmit => (Register::DioMapping1, DIO_MAPPING_TX),
+            17 => (Register::DioMapping1, DIO_MAPPING_RX),
+            18 if transmit => (Register::FifoTxBaseAddr, 0),
+            18 => (Register::FifoRxBaseAddr, 0),
+            19 => (Register::FifoAddrPtr, 0),
+            20 if transmit => (Register::PayloadLength, self.tx_len.get() as u8),
+            _ => return None,
+        };
+        Some(write)
+    }
+
+    fn setup(&self, operation: Operation, index: usize) {
+        match self.setup_write(operation, index) {
+            Some((register, value)) => {
+                self.write_register(State::Setup(operation, index), register, value)
+            }
+            None => match operation {
+                Operation::Transmit => {
+                    let len = self.tx_len.get();
+                    self.tx_buffer.map(|buf| buf[0] = Register::Fifo as u8 | WRITE);
+                    self.tx_buffer.take().map(|tx_buffer| {
+                        self.state.set(State::WriteFifo);
+                        self.spi.read_write_bytes(tx_buffer, None, 1 + len);
+                    });
+                }
+                Operation::Receive => self.write_register(
+                    State::StartReceive,
+                    Register::OpMode,
+                    self.op_mode(MODE_RX_SINGLE),
+                ),
+            },
+        }
+    }
+
+    /// Start `operation`, if the radio is idle
+    fn start(&self, operation: Operation) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        self.spi.configure(
+            spi::ClockPolarity::IdleLow,
+            spi::ClockPhase::SampleLeading,
+            SPI_SPEED,
+        );
+        for (pin, identifier) in [self.dio0, self.dio1].iter().zip(0..) {
+            pin.make_input();
+            pin.enable_interrupt(identifier, gpio::InterruptMode::RisingEdge);
+        }
+        self.operation.set(operation);
+        self.setup(operation, 0);
+        ReturnCode::SUCCESS
+    }
+
+    fn finish(&self) {
+        self.state.set(State::Idle);
+        match self.operation.get() {
+            Operation::Transmit => {
+                self.client
+                    .map(|client| client.transmit_done(ReturnCode::SUCCESS));
+            }
+            Operation::Receive => {
+                let flags = self.irq_flags.get();
+                let result = if flags & RX_DONE == 0 {
+                    ReturnCode::ECANCEL
+                } else if flags & PAYLOAD_CRC_ERROR != 0 {
+                    ReturnCode::FAIL
+                } else {
+                    ReturnCode::SUCCESS
+                };
+                let len = if result == ReturnCode::SUCCESS {
+                    self.rx_len.get()
+                } else {
+                    0
+                };
+                // The payload is after the address byte of the FIFO read
+                self.rx_buffer.map(|rx_buffer| {
+                    self.client.map(|client| {
+                        client.receive_done(
+                            &rx_buffer[1..1 + len],
+                            self.rssi.get(),
+                            self.snr.get(),
+                            result,
+                        )
+                    });
+                });
+            }
+        }
+    }
+}
+
+impl<S: spi::SpiMasterDevice> lora::Lora for SX127x<'a, S> {
+    fn set_client(&self, client: &'static lora::Client) {
+        self.client.set(client);
+    }
+
+    fn configure(&self, config: Config) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        if config.spreading_factor < 6
+            || config.spreading_factor > 12
+            || config.tx_power < MIN_TX_POWER
+            || config.tx_power > MAX_TX_POWER
+        {
+            return ReturnCode::EINVAL;
+        }
+        self.config.set(config);
+        ReturnCode::SUCCESS
+    }
+
+    fn transmit(&self, payload: &[u8]) -> ReturnCode {
+        if payload.len() > MAX_PAYLOAD_LEN {
+            return ReturnCode::ESIZE;
+        }
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        let copied = self.tx_buffer.map_or(false, |buf| {
+            buf[1..1 + payload.len()].copy_from_slice(payload);
+            true
+        });
+        if !copied {
+            return ReturnCode::EBUSY;
+        }
+        self.tx_len.set(payload.len());
+        self.start(Operation::Transmit)
+    }
+
+    fn receive(&self, symbol_timeout: u16) -> ReturnCode {
+        if symbol_timeout < 4 || symbol_timeout > MAX_SYMBOL_TIMEOUT {
+            return ReturnCode::EINVAL;
+        }
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        self.symbol_timeout.set(symbol_timeout);
+        self.start(Operation::Receive)
+    }
+}
+
+impl<S: spi::SpiMasterDevice> spi::SpiMasterClient for SX127x<'a, S> {
+    fn read_write_done(
+        &self,
+        write_buffer: &'static mut [u8],
+        read_buffer: Option<&'static mut [u8]>,
+        _len: usize,
+    ) {
+        self.tx_buffer.replace(write_buffer);
+        let mut bytes = [0; 2];
+        read_buffer.map(|read_buffer| {
+            bytes.copy_from_slice(&read_buffer[1..3]);
+            self.rx_buffer.replace(read_buffer);
+        });
+
+        match self.state.get() {
+            State::Idle | State::Transmitting | State::Receiving => {}
+
+            State::Setup(operation, index) => self.setup(operation, index + 1),
+            State::WriteFifo => self.write_register(
+                State::StartTransmit,
+                Register::OpMode,
+                self.op_mode(MODE_TX),
+            ),
+            State::StartTransmit => self.state.set(State::Transmitting),
+            State::StartReceive => self.state.set(State::Receiving),
+
+            State::ReadIrqFlags => {
+                // RegIrqFlags is followed by RegRxNbBytes
+                self.irq_flags.set(bytes[0]);
+                self.rx_len.set(bytes[1] as usize);
+                if self.operation.get() == Operation::Receive && bytes[0] & RX_DONE != 0 {
+                    self.read_registers(State::ReadPacketStatus, Register::PktSnrValue, 2);
+                } else {
+                    self.write_register(State::ClearIrqFlags, Register::IrqFlags, 0xff);
+                }
+            }
+            State::ReadPacketStatus => {
+                let snr = bytes[0] as i8 / 4;
+                let offset = if self.config.get().frequency < LOW_FREQUENCY_LIMIT {
+                    RSSI_OFFSET_LF
+                } else {
+                    RSSI_OFFSET_HF
+                };
+                let rssi = offset + bytes[1] as i16;
+                self.snr.set(snr);
+                self.rssi
+                    .set(if snr < 0 { rssi + snr as i16 } else { rssi });
+                self.write_register(State::SetFifoPointer, Register::FifoAddrPtr, 0);
+            }
+            State::SetFifoPointer => {
+                let len = self.rx_len.get();
+                self.read_registers(State::ReadFifo, Register::Fifo, len);
+            }
+            State::ReadFifo => {
+                self.write_register(State::ClearIrqFlags, Register::IrqFlags, 0xff);
+            }
+            State::ClearIrqFlags => {
+                self.write_register(State::Sleep, Register::OpMode, self.op_mode(MODE_SLEEP));
+            }
+            State::Sleep => self.finish(),
+        }
+    }
+}
+
+impl<S: spi::SpiMasterDevice> gpio::Client for SX127x<'a, S> {
+    fn fired(&self, _: usize) {
+        match self.state.get() {
+            State::Transmitting | State::Receiving => {
+                self.read_registers(State::ReadIrqFlags, Register::IrqFlags, 2);
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,88 @@
+//! 64-bit monotonic timestamps for userspace.
+//!
+//! `Timestamp` passes the time of a `hil::time::Time64` to processes. The
+//! virtual alarms of `virtual_alarm` implement `Time64` by extending the
+//! counter of the hardware to 64 bits, so capsules that need timestamps use
+//! their virtual alarm directly.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let timestamp_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! let timestamp = static_init!(
+//!     capsules::timestamp::Timestamp<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::timestamp::Timestamp::new(
+//!         timestamp_alarm,
+//!         board_kernel.create_grant(&memory_allocation_capability)
+//!     )
+//! );
+//! ```
+
+use kernel::hil::time::{Frequency, Time64};
+use kernel::{AppId, Driver, Grant, ReturnCode};
+
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = 0x00008;
+
+#[derive(Default)]
+pub struct App {
+    /// Upper 32 bits of the last timestamp the app read.
+    high_word: u32,
+}
+
+pub struct Timestamp<'a, T: Time64 + 'a> {
+    time: &'a T,
+    apps: Grant<App>,
+}
+
+impl<T: Time64> Timestamp<'a, T> {
+    pub fn new(time: &'a T, grant: Grant<App>) -> Timestamp<'a, T> {
+        Timestamp {
+            time: time,
+            apps: grant,
+        }
+    }
+}
+
+impl<T: Time64> Driver for Timestamp<'a, T> {
+    /// Read timestamps.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Get the frequency of the timestamps, in Hz.
+    /// - `2`: Get the lower 32 bits of the current timestamp.
+    /// - `3`: Get the upper 32 bits of the timestamp last read with `2`.
+    fn command(&self, command_num: usize, _: usize, _: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            1 => ReturnCode::SuccessWithValue {
+                value: T::Frequency::frequency() as usize,
+            },
+
+            2 => {
+                let now = self.time.now64();
+                self.apps
+                    .enter(appid, |app, _| {
+                        app.high_word = (now >> 32) as u32;
+                        ReturnCode::SuccessWithValue {
+                            value: now as u32 as usize,
+                        }
+                    }).unwrap_or_else(|err| err.into())
+            }
+
+            3 => self
+                .apps
+                .enter(appid, |app, _| ReturnCode::SuccessWithValue {
+                    value: app.high_word as usize,
+                }).unwrap_or_else(|err| err.into()),
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
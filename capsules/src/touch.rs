@@ -0,0 +1,162 @@
+//! Provides userspace with touch events from capacitive touch sensors.
+//!
+//! Apps use the channels of a touch sensor like buttons: they enable events
+//! for the channels they want, and get a callback when one is touched or
+//! released. The sensor measures while any app has events enabled. Apps can
+//! also read the raw count of a channel to tune its threshold, which is
+//! shared by all apps.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! sam4l::catb::CATB.set_sensors(&[0, 1]);
+//! let touch = static_init!(
+//!     capsules::touch::Touch<'static>,
+//!     capsules::touch::Touch::new(
+//!         &sam4l::catb::CATB,
+//!         board_kernel.create_grant(&memory_allocation_capability)));
+//! hil::touch::TouchSense::set_client(&sam4l::catb::CATB, touch);
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::touch;
+use kernel::{AppId, Callback, Driver, Grant, ReturnCode};
+
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = 0x0000D;
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    /// The channels with events enabled, one bit each
+    subscribed: u32,
+}
+
+pub struct Touch<'a> {
+    sensor: &'a touch::TouchSense,
+    apps: Grant<App>,
+}
+
+impl Touch<'a> {
+    pub fn new(sensor: &'a touch::TouchSense, grant: Grant<App>) -> Touch<'a> {
+        Touch {
+            sensor: sensor,
+            apps: grant,
+        }
+    }
+
+    /// Enables the sensor if any app wants events, and disables it if none
+    /// does.
+    fn update_sensor(&self) {
+        let subscribed = Cell::new(0);
+        self.apps.each(|app| {
+            subscribed.set(subscribed.get() | app.subscribed);
+        });
+        if subscribed.get() != 0 {
+            self.sensor.enable();
+        } else {
+            self.sensor.disable();
+        }
+    }
+}
+
+impl touch::Client for Touch<'a> {
+    fn touch_changed(&self, channel: usize, touched: bool) {
+        let subscribed = Cell::new(false);
+        self.apps.each(|app| {
+            if app.subscribed & (1 << channel) != 0 {
+                subscribed.set(true);
+                app.callback
+                    .map(|mut cb| cb.schedule(channel, touched as usize, 0));
+            }
+        });
+
+        // Apps that subscribed may have since died
+        if !subscribed.get() {
+            self.update_sensor();
+        }
+    }
+}
+
+impl Driver for Touch<'a> {
+    /// Subscribe to touch events.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Called with the channel and its state, touched (1) or released
+    ///        (0), for the channels with events enabled.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Control touch events and read the channels.
+    ///
+    /// `data` is the channel for all commands but 0. They return EINVAL if
+    /// the channel does not exist.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check and get the number of channels.
+    /// - `1`: Enable events for a channel.
+    /// - `2`: Disable events for a channel.
+    /// - `3`: Read whether a channel is touched (1) or not (0).
+    /// - `4`: Get the raw count of a channel. Returns EBUSY if it was not
+    ///        measured yet.
+    /// - `5`: Set the threshold of a channel to `data2` counts.
+    fn command(&self, command_num: usize, data: usize, data2: usize, appid: AppId) -> ReturnCode {
+        if command_num == 0 {
+            return ReturnCode::SuccessWithValue {
+                value: self.sensor.channel_count(),
+            };
+        }
+        if data >= self.sensor.channel_count() {
+            return ReturnCode::EINVAL;
+        }
+
+        match command_num {
+            1 | 2 => {
+                let rcode = self
+                    .apps
+                    .enter(appid, |app, _| {
+                        if command_num == 1 {
+                            app.subscribed |= 1 << data;
+                        } else {
+                            app.subscribed &= !(1 << data);
+                        }
+                        ReturnCode::SUCCESS
+                    }).unwrap_or_else(|err| err.into());
+                self.update_sensor();
+                rcode
+            }
+
+            3 => ReturnCode::SuccessWithValue {
+                value: self.sensor.is_touched(data) as usize,
+            },
+
+            4 => self
+                .sensor
+                .get_count(data)
+                .map_or(ReturnCode::EBUSY, |count| ReturnCode::SuccessWithValue {
+                    value: count as usize,
+                }),
+
+            5 => self.sensor.set_threshold(data, data2 as u32),
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
@@ -0,0 +1,190 @@
+//! Capacitive touch sensing by charge transfer over GPIO pins.
+//!
+//! This implements `hil::touch::TouchSense` on chips without a touch
+//! peripheral, such as the nRF52. Each channel uses two pins: the electrode
+//! is connected to the SNSK pin, and a sampling capacitor, of about 10 nF,
+//! between the SNSK and SNS pins.
+//!
+//! A measurement first discharges both capacitors. Then each pulse charges
+//! the electrode and the sampling capacitor in series from the SNS pin, and
+//! discharges the electrode alone through the SNSK pin, which moves charge
+//! into the sampling capacitor. The count is the number of pulses until the
+//! SNS pin reads high. A finger adds capacitance to the electrode, so each
+//! pulse moves more charge and the count drops.
+//!
+//! The channels are measured every `SCAN_PERIOD_MS`. A measurement is a busy
+//! loop of a few hundred pulses, so it takes the CPU for a fraction of a
+//! millisecond per channel, and interrupts make it noisier.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let touch_pins = static_init!(
+//!     [(&'static kernel::hil::gpio::Pin, &'static kernel::hil::gpio::Pin); 2],
+//!     [(&nrf5x::gpio::PORT[3], &nrf5x::gpio::PORT[4]),
+//!      (&nrf5x::gpio::PORT[28], &nrf5x::gpio::PORT[29])]);
+//! let touch_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, nrf5x::rtc::Rtc>,
+//!     VirtualMuxAlarm::new(mux_alarm));
+//! let touch_gpio = static_init!(
+//!     capsules::touch_gpio::TouchGpio<'static, VirtualMuxAlarm<'static, nrf5x::rtc::Rtc>>,
+//!     capsules::touch_gpio::TouchGpio::new(touch_pins, touch_alarm));
+//! touch_alarm.set_client(touch_gpio);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::hil::gpio;
+use kernel::hil::time::{self, Frequency};
+use kernel::hil::touch::{self, TouchSense};
+use kernel::ReturnCode;
+
+/// The period between measurements of all channels
+const SCAN_PERIOD_MS: u32 = 20;
+
+/// The count of a measurement that did not finish
+const MAX_PULSES: u32 = 2000;
+
+/// The most channels the driver measures
+const MAX_CHANNELS: usize = 8;
+
+/// The threshold of the channels until it is set
+const DEFAULT_THRESHOLD: u32 = 20;
+
+pub struct TouchGpio<'a, A: time::Alarm> {
+    /// The SNS and SNSK pins of each channel
+    pins: &'a [(&'a gpio::Pin, &'a gpio::Pin)],
+    alarm: &'a A,
+    client: OptionalCell<&'static touch::Client>,
+    detectors: Cell<[touch::Detector; MAX_CHANNELS]>,
+    enabled: Cell<bool>,
+}
+
+impl<A: time::Alarm> TouchGpio<'a, A> {
+    pub fn new(pins: &'a [(&'a gpio::Pin, &'a gpio::Pin)], alarm: &'a A) -> TouchGpio<'a, A> {
+        for &(sns, snsk) in pins.iter() {
+            sns.make_output();
+            sns.clear();
+            snsk.make_output();
+            snsk.clear();
+        }
+        TouchGpio {
+            pins: pins,
+            alarm: alarm,
+            client: OptionalCell::empty(),
+            detectors: Cell::new([touch::Detector::new(DEFAULT_THRESHOLD); MAX_CHANNELS]),
+            enabled: Cell::new(false),
+        }
+    }
+
+    /// Measures a channel, and leaves its capacitors discharged.
+    fn measure(&self, channel: usize) -> u32 {
+        let (sns, snsk) = self.pins[channel];
+
+        sns.make_output();
+        sns.clear();
+        snsk.make_output();
+        snsk.clear();
+
+        let mut pulses = 0;
+        while pulses < MAX_PULSES {
+            // Charge the electrode through the sampling capacitor
+            snsk.make_input();
+            sns.set();
+            sns.make_output();
+
+            // Discharge the electrode alone
+            sns.make_input();
+            snsk.make_output();
+            snsk.clear();
+
+            pulses += 1;
+            if sns.read() {
+                break;
+            }
+        }
+
+        sns.make_output();
+        sns.clear();
+        pulses
+    }
+
+    fn schedule(&self, from: u32) {
+        let interval = SCAN_PERIOD_MS * A::Frequency::frequency() / 1000;
+        self.alarm.set_alarm(from.wrapping_add(interval));
+    }
+}
+
+impl<A: time::Alarm> time::Client for TouchGpio<'a, A> {
+    fn fired(&self) {
+        if !self.enabled.get() {
+            return;
+        }
+        let now = self.alarm.get_alarm();
+        self.schedule(now);
+
+        for channel in 0..self.channel_count() {
+            let count = self.measure(channel);
+            let mut detectors = self.detectors.get();
+            let changed = detectors[channel].update(count);
+            self.detectors.set(detectors);
+            changed.map(|touched| {
+                self.client.map(|client| client.touch_changed(channel, touched));
+            });
+        }
+    }
+}
+
+impl<A: time::Alarm> touch::TouchSense for TouchGpio<'a, A> {
+    fn set_client(&self, client: &'static touch::Client) {
+        self.client.set(client);
+    }
+
+    fn channel_count(&self) -> usize {
+        if self.pins.len() < MAX_CHANNELS {
+            self.pins.len()
+        } else {
+            MAX_CHANNELS
+        }
+    }
+
+    fn enable(&self) -> ReturnCode {
+        if self.enabled.get() {
+            return ReturnCode::EALREADY;
+        }
+        self.enabled.set(true);
+        self.schedule(self.alarm.now());
+        ReturnCode::SUCCESS
+    }
+
+    fn disable(&self) -> ReturnCode {
+        if !self.enabled.get() {
+            return ReturnCode::EOFF;
+        }
+        self.alarm.disable();
+        self.enabled.set(false);
+        ReturnCode::SUCCESS
+    }
+
+    fn set_threshold(&self, channel: usize, threshold: u32) -> ReturnCode {
+        if channel >= self.channel_count() {
+            return ReturnCode::EINVAL;
+        }
+        let mut detectors = self.detectors.get();
+        detectors[channel].set_threshold(threshold);
+        self.detectors.set(detectors);
+        ReturnCode::SUCCESS
+    }
+
+    fn get_count(&self, channel: usize) -> Option<u32> {
+        if channel >= self.channel_count() {
+            return None;
+        }
+        self.detectors.get()[channel].count()
+    }
+
+    fn is_touched(&self, channel: usize) -> bool {
+        channel < self.channel_count() && self.detectors.get()[channel].is_touched()
+    }
+}
@@ -1,9 +1,11 @@
 //! Platform-independent USB 2.0 protocol library
 
 use core::cell::Cell;
+use core::cmp::{max, min};
 use core::convert::From;
 use core::fmt;
 use kernel::common::cells::VolatileCell;
+use kernel::hil;
 
 /// The datastructure sent in a SETUP handshake
 #[derive(Debug, Copy, Clone)]
@@ -544,6 +546,339 @@ impl Descriptor for StringDescriptor<'a> {
     }
 }
 
+/// The languages of the string descriptors of the devices
+pub static LANGUAGES: &'static [u16] = &[
+    0x0409, // English (United States)
+];
+
+/// Storage for serializing a single descriptor.  The configuration
+/// descriptor and the descriptors that follow it are longer than this, so
+/// they are serialized one at a time as their bytes are sent.
+pub const DESCRIPTOR_BUFLEN: usize = 32;
+
+/// A USB device class, which a `ControlEndpoint` asks for the descriptors of
+/// the device, and passes the requests it does not handle itself to
+pub trait DeviceClass {
+    /// A class-specific Control transfer
+    type Request: Copy;
+
+    fn device_descriptor(&self) -> DeviceDescriptor;
+
+    /// The strings of the device, which string descriptor `i` is entry
+    /// `i - 1` of
+    fn strings(&self) -> &'static [&'static str];
+
+    fn num_interfaces(&self) -> u8;
+
+    /// Serialize the descriptor at `index` among those that follow the
+    /// configuration descriptor, returning its length, or `None` if there
+    /// are no more descriptors
+    fn write_configuration_part(&self, index: usize, buf: &[Cell<u8>]) -> Option<usize>;
+
+    /// Handle a request that is not a standard device request
+    fn class_request(&self, setup_data: SetupData) -> ClassRequest<Self::Request>;
+
+    /// Serialize part `index` of the data of Control Read `request`,
+    /// returning its length, or `None` if there are no more parts
+    fn write_request_part(
+        &self,
+        request: Self::Request,
+        index: usize,
+        buf: &[Cell<u8>],
+    ) -> Option<usize>;
+
+    /// Handle a packet of the data of Control Write `request`
+    fn request_out(
+        &self,
+        request: Self::Request,
+        packet: &[VolatileCell<u8>],
+        packet_bytes: usize,
+    ) -> hil::usb::CtrlOutResult;
+}
+
+/// How a `DeviceClass` handles a class-specific request
+pub enum ClassRequest<R> {
+    /// Send at most the given number of bytes of the data of the request
+    Read(R, u16),
+
+    /// Receive the data of the request
+    Write(R),
+
+    /// The request has no Data stage, and was handled
+    Ok,
+
+    /// The request cannot be handled; abort this transfer with STALL
+    Err(hil::usb::CtrlSetupResult),
+}
+
+/// The data sent to the host in the Data stage of a Control Read
+#[derive(Copy, Clone)]
+enum Response<R> {
+    Device,
+    /// The configuration descriptor and the descriptors that follow it
+    Configuration,
+    Languages,
+    String(usize),
+    Request(R),
+}
+
+#[derive(Copy, Clone)]
+enum CtrlState<R> {
+    Init,
+
+    /// We are doing a Control In transfer of the given response, with the
+    /// given extent remaining to send
+    CtrlIn(Response<R>, usize, usize),
+
+    /// We will accept the data of a class-specific request from the host
+    CtrlOut(R),
+
+    SetAddress,
+}
+
+/// The default control endpoint of a device, which answers the standard
+/// device requests from the descriptors of a `DeviceClass`, and passes the
+/// other requests to it.  A `DeviceClass` calls the `ctrl_*` methods from
+/// the methods of `hil::usb::Client` of the same name.
+pub struct ControlEndpoint<'a, C: 'a, R> {
+    // The hardware controller
+    controller: &'a C,
+
+    state: Cell<CtrlState<R>>,
+
+    // Storage for composing responses to device-descriptor requests
+    descriptor_storage: [Cell<u8>; DESCRIPTOR_BUFLEN],
+}
+
+impl<C: hil::usb::UsbController, R: Copy> ControlEndpoint<'a, C, R> {
+    pub fn new(controller: &'a C) -> Self {
+        ControlEndpoint {
+            controller: controller,
+            state: Cell::new(CtrlState::Init),
+            descriptor_storage: Default::default(),
+        }
+    }
+
+    /// Serialize part `index` of `response` into the descriptor storage,
+    /// returning its length, or `None` if there are no more parts
+    fn write_part<D: DeviceClass<Request = R>>(
+        &self,
+        device: &D,
+        response: Response<R>,
+        index: usize,
+    ) -> Option<usize> {
+        let buf = &self.descriptor_storage;
+        match (response, index) {
+            (Response::Device, 0) => Some(device.device_descriptor().write_to(buf)),
+            (Response::Configuration, 0) => {
+                let mut related_descriptor_length = 0;
+                let mut i = 0;
+                while let Some(len) = device.write_configuration_part(i, buf) {
+                    related_descriptor_length += len;
+                    i += 1;
+                }
+                Some(
+                    ConfigurationDescriptor {
+                        num_interfaces: device.num_interfaces(),
+                        configuration_value: 1,
+                        related_descriptor_length: related_descriptor_length,
+                        ..Default::default()
+                    }.write_to(buf),
+                )
+            }
+            (Response::Configuration, i) => device.write_configuration_part(i - 1, buf),
+            (Response::Languages, 0) => {
+                Some(LanguagesDescriptor { langs: LANGUAGES }.write_to(buf))
+            }
+            (Response::String(i), 0) => Some(
+                StringDescriptor {
+                    string: device.strings()[i - 1],
+                }.write_to(buf),
+            ),
+            (Response::Request(request), i) => device.write_request_part(request, i, buf),
+            _ => None,
+        }
+    }
+
+    /// Start a Control In transfer of at most `requested_length` bytes of
+    /// `response`
+    fn ctrl_in_start<D: DeviceClass<Request = R>>(
+        &self,
+        device: &D,
+        response: Response<R>,
+        requested_length: u16,
+    ) -> hil::usb::CtrlSetupResult {
+        let mut len = 0;
+        let mut i = 0;
+        while let Some(part_len) = self.write_part(device, response, i) {
+            len += part_len;
+            i += 1;
+        }
+        let end = min(len, requested_length as usize);
+        self.state.set(CtrlState::CtrlIn(response, 0, end));
+        hil::usb::CtrlSetupResult::Ok
+    }
+
+    fn standard_request<D: DeviceClass<Request = R>>(
+        &self,
+        device: &D,
+        request: StandardDeviceRequest,
+    ) -> hil::usb::CtrlSetupResult {
+        match request {
+            StandardDeviceRequest::GetDescriptor {
+                descriptor_type,
+                descriptor_index,
+                lang_id,
+                requested_length,
+            } => {
+                let response = match descriptor_type {
+                    DescriptorType::Device if descriptor_index == 0 => Response::Device,
+                    DescriptorType::Device => {
+                        return hil::usb::CtrlSetupResult::ErrInvalidDeviceIndex;
+                    }
+                    DescriptorType::Configuration if descriptor_index == 0 => {
+                        Response::Configuration
+                    }
+                    DescriptorType::Configuration => {
+                        return hil::usb::CtrlSetupResult::ErrInvalidConfigurationIndex;
+                    }
+                    DescriptorType::String => match descriptor_index as usize {
+                        0 => Response::Languages,
+                        i if i <= device.strings().len() && lang_id == LANGUAGES[0] => {
+                            Response::String(i)
+                        }
+                        _ => return hil::usb::CtrlSetupResult::ErrInvalidStringIndex,
+                    },
+                    DescriptorType::DeviceQualifier => {
+                        // We are full-speed only, so we must
+                        // respond with a request error
+                        return hil::usb::CtrlSetupResult::ErrNoDeviceQualifier;
+                    }
+                    _ => return hil::usb::CtrlSetupResult::ErrUnrecognizedDescriptorType,
+                };
+                self.ctrl_in_start(device, response, requested_length)
+            }
+            StandardDeviceRequest::SetAddress { device_address } => {
+                // Load the address we've been assigned ...
+                self.controller.set_address(device_address);
+
+                // ... and when this request gets to the Status stage
+                // we will actually enable the address.
+                self.state.set(CtrlState::SetAddress);
+                hil::usb::CtrlSetupResult::Ok
+            }
+            StandardDeviceRequest::SetConfiguration { .. } => {
+                // We have been assigned a particular configuration: fine!
+                hil::usb::CtrlSetupResult::Ok
+            }
+            _ => hil::usb::CtrlSetupResult::ErrUnrecognizedRequestType,
+        }
+    }
+
+    /// Handle a Control Setup transaction, with the SETUP packet in `packet`
+    pub fn ctrl_setup<D: DeviceClass<Request = R>>(
+        &self,
+        device: &D,
+        packet: &[VolatileCell<u8>],
+    ) -> hil::usb::CtrlSetupResult {
+        let setup_data = match SetupData::get(packet) {
+            Some(setup_data) => setup_data,
+            None => return hil::usb::CtrlSetupResult::ErrNoParse,
+        };
+        match setup_data.get_standard_request() {
+            Some(request) => self.standard_request(device, request),
+            None => match device.class_request(setup_data) {
+                ClassRequest::Read(request, requested_length) => {
+                    self.ctrl_in_start(device, Response::Request(request), requested_length)
+                }
+                ClassRequest::Write(request) => {
+                    self.state.set(CtrlState::CtrlOut(request));
+                    hil::usb::CtrlSetupResult::Ok
+                }
+                ClassRequest::Ok => hil::usb::CtrlSetupResult::Ok,
+                ClassRequest::Err(result) => result,
+            },
+        }
+    }
+
+    /// Handle a Control In transaction, writing the next packet into
+    /// `packet`
+    pub fn ctrl_in<D: DeviceClass<Request = R>>(
+        &self,
+        device: &D,
+        packet: &[VolatileCell<u8>],
+    ) -> hil::usb::CtrlInResult {
+        match self.state.get() {
+            CtrlState::CtrlIn(response, start, end) => {
+                let len = end.saturating_sub(start);
+                if len > 0 {
+                    let packet_bytes = min(packet.len(), len);
+                    let packet_end = start + packet_bytes;
+
+                    // Copy the bytes of the response that belong in this
+                    // packet into the endpoint buffer, one part at a time
+                    let mut part_start = 0;
+                    let mut i = 0;
+                    while part_start < packet_end {
+                        let part_len = match self.write_part(device, response, i) {
+                            Some(part_len) => part_len,
+                            None => break,
+                        };
+                        let part_end = part_start + part_len;
+                        for j in max(start, part_start)..min(packet_end, part_end) {
+                            packet[j - start].set(self.descriptor_storage[j - part_start].get());
+                        }
+                        part_start = part_end;
+                        i += 1;
+                    }
+
+                    let transfer_complete = packet_end == end;
+
+                    self.state.set(CtrlState::CtrlIn(response, packet_end, end));
+
+                    hil::usb::CtrlInResult::Packet(packet_bytes, transfer_complete)
+                } else {
+                    hil::usb::CtrlInResult::Packet(0, true)
+                }
+            }
+            _ => hil::usb::CtrlInResult::Error,
+        }
+    }
+
+    /// Handle a Control Out transaction, with the packet received in
+    /// `packet`
+    pub fn ctrl_out<D: DeviceClass<Request = R>>(
+        &self,
+        device: &D,
+        packet: &[VolatileCell<u8>],
+        packet_bytes: u32,
+    ) -> hil::usb::CtrlOutResult {
+        match self.state.get() {
+            CtrlState::CtrlOut(request) => {
+                device.request_out(request, packet, packet_bytes as usize)
+            }
+            _ => {
+                // Bad state
+                hil::usb::CtrlOutResult::Halted
+            }
+        }
+    }
+
+    /// Handle the completion of a Control transfer
+    pub fn ctrl_status_complete(&self) {
+        // Control Read: IN request acknowledged
+        // Control Write: status sent
+
+        match self.state.get() {
+            CtrlState::SetAddress => {
+                self.controller.enable_address();
+            }
+            _ => {}
+        };
+        self.state.set(CtrlState::Init);
+    }
+}
+
 /// Parse a `u16` from two bytes as received on the bus
 fn get_u16(b0: u8, b1: u8) -> u16 {
     (b0 as u16) | ((b1 as u16) << 8)
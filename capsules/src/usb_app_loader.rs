@@ -0,0 +1,495 @@
+//! A vendor-specific USB interface for installing apps.
+//!
+//! `UsbAppLoader` is a client of a USB controller that lets host tools read
+//! and write the flash region that apps are stored in, and then start the
+//! apps they wrote without rebooting, using a `ProcessLoader`. Apps can
+//! therefore be installed over USB instead of through a debugger or a
+//! bootloader.
+//!
+//! The device has a single vendor-specific interface, with a bulk OUT
+//! endpoint that the host sends commands on, and a bulk IN endpoint that the
+//! device answers every command on. A command starts with a 7-byte header: a
+//! command byte, a 4-byte address and a 2-byte length, both little-endian.
+//! The commands are:
+//!
+//! * `1` (read): read `length` bytes of flash at `address`.
+//! * `2` (write): write the `length` bytes that follow the header to flash at
+//!   `address`.
+//! * `3` (load): start the apps in flash that are not running yet. The address
+//!   and length are ignored.
+//!
+//! The response starts with a status byte, the `ReturnCode` of the command
+//! as a signed number, followed for a read by the data read. The status of
+//! a load is the number of apps started. Reads and writes must be within the
+//! app flash region, and at most as long as the buffer of the loader. Writes
+//! to the flash of apps that are loaded fail with `EBUSY`. The host sends one
+//! command at a time, and waits for its response before sending the next.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let app_loader = static_init!(
+//!     capsules::usb_app_loader::UsbAppLoader<'static, sam4l::usbc::Usbc<'static>>,
+//!     capsules::usb_app_loader::UsbAppLoader::new(
+//!         &sam4l::usbc::USBC,
+//!         nv_to_page,
+//!         process_loader,
+//!         &process_mgmt_cap,
+//!         &_sapps as *const u8 as usize,
+//!         0x40000,
+//!         &mut capsules::usb_app_loader::BUFFER
+//!     )
+//! );
+//! hil::nonvolatile_storage::NonvolatileStorage::set_client(nv_to_page, app_loader);
+//! sam4l::usbc::USBC.set_client(app_loader);
+//! hil::usb::Client::enable(app_loader);
+//! hil::usb::Client::attach(app_loader);
+//! ```
+
+use core::cell::Cell;
+use core::cmp::min;
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::common::cells::{TakeCell, VolatileCell};
+use kernel::hil;
+use kernel::procs::ProcessLoader;
+use kernel::ReturnCode;
+use usb::ClassRequest;
+use usb::ControlEndpoint;
+use usb::Descriptor;
+use usb::DeviceClass;
+use usb::DeviceDescriptor;
+use usb::EndpointAddress;
+use usb::EndpointDescriptor;
+use usb::InterfaceDescriptor;
+use usb::SetupData;
+use usb::TransferDirection;
+use usb::TransferType;
+
+pub static mut BUFFER: [u8; 512] = [0; 512];
+
+const VENDOR_ID: u16 = 0x6667;
+const PRODUCT_ID: u16 = 0xabd0;
+
+static STRINGS: &'static [&'static str] = &[
+    "Tock",            // Manufacturer
+    "Tock App Loader", // Product
+    "000001",          // Serial number
+];
+
+/// Commands
+const COMMAND_READ: u8 = 1;
+const COMMAND_WRITE: u8 = 2;
+const COMMAND_LOAD: u8 = 3;
+
+const HEADER_LEN: usize = 7;
+
+/// The endpoints the device uses, other than the default control endpoint
+const BULK_IN: usize = 1;
+const BULK_OUT: usize = 2;
+
+const N_ENDPOINTS: usize = 3;
+
+/// Size of every packet buffer, and the max packet size of every endpoint
+const PACKET_SIZE: usize = 8;
+
+/// Serialize the descriptor at `index` among those that follow the
+/// configuration descriptor, returning its length, or `None` if there are
+/// no more descriptors
+fn write_configuration_part(index: usize, buf: &[Cell<u8>]) -> Option<usize> {
+    let len = match index {
+        // A single vendor-specific interface
+        0 => InterfaceDescriptor {
+            num_endpoints: 2,
+            interface_class: 0xff,
+            interface_subclass: 0,
+            interface_protocol: 0,
+            ..Default::default()
+        }.write_to(buf),
+        1 => EndpointDescriptor {
+            endpoint_address: EndpointAddress::new(BULK_IN, TransferDirection::DeviceToHost),
+            transfer_type: TransferType::Bulk,
+            max_packet_size: PACKET_SIZE as u16,
+            interval: 0,
+        }.write_to(buf),
+        2 => EndpointDescriptor {
+            endpoint_address: EndpointAddress::new(BULK_OUT, TransferDirection::HostToDevice),
+            transfer_type: TransferType::Bulk,
+            max_packet_size: PACKET_SIZE as u16,
+            interval: 0,
+        }.write_to(buf),
+        _ => return None,
+    };
+    Some(len)
+}
+
+#[derive(Copy, Clone)]
+enum CommandState {
+    /// Receiving a command header, with the given number of bytes received
+    Header(usize),
+
+    /// Receiving the data of a write of `len` bytes to `address`
+    WriteData {
+        address: usize,
+        len: usize,
+        received: usize,
+    },
+
+    /// Waiting for the storage to complete a read or a write
+    Busy,
+
+    /// Sending the response, made of a status byte followed by the first
+    /// `len - 1` bytes of the buffer, of which `offset` bytes were sent
+    Respond { len: usize, offset: usize },
+}
+
+pub struct UsbAppLoader<'a, C: 'a> {
+    // The hardware controller
+    controller: &'a C,
+
+    // The flash the apps are stored in, and the region of it they are in
+    storage: &'a hil::nonvolatile_storage::NonvolatileStorage,
+    region_start: usize,
+    region_len: usize,
+
+    loader: &'a ProcessLoader,
+    capability: &'a ProcessManagementCapability,
+
+    // The default control endpoint, which has no class-specific requests
+    ctrl: ControlEndpoint<'a, C, ()>,
+
+    // A packet buffer for each endpoint
+    buffers: [[VolatileCell<u8>; PACKET_SIZE]; N_ENDPOINTS],
+
+    // State of the command being received, executed, or answered
+    command_state: Cell<CommandState>,
+    header: [Cell<u8>; HEADER_LEN],
+    status: Cell<u8>,
+    // Holds the data of reads and writes
+    buffer: TakeCell<'static, [u8]>,
+
+    delayed_in: Cell<bool>,
+    delayed_out: Cell<bool>,
+}
+
+impl<C: hil::usb::UsbController> UsbAppLoader<'a, C> {
+    pub fn new(
+        controller: &'a C,
+        storage: &'a hil::nonvolatile_storage::NonvolatileStorage,
+        loader: &'a ProcessLoader,
+        capability: &'a ProcessManagementCapability,
+        region_start: usize,
+        region_len: usize,
+        buffer: &'static mut [u8],
+    ) -> Self {
+        UsbAppLoader {
+            controller: controller,
+            storage: storage,
+            region_start: region_start,
+            region_len: region_len,
+            loader: loader,
+            capability: capability,
+            ctrl: ControlEndpoint::new(controller),
+            buffers: Default::default(),
+            command_state: Cell::new(CommandState::Header(0)),
+            header: Default::default(),
+            status: Cell::new(0),
+            buffer: TakeCell::new(buffer),
+            delayed_in: Cell::new(false),
+            delayed_out: Cell::new(false),
+        }
+    }
+
+    /// Returns whether `len` bytes at `address` are in the app flash region,
+    /// and fit in the buffer
+    fn check_range(&self, address: usize, len: usize) -> bool {
+        let buffer_len = self.buffer.map_or(0, |buffer| buffer.len());
+        let end = match address.checked_add(len) {
+            Some(end) => end,
+            None => return false,
+        };
+        len <= buffer_len
+            && address >= self.region_start
+            && end - self.region_start <= self.region_len
+    }
+
+    /// Execute the command in the header
+    fn execute(&self) {
+        let header: [u8; HEADER_LEN] = {
+            let mut header = [0; HEADER_LEN];
+            for (i, b) in self.header.iter().enumerate() {
+                header[i] = b.get();
+            }
+            header
+        };
+        let address = (header[1] as usize)
+            | (header[2] as usize) << 8
+            | (header[3] as usize) << 16
+            | (header[4] as usize) << 24;
+        let len = (header[5] as usize) | (header[6] as usize) << 8;
+
+        match header[0] {
+            COMMAND_READ if self.check_range(address, len) => {
+                let result = self.buffer.take().map_or(ReturnCode::EBUSY, |buffer| {
+                    self.storage.read(buffer, address, len)
+                });
+                if result == ReturnCode::SUCCESS {
+                    self.command_state.set(CommandState::Busy);
+                } else {
+                    self.respond(result, 0);
+                }
+            }
+            COMMAND_WRITE if self.check_range(address, len) => {
+                if self.loader.flash_in_use(address, len) {
+                    // The flash of a loaded app must not change under it
+                    self.respond(ReturnCode::EBUSY, 0);
+                } else if len == 0 {
+                    self.respond(ReturnCode::SUCCESS, 0);
+                } else {
+                    self.command_state.set(CommandState::WriteData {
+                        address: address,
+                        len: len,
+                        received: 0,
+                    });
+                }
+            }
+            COMMAND_READ | COMMAND_WRITE => self.respond(ReturnCode::EINVAL, 0),
+            COMMAND_LOAD => {
+                let result = self.loader.load_new_processes(self.capability);
+                self.respond(result, 0);
+            }
+            _ => self.respond(ReturnCode::ENOSUPPORT, 0),
+        }
+    }
+
+    /// Start sending a response with `result`, followed by the first
+    /// `data_len` bytes of the buffer
+    fn respond(&self, result: ReturnCode, data_len: usize) {
+        self.status.set(isize::from(result) as u8);
+        self.command_state.set(CommandState::Respond {
+            len: 1 + data_len,
+            offset: 0,
+        });
+
+        // In case we reported Delay before, alert the controller
+        // that we now have data to send on the Bulk IN endpoint
+        if self.delayed_in.take() {
+            self.controller.endpoint_bulk_resume(BULK_IN);
+        }
+    }
+}
+
+impl<C: hil::usb::UsbController> DeviceClass for UsbAppLoader<'a, C> {
+    type Request = ();
+
+    fn device_descriptor(&self) -> DeviceDescriptor {
+        DeviceDescriptor {
+            vendor_id: VENDOR_ID,
+            product_id: PRODUCT_ID,
+            manufacturer_string: 1,
+            product_string: 2,
+            serial_number_string: 3,
+            ..Default::default()
+        }
+    }
+
+    fn strings(&self) -> &'static [&'static str] {
+        STRINGS
+    }
+
+    fn num_interfaces(&self) -> u8 {
+        1
+    }
+
+    fn write_configuration_part(&self, index: usize, buf: &[Cell<u8>]) -> Option<usize> {
+        write_configuration_part(index, buf)
+    }
+
+    fn class_request(&self, _setup_data: SetupData) -> ClassRequest<()> {
+        // Commands are only sent on the bulk endpoints
+        ClassRequest::Err(hil::usb::CtrlSetupResult::ErrNonstandardRequest)
+    }
+
+    fn write_request_part(&self, _request: (), _index: usize, _buf: &[Cell<u8>]) -> Option<usize> {
+        None
+    }
+
+    fn request_out(
+        &self,
+        _request: (),
+        _packet: &[VolatileCell<u8>],
+        _packet_bytes: usize,
+    ) -> hil::usb::CtrlOutResult {
+        // There are no Control Write requests with data
+        hil::usb::CtrlOutResult::Halted
+    }
+}
+
+impl<C: hil::usb::UsbController> hil::usb::Client for UsbAppLoader<'a, C> {
+    fn enable(&self) {
+        // Set up the default control endpoint
+        self.controller.endpoint_set_buffer(0, &self.buffers[0]);
+        self.controller
+            .enable_as_device(hil::usb::DeviceSpeed::Full); // must be Full for Bulk transfers
+        self.controller.endpoint_ctrl_out_enable(0);
+
+        self.controller
+            .endpoint_set_buffer(BULK_IN, &self.buffers[BULK_IN]);
+        self.controller.endpoint_bulk_in_enable(BULK_IN);
+        self.controller
+            .endpoint_set_buffer(BULK_OUT, &self.buffers[BULK_OUT]);
+        self.controller.endpoint_bulk_out_enable(BULK_OUT);
+    }
+
+    fn attach(&self) {
+        self.controller.attach();
+    }
+
+    fn bus_reset(&self) {
+        // The controller reinitializes the endpoints.  A read or write that
+        // is in progress still completes, and its response is sent when the
+        // host next reads from the device.
+        self.delayed_in.set(false);
+        self.delayed_out.set(false);
+        match self.command_state.get() {
+            CommandState::Header(_) | CommandState::WriteData { .. } => {
+                self.command_state.set(CommandState::Header(0))
+            }
+            _ => {}
+        }
+    }
+
+    /// Handle a Control Setup transaction
+    fn ctrl_setup(&self, endpoint: usize) -> hil::usb::CtrlSetupResult {
+        if endpoint != 0 {
+            // We only support the default Control endpoint
+            return hil::usb::CtrlSetupResult::ErrInvalidDeviceIndex;
+        }
+        self.ctrl.ctrl_setup(self, &self.buffers[endpoint])
+    }
+
+    /// Handle a Control In transaction
+    fn ctrl_in(&self, endpoint: usize) -> hil::usb::CtrlInResult {
+        self.ctrl.ctrl_in(self, &self.buffers[endpoint])
+    }
+
+    /// Handle a Control Out transaction
+    fn ctrl_out(&self, endpoint: usize, packet_bytes: u32) -> hil::usb::CtrlOutResult {
+        self.ctrl
+            .ctrl_out(self, &self.buffers[endpoint], packet_bytes)
+    }
+
+    fn ctrl_status(&self, _endpoint: usize) {
+        // Entered Status stage
+    }
+
+    /// Handle the completion of a Control transfer
+    fn ctrl_status_complete(&self, _endpoint: usize) {
+        self.ctrl.ctrl_status_complete();
+    }
+
+    /// Handle a Bulk IN transaction
+    fn bulk_in(&self, endpoint: usize) -> hil::usb::BulkInResult {
+        let (len, offset) = match self.command_state.get() {
+            CommandState::Respond { len, offset } => (len, offset),
+            _ => {
+                // Nothing to send
+                self.delayed_in.set(true);
+                return hil::usb::BulkInResult::Delay;
+            }
+        };
+
+        // Write a packet of the response into the endpoint buffer
+        let packet_bytes = min(PACKET_SIZE, len - offset);
+        let packet = &self.buffers[endpoint];
+        self.buffer.map(|buffer| {
+            for i in 0..packet_bytes {
+                packet[i].set(match offset + i {
+                    0 => self.status.get(),
+                    j => buffer[j - 1],
+                });
+            }
+        });
+
+        let offset = offset + packet_bytes;
+        if offset < len {
+            self.command_state
+                .set(CommandState::Respond { len: len, offset: offset });
+        } else {
+            // Accept the next command
+            self.command_state.set(CommandState::Header(0));
+            if self.delayed_out.take() {
+                self.controller.endpoint_bulk_resume(BULK_OUT);
+            }
+        }
+
+        hil::usb::BulkInResult::Packet(packet_bytes)
+    }
+
+    /// Handle a Bulk OUT transaction
+    fn bulk_out(&self, endpoint: usize, packet_bytes: u32) -> hil::usb::BulkOutResult {
+        let packet = &self.buffers[endpoint];
+        for i in 0..packet_bytes as usize {
+            let byte = packet[i].get();
+            match self.command_state.get() {
+                CommandState::Header(received) => {
+                    self.header[received].set(byte);
+                    if received + 1 < HEADER_LEN {
+                        self.command_state.set(CommandState::Header(received + 1));
+                    } else {
+                        self.execute();
+                    }
+                }
+                CommandState::WriteData {
+                    address,
+                    len,
+                    received,
+                } => {
+                    self.buffer.map(|buffer| buffer[received] = byte);
+                    let received = received + 1;
+                    if received < len {
+                        self.command_state.set(CommandState::WriteData {
+                            address: address,
+                            len: len,
+                            received: received,
+                        });
+                    } else {
+                        let result = self.buffer.take().map_or(ReturnCode::EBUSY, |buffer| {
+                            self.storage.write(buffer, address, len)
+                        });
+                        if result == ReturnCode::SUCCESS {
+                            self.command_state.set(CommandState::Busy);
+                        } else {
+                            self.respond(result, 0);
+                        }
+                    }
+                }
+                CommandState::Busy | CommandState::Respond { .. } => {
+                    if i == 0 {
+                        // Wait until the current command is answered
+                        self.delayed_out.set(true);
+                        return hil::usb::BulkOutResult::Delay;
+                    }
+                    // The host sent more than the command: drop the rest of
+                    // the packet
+                    break;
+                }
+            }
+        }
+        hil::usb::BulkOutResult::Ok
+    }
+}
+
+impl<C: hil::usb::UsbController> hil::nonvolatile_storage::NonvolatileStorageClient
+    for UsbAppLoader<'a, C>
+{
+    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.buffer.replace(buffer);
+        self.respond(ReturnCode::SUCCESS, length);
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], _length: usize) {
+        self.buffer.replace(buffer);
+        self.respond(ReturnCode::SUCCESS, 0);
+    }
+}
@@ -0,0 +1,233 @@
+//! Virtualize the AES-CCM* encryption interface.
+//!
+//! `MuxAES128CCM` shares one `AES128CCM` implementation between several users,
+//! like the 802.15.4 framer and the Thread MLE layer. Each user gets a
+//! `VirtualAES128CCM`, which keeps its own key and nonce. Requests are queued
+//! and handed to the underlying implementation one at a time, with the key and
+//! nonce of the user that made them.
+//!
+//! Usage
+//! -----
+//!
+//! ```
+//! type Ccm = capsules::aes_ccm::AES128CCM<'static, sam4l::aes::Aes<'static>>;
+//!
+//! let mux_aes_ccm = static_init!(
+//!     capsules::virtual_aes_ccm::MuxAES128CCM<'static, Ccm>,
+//!     capsules::virtual_aes_ccm::MuxAES128CCM::new(aes_ccm)
+//! );
+//! aes_ccm.set_client(mux_aes_ccm);
+//!
+//! let framer_aes_ccm = static_init!(
+//!     capsules::virtual_aes_ccm::VirtualAES128CCM<'static, Ccm>,
+//!     capsules::virtual_aes_ccm::VirtualAES128CCM::new(mux_aes_ccm)
+//! );
+//! framer_aes_ccm.set_client(framer);
+//! ```
+
+use core::cell::Cell;
+use core::ptr;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::{List, ListLink, ListNode};
+use kernel::hil::symmetric_encryption::{
+    CCMClient, AES128CCM, AES128_KEY_SIZE, CCM_MIN_NONCE_LENGTH, CCM_NONCE_LENGTH,
+};
+use kernel::ReturnCode;
+
+/// The arguments of a `crypt` call that waits for the underlying
+/// implementation.
+#[derive(Copy, Clone)]
+struct Request {
+    a_off: usize,
+    m_off: usize,
+    m_len: usize,
+    mic_len: usize,
+    confidential: bool,
+    encrypting: bool,
+}
+
+/// Keeps the list of users of an `AES128CCM` implementation and runs their
+/// requests in turn.
+pub struct MuxAES128CCM<'a, A: AES128CCM<'a> + 'a> {
+    aes_ccm: &'a A,
+    users: List<'a, VirtualAES128CCM<'a, A>>,
+    inflight: OptionalCell<&'a VirtualAES128CCM<'a, A>>,
+}
+
+impl<A: AES128CCM<'a>> MuxAES128CCM<'a, A> {
+    pub const fn new(aes_ccm: &'a A) -> MuxAES128CCM<'a, A> {
+        MuxAES128CCM {
+            aes_ccm: aes_ccm,
+            users: List::new(),
+            inflight: OptionalCell::empty(),
+        }
+    }
+
+    /// Hand the request of `user` to the underlying implementation. Returns
+    /// the buffer if the request could not be started.
+    fn start(
+        &self,
+        user: &'a VirtualAES128CCM<'a, A>,
+        buf: &'static mut [u8],
+        request: Request,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.aes_ccm.set_key(&user.key.get()) != ReturnCode::SUCCESS
+            || self
+                .aes_ccm
+                .set_nonce(&user.nonce.get()[..user.nonce_len.get()])
+                != ReturnCode::SUCCESS
+        {
+            return (ReturnCode::FAIL, Some(buf));
+        }
+        let (res, buf) = self.aes_ccm.crypt(
+            buf,
+            request.a_off,
+            request.m_off,
+            request.m_len,
+            request.mic_len,
+            request.confidential,
+            request.encrypting,
+        );
+        if res == ReturnCode::SUCCESS {
+            self.inflight.set(user);
+        }
+        (res, buf)
+    }
+
+    /// Start the next queued request. Requests that fail to start are
+    /// returned to their users, except that of `caller`, which is returned
+    /// from here so that `crypt` can report the error.
+    fn do_next_op(
+        &self,
+        caller: Option<&VirtualAES128CCM<'a, A>>,
+    ) -> Option<(ReturnCode, &'static mut [u8])> {
+        let mut caller_failure = None;
+        while self.inflight.is_none() {
+            let user = match self.users.iter().find(|user| user.buffer.is_some()) {
+                Some(user) => user,
+                None => break,
+            };
+            let buf = match user.buffer.take() {
+                Some(buf) => buf,
+                None => break,
+            };
+            if let (res, Some(buf)) = self.start(user, buf, user.request.get()) {
+                if caller.map_or(false, |caller| ptr::eq(caller, user)) {
+                    caller_failure = Some((res, buf));
+                } else {
+                    user.client
+                        .map(move |client| client.crypt_done(buf, res, false));
+                }
+            }
+        }
+        caller_failure
+    }
+}
+
+impl<A: AES128CCM<'a>> CCMClient for MuxAES128CCM<'a, A> {
+    fn crypt_done(&self, buf: &'static mut [u8], res: ReturnCode, tag_is_valid: bool) {
+        self.inflight.take().map(move |user| {
+            user.client
+                .map(move |client| client.crypt_done(buf, res, tag_is_valid));
+        });
+        self.do_next_op(None);
+    }
+}
+
+/// One user of a shared `AES128CCM` implementation.
+pub struct VirtualAES128CCM<'a, A: AES128CCM<'a> + 'a> {
+    mux: &'a MuxAES128CCM<'a, A>,
+    key: Cell<[u8; AES128_KEY_SIZE]>,
+    nonce: Cell<[u8; CCM_NONCE_LENGTH]>,
+    nonce_len: Cell<usize>,
+    /// The buffer of a queued request.
+    buffer: TakeCell<'static, [u8]>,
+    request: Cell<Request>,
+    next: ListLink<'a, VirtualAES128CCM<'a, A>>,
+    client: OptionalCell<&'a CCMClient>,
+}
+
+impl<A: AES128CCM<'a>> VirtualAES128CCM<'a, A> {
+    pub const fn new(mux: &'a MuxAES128CCM<'a, A>) -> VirtualAES128CCM<'a, A> {
+        VirtualAES128CCM {
+            mux: mux,
+            key: Cell::new([0; AES128_KEY_SIZE]),
+            nonce: Cell::new([0; CCM_NONCE_LENGTH]),
+            nonce_len: Cell::new(CCM_NONCE_LENGTH),
+            buffer: TakeCell::empty(),
+            request: Cell::new(Request {
+                a_off: 0,
+                m_off: 0,
+                m_len: 0,
+                mic_len: 0,
+                confidential: false,
+                encrypting: false,
+            }),
+            next: ListLink::empty(),
+            client: OptionalCell::empty(),
+        }
+    }
+}
+
+impl<A: AES128CCM<'a>> ListNode<'a, VirtualAES128CCM<'a, A>> for VirtualAES128CCM<'a, A> {
+    fn next(&'a self) -> &'a ListLink<'a, VirtualAES128CCM<'a, A>> {
+        &self.next
+    }
+}
+
+impl<A: AES128CCM<'a>> AES128CCM<'a> for VirtualAES128CCM<'a, A> {
+    fn set_client(&'a self, client: &'a CCMClient) {
+        self.mux.users.push_head(self);
+        self.client.set(client);
+    }
+
+    fn set_key(&self, key: &[u8]) -> ReturnCode {
+        if key.len() != AES128_KEY_SIZE {
+            return ReturnCode::EINVAL;
+        }
+        let mut new_key = [0; AES128_KEY_SIZE];
+        new_key.copy_from_slice(key);
+        self.key.set(new_key);
+        ReturnCode::SUCCESS
+    }
+
+    fn set_nonce(&self, nonce: &[u8]) -> ReturnCode {
+        if nonce.len() < CCM_MIN_NONCE_LENGTH || nonce.len() > CCM_NONCE_LENGTH {
+            return ReturnCode::EINVAL;
+        }
+        let mut new_nonce = [0; CCM_NONCE_LENGTH];
+        new_nonce[..nonce.len()].copy_from_slice(nonce);
+        self.nonce.set(new_nonce);
+        self.nonce_len.set(nonce.len());
+        ReturnCode::SUCCESS
+    }
+
+    fn crypt(
+        &self,
+        buf: &'static mut [u8],
+        a_off: usize,
+        m_off: usize,
+        m_len: usize,
+        mic_len: usize,
+        confidential: bool,
+        encrypting: bool,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        let running = self.mux.inflight.map_or(false, |user| ptr::eq(*user, self));
+        if running || self.buffer.is_some() {
+            return (ReturnCode::EBUSY, Some(buf));
+        }
+        self.request.set(Request {
+            a_off: a_off,
+            m_off: m_off,
+            m_len: m_len,
+            mic_len: mic_len,
+            confidential: confidential,
+            encrypting: encrypting,
+        });
+        self.buffer.replace(buf);
+        match self.mux.do_next_op(Some(self)) {
+            Some((res, buf)) => (res, Some(buf)),
+            None => (ReturnCode::SUCCESS, None),
+        }
+    }
+}
@@ -1,14 +1,61 @@
 //! Virtualize the Alarm interface to enable multiple users of an underlying
 //! alarm hardware peripheral.
+//!
+//! The mux extends the counter of the hardware, 32 bits or fewer, to 64 bits
+//! in software: it counts the wraps of the counter whenever it reads it, and
+//! keeps the hardware alarm set at most 7/8 of a wrap ahead, so that it reads
+//! it at least once per wrap. Otherwise the mux is tickless: the hardware
+//! alarm is set for the soonest virtual alarm only, and when no virtual
+//! alarm is armed, the chip wakes only once per wrap and can spend the rest
+//! of the time in its deepest sleep.
+//!
+//! Virtual alarms keep their time in 64 bits, so they fire at any distance,
+//! and implement `hil::time::Alarm64` for clients that should not have to
+//! handle the counter wrapping.
+//!
+//! A virtual alarm can have slack, a number of ticks it may fire late by.
+//! The mux wakes at the earliest time an armed alarm must fire by, and then
+//! fires every alarm that is due, so alarms that fall within each other's
+//! slack share one wakeup. Alarms never fire early, and alarms without slack
+//! fire as soon as they are due. A board that runs many periodic tasks that
+//! need little precision gives their alarms slack to wake less often.
+//!
+//! ```rust
+//! let sensor_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! // 50 ms at 16 kHz
+//! sensor_alarm.set_slack(800);
+//! ```
+//!
+//! Virtual alarms also implement the 32-bit `hil::time::Alarm`, whose
+//! counter is the lower 32 bits of the 64-bit counter, so it wraps at 32
+//! bits even when the hardware counter is narrower. An alarm set there is
+//! the next time the lower 32 bits reach the value, unless the value was
+//! passed since the mux last handled the hardware alarm, and at most half a
+//! 32-bit wrap ago, in which case it fires as soon as possible.
 
 use core::cell::Cell;
+use core::cmp;
 use kernel::common::cells::OptionalCell;
 use kernel::common::{List, ListLink, ListNode};
-use kernel::hil::time::{self, Alarm, Time};
+use kernel::hil::time::{self, Alarm, Alarm64, Time, Time64};
+
+/// The fewest ticks ahead of the counter that the hardware alarm is set for,
+/// so that the counter does not pass it while it is being set.
+const MIN_TICKS: u64 = 2;
+
+/// The hardware alarm is set at most a wrap ahead minus `1 / 2^GUARD_SHIFT`
+/// of a wrap, so that a late interrupt still reads the counter before it
+/// wraps past its last reading.
+const GUARD_SHIFT: u32 = 3;
 
 pub struct VirtualMuxAlarm<'a, Alrm: Alarm> {
     mux: &'a MuxAlarm<'a, Alrm>,
-    when: Cell<u32>,
+    when: Cell<u64>,
+    /// The ticks the alarm may fire late by
+    slack: Cell<u64>,
     armed: Cell<bool>,
     next: ListLink<'a, VirtualMuxAlarm<'a, Alrm>>,
     client: OptionalCell<&'a time::Client>,
@@ -25,6 +72,7 @@ impl<Alrm: Alarm> VirtualMuxAlarm<'a, Alrm> {
         VirtualMuxAlarm {
             mux: mux_alarm,
             when: Cell::new(0),
+            slack: Cell::new(0),
             armed: Cell::new(false),
             next: ListLink::empty(),
             client: OptionalCell::empty(),
@@ -36,6 +84,13 @@ impl<Alrm: Alarm> VirtualMuxAlarm<'a, Alrm> {
         self.when.set(0);
         self.armed.set(false);
         self.client.set(client);
+        self.mux.start();
+    }
+
+    /// Lets the alarm fire up to `slack` ticks late, so that it can share a
+    /// wakeup with other alarms.
+    pub fn set_slack(&self, slack: u32) {
+        self.slack.set(slack as u64);
     }
 }
 
@@ -43,20 +98,7 @@ impl<Alrm: Alarm> Time for VirtualMuxAlarm<'a, Alrm> {
     type Frequency = Alrm::Frequency;
 
     fn disable(&self) {
-        if !self.armed.get() {
-            return;
-        }
-
-        self.armed.set(false);
-
-        let enabled = self.mux.enabled.get() - 1;
-        self.mux.enabled.set(enabled);
-
-        // If there are not more enabled alarms, disable the underlying alarm
-        // completely.
-        if enabled == 0 {
-            self.mux.alarm.disable();
-        }
+        self.disable64();
     }
 
     fn is_armed(&self) -> bool {
@@ -66,36 +108,61 @@ impl<Alrm: Alarm> Time for VirtualMuxAlarm<'a, Alrm> {
 
 impl<Alrm: Alarm> Alarm for VirtualMuxAlarm<'a, Alrm> {
     fn now(&self) -> u32 {
-        self.mux.alarm.now()
+        self.mux.now64() as u32
     }
 
     fn set_alarm(&self, when: u32) {
-        let enabled = self.mux.enabled.get();
+        let now = self.mux.now64();
+        let passed = (now as u32).wrapping_sub(when) as u64;
+        let when = if passed <= cmp::min(now - self.mux.prev.get(), 1 << 31) {
+            now - passed
+        } else {
+            now + when.wrapping_sub(now as u32) as u64
+        };
+        self.set_alarm64(when);
+    }
 
-        if !self.is_armed() {
-            self.mux.enabled.set(enabled + 1);
-            self.armed.set(true);
-        }
+    fn get_alarm(&self) -> u32 {
+        self.when.get() as u32
+    }
+}
 
-        if enabled > 0 {
-            let cur_alarm = self.mux.alarm.get_alarm();
-            let now = self.now();
+impl<Alrm: Alarm> Time64 for VirtualMuxAlarm<'a, Alrm> {
+    type Frequency = Alrm::Frequency;
 
-            if cur_alarm.wrapping_sub(now) > when.wrapping_sub(now) {
-                self.mux.prev.set(self.mux.alarm.now());
-                self.mux.alarm.set_alarm(when);
-            }
-        } else {
-            self.mux.prev.set(self.mux.alarm.now());
-            self.mux.alarm.set_alarm(when);
-        }
+    fn now64(&self) -> u64 {
+        self.mux.now64()
+    }
+}
 
-        self.when.set(when);
+impl<Alrm: Alarm> Alarm64 for VirtualMuxAlarm<'a, Alrm> {
+    fn set_alarm64(&self, tics: u64) {
+        self.when.set(tics);
+        self.armed.set(true);
+        if !self.mux.firing.get() {
+            self.mux.arm();
+        }
     }
 
-    fn get_alarm(&self) -> u32 {
+    fn get_alarm64(&self) -> u64 {
         self.when.get()
     }
+
+    fn disable64(&self) {
+        if !self.armed.get() {
+            return;
+        }
+        self.armed.set(false);
+        // Move the hardware alarm away from this one, rather than wake for
+        // nothing
+        if !self.mux.firing.get() {
+            self.mux.arm();
+        }
+    }
+
+    fn is_armed64(&self) -> bool {
+        self.armed.get()
+    }
 }
 
 impl<Alrm: Alarm> time::Client for VirtualMuxAlarm<'a, Alrm> {
@@ -108,65 +175,114 @@ impl<Alrm: Alarm> time::Client for VirtualMuxAlarm<'a, Alrm> {
 
 pub struct MuxAlarm<'a, Alrm: Alarm> {
     virtual_alarms: List<'a, VirtualMuxAlarm<'a, Alrm>>,
-    enabled: Cell<usize>,
-    prev: Cell<u32>,
     alarm: &'a Alrm,
+    /// The counter of the hardware when it was last read, and the 64-bit
+    /// counter it was extended to
+    last_now: Cell<u32>,
+    now64: Cell<u64>,
+    /// The 64-bit counter when the mux last handled the hardware alarm
+    prev: Cell<u64>,
+    started: Cell<bool>,
+    /// Whether the mux is firing virtual alarms, and sets the hardware alarm
+    /// once it is done
+    firing: Cell<bool>,
 }
 
 impl<Alrm: Alarm> MuxAlarm<'a, Alrm> {
     pub const fn new(alarm: &'a Alrm) -> MuxAlarm<'a, Alrm> {
         MuxAlarm {
             virtual_alarms: List::new(),
-            enabled: Cell::new(0),
-            prev: Cell::new(0),
             alarm: alarm,
+            last_now: Cell::new(0),
+            now64: Cell::new(0),
+            prev: Cell::new(0),
+            started: Cell::new(false),
+            firing: Cell::new(false),
         }
     }
-}
 
-fn has_expired(alarm: u32, now: u32, prev: u32) -> bool {
-    now.wrapping_sub(prev) >= alarm.wrapping_sub(prev)
-}
+    /// The largest value of the hardware counter.
+    fn counter_mask(&self) -> u32 {
+        match self.alarm.counter_bits() {
+            bits if bits >= 32 => u32::max_value(),
+            bits => (1 << bits) - 1,
+        }
+    }
 
-impl<Alrm: Alarm> time::Client for MuxAlarm<'a, Alrm> {
-    fn fired(&self) {
-        let now = self.alarm.now();
+    /// Starts keeping track of wraps, from the first virtual alarm on.
+    fn start(&self) {
+        if self.started.get() {
+            return;
+        }
+        self.started.set(true);
+        self.last_now.set(self.alarm.now() & self.counter_mask());
+        self.arm();
+    }
 
-        // Capture this before the loop because it can change while checking
-        // each alarm. If a timer fires, it can immediately set a new timer
-        // by calling `VirtualMuxAlarm.set_alarm()` which can change `self.prev`
-        // to the current timer time.
-        let prev = self.prev.get();
+    /// Reads the hardware counter and adds the ticks since it was last read
+    /// to the 64-bit counter.
+    fn now64(&self) -> u64 {
+        let mask = self.counter_mask();
+        let now = self.alarm.now() & mask;
+        let ticks = now.wrapping_sub(self.last_now.get()) & mask;
+        self.last_now.set(now);
+        self.now64.set(self.now64.get() + ticks as u64);
+        self.now64.get()
+    }
 
-        // Check whether to fire each alarm. At this level, alarms are one-shot,
-        // so a repeating client will set it again in the fired() callback.
-        self.virtual_alarms
-            .iter()
-            .filter(|cur| cur.armed.get() && has_expired(cur.when.get(), now, prev))
-            .for_each(|cur| {
-                cur.armed.set(false);
-                self.enabled.set(self.enabled.get() - 1);
-                cur.fired();
-            });
-
-        // Find the soonest alarm client (if any) and set the "next" underlying
-        // alarm based on it.  This needs to happen after firing all expired
-        // alarms since those may have reset new alarms.
-        let next = self
+    /// Sets the hardware alarm for the earliest time a virtual alarm must
+    /// fire by, but at most 7/8 of a wrap ahead.
+    fn arm(&self) {
+        let wrap = self.counter_mask() as u64 + 1;
+        let mut now = self.now64();
+        let mut target = self
             .virtual_alarms
             .iter()
             .filter(|cur| cur.armed.get())
-            .min_by_key(|cur| cur.when.get().wrapping_sub(now));
-
-        self.prev.set(now);
-        // If there is an alarm to fire, set the underlying alarm to it
-        if let Some(valrm) = next {
-            self.alarm.set_alarm(valrm.when.get());
-            if has_expired(valrm.when.get(), self.alarm.now(), prev) {
-                self.fired();
+            .map(|cur| cur.when.get() + cur.slack.get())
+            .fold(now + wrap - (wrap >> GUARD_SHIFT), cmp::min);
+
+        // Set the alarm further ahead while the counter passes it before it
+        // is set
+        let mut margin = MIN_TICKS;
+        loop {
+            target = cmp::max(target, now + margin);
+            self.alarm.set_alarm(target as u32 & self.counter_mask());
+            now = self.now64();
+            if now < target {
+                break;
+            }
+            margin *= 2;
+        }
+    }
+}
+
+impl<Alrm: Alarm> time::Client for MuxAlarm<'a, Alrm> {
+    fn fired(&self) {
+        // Virtual alarms set in the callbacks of the loop are handled in it
+        self.firing.set(true);
+        loop {
+            let now = self.now64();
+            let mut fired = false;
+
+            // Check whether to fire each alarm. At this level, alarms are
+            // one-shot, so a repeating client will set it again in the
+            // fired() callback.
+            self.virtual_alarms
+                .iter()
+                .filter(|cur| cur.armed.get() && cur.when.get() <= now)
+                .for_each(|cur| {
+                    cur.armed.set(false);
+                    fired = true;
+                    cur.fired();
+                });
+
+            self.prev.set(now);
+            if !fired {
+                break;
             }
-        } else {
-            self.alarm.disable();
         }
+        self.firing.set(false);
+        self.arm();
     }
 }
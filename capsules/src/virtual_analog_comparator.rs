@@ -0,0 +1,375 @@
+//! Virtualize the analog comparator interface to enable multiple users of the
+//! analog comparators of a chip.
+//!
+//! Each `VirtualAnalogComparator` enables interrupts on the channels and
+//! windows it needs, and only receives the `fired` and `window_fired`
+//! callbacks for those. A channel keeps comparing until none of the clients
+//! that started it need it anymore. As a channel has a single interrupt
+//! condition, a client can't start it with a different condition than the one
+//! it is already running with.
+//!
+//! Channels are identified by their index in the `channels` array passed to
+//! the mux, which has to match the channel number the chip passes to `fired`.
+//!
+//! Usage
+//! -----
+//!
+//! ```
+//! let ac_channels = static_init!(
+//!     [&'static sam4l::acifc::AcChannel; 2],
+//!     [
+//!         &sam4l::acifc::CHANNEL_AC0,
+//!         &sam4l::acifc::CHANNEL_AC1,
+//!     ]
+//! );
+//! let ac_mux = static_init!(
+//!     MuxAnalogComparator<'static, sam4l::acifc::Acifc>,
+//!     MuxAnalogComparator::new(&sam4l::acifc::ACIFC, ac_channels)
+//! );
+//! sam4l::acifc::ACIFC.set_client(ac_mux);
+//!
+//! let virtual_ac = static_init!(
+//!     VirtualAnalogComparator<'static, sam4l::acifc::Acifc>,
+//!     VirtualAnalogComparator::new(ac_mux)
+//! );
+//! virtual_ac.set_client(client);
+//! ```
+
+use core::cell::Cell;
+use core::ptr;
+use kernel::common::cells::OptionalCell;
+use kernel::common::{List, ListLink, ListNode};
+use kernel::hil::analog_comparator::{
+    self, AnalogComparator, Hysteresis, InterruptMode, Reference, WindowInterruptMode,
+};
+use kernel::ReturnCode;
+
+/// The maximum number of channels the mux can handle.
+const MAX_CHANNELS: usize = 8;
+
+/// The maximum number of windows the mux can handle.
+const MAX_WINDOWS: usize = MAX_CHANNELS / 2;
+
+pub struct VirtualAnalogComparator<'a, A: AnalogComparator + 'a> {
+    mux: &'a MuxAnalogComparator<'a, A>,
+    next: ListLink<'a, VirtualAnalogComparator<'a, A>>,
+    client: OptionalCell<&'a analog_comparator::Client>,
+    /// Bit array of the channels this client enabled interrupts on
+    interrupts: Cell<u32>,
+    /// Bit array of the windows this client enabled interrupts on
+    window_interrupts: Cell<u32>,
+    /// Bit array of the channels this client started continuous measurements on
+    continuous: Cell<u32>,
+    /// Bit array of the channels this client enabled in sleep
+    in_sleep: Cell<u32>,
+}
+
+impl<A: AnalogComparator> ListNode<'a, VirtualAnalogComparator<'a, A>>
+    for VirtualAnalogComparator<'a, A>
+{
+    fn next(&self) -> &'a ListLink<VirtualAnalogComparator<'a, A>> {
+        &self.next
+    }
+}
+
+impl<A: AnalogComparator> VirtualAnalogComparator<'a, A> {
+    pub fn new(mux: &'a MuxAnalogComparator<'a, A>) -> VirtualAnalogComparator<'a, A> {
+        VirtualAnalogComparator {
+            mux: mux,
+            next: ListLink::empty(),
+            client: OptionalCell::empty(),
+            interrupts: Cell::new(0),
+            window_interrupts: Cell::new(0),
+            continuous: Cell::new(0),
+            in_sleep: Cell::new(0),
+        }
+    }
+
+    pub fn set_client(&'a self, client: &'a analog_comparator::Client) {
+        self.mux.devices.push_head(self);
+        self.client.set(client);
+    }
+
+    // Start interrupts on a channel with `mode`, or join the clients that
+    // already did. `start` starts the channel in hardware.
+    fn enable_interrupts<F>(
+        &self,
+        channel: &A::Channel,
+        mode: InterruptMode,
+        start: F,
+    ) -> ReturnCode
+    where
+        F: FnOnce() -> ReturnCode,
+    {
+        let index = match self.mux.index(channel) {
+            Some(index) => index,
+            None => return ReturnCode::EINVAL,
+        };
+        let bit = 1 << index;
+        let running = self
+            .mux
+            .any_other_device(self, |device| device.interrupts.get() & bit != 0);
+        if running && self.mux.modes.get()[index] != mode {
+            return ReturnCode::EBUSY;
+        }
+
+        let result = start();
+        if result == ReturnCode::SUCCESS {
+            let mut modes = self.mux.modes.get();
+            modes[index] = mode;
+            self.mux.modes.set(modes);
+            self.interrupts.set(self.interrupts.get() | bit);
+        }
+        result
+    }
+}
+
+impl<A: AnalogComparator> AnalogComparator for VirtualAnalogComparator<'a, A> {
+    type Channel = A::Channel;
+
+    fn comparison(&self, channel: &Self::Channel) -> bool {
+        self.mux.analog_comparator.comparison(channel)
+    }
+
+    fn window_comparison(&self, window: usize) -> bool {
+        self.mux.analog_comparator.window_comparison(window)
+    }
+
+    fn start_comparing(&self, channel: &Self::Channel, mode: InterruptMode) -> ReturnCode {
+        self.enable_interrupts(channel, mode, || {
+            self.mux.analog_comparator.start_comparing(channel, mode)
+        })
+    }
+
+    /// Only stops the channel in hardware once no other client has interrupts
+    /// enabled on it.
+    fn stop_comparing(&self, channel: &Self::Channel) -> ReturnCode {
+        let index = match self.mux.index(channel) {
+            Some(index) => index,
+            None => return ReturnCode::EINVAL,
+        };
+        let bit = 1 << index;
+        self.interrupts.set(self.interrupts.get() & !bit);
+        if self
+            .mux
+            .any_device(|device| device.interrupts.get() & bit != 0)
+        {
+            return ReturnCode::SUCCESS;
+        }
+        self.mux.analog_comparator.stop_comparing(channel)
+    }
+
+    fn start_continuous(&self, channel: &Self::Channel) -> ReturnCode {
+        let index = match self.mux.index(channel) {
+            Some(index) => index,
+            None => return ReturnCode::EINVAL,
+        };
+        let result = self.mux.analog_comparator.start_continuous(channel);
+        if result == ReturnCode::SUCCESS {
+            self.continuous.set(self.continuous.get() | (1 << index));
+        }
+        result
+    }
+
+    fn start_event_only(&self, channel: &Self::Channel) -> ReturnCode {
+        self.enable_interrupts(channel, InterruptMode::Toggle, || {
+            self.mux.analog_comparator.start_event_only(channel)
+        })
+    }
+
+    /// Only stops the channel in hardware once no other client uses it. If
+    /// other clients only measure continuously, the interrupts of this client
+    /// are disabled.
+    fn stop(&self, channel: &Self::Channel) -> ReturnCode {
+        let index = match self.mux.index(channel) {
+            Some(index) => index,
+            None => return ReturnCode::EINVAL,
+        };
+        let bit = 1 << index;
+        let had_interrupts = self.interrupts.get() & bit != 0;
+        self.interrupts.set(self.interrupts.get() & !bit);
+        self.continuous.set(self.continuous.get() & !bit);
+
+        if self
+            .mux
+            .any_device(|device| device.interrupts.get() & bit != 0)
+        {
+            ReturnCode::SUCCESS
+        } else if self
+            .mux
+            .any_device(|device| device.continuous.get() & bit != 0)
+        {
+            if had_interrupts {
+                self.mux.analog_comparator.stop_comparing(channel)
+            } else {
+                ReturnCode::SUCCESS
+            }
+        } else {
+            self.mux.analog_comparator.stop(channel)
+        }
+    }
+
+    fn enable_in_sleep(&self, channel: &Self::Channel) -> ReturnCode {
+        let index = match self.mux.index(channel) {
+            Some(index) => index,
+            None => return ReturnCode::EINVAL,
+        };
+        let result = self.mux.analog_comparator.enable_in_sleep(channel);
+        if result == ReturnCode::SUCCESS {
+            self.in_sleep.set(self.in_sleep.get() | (1 << index));
+        }
+        result
+    }
+
+    /// Only disables the channel in sleep once no other client needs it there.
+    fn disable_in_sleep(&self, channel: &Self::Channel) -> ReturnCode {
+        let index = match self.mux.index(channel) {
+            Some(index) => index,
+            None => return ReturnCode::EINVAL,
+        };
+        let bit = 1 << index;
+        self.in_sleep.set(self.in_sleep.get() & !bit);
+        if self
+            .mux
+            .any_device(|device| device.in_sleep.get() & bit != 0)
+        {
+            return ReturnCode::SUCCESS;
+        }
+        self.mux.analog_comparator.disable_in_sleep(channel)
+    }
+
+    fn set_reference(&self, channel: &Self::Channel, reference: Reference) -> ReturnCode {
+        self.mux.analog_comparator.set_reference(channel, reference)
+    }
+
+    fn set_hysteresis(&self, channel: &Self::Channel, level: Hysteresis) -> ReturnCode {
+        self.mux.analog_comparator.set_hysteresis(channel, level)
+    }
+
+    fn enable_window_interrupts(&self, window: usize, mode: WindowInterruptMode) -> ReturnCode {
+        if window >= MAX_WINDOWS {
+            return ReturnCode::EINVAL;
+        }
+        let bit = 1 << window;
+        let running = self
+            .mux
+            .any_other_device(self, |device| device.window_interrupts.get() & bit != 0);
+        if running && self.mux.window_modes.get()[window] != mode {
+            return ReturnCode::EBUSY;
+        }
+
+        let result = self
+            .mux
+            .analog_comparator
+            .enable_window_interrupts(window, mode);
+        if result == ReturnCode::SUCCESS {
+            let mut modes = self.mux.window_modes.get();
+            modes[window] = mode;
+            self.mux.window_modes.set(modes);
+            self.window_interrupts
+                .set(self.window_interrupts.get() | bit);
+        }
+        result
+    }
+
+    /// Only disables the window interrupts in hardware once no other client
+    /// has them enabled.
+    fn disable_window_interrupts(&self, window: usize) -> ReturnCode {
+        if window >= MAX_WINDOWS {
+            return ReturnCode::EINVAL;
+        }
+        let bit = 1 << window;
+        self.window_interrupts
+            .set(self.window_interrupts.get() & !bit);
+        if self
+            .mux
+            .any_device(|device| device.window_interrupts.get() & bit != 0)
+        {
+            return ReturnCode::SUCCESS;
+        }
+        self.mux
+            .analog_comparator
+            .disable_window_interrupts(window)
+    }
+}
+
+// MuxAnalogComparator
+
+pub struct MuxAnalogComparator<'a, A: AnalogComparator + 'a> {
+    analog_comparator: &'a A,
+    channels: &'a [&'a A::Channel],
+    devices: List<'a, VirtualAnalogComparator<'a, A>>,
+    /// The interrupt condition each channel is running with
+    modes: Cell<[InterruptMode; MAX_CHANNELS]>,
+    /// The interrupt condition each window is running with
+    window_modes: Cell<[WindowInterruptMode; MAX_WINDOWS]>,
+}
+
+impl<A: AnalogComparator> MuxAnalogComparator<'a, A> {
+    pub const fn new(
+        analog_comparator: &'a A,
+        channels: &'a [&'a A::Channel],
+    ) -> MuxAnalogComparator<'a, A> {
+        MuxAnalogComparator {
+            analog_comparator: analog_comparator,
+            channels: channels,
+            devices: List::new(),
+            modes: Cell::new([InterruptMode::VpGtVn; MAX_CHANNELS]),
+            window_modes: Cell::new([WindowInterruptMode::Entering; MAX_WINDOWS]),
+        }
+    }
+
+    // The index of `channel` in `channels`, if the mux can handle it
+    fn index(&self, channel: &A::Channel) -> Option<usize> {
+        self.channels
+            .iter()
+            .position(|chan| ptr::eq(*chan, channel))
+            .filter(|index| *index < MAX_CHANNELS)
+    }
+
+    fn any_device<F>(&self, f: F) -> bool
+    where
+        F: Fn(&VirtualAnalogComparator<A>) -> bool,
+    {
+        self.devices.iter().any(|device| f(device))
+    }
+
+    fn any_other_device<F>(&self, this: &VirtualAnalogComparator<'a, A>, f: F) -> bool
+    where
+        F: Fn(&VirtualAnalogComparator<A>) -> bool,
+    {
+        self.devices
+            .iter()
+            .any(|device| !ptr::eq(device, this) && f(device))
+    }
+}
+
+impl<A: AnalogComparator> analog_comparator::Client for MuxAnalogComparator<'a, A> {
+    /// Pass the interrupt on to the clients that enabled interrupts on
+    /// `channel`
+    fn fired(&self, channel: usize) {
+        if channel >= MAX_CHANNELS {
+            return;
+        }
+        self.devices
+            .iter()
+            .filter(|device| device.interrupts.get() & (1 << channel) != 0)
+            .for_each(|device| {
+                device.client.map(|client| client.fired(channel));
+            });
+    }
+
+    /// Pass the interrupt on to the clients that enabled interrupts on
+    /// `window`
+    fn window_fired(&self, window: usize) {
+        if window >= MAX_WINDOWS {
+            return;
+        }
+        self.devices
+            .iter()
+            .filter(|device| device.window_interrupts.get() & (1 << window) != 0)
+            .for_each(|device| {
+                device.client.map(|client| client.window_fired(window));
+            });
+    }
+}
@@ -0,0 +1,284 @@
+//! Virtualize a pool of DMA channels.
+//!
+//! `MuxDma` shares a few hardware DMA channels among more drivers than there
+//! are channels. Each driver uses a `VirtualDmaChannel`, which gets a free
+//! hardware channel when it starts a transfer and gives it back when the
+//! driver disables it. Transfers that find no free channel wait until one is
+//! given back.
+//!
+//! Drivers that keep a transfer running for a long time, like a UART that
+//! waits for input, hold their channel all that time, so they are better off
+//! with a dedicated channel.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let dma_mux = static_init!(
+//!     capsules::virtual_dma::MuxDma<'static, sam4l::dma::DMAChannel>,
+//!     capsules::virtual_dma::MuxDma::new(&sam4l::dma::DMA_CHANNELS[14..16])
+//! );
+//! for channel in sam4l::dma::DMA_CHANNELS[14..16].iter() {
+//!     channel.set_client(dma_mux);
+//! }
+//!
+//! let spi_rx_dma = static_init!(
+//!     capsules::virtual_dma::VirtualDmaChannel<'static, sam4l::dma::DMAChannel>,
+//!     capsules::virtual_dma::VirtualDmaChannel::new(dma_mux)
+//! );
+//! spi_rx_dma.setup();
+//! spi_rx_dma.set_client(&sam4l::spi::SPI);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::{List, ListLink, ListNode};
+use kernel::hil::dma::{self, DmaChannel};
+
+pub struct MuxDma<'a, C: DmaChannel + 'a> {
+    channels: &'a [C],
+    users: List<'a, VirtualDmaChannel<'a, C>>,
+}
+
+impl<C: DmaChannel> MuxDma<'a, C> {
+    pub const fn new(channels: &'a [C]) -> MuxDma<'a, C> {
+        MuxDma {
+            channels: channels,
+            users: List::new(),
+        }
+    }
+
+    /// Index of a hardware channel that no user holds.
+    fn free_channel(&self) -> Option<usize> {
+        (0..self.channels.len()).find(|&index| {
+            !self
+                .users
+                .iter()
+                .any(|user| user.channel.map_or(false, |&mut held| held == index))
+        })
+    }
+
+    /// Start waiting transfers on free channels.
+    fn do_next_op(&self) {
+        while let Some(user) = self
+            .users
+            .iter()
+            .find(|user| user.channel.is_none() && user.operation.get().is_some())
+        {
+            match self.free_channel() {
+                Some(index) => {
+                    user.channel.set(index);
+                    user.start(&self.channels[index]);
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+impl<C: DmaChannel> dma::Client<C::Peripheral> for MuxDma<'a, C>
+where
+    C::Peripheral: PartialEq,
+{
+    fn transfer_done(&self, peripheral: C::Peripheral) {
+        self.users
+            .iter()
+            .find(|user| {
+                user.channel.is_some() && user.peripheral.map_or(false, |&mut p| p == peripheral)
+            }).map(|user| user.client.map(|client| client.transfer_done(peripheral)));
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Direction {
+    ToPeripheral,
+    FromPeripheral,
+}
+
+pub struct VirtualDmaChannel<'a, C: DmaChannel + 'a> {
+    mux: &'a MuxDma<'a, C>,
+    /// The hardware channel this user holds.
+    channel: OptionalCell<usize>,
+    /// The transfer waiting for a hardware channel.
+    operation: Cell<Option<(Direction, usize)>>,
+    /// Whether the transfer starts as soon as it is armed, rather than with
+    /// a later `start_transfer()`.
+    start_pending: Cell<bool>,
+    peripheral: OptionalCell<C::Peripheral>,
+    buffer: TakeCell<'static, [u8]>,
+    width: Cell<dma::Width>,
+    enabled: Cell<bool>,
+    next: ListLink<'a, VirtualDmaChannel<'a, C>>,
+    client: OptionalCell<&'a dma::Client<C::Peripheral>>,
+}
+
+impl<C: DmaChannel> VirtualDmaChannel<'a, C> {
+    pub fn new(mux: &'a MuxDma<'a, C>) -> VirtualDmaChannel<'a, C> {
+        VirtualDmaChannel {
+            mux: mux,
+            channel: OptionalCell::empty(),
+            operation: Cell::new(None),
+            start_pending: Cell::new(false),
+            peripheral: OptionalCell::empty(),
+            buffer: TakeCell::empty(),
+            width: Cell::new(dma::Width::Width8Bit),
+            enabled: Cell::new(false),
+            next: ListLink::empty(),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Add the channel to the users of the mux. Must be called once before
+    /// the channel is used.
+    pub fn setup(&'a self) {
+        self.mux.users.push_head(self);
+    }
+
+    fn hardware(&self) -> Option<&'a C> {
+        let channels = self.mux.channels;
+        self.channel.map(|index| &channels[*index])
+    }
+
+    /// Arm the waiting transfer on `hardware`, and start it unless the
+    /// driver starts it later.
+    fn start(&self, hardware: &C) {
+        self.operation.take().map(|(direction, len)| {
+            self.buffer.take().map(|buffer| {
+                self.peripheral.map(move |peripheral| {
+                    hardware.set_width(self.width.get());
+                    hardware.enable();
+                    match direction {
+                        Direction::ToPeripheral => {
+                            hardware.prepare_transfer_to_peripheral(*peripheral, buffer, len)
+                        }
+                        Direction::FromPeripheral => {
+                            hardware.prepare_transfer_from_peripheral(*peripheral, buffer, len)
+                        }
+                    }
+                });
+            });
+        });
+        if self.start_pending.replace(false) {
+            hardware.start_transfer();
+        }
+    }
+
+    fn transfer(
+        &self,
+        direction: Direction,
+        peripheral: C::Peripheral,
+        buffer: &'static mut [u8],
+        len: usize,
+        start: bool,
+    ) {
+        self.peripheral.set(peripheral);
+        self.buffer.replace(buffer);
+        self.operation.set(Some((direction, len)));
+        self.start_pending.set(start);
+        match self.hardware() {
+            Some(hardware) => self.start(hardware),
+            None => self.mux.do_next_op(),
+        }
+    }
+}
+
+impl<C: DmaChannel> ListNode<'a, VirtualDmaChannel<'a, C>> for VirtualDmaChannel<'a, C> {
+    fn next(&'a self) -> &'a ListLink<'a, VirtualDmaChannel<'a, C>> {
+        &self.next
+    }
+}
+
+impl<C: DmaChannel> DmaChannel for VirtualDmaChannel<'a, C> {
+    type Peripheral = C::Peripheral;
+
+    fn set_client(&self, client: &'static dma::Client<C::Peripheral>) {
+        self.client.set(client);
+    }
+
+    fn set_width(&self, width: dma::Width) {
+        self.width.set(width);
+    }
+
+    fn enable(&self) {
+        self.enabled.set(true);
+    }
+
+    /// Gives the hardware channel back to the mux.
+    fn disable(&self) {
+        self.enabled.set(false);
+        self.hardware().map(|hardware| hardware.disable());
+        if self.channel.take().is_some() {
+            self.mux.do_next_op();
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled.get()
+    }
+
+    fn transfer_to_peripheral(
+        &self,
+        peripheral: C::Peripheral,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) {
+        self.transfer(Direction::ToPeripheral, peripheral, buffer, len, true);
+    }
+
+    fn transfer_from_peripheral(
+        &self,
+        peripheral: C::Peripheral,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) {
+        self.transfer(Direction::FromPeripheral, peripheral, buffer, len, true);
+    }
+
+    fn prepare_transfer_to_peripheral(
+        &self,
+        peripheral: C::Peripheral,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) {
+        self.transfer(Direction::ToPeripheral, peripheral, buffer, len, false);
+    }
+
+    fn prepare_transfer_from_peripheral(
+        &self,
+        peripheral: C::Peripheral,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) {
+        self.transfer(Direction::FromPeripheral, peripheral, buffer, len, false);
+    }
+
+    /// A transfer still waiting for a hardware channel starts once it gets
+    /// one.
+    fn start_transfer(&self) {
+        if self.operation.get().is_some() {
+            self.start_pending.set(true);
+        } else {
+            self.hardware().map(|hardware| hardware.start_transfer());
+        }
+    }
+
+    fn abort_transfer(&self) -> Option<&'static mut [u8]> {
+        self.start_pending.set(false);
+        match self.operation.take() {
+            // The transfer never started.
+            Some(_) => self.buffer.take(),
+            None => self
+                .hardware()
+                .map_or(None, |hardware| hardware.abort_transfer()),
+        }
+    }
+
+    fn transfer_counter(&self) -> usize {
+        match self.operation.get() {
+            Some((_, len)) => len,
+            None => self
+                .hardware()
+                .map_or(0, |hardware| hardware.transfer_counter()),
+        }
+    }
+}
@@ -2,6 +2,10 @@
 //!
 //! `MuxI2C` provides shared access to a single I2C Master Bus for multiple
 //! users. `I2CDevice` provides access to a specific I2C address.
+//!
+//! Errors are passed on to the device whose operation failed. If a slave
+//! held the bus low, the mux also tries to recover the bus before it starts
+//! the next operation.
 
 use core::cell::Cell;
 use kernel::common::cells::{OptionalCell, TakeCell};
@@ -17,6 +21,11 @@ pub struct MuxI2C<'a> {
 
 impl I2CHwMasterClient for MuxI2C<'a> {
     fn command_complete(&self, buffer: &'static mut [u8], error: Error) {
+        if error == Error::BusStuck {
+            // Free the bus for the next operation, the client still learns
+            // that its own failed
+            self.i2c.recover_bus();
+        }
         self.inflight.take().map(move |device| {
             device.command_complete(buffer, error);
         });
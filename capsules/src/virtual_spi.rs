@@ -1,4 +1,10 @@
 //! Virtualize a SPI master bus to enable multiple users of the SPI bus.
+//!
+//! Every `VirtualSpiMasterDevice` has its own chip select and bus settings,
+//! which the mux applies before each of its operations. Devices can also
+//! transfer several buffers as one transaction with `read_write_vectored`,
+//! for example a command header and a payload that live in separate
+//! buffers.
 
 use core::cell::Cell;
 use kernel::common::cells::{OptionalCell, TakeCell};
@@ -8,6 +14,10 @@ use kernel::ReturnCode;
 
 /// The Mux struct manages multiple Spi clients. Each client may have
 /// at most one outstanding Spi request.
+///
+/// Each client keeps its own chip select, polarity, phase and rate, and the
+/// mux applies them to the controller before every operation of the client,
+/// so clients that share a bus can use different settings.
 pub struct MuxSpiMaster<'a, Spi: hil::spi::SpiMaster> {
     spi: &'a Spi,
     devices: List<'a, VirtualSpiMasterDevice<'a, Spi>>,
@@ -22,8 +32,31 @@ impl<Spi: hil::spi::SpiMaster> hil::spi::SpiMasterClient for MuxSpiMaster<'a, Sp
         len: usize,
     ) {
         self.inflight.take().map(move |device| {
-            self.do_next_op();
-            device.read_write_done(write_buffer, read_buffer, len);
+            if device.segments.is_none() {
+                self.do_next_op();
+                device.read_write_done(write_buffer, read_buffer, len);
+                return;
+            }
+
+            // Put the buffers back into the segment just transferred
+            let index = device.segment.get();
+            let more = device.segments.map_or(false, move |segments| {
+                let segment = &mut segments[index];
+                segment.write_buffer = Some(write_buffer);
+                segment.read_buffer = read_buffer;
+                segment.len = len;
+                index + 1 < segments.len()
+            });
+
+            if more {
+                self.inflight.set(device);
+                device.segment.set(index + 1);
+                self.start_segment(device);
+            } else {
+                let segments = device.segments.take();
+                self.do_next_op();
+                segments.map(|segments| device.read_write_vectored_done(segments));
+            }
         });
     }
 }
@@ -44,18 +77,11 @@ impl<Spi: hil::spi::SpiMaster> MuxSpiMaster<'a, Spi> {
                 .iter()
                 .find(|node| node.operation.get() != Op::Idle);
             mnode.map(|node| {
-                self.spi.specify_chip_select(node.chip_select.get());
+                self.apply_settings(node);
                 let op = node.operation.get();
                 // Need to set idle here in case callback changes state
                 node.operation.set(Op::Idle);
                 match op {
-                    Op::Configure(cpol, cpal, rate) => {
-                        // The `chip_select` type will be correct based on
-                        // what implemented `SpiMaster`.
-                        self.spi.set_clock(cpol);
-                        self.spi.set_phase(cpal);
-                        self.spi.set_rate(rate);
-                    }
                     Op::ReadWriteBytes(len) => {
                         // Only async operations want to block by setting
                         // the devices as inflight.
@@ -65,37 +91,67 @@ impl<Spi: hil::spi::SpiMaster> MuxSpiMaster<'a, Spi> {
                             self.spi.read_write_bytes(txbuffer, rxbuffer, len);
                         });
                     }
-                    Op::SetPolarity(pol) => {
-                        self.spi.set_clock(pol);
-                    }
-                    Op::SetPhase(pal) => {
-                        self.spi.set_phase(pal);
-                    }
-                    Op::SetRate(rate) => {
-                        self.spi.set_rate(rate);
+                    Op::ReadWriteVectored => {
+                        self.inflight.set(node);
+                        node.segment.set(0);
+                        self.spi.hold_low();
+                        self.start_segment(node);
                     }
                     Op::Idle => {} // Can't get here...
                 }
             });
         }
     }
+
+    /// Selects `device` and configures the controller the way it wants, as
+    /// the device that used it last may have configured it differently.
+    fn apply_settings(&self, device: &VirtualSpiMasterDevice<'a, Spi>) {
+        // The `chip_select` type will be correct based on what implemented
+        // `SpiMaster`.
+        self.spi.specify_chip_select(device.chip_select.get());
+        self.spi.set_clock(device.polarity.get());
+        self.spi.set_phase(device.phase.get());
+        self.spi.set_rate(device.rate.get());
+    }
+
+    /// Starts the current segment of the scatter-gather operation of
+    /// `device`. The chip select is released after the last one.
+    fn start_segment(&self, device: &VirtualSpiMasterDevice<'a, Spi>) {
+        let index = device.segment.get();
+        device.segments.map(|segments| {
+            if index + 1 == segments.len() {
+                self.spi.release_low();
+            }
+            let segment = &mut segments[index];
+            segment.write_buffer.take().map(|write_buffer| {
+                let read_buffer = segment.read_buffer.take();
+                self.spi
+                    .read_write_bytes(write_buffer, read_buffer, segment.len);
+            });
+        });
+    }
 }
 
 #[derive(Copy, Clone, PartialEq)]
 enum Op {
     Idle,
-    Configure(hil::spi::ClockPolarity, hil::spi::ClockPhase, u32),
     ReadWriteBytes(usize),
-    SetPolarity(hil::spi::ClockPolarity),
-    SetPhase(hil::spi::ClockPhase),
-    SetRate(u32),
+    ReadWriteVectored,
 }
 
+/// A client of the SPI bus with its own chip select and settings. Until it
+/// is configured, a device uses clock polarity and phase 0 at 1 MHz.
 pub struct VirtualSpiMasterDevice<'a, Spi: hil::spi::SpiMaster> {
     mux: &'a MuxSpiMaster<'a, Spi>,
     chip_select: Cell<Spi::ChipSelect>,
+    polarity: Cell<hil::spi::ClockPolarity>,
+    phase: Cell<hil::spi::ClockPhase>,
+    rate: Cell<u32>,
     txbuffer: TakeCell<'static, [u8]>,
     rxbuffer: TakeCell<'static, [u8]>,
+    segments: TakeCell<'static, [hil::spi::SpiSegment]>,
+    /// The segment of `segments` being transferred
+    segment: Cell<usize>,
     operation: Cell<Op>,
     next: ListLink<'a, VirtualSpiMasterDevice<'a, Spi>>,
     client: OptionalCell<&'a hil::spi::SpiMasterClient>,
@@ -109,8 +165,13 @@ impl<Spi: hil::spi::SpiMaster> VirtualSpiMasterDevice<'a, Spi> {
         VirtualSpiMasterDevice {
             mux: mux,
             chip_select: Cell::new(chip_select),
+            polarity: Cell::new(hil::spi::ClockPolarity::IdleLow),
+            phase: Cell::new(hil::spi::ClockPhase::SampleLeading),
+            rate: Cell::new(1_000_000),
             txbuffer: TakeCell::empty(),
             rxbuffer: TakeCell::empty(),
+            segments: TakeCell::empty(),
+            segment: Cell::new(0),
             operation: Cell::new(Op::Idle),
             next: ListLink::empty(),
             client: OptionalCell::empty(),
@@ -134,6 +195,12 @@ impl<Spi: hil::spi::SpiMaster> hil::spi::SpiMasterClient for VirtualSpiMasterDev
             client.read_write_done(write_buffer, read_buffer, len);
         });
     }
+
+    fn read_write_vectored_done(&self, segments: &'static mut [hil::spi::SpiSegment]) {
+        self.client.map(move |client| {
+            client.read_write_vectored_done(segments);
+        });
+    }
 }
 
 impl<Spi: hil::spi::SpiMaster> ListNode<'a, VirtualSpiMasterDevice<'a, Spi>>
@@ -146,8 +213,9 @@ impl<Spi: hil::spi::SpiMaster> ListNode<'a, VirtualSpiMasterDevice<'a, Spi>>
 
 impl<Spi: hil::spi::SpiMaster> hil::spi::SpiMasterDevice for VirtualSpiMasterDevice<'a, Spi> {
     fn configure(&self, cpol: hil::spi::ClockPolarity, cpal: hil::spi::ClockPhase, rate: u32) {
-        self.operation.set(Op::Configure(cpol, cpal, rate));
-        self.mux.do_next_op();
+        self.polarity.set(cpol);
+        self.phase.set(cpal);
+        self.rate.set(rate);
     }
 
     fn read_write_bytes(
@@ -163,31 +231,41 @@ impl<Spi: hil::spi::SpiMaster> hil::spi::SpiMasterDevice for VirtualSpiMasterDev
         ReturnCode::SUCCESS
     }
 
-    fn set_polarity(&self, cpol: hil::spi::ClockPolarity) {
-        self.operation.set(Op::SetPolarity(cpol));
+    fn read_write_vectored(&self, segments: &'static mut [hil::spi::SpiSegment]) -> ReturnCode {
+        if self.segments.is_some() || self.operation.get() != Op::Idle {
+            return ReturnCode::EBUSY;
+        }
+        if segments.is_empty() || segments.iter().any(|s| s.write_buffer.is_none()) {
+            return ReturnCode::EINVAL;
+        }
+        self.segments.replace(segments);
+        self.operation.set(Op::ReadWriteVectored);
         self.mux.do_next_op();
+        ReturnCode::SUCCESS
+    }
+
+    fn set_polarity(&self, cpol: hil::spi::ClockPolarity) {
+        self.polarity.set(cpol);
     }
 
     fn set_phase(&self, cpal: hil::spi::ClockPhase) {
-        self.operation.set(Op::SetPhase(cpal));
-        self.mux.do_next_op();
+        self.phase.set(cpal);
     }
 
     fn set_rate(&self, rate: u32) {
-        self.operation.set(Op::SetRate(rate));
-        self.mux.do_next_op();
+        self.rate.set(rate);
     }
 
     fn get_polarity(&self) -> hil::spi::ClockPolarity {
-        self.mux.spi.get_clock()
+        self.polarity.get()
     }
 
     fn get_phase(&self) -> hil::spi::ClockPhase {
-        self.mux.spi.get_phase()
+        self.phase.get()
     }
 
     fn get_rate(&self) -> u32 {
-        self.mux.spi.get_rate()
+        self.rate.get()
     }
 }
 
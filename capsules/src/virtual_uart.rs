@@ -10,6 +10,11 @@
 //! `UartMux` provides shared access to a single UART bus for multiple users.
 //! `UartDevice` provides access for a single client.
 //!
+//! A panic does not go through the mux. Once the kernel has stopped, the
+//! panic handler writes with `hil::uart::UARTEmergency`, which drops
+//! whatever transmission the mux has in flight, so the panic output is
+//! complete even if it cuts off a line of a client.
+//!
 //! Usage
 //! -----
 //!
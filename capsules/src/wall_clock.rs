@@ -0,0 +1,312 @@
+//! Provides userspace with the date and time of a real-time clock.
+//!
+//! Apps read the date and time, for example to timestamp data with a time
+//! that stays meaningful across reboots, set it, and set an alarm at a date
+//! and time. Each app has one alarm; the clock is set for the earliest of
+//! them.
+//!
+//! Readings are shared: apps that read while a reading is in progress get
+//! its result. Setting the date and time needs the clock to itself.
+//!
+//! Dates and times are passed packed in two numbers: the date as
+//! `year << 9 | month << 5 | day`, and the time as
+//! `hour << 12 | minute << 6 | second`.
+//!
+//! Usage
+//! -----
+//!
+//! You need a device that provides the `hil::time::WallClock` trait.
+//!
+//! ```rust
+//! let wall_clock = static_init!(
+//!     capsules::wall_clock::WallClockDriver<'static>,
+//!     capsules::wall_clock::WallClockDriver::new(
+//!         ds3231,
+//!         board_kernel.create_grant(&memory_allocation_capability)));
+//! hil::time::WallClock::set_client(ds3231, wall_clock);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::hil::time::{DateTime, WallClock, WallClockClient};
+use kernel::{AppId, Callback, Driver, Grant, ReturnCode};
+
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = 0x00010;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    Idle,
+    Read,
+    /// Setting the date and time for an app
+    Set,
+    /// Setting or disabling the alarm of the clock
+    Alarm,
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    alarm_callback: Option<Callback>,
+    /// Whether the app waits for a reading
+    pending: bool,
+    /// The seconds since 1970 of the alarm of the app, if set
+    alarm: Option<u64>,
+}
+
+pub struct WallClockDriver<'a> {
+    clock: &'a WallClock,
+    apps: Grant<App>,
+    operation: Cell<Operation>,
+    /// The app that sets the date and time
+    current: OptionalCell<AppId>,
+    /// The alarm of the clock, in seconds since 1970
+    armed: Cell<Option<u64>>,
+    /// Whether the alarm of the clock needs to be updated once it is idle
+    update_pending: Cell<bool>,
+}
+
+fn pack(date_time: DateTime) -> (usize, usize) {
+    let date = (date_time.year as usize) << 9
+        | (date_time.month as usize) << 5
+        | date_time.day as usize;
+    let time = (date_time.hour as usize) << 12
+        | (date_time.minute as usize) << 6
+        | date_time.second as usize;
+    (date, time)
+}
+
+fn unpack(date: usize, time: usize) -> DateTime {
+    DateTime {
+        year: (date >> 9) as u16,
+        month: (date >> 5 & 0xf) as u8,
+        day: (date & 0x1f) as u8,
+        hour: (time >> 12 & 0x1f) as u8,
+        minute: (time >> 6 & 0x3f) as u8,
+        second: (time & 0x3f) as u8,
+    }
+}
+
+impl WallClockDriver<'a> {
+    pub fn new(clock: &'a WallClock, grant: Grant<App>) -> WallClockDriver<'a> {
+        WallClockDriver {
+            clock: clock,
+            apps: grant,
+            operation: Cell::new(Operation::Idle),
+            current: OptionalCell::empty(),
+            armed: Cell::new(None),
+            update_pending: Cell::new(false),
+        }
+    }
+
+    fn read(&self, appid: AppId) -> ReturnCode {
+        self.apps
+            .enter(appid, |app, _| {
+                if app.pending {
+                    return ReturnCode::EALREADY;
+                }
+                match self.operation.get() {
+                    Operation::Read => {}
+                    Operation::Idle => {
+                        let rcode = self.clock.get_date_time();
+                        if rcode != ReturnCode::SUCCESS {
+                            return rcode;
+                        }
+                        self.operation.set(Operation::Read);
+                    }
+                    _ => return ReturnCode::EBUSY,
+                }
+                app.pending = true;
+                ReturnCode::SUCCESS
+            }).unwrap_or_else(|err| err.into())
+    }
+
+    fn set(&self, appid: AppId, date_time: DateTime) -> ReturnCode {
+        if !date_time.is_valid() {
+            return ReturnCode::EINVAL;
+        }
+        if self.operation.get() != Operation::Idle {
+            return ReturnCode::EBUSY;
+        }
+        let rcode = self.clock.set_date_time(date_time);
+        if rcode == ReturnCode::SUCCESS {
+            self.operation.set(Operation::Set);
+            self.current.set(appid);
+        }
+        rcode
+    }
+
+    fn set_app_alarm(&self, appid: AppId, alarm: Option<u64>) -> ReturnCode {
+        let rcode = self
+            .apps
+            .enter(appid, |app, _| {
+                if alarm.is_none() && app.alarm.is_none() {
+                    return ReturnCode::EALREADY;
+                }
+                app.alarm = alarm;
+                ReturnCode::SUCCESS
+            }).unwrap_or_else(|err| err.into());
+        if rcode == ReturnCode::SUCCESS {
+            self.update_alarm();
+        }
+        rcode
+    }
+
+    /// Sets the alarm of the clock for the earliest alarm of the apps, or
+    /// disables it, once the clock is idle.
+    fn update_alarm(&self) {
+        if self.operation.get() != Operation::Idle {
+            self.update_pending.set(true);
+            return;
+        }
+        self.update_pending.set(false);
+
+        let earliest: Cell<Option<u64>> = Cell::new(None);
+        self.apps.each(|app| {
+            if let Some(alarm) = app.alarm {
+                if earliest.get().map_or(true, |earliest| alarm < earliest) {
+                    earliest.set(Some(alarm));
+                }
+            }
+        });
+        let earliest = earliest.get();
+        if earliest == self.armed.get() {
+            return;
+        }
+
+        let rcode = match earliest {
+            Some(seconds) => self.clock.set_alarm(DateTime::from_seconds(seconds)),
+            None => self.clock.disable_alarm(),
+        };
+        if rcode == ReturnCode::SUCCESS {
+            self.armed.set(earliest);
+            self.operation.set(Operation::Alarm);
+        }
+    }
+
+    fn idle(&self) {
+        self.operation.set(Operation::Idle);
+        if self.update_pending.get() {
+            self.update_alarm();
+        }
+    }
+}
+
+impl WallClockClient for WallClockDriver<'a> {
+    fn date_time_read(&self, date_time: DateTime, result: ReturnCode) {
+        self.idle();
+        let (date, time) = pack(date_time);
+        self.apps.each(|app| {
+            if app.pending {
+                app.pending = false;
+                app.callback.map(|mut cb| {
+                    if result == ReturnCode::SUCCESS {
+                        cb.schedule(0, date, time)
+                    } else {
+                        cb.schedule(isize::from(result) as usize, 0, 0)
+                    }
+                });
+            }
+        });
+    }
+
+    fn set_done(&self, result: ReturnCode) {
+        let operation = self.operation.get();
+        if operation == Operation::Alarm && result != ReturnCode::SUCCESS {
+            // Try again with the next change of the alarms
+            self.armed.set(None);
+        }
+        self.idle();
+        if operation == Operation::Set {
+            self.current.take().map(|appid| {
+                let _ = self.apps.enter(appid, |app, _| {
+                    app.callback
+                        .map(|mut cb| cb.schedule(isize::from(result) as usize, 0, 0));
+                });
+            });
+        }
+    }
+
+    fn alarm(&self) {
+        let fired = match self.armed.get() {
+            Some(fired) => fired,
+            None => return,
+        };
+        self.armed.set(None);
+        let (date, time) = pack(DateTime::from_seconds(fired));
+        self.apps.each(|app| {
+            if app.alarm.map_or(false, |alarm| alarm <= fired) {
+                app.alarm = None;
+                app.alarm_callback.map(|mut cb| cb.schedule(date, time, 0));
+            }
+        });
+        self.update_alarm();
+    }
+}
+
+impl Driver for WallClockDriver<'a> {
+    /// Subscribe to the results of operations and to alarms.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Called when a reading or setting of the date and time of the
+    ///        app completes, with the result, and for a reading the packed
+    ///        date and time.
+    /// - `1`: Called with the packed date and time of the alarm of the app
+    ///        when it fires.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            1 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    app.alarm_callback = callback;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Read and set the date and time, and set alarms.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Read the date and time.
+    /// - `2`: Set the date and time to the packed date `data` and time
+    ///        `data2`.
+    /// - `3`: Set the alarm of the app to the packed date `data` and time
+    ///        `data2`, replacing any alarm of the app.
+    /// - `4`: Cancel the alarm of the app.
+    fn command(&self, command_num: usize, data: usize, data2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            1 => self.read(appid),
+
+            2 => self.set(appid, unpack(data, data2)),
+
+            3 => {
+                let date_time = unpack(data, data2);
+                if !date_time.is_valid() {
+                    return ReturnCode::EINVAL;
+                }
+                self.set_app_alarm(appid, Some(date_time.seconds()))
+            }
+
+            4 => self.set_app_alarm(appid, None),
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
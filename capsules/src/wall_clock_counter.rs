@@ -0,0 +1,178 @@
+//! Wall-clock time kept in software on the RTC counter of the chip.
+//!
+//! Chips such as the nRF52 and the SAM4L have no calendar, only an RTC
+//! counter, which the kernel uses for its alarms. This implements
+//! `hil::time::WallClock` on top of a virtual alarm of that counter, which
+//! extends it to 64 bits: it remembers the date and time it was set to with
+//! the 64-bit counter at that moment, and counts from there. It keeps the time only while the chip
+//! runs, so it reports EOFF until it is set after every reset; boards that
+//! need the time across power loss use an RTC chip with a backup battery,
+//! such as the DS3231, instead.
+//!
+//! The alarm of the clock is the alarm of the counter. Operations complete
+//! from a deferred call.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let wall_clock_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, nrf5x::rtc::Rtc>,
+//!     VirtualMuxAlarm::new(mux_alarm));
+//! let wall_clock = static_init!(
+//!     capsules::wall_clock_counter::WallClockCounter<
+//!         'static,
+//!         VirtualMuxAlarm<'static, nrf5x::rtc::Rtc>,
+//!     >,
+//!     capsules::wall_clock_counter::WallClockCounter::new(wall_clock_alarm));
+//! wall_clock_alarm.set_client(wall_clock);
+//! wall_clock.initialize();
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::common::deferred_call::{DeferredCall, DeferredCallClient};
+use kernel::hil::time::{self, Alarm64, DateTime, Frequency};
+use kernel::ReturnCode;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Pending {
+    Idle,
+    Read,
+    Set,
+}
+
+pub struct WallClockCounter<'a, A: Alarm64> {
+    alarm: &'a A,
+    /// The seconds since 1970 the clock was set to, and the counter it was
+    /// set at
+    base_seconds: Cell<Option<u64>>,
+    base_ticks: Cell<u64>,
+    /// The seconds since 1970 of the alarm, if set
+    alarm_seconds: Cell<Option<u64>>,
+    pending: Cell<Pending>,
+    deferred_call: DeferredCall,
+    client: OptionalCell<&'static time::WallClockClient>,
+}
+
+impl<A: Alarm64> WallClockCounter<'a, A> {
+    pub fn new(alarm: &'a A) -> WallClockCounter<'a, A> {
+        WallClockCounter {
+            alarm: alarm,
+            base_seconds: Cell::new(None),
+            base_ticks: Cell::new(0),
+            alarm_seconds: Cell::new(None),
+            pending: Cell::new(Pending::Idle),
+            deferred_call: DeferredCall::new(),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// The seconds since 1970, if the clock is set.
+    fn now_seconds(&self) -> Option<u64> {
+        self.base_seconds.get().map(|base| {
+            let ticks = self.alarm.now64() - self.base_ticks.get();
+            base + ticks / A::Frequency::frequency() as u64
+        })
+    }
+
+    /// Sets the alarm of the counter for the alarm of the clock.
+    fn arm(&self) {
+        match (self.alarm_seconds.get(), self.base_seconds.get()) {
+            (Some(alarm), Some(base)) => {
+                let seconds = alarm.saturating_sub(base);
+                self.alarm.set_alarm64(
+                    self.base_ticks.get() + seconds * A::Frequency::frequency() as u64,
+                );
+            }
+            _ => self.alarm.disable64(),
+        }
+    }
+
+    fn complete_later(&self, pending: Pending) -> ReturnCode {
+        if self.pending.get() != Pending::Idle {
+            return ReturnCode::EBUSY;
+        }
+        self.pending.set(pending);
+        self.deferred_call.set();
+        ReturnCode::SUCCESS
+    }
+}
+
+impl<A: Alarm64> WallClockCounter<'static, A> {
+    /// Registers the deferred call that completes operations. The clock can
+    /// be used once this returns SUCCESS.
+    pub fn initialize(&'static self) -> ReturnCode {
+        self.deferred_call.register(self)
+    }
+}
+
+impl<A: Alarm64> time::Client for WallClockCounter<'a, A> {
+    fn fired(&self) {
+        if self.alarm_seconds.get().is_some() {
+            self.alarm_seconds.set(None);
+            self.client.map(|client| client.alarm());
+        }
+    }
+}
+
+impl<A: Alarm64> DeferredCallClient for WallClockCounter<'a, A> {
+    fn handle_deferred_call(&self) {
+        let pending = self.pending.get();
+        self.pending.set(Pending::Idle);
+        self.client.map(|client| match pending {
+            Pending::Idle => {}
+            Pending::Read => match self.now_seconds() {
+                Some(now) => {
+                    client.date_time_read(DateTime::from_seconds(now), ReturnCode::SUCCESS)
+                }
+                None => client.date_time_read(DateTime::from_seconds(0), ReturnCode::EOFF),
+            },
+            Pending::Set => client.set_done(ReturnCode::SUCCESS),
+        });
+    }
+}
+
+impl<A: Alarm64> time::WallClock for WallClockCounter<'a, A> {
+    fn set_client(&self, client: &'static time::WallClockClient) {
+        self.client.set(client);
+    }
+
+    fn get_date_time(&self) -> ReturnCode {
+        self.complete_later(Pending::Read)
+    }
+
+    fn set_date_time(&self, date_time: DateTime) -> ReturnCode {
+        if !date_time.is_valid() {
+            return ReturnCode::EINVAL;
+        }
+        let rcode = self.complete_later(Pending::Set);
+        if rcode == ReturnCode::SUCCESS {
+            self.base_ticks.set(self.alarm.now64());
+            self.base_seconds.set(Some(date_time.seconds()));
+            self.arm();
+        }
+        rcode
+    }
+
+    fn set_alarm(&self, date_time: DateTime) -> ReturnCode {
+        if !date_time.is_valid() {
+            return ReturnCode::EINVAL;
+        }
+        let rcode = self.complete_later(Pending::Set);
+        if rcode == ReturnCode::SUCCESS {
+            self.alarm_seconds.set(Some(date_time.seconds()));
+            self.arm();
+        }
+        rcode
+    }
+
+    fn disable_alarm(&self) -> ReturnCode {
+        let rcode = self.complete_later(Pending::Set);
+        if rcode == ReturnCode::SUCCESS {
+            self.alarm_seconds.set(None);
+            self.alarm.disable64();
+        }
+        rcode
+    }
+}
@@ -0,0 +1,155 @@
+//! Driver for WS2812 ("NeoPixel") LED strips on the MOSI line of an SPI bus.
+//!
+//! <https://cdn-shop.adafruit.com/datasheets/WS2812B.pdf>
+//!
+//! WS2812 LEDs take their colors over a single wire, as pulses 1.25 us apart
+//! that are high for longer for a 1 than for a 0. Without a peripheral that
+//! makes such pulses, this driver shapes them on the data line of an SPI bus
+//! at 2.4 MHz: each bit of the colors is three SPI bits, `110` for a 1 and
+//! `100` for a 0, so each LED takes `BYTES_PER_LED` bytes, and the strip
+//! latches the colors after `RESET_BYTES` more of low data. The clock and the
+//! chip select are not used, but the SPI device needs a chip select of its
+//! own on a shared bus.
+//!
+//! The board provides the buffer the colors are encoded into, which sets the
+//! number of LEDs.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! static mut WS2812_BUF: [u8; 8 * capsules::ws2812_spi::BYTES_PER_LED
+//!     + capsules::ws2812_spi::RESET_BYTES] = [0; 8 * capsules::ws2812_spi::BYTES_PER_LED
+//!     + capsules::ws2812_spi::RESET_BYTES];
+//!
+//! let ws2812_spi = static_init!(
+//!     capsules::virtual_spi::VirtualSpiMasterDevice<'static, sam4l::spi::SpiHw>,
+//!     capsules::virtual_spi::VirtualSpiMasterDevice::new(mux_spi, 3)
+//! );
+//! let ws2812 = static_init!(
+//!     capsules::ws2812_spi::Ws2812Spi<
+//!         'static,
+//!         capsules::virtual_spi::VirtualSpiMasterDevice<'static, sam4l::spi::SpiHw>,
+//!     >,
+//!     capsules::ws2812_spi::Ws2812Spi::new(ws2812_spi, &mut WS2812_BUF)
+//! );
+//! ws2812_spi.set_client(ws2812);
+//! ```
+
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::led_strip;
+use kernel::hil::spi::{ClockPhase, ClockPolarity, SpiMasterClient, SpiMasterDevice};
+use kernel::ReturnCode;
+
+/// The bytes of the SPI buffer for each LED: three bytes of colors of eight
+/// bits, each three SPI bits.
+pub const BYTES_PER_LED: usize = 9;
+
+/// The low bytes at the end of the SPI buffer, for the 300 us the LEDs need
+/// to latch their colors.
+pub const RESET_BYTES: usize = 90;
+
+/// Three SPI bits are one bit of the colors, 1.25 us.
+const SPI_RATE: u32 = 2_400_000;
+
+pub struct Ws2812Spi<'a, S: SpiMasterDevice> {
+    spi: &'a S,
+    buffer: TakeCell<'static, [u8]>,
+    /// The colors being shown, kept for the client
+    colors: TakeCell<'static, [u8]>,
+    client: OptionalCell<&'static led_strip::Client>,
+    led_count: usize,
+}
+
+impl<S: SpiMasterDevice> Ws2812Spi<'a, S> {
+    pub fn new(spi: &'a S, buffer: &'static mut [u8]) -> Ws2812Spi<'a, S> {
+        let led_count = buffer.len().saturating_sub(RESET_BYTES) / BYTES_PER_LED;
+        Ws2812Spi {
+            spi: spi,
+            led_count: led_count,
+            buffer: TakeCell::new(buffer),
+            colors: TakeCell::empty(),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Encodes a byte of the colors as the three SPI bytes that shape it,
+    /// most significant bit first.
+    fn encode(byte: u8, out: &mut [u8]) {
+        let mut bits: u32 = 0;
+        for bit in 0..8 {
+            let pattern = if byte & (0x80 >> bit) != 0 {
+                0b110
+            } else {
+                0b100
+            };
+            bits = bits << 3 | pattern;
+        }
+        out[0] = (bits >> 16) as u8;
+        out[1] = (bits >> 8) as u8;
+        out[2] = bits as u8;
+    }
+}
+
+impl<S: SpiMasterDevice> led_strip::LedStrip for Ws2812Spi<'a, S> {
+    fn set_client(&self, client: &'static led_strip::Client) {
+        self.client.set(client);
+    }
+
+    fn led_count(&self) -> usize {
+        self.led_count
+    }
+
+    fn show(
+        &self,
+        colors: &'static mut [u8],
+        count: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        let buffer = match self.buffer.take() {
+            Some(buffer) => buffer,
+            None => return (ReturnCode::EBUSY, Some(colors)),
+        };
+        if count == 0 || count > self.led_count || colors.len() < count * 3 {
+            self.buffer.replace(buffer);
+            return (ReturnCode::EINVAL, Some(colors));
+        }
+
+        // The LEDs take green first, then red and blue
+        for led in 0..count {
+            let color = &colors[led * 3..led * 3 + 3];
+            let grb = [color[1], color[0], color[2]];
+            for (i, byte) in grb.iter().enumerate() {
+                let start = led * BYTES_PER_LED + i * 3;
+                Self::encode(*byte, &mut buffer[start..start + 3]);
+            }
+        }
+        let len = count * BYTES_PER_LED + RESET_BYTES;
+        for byte in buffer[count * BYTES_PER_LED..len].iter_mut() {
+            *byte = 0;
+        }
+
+        self.spi
+            .configure(ClockPolarity::IdleLow, ClockPhase::SampleLeading, SPI_RATE);
+        self.colors.replace(colors);
+        let rcode = self.spi.read_write_bytes(buffer, None, len);
+        if rcode != ReturnCode::SUCCESS {
+            return (rcode, self.colors.take());
+        }
+        (ReturnCode::SUCCESS, None)
+    }
+}
+
+impl<S: SpiMasterDevice> SpiMasterClient for Ws2812Spi<'a, S> {
+    fn read_write_done(
+        &self,
+        write_buffer: &'static mut [u8],
+        _read_buffer: Option<&'static mut [u8]>,
+        _len: usize,
+    ) {
+        self.buffer.replace(write_buffer);
+        self.colors.take().map(|colors| {
+            self.client
+                .map(|client| client.show_done(colors, ReturnCode::SUCCESS));
+        });
+    }
+}
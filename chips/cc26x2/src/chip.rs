@@ -3,6 +3,7 @@ use enum_primitive::cast::FromPrimitive;
 use gpio;
 use i2c;
 use kernel;
+use kernel::common::deferred_call;
 use peripheral_interrupts::NVIC_IRQ;
 use rtc;
 use uart;
@@ -25,6 +26,7 @@ impl Cc26X2 {
 impl kernel::Chip for Cc26X2 {
     type MPU = cortexm4::mpu::MPU;
     type SysTick = cortexm4::systick::SysTick;
+    type Watchdog = ();
 
     fn mpu(&self) -> &Self::MPU {
         &self.mpu
@@ -33,29 +35,39 @@ impl kernel::Chip for Cc26X2 {
     fn systick(&self) -> &Self::SysTick {
         &self.systick
     }
+
+    fn watchdog(&self) -> &Self::Watchdog {
+        &()
+    }
     fn service_pending_interrupts(&self) {
         unsafe {
-            while let Some(interrupt) = nvic::next_pending() {
-                let irq = NVIC_IRQ::from_u32(interrupt)
-                    .expect("Pending IRQ flag not enumerated in NVIQ_IRQ");
-                match irq {
-                    NVIC_IRQ::GPIO => gpio::PORT.handle_interrupt(),
-                    NVIC_IRQ::AON_RTC => rtc::RTC.handle_interrupt(),
-                    NVIC_IRQ::UART0 => uart::UART0.handle_interrupt(),
-                    NVIC_IRQ::I2C0 => i2c::I2C0.handle_interrupt(),
-                    // We need to ignore JTAG events since some debuggers emit these
-                    NVIC_IRQ::AON_PROG => (),
-                    _ => panic!("Unhandled interrupt {:?}", irq),
+            loop {
+                if let Some(client) = deferred_call::next_pending() {
+                    client.handle_deferred_call();
+                } else if let Some(interrupt) = nvic::next_pending() {
+                    let irq = NVIC_IRQ::from_u32(interrupt)
+                        .expect("Pending IRQ flag not enumerated in NVIQ_IRQ");
+                    match irq {
+                        NVIC_IRQ::GPIO => gpio::PORT.handle_interrupt(),
+                        NVIC_IRQ::AON_RTC => rtc::RTC.handle_interrupt(),
+                        NVIC_IRQ::UART0 => uart::UART0.handle_interrupt(),
+                        NVIC_IRQ::I2C0 => i2c::I2C0.handle_interrupt(),
+                        // We need to ignore JTAG events since some debuggers emit these
+                        NVIC_IRQ::AON_PROG => (),
+                        _ => panic!("Unhandled interrupt {:?}", irq),
+                    }
+                    let n = nvic::Nvic::new(interrupt);
+                    n.clear_pending();
+                    n.enable();
+                } else {
+                    break;
                 }
-                let n = nvic::Nvic::new(interrupt);
-                n.clear_pending();
-                n.enable();
             }
         }
     }
 
     fn has_pending_interrupts(&self) -> bool {
-        unsafe { nvic::has_pending() }
+        unsafe { nvic::has_pending() || deferred_call::has_tasks() }
     }
 
     fn sleep(&self) {
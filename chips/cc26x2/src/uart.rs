@@ -345,3 +345,17 @@ impl kernel::hil::uart::UART for UART {
         });
     }
 }
+
+impl kernel::hil::uart::UARTEmergency for UART {
+    unsafe fn emergency_write(&self, bytes: &[u8]) {
+        // Drop any transmission in progress, its client is not signaled. The
+        // bytes it already pushed into the TX FIFO still go out first.
+        self.tx.take();
+
+        for &byte in bytes {
+            while !self.tx_fifo_not_full() {}
+            self.send_byte(byte);
+        }
+        while self.registers.fr.is_set(Flags::BUSY) {}
+    }
+}
@@ -4,13 +4,19 @@
 
 use core::cell::Cell;
 use core::cmp;
+use cortexm0;
 use kernel::common::cells::OptionalCell;
 use kernel::common::cells::TakeCell;
 use kernel::common::registers::{FieldValue, ReadOnly, ReadWrite, WriteOnly};
 use kernel::common::StaticRef;
 use kernel::hil::i2c;
+use kernel::ReturnCode;
 use {nrf5x, nrf5x::gpio, nrf5x::pinmux::Pinmux};
 
+/// Busy-wait iterations of half a clock period of bus recovery, about 5 us
+/// at 16 MHz.
+const RECOVERY_HALF_PERIOD: usize = 20;
+
 /// An I2C master device.
 ///
 /// A `TWIM` instance wraps a `registers::TWIM` together with
@@ -94,6 +100,25 @@ impl TWIM {
         regs.tasks_startrx.write(Task::ENABLE::SET);
     }
 
+    /// The GPIO pins of SCL and SDA, if they are connected.
+    fn bus_pins(&self) -> Option<(&'static gpio::GPIOPin, &'static gpio::GPIOPin)> {
+        let regs = &*self.registers;
+        let scl = regs.psel_scl.get();
+        let sda = regs.psel_sda.get();
+        if scl < 32 && sda < 32 {
+            unsafe { Some((&gpio::PORT[scl as usize], &gpio::PORT[sda as usize])) }
+        } else {
+            None
+        }
+    }
+
+    /// Whether a slave holds SCL or SDA low.
+    fn bus_stuck(&self) -> bool {
+        use kernel::hil::gpio::Pin;
+        self.bus_pins()
+            .map_or(false, |(scl, sda)| !scl.read() || !sda.read())
+    }
+
     fn reply(&self, result: i2c::Error) {
         self.client.map(|client| {
             self.buf.take().map(|buf| {
@@ -154,6 +179,8 @@ impl TWIM {
                 i2c::Error::AddressNak
             } else if regs.errorsrc.is_set(ErrorSrc::DATANACK) {
                 i2c::Error::DataNak
+            } else if self.bus_stuck() {
+                i2c::Error::BusStuck
             } else {
                 i2c::Error::CommandComplete
             };
@@ -206,6 +233,31 @@ impl i2c::I2CMaster for TWIM {
         self.start_read();
         self.buf.replace(buffer);
     }
+
+    fn recover_bus(&self) -> ReturnCode {
+        let (scl, sda) = match self.bus_pins() {
+            Some(pins) => pins,
+            None => return ReturnCode::ENOSUPPORT,
+        };
+
+        // The pins are GPIO pins while the TWI is disabled
+        let enabled = self.is_enabled();
+        self.disable();
+        let recovered = i2c::recover_bus_with_gpio(scl, sda, &|| {
+            for _ in 0..RECOVERY_HALF_PERIOD {
+                cortexm0::support::nop();
+            }
+        });
+        if enabled {
+            self.enable();
+        }
+
+        if recovered {
+            ReturnCode::SUCCESS
+        } else {
+            ReturnCode::FAIL
+        }
+    }
 }
 
 impl i2c::I2CSlave for TWIM {
@@ -5,19 +5,26 @@
 //! Currently all fields in PAYLOAD array are configurable from user-space
 //! except the PDU_TYPE.
 //!
+//! Advertisements are transmitted directly from the buffer of the client,
+//! `PAYLOAD` only holds received packets and test patterns.
+//!
 //! ### Authors
 //! * Niklas Adolfsson <niklasadolfsson1@gmail.com>
 //! * Fredrik Nilsson <frednils@student.chalmers.se>
 //! * Date: June 22, 2017
 
 use core::cell::Cell;
+use core::cmp;
 use core::convert::TryFrom;
 use kernel;
-use kernel::common::cells::OptionalCell;
+use kernel::capabilities::RadioTestCapability;
+use kernel::common::cells::{OptionalCell, TakeCell};
 use kernel::common::registers::{ReadOnly, ReadWrite, WriteOnly};
 use kernel::common::StaticRef;
 use kernel::hil::ble_advertising;
 use kernel::hil::ble_advertising::RadioChannel;
+use kernel::hil::radio_test;
+use kernel::hil::sniffer;
 use kernel::ReturnCode;
 use nrf5x;
 use nrf5x::constants::TxPower;
@@ -406,8 +413,15 @@ const RADIO_BASE: StaticRef<RadioRegisters> =
 pub struct Radio {
     registers: StaticRef<RadioRegisters>,
     tx_power: Cell<TxPower>,
+    /// A transmission is armed to be started by TIMER0
+    scheduled: Cell<bool>,
+    sniffing: Cell<bool>,
+    testing: Cell<bool>,
     rx_client: OptionalCell<&'static ble_advertising::RxClient>,
     tx_client: OptionalCell<&'static ble_advertising::TxClient>,
+    /// The buffer the radio transmits from
+    tx_buffer: TakeCell<'static, [u8]>,
+    sniffer_client: OptionalCell<&'static sniffer::SnifferClient>,
 }
 
 impl Radio {
@@ -415,11 +429,40 @@ impl Radio {
         Radio {
             registers: RADIO_BASE,
             tx_power: Cell::new(TxPower::ZerodBm),
+            scheduled: Cell::new(false),
+            sniffing: Cell::new(false),
+            testing: Cell::new(false),
             rx_client: OptionalCell::empty(),
             tx_client: OptionalCell::empty(),
+            tx_buffer: TakeCell::empty(),
+            sniffer_client: OptionalCell::empty(),
         }
     }
 
+    fn is_busy(&self) -> bool {
+        self.sniffing.get() || self.scheduled.get() || self.testing.get()
+    }
+
+    // Configure the radio for a test mode on `frequency`, using the BLE 1 Mbit mode without
+    // whitening so that the test pattern is transmitted as is
+    fn test_initialize(&self, frequency: u8) {
+        let regs = &*self.registers;
+        self.disable_interrupts();
+        self.radio_on();
+        self.set_tx_power();
+        self.set_channel_rate(nrf5x::constants::RadioMode::Ble1Mbit as u32);
+        self.set_channel_freq(frequency as u32);
+        regs.prefix0.write(Prefix0::AP0.val(0x8e));
+        regs.base0.write(Base::BASE.val(0x89bed600));
+        self.set_tx_address(0x00);
+        self.set_packet_config(0x00);
+        regs.pcnf1
+            .set(regs.pcnf1.get() & !(1 << nrf5x::constants::RADIO_PCNF1_WHITEEN_POS));
+        self.set_crc_config();
+        self.set_dma_ptr();
+        self.testing.set(true);
+    }
+
     fn ble_initialize(&self, channel: RadioChannel) {
         let regs = &*self.registers;
 
@@ -431,8 +474,8 @@ impl Radio {
         // BLE MODE
         self.set_channel_rate(nrf5x::constants::RadioMode::Ble1Mbit as u32);
 
-        self.set_channel_freq(channel);
-        self.set_data_whitening(channel);
+        self.set_channel_freq(channel.get_frequency());
+        self.set_data_whitening(channel.get_whitening_iv());
 
         // Set PREFIX | BASE Address
         regs.prefix0.write(Prefix0::AP0.val(0x8e));
@@ -451,6 +494,54 @@ impl Radio {
         self.set_dma_ptr();
     }
 
+    // Configure the radio like for BLE but with an arbitrary access address, frequency, data rate
+    // and whitening, and sample the RSSI of every received frame
+    fn sniffer_initialize(&self, config: sniffer::SnifferConfig) {
+        let regs = &*self.registers;
+
+        self.radio_on();
+
+        self.set_channel_rate(nrf5x::constants::RadioMode::from(config.data_rate) as u32);
+        regs.frequency.set(config.frequency as u32);
+
+        // The most significant byte of the access address is the prefix
+        regs.prefix0
+            .write(Prefix0::AP0.val(config.access_address >> 24));
+        regs.base0.write(Base::BASE.val(config.access_address << 8));
+
+        self.set_tx_address(0x00);
+        self.set_rx_address(0x01);
+
+        self.set_packet_config(0x00);
+        match config.whitening {
+            Some(iv) => regs.datawhiteiv.set(iv as u32),
+            None => regs
+                .pcnf1
+                .set(regs.pcnf1.get() & !(1 << nrf5x::constants::RADIO_PCNF1_WHITEEN_POS)),
+        }
+
+        self.set_crc_config();
+
+        self.set_dma_ptr();
+
+        regs.shorts
+            .write(Shortcuts::ADDRESS_RSSISTART::SET + Shortcuts::DISABLED_RSSISTOP::SET);
+    }
+
+    // Pass a frame received in sniffer mode to the client and start receiving the next one
+    fn capture_frame(&self) {
+        let regs = &*self.registers;
+        let crc_ok = regs.crcstatus.get() == 1;
+        let rssi = regs.rssisample.read(RssiSampleResult::RSSISAMPLE) as u8;
+        unsafe {
+            let len = cmp::min(PAYLOAD[1] as usize + 2, PAYLOAD.len());
+            self.sniffer_client
+                .map(|client| client.frame_captured(&PAYLOAD[..len], crc_ok, rssi));
+        }
+        // The radio stays in RXIDLE after END, so it can be started right away
+        regs.start.write(Task::EXECUTE::SET);
+    }
+
     fn tx(&self) {
         let regs = &*self.registers;
         regs.ready.write(Event::READY::CLEAR);
@@ -463,6 +554,40 @@ impl Radio {
         regs.rxen.write(Task::EXECUTE::SET);
     }
 
+    // Start the bit counter once the access address has been received, such that BCMATCH is
+    // generated when the PDU header is in RAM and can be inspected before the payload arrives
+    fn set_bit_counter(&self) {
+        let regs = &*self.registers;
+        regs.bcc
+            .write(BitCounterCompare::BCC.val(nrf5x::constants::RADIO_BCC_BLE_HEADER));
+        regs.bcmatch.write(Event::READY::CLEAR);
+        regs.shorts.write(Shortcuts::ADDRESS_BCSTART::SET);
+    }
+
+    // Arm TIMER0 such that its COMPARE[0] event triggers TXEN through the pre-programmed PPI
+    // channel 20, and let the READY_START shortcut start the transmission once the radio has
+    // ramped up. Only the END interrupt is enabled so the CPU isn't woken up before that.
+    fn schedule_tx(&self, delay_us: u32) {
+        let regs = &*self.registers;
+        regs.ready.write(Event::READY::CLEAR);
+        regs.end.write(Event::READY::CLEAR);
+        regs.shorts.write(Shortcuts::READY_START::SET);
+        self.scheduled.set(true);
+        unsafe {
+            nrf5x::ppi::PPI.enable(nrf5x::ppi::Channel::CH20::SET);
+            nrf5x::timer::TIMER0.start_oneshot_us(0, delay_us);
+        }
+        regs.intenset.set(nrf5x::constants::RADIO_INTENSET_END);
+    }
+
+    fn clear_scheduled_tx(&self) {
+        self.scheduled.set(false);
+        unsafe {
+            nrf5x::timer::TIMER0.stop();
+            nrf5x::ppi::PPI.disable(nrf5x::ppi::Channel::CH20::SET);
+        }
+    }
+
     fn set_crc_config(&self) {
         let regs = &*self.registers;
         regs.crccnf.set(
@@ -514,15 +639,16 @@ impl Radio {
         regs.mode.set(rate);
     }
 
-    fn set_data_whitening(&self, channel: RadioChannel) {
+    // Initial value of the whitening LFSR, see `RadioChannel::get_whitening_iv`
+    fn set_data_whitening(&self, iv: u32) {
         let regs = &*self.registers;
-        regs.datawhiteiv.set(channel.get_channel_index());
+        regs.datawhiteiv.set(iv);
     }
 
-    fn set_channel_freq(&self, channel: RadioChannel) {
+    // Frequency in MHz above 2400 MHz, see `RadioChannel::get_frequency`
+    fn set_channel_freq(&self, frequency: u32) {
         let regs = &*self.registers;
-        //37, 38 and 39 for adv.
-        regs.frequency.set(channel as u32);
+        regs.frequency.set(frequency);
     }
 
     fn radio_on(&self) {
@@ -557,8 +683,11 @@ impl Radio {
 
         if regs.ready.is_set(Event::READY) {
             regs.ready.write(Event::READY::CLEAR);
-            regs.end.write(Event::READY::CLEAR);
-            regs.start.write(Task::EXECUTE::SET);
+            // A scheduled transmission is started by the READY_START shortcut
+            if !self.scheduled.get() {
+                regs.end.write(Event::READY::CLEAR);
+                regs.start.write(Task::EXECUTE::SET);
+            }
         }
 
         if regs.payload.is_set(Event::READY) {
@@ -569,9 +698,37 @@ impl Radio {
             regs.address.write(Event::READY::CLEAR);
         }
 
+        // PDU header received, let the client decide whether to receive the rest
+        if regs.bcmatch.is_set(Event::READY) {
+            regs.bcmatch.write(Event::READY::CLEAR);
+            regs.bcstop.write(Task::EXECUTE::SET);
+
+            let accept = unsafe {
+                self.rx_client
+                    .map_or(true, |client| client.receive_filter(PAYLOAD[0], PAYLOAD[1]))
+            };
+            if !accept {
+                regs.end.write(Event::READY::CLEAR);
+                regs.disable.write(Task::EXECUTE::SET);
+                self.radio_off();
+                unsafe {
+                    self.rx_client
+                        .map(|client| client.receive_event(&mut PAYLOAD, 0, ReturnCode::ECANCEL));
+                }
+            }
+        }
+
+        if regs.end.is_set(Event::READY) && self.sniffing.get() {
+            regs.end.write(Event::READY::CLEAR);
+            self.capture_frame();
+        }
+
         if regs.end.is_set(Event::READY) {
             regs.end.write(Event::READY::CLEAR);
             regs.disable.write(Task::EXECUTE::SET);
+            if self.scheduled.get() {
+                self.clear_scheduled_tx();
+            }
 
             let result = if regs.crcstatus.get() == 1 {
                 ReturnCode::SUCCESS
@@ -585,7 +742,10 @@ impl Radio {
                 | nrf5x::constants::RADIO_STATE_TXDISABLE
                 | nrf5x::constants::RADIO_STATE_TX => {
                     self.radio_off();
-                    self.tx_client.map(|client| client.transmit_event(result));
+                    self.tx_buffer.take().map(|buf| {
+                        self.tx_client
+                            .map(move |client| client.transmit_event(buf, result))
+                    });
                 }
                 nrf5x::constants::RADIO_STATE_RXRU
                 | nrf5x::constants::RADIO_STATE_RXIDLE
@@ -614,7 +774,8 @@ impl Radio {
             nrf5x::constants::RADIO_INTENSET_READY
                 | nrf5x::constants::RADIO_INTENSET_ADDRESS
                 | nrf5x::constants::RADIO_INTENSET_PAYLOAD
-                | nrf5x::constants::RADIO_INTENSET_END,
+                | nrf5x::constants::RADIO_INTENSET_END
+                | nrf5x::constants::RADIO_INTENSET_BCMATCH,
         );
     }
 
@@ -624,33 +785,26 @@ impl Radio {
         regs.intenclr.set(0xffffffff);
     }
 
-    pub fn replace_radio_buffer(&self, buf: &'static mut [u8], len: usize) -> &'static mut [u8] {
-        // set payload
-        for (i, c) in buf.as_ref()[0..len].iter().enumerate() {
-            unsafe {
-                PAYLOAD[i] = *c;
-            }
-        }
-        buf
+    // Transmit directly from `buf` rather than from a copy in `PAYLOAD`. This must
+    // follow `ble_initialize`, which points the radio at `PAYLOAD` for receiving.
+    fn set_tx_buffer(&self, buf: &'static mut [u8]) {
+        let regs = &*self.registers;
+        regs.packetptr.set(buf.as_ptr() as u32);
+        self.tx_buffer.replace(buf);
     }
 }
 
 impl ble_advertising::BleAdvertisementDriver for Radio {
-    fn transmit_advertisement(
-        &self,
-        buf: &'static mut [u8],
-        len: usize,
-        channel: RadioChannel,
-    ) -> &'static mut [u8] {
-        let res = self.replace_radio_buffer(buf, len);
+    fn transmit_advertisement(&self, buf: &'static mut [u8], _len: usize, channel: RadioChannel) {
         self.ble_initialize(channel);
+        self.set_tx_buffer(buf);
         self.tx();
         self.enable_interrupts();
-        res
     }
 
     fn receive_advertisement(&self, channel: RadioChannel) {
         self.ble_initialize(channel);
+        self.set_bit_counter();
         self.rx();
         self.enable_interrupts();
     }
@@ -664,6 +818,31 @@ impl ble_advertising::BleAdvertisementDriver for Radio {
     }
 }
 
+impl ble_advertising::BleAdvertisementScheduler for Radio {
+    fn schedule_transmit_advertisement(
+        &self,
+        buf: &'static mut [u8],
+        _len: usize,
+        channel: RadioChannel,
+        delay_us: u32,
+    ) {
+        self.ble_initialize(channel);
+        self.set_tx_buffer(buf);
+        self.schedule_tx(delay_us);
+    }
+
+    fn cancel_scheduled_advertisement(&self) -> Option<&'static mut [u8]> {
+        if self.scheduled.get() {
+            self.disable_interrupts();
+            self.clear_scheduled_tx();
+            self.radio_off();
+            self.tx_buffer.take()
+        } else {
+            None
+        }
+    }
+}
+
 impl ble_advertising::BleConfig for Radio {
     // The BLE Advertising Driver validates that the `tx_power` is between -20 to 10 dBm but then
     // underlying chip must validate if the current `tx_power` is supported as well
@@ -680,3 +859,79 @@ impl ble_advertising::BleConfig for Radio {
         }
     }
 }
+
+impl sniffer::Sniffer for Radio {
+    fn set_sniffer_client(&self, client: &'static sniffer::SnifferClient) {
+        self.sniffer_client.set(client);
+    }
+
+    fn start_sniffing(&self, config: sniffer::SnifferConfig) -> ReturnCode {
+        if self.is_busy() {
+            return ReturnCode::EBUSY;
+        }
+        self.sniffing.set(true);
+        self.sniffer_initialize(config);
+        self.rx();
+        self.enable_interrupts();
+        ReturnCode::SUCCESS
+    }
+
+    fn stop_sniffing(&self) -> ReturnCode {
+        if !self.sniffing.get() {
+            return ReturnCode::EALREADY;
+        }
+        self.disable_interrupts();
+        self.sniffing.set(false);
+        self.radio_off();
+        ReturnCode::SUCCESS
+    }
+}
+
+impl radio_test::RadioTest for Radio {
+    fn start_constant_carrier(
+        &self,
+        frequency: u8,
+        _capability: &RadioTestCapability,
+    ) -> ReturnCode {
+        if self.is_busy() {
+            return ReturnCode::EBUSY;
+        }
+        self.test_initialize(frequency);
+        let regs = &*self.registers;
+        regs.test
+            .write(Test::CONSTCARRIER::SET + Test::PLLLOCK::SET);
+        regs.txen.write(Task::EXECUTE::SET);
+        ReturnCode::SUCCESS
+    }
+
+    fn start_prbs9(&self, frequency: u8, _capability: &RadioTestCapability) -> ReturnCode {
+        if self.is_busy() {
+            return ReturnCode::EBUSY;
+        }
+        unsafe {
+            // Maximum length packets (MAXLEN is 37 bytes on this radio), back to back
+            PAYLOAD[0] = 0;
+            PAYLOAD[1] = nrf5x::constants::RADIO_PCNF1_MAXLEN_37BYTES as u8;
+            radio_test::fill_prbs9(&mut PAYLOAD[2..]);
+        }
+        self.test_initialize(frequency);
+        let regs = &*self.registers;
+        regs.shorts
+            .write(Shortcuts::READY_START::SET + Shortcuts::END_START::SET);
+        regs.txen.write(Task::EXECUTE::SET);
+        ReturnCode::SUCCESS
+    }
+
+    fn stop_test(&self) -> ReturnCode {
+        if !self.testing.get() {
+            return ReturnCode::EALREADY;
+        }
+        let regs = &*self.registers;
+        regs.shorts.set(0);
+        regs.test.set(0);
+        regs.disable.write(Task::EXECUTE::SET);
+        self.radio_off();
+        self.testing.set(false);
+        ReturnCode::SUCCESS
+    }
+}
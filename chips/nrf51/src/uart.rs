@@ -345,3 +345,25 @@ impl uart::UART for UART {
         unimplemented!()
     }
 }
+
+impl uart::UARTEmergency for UART {
+    unsafe fn emergency_write(&self, bytes: &[u8]) {
+        let regs = &*self.registers;
+
+        // Drop any transmission in progress, its client is not signaled, but
+        // let the byte already in TXD go out first
+        self.disable_tx_interrupts();
+        if self.buffer.take().is_some() {
+            while !self.tx_ready() {}
+        }
+
+        regs.task_starttx.write(Task::ENABLE::SET);
+        for &byte in bytes {
+            regs.event_txdrdy.write(Event::READY::CLEAR);
+            regs.txd.set(byte as u32);
+            while !self.tx_ready() {}
+        }
+        self.index.set(0);
+        self.len.set(0);
+    }
+}
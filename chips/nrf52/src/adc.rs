@@ -1,10 +1,29 @@
 //! ADC driver for the nRF52. Uses the SAADC peripheral.
+//!
+//! Single samples are triggered by the SAMPLE task. High-speed sampling uses
+//! the timer of the SAADC, and double buffers the result pointer: once the
+//! SAADC has started filling one buffer, the next one is written to the
+//! pointer registers, and the START task at the end of the first one moves
+//! on to it.
+//!
+//! Scans use the scan mode of the SAADC: each channel of the sequence is
+//! assigned to one of its eight channel configurations, and a single SAMPLE
+//! task converts all of them in order.
+//!
+//! Sampling on analog comparator events connects the CROSS event of COMP or
+//! LPCOMP to the SAMPLE task through a PPI channel, so the SAADC samples on
+//! every crossing without the CPU. The result buffer holds one sample, and
+//! is latched again after each one.
 
-use kernel::common::cells::{OptionalCell, VolatileCell};
+use comp;
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell, VolatileCell};
 use kernel::common::registers::{ReadOnly, ReadWrite, WriteOnly};
 use kernel::common::StaticRef;
 use kernel::hil;
 use kernel::ReturnCode;
+use nrf5x;
 
 #[repr(C)]
 struct AdcRegisters {
@@ -244,27 +263,161 @@ pub static mut ADC: Adc = Adc::new(SAADC_BASE);
 // Buffer to save completed sample to.
 static mut SAMPLE: [u16; 1] = [0; 1];
 
+/// The SAADC timer runs at 16 MHz, and its compare value must be 80 to 2047.
+const TIMER_FREQUENCY: u32 = 16_000_000;
+const TIMER_CC_MIN: u32 = 80;
+const TIMER_CC_MAX: u32 = 2047;
+
+/// The SAADC has eight channel configurations, so scans have up to eight
+/// channels.
+const MAX_SCAN_CHANNELS: usize = 8;
+
+/// The PPI channel connecting comparator events to the SAMPLE task
+const EVENT_PPI_CHANNEL: usize = 0;
+
+/// Create a trait of both client types to allow a single client reference to
+/// act as both
+pub trait EverythingClient: hil::adc::Client + hil::adc::HighSpeedClient {}
+impl<C: hil::adc::Client + hil::adc::HighSpeedClient> EverythingClient for C {}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Mode {
+    Idle,
+    Single,
+    HighSpeed,
+    Scan,
+    Event,
+}
+
 pub struct Adc {
     registers: StaticRef<AdcRegisters>,
-    client: OptionalCell<&'static hil::adc::Client>,
+    mode: Cell<Mode>,
+    client: OptionalCell<&'static EverythingClient>,
+
+    /// The buffer the SAADC is filling during high-speed sampling
+    buffer: TakeCell<'static, [u16]>,
+    /// The buffer to fill next
+    next_buffer: TakeCell<'static, [u16]>,
+    next_length: Cell<usize>,
+    /// Whether the SAADC has latched the current buffer
+    started: Cell<bool>,
+    /// Whether `next_buffer` is in the result pointer registers, which are
+    /// latched by the START task
+    next_latched: Cell<bool>,
+    /// Whether a buffer filled up before the next one was provided, so
+    /// samples were lost before the current one
+    samples_dropped: Cell<bool>,
+    stopped_buffer: TakeCell<'static, [u16]>,
+
+    scan_channels: Cell<[Option<AdcChannel>; MAX_SCAN_CHANNELS]>,
+    scan_length: Cell<usize>,
+    scan_buffer: TakeCell<'static, [u16]>,
+    scan_client: OptionalCell<&'static hil::adc::ScanClient>,
+
+    /// The comparator and input whose events trigger samples
+    event_source: OptionalCell<(&'static comp::Comparator<'static>, comp::ComparatorInput)>,
 }
 
 impl Adc {
     const fn new(registers: StaticRef<AdcRegisters>) -> Adc {
         Adc {
             registers: registers,
-            // state: Cell::new(State::Idle),
+            mode: Cell::new(Mode::Idle),
             client: OptionalCell::empty(),
+            buffer: TakeCell::empty(),
+            next_buffer: TakeCell::empty(),
+            next_length: Cell::new(0),
+            started: Cell::new(false),
+            next_latched: Cell::new(false),
+            samples_dropped: Cell::new(false),
+            stopped_buffer: TakeCell::empty(),
+            scan_channels: Cell::new([None; MAX_SCAN_CHANNELS]),
+            scan_length: Cell::new(0),
+            scan_buffer: TakeCell::empty(),
+            scan_client: OptionalCell::empty(),
+            event_source: OptionalCell::empty(),
         }
     }
 
-    pub fn set_client(&self, client: &'static hil::adc::Client) {
+    pub fn set_client<C: EverythingClient>(&self, client: &'static C) {
         self.client.set(client);
     }
 
+    pub fn set_scan_client(&self, client: &'static hil::adc::ScanClient) {
+        self.scan_client.set(client);
+    }
+
+    /// Connects `channel` to the input of channel configuration `index` and
+    /// configures it, with the rest of the SAADC, for 14-bit samples.
+    fn configure_channel(&self, index: usize, channel: &AdcChannel) {
+        let regs = &*self.registers;
+
+        // Positive goes to the channel passed in, negative not connected.
+        regs.ch[index].pselp.write(PSEL::PSEL.val(*channel as u32));
+        regs.ch[index].pseln.write(PSEL::PSEL::NotConnected);
+
+        regs.ch[index]
+            .config
+            .write(CONFIG::GAIN::Gain1_4 + CONFIG::REFSEL::VDD1_4 + CONFIG::TACQ::us10);
+
+        // Set max resolution.
+        regs.resolution.write(RESOLUTION::VAL::bit14);
+    }
+
+    fn configure(&self, channel: &AdcChannel) {
+        self.configure_channel(0, channel);
+    }
+
+    /// Disconnects the channel configurations a scan used besides the
+    /// first, so other operations only sample one channel.
+    fn disconnect_scan_channels(&self) {
+        let regs = &*self.registers;
+        for ch in regs.ch.iter().skip(1) {
+            ch.pselp.write(PSEL::PSEL::NotConnected);
+        }
+    }
+
+    /// Points the SAADC at the next buffer, which it moves to at the next
+    /// START task. A buffer without room for samples is kept until sampling
+    /// stops.
+    fn latch_next_buffer(&self) {
+        let regs = &*self.registers;
+        self.next_buffer.map(|buffer| {
+            let length = cmp::min(buffer.len(), self.next_length.get());
+            if length == 0 {
+                return;
+            }
+            regs.result_ptr.set(buffer.as_ptr());
+            regs.result_maxcnt.write(RESULT_MAXCNT::MAXCNT.val(length as u32));
+            self.next_latched.set(true);
+        });
+    }
+
+    /// Starts filling `buffer` right away.
+    fn start_buffer(&self, buffer: &'static mut [u16], length: usize) {
+        let regs = &*self.registers;
+        regs.result_ptr.set(buffer.as_ptr());
+        regs.result_maxcnt.write(RESULT_MAXCNT::MAXCNT.val(length as u32));
+        self.buffer.replace(buffer);
+        self.started.set(false);
+        regs.tasks_start.write(TASK::TASK::SET);
+    }
+
     pub fn handle_interrupt(&self) {
         let regs = &*self.registers;
 
+        match self.mode.get() {
+            Mode::HighSpeed => {
+                self.handle_highspeed_interrupt();
+                return;
+            }
+            Mode::Event => {
+                self.handle_event_interrupt();
+                return;
+            }
+            _ => {}
+        }
+
         // Determine what event occurred.
         if regs.events_started.is_set(EVENT::EVENT) {
             regs.events_started.write(EVENT::EVENT::CLEAR);
@@ -278,14 +431,93 @@ impl Adc {
             regs.events_stopped.write(EVENT::EVENT::CLEAR);
             // ADC is stopped. Disable and return value.
             regs.enable.write(ENABLE::ENABLE::CLEAR);
+            let mode = self.mode.get();
+            self.mode.set(Mode::Idle);
+
+            if mode == Mode::Scan {
+                self.disconnect_scan_channels();
+                let length = self.scan_length.get();
+                self.scan_buffer.take().map(|buffer| {
+                    // Left justify to meet HIL requirements.
+                    for sample in buffer[..length].iter_mut() {
+                        *sample <<= 2;
+                    }
+                    self.scan_client.map(move |client| {
+                        client.scan_done(buffer, length);
+                    });
+                });
+                return;
+            }
+
+            // Left justify to meet HIL requirements.
+            let val = unsafe { SAMPLE[0] } << 2;
+            self.client.map(|client| {
+                client.sample_ready(val);
+            });
+        }
+    }
 
+    fn handle_event_interrupt(&self) {
+        let regs = &*self.registers;
+
+        if regs.events_end.is_set(EVENT::EVENT) {
+            regs.events_end.write(EVENT::EVENT::CLEAR);
             // Left justify to meet HIL requirements.
             let val = unsafe { SAMPLE[0] } << 2;
+            // Latch the buffer again for the next event
+            regs.tasks_start.write(TASK::TASK::SET);
             self.client.map(|client| {
                 client.sample_ready(val);
             });
         }
     }
+
+    fn handle_highspeed_interrupt(&self) {
+        let regs = &*self.registers;
+
+        if regs.events_started.is_set(EVENT::EVENT) {
+            regs.events_started.write(EVENT::EVENT::CLEAR);
+            // The current buffer is latched, so the registers are free for
+            // the next one
+            self.started.set(true);
+            self.latch_next_buffer();
+        }
+
+        if regs.events_end.is_set(EVENT::EVENT) {
+            regs.events_end.write(EVENT::EVENT::CLEAR);
+            let amount = regs.result_amount.read(RESULT_AMOUNT::AMOUNT) as usize;
+
+            // Move on to the next buffer right away, the timer keeps
+            // sampling
+            let full = self.buffer.take();
+            let dropped = self.samples_dropped.get();
+            self.samples_dropped.set(false);
+            if self.next_latched.get() {
+                self.next_latched.set(false);
+                self.next_buffer.take().map(|buffer| {
+                    self.buffer.replace(buffer);
+                });
+                self.started.set(false);
+                regs.tasks_start.write(TASK::TASK::SET);
+            } else {
+                // Samples are lost until a buffer is provided
+                self.samples_dropped.set(true);
+            }
+
+            full.map(|buffer| {
+                // Left justify to meet HIL requirements.
+                for sample in buffer[..amount].iter_mut() {
+                    *sample <<= 2;
+                }
+                self.client.map(move |client| {
+                    if dropped {
+                        client.samples_dropped();
+                    }
+                    client.samples_ready(buffer, amount);
+                });
+            });
+        }
+    }
 }
 
 /// Implements an ADC capable reading ADC samples on any channel.
@@ -295,17 +527,12 @@ impl hil::adc::Adc for Adc {
     fn sample(&self, channel: &Self::Channel) -> ReturnCode {
         let regs = &*self.registers;
 
-        // Positive goes to the channel passed in, negative not connected.
-        regs.ch[0].pselp.write(PSEL::PSEL.val(*channel as u32));
-        regs.ch[0].pseln.write(PSEL::PSEL::NotConnected);
-
-        // Configure the ADC for a single read.
-        regs.ch[0]
-            .config
-            .write(CONFIG::GAIN::Gain1_4 + CONFIG::REFSEL::VDD1_4 + CONFIG::TACQ::us10);
+        if self.mode.get() != Mode::Idle {
+            return ReturnCode::EBUSY;
+        }
+        self.mode.set(Mode::Single);
 
-        // Set max resolution.
-        regs.resolution.write(RESOLUTION::VAL::bit14);
+        self.configure(channel);
 
         // Do one measurement.
         regs.result_maxcnt.write(RESULT_MAXCNT::MAXCNT.val(1));
@@ -334,8 +561,30 @@ impl hil::adc::Adc for Adc {
         ReturnCode::FAIL
     }
 
+    /// Stops high-speed sampling. The buffers are returned by
+    /// `retrieve_buffers`.
     fn stop_sampling(&self) -> ReturnCode {
-        ReturnCode::FAIL
+        let regs = &*self.registers;
+
+        if self.mode.get() != Mode::HighSpeed {
+            return ReturnCode::FAIL;
+        }
+
+        regs.inten.set(0);
+        regs.tasks_stop.write(TASK::TASK::SET);
+        // Stopping takes a few microseconds, after which the buffer is no
+        // longer written to
+        while !regs.events_stopped.is_set(EVENT::EVENT) {}
+        regs.events_stopped.write(EVENT::EVENT::CLEAR);
+        regs.events_started.write(EVENT::EVENT::CLEAR);
+        regs.events_end.write(EVENT::EVENT::CLEAR);
+        regs.enable.write(ENABLE::ENABLE::CLEAR);
+
+        self.mode.set(Mode::Idle);
+        self.next_latched.set(false);
+        self.buffer.take().map(|buffer| self.stopped_buffer.replace(buffer));
+
+        ReturnCode::SUCCESS
     }
 
     fn get_resolution_bits(&self) -> usize {
@@ -346,3 +595,266 @@ impl hil::adc::Adc for Adc {
         Some(3300)
     }
 }
+
+/// Implements an ADC capable of continuous sampling
+impl hil::adc::AdcHighSpeed for Adc {
+    /// Capture buffered samples continuously at `frequency`, which the
+    /// SAADC timer supports from 7813 Hz to 200 kHz. The SAADC moves from
+    /// one buffer to the next without losing samples, as long as the next
+    /// buffer is provided before the current one fills.
+    fn sample_highspeed(
+        &self,
+        channel: &Self::Channel,
+        frequency: u32,
+        buffer1: &'static mut [u16],
+        length1: usize,
+        buffer2: &'static mut [u16],
+        length2: usize,
+    ) -> (
+        ReturnCode,
+        Option<&'static mut [u16]>,
+        Option<&'static mut [u16]>,
+    ) {
+        let regs = &*self.registers;
+
+        if self.mode.get() != Mode::Idle {
+            return (ReturnCode::EBUSY, Some(buffer1), Some(buffer2));
+        }
+        if frequency == 0 {
+            return (ReturnCode::EINVAL, Some(buffer1), Some(buffer2));
+        }
+        let cc = TIMER_FREQUENCY / frequency;
+        let length = cmp::min(buffer1.len(), length1);
+        if cc < TIMER_CC_MIN || cc > TIMER_CC_MAX || length == 0 {
+            return (ReturnCode::EINVAL, Some(buffer1), Some(buffer2));
+        }
+
+        self.mode.set(Mode::HighSpeed);
+        self.samples_dropped.set(false);
+        self.configure(channel);
+        regs.samplerate
+            .write(SAMPLERATE::MODE::Timers + SAMPLERATE::CC.val(cc));
+        regs.enable.write(ENABLE::ENABLE::SET);
+        regs.events_started.write(EVENT::EVENT::CLEAR);
+        regs.events_end.write(EVENT::EVENT::CLEAR);
+        regs.events_stopped.write(EVENT::EVENT::CLEAR);
+        regs.inten.write(INTEN::STARTED::SET + INTEN::END::SET);
+
+        self.next_buffer.replace(buffer2);
+        self.next_length.set(length2);
+        self.start_buffer(buffer1, length);
+        // The first SAMPLE task starts the timer, which triggers the rest
+        regs.tasks_sample.write(TASK::TASK::SET);
+
+        (ReturnCode::SUCCESS, None, None)
+    }
+
+    fn provide_buffer(
+        &self,
+        buf: &'static mut [u16],
+        length: usize,
+    ) -> (ReturnCode, Option<&'static mut [u16]>) {
+        if self.mode.get() != Mode::HighSpeed {
+            return (ReturnCode::EINVAL, Some(buf));
+        }
+        if self.next_buffer.is_some() {
+            return (ReturnCode::EBUSY, Some(buf));
+        }
+        let length = cmp::min(buf.len(), length);
+        if length == 0 {
+            return (ReturnCode::EINVAL, Some(buf));
+        }
+
+        if self.buffer.is_none() {
+            // The last buffer filled up before this one was provided, so
+            // resume sampling into this one
+            self.start_buffer(buf, length);
+        } else {
+            self.next_buffer.replace(buf);
+            self.next_length.set(length);
+            // Until the current buffer is latched, the STARTED event
+            // latches this one
+            if self.started.get() {
+                self.latch_next_buffer();
+            }
+        }
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn retrieve_buffers(
+        &self,
+    ) -> (
+        ReturnCode,
+        Option<&'static mut [u16]>,
+        Option<&'static mut [u16]>,
+    ) {
+        if self.mode.get() == Mode::HighSpeed {
+            return (ReturnCode::EINVAL, None, None);
+        }
+        (
+            ReturnCode::SUCCESS,
+            self.next_buffer.take(),
+            self.stopped_buffer.take(),
+        )
+    }
+}
+
+/// Implements an ADC whose conversions can be triggered by the analog
+/// comparators
+impl hil::analog_comparator::EventSink for Adc {
+    type Channel = AdcChannel;
+
+    /// Sample `channel` every time the output of the comparator on analog
+    /// input `ac` changes, calling the client after each sample. COMP and
+    /// LPCOMP share their registers, so the events come from whichever of
+    /// them is in use, or COMP if neither is.
+    ///
+    /// - `ac`: the analog input of the comparator whose events trigger
+    ///         conversions
+    /// - `channel`: the ADC channel to sample
+    fn start_on_events(&self, ac: usize, channel: &AdcChannel) -> ReturnCode {
+        let regs = &*self.registers;
+
+        let input = match ac {
+            0 => comp::ComparatorInput::AnalogInput0,
+            1 => comp::ComparatorInput::AnalogInput1,
+            2 => comp::ComparatorInput::AnalogInput2,
+            3 => comp::ComparatorInput::AnalogInput3,
+            4 => comp::ComparatorInput::AnalogInput4,
+            5 => comp::ComparatorInput::AnalogInput5,
+            6 => comp::ComparatorInput::AnalogInput6,
+            7 => comp::ComparatorInput::AnalogInput7,
+            _ => return ReturnCode::EINVAL,
+        };
+        if self.mode.get() != Mode::Idle {
+            return ReturnCode::EBUSY;
+        }
+
+        let comparator: &'static comp::Comparator = unsafe {
+            if comp::LPCOMP.is_enabled() {
+                &comp::LPCOMP
+            } else {
+                &comp::COMP
+            }
+        };
+        let res = comparator.enable_events(input);
+        if res != ReturnCode::SUCCESS {
+            return res;
+        }
+        self.mode.set(Mode::Event);
+        self.event_source.set((comparator, input));
+
+        self.configure(channel);
+        regs.result_maxcnt.write(RESULT_MAXCNT::MAXCNT.val(1));
+        unsafe {
+            regs.result_ptr.set(SAMPLE.as_ptr());
+        }
+        regs.samplerate.write(SAMPLERATE::MODE::Task);
+        regs.enable.write(ENABLE::ENABLE::SET);
+        regs.events_started.write(EVENT::EVENT::CLEAR);
+        regs.events_end.write(EVENT::EVENT::CLEAR);
+        regs.inten.write(INTEN::END::SET);
+        regs.tasks_start.write(TASK::TASK::SET);
+
+        // route the comparator events to the SAMPLE task
+        let sample_task = &regs.tasks_sample as *const WriteOnly<u32, TASK::Register> as u32;
+        unsafe {
+            nrf5x::ppi::PPI.configure(
+                EVENT_PPI_CHANNEL,
+                comparator.cross_event_address(),
+                sample_task,
+            );
+            nrf5x::ppi::PPI.enable(nrf5x::ppi::Channel::CH0::SET);
+        }
+
+        ReturnCode::SUCCESS
+    }
+
+    /// Stop sampling on analog comparator events.
+    fn stop_on_events(&self) -> ReturnCode {
+        let regs = &*self.registers;
+
+        match self.event_source.take() {
+            Some((comparator, input)) => {
+                unsafe {
+                    nrf5x::ppi::PPI.disable(nrf5x::ppi::Channel::CH0::SET);
+                }
+                comparator.disable_events(input);
+
+                regs.inten.set(0);
+                regs.tasks_stop.write(TASK::TASK::SET);
+                while !regs.events_stopped.is_set(EVENT::EVENT) {}
+                regs.events_stopped.write(EVENT::EVENT::CLEAR);
+                regs.events_started.write(EVENT::EVENT::CLEAR);
+                regs.events_end.write(EVENT::EVENT::CLEAR);
+                regs.enable.write(ENABLE::ENABLE::CLEAR);
+                self.mode.set(Mode::Idle);
+
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::EINVAL,
+        }
+    }
+}
+
+/// Implements an ADC that samples a sequence of channels per scan
+impl hil::adc::AdcScan for Adc {
+    fn max_scan_channels(&self) -> usize {
+        MAX_SCAN_CHANNELS
+    }
+
+    fn configure_scan(&self, channels: &[&AdcChannel]) -> ReturnCode {
+        if self.mode.get() != Mode::Idle {
+            return ReturnCode::EBUSY;
+        }
+        if channels.is_empty() || channels.len() > MAX_SCAN_CHANNELS {
+            return ReturnCode::EINVAL;
+        }
+        let mut sequence = [None; MAX_SCAN_CHANNELS];
+        for (entry, channel) in sequence.iter_mut().zip(channels.iter()) {
+            *entry = Some(**channel);
+        }
+        self.scan_channels.set(sequence);
+        self.scan_length.set(channels.len());
+        ReturnCode::SUCCESS
+    }
+
+    fn scan(&self, buffer: &'static mut [u16]) -> (ReturnCode, Option<&'static mut [u16]>) {
+        let regs = &*self.registers;
+        let length = self.scan_length.get();
+
+        if self.mode.get() != Mode::Idle {
+            return (ReturnCode::EBUSY, Some(buffer));
+        }
+        if length == 0 {
+            return (ReturnCode::EINVAL, Some(buffer));
+        }
+        if buffer.len() < length {
+            return (ReturnCode::ESIZE, Some(buffer));
+        }
+        self.mode.set(Mode::Scan);
+
+        for (index, channel) in self.scan_channels.get().iter().enumerate() {
+            match channel {
+                Some(channel) => self.configure_channel(index, channel),
+                None => regs.ch[index].pselp.write(PSEL::PSEL::NotConnected),
+            }
+        }
+
+        // One sample of each channel, in the order of their configurations.
+        regs.result_maxcnt
+            .write(RESULT_MAXCNT::MAXCNT.val(length as u32));
+        regs.result_ptr.set(buffer.as_ptr());
+        self.scan_buffer.replace(buffer);
+
+        // The SAMPLE task issued when the SAADC has started converts them
+        // all.
+        regs.samplerate.write(SAMPLERATE::MODE::Task);
+        regs.enable.write(ENABLE::ENABLE::SET);
+        regs.inten
+            .write(INTEN::STARTED::SET + INTEN::END::SET + INTEN::STOPPED::SET);
+        regs.tasks_start.write(TASK::TASK::SET);
+
+        (ReturnCode::SUCCESS, None)
+    }
+}
@@ -1,15 +1,25 @@
 use adc;
+use clock;
+use comp;
 use cortexm4::{self, nvic};
-use deferred_call_tasks::DeferredCallTask;
 use i2c;
+use i2s;
 use kernel;
 use kernel::common::deferred_call;
+use kernel::hil::time::Alarm;
+use kernel::{ClockManager, SleepMode};
 use nrf5x;
 use nrf5x::peripheral_interrupts;
 use nvmc;
+use pdm;
+use pwm;
+use qdec;
+use qspi;
 use radio;
 use spi;
 use uart;
+use usbd;
+use wdt;
 
 pub struct NRF52 {
     mpu: cortexm4::mpu::MPU,
@@ -18,6 +28,8 @@ pub struct NRF52 {
 
 impl NRF52 {
     pub unsafe fn new() -> NRF52 {
+        nvmc::DEFERRED_CALL.register(&nvmc::NVMC);
+
         NRF52 {
             mpu: cortexm4::mpu::MPU::new(),
             // The NRF52's systick is uncalibrated, but is clocked from the
@@ -30,6 +42,7 @@ impl NRF52 {
 impl kernel::Chip for NRF52 {
     type MPU = cortexm4::mpu::MPU;
     type SysTick = cortexm4::systick::SysTick;
+    type Watchdog = wdt::Wdt;
 
     fn mpu(&self) -> &Self::MPU {
         &self.mpu
@@ -39,17 +52,25 @@ impl kernel::Chip for NRF52 {
         &self.systick
     }
 
+    fn watchdog(&self) -> &Self::Watchdog {
+        unsafe { &wdt::WDT }
+    }
+
     fn service_pending_interrupts(&self) {
         unsafe {
             loop {
-                if let Some(task) = deferred_call::DeferredCall::next_pending() {
-                    match task {
-                        DeferredCallTask::Nvmc => nvmc::NVMC.handle_interrupt(),
-                    }
+                if let Some(client) = deferred_call::next_pending() {
+                    client.handle_deferred_call();
                 } else if let Some(interrupt) = nvic::next_pending() {
                     match interrupt {
+                        peripheral_interrupts::CCM_AAR => nrf5x::ccm::CCM.handle_interrupt(),
                         peripheral_interrupts::ECB => nrf5x::aes::AESECB.handle_interrupt(),
                         peripheral_interrupts::GPIOTE => nrf5x::gpio::PORT.handle_interrupt(),
+                        peripheral_interrupts::LPCOMP => {
+                            // COMP and LPCOMP share interrupts, only the enabled one handles it.
+                            comp::COMP.handle_interrupt();
+                            comp::LPCOMP.handle_interrupt();
+                        }
                         peripheral_interrupts::RADIO => radio::RADIO.handle_interrupt(),
                         peripheral_interrupts::RNG => nrf5x::trng::TRNG.handle_interrupt(),
                         peripheral_interrupts::RTC1 => nrf5x::rtc::RTC.handle_interrupt(),
@@ -59,10 +80,14 @@ impl kernel::Chip for NRF52 {
                         peripheral_interrupts::TIMER2 => nrf5x::timer::TIMER2.handle_interrupt(),
                         peripheral_interrupts::UART0 => uart::UARTE0.handle_interrupt(),
                         peripheral_interrupts::SPI0_TWI0 => {
-                            // SPI0 and TWI0 share interrupts.
+                            // SPI0, TWIM0 and TWIS0 share interrupts.
                             // Dispatch the correct handler.
                             match (spi::SPIM0.is_enabled(), i2c::TWIM0.is_enabled()) {
-                                (false, false) => (),
+                                (false, false) => {
+                                    if i2c::TWIS0.is_enabled() {
+                                        i2c::TWIS0.handle_interrupt()
+                                    }
+                                }
                                 (true, false) => spi::SPIM0.handle_interrupt(),
                                 (false, true) => i2c::TWIM0.handle_interrupt(),
                                 (true, true) => debug_assert!(
@@ -73,10 +98,14 @@ impl kernel::Chip for NRF52 {
                             }
                         }
                         peripheral_interrupts::SPI1_TWI1 => {
-                            // SPI1 and TWI1 share interrupts.
+                            // SPI1, TWIM1 and TWIS1 share interrupts.
                             // Dispatch the correct handler.
                             match (spi::SPIM1.is_enabled(), i2c::TWIM1.is_enabled()) {
-                                (false, false) => (),
+                                (false, false) => {
+                                    if i2c::TWIS1.is_enabled() {
+                                        i2c::TWIS1.handle_interrupt()
+                                    }
+                                }
                                 (true, false) => spi::SPIM1.handle_interrupt(),
                                 (false, true) => i2c::TWIM1.handle_interrupt(),
                                 (true, true) => debug_assert!(
@@ -88,6 +117,14 @@ impl kernel::Chip for NRF52 {
                         }
                         peripheral_interrupts::SPIM2_SPIS2_SPI2 => spi::SPIM2.handle_interrupt(),
                         peripheral_interrupts::ADC => adc::ADC.handle_interrupt(),
+                        peripheral_interrupts::I2S => i2s::I2S.handle_interrupt(),
+                        peripheral_interrupts::PDM => pdm::PDM.handle_interrupt(),
+                        peripheral_interrupts::PWM0 => pwm::PWM0.handle_interrupt(),
+                        peripheral_interrupts::PWM1 => pwm::PWM1.handle_interrupt(),
+                        peripheral_interrupts::PWM2 => pwm::PWM2.handle_interrupt(),
+                        peripheral_interrupts::QSPI => qspi::QSPI.handle_interrupt(),
+                        peripheral_interrupts::QDEC => qdec::QDEC.handle_interrupt(),
+                        peripheral_interrupts::USBD => usbd::USBD.handle_interrupt(),
                         _ => debug!("NvicIdx not supported by Tock"),
                     }
                     let n = nvic::Nvic::new(interrupt);
@@ -105,8 +142,24 @@ impl kernel::Chip for NRF52 {
     }
 
     fn sleep(&self) {
+        let mode = unsafe { clock::CLOCK.sleep_mode() };
+        match mode {
+            SleepMode::DeepSleep => unsafe {
+                cortexm4::scb::set_sleepdeep();
+            },
+            SleepMode::Sleep => unsafe {
+                cortexm4::scb::unset_sleepdeep();
+            },
+        }
+
         unsafe {
+            let before = nrf5x::rtc::RTC.now();
             cortexm4::support::wfi();
+            let after = nrf5x::rtc::RTC.now();
+            // Interrupts are disabled, so the interrupt that woke the chip
+            // is still pending
+            let wakeup = nvic::lowest_pending().map(|interrupt| interrupt as usize);
+            clock::SLEEP_STATS.record(mode, before, after, wakeup);
         }
     }
 
@@ -15,10 +15,16 @@
 //!     * 32.768 kHz crystal oscillator (LFXO)
 //!     * 32.768 kHz synthesized from HFCLK (LFSYNT)
 //!
+//! Peripherals request the HFXO and the LFCLK through `ClockManager`. A clock
+//! is started by the first request and stopped when the last request is
+//! released, after which the HFCLK falls back to the HFINT.
+//!
 
+use core::cell::Cell;
 use kernel::common::cells::OptionalCell;
 use kernel::common::registers::{ReadOnly, ReadWrite, WriteOnly};
 use kernel::common::StaticRef;
+use kernel::{ClockManager, ReturnCode, SleepMode, SleepStats};
 
 #[repr(C)]
 struct ClockRegisters {
@@ -137,15 +143,33 @@ pub enum LowClockSource {
 }
 
 /// High frequency clock source
+#[derive(PartialEq)]
 pub enum HighClockSource {
     RC = 0,
     XTAL = 1,
 }
 
+/// Clocks that peripherals can request
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ManagedClock {
+    /// The HFCLK, running from the crystal oscillator
+    High,
+    /// The LFCLK, from the configured source
+    Low,
+}
+
+/// Frequency of the HFCLK in Hz
+const HIGH_FREQUENCY: u32 = 64_000_000;
+
+/// Frequency of the LFCLK in Hz
+const LOW_FREQUENCY: u32 = 32_768;
+
 /// Clock struct
 pub struct Clock {
     registers: StaticRef<ClockRegisters>,
     client: OptionalCell<&'static ClockClient>,
+    high_requests: Cell<usize>,
+    low_requests: Cell<usize>,
 }
 
 pub trait ClockClient {
@@ -158,12 +182,18 @@ pub trait ClockClient {
 
 pub static mut CLOCK: Clock = Clock::new();
 
+/// Statistics of the sleeps of the chip, timed by the 24-bit RTC, which
+/// counts at 32 kHz from the low-frequency clock in every sleep mode.
+pub static mut SLEEP_STATS: SleepStats = SleepStats::new(32768, 24);
+
 impl Clock {
     /// Constructor
     pub const fn new() -> Clock {
         Clock {
             registers: CLOCK_BASE,
             client: OptionalCell::empty(),
+            high_requests: Cell::new(0),
+            low_requests: Cell::new(0),
         }
     }
 
@@ -276,3 +306,60 @@ impl Clock {
             .write(HfClkStat::SRC.val(clock_source as u32));
     }
 }
+
+impl ClockManager for Clock {
+    type Clock = ManagedClock;
+
+    fn request(&self, clock: ManagedClock, min_frequency: u32) -> ReturnCode {
+        let (requests, frequency) = match clock {
+            ManagedClock::High => (&self.high_requests, HIGH_FREQUENCY),
+            ManagedClock::Low => (&self.low_requests, LOW_FREQUENCY),
+        };
+        if frequency < min_frequency {
+            return ReturnCode::ENOSUPPORT;
+        }
+        if requests.get() == 0 {
+            match clock {
+                ManagedClock::High => self.high_start(),
+                ManagedClock::Low => self.low_start(),
+            }
+        }
+        requests.set(requests.get() + 1);
+        ReturnCode::SUCCESS
+    }
+
+    fn release(&self, clock: ManagedClock) {
+        let requests = match clock {
+            ManagedClock::High => &self.high_requests,
+            ManagedClock::Low => &self.low_requests,
+        };
+        match requests.get() {
+            0 => {}
+            1 => {
+                requests.set(0);
+                match clock {
+                    ManagedClock::High => self.high_stop(),
+                    ManagedClock::Low => self.low_stop(),
+                }
+            }
+            n => requests.set(n - 1),
+        }
+    }
+
+    fn is_requested(&self, clock: ManagedClock) -> bool {
+        match clock {
+            ManagedClock::High => self.high_requests.get() > 0,
+            ManagedClock::Low => self.low_requests.get() > 0,
+        }
+    }
+
+    /// Peripherals that need the HFXO, like the radio, keep the chip out of
+    /// deep sleep.
+    fn sleep_mode(&self) -> SleepMode {
+        if self.high_requests.get() > 0 {
+            SleepMode::Sleep
+        } else {
+            SleepMode::DeepSleep
+        }
+    }
+}
@@ -0,0 +1,547 @@
+//! Analog comparator driver for the nRF52. Uses the COMP or LPCOMP peripheral.
+//!
+//! Both peripherals compare one analog input pin against a reference
+//! (single-ended mode), by default half of VDD:
+//!
+//! * COMP is fast but draws more current. The reference can be VDD scaled in
+//!   steps of 1/16, the 1.2 V bandgap or an external pin (AIN0-AIN7). The
+//!   hysteresis is set by the distance between an upward and a downward
+//!   threshold, of up to 4/64 of the reference.
+//! * LPCOMP is slower but runs at a much lower current, and can keep running
+//!   in System ON sleep. The reference can be VDD scaled in steps of 1/16 or
+//!   an external pin (AIN0 or AIN1). The hysteresis is either off or 50 mV.
+//!
+//! COMP and LPCOMP share their registers and interrupt, so only one of them
+//! can be used at a time. Each also only compares a single input at a time:
+//! starting continuous or interrupt-based comparisons on an input while another
+//! one is being monitored returns `EBUSY`. Window comparisons are not
+//! supported.
+//!
+//! In continuous mode the peripheral keeps running, so a comparison only has
+//! to sample the result. Event-only mode interrupts on both crossings of the
+//! reference.
+//!
+//! The CROSS events can also trigger other peripherals through the PPI, such
+//! as the SAADC sampling on every crossing.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let ac_channels = static_init!(
+//!     [&'static nrf52::comp::ComparatorInput; 2],
+//!     [
+//!         &nrf52::comp::ComparatorInput::AnalogInput0,
+//!         &nrf52::comp::ComparatorInput::AnalogInput1,
+//!     ]
+//! );
+//! let analog_comparator = static_init!(
+//!     capsules::analog_comparator::AnalogComparator<
+//!         'static,
+//!         nrf52::comp::Comparator,
+//!         VirtualMuxAlarm<'static, nrf5x::rtc::Rtc>,
+//!     >,
+//!     capsules::analog_comparator::AnalogComparator::new(
+//!         &nrf52::comp::LPCOMP,
+//!         ac_channels,
+//!         ac_virtual_alarm,
+//!         board_kernel.create_grant(&grant_cap),
+//!     )
+//! );
+//! nrf52::comp::LPCOMP.set_client(analog_comparator);
+//! ac_virtual_alarm.set_client(analog_comparator);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::common::registers::{ReadOnly, ReadWrite, WriteOnly};
+use kernel::common::StaticRef;
+use kernel::hil::analog_comparator;
+use kernel::hil::analog_comparator::{Hysteresis, InterruptMode, Reference, WindowInterruptMode};
+use kernel::ReturnCode;
+
+/// Registers of COMP and LPCOMP. Some registers only exist on one of them.
+#[repr(C)]
+struct CompRegisters {
+    /// Start comparator
+    /// - Address: 0x000 - 0x004
+    tasks_start: WriteOnly<u32, Task::Register>,
+    /// Stop comparator
+    /// - Address: 0x004 - 0x008
+    tasks_stop: WriteOnly<u32, Task::Register>,
+    /// Sample comparator value
+    /// - Address: 0x008 - 0x00c
+    tasks_sample: WriteOnly<u32, Task::Register>,
+    _reserved0: [u32; 61],
+    /// Comparator is ready and output is valid
+    /// - Address: 0x100 - 0x104
+    events_ready: ReadWrite<u32, Event::Register>,
+    /// Downward crossing
+    /// - Address: 0x104 - 0x108
+    events_down: ReadWrite<u32, Event::Register>,
+    /// Upward crossing
+    /// - Address: 0x108 - 0x10c
+    events_up: ReadWrite<u32, Event::Register>,
+    /// Downward or upward crossing
+    /// - Address: 0x10c - 0x110
+    events_cross: ReadWrite<u32, Event::Register>,
+    _reserved1: [u32; 125],
+    /// Enable interrupt
+    /// - Address: 0x304 - 0x308
+    intenset: ReadWrite<u32, Interrupt::Register>,
+    /// Disable interrupt
+    /// - Address: 0x308 - 0x30c
+    intenclr: ReadWrite<u32, Interrupt::Register>,
+    _reserved2: [u32; 61],
+    /// Compare result
+    /// - Address: 0x400 - 0x404
+    result: ReadOnly<u32, CompResult::Register>,
+    _reserved3: [u32; 63],
+    /// Enable COMP or LPCOMP
+    /// - Address: 0x500 - 0x504
+    enable: ReadWrite<u32, Enable::Register>,
+    /// Pin select
+    /// - Address: 0x504 - 0x508
+    psel: ReadWrite<u32, PinSelect::Register>,
+    /// Reference source select
+    /// - Address: 0x508 - 0x50c
+    refsel: ReadWrite<u32, ReferenceSelect::Register>,
+    /// External reference select
+    /// - Address: 0x50c - 0x510
+    extrefsel: ReadWrite<u32, PinSelect::Register>,
+    _reserved4: [u32; 8],
+    /// Threshold configuration for hysteresis unit (COMP only)
+    /// - Address: 0x530 - 0x534
+    th: ReadWrite<u32, Threshold::Register>,
+    /// Mode configuration (COMP only)
+    /// - Address: 0x534 - 0x538
+    mode: ReadWrite<u32, Mode::Register>,
+    /// Comparator hysteresis enable (LPCOMP only)
+    /// - Address: 0x538 - 0x53c
+    hyst: ReadWrite<u32, HysteresisEnable::Register>,
+}
+
+register_bitfields! [u32,
+    /// Start task
+    Task [
+        ENABLE OFFSET(0) NUMBITS(1)
+    ],
+
+    /// Read event
+    Event [
+        READY OFFSET(0) NUMBITS(1)
+    ],
+
+    /// Enable and disable interrupts
+    Interrupt [
+        READY OFFSET(0) NUMBITS(1),
+        DOWN OFFSET(1) NUMBITS(1),
+        UP OFFSET(2) NUMBITS(1),
+        CROSS OFFSET(3) NUMBITS(1)
+    ],
+
+    /// Compare result
+    CompResult [
+        RESULT OFFSET(0) NUMBITS(1) [
+            /// Input voltage is below the reference threshold
+            Below = 0,
+            /// Input voltage is above the reference threshold
+            Above = 1
+        ]
+    ],
+
+    /// Enable COMP or LPCOMP
+    Enable [
+        ENABLE OFFSET(0) NUMBITS(2) [
+            Disabled = 0,
+            LpcompEnabled = 1,
+            CompEnabled = 2
+        ]
+    ],
+
+    /// Analog pin select
+    PinSelect [
+        PSEL OFFSET(0) NUMBITS(3) []
+    ],
+
+    /// Reference source select, the values differ between COMP and LPCOMP
+    ReferenceSelect [
+        REFSEL OFFSET(0) NUMBITS(4) []
+    ],
+
+    /// Threshold configuration for hysteresis unit
+    Threshold [
+        THDOWN OFFSET(0) NUMBITS(6) [],
+        THUP OFFSET(8) NUMBITS(6) []
+    ],
+
+    /// Hysteresis enable
+    HysteresisEnable [
+        HYST OFFSET(0) NUMBITS(1) [
+            NoHyst = 0,
+            Hyst50mV = 1
+        ]
+    ],
+
+    /// Mode configuration
+    Mode [
+        SP OFFSET(0) NUMBITS(2) [
+            Low = 0,
+            Normal = 1,
+            High = 2
+        ],
+        MAIN OFFSET(8) NUMBITS(1) [
+            SingleEnded = 0,
+            Differential = 1
+        ]
+    ]
+];
+
+// COMP references, the thresholds are at (TH + 1) / 64 of them
+const COMP_REFSEL_INT1V2: u32 = 0;
+const COMP_REFSEL_VDD: u32 = 4;
+const COMP_REFSEL_AREF: u32 = 5;
+// LPCOMP references. VDD is scaled in 1/8 steps from 0 to 6, and in 1/16
+// steps for odd sixteenths from 8 to 15.
+const LPCOMP_REFSEL_AREF: u32 = 7;
+const LPCOMP_REFSEL_SIXTEENTHS: u32 = 8;
+
+const COMP_BASE: StaticRef<CompRegisters> =
+    unsafe { StaticRef::new(0x40013000 as *const CompRegisters) };
+
+/// The analog input pins that can be compared.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ComparatorInput {
+    AnalogInput0 = 0,
+    AnalogInput1 = 1,
+    AnalogInput2 = 2,
+    AnalogInput3 = 3,
+    AnalogInput4 = 4,
+    AnalogInput5 = 5,
+    AnalogInput6 = 6,
+    AnalogInput7 = 7,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Kind {
+    Comp,
+    Lpcomp,
+}
+
+pub struct Comparator<'a> {
+    registers: StaticRef<CompRegisters>,
+    kind: Kind,
+    client: OptionalCell<&'a analog_comparator::Client>,
+    /// The input continuous, event-only or interrupt-based comparisons are
+    /// running on
+    active: OptionalCell<ComparatorInput>,
+    /// Whether this peripheral has enabled the shared registers
+    enabled: Cell<bool>,
+    reference: Cell<Reference>,
+    hysteresis: Cell<Hysteresis>,
+}
+
+pub static mut COMP: Comparator = Comparator::new(Kind::Comp);
+pub static mut LPCOMP: Comparator = Comparator::new(Kind::Lpcomp);
+
+impl<'a> Comparator<'a> {
+    const fn new(kind: Kind) -> Comparator<'a> {
+        Comparator {
+            registers: COMP_BASE,
+            kind: kind,
+            client: OptionalCell::empty(),
+            active: OptionalCell::empty(),
+            enabled: Cell::new(false),
+            reference: Cell::new(Reference::ScaledVdd(8)),
+            hysteresis: Cell::new(Hysteresis::Medium),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a analog_comparator::Client) {
+        self.client.set(client);
+    }
+
+    /// Whether this peripheral has enabled the shared registers
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.get()
+    }
+
+    /// Keep comparing `input`, so that every change of the output generates
+    /// a CROSS event, which the PPI can route to a task of another
+    /// peripheral. Returns `EBUSY` if another input is being compared.
+    pub fn enable_events(&self, input: ComparatorInput) -> ReturnCode {
+        analog_comparator::AnalogComparator::start_continuous(self, &input)
+    }
+
+    /// Stop comparing `input` for events.
+    pub fn disable_events(&self, input: ComparatorInput) -> ReturnCode {
+        analog_comparator::AnalogComparator::stop(self, &input)
+    }
+
+    /// The address of the CROSS event register, to connect it through the
+    /// PPI.
+    pub fn cross_event_address(&self) -> u32 {
+        let regs = &*self.registers;
+        &regs.events_cross as *const ReadWrite<u32, Event::Register> as u32
+    }
+
+    // Configures and starts the peripheral on `input`. Returns `EBUSY` if the
+    // other peripheral sharing the registers is in use.
+    fn start(&self, input: ComparatorInput) -> ReturnCode {
+        let regs = &*self.registers;
+        if !self.enabled.get() && !regs.enable.matches_all(Enable::ENABLE::Disabled) {
+            return ReturnCode::EBUSY;
+        }
+
+        regs.tasks_stop.write(Task::ENABLE::SET);
+        match self.kind {
+            Kind::Comp => {
+                regs.enable.write(Enable::ENABLE::CompEnabled);
+                regs.mode
+                    .write(Mode::SP::Normal + Mode::MAIN::SingleEnded);
+            }
+            Kind::Lpcomp => {
+                regs.enable.write(Enable::ENABLE::LpcompEnabled);
+            }
+        }
+        self.configure_reference_and_hysteresis(self.reference.get());
+        self.enabled.set(true);
+        regs.psel.write(PinSelect::PSEL.val(input as u32));
+
+        regs.events_ready.write(Event::READY::CLEAR);
+        regs.events_down.write(Event::READY::CLEAR);
+        regs.events_up.write(Event::READY::CLEAR);
+        regs.events_cross.write(Event::READY::CLEAR);
+        regs.tasks_start.write(Task::ENABLE::SET);
+        ReturnCode::SUCCESS
+    }
+
+    // Returns whether `reference` is available on this peripheral
+    fn supports_reference(&self, reference: Reference) -> bool {
+        match (self.kind, reference) {
+            (_, Reference::ScaledVdd(sixteenths)) => sixteenths >= 1 && sixteenths <= 15,
+            (Kind::Comp, Reference::ExternalPin(pin)) => pin <= 7,
+            (Kind::Comp, Reference::Bandgap) => true,
+            (Kind::Lpcomp, Reference::ExternalPin(pin)) => pin <= 1,
+            _ => false,
+        }
+    }
+
+    // Writes the reference, which must be supported, and hysteresis configuration
+    fn configure_reference_and_hysteresis(&self, reference: Reference) {
+        let regs = &*self.registers;
+        let (refsel, threshold) = match (self.kind, reference) {
+            (Kind::Comp, Reference::ExternalPin(pin)) => {
+                regs.extrefsel.write(PinSelect::PSEL.val(pin as u32));
+                (COMP_REFSEL_AREF, 63)
+            }
+            (Kind::Comp, Reference::Bandgap) => (COMP_REFSEL_INT1V2, 63),
+            (Kind::Comp, Reference::ScaledVdd(sixteenths)) => {
+                (COMP_REFSEL_VDD, sixteenths as u32 * 4 - 1)
+            }
+            (Kind::Lpcomp, Reference::ExternalPin(pin)) => {
+                regs.extrefsel.write(PinSelect::PSEL.val(pin as u32));
+                (LPCOMP_REFSEL_AREF, 0)
+            }
+            (Kind::Lpcomp, Reference::ScaledVdd(sixteenths)) if sixteenths % 2 == 0 => {
+                (sixteenths as u32 / 2 - 1, 0)
+            }
+            (Kind::Lpcomp, Reference::ScaledVdd(sixteenths)) => {
+                (LPCOMP_REFSEL_SIXTEENTHS + sixteenths as u32 / 2, 0)
+            }
+            _ => return,
+        };
+        regs.refsel.write(ReferenceSelect::REFSEL.val(refsel));
+        match self.kind {
+            Kind::Comp => {
+                // Distance between the upward and downward thresholds
+                let distance = match self.hysteresis.get() {
+                    Hysteresis::None => 0,
+                    Hysteresis::Low => 1,
+                    Hysteresis::Medium => 2,
+                    Hysteresis::High => 4,
+                };
+                regs.th.write(
+                    Threshold::THUP.val(threshold)
+                        + Threshold::THDOWN.val(threshold.saturating_sub(distance)),
+                );
+            }
+            Kind::Lpcomp => {
+                regs.hyst.write(match self.hysteresis.get() {
+                    Hysteresis::None => HysteresisEnable::HYST::NoHyst,
+                    _ => HysteresisEnable::HYST::Hyst50mV,
+                });
+            }
+        }
+    }
+
+    fn disable(&self) {
+        let regs = &*self.registers;
+        regs.intenclr.write(
+            Interrupt::READY::SET
+                + Interrupt::DOWN::SET
+                + Interrupt::UP::SET
+                + Interrupt::CROSS::SET,
+        );
+        regs.tasks_stop.write(Task::ENABLE::SET);
+        regs.enable.write(Enable::ENABLE::Disabled);
+        self.enabled.set(false);
+    }
+
+    /// COMP/LPCOMP Interrupt handler
+    pub fn handle_interrupt(&self) {
+        let regs = &*self.registers;
+        if !self.enabled.get() {
+            return;
+        }
+
+        let crossed = regs.events_down.is_set(Event::READY)
+            || regs.events_up.is_set(Event::READY)
+            || regs.events_cross.is_set(Event::READY);
+        regs.events_ready.write(Event::READY::CLEAR);
+        regs.events_down.write(Event::READY::CLEAR);
+        regs.events_up.write(Event::READY::CLEAR);
+        regs.events_cross.write(Event::READY::CLEAR);
+
+        if crossed {
+            self.active.map(|input| {
+                let channel = *input as usize;
+                self.client.map(|client| client.fired(channel));
+            });
+        }
+    }
+}
+
+impl<'a> analog_comparator::AnalogComparator for Comparator<'a> {
+    type Channel = ComparatorInput;
+
+    /// Do a single comparison against the reference. Always false while
+    /// interrupt-based comparisons are running on another input.
+    fn comparison(&self, channel: &Self::Channel) -> bool {
+        let regs = &*self.registers;
+        let running = self.active.map_or(false, |input| *input == *channel);
+        if self.active.is_some() && !running {
+            return false;
+        }
+        if !running {
+            if self.start(*channel) != ReturnCode::SUCCESS {
+                return false;
+            }
+            while !regs.events_ready.is_set(Event::READY) {}
+        }
+
+        regs.tasks_sample.write(Task::ENABLE::SET);
+        let result = regs.result.matches_all(CompResult::RESULT::Above);
+
+        if !running {
+            self.disable();
+        }
+        result
+    }
+
+    /// Start interrupt-based comparisons
+    fn start_comparing(&self, channel: &Self::Channel, mode: InterruptMode) -> ReturnCode {
+        let regs = &*self.registers;
+        if self.active.map_or(false, |input| *input != *channel) {
+            return ReturnCode::EBUSY;
+        }
+
+        let result = self.start(*channel);
+        if result != ReturnCode::SUCCESS {
+            return result;
+        }
+        self.active.set(*channel);
+        regs.intenset.write(match mode {
+            InterruptMode::VpGtVn => Interrupt::UP::SET,
+            InterruptMode::VpLtVn => Interrupt::DOWN::SET,
+            InterruptMode::Toggle => Interrupt::CROSS::SET,
+        });
+        ReturnCode::SUCCESS
+    }
+
+    /// Stop interrupt-based comparisons
+    fn stop_comparing(&self, channel: &Self::Channel) -> ReturnCode {
+        match self.active.take() {
+            Some(input) if input == *channel => {
+                self.disable();
+                ReturnCode::SUCCESS
+            }
+            Some(input) => {
+                self.active.set(input);
+                ReturnCode::EINVAL
+            }
+            None => ReturnCode::SUCCESS,
+        }
+    }
+
+    /// Keep the peripheral running on `channel`, so comparisons don't have to
+    /// wait for it to start
+    fn start_continuous(&self, channel: &Self::Channel) -> ReturnCode {
+        if self.active.map_or(false, |input| *input != *channel) {
+            return ReturnCode::EBUSY;
+        }
+        let result = self.start(*channel);
+        if result == ReturnCode::SUCCESS {
+            self.active.set(*channel);
+        }
+        result
+    }
+
+    fn start_event_only(&self, channel: &Self::Channel) -> ReturnCode {
+        self.start_comparing(channel, InterruptMode::Toggle)
+    }
+
+    fn stop(&self, channel: &Self::Channel) -> ReturnCode {
+        self.stop_comparing(channel)
+    }
+
+    /// Both peripherals keep running in System ON sleep, and their interrupts
+    /// wake the chip up, so there is nothing to configure.
+    fn enable_in_sleep(&self, _channel: &Self::Channel) -> ReturnCode {
+        ReturnCode::SUCCESS
+    }
+
+    fn disable_in_sleep(&self, _channel: &Self::Channel) -> ReturnCode {
+        ReturnCode::SUCCESS
+    }
+
+    /// The reference is shared by all inputs, as only one input is compared at
+    /// a time.
+    fn set_reference(&self, _channel: &Self::Channel, reference: Reference) -> ReturnCode {
+        if !self.supports_reference(reference) {
+            return ReturnCode::ENOSUPPORT;
+        }
+        self.reference.set(reference);
+        if self.enabled.get() {
+            let regs = &*self.registers;
+            regs.tasks_stop.write(Task::ENABLE::SET);
+            self.configure_reference_and_hysteresis(reference);
+            regs.tasks_start.write(Task::ENABLE::SET);
+        }
+        ReturnCode::SUCCESS
+    }
+
+    fn set_hysteresis(&self, _channel: &Self::Channel, level: Hysteresis) -> ReturnCode {
+        self.hysteresis.set(level);
+        if self.enabled.get() {
+            let regs = &*self.registers;
+            regs.tasks_stop.write(Task::ENABLE::SET);
+            self.configure_reference_and_hysteresis(self.reference.get());
+            regs.tasks_start.write(Task::ENABLE::SET);
+        }
+        ReturnCode::SUCCESS
+    }
+
+    /// Window comparisons are not supported
+    fn window_comparison(&self, _window: usize) -> bool {
+        false
+    }
+
+    fn enable_window_interrupts(&self, _window: usize, _mode: WindowInterruptMode) -> ReturnCode {
+        ReturnCode::ENOSUPPORT
+    }
+
+    fn disable_window_interrupts(&self, _window: usize) -> ReturnCode {
+        ReturnCode::ENOSUPPORT
+    }
+}
@@ -1,4 +1,5 @@
 use cortexm4::{generic_isr, hard_fault_handler, nvic, svc_handler, systick_handler};
+use wdt;
 
 /*
  * Adapted from crt1.c which was relicensed by the original author from
@@ -78,9 +79,22 @@ pub static BASE_VECTORS: [unsafe extern "C" fn(); 16] = [
     systick_handler,
 ];
 
+/// The interrupt vectors, with the watchdog handled outside of the kernel
+/// loop.
+#[repr(C)]
+pub struct Irqs {
+    before_wdt: [unsafe extern "C" fn(); 16],
+    wdt: unsafe extern "C" fn(),
+    after_wdt: [unsafe extern "C" fn(); 63],
+}
+
 #[link_section = ".vectors"]
 #[used] // Ensures that the symbol is kept until the final binary
-pub static IRQS: [unsafe extern "C" fn(); 80] = [generic_isr; 80];
+pub static IRQS: Irqs = Irqs {
+    before_wdt: [generic_isr; 16],
+    wdt: wdt::wdt_handler,
+    after_wdt: [generic_isr; 63],
+};
 
 #[no_mangle]
 pub unsafe extern "C" fn init() {
@@ -1,18 +1,24 @@
 //! Implementation of I2C for nRF52 using EasyDMA.
 //!
-//! This module supports nRF52's two I2C master (`TWIM`) peripherals,
-//! but not I2C slave (`TWIS`).
+//! This module supports nRF52's two I2C master (`TWIM`) and two I2C slave
+//! (`TWIS`) peripherals. The master and slave of an instance share their
+//! registers, so only one of them can be enabled at a time.
 //!
 //! - Author: Jay Kickliter
 //! - Author: Andrew Thompson
 //! - Date: Nov 4, 2017
 
+use core::cell::Cell;
+use core::cmp;
+use cortexm4;
 use kernel::common::cells::OptionalCell;
 use kernel::common::cells::TakeCell;
 use kernel::common::cells::VolatileCell;
-use kernel::common::registers::{ReadWrite, WriteOnly};
+use kernel::common::registers::{ReadOnly, ReadWrite, WriteOnly};
 use kernel::common::StaticRef;
 use kernel::hil;
+use kernel::ReturnCode;
+use nrf5x;
 use nrf5x::pinmux::Pinmux;
 
 /// Uninitialized `TWIM` instances.
@@ -23,6 +29,10 @@ const INSTANCES: [StaticRef<TwimRegisters>; 2] = unsafe {
     ]
 };
 
+/// Busy-wait iterations of half a clock period of bus recovery, about 5 us
+/// at 64 MHz.
+const RECOVERY_HALF_PERIOD: usize = 80;
+
 /// An I2C master device.
 ///
 /// A `TWIM` instance wraps a `registers::TWIM` together with
@@ -77,6 +87,29 @@ impl TWIM {
         self.registers.enable.write(ENABLE::ENABLE::Disable);
     }
 
+    /// The GPIO pins of SCL and SDA, if they are connected.
+    fn bus_pins(&self) -> Option<(&'static nrf5x::gpio::GPIOPin, &'static nrf5x::gpio::GPIOPin)> {
+        let scl: u32 = self.registers.psel_scl.get().into();
+        let sda: u32 = self.registers.psel_sda.get().into();
+        if scl < 32 && sda < 32 {
+            unsafe {
+                Some((
+                    &nrf5x::gpio::PORT[scl as usize],
+                    &nrf5x::gpio::PORT[sda as usize],
+                ))
+            }
+        } else {
+            None
+        }
+    }
+
+    /// Whether a slave holds SCL or SDA low.
+    fn bus_stuck(&self) -> bool {
+        self.bus_pins().map_or(false, |(scl, sda)| {
+            !hil::gpio::Pin::read(scl) || !hil::gpio::Pin::read(sda)
+        })
+    }
+
     pub fn handle_interrupt(&self) {
         if self.registers.events_stopped.is_set(EVENT::EVENT) {
             self.registers.events_stopped.write(EVENT::EVENT::CLEAR);
@@ -101,6 +134,8 @@ impl TWIM {
                         hil::i2c::Error::AddressNak
                     } else if errorsrc.is_set(ERRORSRC::DNACK) {
                         hil::i2c::Error::DataNak
+                    } else if self.bus_stuck() {
+                        hil::i2c::Error::BusStuck
                     } else {
                         hil::i2c::Error::CommandComplete
                     };
@@ -198,26 +233,51 @@ impl hil::i2c::I2CMaster for TWIM {
         self.registers.tasks_startrx.write(TASK::TASK::SET);
         self.buf.replace(buffer);
     }
+
+    fn recover_bus(&self) -> ReturnCode {
+        let (scl, sda) = match self.bus_pins() {
+            Some(pins) => pins,
+            None => return ReturnCode::ENOSUPPORT,
+        };
+
+        // The pins are GPIO pins while the TWIM is disabled
+        let enabled = self.is_enabled();
+        self.disable();
+        let recovered = hil::i2c::recover_bus_with_gpio(scl, sda, &|| {
+            for _ in 0..RECOVERY_HALF_PERIOD {
+                cortexm4::support::nop();
+            }
+        });
+        if enabled {
+            self.enable();
+        }
+
+        if recovered {
+            ReturnCode::SUCCESS
+        } else {
+            ReturnCode::FAIL
+        }
+    }
 }
 
 impl hil::i2c::I2CSlave for TWIM {
     fn enable(&self) {
-        panic!("I2C slave not implemented for nRF52");
+        panic!("I2C slave not implemented for nRF52 TWIM, use TWIS");
     }
     fn disable(&self) {
-        panic!("I2C slave not implemented for nRF52");
+        panic!("I2C slave not implemented for nRF52 TWIM, use TWIS");
     }
     fn set_address(&self, _addr: u8) {
-        panic!("I2C slave not implemented for nRF52");
+        panic!("I2C slave not implemented for nRF52 TWIM, use TWIS");
     }
     fn write_receive(&self, _data: &'static mut [u8], _max_len: u8) {
-        panic!("I2C slave not implemented for nRF52");
+        panic!("I2C slave not implemented for nRF52 TWIM, use TWIS");
     }
     fn read_send(&self, _data: &'static mut [u8], _max_len: u8) {
-        panic!("I2C slave not implemented for nRF52");
+        panic!("I2C slave not implemented for nRF52 TWIM, use TWIS");
     }
     fn listen(&self) {
-        panic!("I2C slave not implemented for nRF52");
+        panic!("I2C slave not implemented for nRF52 TWIM, use TWIS");
     }
 }
 
@@ -228,6 +288,247 @@ pub static mut TWIM0: TWIM = TWIM::new(INSTANCES[0]);
 /// I2C master instace 1.
 pub static mut TWIM1: TWIM = TWIM::new(INSTANCES[1]);
 
+/// Uninitialized `TWIS` instances, at the same addresses as the `TWIM`s.
+const TWIS_INSTANCES: [StaticRef<TwisRegisters>; 2] = unsafe {
+    [
+        StaticRef::new(0x40003000 as *const TwisRegisters),
+        StaticRef::new(0x40004000 as *const TwisRegisters),
+    ]
+};
+
+/// An I2C slave device.
+///
+/// When a master addresses it, the `TWIS` suspends the transfer, holding
+/// SCL low, until it has a buffer for it. The buffers are used by EasyDMA
+/// while they are in the driver.
+pub struct TWIS {
+    registers: StaticRef<TwisRegisters>,
+    client: OptionalCell<&'static hil::i2c::I2CHwSlaveClient>,
+    address: Cell<u8>,
+    clock_stretching: Cell<bool>,
+    rx_buf: TakeCell<'static, [u8]>,
+    rx_len: Cell<u8>,
+    tx_buf: TakeCell<'static, [u8]>,
+    tx_len: Cell<u8>,
+    /// The transfer a master started, while the driver waits for a buffer
+    waiting: Cell<Option<hil::i2c::SlaveTransmissionType>>,
+    /// The transfer in progress
+    active: Cell<Option<hil::i2c::SlaveTransmissionType>>,
+}
+
+impl TWIS {
+    const fn new(registers: StaticRef<TwisRegisters>) -> TWIS {
+        TWIS {
+            registers: registers,
+            client: OptionalCell::empty(),
+            address: Cell::new(0),
+            clock_stretching: Cell::new(true),
+            rx_buf: TakeCell::empty(),
+            rx_len: Cell::new(0),
+            tx_buf: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            waiting: Cell::new(None),
+            active: Cell::new(None),
+        }
+    }
+
+    pub fn set_client(&self, client: &'static hil::i2c::I2CHwSlaveClient) {
+        self.client.set(client);
+    }
+
+    /// Configures an already constructed `TWIS`.
+    pub fn configure(&self, scl: Pinmux, sda: Pinmux) {
+        self.registers.psel_scl.set(scl);
+        self.registers.psel_sda.set(sda);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.registers
+            .enable
+            .matches_all(ENABLE_TWIS::ENABLE::Enable)
+    }
+
+    /// Points EasyDMA at the receive buffer, if there is one.
+    fn prepare_rx(&self) -> bool {
+        self.rx_buf.map_or(false, |buf| {
+            let len = cmp::min(self.rx_len.get() as usize, buf.len());
+            self.registers.rxd_ptr.set(buf.as_mut_ptr());
+            self.registers
+                .rxd_maxcnt
+                .write(MAXCNT::MAXCNT.val(len as u32));
+            self.registers.tasks_preparerx.write(TASK::TASK::SET);
+            true
+        })
+    }
+
+    /// Points EasyDMA at the transmit buffer, if there is one.
+    fn prepare_tx(&self) -> bool {
+        self.tx_buf.map_or(false, |buf| {
+            let len = cmp::min(self.tx_len.get() as usize, buf.len());
+            self.registers.txd_ptr.set(buf.as_mut_ptr());
+            self.registers
+                .txd_maxcnt
+                .write(MAXCNT::MAXCNT.val(len as u32));
+            self.registers.tasks_preparetx.write(TASK::TASK::SET);
+            true
+        })
+    }
+
+    /// A master addressed us, continue if there is a buffer or ask for one.
+    fn start(&self, transmission_type: hil::i2c::SlaveTransmissionType) {
+        self.client
+            .map(|client| client.address_matched(transmission_type));
+
+        let has_buffer = match transmission_type {
+            hil::i2c::SlaveTransmissionType::Write => self.rx_buf.is_some(),
+            hil::i2c::SlaveTransmissionType::Read => self.tx_buf.is_some(),
+        };
+        if has_buffer {
+            self.active.set(Some(transmission_type));
+            self.registers.tasks_resume.write(TASK::TASK::SET);
+        } else {
+            self.waiting.set(Some(transmission_type));
+            self.client.map(|client| match transmission_type {
+                hil::i2c::SlaveTransmissionType::Write => client.write_expected(),
+                hil::i2c::SlaveTransmissionType::Read => client.read_expected(),
+            });
+        }
+    }
+
+    pub fn handle_interrupt(&self) {
+        if self.registers.events_write.is_set(EVENT::EVENT) {
+            self.registers.events_write.write(EVENT::EVENT::CLEAR);
+            self.start(hil::i2c::SlaveTransmissionType::Write);
+        }
+
+        if self.registers.events_read.is_set(EVENT::EVENT) {
+            self.registers.events_read.write(EVENT::EVENT::CLEAR);
+            self.start(hil::i2c::SlaveTransmissionType::Read);
+        }
+
+        if self.registers.events_error.is_set(EVENT::EVENT) {
+            // Overflows and overreads only mean that the master transferred
+            // more than the buffer, the transfer still ends with STOPPED.
+            self.registers.events_error.write(EVENT::EVENT::CLEAR);
+            self.registers.errorsrc.write(
+                ERRORSRC_TWIS::OVERFLOW::SET
+                    + ERRORSRC_TWIS::DNACK::SET
+                    + ERRORSRC_TWIS::OVERREAD::SET,
+            );
+        }
+
+        if self.registers.events_stopped.is_set(EVENT::EVENT) {
+            self.registers.events_stopped.write(EVENT::EVENT::CLEAR);
+            self.waiting.set(None);
+            match self.active.take() {
+                Some(hil::i2c::SlaveTransmissionType::Write) => {
+                    let amount = self.registers.rxd_amount.get() as u8;
+                    self.rx_buf.take().map(|buf| {
+                        self.client.map(move |client| {
+                            client.command_complete(
+                                buf,
+                                amount,
+                                hil::i2c::SlaveTransmissionType::Write,
+                            );
+                        });
+                    });
+                }
+                Some(hil::i2c::SlaveTransmissionType::Read) => {
+                    let amount = self.registers.txd_amount.get() as u8;
+                    self.tx_buf.take().map(|buf| {
+                        self.client.map(move |client| {
+                            client.command_complete(
+                                buf,
+                                amount,
+                                hil::i2c::SlaveTransmissionType::Read,
+                            );
+                        });
+                    });
+                }
+                None => {}
+            }
+        }
+
+        // We can blindly clear the following events since we're not using them.
+        self.registers.events_rxstarted.write(EVENT::EVENT::CLEAR);
+        self.registers.events_txstarted.write(EVENT::EVENT::CLEAR);
+    }
+}
+
+impl hil::i2c::I2CSlave for TWIS {
+    fn enable(&self) {
+        self.registers.intenset.write(
+            INTE_TWIS::WRITE::SET
+                + INTE_TWIS::READ::SET
+                + INTE_TWIS::STOPPED::SET
+                + INTE_TWIS::ERROR::SET,
+        );
+    }
+
+    fn disable(&self) {
+        self.registers.enable.write(ENABLE_TWIS::ENABLE::Disable);
+        self.registers.intenclr.set(!0);
+    }
+
+    fn set_address(&self, addr: u8) {
+        self.address.set(addr);
+    }
+
+    fn write_receive(&self, data: &'static mut [u8], max_len: u8) {
+        self.rx_buf.replace(data);
+        self.rx_len.set(max_len);
+        if self.is_enabled() {
+            self.prepare_rx();
+            if let Some(hil::i2c::SlaveTransmissionType::Write) = self.waiting.get() {
+                self.waiting.set(None);
+                self.active
+                    .set(Some(hil::i2c::SlaveTransmissionType::Write));
+                self.registers.tasks_resume.write(TASK::TASK::SET);
+            }
+        }
+    }
+
+    fn read_send(&self, data: &'static mut [u8], max_len: u8) {
+        self.tx_buf.replace(data);
+        self.tx_len.set(max_len);
+        if self.is_enabled() {
+            self.prepare_tx();
+            if let Some(hil::i2c::SlaveTransmissionType::Read) = self.waiting.get() {
+                self.waiting.set(None);
+                self.active.set(Some(hil::i2c::SlaveTransmissionType::Read));
+                self.registers.tasks_resume.write(TASK::TASK::SET);
+            }
+        }
+    }
+
+    fn listen(&self) {
+        self.registers.address[0].set((self.address.get() & 0x7f) as u32);
+        self.registers.config.write(CONFIG_TWIS::ADDRESS0::SET);
+        if self.clock_stretching.get() {
+            self.registers
+                .shorts
+                .write(SHORTS_TWIS::WRITE_SUSPEND::SET + SHORTS_TWIS::READ_SUSPEND::SET);
+        } else {
+            self.registers.shorts.set(0);
+        }
+        self.registers.enable.write(ENABLE_TWIS::ENABLE::Enable);
+
+        // Without clock stretching, the buffers must be ready beforehand
+        self.prepare_rx();
+        self.prepare_tx();
+    }
+
+    fn set_clock_stretching(&self, stretch: bool) -> ReturnCode {
+        self.clock_stretching.set(stretch);
+        ReturnCode::SUCCESS
+    }
+}
+
+/// I2C slave instance 0.
+pub static mut TWIS0: TWIS = TWIS::new(TWIS_INSTANCES[0]);
+/// I2C slave instance 1.
+pub static mut TWIS1: TWIS = TWIS::new(TWIS_INSTANCES[1]);
+
 // The SPI0_TWI0 and SPI1_TWI1 interrupts are dispatched to the
 // correct handler by the service_pending_interrupts() routine in
 // chip.rs based on which peripheral is enabled.
@@ -312,6 +613,85 @@ struct TwimRegisters {
     address: ReadWrite<u32, ADDRESS::Register>,
 }
 
+#[repr(C)]
+struct TwisRegisters {
+    _reserved0: [u32; 5],
+    /// Stop TWI transaction
+    tasks_stop: WriteOnly<u32, TASK::Register>,
+    _reserved1: [u32; 1],
+    /// Suspend TWI transaction
+    tasks_suspend: WriteOnly<u32, TASK::Register>,
+    /// Resume TWI transaction
+    tasks_resume: WriteOnly<u32, TASK::Register>,
+    _reserved2: [u32; 3],
+    /// Prepare the TWI slave to respond to a write command
+    tasks_preparerx: WriteOnly<u32, TASK::Register>,
+    /// Prepare the TWI slave to respond to a read command
+    tasks_preparetx: WriteOnly<u32, TASK::Register>,
+    _reserved3: [u32; 51],
+    /// TWI stopped
+    events_stopped: ReadWrite<u32, EVENT::Register>,
+    _reserved4: [u32; 7],
+    /// TWI error
+    events_error: ReadWrite<u32, EVENT::Register>,
+    _reserved5: [u32; 9],
+    /// Receive sequence started
+    events_rxstarted: ReadWrite<u32, EVENT::Register>,
+    /// Transmit sequence started
+    events_txstarted: ReadWrite<u32, EVENT::Register>,
+    _reserved6: [u32; 4],
+    /// Write command received
+    events_write: ReadWrite<u32, EVENT::Register>,
+    /// Read command received
+    events_read: ReadWrite<u32, EVENT::Register>,
+    _reserved7: [u32; 37],
+    /// Shortcut register
+    shorts: ReadWrite<u32, SHORTS_TWIS::Register>,
+    _reserved8: [u32; 63],
+    /// Enable or disable interrupt
+    inten: ReadWrite<u32, INTE_TWIS::Register>,
+    /// Enable interrupt
+    intenset: ReadWrite<u32, INTE_TWIS::Register>,
+    /// Disable interrupt
+    intenclr: ReadWrite<u32, INTE_TWIS::Register>,
+    _reserved9: [u32; 113],
+    /// Error source
+    errorsrc: ReadWrite<u32, ERRORSRC_TWIS::Register>,
+    /// Which of the addresses matched
+    match_: ReadOnly<u32>,
+    _reserved10: [u32; 10],
+    /// Enable TWIS
+    enable: ReadWrite<u32, ENABLE_TWIS::Register>,
+    _reserved11: [u32; 1],
+    /// Pin select for SCL signal
+    psel_scl: VolatileCell<Pinmux>,
+    /// Pin select for SDA signal
+    psel_sda: VolatileCell<Pinmux>,
+    _reserved12: [u32; 9],
+    /// Data pointer
+    rxd_ptr: VolatileCell<*mut u8>,
+    /// Maximum number of bytes in receive buffer
+    rxd_maxcnt: ReadWrite<u32, MAXCNT::Register>,
+    /// Number of bytes transferred in the last transaction
+    rxd_amount: ReadOnly<u32>,
+    _reserved13: [u32; 1],
+    /// Data pointer
+    txd_ptr: VolatileCell<*mut u8>,
+    /// Maximum number of bytes in transmit buffer
+    txd_maxcnt: ReadWrite<u32, MAXCNT::Register>,
+    /// Number of bytes transferred in the last transaction
+    txd_amount: ReadOnly<u32>,
+    _reserved14: [u32; 14],
+    /// TWI slave addresses
+    address: [ReadWrite<u32>; 2],
+    _reserved15: [u32; 1],
+    /// Which of the addresses to match
+    config: ReadWrite<u32, CONFIG_TWIS::Register>,
+    _reserved16: [u32; 10],
+    /// Over-read character, sent when a master reads past the buffer
+    orc: ReadWrite<u32>,
+}
+
 register_bitfields![u32,
     SHORTS [
         /// Shortcut between EVENTS_LASTTX event and TASKS_STARTRX task
@@ -430,5 +810,46 @@ register_bitfields![u32,
     ADDRESS [
         /// Address used in the TWI transfer
         ADDRESS OFFSET(0) NUMBITS(7)
+    ],
+    SHORTS_TWIS [
+        /// Shortcut between EVENTS_WRITE event and TASKS_SUSPEND task
+        WRITE_SUSPEND 13,
+        /// Shortcut between EVENTS_READ event and TASKS_SUSPEND task
+        READ_SUSPEND 14
+    ],
+    INTE_TWIS [
+        /// Interrupt on EVENTS_STOPPED event
+        STOPPED 1,
+        /// Interrupt on EVENTS_ERROR event
+        ERROR 9,
+        /// Interrupt on EVENTS_RXSTARTED event
+        RXSTARTED 19,
+        /// Interrupt on EVENTS_TXSTARTED event
+        TXSTARTED 20,
+        /// Interrupt on EVENTS_WRITE event
+        WRITE 25,
+        /// Interrupt on EVENTS_READ event
+        READ 26
+    ],
+    ERRORSRC_TWIS [
+        /// RX buffer overflow detected, and prevented (write '1' to clear)
+        OVERFLOW 0,
+        /// NACK sent after receiving a data byte (write '1' to clear)
+        DNACK 2,
+        /// TX buffer over-read detected, and prevented (write '1' to clear)
+        OVERREAD 3
+    ],
+    ENABLE_TWIS [
+        /// Enable or disable TWIS
+        ENABLE OFFSET(0) NUMBITS(4) [
+            Disable = 0,
+            Enable = 9
+        ]
+    ],
+    CONFIG_TWIS [
+        /// Match ADDRESS[0]
+        ADDRESS0 0,
+        /// Match ADDRESS[1]
+        ADDRESS1 1
     ]
 ];
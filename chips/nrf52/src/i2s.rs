@@ -0,0 +1,473 @@
+//! I2S driver for the nRF52.
+//!
+//! The I2S peripheral runs as the master of the bus, with 16-bit samples in
+//! the I2S format. Its EasyDMA reads or writes one 32-bit word per stereo
+//! frame, or per two mono frames, so buffers must be word aligned and hold an
+//! even number of samples.
+//!
+//! The peripheral reads the pointer to the next buffer when it starts on a
+//! buffer, and signals this with the TXPTRUPD or RXPTRUPD event. The driver
+//! then writes the pointer to the buffer after it, and passes the buffer the
+//! peripheral was working on back to the client. If no new buffer was
+//! provided in time, the peripheral starts on the same buffer again.
+//!
+//! The sample rate is derived from the 32 MHz clock, through the master clock
+//! generator and the ratio between the master clock and the sample rate. The
+//! combination closest to the configured sample rate is used, if it is
+//! within 2%.
+
+use clock::{self, ManagedClock};
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell, VolatileCell};
+use kernel::common::registers::{ReadWrite, WriteOnly};
+use kernel::common::StaticRef;
+use kernel::hil::i2s::{self, Channels, Config};
+use kernel::{ClockManager, ReturnCode};
+use nrf5x::pinmux::Pinmux;
+
+const I2S_BASE: StaticRef<I2sRegisters> =
+    unsafe { StaticRef::new(0x40025000 as *const I2sRegisters) };
+
+pub static mut I2S: I2s = I2s::new(I2S_BASE);
+
+#[repr(C)]
+struct I2sRegisters {
+    /// Starts continuous I2S transfer
+    tasks_start: WriteOnly<u32, TASK::Register>,
+    /// Stops I2S transfer
+    tasks_stop: WriteOnly<u32, TASK::Register>,
+    _reserved0: [u8; 252],
+    /// The RXD.PTR register has been copied to internal double-buffers
+    events_rxptrupd: ReadWrite<u32, EVENT::Register>,
+    /// I2S transfer stopped
+    events_stopped: ReadWrite<u32, EVENT::Register>,
+    _reserved1: [u8; 8],
+    /// The TXD.PTR register has been copied to internal double-buffers
+    events_txptrupd: ReadWrite<u32, EVENT::Register>,
+    _reserved2: [u8; 488],
+    /// Enable or disable interrupt
+    inten: ReadWrite<u32, INTEN::Register>,
+    /// Enable interrupt
+    intenset: ReadWrite<u32, INTEN::Register>,
+    /// Disable interrupt
+    intenclr: ReadWrite<u32, INTEN::Register>,
+    _reserved3: [u8; 500],
+    /// Enable I2S module
+    enable: ReadWrite<u32, ENABLE::Register>,
+    /// I2S mode
+    config_mode: ReadWrite<u32, MODE::Register>,
+    /// Reception (RX) enable
+    config_rxen: ReadWrite<u32, ENABLE::Register>,
+    /// Transmission (TX) enable
+    config_txen: ReadWrite<u32, ENABLE::Register>,
+    /// Master clock generator enable
+    config_mcken: ReadWrite<u32, ENABLE::Register>,
+    /// Master clock generator frequency
+    config_mckfreq: ReadWrite<u32>,
+    /// MCK / LRCK ratio
+    config_ratio: ReadWrite<u32, RATIO::Register>,
+    /// Sample width
+    config_swidth: ReadWrite<u32, SWIDTH::Register>,
+    /// Alignment of sample within a frame
+    config_align: ReadWrite<u32, ALIGN::Register>,
+    /// Frame format
+    config_format: ReadWrite<u32, FORMAT::Register>,
+    /// Enable channels
+    config_channels: ReadWrite<u32, CHANNELS::Register>,
+    _reserved4: [u8; 12],
+    /// Receive buffer RAM start address
+    rxd_ptr: VolatileCell<*mut i16>,
+    _reserved5: [u8; 4],
+    /// Transmit buffer RAM start address
+    txd_ptr: VolatileCell<*const i16>,
+    _reserved6: [u8; 12],
+    /// Size of RXD and TXD buffers in 32-bit words
+    rxtxd_maxcnt: ReadWrite<u32, MAXCNT::Register>,
+    _reserved7: [u8; 12],
+    /// Pin select for MCK signal
+    psel_mck: ReadWrite<u32>,
+    /// Pin select for SCK signal
+    psel_sck: ReadWrite<u32>,
+    /// Pin select for LRCK signal
+    psel_lrck: ReadWrite<u32>,
+    /// Pin select for SDIN signal
+    psel_sdin: ReadWrite<u32>,
+    /// Pin select for SDOUT signal
+    psel_sdout: ReadWrite<u32>,
+}
+
+register_bitfields![u32,
+    INTEN [
+        /// Enable or disable interrupt on EVENTS_RXPTRUPD event
+        RXPTRUPD 1,
+        /// Enable or disable interrupt on EVENTS_STOPPED event
+        STOPPED 2,
+        /// Enable or disable interrupt on EVENTS_TXPTRUPD event
+        TXPTRUPD 5
+    ],
+    ENABLE [
+        ENABLE 0
+    ],
+    MODE [
+        MODE OFFSET(0) NUMBITS(1) [
+            Master = 0,
+            Slave = 1
+        ]
+    ],
+    RATIO [
+        RATIO OFFSET(0) NUMBITS(4) []
+    ],
+    SWIDTH [
+        SWIDTH OFFSET(0) NUMBITS(2) [
+            Bit8 = 0,
+            Bit16 = 1,
+            Bit24 = 2
+        ]
+    ],
+    ALIGN [
+        ALIGN OFFSET(0) NUMBITS(1) [
+            Left = 0,
+            Right = 1
+        ]
+    ],
+    FORMAT [
+        FORMAT OFFSET(0) NUMBITS(1) [
+            I2S = 0,
+            Aligned = 1
+        ]
+    ],
+    CHANNELS [
+        CHANNELS OFFSET(0) NUMBITS(2) [
+            Stereo = 0,
+            Left = 1,
+            Right = 2
+        ]
+    ],
+    MAXCNT [
+        MAXCNT OFFSET(0) NUMBITS(14) []
+    ],
+    EVENT [
+        EVENT 0
+    ],
+    TASK [
+        TASK 0
+    ]
+];
+
+/// Value of a pin select register for a disconnected signal
+const PSEL_DISCONNECTED: u32 = 0xffffffff;
+
+/// The settings of the master clock generator. The master clock is
+/// 32 MHz * MCKFREQ / 2^32.
+const MCKFREQS: [u32; 13] = [
+    0x20000000, 0x18000000, 0x16000000, 0x11000000, 0x10000000, 0x0c000000, 0x0b000000,
+    0x08800000, 0x08400000, 0x08000000, 0x06000000, 0x04100000, 0x020c0000,
+];
+
+/// The ratios between the master clock and the sample rate, in the order of
+/// their register values. The ratio is at least twice the sample width.
+const RATIOS: [u32; 9] = [32, 48, 64, 96, 128, 192, 256, 384, 512];
+
+/// The longest buffer, in 32-bit words
+const MAX_WORDS: usize = (1 << 14) - 1;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Direction {
+    Transmit,
+    Receive,
+}
+
+pub struct I2s {
+    registers: StaticRef<I2sRegisters>,
+    client: OptionalCell<&'static i2s::Client>,
+    channels: Cell<Channels>,
+    mckfreq: Cell<u32>,
+    ratio: Cell<usize>,
+    sample_rate: Cell<u32>,
+    /// The direction of the stream, if the peripheral is streaming
+    direction: Cell<Option<Direction>>,
+    stopping: Cell<bool>,
+    /// The length of the buffers of the stream
+    len: Cell<usize>,
+    /// The buffer the peripheral is working on
+    active: TakeCell<'static, [i16]>,
+    /// The buffer whose pointer was written, and that comes next
+    pending: TakeCell<'static, [i16]>,
+    /// The buffer that comes after the pending one
+    spare: TakeCell<'static, [i16]>,
+}
+
+impl I2s {
+    const fn new(registers: StaticRef<I2sRegisters>) -> I2s {
+        I2s {
+            registers: registers,
+            client: OptionalCell::empty(),
+            channels: Cell::new(Channels::Stereo),
+            // 1.375 MHz / 32, about 43 kHz
+            mckfreq: Cell::new(0x0b000000),
+            ratio: Cell::new(0),
+            sample_rate: Cell::new(42968),
+            direction: Cell::new(None),
+            stopping: Cell::new(false),
+            len: Cell::new(0),
+            active: TakeCell::empty(),
+            pending: TakeCell::empty(),
+            spare: TakeCell::empty(),
+        }
+    }
+
+    /// Sets the pins of the peripheral. MCK is only needed by devices without
+    /// their own master clock, SDOUT for transmitting, and SDIN for
+    /// receiving.
+    pub fn set_pins(
+        &self,
+        sck: Pinmux,
+        lrck: Pinmux,
+        mck: Option<Pinmux>,
+        sdout: Option<Pinmux>,
+        sdin: Option<Pinmux>,
+    ) {
+        let regs = &*self.registers;
+        regs.psel_sck.set(sck.into());
+        regs.psel_lrck.set(lrck.into());
+        regs.psel_mck
+            .set(mck.map_or(PSEL_DISCONNECTED, |pin| pin.into()));
+        regs.psel_sdout
+            .set(sdout.map_or(PSEL_DISCONNECTED, |pin| pin.into()));
+        regs.psel_sdin
+            .set(sdin.map_or(PSEL_DISCONNECTED, |pin| pin.into()));
+    }
+
+    pub fn handle_interrupt(&self) {
+        let regs = &*self.registers;
+
+        let mut updated = false;
+        if regs.events_txptrupd.is_set(EVENT::EVENT) {
+            regs.events_txptrupd.write(EVENT::EVENT::CLEAR);
+            updated = true;
+        }
+        if regs.events_rxptrupd.is_set(EVENT::EVENT) {
+            regs.events_rxptrupd.write(EVENT::EVENT::CLEAR);
+            updated = true;
+        }
+        if updated && !self.stopping.get() {
+            self.pointer_updated();
+        }
+
+        if regs.events_stopped.is_set(EVENT::EVENT) {
+            regs.events_stopped.write(EVENT::EVENT::CLEAR);
+            regs.inten.set(0);
+            regs.enable.write(ENABLE::ENABLE::CLEAR);
+            unsafe {
+                clock::CLOCK.release(ManagedClock::High);
+            }
+            self.direction.set(None);
+            self.stopping.set(false);
+
+            // The driver holds at most two buffers
+            let mut buffers = [self.active.take(), self.pending.take(), self.spare.take()];
+            let mut held = buffers.iter_mut().filter_map(|buffer| buffer.take());
+            let buffer1 = held.next();
+            let buffer2 = held.next();
+            self.client
+                .map(move |client| client.stopped(buffer1, buffer2));
+        }
+    }
+
+    /// The peripheral started on the pending buffer, so the active one is
+    /// done. If there was no pending buffer, the peripheral started on the
+    /// active buffer again.
+    fn pointer_updated(&self) {
+        if self.pending.is_none() {
+            return;
+        }
+        let finished = self.active.take();
+        self.active.put(self.pending.take());
+        self.spare.take().map(|buffer| {
+            self.write_pointer(buffer);
+            self.pending.replace(buffer);
+        });
+        finished.map(|buffer| {
+            self.client.map(move |client| client.buffer_done(buffer));
+        });
+    }
+
+    fn write_pointer(&self, buffer: &mut [i16]) {
+        let regs = &*self.registers;
+        match self.direction.get() {
+            Some(Direction::Transmit) => regs.txd_ptr.set(buffer.as_ptr()),
+            Some(Direction::Receive) => regs.rxd_ptr.set(buffer.as_mut_ptr()),
+            None => {}
+        }
+    }
+
+    /// Whether EasyDMA can stream `buffer`
+    fn usable(buffer: &[i16]) -> bool {
+        buffer.len() > 0
+            && buffer.len() % 2 == 0
+            && buffer.len() / 2 <= MAX_WORDS
+            && buffer.as_ptr() as usize % 4 == 0
+    }
+
+    fn start(
+        &self,
+        direction: Direction,
+        buffer1: &'static mut [i16],
+        buffer2: &'static mut [i16],
+    ) -> (
+        ReturnCode,
+        Option<&'static mut [i16]>,
+        Option<&'static mut [i16]>,
+    ) {
+        if self.direction.get().is_some() {
+            return (ReturnCode::EBUSY, Some(buffer1), Some(buffer2));
+        }
+        if !I2s::usable(buffer1) || !I2s::usable(buffer2) || buffer1.len() != buffer2.len() {
+            return (ReturnCode::EINVAL, Some(buffer1), Some(buffer2));
+        }
+
+        let regs = &*self.registers;
+        regs.config_mode.write(MODE::MODE::Master);
+        if direction == Direction::Transmit {
+            regs.config_txen.write(ENABLE::ENABLE::SET);
+            regs.config_rxen.write(ENABLE::ENABLE::CLEAR);
+        } else {
+            regs.config_txen.write(ENABLE::ENABLE::CLEAR);
+            regs.config_rxen.write(ENABLE::ENABLE::SET);
+        }
+        regs.config_mcken.write(ENABLE::ENABLE::SET);
+        regs.config_mckfreq.set(self.mckfreq.get());
+        regs.config_ratio
+            .write(RATIO::RATIO.val(self.ratio.get() as u32));
+        regs.config_swidth.write(SWIDTH::SWIDTH::Bit16);
+        regs.config_align.write(ALIGN::ALIGN::Left);
+        regs.config_format.write(FORMAT::FORMAT::I2S);
+        regs.config_channels.write(match self.channels.get() {
+            Channels::Mono => CHANNELS::CHANNELS::Left,
+            Channels::Stereo => CHANNELS::CHANNELS::Stereo,
+        });
+        regs.rxtxd_maxcnt
+            .write(MAXCNT::MAXCNT.val(buffer1.len() as u32 / 2));
+
+        self.direction.set(Some(direction));
+        self.len.set(buffer1.len());
+        self.write_pointer(buffer1);
+        self.pending.replace(buffer1);
+        self.spare.replace(buffer2);
+
+        regs.inten.write(match direction {
+            Direction::Transmit => INTEN::TXPTRUPD::SET + INTEN::STOPPED::SET,
+            Direction::Receive => INTEN::RXPTRUPD::SET + INTEN::STOPPED::SET,
+        });
+        // The master clock is divided from the HFCLK, which is only accurate
+        // enough for audio when it runs from the crystal.
+        unsafe {
+            clock::CLOCK.request(ManagedClock::High, 0);
+        }
+        regs.enable.write(ENABLE::ENABLE::SET);
+        regs.tasks_start.write(TASK::TASK::SET);
+        (ReturnCode::SUCCESS, None, None)
+    }
+}
+
+impl i2s::I2S for I2s {
+    fn set_client(&self, client: &'static i2s::Client) {
+        self.client.set(client);
+    }
+
+    fn configure(&self, config: Config) -> ReturnCode {
+        if self.direction.get().is_some() {
+            return ReturnCode::EBUSY;
+        }
+        if config.sample_rate == 0 {
+            return ReturnCode::EINVAL;
+        }
+
+        // Find the master clock and ratio closest to the sample rate
+        let mut best = (0, 0, 0);
+        for &mckfreq in MCKFREQS.iter() {
+            let mck = (32_000_000u64 * mckfreq as u64 >> 32) as u32;
+            for (ratio, &divider) in RATIOS.iter().enumerate() {
+                let rate = mck / divider;
+                let error = (rate as i32 - config.sample_rate as i32).abs() as u32;
+                let best_error = (best.2 as i32 - config.sample_rate as i32).abs() as u32;
+                if error < best_error {
+                    best = (mckfreq, ratio, rate);
+                }
+            }
+        }
+        let (mckfreq, ratio, rate) = best;
+        if (rate as i32 - config.sample_rate as i32).abs() as u32 > config.sample_rate / 50 {
+            return ReturnCode::EINVAL;
+        }
+
+        self.mckfreq.set(mckfreq);
+        self.ratio.set(ratio);
+        self.sample_rate.set(rate);
+        self.channels.set(config.channels);
+        ReturnCode::SUCCESS
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate.get()
+    }
+
+    fn transmit(
+        &self,
+        buffer1: &'static mut [i16],
+        buffer2: &'static mut [i16],
+    ) -> (
+        ReturnCode,
+        Option<&'static mut [i16]>,
+        Option<&'static mut [i16]>,
+    ) {
+        self.start(Direction::Transmit, buffer1, buffer2)
+    }
+
+    fn receive(
+        &self,
+        buffer1: &'static mut [i16],
+        buffer2: &'static mut [i16],
+    ) -> (
+        ReturnCode,
+        Option<&'static mut [i16]>,
+        Option<&'static mut [i16]>,
+    ) {
+        self.start(Direction::Receive, buffer1, buffer2)
+    }
+
+    fn provide_buffer(
+        &self,
+        buffer: &'static mut [i16],
+    ) -> (ReturnCode, Option<&'static mut [i16]>) {
+        if self.direction.get().is_none() || self.stopping.get() {
+            return (ReturnCode::EOFF, Some(buffer));
+        }
+        if !I2s::usable(buffer) || buffer.len() != self.len.get() {
+            return (ReturnCode::EINVAL, Some(buffer));
+        }
+        let held = [&self.active, &self.pending, &self.spare]
+            .iter()
+            .filter(|cell| cell.is_some())
+            .count();
+        if held >= 2 {
+            return (ReturnCode::EBUSY, Some(buffer));
+        }
+        if self.pending.is_none() {
+            self.write_pointer(buffer);
+            self.pending.replace(buffer);
+        } else {
+            self.spare.replace(buffer);
+        }
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn stop(&self) -> ReturnCode {
+        if self.direction.get().is_none() || self.stopping.get() {
+            return ReturnCode::EOFF;
+        }
+        self.stopping.set(true);
+        self.registers.tasks_stop.write(TASK::TASK::SET);
+        ReturnCode::SUCCESS
+    }
+}
@@ -20,15 +20,22 @@ extern crate kernel;
 pub mod adc;
 pub mod chip;
 pub mod clock;
+pub mod comp;
 pub mod crt1;
-mod deferred_call_tasks;
 pub mod ficr;
 pub mod i2c;
+pub mod i2s;
 pub mod nvmc;
-pub mod ppi;
+pub mod pdm;
+pub mod pwm;
+pub mod qdec;
+pub mod qspi;
 pub mod radio;
 pub mod spi;
 pub mod uart;
 pub mod uicr;
+pub mod usbd;
+pub mod wdt;
 
 pub use crt1::init;
+pub use nrf5x::ppi;
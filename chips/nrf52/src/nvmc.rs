@@ -7,14 +7,12 @@ use core::ops::{Index, IndexMut};
 use kernel::common::cells::OptionalCell;
 use kernel::common::cells::TakeCell;
 use kernel::common::cells::VolatileCell;
-use kernel::common::deferred_call::DeferredCall;
+use kernel::common::deferred_call::{DeferredCall, DeferredCallClient};
 use kernel::common::registers::{ReadOnly, ReadWrite};
 use kernel::common::StaticRef;
 use kernel::hil;
 use kernel::ReturnCode;
 
-use deferred_call_tasks::DeferredCallTask;
-
 const NVMC_BASE: StaticRef<NvmcRegisters> =
     unsafe { StaticRef::new(0x4001E400 as *const NvmcRegisters) };
 
@@ -138,8 +136,7 @@ register_bitfields! [u32,
 
 /// This mechanism allows us to schedule "interrupts" even if the hardware
 /// does not support them.
-static DEFERRED_CALL: DeferredCall<DeferredCallTask> =
-    unsafe { DeferredCall::new(DeferredCallTask::Nvmc) };
+pub static DEFERRED_CALL: DeferredCall = DeferredCall::new();
 
 const PAGE_SIZE: usize = 4096;
 
@@ -237,6 +234,16 @@ impl Nvmc {
         regs.ready.is_set(Ready::READY)
     }
 
+    /// Invalidate the instruction cache, which also caches the external flash
+    /// mapped by the QSPI peripheral.
+    pub fn invalidate_instruction_cache(&self) {
+        let regs = &*self.registers;
+        if regs.icachecnf.is_set(CacheConfiguration::CACHEEN) {
+            regs.icachecnf.modify(CacheConfiguration::CACHEEN::DISABLED);
+            regs.icachecnf.modify(CacheConfiguration::CACHEEN::ENABLED);
+        }
+    }
+
     pub fn handle_interrupt(&self) {
         let state = self.state.get();
         self.state.set(FlashState::Ready);
@@ -350,6 +357,12 @@ impl Nvmc {
     }
 }
 
+impl DeferredCallClient for Nvmc {
+    fn handle_deferred_call(&self) {
+        self.handle_interrupt();
+    }
+}
+
 impl<C: hil::flash::Client<Self>> hil::flash::HasClient<'static, C> for Nvmc {
     fn set_client(&self, client: &'static C) {
         self.client.set(client);
@@ -0,0 +1,354 @@
+//! PDM driver for the nRF52840.
+//!
+//! The PDM peripheral clocks a single digital microphone, which is sampled on
+//! the falling edge of the clock as the left channel, and its decimation
+//! filter produces 16-bit samples at the clock frequency divided by 64 or 80.
+//! The RATIO register and the clock frequencies above 1.067 MHz only exist on
+//! the nRF52840. The clock and ratio closest to the configured sample rate
+//! are used, if they are within 2% of it.
+//!
+//! The peripheral reads the pointer to the next buffer when it starts on a
+//! buffer, and signals this with the STARTED event. The driver then writes
+//! the pointer to the buffer after it, and passes the buffer the peripheral
+//! filled before back to the client. If no new buffer was provided in time,
+//! the peripheral fills the same buffer again.
+
+use clock::{self, ManagedClock};
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell, VolatileCell};
+use kernel::common::registers::{ReadWrite, WriteOnly};
+use kernel::common::StaticRef;
+use kernel::hil::pdm;
+use kernel::{ClockManager, ReturnCode};
+use nrf5x::pinmux::Pinmux;
+
+const PDM_BASE: StaticRef<PdmRegisters> =
+    unsafe { StaticRef::new(0x4001D000 as *const PdmRegisters) };
+
+pub static mut PDM: Pdm = Pdm::new(PDM_BASE);
+
+#[repr(C)]
+struct PdmRegisters {
+    /// Starts continuous PDM transfer
+    tasks_start: WriteOnly<u32, TASK::Register>,
+    /// Stops PDM transfer
+    tasks_stop: WriteOnly<u32, TASK::Register>,
+    _reserved0: [u8; 248],
+    /// PDM transfer has started
+    events_started: ReadWrite<u32, EVENT::Register>,
+    /// PDM transfer has finished
+    events_stopped: ReadWrite<u32, EVENT::Register>,
+    /// The PDM has written the last sample specified by SAMPLE.MAXCNT
+    events_end: ReadWrite<u32, EVENT::Register>,
+    _reserved1: [u8; 500],
+    /// Enable or disable interrupt
+    inten: ReadWrite<u32, INTEN::Register>,
+    /// Enable interrupt
+    intenset: ReadWrite<u32, INTEN::Register>,
+    /// Disable interrupt
+    intenclr: ReadWrite<u32, INTEN::Register>,
+    _reserved2: [u8; 500],
+    /// PDM module enable register
+    enable: ReadWrite<u32, ENABLE::Register>,
+    /// PDM clock generator control
+    pdmclkctrl: ReadWrite<u32>,
+    /// Defines the routing of the connected PDM microphones' signals
+    mode: ReadWrite<u32, MODE::Register>,
+    _reserved3: [u8; 12],
+    /// Left output gain adjustment
+    gainl: ReadWrite<u32, GAIN::Register>,
+    /// Right output gain adjustment
+    gainr: ReadWrite<u32, GAIN::Register>,
+    /// Selects the ratio between PDM_CLK and output sample rate
+    ratio: ReadWrite<u32, RATIO::Register>,
+    _reserved4: [u8; 28],
+    /// Pin number configuration for PDM CLK signal
+    psel_clk: ReadWrite<u32>,
+    /// Pin number configuration for PDM DIN signal
+    psel_din: ReadWrite<u32>,
+    _reserved5: [u8; 24],
+    /// RAM address pointer to write samples to with EasyDMA
+    sample_ptr: VolatileCell<*mut i16>,
+    /// Number of samples to allocate memory for in EasyDMA mode
+    sample_maxcnt: ReadWrite<u32, MAXCNT::Register>,
+}
+
+register_bitfields![u32,
+    INTEN [
+        /// Enable or disable interrupt on EVENTS_STARTED event
+        STARTED 0,
+        /// Enable or disable interrupt on EVENTS_STOPPED event
+        STOPPED 1,
+        /// Enable or disable interrupt on EVENTS_END event
+        END 2
+    ],
+    ENABLE [
+        ENABLE 0
+    ],
+    MODE [
+        OPERATION OFFSET(0) NUMBITS(1) [
+            Stereo = 0,
+            Mono = 1
+        ],
+        EDGE OFFSET(1) NUMBITS(1) [
+            LeftFalling = 0,
+            LeftRising = 1
+        ]
+    ],
+    GAIN [
+        GAIN OFFSET(0) NUMBITS(7) [
+            MinGain = 0x00,
+            DefaultGain = 0x28,
+            MaxGain = 0x50
+        ]
+    ],
+    RATIO [
+        RATIO OFFSET(0) NUMBITS(1) [
+            Ratio64 = 0,
+            Ratio80 = 1
+        ]
+    ],
+    MAXCNT [
+        BUFFSIZE OFFSET(0) NUMBITS(15) []
+    ],
+    EVENT [
+        EVENT 0
+    ],
+    TASK [
+        TASK 0
+    ]
+];
+
+/// The settings of the clock generator, and the frequencies they produce
+const PDMCLKCTRLS: [(u32, u32); 6] = [
+    (0x08000000, 1_000_000),
+    (0x08400000, 1_032_000),
+    (0x08800000, 1_067_000),
+    (0x09800000, 1_231_000),
+    (0x0A000000, 1_280_000),
+    (0x0A800000, 1_333_000),
+];
+
+/// The longest buffer, in samples
+const MAX_SAMPLES: usize = (1 << 15) - 1;
+
+pub struct Pdm {
+    registers: StaticRef<PdmRegisters>,
+    client: OptionalCell<&'static pdm::Client>,
+    pdmclkctrl: Cell<u32>,
+    ratio80: Cell<bool>,
+    sample_rate: Cell<u32>,
+    sampling: Cell<bool>,
+    stopping: Cell<bool>,
+    /// The length of the buffers
+    len: Cell<usize>,
+    /// The buffer the peripheral is filling
+    active: TakeCell<'static, [i16]>,
+    /// The buffer whose pointer was written, and that comes next
+    pending: TakeCell<'static, [i16]>,
+    /// The buffer that comes after the pending one
+    spare: TakeCell<'static, [i16]>,
+}
+
+impl Pdm {
+    const fn new(registers: StaticRef<PdmRegisters>) -> Pdm {
+        Pdm {
+            registers: registers,
+            client: OptionalCell::empty(),
+            // 1.280 MHz / 80
+            pdmclkctrl: Cell::new(0x0A000000),
+            ratio80: Cell::new(true),
+            sample_rate: Cell::new(16000),
+            sampling: Cell::new(false),
+            stopping: Cell::new(false),
+            len: Cell::new(0),
+            active: TakeCell::empty(),
+            pending: TakeCell::empty(),
+            spare: TakeCell::empty(),
+        }
+    }
+
+    /// Sets the pins of the clock and data signals of the microphone.
+    pub fn set_pins(&self, clk: Pinmux, din: Pinmux) {
+        let regs = &*self.registers;
+        regs.psel_clk.set(clk.into());
+        regs.psel_din.set(din.into());
+    }
+
+    pub fn handle_interrupt(&self) {
+        let regs = &*self.registers;
+
+        if regs.events_started.is_set(EVENT::EVENT) {
+            regs.events_started.write(EVENT::EVENT::CLEAR);
+            if !self.stopping.get() {
+                self.started();
+            }
+        }
+
+        if regs.events_stopped.is_set(EVENT::EVENT) {
+            regs.events_stopped.write(EVENT::EVENT::CLEAR);
+            regs.inten.set(0);
+            regs.enable.write(ENABLE::ENABLE::CLEAR);
+            unsafe {
+                clock::CLOCK.release(ManagedClock::High);
+            }
+            self.sampling.set(false);
+            self.stopping.set(false);
+
+            // The driver holds at most two buffers
+            let mut buffers = [self.active.take(), self.pending.take(), self.spare.take()];
+            let mut held = buffers.iter_mut().filter_map(|buffer| buffer.take());
+            let buffer1 = held.next();
+            let buffer2 = held.next();
+            self.client
+                .map(move |client| client.stopped(buffer1, buffer2));
+        }
+    }
+
+    /// The peripheral started on the pending buffer, so the active one is
+    /// full. If there was no pending buffer, the peripheral started on the
+    /// active buffer again.
+    fn started(&self) {
+        if self.pending.is_none() {
+            return;
+        }
+        let finished = self.active.take();
+        self.active.put(self.pending.take());
+        self.spare.take().map(|buffer| {
+            self.registers.sample_ptr.set(buffer.as_mut_ptr());
+            self.pending.replace(buffer);
+        });
+        finished.map(|buffer| {
+            self.client.map(move |client| client.frame_done(buffer));
+        });
+    }
+
+    /// Whether EasyDMA can fill `buffer`
+    fn usable(buffer: &[i16]) -> bool {
+        buffer.len() > 0 && buffer.len() <= MAX_SAMPLES
+    }
+}
+
+impl pdm::Pdm for Pdm {
+    fn set_client(&self, client: &'static pdm::Client) {
+        self.client.set(client);
+    }
+
+    fn set_sample_rate(&self, sample_rate: u32) -> ReturnCode {
+        if self.sampling.get() {
+            return ReturnCode::EBUSY;
+        }
+        if sample_rate == 0 {
+            return ReturnCode::EINVAL;
+        }
+
+        // Find the clock and ratio closest to the sample rate
+        let mut best = (0, false, 0);
+        for &(pdmclkctrl, frequency) in PDMCLKCTRLS.iter() {
+            for &ratio80 in [false, true].iter() {
+                let rate = frequency / if ratio80 { 80 } else { 64 };
+                let error = (rate as i32 - sample_rate as i32).abs() as u32;
+                let best_error = (best.2 as i32 - sample_rate as i32).abs() as u32;
+                if error < best_error {
+                    best = (pdmclkctrl, ratio80, rate);
+                }
+            }
+        }
+        let (pdmclkctrl, ratio80, rate) = best;
+        if (rate as i32 - sample_rate as i32).abs() as u32 > sample_rate / 50 {
+            return ReturnCode::EINVAL;
+        }
+
+        self.pdmclkctrl.set(pdmclkctrl);
+        self.ratio80.set(ratio80);
+        self.sample_rate.set(rate);
+        ReturnCode::SUCCESS
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate.get()
+    }
+
+    fn start(
+        &self,
+        buffer1: &'static mut [i16],
+        buffer2: &'static mut [i16],
+    ) -> (
+        ReturnCode,
+        Option<&'static mut [i16]>,
+        Option<&'static mut [i16]>,
+    ) {
+        if self.sampling.get() {
+            return (ReturnCode::EBUSY, Some(buffer1), Some(buffer2));
+        }
+        if !Pdm::usable(buffer1) || !Pdm::usable(buffer2) || buffer1.len() != buffer2.len() {
+            return (ReturnCode::EINVAL, Some(buffer1), Some(buffer2));
+        }
+
+        let regs = &*self.registers;
+        regs.pdmclkctrl.set(self.pdmclkctrl.get());
+        regs.ratio.write(if self.ratio80.get() {
+            RATIO::RATIO::Ratio80
+        } else {
+            RATIO::RATIO::Ratio64
+        });
+        regs.mode
+            .write(MODE::OPERATION::Mono + MODE::EDGE::LeftFalling);
+        regs.gainl.write(GAIN::GAIN::DefaultGain);
+        regs.gainr.write(GAIN::GAIN::DefaultGain);
+        regs.sample_maxcnt
+            .write(MAXCNT::BUFFSIZE.val(buffer1.len() as u32));
+
+        self.sampling.set(true);
+        self.len.set(buffer1.len());
+        regs.sample_ptr.set(buffer1.as_mut_ptr());
+        self.pending.replace(buffer1);
+        self.spare.replace(buffer2);
+
+        regs.inten
+            .write(INTEN::STARTED::SET + INTEN::STOPPED::SET);
+        // The PDM clock is divided from the HFCLK, which is only accurate
+        // enough for audio when it runs from the crystal.
+        unsafe {
+            clock::CLOCK.request(ManagedClock::High, 0);
+        }
+        regs.enable.write(ENABLE::ENABLE::SET);
+        regs.tasks_start.write(TASK::TASK::SET);
+        (ReturnCode::SUCCESS, None, None)
+    }
+
+    fn provide_buffer(
+        &self,
+        buffer: &'static mut [i16],
+    ) -> (ReturnCode, Option<&'static mut [i16]>) {
+        if !self.sampling.get() || self.stopping.get() {
+            return (ReturnCode::EOFF, Some(buffer));
+        }
+        if buffer.len() != self.len.get() {
+            return (ReturnCode::EINVAL, Some(buffer));
+        }
+        let held = [&self.active, &self.pending, &self.spare]
+            .iter()
+            .filter(|cell| cell.is_some())
+            .count();
+        if held >= 2 {
+            return (ReturnCode::EBUSY, Some(buffer));
+        }
+        if self.pending.is_none() {
+            self.registers.sample_ptr.set(buffer.as_mut_ptr());
+            self.pending.replace(buffer);
+        } else {
+            self.spare.replace(buffer);
+        }
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn stop(&self) -> ReturnCode {
+        if !self.sampling.get() || self.stopping.get() {
+            return ReturnCode::EOFF;
+        }
+        self.stopping.set(true);
+        self.registers.tasks_stop.write(TASK::TASK::SET);
+        ReturnCode::SUCCESS
+    }
+}
@@ -0,0 +1,473 @@
+//! PWM driver for the nRF52.
+//!
+//! Each of the three PWM peripherals drives four channels from one 15-bit
+//! counter, clocked at 16 MHz divided by a power of two. The driver picks the
+//! smallest prescaler whose counter top fits the frequency, for the finest
+//! duty cycles.
+//!
+//! The peripheral reads the compare values of all channels from RAM with
+//! EasyDMA, as a sequence of one step. To change duty cycles, the driver
+//! writes a new sequence into the buffer that is not playing and starts it,
+//! so all channels take their new values together at the start of the next
+//! period. After the sequence, the peripheral keeps outputting its last
+//! step. Complementary channels play the compare value of the channel before
+//! them with the inverse polarity, without dead time.
+//!
+//! When stopped, the pins go back to their GPIO configuration, so boards
+//! should configure them as outputs that are low.
+//!
+//! While it is not used as a PWM, the peripheral can also drive a strip of
+//! WS2812 LEDs connected to channel 0, as a `hil::led_strip::LedStrip`. Each
+//! bit of the colors is one PWM period of 1.25 us, high for longer for a 1
+//! than for a 0, so the whole strip plays as one sequence that the board
+//! provides the buffer for: `STRIP_STEPS_PER_LED` values per LED, and
+//! `STRIP_RESET_STEPS` more for the low time that latches the colors. All
+//! channels play the strip sequence, so the other channels should not be
+//! connected to pins.
+//!
+//! ```rust
+//! static mut STRIP_SEQUENCE: [u16; 8 * nrf52::pwm::STRIP_STEPS_PER_LED
+//!     + nrf52::pwm::STRIP_RESET_STEPS] = [0; 8 * nrf52::pwm::STRIP_STEPS_PER_LED
+//!     + nrf52::pwm::STRIP_RESET_STEPS];
+//!
+//! nrf52::pwm::PWM1.set_pin(0, Pinmux::new(16));
+//! nrf52::pwm::PWM1.set_strip_sequence(&mut STRIP_SEQUENCE);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell, VolatileCell};
+use kernel::common::registers::{ReadWrite, WriteOnly};
+use kernel::common::StaticRef;
+use kernel::hil::led_strip;
+use kernel::hil::pwm;
+use kernel::ReturnCode;
+use nrf5x::pinmux::Pinmux;
+
+const PWM0_BASE: StaticRef<PwmRegisters> =
+    unsafe { StaticRef::new(0x4001C000 as *const PwmRegisters) };
+const PWM1_BASE: StaticRef<PwmRegisters> =
+    unsafe { StaticRef::new(0x40021000 as *const PwmRegisters) };
+const PWM2_BASE: StaticRef<PwmRegisters> =
+    unsafe { StaticRef::new(0x40022000 as *const PwmRegisters) };
+
+pub static mut PWM0: Pwm = Pwm::new(PWM0_BASE);
+pub static mut PWM1: Pwm = Pwm::new(PWM1_BASE);
+pub static mut PWM2: Pwm = Pwm::new(PWM2_BASE);
+
+#[repr(C)]
+struct SequenceRegisters {
+    /// Beginning address in RAM of this sequence
+    ptr: VolatileCell<*const u16>,
+    /// Number of values (duty cycles) in this sequence
+    cnt: ReadWrite<u32>,
+    /// Number of additional PWM periods between samples loaded into compare
+    /// register
+    refresh: ReadWrite<u32>,
+    /// Time added after the sequence
+    enddelay: ReadWrite<u32>,
+    _reserved: [u8; 16],
+}
+
+#[repr(C)]
+struct PwmRegisters {
+    /// Stops PWM pulse generation on all channels at the end of current PWM
+    /// period, and stops sequence playback
+    tasks_stop: WriteOnly<u32, TASK::Register>,
+    _reserved_tasks: [u8; 4],
+    /// Loads the first PWM value on all enabled channels from a sequence,
+    /// and starts playing that sequence
+    tasks_seqstart: [WriteOnly<u32, TASK::Register>; 2],
+    /// Steps by one value in the current sequence on all enabled channels
+    tasks_nextstep: WriteOnly<u32, TASK::Register>,
+    _reserved0: [u8; 240],
+    /// Response to STOP task, emitted when PWM pulses are no longer
+    /// generated
+    events_stopped: ReadWrite<u32, EVENT::Register>,
+    /// First PWM period started on a sequence
+    events_seqstarted: [ReadWrite<u32, EVENT::Register>; 2],
+    /// Emitted at end of every sequence, when last value from RAM has been
+    /// applied to wave counter
+    events_seqend: [ReadWrite<u32, EVENT::Register>; 2],
+    /// Emitted at the end of each PWM period
+    events_pwmperiodend: ReadWrite<u32, EVENT::Register>,
+    /// Concatenated sequences have been played the amount of times defined
+    /// in LOOP.CNT
+    events_loopsdone: ReadWrite<u32, EVENT::Register>,
+    _reserved1: [u8; 224],
+    /// Shortcut register
+    shorts: ReadWrite<u32, SHORTS::Register>,
+    _reserved2: [u8; 252],
+    /// Enable or disable interrupt
+    inten: ReadWrite<u32, INTEN::Register>,
+    /// Enable interrupt
+    intenset: ReadWrite<u32, INTEN::Register>,
+    /// Disable interrupt
+    intenclr: ReadWrite<u32, INTEN::Register>,
+    _reserved3: [u8; 500],
+    /// PWM module enable register
+    enable: ReadWrite<u32, ENABLE::Register>,
+    /// Selects operating mode of the wave counter
+    mode: ReadWrite<u32, MODE::Register>,
+    /// Value up to which the pulse generator counter counts
+    countertop: ReadWrite<u32>,
+    /// Configuration for PWM_CLK
+    prescaler: ReadWrite<u32>,
+    /// Configuration of the decoder
+    decoder: ReadWrite<u32, DECODER::Register>,
+    /// Amount of playback of a loop
+    loop_: ReadWrite<u32>,
+    _reserved4: [u8; 8],
+    seq: [SequenceRegisters; 2],
+    /// Output pin select for PWM channels
+    psel_out: [ReadWrite<u32, PSEL::Register>; 4],
+}
+
+register_bitfields![u32,
+    INTEN [
+        /// Enable or disable interrupt on EVENTS_STOPPED event
+        STOPPED 1
+    ],
+    SHORTS [
+        /// Shortcut between SEQEND[0] event and STOP task
+        SEQEND0_STOP 0
+    ],
+    ENABLE [
+        ENABLE 0
+    ],
+    MODE [
+        UPDOWN OFFSET(0) NUMBITS(1) [
+            Up = 0,
+            UpAndDown = 1
+        ]
+    ],
+    DECODER [
+        LOAD OFFSET(0) NUMBITS(2) [
+            Common = 0,
+            Grouped = 1,
+            Individual = 2,
+            WaveForm = 3
+        ],
+        MODE OFFSET(8) NUMBITS(1) [
+            RefreshCount = 0,
+            NextStep = 1
+        ]
+    ],
+    PSEL [
+        PIN OFFSET(0) NUMBITS(5) [],
+        CONNECT OFFSET(31) NUMBITS(1) [
+            Connected = 0,
+            Disconnected = 1
+        ]
+    ],
+    EVENT [
+        EVENT 0
+    ],
+    TASK [
+        TASK 0
+    ]
+];
+
+const CHANNELS: usize = 4;
+
+const CLOCK_HZ: usize = 16_000_000;
+const MAX_PRESCALER: usize = 7;
+const MIN_COUNTERTOP: usize = 3;
+const MAX_COUNTERTOP: usize = (1 << 15) - 1;
+
+/// Duty cycles are scaled from this to the counter top
+const MAX_DUTY_CYCLE: usize = (1 << 15) - 1;
+
+/// In a compare value, the output starts the period high, and falls at the
+/// compare value
+const POLARITY_FALLING: u16 = 1 << 15;
+
+/// The values of the strip sequence for each LED, one per bit of its colors
+pub const STRIP_STEPS_PER_LED: usize = 24;
+
+/// The low values at the end of the strip sequence, for the 300 us the LEDs
+/// need to latch their colors
+pub const STRIP_RESET_STEPS: usize = 240;
+
+/// A WS2812 bit lasts 1.25 us, 20 cycles at 16 MHz, and is high for 0.375 us
+/// for a 0, and for 0.8125 us for a 1
+const STRIP_COUNTERTOP: u32 = 20;
+const STRIP_T0H: u16 = 6;
+const STRIP_T1H: u16 = 13;
+
+pub struct Pwm {
+    registers: StaticRef<PwmRegisters>,
+    running: Cell<bool>,
+    stopping: Cell<bool>,
+    countertop: Cell<usize>,
+    duty_cycles: Cell<[usize; CHANNELS]>,
+    complementary: Cell<[bool; CHANNELS]>,
+    /// The sequences the peripheral plays in turn, one compare value per
+    /// channel
+    sequences: [Cell<[u16; CHANNELS]>; 2],
+    /// The sequence that was started last
+    sequence: Cell<usize>,
+    /// The buffer the LED strip sequence plays from, if the board provided
+    /// one
+    strip_sequence: TakeCell<'static, [u16]>,
+    strip_colors: TakeCell<'static, [u8]>,
+    strip_client: OptionalCell<&'static led_strip::Client>,
+    /// Whether the peripheral is showing the colors of the LED strip
+    strip_showing: Cell<bool>,
+}
+
+impl Pwm {
+    const fn new(registers: StaticRef<PwmRegisters>) -> Pwm {
+        Pwm {
+            registers: registers,
+            running: Cell::new(false),
+            stopping: Cell::new(false),
+            countertop: Cell::new(0),
+            duty_cycles: Cell::new([0; CHANNELS]),
+            complementary: Cell::new([false; CHANNELS]),
+            sequences: [Cell::new([0; CHANNELS]), Cell::new([0; CHANNELS])],
+            sequence: Cell::new(0),
+            strip_sequence: TakeCell::empty(),
+            strip_colors: TakeCell::empty(),
+            strip_client: OptionalCell::empty(),
+            strip_showing: Cell::new(false),
+        }
+    }
+
+    /// Provides the buffer of the LED strip sequence, which sets the number
+    /// of LEDs of the strip.
+    pub fn set_strip_sequence(&self, sequence: &'static mut [u16]) {
+        self.strip_sequence.replace(sequence);
+    }
+
+    /// Connects a channel to a pin.
+    pub fn set_pin(&self, channel: usize, pin: Pinmux) {
+        self.registers.psel_out[channel].write(PSEL::PIN.val(pin.into()));
+    }
+
+    pub fn handle_interrupt(&self) {
+        let regs = &*self.registers;
+
+        if regs.events_stopped.is_set(EVENT::EVENT) {
+            regs.events_stopped.write(EVENT::EVENT::CLEAR);
+            regs.intenclr.write(INTEN::STOPPED::SET);
+            regs.enable.write(ENABLE::ENABLE::CLEAR);
+            self.running.set(false);
+            self.stopping.set(false);
+
+            if self.strip_showing.get() {
+                regs.shorts.set(0);
+                self.strip_showing.set(false);
+                self.strip_colors.take().map(|colors| {
+                    self.strip_client
+                        .map(|client| client.show_done(colors, ReturnCode::SUCCESS));
+                });
+            }
+        }
+    }
+
+    /// Plays the current duty cycles from the sequence that is not playing.
+    fn update(&self) {
+        let regs = &*self.registers;
+        let countertop = self.countertop.get();
+        let duty_cycles = self.duty_cycles.get();
+        let complementary = self.complementary.get();
+
+        let mut values = [0; CHANNELS];
+        for channel in 0..CHANNELS {
+            let (duty_cycle, polarity) = if complementary[channel] {
+                (duty_cycles[channel - 1], 0)
+            } else {
+                (duty_cycles[channel], POLARITY_FALLING)
+            };
+            let compare = duty_cycle * countertop / MAX_DUTY_CYCLE;
+            values[channel] = compare as u16 | polarity;
+        }
+
+        let sequence = 1 - self.sequence.get();
+        self.sequences[sequence].set(values);
+        regs.seq[sequence]
+            .ptr
+            .set(self.sequences[sequence].as_ptr() as *const u16);
+        regs.seq[sequence].cnt.set(CHANNELS as u32);
+        regs.seq[sequence].refresh.set(0);
+        regs.seq[sequence].enddelay.set(0);
+        regs.tasks_seqstart[sequence].write(TASK::TASK::SET);
+        self.sequence.set(sequence);
+    }
+}
+
+impl pwm::Pwm for Pwm {
+    fn channel_count(&self) -> usize {
+        CHANNELS
+    }
+
+    fn get_maximum_frequency_hz(&self) -> usize {
+        CLOCK_HZ / MIN_COUNTERTOP
+    }
+
+    fn get_maximum_duty_cycle(&self) -> usize {
+        MAX_DUTY_CYCLE
+    }
+
+    fn start(&self, frequency_hz: usize) -> ReturnCode {
+        if self.stopping.get() {
+            return ReturnCode::EBUSY;
+        }
+        if frequency_hz == 0 {
+            return ReturnCode::EINVAL;
+        }
+
+        // The smallest prescaler the counter top fits with
+        let setting = (0..MAX_PRESCALER + 1)
+            .map(|prescaler| (prescaler, (CLOCK_HZ >> prescaler) / frequency_hz))
+            .find(|&(_, countertop)| countertop <= MAX_COUNTERTOP);
+        let (prescaler, countertop) = match setting {
+            Some((prescaler, countertop)) if countertop >= MIN_COUNTERTOP => {
+                (prescaler, countertop)
+            }
+            _ => return ReturnCode::EINVAL,
+        };
+
+        let regs = &*self.registers;
+        if !self.running.get() {
+            regs.enable.write(ENABLE::ENABLE::SET);
+            regs.mode.write(MODE::UPDOWN::Up);
+            regs.decoder
+                .write(DECODER::LOAD::Individual + DECODER::MODE::RefreshCount);
+            regs.loop_.set(0);
+            self.running.set(true);
+        }
+        regs.prescaler.set(prescaler as u32);
+        regs.countertop.set(countertop as u32);
+        self.countertop.set(countertop);
+        self.duty_cycles.set([0; CHANNELS]);
+        self.update();
+
+        ReturnCode::SuccessWithValue {
+            value: (CLOCK_HZ >> prescaler) / countertop,
+        }
+    }
+
+    fn stop(&self) -> ReturnCode {
+        if !self.running.get() || self.stopping.get() {
+            return ReturnCode::EOFF;
+        }
+        let regs = &*self.registers;
+        self.stopping.set(true);
+        regs.events_stopped.write(EVENT::EVENT::CLEAR);
+        regs.intenset.write(INTEN::STOPPED::SET);
+        regs.tasks_stop.write(TASK::TASK::SET);
+        ReturnCode::SUCCESS
+    }
+
+    fn set_duty_cycles(&self, duty_cycles: &[Option<usize>]) -> ReturnCode {
+        if !self.running.get() || self.stopping.get() {
+            return ReturnCode::EOFF;
+        }
+        let complementary = self.complementary.get();
+        let mut new = self.duty_cycles.get();
+        for (channel, duty_cycle) in duty_cycles.iter().enumerate() {
+            if let Some(duty_cycle) = *duty_cycle {
+                if channel >= CHANNELS
+                    || complementary[channel]
+                    || duty_cycle > MAX_DUTY_CYCLE
+                {
+                    return ReturnCode::EINVAL;
+                }
+                new[channel] = duty_cycle;
+            }
+        }
+        self.duty_cycles.set(new);
+        self.update();
+        ReturnCode::SUCCESS
+    }
+
+    fn set_complementary(&self, channel: usize, complementary: bool) -> ReturnCode {
+        if channel >= CHANNELS || channel % 2 == 0 {
+            return ReturnCode::EINVAL;
+        }
+        let mut pairs = self.complementary.get();
+        pairs[channel] = complementary;
+        self.complementary.set(pairs);
+        let mut duty_cycles = self.duty_cycles.get();
+        duty_cycles[channel] = 0;
+        self.duty_cycles.set(duty_cycles);
+        if self.running.get() && !self.stopping.get() {
+            self.update();
+        }
+        ReturnCode::SUCCESS
+    }
+}
+
+impl led_strip::LedStrip for Pwm {
+    fn set_client(&self, client: &'static led_strip::Client) {
+        self.strip_client.set(client);
+    }
+
+    fn led_count(&self) -> usize {
+        self.strip_sequence.map_or(0, |sequence| {
+            sequence.len().saturating_sub(STRIP_RESET_STEPS) / STRIP_STEPS_PER_LED
+        })
+    }
+
+    fn show(
+        &self,
+        colors: &'static mut [u8],
+        count: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.running.get() || self.stopping.get() || self.strip_showing.get() {
+            return (ReturnCode::EBUSY, Some(colors));
+        }
+        if count == 0 || count > self.led_count() || colors.len() < count * 3 {
+            return (ReturnCode::EINVAL, Some(colors));
+        }
+
+        let regs = &*self.registers;
+        self.strip_sequence.map(|sequence| {
+            // The LEDs take green first, then red and blue, each most
+            // significant bit first
+            for led in 0..count {
+                let color = &colors[led * 3..led * 3 + 3];
+                let grb = [color[1], color[0], color[2]];
+                for (i, byte) in grb.iter().enumerate() {
+                    for bit in 0..8 {
+                        let high = if byte & (0x80 >> bit) != 0 {
+                            STRIP_T1H
+                        } else {
+                            STRIP_T0H
+                        };
+                        sequence[led * STRIP_STEPS_PER_LED + i * 8 + bit] =
+                            high | POLARITY_FALLING;
+                    }
+                }
+            }
+            let len = count * STRIP_STEPS_PER_LED + STRIP_RESET_STEPS;
+            for step in sequence[count * STRIP_STEPS_PER_LED..len].iter_mut() {
+                *step = POLARITY_FALLING;
+            }
+
+            regs.enable.write(ENABLE::ENABLE::SET);
+            regs.mode.write(MODE::UPDOWN::Up);
+            regs.decoder
+                .write(DECODER::LOAD::Common + DECODER::MODE::RefreshCount);
+            regs.loop_.set(0);
+            regs.prescaler.set(0);
+            regs.countertop.set(STRIP_COUNTERTOP);
+            regs.seq[0].ptr.set(sequence.as_ptr());
+            regs.seq[0].cnt.set(len as u32);
+            regs.seq[0].refresh.set(0);
+            regs.seq[0].enddelay.set(0);
+        });
+
+        // The peripheral stops by itself at the end of the sequence
+        self.strip_colors.replace(colors);
+        self.running.set(true);
+        self.stopping.set(true);
+        self.strip_showing.set(true);
+        regs.shorts.write(SHORTS::SEQEND0_STOP::SET);
+        regs.events_stopped.write(EVENT::EVENT::CLEAR);
+        regs.intenset.write(INTEN::STOPPED::SET);
+        regs.tasks_seqstart[0].write(TASK::TASK::SET);
+        (ReturnCode::SUCCESS, None)
+    }
+}
@@ -0,0 +1,263 @@
+//! Quadrature decoder driver for the nRF52.
+//!
+//! The QDEC samples the A and B inputs every 128 µs and adds each valid
+//! transition to its accumulator. Every 80 samples in which the encoder
+//! moved, it reports: a shortcut copies the accumulator to ACCREAD and
+//! clears it, and the driver adds ACCREAD to the position it keeps. The
+//! current position also includes the accumulator, so it is exact between
+//! reports. Inputs are debounced, which limits the encoder to transitions
+//! longer than the sample period.
+//!
+//! The QDEC has no index input, so the index is an optional GPIO pin, whose
+//! client must be set to the driver:
+//!
+//! ```rust
+//! nrf52::qdec::QDEC.set_pins(Pinmux::new(11), Pinmux::new(12));
+//! nrf5x::gpio::PORT[13].set_client(&nrf52::qdec::QDEC);
+//! nrf52::qdec::QDEC.set_index_pin(&nrf5x::gpio::PORT[13]);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::common::registers::{ReadOnly, ReadWrite, WriteOnly};
+use kernel::common::StaticRef;
+use kernel::hil::gpio;
+use kernel::hil::qdec;
+use kernel::ReturnCode;
+use nrf5x::pinmux::Pinmux;
+
+const QDEC_BASE: StaticRef<QdecRegisters> =
+    unsafe { StaticRef::new(0x40012000 as *const QdecRegisters) };
+
+pub static mut QDEC: Qdec = Qdec::new(QDEC_BASE);
+
+#[repr(C)]
+struct QdecRegisters {
+    /// Task starting the quadrature decoder
+    tasks_start: WriteOnly<u32, TASK::Register>,
+    /// Task stopping the quadrature decoder
+    tasks_stop: WriteOnly<u32, TASK::Register>,
+    /// Read and clear ACC and ACCDBL
+    tasks_readclracc: WriteOnly<u32, TASK::Register>,
+    /// Read and clear ACC
+    tasks_rdclracc: WriteOnly<u32, TASK::Register>,
+    /// Read and clear ACCDBL
+    tasks_rdclrdbl: WriteOnly<u32, TASK::Register>,
+    _reserved0: [u8; 236],
+    /// Event being generated for every new sample value written to the
+    /// SAMPLE register
+    events_samplerdy: ReadWrite<u32, EVENT::Register>,
+    /// Non-null report ready
+    events_reportrdy: ReadWrite<u32, EVENT::Register>,
+    /// ACC or ACCDBL register overflow
+    events_accof: ReadWrite<u32, EVENT::Register>,
+    /// Double displacement(s) detected
+    events_dblrdy: ReadWrite<u32, EVENT::Register>,
+    /// QDEC has been stopped
+    events_stopped: ReadWrite<u32, EVENT::Register>,
+    _reserved1: [u8; 236],
+    /// Shortcut register
+    shorts: ReadWrite<u32, SHORTS::Register>,
+    _reserved2: [u8; 256],
+    /// Enable interrupt
+    intenset: ReadWrite<u32, INTEN::Register>,
+    /// Disable interrupt
+    intenclr: ReadWrite<u32, INTEN::Register>,
+    _reserved3: [u8; 500],
+    /// Enable the quadrature decoder
+    enable: ReadWrite<u32, ENABLE::Register>,
+    /// LED output pin polarity
+    ledpol: ReadWrite<u32>,
+    /// Sample period
+    sampleper: ReadWrite<u32, SAMPLEPER::Register>,
+    /// Motion sample value
+    sample: ReadOnly<u32>,
+    /// Number of samples to be taken before REPORTRDY and DBLRDY events can
+    /// be generated
+    reportper: ReadWrite<u32, REPORTPER::Register>,
+    /// Register accumulating the valid transitions
+    acc: ReadOnly<u32>,
+    /// Snapshot of the ACC register, updated by the READCLRACC or RDCLRACC
+    /// task
+    accread: ReadOnly<u32>,
+    /// Pin select for LED signal
+    psel_led: ReadWrite<u32, PSEL::Register>,
+    /// Pin select for A signal
+    psel_a: ReadWrite<u32, PSEL::Register>,
+    /// Pin select for B signal
+    psel_b: ReadWrite<u32, PSEL::Register>,
+    /// Enable input debounce filters
+    dbfen: ReadWrite<u32, DBFEN::Register>,
+}
+
+register_bitfields![u32,
+    SHORTS [
+        /// Shortcut between REPORTRDY event and READCLRACC task
+        REPORTRDY_READCLRACC 0
+    ],
+    INTEN [
+        /// Enable interrupt for REPORTRDY event
+        REPORTRDY 1
+    ],
+    ENABLE [
+        ENABLE 0
+    ],
+    SAMPLEPER [
+        SAMPLEPER OFFSET(0) NUMBITS(4) [
+            Us128 = 0,
+            Us256 = 1,
+            Us512 = 2,
+            Us1024 = 3
+        ]
+    ],
+    REPORTPER [
+        REPORTPER OFFSET(0) NUMBITS(4) [
+            Samples10 = 0,
+            Samples40 = 1,
+            Samples80 = 2,
+            Samples120 = 3
+        ]
+    ],
+    PSEL [
+        PIN OFFSET(0) NUMBITS(5) [],
+        CONNECT OFFSET(31) NUMBITS(1) [
+            Connected = 0,
+            Disconnected = 1
+        ]
+    ],
+    DBFEN [
+        DBFEN 0
+    ],
+    EVENT [
+        EVENT 0
+    ],
+    TASK [
+        TASK 0
+    ]
+];
+
+pub struct Qdec {
+    registers: StaticRef<QdecRegisters>,
+    client: OptionalCell<&'static qdec::Client>,
+    index_pin: OptionalCell<&'static gpio::Pin>,
+    enabled: Cell<bool>,
+    /// The position up to the last report
+    position: Cell<i32>,
+}
+
+impl Qdec {
+    const fn new(registers: StaticRef<QdecRegisters>) -> Qdec {
+        Qdec {
+            registers: registers,
+            client: OptionalCell::empty(),
+            index_pin: OptionalCell::empty(),
+            enabled: Cell::new(false),
+            position: Cell::new(0),
+        }
+    }
+
+    /// Sets the pins of the A and B outputs of the encoder.
+    pub fn set_pins(&self, a: Pinmux, b: Pinmux) {
+        let regs = &*self.registers;
+        regs.psel_a.write(PSEL::PIN.val(a.into()));
+        regs.psel_b.write(PSEL::PIN.val(b.into()));
+    }
+
+    /// Sets the pin of the index output of the encoder, which is active
+    /// high.
+    pub fn set_index_pin(&self, pin: &'static gpio::Pin) {
+        self.index_pin.set(pin);
+    }
+
+    pub fn handle_interrupt(&self) {
+        let regs = &*self.registers;
+
+        if regs.events_reportrdy.is_set(EVENT::EVENT) {
+            regs.events_reportrdy.write(EVENT::EVENT::CLEAR);
+            let position = self
+                .position
+                .get()
+                .wrapping_add(regs.accread.get() as i32);
+            self.position.set(position);
+            self.client
+                .map(|client| client.position_changed(position));
+        }
+    }
+}
+
+impl qdec::Qdec for Qdec {
+    fn set_client(&self, client: &'static qdec::Client) {
+        self.client.set(client);
+    }
+
+    fn enable(&self) -> ReturnCode {
+        if self.enabled.get() {
+            return ReturnCode::EALREADY;
+        }
+        let regs = &*self.registers;
+        regs.psel_led.write(PSEL::CONNECT::Disconnected);
+        regs.sampleper.write(SAMPLEPER::SAMPLEPER::Us128);
+        regs.reportper.write(REPORTPER::REPORTPER::Samples80);
+        regs.dbfen.write(DBFEN::DBFEN::SET);
+        regs.shorts.write(SHORTS::REPORTRDY_READCLRACC::SET);
+        regs.events_reportrdy.write(EVENT::EVENT::CLEAR);
+        regs.intenset.write(INTEN::REPORTRDY::SET);
+        regs.enable.write(ENABLE::ENABLE::SET);
+        regs.tasks_start.write(TASK::TASK::SET);
+
+        self.index_pin.map(|pin| {
+            pin.make_input();
+            pin.enable_interrupt(0, gpio::InterruptMode::RisingEdge);
+        });
+        self.enabled.set(true);
+        ReturnCode::SUCCESS
+    }
+
+    fn disable(&self) -> ReturnCode {
+        if !self.enabled.get() {
+            return ReturnCode::EOFF;
+        }
+        self.index_pin.map(|pin| pin.disable_interrupt());
+
+        // Keep the transitions since the last report
+        let position = qdec::Qdec::get_position(self);
+        let regs = &*self.registers;
+        regs.intenclr.write(INTEN::REPORTRDY::SET);
+        regs.tasks_stop.write(TASK::TASK::SET);
+        regs.tasks_readclracc.write(TASK::TASK::SET);
+        regs.enable.write(ENABLE::ENABLE::CLEAR);
+        self.position.set(position);
+        self.enabled.set(false);
+        ReturnCode::SUCCESS
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled.get()
+    }
+
+    fn get_position(&self) -> i32 {
+        let acc = if self.enabled.get() {
+            self.registers.acc.get() as i32
+        } else {
+            0
+        };
+        self.position.get().wrapping_add(acc)
+    }
+
+    fn set_position(&self, position: i32) {
+        if self.enabled.get() {
+            // Drop the transitions since the last report, without a report
+            self.registers.tasks_rdclracc.write(TASK::TASK::SET);
+        }
+        self.position.set(position);
+    }
+}
+
+impl gpio::Client for Qdec {
+    fn fired(&self, _: usize) {
+        if self.enabled.get() {
+            let position = qdec::Qdec::get_position(self);
+            self.client.map(|client| client.index(position));
+        }
+    }
+}
@@ -0,0 +1,468 @@
+//! QSPI driver for the nRF52840.
+//!
+//! The QSPI peripheral reads the flash with the READ4O instruction and
+//! programs it with the PP4O instruction, using 24-bit addresses, so the
+//! flash can be at most 16 MB. Both instructions need the quad enable bit of
+//! the flash's status register, which `initialize` sets as for Macronix
+//! flashes such as the MX25R6435F on the nRF52840-DK. EasyDMA moves whole
+//! words, so buffers, flash addresses and lengths must be word aligned.
+//!
+//! The peripheral signals the end of each task with the READY event. Writes
+//! and erases are followed by reading the status register with a custom
+//! instruction that waits until the flash is no longer busy, so that an
+//! operation is only done once the flash is.
+//!
+//! While the flash is activated, the peripheral maps it from address
+//! 0x12000000 for execute in place. The instruction cache also caches the
+//! mapped flash, so it is invalidated after the flash is programmed or erased
+//! while it is mapped. Code must not be executed from a sector while it is
+//! being programmed or erased.
+
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::registers::{ReadOnly, ReadWrite, WriteOnly};
+use kernel::common::StaticRef;
+use kernel::hil::qspi::{self, PAGE_SIZE, SECTOR_SIZE};
+use kernel::ReturnCode;
+use nrf5x::pinmux::Pinmux;
+use nvmc;
+
+const QSPI_BASE: StaticRef<QspiRegisters> =
+    unsafe { StaticRef::new(0x40029000 as *const QspiRegisters) };
+
+pub static mut QSPI: Qspi = Qspi::new(QSPI_BASE);
+
+#[repr(C)]
+struct QspiRegisters {
+    /// Activate QSPI interface
+    tasks_activate: WriteOnly<u32, TASK::Register>,
+    /// Start transfer from external flash memory to internal RAM
+    tasks_readstart: WriteOnly<u32, TASK::Register>,
+    /// Start transfer from internal RAM to external flash memory
+    tasks_writestart: WriteOnly<u32, TASK::Register>,
+    /// Start external flash memory erase operation
+    tasks_erasestart: WriteOnly<u32, TASK::Register>,
+    /// Deactivate QSPI interface
+    tasks_deactivate: WriteOnly<u32, TASK::Register>,
+    _reserved0: [u8; 236],
+    /// QSPI peripheral is ready
+    events_ready: ReadWrite<u32, EVENT::Register>,
+    _reserved1: [u8; 508],
+    /// Enable or disable interrupt
+    inten: ReadWrite<u32, INTEN::Register>,
+    /// Enable interrupt
+    intenset: ReadWrite<u32, INTEN::Register>,
+    /// Disable interrupt
+    intenclr: ReadWrite<u32, INTEN::Register>,
+    _reserved2: [u8; 500],
+    /// Enable QSPI peripheral and acquire the pins selected in PSELn registers
+    enable: ReadWrite<u32, ENABLE::Register>,
+    /// Flash memory source address
+    read_src: ReadWrite<u32>,
+    /// RAM destination address
+    read_dst: ReadWrite<u32>,
+    /// Read transfer length
+    read_cnt: ReadWrite<u32, CNT::Register>,
+    /// Flash destination address
+    write_dst: ReadWrite<u32>,
+    /// RAM source address
+    write_src: ReadWrite<u32>,
+    /// Write transfer length
+    write_cnt: ReadWrite<u32, CNT::Register>,
+    /// Start address of flash block to be erased
+    erase_ptr: ReadWrite<u32>,
+    /// Size of block to be erased
+    erase_len: ReadWrite<u32, ERASELEN::Register>,
+    /// Pin select for serial clock SCK
+    psel_sck: ReadWrite<u32>,
+    /// Pin select for chip select signal CSN
+    psel_csn: ReadWrite<u32>,
+    _reserved3: [u8; 4],
+    /// Pin select for serial data MOSI/IO0
+    psel_io0: ReadWrite<u32>,
+    /// Pin select for serial data MISO/IO1
+    psel_io1: ReadWrite<u32>,
+    /// Pin select for serial data IO2
+    psel_io2: ReadWrite<u32>,
+    /// Pin select for serial data IO3
+    psel_io3: ReadWrite<u32>,
+    /// Address offset into the external memory for Execute in Place operation
+    xipoffset: ReadWrite<u32>,
+    /// Interface configuration
+    ifconfig0: ReadWrite<u32, IFCONFIG0::Register>,
+    _reserved4: [u8; 184],
+    /// Interface configuration
+    ifconfig1: ReadWrite<u32, IFCONFIG1::Register>,
+    /// Status register
+    status: ReadOnly<u32, STATUS::Register>,
+    _reserved5: [u8; 44],
+    /// Custom instruction configuration register
+    cinstrconf: ReadWrite<u32, CINSTRCONF::Register>,
+    /// Custom instruction data register 0
+    cinstrdat0: ReadWrite<u32>,
+    /// Custom instruction data register 1
+    cinstrdat1: ReadWrite<u32>,
+}
+
+register_bitfields![u32,
+    INTEN [
+        /// Enable or disable interrupt on EVENTS_READY event
+        READY 0
+    ],
+    ENABLE [
+        ENABLE 0
+    ],
+    CNT [
+        CNT OFFSET(0) NUMBITS(20) []
+    ],
+    ERASELEN [
+        LEN OFFSET(0) NUMBITS(2) [
+            Len4KB = 0,
+            Len64KB = 1,
+            All = 2
+        ]
+    ],
+    IFCONFIG0 [
+        READOC OFFSET(0) NUMBITS(3) [
+            FastRead = 0,
+            Read2O = 1,
+            Read2IO = 2,
+            Read4O = 3,
+            Read4IO = 4
+        ],
+        WRITEOC OFFSET(3) NUMBITS(3) [
+            PP = 0,
+            PP2O = 1,
+            PP4O = 2,
+            PP4IO = 3
+        ],
+        ADDRMODE OFFSET(6) NUMBITS(1) [
+            Bit24 = 0,
+            Bit32 = 1
+        ],
+        DPMENABLE OFFSET(7) NUMBITS(1) [],
+        PPSIZE OFFSET(12) NUMBITS(1) [
+            Bytes256 = 0,
+            Bytes512 = 1
+        ]
+    ],
+    IFCONFIG1 [
+        /// Minimum amount of time that the CSN pin must stay high before it
+        /// can go low again, in 62.5 ns units
+        SCKDELAY OFFSET(0) NUMBITS(8) [],
+        DPMEN OFFSET(24) NUMBITS(1) [],
+        SPIMODE OFFSET(25) NUMBITS(1) [
+            Mode0 = 0,
+            Mode3 = 1
+        ],
+        /// SCK frequency is 32 MHz / (SCKFREQ + 1)
+        SCKFREQ OFFSET(28) NUMBITS(4) []
+    ],
+    STATUS [
+        DPM OFFSET(2) NUMBITS(1) [],
+        READY OFFSET(3) NUMBITS(1) [],
+        /// Value of the status register of the flash
+        SREG OFFSET(24) NUMBITS(8) []
+    ],
+    CINSTRCONF [
+        OPCODE OFFSET(0) NUMBITS(8) [],
+        /// Length of the instruction, including the opcode, in bytes
+        LENGTH OFFSET(8) NUMBITS(4) [],
+        /// Level of the IO2 line during the instruction
+        LIO2 OFFSET(12) NUMBITS(1) [],
+        /// Level of the IO3 line during the instruction
+        LIO3 OFFSET(13) NUMBITS(1) [],
+        /// Wait until the flash is no longer busy before the instruction
+        WIPWAIT OFFSET(14) NUMBITS(1) [],
+        /// Send the write enable instruction before the instruction
+        WREN OFFSET(15) NUMBITS(1) []
+    ],
+    EVENT [
+        EVENT 0
+    ],
+    TASK [
+        TASK 0
+    ]
+];
+
+/// The address the flash is mapped at
+const XIP_BASE: usize = 0x12000000;
+
+/// The largest flash that 24-bit addresses reach
+const MAX_SIZE: usize = 1 << 24;
+
+/// Flash instructions sent as custom instructions
+#[allow(dead_code)]
+enum Opcodes {
+    WRSR = 0x01, // Write Status Register
+    RDSR = 0x05, // Read Status Register
+}
+
+/// The quad enable bit of the status register of Macronix flashes
+const STATUS_QE: u32 = 1 << 6;
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Off,
+    Activating,
+    EnablingQuad,
+    Ready,
+    Reading { length: usize },
+    Programming { length: usize },
+    ProgrammingWait { length: usize },
+    Erasing,
+    ErasingWait,
+}
+
+pub struct Qspi {
+    registers: StaticRef<QspiRegisters>,
+    client: OptionalCell<&'static qspi::Client>,
+    state: Cell<State>,
+    size: Cell<usize>,
+    mapped: Cell<bool>,
+    buffer: TakeCell<'static, [u8]>,
+}
+
+impl Qspi {
+    const fn new(registers: StaticRef<QspiRegisters>) -> Qspi {
+        Qspi {
+            registers: registers,
+            client: OptionalCell::empty(),
+            state: Cell::new(State::Off),
+            size: Cell::new(0),
+            mapped: Cell::new(false),
+            buffer: TakeCell::empty(),
+        }
+    }
+
+    /// Sets the pins of the peripheral and activates the flash, which is
+    /// `size` bytes large. The client is told once the flash is ready.
+    pub fn initialize(
+        &self,
+        sck: Pinmux,
+        csn: Pinmux,
+        io: [Pinmux; 4],
+        size: usize,
+    ) -> ReturnCode {
+        if self.state.get() != State::Off {
+            return ReturnCode::EALREADY;
+        }
+        if size > MAX_SIZE || size % SECTOR_SIZE != 0 {
+            return ReturnCode::EINVAL;
+        }
+        let regs = &*self.registers;
+        regs.psel_sck.set(sck.into());
+        regs.psel_csn.set(csn.into());
+        regs.psel_io0.set(io[0].into());
+        regs.psel_io1.set(io[1].into());
+        regs.psel_io2.set(io[2].into());
+        regs.psel_io3.set(io[3].into());
+        regs.ifconfig0.write(
+            IFCONFIG0::READOC::Read4O
+                + IFCONFIG0::WRITEOC::PP4O
+                + IFCONFIG0::ADDRMODE::Bit24
+                + IFCONFIG0::PPSIZE::Bytes256,
+        );
+        // 16 MHz
+        regs.ifconfig1.write(
+            IFCONFIG1::SCKDELAY.val(1) + IFCONFIG1::SPIMODE::Mode0 + IFCONFIG1::SCKFREQ.val(1),
+        );
+        regs.xipoffset.set(0);
+
+        self.size.set(size);
+        self.state.set(State::Activating);
+        regs.events_ready.write(EVENT::EVENT::CLEAR);
+        regs.inten.write(INTEN::READY::SET);
+        regs.enable.write(ENABLE::ENABLE::SET);
+        regs.tasks_activate.write(TASK::TASK::SET);
+        ReturnCode::SUCCESS
+    }
+
+    pub fn handle_interrupt(&self) {
+        let regs = &*self.registers;
+        if !regs.events_ready.is_set(EVENT::EVENT) {
+            return;
+        }
+        regs.events_ready.write(EVENT::EVENT::CLEAR);
+
+        match self.state.get() {
+            State::Off | State::Ready => {}
+            State::Activating => {
+                self.state.set(State::EnablingQuad);
+                regs.cinstrdat0.set(STATUS_QE);
+                self.custom_instruction(Opcodes::WRSR, 2, true);
+            }
+            State::EnablingQuad => {
+                self.state.set(State::Ready);
+                self.client
+                    .map(|client| client.ready(ReturnCode::SUCCESS));
+            }
+            State::Reading { length } => {
+                self.state.set(State::Ready);
+                self.buffer.take().map(|buffer| {
+                    self.client
+                        .map(move |client| client.read_done(buffer, length, ReturnCode::SUCCESS));
+                });
+            }
+            State::Programming { length } => {
+                self.state.set(State::ProgrammingWait { length: length });
+                self.custom_instruction(Opcodes::RDSR, 2, false);
+            }
+            State::ProgrammingWait { length } => {
+                self.state.set(State::Ready);
+                self.flash_changed();
+                self.buffer.take().map(|buffer| {
+                    self.client.map(move |client| {
+                        client.program_done(buffer, length, ReturnCode::SUCCESS)
+                    });
+                });
+            }
+            State::Erasing => {
+                self.state.set(State::ErasingWait);
+                self.custom_instruction(Opcodes::RDSR, 2, false);
+            }
+            State::ErasingWait => {
+                self.state.set(State::Ready);
+                self.flash_changed();
+                self.client
+                    .map(|client| client.erase_done(ReturnCode::SUCCESS));
+            }
+        }
+    }
+
+    /// Send `opcode` once the flash is no longer busy, followed by the bytes
+    /// in CINSTRDAT0 up to a total of `length` bytes.
+    fn custom_instruction(&self, opcode: Opcodes, length: u32, write_enable: bool) {
+        let regs = &*self.registers;
+        regs.cinstrconf.write(
+            CINSTRCONF::OPCODE.val(opcode as u32)
+                + CINSTRCONF::LENGTH.val(length)
+                + CINSTRCONF::LIO2::SET
+                + CINSTRCONF::LIO3::SET
+                + CINSTRCONF::WIPWAIT::SET
+                + CINSTRCONF::WREN.val(write_enable as u32),
+        );
+    }
+
+    /// Makes sure no stale contents of the mapped flash are executed.
+    fn flash_changed(&self) {
+        if self.mapped.get() {
+            unsafe {
+                nvmc::NVMC.invalidate_instruction_cache();
+            }
+        }
+    }
+
+    /// Checks that an operation on `length` bytes at `address` can start.
+    fn check(&self, address: usize, buffer: &[u8], length: usize) -> ReturnCode {
+        match self.state.get() {
+            State::Ready => {}
+            State::Off | State::Activating | State::EnablingQuad => return ReturnCode::EOFF,
+            _ => return ReturnCode::EBUSY,
+        }
+        if length > buffer.len() || address + length > self.size.get() {
+            return ReturnCode::ESIZE;
+        }
+        if length == 0
+            || address % 4 != 0
+            || length % 4 != 0
+            || buffer.as_ptr() as usize % 4 != 0
+        {
+            return ReturnCode::EINVAL;
+        }
+        ReturnCode::SUCCESS
+    }
+}
+
+impl qspi::Qspi for Qspi {
+    fn set_client(&self, client: &'static qspi::Client) {
+        self.client.set(client);
+    }
+
+    fn size(&self) -> usize {
+        self.size.get()
+    }
+
+    fn read(
+        &self,
+        address: usize,
+        buffer: &'static mut [u8],
+        length: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        let result = self.check(address, buffer, length);
+        if result != ReturnCode::SUCCESS {
+            return (result, Some(buffer));
+        }
+        let regs = &*self.registers;
+        regs.read_src.set(address as u32);
+        regs.read_dst.set(buffer.as_mut_ptr() as u32);
+        regs.read_cnt.write(CNT::CNT.val(length as u32));
+        self.buffer.replace(buffer);
+        self.state.set(State::Reading { length: length });
+        regs.tasks_readstart.write(TASK::TASK::SET);
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn page_program(
+        &self,
+        address: usize,
+        buffer: &'static mut [u8],
+        length: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        let result = self.check(address, buffer, length);
+        if result != ReturnCode::SUCCESS {
+            return (result, Some(buffer));
+        }
+        if address / PAGE_SIZE != (address + length - 1) / PAGE_SIZE {
+            return (ReturnCode::EINVAL, Some(buffer));
+        }
+        let regs = &*self.registers;
+        regs.write_dst.set(address as u32);
+        regs.write_src.set(buffer.as_ptr() as u32);
+        regs.write_cnt.write(CNT::CNT.val(length as u32));
+        self.buffer.replace(buffer);
+        self.state.set(State::Programming { length: length });
+        regs.tasks_writestart.write(TASK::TASK::SET);
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn erase_sector(&self, address: usize) -> ReturnCode {
+        match self.state.get() {
+            State::Ready => {}
+            State::Off | State::Activating | State::EnablingQuad => return ReturnCode::EOFF,
+            _ => return ReturnCode::EBUSY,
+        }
+        if address % SECTOR_SIZE != 0 || address >= self.size.get() {
+            return ReturnCode::EINVAL;
+        }
+        let regs = &*self.registers;
+        regs.erase_ptr.set(address as u32);
+        regs.erase_len.write(ERASELEN::LEN::Len4KB);
+        self.state.set(State::Erasing);
+        regs.tasks_erasestart.write(TASK::TASK::SET);
+        ReturnCode::SUCCESS
+    }
+
+    fn enable_memory_mapping(&self) -> ReturnCode {
+        match self.state.get() {
+            State::Off | State::Activating | State::EnablingQuad => ReturnCode::EOFF,
+            _ => {
+                self.mapped.set(true);
+                ReturnCode::SUCCESS
+            }
+        }
+    }
+
+    fn disable_memory_mapping(&self) -> ReturnCode {
+        self.mapped.set(false);
+        ReturnCode::SUCCESS
+    }
+
+    fn mapped_address(&self) -> Option<usize> {
+        if self.mapped.get() {
+            Some(XIP_BASE)
+        } else {
+            None
+        }
+    }
+}
@@ -33,15 +33,20 @@
 //!
 //! * CRC - 3 bytes
 
+use clock::{self, HighClockSource, ManagedClock};
 use core::cell::Cell;
+use core::cmp;
 use core::convert::TryFrom;
 use kernel;
-use kernel::common::cells::OptionalCell;
+use kernel::capabilities::RadioTestCapability;
+use kernel::common::cells::{OptionalCell, TakeCell};
 use kernel::common::registers::{ReadOnly, ReadWrite, WriteOnly};
 use kernel::common::StaticRef;
 use kernel::hil::ble_advertising;
 use kernel::hil::ble_advertising::RadioChannel;
-use kernel::ReturnCode;
+use kernel::hil::radio_test;
+use kernel::hil::sniffer;
+use kernel::{ClockManager, ReturnCode};
 use nrf5x;
 use nrf5x::constants::TxPower;
 
@@ -533,8 +538,17 @@ static mut PAYLOAD: [u8; nrf5x::constants::RADIO_PAYLOAD_LENGTH] =
 pub struct Radio {
     registers: StaticRef<RadioRegisters>,
     tx_power: Cell<TxPower>,
+    /// A transmission is armed to be started by TIMER0
+    scheduled: Cell<bool>,
+    /// The radio is powered, and holds a request for the HFXO
+    powered: Cell<bool>,
+    sniffing: Cell<bool>,
+    testing: Cell<bool>,
     rx_client: OptionalCell<&'static ble_advertising::RxClient>,
     tx_client: OptionalCell<&'static ble_advertising::TxClient>,
+    /// The buffer the radio transmits from
+    tx_buffer: TakeCell<'static, [u8]>,
+    sniffer_client: OptionalCell<&'static sniffer::SnifferClient>,
 }
 
 pub static mut RADIO: Radio = Radio::new();
@@ -544,8 +558,14 @@ impl Radio {
         Radio {
             registers: RADIO_BASE,
             tx_power: Cell::new(TxPower::ZerodBm),
+            scheduled: Cell::new(false),
+            powered: Cell::new(false),
+            sniffing: Cell::new(false),
+            testing: Cell::new(false),
             rx_client: OptionalCell::empty(),
             tx_client: OptionalCell::empty(),
+            tx_buffer: TakeCell::empty(),
+            sniffer_client: OptionalCell::empty(),
         }
     }
 
@@ -561,6 +581,40 @@ impl Radio {
         regs.task_rxen.write(Task::ENABLE::SET);
     }
 
+    // Start the bit counter once the access address has been received, such that BCMATCH is
+    // generated when the PDU header is in RAM and can be inspected before the payload arrives
+    fn set_bit_counter(&self) {
+        let regs = &*self.registers;
+        regs.bcc
+            .write(BitCounterCompare::BCC.val(nrf5x::constants::RADIO_BCC_BLE_HEADER));
+        regs.event_bcmatch.write(Event::READY::CLEAR);
+        regs.shorts.write(Shortcut::ADDRESS_BCSTART::SET);
+    }
+
+    // Arm TIMER0 such that its COMPARE[0] event triggers TXEN through the pre-programmed PPI
+    // channel 20, and let the READY_START shortcut start the transmission once the radio has
+    // ramped up. Only the END interrupt is enabled so the CPU isn't woken up before that.
+    fn schedule_tx(&self, delay_us: u32) {
+        let regs = &*self.registers;
+        regs.event_ready.write(Event::READY::CLEAR);
+        regs.event_end.write(Event::READY::CLEAR);
+        regs.shorts.write(Shortcut::READY_START::SET);
+        self.scheduled.set(true);
+        unsafe {
+            nrf5x::ppi::PPI.enable(nrf5x::ppi::Channel::CH20::SET);
+            nrf5x::timer::TIMER0.start_oneshot_us(0, delay_us);
+        }
+        regs.intenset.write(Interrupt::END::SET);
+    }
+
+    fn clear_scheduled_tx(&self) {
+        self.scheduled.set(false);
+        unsafe {
+            nrf5x::timer::TIMER0.stop();
+            nrf5x::ppi::PPI.disable(nrf5x::ppi::Channel::CH20::SET);
+        }
+    }
+
     fn set_rx_address(&self) {
         let regs = &*self.registers;
         regs.rxaddresses.write(ReceiveAddresses::ADDRESS.val(1));
@@ -571,8 +625,19 @@ impl Radio {
         regs.txaddress.write(TransmitAddress::ADDRESS.val(0));
     }
 
+    // The radio only works from the HFXO, so it is requested when the radio is powered, and the
+    // radio waits the few hundred microseconds it takes to start
     fn radio_on(&self) {
         let regs = &*self.registers;
+        if !self.powered.get() {
+            self.powered.set(true);
+            unsafe {
+                clock::CLOCK.request(ManagedClock::High, 0);
+                while !clock::CLOCK.high_running()
+                    || clock::CLOCK.high_source() != HighClockSource::XTAL
+                {}
+            }
+        }
         // reset and enable power
         regs.power.write(Task::ENABLE::CLEAR);
         regs.power.write(Task::ENABLE::SET);
@@ -581,6 +646,12 @@ impl Radio {
     fn radio_off(&self) {
         let regs = &*self.registers;
         regs.power.write(Task::ENABLE::CLEAR);
+        if self.powered.get() {
+            self.powered.set(false);
+            unsafe {
+                clock::CLOCK.release(ManagedClock::High);
+            }
+        }
     }
 
     fn set_tx_power(&self) {
@@ -602,8 +673,11 @@ impl Radio {
 
         if regs.event_ready.is_set(Event::READY) {
             regs.event_ready.write(Event::READY::CLEAR);
-            regs.event_end.write(Event::READY::CLEAR);
-            regs.task_start.write(Task::ENABLE::SET);
+            // A scheduled transmission is started by the READY_START shortcut
+            if !self.scheduled.get() {
+                regs.event_end.write(Event::READY::CLEAR);
+                regs.task_start.write(Task::ENABLE::SET);
+            }
         }
 
         if regs.event_address.is_set(Event::READY) {
@@ -613,9 +687,37 @@ impl Radio {
             regs.event_payload.write(Event::READY::CLEAR);
         }
 
+        // PDU header received, let the client decide whether to receive the rest
+        if regs.event_bcmatch.is_set(Event::READY) {
+            regs.event_bcmatch.write(Event::READY::CLEAR);
+            regs.task_bcstop.write(Task::ENABLE::SET);
+
+            let accept = unsafe {
+                self.rx_client
+                    .map_or(true, |client| client.receive_filter(PAYLOAD[0], PAYLOAD[1]))
+            };
+            if !accept {
+                regs.event_end.write(Event::READY::CLEAR);
+                regs.task_disable.write(Task::ENABLE::SET);
+                self.radio_off();
+                unsafe {
+                    self.rx_client
+                        .map(|client| client.receive_event(&mut PAYLOAD, 0, ReturnCode::ECANCEL));
+                }
+            }
+        }
+
         // tx or rx finished!
+        if regs.event_end.is_set(Event::READY) && self.sniffing.get() {
+            regs.event_end.write(Event::READY::CLEAR);
+            self.capture_frame();
+        }
+
         if regs.event_end.is_set(Event::READY) {
             regs.event_end.write(Event::READY::CLEAR);
+            if self.scheduled.get() {
+                self.clear_scheduled_tx();
+            }
 
             let result = if regs.crcstatus.is_set(Event::READY) {
                 ReturnCode::SUCCESS
@@ -629,7 +731,10 @@ impl Radio {
                 | nrf5x::constants::RADIO_STATE_TXDISABLE
                 | nrf5x::constants::RADIO_STATE_TX => {
                     self.radio_off();
-                    self.tx_client.map(|client| client.transmit_event(result));
+                    self.tx_buffer.take().map(|buf| {
+                        self.tx_client
+                            .map(move |client| client.transmit_event(buf, result))
+                    });
                 }
                 nrf5x::constants::RADIO_STATE_RXRU
                 | nrf5x::constants::RADIO_STATE_RXIDLE
@@ -658,7 +763,8 @@ impl Radio {
             Interrupt::READY::SET
                 + Interrupt::ADDRESS::SET
                 + Interrupt::PAYLOAD::SET
-                + Interrupt::END::SET,
+                + Interrupt::END::SET
+                + Interrupt::BCMATCH::SET,
         );
     }
 
@@ -678,14 +784,80 @@ impl Radio {
         regs.intenclr.set(0xffffffff);
     }
 
-    fn replace_radio_buffer(&self, buf: &'static mut [u8]) -> &'static mut [u8] {
-        // set payload
-        for (i, c) in buf.as_ref().iter().enumerate() {
-            unsafe {
-                PAYLOAD[i] = *c;
-            }
+    // Transmit directly from `buf` rather than from a copy in `PAYLOAD`. This must
+    // follow `ble_initialize`, which points the radio at `PAYLOAD` for receiving.
+    fn set_tx_buffer(&self, buf: &'static mut [u8]) {
+        let regs = &*self.registers;
+        regs.packetptr.set(buf.as_ptr() as u32);
+        self.tx_buffer.replace(buf);
+    }
+
+    // Configure the radio like for BLE but with an arbitrary access address, frequency, data rate
+    // and whitening, and sample the RSSI of every received frame
+    fn sniffer_initialize(&self, config: sniffer::SnifferConfig) {
+        let regs = &*self.registers;
+        self.radio_on();
+
+        regs.mode
+            .set(nrf5x::constants::RadioMode::from(config.data_rate) as u32);
+        regs.frequency
+            .write(Frequency::FREQUENCY.val(config.frequency as u32));
+
+        self.set_tx_address();
+        self.set_rx_address();
+
+        self.ble_set_packet_config();
+        match config.whitening {
+            Some(iv) => regs.datawhiteiv.set(iv as u32),
+            None => regs.pcnf1.modify(PacketConfiguration1::WHITEEN::DISABLED),
         }
-        buf
+
+        // The most significant byte of the access address is the prefix
+        regs.prefix0.set(config.access_address >> 24);
+        regs.base0.set(config.access_address << 8);
+
+        self.ble_set_crc_config();
+
+        self.set_dma_ptr();
+
+        regs.shorts
+            .write(Shortcut::ADDRESS_RSSISTART::SET + Shortcut::DISABLED_RSSISTOP::SET);
+    }
+
+    // Pass a frame received in sniffer mode to the client and start receiving the next one
+    fn capture_frame(&self) {
+        let regs = &*self.registers;
+        let crc_ok = regs.crcstatus.is_set(Event::READY);
+        let rssi = regs.rssisample.read(RssiSample::RSSISAMPLE) as u8;
+        unsafe {
+            let len = cmp::min(PAYLOAD[1] as usize + 2, PAYLOAD.len());
+            self.sniffer_client
+                .map(|client| client.frame_captured(&PAYLOAD[..len], crc_ok, rssi));
+        }
+        // The radio stays in RXIDLE after END, so it can be started right away
+        regs.task_start.write(Task::ENABLE::SET);
+    }
+
+    fn is_busy(&self) -> bool {
+        self.sniffing.get() || self.scheduled.get() || self.testing.get()
+    }
+
+    // Configure the radio for a test mode on `frequency`, using the BLE 1 Mbit mode without
+    // whitening so that the test pattern is transmitted as is
+    fn test_initialize(&self, frequency: u8) {
+        let regs = &*self.registers;
+        self.disable_all_interrupts();
+        self.radio_on();
+        self.ble_set_tx_power();
+        self.ble_set_channel_rate();
+        self.ble_set_channel_freq(frequency as u32);
+        self.set_tx_address();
+        self.ble_set_packet_config();
+        regs.pcnf1.modify(PacketConfiguration1::WHITEEN::DISABLED);
+        self.ble_set_advertising_access_address();
+        self.ble_set_crc_config();
+        self.set_dma_ptr();
+        self.testing.set(true);
     }
 
     fn ble_initialize(&self, channel: RadioChannel) {
@@ -695,8 +867,8 @@ impl Radio {
 
         self.ble_set_channel_rate();
 
-        self.ble_set_channel_freq(channel);
-        self.ble_set_data_whitening(channel);
+        self.ble_set_channel_freq(channel.get_frequency());
+        self.ble_set_data_whitening(channel.get_whitening_iv());
 
         self.set_tx_address();
         self.set_rx_address();
@@ -765,20 +937,21 @@ impl Radio {
     }
 
     // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 3.2 Data Whitening
-    // Configure channel index to the LFSR and the hardware solves the rest
-    fn ble_set_data_whitening(&self, channel: RadioChannel) {
+    // Configure the initial value of the LFSR and the hardware solves the rest
+    fn ble_set_data_whitening(&self, iv: u32) {
         let regs = &*self.registers;
-        regs.datawhiteiv.set(channel.get_channel_index());
+        regs.datawhiteiv.write(DataWhiteIv::DATEWHITEIV.val(iv));
     }
 
     // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 1.4.1
     // RF Channels:     0 - 39
     // Data:            0 - 36
     // Advertising:     37, 38, 39
-    fn ble_set_channel_freq(&self, channel: RadioChannel) {
+    //
+    // `frequency` is in MHz above 2400 MHz, see `RadioChannel::get_frequency`
+    fn ble_set_channel_freq(&self, frequency: u32) {
         let regs = &*self.registers;
-        regs.frequency
-            .write(Frequency::FREQUENCY.val(channel as u32));
+        regs.frequency.write(Frequency::FREQUENCY.val(frequency));
     }
 
     // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 3 TRANSMITTER CHARACTERISTICS
@@ -793,21 +966,16 @@ impl Radio {
 }
 
 impl ble_advertising::BleAdvertisementDriver for Radio {
-    fn transmit_advertisement(
-        &self,
-        buf: &'static mut [u8],
-        _len: usize,
-        channel: RadioChannel,
-    ) -> &'static mut [u8] {
-        let res = self.replace_radio_buffer(buf);
+    fn transmit_advertisement(&self, buf: &'static mut [u8], _len: usize, channel: RadioChannel) {
         self.ble_initialize(channel);
+        self.set_tx_buffer(buf);
         self.tx();
         self.enable_interrupts();
-        res
     }
 
     fn receive_advertisement(&self, channel: RadioChannel) {
         self.ble_initialize(channel);
+        self.set_bit_counter();
         self.rx();
         self.enable_interrupts();
     }
@@ -821,6 +989,31 @@ impl ble_advertising::BleAdvertisementDriver for Radio {
     }
 }
 
+impl ble_advertising::BleAdvertisementScheduler for Radio {
+    fn schedule_transmit_advertisement(
+        &self,
+        buf: &'static mut [u8],
+        _len: usize,
+        channel: RadioChannel,
+        delay_us: u32,
+    ) {
+        self.ble_initialize(channel);
+        self.set_tx_buffer(buf);
+        self.schedule_tx(delay_us);
+    }
+
+    fn cancel_scheduled_advertisement(&self) -> Option<&'static mut [u8]> {
+        if self.scheduled.get() {
+            self.disable_all_interrupts();
+            self.clear_scheduled_tx();
+            self.radio_off();
+            self.tx_buffer.take()
+        } else {
+            None
+        }
+    }
+}
+
 impl ble_advertising::BleConfig for Radio {
     // The BLE Advertising Driver validates that the `tx_power` is between -20 to 10 dBm but then
     // underlying chip must validate if the current `tx_power` is supported as well
@@ -837,3 +1030,78 @@ impl ble_advertising::BleConfig for Radio {
         }
     }
 }
+
+impl sniffer::Sniffer for Radio {
+    fn set_sniffer_client(&self, client: &'static sniffer::SnifferClient) {
+        self.sniffer_client.set(client);
+    }
+
+    fn start_sniffing(&self, config: sniffer::SnifferConfig) -> ReturnCode {
+        if self.is_busy() {
+            return ReturnCode::EBUSY;
+        }
+        self.sniffing.set(true);
+        self.sniffer_initialize(config);
+        self.rx();
+        self.enable_interrupts();
+        ReturnCode::SUCCESS
+    }
+
+    fn stop_sniffing(&self) -> ReturnCode {
+        if !self.sniffing.get() {
+            return ReturnCode::EALREADY;
+        }
+        self.disable_all_interrupts();
+        self.sniffing.set(false);
+        self.radio_off();
+        ReturnCode::SUCCESS
+    }
+}
+
+impl radio_test::RadioTest for Radio {
+    fn start_constant_carrier(
+        &self,
+        frequency: u8,
+        _capability: &RadioTestCapability,
+    ) -> ReturnCode {
+        if self.is_busy() {
+            return ReturnCode::EBUSY;
+        }
+        self.test_initialize(frequency);
+        let regs = &*self.registers;
+        // Without a START task the radio transmits the center frequency after ramp-up
+        regs.modecnf0.write(RadioModeConfig::DTX::CENTER);
+        regs.task_txen.write(Task::ENABLE::SET);
+        ReturnCode::SUCCESS
+    }
+
+    fn start_prbs9(&self, frequency: u8, _capability: &RadioTestCapability) -> ReturnCode {
+        if self.is_busy() {
+            return ReturnCode::EBUSY;
+        }
+        unsafe {
+            // Maximum length packets, back to back
+            PAYLOAD[0] = 0;
+            PAYLOAD[1] = (PAYLOAD.len() - 2) as u8;
+            radio_test::fill_prbs9(&mut PAYLOAD[2..]);
+        }
+        self.test_initialize(frequency);
+        let regs = &*self.registers;
+        regs.shorts
+            .write(Shortcut::READY_START::SET + Shortcut::END_START::SET);
+        regs.task_txen.write(Task::ENABLE::SET);
+        ReturnCode::SUCCESS
+    }
+
+    fn stop_test(&self) -> ReturnCode {
+        if !self.testing.get() {
+            return ReturnCode::EALREADY;
+        }
+        let regs = &*self.registers;
+        regs.shorts.set(0);
+        regs.task_disable.write(Task::ENABLE::SET);
+        self.radio_off();
+        self.testing.set(false);
+        ReturnCode::SUCCESS
+    }
+}
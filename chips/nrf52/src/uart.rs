@@ -482,3 +482,24 @@ impl kernel::hil::uart::UART for Uarte {
         regs.task_stoprx.write(Task::ENABLE::SET);
     }
 }
+
+impl kernel::hil::uart::UARTEmergency for Uarte {
+    unsafe fn emergency_write(&self, bytes: &[u8]) {
+        let regs = &*self.registers;
+
+        // Stop any DMA transmission in progress, its client is not signaled
+        if self.tx_buffer.take().is_some() {
+            self.disable_tx_interrupts();
+            regs.event_txstopped.write(Event::READY::CLEAR);
+            regs.task_stoptx.write(Task::ENABLE::SET);
+            while !regs.event_txstopped.is_set(Event::READY) {}
+        }
+
+        self.enable_uart();
+        for &byte in bytes {
+            self.send_byte(byte);
+            while !self.tx_ready() {}
+        }
+        self.tx_remaining_bytes.set(0);
+    }
+}
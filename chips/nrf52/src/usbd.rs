@@ -0,0 +1,783 @@
+//! USB device controller (USBD) driver for the nRF52840.
+//!
+//! The USBD is a full-speed device controller with a control endpoint and
+//! seven bulk or interrupt endpoints in each direction. Each of the endpoint
+//! numbers of `hil::usb` is used in one direction only, as on the SAM4L, so a
+//! client enables endpoint 1 as Bulk IN and endpoint 2 as Bulk OUT rather
+//! than both directions of endpoint 1.
+//!
+//! Unlike the SAM4L USBC, the USBD does not send and receive packets from
+//! the endpoint buffers of the client. It keeps each packet in a buffer of
+//! its own, and EasyDMA copies packets between these buffers and RAM. Only
+//! one EasyDMA transfer may run at a time, and a packet is at most 64 bytes,
+//! so the driver waits for each transfer to end before it goes on.
+//!
+//! The USBD decodes the SETUP packet of a control transfer into registers,
+//! from which the driver copies it into the buffer of the control endpoint
+//! for the client. It also handles the data toggles and `SET_ADDRESS` by
+//! itself, so `set_address()` and `enable_address()` do nothing.
+//!
+//! The USBD is clocked from the HFXO, which it requests through the clock
+//! manager when it is enabled. It only becomes ready once the board is
+//! powered over USB, so `attach()` connects the pull-up on D+ once it is.
+//! Suspend and resume are not handled.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let cdc = static_init!(
+//!     capsules::cdc::CdcAcm<'static, nrf52::usbd::Usbd<'static>>,
+//!     capsules::cdc::CdcAcm::new(&nrf52::usbd::USBD)
+//! );
+//! nrf52::usbd::USBD.set_client(cdc);
+//! hil::usb::Client::enable(cdc);
+//! hil::usb::Client::attach(cdc);
+//! ```
+
+use clock::{self, ManagedClock};
+use core::cell::Cell;
+use core::ptr;
+use kernel::common::cells::{OptionalCell, VolatileCell};
+use kernel::common::registers::{FieldValue, ReadOnly, ReadWrite, WriteOnly};
+use kernel::common::StaticRef;
+use kernel::hil;
+use kernel::ClockManager;
+
+const USBD_BASE: StaticRef<UsbdRegisters> =
+    unsafe { StaticRef::new(0x40027000 as *const UsbdRegisters) };
+
+/// Registers of the workarounds for the anomalies of the nRF52840 in the
+/// errata: 171 and 187 keep the USBD from working after it is enabled, and
+/// 199 corrupts EasyDMA transfers of the USBD.
+const ERRATA_UNLOCK: *mut u32 = 0x4006EC00 as *mut u32;
+const ERRATA_171: *mut u32 = 0x4006EC14 as *mut u32;
+const ERRATA_187: *mut u32 = 0x4006ED14 as *mut u32;
+const ERRATA_199: *mut u32 = 0x40027C1C as *mut u32;
+const ERRATA_UNLOCK_KEY: u32 = 0x00009375;
+
+pub const N_ENDPOINTS: usize = 8;
+
+/// The largest packet of any endpoint
+const MAX_PACKET_SIZE: usize = 64;
+
+#[repr(C)]
+struct EndpointDma {
+    /// Data pointer
+    ptr: ReadWrite<u32>,
+    /// Maximum number of bytes to transfer
+    maxcnt: ReadWrite<u32>,
+    /// Number of bytes transferred in the last transaction
+    amount: ReadOnly<u32>,
+    _reserved: [u32; 2],
+}
+
+#[repr(C)]
+struct UsbdRegisters {
+    _reserved0: u32,
+    /// Captures the EPIN[n].PTR and EPIN[n].MAXCNT registers values, and
+    /// enables endpoint IN n to respond to traffic from host
+    tasks_startepin: [WriteOnly<u32, Task::Register>; N_ENDPOINTS],
+    tasks_startisoin: WriteOnly<u32, Task::Register>,
+    /// Captures the EPOUT[n].PTR and EPOUT[n].MAXCNT registers values, and
+    /// enables endpoint n to respond to traffic from host
+    tasks_startepout: [WriteOnly<u32, Task::Register>; N_ENDPOINTS],
+    tasks_startisoout: WriteOnly<u32, Task::Register>,
+    /// Allows OUT data stage on control endpoint 0
+    tasks_ep0rcvout: WriteOnly<u32, Task::Register>,
+    /// Allows status stage on control endpoint 0
+    tasks_ep0status: WriteOnly<u32, Task::Register>,
+    /// Stalls data and status stage on control endpoint 0
+    tasks_ep0stall: WriteOnly<u32, Task::Register>,
+    tasks_dpdmdrive: WriteOnly<u32, Task::Register>,
+    tasks_dpdmnodrive: WriteOnly<u32, Task::Register>,
+    _reserved1: [u32; 40],
+    /// Signals that a USB reset condition has been detected on USB lines
+    events_usbreset: ReadWrite<u32, Event::Register>,
+    events_started: ReadWrite<u32, Event::Register>,
+    /// The whole EPIN[n] buffer has been consumed
+    events_endepin: [ReadWrite<u32, Event::Register>; N_ENDPOINTS],
+    /// An acknowledged data transfer has taken place on the control endpoint
+    events_ep0datadone: ReadWrite<u32, Event::Register>,
+    events_endisoin: ReadWrite<u32, Event::Register>,
+    /// The whole EPOUT[n] buffer has been consumed
+    events_endepout: [ReadWrite<u32, Event::Register>; N_ENDPOINTS],
+    events_endisoout: ReadWrite<u32, Event::Register>,
+    events_sof: ReadWrite<u32, Event::Register>,
+    /// An event or an error not covered by specific events has occurred,
+    /// see EVENTCAUSE
+    events_usbevent: ReadWrite<u32, Event::Register>,
+    /// A valid SETUP token has been received on the control endpoint
+    events_ep0setup: ReadWrite<u32, Event::Register>,
+    /// A data transfer has occurred on a data endpoint, see EPDATASTATUS
+    events_epdata: ReadWrite<u32, Event::Register>,
+    _reserved2: [u32; 39],
+    shorts: ReadWrite<u32>,
+    _reserved3: [u32; 63],
+    inten: ReadWrite<u32, Interrupt::Register>,
+    intenset: ReadWrite<u32, Interrupt::Register>,
+    intenclr: ReadWrite<u32, Interrupt::Register>,
+    _reserved4: [u32; 61],
+    /// Details on what caused the USBEVENT event
+    eventcause: ReadWrite<u32, EventCause::Register>,
+    _reserved5: [u32; 7],
+    halted_epin: [ReadOnly<u32>; N_ENDPOINTS],
+    _reserved6: u32,
+    halted_epout: [ReadOnly<u32>; N_ENDPOINTS],
+    _reserved7: u32,
+    epstatus: ReadWrite<u32>,
+    /// Which data endpoints the EPDATA event was for, write 1 to clear
+    epdatastatus: ReadWrite<u32>,
+    usbaddr: ReadOnly<u32>,
+    _reserved8: [u32; 3],
+    /// The fields of the last SETUP packet
+    bmrequesttype: ReadOnly<u32>,
+    brequest: ReadOnly<u32>,
+    wvaluel: ReadOnly<u32>,
+    wvalueh: ReadOnly<u32>,
+    windexl: ReadOnly<u32>,
+    windexh: ReadOnly<u32>,
+    wlengthl: ReadOnly<u32>,
+    wlengthh: ReadOnly<u32>,
+    /// Number of bytes received last in the data stage of OUT endpoint n
+    size_epout: [ReadWrite<u32>; N_ENDPOINTS],
+    size_isoout: ReadOnly<u32>,
+    _reserved9: [u32; 15],
+    enable: ReadWrite<u32, Enable::Register>,
+    usbpullup: ReadWrite<u32, UsbPullup::Register>,
+    dpdmvalue: ReadWrite<u32>,
+    dtoggle: ReadWrite<u32>,
+    /// Enables the IN endpoints, one bit each
+    epinen: ReadWrite<u32>,
+    /// Enables the OUT endpoints, one bit each
+    epouten: ReadWrite<u32>,
+    epstall: WriteOnly<u32, EndpointStall::Register>,
+    isosplit: ReadWrite<u32>,
+    framecntr: ReadOnly<u32>,
+    _reserved10: [u32; 2],
+    lowpower: ReadWrite<u32>,
+    isoinconfig: ReadWrite<u32>,
+    _reserved11: [u32; 51],
+    epin: [EndpointDma; N_ENDPOINTS],
+    _reserved12: [u32; 24],
+    epout: [EndpointDma; N_ENDPOINTS],
+}
+
+register_bitfields![u32,
+    Task [
+        TRIGGER 0
+    ],
+    Event [
+        READY 0
+    ],
+    Interrupt [
+        USBRESET 0,
+        EP0DATADONE 10,
+        USBEVENT 22,
+        EP0SETUP 23,
+        EPDATA 24
+    ],
+    EventCause [
+        ISOOUTCRC 0,
+        SUSPEND 8,
+        RESUME 9,
+        USBWUALLOWED 10,
+        READY 11
+    ],
+    Enable [
+        ENABLE 0
+    ],
+    UsbPullup [
+        CONNECT 0
+    ],
+    EndpointStall [
+        EP OFFSET(0) NUMBITS(3) [],
+        IO OFFSET(7) NUMBITS(1) [
+            Out = 0,
+            In = 1
+        ],
+        STALL OFFSET(8) NUMBITS(1) []
+    ]
+];
+
+/// The bit of an IN endpoint in EPDATASTATUS
+fn epdata_in(endpoint: usize) -> u32 {
+    1 << endpoint
+}
+
+/// The bit of an OUT endpoint in EPDATASTATUS
+fn epdata_out(endpoint: usize) -> u32 {
+    1 << (16 + endpoint)
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum State {
+    // Controller disabled
+    Reset,
+
+    // Controller enabled, detached from bus
+    Idle,
+
+    // Controller enabled, attached to bus once it is ready
+    Active,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum EndpointState {
+    Disabled,
+    Ctrl(CtrlState),
+    BulkIn(BulkInState),
+    BulkOut(BulkOutState),
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum CtrlState {
+    Init,
+    /// Sending the IN data stage. `complete` once the client has passed the
+    /// last packet, after which a zero-length packet is sent if `zlp`.
+    ReadIn {
+        complete: bool,
+        zlp: bool,
+    },
+    WriteOut,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum BulkInState {
+    /// The endpoint can take a packet from the client
+    Init,
+    /// A packet waits for the host to read it
+    InFlight,
+    Delay,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum BulkOutState {
+    Init,
+    /// The client has not consumed the packet of `size` bytes in its
+    /// buffer yet. If `pending`, the host has sent the next one.
+    Delay {
+        size: usize,
+        pending: bool,
+    },
+}
+
+pub struct Usbd<'a> {
+    registers: StaticRef<UsbdRegisters>,
+    state: Cell<State>,
+    /// The USBD has signalled that it is ready to attach
+    ready: Cell<bool>,
+    /// The buffer of each endpoint and its length
+    buffers: [Cell<(*mut u8, usize)>; N_ENDPOINTS],
+    endpoints: [Cell<EndpointState>; N_ENDPOINTS],
+    /// The length of the data stage of the current control transfer, and
+    /// the bytes of it transferred so far
+    ctrl_length: Cell<usize>,
+    ctrl_bytes: Cell<usize>,
+    client: OptionalCell<&'a hil::usb::Client>,
+}
+
+impl Usbd<'a> {
+    const fn new() -> Self {
+        Usbd {
+            registers: USBD_BASE,
+            state: Cell::new(State::Reset),
+            ready: Cell::new(false),
+            buffers: [
+                Cell::new((ptr::null_mut(), 0)),
+                Cell::new((ptr::null_mut(), 0)),
+                Cell::new((ptr::null_mut(), 0)),
+                Cell::new((ptr::null_mut(), 0)),
+                Cell::new((ptr::null_mut(), 0)),
+                Cell::new((ptr::null_mut(), 0)),
+                Cell::new((ptr::null_mut(), 0)),
+                Cell::new((ptr::null_mut(), 0)),
+            ],
+            endpoints: [
+                Cell::new(EndpointState::Disabled),
+                Cell::new(EndpointState::Disabled),
+                Cell::new(EndpointState::Disabled),
+                Cell::new(EndpointState::Disabled),
+                Cell::new(EndpointState::Disabled),
+                Cell::new(EndpointState::Disabled),
+                Cell::new(EndpointState::Disabled),
+                Cell::new(EndpointState::Disabled),
+            ],
+            ctrl_length: Cell::new(0),
+            ctrl_bytes: Cell::new(0),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Set a client to receive data from the USBD
+    pub fn set_client(&self, client: &'a hil::usb::Client) {
+        self.client.set(client);
+    }
+
+    /// Write `value` to a register of the errata workarounds.
+    fn errata_write(&self, register: *mut u32, value: u32) {
+        unsafe {
+            if ptr::read_volatile(ERRATA_UNLOCK) == 0 {
+                ptr::write_volatile(ERRATA_UNLOCK, ERRATA_UNLOCK_KEY);
+            }
+            ptr::write_volatile(register, value);
+            ptr::write_volatile(ERRATA_UNLOCK, ERRATA_UNLOCK_KEY);
+        }
+    }
+
+    /// Run an EasyDMA transfer to completion
+    fn dma(&self, task: &WriteOnly<u32, Task::Register>, end: &ReadWrite<u32, Event::Register>) {
+        end.write(Event::READY::CLEAR);
+        unsafe {
+            ptr::write_volatile(ERRATA_199, 0x82);
+        }
+        task.write(Task::TRIGGER::SET);
+        while !end.is_set(Event::READY) {}
+        end.write(Event::READY::CLEAR);
+        unsafe {
+            ptr::write_volatile(ERRATA_199, 0);
+        }
+    }
+
+    /// Pass the first `len` bytes of the buffer of IN endpoint `endpoint`
+    /// to the USBD to send
+    fn dma_in(&self, endpoint: usize, len: usize) {
+        let regs = &*self.registers;
+        let (buf, _) = self.buffers[endpoint].get();
+        regs.epin[endpoint].ptr.set(buf as u32);
+        regs.epin[endpoint].maxcnt.set(len as u32);
+        self.dma(
+            &regs.tasks_startepin[endpoint],
+            &regs.events_endepin[endpoint],
+        );
+    }
+
+    /// Copy the packet the USBD received on OUT endpoint `endpoint` into its
+    /// buffer, returning its length
+    fn dma_out(&self, endpoint: usize) -> usize {
+        let regs = &*self.registers;
+        let (buf, buf_len) = self.buffers[endpoint].get();
+        let size = regs.size_epout[endpoint].get() as usize;
+        regs.epout[endpoint].ptr.set(buf as u32);
+        regs.epout[endpoint]
+            .maxcnt
+            .set(if size < buf_len { size } else { buf_len } as u32);
+        self.dma(
+            &regs.tasks_startepout[endpoint],
+            &regs.events_endepout[endpoint],
+        );
+        regs.epout[endpoint].amount.get() as usize
+    }
+
+    /// Enable the endpoints in EPINEN and EPOUTEN that have a state
+    fn write_endpoint_enables(&self) {
+        let regs = &*self.registers;
+        let mut epinen = 0;
+        let mut epouten = 0;
+        for (endpoint, state) in self.endpoints.iter().enumerate() {
+            match state.get() {
+                EndpointState::Ctrl(_) => {
+                    epinen |= 1 << endpoint;
+                    epouten |= 1 << endpoint;
+                }
+                EndpointState::BulkIn(_) => epinen |= 1 << endpoint,
+                EndpointState::BulkOut(_) => epouten |= 1 << endpoint,
+                EndpointState::Disabled => {}
+            }
+        }
+        regs.epinen.set(epinen);
+        regs.epouten.set(epouten);
+    }
+
+    /// Respond with STALL on `endpoint` in the direction of `io`
+    fn stall(&self, endpoint: usize, io: FieldValue<u32, EndpointStall::Register>) {
+        let regs = &*self.registers;
+        regs.epstall
+            .write(EndpointStall::EP.val(endpoint as u32) + io + EndpointStall::STALL::SET);
+    }
+
+    /// Handle an interrupt from the USBD
+    pub fn handle_interrupt(&self) {
+        let regs = &*self.registers;
+
+        if regs.events_usbevent.is_set(Event::READY) {
+            regs.events_usbevent.write(Event::READY::CLEAR);
+            let cause = regs.eventcause.extract();
+            // Acknowledge the causes that were read
+            regs.eventcause.set(cause.get());
+            if cause.is_set(EventCause::READY) {
+                self.handle_ready();
+            }
+        }
+
+        if regs.events_usbreset.is_set(Event::READY) {
+            regs.events_usbreset.write(Event::READY::CLEAR);
+            self.handle_bus_reset();
+        }
+
+        if regs.events_ep0datadone.is_set(Event::READY) {
+            regs.events_ep0datadone.write(Event::READY::CLEAR);
+            self.handle_ctrl_data_done();
+        }
+
+        if regs.events_ep0setup.is_set(Event::READY) {
+            regs.events_ep0setup.write(Event::READY::CLEAR);
+            self.handle_ctrl_setup();
+        }
+
+        if regs.events_epdata.is_set(Event::READY) {
+            regs.events_epdata.write(Event::READY::CLEAR);
+            let status = regs.epdatastatus.get();
+            // Acknowledge the endpoints that were read
+            regs.epdatastatus.set(status);
+            for endpoint in 1..N_ENDPOINTS {
+                if status & epdata_in(endpoint) != 0 {
+                    self.handle_bulk_in_done(endpoint);
+                }
+                if status & epdata_out(endpoint) != 0 {
+                    self.handle_bulk_out_received(endpoint);
+                }
+            }
+        }
+    }
+
+    /// The USBD is powered and ready, so the workarounds for errata 171 and
+    /// 187 can be undone, and the device can attach if it was asked to
+    fn handle_ready(&self) {
+        let regs = &*self.registers;
+        self.errata_write(ERRATA_171, 0);
+        self.errata_write(ERRATA_187, 0);
+        self.ready.set(true);
+        if self.state.get() == State::Active {
+            regs.usbpullup.write(UsbPullup::CONNECT::SET);
+        }
+    }
+
+    fn handle_bus_reset(&self) {
+        // Return every endpoint to its initial state
+        for state in self.endpoints.iter() {
+            state.set(match state.get() {
+                EndpointState::Ctrl(_) => EndpointState::Ctrl(CtrlState::Init),
+                EndpointState::BulkIn(_) => EndpointState::BulkIn(BulkInState::Init),
+                EndpointState::BulkOut(_) => EndpointState::BulkOut(BulkOutState::Init),
+                EndpointState::Disabled => EndpointState::Disabled,
+            });
+        }
+        self.write_endpoint_enables();
+
+        self.client.map(|client| client.bus_reset());
+
+        // The Bulk IN endpoints can take a packet to send right away
+        for endpoint in 1..N_ENDPOINTS {
+            if self.endpoints[endpoint].get() == EndpointState::BulkIn(BulkInState::Init) {
+                self.bulk_in_start(endpoint);
+            }
+        }
+    }
+
+    fn handle_ctrl_setup(&self) {
+        let regs = &*self.registers;
+        let setup = [
+            regs.bmrequesttype.get() as u8,
+            regs.brequest.get() as u8,
+            regs.wvaluel.get() as u8,
+            regs.wvalueh.get() as u8,
+            regs.windexl.get() as u8,
+            regs.windexh.get() as u8,
+            regs.wlengthl.get() as u8,
+            regs.wlengthh.get() as u8,
+        ];
+
+        // A new SETUP ends any transfer in progress
+        self.endpoints[0].set(EndpointState::Ctrl(CtrlState::Init));
+
+        let (buf, buf_len) = self.buffers[0].get();
+        if buf.is_null() || buf_len < setup.len() {
+            self.ctrl_stall();
+            return;
+        }
+        for (i, byte) in setup.iter().enumerate() {
+            unsafe {
+                ptr::write_volatile(buf.offset(i as isize), *byte);
+            }
+        }
+
+        let length = setup[6] as usize | (setup[7] as usize) << 8;
+        self.ctrl_length.set(length);
+        self.ctrl_bytes.set(0);
+
+        match self.client.map(|client| client.ctrl_setup(0)) {
+            Some(hil::usb::CtrlSetupResult::Ok) => {
+                if length == 0 {
+                    self.ctrl_status();
+                } else if setup[0] & 0x80 != 0 {
+                    self.ctrl_in_next();
+                } else {
+                    self.endpoints[0].set(EndpointState::Ctrl(CtrlState::WriteOut));
+                    regs.tasks_ep0rcvout.write(Task::TRIGGER::SET);
+                }
+            }
+            _ => self.ctrl_stall(),
+        }
+    }
+
+    fn handle_ctrl_data_done(&self) {
+        let regs = &*self.registers;
+        match self.endpoints[0].get() {
+            EndpointState::Ctrl(CtrlState::ReadIn {
+                complete: false, ..
+            }) => self.ctrl_in_next(),
+            EndpointState::Ctrl(CtrlState::ReadIn {
+                complete: true,
+                zlp: true,
+            }) => {
+                self.endpoints[0].set(EndpointState::Ctrl(CtrlState::ReadIn {
+                    complete: true,
+                    zlp: false,
+                }));
+                self.dma_in(0, 0);
+            }
+            EndpointState::Ctrl(CtrlState::ReadIn {
+                complete: true,
+                zlp: false,
+            }) => self.ctrl_status(),
+            EndpointState::Ctrl(CtrlState::WriteOut) => {
+                let packet_bytes = self.dma_out(0);
+                match self
+                    .client
+                    .map(|client| client.ctrl_out(0, packet_bytes as u32))
+                {
+                    Some(hil::usb::CtrlOutResult::Ok) => {
+                        let received = self.ctrl_bytes.get() + packet_bytes;
+                        self.ctrl_bytes.set(received);
+                        if received >= self.ctrl_length.get()
+                            || packet_bytes < self.buffers[0].get().1
+                        {
+                            self.ctrl_status();
+                        } else {
+                            regs.tasks_ep0rcvout.write(Task::TRIGGER::SET);
+                        }
+                    }
+                    // There is no interface for the client to resume a
+                    // delayed control transfer
+                    _ => self.ctrl_stall(),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Ask the client for the next packet of the IN data stage
+    fn ctrl_in_next(&self) {
+        match self.client.map(|client| client.ctrl_in(0)) {
+            Some(hil::usb::CtrlInResult::Packet(packet_bytes, transfer_complete)) => {
+                let sent = self.ctrl_bytes.get() + packet_bytes;
+                self.ctrl_bytes.set(sent);
+                // A transfer shorter than the host asked for that ends with
+                // a full packet is ended with a zero-length packet
+                let zlp = transfer_complete
+                    && packet_bytes > 0
+                    && packet_bytes == self.buffers[0].get().1
+                    && sent < self.ctrl_length.get();
+                self.endpoints[0].set(EndpointState::Ctrl(CtrlState::ReadIn {
+                    complete: transfer_complete,
+                    zlp: zlp,
+                }));
+                self.dma_in(0, packet_bytes);
+            }
+            // There is no interface for the client to resume a delayed
+            // control transfer
+            _ => self.ctrl_stall(),
+        }
+    }
+
+    /// Let the USBD complete the status stage, and end the transfer
+    fn ctrl_status(&self) {
+        let regs = &*self.registers;
+        regs.tasks_ep0status.write(Task::TRIGGER::SET);
+        self.endpoints[0].set(EndpointState::Ctrl(CtrlState::Init));
+        self.client.map(|client| {
+            client.ctrl_status(0);
+            client.ctrl_status_complete(0);
+        });
+    }
+
+    /// Respond with STALL to the rest of the transfer
+    fn ctrl_stall(&self) {
+        let regs = &*self.registers;
+        regs.tasks_ep0stall.write(Task::TRIGGER::SET);
+        self.endpoints[0].set(EndpointState::Ctrl(CtrlState::Init));
+    }
+
+    /// Ask the client for a packet to send on Bulk IN endpoint `endpoint`
+    fn bulk_in_start(&self, endpoint: usize) {
+        match self.client.map(|client| client.bulk_in(endpoint)) {
+            Some(hil::usb::BulkInResult::Packet(packet_bytes)) => {
+                self.endpoints[endpoint].set(EndpointState::BulkIn(BulkInState::InFlight));
+                self.dma_in(endpoint, packet_bytes);
+            }
+            Some(hil::usb::BulkInResult::Delay) => {
+                self.endpoints[endpoint].set(EndpointState::BulkIn(BulkInState::Delay));
+            }
+            _ => {
+                // Respond with STALL, and wait for the client to resume
+                self.stall(endpoint, EndpointStall::IO::In);
+                self.endpoints[endpoint].set(EndpointState::BulkIn(BulkInState::Delay));
+            }
+        }
+    }
+
+    /// The host has read the packet of Bulk IN endpoint `endpoint`
+    fn handle_bulk_in_done(&self, endpoint: usize) {
+        if self.endpoints[endpoint].get() == EndpointState::BulkIn(BulkInState::InFlight) {
+            self.endpoints[endpoint].set(EndpointState::BulkIn(BulkInState::Init));
+            self.bulk_in_start(endpoint);
+        }
+    }
+
+    /// The host has sent a packet to Bulk OUT endpoint `endpoint`
+    fn handle_bulk_out_received(&self, endpoint: usize) {
+        match self.endpoints[endpoint].get() {
+            EndpointState::BulkOut(BulkOutState::Init) => {
+                let packet_bytes = self.dma_out(endpoint);
+                self.bulk_out_deliver(endpoint, packet_bytes);
+            }
+            EndpointState::BulkOut(BulkOutState::Delay { size, .. }) => {
+                // Leave the packet in the USBD until the client has consumed
+                // the one in its buffer
+                self.endpoints[endpoint].set(EndpointState::BulkOut(BulkOutState::Delay {
+                    size: size,
+                    pending: true,
+                }));
+            }
+            _ => {}
+        }
+    }
+
+    /// Pass the packet of `packet_bytes` bytes in the buffer of Bulk OUT
+    /// endpoint `endpoint` to the client
+    fn bulk_out_deliver(&self, endpoint: usize, packet_bytes: usize) {
+        match self
+            .client
+            .map(|client| client.bulk_out(endpoint, packet_bytes as u32))
+        {
+            Some(hil::usb::BulkOutResult::Ok) => {
+                self.endpoints[endpoint].set(EndpointState::BulkOut(BulkOutState::Init));
+            }
+            Some(hil::usb::BulkOutResult::Delay) => {
+                self.endpoints[endpoint].set(EndpointState::BulkOut(BulkOutState::Delay {
+                    size: packet_bytes,
+                    pending: false,
+                }));
+            }
+            _ => {
+                // Respond with STALL to the packets that follow
+                self.stall(endpoint, EndpointStall::IO::Out);
+                self.endpoints[endpoint].set(EndpointState::BulkOut(BulkOutState::Init));
+            }
+        }
+    }
+}
+
+impl hil::usb::UsbController for Usbd<'a> {
+    fn endpoint_set_buffer(&self, endpoint: usize, buf: &[VolatileCell<u8>]) {
+        if buf.len() > MAX_PACKET_SIZE {
+            panic!("Bad endpoint buffer size");
+        }
+        self.buffers[endpoint].set((buf.as_ptr() as *mut u8, buf.len()));
+    }
+
+    /// The USBD only runs at full speed, whatever `speed` is.
+    fn enable_as_device(&self, _speed: hil::usb::DeviceSpeed) {
+        let regs = &*self.registers;
+        if self.state.get() != State::Reset {
+            panic!("Already enabled");
+        }
+
+        unsafe {
+            clock::CLOCK.request(ManagedClock::High, 0);
+        }
+
+        self.errata_write(ERRATA_171, 0xC0);
+        self.errata_write(ERRATA_187, 3);
+        regs.enable.write(Enable::ENABLE::SET);
+
+        regs.intenset.write(
+            Interrupt::USBRESET::SET
+                + Interrupt::EP0DATADONE::SET
+                + Interrupt::USBEVENT::SET
+                + Interrupt::EP0SETUP::SET
+                + Interrupt::EPDATA::SET,
+        );
+
+        self.state.set(State::Idle);
+    }
+
+    fn attach(&self) {
+        let regs = &*self.registers;
+        match self.state.get() {
+            State::Reset => debug!("Not enabled"),
+            State::Active => debug!("Already attached"),
+            State::Idle => {
+                self.state.set(State::Active);
+                if self.ready.get() {
+                    regs.usbpullup.write(UsbPullup::CONNECT::SET);
+                }
+            }
+        }
+    }
+
+    fn detach(&self) {
+        let regs = &*self.registers;
+        match self.state.get() {
+            State::Reset => debug!("Not enabled"),
+            State::Idle => debug!("Not attached"),
+            State::Active => {
+                regs.usbpullup.write(UsbPullup::CONNECT::CLEAR);
+                self.state.set(State::Idle);
+            }
+        }
+    }
+
+    fn set_address(&self, _addr: u16) {
+        // The USBD handles SET_ADDRESS itself
+    }
+
+    fn enable_address(&self) {
+        // The USBD handles SET_ADDRESS itself
+    }
+
+    fn endpoint_ctrl_out_enable(&self, endpoint: usize) {
+        self.endpoints[endpoint].set(EndpointState::Ctrl(CtrlState::Init));
+        self.write_endpoint_enables();
+    }
+
+    fn endpoint_bulk_in_enable(&self, endpoint: usize) {
+        // The endpoint takes packets from the client after the next bus reset
+        self.endpoints[endpoint].set(EndpointState::BulkIn(BulkInState::Delay));
+        self.write_endpoint_enables();
+    }
+
+    fn endpoint_bulk_out_enable(&self, endpoint: usize) {
+        self.endpoints[endpoint].set(EndpointState::BulkOut(BulkOutState::Init));
+        self.write_endpoint_enables();
+    }
+
+    fn endpoint_bulk_resume(&self, endpoint: usize) {
+        match self.endpoints[endpoint].get() {
+            EndpointState::BulkIn(BulkInState::Delay) => self.bulk_in_start(endpoint),
+            EndpointState::BulkOut(BulkOutState::Delay { size, pending }) => {
+                self.bulk_out_deliver(endpoint, size);
+                if pending
+                    && self.endpoints[endpoint].get() == EndpointState::BulkOut(BulkOutState::Init)
+                {
+                    self.handle_bulk_out_received(endpoint);
+                }
+            }
+            _ => debug!("Ignoring superfluous resume"),
+        }
+    }
+}
+
+/// Static state to manage the USBD
+pub static mut USBD: Usbd<'static> = Usbd::new();
@@ -0,0 +1,174 @@
+//! Watchdog timer, nRF52
+//!
+//! The watchdog counts down at 32.768 kHz and resets the chip when it reaches
+//! zero before it is reloaded. It is configured to pause while the CPU sleeps
+//! or is halted by a debugger, so an idle board is not reset.
+//!
+//! Once started, the watchdog can only be stopped by a reset. The timeout
+//! event warns the client, but the chip resets two 32.768 kHz clock cycles
+//! later, which only leaves time for very short actions.
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::common::registers::{ReadOnly, ReadWrite, WriteOnly};
+use kernel::common::StaticRef;
+use kernel::hil;
+
+const WDT_BASE: StaticRef<WdtRegisters> =
+    unsafe { StaticRef::new(0x40010000 as *const WdtRegisters) };
+
+/// Value to write to a reload request register to reload the watchdog.
+const RELOAD_VALUE: u32 = 0x6E524635;
+
+/// Frequency the watchdog counts at, in Hz.
+const WDT_FREQUENCY: u64 = 32768;
+
+#[repr(C)]
+struct WdtRegisters {
+    /// Start the watchdog
+    /// Address: 0x000 - 0x004
+    pub task_start: WriteOnly<u32, Task::Register>,
+    /// Reserved
+    pub _reserved1: [u32; 63],
+    /// Watchdog timeout
+    /// Address: 0x100 - 0x104
+    pub event_timeout: ReadWrite<u32, Event::Register>,
+    /// Reserved
+    pub _reserved2: [u32; 128],
+    /// Enable interrupt
+    /// Address: 0x304 - 0x308
+    pub intenset: ReadWrite<u32, Interrupt::Register>,
+    /// Disable interrupt
+    /// Address: 0x308 - 0x30c
+    pub intenclr: ReadWrite<u32, Interrupt::Register>,
+    /// Reserved
+    pub _reserved3: [u32; 61],
+    /// Run status
+    /// Address: 0x400 - 0x404
+    pub runstatus: ReadOnly<u32, RunStatus::Register>,
+    /// Request status
+    /// Address: 0x404 - 0x408
+    pub reqstatus: ReadOnly<u32>,
+    /// Reserved
+    pub _reserved4: [u32; 63],
+    /// Counter reload value, in 32.768 kHz clock cycles
+    /// Address: 0x504 - 0x508
+    pub crv: ReadWrite<u32>,
+    /// Enable register for reload request registers
+    /// Address: 0x508 - 0x50c
+    pub rren: ReadWrite<u32, ReloadEnable::Register>,
+    /// Configuration register
+    /// Address: 0x50c - 0x510
+    pub config: ReadWrite<u32, Config::Register>,
+    /// Reserved
+    pub _reserved5: [u32; 60],
+    /// Reload request registers
+    /// Address: 0x600 - 0x620
+    pub rr: [WriteOnly<u32>; 8],
+}
+
+register_bitfields! [u32,
+    /// Start task
+    Task [
+        ENABLE OFFSET(0) NUMBITS(1)
+    ],
+
+    /// Timeout event
+    Event [
+        READY OFFSET(0) NUMBITS(1)
+    ],
+
+    /// Interrupts
+    Interrupt [
+        TIMEOUT OFFSET(0) NUMBITS(1)
+    ],
+
+    /// Whether the watchdog is running
+    RunStatus [
+        RUNNING OFFSET(0) NUMBITS(1)
+    ],
+
+    /// Enabled reload request registers
+    ReloadEnable [
+        RR0 OFFSET(0) NUMBITS(1)
+    ],
+
+    /// Behaviour while sleeping or halted
+    Config [
+        SLEEP OFFSET(0) NUMBITS(1) [
+            Pause = 0,
+            Run = 1
+        ],
+        HALT OFFSET(3) NUMBITS(1) [
+            Pause = 0,
+            Run = 1
+        ]
+    ]
+];
+
+pub struct Wdt {
+    registers: StaticRef<WdtRegisters>,
+    enabled: Cell<bool>,
+    client: OptionalCell<&'static hil::watchdog::WatchdogClient>,
+}
+
+pub static mut WDT: Wdt = Wdt::new();
+
+impl Wdt {
+    const fn new() -> Wdt {
+        Wdt {
+            registers: WDT_BASE,
+            enabled: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    fn handle_interrupt(&self) {
+        self.registers.event_timeout.write(Event::READY::CLEAR);
+        self.client.map(|client| client.early_warning());
+    }
+}
+
+/// Handles the timeout interrupt directly in the vector table, rather than
+/// through the kernel loop, because the timeout usually means that the kernel
+/// loop is stuck.
+pub unsafe extern "C" fn wdt_handler() {
+    WDT.handle_interrupt();
+}
+
+impl hil::watchdog::Watchdog for Wdt {
+    /// The period can only be set once, since the watchdog cannot be
+    /// stopped or reconfigured once it runs.
+    fn start(&self, period: usize) {
+        if self.registers.runstatus.is_set(RunStatus::RUNNING) {
+            return;
+        }
+        self.enabled.set(true);
+
+        let ticks = (period as u64 * WDT_FREQUENCY / 1000)
+            .max(0xF)
+            .min(0xFFFFFFFF);
+        self.registers.crv.set(ticks as u32);
+        self.registers.rren.write(ReloadEnable::RR0::SET);
+        self.registers
+            .config
+            .write(Config::SLEEP::Pause + Config::HALT::Pause);
+        if self.client.is_some() {
+            self.registers.intenset.write(Interrupt::TIMEOUT::SET);
+        }
+        self.registers.task_start.write(Task::ENABLE::SET);
+    }
+
+    /// The watchdog cannot be stopped once it runs, so this does nothing.
+    fn stop(&self) {}
+
+    fn pet(&self) {
+        if self.enabled.get() {
+            self.registers.rr[0].set(RELOAD_VALUE);
+        }
+    }
+
+    fn set_client(&self, client: &'static hil::watchdog::WatchdogClient) {
+        self.client.set(client);
+    }
+}
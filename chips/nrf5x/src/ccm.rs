@@ -0,0 +1,343 @@
+//! AES-CCM driver, nRF5X-family
+//!
+//! Encrypts and decrypts packets with the CCM peripheral, which implements the
+//! AES-CCM variant used by the Bluetooth Low Energy link layer:
+//!
+//! * The nonce is made up of a 39-bit packet counter, a direction bit and an
+//!   8-byte initialization vector (IV).
+//! * The additional authenticated data is the single header byte of the
+//!   packet.
+//! * The message integrity check (MIC) is always 4 bytes long.
+//! * Payloads are at most 27 bytes long.
+//!
+//! The driver implements `kernel::hil::symmetric_encryption::AES128CCM`
+//! restricted to these parameters. The nonce passed to `set_nonce` is laid out
+//! as in the Bluetooth specification (Vol 6, Part E, section 2.2): bytes 0-4
+//! hold the packet counter with the direction bit as the most significant bit
+//! of byte 4 and bytes 5-12 hold the IV.
+//!
+//! The CCM peripheral can also encrypt and decrypt packets on-the-fly while
+//! the radio sends or receives them, by connecting the radio events to the CCM
+//! tasks through the PPI. The data structure and buffers used by this driver
+//! are laid out for that use as well.
+//!
+//! The CCM peripheral shares its interrupt with the AAR peripheral.
+
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::registers::{ReadOnly, ReadWrite, WriteOnly};
+use kernel::common::StaticRef;
+use kernel::hil::symmetric_encryption;
+use kernel::ReturnCode;
+
+/// Maximum payload length supported by the CCM peripheral
+pub const MAX_PAYLOAD_LENGTH: usize = 27;
+/// Length of the message integrity check appended to encrypted packets
+pub const MIC_LENGTH: usize = 4;
+
+// Packets in the CCM format: header, length, RFU and payload (+ MIC)
+const PACKET_HEADER_LENGTH: usize = 3;
+const PACKET_LENGTH: usize = PACKET_HEADER_LENGTH + MAX_PAYLOAD_LENGTH + MIC_LENGTH;
+
+// CCM data structure
+// Byte 0-15   - Key
+// Byte 16-23  - Packet counter (39 bits)
+// Byte 24     - Direction (bit 0)
+// Byte 25-32  - IV
+static mut CCM_DATA: [u8; 33] = [0; 33];
+static mut CCM_INPUT: [u8; PACKET_LENGTH] = [0; PACKET_LENGTH];
+static mut CCM_OUTPUT: [u8; PACKET_LENGTH] = [0; PACKET_LENGTH];
+// Temporary storage for the key stream, at least 16 + MAX_PAYLOAD_LENGTH bytes
+static mut CCM_SCRATCH: [u8; 43] = [0; 43];
+
+const KEY_START: usize = 0;
+const PKTCTR_START: usize = 16;
+const PKTCTR_LENGTH: usize = 5;
+const DIRECTION_OFFSET: usize = 24;
+const IV_START: usize = 25;
+const IV_LENGTH: usize = 8;
+
+const CCM_BASE: StaticRef<CcmRegisters> =
+    unsafe { StaticRef::new(0x4000F000 as *const CcmRegisters) };
+
+#[repr(C)]
+struct CcmRegisters {
+    /// Start generation of key-stream
+    /// - Address: 0x000 - 0x004
+    task_ksgen: WriteOnly<u32, Task::Register>,
+    /// Start encryption/decryption, triggered through the ENDKSGEN_CRYPT shortcut
+    /// - Address: 0x004 - 0x008
+    _task_crypt: WriteOnly<u32, Task::Register>,
+    /// Stop encryption/decryption
+    /// - Address: 0x008 - 0x00c
+    task_stop: WriteOnly<u32, Task::Register>,
+    /// Reserved
+    _reserved1: [u32; 61],
+    /// Key-stream generation complete
+    /// - Address: 0x100 - 0x104
+    event_endksgen: ReadWrite<u32, Event::Register>,
+    /// Encrypt/decrypt complete
+    /// - Address: 0x104 - 0x108
+    event_endcrypt: ReadWrite<u32, Event::Register>,
+    /// CCM error event
+    /// - Address: 0x108 - 0x10c
+    event_error: ReadWrite<u32, Event::Register>,
+    /// Reserved
+    _reserved2: [u32; 61],
+    /// Shortcut register
+    /// - Address: 0x200 - 0x204
+    shorts: ReadWrite<u32, Shorts::Register>,
+    /// Reserved
+    _reserved3: [u32; 64],
+    /// Enable interrupt
+    /// - Address: 0x304 - 0x308
+    intenset: ReadWrite<u32, Interrupt::Register>,
+    /// Disable interrupt
+    /// - Address: 0x308 - 0x30c
+    intenclr: ReadWrite<u32, Interrupt::Register>,
+    /// Reserved
+    _reserved4: [u32; 61],
+    /// MIC check result
+    /// - Address: 0x400 - 0x404
+    micstatus: ReadOnly<u32, MicStatus::Register>,
+    /// Reserved
+    _reserved5: [u32; 63],
+    /// Enable
+    /// - Address: 0x500 - 0x504
+    enable: ReadWrite<u32, Enable::Register>,
+    /// Operation mode
+    /// - Address: 0x504 - 0x508
+    mode: ReadWrite<u32, Mode::Register>,
+    /// Pointer to the CCM data structure
+    /// - Address: 0x508 - 0x50c
+    cnfptr: ReadWrite<u32>,
+    /// Input pointer
+    /// - Address: 0x50c - 0x510
+    inptr: ReadWrite<u32>,
+    /// Output pointer
+    /// - Address: 0x510 - 0x514
+    outptr: ReadWrite<u32>,
+    /// Pointer to data area used for temporary storage
+    /// - Address: 0x514 - 0x518
+    scratchptr: ReadWrite<u32>,
+}
+
+register_bitfields! [u32,
+    /// Start task
+    Task [
+        ENABLE OFFSET(0) NUMBITS(1)
+    ],
+
+    /// Read event
+    Event [
+        READY OFFSET(0) NUMBITS(1)
+    ],
+
+    /// Shortcuts
+    Shorts [
+        ENDKSGEN_CRYPT OFFSET(0) NUMBITS(1)
+    ],
+
+    /// Enable and disable interrupts
+    Interrupt [
+        ENDKSGEN OFFSET(0) NUMBITS(1),
+        ENDCRYPT OFFSET(1) NUMBITS(1),
+        ERROR OFFSET(2) NUMBITS(1)
+    ],
+
+    /// MIC check result
+    MicStatus [
+        MICSTATUS OFFSET(0) NUMBITS(1) [
+            CheckFailed = 0,
+            CheckPassed = 1
+        ]
+    ],
+
+    /// Enable or disable CCM
+    Enable [
+        ENABLE OFFSET(0) NUMBITS(2) [
+            Disabled = 0,
+            Enabled = 2
+        ]
+    ],
+
+    /// Operation mode
+    Mode [
+        MODE OFFSET(0) NUMBITS(1) [
+            Encryption = 0,
+            Decryption = 1
+        ]
+    ]
+];
+
+pub struct AesCcm<'a> {
+    registers: StaticRef<CcmRegisters>,
+    client: OptionalCell<&'a symmetric_encryption::CCMClient>,
+    buf: TakeCell<'static, [u8]>,
+    /// Offset of the payload in `buf`
+    m_off: Cell<usize>,
+    m_len: Cell<usize>,
+    encrypting: Cell<bool>,
+}
+
+pub static mut CCM: AesCcm = AesCcm::new();
+
+impl AesCcm<'a> {
+    const fn new() -> AesCcm<'a> {
+        AesCcm {
+            registers: CCM_BASE,
+            client: OptionalCell::empty(),
+            buf: TakeCell::empty(),
+            m_off: Cell::new(0),
+            m_len: Cell::new(0),
+            encrypting: Cell::new(false),
+        }
+    }
+
+    fn start(&self, header: u8, encrypting: bool) {
+        let regs = &*self.registers;
+        let payload_len = self.m_len.get() + if encrypting { 0 } else { MIC_LENGTH };
+
+        unsafe {
+            CCM_INPUT[0] = header;
+            CCM_INPUT[1] = payload_len as u8;
+            CCM_INPUT[2] = 0;
+
+            regs.cnfptr.set(CCM_DATA.as_ptr() as u32);
+            regs.inptr.set(CCM_INPUT.as_ptr() as u32);
+            regs.outptr.set(CCM_OUTPUT.as_ptr() as u32);
+            regs.scratchptr.set(CCM_SCRATCH.as_ptr() as u32);
+        }
+
+        regs.enable.write(Enable::ENABLE::Enabled);
+        regs.mode.write(if encrypting {
+            Mode::MODE::Encryption
+        } else {
+            Mode::MODE::Decryption
+        });
+        regs.event_endksgen.write(Event::READY::CLEAR);
+        regs.event_endcrypt.write(Event::READY::CLEAR);
+        regs.event_error.write(Event::READY::CLEAR);
+        regs.shorts.write(Shorts::ENDKSGEN_CRYPT::SET);
+        regs.intenset
+            .write(Interrupt::ENDCRYPT::SET + Interrupt::ERROR::SET);
+        regs.task_ksgen.write(Task::ENABLE::SET);
+    }
+
+    fn disable(&self) {
+        let regs = &*self.registers;
+        regs.intenclr
+            .write(Interrupt::ENDKSGEN::SET + Interrupt::ENDCRYPT::SET + Interrupt::ERROR::SET);
+        regs.shorts.set(0);
+        regs.task_stop.write(Task::ENABLE::SET);
+        regs.enable.write(Enable::ENABLE::Disabled);
+    }
+
+    /// CCM/AAR Interrupt handler
+    pub fn handle_interrupt(&self) {
+        let regs = &*self.registers;
+
+        let result = if regs.event_endcrypt.is_set(Event::READY) {
+            ReturnCode::SUCCESS
+        } else if regs.event_error.is_set(Event::READY) {
+            ReturnCode::FAIL
+        } else {
+            return;
+        };
+        let tag_is_valid = result == ReturnCode::SUCCESS
+            && (self.encrypting.get() || regs.micstatus.is_set(MicStatus::MICSTATUS));
+        self.disable();
+
+        self.buf.take().map(|buf| {
+            if result == ReturnCode::SUCCESS {
+                // The output holds the payload followed by the MIC when encrypting, and only
+                // the payload when decrypting
+                let m_off = self.m_off.get();
+                let len = self.m_len.get() + if self.encrypting.get() { MIC_LENGTH } else { 0 };
+                unsafe {
+                    buf[m_off..m_off + len].copy_from_slice(
+                        &CCM_OUTPUT[PACKET_HEADER_LENGTH..PACKET_HEADER_LENGTH + len],
+                    );
+                }
+            }
+            self.client
+                .map(move |client| client.crypt_done(buf, result, tag_is_valid));
+        });
+    }
+}
+
+impl symmetric_encryption::AES128CCM<'a> for AesCcm<'a> {
+    fn set_client(&'a self, client: &'a symmetric_encryption::CCMClient) {
+        self.client.set(client);
+    }
+
+    fn set_key(&self, key: &[u8]) -> ReturnCode {
+        if key.len() != symmetric_encryption::AES128_KEY_SIZE {
+            ReturnCode::EINVAL
+        } else {
+            unsafe {
+                CCM_DATA[KEY_START..KEY_START + symmetric_encryption::AES128_KEY_SIZE]
+                    .copy_from_slice(key);
+            }
+            ReturnCode::SUCCESS
+        }
+    }
+
+    fn set_nonce(&self, nonce: &[u8]) -> ReturnCode {
+        if nonce.len() != symmetric_encryption::CCM_NONCE_LENGTH {
+            ReturnCode::EINVAL
+        } else {
+            unsafe {
+                for b in CCM_DATA[PKTCTR_START..DIRECTION_OFFSET].iter_mut() {
+                    *b = 0;
+                }
+                CCM_DATA[PKTCTR_START..PKTCTR_START + PKTCTR_LENGTH]
+                    .copy_from_slice(&nonce[..PKTCTR_LENGTH]);
+                // The direction bit is stored separately from the 39-bit packet counter
+                CCM_DATA[PKTCTR_START + PKTCTR_LENGTH - 1] &= 0x7f;
+                CCM_DATA[DIRECTION_OFFSET] = nonce[PKTCTR_LENGTH - 1] >> 7;
+                CCM_DATA[IV_START..IV_START + IV_LENGTH]
+                    .copy_from_slice(&nonce[PKTCTR_LENGTH..PKTCTR_LENGTH + IV_LENGTH]);
+            }
+            ReturnCode::SUCCESS
+        }
+    }
+
+    // The authenticated data must be the single header byte preceding the payload, and the MIC
+    // must be 4 bytes long.
+    fn crypt(
+        &self,
+        buf: &'static mut [u8],
+        a_off: usize,
+        m_off: usize,
+        m_len: usize,
+        mic_len: usize,
+        confidential: bool,
+        encrypting: bool,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.buf.is_some() {
+            return (ReturnCode::EBUSY, Some(buf));
+        }
+        if m_off + m_len + mic_len > buf.len() || a_off + 1 != m_off {
+            return (ReturnCode::EINVAL, Some(buf));
+        }
+        if mic_len != MIC_LENGTH || !confidential || m_len > MAX_PAYLOAD_LENGTH {
+            return (ReturnCode::ENOSUPPORT, Some(buf));
+        }
+
+        // Decryption checks the MIC following the payload
+        let len = m_len + if encrypting { 0 } else { MIC_LENGTH };
+        unsafe {
+            CCM_INPUT[PACKET_HEADER_LENGTH..PACKET_HEADER_LENGTH + len]
+                .copy_from_slice(&buf[m_off..m_off + len]);
+        }
+        let header = buf[a_off];
+
+        self.m_off.set(m_off);
+        self.m_len.set(m_len);
+        self.encrypting.set(encrypting);
+        self.buf.replace(buf);
+        self.start(header, encrypting);
+        (ReturnCode::SUCCESS, None)
+    }
+}
@@ -1,4 +1,5 @@
 use core::convert::TryFrom;
+use kernel::hil::sniffer::DataRate;
 
 // PCNF0
 pub const RADIO_PCNF0_LFLEN_POS: u32 = 0;
@@ -45,6 +46,11 @@ pub const RADIO_INTENSET_ADDRESS: u32 = 1 << 1;
 pub const RADIO_INTENSET_PAYLOAD: u32 = 1 << 2;
 pub const RADIO_INTENSET_END: u32 = 1 << 3;
 pub const RADIO_INTENSET_DISABLED: u32 = 1 << 4;
+pub const RADIO_INTENSET_BCMATCH: u32 = 1 << 10;
+
+// BCC
+// The bit counter is started on the ADDRESS event, so this is the S0 and LENGTH fields
+pub const RADIO_BCC_BLE_HEADER: u32 = 16;
 
 // STATE
 pub const RADIO_STATE_DISABLE: u32 = 0;
@@ -67,6 +73,17 @@ pub enum RadioMode {
     Ble1Mbit = 3,
 }
 
+impl From<DataRate> for RadioMode {
+    fn from(rate: DataRate) -> RadioMode {
+        match rate {
+            DataRate::Nrf250Kbit => RadioMode::Nrt250Kbit,
+            DataRate::Nrf1Mbit => RadioMode::Nrf1Mbit,
+            DataRate::Nrf2Mbit => RadioMode::Nrf2Mbit,
+            DataRate::Ble1Mbit => RadioMode::Ble1Mbit,
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub enum TxPower {
     Positive4dBM = 0x04,
@@ -13,10 +13,12 @@
 extern crate kernel;
 
 pub mod aes;
+pub mod ccm;
 pub mod constants;
 pub mod gpio;
 pub mod peripheral_interrupts;
 pub mod pinmux;
+pub mod ppi;
 pub mod rtc;
 pub mod temperature;
 pub mod timer;
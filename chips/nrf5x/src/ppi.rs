@@ -1,4 +1,4 @@
-//! Programmable peripheral interconnect, nRF52
+//! Programmable peripheral interconnect, nRF5X-family
 //!
 //! Chapter 20 of the nRF52832 Objective Product Specification v0.6.3:
 //!
@@ -27,6 +27,9 @@
 //!     * 30        RTC0->EVENTS_COMPARE[0]         TIMER0->TASKS_CLEAR
 //!     * 31        RTC0->EVENTS_COMPARE[0]         TIMER0->TASKS_START
 //!
+//! The nRF51 has the same register layout and pre-programmed channels, but only
+//! channels 0-15 are programmable and there is no FORK task end point.
+//!
 //! Authors
 //! ---------
 //! * Johan Lindskogen
@@ -57,52 +60,22 @@ struct PpiRegisters {
     chen: ReadWrite<u32, Channel::Register>,
     chenset: ReadWrite<u32, Channel::Register>,
     chenclr: ReadWrite<u32, Channel::Register>,
-    ch0_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch0_tep: ReadWrite<u32, TaskEndPoint::Register>,
-    ch1_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch1_tep: ReadWrite<u32, TaskEndPoint::Register>,
-    ch2_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch2_tep: ReadWrite<u32, TaskEndPoint::Register>,
-    ch3_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch3_tep: ReadWrite<u32, TaskEndPoint::Register>,
-    ch4_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch4_tep: ReadWrite<u32, TaskEndPoint::Register>,
-    ch5_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch5_tep: ReadWrite<u32, TaskEndPoint::Register>,
-    ch6_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch6_tep: ReadWrite<u32, TaskEndPoint::Register>,
-    ch7_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch7_tep: ReadWrite<u32, TaskEndPoint::Register>,
-    ch8_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch8_tep: ReadWrite<u32, TaskEndPoint::Register>,
-    ch9_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch9_tep: ReadWrite<u32, TaskEndPoint::Register>,
-    ch10_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch10_tep: ReadWrite<u32, TaskEndPoint::Register>,
-    ch11_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch11_tep: ReadWrite<u32, TaskEndPoint::Register>,
-    ch12_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch12_tep: ReadWrite<u32, TaskEndPoint::Register>,
-    ch13_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch13_tep: ReadWrite<u32, TaskEndPoint::Register>,
-    ch14_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch14_tep: ReadWrite<u32, TaskEndPoint::Register>,
-    ch15_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch15_tep: ReadWrite<u32, TaskEndPoint::Register>,
-    ch16_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch16_tep: ReadWrite<u32, TaskEndPoint::Register>,
-    ch17_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch17_tep: ReadWrite<u32, TaskEndPoint::Register>,
-    ch18_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch18_tep: ReadWrite<u32, TaskEndPoint::Register>,
-    ch19_eep: ReadWrite<u32, EventEndPoint::Register>,
-    ch19_tep: ReadWrite<u32, TaskEndPoint::Register>,
+    ch: [PpiChannelRegisters; 20],
     _reserved2: [u32; 148],
     chg: [ReadWrite<u32, Channel::Register>; 6],
     _reserved3: [u32; 62],
     fork_tep: [ReadWrite<u32, TaskEndPoint::Register>; 32],
 }
 
+#[repr(C)]
+struct PpiChannelRegisters {
+    eep: ReadWrite<u32, EventEndPoint::Register>,
+    tep: ReadWrite<u32, TaskEndPoint::Register>,
+}
+
+/// Number of channels that can be configured on all chips of the family
+pub const NUM_PROGRAMMABLE_CHANNELS: usize = 16;
+
 register_bitfields! [u32,
     Control [
         ENABLE OFFSET(0) NUMBITS(1)
@@ -171,4 +144,16 @@ impl Ppi {
         let regs = &*self.registers;
         regs.chenclr.write(channels);
     }
+
+    /// Connects the event register at address `event` to the task register at
+    /// address `task` through the programmable channel `channel`.
+    ///
+    /// The channel still has to be enabled with `enable`.
+    pub fn configure(&self, channel: usize, event: u32, task: u32) {
+        let regs = &*self.registers;
+        if channel < NUM_PROGRAMMABLE_CHANNELS {
+            regs.ch[channel].eep.write(EventEndPoint::ADDRESS.val(event));
+            regs.ch[channel].tep.write(TaskEndPoint::ADDRESS.val(task));
+        }
+    }
 }
@@ -162,4 +162,8 @@ impl Alarm for Rtc {
     fn get_alarm(&self) -> u32 {
         self.registers.cc[0].read(CC::CC)
     }
+
+    fn counter_bits(&self) -> u32 {
+        24
+    }
 }
@@ -256,6 +256,42 @@ impl Timer {
             client.compare(val as u8);
         });
     }
+
+    /// Returns the address of `EVENTS_COMPARE[compare]`, to be used as a PPI
+    /// event end point.
+    pub fn compare_event_address(&self, compare: usize) -> u32 {
+        &self.registers.events_compare[compare] as *const _ as u32
+    }
+
+    /// Starts the timer at 1 MHz such that `EVENTS_COMPARE[compare]` is
+    /// generated `us` microseconds from now. The timer stops itself when the
+    /// event is generated, so no interrupt or CPU involvement is needed.
+    pub fn start_oneshot_us(&self, compare: usize, us: u32) {
+        let regs = &*self.registers;
+        regs.tasks_stop.write(Task::ENABLE::SET);
+        regs.tasks_clear.write(Task::ENABLE::SET);
+        // Timer mode, 16 MHz / 2^4 = 1 MHz
+        regs.mode.set(0);
+        regs.bitmode.write(Bitmode::BITMODE::Bit32);
+        regs.prescaler.set(4);
+        regs.cc[compare].write(CC::CC.val(us));
+        regs.events_compare[compare].write(Event::READY::CLEAR);
+        let stop = match compare {
+            0 => Shorts::COMPARE0_STOP::EnableShortcut,
+            1 => Shorts::COMPARE1_STOP::EnableShortcut,
+            2 => Shorts::COMPARE2_STOP::EnableShortcut,
+            _ => Shorts::COMPARE3_STOP::EnableShortcut,
+        };
+        regs.shorts.write(stop);
+        regs.tasks_start.write(Task::ENABLE::SET);
+    }
+
+    /// Stops a timer started with `start_oneshot_us` before it expires.
+    pub fn stop(&self) {
+        let regs = &*self.registers;
+        regs.tasks_stop.write(Task::ENABLE::SET);
+        regs.shorts.set(0);
+    }
 }
 
 pub struct TimerAlarm {
@@ -22,14 +22,23 @@
 //! Currently, no version of the SAM4L exists with all the 8 ACs
 //! implemented. Therefore a lot of the defined bitfields remain unused, but
 //! are initialized for a possible future scenario.
+//!
+//! Sleepwalking
+//! -----
+//! The ACIFC clock normally keeps the chip out of deep sleep. ACs enabled in
+//! sleep instead let the chip deep sleep while they have interrupts enabled:
+//! the ACIFC then requests its clock from the power manager by itself, and
+//! its interrupts wake the chip up.
 
 // Author: Danilo Verhaert <verhaert@cs.stanford.edu>
 // Last modified August 8th, 2018
 
 use core::cell::Cell;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use kernel::common::registers::{ReadOnly, ReadWrite, WriteOnly};
 use kernel::common::StaticRef;
 use kernel::hil::analog_comparator;
+use kernel::hil::analog_comparator::{Hysteresis, InterruptMode, Reference, WindowInterruptMode};
 use kernel::ReturnCode;
 use pm;
 
@@ -273,8 +282,19 @@ register_bitfields![u32,
 const ACIFC_BASE: StaticRef<AcifcRegisters> =
     unsafe { StaticRef::new(0x40040000 as *const AcifcRegisters) };
 
+/// The number of ACs that are enabled in sleep and have interrupts enabled.
+/// The power manager lets the chip deep sleep with the ACIFC clock on while
+/// this is nonzero. Use `Ordering::Relaxed` when reading/writing the value.
+pub static SLEEPWALKING: AtomicUsize = AtomicUsize::new(0);
+
 pub struct Acifc<'a> {
     client: Cell<Option<&'a analog_comparator::Client>>,
+    /// Requested interrupt condition of each AC
+    interrupt_modes: [Cell<InterruptMode>; 4],
+    /// Requested interrupt condition of each window
+    window_modes: [Cell<WindowInterruptMode>; 2],
+    /// Bit array of the ACs that keep comparing in deep sleep
+    sleep_enabled: Cell<u8>,
 }
 
 /// Implement constructor for struct Acifc
@@ -282,6 +302,17 @@ impl<'a> Acifc<'a> {
     const fn new() -> Acifc<'a> {
         Acifc {
             client: Cell::new(None),
+            interrupt_modes: [
+                Cell::new(InterruptMode::VpGtVn),
+                Cell::new(InterruptMode::VpGtVn),
+                Cell::new(InterruptMode::VpGtVn),
+                Cell::new(InterruptMode::VpGtVn),
+            ],
+            window_modes: [
+                Cell::new(WindowInterruptMode::Entering),
+                Cell::new(WindowInterruptMode::Entering),
+            ],
+            sleep_enabled: Cell::new(0),
         }
     }
 
@@ -302,24 +333,21 @@ impl<'a> Acifc<'a> {
     /// measurement on an AC to be made quickly after a measurement is
     /// triggered, without waiting for the AC startup time. The drawback is
     /// that the AC is always on, leading to a higher power dissipation.
+    ///
+    /// The measurement mode of each AC is left untouched: ACs start out off,
+    /// and are put in continuous measurement mode when continuous, event-only
+    /// or interrupt-based comparisons are started on them.
     fn enable(&self) {
         let regs = ACIFC_BASE;
         self.enable_clock();
-        regs.ctrl.write(Control::EN::SET);
-
-        // Enable continuous measurement mode and always-on mode for all the analog comparators
-        regs.conf[0].write(
-            ACConfiguration::MODE::ContinuousMeasurementMode + ACConfiguration::ALWAYSON::SET,
-        );
-        regs.conf[1].write(
-            ACConfiguration::MODE::ContinuousMeasurementMode + ACConfiguration::ALWAYSON::SET,
-        );
-        regs.conf[2].write(
-            ACConfiguration::MODE::ContinuousMeasurementMode + ACConfiguration::ALWAYSON::SET,
-        );
-        regs.conf[3].write(
-            ACConfiguration::MODE::ContinuousMeasurementMode + ACConfiguration::ALWAYSON::SET,
-        );
+        regs.ctrl.modify(Control::EN::SET);
+
+        // Enable always-on mode for all the analog comparators, keeping their
+        // measurement and interrupt settings
+        regs.conf[0].modify(ACConfiguration::ALWAYSON::SET);
+        regs.conf[1].modify(ACConfiguration::ALWAYSON::SET);
+        regs.conf[2].modify(ACConfiguration::ALWAYSON::SET);
+        regs.conf[3].modify(ACConfiguration::ALWAYSON::SET);
 
         // Make sure enabling was succesful
         let result = regs.ctrl.is_set(Control::EN);
@@ -328,6 +356,68 @@ impl<'a> Acifc<'a> {
         }
     }
 
+    /// Put an AC in continuous measurement mode, in which SR always holds the
+    /// result of its last measurement
+    fn set_continuous(&self, ac: usize) {
+        let regs = ACIFC_BASE;
+        regs.conf[ac].modify(ACConfiguration::MODE::ContinuousMeasurementMode);
+    }
+
+    /// Returns whether an AC is in continuous measurement mode
+    fn is_continuous(&self, ac: usize) -> bool {
+        let regs = ACIFC_BASE;
+        regs.conf[ac].matches_all(ACConfiguration::MODE::ContinuousMeasurementMode)
+    }
+
+    /// Do a single measurement on an AC in user triggered single measurement
+    /// mode, waiting until it is done
+    fn trigger_measurement(&self, ac: usize) {
+        let regs = ACIFC_BASE;
+        regs.conf[ac].modify(ACConfiguration::MODE::UserMode);
+        regs.ctrl.modify(Control::USTART::SET);
+        while regs.ctrl.is_set(Control::USTART) {}
+    }
+
+    /// Generate a peripheral event every time the output of `ac` changes. The
+    /// events can be routed to other peripherals through the PEVC.
+    pub fn enable_events(&self, ac: usize) -> ReturnCode {
+        if ac > 3 {
+            return ReturnCode::EINVAL;
+        }
+        self.enable();
+        self.set_continuous(ac);
+        let regs = ACIFC_BASE;
+        regs.conf[ac].modify(ACConfiguration::EVENP::SET + ACConfiguration::EVENN::SET);
+        ReturnCode::SUCCESS
+    }
+
+    /// Stop generating peripheral events for `ac`
+    pub fn disable_events(&self, ac: usize) -> ReturnCode {
+        if ac > 3 {
+            return ReturnCode::EINVAL;
+        }
+        let regs = ACIFC_BASE;
+        regs.conf[ac].modify(ACConfiguration::EVENP::CLEAR + ACConfiguration::EVENN::CLEAR);
+        ReturnCode::SUCCESS
+    }
+
+    /// Recount the ACs that are enabled in sleep and have interrupts enabled,
+    /// and only let the ACIFC request its clock in deep sleep if there are
+    /// any
+    fn update_sleepwalking(&self) {
+        let regs = ACIFC_BASE;
+        let imr = regs.imr.get();
+        let mut count = 0;
+        for ac in 0..4 {
+            // ACINTx is bit 2x
+            if self.sleep_enabled.get() & (1 << ac) != 0 && imr & (1 << (2 * ac)) != 0 {
+                count += 1;
+            }
+        }
+        SLEEPWALKING.store(count, Ordering::Relaxed);
+        pm::set_acifc_clock_request(count > 0);
+    }
+
     /// Disable the entire ACIFC
     fn disable(&self) {
         let regs = ACIFC_BASE;
@@ -354,19 +444,7 @@ impl<'a> Acifc<'a> {
             // to IER
             regs.idr.write(Interrupt::ACINT0::SET);
 
-            // If Vinp > Vinn, throw an interrupt to the client and set the AC so
-            // that it will throw an interrupt when Vinn < Vinp instead.
-            if !regs.conf[0].is_set(ACConfiguration::IS) {
-                self.client.get().map(|client| {
-                    client.fired(0);
-                });
-                regs.conf[0].modify(ACConfiguration::IS::WhenVinpLtVinn);
-            }
-            // If Vinp < Vinn, set the AC so that it will throw an interrupt when
-            // Vinp > Vinn instead.
-            else {
-                regs.conf[0].modify(ACConfiguration::IS::WhenVinpGtVinn);
-            }
+            self.handle_ac_interrupt(0);
 
             // Clear the interrupt request
             regs.icr.write(Interrupt::ACINT0::SET);
@@ -381,19 +459,7 @@ impl<'a> Acifc<'a> {
             // to IER
             regs.idr.write(Interrupt::ACINT1::SET);
 
-            // If Vinp > Vinn, throw an interrupt to the client and set the AC so
-            // that it will throw an interrupt when Vinn < Vinp instead.
-            if !regs.conf[1].is_set(ACConfiguration::IS) {
-                self.client.get().map(|client| {
-                    client.fired(1);
-                });
-                regs.conf[1].modify(ACConfiguration::IS::WhenVinpLtVinn);
-            }
-            // If Vinp < Vinn, set the AC so that it will throw an interrupt when
-            // Vinp > Vinn instead.
-            else {
-                regs.conf[1].modify(ACConfiguration::IS::WhenVinpGtVinn);
-            }
+            self.handle_ac_interrupt(1);
 
             // Clear the interrupt request
             regs.icr.write(Interrupt::ACINT1::SET);
@@ -408,19 +474,7 @@ impl<'a> Acifc<'a> {
             // to IER
             regs.idr.write(Interrupt::ACINT2::SET);
 
-            // If Vinp > Vinn, throw an interrupt to the client and set the AC so
-            // that it will throw an interrupt when Vinn < Vinp instead.
-            if !regs.conf[2].is_set(ACConfiguration::IS) {
-                self.client.get().map(|client| {
-                    client.fired(2);
-                });
-                regs.conf[2].modify(ACConfiguration::IS::WhenVinpLtVinn);
-            }
-            // If Vinp < Vinn, set the AC so that it will throw an interrupt when
-            // Vinp > Vinn instead.
-            else {
-                regs.conf[2].modify(ACConfiguration::IS::WhenVinpGtVinn);
-            }
+            self.handle_ac_interrupt(2);
 
             // Clear the interrupt request
             regs.icr.write(Interrupt::ACINT2::SET);
@@ -435,23 +489,98 @@ impl<'a> Acifc<'a> {
             // to IER
             regs.idr.write(Interrupt::ACINT3::SET);
 
-            // If Vinp > Vinn, throw an interrupt to the client and set the AC so
-            // that it will throw an interrupt when Vinn < Vinp instead.
-            if !regs.conf[3].is_set(ACConfiguration::IS) {
-                self.client.get().map(|client| {
-                    client.fired(3);
-                });
-                regs.conf[3].modify(ACConfiguration::IS::WhenVinpLtVinn);
-            }
-            // If Vinp < Vinn, set the AC so that it will throw an interrupt when
-            // Vinp > Vinn instead.
-            else {
-                regs.conf[3].modify(ACConfiguration::IS::WhenVinpGtVinn);
-            }
+            self.handle_ac_interrupt(3);
 
             // Clear the interrupt request
             regs.icr.write(Interrupt::ACINT3::SET);
             regs.ier.write(Interrupt::ACINT3::SET);
+        } else if regs.isr.is_set(Interrupt::WFINT0) {
+            if !regs.imr.is_set(Interrupt::WFINT0) {
+                return;
+            }
+            regs.idr.write(Interrupt::WFINT0::SET);
+            self.handle_window_interrupt(0);
+            regs.icr.write(Interrupt::WFINT0::SET);
+            regs.ier.write(Interrupt::WFINT0::SET);
+        } else if regs.isr.is_set(Interrupt::WFINT1) {
+            if !regs.imr.is_set(Interrupt::WFINT1) {
+                return;
+            }
+            regs.idr.write(Interrupt::WFINT1::SET);
+            self.handle_window_interrupt(1);
+            regs.icr.write(Interrupt::WFINT1::SET);
+            regs.ier.write(Interrupt::WFINT1::SET);
+        }
+    }
+
+    /// Throw an interrupt to the client if the condition requested for `ac` is
+    /// met. In the VpGtVn mode, once Vinp > Vinn the AC is set so that it will
+    /// throw an interrupt when Vinp < Vinn instead, without notifying the
+    /// client, after which it is set back to Vinp > Vinn. The VpLtVn mode
+    /// works the other way around.
+    fn handle_ac_interrupt(&self, ac: usize) {
+        let regs = ACIFC_BASE;
+        let conf = &regs.conf[ac];
+        let fire = match self.interrupt_modes[ac].get() {
+            InterruptMode::VpGtVn => {
+                if conf.matches_all(ACConfiguration::IS::WhenVinpGtVinn) {
+                    conf.modify(ACConfiguration::IS::WhenVinpLtVinn);
+                    true
+                } else {
+                    conf.modify(ACConfiguration::IS::WhenVinpGtVinn);
+                    false
+                }
+            }
+            InterruptMode::VpLtVn => {
+                if conf.matches_all(ACConfiguration::IS::WhenVinpLtVinn) {
+                    conf.modify(ACConfiguration::IS::WhenVinpGtVinn);
+                    true
+                } else {
+                    conf.modify(ACConfiguration::IS::WhenVinpLtVinn);
+                    false
+                }
+            }
+            InterruptMode::Toggle => true,
+        };
+        if fire {
+            self.client.get().map(|client| {
+                client.fired(ac);
+            });
+        }
+    }
+
+    /// Like for the normal mode interrupts, the Inside and Outside modes fire
+    /// once when the condition is true. The window is then set to interrupt
+    /// when the condition becomes false, without notifying the client, after
+    /// which the original condition is restored.
+    fn handle_window_interrupt(&self, window: usize) {
+        let regs = ACIFC_BASE;
+        let confw = &regs.confw[window];
+        let fire = match self.window_modes[window].get() {
+            WindowInterruptMode::Inside => {
+                if confw.matches_all(WindowConfiguration::WIS::InterruptInsideWindow) {
+                    confw.modify(WindowConfiguration::WIS::InterruptLeaveWindow);
+                    true
+                } else {
+                    confw.modify(WindowConfiguration::WIS::InterruptInsideWindow);
+                    false
+                }
+            }
+            WindowInterruptMode::Outside => {
+                if confw.matches_all(WindowConfiguration::WIS::InterruptOutsideWindow) {
+                    confw.modify(WindowConfiguration::WIS::InterruptEnterWindow);
+                    true
+                } else {
+                    confw.modify(WindowConfiguration::WIS::InterruptOutsideWindow);
+                    false
+                }
+            }
+            WindowInterruptMode::Entering | WindowInterruptMode::Leaving => true,
+        };
+        if fire {
+            self.client.get().map(|client| {
+                client.window_fired(window);
+            });
         }
     }
 }
@@ -459,10 +588,15 @@ impl<'a> Acifc<'a> {
 impl<'a> analog_comparator::AnalogComparator for Acifc<'a> {
     type Channel = AcChannel;
 
-    /// Do a single comparison
+    /// Do a single comparison. If the AC is measuring continuously, its latched
+    /// result is returned, otherwise a single measurement is triggered first.
     fn comparison(&self, channel: &Self::Channel) -> bool {
         self.enable();
         let regs = ACIFC_BASE;
+        let ac = channel.chan_num as usize;
+        if ac <= 3 && !self.is_continuous(ac) {
+            self.trigger_measurement(ac);
+        }
         let result;
         if channel.chan_num == 0 {
             result = regs.sr.is_set(Status::ACCS0);
@@ -480,33 +614,136 @@ impl<'a> analog_comparator::AnalogComparator for Acifc<'a> {
         return result;
     }
 
-    /// Start interrupt-based comparisons
-    fn start_comparing(&self, channel: &Self::Channel) -> ReturnCode {
+    /// Do a single window comparison. Window 0 is made up of AC0 and AC1,
+    /// window 1 of AC2 and AC3.
+    fn window_comparison(&self, window: usize) -> bool {
         self.enable();
         let regs = ACIFC_BASE;
-
-        if channel.chan_num == 0 {
-            // Enable interrupts.
-            regs.ier.write(Interrupt::ACINT0::SET);
-            return ReturnCode::SUCCESS;
-        } else if channel.chan_num == 1 {
-            // Repeat the same for ac == 1
-            regs.ier.write(Interrupt::ACINT1::SET);
-            return ReturnCode::SUCCESS;
-        } else if channel.chan_num == 2 {
-            // Repeat the same for ac == 2
-            regs.ier.write(Interrupt::ACINT2::SET);
-            return ReturnCode::SUCCESS;
-        } else if channel.chan_num == 3 {
-            // Repeat the same for ac == 3
-            regs.ier.write(Interrupt::ACINT3::SET);
-            return ReturnCode::SUCCESS;
+        if window <= 1 {
+            self.set_continuous(2 * window);
+            self.set_continuous(2 * window + 1);
+        }
+        let result;
+        if window == 0 {
+            regs.confw[0].write(WindowConfiguration::WFEN::SET);
+            result = regs.sr.is_set(Status::WFCS0);
+        } else if window == 1 {
+            regs.confw[1].write(WindowConfiguration::WFEN::SET);
+            result = regs.sr.is_set(Status::WFCS1);
         } else {
             // Should never get here, just making sure
             self.disable();
+            panic!("PANIC! Please choose a window that this chip supports");
+        }
+        return result;
+    }
+
+    /// Start interrupt-based comparisons
+    fn start_comparing(&self, channel: &Self::Channel, mode: InterruptMode) -> ReturnCode {
+        let ac = channel.chan_num as usize;
+        if ac > 3 {
             debug!("Please choose a comparator (value of ac) that this chip supports");
             return ReturnCode::EINVAL;
         }
+        self.enable();
+        let regs = ACIFC_BASE;
+
+        // Set the interrupt condition
+        self.set_continuous(ac);
+        self.interrupt_modes[ac].set(mode);
+        regs.conf[ac].modify(match mode {
+            InterruptMode::VpGtVn => ACConfiguration::IS::WhenVinpGtVinn,
+            InterruptMode::VpLtVn => ACConfiguration::IS::WhenVinpLtVinn,
+            InterruptMode::Toggle => ACConfiguration::IS::OnToggleOfACOUT,
+        });
+
+        // Enable interrupts.
+        match ac {
+            0 => regs.ier.write(Interrupt::ACINT0::SET),
+            1 => regs.ier.write(Interrupt::ACINT1::SET),
+            2 => regs.ier.write(Interrupt::ACINT2::SET),
+            _ => regs.ier.write(Interrupt::ACINT3::SET),
+        }
+        self.update_sleepwalking();
+        ReturnCode::SUCCESS
+    }
+
+    /// Measure continuously, without interrupts
+    fn start_continuous(&self, channel: &Self::Channel) -> ReturnCode {
+        let ac = channel.chan_num as usize;
+        if ac > 3 {
+            return ReturnCode::EINVAL;
+        }
+        self.enable();
+        self.set_continuous(ac);
+        ReturnCode::SUCCESS
+    }
+
+    /// Measure continuously, interrupting on every toggle of ACOUT
+    fn start_event_only(&self, channel: &Self::Channel) -> ReturnCode {
+        self.start_comparing(channel, InterruptMode::Toggle)
+    }
+
+    /// Turn the AC off and disable its interrupts
+    fn stop(&self, channel: &Self::Channel) -> ReturnCode {
+        let ac = channel.chan_num as usize;
+        let regs = ACIFC_BASE;
+        match ac {
+            0 => regs.idr.write(Interrupt::ACINT0::SET),
+            1 => regs.idr.write(Interrupt::ACINT1::SET),
+            2 => regs.idr.write(Interrupt::ACINT2::SET),
+            3 => regs.idr.write(Interrupt::ACINT3::SET),
+            _ => return ReturnCode::EINVAL,
+        }
+        regs.conf[ac].modify(ACConfiguration::MODE::Off);
+        self.update_sleepwalking();
+        ReturnCode::SUCCESS
+    }
+
+    /// Let the ACIFC request its clock in deep sleep while `channel` has
+    /// interrupts enabled
+    fn enable_in_sleep(&self, channel: &Self::Channel) -> ReturnCode {
+        let ac = channel.chan_num as usize;
+        if ac > 3 {
+            return ReturnCode::EINVAL;
+        }
+        self.sleep_enabled.set(self.sleep_enabled.get() | (1 << ac));
+        self.update_sleepwalking();
+        ReturnCode::SUCCESS
+    }
+
+    fn disable_in_sleep(&self, channel: &Self::Channel) -> ReturnCode {
+        let ac = channel.chan_num as usize;
+        if ac > 3 {
+            return ReturnCode::EINVAL;
+        }
+        self.sleep_enabled.set(self.sleep_enabled.get() & !(1 << ac));
+        self.update_sleepwalking();
+        ReturnCode::SUCCESS
+    }
+
+    /// The negative input of each AC is always its ACANx pin.
+    fn set_reference(&self, _channel: &Self::Channel, reference: Reference) -> ReturnCode {
+        match reference {
+            Reference::ExternalPin(_) => ReturnCode::SUCCESS,
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Set the hysteresis voltage: 0, 25, 50 or 75 mV
+    fn set_hysteresis(&self, channel: &Self::Channel, level: Hysteresis) -> ReturnCode {
+        let ac = channel.chan_num as usize;
+        if ac > 3 {
+            return ReturnCode::EINVAL;
+        }
+        let regs = ACIFC_BASE;
+        regs.conf[ac].modify(match level {
+            Hysteresis::None => ACConfiguration::HYS::HysteresisVoltage0mV,
+            Hysteresis::Low => ACConfiguration::HYS::HysteresisVoltage25mV,
+            Hysteresis::Medium => ACConfiguration::HYS::HysteresisVoltage50mV,
+            Hysteresis::High => ACConfiguration::HYS::HysteresisVoltage75mV,
+        });
+        ReturnCode::SUCCESS
     }
 
     /// Stop interrupt-based comparisons
@@ -515,19 +752,23 @@ impl<'a> analog_comparator::AnalogComparator for Acifc<'a> {
 
         if channel.chan_num == 0 {
             // Disable interrupts.
-            regs.ier.write(Interrupt::ACINT0::CLEAR);
+            regs.idr.write(Interrupt::ACINT0::SET);
+            self.update_sleepwalking();
             return ReturnCode::SUCCESS;
         } else if channel.chan_num == 1 {
             // Repeat the same for ac == 1
-            regs.ier.write(Interrupt::ACINT1::CLEAR);
+            regs.idr.write(Interrupt::ACINT1::SET);
+            self.update_sleepwalking();
             return ReturnCode::SUCCESS;
         } else if channel.chan_num == 2 {
             // Repeat the same for ac == 2
-            regs.ier.write(Interrupt::ACINT2::CLEAR);
+            regs.idr.write(Interrupt::ACINT2::SET);
+            self.update_sleepwalking();
             return ReturnCode::SUCCESS;
         } else if channel.chan_num == 3 {
             // Repeat the same for ac == 3
-            regs.ier.write(Interrupt::ACINT3::CLEAR);
+            regs.idr.write(Interrupt::ACINT3::SET);
+            self.update_sleepwalking();
             return ReturnCode::SUCCESS;
         } else {
             // Should never get here, just making sure
@@ -536,6 +777,50 @@ impl<'a> analog_comparator::AnalogComparator for Acifc<'a> {
             return ReturnCode::EINVAL;
         }
     }
+    /// Start interrupt-based window comparisons. Window 0 is made up of AC0
+    /// and AC1, window 1 of AC2 and AC3.
+    fn enable_window_interrupts(&self, window: usize, mode: WindowInterruptMode) -> ReturnCode {
+        if window > 1 {
+            return ReturnCode::EINVAL;
+        }
+        self.enable();
+        self.set_continuous(2 * window);
+        self.set_continuous(2 * window + 1);
+        let regs = ACIFC_BASE;
+
+        let wis = match mode {
+            WindowInterruptMode::Inside => WindowConfiguration::WIS::InterruptInsideWindow,
+            WindowInterruptMode::Outside => WindowConfiguration::WIS::InterruptOutsideWindow,
+            WindowInterruptMode::Entering => WindowConfiguration::WIS::InterruptEnterWindow,
+            WindowInterruptMode::Leaving => WindowConfiguration::WIS::InterruptLeaveWindow,
+        };
+        self.window_modes[window].set(mode);
+        regs.confw[window].write(WindowConfiguration::WFEN::SET + wis);
+
+        if window == 0 {
+            regs.icr.write(Interrupt::WFINT0::SET);
+            regs.ier.write(Interrupt::WFINT0::SET);
+        } else {
+            regs.icr.write(Interrupt::WFINT1::SET);
+            regs.ier.write(Interrupt::WFINT1::SET);
+        }
+        ReturnCode::SUCCESS
+    }
+
+    /// Stop interrupt-based window comparisons
+    fn disable_window_interrupts(&self, window: usize) -> ReturnCode {
+        let regs = ACIFC_BASE;
+
+        if window == 0 {
+            regs.idr.write(Interrupt::WFINT0::SET);
+        } else if window == 1 {
+            regs.idr.write(Interrupt::WFINT1::SET);
+        } else {
+            return ReturnCode::EINVAL;
+        }
+        regs.confw[window].write(WindowConfiguration::WFEN::CLEAR);
+        ReturnCode::SUCCESS
+    }
 }
 
 /// Static state to manage the ACIFC
@@ -11,12 +11,19 @@
 //! - are left justified
 //!
 //! Samples can either be collected individually or continuously at a specified
-//! frequency.
+//! frequency. They can also be triggered by an analog comparator through the
+//! peripheral event controller, without CPU involvement.
+//!
+//! Scans sample a sequence of up to eight channels. The sequencer converts a
+//! single channel per trigger, so the end of conversion interrupt
+//! reconfigures it for the next channel of the sequence and triggers the
+//! next conversion right away.
 //!
 //! - Author: Philip Levis <pal@cs.stanford.edu>, Branden Ghena <brghena@umich.edu>
 //! - Updated: May 1, 2017
 
 use core::cell::Cell;
+use acifc;
 use core::{cmp, mem, slice};
 use dma;
 use kernel::common::cells::{OptionalCell, TakeCell};
@@ -25,6 +32,7 @@ use kernel::common::registers::{ReadOnly, ReadWrite, WriteOnly};
 use kernel::common::StaticRef;
 use kernel::hil;
 use kernel::ReturnCode;
+use pevc;
 use pm::{self, Clock, PBAClock};
 use scif;
 
@@ -97,6 +105,9 @@ pub static mut CHANNEL_DAC: AdcChannel = AdcChannel::new(Channel::DAC);
 pub static mut CHANNEL_VSINGLE: AdcChannel = AdcChannel::new(Channel::Vsingle);
 pub static mut CHANNEL_REFERENCE_GROUND: AdcChannel = AdcChannel::new(Channel::ReferenceGround);
 
+/// The most channels in a scan sequence
+const MAX_SCAN_CHANNELS: usize = 8;
+
 /// Create a trait of both client types to allow a single client reference to
 /// act as both
 pub trait EverythingClient: hil::adc::Client + hil::adc::HighSpeedClient {}
@@ -113,21 +124,31 @@ pub struct Adc {
     continuous: Cell<bool>,
     dma_running: Cell<bool>,
     cpu_clock: Cell<bool>,
+    /// The analog comparator triggering conversions, if any
+    event_source: OptionalCell<usize>,
 
     // timer fire counting for slow sampling rates
     timer_repeats: Cell<u8>,
     timer_counts: Cell<u8>,
 
     // DMA peripheral, buffers, and length
-    rx_dma: OptionalCell<&'static dma::DMAChannel>,
+    rx_dma: OptionalCell<&'static dma::Channel>,
     rx_dma_peripheral: dma::DMAPeripheral,
     rx_length: Cell<usize>,
     next_dma_buffer: TakeCell<'static, [u16]>,
     next_dma_length: Cell<usize>,
     stopped_buffer: TakeCell<'static, [u16]>,
 
+    // scan sequence, with the channel number in the low nibble and whether
+    // it is internal in the high nibble of each entry
+    scan_channels: Cell<[u8; MAX_SCAN_CHANNELS]>,
+    scan_length: Cell<usize>,
+    scan_index: Cell<usize>,
+    scan_buffer: TakeCell<'static, [u16]>,
+
     // ADC client to send sample complete notifications to
     client: OptionalCell<&'static EverythingClient>,
+    scan_client: OptionalCell<&'static hil::adc::ScanClient>,
 }
 
 /// Memory mapped registers for the ADC.
@@ -366,6 +387,7 @@ impl Adc {
             continuous: Cell::new(false),
             dma_running: Cell::new(false),
             cpu_clock: Cell::new(false),
+            event_source: OptionalCell::empty(),
 
             // timer repeating state for slow sampling rates
             timer_repeats: Cell::new(0),
@@ -379,8 +401,15 @@ impl Adc {
             next_dma_length: Cell::new(0),
             stopped_buffer: TakeCell::empty(),
 
+            // scan sequence and progress
+            scan_channels: Cell::new([0; MAX_SCAN_CHANNELS]),
+            scan_length: Cell::new(0),
+            scan_index: Cell::new(0),
+            scan_buffer: TakeCell::empty(),
+
             // higher layer to send responses to
             client: OptionalCell::empty(),
+            scan_client: OptionalCell::empty(),
         }
     }
 
@@ -391,10 +420,16 @@ impl Adc {
         self.client.set(client);
     }
 
+    /// Sets the client for scans.
+    pub fn set_scan_client(&self, client: &'static hil::adc::ScanClient) {
+        self.scan_client.set(client);
+    }
+
     /// Sets the DMA channel for this driver.
     ///
     /// - `rx_dma`: reference to the DMA channel the ADC should use
-    pub fn set_dma(&self, rx_dma: &'static dma::DMAChannel) {
+    pub fn set_dma(&self, rx_dma: &'static dma::Channel) {
+        rx_dma.set_width(hil::dma::Width::Width16Bit);
         self.rx_dma.set(rx_dma);
     }
 
@@ -403,7 +438,29 @@ impl Adc {
         let regs: &AdcRegisters = &*self.registers;
         let status = regs.sr.is_set(Status::SEOC);
 
-        if self.enabled.get() && self.active.get() {
+        if self.enabled.get() && self.active.get() && self.scan_buffer.is_some() {
+            if status {
+                // a channel of the scan sequence is converted
+                let val = regs.lcv.read(SequencerLastConvertedValue::LCV) as u16;
+                regs.scr.write(Interrupt::SEOC::SET);
+
+                let index = self.scan_index.get();
+                self.scan_buffer.map(|buffer| buffer[index] = val);
+                if index + 1 < self.scan_length.get() {
+                    self.scan_index.set(index + 1);
+                    self.start_scan_conversion(index + 1);
+                } else {
+                    // frame complete
+                    self.active.set(false);
+                    regs.idr.write(Interrupt::SEOC::SET);
+                    self.scan_buffer.take().map(|buffer| {
+                        self.scan_client.map(move |client| {
+                            client.scan_done(buffer, index + 1);
+                        });
+                    });
+                }
+            }
+        } else if self.enabled.get() && self.active.get() {
             if status {
                 // sample complete interrupt
 
@@ -461,6 +518,46 @@ impl Adc {
         );
     }
 
+    /// Configure the sequencer for channel `index` of the scan sequence and
+    /// trigger its conversion.
+    fn start_scan_conversion(&self, index: usize) {
+        let regs: &AdcRegisters = &*self.registers;
+        let channel = self.scan_channels.get()[index];
+        let cfg = SequencerConfig::MUXNEG.val(0x7) + // ground pad
+            SequencerConfig::MUXPOS.val((channel & 0x0F) as u32)
+            + SequencerConfig::INTERNAL.val(0x2 | (channel >> 4) as u32)
+            + SequencerConfig::RES::Bits12
+            + SequencerConfig::TRGSEL::Software
+            + SequencerConfig::GCOMP::Disable
+            + SequencerConfig::GAIN::Gain0p5x
+            + SequencerConfig::BIPOLAR::Disable
+            + SequencerConfig::HWLA::Enable;
+        regs.seqcfg.write(cfg);
+        regs.cr.write(Control::STRIG::SET);
+    }
+
+    /// Start moving `dma_len` samples into `buf`.
+    fn start_dma(&self, buf: &'static mut [u16], dma_len: usize) {
+        // change buffer into a [u8]
+        // this is unsafe but acceptable for the following reasons
+        //  * the buffer is aligned based on 16-bit boundary, so the 8-bit
+        //    alignment is fine
+        //  * the DMA is doing checking based on our expected data width to
+        //    make sure we don't go past dma_buf.len()/width
+        //  * we will transmute the array back to a [u16] after the DMA
+        //    transfer is complete
+        let dma_buf_ptr = unsafe { mem::transmute::<*mut u16, *mut u8>(buf.as_mut_ptr()) };
+        let dma_buf = unsafe { slice::from_raw_parts_mut(dma_buf_ptr, buf.len() * 2) };
+
+        // set up the DMA
+        self.rx_dma.map(move |dma| {
+            self.dma_running.set(true);
+            dma.enable();
+            self.rx_length.set(dma_len);
+            dma.transfer_from_peripheral(self.rx_dma_peripheral, dma_buf, dma_len);
+        });
+    }
+
     // Configures the ADC with the slowest clock that can provide continuous sampling at
     // the desired frequency and enables the ADC. Subsequent calls with the same frequency
     // value have no effect. Using the slowest clock also ensures efficient discrete
@@ -785,6 +882,11 @@ impl hil::adc::Adc for Adc {
             });
             self.rx_length.set(0);
 
+            // an interrupted scan gives its buffer back the same way
+            self.scan_buffer.take().map(|buf| {
+                self.stopped_buffer.replace(buf);
+            });
+
             // store the buffer if it exists
             dma_buffer.map(|dma_buf| {
                 // change buffer back into a [u16]
@@ -813,6 +915,87 @@ impl hil::adc::Adc for Adc {
     }
 }
 
+/// Implements an ADC whose conversions can be triggered by the analog
+/// comparators
+impl hil::analog_comparator::EventSink for Adc {
+    type Channel = AdcChannel;
+
+    /// Sample `channel` every time the output of analog comparator `ac`
+    /// changes, calling the client after each sample. The comparator events
+    /// are routed to the ADC by the PEVC.
+    ///
+    /// - `ac`: the analog comparator whose events trigger conversions
+    /// - `channel`: the ADC channel to sample
+    fn start_on_events(&self, ac: usize, channel: &AdcChannel) -> ReturnCode {
+        let regs: &AdcRegisters = &*self.registers;
+
+        let generator = match ac {
+            0 => pevc::Generator::AcifcAc0,
+            1 => pevc::Generator::AcifcAc1,
+            2 => pevc::Generator::AcifcAc2,
+            3 => pevc::Generator::AcifcAc3,
+            _ => return ReturnCode::EINVAL,
+        };
+
+        // conversions are spread out, so use the slowest clock
+        let res = self.config_and_enable(1000);
+
+        if res != ReturnCode::SUCCESS {
+            return res;
+        } else if !self.enabled.get() {
+            ReturnCode::EOFF
+        } else if self.active.get() {
+            // only one operation at a time
+            ReturnCode::EBUSY
+        } else {
+            self.active.set(true);
+            self.continuous.set(true);
+            self.timer_repeats.set(0);
+            self.timer_counts.set(0);
+            self.event_source.set(ac);
+
+            let cfg = SequencerConfig::MUXNEG.val(0x7) + // ground pad
+                SequencerConfig::MUXPOS.val(channel.chan_num)
+                + SequencerConfig::INTERNAL.val(0x2 | channel.internal)
+                + SequencerConfig::RES::Bits12
+                + SequencerConfig::TRGSEL::InternalTriggerSource
+                + SequencerConfig::GCOMP::Disable
+                + SequencerConfig::GAIN::Gain0p5x
+                + SequencerConfig::BIPOLAR::Disable
+                + SequencerConfig::HWLA::Enable;
+            regs.seqcfg.write(cfg);
+
+            // clear any current status
+            self.clear_status();
+
+            // enable end of conversion interrupt
+            regs.ier.write(Interrupt::SEOC::SET);
+
+            // route the comparator events to the ADC
+            unsafe {
+                acifc::ACIFC.enable_events(ac);
+                pevc::PEVC.connect(generator, pevc::User::Adcife);
+            }
+
+            ReturnCode::SUCCESS
+        }
+    }
+
+    /// Stop sampling on analog comparator events.
+    fn stop_on_events(&self) -> ReturnCode {
+        match self.event_source.take() {
+            Some(ac) => {
+                unsafe {
+                    pevc::PEVC.disconnect(pevc::User::Adcife);
+                    acifc::ACIFC.disable_events(ac);
+                }
+                hil::adc::Adc::stop_sampling(self)
+            }
+            None => ReturnCode::EINVAL,
+        }
+    }
+}
+
 /// Implements an ADC capable of continuous sampling
 impl hil::adc::AdcHighSpeed for Adc {
     /// Capture buffered samples from the ADC continuously at a given
@@ -901,25 +1084,7 @@ impl hil::adc::AdcHighSpeed for Adc {
 
             // receive up to the buffer's length samples
             let dma_len = cmp::min(buffer1.len(), length1);
-
-            // change buffer into a [u8]
-            // this is unsafe but acceptable for the following reasons
-            //  * the buffer is aligned based on 16-bit boundary, so the 8-bit
-            //    alignment is fine
-            //  * the DMA is doing checking based on our expected data width to
-            //    make sure we don't go past dma_buf.len()/width
-            //  * we will transmute the array back to a [u16] after the DMA
-            //    transfer is complete
-            let dma_buf_ptr = unsafe { mem::transmute::<*mut u16, *mut u8>(buffer1.as_mut_ptr()) };
-            let dma_buf = unsafe { slice::from_raw_parts_mut(dma_buf_ptr, buffer1.len() * 2) };
-
-            // set up the DMA
-            self.rx_dma.map(move |dma| {
-                self.dma_running.set(true);
-                dma.enable();
-                self.rx_length.set(dma_len);
-                dma.do_transfer(self.rx_dma_peripheral, dma_buf, dma_len);
-            });
+            self.start_dma(buffer1, dma_len);
 
             // start timer
             regs.cr.write(Control::TSTART::SET);
@@ -949,6 +1114,14 @@ impl hil::adc::AdcHighSpeed for Adc {
         } else if self.next_dma_buffer.is_some() {
             // we've already got a second buffer, we don't need a third yet
             (ReturnCode::EBUSY, Some(buf))
+        } else if !self.dma_running.get() && cmp::min(buf.len(), length) > 0 {
+            // the last buffer filled up before this one was provided. Resume
+            // with this one right away; the samples converted in between
+            // overrun the last converted value and are reported as dropped
+            let dma_len = cmp::min(buf.len(), length);
+            self.start_dma(buf, dma_len);
+
+            (ReturnCode::SUCCESS, None)
         } else {
             // store the buffer for later use
             self.next_dma_buffer.replace(buf);
@@ -982,7 +1155,7 @@ impl hil::adc::AdcHighSpeed for Adc {
 }
 
 /// Implements a client of a DMA.
-impl dma::DMAClient for Adc {
+impl hil::dma::Client<dma::DMAPeripheral> for Adc {
     /// Handler for DMA transfer completion.
     ///
     /// - `pid`: the DMA peripheral that is complete
@@ -1013,26 +1186,7 @@ impl dma::DMAClient for Adc {
                 // zero-length buffer or length field, assume that the user knew
                 // what was going on, and just don't use the buffer
                 if dma_len > 0 {
-                    // change buffer into a [u8]
-                    // this is unsafe but acceptable for the following reasons
-                    //  * the buffer is aligned based on 16-bit boundary, so the
-                    //    8-bit alignment is fine
-                    //  * the DMA is doing checking based on our expected data
-                    //    width to make sure we don't go past
-                    //    dma_buf.len()/width
-                    //  * we will transmute the array back to a [u16] after the
-                    //    DMA transfer is complete
-                    let dma_buf_ptr =
-                        unsafe { mem::transmute::<*mut u16, *mut u8>(buf.as_mut_ptr()) };
-                    let dma_buf = unsafe { slice::from_raw_parts_mut(dma_buf_ptr, buf.len() * 2) };
-
-                    // set up the DMA
-                    self.rx_dma.map(move |dma| {
-                        self.dma_running.set(true);
-                        dma.enable();
-                        self.rx_length.set(dma_len);
-                        dma.do_transfer(self.rx_dma_peripheral, dma_buf, dma_len);
-                    });
+                    self.start_dma(buf, dma_len);
                 } else {
                     // if length was zero, just keep the buffer in the takecell
                     // so we can return it when `stop_sampling` is called
@@ -1040,8 +1194,18 @@ impl dma::DMAClient for Adc {
                 }
             });
 
+            // a sample was replaced by the next one before the DMA read it
+            let regs: &AdcRegisters = &*self.registers;
+            let dropped = regs.sr.is_set(Status::LOVR);
+            if dropped {
+                regs.scr.write(Interrupt::LOVR::SET);
+            }
+
             // alert client
             self.client.map(|client| {
+                if dropped {
+                    client.samples_dropped();
+                }
                 dma_buffer.map(|dma_buf| {
                     // change buffer back into a [u16]
                     // the buffer was originally a [u16] so this should be okay
@@ -1058,3 +1222,63 @@ impl dma::DMAClient for Adc {
         }
     }
 }
+
+/// Implements an ADC that samples a sequence of channels per scan
+impl hil::adc::AdcScan for Adc {
+    fn max_scan_channels(&self) -> usize {
+        MAX_SCAN_CHANNELS
+    }
+
+    fn configure_scan(&self, channels: &[&AdcChannel]) -> ReturnCode {
+        if self.active.get() {
+            return ReturnCode::EBUSY;
+        }
+        if channels.is_empty() || channels.len() > MAX_SCAN_CHANNELS {
+            return ReturnCode::EINVAL;
+        }
+        let mut sequence = [0; MAX_SCAN_CHANNELS];
+        for (entry, channel) in sequence.iter_mut().zip(channels.iter()) {
+            *entry = (channel.chan_num | channel.internal << 4) as u8;
+        }
+        self.scan_channels.set(sequence);
+        self.scan_length.set(channels.len());
+        ReturnCode::SUCCESS
+    }
+
+    /// Sample the sequence once, triggering each conversion in software as
+    /// soon as the previous one completes.
+    fn scan(&self, buffer: &'static mut [u16]) -> (ReturnCode, Option<&'static mut [u16]>) {
+        let regs: &AdcRegisters = &*self.registers;
+
+        // always configure to 1KHz to get the slowest clock with single sampling
+        let res = self.config_and_enable(1000);
+
+        if res != ReturnCode::SUCCESS {
+            (res, Some(buffer))
+        } else if !self.enabled.get() {
+            (ReturnCode::EOFF, Some(buffer))
+        } else if self.active.get() {
+            // only one operation at a time
+            (ReturnCode::EBUSY, Some(buffer))
+        } else if self.scan_length.get() == 0 {
+            (ReturnCode::EINVAL, Some(buffer))
+        } else if buffer.len() < self.scan_length.get() {
+            (ReturnCode::ESIZE, Some(buffer))
+        } else {
+            self.active.set(true);
+            self.continuous.set(false);
+            self.scan_index.set(0);
+            self.scan_buffer.replace(buffer);
+
+            // clear any current status
+            self.clear_status();
+
+            // enable end of conversion interrupt
+            regs.ier.write(Interrupt::SEOC::SET);
+
+            self.start_scan_conversion(0);
+
+            (ReturnCode::SUCCESS, None)
+        }
+    }
+}
@@ -288,6 +288,17 @@ impl Ast<'a> {
         while self.busy() {}
     }
 
+    /// Returns the tics until the alarm fires, if it is armed.
+    pub fn tics_until_alarm(&self) -> Option<u32> {
+        let regs: &AstRegisters = &*self.registers;
+        if !regs.imr.is_set(Interrupt::ALARM0) {
+            return None;
+        }
+        while self.busy() {}
+        let alarm = regs.ar0.read(Value::VALUE);
+        Some(alarm.wrapping_sub(self.get_counter()))
+    }
+
     fn get_counter(&self) -> u32 {
         let regs: &AstRegisters = &*self.registers;
         while self.busy() {}
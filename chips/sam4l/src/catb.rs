@@ -0,0 +1,253 @@
+//! Implementation of capacitive touch sensing on the SAM4L CATB.
+//!
+//! See datasheet section "23. Capacitive Touch Module (CATB)".
+//!
+//! The CATB measures the capacitance of one sensor at a time, the one
+//! selected by PINSEL, by repeatedly charging it and discharging it through
+//! the DIS pin. The autonomous mode that moves the state of many sensors in
+//! and out of the CATB with the PDCA is not used. Instead the driver runs the
+//! CATB continuously, takes the raw count of each acquisition from the RAW
+//! register, and selects the next sensor, so each sensor is measured in turn.
+//! The baseline and touch detection are done in software with
+//! `touch::Detector`. The first acquisition after a change of sensor is
+//! discarded, as the CATB still settles from the previous one.
+//!
+//! An acquisition lasts `ACQUISITION_CYCLES` cycles of the PBA clock, about
+//! 2 ms at 48 MHz. The CATB clock keeps the chip out of deep sleep while
+//! measuring.
+//!
+//! The board configures the DIS pin and the sensor pins in their CATB
+//! function, and passes the indices of the sensors it uses:
+//!
+//! ```rust
+//! sam4l::catb::CATB.set_sensors(&[0, 1, 2]);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::common::registers::{ReadOnly, ReadWrite, WriteOnly};
+use kernel::common::StaticRef;
+use kernel::hil::touch::{self, TouchSense};
+use kernel::ReturnCode;
+use pm::{self, Clock, PBAClock};
+
+#[repr(C)]
+struct CatbRegisters {
+    cr: ReadWrite<u32, Control::Register>, //            Control                  (0x00)
+    cntcr: ReadWrite<u32, CounterControl::Register>, //  Counter Control          (0x04)
+    idle: ReadWrite<u32>, //                             Sensor Idle Level        (0x08)
+    level: ReadOnly<u32>, //                             Sensor Relative Level    (0x0c)
+    raw: ReadOnly<u32, Raw::Register>, //                Sensor Raw Value         (0x10)
+    timing: ReadWrite<u32>, //                           Filter Timing            (0x14)
+    thresh: ReadWrite<u32>, //                           Threshold                (0x18)
+    pinsel: ReadWrite<u32, PinSelect::Register>, //      Pin Selection            (0x1c)
+    dma: ReadWrite<u32>, //                              Direct Memory Access     (0x20)
+    isr: ReadOnly<u32, Interrupt::Register>, //          Interrupt Status         (0x24)
+    ier: WriteOnly<u32, Interrupt::Register>, //         Interrupt Enable         (0x28)
+    idr: WriteOnly<u32, Interrupt::Register>, //         Interrupt Disable        (0x2c)
+    imr: ReadOnly<u32, Interrupt::Register>, //          Interrupt Mask           (0x30)
+    scr: WriteOnly<u32, Interrupt::Register>, //         Status Clear             (0x34)
+}
+
+register_bitfields![u32,
+    Control [
+        /// Charge time
+        CHARGET OFFSET(24) NUMBITS(8) [],
+        /// Number of event samples
+        ESAMPLES OFFSET(16) NUMBITS(7) [],
+        /// DMA enable
+        DMAEN OFFSET(8) NUMBITS(1) [],
+        /// Differential mode
+        DIFF OFFSET(7) NUMBITS(1) [],
+        /// Clock select
+        CKSEL OFFSET(6) NUMBITS(1) [],
+        /// Interrupt resolution
+        INTRES OFFSET(5) NUMBITS(1) [],
+        /// Event triggered operation
+        ETRIG OFFSET(4) NUMBITS(1) [],
+        /// Software reset
+        SWRST OFFSET(3) NUMBITS(1) [],
+        /// Run operation
+        RUN OFFSET(1) NUMBITS(1) [],
+        /// Module enable
+        EN OFFSET(0) NUMBITS(1) []
+    ],
+
+    CounterControl [
+        /// Repeat time
+        REPEAT OFFSET(28) NUMBITS(3) [],
+        /// Spread spectrum
+        SPREAD OFFSET(24) NUMBITS(4) [],
+        /// Counter top value
+        TOP OFFSET(0) NUMBITS(24) []
+    ],
+
+    Raw [
+        /// Current raw B value
+        RAWB OFFSET(24) NUMBITS(8) [],
+        /// Current raw A value
+        RAWA OFFSET(16) NUMBITS(8) []
+    ],
+
+    PinSelect [
+        /// Pin selected
+        PINSEL OFFSET(0) NUMBITS(8) []
+    ],
+
+    Interrupt [
+        /// Out of touch
+        OUTTCH 2,
+        /// In touch
+        INTCH 1,
+        /// Sample ready
+        SAMPLE 0
+    ]
+];
+
+const CATB_BASE: StaticRef<CatbRegisters> =
+    unsafe { StaticRef::new(0x40070000 as *const CatbRegisters) };
+
+/// The most sensors the driver measures
+const MAX_SENSORS: usize = 8;
+
+/// The length of an acquisition, in cycles of the PBA clock
+const ACQUISITION_CYCLES: u32 = 96000;
+
+/// The threshold of the sensors until it is set
+const DEFAULT_THRESHOLD: u32 = 8;
+
+pub static mut CATB: Catb = Catb::new(CATB_BASE);
+
+pub struct Catb {
+    registers: StaticRef<CatbRegisters>,
+    client: OptionalCell<&'static touch::Client>,
+    sensors: Cell<&'static [u8]>,
+    detectors: Cell<[touch::Detector; MAX_SENSORS]>,
+    enabled: Cell<bool>,
+    /// The channel being measured
+    channel: Cell<usize>,
+    /// Whether the acquisition is the first since the sensor changed
+    settling: Cell<bool>,
+}
+
+impl Catb {
+    const fn new(registers: StaticRef<CatbRegisters>) -> Catb {
+        Catb {
+            registers: registers,
+            client: OptionalCell::empty(),
+            sensors: Cell::new(&[]),
+            detectors: Cell::new([touch::Detector::new(DEFAULT_THRESHOLD); MAX_SENSORS]),
+            enabled: Cell::new(false),
+            channel: Cell::new(0),
+            settling: Cell::new(true),
+        }
+    }
+
+    /// Sets the indices of the sensor pins, one per channel. Only the first
+    /// `MAX_SENSORS` are used.
+    pub fn set_sensors(&self, sensors: &'static [u8]) {
+        self.sensors.set(sensors);
+    }
+
+    fn select(&self, channel: usize) {
+        self.channel.set(channel);
+        self.settling.set(true);
+        self.registers
+            .pinsel
+            .write(PinSelect::PINSEL.val(self.sensors.get()[channel] as u32));
+    }
+
+    pub fn handle_interrupt(&self) {
+        let regs = &*self.registers;
+        if !regs.isr.is_set(Interrupt::SAMPLE) {
+            return;
+        }
+        regs.scr.write(Interrupt::SAMPLE::SET);
+        if !self.enabled.get() {
+            return;
+        }
+        if self.settling.get() {
+            self.settling.set(false);
+            return;
+        }
+
+        let channel = self.channel.get();
+        let count = regs.raw.read(Raw::RAWA);
+        let mut detectors = self.detectors.get();
+        let changed = detectors[channel].update(count);
+        self.detectors.set(detectors);
+
+        self.select((channel + 1) % self.channel_count());
+        changed.map(|touched| {
+            self.client.map(|client| client.touch_changed(channel, touched));
+        });
+    }
+}
+
+impl touch::TouchSense for Catb {
+    fn set_client(&self, client: &'static touch::Client) {
+        self.client.set(client);
+    }
+
+    fn channel_count(&self) -> usize {
+        let sensors = self.sensors.get().len();
+        if sensors < MAX_SENSORS {
+            sensors
+        } else {
+            MAX_SENSORS
+        }
+    }
+
+    fn enable(&self) -> ReturnCode {
+        if self.enabled.get() {
+            return ReturnCode::EALREADY;
+        }
+        if self.channel_count() == 0 {
+            return ReturnCode::ENODEVICE;
+        }
+        pm::enable_clock(Clock::PBA(PBAClock::CATB));
+        let regs = &*self.registers;
+        regs.cr.write(Control::SWRST::SET);
+        regs.cntcr.write(CounterControl::TOP.val(ACQUISITION_CYCLES));
+        self.select(0);
+        regs.scr.write(Interrupt::SAMPLE::SET);
+        regs.ier.write(Interrupt::SAMPLE::SET);
+        regs.cr
+            .write(Control::EN::SET + Control::RUN::SET + Control::CHARGET.val(0x10));
+        self.enabled.set(true);
+        ReturnCode::SUCCESS
+    }
+
+    fn disable(&self) -> ReturnCode {
+        if !self.enabled.get() {
+            return ReturnCode::EOFF;
+        }
+        let regs = &*self.registers;
+        regs.idr.write(Interrupt::SAMPLE::SET);
+        regs.cr.write(Control::EN::CLEAR);
+        pm::disable_clock(Clock::PBA(PBAClock::CATB));
+        self.enabled.set(false);
+        ReturnCode::SUCCESS
+    }
+
+    fn set_threshold(&self, channel: usize, threshold: u32) -> ReturnCode {
+        if channel >= self.channel_count() {
+            return ReturnCode::EINVAL;
+        }
+        let mut detectors = self.detectors.get();
+        detectors[channel].set_threshold(threshold);
+        self.detectors.set(detectors);
+        ReturnCode::SUCCESS
+    }
+
+    fn get_count(&self, channel: usize) -> Option<u32> {
+        if channel >= self.channel_count() {
+            return None;
+        }
+        self.detectors.get()[channel].count()
+    }
+
+    fn is_touched(&self, channel: usize) -> bool {
+        channel < self.channel_count() && self.detectors.get()[channel].is_touched()
+    }
+}
@@ -4,22 +4,30 @@ use acifc;
 use adc;
 use aes;
 use ast;
+use catb;
 use cortexm4;
 use crccu;
 use dac;
-use deferred_call_tasks::Task;
 use dma;
 use flashcalw;
 use gpio;
 use i2c;
 use kernel::common::deferred_call;
-use kernel::Chip;
+use kernel::hil::time::Alarm;
+use kernel::{Chip, ClockManager, SleepMode};
 use nvic;
 use pm;
 use spi;
+use tc;
 use trng;
 use usart;
 use usbc;
+use wdt;
+
+/// Waking from deep sleep waits for the main clock to start again, which
+/// takes around a millisecond, so the chip only deep sleeps if the AST alarm
+/// is further away than that.
+const DEEP_SLEEP_MIN_TICS: u32 = 16;
 
 pub struct Sam4l {
     pub mpu: cortexm4::mpu::MPU,
@@ -60,6 +68,8 @@ impl Sam4l {
         adc::ADC0.set_dma(&dma::DMA_CHANNELS[13]);
         dma::DMA_CHANNELS[13].initialize(&mut adc::ADC0, dma::DMAWidth::Width16Bit);
 
+        flashcalw::DEFERRED_CALL.register(&flashcalw::FLASH_CONTROLLER);
+
         Sam4l {
             mpu: cortexm4::mpu::MPU::new(),
             systick: cortexm4::systick::SysTick::new(),
@@ -70,14 +80,13 @@ impl Sam4l {
 impl Chip for Sam4l {
     type MPU = cortexm4::mpu::MPU;
     type SysTick = cortexm4::systick::SysTick;
+    type Watchdog = wdt::Wdt;
 
     fn service_pending_interrupts(&self) {
         unsafe {
             loop {
-                if let Some(task) = deferred_call::DeferredCall::next_pending() {
-                    match task {
-                        Task::Flashcalw => flashcalw::FLASH_CONTROLLER.handle_interrupt(),
-                    }
+                if let Some(client) = deferred_call::next_pending() {
+                    client.handle_deferred_call();
                 } else if let Some(interrupt) = cortexm4::nvic::next_pending() {
                     match interrupt {
                         nvic::ASTALARM => ast::AST.handle_interrupt(),
@@ -134,6 +143,14 @@ impl Chip for Sam4l {
                         nvic::ADCIFE => adc::ADC0.handle_interrupt(),
                         nvic::DACC => dac::DAC.handle_interrupt(),
                         nvic::ACIFC => acifc::ACIFC.handle_interrupt(),
+                        nvic::CATB => catb::CATB.handle_interrupt(),
+
+                        nvic::TC00 => tc::TC00.handle_interrupt(),
+                        nvic::TC01 => tc::TC01.handle_interrupt(),
+                        nvic::TC02 => tc::TC02.handle_interrupt(),
+                        nvic::TC10 => tc::TC10.handle_interrupt(),
+                        nvic::TC11 => tc::TC11.handle_interrupt(),
+                        nvic::TC12 => tc::TC12.handle_interrupt(),
 
                         nvic::TRNG => trng::TRNG.handle_interrupt(),
                         nvic::AESA => aes::AES.handle_interrupt(),
@@ -163,19 +180,32 @@ impl Chip for Sam4l {
         &self.systick
     }
 
+    fn watchdog(&self) -> &wdt::Wdt {
+        unsafe { &wdt::WDT }
+    }
+
     fn sleep(&self) {
-        if pm::deep_sleep_ready() {
-            unsafe {
+        let alarm_soon = unsafe { ast::AST.tics_until_alarm() }
+            .map_or(false, |tics| tics < DEEP_SLEEP_MIN_TICS);
+        let mode = match unsafe { pm::PM.sleep_mode() } {
+            SleepMode::DeepSleep if !alarm_soon => unsafe {
                 cortexm4::scb::set_sleepdeep();
-            }
-        } else {
-            unsafe {
+                SleepMode::DeepSleep
+            },
+            _ => unsafe {
                 cortexm4::scb::unset_sleepdeep();
-            }
-        }
+                SleepMode::Sleep
+            },
+        };
 
         unsafe {
+            let before = ast::AST.now();
             cortexm4::support::wfi();
+            let after = ast::AST.now();
+            // Interrupts are disabled, so the interrupt that woke the chip
+            // is still pending
+            let wakeup = cortexm4::nvic::lowest_pending().map(|interrupt| interrupt as usize);
+            pm::SLEEP_STATS.record(mode, before, after, wakeup);
         }
     }
 
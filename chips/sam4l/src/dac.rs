@@ -116,6 +116,9 @@ register_bitfields![u32,
 const DAC_BASE: StaticRef<DacRegisters> =
     unsafe { StaticRef::new(0x4003C000 as *const DacRegisters) };
 
+/// The DACC converts 10-bit values.
+const DAC_BITS: usize = 10;
+
 pub struct Dac {
     registers: StaticRef<DacRegisters>,
     enabled: Cell<bool>,
@@ -167,6 +170,8 @@ impl hil::dac::DacChannel for Dac {
         let regs: &DacRegisters = &*self.registers;
         if !self.enabled.get() {
             ReturnCode::EOFF
+        } else if value >= 1 << DAC_BITS {
+            ReturnCode::EINVAL
         } else {
             // Check if ready to write to CDR
             if !regs.isr.is_set(InterruptStatus::TXRDY) {
@@ -178,4 +183,8 @@ impl hil::dac::DacChannel for Dac {
             ReturnCode::SUCCESS
         }
     }
+
+    fn get_resolution_bits(&self) -> usize {
+        DAC_BITS
+    }
 }
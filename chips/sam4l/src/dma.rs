@@ -6,6 +6,8 @@ use kernel::common::cells::VolatileCell;
 use kernel::common::cells::{OptionalCell, TakeCell};
 use kernel::common::registers::{ReadOnly, ReadWrite, WriteOnly};
 use kernel::common::StaticRef;
+use kernel::hil;
+use kernel::ClockManager;
 use pm;
 
 /// Memory registers for a DMA channel. Section 16.6.1 of the datasheet.
@@ -165,6 +167,10 @@ pub enum DMAPeripheral {
     LCDCA_ABMDR_TX = 38,
 }
 
+/// A DMA channel for SAM4L peripherals, either a PDCA channel or a channel
+/// shared through a virtualizer.
+pub type Channel = hil::dma::DmaChannel<Peripheral = DMAPeripheral>;
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 #[repr(u8)]
 pub enum DMAWidth {
@@ -194,16 +200,12 @@ pub static mut DMA_CHANNELS: [DMAChannel; 16] = [
 
 pub struct DMAChannel {
     registers: StaticRef<DMARegisters>,
-    client: OptionalCell<&'static DMAClient>,
+    client: OptionalCell<&'static hil::dma::Client<DMAPeripheral>>,
     width: Cell<DMAWidth>,
     enabled: Cell<bool>,
     buffer: TakeCell<'static, [u8]>,
 }
 
-pub trait DMAClient {
-    fn transfer_done(&self, pid: DMAPeripheral);
-}
-
 impl DMAChannel {
     const fn new(channel: DMAChannelNum) -> DMAChannel {
         DMAChannel {
@@ -219,21 +221,19 @@ impl DMAChannel {
         }
     }
 
-    pub fn initialize(&self, client: &'static mut DMAClient, width: DMAWidth) {
+    pub fn initialize(&self, client: &'static hil::dma::Client<DMAPeripheral>, width: DMAWidth) {
         self.client.set(client);
         self.width.set(width);
     }
 
     pub fn enable(&self) {
-        pm::enable_clock(pm::Clock::HSB(pm::HSBClock::PDCA));
-        pm::enable_clock(pm::Clock::PBB(pm::PBBClock::PDCA));
-
         if !self.enabled.get() {
             unsafe {
+                // The first enabled channel requests the clocks of the PDCA.
                 let num_enabled = intrinsics::atomic_xadd(&mut NUM_ENABLED, 1);
-                if num_enabled == 1 {
-                    pm::enable_clock(pm::Clock::HSB(pm::HSBClock::PDCA));
-                    pm::enable_clock(pm::Clock::PBB(pm::PBBClock::PDCA));
+                if num_enabled == 0 {
+                    pm::PM.request(pm::Clock::HSB(pm::HSBClock::PDCA), 0);
+                    pm::PM.request(pm::Clock::PBB(pm::PBBClock::PDCA), 0);
                 }
             }
             let registers: &DMARegisters = &*self.registers;
@@ -251,8 +251,8 @@ impl DMAChannel {
             unsafe {
                 let num_enabled = intrinsics::atomic_xsub(&mut NUM_ENABLED, 1);
                 if num_enabled == 1 {
-                    pm::disable_clock(pm::Clock::HSB(pm::HSBClock::PDCA));
-                    pm::disable_clock(pm::Clock::PBB(pm::PBBClock::PDCA));
+                    pm::PM.release(pm::Clock::HSB(pm::HSBClock::PDCA));
+                    pm::PM.release(pm::Clock::PBB(pm::PBBClock::PDCA));
                 }
             }
             let registers: &DMARegisters = &*self.registers;
@@ -332,3 +332,70 @@ impl DMAChannel {
         registers.tcr.read(TransferCounter::TCV) as usize
     }
 }
+
+impl hil::dma::DmaChannel for DMAChannel {
+    type Peripheral = DMAPeripheral;
+
+    fn set_client(&self, client: &'static hil::dma::Client<DMAPeripheral>) {
+        self.client.set(client);
+    }
+
+    fn set_width(&self, width: hil::dma::Width) {
+        self.width.set(match width {
+            hil::dma::Width::Width8Bit => DMAWidth::Width8Bit,
+            hil::dma::Width::Width16Bit => DMAWidth::Width16Bit,
+            hil::dma::Width::Width32Bit => DMAWidth::Width32Bit,
+        });
+    }
+
+    fn enable(&self) {
+        self.enable();
+    }
+
+    fn disable(&self) {
+        self.disable();
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.is_enabled()
+    }
+
+    // The peripheral function decides the direction of PDCA transfers.
+    fn transfer_to_peripheral(&self, pid: DMAPeripheral, buf: &'static mut [u8], len: usize) {
+        self.do_transfer(pid, buf, len);
+    }
+
+    fn transfer_from_peripheral(&self, pid: DMAPeripheral, buf: &'static mut [u8], len: usize) {
+        self.do_transfer(pid, buf, len);
+    }
+
+    fn prepare_transfer_to_peripheral(
+        &self,
+        pid: DMAPeripheral,
+        buf: &'static mut [u8],
+        len: usize,
+    ) {
+        self.prepare_transfer(pid, buf, len);
+    }
+
+    fn prepare_transfer_from_peripheral(
+        &self,
+        pid: DMAPeripheral,
+        buf: &'static mut [u8],
+        len: usize,
+    ) {
+        self.prepare_transfer(pid, buf, len);
+    }
+
+    fn start_transfer(&self) {
+        self.start_transfer();
+    }
+
+    fn abort_transfer(&self) -> Option<&'static mut [u8]> {
+        self.abort_transfer()
+    }
+
+    fn transfer_counter(&self) -> usize {
+        self.transfer_counter()
+    }
+}
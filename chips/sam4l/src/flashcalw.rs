@@ -23,9 +23,8 @@
 
 use core::cell::Cell;
 use core::ops::{Index, IndexMut};
-use deferred_call_tasks::Task;
 use kernel::common::cells::{OptionalCell, TakeCell};
-use kernel::common::deferred_call::DeferredCall;
+use kernel::common::deferred_call::{DeferredCall, DeferredCallClient};
 use kernel::common::registers::{ReadOnly, ReadWrite, WriteOnly};
 use kernel::common::StaticRef;
 use kernel::hil;
@@ -318,7 +317,7 @@ enum RegKey {
     GPFRLO,
 }
 
-static DEFERRED_CALL: DeferredCall<Task> = unsafe { DeferredCall::new(Task::Flashcalw) };
+pub static DEFERRED_CALL: DeferredCall = DeferredCall::new();
 
 /// There are 18 recognized commands for the flash. These are "bare-bones"
 /// commands and values that are written to the Flash's command register to
@@ -906,6 +905,12 @@ impl FLASHCALW {
     }
 }
 
+impl DeferredCallClient for FLASHCALW {
+    fn handle_deferred_call(&self) {
+        self.handle_interrupt();
+    }
+}
+
 impl<C: hil::flash::Client<Self>> hil::flash::HasClient<'static, C> for FLASHCALW {
     fn set_client(&self, client: &'static C) {
         self.client.set(client);
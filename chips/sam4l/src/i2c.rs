@@ -10,15 +10,23 @@
 //! CHANGE THIS DRIVER, TEST RIGOROUSLY!!!
 
 use core::cell::Cell;
-use dma::{DMAChannel, DMAClient, DMAPeripheral};
+use cortexm4;
+use dma;
+use dma::DMAPeripheral;
+use gpio;
 use kernel::common::cells::{OptionalCell, TakeCell};
 use kernel::common::peripherals::{PeripheralManagement, PeripheralManager};
 use kernel::common::registers::{FieldValue, ReadOnly, ReadWrite, WriteOnly};
 use kernel::common::StaticRef;
 use kernel::hil;
 use kernel::ClockInterface;
+use kernel::ReturnCode;
 use pm;
 
+/// Busy-wait iterations of half a clock period of bus recovery, about 5 us
+/// at 48 MHz.
+const RECOVERY_HALF_PERIOD: usize = 60;
+
 // Listing of all registers related to the TWIM peripheral.
 // Section 27.9 of the datasheet
 #[repr(C)]
@@ -546,13 +554,21 @@ pub struct I2CHw {
     slave_mmio_address: Option<StaticRef<TWISRegisters>>,
     master_clock: TWIMClock,
     slave_clock: TWISClock,
-    dma: OptionalCell<&'static DMAChannel>,
+    dma: OptionalCell<&'static dma::Channel>,
     dma_pids: (DMAPeripheral, DMAPeripheral),
     master_client: Cell<Option<&'static hil::i2c::I2CHwMasterClient>>,
     slave_client: Cell<Option<&'static hil::i2c::I2CHwSlaveClient>>,
     on_deck: Cell<Option<(DMAPeripheral, usize)>>,
+    /// SCL, SDA and their peripheral function, to detect and recover a
+    /// stuck bus
+    bus_pins: OptionalCell<(
+        &'static gpio::GPIOPin,
+        &'static gpio::GPIOPin,
+        gpio::PeripheralFunction,
+    )>,
 
     slave_enabled: Cell<bool>,
+    slave_clock_stretching: Cell<bool>,
     my_slave_address: Cell<u8>,
     slave_read_buffer: TakeCell<'static, [u8]>,
     slave_read_buffer_len: Cell<u8>,
@@ -680,8 +696,10 @@ impl I2CHw {
             master_client: Cell::new(None),
             slave_client: Cell::new(None),
             on_deck: Cell::new(None),
+            bus_pins: OptionalCell::empty(),
 
             slave_enabled: Cell::new(false),
+            slave_clock_stretching: Cell::new(true),
             my_slave_address: Cell::new(0),
             slave_read_buffer: TakeCell::empty(),
             slave_read_buffer_len: Cell::new(0),
@@ -724,10 +742,28 @@ impl I2CHw {
         )
     }
 
-    pub fn set_dma(&self, dma: &'static DMAChannel) {
+    pub fn set_dma(&self, dma: &'static dma::Channel) {
         self.dma.set(dma);
     }
 
+    /// Tells the driver which pins are SCL and SDA, and which peripheral
+    /// function connects them to this TWIM, so that it can report and
+    /// recover a bus that is held low.
+    pub fn set_bus_pins(
+        &self,
+        scl: &'static gpio::GPIOPin,
+        sda: &'static gpio::GPIOPin,
+        function: gpio::PeripheralFunction,
+    ) {
+        self.bus_pins.set((scl, sda, function));
+    }
+
+    /// Whether a slave holds SCL or SDA low.
+    fn bus_stuck(&self) -> bool {
+        self.bus_pins
+            .map_or(false, |&mut (scl, sda, _)| !scl.read() || !sda.read())
+    }
+
     pub fn set_master_client(&self, client: &'static hil::i2c::I2CHwMasterClient) {
         self.master_client.set(Some(client));
     }
@@ -764,7 +800,12 @@ impl I2CHw {
         } else if old_status.is_set(Status::DNAK) {
             Some(Error::DataNak)
         } else if old_status.is_set(Status::ARBLST) {
-            Some(Error::ArbitrationLost)
+            // A slave holding SDA low makes every transfer lose arbitration
+            if self.bus_stuck() {
+                Some(Error::BusStuck)
+            } else {
+                Some(Error::ArbitrationLost)
+            }
         } else if old_status.is_set(Status::CCOMP) {
             Some(Error::CommandComplete)
         } else {
@@ -856,7 +897,7 @@ impl I2CHw {
                     }
                     self.dma.map(|dma| {
                         let buf = dma.abort_transfer().unwrap();
-                        dma.prepare_transfer(dma_periph, buf, len);
+                        dma.prepare_transfer_from_peripheral(dma_periph, buf, len);
                         dma.start_transfer();
                     });
                 }
@@ -932,7 +973,7 @@ impl I2CHw {
         let twim = &TWIMRegisterManager::new(&self);
         self.dma.map(move |dma| {
             dma.enable();
-            dma.prepare_transfer(self.dma_pids.1, data, len as usize);
+            dma.prepare_transfer_to_peripheral(self.dma_pids.1, data, len as usize);
             self.setup_transfer(twim, chip, flags, Command::READ::Transmit, len);
             self.master_enable(twim);
             dma.start_transfer();
@@ -949,7 +990,7 @@ impl I2CHw {
         let twim = &TWIMRegisterManager::new(&self);
         self.dma.map(move |dma| {
             dma.enable();
-            dma.prepare_transfer(self.dma_pids.0, data, len as usize);
+            dma.prepare_transfer_from_peripheral(self.dma_pids.0, data, len as usize);
             self.setup_transfer(twim, chip, flags, Command::READ::Receive, len);
             self.master_enable(twim);
             dma.start_transfer();
@@ -960,7 +1001,7 @@ impl I2CHw {
         let twim = &TWIMRegisterManager::new(&self);
         self.dma.map(move |dma| {
             dma.enable();
-            dma.prepare_transfer(self.dma_pids.1, data, split as usize);
+            dma.prepare_transfer_to_peripheral(self.dma_pids.1, data, split as usize);
             self.setup_transfer(
                 twim,
                 chip,
@@ -1020,6 +1061,14 @@ impl I2CHw {
             if interrupts.is_set(StatusSlave::SAM) {
                 twis.registers.nbytes.write(Nbytes::NBYTES.val(0));
 
+                self.slave_client.get().map(|client| {
+                    client.address_matched(if status.is_set(StatusSlave::TRA) {
+                        hil::i2c::SlaveTransmissionType::Read
+                    } else {
+                        hil::i2c::SlaveTransmissionType::Write
+                    });
+                });
+
                 // Did we get a read or a write?
                 if status.is_set(StatusSlave::TRA) {
                     // This means the slave is in transmit mode, AKA we got a
@@ -1283,11 +1332,16 @@ impl I2CHw {
         if self.slave_mmio_address.is_some() {
             let twis = &TWISRegisterManager::new(&self);
 
+            let stretch = if self.slave_clock_stretching.get() {
+                ControlSlave::SOAM::Stretch + ControlSlave::STREN::Enable
+            } else {
+                ControlSlave::SOAM::NoStretch + ControlSlave::STREN::Disable
+            };
+
             // Enable and configure
             let control = ControlSlave::ADR.val((self.my_slave_address.get() as u32) & 0x7F)
-                + ControlSlave::SOAM::Stretch
+                + stretch
                 + ControlSlave::CUP::CountUp
-                + ControlSlave::STREN::Enable
                 + ControlSlave::SMATCH::AckSlaveAddress;
             twis.registers.cr.write(control);
 
@@ -1297,7 +1351,7 @@ impl I2CHw {
     }
 }
 
-impl DMAClient for I2CHw {
+impl hil::dma::Client<DMAPeripheral> for I2CHw {
     fn transfer_done(&self, _pid: DMAPeripheral) {}
 }
 
@@ -1358,6 +1412,33 @@ impl hil::i2c::I2CMaster for I2CHw {
     fn write_read(&self, addr: u8, data: &'static mut [u8], write_len: u8, read_len: u8) {
         I2CHw::write_read(self, addr, data, write_len, read_len)
     }
+
+    fn recover_bus(&self) -> ReturnCode {
+        let (scl, sda, function) = match self.bus_pins.map(|pins| *pins) {
+            Some(pins) => pins,
+            None => return ReturnCode::ENOSUPPORT,
+        };
+
+        {
+            let twim = &TWIMRegisterManager::new(&self);
+            twim.registers.cr.write(Control::MDIS::SET);
+        }
+
+        // Take the pins from the TWIM while driving them
+        let recovered = hil::i2c::recover_bus_with_gpio(scl, sda, &|| {
+            for _ in 0..RECOVERY_HALF_PERIOD {
+                cortexm4::support::nop();
+            }
+        });
+        scl.select_peripheral(function);
+        sda.select_peripheral(function);
+
+        if recovered {
+            ReturnCode::SUCCESS
+        } else {
+            ReturnCode::FAIL
+        }
+    }
 }
 
 impl hil::i2c::I2CSlave for I2CHw {
@@ -1422,6 +1503,11 @@ impl hil::i2c::I2CSlave for I2CHw {
     fn listen(&self) {
         self.slave_listen();
     }
+
+    fn set_clock_stretching(&self, stretch: bool) -> ReturnCode {
+        self.slave_clock_stretching.set(stretch);
+        ReturnCode::SUCCESS
+    }
 }
 
 impl hil::i2c::I2CMasterSlave for I2CHw {}
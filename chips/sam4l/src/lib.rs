@@ -4,14 +4,7 @@
 
 #![crate_name = "sam4l"]
 #![crate_type = "rlib"]
-#![feature(
-    asm,
-    concat_idents,
-    const_fn,
-    core_intrinsics,
-    try_from,
-    used
-)]
+#![feature(asm, concat_idents, const_fn, core_intrinsics, used)]
 #![feature(in_band_lifetimes, tool_attributes)]
 #![no_std]
 
@@ -26,14 +19,13 @@ extern crate cortexm4;
 )]
 extern crate kernel;
 
-mod deferred_call_tasks;
-
 pub mod acifc;
 pub mod adc;
 pub mod aes;
 pub mod ast;
 pub mod bpm;
 pub mod bscif;
+pub mod catb;
 pub mod chip;
 pub mod crccu;
 pub mod dac;
@@ -42,9 +34,11 @@ pub mod flashcalw;
 pub mod gpio;
 pub mod i2c;
 pub mod nvic;
+pub mod pevc;
 pub mod pm;
 pub mod scif;
 pub mod spi;
+pub mod tc;
 pub mod trng;
 pub mod usart;
 pub mod usbc;
@@ -106,9 +100,22 @@ pub static BASE_VECTORS: [unsafe extern "C" fn(); 16] = [
     systick_handler,     // SysTick
 ];
 
+/// The interrupt vectors, with the watchdog handled outside of the kernel
+/// loop.
+#[repr(C)]
+pub struct Irqs {
+    before_wdt: [unsafe extern "C" fn(); 44],
+    wdt: unsafe extern "C" fn(),
+    after_wdt: [unsafe extern "C" fn(); 35],
+}
+
 #[link_section = ".vectors"]
 #[used] // Ensures that the symbol is kept until the final binary
-pub static IRQS: [unsafe extern "C" fn(); 80] = [generic_isr; 80];
+pub static IRQS: Irqs = Irqs {
+    before_wdt: [generic_isr; 44],
+    wdt: wdt::wdt_handler,
+    after_wdt: [generic_isr; 35],
+};
 
 pub unsafe fn init() {
     // Relocate data segment.
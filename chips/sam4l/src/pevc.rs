@@ -0,0 +1,89 @@
+//! Implementation of the SAM4L Peripheral Event Controller (PEVC).
+//!
+//! See datasheet section "31. Peripheral Event Controller (PEVC)".
+//!
+//! The PEVC routes events from a generator peripheral to a user peripheral
+//! without CPU involvement. Each user has one channel, whose multiplexer
+//! selects the generator driving it. For example, an analog comparator
+//! toggling its output can trigger an ADC conversion.
+
+use kernel::common::registers::{ReadOnly, ReadWrite, WriteOnly};
+use kernel::common::StaticRef;
+use pm;
+
+/// Number of channels, one per event user
+const NUM_CHANNELS: usize = 19;
+
+#[repr(C)]
+struct PevcRegisters {
+    chsr: ReadOnly<u32>,
+    cher: WriteOnly<u32>,
+    chdr: WriteOnly<u32>,
+    _reserved0: [u32; 61],
+    chmx: [ReadWrite<u32, ChannelMultiplexer::Register>; NUM_CHANNELS],
+}
+
+register_bitfields![u32,
+    ChannelMultiplexer [
+        /// Software Event Multiplexer: if one, the channel is driven by
+        /// software events instead of EVMX
+        SMX OFFSET(8) NUMBITS(1) [],
+        /// Event Multiplexer: the generator driving the channel
+        EVMX OFFSET(0) NUMBITS(6) []
+    ]
+];
+
+const BASE_ADDRESS: StaticRef<PevcRegisters> =
+    unsafe { StaticRef::new(0x400A6000 as *const PevcRegisters) };
+
+/// Event generators used by the drivers of this chip
+#[derive(Copy, Clone, Debug)]
+pub enum Generator {
+    AcifcAc0 = 36,
+    AcifcAc1 = 37,
+    AcifcAc2 = 38,
+    AcifcAc3 = 39,
+}
+
+/// Event users used by the drivers of this chip. The value is the channel of
+/// the user.
+#[derive(Copy, Clone, Debug)]
+pub enum User {
+    /// Triggers one ADCIFE conversion
+    Adcife = 2,
+}
+
+pub struct Pevc {
+    registers: StaticRef<PevcRegisters>,
+}
+
+pub static mut PEVC: Pevc = Pevc::new();
+
+impl Pevc {
+    const fn new() -> Pevc {
+        Pevc {
+            registers: BASE_ADDRESS,
+        }
+    }
+
+    /// Route the events of `generator` to `user`.
+    pub fn connect(&self, generator: Generator, user: User) {
+        let regs: &PevcRegisters = &*self.registers;
+        pm::enable_clock(pm::Clock::PBB(pm::PBBClock::PEVC));
+
+        let channel = user as usize;
+        regs.chdr.set(1 << channel);
+        regs.chmx[channel].write(ChannelMultiplexer::EVMX.val(generator as u32));
+        regs.cher.set(1 << channel);
+    }
+
+    /// Stop routing events to `user`. The clock is turned off once no channel
+    /// is enabled anymore.
+    pub fn disconnect(&self, user: User) {
+        let regs: &PevcRegisters = &*self.registers;
+        regs.chdr.set(1 << (user as usize));
+        if regs.chsr.get() == 0 {
+            pm::disable_clock(pm::Clock::PBB(pm::PBBClock::PEVC));
+        }
+    }
+}
@@ -1,5 +1,6 @@
 //! Implementation of the power manager (PM) peripheral.
 
+use acifc;
 use bpm;
 use bscif;
 use core::cell::Cell;
@@ -8,7 +9,7 @@ use flashcalw;
 use gpio;
 use kernel::common::registers::{FieldValue, ReadOnly, ReadWrite, WriteOnly};
 use kernel::common::StaticRef;
-use kernel::ClockInterface;
+use kernel::{ClockInterface, ClockManager, ReturnCode, SleepMode, SleepStats};
 use scif;
 
 /// §10.7 PM::UserInterface from SAM4L Datasheet.
@@ -548,6 +549,7 @@ const PBA_MASK_OFFSET: u32 = 0x28;
 const PBB_MASK_OFFSET: u32 = 0x2C;
 const PBC_MASK_OFFSET: u32 = 0x30;
 const PBD_MASK_OFFSET: u32 = 0x34;
+const PPCR_OFFSET: u32 = 0x160;
 
 const PM_BASE: usize = 0x400E0000;
 const PM_REGS: StaticRef<PmRegisters> = unsafe { StaticRef::new(PM_BASE as *const PmRegisters) };
@@ -575,6 +577,10 @@ pub static mut PM: PowerManager = PowerManager {
     system_initial_configs: Cell::new(false),
 };
 
+/// Statistics of the sleeps of the chip, timed by the AST, which counts at
+/// 16 kHz in every sleep mode.
+pub static mut SLEEP_STATS: SleepStats = SleepStats::new(16_000, 32);
+
 impl PowerManager {
     /// Sets up the system clock. This should be called as one of the first
     /// lines in the `reset_handler` within the platform's `main.rs`.
@@ -988,6 +994,38 @@ unsafe fn configure_1mhz_rc() {
     PM.system_on_clocks.set(clock_mask | ClockMask::RC1M as u32);
 }
 
+/// The clock mask registers hold the requested clocks, so a clock is gated as
+/// soon as it is released. Since the system clock is not changed at runtime,
+/// requests for a faster clock than the board set up fail. Peripherals that
+/// share a clock, like the PDCA channels, count their own users.
+impl ClockManager for PowerManager {
+    type Clock = Clock;
+
+    fn request(&self, clock: Clock, min_frequency: u32) -> ReturnCode {
+        if get_system_frequency() < min_frequency {
+            return ReturnCode::ENOSUPPORT;
+        }
+        enable_clock(clock);
+        ReturnCode::SUCCESS
+    }
+
+    fn release(&self, clock: Clock) {
+        disable_clock(clock);
+    }
+
+    fn is_requested(&self, clock: Clock) -> bool {
+        is_clock_enabled(clock)
+    }
+
+    fn sleep_mode(&self) -> SleepMode {
+        if deep_sleep_ready() {
+            SleepMode::DeepSleep
+        } else {
+            SleepMode::Sleep
+        }
+    }
+}
+
 pub fn get_system_frequency() -> u32 {
     // Return the current system frequency
     unsafe {
@@ -1055,7 +1093,9 @@ macro_rules! get_clock {
 ///
 ///   * HSB may only have clocks for the flash (and PicoCache), APBx bridges, and PDCA on.
 ///
-///   * PBA may only have I2C Slaves on as they can self-wake.
+///   * PBA may only have I2C Slaves on as they can self-wake, and the ACIFC
+///     if any analog comparator is enabled in sleep (see
+///     `set_acifc_clock_request`).
 ///
 ///   * PBB may only have clocks for the flash, HRAMC1 (also flash related), and PDCA on.
 ///
@@ -1094,13 +1134,36 @@ pub fn deep_sleep_ready() -> bool {
         /* added by us */ ClockMaskPbb::HRAMC1::SET +
         /* added by us */ ClockMaskPbb::PDCA::SET;
 
+    let mut pba_allowed = deep_sleep_pbamask.mask();
+    if acifc::SLEEPWALKING.load(Ordering::Relaxed) > 0 {
+        pba_allowed |= ClockMaskPba::ACIFC::SET.mask();
+    }
+
     let hsb = PM_REGS.hsbmask.get() & !deep_sleep_hsbmask.mask() == 0;
-    let pba = PM_REGS.pbamask.get() & !deep_sleep_pbamask.mask() == 0;
+    let pba = PM_REGS.pbamask.get() & !pba_allowed == 0;
     let pbb = PM_REGS.pbbmask.get() & !deep_sleep_pbbmask.mask() == 0;
     let gpio = gpio::INTERRUPT_COUNT.load(Ordering::Relaxed) == 0;
     hsb && pba && pbb && gpio
 }
 
+/// Allows or prevents the ACIFC from requesting its clock while the chip is in
+/// deep sleep. With the request enabled, the analog comparators keep
+/// comparing in deep sleep and their interrupts wake the chip up
+/// (sleepwalking).
+pub fn set_acifc_clock_request(enable: bool) {
+    let ppcr = PM_REGS.ppcr.extract();
+    unlock(PPCR_OFFSET);
+    if enable {
+        PM_REGS
+            .ppcr
+            .modify_no_read(ppcr, PeripheralPowerControl::ACIFCRCMASK::SET);
+    } else {
+        PM_REGS
+            .ppcr
+            .modify_no_read(ppcr, PeripheralPowerControl::ACIFCRCMASK::CLEAR);
+    }
+}
+
 impl ClockInterface for Clock {
     fn is_enabled(&self) -> bool {
         match self {
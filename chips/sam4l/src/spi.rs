@@ -8,13 +8,13 @@
 
 use core::cell::Cell;
 use core::cmp;
-use dma::DMAChannel;
-use dma::DMAClient;
+use dma;
 use dma::DMAPeripheral;
 use kernel::common::cells::OptionalCell;
 use kernel::common::peripherals::{PeripheralManagement, PeripheralManager};
 use kernel::common::registers::{self, ReadOnly, ReadWrite, WriteOnly};
 use kernel::common::StaticRef;
+use kernel::hil;
 use kernel::hil::spi;
 use kernel::hil::spi::ClockPhase;
 use kernel::hil::spi::ClockPolarity;
@@ -182,8 +182,8 @@ pub enum SpiRole {
 /// Abstraction of the SPI Hardware
 pub struct SpiHw {
     client: OptionalCell<&'static SpiMasterClient>,
-    dma_read: OptionalCell<&'static DMAChannel>,
-    dma_write: OptionalCell<&'static DMAChannel>,
+    dma_read: OptionalCell<&'static dma::Channel>,
+    dma_write: OptionalCell<&'static dma::Channel>,
     // keep track of which how many DMA transfers are pending to correctly
     // issue completion event only after both complete.
     transfers_in_progress: Cell<u8>,
@@ -417,7 +417,7 @@ impl SpiHw {
     }
 
     /// Set the DMA channels used for reading and writing.
-    pub fn set_dma(&mut self, read: &'static DMAChannel, write: &'static DMAChannel) {
+    pub fn set_dma(&mut self, read: &'static dma::Channel, write: &'static dma::Channel) {
         self.dma_read.set(read);
         self.dma_write.set(write);
     }
@@ -486,7 +486,7 @@ impl SpiHw {
                 .set(self.transfers_in_progress.get() + 1);
             self.dma_read.map(move |read| {
                 read.enable();
-                read.do_transfer(DMAPeripheral::SPI_RX, rbuf, count);
+                read.transfer_from_peripheral(DMAPeripheral::SPI_RX, rbuf, count);
             });
         });
 
@@ -498,7 +498,7 @@ impl SpiHw {
                 .set(self.transfers_in_progress.get() + 1);
             self.dma_write.map(move |write| {
                 write.enable();
-                write.do_transfer(DMAPeripheral::SPI_TX, wbuf, count);
+                write.transfer_to_peripheral(DMAPeripheral::SPI_TX, wbuf, count);
             });
         });
 
@@ -679,7 +679,7 @@ impl spi::SpiSlave for SpiHw {
     }
 }
 
-impl DMAClient for SpiHw {
+impl hil::dma::Client<DMAPeripheral> for SpiHw {
     fn transfer_done(&self, _pid: DMAPeripheral) {
         // Only callback that the transfer is done if either:
         // 1) The transfer was TX only and TX finished
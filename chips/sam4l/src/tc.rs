@@ -0,0 +1,352 @@
+//! Implementation of PWM on the SAM4L Timer/Counters.
+//!
+//! The two TC modules have three 16-bit channels each, and each channel
+//! generates PWM on its two outputs, TIOA and TIOB, at a period set by its RC
+//! register. So each channel is a `Pwm` group of two channels: 0 on TIOA with
+//! its duty cycle in RA, and 1 on TIOB with its duty cycle in RB. TIOB can be
+//! the complement of TIOA, without dead time. The counter runs from the PBA
+//! clock divided by 2, 8, 32 or 128, the smallest divider the period fits
+//! with.
+//!
+//! The RA and RB registers update the outputs as soon as they are written.
+//! So that both duty cycles change in the same period, the driver writes
+//! them in the interrupt at the RC compare, which starts a period. This
+//! assumes the interrupt is handled before the counter reaches the new
+//! compare values, so very short duty cycles can be late by a period.
+//!
+//! The pins are configured by the board, for example PA08 (TIOA0) and PA09
+//! (TIOB0) of TC0 channel 0 in peripheral function B.
+
+use core::cell::Cell;
+use core::intrinsics;
+use kernel::common::registers::{ReadOnly, ReadWrite, WriteOnly};
+use kernel::common::StaticRef;
+use kernel::hil::pwm;
+use kernel::ReturnCode;
+use pm::{self, Clock, PBAClock};
+
+#[repr(C)]
+struct TcChannelRegisters {
+    ccr: WriteOnly<u32, ChannelControl::Register>, //   Channel Control          (0x00)
+    cmr: ReadWrite<u32, WaveformMode::Register>, //     Channel Mode             (0x04)
+    smmr: ReadWrite<u32>, //                            Stepper Motor Mode       (0x08)
+    _reserved0: u32, //                                                          (0x0c)
+    cv: ReadOnly<u32>, //                               Counter Value            (0x10)
+    ra: ReadWrite<u32>, //                              Register A               (0x14)
+    rb: ReadWrite<u32>, //                              Register B               (0x18)
+    rc: ReadWrite<u32>, //                              Register C               (0x1c)
+    sr: ReadOnly<u32, Status::Register>, //             Status                   (0x20)
+    ier: WriteOnly<u32, Interrupt::Register>, //        Interrupt Enable         (0x24)
+    idr: WriteOnly<u32, Interrupt::Register>, //        Interrupt Disable        (0x28)
+    imr: ReadOnly<u32, Interrupt::Register>, //         Interrupt Mask           (0x2c)
+    _reserved1: [u32; 4], //                                                     (0x30 - 0x3c)
+}
+
+register_bitfields![u32,
+    ChannelControl [
+        /// Software trigger
+        SWTRG 2,
+        /// Counter clock disable
+        CLKDIS 1,
+        /// Counter clock enable
+        CLKEN 0
+    ],
+
+    /// Channel mode in waveform mode
+    WaveformMode [
+        /// Software trigger effect on TIOB
+        BSWTRG OFFSET(30) NUMBITS(2) [],
+        /// RC compare effect on TIOB
+        BCPC OFFSET(26) NUMBITS(2) [],
+        /// RB compare effect on TIOB
+        BCPB OFFSET(24) NUMBITS(2) [],
+        /// Software trigger effect on TIOA
+        ASWTRG OFFSET(22) NUMBITS(2) [],
+        /// RC compare effect on TIOA
+        ACPC OFFSET(18) NUMBITS(2) [],
+        /// RA compare effect on TIOA
+        ACPA OFFSET(16) NUMBITS(2) [],
+        /// Waveform mode
+        WAVE OFFSET(15) NUMBITS(1) [],
+        /// Waveform selection
+        WAVSEL OFFSET(13) NUMBITS(2) [
+            Up = 0,
+            UpDown = 1,
+            UpRc = 2,
+            UpDownRc = 3
+        ],
+        /// External event selection. Selecting anything but TIOB lets TIOB
+        /// be an output.
+        EEVT OFFSET(10) NUMBITS(2) [
+            Tiob = 0,
+            Xc0 = 1,
+            Xc1 = 2,
+            Xc2 = 3
+        ],
+        /// Clock selection
+        TCCLKS OFFSET(0) NUMBITS(3) []
+    ],
+
+    Status [
+        /// Clock enabled
+        CLKSTA 16,
+        /// RC compare
+        CPCS 4
+    ],
+
+    Interrupt [
+        /// RC compare
+        CPCS 4
+    ]
+];
+
+/// The output actions of the compares and the software trigger
+const ACTION_SET: u32 = 1;
+const ACTION_CLEAR: u32 = 2;
+
+/// The PBA clock dividers of TIMER_CLOCK2 to TIMER_CLOCK5
+const DIVIDERS: [(u32, usize); 4] = [(1, 2), (2, 8), (3, 32), (4, 128)];
+
+const MIN_PERIOD: usize = 2;
+const MAX_PERIOD: usize = 1 << 16;
+
+/// Duty cycles are scaled from this to the period
+const MAX_DUTY_CYCLE: usize = 0xFFFF;
+
+const TC0_BASE: usize = 0x40010000;
+const TC1_BASE: usize = 0x40014000;
+
+const fn channel_base(module: usize, channel: usize) -> StaticRef<TcChannelRegisters> {
+    unsafe { StaticRef::new((module + 0x40 * channel) as *const TcChannelRegisters) }
+}
+
+/// The number of running channels in each module, which need its clock
+static mut NUM_RUNNING: [usize; 2] = [0, 0];
+
+pub static mut TC00: Tc = Tc::new(channel_base(TC0_BASE, 0), 0);
+pub static mut TC01: Tc = Tc::new(channel_base(TC0_BASE, 1), 0);
+pub static mut TC02: Tc = Tc::new(channel_base(TC0_BASE, 2), 0);
+pub static mut TC10: Tc = Tc::new(channel_base(TC1_BASE, 0), 1);
+pub static mut TC11: Tc = Tc::new(channel_base(TC1_BASE, 1), 1);
+pub static mut TC12: Tc = Tc::new(channel_base(TC1_BASE, 2), 1);
+
+pub struct Tc {
+    registers: StaticRef<TcChannelRegisters>,
+    module: usize,
+    running: Cell<bool>,
+    tcclks: Cell<u32>,
+    /// Counter ticks per period
+    period: Cell<usize>,
+    duty_cycles: Cell<[usize; 2]>,
+    complementary: Cell<bool>,
+    /// Whether the duty cycles changed since they were last written
+    update_pending: Cell<bool>,
+}
+
+impl Tc {
+    const fn new(registers: StaticRef<TcChannelRegisters>, module: usize) -> Tc {
+        Tc {
+            registers: registers,
+            module: module,
+            running: Cell::new(false),
+            tcclks: Cell::new(0),
+            period: Cell::new(0),
+            duty_cycles: Cell::new([0; 2]),
+            complementary: Cell::new(false),
+            update_pending: Cell::new(false),
+        }
+    }
+
+    fn clock(&self) -> Clock {
+        if self.module == 0 {
+            Clock::PBA(PBAClock::TC0)
+        } else {
+            Clock::PBA(PBAClock::TC1)
+        }
+    }
+
+    pub fn handle_interrupt(&self) {
+        let regs = &*self.registers;
+        // Reading the status clears it
+        if regs.sr.is_set(Status::CPCS) && self.update_pending.get() {
+            self.write_outputs();
+        }
+        regs.idr.write(Interrupt::CPCS::SET);
+    }
+
+    /// The compare value and the output level at the start of the period,
+    /// for an output that is high for `duty_cycle` from the start of the
+    /// period, or low if `inverted`. Other outputs change at the compare
+    /// value.
+    fn output(&self, duty_cycle: usize, inverted: bool) -> (u32, u32) {
+        let compare = duty_cycle as u64 * self.period.get() as u64 / MAX_DUTY_CYCLE as u64;
+        let compare = compare as u32;
+        // At 0 the compare would still follow the start by one tick
+        let high = (compare != 0) != inverted;
+        (compare, if high { ACTION_SET } else { ACTION_CLEAR })
+    }
+
+    /// Writes the compare values and output actions for the duty cycles.
+    /// The software trigger starts the outputs at their level at the start
+    /// of the period.
+    fn write_outputs(&self) {
+        let regs = &*self.registers;
+        let duty_cycles = self.duty_cycles.get();
+        let complementary = self.complementary.get();
+
+        let (ra, a_start) = self.output(duty_cycles[0], false);
+        let (rb, b_start) = if complementary {
+            self.output(duty_cycles[0], true)
+        } else {
+            self.output(duty_cycles[1], false)
+        };
+
+        // A complementary output starts the period low and goes high at its
+        // compare value
+        regs.cmr.write(
+            WaveformMode::WAVE::SET
+                + WaveformMode::WAVSEL::UpRc
+                + WaveformMode::EEVT::Xc0
+                + WaveformMode::TCCLKS.val(self.tcclks.get())
+                + WaveformMode::ASWTRG.val(a_start)
+                + WaveformMode::ACPC.val(a_start)
+                + WaveformMode::ACPA.val(ACTION_CLEAR)
+                + WaveformMode::BSWTRG.val(b_start)
+                + WaveformMode::BCPC.val(b_start)
+                + WaveformMode::BCPB.val(if complementary {
+                    ACTION_SET
+                } else {
+                    ACTION_CLEAR
+                }),
+        );
+        regs.ra.set(ra);
+        regs.rb.set(rb);
+        self.update_pending.set(false);
+    }
+}
+
+impl pwm::Pwm for Tc {
+    fn channel_count(&self) -> usize {
+        2
+    }
+
+    fn get_maximum_frequency_hz(&self) -> usize {
+        pm::get_system_frequency() as usize / DIVIDERS[0].1 / MIN_PERIOD
+    }
+
+    fn get_maximum_duty_cycle(&self) -> usize {
+        MAX_DUTY_CYCLE
+    }
+
+    fn start(&self, frequency_hz: usize) -> ReturnCode {
+        if frequency_hz == 0 {
+            return ReturnCode::EINVAL;
+        }
+        let clock_hz = pm::get_system_frequency() as usize;
+        let setting = DIVIDERS
+            .iter()
+            .map(|&(tcclks, divider)| (tcclks, divider, clock_hz / divider / frequency_hz))
+            .find(|&(_, _, period)| period <= MAX_PERIOD);
+        let (tcclks, divider, period) = match setting {
+            Some((tcclks, divider, period)) if period >= MIN_PERIOD => (tcclks, divider, period),
+            _ => return ReturnCode::EINVAL,
+        };
+
+        let regs = &*self.registers;
+        if !self.running.get() {
+            unsafe {
+                // The first running channel of a module requests its clock.
+                let num_running = intrinsics::atomic_xadd(&mut NUM_RUNNING[self.module], 1);
+                if num_running == 0 {
+                    pm::enable_clock(self.clock());
+                }
+            }
+            self.running.set(true);
+        }
+
+        self.tcclks.set(tcclks);
+        self.period.set(period);
+        self.duty_cycles.set([0; 2]);
+        regs.idr.write(Interrupt::CPCS::SET);
+        self.write_outputs();
+        // The counter resets after RC
+        regs.rc.set(period as u32 - 1);
+        regs.ccr
+            .write(ChannelControl::CLKEN::SET + ChannelControl::SWTRG::SET);
+
+        ReturnCode::SuccessWithValue {
+            value: clock_hz / divider / period,
+        }
+    }
+
+    fn stop(&self) -> ReturnCode {
+        if !self.running.get() {
+            return ReturnCode::EOFF;
+        }
+        let regs = &*self.registers;
+        regs.idr.write(Interrupt::CPCS::SET);
+        self.update_pending.set(false);
+
+        // Drive both outputs low before stopping the counter
+        regs.cmr.write(
+            WaveformMode::WAVE::SET
+                + WaveformMode::EEVT::Xc0
+                + WaveformMode::ASWTRG.val(ACTION_CLEAR)
+                + WaveformMode::BSWTRG.val(ACTION_CLEAR),
+        );
+        regs.ccr.write(ChannelControl::SWTRG::SET);
+        regs.ccr.write(ChannelControl::CLKDIS::SET);
+
+        unsafe {
+            let num_running = intrinsics::atomic_xsub(&mut NUM_RUNNING[self.module], 1);
+            if num_running == 1 {
+                pm::disable_clock(self.clock());
+            }
+        }
+        self.running.set(false);
+        ReturnCode::SUCCESS
+    }
+
+    fn set_duty_cycles(&self, duty_cycles: &[Option<usize>]) -> ReturnCode {
+        if !self.running.get() {
+            return ReturnCode::EOFF;
+        }
+        let mut new = self.duty_cycles.get();
+        for (channel, duty_cycle) in duty_cycles.iter().enumerate() {
+            if let Some(duty_cycle) = *duty_cycle {
+                if channel >= 2
+                    || (channel == 1 && self.complementary.get())
+                    || duty_cycle > MAX_DUTY_CYCLE
+                {
+                    return ReturnCode::EINVAL;
+                }
+                new[channel] = duty_cycle;
+            }
+        }
+        self.duty_cycles.set(new);
+
+        // The compare values are written at the start of the next period
+        self.update_pending.set(true);
+        let regs = &*self.registers;
+        regs.sr.get();
+        regs.ier.write(Interrupt::CPCS::SET);
+        ReturnCode::SUCCESS
+    }
+
+    fn set_complementary(&self, channel: usize, complementary: bool) -> ReturnCode {
+        if channel != 1 {
+            return ReturnCode::EINVAL;
+        }
+        self.complementary.set(complementary);
+        let mut duty_cycles = self.duty_cycles.get();
+        duty_cycles[1] = 0;
+        self.duty_cycles.set(duty_cycles);
+        if self.running.get() {
+            self.update_pending.set(true);
+            let regs = &*self.registers;
+            regs.sr.get();
+            regs.ier.write(Interrupt::CPCS::SET);
+        }
+        ReturnCode::SUCCESS
+    }
+}
@@ -283,8 +283,8 @@ const USART_BASE_ADDRS: [StaticRef<UsartRegisters>; 4] = unsafe {
 pub struct USARTRegManager<'a> {
     registers: &'a UsartRegisters,
     clock: pm::Clock,
-    rx_dma: Option<&'static dma::DMAChannel>,
-    tx_dma: Option<&'static dma::DMAChannel>,
+    rx_dma: Option<&'static dma::Channel>,
+    tx_dma: Option<&'static dma::Channel>,
 }
 
 static IS_PANICING: AtomicBool = AtomicBool::new(false);
@@ -330,10 +330,9 @@ impl Drop for USARTRegManager<'a> {
         let rx_active = self.rx_dma.map_or(false, |rx_dma| rx_dma.is_enabled());
         let tx_active = self.tx_dma.map_or(false, |tx_dma| tx_dma.is_enabled());
 
-        // Special-case panic here as panic does not actually use the
-        // USART driver code in this file, rather it writes the registers
-        // directly and we can't safely reason about what the custom panic
-        // USART driver is doing / expects.
+        // Special-case panic here as the panic writer polls the USART
+        // through `emergency_write` without interrupts or DMA, so nothing
+        // above tells that it is still in use.
         let is_panic = IS_PANICING.load(Ordering::Relaxed);
         if !(rx_active || tx_active || ints_active || is_panic) {
             pm::disable_clock(self.clock);
@@ -378,16 +377,17 @@ pub struct USART {
     usart_tx_state: Cell<USARTStateTX>,
     usart_rx_state: Cell<USARTStateRX>,
 
-    rx_dma: Cell<Option<&'static dma::DMAChannel>>,
+    rx_dma: Cell<Option<&'static dma::Channel>>,
     rx_dma_peripheral: dma::DMAPeripheral,
     rx_len: Cell<usize>,
-    tx_dma: Cell<Option<&'static dma::DMAChannel>>,
+    tx_dma: Cell<Option<&'static dma::Channel>>,
     tx_dma_peripheral: dma::DMAPeripheral,
     tx_len: Cell<usize>,
 
     client: OptionalCell<UsartClient<'static>>,
 
     spi_chip_select: OptionalCell<&'static hil::gpio::Pin>,
+    spi_hold_low: Cell<bool>,
 }
 
 // USART hardware peripherals on SAM4L
@@ -445,10 +445,11 @@ impl USART {
 
             // This is only used if the USART is in SPI mode.
             spi_chip_select: OptionalCell::empty(),
+            spi_hold_low: Cell::new(false),
         }
     }
 
-    pub fn set_dma(&self, rx_dma: &'static dma::DMAChannel, tx_dma: &'static dma::DMAChannel) {
+    pub fn set_dma(&self, rx_dma: &'static dma::Channel, tx_dma: &'static dma::Channel) {
         self.rx_dma.set(Some(rx_dma));
         self.tx_dma.set(Some(tx_dma));
     }
@@ -632,16 +633,19 @@ impl USART {
                         // For the SPI case it is a little more complicated.
 
                         // First, it is now a valid time to de-assert the CS
-                        // line because we know the write and/or read is done.
-                        self.spi_chip_select.map_or_else(
-                            || {
-                                // Do "else" case first. Thanks, rust.
-                                self.rts_disable_spi_deassert_cs(usart);
-                            },
-                            |cs| {
-                                cs.set();
-                            },
-                        );
+                        // line because we know the write and/or read is done,
+                        // unless the client holds it for another transfer.
+                        if !self.spi_hold_low.get() {
+                            self.spi_chip_select.map_or_else(
+                                || {
+                                    // Do "else" case first. Thanks, rust.
+                                    self.rts_disable_spi_deassert_cs(usart);
+                                },
+                                |cs| {
+                                    cs.set();
+                                },
+                            );
+                        }
 
                         // Get the RX buffer, and it is ok if we didn't use one,
                         // we can just return None.
@@ -741,7 +745,7 @@ impl USART {
     }
 }
 
-impl dma::DMAClient for USART {
+impl hil::dma::Client<dma::DMAPeripheral> for USART {
     fn transfer_done(&self, pid: dma::DMAPeripheral) {
         let usart = &USARTRegManager::new(&self);
         match self.usart_mode.get() {
@@ -872,7 +876,7 @@ impl hil::uart::UART for USART {
         // set up dma transfer and start transmission
         self.tx_dma.get().map(move |dma| {
             dma.enable();
-            dma.do_transfer(self.tx_dma_peripheral, tx_data, tx_len);
+            dma.transfer_to_peripheral(self.tx_dma_peripheral, tx_data, tx_len);
             self.tx_len.set(tx_len);
         });
     }
@@ -897,7 +901,7 @@ impl hil::uart::UART for USART {
         self.rx_dma.get().map(move |dma| {
             dma.enable();
             self.rx_len.set(length);
-            dma.do_transfer(self.rx_dma_peripheral, rx_buffer, length);
+            dma.transfer_from_peripheral(self.rx_dma_peripheral, rx_buffer, length);
         });
     }
 
@@ -927,12 +931,35 @@ impl hil::uart::UARTReceiveAdvanced for USART {
         self.rx_dma.get().map(move |dma| {
             dma.enable();
             let length = rx_buffer.len();
-            dma.do_transfer(self.rx_dma_peripheral, rx_buffer, length);
+            dma.transfer_from_peripheral(self.rx_dma_peripheral, rx_buffer, length);
             self.rx_len.set(length);
         });
     }
 }
 
+impl hil::uart::UARTEmergency for USART {
+    unsafe fn emergency_write(&self, bytes: &[u8]) {
+        let usart = &USARTRegManager::panic_new(&self);
+
+        // Drop any DMA transmission in progress, its client is not signaled
+        if self.usart_tx_state.get() != USARTStateTX::Idle {
+            self.disable_tx_interrupts(usart);
+            self.tx_dma.get().map(|tx_dma| {
+                tx_dma.abort_transfer();
+                tx_dma.disable();
+            });
+            self.tx_len.set(0);
+            self.usart_tx_state.set(USARTStateTX::Idle);
+        }
+
+        self.enable_tx(usart);
+        for &byte in bytes {
+            while !self.tx_ready(usart) {}
+            self.send_byte(usart, byte);
+        }
+    }
+}
+
 /// SPI
 impl hil::spi::SpiMaster for USART {
     type ChipSelect = Option<&'static hil::gpio::Pin>;
@@ -1011,12 +1038,12 @@ impl hil::spi::SpiMaster for USART {
                         self.usart_tx_state.set(USARTStateTX::DMA_Transmitting);
                         self.usart_rx_state.set(USARTStateRX::Idle);
                         dma.enable();
-                        dma.do_transfer(self.tx_dma_peripheral, write_buffer, count);
+                        dma.transfer_to_peripheral(self.tx_dma_peripheral, write_buffer, count);
 
                         // Start the read transaction.
                         self.usart_rx_state.set(USARTStateRX::DMA_Receiving);
                         read.enable();
-                        read.do_transfer(self.rx_dma_peripheral, rbuf, count);
+                        read.transfer_from_peripheral(self.rx_dma_peripheral, rbuf, count);
                     });
                 });
             });
@@ -1026,7 +1053,7 @@ impl hil::spi::SpiMaster for USART {
                 self.usart_tx_state.set(USARTStateTX::DMA_Transmitting);
                 self.usart_rx_state.set(USARTStateRX::Idle);
                 dma.enable();
-                dma.do_transfer(self.tx_dma_peripheral, write_buffer, count);
+                dma.transfer_to_peripheral(self.tx_dma_peripheral, write_buffer, count);
             });
         }
 
@@ -1147,10 +1174,10 @@ impl hil::spi::SpiMaster for USART {
     // CS line is high or low, such that it can issue multi-byte
     // requests with single byte operations.
     fn hold_low(&self) {
-        unimplemented!("USART: SPI: Use `read_write_bytes()` instead.");
+        self.spi_hold_low.set(true);
     }
 
     fn release_low(&self) {
-        unimplemented!("USART: SPI: Use `read_write_bytes()` instead.");
+        self.spi_hold_low.set(false);
     }
 }
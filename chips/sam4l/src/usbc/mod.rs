@@ -1505,6 +1505,13 @@ impl hil::usb::UsbController for Usbc<'a> {
         let mut requests = self.requests[endpoint].get();
         requests.resume = true;
         self.requests[endpoint].set(requests);
+
+        // If we are not handling an interrupt (during which the state is in
+        // use), nothing else would process the request until the next
+        // interrupt, so process it now
+        if self.state.is_some() {
+            self.handle_requests();
+        }
     }
 }
 
@@ -2,6 +2,7 @@
 
 use core::cell::Cell;
 use cortexm4::support;
+use kernel::common::cells::OptionalCell;
 use kernel::common::math::log_base_two_u64;
 use kernel::common::registers::{FieldValue, ReadOnly, ReadWrite, WriteOnly};
 use kernel::common::StaticRef;
@@ -102,6 +103,7 @@ const WDT_REGS: StaticRef<WdtRegisters> =
 
 pub struct Wdt {
     enabled: Cell<bool>,
+    client: OptionalCell<&'static hil::watchdog::WatchdogClient>,
 }
 
 pub static mut WDT: Wdt = Wdt::new();
@@ -125,6 +127,7 @@ impl Wdt {
     const fn new() -> Wdt {
         Wdt {
             enabled: Cell::new(false),
+            client: OptionalCell::empty(),
         }
     }
 
@@ -197,10 +200,20 @@ impl Wdt {
         let mult: u64 = f_clk_khz * (period as u64);
         let scaler = log_base_two_u64(mult); // prefer rounding for longer WD (thus no -1)
 
+        // With a client, the first timeout raises an interrupt to warn it,
+        // and the second one resets the chip.
+        let interrupt_mode = if self.client.is_some() {
+            WDT_REGS.ier.write(Interrupt::WINT::SET);
+            Control::IM::InterruptModeEnabled
+        } else {
+            Control::IM::InterruptModeDisabled
+        };
+
         let control = Control::CEN::ClockEnable
             + Control::PSEL.val(scaler)
             + Control::FCD::DoNotRedoCalibration
             + Control::DAR::DisableAfterReset
+            + interrupt_mode
             + Control::EN::Enable;
         self.write_cr(control);
     }
@@ -213,11 +226,23 @@ impl Wdt {
         self.enabled.set(false);
     }
 
-    fn tickle(&self) {
+    fn pet(&self) {
         // Need to write the WDTCLR bit twice for it to work
         WDT_REGS.clr.write(Clear::KEY::KEY1 + Clear::WDTCLR::SET);
         WDT_REGS.clr.write(Clear::KEY::KEY2 + Clear::WDTCLR::SET);
     }
+
+    fn handle_interrupt(&self) {
+        WDT_REGS.icr.write(Interrupt::WINT::SET);
+        self.client.map(|client| client.early_warning());
+    }
+}
+
+/// Handles the early-warning interrupt directly in the vector table, rather
+/// than through the kernel loop, because the warning usually means that the
+/// kernel loop is stuck.
+pub unsafe extern "C" fn wdt_handler() {
+    WDT.handle_interrupt();
 }
 
 impl hil::watchdog::Watchdog for Wdt {
@@ -229,7 +254,25 @@ impl hil::watchdog::Watchdog for Wdt {
         self.stop();
     }
 
-    fn tickle(&self) {
-        self.tickle();
+    fn pet(&self) {
+        self.pet();
+    }
+
+    // The watchdog keeps counting in deep sleep, so it is disabled while the
+    // chip sleeps. Enabling it again restarts the count.
+    fn suspend(&self) {
+        if self.enabled.get() {
+            self.write_cr(Control::EN::Disable);
+        }
+    }
+
+    fn resume(&self) {
+        if self.enabled.get() {
+            self.write_cr(Control::EN::Enable);
+        }
+    }
+
+    fn set_client(&self, client: &'static hil::watchdog::WatchdogClient) {
+        self.client.set(client);
     }
 }
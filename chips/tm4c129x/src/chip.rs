@@ -2,6 +2,7 @@
 
 use cortexm4;
 use gpt;
+use kernel::common::deferred_call;
 use kernel::Chip;
 use uart;
 
@@ -22,13 +23,16 @@ impl Tm4c129x {
 impl Chip for Tm4c129x {
     type MPU = cortexm4::mpu::MPU;
     type SysTick = cortexm4::systick::SysTick;
+    type Watchdog = ();
 
     fn service_pending_interrupts(&self) {
         use nvic;
 
         unsafe {
             loop {
-                if let Some(interrupt) = cortexm4::nvic::next_pending() {
+                if let Some(client) = deferred_call::next_pending() {
+                    client.handle_deferred_call();
+                } else if let Some(interrupt) = cortexm4::nvic::next_pending() {
                     match interrupt {
                         nvic::UART0 => uart::UART0.handle_interrupt(),
                         nvic::TIMER0A => gpt::TIMER0.handle_interrupt(),
@@ -47,7 +51,7 @@ impl Chip for Tm4c129x {
     }
 
     fn has_pending_interrupts(&self) -> bool {
-        unsafe { cortexm4::nvic::has_pending() }
+        unsafe { cortexm4::nvic::has_pending() || deferred_call::has_tasks() }
     }
 
     fn mpu(&self) -> &cortexm4::mpu::MPU {
@@ -58,6 +62,10 @@ impl Chip for Tm4c129x {
         &self.systick
     }
 
+    fn watchdog(&self) -> &Self::Watchdog {
+        &()
+    }
+
     fn sleep(&self) {
         /*if pm::deep_sleep_ready() {
             unsafe {
@@ -206,3 +206,17 @@ impl hil::uart::UART for UART {
         unimplemented!()
     }
 }
+
+impl hil::uart::UARTEmergency for UART {
+    unsafe fn emergency_write(&self, bytes: &[u8]) {
+        // Drop any transmission in progress, its client is not signaled
+        self.disable_tx_interrupts();
+        self.buffer.take();
+        self.remaining.set(0);
+
+        for &byte in bytes {
+            self.send_byte(byte);
+            while !self.tx_ready() {}
+        }
+    }
+}
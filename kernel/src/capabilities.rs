@@ -58,3 +58,8 @@ pub unsafe trait MainLoopCapability {}
 /// The `MemoryAllocationCapability` capability allows the holder to allocate
 /// memory, for example by creating grants.
 pub unsafe trait MemoryAllocationCapability {}
+
+/// The `RadioTestCapability` capability allows the holder to put a radio in a
+/// test mode where it emits a carrier or test pattern outside of any protocol,
+/// e.g. for regulatory or antenna testing.
+pub unsafe trait RadioTestCapability {}
@@ -2,15 +2,15 @@
 //!
 //! This is a tool to allow chip peripherals to schedule "interrupts"
 //! in the chip scheduler if the hardware doesn't support interrupts where
-//! they are needed.
+//! they are needed. Capsules can use it as well, to defer work to a later
+//! iteration of the kernel loop.
 
 use core::cell::UnsafeCell;
-use core::convert::Into;
-use core::convert::TryFrom;
-use core::convert::TryInto;
 use core::intrinsics;
-use core::marker::Copy;
 use core::marker::Sync;
+use core::usize;
+
+use returncode::ReturnCode;
 
 /// AtomicUsize with no CAS operations that works on targets that have "no atomic
 /// support" according to their specification. This makes it work on thumbv6
@@ -44,41 +44,94 @@ impl AtomicUsize {
 
 unsafe impl Sync for AtomicUsize {}
 
+/// Maximum number of deferred calls, one per bit of the pending word.
+const MAX_DEFERRED_CALLS: usize = 32;
+
+/// Slot of a `DeferredCall` that has not been registered yet.
+const UNREGISTERED: usize = usize::MAX;
+
 static DEFERRED_CALL: AtomicUsize = AtomicUsize::new(0);
 
+/// Number of slots that have been allocated.
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+/// The client of each allocated slot.
+static mut CLIENTS: [Option<&'static DeferredCallClient>; MAX_DEFERRED_CALLS] =
+    [None; MAX_DEFERRED_CALLS];
+
 /// Are there any pending `DeferredCall`s?
 pub fn has_tasks() -> bool {
     DEFERRED_CALL.load_relaxed() != 0
 }
 
+/// Gets and clears the next pending `DeferredCall`, and returns the client
+/// that handles it.
+pub fn next_pending() -> Option<&'static DeferredCallClient> {
+    let val = DEFERRED_CALL.load_relaxed();
+    if val == 0 {
+        None
+    } else {
+        let bit = val.trailing_zeros() as usize;
+        let new_val = val & !(1 << bit);
+        DEFERRED_CALL.store_relaxed(new_val);
+        unsafe { CLIENTS[bit] }
+    }
+}
+
+/// Implemented by drivers and capsules that handle a `DeferredCall`.
+pub trait DeferredCallClient {
+    /// Called from the kernel loop when the deferred call is pending.
+    fn handle_deferred_call(&self);
+}
+
 /// Represents a way to generate an asynchronous call without a hardware
-/// interrupt. Supports up to 32 possible deferrable tasks.
-pub struct DeferredCall<T>(T);
+/// interrupt. Supports up to 32 possible deferrable tasks, across the whole
+/// kernel.
+///
+/// A chip driver or capsule creates a `DeferredCall` when it is constructed,
+/// and registers itself as the client once it has a `'static` reference to
+/// itself, which allocates a slot for the call. Chips service pending calls
+/// with `next_pending()`, before their hardware interrupts.
+pub struct DeferredCall {
+    slot: AtomicUsize,
+}
 
-impl<T: Into<usize> + TryFrom<usize> + Copy> DeferredCall<T> {
+impl DeferredCall {
     /// Creates a new DeferredCall
     ///
     /// Only create one per task, preferably in the module that it will be used
     /// in.
-    pub const unsafe fn new(task: T) -> Self {
-        DeferredCall(task)
+    pub const fn new() -> DeferredCall {
+        DeferredCall {
+            slot: AtomicUsize::new(UNREGISTERED),
+        }
     }
 
-    /// Set the `DeferredCall` as pending
-    pub fn set(&self) {
-        DEFERRED_CALL.fetch_or_relaxed(1 << self.0.into() as usize);
+    /// Allocate a slot for this `DeferredCall`, which `client` handles.
+    /// Returns `EALREADY` if it is already registered, and `ENOMEM` if all
+    /// slots are allocated.
+    pub fn register(&self, client: &'static DeferredCallClient) -> ReturnCode {
+        if self.slot.load_relaxed() != UNREGISTERED {
+            return ReturnCode::EALREADY;
+        }
+        let slot = ALLOCATED.load_relaxed();
+        if slot >= MAX_DEFERRED_CALLS {
+            return ReturnCode::ENOMEM;
+        }
+        ALLOCATED.store_relaxed(slot + 1);
+        unsafe {
+            CLIENTS[slot] = Some(client);
+        }
+        self.slot.store_relaxed(slot);
+        ReturnCode::SUCCESS
     }
 
-    /// Gets and clears the next pending `DeferredCall`
-    pub fn next_pending() -> Option<T> {
-        let val = DEFERRED_CALL.load_relaxed();
-        if val == 0 {
-            None
-        } else {
-            let bit = val.trailing_zeros() as usize;
-            let new_val = val & !(1 << bit);
-            DEFERRED_CALL.store_relaxed(new_val);
-            bit.try_into().ok()
+    /// Set the `DeferredCall` as pending. Does nothing if it has not been
+    /// registered.
+    pub fn set(&self) {
+        let slot = self.slot.load_relaxed();
+        if slot != UNREGISTERED {
+            DEFERRED_CALL.fetch_or_relaxed(1 << slot);
         }
     }
 }
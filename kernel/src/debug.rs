@@ -49,12 +49,16 @@ use common::cells::NumericCellExt;
 use common::cells::{MapCell, TakeCell};
 use hil;
 use process::ProcessType;
+use returncode::ReturnCode;
 
 ///////////////////////////////////////////////////////////////////
 // panic! support routines
 
 /// Tock default panic routine.
 ///
+/// The panic output goes to `writer` and to every sink registered with
+/// `add_panic_sink()`.
+///
 /// **NOTE:** The supplied `writer` must be synchronous.
 pub unsafe fn panic<L: hil::led::Led, W: Write>(
     leds: &mut [&mut L],
@@ -64,13 +68,66 @@ pub unsafe fn panic<L: hil::led::Led, W: Write>(
     processes: &'static [Option<&'static ProcessType>],
 ) -> ! {
     panic_begin(nop);
+    panic_sinks().for_each(|sink| sink.start(panic_info));
+    let writer = &mut PanicWriter { writer: writer };
     panic_banner(writer, panic_info);
     // Flush debug buffer if needed
     flush(writer);
     panic_process_info(processes, writer);
+    panic_sinks().for_each(|sink| sink.finish());
     panic_blink_forever(leds)
 }
 
+/// A destination for the output of a kernel panic besides the board's panic
+/// writer, for example an RTT channel or a crash log in flash.
+///
+/// **NOTE:** Sinks must be synchronous, since interrupts are no longer
+/// serviced once the kernel panics.
+pub trait PanicSink {
+    /// Called before any panic output is written, with the panic.
+    fn start(&self, _panic_info: &PanicInfo) {}
+
+    /// Write the next part of the panic output.
+    fn write(&self, s: &str);
+
+    /// Called after all panic output has been written.
+    fn finish(&self) {}
+}
+
+const MAX_PANIC_SINKS: usize = 4;
+
+static mut PANIC_SINKS: [Option<&'static PanicSink>; MAX_PANIC_SINKS] = [None; MAX_PANIC_SINKS];
+
+/// Register a sink that receives the output of a kernel panic. Returns
+/// `ENOMEM` if the maximum number of sinks is already registered.
+pub unsafe fn add_panic_sink(sink: &'static PanicSink) -> ReturnCode {
+    match PANIC_SINKS.iter_mut().find(|slot| slot.is_none()) {
+        Some(slot) => {
+            *slot = Some(sink);
+            ReturnCode::SUCCESS
+        }
+        None => ReturnCode::ENOMEM,
+    }
+}
+
+unsafe fn panic_sinks() -> impl Iterator<Item = &'static (PanicSink + 'static)> {
+    PANIC_SINKS.iter().filter_map(|sink| *sink)
+}
+
+/// Writes to the board's panic writer and to every registered panic sink.
+struct PanicWriter<'a, W: Write + 'a> {
+    writer: &'a mut W,
+}
+
+impl<W: Write> Write for PanicWriter<'a, W> {
+    fn write_str(&mut self, s: &str) -> Result {
+        unsafe {
+            panic_sinks().for_each(|sink| sink.write(s));
+        }
+        self.writer.write_str(s)
+    }
+}
+
 /// Generic panic entry.
 ///
 /// This opaque method should always be called at the beginning of a board's
@@ -131,6 +188,37 @@ pub unsafe fn panic_process_info<W: Write>(
     }
 }
 
+/// Watchdog client that prints the state of all processes when the watchdog
+/// is about to reset the chip, since that usually means the kernel hangs.
+///
+/// **NOTE:** The supplied `writer` must be synchronous.
+pub struct WatchdogProcessDump<W: Write + 'static> {
+    writer: TakeCell<'static, W>,
+    processes: &'static [Option<&'static ProcessType>],
+}
+
+impl<W: Write> WatchdogProcessDump<W> {
+    pub fn new(
+        writer: &'static mut W,
+        processes: &'static [Option<&'static ProcessType>],
+    ) -> WatchdogProcessDump<W> {
+        WatchdogProcessDump {
+            writer: TakeCell::new(writer),
+            processes: processes,
+        }
+    }
+}
+
+impl<W: Write> hil::watchdog::WatchdogClient for WatchdogProcessDump<W> {
+    fn early_warning(&self) {
+        self.writer.map(|writer| unsafe {
+            let _ = writer.write_str("\r\n\nWatchdog timeout, resetting:\r\n");
+            flush(writer);
+            panic_process_info(self.processes, writer);
+        });
+    }
+}
+
 /// Blinks a recognizable pattern forever.
 ///
 /// If a multi-color LED is used for the panic pattern, it is
@@ -159,6 +247,81 @@ pub fn panic_blink_forever<L: hil::led::Led>(leds: &mut [&mut L]) -> ! {
     }
 }
 
+/// The times `PanicBlinkCode` blinks the line number before `panic()` blinks
+/// its pattern.
+const PANIC_BLINK_CODE_REPEATS: usize = 3;
+
+/// Panic sink that blinks the line number of the panic on an LED, to tell
+/// panics apart on a board whose panic output nobody reads.
+///
+/// Each digit of the line number is blinked as that many short blinks, ten
+/// for a zero, with a pause after each digit and a longer pause after the
+/// number. The number is blinked a few times when the panic output is done,
+/// after which `panic()` blinks its pattern forever.
+pub struct PanicBlinkCode<L: hil::led::Led + 'static> {
+    led: TakeCell<'static, L>,
+    /// The line of the panic, 0 if it has no location
+    line: Cell<u32>,
+}
+
+impl<L: hil::led::Led> PanicBlinkCode<L> {
+    pub fn new(led: &'static mut L) -> PanicBlinkCode<L> {
+        PanicBlinkCode {
+            led: TakeCell::new(led),
+            line: Cell::new(0),
+        }
+    }
+}
+
+/// Keeps `led` on or off for `iterations` iterations of a busy loop.
+fn hold_led<L: hil::led::Led>(led: &mut L, on: bool, iterations: usize) {
+    for _ in 0..iterations {
+        if on {
+            led.on();
+        } else {
+            led.off();
+        }
+    }
+}
+
+impl<L: hil::led::Led> PanicSink for PanicBlinkCode<L> {
+    fn start(&self, panic_info: &PanicInfo) {
+        self.line
+            .set(panic_info.location().map_or(0, |location| location.line()));
+    }
+
+    fn write(&self, _s: &str) {}
+
+    fn finish(&self) {
+        let line = self.line.get();
+        if line == 0 {
+            return;
+        }
+        self.led.map(|led| {
+            led.init();
+            hold_led(led, false, 2000000);
+            for _ in 0..PANIC_BLINK_CODE_REPEATS {
+                // Blink the digits from the most significant one.
+                let mut divisor = 1;
+                while line / divisor >= 10 {
+                    divisor *= 10;
+                }
+                while divisor > 0 {
+                    let digit = line / divisor % 10;
+                    let blinks = if digit == 0 { 10 } else { digit };
+                    for _ in 0..blinks {
+                        hold_led(led, true, 300000);
+                        hold_led(led, false, 300000);
+                    }
+                    hold_led(led, false, 1000000);
+                    divisor /= 10;
+                }
+                hold_led(led, false, 2000000);
+            }
+        });
+    }
+}
+
 // panic! support routines
 ///////////////////////////////////////////////////////////////////
 
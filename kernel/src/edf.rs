@@ -0,0 +1,148 @@
+//! Earliest-deadline-first scheduling for Tock.
+//!
+//! Processes declare the deadline of their current job through the EDF
+//! syscall driver, and tell it when the job is complete. Among the processes
+//! that are ready to run, the scheduler always picks the one with the earliest
+//! deadline. Processes without a deadline only run when no process with a
+//! deadline is ready, in turn.
+//!
+//! Scheduling is cooperative: a process keeps its deadline until it completes
+//! its job, even if the deadline has passed. A job that completes after its
+//! deadline counts as a deadline miss, and so does a job whose deadline
+//! passed before the process declared a new one. Miss counts are available
+//! to userspace through the driver and to the board through
+//! `Introspection`.
+//!
+//! Deadlines are measured with an `Alarm`, which the scheduler only reads and
+//! never arms, so it can share one that is in use by other capsules.
+
+/// Syscall number
+pub const DRIVER_NUM: usize = 0x00010001;
+
+use core::cell::Cell;
+
+use callback::AppId;
+use driver::Driver;
+use hil::time::{Alarm, Frequency};
+use process::ProcessType;
+use returncode::ReturnCode;
+use scheduler::Scheduler;
+
+pub struct EdfScheduler<'a, A: Alarm + 'a> {
+    alarm: &'a A,
+    /// Index of the process without a deadline that ran last.
+    last: Cell<usize>,
+}
+
+impl<A: Alarm + 'a> EdfScheduler<'a, A> {
+    pub fn new(alarm: &'a A) -> EdfScheduler<'a, A> {
+        EdfScheduler {
+            alarm: alarm,
+            last: Cell::new(0),
+        }
+    }
+
+    /// Returns how far in the future `deadline` is, negative if it has
+    /// passed.
+    fn time_left(&self, deadline: u32) -> i32 {
+        deadline.wrapping_sub(self.alarm.now()) as i32
+    }
+
+    fn ms_to_ticks(ms: usize) -> u32 {
+        (ms as u64 * A::Frequency::frequency() as u64 / 1000) as u32
+    }
+}
+
+impl<A: Alarm + 'a> Scheduler for EdfScheduler<'a, A> {
+    fn next(&self, processes: &[Cell<Option<&'static ProcessType>>]) -> Option<usize> {
+        let len = processes.len();
+
+        // The earliest deadline of the ready processes, and the first ready
+        // process without a deadline after the one that ran last.
+        let mut earliest: Option<(usize, i32)> = None;
+        let mut next_without_deadline: Option<usize> = None;
+
+        for offset in 1..len + 1 {
+            let index = (self.last.get() + offset) % len;
+            if let Some(process) = processes[index].get() {
+                if !process.ready() {
+                    continue;
+                }
+                match process.get_deadline() {
+                    Some(deadline) => {
+                        let time_left = self.time_left(deadline);
+                        if earliest.map_or(true, |(_, earliest_left)| time_left < earliest_left) {
+                            earliest = Some((index, time_left));
+                        }
+                    }
+                    None => {
+                        if next_without_deadline.is_none() {
+                            next_without_deadline = Some(index);
+                        }
+                    }
+                }
+            }
+        }
+
+        match earliest {
+            Some((index, _)) => Some(index),
+            None => next_without_deadline.map(|index| {
+                self.last.set(index);
+                index
+            }),
+        }
+    }
+}
+
+impl<A: Alarm + 'a> Driver for EdfScheduler<'a, A> {
+    /// Declare deadlines and complete jobs.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Set the deadline of the current job to `data` milliseconds from
+    ///   now.
+    /// - `2`: Complete the current job. Returns 1 if the job missed its
+    ///   deadline, 0 otherwise, and `EINVAL` if there is no deadline set.
+    /// - `3`: Get the number of deadlines this process missed.
+    fn command(&self, command_num: usize, data: usize, _: usize, appid: AppId) -> ReturnCode {
+        appid
+            .kernel
+            .process_map_or(ReturnCode::EINVAL, appid.idx(), |process| {
+                match command_num {
+                    0 => ReturnCode::SUCCESS,
+
+                    1 => {
+                        if let Some(deadline) = process.get_deadline() {
+                            if self.time_left(deadline) < 0 {
+                                process.debug_deadline_missed();
+                            }
+                        }
+                        let deadline = self.alarm.now().wrapping_add(Self::ms_to_ticks(data));
+                        process.set_deadline(Some(deadline));
+                        ReturnCode::SUCCESS
+                    }
+
+                    2 => match process.get_deadline() {
+                        Some(deadline) => {
+                            process.set_deadline(None);
+                            let missed = self.time_left(deadline) < 0;
+                            if missed {
+                                process.debug_deadline_missed();
+                            }
+                            ReturnCode::SuccessWithValue {
+                                value: missed as usize,
+                            }
+                        }
+                        None => ReturnCode::EINVAL,
+                    },
+
+                    3 => ReturnCode::SuccessWithValue {
+                        value: process.debug_deadline_miss_count(),
+                    },
+
+                    _ => ReturnCode::ENOSUPPORT,
+                }
+            })
+    }
+}
@@ -6,7 +6,7 @@ use core::ops::{Deref, DerefMut};
 use core::ptr::{write, write_volatile, Unique};
 
 use callback::AppId;
-use process::Error;
+use process::{Error, SwapState};
 use sched::Kernel;
 
 pub struct Grant<T: Default> {
@@ -144,6 +144,9 @@ impl<T: Default> Grant<T> {
     pub fn grant(&self, appid: AppId) -> Option<AppliedGrant<T>> {
         unsafe {
             appid.kernel.process_map_or(None, appid.idx(), |process| {
+                if process.swap_state() != SwapState::Resident {
+                    return None;
+                }
                 let cntr = *(process.grant_ptr(self.grant_num) as *mut *mut T);
                 if cntr.is_null() {
                     None
@@ -167,36 +170,39 @@ impl<T: Default> Grant<T> {
             appid
                 .kernel
                 .process_map_or(Err(Error::NoSuchApp), appid.idx(), |process| {
+                    // The grant memory of a process is not there while its
+                    // memory is swapped out.
+                    if process.swap_state() != SwapState::Resident {
+                        return Err(Error::InactiveApp);
+                    }
+
                     // Here is an example of how the grants are laid out in a
                     // process's memory:
                     //
                     // Mem. Addr.
                     // 0x0040000  ┌────────────────────
-                    //            │   GrantPointer0 [0x003FFC8]
-                    //            │   GrantPointer1 [0x003FFC0]
-                    //            │   ...
-                    //            │   GrantPointerN [0x0000000 (NULL)]
-                    // 0x003FFE0  ├────────────────────
                     //            │   GrantRegion0
-                    // 0x003FFC8  ├────────────────────
+                    // 0x003FFE8  ├────────────────────
                     //            │   GrantRegion1
-                    // 0x003FFC0  ├────────────────────
+                    // 0x003FFE0  ├────────────────────
                     //            │
                     //            │   --unallocated--
                     //            │
                     //            └────────────────────
                     //
-                    // An array of pointers (one per possible grant region)
-                    // point to where the actual grant memory is allocated
-                    // inside of the process. The grant memory is not allocated
-                    // until the actual grant region is actually used.
+                    // An array of pointers (one per possible grant region),
+                    // which the kernel keeps with the process struct outside of
+                    // process memory, point to where the actual grant memory is
+                    // allocated inside of the process, here [0x003FFE8,
+                    // 0x003FFE0, ..., 0x0000000 (NULL)]. The grant memory is
+                    // not allocated until the actual grant region is actually
+                    // used.
                     //
                     // This function provides the app access to the specific
                     // grant memory, and allocates the grant region in the
                     // process memory if needed.
                     //
-                    // Get a pointer to where the grant pointer is stored in the
-                    // process memory.
+                    // Get a pointer to where the grant pointer is stored.
                     let ctr_ptr = process.grant_ptr(self.grant_num) as *mut *mut T;
                     // If the pointer at that location is NULL then the grant
                     // memory needs to be allocated.
@@ -235,6 +241,9 @@ impl<T: Default> Grant<T> {
     {
         self.kernel
             .process_each_enumerate(|app_id, process| unsafe {
+                if process.swap_state() != SwapState::Resident {
+                    return;
+                }
                 let root_ptr = *(process.grant_ptr(self.grant_num) as *mut *mut T);
                 if !root_ptr.is_null() {
                     let mut root = Owned::new(root_ptr, AppId::new(self.kernel, app_id));
@@ -77,9 +77,9 @@ pub trait AdcHighSpeed: Adc {
     /// configuration.
     /// Expected to be called in a `buffer_ready` callback. Note that if this
     /// is not called before the second buffer is filled, samples will be
-    /// missed. Length field corresponds to the number of samples that should
-    /// be collected in the buffer. If an error occurs, the buffer will be
-    /// returned.
+    /// missed, and sampling resumes into this buffer. Length field
+    /// corresponds to the number of samples that should be collected in the
+    /// buffer. If an error occurs, the buffer will be returned.
     ///
     /// All ADC samples will be the raw ADC value left-justified in the u16.
     fn provide_buffer(
@@ -112,4 +112,42 @@ pub trait HighSpeedClient {
     /// the buffer. Expects an additional call to either provide another buffer
     /// or stop sampling
     fn samples_ready(&self, buf: &'static mut [u16], length: usize);
+
+    /// Called before `samples_ready` if samples were lost since the previous
+    /// buffer, because no buffer was provided in time or the samples were not
+    /// moved to memory fast enough. The buffer holds the samples taken after
+    /// sampling resumed.
+    fn samples_dropped(&self) {}
+}
+
+// *** Interfaces for scanning several channels ***
+
+/// Interface for sampling an ordered sequence of channels per trigger, so
+/// that readings of several inputs are taken as close together as the ADC
+/// allows.
+pub trait AdcScan: Adc {
+    /// The most channels a sequence can have.
+    fn max_scan_channels(&self) -> usize;
+
+    /// Set the sequence of channels that `scan` samples, in order. A channel
+    /// may appear more than once. Returns EINVAL if the sequence is empty or
+    /// longer than `max_scan_channels`, and EBUSY if the ADC is sampling.
+    fn configure_scan(&self, channels: &[&Self::Channel]) -> ReturnCode;
+
+    /// Sample each channel of the sequence once, into consecutive entries of
+    /// `buffer`, and pass the frame to `ScanClient::scan_done`. Returns
+    /// EBUSY if the ADC is sampling, EINVAL if no sequence is configured,
+    /// and ESIZE if `buffer` is shorter than the sequence. If an error
+    /// occurs, the buffer is returned.
+    ///
+    /// All ADC samples will be the raw ADC value left-justified in the u16.
+    fn scan(&self, buffer: &'static mut [u16]) -> (ReturnCode, Option<&'static mut [u16]>);
+}
+
+/// Trait for handling callbacks from scans.
+pub trait ScanClient {
+    /// Called when a frame is sampled. `length` is the number of channels in
+    /// the sequence, with the sample of the first one at the start of
+    /// `buffer`.
+    fn scan_done(&self, buffer: &'static mut [u16], length: usize);
 }
@@ -5,6 +5,60 @@
 
 use returncode::ReturnCode;
 
+/// The condition on which an interrupt-based comparison fires.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum InterruptMode {
+    /// When Vp > Vn. Fires again only after Vp < Vn.
+    VpGtVn,
+    /// When Vp < Vn. Fires again only after Vp > Vn.
+    VpLtVn,
+    /// Every time the output of the comparator toggles, i.e. on both
+    /// crossings.
+    Toggle,
+}
+
+/// The source of the negative input (Vn) of a comparator.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Reference {
+    /// An external pin. The value selects the analog input pin on chips that
+    /// can route one of several pins to the negative input, and is ignored on
+    /// chips with a dedicated negative input pin.
+    ExternalPin(u8),
+    /// The internal bandgap reference
+    Bandgap,
+    /// VDD scaled down by a ladder, in sixteenths of VDD (1 to 15)
+    ScaledVdd(u8),
+    /// The output of the internal DAC
+    Dac,
+}
+
+/// The hysteresis applied by a comparator. The actual voltages depend on the
+/// chip.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Hysteresis {
+    None,
+    Low,
+    Medium,
+    High,
+}
+
+/// The condition on which a window interrupt fires.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum WindowInterruptMode {
+    /// When the common input voltage is inside the window, including when it
+    /// already is as interrupts are enabled. Fires again only after the
+    /// voltage has been outside the window.
+    Inside,
+    /// When the common input voltage is outside the window, including when it
+    /// already is as interrupts are enabled. Fires again only after the
+    /// voltage has been inside the window.
+    Outside,
+    /// Every time the common input voltage enters the window
+    Entering,
+    /// Every time the common input voltage leaves the window
+    Leaving,
+}
+
 pub trait AnalogComparator {
     /// The chip-dependent type of an analog comparator channel.
     type Channel;
@@ -15,17 +69,82 @@ pub trait AnalogComparator {
     /// > Vin negative), and False if Vp < Vn.
     fn comparison(&self, channel: &Self::Channel) -> bool;
 
+    /// Do a single window comparison, using the two comparators making up
+    /// `window`. Output will be True (1) when the common input voltage is
+    /// inside the window set by the two other inputs, and False (0) otherwise.
+    fn window_comparison(&self, window: usize) -> bool;
+
     /// Start interrupt-based comparison for the chosen channel (e.g. channel 1
     /// for AC1). This will make it listen and send an interrupt as soon as
-    /// Vp > Vn.
-    fn start_comparing(&self, channel: &Self::Channel) -> ReturnCode;
+    /// the condition set by `mode` is met, e.g. Vp > Vn.
+    fn start_comparing(&self, channel: &Self::Channel, mode: InterruptMode) -> ReturnCode;
 
     /// Stop interrupt-based comparison for the chosen channel.
     fn stop_comparing(&self, channel: &Self::Channel) -> ReturnCode;
+
+    /// Start continuous measurements on the chosen channel. The comparator
+    /// keeps measuring without interrupting the CPU, and `comparison` returns
+    /// the latched result of the last measurement instead of triggering a new
+    /// one.
+    fn start_continuous(&self, channel: &Self::Channel) -> ReturnCode;
+
+    /// Start continuous measurements on the chosen channel in event-only mode.
+    /// The CPU is only interrupted, and the client's `fired` only called, when
+    /// the output of the comparator changes.
+    fn start_event_only(&self, channel: &Self::Channel) -> ReturnCode;
+
+    /// Stop continuous or event-only measurements on the chosen channel.
+    /// Afterwards, `comparison` triggers a single measurement again.
+    fn stop(&self, channel: &Self::Channel) -> ReturnCode;
+
+    /// Keep the chosen channel comparing while the chip is in deep sleep, so
+    /// that its interrupts can wake the chip up.
+    fn enable_in_sleep(&self, channel: &Self::Channel) -> ReturnCode;
+
+    /// Stop comparing on the chosen channel while the chip is in deep sleep.
+    fn disable_in_sleep(&self, channel: &Self::Channel) -> ReturnCode;
+
+    /// Select the source of the negative input of the chosen channel. Returns
+    /// `ENOSUPPORT` if the chip can't use `reference` for this channel.
+    fn set_reference(&self, channel: &Self::Channel, reference: Reference) -> ReturnCode;
+
+    /// Set the hysteresis of the chosen channel, so that a noisy input close
+    /// to the reference doesn't make the output toggle repeatedly.
+    fn set_hysteresis(&self, channel: &Self::Channel, level: Hysteresis) -> ReturnCode;
+
+    /// Start interrupt-based window comparison for the chosen window. The
+    /// client's `window_fired` is called according to `mode`.
+    fn enable_window_interrupts(&self, window: usize, mode: WindowInterruptMode) -> ReturnCode;
+
+    /// Stop interrupt-based window comparison for the chosen window.
+    fn disable_window_interrupts(&self, window: usize) -> ReturnCode;
+}
+
+/// A peripheral that can be triggered directly by analog comparator events,
+/// without CPU involvement, through the peripheral event system of the chip
+/// (e.g. the SAM4L Peripheral Event Controller or the nRF PPI).
+pub trait EventSink {
+    /// The chip-dependent type of the input the sink acts on, e.g. an ADC
+    /// channel.
+    type Channel;
+
+    /// Act on `channel` every time the output of analog comparator `ac`
+    /// changes. An ADC samples `channel` and passes each sample to its client
+    /// as usual.
+    fn start_on_events(&self, ac: usize, channel: &Self::Channel) -> ReturnCode;
+
+    /// Stop acting on analog comparator events.
+    fn stop_on_events(&self) -> ReturnCode;
 }
 
 pub trait Client {
     /// Fires when handle_interrupt is called, returning the channel on which
     /// the interrupt occurred.
-    fn fired(&self, usize);
+    ///
+    /// `channel` is the number of the comparator that triggered, i.e. `x` for
+    /// ACx.
+    fn fired(&self, channel: usize);
+
+    /// Fires when a window interrupt occurred on `window`.
+    fn window_fired(&self, window: usize);
 }
@@ -50,12 +50,10 @@
 use returncode::ReturnCode;
 
 pub trait BleAdvertisementDriver {
-    fn transmit_advertisement(
-        &self,
-        buf: &'static mut [u8],
-        len: usize,
-        channel: RadioChannel,
-    ) -> &'static mut [u8];
+    /// Transmits the first `len` bytes of `buf` on `channel`. The radio
+    /// transmits directly from `buf`, and hands it back with
+    /// `TxClient::transmit_event` once the transmission is done.
+    fn transmit_advertisement(&self, buf: &'static mut [u8], len: usize, channel: RadioChannel);
     fn receive_advertisement(&self, channel: RadioChannel);
     fn set_receive_client(&self, client: &'static RxClient);
     fn set_transmit_client(&self, client: &'static TxClient);
@@ -65,12 +63,42 @@ pub trait BleConfig {
     fn set_tx_power(&self, power: u8) -> ReturnCode;
 }
 
+/// Radios that can start an advertising event from a hardware timer
+///
+/// The radio is armed ahead of time so that the transmission starts without any
+/// CPU involvement, and the CPU can sleep until the `TxClient` is notified.
+pub trait BleAdvertisementScheduler {
+    /// Same as `BleAdvertisementDriver::transmit_advertisement` but the
+    /// transmission starts `delay_us` microseconds from now.
+    fn schedule_transmit_advertisement(
+        &self,
+        buf: &'static mut [u8],
+        len: usize,
+        channel: RadioChannel,
+        delay_us: u32,
+    );
+
+    /// Cancels a scheduled advertisement that has not been transmitted yet
+    /// and returns its buffer. Returns `None` if nothing was scheduled.
+    fn cancel_scheduled_advertisement(&self) -> Option<&'static mut [u8]>;
+}
+
 pub trait RxClient {
     fn receive_event(&self, buf: &'static mut [u8], len: u8, result: ReturnCode);
+
+    /// Called by radios with a bit counter as soon as the PDU header (`header` is
+    /// the first byte and `len` the LENGTH field) has been received. Returning
+    /// `false` aborts the reception, in which case `receive_event` is called
+    /// with `ECANCEL` and a length of zero.
+    fn receive_filter(&self, _header: u8, _len: u8) -> bool {
+        true
+    }
 }
 
 pub trait TxClient {
-    fn transmit_event(&self, result: ReturnCode);
+    /// Called once a transmission is done, with the buffer passed to the
+    /// radio.
+    fn transmit_event(&self, buf: &'static mut [u8], result: ReturnCode);
 }
 
 // Bluetooth Core Specification:Vol. 6. Part B, section 1.4.1 Advertising and Data Channel Indices
@@ -119,6 +147,66 @@ pub enum RadioChannel {
 }
 
 impl RadioChannel {
+    /// Returns the channel with the channel index `index` (0-39)
+    pub fn from_channel_index(index: u8) -> Option<RadioChannel> {
+        match index {
+            0 => Some(RadioChannel::DataChannel0),
+            1 => Some(RadioChannel::DataChannel1),
+            2 => Some(RadioChannel::DataChannel2),
+            3 => Some(RadioChannel::DataChannel3),
+            4 => Some(RadioChannel::DataChannel4),
+            5 => Some(RadioChannel::DataChannel5),
+            6 => Some(RadioChannel::DataChannel6),
+            7 => Some(RadioChannel::DataChannel7),
+            8 => Some(RadioChannel::DataChannel8),
+            9 => Some(RadioChannel::DataChannel9),
+            10 => Some(RadioChannel::DataChannel10),
+            11 => Some(RadioChannel::DataChannel11),
+            12 => Some(RadioChannel::DataChannel12),
+            13 => Some(RadioChannel::DataChannel13),
+            14 => Some(RadioChannel::DataChannel14),
+            15 => Some(RadioChannel::DataChannel15),
+            16 => Some(RadioChannel::DataChannel16),
+            17 => Some(RadioChannel::DataChannel17),
+            18 => Some(RadioChannel::DataChannel18),
+            19 => Some(RadioChannel::DataChannel19),
+            20 => Some(RadioChannel::DataChannel20),
+            21 => Some(RadioChannel::DataChannel21),
+            22 => Some(RadioChannel::DataChannel22),
+            23 => Some(RadioChannel::DataChannel23),
+            24 => Some(RadioChannel::DataChannel24),
+            25 => Some(RadioChannel::DataChannel25),
+            26 => Some(RadioChannel::DataChannel26),
+            27 => Some(RadioChannel::DataChannel27),
+            28 => Some(RadioChannel::DataChannel28),
+            29 => Some(RadioChannel::DataChannel29),
+            30 => Some(RadioChannel::DataChannel30),
+            31 => Some(RadioChannel::DataChannel31),
+            32 => Some(RadioChannel::DataChannel32),
+            33 => Some(RadioChannel::DataChannel33),
+            34 => Some(RadioChannel::DataChannel34),
+            35 => Some(RadioChannel::DataChannel35),
+            36 => Some(RadioChannel::DataChannel36),
+            37 => Some(RadioChannel::AdvertisingChannel37),
+            38 => Some(RadioChannel::AdvertisingChannel38),
+            39 => Some(RadioChannel::AdvertisingChannel39),
+            _ => None,
+        }
+    }
+
+    /// Returns the frequency of the channel in MHz above 2400 MHz
+    pub fn get_frequency(&self) -> u32 {
+        *self as u32
+    }
+
+    /// Returns the initial value of the data whitening LFSR for the channel
+    ///
+    /// BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 3.2 Data Whitening
+    /// Position 0 of the LFSR is set to one and positions 1-6 to the channel index.
+    pub fn get_whitening_iv(&self) -> u32 {
+        0x40 | self.get_channel_index()
+    }
+
     pub fn get_channel_index(&self) -> u32 {
         match *self {
             RadioChannel::DataChannel0 => 0,
@@ -0,0 +1,56 @@
+//! Interface for block storage devices.
+//!
+//! A block storage device, such as an SD card, is read and written in whole
+//! blocks of a fixed size. Blocks are numbered from 0, and an operation works
+//! on a number of consecutive blocks. Erasing blocks lets some devices write
+//! them faster later, and what erased blocks read as depends on the device.
+//! This is the interface filesystems are built on.
+
+use returncode::ReturnCode;
+
+pub trait BlockStorage {
+    fn set_client(&self, client: &'static Client);
+
+    /// The size of a block, in bytes.
+    fn block_size(&self) -> usize;
+
+    /// The number of blocks of the device, or 0 if the device is not ready.
+    fn block_count(&self) -> usize;
+
+    /// Read `count` blocks starting at block `block` into `buffer`. Returns
+    /// EOFF if the device is not ready, EBUSY if an operation is in progress,
+    /// ESIZE if `buffer` is too short for the blocks, and EINVAL if the blocks
+    /// extend past the end of the device. If an error occurs, the buffer is
+    /// returned.
+    fn read_blocks(
+        &self,
+        block: usize,
+        count: usize,
+        buffer: &'static mut [u8],
+    ) -> (ReturnCode, Option<&'static mut [u8]>);
+
+    /// Write `count` blocks starting at block `block` from `buffer`. The
+    /// return values are the same as for `read_blocks`.
+    fn write_blocks(
+        &self,
+        block: usize,
+        count: usize,
+        buffer: &'static mut [u8],
+    ) -> (ReturnCode, Option<&'static mut [u8]>);
+
+    /// Erase `count` blocks starting at block `block`. Returns ENOSUPPORT if
+    /// the device cannot erase blocks, and otherwise the same values as
+    /// `read_blocks`.
+    fn erase_blocks(&self, block: usize, count: usize) -> ReturnCode;
+}
+
+pub trait Client {
+    /// The blocks were read into `buffer`, unless `result` is an error.
+    fn read_done(&self, buffer: &'static mut [u8], result: ReturnCode);
+
+    /// The blocks in `buffer` were written, unless `result` is an error.
+    fn write_done(&self, buffer: &'static mut [u8], result: ReturnCode);
+
+    /// The blocks were erased, unless `result` is an error.
+    fn erase_done(&self, result: ReturnCode);
+}
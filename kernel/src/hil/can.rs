@@ -0,0 +1,117 @@
+//! Interface for CAN bus controllers.
+//!
+//! A controller sends and receives classic CAN frames of up to 8 data bytes,
+//! with standard (11-bit) or extended (29-bit) identifiers. It only passes
+//! the client the frames that match one of its acceptance filters, or every
+//! frame if it has no filters. It also tells the client when its error state
+//! changes, in particular when too many errors take it off the bus.
+
+use returncode::ReturnCode;
+
+/// The maximum number of data bytes in a frame
+pub const MAX_DATA_LEN: usize = 8;
+
+/// The identifier of a frame, which is also its priority on the bus
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Id {
+    /// An 11-bit identifier
+    Standard(u16),
+    /// A 29-bit identifier
+    Extended(u32),
+}
+
+impl Id {
+    /// The value of the identifier, without its kind
+    pub fn value(&self) -> u32 {
+        match *self {
+            Id::Standard(id) => id as u32,
+            Id::Extended(id) => id,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Frame {
+    pub id: Id,
+    /// Whether this is a remote frame, which requests the frame with the same
+    /// identifier and has no data
+    pub remote: bool,
+    /// The number of data bytes, at most `MAX_DATA_LEN`
+    pub len: usize,
+    pub data: [u8; MAX_DATA_LEN],
+}
+
+/// An acceptance filter. A frame matches the filter if its identifier is of
+/// the same kind as `id`, and equal to it in every bit that is set in
+/// `mask`.
+#[derive(Copy, Clone, Debug)]
+pub struct Filter {
+    pub id: Id,
+    pub mask: u32,
+}
+
+impl Filter {
+    pub fn matches(&self, id: Id) -> bool {
+        match (self.id, id) {
+            (Id::Standard(_), Id::Standard(_)) | (Id::Extended(_), Id::Extended(_)) => {
+                (self.id.value() ^ id.value()) & self.mask == 0
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The fault confinement state of a controller, which depends on how many
+/// errors it has seen on the bus recently
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ErrorState {
+    /// The controller takes part in the bus normally
+    Active,
+    /// The controller has seen many errors, and no longer signals the errors
+    /// it detects
+    Passive,
+    /// The controller has seen too many errors while sending, and is
+    /// disconnected from the bus until it sees it idle for long enough
+    BusOff,
+}
+
+pub trait Can {
+    fn set_client(&self, client: &'static Client);
+
+    /// Set the bit rate of the bus, in bits per second. Returns EINVAL if the
+    /// controller cannot use this bit rate, and EBUSY if it is enabled.
+    fn set_bitrate(&self, bitrate: u32) -> ReturnCode;
+
+    /// Set the acceptance filters, replacing any that were set before. An
+    /// empty slice makes the controller accept every frame. Returns ESIZE if
+    /// the controller cannot use this many filters, and EBUSY if it is
+    /// enabled.
+    fn set_filters(&self, filters: &[Filter]) -> ReturnCode;
+
+    /// Connect the controller to the bus, so it can send and receive frames.
+    fn enable(&self) -> ReturnCode;
+
+    /// Disconnect the controller from the bus. A frame that is being sent is
+    /// not reported as sent.
+    fn disable(&self) -> ReturnCode;
+
+    /// Send a frame. The client is told when it has been sent. Returns EBUSY
+    /// if a frame is still being sent, EOFF if the controller is disabled,
+    /// and ESIZE if the frame is longer than `MAX_DATA_LEN`.
+    fn send(&self, frame: &Frame) -> ReturnCode;
+
+    /// The current error state of the controller.
+    fn error_state(&self) -> ErrorState;
+}
+
+pub trait Client {
+    /// A frame was sent, or could not be sent, for example because the
+    /// controller went bus-off.
+    fn frame_sent(&self, result: ReturnCode);
+
+    /// A frame that matches the acceptance filters was received.
+    fn frame_received(&self, frame: &Frame);
+
+    /// The error state of the controller changed.
+    fn error_state_changed(&self, state: ErrorState);
+}
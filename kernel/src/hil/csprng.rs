@@ -0,0 +1,23 @@
+//! Interface for cryptographically secure pseudorandom number generators
+//! (CSPRNGs).
+//!
+//! A CSPRNG stretches a seed from an entropy source, as provided by
+//! `hil::entropy`, into any number of random bytes that cannot be predicted
+//! without the seed, and from which earlier output cannot be recovered even
+//! if the state of the generator leaks. Unlike `hil::rng::Rng`, its output
+//! can be used for keys and nonces, for example by DTLS or for BLE private
+//! addresses. Unlike `hil::entropy`, it produces bytes synchronously, at the
+//! speed of the processor, once it is seeded, which matters on chips with
+//! slow TRNGs.
+
+use returncode::ReturnCode;
+
+pub trait Csprng {
+    /// Whether the generator collected enough entropy to produce random
+    /// bytes.
+    fn is_seeded(&self) -> bool;
+
+    /// Fill `buf` with random bytes. Returns EBUSY if the generator is still
+    /// collecting its seed, in which case `buf` is not changed.
+    fn fill_bytes(&self, buf: &mut [u8]) -> ReturnCode;
+}
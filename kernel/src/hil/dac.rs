@@ -9,4 +9,39 @@ pub trait DacChannel {
 
     /// Set the DAC output value.
     fn set_value(&self, value: usize) -> ReturnCode;
+
+    /// The number of bits of the output values, so values range from 0 to
+    /// `(1 << bits) - 1`.
+    fn get_resolution_bits(&self) -> usize;
+}
+
+/// Interface for outputting a buffer of values at a fixed rate, for example
+/// to generate a waveform.
+pub trait DacBuffered {
+    fn set_client(&self, client: &'static BufferedClient);
+
+    /// Output `buffer[..length]` one value after the other, `frequency`
+    /// values per second. With `repeat`, playback starts over at the end of
+    /// the buffer until `stop` is called. Returns EBUSY if a playback is in
+    /// progress, EINVAL if the frequency is not supported, and ESIZE if the
+    /// buffer is shorter than `length` or `length` is 0, in which case the
+    /// buffer is returned.
+    fn play(
+        &self,
+        buffer: &'static mut [u16],
+        length: usize,
+        frequency: u32,
+        repeat: bool,
+    ) -> (ReturnCode, Option<&'static mut [u16]>);
+
+    /// Stop a playback. The buffer is returned through `playback_done`
+    /// with ECANCEL. Returns EOFF if nothing is playing.
+    fn stop(&self) -> ReturnCode;
+}
+
+/// Trait for handling callbacks from buffered playback.
+pub trait BufferedClient {
+    /// Called when the last value of a buffer played without `repeat` was
+    /// output, or the playback was stopped, which `result` tells apart.
+    fn playback_done(&self, buffer: &'static mut [u16], result: ReturnCode);
 }
@@ -0,0 +1,70 @@
+//! Interface for message digests, such as SHA-256, and message
+//! authentication codes built on them, such as HMAC-SHA256.
+//!
+//! A message is added to the digest in parts, and the digest of all parts
+//! added since the last digest was computed is then computed with `run`, or
+//! compared with an expected digest with `verify`. Implementations that
+//! support several algorithms also implement the traits that select the
+//! algorithm, such as `Sha256` and `HmacSha256`.
+
+use returncode::ReturnCode;
+
+pub trait Digest {
+    fn set_client(&self, client: &'static Client);
+
+    /// The length of the digest, in bytes.
+    fn digest_len(&self) -> usize;
+
+    /// Add the first `length` bytes of `data` to the message. Returns EBUSY
+    /// if an operation is in progress and EINVAL if `length` is longer than
+    /// `data`. If an error occurs, the buffer is returned.
+    fn add_data(
+        &self,
+        data: &'static mut [u8],
+        length: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>);
+
+    /// Compute the digest of the message into `digest`, and start a new
+    /// message. Returns EBUSY if an operation is in progress and ESIZE if
+    /// `digest` is shorter than `digest_len`. If an error occurs, the buffer
+    /// is returned.
+    fn run(&self, digest: &'static mut [u8]) -> (ReturnCode, Option<&'static mut [u8]>);
+
+    /// Compute the digest of the message, compare it with the first
+    /// `digest_len` bytes of `compare`, and start a new message. Returns
+    /// EBUSY if an operation is in progress and ESIZE if `compare` is
+    /// shorter than `digest_len`. If an error occurs, the buffer is returned.
+    fn verify(&self, compare: &'static mut [u8]) -> (ReturnCode, Option<&'static mut [u8]>);
+
+    /// Drop the parts of the message added so far.
+    fn clear_data(&self);
+}
+
+/// Selects SHA-256 as the algorithm of a `Digest`.
+pub trait Sha256 {
+    /// Compute SHA-256 digests from the next message on. Returns EBUSY if
+    /// an operation is in progress.
+    fn set_mode_sha256(&self) -> ReturnCode;
+}
+
+/// Selects HMAC-SHA256 (RFC 2104) as the algorithm of a `Digest`.
+pub trait HmacSha256 {
+    /// Compute HMAC-SHA256 codes with `key` from the next message on.
+    /// Returns EBUSY if an operation is in progress and ESIZE if `key` is
+    /// longer than the implementation supports.
+    fn set_mode_hmacsha256(&self, key: &[u8]) -> ReturnCode;
+}
+
+pub trait Client {
+    /// The data in `data` was added to the message, unless `result` is an
+    /// error.
+    fn add_data_done(&self, result: ReturnCode, data: &'static mut [u8]);
+
+    /// The digest was computed into `digest`, unless `result` is an error.
+    fn hash_done(&self, result: ReturnCode, digest: &'static mut [u8]);
+
+    /// The digest was compared with `compare`. `result` is SUCCESS if they
+    /// are equal, FAIL if they differ, and another error if the digest could
+    /// not be computed.
+    fn verification_done(&self, result: ReturnCode, compare: &'static mut [u8]);
+}
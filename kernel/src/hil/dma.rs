@@ -0,0 +1,101 @@
+//! Interface for direct memory access (DMA) channels.
+//!
+//! A DMA channel moves a buffer between memory and a peripheral without the
+//! CPU. Each transfer is for one peripheral function, which the chip
+//! identifies with `DmaChannel::Peripheral`, for example the transmit or the
+//! receive side of a UART.
+//!
+//! A driver enables the channel, starts a transfer, and once its client is
+//! told the transfer is done, takes the buffer back with `abort_transfer()`
+//! and disables the channel. The same calls stop a transfer early.
+//!
+//! Some peripherals must be set up after the channel is armed but before the
+//! transfer starts. Their drivers arm the channel with
+//! `prepare_transfer_to_peripheral()` or `prepare_transfer_from_peripheral()`
+//! and start the transfer with `start_transfer()` once the peripheral is
+//! ready.
+//!
+//! The nRF5x UART, SPI and ADC drivers still program the EasyDMA engine of
+//! their peripheral directly. Moving them onto this interface is left to a
+//! follow-up change.
+
+/// Size of the elements a channel transfers.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Width {
+    Width8Bit,
+    Width16Bit,
+    Width32Bit,
+}
+
+pub trait DmaChannel {
+    /// The peripheral functions the channel can transfer data for.
+    type Peripheral: Copy;
+
+    /// Set the client that is told when a transfer is done.
+    fn set_client(&self, client: &'static Client<Self::Peripheral>);
+
+    /// Set the size of the elements the channel transfers. Transfer lengths
+    /// count elements, not bytes.
+    fn set_width(&self, width: Width);
+
+    /// Enable the channel before starting a transfer.
+    fn enable(&self);
+
+    /// Disable the channel once the buffer of the last transfer has been
+    /// taken back.
+    fn disable(&self);
+
+    fn is_enabled(&self) -> bool;
+
+    /// Start moving `len` elements of `buffer` to `peripheral`. The length
+    /// is truncated to the size of the buffer.
+    fn transfer_to_peripheral(
+        &self,
+        peripheral: Self::Peripheral,
+        buffer: &'static mut [u8],
+        len: usize,
+    );
+
+    /// Start moving `len` elements from `peripheral` into `buffer`. The
+    /// length is truncated to the size of the buffer.
+    fn transfer_from_peripheral(
+        &self,
+        peripheral: Self::Peripheral,
+        buffer: &'static mut [u8],
+        len: usize,
+    );
+
+    /// Like `transfer_to_peripheral()`, but the transfer only starts with
+    /// `start_transfer()`.
+    fn prepare_transfer_to_peripheral(
+        &self,
+        peripheral: Self::Peripheral,
+        buffer: &'static mut [u8],
+        len: usize,
+    );
+
+    /// Like `transfer_from_peripheral()`, but the transfer only starts with
+    /// `start_transfer()`.
+    fn prepare_transfer_from_peripheral(
+        &self,
+        peripheral: Self::Peripheral,
+        buffer: &'static mut [u8],
+        len: usize,
+    );
+
+    /// Start the transfer armed with one of the `prepare_transfer_*()`
+    /// functions.
+    fn start_transfer(&self);
+
+    /// Stop the current transfer, if any, and return its buffer.
+    fn abort_transfer(&self) -> Option<&'static mut [u8]>;
+
+    /// The number of elements the current transfer has left to move.
+    fn transfer_counter(&self) -> usize;
+}
+
+/// Implement `Client` to be told when transfers are done.
+pub trait Client<P> {
+    /// Called when the transfer for `peripheral` has moved all its elements.
+    fn transfer_done(&self, peripheral: P);
+}
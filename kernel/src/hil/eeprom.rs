@@ -0,0 +1,46 @@
+//! Interface for byte-addressable nonvolatile memory, such as EEPROM and
+//! FRAM.
+//!
+//! Unlike flash, this memory does not have to be erased before it is
+//! written, and any range of bytes can be read or written. Addresses start
+//! at 0. A device may transfer fewer bytes than asked for at once, in which
+//! case the client continues with the rest.
+
+use returncode::ReturnCode;
+
+pub trait Eeprom {
+    fn set_client(&self, client: &'static Client);
+
+    /// The size of the memory, in bytes.
+    fn size(&self) -> usize;
+
+    /// Read `length` bytes starting at `address` into `buffer`. Returns
+    /// EBUSY if an operation is in progress, ESIZE if `buffer` is shorter
+    /// than `length`, and EINVAL if the bytes extend past the end of the
+    /// memory. If an error occurs, the buffer is returned.
+    fn read(
+        &self,
+        address: usize,
+        buffer: &'static mut [u8],
+        length: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>);
+
+    /// Write the first `length` bytes of `buffer` starting at `address`. The
+    /// return values are the same as for `read`.
+    fn write(
+        &self,
+        address: usize,
+        buffer: &'static mut [u8],
+        length: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>);
+}
+
+pub trait Client {
+    /// The first `length` bytes of `buffer` were read, unless `result` is an
+    /// error. `length` can be less than asked for.
+    fn read_done(&self, buffer: &'static mut [u8], length: usize, result: ReturnCode);
+
+    /// The first `length` bytes of `buffer` were written, unless `result` is
+    /// an error. `length` can be less than asked for.
+    fn write_done(&self, buffer: &'static mut [u8], length: usize, result: ReturnCode);
+}
@@ -0,0 +1,78 @@
+//! Interface for Ethernet MACs.
+//!
+//! A MAC sends and receives Ethernet frames, starting with the destination
+//! and source addresses and the EtherType, and without the frame check
+//! sequence, which the MAC adds and checks itself. It only passes the client
+//! the frames that its filter accepts.
+
+use returncode::ReturnCode;
+
+/// The longest frame, without the frame check sequence
+pub const MAX_FRAME_LEN: usize = 1514;
+
+/// The length of the header of a frame: the destination address, the source
+/// address, and the EtherType
+pub const HEADER_LEN: usize = 14;
+
+pub type MacAddress = [u8; 6];
+
+/// The address that every MAC receives frames for
+pub const BROADCAST_ADDRESS: MacAddress = [0xff; 6];
+
+/// Which received frames the MAC passes to the client
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Filter {
+    /// Accept frames sent to the address of the MAC
+    pub unicast: bool,
+    /// Accept frames sent to a multicast address
+    pub multicast: bool,
+    /// Accept frames sent to the broadcast address
+    pub broadcast: bool,
+    /// Accept every frame, whatever the other fields are
+    pub promiscuous: bool,
+}
+
+impl Default for Filter {
+    fn default() -> Filter {
+        Filter {
+            unicast: true,
+            multicast: true,
+            broadcast: true,
+            promiscuous: false,
+        }
+    }
+}
+
+pub trait Ethernet {
+    fn set_client(&self, client: &'static Client);
+
+    /// Set the address of the MAC. Returns EBUSY if it is enabled.
+    fn set_address(&self, address: MacAddress) -> ReturnCode;
+
+    fn get_address(&self) -> MacAddress;
+
+    /// Set which received frames are passed to the client. Returns EBUSY if
+    /// the MAC is enabled.
+    fn set_filter(&self, filter: Filter) -> ReturnCode;
+
+    /// Start sending and receiving frames.
+    fn enable(&self) -> ReturnCode;
+
+    /// Stop sending and receiving frames. A frame that is being sent is not
+    /// reported as sent.
+    fn disable(&self) -> ReturnCode;
+
+    /// Send a frame, which is copied before this returns. The client is told
+    /// when it has been sent. Returns EBUSY if a frame is still being sent,
+    /// EOFF if the MAC is disabled, and ESIZE if the frame is shorter than a
+    /// header or longer than `MAX_FRAME_LEN`.
+    fn transmit(&self, frame: &[u8]) -> ReturnCode;
+}
+
+pub trait Client {
+    /// A frame was sent, or could not be sent.
+    fn transmit_done(&self, result: ReturnCode);
+
+    /// A frame that the filter accepts was received.
+    fn frame_received(&self, frame: &[u8]);
+}
@@ -1,6 +1,8 @@
 //! Interface for I2C master and slave peripherals.
 
 use core::fmt::{Display, Formatter, Result};
+use hil::gpio;
+use returncode::ReturnCode;
 
 /// The type of error encoutered during I2C communication.
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -21,6 +23,11 @@ pub enum Error {
     /// from the receive register.
     Overrun,
 
+    /// SDA or SCL is held low, most likely by a slave that was interrupted
+    /// in the middle of a byte, so no transfer can take place.
+    /// `I2CMaster::recover_bus` may free the bus.
+    BusStuck,
+
     /// No error occured and the command completed successfully.
     CommandComplete,
 }
@@ -32,6 +39,7 @@ impl Display for Error {
             Error::DataNak => "I2C Data Not Acknowledged",
             Error::ArbitrationLost => "I2C Bus Arbitration Lost",
             Error::Overrun => "I2C receive overrun",
+            Error::BusStuck => "I2C Bus Held Low",
             Error::CommandComplete => "I2C Command Completed",
         };
         write!(fmt, "{}", display_str)
@@ -52,9 +60,69 @@ pub trait I2CMaster {
     fn write_read(&self, addr: u8, data: &'static mut [u8], write_len: u8, read_len: u8);
     fn write(&self, addr: u8, data: &'static mut [u8], len: u8);
     fn read(&self, addr: u8, buffer: &'static mut [u8], len: u8);
+
+    /// Try to free a bus that a slave holds low, for example after a
+    /// transfer completed with `Error::BusStuck`. This must not be called
+    /// during a transfer.
+    ///
+    /// Returns SUCCESS if the bus is free afterwards, or
+    ///
+    /// - FAIL: The bus is still held low.
+    /// - ENOSUPPORT: The driver cannot recover the bus.
+    fn recover_bus(&self) -> ReturnCode {
+        ReturnCode::ENOSUPPORT
+    }
+}
+
+/// Frees a stuck bus by driving its lines as GPIO pins, for drivers of
+/// controllers that can't do it themselves.
+///
+/// A slave that was interrupted while sending a byte holds SDA low until it
+/// has clocked out the rest of it. This pulses SCL up to nine times until
+/// SDA is released, then sends a stop condition. The pins are used like open
+/// drain outputs, being either driven low or left as inputs for the pull-ups
+/// of the bus, and must not be connected to the controller meanwhile.
+/// `half_period` waits for half a clock period, at most 5 us for 100 kHz.
+///
+/// Returns whether both lines are high afterwards.
+pub fn recover_bus_with_gpio(scl: &gpio::Pin, sda: &gpio::Pin, half_period: &Fn()) -> bool {
+    scl.make_input();
+    sda.make_input();
+    half_period();
+
+    for _ in 0..9 {
+        if sda.read() {
+            break;
+        }
+        scl.clear();
+        scl.make_output();
+        half_period();
+        scl.make_input();
+        half_period();
+    }
+
+    // Stop condition: SDA rises while SCL is high
+    scl.clear();
+    scl.make_output();
+    sda.clear();
+    sda.make_output();
+    half_period();
+    scl.make_input();
+    half_period();
+    sda.make_input();
+    half_period();
+
+    scl.read() && sda.read()
 }
 
 /// Interface for an I2C Slave hardware driver.
+///
+/// Once `listen` is called, the slave acknowledges its address. When a
+/// master writes to it, the bytes go into the buffer passed to
+/// `write_receive`, and when a master reads from it, it sends the bytes of
+/// the buffer passed to `read_send`. If there is no buffer when its address
+/// matches, the client is asked for one with `read_expected` or
+/// `write_expected`, and the slave stretches the clock until it gets it.
 pub trait I2CSlave {
     fn enable(&self);
     fn disable(&self);
@@ -62,6 +130,16 @@ pub trait I2CSlave {
     fn write_receive(&self, data: &'static mut [u8], max_len: u8);
     fn read_send(&self, data: &'static mut [u8], max_len: u8);
     fn listen(&self);
+
+    /// Set whether the slave holds SCL low while it waits for a buffer,
+    /// which it does by default. Without clock stretching, a master that
+    /// addresses the slave before it has a buffer reads filler bytes or has
+    /// its bytes dropped. Takes effect on the next call to `listen`.
+    ///
+    /// Returns SUCCESS, or ENOSUPPORT if the hardware can't change it.
+    fn set_clock_stretching(&self, _stretch: bool) -> ReturnCode {
+        ReturnCode::ENOSUPPORT
+    }
 }
 
 /// Convenience type for capsules that need hardware that supports both
@@ -77,6 +155,11 @@ pub trait I2CHwMasterClient {
 
 /// Client interface for capsules that use I2CSlave devices.
 pub trait I2CHwSlaveClient {
+    /// Called when a master addressed this slave, before any data is
+    /// transferred. This is followed by `read_expected` or
+    /// `write_expected` if there is no buffer for the transfer.
+    fn address_matched(&self, _transmission_type: SlaveTransmissionType) {}
+
     /// Called when an I2C command completed.
     fn command_complete(
         &self,
@@ -0,0 +1,89 @@
+//! Interface for I2S digital audio controllers.
+//!
+//! An I2S controller streams 16-bit PCM samples to or from an audio device,
+//! such as a codec, an amplifier, or a microphone, one direction at a time.
+//! Samples are double buffered: the controller is given two buffers when it
+//! starts, and passes each buffer back to the client once it has been sent
+//! or filled. The client then provides the next buffer with
+//! `provide_buffer`, while the controller works on the other one. Stereo
+//! samples are interleaved, starting with the left channel.
+
+use returncode::ReturnCode;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Channels {
+    /// Only the left channel
+    Mono,
+    Stereo,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Config {
+    /// Frames per second
+    pub sample_rate: u32,
+    pub channels: Channels,
+}
+
+pub trait I2S {
+    fn set_client(&self, client: &'static Client);
+
+    /// Set the format of the samples. Returns EINVAL if the controller
+    /// cannot produce the sample rate or the channels, and EBUSY if it is
+    /// streaming.
+    fn configure(&self, config: Config) -> ReturnCode;
+
+    /// The sample rate that the controller actually produces, which can
+    /// differ slightly from the configured one.
+    fn sample_rate(&self) -> u32;
+
+    /// Start sending the samples in `buffer1`, followed by those in
+    /// `buffer2`. Returns EBUSY if the controller is streaming, and EINVAL
+    /// if it cannot use the buffers, for example because their lengths
+    /// differ. If an error occurs, the buffers are returned.
+    fn transmit(
+        &self,
+        buffer1: &'static mut [i16],
+        buffer2: &'static mut [i16],
+    ) -> (
+        ReturnCode,
+        Option<&'static mut [i16]>,
+        Option<&'static mut [i16]>,
+    );
+
+    /// Start receiving samples into `buffer1`, followed by `buffer2`. The
+    /// return values are the same as for `transmit`.
+    fn receive(
+        &self,
+        buffer1: &'static mut [i16],
+        buffer2: &'static mut [i16],
+    ) -> (
+        ReturnCode,
+        Option<&'static mut [i16]>,
+        Option<&'static mut [i16]>,
+    );
+
+    /// Provide the next buffer to send or fill, which must be as long as the
+    /// buffers the stream was started with. Expected to be called in a
+    /// `buffer_done` callback. If no buffer is provided before the
+    /// controller finishes its current one, samples are repeated or lost.
+    /// Returns EOFF if the controller is not streaming, EBUSY if it already
+    /// holds two buffers, and EINVAL if it cannot use the buffer. If an
+    /// error occurs, the buffer is returned.
+    fn provide_buffer(
+        &self,
+        buffer: &'static mut [i16],
+    ) -> (ReturnCode, Option<&'static mut [i16]>);
+
+    /// Stop streaming. The client is told once the controller has stopped.
+    /// Returns EOFF if the controller is not streaming.
+    fn stop(&self) -> ReturnCode;
+}
+
+pub trait Client {
+    /// All samples of `buffer` were sent, or `buffer` is full of received
+    /// samples.
+    fn buffer_done(&self, buffer: &'static mut [i16]);
+
+    /// The controller stopped, and returns the buffers it still held.
+    fn stopped(&self, buffer1: Option<&'static mut [i16]>, buffer2: Option<&'static mut [i16]>);
+}
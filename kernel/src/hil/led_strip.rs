@@ -0,0 +1,31 @@
+//! Interface for addressable LED strips, such as WS2812 ("NeoPixel") LEDs.
+//!
+//! The LEDs of a strip are chained, and take their colors all at once: the
+//! user passes a buffer with the color of each LED, three bytes for red,
+//! green and blue in that order, and the driver shifts them out to the strip
+//! in the order and timing the LEDs need. The LEDs keep showing their colors
+//! until the next `show`.
+
+use returncode::ReturnCode;
+
+pub trait LedStrip {
+    fn set_client(&self, client: &'static Client);
+
+    /// The number of LEDs the driver can show.
+    fn led_count(&self) -> usize;
+
+    /// Shows the colors of the first `count` LEDs in `colors`. Returns EBUSY
+    /// if a `show` is in progress or the hardware is in use otherwise, and
+    /// EINVAL if `count` is 0 or more than `led_count`, or `colors` is too
+    /// short for it. If an error occurs, the buffer is returned.
+    fn show(
+        &self,
+        colors: &'static mut [u8],
+        count: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>);
+}
+
+pub trait Client {
+    /// The colors in `colors` were shown, unless `result` is an error.
+    fn show_done(&self, colors: &'static mut [u8], result: ReturnCode);
+}
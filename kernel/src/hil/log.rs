@@ -0,0 +1,95 @@
+//! Interface for persistent logs of entries.
+//!
+//! A log stores entries, each a sequence of bytes, in the order they were
+//! appended. Each entry is identified by an entry ID, which grows with each
+//! entry appended, so that a reader can tell where it is in the log. A log
+//! has a read position, the ID of the next entry to read, which moves past
+//! each entry read and can be moved with `seek`.
+//!
+//! A log can be circular, in which case the oldest entries are overwritten
+//! when it is full, or linear, in which case appending to a full log fails.
+
+use returncode::ReturnCode;
+
+pub trait LogRead {
+    fn set_read_client(&self, client: &'static LogReadClient);
+
+    /// The ID of the oldest entry in the log.
+    fn log_start(&self) -> usize;
+
+    /// The ID the next entry appended will get.
+    fn log_end(&self) -> usize;
+
+    /// The ID of the next entry to read.
+    fn next_read_entry_id(&self) -> usize;
+
+    /// Read the next entry into `buffer`, which holds `length` bytes. Returns
+    /// EOFF if the log is not ready, EBUSY if an operation is in progress, and
+    /// EINVAL if `length` is longer than `buffer`. If an error occurs, the
+    /// buffer is returned.
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        length: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>);
+
+    /// Move the read position to the entry `entry_id`, which must be an ID
+    /// returned by the log. Returns EINVAL if it is not between the start and
+    /// the end of the log, and EBUSY if an operation is in progress.
+    fn seek(&self, entry_id: usize) -> ReturnCode;
+
+    /// The number of bytes the log can hold, including the overhead of its
+    /// entries.
+    fn get_size(&self) -> usize;
+}
+
+pub trait LogReadClient {
+    /// The next entry was read into `buffer`, and is `length` bytes long.
+    /// `result` is FAIL if there was no entry to read, and ESIZE if the entry
+    /// is longer than the buffer, in which case the read position stays at
+    /// the entry and `length` is its length.
+    fn read_done(&self, buffer: &'static mut [u8], length: usize, result: ReturnCode);
+}
+
+pub trait LogWrite {
+    fn set_append_client(&self, client: &'static LogWriteClient);
+
+    /// Append the first `length` bytes of `buffer` as an entry. Returns EOFF
+    /// if the log is not ready, EBUSY if an operation is in progress, EINVAL
+    /// if `length` is longer than `buffer`, ESIZE if the entry is empty or
+    /// longer than the log can store, and ENOMEM if a linear log is full. If
+    /// an error occurs, the buffer is returned.
+    ///
+    /// An appended entry can be read at once, but may be lost if the board
+    /// resets before the log is synced.
+    fn append(
+        &self,
+        buffer: &'static mut [u8],
+        length: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>);
+
+    /// Make sure that all entries appended so far are kept in storage.
+    fn sync(&self) -> ReturnCode;
+
+    /// Remove all entries from the log.
+    fn erase(&self) -> ReturnCode;
+}
+
+pub trait LogWriteClient {
+    /// The entry in `buffer`, `length` bytes long, was appended.
+    /// `records_lost` is true if older entries were overwritten to make room
+    /// for it.
+    fn append_done(
+        &self,
+        buffer: &'static mut [u8],
+        length: usize,
+        records_lost: bool,
+        result: ReturnCode,
+    );
+
+    /// The log was synced.
+    fn sync_done(&self, result: ReturnCode);
+
+    /// The log was erased.
+    fn erase_done(&self, result: ReturnCode);
+}
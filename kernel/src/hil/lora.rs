@@ -0,0 +1,95 @@
+//! Interface for LoRa radios.
+//!
+//! A LoRa radio sends and receives packets of up to 255 bytes with the
+//! explicit header and CRC of the LoRa physical layer. The modulation and
+//! frequency are set with `configure`, and apply to the next transmission
+//! or reception. Between them the radio sleeps.
+
+use returncode::ReturnCode;
+
+/// The longest payload of a packet
+pub const MAX_PAYLOAD_LEN: usize = 255;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Bandwidth {
+    Khz125,
+    Khz250,
+    Khz500,
+}
+
+impl Bandwidth {
+    pub fn hz(&self) -> u32 {
+        match *self {
+            Bandwidth::Khz125 => 125_000,
+            Bandwidth::Khz250 => 250_000,
+            Bandwidth::Khz500 => 500_000,
+        }
+    }
+}
+
+/// The forward error correction rate
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CodingRate {
+    Cr4_5,
+    Cr4_6,
+    Cr4_7,
+    Cr4_8,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Config {
+    /// Carrier frequency in Hz
+    pub frequency: u32,
+    /// Spreading factor, from 6 to 12
+    pub spreading_factor: u8,
+    pub bandwidth: Bandwidth,
+    pub coding_rate: CodingRate,
+    /// Transmit power in dBm
+    pub tx_power: i8,
+    /// Number of preamble symbols
+    pub preamble_len: u16,
+    /// The sync word, which separates networks: 0x34 for LoRaWAN, 0x12 for
+    /// private networks
+    pub sync_word: u8,
+    /// Whether the I and Q signals are inverted, as they are for downlinks
+    /// in LoRaWAN
+    pub invert_iq: bool,
+}
+
+impl Config {
+    /// The length of a symbol in microseconds
+    pub fn symbol_us(&self) -> u32 {
+        ((1 << self.spreading_factor) as u64 * 1_000_000 / self.bandwidth.hz() as u64) as u32
+    }
+}
+
+pub trait Lora {
+    fn set_client(&self, client: &'static Client);
+
+    /// Set the modulation and frequency of the next transmissions and
+    /// receptions. Returns EINVAL if the radio does not support them, and
+    /// EBUSY if it is sending or receiving.
+    fn configure(&self, config: Config) -> ReturnCode;
+
+    /// Send a packet, which is copied before this returns. Returns EBUSY if
+    /// the radio is sending or receiving, and ESIZE if the payload is longer
+    /// than `MAX_PAYLOAD_LEN`.
+    fn transmit(&self, payload: &[u8]) -> ReturnCode;
+
+    /// Receive a single packet. The radio gives up if it does not detect a
+    /// preamble within `symbol_timeout` symbols. Returns EBUSY if the radio
+    /// is sending or receiving, and EINVAL if the radio does not support the
+    /// timeout.
+    fn receive(&self, symbol_timeout: u16) -> ReturnCode;
+}
+
+pub trait Client {
+    /// A packet was sent, or could not be sent.
+    fn transmit_done(&self, result: ReturnCode);
+
+    /// A reception ended. The result is SUCCESS if a packet was received,
+    /// FAIL if its CRC was wrong, and ECANCEL if no packet was detected
+    /// before the timeout. `rssi` is the signal strength in dBm, and `snr`
+    /// the signal to noise ratio in dB, of the packet.
+    fn receive_done(&self, payload: &[u8], rssi: i16, snr: i8, result: ReturnCode);
+}
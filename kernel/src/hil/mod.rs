@@ -3,21 +3,43 @@
 pub mod adc;
 pub mod analog_comparator;
 pub mod ble_advertising;
+pub mod block_storage;
+pub mod can;
 pub mod crc;
+pub mod csprng;
 pub mod dac;
+pub mod digest;
+pub mod dma;
+pub mod eeprom;
 pub mod entropy;
+pub mod ethernet;
 pub mod flash;
 pub mod gpio;
 pub mod gpio_async;
 pub mod i2c;
+pub mod i2s;
 pub mod led;
+pub mod led_strip;
+pub mod log;
+pub mod lora;
+pub mod nonvolatile_counter;
 pub mod nonvolatile_storage;
+pub mod onewire;
+pub mod pdm;
+pub mod public_key_crypto;
+pub mod pwm;
+pub mod qdec;
+pub mod qspi;
 pub mod radio;
+pub mod radio_test;
 pub mod rng;
+pub mod screen;
 pub mod sensors;
+pub mod sniffer;
 pub mod spi;
 pub mod symmetric_encryption;
 pub mod time;
+pub mod touch;
 pub mod uart;
 pub mod usb;
 pub mod watchdog;
@@ -0,0 +1,26 @@
+//! Interface for counters kept in nonvolatile memory.
+//!
+//! A nonvolatile counter only counts up and keeps its value across resets,
+//! for example for sequence numbers or for counting failed unlock attempts.
+//! A value returned by an increment is never returned again, even if the
+//! board loses power while the counter is written.
+
+use returncode::ReturnCode;
+
+pub trait NonvolatileCounter {
+    fn set_client(&self, client: &'static NonvolatileCounterClient);
+
+    /// The value of the counter, or None if it was not yet read from
+    /// storage.
+    fn get(&self) -> Option<usize>;
+
+    /// Add one to the counter. Returns EOFF if the counter was not yet read
+    /// from storage, and ESIZE if it reached its largest value.
+    fn increment(&self) -> ReturnCode;
+}
+
+pub trait NonvolatileCounterClient {
+    /// The counter was incremented to `value` and stored. If `result` is not
+    /// SUCCESS, the counter was not changed and `value` is its old value.
+    fn increment_done(&self, value: usize, result: ReturnCode);
+}
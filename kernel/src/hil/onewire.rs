@@ -0,0 +1,83 @@
+//! Interface for a 1-Wire bus master.
+//!
+//! A 1-Wire bus connects devices over a single open-drain data line. Every
+//! transaction starts with a reset, to which devices answer with a presence
+//! pulse, followed by a ROM command that selects one device, or all of them,
+//! and then commands of the device. Bytes are sent least significant bit
+//! first.
+//!
+//! Each device has a unique 64-bit ROM code: a family code in the lowest
+//! byte, a 48-bit serial number, and a CRC-8 of both in the highest byte.
+//! `search` finds the ROM codes of the devices on the bus one at a time.
+
+use returncode::ReturnCode;
+
+/// Selects the device whose ROM code follows.
+pub const MATCH_ROM: u8 = 0x55;
+/// Selects all devices, or the only one on the bus.
+pub const SKIP_ROM: u8 = 0xCC;
+/// Starts a ROM search.
+pub const SEARCH_ROM: u8 = 0xF0;
+
+pub trait OneWire {
+    fn set_client(&self, client: &'static Client);
+
+    /// Resets the bus. Returns EBUSY if an operation is in progress.
+    fn reset(&self) -> ReturnCode;
+
+    /// Writes the first `len` bytes of `buffer`. Returns EBUSY if an
+    /// operation is in progress and ESIZE if `buffer` is shorter than `len`,
+    /// in which case the buffer is returned.
+    fn write(
+        &self,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>);
+
+    /// Reads `len` bytes into `buffer`. The return values are the same as
+    /// for `write`.
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>);
+
+    /// Finds the ROM code of the next device on the bus, or of the first one
+    /// if `restart` is true. The search includes the reset and the Search
+    /// ROM command, and the device found is not selected afterwards.
+    fn search(&self, restart: bool) -> ReturnCode;
+}
+
+pub trait Client {
+    /// The bus was reset, and `present` is whether a device answered.
+    fn reset_done(&self, present: bool);
+
+    fn write_done(&self, buffer: &'static mut [u8], result: ReturnCode);
+
+    fn read_done(&self, buffer: &'static mut [u8], result: ReturnCode);
+
+    /// A search finished. `rom` is the ROM code found, unless `result` is
+    /// ENODEVICE because no device answered, or FAIL because the code read
+    /// was corrupted. `last` is whether all devices were found, so the next
+    /// search starts over.
+    fn search_done(&self, rom: u64, last: bool, result: ReturnCode);
+}
+
+/// The Dallas/Maxim CRC-8 (polynomial x^8 + x^5 + x^4 + 1, reflected) that
+/// protects ROM codes and device data. The CRC of data followed by its CRC
+/// is 0.
+pub fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &byte in data {
+        let mut byte = byte;
+        for _ in 0..8 {
+            let mix = (crc ^ byte) & 1;
+            crc >>= 1;
+            if mix != 0 {
+                crc ^= 0x8C;
+            }
+            byte >>= 1;
+        }
+    }
+    crc
+}
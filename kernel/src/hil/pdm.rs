@@ -0,0 +1,63 @@
+//! Interface for PDM microphone controllers.
+//!
+//! A PDM controller clocks a digital microphone and filters its pulse
+//! density modulated output into 16-bit PCM samples, of a single channel.
+//! Samples are delivered in fixed-size frames, which are double buffered: the
+//! controller is given two buffers when it starts, and passes each buffer
+//! back to the client once it is full. The client then provides the next
+//! buffer with `provide_buffer`, while the controller fills the other one.
+
+use returncode::ReturnCode;
+
+pub trait Pdm {
+    fn set_client(&self, client: &'static Client);
+
+    /// Set the sample rate, in samples per second. Returns EINVAL if the
+    /// controller cannot produce the sample rate, and EBUSY if it is
+    /// sampling.
+    fn set_sample_rate(&self, sample_rate: u32) -> ReturnCode;
+
+    /// The sample rate that the controller actually produces, which can
+    /// differ slightly from the configured one.
+    fn sample_rate(&self) -> u32;
+
+    /// Start filling `buffer1` with samples, followed by `buffer2`. The length
+    /// of the buffers is the length of every frame. Returns EBUSY if the
+    /// controller is sampling, and EINVAL if it cannot use the buffers, for
+    /// example because their lengths differ. If an error occurs, the buffers
+    /// are returned.
+    fn start(
+        &self,
+        buffer1: &'static mut [i16],
+        buffer2: &'static mut [i16],
+    ) -> (
+        ReturnCode,
+        Option<&'static mut [i16]>,
+        Option<&'static mut [i16]>,
+    );
+
+    /// Provide the next buffer to fill, which must be as long as the buffers
+    /// the controller was started with. Expected to be called in a
+    /// `frame_done` callback. If no buffer is provided before the controller
+    /// fills its current one, that frame is overwritten. Returns EOFF if the
+    /// controller is not sampling, EBUSY if it already holds two buffers, and
+    /// EINVAL if it cannot use the buffer. If an error occurs, the buffer is
+    /// returned.
+    fn provide_buffer(
+        &self,
+        buffer: &'static mut [i16],
+    ) -> (ReturnCode, Option<&'static mut [i16]>);
+
+    /// Stop sampling. The client is told once the controller has stopped.
+    /// Returns EOFF if the controller is not sampling.
+    fn stop(&self) -> ReturnCode;
+}
+
+pub trait Client {
+    /// `buffer` is full of samples.
+    fn frame_done(&self, buffer: &'static mut [i16]);
+
+    /// The controller stopped, and returns the buffers it still held. Their
+    /// samples are not complete.
+    fn stopped(&self, buffer1: Option<&'static mut [i16]>, buffer2: Option<&'static mut [i16]>);
+}
@@ -0,0 +1,3 @@
+//! Interfaces for public key cryptography.
+
+pub mod signature;
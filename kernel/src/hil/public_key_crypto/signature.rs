@@ -0,0 +1,90 @@
+//! Interface for verifying and making digital signatures, such as ECDSA and
+//! Ed25519 signatures.
+//!
+//! The formats of keys, messages and signatures depend on the algorithm:
+//!
+//! - For ECDSA with the P-256 curve, a public key is the big-endian X and Y
+//!   coordinates of the public point, 64 bytes, and a signature is the
+//!   big-endian R and S values, 64 bytes. The signature is of the SHA-256
+//!   digest of the message, which is computed first with a
+//!   `hil::digest::Digest`, so `hash` holds the 32-byte digest.
+//! - For Ed25519, a public key is the 32-byte encoding of the public point,
+//!   and a signature is 64 bytes. Ed25519 hashes the message itself, so
+//!   `hash` holds the whole message.
+//!
+//! Chips with a public key accelerator implement these traits on it, and
+//! `capsules::signature::SoftwareSignatureVerify` implements verification in
+//! software for the others.
+
+use returncode::ReturnCode;
+
+/// Signature algorithms.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SignatureAlgorithm {
+    /// Ed25519, as described in RFC 8032.
+    Ed25519,
+    /// ECDSA on the NIST P-256 curve, of the SHA-256 digest of the message.
+    EcdsaNistP256,
+}
+
+pub trait SignatureVerify {
+    fn set_client(&self, client: &'static SignatureVerifyClient);
+
+    /// Check signatures with `algorithm` from the next verification on.
+    /// Returns EBUSY if a verification is in progress and ENOSUPPORT if the
+    /// implementation does not support `algorithm`.
+    fn set_algorithm(&self, algorithm: SignatureAlgorithm) -> ReturnCode;
+
+    /// Check that `signature` is a signature of the message with digest
+    /// `hash` by the holder of `key`. Returns EBUSY if a verification is in
+    /// progress and EINVAL if the key, digest or signature has the wrong
+    /// length. If an error occurs, the buffers are returned.
+    fn verify(
+        &self,
+        key: &'static [u8],
+        hash: &'static mut [u8],
+        signature: &'static mut [u8],
+    ) -> (ReturnCode, Option<(&'static mut [u8], &'static mut [u8])>);
+}
+
+pub trait SignatureVerifyClient {
+    /// The verification ended. `result` is SUCCESS if the signature is
+    /// valid, and FAIL if it is not.
+    fn verification_done(
+        &self,
+        result: ReturnCode,
+        hash: &'static mut [u8],
+        signature: &'static mut [u8],
+    );
+}
+
+/// Makes signatures with a private key the implementation holds, for
+/// example in a key slot of a crypto accelerator, so that the key is never
+/// in memory the kernel can read. Implementations that cannot keep a key
+/// secret do not implement this trait.
+pub trait SignatureSign {
+    fn set_sign_client(&self, client: &'static SignatureSignClient);
+
+    /// Sign the message with digest `hash` into `signature`, with the
+    /// algorithm set with `SignatureVerify::set_algorithm`. Returns EBUSY if
+    /// an operation is in progress, EINVAL if the digest has the wrong
+    /// length, ESIZE if `signature` is too short for a signature, and
+    /// ENOSUPPORT if no key is provisioned. If an error occurs, the buffers
+    /// are returned.
+    fn sign(
+        &self,
+        hash: &'static mut [u8],
+        signature: &'static mut [u8],
+    ) -> (ReturnCode, Option<(&'static mut [u8], &'static mut [u8])>);
+}
+
+pub trait SignatureSignClient {
+    /// The signature was made into `signature`, unless `result` is an
+    /// error.
+    fn signing_done(
+        &self,
+        result: ReturnCode,
+        hash: &'static mut [u8],
+        signature: &'static mut [u8],
+    );
+}
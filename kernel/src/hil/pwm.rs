@@ -0,0 +1,58 @@
+//! Interface for pulse width modulation.
+//!
+//! A `Pwm` is a group of channels that share a frequency, such as the outputs
+//! of one timer. Each channel has its own duty cycle, from 0 (always low) to
+//! `get_maximum_duty_cycle()` (always high), which implementations scale to
+//! the resolution the frequency leaves them. Duty cycles changed together
+//! with `set_duty_cycles` take effect in the same period, so for example the
+//! phases of a motor never see a mix of old and new values.
+//!
+//! Channels are paired, 0 with 1, 2 with 3 and so on. An odd channel can be
+//! made complementary, so it outputs the inverse of the even channel before
+//! it, for example to drive both switches of a half bridge.
+
+use returncode::ReturnCode;
+
+pub trait Pwm {
+    /// The number of channels in the group.
+    fn channel_count(&self) -> usize;
+
+    fn get_maximum_frequency_hz(&self) -> usize;
+
+    /// The duty cycle of a channel that is always high.
+    fn get_maximum_duty_cycle(&self) -> usize;
+
+    /// Starts the outputs at `frequency_hz`, or changes their frequency if
+    /// they are running. All duty cycles start at 0. Returns the frequency
+    /// actually produced, which can differ slightly from the requested one,
+    /// as `SuccessWithValue`, or EINVAL if the frequency is not supported.
+    fn start(&self, frequency_hz: usize) -> ReturnCode;
+
+    /// Stops the outputs, which are then low. Returns EOFF if they were not
+    /// running.
+    fn stop(&self) -> ReturnCode;
+
+    /// Sets the duty cycle of a channel. Returns EOFF if the outputs are not
+    /// running, and EINVAL if the channel does not exist or is
+    /// complementary, or if the duty cycle is above the maximum.
+    fn set_duty_cycle(&self, channel: usize, duty_cycle: usize) -> ReturnCode {
+        let mut duty_cycles = [None; 8];
+        if channel >= self.channel_count() || channel >= duty_cycles.len() {
+            return ReturnCode::EINVAL;
+        }
+        duty_cycles[channel] = Some(duty_cycle);
+        self.set_duty_cycles(&duty_cycles[..channel + 1])
+    }
+
+    /// Sets the duty cycles of the channels whose entries are `Some`, with
+    /// channel `i` in `duty_cycles[i]`, so they change in the same period.
+    /// The return values are the same as for `set_duty_cycle`, and on error
+    /// no duty cycle changes.
+    fn set_duty_cycles(&self, duty_cycles: &[Option<usize>]) -> ReturnCode;
+
+    /// Makes the odd `channel` output the inverse of the channel before it,
+    /// or an independent channel again with a duty cycle of 0. Returns
+    /// EINVAL if the channel does not exist or is even, and ENOSUPPORT if
+    /// the hardware cannot pair it.
+    fn set_complementary(&self, channel: usize, complementary: bool) -> ReturnCode;
+}
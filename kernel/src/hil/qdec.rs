@@ -0,0 +1,42 @@
+//! Interface for quadrature decoders.
+//!
+//! A quadrature decoder counts the transitions of the two outputs of an
+//! incremental encoder, A and B, which are a quarter of a cycle apart, so
+//! the count goes up in one direction and down in the other. A full cycle of
+//! the encoder is four counts. Encoders with an index output pulse it once
+//! per revolution, so the position can be referenced to a mark.
+//!
+//! The decoder samples the outputs in hardware and reports changes of the
+//! position periodically, so the position is known without polling pins.
+//! Speed is the change of position over time, which users measure from the
+//! reports or by reading the position with an alarm.
+
+use returncode::ReturnCode;
+
+pub trait Qdec {
+    fn set_client(&self, client: &'static Client);
+
+    /// Starts counting. Returns EALREADY if the decoder is enabled.
+    fn enable(&self) -> ReturnCode;
+
+    /// Stops counting. The position is kept. Returns EOFF if the decoder is
+    /// not enabled.
+    fn disable(&self) -> ReturnCode;
+
+    fn is_enabled(&self) -> bool;
+
+    /// The position, in counts. It wraps around on overflow.
+    fn get_position(&self) -> i32;
+
+    /// Sets the current position, for example to 0 at the index mark.
+    fn set_position(&self, position: i32);
+}
+
+pub trait Client {
+    /// The position changed to `position`. Called at most once per report
+    /// period of the decoder, and not while the encoder stands still.
+    fn position_changed(&self, position: i32);
+
+    /// The encoder passed its index mark, at `position`.
+    fn index(&self, position: i32);
+}
@@ -0,0 +1,81 @@
+//! Interface for QSPI controllers of external NOR flash.
+//!
+//! A QSPI controller talks to a flash chip over four data lines, and issues
+//! the flash's read, page program and erase instructions itself, including
+//! enabling writes before programming and waiting for the flash to finish.
+//! Addresses are offsets into the flash.
+//!
+//! Many controllers can also map the flash into the address space, so that
+//! it can be read, and code executed from it in place (XIP), with ordinary
+//! loads. Reads of the mapped flash wait while it is being programmed or
+//! erased, and the controller takes care that no stale contents of the
+//! flash are read through caches afterwards.
+
+use returncode::ReturnCode;
+
+/// The most bytes programmed at once, which must not cross a multiple of
+/// this size
+pub const PAGE_SIZE: usize = 256;
+
+/// The fewest bytes erased at once
+pub const SECTOR_SIZE: usize = 4096;
+
+pub trait Qspi {
+    fn set_client(&self, client: &'static Client);
+
+    /// The size of the flash, in bytes.
+    fn size(&self) -> usize;
+
+    /// Read `length` bytes starting at `address` into `buffer`. Returns
+    /// EBUSY if an operation is in progress, EOFF if the flash is not ready,
+    /// ESIZE if `buffer` is shorter than `length` or the bytes extend past
+    /// the end of the flash, and EINVAL if the controller cannot use the
+    /// address, length or buffer, for example because they are not aligned.
+    /// If an error occurs, the buffer is returned.
+    fn read(
+        &self,
+        address: usize,
+        buffer: &'static mut [u8],
+        length: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>);
+
+    /// Program `length` bytes of `buffer` starting at `address`, which must
+    /// have been erased. The bytes must be within a single page. The return
+    /// values are the same as for `read`, and EINVAL if the bytes cross a
+    /// page.
+    fn page_program(
+        &self,
+        address: usize,
+        buffer: &'static mut [u8],
+        length: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>);
+
+    /// Erase the sector that starts at `address`. Returns EBUSY if an
+    /// operation is in progress, EOFF if the flash is not ready, and EINVAL
+    /// if `address` is not the start of a sector in the flash.
+    fn erase_sector(&self, address: usize) -> ReturnCode;
+
+    /// Map the flash into the address space. Returns ENOSUPPORT if the
+    /// controller cannot map the flash, and EOFF if the flash is not ready.
+    fn enable_memory_mapping(&self) -> ReturnCode;
+
+    /// Stop mapping the flash into the address space.
+    fn disable_memory_mapping(&self) -> ReturnCode;
+
+    /// The address the start of the flash is mapped at, if it is mapped.
+    fn mapped_address(&self) -> Option<usize>;
+}
+
+pub trait Client {
+    /// The flash is ready after the controller was set up.
+    fn ready(&self, result: ReturnCode);
+
+    /// `length` bytes were read into `buffer`.
+    fn read_done(&self, buffer: &'static mut [u8], length: usize, result: ReturnCode);
+
+    /// `length` bytes of `buffer` were programmed.
+    fn program_done(&self, buffer: &'static mut [u8], length: usize, result: ReturnCode);
+
+    /// A sector was erased.
+    fn erase_done(&self, result: ReturnCode);
+}
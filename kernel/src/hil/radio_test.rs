@@ -0,0 +1,41 @@
+//! Interface for radio test modes
+//!
+//! Test modes make the radio continuously transmit on a single frequency,
+//! without following any protocol, which is required for regulatory and
+//! antenna testing. Since this can disrupt other devices, starting a test mode
+//! requires the `RadioTestCapability`.
+
+use capabilities::RadioTestCapability;
+use returncode::ReturnCode;
+
+pub trait RadioTest {
+    /// Transmits an unmodulated carrier on `frequency` (MHz above 2400 MHz)
+    /// until `stop_test` is called.
+    fn start_constant_carrier(
+        &self,
+        frequency: u8,
+        capability: &RadioTestCapability,
+    ) -> ReturnCode;
+
+    /// Continuously transmits a PRBS9 pattern on `frequency` (MHz above
+    /// 2400 MHz) until `stop_test` is called.
+    fn start_prbs9(&self, frequency: u8, capability: &RadioTestCapability) -> ReturnCode;
+
+    /// Stops the current test mode, returns `EALREADY` if no test is running.
+    fn stop_test(&self) -> ReturnCode;
+}
+
+/// Fills `buf` with the PRBS9 sequence (x^9 + x^5 + 1, all ones seed), least
+/// significant bit first.
+pub fn fill_prbs9(buf: &mut [u8]) {
+    let mut lfsr: u16 = 0x1ff;
+    for byte in buf.iter_mut() {
+        let mut out = 0;
+        for bit in 0..8 {
+            out |= ((lfsr & 1) as u8) << bit;
+            let feedback = (lfsr ^ (lfsr >> 4)) & 1;
+            lfsr = (lfsr >> 1) | (feedback << 8);
+        }
+        *byte = out;
+    }
+}
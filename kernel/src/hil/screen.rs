@@ -0,0 +1,53 @@
+//! Interface for screens.
+//!
+//! A screen is a rectangle of pixels. Drawing is split-phase: the user sets
+//! the write frame, the rectangle to draw to, and then writes the pixels of
+//! the frame row by row, in the pixel format of the screen.
+
+use returncode::ReturnCode;
+
+/// The layout of the pixels in the buffer of `write`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ScreenPixelFormat {
+    /// One bit per pixel, 1 for lit, eight pixels per byte starting from the
+    /// most significant bit.
+    Mono,
+    /// 16 bits per pixel, 5 bits red, 6 bits green and 5 bits blue, most
+    /// significant byte first.
+    Rgb565,
+}
+
+impl ScreenPixelFormat {
+    pub fn bits_per_pixel(&self) -> usize {
+        match *self {
+            ScreenPixelFormat::Mono => 1,
+            ScreenPixelFormat::Rgb565 => 16,
+        }
+    }
+}
+
+pub trait Screen {
+    fn set_client(&self, client: &'static ScreenClient);
+
+    /// The width and the height of the screen, in pixels.
+    fn get_resolution(&self) -> (usize, usize);
+
+    fn get_pixel_format(&self) -> ScreenPixelFormat;
+
+    /// Sets the rectangle that the next `write` draws to. Returns EINVAL if
+    /// it does not fit the screen, and EBUSY if an operation is in progress.
+    /// `command_complete` is called once it is set.
+    fn set_write_frame(&self, x: usize, y: usize, width: usize, height: usize) -> ReturnCode;
+
+    /// Draws the pixels in the first `len` bytes of `buffer` to the write
+    /// frame. `write_complete` is called once they are drawn.
+    fn write(&self, buffer: &'static mut [u8], len: usize) -> ReturnCode;
+}
+
+pub trait ScreenClient {
+    /// Called when `set_write_frame` has completed.
+    fn command_complete(&self, result: ReturnCode);
+
+    /// Called when `write` has completed, with the buffer passed to it.
+    fn write_complete(&self, buffer: &'static mut [u8], result: ReturnCode);
+}
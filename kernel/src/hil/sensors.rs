@@ -89,3 +89,171 @@ pub trait NineDofClient {
     /// over the syscall interface to an application.
     fn callback(&self, arg1: usize, arg2: usize, arg3: usize);
 }
+
+/// A basic interface for a distance sensor, such as an ultrasonic or
+/// time-of-flight ranger.
+pub trait Distance {
+    fn set_client(&self, client: &'static DistanceClient);
+
+    /// Start a single measurement. Returns EBUSY if one is in progress.
+    fn read_distance(&self) -> ReturnCode;
+
+    /// The shortest distance the sensor measures, in millimeters.
+    fn get_minimum_distance(&self) -> u32;
+
+    /// The longest distance the sensor measures, in millimeters.
+    fn get_maximum_distance(&self) -> u32;
+}
+
+/// Client for receiving distance readings.
+pub trait DistanceClient {
+    /// Called when a distance reading has completed.
+    ///
+    /// - `distance`: the distance in millimeters, if `result` is SUCCESS.
+    /// - `result`: ENOACK if nothing was in range, or another error if the
+    /// measurement failed.
+    fn callback(&self, distance: u32, result: ReturnCode);
+}
+
+/// An interface for sensors that stream samples at a fixed rate.
+///
+/// Instead of a callback per reading, the sensor buffers samples in a FIFO,
+/// in hardware or software, and delivers them in batches of `watermark`
+/// samples, so that high-rate sensors such as IMUs do not wake the kernel
+/// and apps for each sample. A sample is `sample_size` signed 16-bit values,
+/// for example the three axes of an accelerometer, in the units of the
+/// one-shot interface of the sensor.
+pub trait StreamingSensor {
+    fn set_client(&self, client: &'static StreamingSensorClient);
+
+    /// The number of values in a sample.
+    fn sample_size(&self) -> usize;
+
+    /// The highest output data rate, in samples per second.
+    fn get_maximum_rate(&self) -> u32;
+
+    /// The most samples the FIFO holds, and so the largest watermark.
+    fn get_fifo_size(&self) -> usize;
+
+    /// Start streaming at the supported rate closest to `rate` samples per
+    /// second without exceeding it. The client is called with `identifier`
+    /// each time `watermark` samples are buffered. Returns the actual rate as
+    /// SuccessWithValue, EINVAL if the rate or watermark is 0 or the
+    /// watermark is larger than the FIFO, or EBUSY if streaming.
+    fn start(&self, identifier: usize, rate: u32, watermark: usize) -> ReturnCode;
+
+    /// Stop streaming. Samples in the FIFO are dropped. Returns EOFF if not
+    /// streaming.
+    fn stop(&self) -> ReturnCode;
+
+    /// Deliver the samples in the FIFO now, even if fewer than the
+    /// watermark. Returns EOFF if not streaming.
+    fn flush(&self) -> ReturnCode;
+}
+
+/// Client for receiving batches of streamed samples.
+pub trait StreamingSensorClient {
+    /// Called when a batch of samples is ready.
+    ///
+    /// - `identifier`: the identifier passed to `start`.
+    /// - `samples`: the values of the samples, one sample after the other.
+    /// - `dropped`: the number of samples lost since the last batch, because
+    /// the FIFO was full or the sensor did not keep up with the rate.
+    fn samples_ready(&self, identifier: usize, samples: &[i16], dropped: usize);
+}
+
+/// An interface for sensors that detect motion while the rest of the system
+/// sleeps, such as accelerometers with a wake-up interrupt.
+pub trait MotionDetector {
+    fn set_client(&self, client: &'static MotionDetectorClient);
+
+    /// Start detecting motion, and calling the client each time the
+    /// acceleration changes by more than `threshold` milli-g. Returns EBUSY
+    /// if the sensor cannot detect motion in its current mode.
+    fn enable_motion_detection(&self, threshold: u32) -> ReturnCode;
+
+    /// Stop detecting motion. Returns EOFF if not detecting.
+    fn disable_motion_detection(&self) -> ReturnCode;
+}
+
+/// Client for receiving motion events.
+pub trait MotionDetectorClient {
+    /// Called when the sensor detected motion.
+    fn motion_detected(&self);
+}
+
+/// A basic interface for a battery fuel gauge.
+pub trait FuelGauge {
+    fn set_client(&self, client: &'static FuelGaugeClient);
+
+    /// Read the state of charge, the voltage and the charge rate of the
+    /// battery.
+    fn read_battery(&self) -> ReturnCode;
+
+    /// Alert the client when the state of charge falls below `percent`, or
+    /// stop alerting if it is 0. Returns EINVAL if the gauge does not
+    /// support the threshold.
+    fn set_low_charge_alert(&self, percent: u32) -> ReturnCode;
+}
+
+/// Client for receiving battery readings and alerts.
+pub trait FuelGaugeClient {
+    /// Called when a battery reading has completed.
+    ///
+    /// - `state_of_charge`: the charge left in hundredths of percent.
+    /// - `voltage`: the cell voltage in millivolts.
+    /// - `rate`: the change of the state of charge in hundredths of percent
+    /// per hour, positive while charging and negative while discharging.
+    fn battery_read(&self, state_of_charge: u32, voltage: u32, rate: i32, result: ReturnCode);
+
+    /// Called when the state of charge fell below the alert threshold.
+    ///
+    /// - `state_of_charge`: the charge left in hundredths of percent.
+    fn low_charge(&self, state_of_charge: u32);
+}
+
+/// A basic interface for a proximity sensor.
+pub trait Proximity {
+    fn set_client(&self, client: &'static ProximityClient);
+
+    /// Read the proximity once.
+    fn read_proximity(&self) -> ReturnCode;
+
+    /// Read the proximity once it is below `low` or above `high`. The sensor
+    /// compares the proximity by itself, so the CPU can sleep until then.
+    fn read_proximity_on_interrupt(&self, low: u8, high: u8) -> ReturnCode;
+}
+
+/// Client for receiving proximity readings.
+pub trait ProximityClient {
+    /// Called when a proximity reading has completed.
+    ///
+    /// - `proximity`: from 0 for far to 255 for close. The distance this
+    /// corresponds to depends on the sensor and the reflecting object.
+    fn callback(&self, proximity: u8);
+}
+
+/// The direction of a swipe over a gesture sensor.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Gesture {
+    Up = 1,
+    Down = 2,
+    Left = 3,
+    Right = 4,
+}
+
+/// A basic interface for a sensor that recognizes gestures.
+pub trait GestureSensor {
+    fn set_client(&self, client: &'static GestureClient);
+
+    /// Start recognizing gestures, until `disable_gestures`.
+    fn enable_gestures(&self) -> ReturnCode;
+
+    fn disable_gestures(&self) -> ReturnCode;
+}
+
+/// Client for receiving gestures.
+pub trait GestureClient {
+    /// Called when the sensor recognized a gesture.
+    fn gesture(&self, gesture: Gesture);
+}
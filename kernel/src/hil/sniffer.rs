@@ -0,0 +1,44 @@
+//! Interface for radios that can capture raw frames
+//!
+//! A radio in sniffer mode receives every frame sent to the configured access
+//! address on the configured frequency, regardless of its content, and passes
+//! it to the client together with its CRC status and signal strength. This
+//! allows a board to be used as a cheap BLE or proprietary-protocol sniffer.
+
+use returncode::ReturnCode;
+
+/// On-air data rate and modulation
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DataRate {
+    Nrf250Kbit,
+    Nrf1Mbit,
+    Nrf2Mbit,
+    Ble1Mbit,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct SnifferConfig {
+    /// Access address of the frames to capture, e.g. 0x8E89BED6 for BLE advertisements
+    pub access_address: u32,
+    /// Frequency in MHz above 2400 MHz
+    pub frequency: u8,
+    pub data_rate: DataRate,
+    /// Initial value of the data whitening LFSR, `None` disables whitening
+    pub whitening: Option<u8>,
+}
+
+pub trait Sniffer {
+    fn set_sniffer_client(&self, client: &'static SnifferClient);
+
+    /// Starts capturing frames until `stop_sniffing` is called. Returns `EBUSY`
+    /// if the radio is in use.
+    fn start_sniffing(&self, config: SnifferConfig) -> ReturnCode;
+
+    fn stop_sniffing(&self) -> ReturnCode;
+}
+
+pub trait SnifferClient {
+    /// Called for every captured frame. `frame` starts with the header and
+    /// LENGTH field, `rssi` is the received signal strength in -dBm.
+    fn frame_captured(&self, frame: &[u8], crc_ok: bool, rssi: u8);
+}
@@ -24,6 +24,31 @@ pub enum ClockPhase {
     SampleTrailing,
 }
 
+/// One buffer of a scatter-gather transaction, see
+/// `SpiMasterDevice::read_write_vectored`.
+pub struct SpiSegment {
+    /// The bytes to write, which must be present when the transaction starts
+    pub write_buffer: Option<&'static mut [u8]>,
+    /// Where to store the bytes read, if they are needed
+    pub read_buffer: Option<&'static mut [u8]>,
+    /// The number of bytes to transfer, and once done, the number transferred
+    pub len: usize,
+}
+
+impl SpiSegment {
+    pub fn new(
+        write_buffer: &'static mut [u8],
+        read_buffer: Option<&'static mut [u8]>,
+        len: usize,
+    ) -> SpiSegment {
+        SpiSegment {
+            write_buffer: Some(write_buffer),
+            read_buffer: read_buffer,
+            len: len,
+        }
+    }
+}
+
 pub trait SpiMasterClient {
     /// Called when a read/write operation finishes
     fn read_write_done(
@@ -32,6 +57,11 @@ pub trait SpiMasterClient {
         read_buffer: Option<&'static mut [u8]>,
         len: usize,
     );
+
+    /// Called when a scatter-gather operation finishes, with the buffers
+    /// back in their segments. Only clients that use
+    /// `SpiMasterDevice::read_write_vectored` need to implement this.
+    fn read_write_vectored_done(&self, _segments: &'static mut [SpiSegment]) {}
 }
 /// The `SpiMaster` trait for interacting with SPI slave
 /// devices at a byte or buffer level.
@@ -125,6 +155,9 @@ pub trait SpiMaster {
 /// SPIMasterDevice provides a chip-specific interface to the SPI Master
 /// hardware. The interface wraps the chip select line so that chip drivers
 /// cannot communicate with different SPI devices.
+///
+/// The settings of a device are its own: they apply to its operations,
+/// whatever other devices on the same bus have configured.
 pub trait SpiMasterDevice {
     /// Setup the SPI settings and speed of the bus.
     fn configure(&self, cpol: ClockPolarity, cpal: ClockPhase, rate: u32);
@@ -142,6 +175,13 @@ pub trait SpiMasterDevice {
         len: usize,
     ) -> ReturnCode;
 
+    /// Perform the read/write operations of all `segments` back to back,
+    /// with the chip select held asserted between them, so that for
+    /// example a header and a payload in separate buffers need not be
+    /// copied together. Every segment must have a write buffer. Completion
+    /// is signaled by invoking `SpiMasterClient.read_write_vectored_done`.
+    fn read_write_vectored(&self, segments: &'static mut [SpiSegment]) -> ReturnCode;
+
     fn set_polarity(&self, cpol: ClockPolarity);
     fn set_phase(&self, cpal: ClockPhase);
     fn set_rate(&self, rate: u32);
@@ -106,8 +106,12 @@ pub trait CCMClient {
     fn crypt_done(&self, buf: &'static mut [u8], res: ReturnCode, tag_is_valid: bool);
 }
 
+/// The longest CCM nonce, as used by IEEE 802.15.4.
 pub const CCM_NONCE_LENGTH: usize = 13;
 
+/// The shortest CCM nonce (RFC 3610).
+pub const CCM_MIN_NONCE_LENGTH: usize = 7;
+
 pub trait AES128CCM<'a> {
     /// Set the client instance which will receive `crypt_done()` callbacks
     fn set_client(&'a self, client: &'a CCMClient);
@@ -115,7 +119,11 @@ pub trait AES128CCM<'a> {
     /// Set the key to be used for CCM encryption
     fn set_key(&self, key: &[u8]) -> ReturnCode;
 
-    /// Set the nonce (length NONCE_LENGTH) to be used for CCM encryption
+    /// Set the nonce to be used for CCM encryption. It may be between
+    /// `CCM_MIN_NONCE_LENGTH` and `CCM_NONCE_LENGTH` bytes long, and shorter
+    /// nonces leave more bytes for the length of the message. Returns
+    /// `EINVAL` if the implementation does not support the length of
+    /// `nonce`.
     fn set_nonce(&self, nonce: &[u8]) -> ReturnCode;
 
     /// Try to begin the encryption/decryption process
@@ -130,3 +138,46 @@ pub trait AES128CCM<'a> {
         encrypting: bool,
     ) -> (ReturnCode, Option<&'static mut [u8]>);
 }
+
+pub trait GCMClient {
+    /// `res` is SUCCESS if the encryption/decryption process succeeded. This
+    /// does not mean that the message has been verified in the case of
+    /// decryption.
+    /// If we are encrypting: `tag_is_valid` is `true` iff `res` is SUCCESS.
+    /// If we are decrypting: `tag_is_valid` is `true` iff `res` is SUCCESS and the
+    /// authentication tag is valid. The message is only decrypted if the tag
+    /// is valid, and is zeroed otherwise.
+    fn crypt_done(&self, buf: &'static mut [u8], res: ReturnCode, tag_is_valid: bool);
+}
+
+pub const GCM_NONCE_LENGTH: usize = 12;
+pub const GCM_TAG_LENGTH: usize = 16;
+
+pub trait AES128GCM<'a> {
+    /// Set the client instance which will receive `crypt_done()` callbacks
+    fn set_client(&'a self, client: &'a GCMClient);
+
+    /// Set the key to be used for GCM encryption
+    fn set_key(&self, key: &[u8]) -> ReturnCode;
+
+    /// Set the nonce (length GCM_NONCE_LENGTH) to be used for GCM encryption.
+    /// A nonce must never be used twice with the same key.
+    fn set_nonce(&self, nonce: &[u8]) -> ReturnCode;
+
+    /// Try to begin the encryption/decryption process
+    ///
+    /// `buf[a_off..m_off]` is the additional data, which is authenticated
+    /// but not encrypted, `buf[m_off..m_off + m_len]` the message, and the
+    /// `tag_len` bytes after it the authentication tag. When encrypting, the
+    /// tag is written there, and when decrypting, it is checked. `tag_len`
+    /// must be 4, 8, or between 12 and `GCM_TAG_LENGTH`.
+    fn crypt(
+        &self,
+        buf: &'static mut [u8],
+        a_off: usize,
+        m_off: usize,
+        m_len: usize,
+        tag_len: usize,
+        encrypting: bool,
+    ) -> (ReturnCode, Option<&'static mut [u8]>);
+}
@@ -1,5 +1,7 @@
 //! Hardware agnostic interfaces for counter-like resources.
 
+use returncode::ReturnCode;
+
 pub trait Time {
     type Frequency: Frequency;
 
@@ -81,9 +83,53 @@ pub trait Alarm: Time {
 
     /// Returns the value set in [`set_alarm`](#tymethod.set_alarm)
     fn get_alarm(&self) -> u32;
+
+    /// Returns the number of bits of the counter, for counters that wrap
+    /// before 32 bits. `now` and `get_alarm` stay below `2^counter_bits()`,
+    /// and `set_alarm` uses only as many bits of `tics`.
+    fn counter_bits(&self) -> u32 {
+        32
+    }
+}
+
+/// The `Time64` trait models a 64-bit counter that does not wrap in practice,
+/// usually built on an [`Alarm`](trait.Alarm.html) that counts the wraps of its
+/// 32-bit counter in software.
+pub trait Time64 {
+    type Frequency: Frequency;
+
+    /// Returns the time since the counter started, in hardware clock units.
+    /// The value never decreases.
+    fn now64(&self) -> u64;
 }
 
-/// A client of an implementor of the [`Alarm`](trait.Alarm.html) trait.
+/// The `Alarm64` trait models a 64-bit counter that does not wrap in
+/// practice, capable of notifying when the counter reaches a certain value.
+///
+/// Clients of `Alarm64` set alarms at any distance, and need not handle the
+/// counter wrapping. The virtual alarms of `capsules::virtual_alarm`
+/// implement it on top of the 32-bit or narrower counter of the hardware,
+/// which they extend to 64 bits in software.
+pub trait Alarm64: Time64 {
+    /// Sets a one-shot alarm to fire when the counter reaches `tics`. A value
+    /// that has passed fires as soon as possible.
+    ///
+    /// [`Client#fired`](trait.Client.html#tymethod.fired) is signaled
+    /// when `tics` is reached.
+    fn set_alarm64(&self, tics: u64);
+
+    /// Returns the value set in [`set_alarm64`](#tymethod.set_alarm64)
+    fn get_alarm64(&self) -> u64;
+
+    /// Disables the alarm, if armed.
+    fn disable64(&self);
+
+    /// Returns whether the alarm is armed.
+    fn is_armed64(&self) -> bool;
+}
+
+/// A client of an implementor of the [`Alarm`](trait.Alarm.html) or the
+/// [`Alarm64`](trait.Alarm64.html) trait.
 pub trait Client {
     /// Callback signaled when the alarm's clock reaches the value set in
     /// [`Alarm#set_alarm`](trait.Alarm.html#tymethod.set_alarm).
@@ -98,3 +144,138 @@ pub trait Timer: Time {
     /// Sets repeating timer to fire every `interval` clock-tics.
     fn repeat(&self, interval: u32);
 }
+
+/// A date and time of the Gregorian calendar, in the time zone the clock
+/// was set in, usually UTC.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct DateTime {
+    /// The year, from 1970
+    pub year: u16,
+    /// The month, from 1 for January to 12
+    pub month: u8,
+    /// The day of the month, from 1
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    fn is_leap_year(year: u16) -> bool {
+        (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+    }
+
+    fn days_in_month(year: u16, month: u8) -> u8 {
+        match month {
+            2 if DateTime::is_leap_year(year) => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        }
+    }
+
+    /// Whether the fields are a date and time from 1970 on.
+    pub fn is_valid(&self) -> bool {
+        self.year >= 1970
+            && self.month >= 1
+            && self.month <= 12
+            && self.day >= 1
+            && self.day <= DateTime::days_in_month(self.year, self.month)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+
+    /// The days since 1970-01-01.
+    fn days(&self) -> u64 {
+        // Years start in March, so that leap days end them
+        let year = if self.month <= 2 {
+            self.year as u64 - 1
+        } else {
+            self.year as u64
+        };
+        let month = self.month as u64;
+        let era = year / 400;
+        let year_of_era = year - era * 400;
+        let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + self.day as u64 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        era * 146097 + day_of_era - 719468
+    }
+
+    /// The date and time `seconds` seconds after 1970-01-01 00:00:00, as in
+    /// Unix time.
+    pub fn from_seconds(seconds: u64) -> DateTime {
+        let days = seconds / 86400 + 719468;
+        let era = days / 146097;
+        let day_of_era = days - era * 146097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month + 2) / 5 + 1;
+        let month = (month + 2) % 12 + 1;
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        let second_of_day = seconds % 86400;
+        DateTime {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (second_of_day / 3600) as u8,
+            minute: (second_of_day / 60 % 60) as u8,
+            second: (second_of_day % 60) as u8,
+        }
+    }
+
+    /// The seconds since 1970-01-01 00:00:00, as in Unix time.
+    pub fn seconds(&self) -> u64 {
+        self.days() * 86400 + self.hour as u64 * 3600 + self.minute as u64 * 60 + self.second as u64
+    }
+
+    /// The day of the week, from 1 for Monday to 7 for Sunday.
+    pub fn weekday(&self) -> u8 {
+        // 1970-01-01 was a Thursday
+        ((self.days() + 3) % 7 + 1) as u8
+    }
+}
+
+/// The `WallClock` trait models a real-time clock that keeps the date and
+/// time, such as an RTC chip with a backup battery that keeps counting
+/// while the board is off.
+///
+/// All operations are split-phase: they return SUCCESS if they started, or
+/// EBUSY if another operation is in progress, and complete with a call to
+/// the [`WallClockClient`](trait.WallClockClient.html).
+pub trait WallClock {
+    fn set_client(&self, client: &'static WallClockClient);
+
+    /// Reads the date and time. `date_time_read` is called with it.
+    fn get_date_time(&self) -> ReturnCode;
+
+    /// Sets the date and time. Returns EINVAL if the clock cannot keep it.
+    /// `set_done` is called once it is set.
+    fn set_date_time(&self, date_time: DateTime) -> ReturnCode;
+
+    /// Sets the alarm to fire once, at `date_time`, replacing any alarm set
+    /// before. The date and time should be in the future. Returns ENODEVICE
+    /// if the clock has no alarm. `set_done` is called once it is set, and
+    /// `alarm` once it fires.
+    fn set_alarm(&self, date_time: DateTime) -> ReturnCode;
+
+    /// Disables the alarm. `set_done` is called once it is disabled.
+    fn disable_alarm(&self) -> ReturnCode;
+}
+
+/// A client of an implementor of the [`WallClock`](trait.WallClock.html)
+/// trait.
+pub trait WallClockClient {
+    /// The date and time were read, unless `result` is an error. `result` is
+    /// EOFF if the clock was not set since it lost power.
+    fn date_time_read(&self, date_time: DateTime, result: ReturnCode);
+
+    /// The date and time or the alarm were set, unless `result` is an error.
+    fn set_done(&self, result: ReturnCode);
+
+    /// The date and time of the alarm was reached.
+    fn alarm(&self);
+}
@@ -0,0 +1,109 @@
+//! Interface for capacitive touch sensing.
+//!
+//! A touch sensor measures the capacitance of its channels, electrodes under
+//! the surface of a device, as a count whose scale depends on the method.
+//! A finger near an electrode changes its capacitance, so the count moves
+//! away from the baseline of the untouched electrode. The channel is touched
+//! when the count differs from the baseline by more than its threshold, and
+//! released when the difference falls below half of it. The baseline
+//! follows slow changes, for example of humidity, while the channel is not
+//! touched.
+//!
+//! Implementations that measure counts in software can use `Detector` to
+//! keep the baseline and detect touches.
+
+use returncode::ReturnCode;
+
+pub trait TouchSense {
+    fn set_client(&self, client: &'static Client);
+
+    fn channel_count(&self) -> usize;
+
+    /// Starts measuring the channels periodically, and calling the client
+    /// when one is touched or released. Returns EALREADY if measuring.
+    fn enable(&self) -> ReturnCode;
+
+    /// Stops measuring. Returns EOFF if not measuring.
+    fn disable(&self) -> ReturnCode;
+
+    /// Sets the threshold of a channel, in counts. Returns EINVAL if the
+    /// channel does not exist.
+    fn set_threshold(&self, channel: usize, threshold: u32) -> ReturnCode;
+
+    /// The last count measured on a channel, or `None` if it does not exist
+    /// or was not measured yet.
+    fn get_count(&self, channel: usize) -> Option<u32>;
+
+    /// Whether a channel is touched.
+    fn is_touched(&self, channel: usize) -> bool;
+}
+
+pub trait Client {
+    /// A channel was touched, or released if `touched` is false.
+    fn touch_changed(&self, channel: usize, touched: bool);
+}
+
+/// The baseline and touch state of a channel.
+#[derive(Clone, Copy)]
+pub struct Detector {
+    /// The baseline, in sixteenths of counts, or 0 before the first count
+    baseline: u32,
+    count: Option<u32>,
+    threshold: u32,
+    touched: bool,
+}
+
+impl Detector {
+    pub const fn new(threshold: u32) -> Detector {
+        Detector {
+            baseline: 0,
+            count: None,
+            threshold: threshold,
+            touched: false,
+        }
+    }
+
+    pub fn set_threshold(&mut self, threshold: u32) {
+        self.threshold = threshold;
+    }
+
+    pub fn count(&self) -> Option<u32> {
+        self.count
+    }
+
+    pub fn is_touched(&self) -> bool {
+        self.touched
+    }
+
+    /// Adds a new count, and returns the new touch state if it changed.
+    pub fn update(&mut self, count: u32) -> Option<bool> {
+        if self.count.is_none() {
+            // The first count is the untouched electrode
+            self.baseline = count << 4;
+        }
+        self.count = Some(count);
+
+        let baseline = self.baseline >> 4;
+        let difference = if count > baseline {
+            count - baseline
+        } else {
+            baseline - count
+        };
+        let touched = if self.touched {
+            difference > self.threshold / 2
+        } else {
+            difference > self.threshold
+        };
+
+        if !touched {
+            // Move the baseline a sixteenth of the way to the count
+            self.baseline = self.baseline - (self.baseline >> 4) + count;
+        }
+        if touched != self.touched {
+            self.touched = touched;
+            Some(touched)
+        } else {
+            None
+        }
+    }
+}
@@ -101,6 +101,24 @@ pub trait UARTReceiveAdvanced: UART {
     fn receive_automatic(&self, rx_buffer: &'static mut [u8], interbyte_timeout: u8);
 }
 
+/// Synchronous transmission for when the kernel has stopped, such as in the
+/// panic handler.
+///
+/// A panic or fault dump should be printed completely even if the UART was
+/// in the middle of a transmission for a capsule, for example of an app's
+/// console output through a `virtual_uart::UartMux`. Such a transmission is
+/// never finished once the kernel has stopped, so it must not hold the UART.
+pub trait UARTEmergency: UART {
+    /// Stop any ongoing transmission, without signaling its client, and
+    /// transmit `bytes` by busy waiting until they are sent.
+    ///
+    /// The UART must already be configured. This leaves the driver state
+    /// inconsistent, as the buffer of an interrupted transmission is never
+    /// returned, so it must only be called once the kernel no longer runs,
+    /// and may be called repeatedly from then on.
+    unsafe fn emergency_write(&self, bytes: &[u8]);
+}
+
 /// Implement Client to receive callbacks from UART.
 pub trait Client {
     /// UART transmit complete.
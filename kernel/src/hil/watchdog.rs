@@ -1,4 +1,9 @@
 //! Interface for a watchdog timer.
+//!
+//! The kernel pets the watchdog of the chip in every iteration of its main
+//! loop, so the watchdog resets the board if the kernel hangs, for example in
+//! a capsule that never returns. Boards start the watchdog with the period
+//! they want. Chips without a watchdog use `()`, which does nothing.
 
 pub trait Watchdog {
     /// Enable the watchdog timer. Period is the time in milliseconds
@@ -10,5 +15,36 @@ pub trait Watchdog {
 
     /// Service the watchdog to let the hardware know the application
     /// is still executing.
-    fn tickle(&self);
+    fn pet(&self);
+
+    /// Pause the watchdog while the chip sleeps, if it would otherwise keep
+    /// counting. Does nothing if the watchdog is not started.
+    fn suspend(&self) {}
+
+    /// Continue the watchdog after `suspend()`, with the same period.
+    fn resume(&self) {}
+
+    /// Set the client that is warned shortly before the watchdog resets the
+    /// chip. Watchdogs without an early warning never call the client.
+    fn set_client(&self, client: &'static WatchdogClient);
+}
+
+/// Implement `WatchdogClient` to be warned before the watchdog resets the
+/// chip.
+pub trait WatchdogClient {
+    /// Called when the watchdog timed out and is about to reset the chip.
+    /// The chip resets shortly after, so the client must act synchronously,
+    /// for example to print debugging information.
+    fn early_warning(&self);
+}
+
+/// Implement default Watchdog trait for unit, for chips without a watchdog.
+impl Watchdog for () {
+    fn start(&self, _period: usize) {}
+
+    fn stop(&self) {}
+
+    fn pet(&self) {}
+
+    fn set_client(&self, _client: &'static WatchdogClient) {}
 }
@@ -18,6 +18,33 @@ use common::cells::NumericCellExt;
 use process;
 use sched::Kernel;
 
+/// Translates the CPU time of a process into an estimate of the energy it
+/// used. Boards implement this with the power draw of their chip.
+pub trait EnergyModel {
+    /// Returns the estimated energy, in microjoules, used by running a process
+    /// for `cpu_time_us` microseconds.
+    fn energy_uj(&self, cpu_time_us: u64) -> u64;
+}
+
+/// Energy model for a chip that draws a constant power while running.
+pub struct ConstantPowerModel {
+    microwatts: u64,
+}
+
+impl ConstantPowerModel {
+    pub const fn new(microwatts: u64) -> ConstantPowerModel {
+        ConstantPowerModel {
+            microwatts: microwatts,
+        }
+    }
+}
+
+impl EnergyModel for ConstantPowerModel {
+    fn energy_uj(&self, cpu_time_us: u64) -> u64 {
+        cpu_time_us * self.microwatts / 1_000_000
+    }
+}
+
 /// This struct provides the introspection functions.
 pub struct Introspection {
     kernel: &'static Kernel,
@@ -49,6 +76,8 @@ impl Introspection {
             .process_each_enumerate(|_, process| match process.get_state() {
                 process::State::Running => count.increment(),
                 process::State::Yielded => count.increment(),
+                process::State::StoppedRunning => {}
+                process::State::StoppedYielded => {}
                 process::State::Fault => {}
             });
         count.get()
@@ -63,11 +92,53 @@ impl Introspection {
             .process_each_enumerate(|_, process| match process.get_state() {
                 process::State::Running => {}
                 process::State::Yielded => {}
+                process::State::StoppedRunning => count.increment(),
+                process::State::StoppedYielded => count.increment(),
                 process::State::Fault => count.increment(),
             });
         count.get()
     }
 
+    /// Returns how many processes this board supports.
+    pub fn number_process_slots(&self, _capability: &ProcessManagementCapability) -> usize {
+        self.kernel.number_of_process_slots()
+    }
+
+    /// Returns the `AppId` of the process in slot `index`, if a process has
+    /// been loaded in it.
+    pub fn app_id(&self, index: usize, _capability: &ProcessManagementCapability) -> Option<AppId> {
+        if index >= self.kernel.number_of_process_slots() {
+            return None;
+        }
+        self.kernel
+            .process_map_or(None, index, |_| Some(AppId::new(self.kernel, index)))
+    }
+
+    /// Returns the `AppId` of the first process named `name`.
+    pub fn app_by_name(
+        &self,
+        name: &str,
+        _capability: &ProcessManagementCapability,
+    ) -> Option<AppId> {
+        let app: Cell<Option<AppId>> = Cell::new(None);
+        self.kernel.process_each_enumerate(|index, process| {
+            if app.get().is_none() && process.get_process_name() == name {
+                app.set(Some(AppId::new(self.kernel, index)));
+            }
+        });
+        app.get()
+    }
+
+    /// Returns the state the process is in.
+    pub fn process_state(
+        &self,
+        app: AppId,
+        _capability: &ProcessManagementCapability,
+    ) -> Option<process::State> {
+        self.kernel
+            .process_map_or(None, app.idx(), |process| Some(process.get_state()))
+    }
+
     /// Get the name of the process.
     pub fn process_name(
         &self,
@@ -78,6 +149,19 @@ impl Introspection {
             .process_map_or("", app.idx(), |process| process.get_process_name())
     }
 
+    /// Get the name of the process if its app passed the `AppVerifier` of
+    /// the board, and `None` if the board loads apps without one. Unlike the
+    /// name alone, which any app can claim, this can be used to identify an
+    /// app.
+    pub fn verified_process_name(
+        &self,
+        app: AppId,
+        _capability: &ProcessManagementCapability,
+    ) -> Option<&'static str> {
+        self.kernel
+            .process_map_or(None, app.idx(), |process| process.get_verified_name())
+    }
+
     /// Returns the number of syscalls the app has called.
     pub fn number_app_syscalls(
         &self,
@@ -121,4 +205,105 @@ impl Introspection {
             process.debug_timeslice_expiration_count()
         })
     }
+
+    /// Returns how many microseconds of CPU time this app has used.
+    pub fn app_cpu_time_us(&self, app: AppId, _capability: &ProcessManagementCapability) -> u64 {
+        self.kernel
+            .process_map_or(0, app.idx(), |process| process.debug_cpu_time_us())
+    }
+
+    /// Returns the number of deadlines this app has missed under the
+    /// `EdfScheduler`.
+    pub fn number_app_deadline_misses(
+        &self,
+        app: AppId,
+        _capability: &ProcessManagementCapability,
+    ) -> usize {
+        self.kernel
+            .process_map_or(0, app.idx(), |process| process.debug_deadline_miss_count())
+    }
+
+    /// Returns the number of bytes of RAM allocated to the app, and how many
+    /// of them are in use by its stack, data and heap or by grants.
+    pub fn app_memory_usage(
+        &self,
+        app: AppId,
+        _capability: &ProcessManagementCapability,
+    ) -> (usize, usize) {
+        self.kernel.process_map_or((0, 0), app.idx(), |process| {
+            let start = process.mem_start() as usize;
+            let end = process.mem_end() as usize;
+            let app_used = process.app_memory_break() as usize - start;
+            let grant_used = end - process.kernel_memory_break() as usize;
+            (end - start, app_used + grant_used)
+        })
+    }
+
+    /// Returns the start and the end of the flash region of the app.
+    pub fn app_flash_region(
+        &self,
+        app: AppId,
+        _capability: &ProcessManagementCapability,
+    ) -> (usize, usize) {
+        self.kernel.process_map_or((0, 0), app.idx(), |process| {
+            (process.flash_start() as usize, process.flash_end() as usize)
+        })
+    }
+
+    /// Returns the start and the end of the RAM region of the app.
+    pub fn app_ram_region(
+        &self,
+        app: AppId,
+        _capability: &ProcessManagementCapability,
+    ) -> (usize, usize) {
+        self.kernel.process_map_or((0, 0), app.idx(), |process| {
+            (process.mem_start() as usize, process.mem_end() as usize)
+        })
+    }
+
+    /// Returns the end of the memory the app uses for its stack, data and
+    /// heap, and the start of its grant region.
+    pub fn app_memory_breaks(
+        &self,
+        app: AppId,
+        _capability: &ProcessManagementCapability,
+    ) -> (usize, usize) {
+        self.kernel.process_map_or((0, 0), app.idx(), |process| {
+            (
+                process.app_memory_break() as usize,
+                process.kernel_memory_break() as usize,
+            )
+        })
+    }
+
+    /// Stop scheduling the app until it is resumed.
+    pub fn stop_app(&self, app: AppId, _capability: &ProcessManagementCapability) {
+        self.kernel
+            .process_map_or((), app.idx(), |process| process.stop())
+    }
+
+    /// Resume an app that was stopped with `stop_app()`.
+    pub fn resume_app(&self, app: AppId, _capability: &ProcessManagementCapability) {
+        self.kernel
+            .process_map_or((), app.idx(), |process| process.resume())
+    }
+
+    /// Put the app in the fault state, as if it had faulted. The app's
+    /// `FaultResponse` decides what happens next.
+    pub fn fault_app(&self, app: AppId, _capability: &ProcessManagementCapability) {
+        self.kernel
+            .process_map_or((), app.idx(), |process| process.set_fault_state())
+    }
+
+    /// Stop the app for good, without triggering its `FaultResponse`.
+    pub fn terminate_app(&self, app: AppId, _capability: &ProcessManagementCapability) {
+        self.kernel
+            .process_map_or((), app.idx(), |process| process.terminate())
+    }
+
+    /// Start the app over from its init function.
+    pub fn restart_app(&self, app: AppId, _capability: &ProcessManagementCapability) {
+        self.kernel
+            .process_map_or((), app.idx(), |process| process.restart())
+    }
 }
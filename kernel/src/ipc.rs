@@ -2,10 +2,18 @@
 //!
 //! This is a special syscall driver that allows userspace applications to
 //! share memory.
+//!
+//! Notifications are queued per receiving process, so a notification sent
+//! while the receiver is busy is delivered once its task queue has room
+//! again. A buffer a client shares with a service is made accessible to the
+//! service through an MPU region that allows reading and writing, but not
+//! executing.
 
 /// Syscall number
 pub const DRIVER_NUM: usize = 0x00010000;
 
+use core::cell::Cell;
+
 use callback::{AppId, Callback};
 use capabilities::MemoryAllocationCapability;
 use driver::Driver;
@@ -15,10 +23,17 @@ use process;
 use returncode::ReturnCode;
 use sched::Kernel;
 
+/// How many notifications can wait for room in the task queue of a process.
+const NOTIFICATION_QUEUE_LEN: usize = 4;
+
 struct IPCData {
     shared_memory: [Option<AppSlice<Shared, u8>>; 8],
     client_callbacks: [Option<Callback>; 8],
     callback: Option<Callback>,
+    /// Notifications for this process that are not in its task queue yet,
+    /// oldest first. Each is the index of the notifying process and whether
+    /// it notified this process as a service or as a client.
+    notifications: [Option<(usize, process::IPCType)>; NOTIFICATION_QUEUE_LEN],
 }
 
 impl Default for IPCData {
@@ -27,6 +42,7 @@ impl Default for IPCData {
             shared_memory: [None, None, None, None, None, None, None, None],
             client_callbacks: [None, None, None, None, None, None, None, None],
             callback: None,
+            notifications: [None; NOTIFICATION_QUEUE_LEN],
         }
     }
 }
@@ -79,6 +95,58 @@ impl IPC {
                             }).unwrap_or(());
                     }).unwrap_or(());
             }).unwrap_or(());
+
+        // Delivering this notification freed a slot in the task queue.
+        self.flush_notifications(appid);
+    }
+
+    /// Move queued notifications for `appid` into its task queue, oldest
+    /// first, until the task queue is full. Returns whether any notification
+    /// was moved.
+    crate fn flush_notifications(&self, appid: AppId) -> bool {
+        let kernel = self.data.kernel;
+        self.data
+            .enter(appid, |data, _| {
+                let mut moved = false;
+                while let Some((notifier, cb_type)) = data.notifications[0] {
+                    let task = process::Task::IPC((AppId::new(kernel, notifier), cb_type));
+                    let enqueued =
+                        kernel.process_map_or(false, appid.idx(), |p| p.enqueue_task(task));
+                    if !enqueued {
+                        break;
+                    }
+                    for i in 1..NOTIFICATION_QUEUE_LEN {
+                        data.notifications[i - 1] = data.notifications[i];
+                    }
+                    data.notifications[NOTIFICATION_QUEUE_LEN - 1] = None;
+                    moved = true;
+                }
+                moved
+            }).unwrap_or(false)
+    }
+
+    /// Returns the id of the `index`th process, counting from 0, that
+    /// registered itself as a service.
+    fn service_id(&self, index: usize) -> ReturnCode {
+        let kernel = self.data.kernel;
+        let count = Cell::new(0);
+        let ret = kernel.process_each_enumerate_stop(|i, _| {
+            let registered = self
+                .data
+                .enter(AppId::new(kernel, i), |data, _| data.callback.is_some())
+                .unwrap_or(false);
+            if registered {
+                if count.get() == index {
+                    return ReturnCode::SuccessWithValue { value: i + 1 };
+                }
+                count.set(count.get() + 1);
+            }
+            ReturnCode::FAIL
+        });
+        match ret {
+            ReturnCode::FAIL => ReturnCode::EINVAL,
+            _ => ret,
+        }
     }
 }
 
@@ -134,7 +202,14 @@ impl Driver for IPC {
     /// In either case, the target_id is the same number as provided in a notify
     /// callback or as returned by allow.
     ///
-    /// Returns EINVAL if the other process doesn't exist.
+    /// The notification is queued for the other process, and delivered in
+    /// order once its task queue has room. Returns EINVAL if the other process
+    /// doesn't exist, and ENOMEM if too many notifications are already queued
+    /// for it.
+    ///
+    /// With target_id == 0, command enumerates the registered IPC services:
+    /// it returns the id of the service with index client_or_svc, counting
+    /// from 0, or EINVAL if there are fewer services.
     fn command(
         &self,
         target_id: usize,
@@ -142,21 +217,40 @@ impl Driver for IPC {
         _: usize,
         appid: AppId,
     ) -> ReturnCode {
+        if target_id == 0 {
+            return self.service_id(client_or_svc);
+        }
+
         let cb_type = if client_or_svc == 0 {
             process::IPCType::Service
         } else {
             process::IPCType::Client
         };
 
-        self.data
-            .kernel
-            .process_map_or(ReturnCode::EINVAL, target_id - 1, |target| {
-                let ret = target.enqueue_task(process::Task::IPC((appid, cb_type)));
-                match ret {
-                    true => ReturnCode::SUCCESS,
-                    false => ReturnCode::FAIL,
-                }
-            })
+        let kernel = self.data.kernel;
+        let target_idx = target_id - 1;
+        if target_idx >= kernel.number_of_process_slots() {
+            return ReturnCode::EINVAL;
+        }
+        let target = AppId::new(kernel, target_idx);
+
+        kernel.process_map_or(ReturnCode::EINVAL, target_idx, |_| {
+            let ret = self
+                .data
+                .enter(target, |data, _| {
+                    match data.notifications.iter_mut().find(|n| n.is_none()) {
+                        Some(slot) => {
+                            *slot = Some((appid.idx(), cb_type));
+                            ReturnCode::SUCCESS
+                        }
+                        None => ReturnCode::ENOMEM,
+                    }
+                }).unwrap_or(ReturnCode::ENOMEM);
+            if ret == ReturnCode::SUCCESS {
+                self.flush_notifications(target);
+            }
+            ret
+        })
     }
 
     /// allow enables processes to discover IPC services on the platform or
@@ -0,0 +1,123 @@
+//! Read-only page of kernel information that processes can read directly.
+//!
+//! The page holds the kernel version, the board name and a coarse time, so
+//! apps can read them without making syscalls. An app gets the address of the
+//! page with memop `13`, which also maps the page read-only into the app
+//! through an MPU region.
+//!
+//! The page is laid out as:
+//!
+//! ```text
+//! Offset  Size  Field
+//! 0       2     Kernel major version
+//! 2       2     Kernel minor version
+//! 4       4     Frequency of the time, in Hz
+//! 8       8     Time, in ticks since boot
+//! 16      16    Board name, padded with NUL bytes
+//! ```
+//!
+//! The kernel updates the time every time it switches to a process, so the
+//! time is never older than the current timeslice of the process and never
+//! changes while the process reads it. The time comes from a `Time64`, such
+//! as a virtual alarm, whose mux extends the counter of the hardware to 64
+//! bits whatever its width.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! static mut KERNEL_INFO_PAGE: kernel::kernel_info::KernelInfoPage =
+//!     kernel::kernel_info::KernelInfoPage::new();
+//!
+//! let kernel_info_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! let kernel_info = static_init!(
+//!     kernel::kernel_info::KernelInfo<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     kernel::kernel_info::KernelInfo::new(&KERNEL_INFO_PAGE, "hail", kernel_info_alarm)
+//! );
+//! board_kernel.set_kernel_info(kernel_info);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use core::mem;
+
+use hil::time::{Frequency, Time64};
+use process;
+
+/// Length of the board name in the page.
+pub const BOARD_NAME_LEN: usize = 16;
+
+/// The memory of the page. The MPU can cover it with a single region, as it
+/// is 32 bytes and aligned to its size.
+#[repr(C, align(32))]
+pub struct KernelInfoPage {
+    version_major: Cell<u16>,
+    version_minor: Cell<u16>,
+    frequency: Cell<u32>,
+    ticks: Cell<u64>,
+    board_name: Cell<[u8; BOARD_NAME_LEN]>,
+}
+
+impl KernelInfoPage {
+    pub const fn new() -> KernelInfoPage {
+        KernelInfoPage {
+            version_major: Cell::new(0),
+            version_minor: Cell::new(0),
+            frequency: Cell::new(0),
+            ticks: Cell::new(0),
+            board_name: Cell::new([0; BOARD_NAME_LEN]),
+        }
+    }
+}
+
+/// Lets the kernel map and update a kernel information page without knowing
+/// the type of the timer behind it.
+pub trait KernelInfoSource {
+    /// The start and size of the page.
+    fn page(&self) -> (*const u8, usize);
+
+    /// Update the time in the page.
+    fn update(&self);
+}
+
+/// Fills a `KernelInfoPage` and keeps its time up to date with `time`.
+pub struct KernelInfo<'a, T: Time64 + 'a> {
+    page: &'static KernelInfoPage,
+    time: &'a T,
+}
+
+impl<T: Time64> KernelInfo<'a, T> {
+    /// Board names longer than `BOARD_NAME_LEN` bytes are truncated.
+    pub fn new(page: &'static KernelInfoPage, board_name: &str, time: &'a T) -> KernelInfo<'a, T> {
+        let mut name = [0; BOARD_NAME_LEN];
+        let name_len = cmp::min(board_name.len(), BOARD_NAME_LEN);
+        name[..name_len].copy_from_slice(&board_name.as_bytes()[..name_len]);
+
+        page.version_major.set(process::KERNEL_MAJOR_VERSION);
+        page.version_minor.set(process::KERNEL_MINOR_VERSION);
+        page.frequency.set(T::Frequency::frequency());
+        page.ticks.set(time.now64());
+        page.board_name.set(name);
+
+        KernelInfo {
+            page: page,
+            time: time,
+        }
+    }
+}
+
+impl<T: Time64> KernelInfoSource for KernelInfo<'a, T> {
+    fn page(&self) -> (*const u8, usize) {
+        (
+            self.page as *const KernelInfoPage as *const u8,
+            mem::size_of::<KernelInfoPage>(),
+        )
+    }
+
+    fn update(&self) {
+        self.page.ticks.set(self.time.now64());
+    }
+}
@@ -9,7 +9,7 @@
 #![feature(asm, core_intrinsics, ptr_internals, const_fn)]
 #![feature(use_extern_macros, try_from, used, panic_info_message)]
 #![feature(in_band_lifetimes, crate_visibility_modifier)]
-#![feature(associated_type_defaults)]
+#![feature(associated_type_defaults, as_cell)]
 #![warn(unreachable_pub)]
 #![no_std]
 
@@ -24,10 +24,16 @@ pub mod common;
 pub mod component;
 #[macro_use]
 pub mod debug;
+pub mod edf;
 pub mod hil;
 pub mod introspection;
 pub mod ipc;
+pub mod kernel_info;
+pub mod profiling;
+pub mod restart_timer;
+pub mod swap;
 pub mod syscall;
+pub mod work_queue;
 
 mod callback;
 mod driver;
@@ -38,22 +44,32 @@ mod platform;
 mod process;
 mod returncode;
 mod sched;
+mod scheduler;
 mod tbfheader;
 
 pub use callback::{AppId, Callback};
 pub use driver::Driver;
 pub use grant::Grant;
-pub use mem::{AppPtr, AppSlice, Private, Shared};
+pub use mem::{AppPtr, AppSlice, LentAppSlice, Private, Shared};
+pub use platform::sleep_stats::{SleepStatistics, SleepStats};
 pub use platform::systick::SysTick;
 pub use platform::{mpu, Chip, Platform};
-pub use platform::{ClockInterface, NoClockControl, NO_CLOCK_CONTROL};
+pub use platform::{ClockInterface, ClockManager, SleepMode};
+pub use platform::{NoClockControl, NO_CLOCK_CONTROL};
 pub use returncode::ReturnCode;
 pub use sched::Kernel;
+pub use scheduler::{PriorityScheduler, RoundRobinScheduler, Scheduler};
 
 // Export only select items from the process module. To remove the name conflict
 // this cannot be called `process`, so we use a shortened version. These
 // functions and types are used by board files to setup the platform and setup
 // processes.
 pub mod procs {
-    pub use process::{load_processes, FaultResponse, FunctionCall, Process, ProcessType};
+    pub use hil::public_key_crypto::signature::SignatureAlgorithm;
+    pub use process::{
+        load_processes, AppVerifier, BackoffRestartFaultResponse, DynamicProcessLoader,
+        FaultAction, FaultResponse, FunctionCall, PanicFaultResponse, Process, ProcessLoader,
+        ProcessType, RestartFaultResponse, State, StopFaultResponse, SwapState,
+        ThresholdRestartFaultResponse,
+    };
 }
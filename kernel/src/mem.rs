@@ -6,6 +6,7 @@ use core::ptr::Unique;
 use core::slice;
 
 use callback::AppId;
+use platform::mpu;
 
 #[derive(Debug)]
 pub struct Private;
@@ -92,8 +93,12 @@ impl<L, T> AppSlice<L, T> {
                 .kernel
                 .process_map_or(false, appid.idx(), |process| {
                     process
-                        .add_mpu_region(self.ptr() as *const u8, self.len(), self.len())
-                        .is_some()
+                        .add_mpu_region(
+                            self.ptr() as *const u8,
+                            self.len(),
+                            self.len(),
+                            mpu::Permissions::ReadWriteOnly,
+                        ).is_some()
                 })
         } else {
             false
@@ -117,6 +122,65 @@ impl<L, T> AppSlice<L, T> {
     }
 }
 
+impl AppSlice<Shared, u8> {
+    /// Lend the buffer to the kernel, for example for a peripheral to DMA
+    /// from it directly instead of from a copy.
+    ///
+    /// While the buffer is lent, the process can only read it. This only
+    /// works if the MPU can cover exactly the buffer, which on Cortex-M
+    /// means a power of two of at least 32 bytes, aligned to its size.
+    /// Otherwise the slice is handed back.
+    ///
+    /// The returned `LentAppSlice` must be reclaimed with the buffer once
+    /// the peripheral is done with it, or the process stays unable to write
+    /// the buffer.
+    pub fn lend(self) -> Result<(LentAppSlice, &'static mut [u8]), AppSlice<Shared, u8>> {
+        let ptr = self.ptr.ptr.as_ptr();
+        let len = self.len;
+        let protected = self
+            .ptr
+            .process
+            .kernel
+            .process_map_or(false, self.ptr.process.idx(), |process| {
+                process.protect_buffer(ptr as *const u8, len)
+            });
+        if protected {
+            let buffer = unsafe { slice::from_raw_parts_mut(ptr, len) };
+            Ok((LentAppSlice { slice: self }, buffer))
+        } else {
+            Err(self)
+        }
+    }
+}
+
+/// An `AppSlice` lent to the kernel with `AppSlice::lend()`.
+pub struct LentAppSlice {
+    slice: AppSlice<Shared, u8>,
+}
+
+impl LentAppSlice {
+    /// Give the process write access to the buffer again and get back the
+    /// `AppSlice`. Fails if `buffer` is not the buffer that was lent.
+    pub fn reclaim(
+        self,
+        buffer: &'static mut [u8],
+    ) -> Result<AppSlice<Shared, u8>, (LentAppSlice, &'static mut [u8])> {
+        if buffer.as_ptr() != self.slice.ptr() || buffer.len() != self.slice.len() {
+            return Err((self, buffer));
+        }
+        let ptr = self.slice.ptr();
+        let len = self.slice.len();
+        self.slice
+            .ptr
+            .process
+            .kernel
+            .process_map_or((), self.slice.ptr.process.idx(), |process| {
+                process.unprotect_buffer(ptr, len)
+            });
+        Ok(self.slice)
+    }
+}
+
 impl<L, T> AsRef<[T]> for AppSlice<L, T> {
     fn as_ref(&self) -> &[T] {
         unsafe { slice::from_raw_parts(self.ptr.ptr.as_ref(), self.len) }
@@ -1,16 +1,19 @@
 //! Implementation of the MEMOP family of syscalls.
 
+use platform::mpu;
 use process::ProcessType;
 use returncode::ReturnCode;
+use sched::Kernel;
 
 /// Handle the `memop` syscall.
 ///
 /// ### `memop_num`
 ///
 /// - `0`: BRK. Change the location of the program break and return a
-///   ReturnCode.
+///   ReturnCode. The break cannot move across a buffer the kernel has lent
+///   to a peripheral.
 /// - `1`: SBRK. Change the location of the program break and return the
-///   previous break address.
+///   previous break address. The same restriction as for BRK applies.
 /// - `2`: Get the address of the start of the application's RAM allocation.
 /// - `3`: Get the address pointing to the first address after the end of the
 ///   application's RAM allocation.
@@ -36,7 +39,11 @@ use returncode::ReturnCode;
 ///   where the app has put the start of its heap. This is not strictly
 ///   necessary for correct operation, but allows for better debugging if the
 ///   app crashes.
-crate fn memop(process: &ProcessType, op_type: usize, r1: usize) -> ReturnCode {
+/// - `12`: Get how many milliseconds of CPU time the app has used.
+/// - `13`: Get the address of the kernel information page, and map it
+///   read-only into the app. Returns `ENOSUPPORT` if the board has no page,
+///   and `ENOMEM` if the app has no MPU region left to map it with.
+crate fn memop(kernel: &Kernel, process: &ProcessType, op_type: usize, r1: usize) -> ReturnCode {
     match op_type {
         // Op Type 0: BRK
         0 /* BRK */ => {
@@ -108,6 +115,22 @@ crate fn memop(process: &ProcessType, op_type: usize, r1: usize) -> ReturnCode {
             ReturnCode::SUCCESS
         }
 
+        // Op Type 12: CPU time used by the app, in milliseconds.
+        12 => ReturnCode::SuccessWithValue { value: (process.debug_cpu_time_us() / 1000) as usize },
+
+        // Op Type 13: Map the kernel information page.
+        13 => {
+            match kernel.kernel_info_page() {
+                Some((start, size)) => {
+                    match process.add_mpu_region(start, size, size, mpu::Permissions::ReadOnly) {
+                        Some(_) => ReturnCode::SuccessWithValue { value: start as usize },
+                        None => ReturnCode::ENOMEM,
+                    }
+                }
+                None => ReturnCode::ENOSUPPORT,
+            }
+        }
+
         _ => ReturnCode::ENOSUPPORT,
     }
 }
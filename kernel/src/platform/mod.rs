@@ -1,8 +1,11 @@
 //! Interface for chips and boards.
 
 use driver::Driver;
+use hil;
+use returncode::ReturnCode;
 
 pub mod mpu;
+pub mod sleep_stats;
 crate mod systick;
 
 /// Interface for individual boards.
@@ -18,11 +21,13 @@ pub trait Platform {
 pub trait Chip {
     type MPU: mpu::MPU;
     type SysTick: systick::SysTick;
+    type Watchdog: hil::watchdog::Watchdog;
 
     fn service_pending_interrupts(&self);
     fn has_pending_interrupts(&self) -> bool;
     fn mpu(&self) -> &Self::MPU;
     fn systick(&self) -> &Self::SysTick;
+    fn watchdog(&self) -> &Self::Watchdog;
     fn sleep(&self);
     unsafe fn atomic<F, R>(&self, f: F) -> R
     where
@@ -36,6 +41,41 @@ pub trait ClockInterface {
     fn disable(&self);
 }
 
+/// How deeply the chip may sleep, from the lightest to the deepest mode.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SleepMode {
+    /// Only the CPU stops, all clocks keep running.
+    Sleep,
+    /// Clocks that no wakeup source needs are stopped.
+    DeepSleep,
+}
+
+/// Interface to the clocks of a chip.
+///
+/// Peripheral drivers request the clocks they need while they are active and
+/// release them when they become idle. The chip gates clocks that are not
+/// requested, and sleeps as deeply as the requested clocks allow.
+pub trait ClockManager {
+    /// Identifies one clock of the chip.
+    type Clock: Copy;
+
+    /// Declare that a peripheral needs `clock`, running at `min_frequency`
+    /// Hz or faster, or at any frequency if `min_frequency` is 0. Returns
+    /// `ENOSUPPORT` if the chip cannot provide that frequency, in which case
+    /// the clock is not requested.
+    fn request(&self, clock: Self::Clock, min_frequency: u32) -> ReturnCode;
+
+    /// Declare that a peripheral no longer needs `clock`. The clock is gated
+    /// once no peripheral needs it.
+    fn release(&self, clock: Self::Clock);
+
+    /// Whether `clock` is currently requested.
+    fn is_requested(&self, clock: Self::Clock) -> bool;
+
+    /// The deepest sleep mode that keeps all requested clocks running.
+    fn sleep_mode(&self) -> SleepMode;
+}
+
 /// Helper struct for interfaces that expect clocks, but have no clock control
 pub struct NoClockControl {}
 impl ClockInterface for NoClockControl {
@@ -3,13 +3,16 @@
 use core::cmp;
 
 /// User mode access permissions.
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq)]
 pub enum Permissions {
     ReadWriteExecute,
     ReadWriteOnly,
     ReadExecuteOnly,
     ReadOnly,
     ExecuteOnly,
+    /// Read-only to the process, while the kernel can still write it, for
+    /// memory the kernel lends to a peripheral.
+    LentReadOnly,
 }
 
 /// MPU region.
@@ -175,6 +178,44 @@ pub trait MPU {
         }
     }
 
+    /// Adds a region on top of memory that is already covered by other
+    /// regions, to change the user mode permissions of part of that memory.
+    ///
+    /// The region must cover exactly `size` bytes from `start`, so that the
+    /// permissions of neighbouring memory do not change.
+    ///
+    /// # Arguments
+    ///
+    /// `start`         : start of the memory
+    /// `size`          : size of the memory
+    /// `permissions`   : permissions for the region
+    /// `config`        : MPU region configuration
+    ///
+    /// # Return Value
+    ///
+    /// Returns the added region. If the MPU cannot cover exactly this memory,
+    /// or has no region left, returns None.
+    #[allow(unused_variables)]
+    fn overlay_region(
+        &self,
+        start: *const u8,
+        size: usize,
+        permissions: Permissions,
+        config: &mut Self::MpuConfig,
+    ) -> Option<Region> {
+        None
+    }
+
+    /// Removes a region added with `overlay_region`, restoring the
+    /// permissions of the regions below it.
+    ///
+    /// # Arguments
+    ///
+    /// `region`    : the region returned by `overlay_region`
+    /// `config`    : MPU region configuration
+    #[allow(unused_variables)]
+    fn remove_overlay_region(&self, region: Region, config: &mut Self::MpuConfig) {}
+
     /// Configures the MPU with the provided region configuration.
     ///
     /// An implementation must ensure that all memory locations not covered by
@@ -0,0 +1,142 @@
+//! Statistics of how a chip sleeps.
+//!
+//! A board that misses its power targets usually does not reach its deepest
+//! sleep mode often enough, or wakes up too often. The power manager of a
+//! chip keeps a `SleepStats`, and the `sleep()` of the chip records every
+//! sleep in it: the mode, how long it lasted by a counter that keeps running
+//! in all sleep modes, and the interrupt that woke the chip. `SleepStats`
+//! implements `SleepStatistics`, which capsules read the statistics with.
+//!
+//! Time is counted from the first sleep after the statistics are reset.
+//! Stretches asleep or awake longer than a wrap of the counter are counted
+//! short by the wraps.
+
+use core::cell::Cell;
+
+use common::cells::MapCell;
+use platform::SleepMode;
+
+/// The interrupts, from the first, whose wakeups are counted.
+pub const WAKEUP_INTERRUPTS: usize = 64;
+
+/// The number of `SleepMode`s.
+const SLEEP_MODES: usize = 2;
+
+/// Statistics of the time a chip spends in each sleep mode, and of what
+/// wakes it up.
+pub trait SleepStatistics {
+    /// The microseconds counted, asleep or awake.
+    fn elapsed_us(&self) -> u64;
+
+    /// The microseconds spent asleep in `mode`.
+    fn sleep_time_us(&self, mode: SleepMode) -> u64;
+
+    /// The number of times the chip slept in `mode`.
+    fn sleep_count(&self, mode: SleepMode) -> u32;
+
+    /// The number of interrupts, from the first, whose wakeups are counted.
+    fn number_interrupts(&self) -> usize;
+
+    /// The number of times `interrupt` woke the chip.
+    fn wakeups(&self, interrupt: usize) -> u32;
+
+    /// Clears the statistics.
+    fn reset(&self);
+}
+
+pub struct SleepStats {
+    /// The frequency of the counter, and its largest value
+    frequency: u32,
+    counter_mask: u32,
+    /// The counter when the chip last woke up, unless the statistics were
+    /// reset since
+    last_wakeup: Cell<Option<u32>>,
+    awake_ticks: Cell<u64>,
+    sleep_ticks: [Cell<u64>; SLEEP_MODES],
+    sleep_counts: [Cell<u32>; SLEEP_MODES],
+    wakeups: MapCell<[u32; WAKEUP_INTERRUPTS]>,
+}
+
+impl SleepStats {
+    /// Statistics timed by a counter of `counter_bits` bits that counts at
+    /// `frequency` Hz.
+    pub const fn new(frequency: u32, counter_bits: u32) -> SleepStats {
+        SleepStats {
+            frequency: frequency,
+            counter_mask: u32::max_value() >> (32 - counter_bits),
+            last_wakeup: Cell::new(None),
+            awake_ticks: Cell::new(0),
+            sleep_ticks: [Cell::new(0), Cell::new(0)],
+            sleep_counts: [Cell::new(0), Cell::new(0)],
+            wakeups: MapCell::new([0; WAKEUP_INTERRUPTS]),
+        }
+    }
+
+    /// Records a sleep in `mode`, from when the counter was `before` to when
+    /// it was `after`, and the interrupt that woke the chip, if there was
+    /// one.
+    pub fn record(&self, mode: SleepMode, before: u32, after: u32, wakeup: Option<usize>) {
+        if let Some(last_wakeup) = self.last_wakeup.get() {
+            let awake = before.wrapping_sub(last_wakeup) & self.counter_mask;
+            self.awake_ticks.set(self.awake_ticks.get() + awake as u64);
+        }
+        let asleep = after.wrapping_sub(before) & self.counter_mask;
+        let sleep_ticks = &self.sleep_ticks[mode as usize];
+        sleep_ticks.set(sleep_ticks.get() + asleep as u64);
+        let sleep_count = &self.sleep_counts[mode as usize];
+        sleep_count.set(sleep_count.get().saturating_add(1));
+        self.last_wakeup.set(Some(after));
+
+        wakeup.map(|interrupt| {
+            self.wakeups.map(|wakeups| {
+                wakeups
+                    .get_mut(interrupt)
+                    .map(|count| *count = count.saturating_add(1));
+            });
+        });
+    }
+
+    fn ticks_to_us(&self, ticks: u64) -> u64 {
+        ticks * 1_000_000 / self.frequency as u64
+    }
+}
+
+impl SleepStatistics for SleepStats {
+    fn elapsed_us(&self) -> u64 {
+        let asleep = self
+            .sleep_ticks
+            .iter()
+            .fold(0, |total, ticks| total + ticks.get());
+        self.ticks_to_us(self.awake_ticks.get() + asleep)
+    }
+
+    fn sleep_time_us(&self, mode: SleepMode) -> u64 {
+        self.ticks_to_us(self.sleep_ticks[mode as usize].get())
+    }
+
+    fn sleep_count(&self, mode: SleepMode) -> u32 {
+        self.sleep_counts[mode as usize].get()
+    }
+
+    fn number_interrupts(&self) -> usize {
+        WAKEUP_INTERRUPTS
+    }
+
+    fn wakeups(&self, interrupt: usize) -> u32 {
+        self.wakeups
+            .map(|wakeups| wakeups.get(interrupt).map_or(0, |count| *count))
+            .unwrap_or(0)
+    }
+
+    fn reset(&self) {
+        self.last_wakeup.set(None);
+        self.awake_ticks.set(0);
+        for ticks in self.sleep_ticks.iter() {
+            ticks.set(0);
+        }
+        for count in self.sleep_counts.iter() {
+            count.set(0);
+        }
+        self.wakeups.map(|wakeups| *wakeups = [0; WAKEUP_INTERRUPTS]);
+    }
+}
@@ -1,7 +1,9 @@
 //! Support for creating and running userspace applications.
 
 use core::cell::Cell;
+use core::cmp;
 use core::fmt::Write;
+use core::iter;
 use core::ptr::write_volatile;
 use core::{mem, ptr, slice, str};
 
@@ -9,63 +11,149 @@ use callback::AppId;
 use capabilities::ProcessManagementCapability;
 use common::cells::MapCell;
 use common::{Queue, RingBuffer};
+use hil::public_key_crypto::signature::SignatureAlgorithm;
 use platform::mpu::{self, MPU};
 use returncode::ReturnCode;
 use sched::Kernel;
 use syscall::{self, Syscall, UserspaceKernelBoundary};
 use tbfheader;
 
-/// Helper function to load processes from flash into an array of active
-/// processes. This is the default template for loading processes, but a board
-/// is able to create its own `load_processes()` function and use that instead.
+/// Helper function to load processes from flash into the kernel's array of
+/// active processes. This is the default template for loading processes, but
+/// a board is able to create its own `load_processes()` function and use that
+/// instead.
 ///
 /// Processes are found in flash starting from the given address and iterating
-/// through Tock Binary Format headers. Processes are given memory out of the
-/// `app_memory` buffer until either the memory is exhausted or the allocated
-/// number of processes are created, with process structures placed in the
-/// provided array. How process faults are handled by the kernel is also
-/// selected.
+/// through Tock Binary Format headers. The kernel is given the `app_memory`
+/// buffer, and processes are given memory out of it until either the memory
+/// is exhausted or there are no more process slots in the kernel. How process
+/// faults are handled by the kernel is also selected. If `app_verifier` is
+/// given, only apps with a signature it accepts are loaded.
 pub fn load_processes<S: UserspaceKernelBoundary, M: MPU>(
     kernel: &'static Kernel,
     syscall: &'static S,
     mpu: &'static M,
     start_of_flash: *const u8,
-    app_memory: &mut [u8],
-    procs: &'static mut [Option<&'static ProcessType>],
-    fault_response: FaultResponse,
-    _capability: &ProcessManagementCapability,
+    app_memory: &'static mut [u8],
+    fault_response: &'static FaultResponse,
+    app_verifier: Option<&'static AppVerifier>,
+    capability: &ProcessManagementCapability,
 ) {
+    kernel.set_app_memory(app_memory, capability);
+
     let mut apps_in_flash_ptr = start_of_flash;
-    let mut app_memory_ptr = app_memory.as_mut_ptr();
-    let mut app_memory_size = app_memory.len();
-    for i in 0..procs.len() {
-        unsafe {
-            let (process, flash_offset, memory_offset) = Process::create(
-                kernel,
-                syscall,
-                mpu,
+    loop {
+        let (result, flash_size) = kernel.create_process(
+            syscall,
+            mpu,
+            apps_in_flash_ptr,
+            fault_response,
+            app_verifier,
+            capability,
+        );
+
+        // We may have gotten a disabled process or padding, which we skip to
+        // see if there is a valid app after it. However, if we cannot advance
+        // the flash pointer, or there is no slot left, then we are done.
+        if flash_size == 0 || result == ReturnCode::ENOMEM {
+            break;
+        }
+        apps_in_flash_ptr = unsafe { apps_in_flash_ptr.offset(flash_size as isize) };
+    }
+}
+
+/// Interface for loading processes that were flashed after the kernel started,
+/// without rebooting. This lets capsules trigger loading without knowing the
+/// chip-specific types of the processes.
+pub trait ProcessLoader {
+    /// Scan the app flash area for processes that are not loaded yet and start
+    /// them. Returns the number of processes started as `SuccessWithValue`,
+    /// or `ENOMEM` if there was no process slot to put a new process in.
+    fn load_new_processes(&self, capability: &ProcessManagementCapability) -> ReturnCode;
+
+    /// Returns whether any of the `len` bytes of flash at `address` belong to
+    /// a loaded process.
+    fn flash_in_use(&self, address: usize, len: usize) -> bool;
+}
+
+/// Loads processes at runtime from the same flash area that
+/// `load_processes()` loaded the processes at boot from.
+///
+/// Flash is scanned from the start, skipping the flash of processes that are
+/// already loaded. The kernel creates the new processes in its free process
+/// slots, with the app memory that the processes loaded before left.
+pub struct DynamicProcessLoader<S: 'static + UserspaceKernelBoundary, M: 'static + MPU> {
+    kernel: &'static Kernel,
+    syscall: &'static S,
+    mpu: &'static M,
+    start_of_flash: *const u8,
+    fault_response: &'static FaultResponse,
+    app_verifier: Option<&'static AppVerifier>,
+}
+
+impl<S: 'static + UserspaceKernelBoundary, M: 'static + MPU> DynamicProcessLoader<S, M> {
+    /// `start_of_flash` and `app_verifier` must be the same as the ones
+    /// passed to `load_processes()`.
+    pub fn new(
+        kernel: &'static Kernel,
+        syscall: &'static S,
+        mpu: &'static M,
+        start_of_flash: *const u8,
+        fault_response: &'static FaultResponse,
+        app_verifier: Option<&'static AppVerifier>,
+    ) -> DynamicProcessLoader<S, M> {
+        DynamicProcessLoader {
+            kernel: kernel,
+            syscall: syscall,
+            mpu: mpu,
+            start_of_flash: start_of_flash,
+            fault_response: fault_response,
+            app_verifier: app_verifier,
+        }
+    }
+}
+
+impl<S: 'static + UserspaceKernelBoundary, M: 'static + MPU> ProcessLoader
+    for DynamicProcessLoader<S, M>
+{
+    fn load_new_processes(&self, capability: &ProcessManagementCapability) -> ReturnCode {
+        let mut apps_in_flash_ptr = self.start_of_flash;
+        let mut loaded = 0;
+        loop {
+            let (result, flash_size) = self.kernel.create_process(
+                self.syscall,
+                self.mpu,
                 apps_in_flash_ptr,
-                app_memory_ptr,
-                app_memory_size,
-                fault_response,
+                self.fault_response,
+                self.app_verifier,
+                capability,
             );
-
-            if process.is_none() {
-                // We did not get a valid process, but we may have gotten a disabled
-                // process or padding. Therefore we want to skip this chunk of flash
-                // and see if there is a valid app there. However, if we cannot
-                // advance the flash pointer, then we are done.
-                if flash_offset == 0 && memory_offset == 0 {
-                    break;
-                }
-            } else {
-                procs[i] = process;
+            match result {
+                ReturnCode::SUCCESS => loaded += 1,
+                ReturnCode::ENOMEM => return ReturnCode::ENOMEM,
+                // Loaded already, padding, a disabled app, an app without a
+                // valid signature, or an app that doesn't fit.
+                _ => {}
             }
 
-            apps_in_flash_ptr = apps_in_flash_ptr.offset(flash_offset as isize);
-            app_memory_ptr = app_memory_ptr.offset(memory_offset as isize);
-            app_memory_size -= memory_offset;
+            if flash_size == 0 {
+                break;
+            }
+            apps_in_flash_ptr = unsafe { apps_in_flash_ptr.offset(flash_size as isize) };
         }
+
+        ReturnCode::SuccessWithValue { value: loaded }
+    }
+
+    fn flash_in_use(&self, address: usize, len: usize) -> bool {
+        let end = address.saturating_add(len);
+        self.kernel.process_each_enumerate_stop(|_, process| {
+            if address < process.flash_end() as usize && end > process.flash_start() as usize {
+                ReturnCode::SUCCESS
+            } else {
+                ReturnCode::FAIL
+            }
+        }) == ReturnCode::SUCCESS
     }
 }
 
@@ -91,16 +179,86 @@ pub trait ProcessType {
     /// or "yielded".
     fn get_state(&self) -> State;
 
+    /// Returns whether the process has something to do: it is running or has
+    /// a task queued.
+    fn ready(&self) -> bool;
+
+    /// Returns the scheduling priority of the process. Higher values are more
+    /// urgent.
+    fn get_priority(&self) -> u32;
+
+    /// Returns the deadline of the current job of the process, in ticks of
+    /// the clock of the `EdfScheduler`, if the process declared one.
+    fn get_deadline(&self) -> Option<u32>;
+
+    /// Set or clear the deadline of the current job of the process.
+    fn set_deadline(&self, deadline: Option<u32>);
+
     /// Move this process from the running state to the yielded state.
     fn set_yielded_state(&self);
 
+    /// Stop scheduling this process until `resume()` is called. Tasks for the
+    /// process are still queued while it is stopped.
+    fn stop(&self);
+
+    /// Resume a stopped process in the state it was stopped in. A process
+    /// that is not resident stays stopped until it is swapped in.
+    fn resume(&self);
+
+    /// Stop the process for good, without triggering its `FaultResponse`.
+    /// Queued tasks are dropped, and the process stays in the fault state
+    /// until it is restarted.
+    fn terminate(&self);
+
+    /// Start the process over from its init function. Queued tasks are
+    /// dropped. Does nothing while the process is not resident.
+    fn restart(&self);
+
+    /// Whether the memory of the process is in RAM, swapped out, or being
+    /// copied.
+    fn swap_state(&self) -> SwapState;
+
+    /// Stop the process so that its memory can be saved. Returns false if
+    /// the process is not resident, cannot be stopped, or has buffers lent
+    /// to peripherals.
+    fn swap_out(&self) -> bool;
+
+    /// Mark the memory of the process as saved. From now on, other processes
+    /// may be created in it.
+    fn swapped_out(&self);
+
+    /// Mark the memory of a swapped out process as being restored. Returns
+    /// false if the process is not swapped out.
+    fn swap_in(&self) -> bool;
+
+    /// Mark the memory of the process as in place again and resume the
+    /// process, unless it was stopped before it was swapped out. This also
+    /// undoes `swap_out()` if saving the memory failed.
+    fn swapped_in(&self);
+
     /// Put this process in the fault state. This will trigger the
     /// `FaultResponse` for this process to occur.
     fn set_fault_state(&self);
 
+    /// Restarts the process if it is waiting for a restart delayed by its
+    /// `FaultResponse` that is due by `now`, in ticks of the restart timer of
+    /// the kernel. Returns when the restart is due if it is still pending.
+    fn restart_if_due(&self, now: u64) -> Option<u64>;
+
     /// Get the name of the process. Used for IPC.
     fn get_process_name(&self) -> &'static str;
 
+    /// Get the name of the process if its app was signed with a key that the
+    /// `AppVerifier` of the board accepts. The signature covers the TBF
+    /// header, so unlike the name alone, this identifies the app: an app
+    /// cannot claim the name of another without a signature by the board.
+    /// Returns `None` if the board loads apps without an `AppVerifier`.
+    fn get_verified_name(&self) -> Option<&'static str>;
+
+    /// Return whether the process may call the driver with number
+    /// `driver_number`, as set by the permissions in its TBF header.
+    fn driver_permitted(&self, driver_number: usize) -> bool;
+
     // memop operations
 
     /// Change the location of the program break and reallocate the MPU region
@@ -124,6 +282,10 @@ pub trait ProcessType {
     /// process.
     fn flash_end(&self) -> *const u8;
 
+    /// The end of the memory the process has been given for its stack, data
+    /// and heap.
+    fn app_memory_break(&self) -> *const u8;
+
     /// The lowest address of the grant region for the process.
     fn kernel_memory_break(&self) -> *const u8;
 
@@ -164,14 +326,35 @@ pub trait ProcessType {
     fn setup_mpu(&self);
 
     /// Allocate a new MPU region for the process that is at least `min_region_size`
-    /// bytes and lies within the specified stretch of unallocated memory.
+    /// bytes and lies within the specified stretch of unallocated memory. If
+    /// the process already has a region covering that memory, it is returned
+    /// instead.
     fn add_mpu_region(
         &self,
         unallocated_memory_start: *const u8,
         unallocated_memory_size: usize,
         min_region_size: usize,
+        permissions: mpu::Permissions,
     ) -> Option<mpu::Region>;
 
+    /// Make a buffer in the memory of the process read-only to the process,
+    /// so that the kernel can lend it to a peripheral. Returns false if the
+    /// buffer is not in the memory of the process, the MPU cannot cover
+    /// exactly the buffer, or too many buffers are lent already.
+    ///
+    /// While the buffer is lent, the process cannot move its break across
+    /// it.
+    fn protect_buffer(&self, buf_start_addr: *const u8, size: usize) -> bool;
+
+    /// Give the process back write access to a buffer protected with
+    /// `protect_buffer`.
+    fn unprotect_buffer(&self, buf_start_addr: *const u8, size: usize);
+
+    /// Whether the process has been given a region with `add_mpu_region` that
+    /// overlaps the `size` bytes at `start` and lies outside its own memory,
+    /// such as a buffer another process shared with it over IPC.
+    fn has_shared_region_in(&self, start: *const u8, size: usize) -> bool;
+
     // grants
 
     /// Create new memory in the grant region, and check that the MPU region
@@ -218,11 +401,25 @@ pub trait ProcessType {
 
     /// Returns how many times this process has exceeded its timeslice.
     fn debug_timeslice_expiration_count(&self) -> usize;
+
+    /// Returns how many times this process has missed a deadline.
+    fn debug_deadline_miss_count(&self) -> usize;
+
+    /// Record that this process missed a deadline.
+    fn debug_deadline_missed(&self);
+
+    /// Returns how many microseconds this process has run for.
+    fn debug_cpu_time_us(&self) -> u64;
+
+    /// Add `us` microseconds to the time this process has run for.
+    fn debug_add_cpu_time(&self, us: u32);
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum Error {
     NoSuchApp,
+    /// The memory of the app is swapped out.
+    InactiveApp,
     OutOfMemory,
     AddressOutOfBounds,
     KernelError, // This likely indicates a bug in the kernel and that some
@@ -235,6 +432,7 @@ impl From<Error> for ReturnCode {
             Error::OutOfMemory => ReturnCode::ENOMEM,
             Error::AddressOutOfBounds => ReturnCode::EINVAL,
             Error::NoSuchApp => ReturnCode::EINVAL,
+            Error::InactiveApp => ReturnCode::EOFF,
             Error::KernelError => ReturnCode::FAIL,
         }
     }
@@ -244,13 +442,188 @@ impl From<Error> for ReturnCode {
 pub enum State {
     Running,
     Yielded,
+    /// Stopped while running, see `ProcessType::stop()`.
+    StoppedRunning,
+    /// Stopped while yielded, see `ProcessType::stop()`.
+    StoppedYielded,
     Fault,
 }
 
+/// Where the memory of a process is, see `kernel::swap`.
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
-pub enum FaultResponse {
+pub enum SwapState {
+    /// The memory is in RAM and belongs to the process.
+    Resident,
+    /// The process is stopped while its memory is saved.
+    SwappingOut,
+    /// The memory is saved, and the RAM may hold other processes.
+    SwappedOut,
+    /// The process is stopped while its memory is restored.
+    SwappingIn,
+}
+
+/// What the kernel does with a process that faulted.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FaultAction {
+    /// Panic the kernel and print the state of the process.
     Panic,
-    Restart,
+    /// Leave the process in the fault state, so it is never scheduled again.
+    Stop,
+    /// Restart the process after `delay` milliseconds, timed by the restart
+    /// timer of the kernel.
+    Restart { delay: usize },
+}
+
+/// Policy for handling process faults.
+///
+/// Boards choose the policy for all processes when loading them. A process
+/// can override it by setting a fault response in its TBF header.
+pub trait FaultResponse {
+    /// Decide what to do with a faulted process that has already been
+    /// restarted `restart_count` times.
+    fn action(&self, restart_count: usize) -> FaultAction;
+}
+
+/// Panic the kernel when a process faults.
+pub struct PanicFaultResponse;
+
+impl FaultResponse for PanicFaultResponse {
+    fn action(&self, _restart_count: usize) -> FaultAction {
+        FaultAction::Panic
+    }
+}
+
+/// Stop processes that fault, keeping the kernel and other processes running.
+pub struct StopFaultResponse;
+
+impl FaultResponse for StopFaultResponse {
+    fn action(&self, _restart_count: usize) -> FaultAction {
+        FaultAction::Stop
+    }
+}
+
+/// Restart processes immediately each time they fault.
+pub struct RestartFaultResponse;
+
+impl FaultResponse for RestartFaultResponse {
+    fn action(&self, _restart_count: usize) -> FaultAction {
+        FaultAction::Restart { delay: 0 }
+    }
+}
+
+/// Restart processes that fault up to `threshold` times, then stop them.
+pub struct ThresholdRestartFaultResponse {
+    threshold: usize,
+}
+
+impl ThresholdRestartFaultResponse {
+    pub const fn new(threshold: usize) -> ThresholdRestartFaultResponse {
+        ThresholdRestartFaultResponse {
+            threshold: threshold,
+        }
+    }
+}
+
+impl FaultResponse for ThresholdRestartFaultResponse {
+    fn action(&self, restart_count: usize) -> FaultAction {
+        if restart_count < self.threshold {
+            FaultAction::Restart { delay: 0 }
+        } else {
+            FaultAction::Stop
+        }
+    }
+}
+
+/// Restart processes that fault with exponential backoff. The first restart
+/// waits `base_delay` milliseconds, and the delay doubles with every restart
+/// up to `max_delay`.
+///
+/// The delays are timed by the restart timer the board sets with
+/// `Kernel::set_restart_timer()`, and the kernel sleeps while they pass.
+/// Without a restart timer, processes are restarted right away.
+pub struct BackoffRestartFaultResponse {
+    base_delay: usize,
+    max_delay: usize,
+}
+
+impl BackoffRestartFaultResponse {
+    pub const fn new(base_delay: usize, max_delay: usize) -> BackoffRestartFaultResponse {
+        BackoffRestartFaultResponse {
+            base_delay: base_delay,
+            max_delay: max_delay,
+        }
+    }
+}
+
+impl FaultResponse for BackoffRestartFaultResponse {
+    fn action(&self, restart_count: usize) -> FaultAction {
+        let factor = 1 << cmp::min(restart_count, 31);
+        FaultAction::Restart {
+            delay: cmp::min(self.base_delay.saturating_mul(factor), self.max_delay),
+        }
+    }
+}
+
+impl FaultResponse for tbfheader::TbfFaultResponse {
+    fn action(&self, restart_count: usize) -> FaultAction {
+        match *self {
+            tbfheader::TbfFaultResponse::Panic => PanicFaultResponse.action(restart_count),
+            tbfheader::TbfFaultResponse::Stop => StopFaultResponse.action(restart_count),
+            tbfheader::TbfFaultResponse::Restart => RestartFaultResponse.action(restart_count),
+            tbfheader::TbfFaultResponse::ThresholdRestart(threshold) => {
+                ThresholdRestartFaultResponse::new(threshold as usize).action(restart_count)
+            }
+            tbfheader::TbfFaultResponse::BackoffRestart(base_delay, max_delay) => {
+                BackoffRestartFaultResponse::new(base_delay as usize, max_delay as usize)
+                    .action(restart_count)
+            }
+        }
+    }
+}
+
+/// Verifies the signatures of apps before they are loaded.
+///
+/// A board that only runs apps it trusts passes an `AppVerifier` to
+/// `load_processes()`. An app is then only loaded if its TBF footers hold a
+/// signature that the verifier accepts, so unsigned apps are not loaded. The
+/// signed data is the TBF from the start of the header to the end of the
+/// binary.
+///
+/// Processes are loaded before the kernel loop runs, so verifying is
+/// synchronous, unlike `hil::public_key_crypto::signature::SignatureVerify`.
+/// Chips with a crypto accelerator can implement this trait on it, and
+/// `capsules::app_verifier::SoftwareAppVerifier` implements it in software
+/// for the others.
+pub trait AppVerifier {
+    /// Return whether `signature` is a valid `algorithm` signature of `data`
+    /// by one of the keys of the board. Signatures made with algorithms the
+    /// verifier does not support are never valid.
+    fn verify(&self, algorithm: SignatureAlgorithm, data: &[u8], signature: &[u8]) -> bool;
+}
+
+/// Return whether the app at `app_flash_address` has credentials in its
+/// footers that `verifier` accepts.
+unsafe fn app_signature_valid(
+    verifier: &AppVerifier,
+    app_flash_address: *const u8,
+    tbf_header: &tbfheader::TbfHeader,
+) -> bool {
+    let binary_end = match tbf_header.get_binary_end_offset() {
+        Some(offset) => offset as usize,
+        None => return false,
+    };
+    let data = slice::from_raw_parts(app_flash_address, binary_end);
+    tbfheader::parse_tbf_footers(app_flash_address, tbf_header).any(|credentials| {
+        let (algorithm, signature) = match credentials {
+            tbfheader::TbfFooterCredentials::Ed25519(signature) => {
+                (SignatureAlgorithm::Ed25519, signature)
+            }
+            tbfheader::TbfFooterCredentials::EcdsaNistP256(signature) => {
+                (SignatureAlgorithm::EcdsaNistP256, signature)
+            }
+        };
+        verifier.verify(algorithm, data, signature)
+    })
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -313,6 +686,13 @@ struct ProcessDebug {
     /// How many times this process has been paused because it exceeded its
     /// timeslice.
     timeslice_expiration_count: usize,
+
+    /// How many times this process has completed a job after its deadline.
+    deadline_miss_count: usize,
+
+    /// How many microseconds this process has run for, measured with the
+    /// system tick timer.
+    cpu_time_us: u64,
 }
 
 pub struct Process<'a, S: 'static + UserspaceKernelBoundary, M: 'static + MPU> {
@@ -346,9 +726,15 @@ pub struct Process<'a, S: 'static + UserspaceKernelBoundary, M: 'static + MPU> {
     ///  ╚═ ╘════════ ← memory[0]
     /// ```
     ///
-    /// The process's memory.
+    /// The process's memory. The process struct, the task queue and the grant
+    /// pointers are kept apart from it, so that they stay in RAM while the
+    /// memory is swapped out.
     memory: &'static mut [u8],
 
+    /// The pointers to the grants of the process in the grant region, one
+    /// for each grant of the kernel.
+    grant_pointers: *mut *mut u8,
+
     /// Pointer to the end of the allocated (and MPU protected) grant region.
     kernel_memory_break: Cell<*const u8>,
 
@@ -379,8 +765,23 @@ pub struct Process<'a, S: 'static + UserspaceKernelBoundary, M: 'static + MPU> {
     /// Whether the scheduler can schedule this app.
     state: Cell<State>,
 
-    /// How to deal with Faults occurring in the process
-    fault_response: FaultResponse,
+    /// How to deal with Faults occurring in the process, unless the TBF
+    /// header overrides it.
+    fault_response: &'static FaultResponse,
+
+    /// When this process is restarted, in ticks of the restart timer of the
+    /// kernel, if a delayed restart is pending.
+    restart_at: Cell<Option<u64>>,
+
+    /// Deadline of the current job of the process, if it declared one.
+    deadline: Cell<Option<u32>>,
+
+    /// Whether the memory of the process is swapped out.
+    swap_state: Cell<SwapState>,
+
+    /// Whether the process was stopped for swapping, rather than before it,
+    /// and so should be resumed once it is swapped in.
+    stopped_for_swap: Cell<bool>,
 
     /// Pointer to the MPU
     mpu: &'static M,
@@ -388,8 +789,13 @@ pub struct Process<'a, S: 'static + UserspaceKernelBoundary, M: 'static + MPU> {
     /// Configuration data for the MPU
     mpu_config: MapCell<M::MpuConfig>,
 
-    /// MPU regions are saved as a pointer-size pair.
-    mpu_regions: [Cell<Option<mpu::Region>>; 6],
+    /// MPU regions are saved as a pointer-size pair, with the permissions
+    /// they were allocated with.
+    mpu_regions: [Cell<Option<(mpu::Region, mpu::Permissions)>>; 6],
+
+    /// Buffers of the process the kernel has lent to peripherals, which the
+    /// break must not move across.
+    lent_buffers: [Cell<Option<mpu::Region>>; 4],
 
     /// Essentially a list of callbacks that want to call functions in the
     /// process.
@@ -398,6 +804,10 @@ pub struct Process<'a, S: 'static + UserspaceKernelBoundary, M: 'static + MPU> {
     /// Name of the app.
     process_name: &'static str,
 
+    /// Whether the app was signed with a key the `AppVerifier` of the board
+    /// accepts.
+    signature_verified: bool,
+
     /// Values kept so that we can print useful debug messages when apps fault.
     debug: MapCell<ProcessDebug>,
 }
@@ -410,13 +820,16 @@ impl<S: UserspaceKernelBoundary, M: MPU> ProcessType for Process<'a, S, M> {
             return false;
         }
 
-        self.kernel.increment_work();
-
         let ret = self.tasks.map_or(false, |tasks| tasks.enqueue(task));
 
-        // Make a note that we lost this callback if the enqueue function
-        // fails.
-        if ret == false {
+        if ret {
+            // Tasks of a stopped process are only work once it is resumed.
+            if !self.is_stopped() {
+                self.kernel.increment_work();
+            }
+        } else {
+            // Make a note that we lost this callback if the enqueue function
+            // fails.
             self.debug.map(|debug| {
                 debug.dropped_callback_count += 1;
             });
@@ -429,6 +842,27 @@ impl<S: UserspaceKernelBoundary, M: MPU> ProcessType for Process<'a, S, M> {
         self.state.get()
     }
 
+    fn ready(&self) -> bool {
+        match self.state.get() {
+            State::Running => true,
+            State::Yielded => self.tasks.map_or(false, |tasks| tasks.has_elements()),
+            State::StoppedRunning | State::StoppedYielded => false,
+            State::Fault => false,
+        }
+    }
+
+    fn get_priority(&self) -> u32 {
+        self.header.get_priority()
+    }
+
+    fn get_deadline(&self) -> Option<u32> {
+        self.deadline.get()
+    }
+
+    fn set_deadline(&self, deadline: Option<u32>) {
+        self.deadline.set(deadline);
+    }
+
     fn set_yielded_state(&self) {
         if self.state.get() == State::Running {
             self.state.set(State::Yielded);
@@ -436,74 +870,199 @@ impl<S: UserspaceKernelBoundary, M: MPU> ProcessType for Process<'a, S, M> {
         }
     }
 
+    fn stop(&self) {
+        let stopped = match self.state.get() {
+            State::Running => State::StoppedRunning,
+            State::Yielded => State::StoppedYielded,
+            _ => return,
+        };
+        self.state.set(stopped);
+
+        // A stopped process has no work to do until it is resumed.
+        if stopped == State::StoppedRunning {
+            self.kernel.decrement_work();
+        }
+        let tasks_len = self.tasks.map_or(0, |tasks| tasks.len());
+        for _ in 0..tasks_len {
+            self.kernel.decrement_work();
+        }
+    }
+
+    fn resume(&self) {
+        if self.swap_state.get() != SwapState::Resident {
+            return;
+        }
+        self.unstop().map(|state| self.state.set(state));
+    }
+
+    fn terminate(&self) {
+        self.unstop();
+        self.cancel_restart();
+        self.leave_running();
+        self.state.set(State::Fault);
+        self.remove_tasks();
+        self.deadline.set(None);
+    }
+
     fn set_fault_state(&self) {
+        self.unstop();
+        self.leave_running();
         self.state.set(State::Fault);
 
-        match self.fault_response {
-            FaultResponse::Panic => {
+        // A new fault replaces a restart that is still pending.
+        self.cancel_restart();
+
+        let restart_count = self.debug_restart_count();
+        let action = match self.header.get_fault_response() {
+            Some(fault_response) => fault_response.action(restart_count),
+            None => self.fault_response.action(restart_count),
+        };
+
+        match action {
+            FaultAction::Panic => {
                 // process faulted. Panic and print status
                 panic!("Process {} had a fault", self.process_name);
             }
-            FaultResponse::Restart => {
-                // Remove the tasks that were scheduled for the app from the
-                // amount of work queue.
-                let tasks_len = self.tasks.map_or(0, |tasks| tasks.len());
-                for _ in 0..tasks_len {
-                    self.kernel.decrement_work();
+            FaultAction::Stop => {
+                // The process stays in the fault state, so it will not be
+                // scheduled anymore.
+                self.remove_tasks();
+            }
+            FaultAction::Restart { delay } => {
+                let restart_at = if delay == 0 {
+                    None
+                } else {
+                    self.kernel.schedule_restart(delay)
+                };
+                match restart_at {
+                    Some(restart_at) => {
+                        // The process is not ready until the kernel restarts
+                        // it, so the kernel can sleep in the meantime.
+                        self.remove_tasks();
+                        self.restart_at.set(Some(restart_at));
+                    }
+                    None => self.restart(),
+                }
+            }
+        }
+    }
+
+    fn restart_if_due(&self, now: u64) -> Option<u64> {
+        match self.restart_at.get() {
+            Some(restart_at) if self.state.get() == State::Fault => {
+                if restart_at <= now {
+                    self.restart();
+                    None
+                } else {
+                    Some(restart_at)
                 }
+            }
+            _ => None,
+        }
+    }
 
-                // And remove those tasks
-                self.tasks.map(|tasks| {
-                    tasks.empty();
-                });
+    fn restart(&self) {
+        if self.swap_state.get() != SwapState::Resident {
+            return;
+        }
+        self.unstop();
+        self.cancel_restart();
+        self.leave_running();
+        self.remove_tasks();
 
-                // Update debug information
-                self.debug.map(|debug| {
-                    // Mark that we restarted this process.
-                    debug.restart_count += 1;
+        // Update debug information
+        self.debug.map(|debug| {
+            // Mark that we restarted this process.
+            debug.restart_count += 1;
 
-                    // Reset some state for the process.
-                    debug.syscall_count = 0;
-                    debug.last_syscall = None;
-                    debug.dropped_callback_count = 0;
-                });
+            // Reset some state for the process.
+            debug.syscall_count = 0;
+            debug.last_syscall = None;
+            debug.dropped_callback_count = 0;
+        });
 
-                // We are going to start this process over again, so need
-                // the init_fn location.
-                let app_flash_address = self.flash_start();
-                let init_fn = unsafe {
-                    app_flash_address.offset(self.header.get_init_function_offset() as isize)
-                        as usize
-                };
-                self.state.set(State::Yielded);
+        // We are going to start this process over again, so need
+        // the init_fn location.
+        let app_flash_address = self.flash_start();
+        let init_fn = unsafe {
+            app_flash_address.offset(self.header.get_init_function_offset() as isize) as usize
+        };
+        self.state.set(State::Yielded);
+        self.deadline.set(None);
 
-                // Need to reset the grant region.
-                unsafe {
-                    self.grant_ptrs_reset();
-                }
-                self.kernel_memory_break
-                    .set(self.original_kernel_memory_break);
-
-                // Reset other memory pointers.
-                self.app_break.set(self.original_app_break);
-                self.current_stack_pointer.set(self.original_stack_pointer);
-
-                // And queue up this app to be restarted.
-                let flash_protected_size = self.header.get_protected_size() as usize;
-                let flash_app_start = app_flash_address as usize + flash_protected_size;
-
-                self.tasks.map(|tasks| {
-                    tasks.enqueue(Task::FunctionCall(FunctionCall {
-                        pc: init_fn,
-                        argument0: flash_app_start,
-                        argument1: self.memory.as_ptr() as usize,
-                        argument2: self.memory.len() as usize,
-                        argument3: self.app_break.get() as usize,
-                    }));
-                });
+        // Need to reset the grant region.
+        unsafe {
+            self.grant_ptrs_reset();
+        }
+        self.kernel_memory_break.set(self.original_kernel_memory_break);
 
-                self.kernel.increment_work();
+        // Reset other memory pointers.
+        self.app_break.set(self.original_app_break);
+        self.current_stack_pointer.set(self.original_stack_pointer);
+
+        // And queue up this app to be restarted.
+        let flash_protected_size = self.header.get_protected_size() as usize;
+        let flash_app_start = app_flash_address as usize + flash_protected_size;
+
+        self.tasks.map(|tasks| {
+            tasks.enqueue(Task::FunctionCall(FunctionCall {
+                pc: init_fn,
+                argument0: flash_app_start,
+                argument1: self.memory.as_ptr() as usize,
+                argument2: self.memory.len() as usize,
+                argument3: self.app_break.get() as usize,
+            }));
+        });
+
+        self.kernel.increment_work();
+    }
+
+    fn swap_state(&self) -> SwapState {
+        self.swap_state.get()
+    }
+
+    fn swap_out(&self) -> bool {
+        if self.swap_state.get() != SwapState::Resident
+            || self.lent_buffers.iter().any(|buffer| buffer.get().is_some())
+        {
+            return false;
+        }
+        let stopped_before = self.is_stopped();
+        self.stop();
+        if !self.is_stopped() {
+            return false;
+        }
+        self.stopped_for_swap.set(!stopped_before);
+        self.swap_state.set(SwapState::SwappingOut);
+        true
+    }
+
+    fn swapped_out(&self) {
+        match self.swap_state.get() {
+            SwapState::SwappingOut | SwapState::SwappingIn => {
+                self.swap_state.set(SwapState::SwappedOut);
+            }
+            _ => {}
+        }
+    }
+
+    fn swap_in(&self) -> bool {
+        if self.swap_state.get() != SwapState::SwappedOut {
+            return false;
+        }
+        self.swap_state.set(SwapState::SwappingIn);
+        true
+    }
+
+    fn swapped_in(&self) {
+        match self.swap_state.get() {
+            SwapState::SwappingOut | SwapState::SwappingIn => {
+                self.swap_state.set(SwapState::Resident);
+                if self.stopped_for_swap.get() {
+                    self.resume();
+                }
             }
+            _ => {}
         }
     }
 
@@ -536,6 +1095,10 @@ impl<S: UserspaceKernelBoundary, M: MPU> ProcessType for Process<'a, S, M> {
         unsafe { self.flash.as_ptr().offset(self.flash.len() as isize) }
     }
 
+    fn app_memory_break(&self) -> *const u8 {
+        self.app_break.get()
+    }
+
     fn kernel_memory_break(&self) -> *const u8 {
         self.kernel_memory_break.get()
     }
@@ -579,13 +1142,30 @@ impl<S: UserspaceKernelBoundary, M: MPU> ProcessType for Process<'a, S, M> {
         unallocated_memory_start: *const u8,
         unallocated_memory_size: usize,
         min_region_size: usize,
+        permissions: mpu::Permissions,
     ) -> Option<mpu::Region> {
+        // Sharing the same memory again, e.g. for every IPC notification,
+        // must not use up the regions of the process. A region only serves
+        // a request with the same permissions.
+        let start = unallocated_memory_start as usize;
+        for region in self.mpu_regions.iter() {
+            if let Some((existing, existing_permissions)) = region.get() {
+                let existing_start = existing.start_address() as usize;
+                if existing_permissions == permissions
+                    && existing_start <= start
+                    && start + min_region_size <= existing_start + existing.size()
+                {
+                    return Some(existing);
+                }
+            }
+        }
+
         self.mpu_config.and_then(|mut config| {
             let new_region = self.mpu.allocate_region(
                 unallocated_memory_start,
                 unallocated_memory_size,
                 min_region_size,
-                mpu::Permissions::ReadWriteExecute,
+                permissions,
                 &mut config,
             );
 
@@ -595,7 +1175,7 @@ impl<S: UserspaceKernelBoundary, M: MPU> ProcessType for Process<'a, S, M> {
 
             for region in self.mpu_regions.iter() {
                 if region.get().is_none() {
-                    region.set(new_region);
+                    region.set(new_region.map(|new_region| (new_region, permissions)));
                     return new_region;
                 }
             }
@@ -605,6 +1185,53 @@ impl<S: UserspaceKernelBoundary, M: MPU> ProcessType for Process<'a, S, M> {
         })
     }
 
+    fn protect_buffer(&self, buf_start_addr: *const u8, size: usize) -> bool {
+        if !self.in_app_owned_memory(buf_start_addr, size) {
+            return false;
+        }
+        let slot = match self.lent_buffers.iter().find(|slot| slot.get().is_none()) {
+            Some(slot) => slot,
+            None => return false,
+        };
+        // The new configuration takes effect the next time the process runs.
+        self.mpu_config.map_or(false, |config| {
+            self.mpu
+                .overlay_region(buf_start_addr, size, mpu::Permissions::LentReadOnly, config)
+                .map(|region| slot.set(Some(region)))
+                .is_some()
+        })
+    }
+
+    fn unprotect_buffer(&self, buf_start_addr: *const u8, size: usize) {
+        let region = mpu::Region::new(buf_start_addr, size);
+        for slot in self.lent_buffers.iter() {
+            if slot.get().map_or(false, |lent| {
+                lent.start_address() == buf_start_addr && lent.size() == size
+            }) {
+                slot.set(None);
+            }
+        }
+        self.mpu_config.map(|config| {
+            self.mpu.remove_overlay_region(region, config);
+        });
+    }
+
+    fn has_shared_region_in(&self, start: *const u8, size: usize) -> bool {
+        let start = start as usize;
+        let end = start + size;
+        let mem_start = self.mem_start() as usize;
+        let mem_end = self.mem_end() as usize;
+        self.mpu_regions
+            .iter()
+            .filter_map(|region| region.get())
+            .any(|(region, _)| {
+                let region_start = region.start_address() as usize;
+                let region_end = region_start + region.size();
+                let own_memory = mem_start <= region_start && region_end <= mem_end;
+                !own_memory && region_start < end && start < region_end
+            })
+    }
+
     fn sbrk(&self, increment: isize) -> Result<*const u8, Error> {
         let new_break = unsafe { self.app_break.get().offset(increment) };
         self.brk(new_break)
@@ -617,6 +1244,8 @@ impl<S: UserspaceKernelBoundary, M: MPU> ProcessType for Process<'a, S, M> {
                     Err(Error::AddressOutOfBounds)
                 } else if new_break > self.kernel_memory_break.get() {
                     Err(Error::OutOfMemory)
+                } else if self.break_crosses_lent_buffer(new_break) {
+                    Err(Error::AddressOutOfBounds)
                 } else if let Err(_) = self.mpu.update_app_memory_region(
                     new_break,
                     self.kernel_memory_break.get(),
@@ -633,6 +1262,21 @@ impl<S: UserspaceKernelBoundary, M: MPU> ProcessType for Process<'a, S, M> {
             })
     }
 
+    /// Returns whether moving the break to `new_break` would move it into or
+    /// across a buffer lent to a peripheral, which would let the process
+    /// reuse the memory while the peripheral still accesses it.
+    fn break_crosses_lent_buffer(&self, new_break: *const u8) -> bool {
+        let old_break = self.app_break.get() as usize;
+        let new_break = new_break as usize;
+        let (low, high) = (cmp::min(old_break, new_break), cmp::max(old_break, new_break));
+        self.lent_buffers.iter().any(|slot| {
+            slot.get().map_or(false, |lent| {
+                let start = lent.start_address() as usize;
+                low < start + lent.size() && high > start
+            })
+        })
+    }
+
     /// Checks if the buffer represented by the passed in base pointer and size
     /// are within the memory bounds currently exposed to the processes (i.e.
     /// ending at `kernel_memory_break`. If this method returns true, the buffer
@@ -668,14 +1312,25 @@ impl<S: UserspaceKernelBoundary, M: MPU> ProcessType for Process<'a, S, M> {
     unsafe fn free(&self, _: *mut u8) {}
 
     unsafe fn grant_ptr(&self, grant_num: usize) -> *mut *mut u8 {
-        let grant_num = grant_num as isize;
-        (self.mem_end() as *mut *mut u8).offset(-(grant_num + 1))
+        self.grant_pointers.offset(grant_num as isize)
     }
 
     fn get_process_name(&self) -> &'static str {
         self.process_name
     }
 
+    fn get_verified_name(&self) -> Option<&'static str> {
+        if self.signature_verified {
+            Some(self.process_name)
+        } else {
+            None
+        }
+    }
+
+    fn driver_permitted(&self, driver_number: usize) -> bool {
+        self.header.driver_permitted(driver_number)
+    }
+
     unsafe fn get_syscall(&self) -> Option<Syscall> {
         let last_syscall = self.syscall.get_syscall(self.sp());
 
@@ -799,6 +1454,26 @@ impl<S: UserspaceKernelBoundary, M: MPU> ProcessType for Process<'a, S, M> {
             .map_or(0, |debug| debug.timeslice_expiration_count)
     }
 
+    fn debug_deadline_miss_count(&self) -> usize {
+        self.debug.map_or(0, |debug| debug.deadline_miss_count)
+    }
+
+    fn debug_deadline_missed(&self) {
+        self.debug.map(|debug| {
+            debug.deadline_miss_count += 1;
+        });
+    }
+
+    fn debug_cpu_time_us(&self) -> u64 {
+        self.debug.map_or(0, |debug| debug.cpu_time_us)
+    }
+
+    fn debug_add_cpu_time(&self, us: u32) {
+        self.debug.map(|debug| {
+            debug.cpu_time_us += us as u64;
+        });
+    }
+
     unsafe fn fault_fmt(&self, writer: &mut Write) {
         self.syscall.fault_fmt(writer);
     }
@@ -929,23 +1604,56 @@ impl<S: UserspaceKernelBoundary, M: MPU> ProcessType for Process<'a, S, M> {
     }
 }
 
+/// Version of the kernel, which apps can require a minimum of in their TBF
+/// header.
+crate const KERNEL_MAJOR_VERSION: u16 = 1;
+crate const KERNEL_MINOR_VERSION: u16 = 2;
+
 impl<S: 'static + UserspaceKernelBoundary, M: 'static + MPU> Process<'a, S, M> {
-    crate unsafe fn create(
+    /// Create the process of the app at `app_flash_address`. The process
+    /// struct, task queue and grant pointers go at the end of the unallocated
+    /// memory. Process memory comes from the start of the unallocated memory,
+    /// or else from one of the `reusable_memory` blocks, which the kernel
+    /// gives back once the process no longer uses them.
+    ///
+    /// Returns the process, the size of the app in flash, and how many bytes
+    /// of the unallocated memory were used from its start and from its end.
+    crate unsafe fn create<I: Iterator<Item = (*const u8, usize)>>(
         kernel: &'static Kernel,
         syscall: &'static S,
         mpu: &'static M,
         app_flash_address: *const u8,
-        remaining_app_memory: *mut u8,
-        remaining_app_memory_size: usize,
-        fault_response: FaultResponse,
-    ) -> (Option<&'static ProcessType>, usize, usize) {
+        unallocated_memory: *mut u8,
+        unallocated_memory_size: usize,
+        reusable_memory: I,
+        fault_response: &'static FaultResponse,
+        app_verifier: Option<&'static AppVerifier>,
+    ) -> (Option<&'static ProcessType>, usize, usize, usize) {
         if let Some(tbf_header) = tbfheader::parse_and_validate_tbf_header(app_flash_address) {
             let app_flash_size = tbf_header.get_total_size() as usize;
 
             // If this isn't an app (i.e. it is padding) or it is an app but it
             // isn't enabled, then we can skip it but increment past its flash.
             if !tbf_header.is_app() || !tbf_header.enabled() {
-                return (None, app_flash_size, 0);
+                return (None, app_flash_size, 0, 0);
+            }
+
+            // Skip apps that need a newer kernel, or that were compiled for a
+            // different location in flash.
+            if !tbf_header.kernel_version_compatible(KERNEL_MAJOR_VERSION, KERNEL_MINOR_VERSION)
+                || tbf_header
+                    .get_fixed_address_flash()
+                    .map_or(false, |address| address as usize != app_flash_address as usize)
+            {
+                return (None, app_flash_size, 0, 0);
+            }
+
+            // Skip apps that are not signed by the board's keys, if the board
+            // verifies apps.
+            if let Some(verifier) = app_verifier {
+                if !app_signature_valid(verifier, app_flash_address, &tbf_header) {
+                    return (None, app_flash_size, 0, 0);
+                }
             }
 
             // Otherwise, actually load the app.
@@ -965,30 +1673,40 @@ impl<S: 'static + UserspaceKernelBoundary, M: 'static + MPU> Process<'a, S, M> {
                 mpu::Permissions::ReadExecuteOnly,
                 &mut mpu_config,
             ) {
-                return (None, app_flash_size, 0);
+                return (None, app_flash_size, 0, 0);
             }
 
-            // Determine how much space we need in the application's
-            // memory space just for kernel and grant state. We need to make
-            // sure we allocate enough memory just for that.
+            // Determine how much space the kernel needs for the state of the
+            // process. It stays in RAM while the process is swapped out, so it
+            // goes at the end of the unallocated memory rather than in
+            // process memory.
 
-            // Make room for grant pointers.
-            let grant_ptr_size = mem::size_of::<*const usize>();
-            let grant_ptrs_num = kernel.get_grant_count_and_finalize();
-            let grant_ptrs_offset = grant_ptrs_num * grant_ptr_size;
+            // Make room to store this process's metadata.
+            let process_struct_size = mem::size_of::<Process<S, M>>();
 
             // Allocate memory for callback ring buffer.
             let callback_size = mem::size_of::<Task>();
             let callback_len = 10;
-            let callbacks_offset = callback_len * callback_size;
+            let callbacks_size = callback_len * callback_size;
 
-            // Make room to store this process's metadata.
-            let process_struct_offset = mem::size_of::<Process<S, M>>();
+            // Make room for grant pointers.
+            let grant_ptr_size = mem::size_of::<*const usize>();
+            let grant_ptrs_num = kernel.get_grant_count_and_finalize();
+            let grant_ptrs_size = grant_ptrs_num * grant_ptr_size;
+
+            let unallocated_memory_end = unallocated_memory as usize + unallocated_memory_size;
+            let process_struct_start = unallocated_memory_end
+                .saturating_sub(process_struct_size + callbacks_size + grant_ptrs_size)
+                & !(mem::align_of::<Process<S, M>>() - 1);
+            if process_struct_start < unallocated_memory as usize {
+                // Failed to load process. Insufficient memory.
+                return (None, app_flash_size, 0, 0);
+            }
 
             // Initial sizes of the app-owned and kernel-owned parts of process memory.
-            // Provide the app with plenty of initial process accessible memory.
-            let initial_kernel_memory_size =
-                grant_ptrs_offset + callbacks_offset + process_struct_offset;
+            // Provide the app with plenty of initial process accessible memory,
+            // and leave room for the first grants.
+            let initial_kernel_memory_size = 512;
             let initial_app_memory_size = 3 * 1024;
 
             if min_app_ram_size < initial_app_memory_size {
@@ -998,25 +1716,70 @@ impl<S: 'static + UserspaceKernelBoundary, M: 'static + MPU> Process<'a, S, M> {
             // Minimum memory size for the process.
             let min_total_memory_size = min_app_ram_size + initial_kernel_memory_size;
 
-            // Determine where process memory will go and allocate MPU region for app-owned memory.
-            let (memory_start, memory_size) = match mpu.allocate_app_memory_region(
-                remaining_app_memory as *const u8,
-                remaining_app_memory_size,
-                min_total_memory_size,
-                initial_app_memory_size,
-                initial_kernel_memory_size,
-                mpu::Permissions::ReadWriteExecute,
-                &mut mpu_config,
-            ) {
-                Some((memory_start, memory_size)) => (memory_start, memory_size),
+            // Process memory goes in the first block of memory it fits in,
+            // starting with what is left of the unallocated memory.
+            let unallocated_memory_left = (
+                unallocated_memory as *const u8,
+                process_struct_start - unallocated_memory as usize,
+            );
+            let mut allocation = None;
+            for (block, (block_start, block_size)) in
+                iter::once(unallocated_memory_left).chain(reusable_memory).enumerate()
+            {
+                // An app compiled for a fixed RAM address must start there, so
+                // skip ahead to it.
+                let (available_memory, available_memory_size) =
+                    match tbf_header.get_fixed_address_ram() {
+                        Some(address) => {
+                            let skip = (address as usize).wrapping_sub(block_start as usize);
+                            if skip > block_size {
+                                continue;
+                            }
+                            (address as *const u8, block_size - skip)
+                        }
+                        None => (block_start, block_size),
+                    };
+
+                // Determine where process memory will go and allocate MPU region for app-owned memory.
+                if let Some((memory_start, memory_size)) = mpu.allocate_app_memory_region(
+                    available_memory,
+                    available_memory_size,
+                    min_total_memory_size,
+                    initial_app_memory_size,
+                    initial_kernel_memory_size,
+                    mpu::Permissions::ReadWriteExecute,
+                    &mut mpu_config,
+                ) {
+                    allocation = Some((block, block_start, memory_start, memory_size));
+                    break;
+                }
+            }
+            let (block, block_start, memory_start, memory_size) = match allocation {
+                Some(allocation) => allocation,
                 None => {
                     // Failed to load process. Insufficient memory.
-                    return (None, app_flash_size, 0);
+                    return (None, app_flash_size, 0, 0);
                 }
             };
 
-            // Compute how much padding before start of process memory.
-            let memory_padding_size = (memory_start as usize) - (remaining_app_memory as usize);
+            // The MPU may have moved the start of memory to meet its alignment
+            // requirements, which an app with a fixed RAM address cannot
+            // follow.
+            if tbf_header
+                .get_fixed_address_ram()
+                .map_or(false, |address| address as usize != memory_start as usize)
+            {
+                return (None, app_flash_size, 0, 0);
+            }
+
+            // Compute how much of the unallocated memory is used, including the
+            // padding before the start of process memory.
+            let memory_used = if block == 0 {
+                (memory_start as usize) - (block_start as usize) + memory_size
+            } else {
+                0
+            };
+            let process_struct_used = unallocated_memory_end - process_struct_start;
 
             // Set up process memory.
             let app_memory = slice::from_raw_parts_mut(memory_start as *mut u8, memory_size);
@@ -1026,45 +1789,40 @@ impl<S: 'static + UserspaceKernelBoundary, M: 'static + MPU> Process<'a, S, M> {
             let initial_sbrk_pointer = memory_start.offset(initial_app_memory_size as isize);
 
             // Set up initial grant region.
-            let mut kernel_memory_break = app_memory.as_mut_ptr().offset(app_memory.len() as isize);
+            let kernel_memory_break = app_memory.as_mut_ptr().offset(app_memory.len() as isize);
+
+            // The process struct comes first, then the memory for the
+            // callbacks.
+            let process_struct_memory_location = process_struct_start as *mut u8;
+            let callbacks_start = process_struct_memory_location.offset(process_struct_size as isize);
+
+            // Set up ring buffer.
+            let callback_buf = slice::from_raw_parts_mut(callbacks_start as *mut Task, callback_len);
+            let tasks = RingBuffer::new(callback_buf);
 
-            // Now that we know we have the space we can setup the grant
-            // pointers.
-            kernel_memory_break = kernel_memory_break.offset(-(grant_ptrs_offset as isize));
+            // Last thing are the grant pointers.
+            let grant_ptrs_start = callbacks_start.offset(callbacks_size as isize);
 
             // Set all pointers to null.
-            let opts =
-                slice::from_raw_parts_mut(kernel_memory_break as *mut *const usize, grant_ptrs_num);
+            let opts = slice::from_raw_parts_mut(grant_ptrs_start as *mut *const usize, grant_ptrs_num);
             for opt in opts.iter_mut() {
                 *opt = ptr::null()
             }
 
-            // Now that we know we have the space we can setup the memory
-            // for the callbacks.
-            kernel_memory_break = kernel_memory_break.offset(-(callbacks_offset as isize));
-
-            // Set up ring buffer.
-            let callback_buf =
-                slice::from_raw_parts_mut(kernel_memory_break as *mut Task, callback_len);
-            let tasks = RingBuffer::new(callback_buf);
-
-            // Last thing is the process struct.
-            kernel_memory_break = kernel_memory_break.offset(-(process_struct_offset as isize));
-            let process_struct_memory_location = kernel_memory_break;
-
             // Determine the debug information to the best of our
             // understanding. If the app is doing all of the PIC fixup and
             // memory management we don't know much.
             let mut app_heap_start_pointer = None;
             let mut app_stack_start_pointer = None;
 
-            // Create the Process struct in the app grant region.
+            // Create the Process struct in the memory set aside for it.
             let mut process: &mut Process<S, M> =
                 &mut *(process_struct_memory_location as *mut Process<'static, S, M>);
 
             process.kernel = kernel;
             process.syscall = syscall;
             process.memory = app_memory;
+            process.grant_pointers = grant_ptrs_start as *mut *mut u8;
             process.header = tbf_header;
             process.kernel_memory_break = Cell::new(kernel_memory_break);
             process.original_kernel_memory_break = kernel_memory_break;
@@ -1078,6 +1836,10 @@ impl<S: 'static + UserspaceKernelBoundary, M: 'static + MPU> Process<'a, S, M> {
             process.stored_state = Cell::new(Default::default());
             process.state = Cell::new(State::Yielded);
             process.fault_response = fault_response;
+            process.restart_at = Cell::new(None);
+            process.deadline = Cell::new(None);
+            process.swap_state = Cell::new(SwapState::Resident);
+            process.stopped_for_swap = Cell::new(false);
 
             process.mpu = mpu;
             process.mpu_config = MapCell::new(mpu_config);
@@ -1089,8 +1851,15 @@ impl<S: 'static + UserspaceKernelBoundary, M: 'static + MPU> Process<'a, S, M> {
                 Cell::new(None),
                 Cell::new(None),
             ];
+            process.lent_buffers = [
+                Cell::new(None),
+                Cell::new(None),
+                Cell::new(None),
+                Cell::new(None),
+            ];
             process.tasks = MapCell::new(tasks);
             process.process_name = process_name;
+            process.signature_verified = app_verifier.is_some();
 
             process.debug = MapCell::new(ProcessDebug {
                 app_heap_start_pointer: app_heap_start_pointer,
@@ -1101,6 +1870,8 @@ impl<S: 'static + UserspaceKernelBoundary, M: 'static + MPU> Process<'a, S, M> {
                 dropped_callback_count: 0,
                 restart_count: 0,
                 timeslice_expiration_count: 0,
+                deadline_miss_count: 0,
+                cpu_time_us: 0,
             });
 
             if (init_fn & 0x1) != 1 {
@@ -1129,16 +1900,71 @@ impl<S: 'static + UserspaceKernelBoundary, M: 'static + MPU> Process<'a, S, M> {
             return (
                 Some(process),
                 app_flash_size,
-                memory_padding_size + memory_size,
+                memory_used,
+                process_struct_used,
             );
         }
-        (None, 0, 0)
+        (None, 0, 0, 0)
     }
 
     fn mem_break(&self) -> *const u8 {
         self.kernel_memory_break.get()
     }
 
+    /// Remove the tasks that were scheduled for the app, and drop them from
+    /// the amount of outstanding work.
+    fn remove_tasks(&self) {
+        let tasks_len = self.tasks.map_or(0, |tasks| tasks.len());
+        for _ in 0..tasks_len {
+            self.kernel.decrement_work();
+        }
+
+        self.tasks.map(|tasks| {
+            tasks.empty();
+        });
+    }
+
+    /// Returns whether the process is in one of the stopped states.
+    fn is_stopped(&self) -> bool {
+        match self.state.get() {
+            State::StoppedRunning | State::StoppedYielded => true,
+            _ => false,
+        }
+    }
+
+    /// Give back the work a stopped process had when it was stopped, and
+    /// return the state it was stopped in. Returns `None` without changing
+    /// anything if the process is not stopped. The state itself is left for
+    /// the caller to change.
+    fn unstop(&self) -> Option<State> {
+        let state = match self.state.get() {
+            State::StoppedRunning => State::Running,
+            State::StoppedYielded => State::Yielded,
+            _ => return None,
+        };
+        if state == State::Running {
+            self.kernel.increment_work();
+        }
+        let tasks_len = self.tasks.map_or(0, |tasks| tasks.len());
+        for _ in 0..tasks_len {
+            self.kernel.increment_work();
+        }
+        Some(state)
+    }
+
+    /// Drop the unit of work a running process holds, before moving it to a
+    /// state other than yielded.
+    fn leave_running(&self) {
+        if self.state.get() == State::Running {
+            self.kernel.decrement_work();
+        }
+    }
+
+    /// Cancel a restart that is pending because of a `FaultResponse` delay.
+    fn cancel_restart(&self) {
+        self.restart_at.set(None);
+    }
+
     fn sp(&self) -> *const usize {
         self.current_stack_pointer.get() as *const usize
     }
@@ -1147,8 +1973,7 @@ impl<S: 'static + UserspaceKernelBoundary, M: 'static + MPU> Process<'a, S, M> {
     unsafe fn grant_ptrs_reset(&self) {
         let grant_ptrs_num = self.kernel.get_grant_count_and_finalize();
         for grant_num in 0..grant_ptrs_num {
-            let grant_num = grant_num as isize;
-            let ctr_ptr = (self.mem_end() as *mut *mut usize).offset(-(grant_num + 1));
+            let ctr_ptr = self.grant_pointers.offset(grant_num as isize);
             write_volatile(ctr_ptr, ptr::null_mut());
         }
     }
@@ -0,0 +1,226 @@
+//! Cycle-count profiling of the kernel.
+//!
+//! A `Profiler` times, with a cycle counter of the chip, what the kernel
+//! spends its time on:
+//!
+//! * servicing interrupts, which includes the interrupt handlers of the
+//!   capsules,
+//! * running each process, from switching to it until it returns to the
+//!   kernel, and
+//! * handling the subscribe, command and allow syscalls to each driver,
+//!   which is the time spent in each capsule on behalf of processes.
+//!
+//! For each of them it keeps the number of samples, their total and largest
+//! cycle counts, and a histogram of the cycle counts, so a board can find
+//! where the time goes, and which capsules and processes cause long stalls.
+//! Boards set one up with `Kernel::set_profiler()`; without a profiler the
+//! kernel does not time anything.
+//!
+//! The profiler keeps the statistics in two buffers from the board: one for
+//! each process slot, and one for the drivers, where each driver gets an
+//! entry the first time it is called. Processes in slots past the end of
+//! the first buffer, and drivers that do not fit in the second, are not
+//! profiled.
+
+use core::cell::Cell;
+
+use common::cells::TakeCell;
+
+/// The number of buckets of the histograms. Bucket `i` counts the samples
+/// below `bucket_limit(i)` cycles and at or above the limit of the bucket
+/// before it; the last bucket has no limit.
+pub const HISTOGRAM_BUCKETS: usize = 8;
+
+/// The limit of the first bucket of the histograms, in bits. Each bucket
+/// after it is 4 times wider.
+const FIRST_BUCKET_BITS: u32 = 8;
+
+pub static mut PROCESS_STATS: [Stats; 8] = [Stats::new(); 8];
+pub static mut DRIVER_STATS: [DriverStats; 16] = [DriverStats::new(); 16];
+
+/// A free-running cycle counter of the processor.
+pub trait CycleCounter {
+    /// The cycles counted, wrapping at 32 bits.
+    fn cycles(&self) -> u32;
+}
+
+/// The latency of each interrupt, from its handler to the kernel servicing
+/// it, as measured by the architecture.
+pub trait InterruptLatency {
+    /// The number of interrupts, from the first, that are measured.
+    fn number_interrupts(&self) -> usize;
+
+    /// Returns the latency statistics of `interrupt`, if it is measured.
+    fn latency_stats(&self, interrupt: usize) -> Option<Stats>;
+
+    /// Clears the statistics of all interrupts.
+    fn reset(&self);
+}
+
+/// Returns the cycle count the samples in `bucket` are below, or `None` for
+/// the last bucket.
+pub fn bucket_limit(bucket: usize) -> Option<u64> {
+    if bucket + 1 >= HISTOGRAM_BUCKETS {
+        None
+    } else {
+        Some(1 << (FIRST_BUCKET_BITS + 2 * bucket as u32))
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Stats {
+    /// The number of samples
+    pub count: u32,
+    /// The cycles of all samples
+    pub cycles: u64,
+    /// The cycles of the longest sample
+    pub max: u32,
+    pub histogram: [u32; HISTOGRAM_BUCKETS],
+}
+
+impl Stats {
+    pub const fn new() -> Stats {
+        Stats {
+            count: 0,
+            cycles: 0,
+            max: 0,
+            histogram: [0; HISTOGRAM_BUCKETS],
+        }
+    }
+
+    /// The mean cycles of a sample.
+    pub fn mean(&self) -> u64 {
+        if self.count == 0 {
+            0
+        } else {
+            self.cycles / self.count as u64
+        }
+    }
+
+    /// Adds a sample of `cycles` cycles.
+    pub fn add(&mut self, cycles: u32) {
+        let bits = 32 - cycles.leading_zeros();
+        let bucket = (bits.saturating_sub(FIRST_BUCKET_BITS) as usize + 1) / 2;
+        self.count = self.count.saturating_add(1);
+        self.cycles += cycles as u64;
+        if cycles > self.max {
+            self.max = cycles;
+        }
+        let samples = &mut self.histogram[bucket.min(HISTOGRAM_BUCKETS - 1)];
+        *samples = samples.saturating_add(1);
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct DriverStats {
+    /// The driver number of the entry, if it is in use
+    driver_num: Option<usize>,
+    stats: Stats,
+}
+
+impl DriverStats {
+    pub const fn new() -> DriverStats {
+        DriverStats {
+            driver_num: None,
+            stats: Stats::new(),
+        }
+    }
+}
+
+pub struct Profiler {
+    counter: &'static CycleCounter,
+    interrupts: Cell<Stats>,
+    processes: TakeCell<'static, [Stats]>,
+    drivers: TakeCell<'static, [DriverStats]>,
+}
+
+impl Profiler {
+    pub fn new(
+        counter: &'static CycleCounter,
+        processes: &'static mut [Stats],
+        drivers: &'static mut [DriverStats],
+    ) -> Profiler {
+        Profiler {
+            counter: counter,
+            interrupts: Cell::new(Stats::new()),
+            processes: TakeCell::new(processes),
+            drivers: TakeCell::new(drivers),
+        }
+    }
+
+    /// The cycle counter, at the start of what is timed.
+    crate fn start(&self) -> u32 {
+        self.counter.cycles()
+    }
+
+    fn elapsed(&self, start: u32) -> u32 {
+        self.counter.cycles().wrapping_sub(start)
+    }
+
+    crate fn interrupts_done(&self, start: u32) {
+        let mut stats = self.interrupts.get();
+        stats.add(self.elapsed(start));
+        self.interrupts.set(stats);
+    }
+
+    crate fn process_done(&self, index: usize, start: u32) {
+        let cycles = self.elapsed(start);
+        self.processes.map(|processes| {
+            processes.get_mut(index).map(|stats| stats.add(cycles));
+        });
+    }
+
+    crate fn driver_done(&self, driver_num: usize, start: u32) {
+        let cycles = self.elapsed(start);
+        self.drivers.map(|drivers| {
+            let entry = match drivers
+                .iter()
+                .position(|entry| entry.driver_num == Some(driver_num))
+            {
+                Some(index) => Some(index),
+                None => drivers.iter().position(|entry| entry.driver_num.is_none()),
+            };
+            entry.map(|index| {
+                drivers[index].driver_num = Some(driver_num);
+                drivers[index].stats.add(cycles);
+            });
+        });
+    }
+
+    /// Returns the statistics of servicing interrupts.
+    pub fn interrupt_stats(&self) -> Stats {
+        self.interrupts.get()
+    }
+
+    /// Returns the statistics of running the process in slot `index`, if
+    /// the slot is profiled.
+    pub fn process_stats(&self, index: usize) -> Option<Stats> {
+        self.processes
+            .map_or(None, |processes| processes.get(index).map(|stats| *stats))
+    }
+
+    /// Returns the driver number and the statistics of handling syscalls
+    /// for entry `index` of the drivers, if a driver has the entry.
+    pub fn driver_stats(&self, index: usize) -> Option<(usize, Stats)> {
+        self.drivers.map_or(None, |drivers| {
+            drivers
+                .get(index)
+                .and_then(|entry| entry.driver_num.map(|num| (num, entry.stats)))
+        })
+    }
+
+    /// Clears all statistics.
+    pub fn reset(&self) {
+        self.interrupts.set(Stats::new());
+        self.processes.map(|processes| {
+            for stats in processes.iter_mut() {
+                *stats = Stats::new();
+            }
+        });
+        self.drivers.map(|drivers| {
+            for entry in drivers.iter_mut() {
+                *entry = DriverStats::new();
+            }
+        });
+    }
+}
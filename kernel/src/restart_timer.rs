@@ -0,0 +1,79 @@
+//! Timer for the delayed restarts of faulted processes.
+//!
+//! A `FaultResponse` can restart a faulted process after a delay, such as
+//! the growing delays of `BackoffRestartFaultResponse`. The delay is measured
+//! with an `Alarm64`, such as a virtual alarm, so the kernel can sleep while a
+//! restart is pending: the alarm wakes the chip up when the earliest pending
+//! restart is due, and the kernel loop restarts the process. The alarm is only
+//! armed while a restart is pending.
+//!
+//! A board without a restart timer restarts faulted processes right away,
+//! whatever the delay.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let restart_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! let restart_timer = static_init!(
+//!     kernel::restart_timer::RestartTimer<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     kernel::restart_timer::RestartTimer::new(restart_alarm)
+//! );
+//! restart_alarm.set_client(restart_timer);
+//! board_kernel.set_restart_timer(restart_timer);
+//! ```
+
+use hil::time::{self, Alarm64, Frequency};
+
+/// Lets the kernel time restarts without knowing the type of the alarm
+/// behind the timer.
+pub trait RestartClock {
+    /// The current time, in ticks.
+    fn now(&self) -> u64;
+
+    /// The number of ticks in `ms` milliseconds.
+    fn ms_to_ticks(&self, ms: usize) -> u64;
+
+    /// Wake the kernel up at `when`, replacing the previous wakeup.
+    fn set_wakeup(&self, when: u64);
+
+    /// Cancel the wakeup, as no restart is pending.
+    fn clear_wakeup(&self);
+}
+
+pub struct RestartTimer<'a, A: Alarm64 + 'a> {
+    alarm: &'a A,
+}
+
+impl<A: Alarm64 + 'a> RestartTimer<'a, A> {
+    pub fn new(alarm: &'a A) -> RestartTimer<'a, A> {
+        RestartTimer { alarm: alarm }
+    }
+}
+
+impl<A: Alarm64 + 'a> RestartClock for RestartTimer<'a, A> {
+    fn now(&self) -> u64 {
+        self.alarm.now64()
+    }
+
+    fn ms_to_ticks(&self, ms: usize) -> u64 {
+        ms as u64 * A::Frequency::frequency() as u64 / 1000
+    }
+
+    fn set_wakeup(&self, when: u64) {
+        self.alarm.set_alarm64(when);
+    }
+
+    fn clear_wakeup(&self) {
+        self.alarm.disable64();
+    }
+}
+
+impl<A: Alarm64 + 'a> time::Client for RestartTimer<'a, A> {
+    /// Nothing to do, the kernel loop restarts the processes that are due
+    /// after servicing the interrupt.
+    fn fired(&self) {}
+}
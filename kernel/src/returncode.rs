@@ -37,6 +37,8 @@ pub enum ReturnCode {
     EUNINSTALLED,
     /// Packet transmission not acknowledged
     ENOACK,
+    /// Caller is not permitted to perform the operation
+    EPERM,
 }
 
 impl From<ReturnCode> for isize {
@@ -57,6 +59,7 @@ impl From<ReturnCode> for isize {
             ReturnCode::ENODEVICE => -11,
             ReturnCode::EUNINSTALLED => -12,
             ReturnCode::ENOACK => -13,
+            ReturnCode::EPERM => -14,
         }
     }
 }
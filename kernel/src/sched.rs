@@ -1,28 +1,38 @@
 //! Tock core scheduler.
 
 use core::cell::Cell;
+use core::cmp;
 use core::ptr;
 use core::ptr::NonNull;
 
 use callback;
 use callback::{AppId, Callback};
 use capabilities;
-use common::cells::NumericCellExt;
+use common::cells::{NumericCellExt, OptionalCell, TakeCell};
 use grant::Grant;
+use hil::watchdog::Watchdog;
 use ipc;
+use kernel_info::KernelInfoSource;
 use mem::AppSlice;
 use memop;
 use platform::mpu::MPU;
 use platform::systick::SysTick;
 use platform::{Chip, Platform};
-use process::{self, Task};
+use process::{self, AppVerifier, FaultResponse, Task};
+use profiling::Profiler;
+use restart_timer::RestartClock;
 use returncode::ReturnCode;
-use syscall::{ContextSwitchReason, Syscall};
+use scheduler::Scheduler;
+use syscall::{ContextSwitchReason, Syscall, UserspaceKernelBoundary};
+use tbfheader;
+use work_queue::WorkQueue;
 
 /// The time a process is permitted to run before being pre-empted
 const KERNEL_TICK_DURATION_US: u32 = 10000;
 /// Skip re-scheduling a process if its quanta is nearly exhausted
 const MIN_QUANTA_THRESHOLD_US: u32 = 500;
+/// The iterations a chunk of queued kernel work may do before yielding
+const WORK_QUEUE_ITERATIONS: usize = 64;
 
 /// Main object for the kernel. Each board will need to create one.
 pub struct Kernel {
@@ -30,7 +40,11 @@ pub struct Kernel {
     /// outstanding callbacks and processes in the Running state.
     work: Cell<usize>,
     /// This holds a pointer to the static array of Process pointers.
-    processes: &'static [Option<&'static process::ProcessType>],
+    processes: &'static [Cell<Option<&'static process::ProcessType>>],
+    /// The app memory that has not been given to a process yet.
+    app_memory: TakeCell<'static, [u8]>,
+    /// Chooses which process runs next.
+    scheduler: &'static Scheduler,
     /// How many grant regions have been setup. This is incremented on every
     /// call to `create_grant()`. We need to explicitly track this so that when
     /// processes are created they can allocated pointers for each grant.
@@ -40,15 +54,37 @@ pub struct Kernel {
     /// created and the data structures for grants have already been
     /// established.
     grants_finalized: Cell<bool>,
+    /// Long-running kernel work, which runs when no process is ready.
+    work_queue: WorkQueue<'static>,
+    /// The page of kernel information processes can map, if the board set
+    /// one up.
+    kernel_info: OptionalCell<&'static KernelInfoSource>,
+    /// Times interrupts, processes and syscalls, if the board set one up.
+    profiler: OptionalCell<&'static Profiler>,
+    /// Times the delayed restarts of faulted processes, if the board set one
+    /// up.
+    restart_timer: OptionalCell<&'static RestartClock>,
+    /// Whether a process may be waiting for a delayed restart.
+    restart_pending: Cell<bool>,
 }
 
 impl Kernel {
-    pub fn new(processes: &'static [Option<&'static process::ProcessType>]) -> Kernel {
+    pub fn new(
+        processes: &'static mut [Option<&'static process::ProcessType>],
+        scheduler: &'static Scheduler,
+    ) -> Kernel {
         Kernel {
             work: Cell::new(0),
-            processes: processes,
+            processes: Cell::from_mut(processes).as_slice_of_cells(),
+            app_memory: TakeCell::empty(),
+            scheduler: scheduler,
             grant_counter: Cell::new(0),
             grants_finalized: Cell::new(false),
+            work_queue: WorkQueue::new(),
+            kernel_info: OptionalCell::empty(),
+            profiler: OptionalCell::empty(),
+            restart_timer: OptionalCell::empty(),
+            restart_pending: Cell::new(false),
         }
     }
 
@@ -80,7 +116,9 @@ impl Kernel {
         if process_index > self.processes.len() {
             return default;
         }
-        self.processes[process_index].map_or(default, |process| closure(process))
+        self.processes[process_index]
+            .get()
+            .map_or(default, |process| closure(process))
     }
 
     /// Run a closure on every valid process. This will iterate the array of
@@ -90,9 +128,9 @@ impl Kernel {
         F: Fn(usize, &process::ProcessType),
     {
         for (i, process) in self.processes.iter().enumerate() {
-            match process {
+            match process.get() {
                 Some(p) => {
-                    closure(i, *p);
+                    closure(i, p);
                 }
                 None => {}
             }
@@ -108,9 +146,9 @@ impl Kernel {
         F: Fn(usize, &process::ProcessType) -> ReturnCode,
     {
         for (i, process) in self.processes.iter().enumerate() {
-            match process {
+            match process.get() {
                 Some(p) => {
-                    let ret = closure(i, *p);
+                    let ret = closure(i, p);
                     if ret != ReturnCode::FAIL {
                         return ret;
                     }
@@ -126,6 +164,140 @@ impl Kernel {
         self.processes.len()
     }
 
+    /// Give the kernel the memory that processes are created in. Processes
+    /// get memory from the start of what is left of it, in the order they
+    /// are created, and the kernel keeps the struct of each process at the
+    /// end of it.
+    ///
+    /// Only callers with the `ProcessManagementCapability` can give the
+    /// kernel app memory.
+    pub fn set_app_memory(
+        &self,
+        app_memory: &'static mut [u8],
+        _capability: &capabilities::ProcessManagementCapability,
+    ) {
+        self.app_memory.replace(app_memory);
+    }
+
+    /// Create the process of the app in flash at `app_flash` in a free
+    /// process slot, with memory from the app memory that is left. If that
+    /// is not enough, the process is created in the memory of a swapped out
+    /// process that no other process uses.
+    ///
+    /// Returns the size of the app in flash, which is where the next app
+    /// starts, or 0 if there is no app or padding at `app_flash`. The return
+    /// code is:
+    ///
+    /// - `SUCCESS` if the process was created,
+    /// - `EALREADY` if a process runs from `app_flash` already,
+    /// - `ENOMEM` if there is an app but no free process slot for it, and
+    /// - `FAIL` if there is padding or a disabled app at `app_flash`, or the
+    ///   app did not pass the `app_verifier` or fit in the app memory.
+    ///
+    /// Only callers with the `ProcessManagementCapability` can create
+    /// processes.
+    pub fn create_process<S: UserspaceKernelBoundary, M: MPU>(
+        &'static self,
+        syscall: &'static S,
+        mpu: &'static M,
+        app_flash: *const u8,
+        fault_response: &'static FaultResponse,
+        app_verifier: Option<&'static AppVerifier>,
+        _capability: &capabilities::ProcessManagementCapability,
+    ) -> (ReturnCode, usize) {
+        let running = self
+            .processes
+            .iter()
+            .filter_map(|slot| slot.get())
+            .find(|process| process.flash_start() == app_flash);
+        if let Some(process) = running {
+            let flash_size = process.flash_end() as usize - app_flash as usize;
+            return (ReturnCode::EALREADY, flash_size);
+        }
+
+        let slot = match self.processes.iter().find(|slot| slot.get().is_none()) {
+            Some(slot) => slot,
+            None => {
+                let header = unsafe { tbfheader::parse_and_validate_tbf_header(app_flash) };
+                return header.map_or((ReturnCode::FAIL, 0), |header| {
+                    let flash_size = header.get_total_size() as usize;
+                    if header.is_app() && header.enabled() {
+                        (ReturnCode::ENOMEM, flash_size)
+                    } else {
+                        (ReturnCode::FAIL, flash_size)
+                    }
+                });
+            }
+        };
+
+        // The memory of swapped out processes can be reused, as long as no
+        // other process has been created in it.
+        let reusable_memory = self
+            .processes
+            .iter()
+            .filter_map(|slot| slot.get())
+            .filter(|process| process.swap_state() == process::SwapState::SwappedOut)
+            .map(|process| {
+                let start = process.mem_start();
+                (start, process.mem_end() as usize - start as usize)
+            })
+            .filter(|&(start, size)| !self.process_memory_in_use(start, size));
+
+        let app_memory = self.app_memory.take().unwrap_or(&mut []);
+        let (process, flash_offset, memory_offset, process_struct_offset) = unsafe {
+            process::Process::create(
+                self,
+                syscall,
+                mpu,
+                app_flash,
+                app_memory.as_mut_ptr(),
+                app_memory.len(),
+                reusable_memory,
+                fault_response,
+                app_verifier,
+            )
+        };
+        // The process owns its memory and its struct now, only the rest is
+        // left.
+        let rest_len = app_memory.len() - memory_offset - process_struct_offset;
+        let (_, rest) = app_memory.split_at_mut(memory_offset);
+        let (rest, _) = rest.split_at_mut(rest_len);
+        self.app_memory.replace(rest);
+
+        match process {
+            Some(process) => {
+                slot.set(Some(process));
+                (ReturnCode::SUCCESS, flash_offset)
+            }
+            None => (ReturnCode::FAIL, flash_offset),
+        }
+    }
+
+    /// Whether any of the `size` bytes at `start` belong to a process whose
+    /// memory is not swapped out.
+    crate fn process_memory_in_use(&self, start: *const u8, size: usize) -> bool {
+        let start = start as usize;
+        let end = start + size;
+        self.processes
+            .iter()
+            .filter_map(|slot| slot.get())
+            .any(|process| {
+                process.swap_state() != process::SwapState::SwappedOut
+                    && (process.mem_start() as usize) < end
+                    && start < process.mem_end() as usize
+            })
+    }
+
+    /// Whether any of the `size` bytes at `start` were shared with another
+    /// process over IPC. The MPU regions of shared memory are never taken
+    /// back, so such memory must stay with its process.
+    crate fn process_memory_shared(&self, start: *const u8, size: usize) -> bool {
+        self.processes
+            .iter()
+            .filter_map(|slot| slot.get())
+            .any(|process| process.has_shared_region_in(start, size))
+    }
+
     /// Create a new grant. This is used in board initialization to setup grants
     /// that capsules use to interact with processes.
     ///
@@ -163,6 +335,85 @@ impl Kernel {
         self.grant_counter.get()
     }
 
+    /// The queue that capsules put long-running work on.
+    pub fn work_queue(&self) -> &WorkQueue<'static> {
+        &self.work_queue
+    }
+
+    /// Set the page of kernel information that processes can map with
+    /// memop `13`.
+    pub fn set_kernel_info(&self, kernel_info: &'static KernelInfoSource) {
+        self.kernel_info.set(kernel_info);
+    }
+
+    /// The start and size of the kernel information page, if there is one.
+    crate fn kernel_info_page(&self) -> Option<(*const u8, usize)> {
+        self.kernel_info.map(|kernel_info| kernel_info.page())
+    }
+
+    /// Set the profiler that times what the kernel spends its time on.
+    pub fn set_profiler(&self, profiler: &'static Profiler) {
+        self.profiler.set(profiler);
+    }
+
+    /// Set the timer that delays the restarts of faulted processes.
+    pub fn set_restart_timer(&self, restart_timer: &'static RestartClock) {
+        self.restart_timer.set(restart_timer);
+    }
+
+    /// The time a restart delayed by `delay_ms` milliseconds is due, or
+    /// `None` if there is no restart timer to delay it with. The kernel
+    /// checks for due restarts until no process is waiting for one.
+    crate fn schedule_restart(&self, delay_ms: usize) -> Option<u64> {
+        self.restart_timer.map(|timer| {
+            self.restart_pending.set(true);
+            timer.now() + timer.ms_to_ticks(delay_ms)
+        })
+    }
+
+    /// Restart the processes whose restart delay has passed, and wake up
+    /// when the earliest restart that is still pending is due.
+    fn restart_due_processes(&self) {
+        if !self.restart_pending.get() {
+            return;
+        }
+        self.restart_timer.map(|timer| {
+            let now = timer.now();
+            let mut next: Option<u64> = None;
+            for p in self.processes.iter() {
+                p.get().map(|process| {
+                    process.restart_if_due(now).map(|due| {
+                        next = Some(next.map_or(due, |next| cmp::min(next, due)));
+                    });
+                });
+            }
+            match next {
+                Some(due) => timer.set_wakeup(due),
+                None => {
+                    timer.clear_wakeup();
+                    self.restart_pending.set(false);
+                }
+            }
+        });
+    }
+
+    /// The cycle counter of the profiler, if there is one, to time from.
+    fn profile_start(&self) -> u32 {
+        self.profiler.map_or(0, |profiler| profiler.start())
+    }
+
+    /// Runs `f`, which handles a syscall to `driver_number`, and adds its
+    /// cycles to the driver if there is a profiler.
+    fn profile_driver<F>(&self, driver_number: usize, f: F) -> ReturnCode
+    where
+        F: FnOnce() -> ReturnCode,
+    {
+        let start = self.profile_start();
+        let res = f();
+        self.profiler.map(|profiler| profiler.driver_done(driver_number, start));
+        res
+    }
+
     /// Cause all apps to fault.
     ///
     /// This will call `set_fault_state()` on each app, causing the app to enter
@@ -175,7 +426,7 @@ impl Kernel {
     /// apps.
     pub fn hardfault_all_apps<C: capabilities::ProcessManagementCapability>(&self, _c: &C) {
         for p in self.processes.iter() {
-            p.map(|process| {
+            p.get().map(|process| {
                 process.set_fault_state();
             });
         }
@@ -191,32 +442,68 @@ impl Kernel {
     ) {
         loop {
             unsafe {
+                chip.watchdog().pet();
+                // Only time the loops that had interrupts to service
+                let pending = chip.has_pending_interrupts();
+                let start = self.profile_start();
                 chip.service_pending_interrupts();
+                if pending {
+                    self.profiler.map(|profiler| profiler.interrupts_done(start));
+                }
 
-                for (i, p) in self.processes.iter().enumerate() {
-                    p.map(|process| {
-                        self.do_process(
-                            platform,
-                            chip,
-                            process,
-                            callback::AppId::new(self, i),
-                            ipc,
-                        );
-                    });
-                    if chip.has_pending_interrupts() {
-                        break;
+                // Run processes until an interrupt needs servicing, asking the
+                // scheduler again after each one so that a process that just
+                // became ready can preempt the others.
+                while !chip.has_pending_interrupts() {
+                    match self.scheduler.next(self.processes) {
+                        Some(i) => {
+                            self.processes[i].get().map(|process| {
+                                self.do_process(
+                                    platform,
+                                    chip,
+                                    process,
+                                    callback::AppId::new(self, i),
+                                    ipc,
+                                );
+                            });
+                        }
+                        None => {
+                            // No process is ready, so give the time to queued
+                            // kernel work, one chunk per loop.
+                            self.work_queue.run_chunk(WORK_QUEUE_ITERATIONS);
+                            break;
+                        }
                     }
                 }
 
+                self.restart_due_processes();
+
                 chip.atomic(|| {
-                    if !chip.has_pending_interrupts() && self.processes_blocked() {
+                    if !chip.has_pending_interrupts()
+                        && self.processes_blocked()
+                        && !self.work_queue.has_work()
+                    {
+                        chip.watchdog().suspend();
                         chip.sleep();
+                        chip.watchdog().resume();
                     }
                 });
             };
         }
     }
 
+    /// Returns whether `process` may make `syscall`. Processes can be
+    /// restricted to a set of drivers in their TBF header, which applies to
+    /// subscribe, command and allow. Yield and memop are always permitted.
+    fn syscall_permitted(process: &process::ProcessType, syscall: Option<Syscall>) -> bool {
+        match syscall {
+            Some(Syscall::SUBSCRIBE { driver_number, .. })
+            | Some(Syscall::COMMAND { driver_number, .. })
+            | Some(Syscall::ALLOW { driver_number, .. }) => process.driver_permitted(driver_number),
+            _ => true,
+        }
+    }
+
     unsafe fn do_process<P: Platform, C: Chip>(
         &self,
         platform: &P,
@@ -243,11 +530,17 @@ impl Kernel {
                     // Running means that this process expects to be running,
                     // so go ahead and set things up and switch to executing
                     // the process.
+                    self.kernel_info.map(|kernel_info| kernel_info.update());
                     process.setup_mpu();
                     chip.mpu().enable_mpu();
                     systick.enable(true);
+                    let remaining_before = systick.remaining_us();
+                    let start = self.profile_start();
                     let context_switch_reason = process.switch_to();
+                    self.profiler.map(|profiler| profiler.process_done(appid.idx(), start));
+                    let remaining_after = systick.remaining_us();
                     systick.enable(false);
+                    process.debug_add_cpu_time(remaining_before.saturating_sub(remaining_after));
                     chip.mpu().disable_mpu();
 
                     // Now the process has returned back to the kernel. Check
@@ -258,10 +551,16 @@ impl Kernel {
                             process.set_fault_state();
                         }
                         Some(ContextSwitchReason::SyscallFired) => {
+                            let syscall = process.get_syscall();
+                            if !Kernel::syscall_permitted(process, syscall) {
+                                process.set_syscall_return_value(ReturnCode::EPERM.into());
+                                continue;
+                            }
+
                             // Handle each of the syscalls.
-                            match process.get_syscall() {
+                            match syscall {
                                 Some(Syscall::MEMOP { operand, arg0 }) => {
-                                    let res = memop::memop(process, operand, arg0);
+                                    let res = memop::memop(self, process, operand, arg0);
                                     process.set_syscall_return_value(res.into());
                                 }
                                 Some(Syscall::YIELD) => {
@@ -281,16 +580,14 @@ impl Kernel {
                                     let callback = callback_ptr
                                         .map(|ptr| Callback::new(appid, appdata, ptr.cast()));
 
-                                    let res =
-                                        platform.with_driver(
-                                            driver_number,
-                                            |driver| match driver {
-                                                Some(d) => {
-                                                    d.subscribe(subdriver_number, callback, appid)
-                                                }
-                                                None => ReturnCode::ENODEVICE,
-                                            },
-                                        );
+                                    let res = self.profile_driver(driver_number, || {
+                                        platform.with_driver(driver_number, |driver| match driver {
+                                            Some(d) => {
+                                                d.subscribe(subdriver_number, callback, appid)
+                                            }
+                                            None => ReturnCode::ENODEVICE,
+                                        })
+                                    });
                                     process.set_syscall_return_value(res.into());
                                 }
                                 Some(Syscall::COMMAND {
@@ -299,16 +596,14 @@ impl Kernel {
                                     arg0,
                                     arg1,
                                 }) => {
-                                    let res =
-                                        platform.with_driver(
-                                            driver_number,
-                                            |driver| match driver {
-                                                Some(d) => {
-                                                    d.command(subdriver_number, arg0, arg1, appid)
-                                                }
-                                                None => ReturnCode::ENODEVICE,
-                                            },
-                                        );
+                                    let res = self.profile_driver(driver_number, || {
+                                        platform.with_driver(driver_number, |driver| match driver {
+                                            Some(d) => {
+                                                d.command(subdriver_number, arg0, arg1, appid)
+                                            }
+                                            None => ReturnCode::ENODEVICE,
+                                        })
+                                    });
                                     process.set_syscall_return_value(res.into());
                                 }
                                 Some(Syscall::ALLOW {
@@ -317,33 +612,35 @@ impl Kernel {
                                     allow_address,
                                     allow_size,
                                 }) => {
-                                    let res = platform.with_driver(driver_number, |driver| {
-                                        match driver {
-                                            Some(d) => {
-                                                if allow_address != ptr::null_mut() {
-                                                    if process.in_app_owned_memory(
-                                                        allow_address,
-                                                        allow_size,
-                                                    ) {
-                                                        let slice = AppSlice::new(
+                                    let res = self.profile_driver(driver_number, || {
+                                        platform.with_driver(driver_number, |driver| {
+                                            match driver {
+                                                Some(d) => {
+                                                    if allow_address != ptr::null_mut() {
+                                                        if process.in_app_owned_memory(
                                                             allow_address,
                                                             allow_size,
-                                                            appid,
-                                                        );
-                                                        d.allow(
-                                                            appid,
-                                                            subdriver_number,
-                                                            Some(slice),
-                                                        )
+                                                        ) {
+                                                            let slice = AppSlice::new(
+                                                                allow_address,
+                                                                allow_size,
+                                                                appid,
+                                                            );
+                                                            d.allow(
+                                                                appid,
+                                                                subdriver_number,
+                                                                Some(slice),
+                                                            )
+                                                        } else {
+                                                            ReturnCode::EINVAL /* memory not allocated to process */
+                                                        }
                                                     } else {
-                                                        ReturnCode::EINVAL /* memory not allocated to process */
+                                                        d.allow(appid, subdriver_number, None)
                                                     }
-                                                } else {
-                                                    d.allow(appid, subdriver_number, None)
                                                 }
+                                                None => ReturnCode::ENODEVICE,
                                             }
-                                            None => ReturnCode::ENODEVICE,
-                                        }
+                                        })
                                     });
                                     process.set_syscall_return_value(res.into());
                                 }
@@ -370,7 +667,14 @@ impl Kernel {
                     // If the process is yielded it might be waiting for a
                     // callback. If there is a task scheduled for this process
                     // go ahead and set the process to execute it.
-                    None => break,
+                    None => {
+                        // IPC notifications that did not fit in the task queue
+                        // can be delivered now that it is empty.
+                        if ipc.map_or(false, |ipc| ipc.flush_notifications(appid)) {
+                            continue;
+                        }
+                        break;
+                    }
                     Some(cb) => match cb {
                         Task::FunctionCall(ccb) => {
                             process.push_function_call(ccb);
@@ -390,9 +694,14 @@ impl Kernel {
                         }
                     },
                 },
+                process::State::StoppedRunning | process::State::StoppedYielded => {
+                    // A stopped process does not run until it is resumed.
+                    break;
+                }
                 process::State::Fault => {
-                    // We should never be scheduling a process in fault.
-                    panic!("Attempted to schedule a faulty process");
+                    // A faulted process never runs. It is either stopped or
+                    // waiting to be restarted by its fault response.
+                    break;
                 }
             }
         }
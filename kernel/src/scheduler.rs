@@ -0,0 +1,93 @@
+//! Policies for choosing which process the kernel runs next.
+//!
+//! Boards pick a scheduler when creating the `Kernel`. After handling
+//! interrupts, the kernel loop asks the scheduler for the next process to run,
+//! runs it until it yields, its timeslice expires or an interrupt is pending,
+//! and asks again. Since a pending interrupt hands control back to the
+//! scheduler, a process that becomes ready in an interrupt can preempt a
+//! process with a lower priority.
+
+use core::cell::Cell;
+
+use process::ProcessType;
+
+/// Chooses the next process the kernel runs.
+pub trait Scheduler {
+    /// Return the index in `processes` of the process to run next, or `None`
+    /// if no process is ready to run.
+    fn next(&self, processes: &[Cell<Option<&'static ProcessType>>]) -> Option<usize>;
+}
+
+/// Runs ready processes in turn, in the order of the process array.
+pub struct RoundRobinScheduler {
+    /// Index of the process that ran last.
+    last: Cell<usize>,
+}
+
+impl RoundRobinScheduler {
+    pub const fn new() -> RoundRobinScheduler {
+        RoundRobinScheduler {
+            last: Cell::new(0),
+        }
+    }
+}
+
+impl Scheduler for RoundRobinScheduler {
+    fn next(&self, processes: &[Cell<Option<&'static ProcessType>>]) -> Option<usize> {
+        let len = processes.len();
+        for offset in 1..len + 1 {
+            let index = (self.last.get() + offset) % len;
+            if let Some(process) = processes[index].get() {
+                if process.ready() {
+                    self.last.set(index);
+                    return Some(index);
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Always runs the ready process with the highest priority. Processes set
+/// their priority in their TBF header, and processes with the same priority
+/// run in turn.
+pub struct PriorityScheduler {
+    /// Index of the process that ran last.
+    last: Cell<usize>,
+}
+
+impl PriorityScheduler {
+    pub const fn new() -> PriorityScheduler {
+        PriorityScheduler {
+            last: Cell::new(0),
+        }
+    }
+}
+
+impl Scheduler for PriorityScheduler {
+    fn next(&self, processes: &[Cell<Option<&'static ProcessType>>]) -> Option<usize> {
+        let len = processes.len();
+        let mut next: Option<(usize, u32)> = None;
+
+        // Start after the process that ran last, so that only a strictly
+        // higher priority replaces the first candidate and processes with the
+        // same priority take turns.
+        for offset in 1..len + 1 {
+            let index = (self.last.get() + offset) % len;
+            if let Some(process) = processes[index].get() {
+                if !process.ready() {
+                    continue;
+                }
+                let priority = process.get_priority();
+                if next.map_or(true, |(_, next_priority)| priority > next_priority) {
+                    next = Some((index, priority));
+                }
+            }
+        }
+
+        next.map(|(index, _)| {
+            self.last.set(index);
+            index
+        })
+    }
+}
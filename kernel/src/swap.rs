@@ -0,0 +1,290 @@
+//! Swapping the memory of processes out to nonvolatile storage.
+//!
+//! `ProcessSwap` stops a process and copies its memory to external flash or
+//! FRAM, and later copies it back to the same address and resumes the
+//! process. This lets a board run more apps than fit in RAM at once: once a
+//! process is swapped out, `Kernel::create_process()` can create another
+//! process in its memory, for example through a `DynamicProcessLoader`.
+//!
+//! All of process memory is saved, grants included. The process struct, its
+//! task queue and its grant pointers are kept apart from process memory, so
+//! they stay in RAM. While a process is not resident it is not scheduled, and
+//! capsules cannot enter its grants, which fails with `EOFF`. Callbacks for it
+//! are still queued, and are delivered once it is swapped in. The MPU
+//! configuration of the process stays with the kernel and is applied again on
+//! the next context switch after it is swapped in.
+//!
+//! A process cannot be swapped out while it has buffers lent to peripherals,
+//! or while another process has access to its memory over IPC. It can only be
+//! swapped in once no resident process uses its memory anymore. Capsules must
+//! keep the buffers of processes in grants, not hold on to them elsewhere.
+//!
+//! Each process slot gets `slot_size` bytes of the storage, starting at
+//! `slot_size` times the slot index.
+//!
+//! Since swapping reads and writes the memory of processes, `ProcessSwap` can
+//! only be created with the `ProcessManagementCapability`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! static mut SWAP_BUF: [u8; 512] = [0; 512];
+//!
+//! let process_swap = static_init!(
+//!     kernel::swap::ProcessSwap<'static, capsules::fm25cl::FM25CL<'static, FramSpi>>,
+//!     kernel::swap::ProcessSwap::new(
+//!         board_kernel,
+//!         fm25cl,
+//!         &mut SWAP_BUF,
+//!         0x4000,
+//!         &process_management_capability
+//!     )
+//! );
+//! hil::nonvolatile_storage::NonvolatileStorage::set_client(fm25cl, process_swap);
+//! ```
+
+use core::cell::Cell;
+use core::{cmp, slice};
+
+use callback::AppId;
+use capabilities::ProcessManagementCapability;
+use common::cells::{OptionalCell, TakeCell};
+use hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use process::SwapState;
+use returncode::ReturnCode;
+use sched::Kernel;
+
+/// Told when swapping a process out or in is done.
+pub trait SwapClient {
+    /// The memory of `app` has been saved and may hold other processes now,
+    /// or saving it failed and the app goes on.
+    fn swap_out_done(&self, app: AppId, result: ReturnCode);
+
+    /// The memory of `app` has been restored and the app resumed, or
+    /// restoring it failed and the app stays swapped out.
+    fn swap_in_done(&self, app: AppId, result: ReturnCode);
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    Idle,
+    SwapOut,
+    SwapIn,
+}
+
+pub struct ProcessSwap<'a, S: NonvolatileStorage + 'a> {
+    kernel: &'static Kernel,
+    storage: &'a S,
+    buffer: TakeCell<'static, [u8]>,
+    /// Bytes of storage for each process slot.
+    slot_size: usize,
+    operation: Cell<Operation>,
+    app: OptionalCell<AppId>,
+    /// Bytes of the memory of the app copied so far.
+    offset: Cell<usize>,
+    client: OptionalCell<&'static SwapClient>,
+}
+
+impl<S: NonvolatileStorage> ProcessSwap<'a, S> {
+    pub fn new(
+        kernel: &'static Kernel,
+        storage: &'a S,
+        buffer: &'static mut [u8],
+        slot_size: usize,
+        _capability: &ProcessManagementCapability,
+    ) -> ProcessSwap<'a, S> {
+        ProcessSwap {
+            kernel: kernel,
+            storage: storage,
+            buffer: TakeCell::new(buffer),
+            slot_size: slot_size,
+            operation: Cell::new(Operation::Idle),
+            app: OptionalCell::empty(),
+            offset: Cell::new(0),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'static SwapClient) {
+        self.client.set(client);
+    }
+
+    /// Stop `app` and save its memory. Returns
+    ///
+    /// - `EBUSY` if another process is being swapped, or the memory of the app
+    ///   is shared with another process,
+    /// - `EALREADY` if the app is not resident,
+    /// - `ESIZE` if its memory does not fit in a slot of the storage, and
+    /// - `FAIL` if the app cannot be stopped or has buffers lent to
+    ///   peripherals.
+    pub fn swap_out(&self, app: AppId) -> ReturnCode {
+        if self.operation.get() != Operation::Idle {
+            return ReturnCode::EBUSY;
+        }
+        let result = self
+            .kernel
+            .process_map_or(ReturnCode::EINVAL, app.idx(), |process| {
+                let start = process.mem_start();
+                let size = process.mem_end() as usize - start as usize;
+                if process.swap_state() != SwapState::Resident {
+                    ReturnCode::EALREADY
+                } else if size > self.slot_size {
+                    ReturnCode::ESIZE
+                } else if self.kernel.process_memory_shared(start, size) {
+                    ReturnCode::EBUSY
+                } else if !process.swap_out() {
+                    ReturnCode::FAIL
+                } else {
+                    ReturnCode::SUCCESS
+                }
+            });
+        if result == ReturnCode::SUCCESS {
+            self.start(Operation::SwapOut, app);
+        }
+        result
+    }
+
+    /// Restore the memory of `app` and resume it. Returns `EBUSY` if another
+    /// process is being swapped or is using the memory of the app, and
+    /// `EALREADY` if the app is not swapped out.
+    pub fn swap_in(&self, app: AppId) -> ReturnCode {
+        if self.operation.get() != Operation::Idle {
+            return ReturnCode::EBUSY;
+        }
+        let result = self
+            .kernel
+            .process_map_or(ReturnCode::EINVAL, app.idx(), |process| {
+                let start = process.mem_start();
+                let size = process.mem_end() as usize - start as usize;
+                if process.swap_state() != SwapState::SwappedOut {
+                    ReturnCode::EALREADY
+                } else if self.kernel.process_memory_in_use(start, size) {
+                    ReturnCode::EBUSY
+                } else if !process.swap_in() {
+                    ReturnCode::FAIL
+                } else {
+                    ReturnCode::SUCCESS
+                }
+            });
+        if result == ReturnCode::SUCCESS {
+            self.start(Operation::SwapIn, app);
+        }
+        result
+    }
+
+    fn start(&self, operation: Operation, app: AppId) {
+        self.operation.set(operation);
+        self.app.set(app);
+        self.offset.set(0);
+        self.next_chunk();
+    }
+
+    /// The memory of the app being swapped.
+    fn memory(&self) -> Option<(*const u8, usize)> {
+        self.app.map_or(None, |app| {
+            self.kernel.process_map_or(None, app.idx(), |process| {
+                let start = process.mem_start();
+                Some((start, process.mem_end() as usize - start as usize))
+            })
+        })
+    }
+
+    /// Move the next chunk of memory, or finish if all of it has been moved.
+    fn next_chunk(&self) {
+        let (start, size) = match self.memory() {
+            Some(memory) => memory,
+            None => {
+                self.finish(ReturnCode::FAIL);
+                return;
+            }
+        };
+        let offset = self.offset.get();
+        if offset >= size {
+            self.finish(ReturnCode::SUCCESS);
+            return;
+        }
+        let address = self.app.map_or(0, |app| app.idx() * self.slot_size) + offset;
+
+        let result = self.buffer.take().map_or(ReturnCode::FAIL, |buffer| {
+            let length = cmp::min(buffer.len(), size - offset);
+            match self.operation.get() {
+                Operation::SwapOut => {
+                    let memory =
+                        unsafe { slice::from_raw_parts(start.offset(offset as isize), length) };
+                    buffer[..length].copy_from_slice(memory);
+                    self.storage.write(buffer, address, length)
+                }
+                Operation::SwapIn => self.storage.read(buffer, address, length),
+                Operation::Idle => {
+                    self.buffer.replace(buffer);
+                    ReturnCode::FAIL
+                }
+            }
+        });
+        if result != ReturnCode::SUCCESS {
+            self.finish(result);
+        }
+    }
+
+    fn finish(&self, result: ReturnCode) {
+        let operation = self.operation.get();
+        self.operation.set(Operation::Idle);
+        self.app.take().map(|app| {
+            match operation {
+                Operation::SwapOut => {
+                    self.kernel.process_map_or((), app.idx(), |process| {
+                        if result == ReturnCode::SUCCESS {
+                            process.swapped_out();
+                        } else {
+                            // The memory is still in place, so the app can go
+                            // on.
+                            process.swapped_in();
+                        }
+                    });
+                    self.client.map(|client| client.swap_out_done(app, result));
+                }
+                Operation::SwapIn => {
+                    self.kernel.process_map_or((), app.idx(), |process| {
+                        if result == ReturnCode::SUCCESS {
+                            process.swapped_in();
+                        } else {
+                            process.swapped_out();
+                        }
+                    });
+                    self.client.map(|client| client.swap_in_done(app, result));
+                }
+                Operation::Idle => {}
+            }
+        });
+    }
+}
+
+impl<S: NonvolatileStorage> NonvolatileStorageClient for ProcessSwap<'a, S> {
+    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        if let Some((start, _)) = self.memory() {
+            let offset = self.offset.get();
+            let memory = unsafe {
+                slice::from_raw_parts_mut(start.offset(offset as isize) as *mut u8, length)
+            };
+            memory.copy_from_slice(&buffer[..length]);
+            self.offset.set(offset + length);
+        }
+        self.buffer.replace(buffer);
+        if length == 0 {
+            self.finish(ReturnCode::FAIL);
+        } else {
+            self.next_chunk();
+        }
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], length: usize) {
+        self.buffer.replace(buffer);
+        if length == 0 {
+            self.finish(ReturnCode::FAIL);
+        } else {
+            self.offset.set(self.offset.get() + length);
+            self.next_chunk();
+        }
+    }
+}
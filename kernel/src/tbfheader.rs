@@ -1,6 +1,6 @@
 //! Tock Binary Format Header definitions and parsing code.
 
-use core::{mem, slice, str};
+use core::{cmp, mem, slice, str};
 
 /// Takes a value and rounds it up to be aligned % 4
 macro_rules! align4 {
@@ -21,21 +21,47 @@ crate struct TbfHeaderV2Base {
 }
 
 /// Types in TLV structures for each optional block of the header.
+///
+/// The numbering follows elf2tab and tockloader. Types 4 (PIC option) and 7
+/// (persistent ACL) are not used by the kernel.
 #[repr(u16)]
 #[derive(Clone, Copy, Debug)]
-#[allow(dead_code)]
 crate enum TbfHeaderTypes {
     TbfHeaderMain = 1,
     TbfHeaderWriteableFlashRegions = 2,
     TbfHeaderPackageName = 3,
-    Unused = 5,
+    TbfHeaderFixedAddresses = 5,
+    TbfHeaderPermissions = 6,
+    TbfHeaderKernelVersion = 8,
+    TbfHeaderFaultResponse = 9,
+    TbfHeaderPriority = 10,
+    TbfHeaderBinaryEnd = 11,
+}
+
+impl TbfHeaderTypes {
+    /// The type with the number `tipe`, or `None` for types the kernel does
+    /// not know.
+    fn from_u16(tipe: u16) -> Option<TbfHeaderTypes> {
+        match tipe {
+            1 => Some(TbfHeaderTypes::TbfHeaderMain),
+            2 => Some(TbfHeaderTypes::TbfHeaderWriteableFlashRegions),
+            3 => Some(TbfHeaderTypes::TbfHeaderPackageName),
+            5 => Some(TbfHeaderTypes::TbfHeaderFixedAddresses),
+            6 => Some(TbfHeaderTypes::TbfHeaderPermissions),
+            8 => Some(TbfHeaderTypes::TbfHeaderKernelVersion),
+            9 => Some(TbfHeaderTypes::TbfHeaderFaultResponse),
+            10 => Some(TbfHeaderTypes::TbfHeaderPriority),
+            11 => Some(TbfHeaderTypes::TbfHeaderBinaryEnd),
+            _ => None,
+        }
+    }
 }
 
 /// The TLV header (T and L).
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
 crate struct TbfHeaderTlv {
-    tipe: TbfHeaderTypes,
+    tipe: u16,
     length: u16,
 }
 
@@ -62,6 +88,66 @@ crate struct TbfHeaderV2WriteableFlashRegion {
     writeable_flash_region_size: u32,
 }
 
+/// Fault response the process asks for, overriding the one of the board.
+///
+/// `argument0` and `argument1` are only used by some responses, see
+/// `TbfFaultResponse`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+crate struct TbfHeaderV2FaultResponse {
+    response: u32,
+    argument0: u32,
+    argument1: u32,
+}
+
+/// Scheduling priority of the process, used by the `PriorityScheduler`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+crate struct TbfHeaderV2Priority {
+    priority: u32,
+}
+
+/// Addresses the process was compiled for, if it is not position
+/// independent. `0xFFFFFFFF` means the process can be placed anywhere.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+crate struct TbfHeaderV2FixedAddresses {
+    start_process_ram: u32,
+    start_process_flash: u32,
+}
+
+/// Minimum kernel version the process needs.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+crate struct TbfHeaderV2KernelVersion {
+    major: u16,
+    minor: u16,
+}
+
+/// Where the app binary ends. Footers, like the credentials of the app, are
+/// between the end of the binary and the end of the TBF.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+crate struct TbfHeaderV2BinaryEnd {
+    binary_end_offset: u32,
+}
+
+/// Fault responses that can be selected in the header.
+#[derive(Clone, Copy, Debug)]
+crate enum TbfFaultResponse {
+    /// `response` 0
+    Panic,
+    /// `response` 1
+    Stop,
+    /// `response` 2
+    Restart,
+    /// `response` 3, with the maximum number of restarts in `argument0`
+    ThresholdRestart(u32),
+    /// `response` 4, with the base and maximum delay in `argument0` and
+    /// `argument1`
+    BackoffRestart(u32, u32),
+}
+
 /// Single header that can contain all parts of a v2 header.
 #[derive(Clone, Copy, Debug)]
 crate struct TbfHeaderV2 {
@@ -69,6 +155,12 @@ crate struct TbfHeaderV2 {
     main: Option<&'static TbfHeaderV2Main>,
     package_name: Option<&'static str>,
     writeable_regions: Option<&'static [TbfHeaderV2WriteableFlashRegion]>,
+    fault_response: Option<&'static TbfHeaderV2FaultResponse>,
+    priority: Option<&'static TbfHeaderV2Priority>,
+    permitted_drivers: Option<&'static [u32]>,
+    fixed_addresses: Option<&'static TbfHeaderV2FixedAddresses>,
+    kernel_version: Option<&'static TbfHeaderV2KernelVersion>,
+    binary_end: Option<&'static TbfHeaderV2BinaryEnd>,
 }
 
 /// Type that represents the fields of the Tock Binary Format header.
@@ -152,6 +244,18 @@ impl TbfHeader {
         }
     }
 
+    /// Return whether the app may use the driver with number `driver_number`.
+    /// Apps without a permissions element in their header may use every
+    /// driver.
+    crate fn driver_permitted(&self, driver_number: usize) -> bool {
+        match *self {
+            TbfHeader::TbfHeaderV2(hd) => hd.permitted_drivers.map_or(true, |drivers| {
+                drivers.iter().any(|&driver| driver as usize == driver_number)
+            }),
+            _ => false,
+        }
+    }
+
     /// Get the number of flash regions this app has specified in its header.
     crate fn number_writeable_flash_regions(&self) -> usize {
         match *self {
@@ -176,6 +280,81 @@ impl TbfHeader {
             _ => (0, 0),
         }
     }
+
+    /// Get the scheduling priority of the app. Apps without a priority in
+    /// their header get the lowest priority, 0.
+    crate fn get_priority(&self) -> u32 {
+        match *self {
+            TbfHeader::TbfHeaderV2(hd) => hd.priority.map_or(0, |p| p.priority),
+            _ => 0,
+        }
+    }
+
+    /// Get the address the app's RAM must start at, if the app is not
+    /// position independent.
+    crate fn get_fixed_address_ram(&self) -> Option<u32> {
+        match *self {
+            TbfHeader::TbfHeaderV2(hd) => hd
+                .fixed_addresses
+                .map(|fa| fa.start_process_ram)
+                .filter(|&address| address != 0xFFFFFFFF),
+            _ => None,
+        }
+    }
+
+    /// Get the address the app must be placed at in flash, if the app is not
+    /// position independent.
+    crate fn get_fixed_address_flash(&self) -> Option<u32> {
+        match *self {
+            TbfHeader::TbfHeaderV2(hd) => hd
+                .fixed_addresses
+                .map(|fa| fa.start_process_flash)
+                .filter(|&address| address != 0xFFFFFFFF),
+            _ => None,
+        }
+    }
+
+    /// Return whether a kernel with version `major.minor` is recent enough
+    /// for the app. Apps without a kernel version in their header run on
+    /// any kernel.
+    crate fn kernel_version_compatible(&self, major: u16, minor: u16) -> bool {
+        match *self {
+            TbfHeader::TbfHeaderV2(hd) => hd
+                .kernel_version
+                .map_or(true, |kv| (major, minor) >= (kv.major, kv.minor)),
+            _ => false,
+        }
+    }
+
+    /// Get the offset from the beginning of the app's flash region where the
+    /// binary ends and its footers start, if the header has a valid one.
+    crate fn get_binary_end_offset(&self) -> Option<u32> {
+        match *self {
+            TbfHeader::TbfHeaderV2(hd) => hd.binary_end.map(|be| be.binary_end_offset).filter(
+                |&offset| {
+                    offset >= hd.base.header_size as u32
+                        && offset <= hd.base.total_size
+                        && offset % 4 == 0
+                },
+            ),
+            _ => None,
+        }
+    }
+
+    /// Get the fault response the app asks for, if it set a valid one.
+    crate fn get_fault_response(&self) -> Option<TbfFaultResponse> {
+        match *self {
+            TbfHeader::TbfHeaderV2(hd) => hd.fault_response.and_then(|fr| match fr.response {
+                0 => Some(TbfFaultResponse::Panic),
+                1 => Some(TbfFaultResponse::Stop),
+                2 => Some(TbfFaultResponse::Restart),
+                3 => Some(TbfFaultResponse::ThresholdRestart(fr.argument0)),
+                4 => Some(TbfFaultResponse::BackoffRestart(fr.argument0, fr.argument1)),
+                _ => None,
+            }),
+            _ => None,
+        }
+    }
 }
 
 /// Converts a pointer to memory to a TbfHeader struct
@@ -191,10 +370,11 @@ crate unsafe fn parse_and_validate_tbf_header(address: *const u8) -> Option<TbfH
             let tbf_header_base = &*(address as *const TbfHeaderV2Base);
 
             // Some sanity checking. Make sure the header isn't longer than the
-            // total app. Make sure the total app fits inside a reasonable size
-            // of flash.
+            // total app, and is at least as long as the base. Make sure the
+            // total app fits inside a reasonable size of flash.
             if tbf_header_base.header_size as u32 >= tbf_header_base.total_size
                 || tbf_header_base.total_size > 0x010000000
+                || (tbf_header_base.header_size as usize) < mem::size_of::<TbfHeaderV2Base>()
             {
                 return None;
             }
@@ -246,6 +426,12 @@ crate unsafe fn parse_and_validate_tbf_header(address: *const u8) -> Option<TbfH
                     &'static [TbfHeaderV2WriteableFlashRegion],
                 > = None;
                 let mut app_name_str = "";
+                let mut fault_response_pointer: Option<&TbfHeaderV2FaultResponse> = None;
+                let mut priority_pointer: Option<&TbfHeaderV2Priority> = None;
+                let mut permissions_pointer: Option<&'static [u32]> = None;
+                let mut fixed_addresses_pointer: Option<&TbfHeaderV2FixedAddresses> = None;
+                let mut kernel_version_pointer: Option<&TbfHeaderV2KernelVersion> = None;
+                let mut binary_end_pointer: Option<&TbfHeaderV2BinaryEnd> = None;
 
                 // Loop through the header looking for known options.
                 while remaining_length > mem::size_of::<TbfHeaderTlv>() {
@@ -254,13 +440,16 @@ crate unsafe fn parse_and_validate_tbf_header(address: *const u8) -> Option<TbfH
                     remaining_length -= mem::size_of::<TbfHeaderTlv>();
                     offset += mem::size_of::<TbfHeaderTlv>() as isize;
 
-                    // Only parse known TLV blocks. There is no type 0.
-                    if (tbf_tlv_header.tipe as u16) < TbfHeaderTypes::Unused as u16
-                        && (tbf_tlv_header.tipe as u16) > 0
-                    {
-                        // This lets us skip unknown header types.
+                    // A TLV block that runs past the end of the header means
+                    // the header is malformed.
+                    if align4!(tbf_tlv_header.length as usize) > remaining_length {
+                        return None;
+                    }
 
-                        match tbf_tlv_header.tipe {
+                    // Only parse known TLV blocks. This lets us skip unknown
+                    // header types.
+                    if let Some(tipe) = TbfHeaderTypes::from_u16(tbf_tlv_header.tipe) {
+                        match tipe {
                             TbfHeaderTypes::TbfHeaderMain =>
                             /* Main */
                             {
@@ -277,9 +466,10 @@ crate unsafe fn parse_and_validate_tbf_header(address: *const u8) -> Option<TbfH
                             /* Writeable Flash Regions */
                             {
                                 // Length must be a multiple of the size of a region definition.
-                                if tbf_tlv_header.length as usize
-                                    % mem::size_of::<TbfHeaderV2WriteableFlashRegion>()
-                                    == 0
+                                if remaining_length >= tbf_tlv_header.length as usize
+                                    && tbf_tlv_header.length as usize
+                                        % mem::size_of::<TbfHeaderV2WriteableFlashRegion>()
+                                        == 0
                                 {
                                     let number_regions = tbf_tlv_header.length as usize
                                         / mem::size_of::<TbfHeaderV2WriteableFlashRegion>();
@@ -304,14 +494,89 @@ crate unsafe fn parse_and_validate_tbf_header(address: *const u8) -> Option<TbfH
                                         });
                                 }
                             }
-                            TbfHeaderTypes::Unused => {}
+                            TbfHeaderTypes::TbfHeaderFaultResponse =>
+                            /* Fault Response */
+                            {
+                                if remaining_length >= mem::size_of::<TbfHeaderV2FaultResponse>()
+                                    && tbf_tlv_header.length as usize
+                                        == mem::size_of::<TbfHeaderV2FaultResponse>()
+                                {
+                                    let tbf_fault_response = &*(address.offset(offset)
+                                        as *const TbfHeaderV2FaultResponse);
+                                    fault_response_pointer = Some(tbf_fault_response);
+                                }
+                            }
+                            TbfHeaderTypes::TbfHeaderPriority =>
+                            /* Priority */
+                            {
+                                if remaining_length >= mem::size_of::<TbfHeaderV2Priority>()
+                                    && tbf_tlv_header.length as usize
+                                        == mem::size_of::<TbfHeaderV2Priority>()
+                                {
+                                    let tbf_priority =
+                                        &*(address.offset(offset) as *const TbfHeaderV2Priority);
+                                    priority_pointer = Some(tbf_priority);
+                                }
+                            }
+                            TbfHeaderTypes::TbfHeaderPermissions =>
+                            /* Permissions */
+                            {
+                                // Length must be a multiple of the size of a driver number.
+                                if remaining_length >= tbf_tlv_header.length as usize
+                                    && tbf_tlv_header.length as usize % mem::size_of::<u32>() == 0
+                                {
+                                    let number_drivers =
+                                        tbf_tlv_header.length as usize / mem::size_of::<u32>();
+                                    let drivers = slice::from_raw_parts(
+                                        address.offset(offset) as *const u32,
+                                        number_drivers,
+                                    );
+                                    permissions_pointer = Some(drivers);
+                                }
+                            }
+                            TbfHeaderTypes::TbfHeaderFixedAddresses =>
+                            /* Fixed Addresses */
+                            {
+                                if remaining_length >= mem::size_of::<TbfHeaderV2FixedAddresses>()
+                                    && tbf_tlv_header.length as usize
+                                        == mem::size_of::<TbfHeaderV2FixedAddresses>()
+                                {
+                                    let tbf_fixed_addresses = &*(address.offset(offset)
+                                        as *const TbfHeaderV2FixedAddresses);
+                                    fixed_addresses_pointer = Some(tbf_fixed_addresses);
+                                }
+                            }
+                            TbfHeaderTypes::TbfHeaderKernelVersion =>
+                            /* Kernel Version */
+                            {
+                                if remaining_length >= mem::size_of::<TbfHeaderV2KernelVersion>()
+                                    && tbf_tlv_header.length as usize
+                                        == mem::size_of::<TbfHeaderV2KernelVersion>()
+                                {
+                                    let tbf_kernel_version = &*(address.offset(offset)
+                                        as *const TbfHeaderV2KernelVersion);
+                                    kernel_version_pointer = Some(tbf_kernel_version);
+                                }
+                            }
+                            TbfHeaderTypes::TbfHeaderBinaryEnd =>
+                            /* Binary End */
+                            {
+                                if remaining_length >= mem::size_of::<TbfHeaderV2BinaryEnd>()
+                                    && tbf_tlv_header.length as usize
+                                        == mem::size_of::<TbfHeaderV2BinaryEnd>()
+                                {
+                                    let tbf_binary_end =
+                                        &*(address.offset(offset) as *const TbfHeaderV2BinaryEnd);
+                                    binary_end_pointer = Some(tbf_binary_end);
+                                }
+                            }
                         }
                     }
 
                     // All TLV blocks are padded to 4 bytes, so we need to skip
                     // more if the length is not a multiple of 4.
-                    remaining_length -= align4!(tbf_tlv_header.length) as usize;
-                    offset += align4!(tbf_tlv_header.length) as isize;
+                    remaining_length -= align4!(tbf_tlv_header.length as usize);
+                    offset += align4!(tbf_tlv_header.length as isize);
                 }
 
                 let tbf_header = TbfHeaderV2 {
@@ -319,6 +584,12 @@ crate unsafe fn parse_and_validate_tbf_header(address: *const u8) -> Option<TbfH
                     main: main_pointer,
                     package_name: Some(app_name_str),
                     writeable_regions: wfr_pointer,
+                    fault_response: fault_response_pointer,
+                    priority: priority_pointer,
+                    permitted_drivers: permissions_pointer,
+                    fixed_addresses: fixed_addresses_pointer,
+                    kernel_version: kernel_version_pointer,
+                    binary_end: binary_end_pointer,
                 };
 
                 Some(TbfHeader::TbfHeaderV2(tbf_header))
@@ -330,3 +601,234 @@ crate unsafe fn parse_and_validate_tbf_header(address: *const u8) -> Option<TbfH
         _ => None,
     }
 }
+
+/// Type of the credentials TLV element in the footers.
+const TBF_FOOTER_CREDENTIALS: u16 = 128;
+
+/// Credentials of an app, found in its footers.
+#[derive(Clone, Copy, Debug)]
+crate enum TbfFooterCredentials {
+    /// `format` 1, a 64-byte signature
+    Ed25519(&'static [u8]),
+    /// `format` 2, a 64-byte signature of the SHA-256 digest
+    EcdsaNistP256(&'static [u8]),
+}
+
+/// Iterates over the credentials in the footers of an app. Footers of other
+/// types, and credentials in unknown formats, are skipped.
+crate struct TbfFooters {
+    footers: &'static [u8],
+}
+
+impl Iterator for TbfFooters {
+    type Item = TbfFooterCredentials;
+
+    fn next(&mut self) -> Option<TbfFooterCredentials> {
+        while self.footers.len() >= mem::size_of::<TbfHeaderTlv>() {
+            let footer = self.footers;
+            let tipe = footer[0] as u16 | (footer[1] as u16) << 8;
+            let length = footer[2] as usize | (footer[3] as usize) << 8;
+            let data_end = mem::size_of::<TbfHeaderTlv>() + length;
+
+            // A TLV block that runs past the end of the TBF means the
+            // footers are malformed.
+            if data_end > footer.len() {
+                break;
+            }
+            let data = &footer[mem::size_of::<TbfHeaderTlv>()..data_end];
+            self.footers = &footer[cmp::min(align4!(data_end), footer.len())..];
+
+            if tipe == TBF_FOOTER_CREDENTIALS && length >= 4 {
+                let format = data[0] as u32
+                    | (data[1] as u32) << 8
+                    | (data[2] as u32) << 16
+                    | (data[3] as u32) << 24;
+                match format {
+                    1 => return Some(TbfFooterCredentials::Ed25519(&data[4..])),
+                    2 => return Some(TbfFooterCredentials::EcdsaNistP256(&data[4..])),
+                    _ => {}
+                }
+            }
+        }
+        self.footers = &[];
+        None
+    }
+}
+
+/// Get the footers of the app at `address`, which has the header `header`.
+/// Apps without a Binary End element have no footers.
+crate unsafe fn parse_tbf_footers(address: *const u8, header: &TbfHeader) -> TbfFooters {
+    let footers = header.get_binary_end_offset().map_or(&[][..], |offset| {
+        slice::from_raw_parts(
+            address.offset(offset as isize),
+            header.get_total_size() as usize - offset as usize,
+        )
+    });
+    TbfFooters { footers: footers }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Total size of the TBFs in the tests.
+    const TOTAL_SIZE: u32 = 1024;
+
+    /// Words in the buffers the headers of the tests are built in.
+    const WORDS: usize = 16;
+
+    /// Build a header of `header_size` bytes from the words of its TLV
+    /// elements, with a valid checksum.
+    fn header(header_size: u16, elements: &[u32]) -> [u32; WORDS] {
+        let mut words = [0; WORDS];
+        words[0] = 2 | (header_size as u32) << 16;
+        words[1] = TOTAL_SIZE;
+        words[2] = 1;
+        words[4..4 + elements.len()].copy_from_slice(elements);
+        let checksum_words = (header_size as usize + 3) / 4;
+        let leftover_bytes = header_size as usize % 4;
+        for i in 0..checksum_words {
+            if i == 3 {
+                continue;
+            }
+            words[3] ^= if i == checksum_words - 1 && leftover_bytes != 0 {
+                words[i] & (0xFFFFFFFF >> (4 - leftover_bytes))
+            } else {
+                words[i]
+            };
+        }
+        words
+    }
+
+    /// The first word of a TLV element.
+    fn tlv(tipe: TbfHeaderTypes, length: u16) -> u32 {
+        tipe as u32 | (length as u32) << 16
+    }
+
+    fn parse(words: &[u32; WORDS]) -> Option<TbfHeader> {
+        unsafe { parse_and_validate_tbf_header(words.as_ptr() as *const u8) }
+    }
+
+    #[test]
+    fn main_element() {
+        let words = header(32, &[tlv(TbfHeaderTypes::TbfHeaderMain, 12), 0x40, 0, 0x800]);
+        let header = parse(&words).expect("well formed header refused");
+        assert!(header.is_app());
+        assert!(header.enabled());
+        assert_eq!(header.get_total_size(), TOTAL_SIZE);
+        assert_eq!(header.get_init_function_offset(), 32 + 0x40);
+        assert_eq!(header.get_minimum_app_ram_size(), 0x800);
+    }
+
+    #[test]
+    fn padding() {
+        let words = header(16, &[]);
+        let header = parse(&words).expect("padding refused");
+        assert!(!header.is_app());
+        assert_eq!(header.get_total_size(), TOTAL_SIZE);
+    }
+
+    #[test]
+    fn short_base_header() {
+        for &header_size in &[0, 4, 8, 12, 15] {
+            let words = header(header_size, &[]);
+            assert!(parse(&words).is_none(), "header_size {}", header_size);
+        }
+    }
+
+    #[test]
+    fn header_longer_than_tbf() {
+        let mut words = header(32, &[tlv(TbfHeaderTypes::TbfHeaderMain, 12), 0, 0, 0]);
+        words[1] = 32;
+        assert!(parse(&words).is_none());
+    }
+
+    #[test]
+    fn bad_checksum() {
+        let mut words = header(32, &[tlv(TbfHeaderTypes::TbfHeaderMain, 12), 0, 0, 0]);
+        words[3] ^= 1;
+        assert!(parse(&words).is_none());
+    }
+
+    #[test]
+    fn truncated_tlv() {
+        // The main element is cut off by the end of the header.
+        let words = header(28, &[tlv(TbfHeaderTypes::TbfHeaderMain, 12), 0, 0]);
+        assert!(parse(&words).is_none());
+
+        // Too few bytes are left for a TLV header, so they are ignored.
+        let words = header(34, &[tlv(TbfHeaderTypes::TbfHeaderMain, 12), 0, 0, 0x800, 3]);
+        let header = parse(&words).expect("trailing bytes refused");
+        assert_eq!(header.get_minimum_app_ram_size(), 0x800);
+    }
+
+    #[test]
+    fn overlong_tlv_length() {
+        let words = header(32, &[tlv(TbfHeaderTypes::TbfHeaderPackageName, 0xFFFF), 0, 0, 0]);
+        assert!(parse(&words).is_none());
+
+        // Padding to 4 bytes must fit in the header as well.
+        let words = header(32, &[tlv(TbfHeaderTypes::TbfHeaderPackageName, 11), 0, 0, 0]);
+        assert!(parse(&words).is_some());
+        let words = header(30, &[tlv(TbfHeaderTypes::TbfHeaderPackageName, 10), 0, 0, 0]);
+        assert!(parse(&words).is_none());
+    }
+
+    #[test]
+    fn wrong_element_length() {
+        // A main element of the wrong length is skipped.
+        let words = header(
+            32,
+            &[tlv(TbfHeaderTypes::TbfHeaderMain, 8), 0, 0x800, 0x1_0001],
+        );
+        let header = parse(&words).expect("header refused");
+        assert!(header.is_app());
+        assert_eq!(header.get_minimum_app_ram_size(), 0);
+    }
+
+    #[test]
+    fn unknown_elements_are_skipped() {
+        for &tipe in &[0, 4, 7, 12, 0xFFFF] {
+            let words = header(
+                48,
+                &[
+                    tipe as u32 | 8 << 16,
+                    0xFFFFFFFF,
+                    0xFFFFFFFF,
+                    tlv(TbfHeaderTypes::TbfHeaderMain, 12),
+                    0,
+                    0,
+                    0x800,
+                ],
+            );
+            let header = parse(&words).expect("header refused");
+            assert_eq!(header.get_minimum_app_ram_size(), 0x800, "type {}", tipe);
+        }
+    }
+
+    #[test]
+    fn element_numbers() {
+        let words = header(
+            48,
+            &[
+                tlv(TbfHeaderTypes::TbfHeaderFixedAddresses, 8),
+                0x2000_0000,
+                0xFFFFFFFF,
+                tlv(TbfHeaderTypes::TbfHeaderPermissions, 4),
+                1,
+                tlv(TbfHeaderTypes::TbfHeaderKernelVersion, 4),
+                1 | 2 << 16,
+            ],
+        );
+        assert_eq!(words[4] & 0xFFFF, 5);
+        assert_eq!(words[7] & 0xFFFF, 6);
+        assert_eq!(words[9] & 0xFFFF, 8);
+        let header = parse(&words).expect("header refused");
+        assert_eq!(header.get_fixed_address_ram(), Some(0x2000_0000));
+        assert_eq!(header.get_fixed_address_flash(), None);
+        assert!(header.driver_permitted(1));
+        assert!(!header.driver_permitted(2));
+        assert!(header.kernel_version_compatible(1, 2));
+        assert!(!header.kernel_version_compatible(1, 1));
+    }
+}
@@ -0,0 +1,151 @@
+//! Cooperative queue for long-running kernel work.
+//!
+//! Some kernel work, like software cryptography or scrubbing flash, takes too
+//! long to do at once without delaying processes and interrupts. A capsule
+//! splits such work into chunks and queues a `Work` item on the `WorkQueue` of
+//! the kernel. The kernel loop runs one chunk at a time, and only when no
+//! process is ready to run and no interrupt is pending.
+//!
+//! Each chunk is given a budget of iterations, and must return after doing at
+//! most that many. What an iteration is depends on the work, e.g. one block
+//! of a cipher, but it should take a few microseconds at most. This bounds
+//! how long a syscall or interrupt can wait for a chunk to finish. Queued
+//! items take turns, one chunk each.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! struct Scrubber<'a> {
+//!     work: Work<'a>,
+//!     next_page: Cell<usize>,
+//! }
+//!
+//! impl WorkClient for Scrubber<'a> {
+//!     fn do_work(&self, max_iterations: usize) -> bool {
+//!         for _ in 0..max_iterations {
+//!             // Scrub one page.
+//!             if self.next_page.get() == LAST_PAGE {
+//!                 return false;
+//!             }
+//!             self.next_page.set(self.next_page.get() + 1);
+//!         }
+//!         true
+//!     }
+//! }
+//!
+//! // In the board:
+//! scrubber.work.set_client(scrubber);
+//! board_kernel.work_queue().enqueue(&scrubber.work);
+//! ```
+
+use core::cell::Cell;
+use core::ptr;
+
+use common::cells::OptionalCell;
+use common::{List, ListLink, ListNode};
+use returncode::ReturnCode;
+
+/// Implemented by capsules that do work through the `WorkQueue`.
+pub trait WorkClient {
+    /// Do the next chunk of the work, of at most `max_iterations`
+    /// iterations. Returns true if work is left, in which case the item stays
+    /// queued, and false once the work is done.
+    fn do_work(&self, max_iterations: usize) -> bool;
+}
+
+/// An item of work that can be queued on the `WorkQueue`.
+pub struct Work<'a> {
+    client: OptionalCell<&'a WorkClient>,
+    /// Whether the work should run. Cancelled items stay in the list until
+    /// the queue reaches them.
+    queued: Cell<bool>,
+    next: ListLink<'a, Work<'a>>,
+}
+
+impl Work<'a> {
+    pub const fn new() -> Work<'a> {
+        Work {
+            client: OptionalCell::empty(),
+            queued: Cell::new(false),
+            next: ListLink::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a WorkClient) {
+        self.client.set(client);
+    }
+
+    pub fn is_queued(&self) -> bool {
+        self.queued.get()
+    }
+}
+
+impl ListNode<'a, Work<'a>> for Work<'a> {
+    fn next(&'a self) -> &'a ListLink<'a, Work<'a>> {
+        &self.next
+    }
+}
+
+/// The queue of kernel work. Each kernel has one, see
+/// `Kernel::work_queue()`.
+pub struct WorkQueue<'a> {
+    items: List<'a, Work<'a>>,
+}
+
+impl WorkQueue<'a> {
+    crate const fn new() -> WorkQueue<'a> {
+        WorkQueue { items: List::new() }
+    }
+
+    fn contains(&self, work: &Work<'a>) -> bool {
+        self.items.iter().any(|item| ptr::eq(item, work))
+    }
+
+    /// Queue `work` to run once the kernel is idle. Returns `EALREADY` if it
+    /// is already queued.
+    pub fn enqueue(&self, work: &'a Work<'a>) -> ReturnCode {
+        if work.queued.get() {
+            return ReturnCode::EALREADY;
+        }
+        work.queued.set(true);
+        if !self.contains(work) {
+            self.items.push_tail(work);
+        }
+        ReturnCode::SUCCESS
+    }
+
+    /// Stop `work` from running any further chunks.
+    pub fn cancel(&self, work: &Work<'a>) {
+        work.queued.set(false);
+    }
+
+    /// Whether any work is queued.
+    pub fn has_work(&self) -> bool {
+        self.items.iter().any(|item| item.queued.get())
+    }
+
+    /// Run one chunk of the item at the head of the queue, and move the item
+    /// to the tail if work is left.
+    crate fn run_chunk(&self, max_iterations: usize) {
+        while let Some(work) = self.items.pop_head() {
+            if !work.queued.get() {
+                // Cancelled, drop it from the list.
+                continue;
+            }
+            let more = work
+                .client
+                .map_or(false, |client| client.do_work(max_iterations));
+            // The client may have cancelled the work, or queued it again,
+            // while it ran.
+            if more && work.queued.get() {
+                if !self.contains(work) {
+                    self.items.push_tail(work);
+                }
+            } else if !self.contains(work) {
+                work.queued.set(false);
+            }
+            break;
+        }
+    }
+}